@@ -0,0 +1,80 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2023 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+ */
+
+//! # vSMTP SDK example
+//!
+//! A transport dropping the messages, and a rhai plugin, depending only on `vsmtp-sdk`.
+
+#![doc(html_no_source)]
+#![deny(missing_docs)]
+#![deny(unsafe_code)]
+//
+#![warn(rust_2018_idioms)]
+#![warn(clippy::all)]
+#![warn(clippy::pedantic)]
+#![warn(clippy::nursery)]
+#![warn(clippy::cargo)]
+
+use vsmtp_sdk::{
+    context::ContextFinished,
+    rhai,
+    serde::{Deserialize, Serialize},
+    transfer::Status,
+    transport::{AbstractTransport, DeliverTo, GetID, TransportStates},
+};
+
+/// A transport marking every recipient as sent, without delivering anything.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(crate = "vsmtp_sdk::serde")]
+pub struct Noop {
+    // identify the transport in its serialized form.
+    noop: (),
+}
+
+impl GetID for Noop {}
+
+#[vsmtp_sdk::async_trait]
+impl AbstractTransport for Noop {
+    async fn deliver(
+        self: std::sync::Arc<Self>,
+        _: &TransportStates,
+        _: &ContextFinished,
+        rcpt_to: DeliverTo,
+        _: &[u8],
+    ) -> DeliverTo {
+        rcpt_to
+            .into_iter()
+            .map(|(rcpt, _)| (rcpt, Status::sent()))
+            .collect()
+    }
+}
+
+vsmtp_sdk::export_transport!(Noop);
+
+/// A rhai module greeting the users.
+#[rhai::plugin::export_module]
+pub mod greeting {
+    use vsmtp_sdk::rhai::plugin::*;
+
+    /// Produce a greeting message for `name`.
+    #[rhai_fn(global)]
+    pub fn hello(name: &str) -> String {
+        format!("Hello, {name}!")
+    }
+}
+
+vsmtp_sdk::export_plugin!(greeting);
@@ -15,51 +15,13 @@
  *
 */
 
-use std::str::FromStr;
-
 use rhai::plugin::{
     mem, Dynamic, FnAccess, FnNamespace, Module, NativeCallContext, PluginFunction, RhaiResult,
     TypeId,
 };
-use strum_macros::EnumString;
 use trust_dns_resolver::config::{ResolverConfig, ResolverOpts};
 use trust_dns_resolver::Resolver;
 
-#[derive(Debug, EnumString)]
-enum BlockListKind {
-    #[strum(ascii_case_insensitive)]
-    Spamhaus,
-    #[strum(ascii_case_insensitive)]
-    Spamrats,
-    #[strum(ascii_case_insensitive)]
-    Spamcops,
-    #[strum(ascii_case_insensitive)]
-    Lashback,
-    #[strum(ascii_case_insensitive)]
-    S5h,
-    #[strum(ascii_case_insensitive)]
-    Sorbs,
-    #[strum(ascii_case_insensitive)]
-    BackScatterer,
-    #[strum(ascii_case_insensitive)]
-    Singular,
-}
-
-impl BlockListKind {
-    fn to_url(&self) -> String {
-        match self {
-            Self::Spamhaus => String::from("zen.spamhaus.org"),
-            Self::Spamrats => String::from("all.spamrats.com"),
-            Self::Spamcops => String::from("bl.spamcop.net"),
-            Self::Lashback => String::from("ubl.unsubscore.com"),
-            Self::S5h => String::from("all.s5h.net"),
-            Self::Sorbs => String::from("dnsxl.sorbs.net"),
-            Self::BackScatterer => String::from("ips.backscatterer.org"),
-            Self::Singular => String::from("singular.ttk.pte.hu"),
-        }
-    }
-}
-
 #[derive(Debug, serde::Deserialize)]
 struct DnsxlParameters {
     #[serde(default)]
@@ -82,11 +44,8 @@ impl Dnsbl {
     pub fn contains(&self, domain: &str, map: &mut rhai::Map) -> bool {
         let mut result = false;
         for element in &self.bl {
-            let bl_kind = BlockListKind::from_str(element);
-            if let Ok(kind) = bl_kind {
-                let response = self
-                    .resolver
-                    .lookup_ip(domain.to_owned() + "." + kind.to_url().as_str());
+            if let Some(zone) = vsmtp_common::dnsxl::zone(element) {
+                let response = self.resolver.lookup_ip(domain.to_owned() + "." + zone);
                 if let Ok(ips) = response {
                     map.insert(
                         element.into(),
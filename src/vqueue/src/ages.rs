@@ -0,0 +1,251 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2023 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+
+use crate::QueueID;
+extern crate alloc;
+
+fn unix_now() -> i64 {
+    vsmtp_common::clock::now().unix_timestamp()
+}
+
+/// The messages of a queue, by time of entry.
+#[derive(Debug, Default)]
+struct Entries {
+    by_uuid: alloc::collections::BTreeMap<uuid::Uuid, i64>,
+    by_age: alloc::collections::BTreeSet<(i64, uuid::Uuid)>,
+}
+
+/// Messages of a queue older than a threshold, see [`QueueAges::older_than`].
+#[non_exhaustive]
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct StuckMessages {
+    /// Number of messages older than the threshold.
+    pub count: usize,
+    /// The oldest of them, from the oldest.
+    pub samples: Vec<uuid::Uuid>,
+}
+
+/// Time of entry of the messages in each queue, to know how long they have been waiting.
+///
+/// The queue manager updates it on each write and removal of a context, instead of
+/// scanning the queues: a message rewritten in its queue (the deferred queue after
+/// each attempt for instance) keeps its time of entry, and a moved message enters
+/// the new queue. The queues are read once at the startup of the server (see
+/// [`crate::fs::QueueManager::load_ages`]), the time of entry of a message being the last
+/// modification of its context then.
+///
+/// NOTE: the changes made by another process (the `vqueue` command line, another
+/// instance sharing the spool) are only seen at the next startup.
+#[derive(Debug)]
+pub struct QueueAges {
+    queues: std::sync::Mutex<alloc::collections::BTreeMap<String, Entries>>,
+}
+
+impl Default for QueueAges {
+    #[inline]
+    fn default() -> Self {
+        // the queues are listed even when empty, for their metrics to be continuous.
+        Self {
+            queues: std::sync::Mutex::new(
+                <QueueID as strum::IntoEnumIterator>::iter()
+                    .filter(|queue| !matches!(queue, QueueID::Quarantine { .. }))
+                    .map(|queue| (queue.to_string(), Entries::default()))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+impl QueueAges {
+    fn with_queues<R>(
+        &self,
+        f: impl FnOnce(&mut alloc::collections::BTreeMap<String, Entries>) -> R,
+    ) -> R {
+        f(&mut self
+            .queues
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner))
+    }
+
+    /// Record the entry of a message in `queue` now, if it is not already in it.
+    #[inline]
+    pub fn enter(&self, queue: &QueueID, msg_uuid: &uuid::Uuid) {
+        self.enter_at(queue, msg_uuid, unix_now());
+    }
+
+    pub(crate) fn enter_at(&self, queue: &QueueID, msg_uuid: &uuid::Uuid, at: i64) {
+        self.with_queues(|queues| {
+            let entries = queues.entry(queue.to_string()).or_default();
+            if !entries.by_uuid.contains_key(msg_uuid) {
+                entries.by_uuid.insert(*msg_uuid, at);
+                entries.by_age.insert((at, *msg_uuid));
+            }
+        });
+    }
+
+    /// Record the exit of a message from `queue`.
+    #[inline]
+    pub fn leave(&self, queue: &QueueID, msg_uuid: &uuid::Uuid) {
+        self.with_queues(|queues| {
+            if let Some(entries) = queues.get_mut(&queue.to_string()) {
+                if let Some(at) = entries.by_uuid.remove(msg_uuid) {
+                    entries.by_age.remove(&(at, *msg_uuid));
+                }
+            }
+        });
+    }
+
+    /// Read the contexts stored in the directory of `queue`, entered at their last modification.
+    pub(crate) fn load(&self, queue: &QueueID, dir: &std::path::Path) {
+        let Ok(read_dir) = dir.read_dir() else {
+            return;
+        };
+
+        for entry in read_dir.flatten() {
+            let path = entry.path();
+            let Some(msg_uuid) = path
+                .file_stem()
+                .and_then(std::ffi::OsStr::to_str)
+                .and_then(|stem| stem.parse::<uuid::Uuid>().ok())
+            else {
+                continue;
+            };
+            let modified_at = entry
+                .metadata()
+                .and_then(|metadata| metadata.modified())
+                .ok()
+                .and_then(|modified| modified.duration_since(std::time::UNIX_EPOCH).ok())
+                .and_then(|elapsed| i64::try_from(elapsed.as_secs()).ok())
+                .unwrap_or_else(unix_now);
+
+            self.enter_at(queue, &msg_uuid, modified_at);
+        }
+    }
+
+    /// The queues tracked: the queues of [`QueueID`], and the quarantines written since the startup.
+    #[must_use]
+    #[inline]
+    pub fn queues(&self) -> Vec<String> {
+        self.with_queues(|queues| queues.keys().cloned().collect())
+    }
+
+    /// Number of messages in `queue`.
+    #[must_use]
+    #[inline]
+    pub fn count(&self, queue: &str) -> usize {
+        self.with_queues(|queues| queues.get(queue).map_or(0, |entries| entries.by_uuid.len()))
+    }
+
+    /// Age of the oldest message of `queue`, `None` if the queue is empty.
+    #[must_use]
+    #[inline]
+    pub fn oldest(&self, queue: &str) -> Option<core::time::Duration> {
+        let now = unix_now();
+        self.with_queues(|queues| {
+            queues
+                .get(queue)
+                .and_then(|entries| entries.by_age.first())
+                .map(|(at, _)| {
+                    core::time::Duration::from_secs(u64::try_from(now - at).unwrap_or(0))
+                })
+        })
+    }
+
+    /// The messages of `queue` waiting for longer than `age`, with the `samples` oldest ones.
+    #[must_use]
+    #[inline]
+    pub fn older_than(
+        &self,
+        queue: &str,
+        age: core::time::Duration,
+        samples: usize,
+    ) -> StuckMessages {
+        let cutoff = unix_now() - i64::try_from(age.as_secs()).unwrap_or(i64::MAX);
+        self.with_queues(|queues| {
+            queues
+                .get(queue)
+                .map_or_else(StuckMessages::default, |entries| {
+                    let mut stuck = entries
+                        .by_age
+                        .iter()
+                        .take_while(|(at, _)| *at < cutoff)
+                        .map(|(_, msg_uuid)| *msg_uuid);
+
+                    let samples = stuck.by_ref().take(samples).collect::<Vec<_>>();
+                    StuckMessages {
+                        count: samples.len() + stuck.count(),
+                        samples,
+                    }
+                })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn incremental() {
+        let ages = QueueAges::default();
+        let clock = vsmtp_test::clock::TestClock::start();
+        let (first, second) = (uuid::Uuid::new_v4(), uuid::Uuid::new_v4());
+
+        assert_eq!(ages.oldest("deliver"), None);
+        assert!(ages.queues().contains(&"deliver".to_owned()));
+
+        ages.enter(&QueueID::Deliver, &first);
+        clock.advance(time::Duration::minutes(10));
+        ages.enter(&QueueID::Deliver, &second);
+        // rewritten in the same queue, the time of entry is kept.
+        ages.enter(&QueueID::Deliver, &first);
+        clock.advance(time::Duration::minutes(10));
+
+        assert_eq!(ages.count("deliver"), 2);
+        assert_eq!(
+            ages.oldest("deliver"),
+            Some(core::time::Duration::from_secs(20 * 60))
+        );
+        assert_eq!(
+            ages.older_than("deliver", core::time::Duration::from_secs(5 * 60), 1),
+            StuckMessages {
+                count: 2,
+                samples: vec![first],
+            }
+        );
+        assert_eq!(
+            ages.older_than("deliver", core::time::Duration::from_secs(15 * 60), 5),
+            StuckMessages {
+                count: 1,
+                samples: vec![first],
+            }
+        );
+
+        // moved to the deferred queue.
+        ages.enter(&QueueID::Deferred, &first);
+        ages.leave(&QueueID::Deliver, &first);
+        assert_eq!(
+            ages.oldest("deliver"),
+            Some(core::time::Duration::from_secs(10 * 60))
+        );
+        assert_eq!(ages.oldest("deferred"), Some(core::time::Duration::ZERO));
+
+        ages.leave(&QueueID::Deliver, &second);
+        assert_eq!(ages.oldest("deliver"), None);
+        assert_eq!(ages.count("deliver"), 0);
+    }
+}
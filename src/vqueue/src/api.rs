@@ -14,6 +14,7 @@
  * this program. If not, see https://www.gnu.org/licenses/.
  *
  */
+use crate::QueueAges;
 use vsmtp_common::{transport::DeserializerFn, ContextFinished};
 use vsmtp_config::Config;
 use vsmtp_mail_parser::MessageBody;
@@ -34,6 +35,8 @@ pub enum QueueID {
     Deferred,
     /// Too many attempts failed.
     Dead,
+    /// Put on hold by an administrator, not delivered until released.
+    Hold,
     ///
     Quarantine {
         /// User defined name of the quarantine, can be a reason (ex: "spam")
@@ -47,7 +50,12 @@ impl core::fmt::Display for QueueID {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         match self {
             Self::Quarantine { name } => write!(f, "quarantine/{name}"),
-            &Self::Working | &Self::Deliver | &Self::Delegated | &Self::Deferred | &Self::Dead => {
+            &Self::Working
+            | &Self::Deliver
+            | &Self::Delegated
+            | &Self::Deferred
+            | &Self::Dead
+            | &Self::Hold => {
                 write!(f, "{}", Into::<&'static str>::into(self))
             }
         }
@@ -66,6 +74,7 @@ mod tests {
             QueueID::Delegated,
             QueueID::Deferred,
             QueueID::Dead,
+            QueueID::Hold,
             QueueID::Quarantine {
                 name: "foobar".to_owned(),
             },
@@ -77,6 +86,7 @@ mod tests {
             "delegated",
             "deferred",
             "dead",
+            "hold",
             "quarantine/foobar",
         ]) {
             assert_eq!(q.to_string(), str);
@@ -84,6 +94,21 @@ mod tests {
     }
 }
 
+/// Number of messages between two progress reports of [`GenericQueueManager::move_all`].
+const MOVE_ALL_PROGRESS_PERIOD: usize = 1000;
+
+/// Outcome of [`GenericQueueManager::move_all`].
+#[non_exhaustive]
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct MoveAllReport {
+    /// Messages moved.
+    pub moved: Vec<uuid::Uuid>,
+    /// Number of messages left in place by the filter.
+    pub skipped: usize,
+    /// Messages whose move failed, by id, with the error.
+    pub failed: Vec<(String, String)>,
+}
+
 ///
 #[derive(Clone)]
 pub struct DetailedMailContext {
@@ -116,6 +141,13 @@ where
     ///
     fn get_transport_deserializer(&self) -> &[DeserializerFn];
 
+    /// Time of entry of the messages in the queues, updated by [`GenericQueueManager::write_ctx`]
+    /// and [`GenericQueueManager::remove_ctx`], `None` if the queue manager does not track them.
+    #[inline]
+    fn get_ages(&self) -> Option<&alloc::sync::Arc<QueueAges>> {
+        None
+    }
+
     ///
     async fn write_ctx(&self, queue: &QueueID, ctx: &ContextFinished) -> anyhow::Result<()>;
 
@@ -158,6 +190,37 @@ where
     /// Get the list of message IDs in the queue.
     async fn list(&self, queue: &QueueID) -> anyhow::Result<Vec<anyhow::Result<String>>>;
 
+    /// Take the claim of this instance on a message, before processing it.
+    ///
+    /// Several instances can share the same spool (active/active), the claim
+    /// ensures that a message is processed by only one of them at a time.
+    /// Returns `false` if the message is already claimed (by this instance or another)
+    /// and the heartbeat is younger than `server.queues.claim_timeout`, a stale claim
+    /// is taken over.
+    ///
+    /// The default implementation does not share its queues, and always succeeds.
+    #[inline]
+    async fn claim(&self, _msg_uuid: &uuid::Uuid) -> anyhow::Result<bool> {
+        Ok(true)
+    }
+
+    /// Refresh the heartbeat of the claim of this instance on a message,
+    /// to be called periodically while processing it.
+    ///
+    /// Returns `false` if the claim has been lost.
+    #[inline]
+    async fn heartbeat(&self, _msg_uuid: &uuid::Uuid) -> anyhow::Result<bool> {
+        Ok(true)
+    }
+
+    /// Release the claim of this instance on a message, once processed.
+    ///
+    /// A claim taken over by another instance is left untouched.
+    #[inline]
+    async fn release(&self, _msg_uuid: &uuid::Uuid) -> anyhow::Result<()> {
+        Ok(())
+    }
+
     ///
     async fn get_ctx(
         &self,
@@ -175,6 +238,13 @@ where
     ///
     async fn get_msg(&self, msg_uuid: &uuid::Uuid) -> anyhow::Result<MessageBody>;
 
+    /// Get the message exactly as stored in the queue, headers and body, without going
+    /// through the parser which may normalize it.
+    ///
+    /// To use when the bytes must be preserved, to verify the signatures again, to
+    /// re-inject the message or to keep it under legal hold.
+    async fn get_msg_raw(&self, msg_uuid: &uuid::Uuid) -> anyhow::Result<Vec<u8>>;
+
     ///
     #[inline]
     async fn get_both(
@@ -191,6 +261,22 @@ where
         ))
     }
 
+    /// Get the context and the message as stored, see [`GenericQueueManager::get_msg_raw`].
+    #[inline]
+    async fn get_both_raw(
+        &self,
+        queue: &QueueID,
+        msg_uuid: &uuid::Uuid,
+    ) -> anyhow::Result<(ContextFinished, Vec<u8>)>
+    where
+        Self: Sized,
+    {
+        Ok((
+            self.get_ctx(queue, msg_uuid).await?,
+            self.get_msg_raw(msg_uuid).await?,
+        ))
+    }
+
     ///
     #[inline]
     async fn move_to_from_id(
@@ -230,4 +316,105 @@ where
 
         Ok(())
     }
+
+    /// Move the messages of `before` accepted by `filter` to `after`, one by one.
+    ///
+    /// The filter can update the context, written in `after`. A message being processed
+    /// (see [`GenericQueueManager::claim`]), or failing to be read or moved, is left in place
+    /// and reported, the others are moved anyway. The progress is logged every
+    /// thousand messages.
+    ///
+    /// # Errors
+    ///
+    /// * the queues are the same
+    /// * failed to list `before`
+    #[inline]
+    async fn move_all(
+        &self,
+        before: &QueueID,
+        after: &QueueID,
+        filter: &(dyn for<'ctx> Fn(&'ctx mut ContextFinished) -> bool + Send + Sync),
+    ) -> anyhow::Result<MoveAllReport>
+    where
+        Self: Sized,
+    {
+        anyhow::ensure!(before != after, "Queues are the same: `{before}`");
+
+        let queued = self.list(before).await?;
+        let total = queued.len();
+        let mut report = MoveAllReport::default();
+
+        for (index, msg_id) in queued.into_iter().enumerate() {
+            if index != 0 && index % MOVE_ALL_PROGRESS_PERIOD == 0 {
+                tracing::info!(
+                    from = %before,
+                    to = %after,
+                    index,
+                    total,
+                    moved = report.moved.len(),
+                    failed = report.failed.len(),
+                    "Moving emails."
+                );
+            }
+
+            let msg_id = match msg_id {
+                Ok(msg_id) => msg_id,
+                Err(error) => {
+                    report.failed.push((String::new(), format!("{error:#}")));
+                    continue;
+                }
+            };
+            let Ok(msg_uuid) = msg_id.parse::<uuid::Uuid>() else {
+                report
+                    .failed
+                    .push((msg_id, "invalid message id".to_owned()));
+                continue;
+            };
+
+            match self.claim(&msg_uuid).await {
+                Ok(true) => {}
+                Ok(false) => {
+                    report
+                        .failed
+                        .push((msg_id, "message is being processed".to_owned()));
+                    continue;
+                }
+                Err(error) => {
+                    report.failed.push((msg_id, format!("{error:#}")));
+                    continue;
+                }
+            }
+
+            let moved = match self.get_ctx(before, &msg_uuid).await {
+                Ok(mut ctx) => {
+                    if filter(&mut ctx) {
+                        self.move_to(before, after, &ctx).await.map(|()| true)
+                    } else {
+                        Ok(false)
+                    }
+                }
+                Err(error) => Err(error),
+            };
+            if let Err(error) = self.release(&msg_uuid).await {
+                tracing::warn!(uuid = %msg_uuid, %error, "Releasing the claim failure.");
+            }
+
+            match moved {
+                Ok(true) => report.moved.push(msg_uuid),
+                Ok(false) => report.skipped += 1,
+                Err(error) => report.failed.push((msg_id, format!("{error:#}"))),
+            }
+        }
+
+        tracing::info!(
+            from = %before,
+            to = %after,
+            moved = report.moved.len(),
+            skipped = report.skipped,
+            failed = report.failed.len(),
+            "Emails moved."
+        );
+
+        Ok(report)
+    }
 }
@@ -59,18 +59,22 @@ pub enum Commands {
     },
     /// Operate action to a given message
     Msg {
-        /// ID of the concerned message
-        #[clap(value_parser = parse_uuid)]
-        msg: uuid::Uuid,
+        /// ID of the concerned message: the uuid, or a unique prefix of the uuid
+        /// or of the short queue id
+        #[clap(value_parser = parse_message_id)]
+        msg: String,
         ///
         #[clap(subcommand)]
         command: MessageCommand,
     },
 }
 
-fn parse_uuid(value: &str) -> Result<uuid::Uuid, clap::Error> {
-    uuid::Uuid::parse_str(value)
-        .map_err(|_err| clap::Error::new(clap::error::ErrorKind::ValueValidation))
+fn parse_message_id(value: &str) -> Result<String, clap::Error> {
+    if !value.is_empty() && value.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+        Ok(value.to_owned())
+    } else {
+        Err(clap::Error::new(clap::error::ErrorKind::ValueValidation))
+    }
 }
 
 ///
@@ -196,7 +200,7 @@ mod tests {
                 version: false,
                 config: Args::default_config_location(),
                 command: Some(Commands::Msg {
-                    msg: uuid::Uuid::nil(),
+                    msg: "00000000-0000-0000-0000-000000000000".to_owned(),
                     command: MessageCommand::Show {
                         format: MessageShowFormat::Json
                     }
@@ -216,7 +220,7 @@ mod tests {
                 version: false,
                 config: Args::default_config_location(),
                 command: Some(Commands::Msg {
-                    msg: uuid::Uuid::nil(),
+                    msg: "00000000-0000-0000-0000-000000000000".to_owned(),
                     command: MessageCommand::Show {
                         format: MessageShowFormat::Json
                     }
@@ -237,7 +241,7 @@ mod tests {
                 version: false,
                 config: Args::default_config_location(),
                 command: Some(Commands::Msg {
-                    msg: uuid::Uuid::nil(),
+                    msg: "00000000-0000-0000-0000-000000000000".to_owned(),
                     command: MessageCommand::Show {
                         format: MessageShowFormat::Eml
                     }
@@ -254,6 +258,30 @@ mod tests {
         );
     }
 
+    #[test]
+    fn arg_message_short_id() {
+        assert_eq!(
+            Args {
+                version: false,
+                config: Args::default_config_location(),
+                command: Some(Commands::Msg {
+                    msg: "7KQ2".to_owned(),
+                    command: MessageCommand::Show {
+                        format: MessageShowFormat::Json
+                    }
+                })
+            },
+            <Args as clap::Parser>::try_parse_from(["", "msg", "7KQ2", "show"]).unwrap()
+        );
+
+        assert_eq!(
+            <Args as clap::Parser>::try_parse_from(["", "msg", "../foo", "show"])
+                .unwrap_err()
+                .kind(),
+            clap::error::ErrorKind::ValueValidation,
+        );
+    }
+
     #[test]
     fn arg_move_message() {
         assert_eq!(
@@ -261,7 +289,7 @@ mod tests {
                 version: false,
                 config: Args::default_config_location(),
                 command: Some(Commands::Msg {
-                    msg: uuid::Uuid::nil(),
+                    msg: "00000000-0000-0000-0000-000000000000".to_owned(),
                     command: MessageCommand::Move {
                         queue: QueueID::Dead
                     }
@@ -285,7 +313,7 @@ mod tests {
                 version: false,
                 config: Args::default_config_location(),
                 command: Some(Commands::Msg {
-                    msg: uuid::Uuid::nil(),
+                    msg: "00000000-0000-0000-0000-000000000000".to_owned(),
                     command: MessageCommand::Remove { yes: false }
                 })
             },
@@ -303,7 +331,7 @@ mod tests {
                 version: false,
                 config: Args::default_config_location(),
                 command: Some(Commands::Msg {
-                    msg: uuid::Uuid::nil(),
+                    msg: "00000000-0000-0000-0000-000000000000".to_owned(),
                     command: MessageCommand::Remove { yes: true }
                 })
             },
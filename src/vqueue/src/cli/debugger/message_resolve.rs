@@ -0,0 +1,126 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2022 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+ */
+use crate::{cli::args::Commands, GenericQueueManager, QueueID};
+extern crate alloc;
+
+#[allow(clippy::multiple_inherent_impl)]
+impl Commands {
+    /// Find the message designated by `msg_id`, either a full uuid, or a prefix
+    /// of the uuid or of the short queue id (case insensitive).
+    pub(crate) async fn message_resolve(
+        msg_id: &str,
+        queue_manager: &alloc::sync::Arc<impl GenericQueueManager + Send + Sync>,
+    ) -> anyhow::Result<uuid::Uuid> {
+        if let Ok(msg_uuid) = uuid::Uuid::parse_str(msg_id) {
+            return Ok(msg_uuid);
+        }
+
+        let uuid_prefix = msg_id.to_ascii_lowercase();
+        let queue_id_prefix = msg_id.to_ascii_uppercase();
+
+        let mut candidates = alloc::collections::BTreeSet::new();
+        for queue in <QueueID as strum::IntoEnumIterator>::iter() {
+            let Ok(entries) = queue_manager.list(&queue).await else {
+                continue;
+            };
+
+            for msg_uuid in entries
+                .into_iter()
+                .filter_map(Result::ok)
+                .filter_map(|i| uuid::Uuid::parse_str(&i).ok())
+            {
+                let is_match = msg_uuid.to_string().starts_with(&uuid_prefix)
+                    || queue_manager
+                        .get_ctx(&queue, &msg_uuid)
+                        .await
+                        .map_or(false, |ctx| {
+                            ctx.mail_from.queue_id().starts_with(&queue_id_prefix)
+                        });
+                if is_match {
+                    candidates.insert(msg_uuid);
+                }
+            }
+        }
+
+        let mut candidates = candidates.into_iter();
+        match (candidates.next(), candidates.next()) {
+            (Some(msg_uuid), None) => Ok(msg_uuid),
+            (None, _) => anyhow::bail!("No message matching the id '{msg_id}'"),
+            (Some(first), Some(second)) => anyhow::bail!(
+                "The id '{msg_id}' is ambiguous, it matches: {}",
+                [first, second]
+                    .into_iter()
+                    .chain(candidates)
+                    .map(|i| i.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vsmtp_test::config::{local_ctx, local_msg, local_test};
+
+    #[tokio::test]
+    async fn resolve() {
+        let config = alloc::sync::Arc::new(local_test());
+        let queue_manager = crate::temp::QueueManager::init(config, vec![]).unwrap();
+
+        let mut first = local_ctx();
+        first.mail_from.message_uuid =
+            uuid::Uuid::parse_str("a1b2c3d4-0000-4000-8000-000000000001").unwrap();
+        let mut second = local_ctx();
+        second.mail_from.message_uuid =
+            uuid::Uuid::parse_str("a1b2ffff-0000-4000-8000-000000000002").unwrap();
+
+        queue_manager
+            .write_both(&QueueID::Deferred, &first, &local_msg())
+            .await
+            .unwrap();
+        queue_manager
+            .write_both(&QueueID::Dead, &second, &local_msg())
+            .await
+            .unwrap();
+
+        for id in [
+            "a1b2c3d4-0000-4000-8000-000000000001",
+            "a1b2c3",
+            "A1B2C3",
+            &first.mail_from.queue_id(),
+            &first.mail_from.queue_id().to_ascii_lowercase(),
+        ] {
+            assert_eq!(
+                Commands::message_resolve(id, &queue_manager).await.unwrap(),
+                first.mail_from.message_uuid
+            );
+        }
+
+        assert!(Commands::message_resolve("a1b2", &queue_manager)
+            .await
+            .unwrap_err()
+            .to_string()
+            .contains("ambiguous"));
+        assert!(Commands::message_resolve("ffffff", &queue_manager)
+            .await
+            .unwrap_err()
+            .to_string()
+            .contains("No message"));
+    }
+}
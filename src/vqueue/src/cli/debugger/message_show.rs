@@ -40,10 +40,15 @@ impl Commands {
             .find_map(Result::ok)
             .context("Mail context not found")?;
 
-        let msg = queue_manager
-            .get_msg(msg_uuid)
-            .await
-            .context("Message not found")?;
+        // NOTE: the message is printed as stored, without going through the parser.
+        let msg = match *format {
+            MessageShowFormat::Eml => queue_manager.get_msg_raw(msg_uuid).await,
+            MessageShowFormat::Json => queue_manager
+                .get_msg(msg_uuid)
+                .await
+                .and_then(|msg| Ok(serde_json::to_string_pretty(&msg)?.into_bytes())),
+        }
+        .context("Message not found")?;
 
         output.write_fmt(format_args!(
             "Message context:\n{}\n",
@@ -52,13 +57,7 @@ impl Commands {
 
         output.write_all(b"Message body:\n")?;
 
-        output.write_all(
-            match *format {
-                MessageShowFormat::Eml => msg.inner().to_string(),
-                MessageShowFormat::Json => serde_json::to_string_pretty(&msg)?,
-            }
-            .as_bytes(),
-        )?;
+        output.write_all(&msg)?;
 
         Ok(())
     }
@@ -127,6 +126,8 @@ mod tests {
   "skipped": null,
   "tls": null,
   "auth": null,
+  "error_count": 0,
+  "sender_churn": 0,
   "client_name": "client.testserver.com",
   "using_deprecated": false,
   "reverse_path": "client@testserver.com",
@@ -134,6 +135,8 @@ mod tests {
   "message_uuid": "{msg_uuid}",
   "spf": null,
   "utf8": false,
+  "body_type": null,
+  "declared_size": null,
   "forward_paths": [
     "recipient@testserver.com"
   ],
@@ -216,6 +219,8 @@ Message body:
   "skipped": null,
   "tls": null,
   "auth": null,
+  "error_count": 0,
+  "sender_churn": 0,
   "client_name": "client.testserver.com",
   "using_deprecated": false,
   "reverse_path": "client@testserver.com",
@@ -223,6 +228,8 @@ Message body:
   "message_uuid": "{msg_uuid}",
   "spf": null,
   "utf8": false,
+  "body_type": null,
+  "declared_size": null,
   "forward_paths": [
     "recipient@testserver.com"
   ],
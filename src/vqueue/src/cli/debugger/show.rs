@@ -15,7 +15,7 @@
  *
  */
 use crate::{api::DetailedMailContext, cli::args::Commands, GenericQueueManager, QueueID};
-use vsmtp_common::ClientName;
+use vsmtp_common::{transfer::error::Reason, ClientName};
 extern crate alloc;
 
 struct Content {
@@ -24,6 +24,8 @@ struct Content {
     inner: Vec<anyhow::Result<DetailedMailContext>>,
     error_count: usize,
     result: std::collections::HashMap<ClientName, MessageByLifetime>,
+    /// Number of recipients held back, by reason of their last error.
+    held_back: std::collections::BTreeMap<Reason, usize>,
     empty_token: char,
     exists: bool,
 }
@@ -172,6 +174,18 @@ impl core::fmt::Display for Content {
             f.write_str("\n")?;
         }
 
+        if !self.held_back.is_empty() {
+            f.write_fmt(format_args!(
+                "{:>20}    {}\n",
+                "HELD BACK",
+                self.held_back
+                    .iter()
+                    .map(|(reason, count)| format!("{reason}={count}"))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ))?;
+        }
+
         Ok(())
     }
 }
@@ -191,6 +205,7 @@ impl Commands {
                 inner: vec![],
                 error_count: 0,
                 result: std::collections::HashMap::new(),
+                held_back: std::collections::BTreeMap::new(),
                 empty_token,
                 exists: true,
             };
@@ -219,6 +234,17 @@ impl Commands {
                         .cloned()
                         .collect::<Vec<_>>();
 
+                    for error in valid_entries.iter().flat_map(|i| {
+                        i.ctx
+                            .rcpt_to
+                            .delivery
+                            .values()
+                            .flatten()
+                            .filter_map(|(_, status)| status.held_back_error())
+                    }) {
+                        *content.held_back.entry(error.reason()).or_default() += 1;
+                    }
+
                     valid_entries
                         .sort_by(|a, b| Ord::cmp(&a.ctx.helo.client_name, &b.ctx.helo.client_name));
 
@@ -294,7 +320,8 @@ mod tests {
                 "DELIVER    has :\t<EMPTY>\n",
                 "DELEGATED  has :\t<EMPTY>\n",
                 "DEFERRED   has :\t<EMPTY>\n",
-                "DEAD       has :\t<EMPTY>\n"
+                "DEAD       has :\t<EMPTY>\n",
+                "HOLD       has :\t<EMPTY>\n"
             ]
             .concat(),
         );
@@ -325,7 +352,8 @@ mod tests {
                 "DELIVER    has :\t<MISSING>\n",
                 "DELEGATED  has :\t<MISSING>\n",
                 "DEFERRED   has :\t<MISSING>\n",
-                "DEAD       has :\t<MISSING>\n"
+                "DEAD       has :\t<MISSING>\n",
+                "HOLD       has :\t<MISSING>\n"
             ]
             .concat(),
         );
@@ -368,7 +396,8 @@ mod tests {
                 "DELIVER    has :\t<EMPTY>\n",
                 "DELEGATED  has :\t<EMPTY>\n",
                 "DEFERRED   has :\t<EMPTY>\n",
-                "DEAD       has :\t<EMPTY>\n"
+                "DEAD       has :\t<EMPTY>\n",
+                "HOLD       has :\t<EMPTY>\n"
             ]
             .concat(),
         );
@@ -414,6 +443,7 @@ mod tests {
                 "                        T    5   10   20   40   80  160  320  640 1280 1280+\n",
                 "               TOTAL    1    1    .    .    .    .    .    .    .    .    .\n",
                 "client.testserver.com    1    1    .    .    .    .    .    .    .    .    .\n",
+                "HOLD       has :\t<EMPTY>\n",
             ]
             .concat(),
         );
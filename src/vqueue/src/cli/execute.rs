@@ -46,26 +46,30 @@ impl Commands {
                 .await
             }
 
-            Self::Msg { msg, command } => match command {
-                MessageCommand::Show { format } => {
-                    Self::message_show(&msg, &queue_manager, &format, &mut std::io::stdout()).await
+            Self::Msg { msg, command } => {
+                let msg = Self::message_resolve(&msg, &queue_manager).await?;
+                match command {
+                    MessageCommand::Show { format } => {
+                        Self::message_show(&msg, &queue_manager, &format, &mut std::io::stdout())
+                            .await
+                    }
+                    MessageCommand::Move { queue } => {
+                        Self::message_move(&msg, &queue, queue_manager).await
+                    }
+                    MessageCommand::Remove { yes } => {
+                        Self::message_remove(
+                            &msg,
+                            yes,
+                            queue_manager,
+                            &mut std::io::stdout(),
+                            tokio::io::stdin(),
+                        )
+                        .await
+                    }
+                    #[allow(clippy::unimplemented)]
+                    MessageCommand::ReRun {} => unimplemented!(),
                 }
-                MessageCommand::Move { queue } => {
-                    Self::message_move(&msg, &queue, queue_manager).await
-                }
-                MessageCommand::Remove { yes } => {
-                    Self::message_remove(
-                        &msg,
-                        yes,
-                        queue_manager,
-                        &mut std::io::stdout(),
-                        tokio::io::stdin(),
-                    )
-                    .await
-                }
-                #[allow(clippy::unimplemented)]
-                MessageCommand::ReRun {} => unimplemented!(),
-            },
+            }
         }
     }
 }
@@ -14,7 +14,7 @@
  * this program. If not, see https://www.gnu.org/licenses/.
  *
  */
-use crate::{api::DetailedMailContext, GenericQueueManager, QueueID};
+use crate::{api::DetailedMailContext, GenericQueueManager, QueueAges, QueueID};
 use anyhow::Context;
 use vsmtp_common::{transport::DeserializerFn, ContextFinished};
 use vsmtp_config::Config;
@@ -33,6 +33,7 @@ pub trait FilesystemQueueManagerExt {
             | QueueID::Deferred
             | QueueID::Delegated
             | QueueID::Deliver
+            | QueueID::Hold
             | QueueID::Working => config.server.queues.dirpath.clone(),
             QueueID::Quarantine { .. } => config.app.dirpath.clone(),
         }
@@ -44,6 +45,12 @@ pub trait FilesystemQueueManagerExt {
         Self::get_root_folder(self.get_config(), queue).join(queue.to_string())
     }
 
+    /// Directory of the claims taken on the messages, see [`GenericQueueManager::claim`].
+    #[inline]
+    fn get_claims_path(&self) -> std::path::PathBuf {
+        self.get_config().server.queues.dirpath.join("claims")
+    }
+
     ///
     fn init(
         config: alloc::sync::Arc<Config>,
@@ -57,6 +64,190 @@ pub trait FilesystemQueueManagerExt {
 
     ///
     fn get_transport_deserializer(&self) -> &[DeserializerFn];
+
+    /// Identifier of this instance, unique among the instances sharing the spool.
+    fn get_instance_id(&self) -> &str;
+
+    /// see [`GenericQueueManager::get_ages`]
+    #[inline]
+    fn get_ages(&self) -> Option<&alloc::sync::Arc<QueueAges>> {
+        None
+    }
+}
+
+/// Claim of an instance on a message, stored at `claims/<msg-id>.claim`
+/// with the content `<instance-id> <nonce>`, and its heartbeat at
+/// `claims/<msg-id>.<nonce>.heartbeat`.
+///
+/// The nonce is unique to each claim, distinguishing two claims of the
+/// same instance (for example the startup scan and the scheduler racing).
+///
+/// The claim file is never modified while held, only its own heartbeat file is
+/// refreshed: an owner late to refresh its heartbeat cannot overwrite the claim
+/// of the instance which took it over, and finds out when reading the claim back.
+///
+/// NOTE: about sharing the spool over NFS:
+/// * the claim is created by writing a temporary file and hard linking it, as
+///   `link(2)` is atomic on NFS while `O_EXCL` is not guaranteed to be before `NFSv3`.
+///   The reply of a `link(2)` can be lost and the call retried by the client,
+///   reporting an error while the claim has been created: the claim is then
+///   read back and recognized by its nonce.
+/// * the heartbeat is the wall clock of the instance, compared to the wall clock
+///   of the others: the instances must be synchronized (NTP) with a skew far
+///   lower than `server.queues.claim_timeout`.
+/// * the attribute cache of the clients (`actimeo`) delays the visibility of
+///   the files, the timeout must be far greater than the cache duration.
+#[derive(Debug, PartialEq, Eq)]
+struct Claim {
+    instance: String,
+    nonce: String,
+}
+
+fn unix_now() -> u64 {
+    u64::try_from(vsmtp_common::clock::now().unix_timestamp()).unwrap_or(0)
+}
+
+/// Remove `path`, if it exists.
+async fn remove_if_exists(path: &std::path::Path) -> anyhow::Result<()> {
+    match tokio::fs::remove_file(path).await {
+        Ok(()) => Ok(()),
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(error) => Err(error).with_context(|| format!("failed to remove `{}`", path.display())),
+    }
+}
+
+impl Claim {
+    fn new(instance: &str) -> Self {
+        Self {
+            instance: instance.to_owned(),
+            nonce: uuid::Uuid::new_v4().simple().to_string(),
+        }
+    }
+
+    fn heartbeat_path(
+        &self,
+        claims: &std::path::Path,
+        msg_uuid: &uuid::Uuid,
+    ) -> std::path::PathBuf {
+        claims.join(format!("{msg_uuid}.{}.heartbeat", self.nonce))
+    }
+
+    /// Is the heartbeat older than `timeout` ?
+    ///
+    /// The heartbeat is written before the claim is created, and removed after it is
+    /// released: a claim without heartbeat is stale only if it is still held once the
+    /// heartbeat is found missing, otherwise it has just been released.
+    async fn is_stale(
+        &self,
+        claims: &std::path::Path,
+        msg_uuid: &uuid::Uuid,
+        timeout: core::time::Duration,
+    ) -> anyhow::Result<bool> {
+        let path = self.heartbeat_path(claims, msg_uuid);
+        let heartbeat = match tokio::fs::read_to_string(&path).await {
+            Ok(heartbeat) => heartbeat,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+                let claim_path = claims.join(format!("{msg_uuid}.claim"));
+                return Ok(Self::read(&claim_path).await?.as_ref() == Some(self));
+            }
+            Err(error) => {
+                return Err(error).with_context(|| format!("Cannot read `{}`", path.display()))
+            }
+        };
+        let heartbeat = heartbeat
+            .trim()
+            .parse::<u64>()
+            .with_context(|| format!("Invalid heartbeat at `{}`", path.display()))?;
+
+        Ok(unix_now().saturating_sub(heartbeat) > timeout.as_secs())
+    }
+
+    /// Write the heartbeat of the claim, replacing the previous one.
+    ///
+    /// The file is renamed over the previous heartbeat, so it exists at all times.
+    async fn beat(&self, claims: &std::path::Path, msg_uuid: &uuid::Uuid) -> anyhow::Result<()> {
+        let temporary = claims.join(format!("{msg_uuid}.{}.tmp", uuid::Uuid::new_v4().simple()));
+        tokio::fs::write(&temporary, format!("{}\n", unix_now()))
+            .await
+            .with_context(|| format!("Cannot write `{}`", temporary.display()))?;
+
+        let path = self.heartbeat_path(claims, msg_uuid);
+        if let Err(error) = tokio::fs::rename(&temporary, &path).await {
+            remove_if_exists(&temporary).await?;
+            return Err(error).with_context(|| format!("Cannot write `{}`", path.display()));
+        }
+        Ok(())
+    }
+
+    async fn read(path: &std::path::Path) -> anyhow::Result<Option<Self>> {
+        let content = match tokio::fs::read_to_string(path).await {
+            Ok(content) => content,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(error) => {
+                return Err(error).with_context(|| format!("Cannot read `{}`", path.display()))
+            }
+        };
+
+        let mut fields = content.split_whitespace();
+        match (fields.next(), fields.next(), fields.next()) {
+            (Some(instance), Some(nonce), None) => Ok(Some(Self {
+                instance: instance.to_owned(),
+                nonce: nonce.to_owned(),
+            })),
+            _ => anyhow::bail!("Invalid claim at `{}`", path.display()),
+        }
+    }
+
+    /// Write the claim to a temporary file, to be linked to its final path.
+    async fn write_temporary(
+        &self,
+        claims: &std::path::Path,
+        msg_uuid: &uuid::Uuid,
+    ) -> anyhow::Result<std::path::PathBuf> {
+        let path = claims.join(format!("{msg_uuid}.{}.tmp", self.nonce));
+        tokio::fs::write(&path, format!("{} {}\n", self.instance, self.nonce))
+            .await
+            .with_context(|| format!("Cannot write `{}`", path.display()))?;
+        Ok(path)
+    }
+
+    /// Move the claim at `claim_path` away to `aside`, if it is still `self`.
+    ///
+    /// The rename is atomic, only one instance can move a claim away. The claim read
+    /// before can have been replaced in the meantime: it is then given back, and
+    /// `false` is returned.
+    async fn move_aside(
+        &self,
+        claim_path: &std::path::Path,
+        aside: &std::path::Path,
+    ) -> anyhow::Result<bool> {
+        match tokio::fs::rename(claim_path, aside).await {
+            Ok(()) => (),
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+            Err(error) => {
+                return Err(error)
+                    .with_context(|| format!("Cannot move `{}`", claim_path.display()))
+            }
+        }
+
+        let moved = Self::read(aside).await?;
+        if moved.as_ref() != Some(self) {
+            match tokio::fs::hard_link(aside, claim_path).await {
+                Ok(()) => (),
+                Err(error) if error.kind() == std::io::ErrorKind::AlreadyExists => (),
+                Err(error) => {
+                    return Err(error)
+                        .with_context(|| format!("Cannot restore `{}`", claim_path.display()))
+                }
+            }
+            tokio::fs::remove_file(aside)
+                .await
+                .with_context(|| format!("failed to remove `{}`", aside.display()))?;
+            return Ok(false);
+        }
+
+        Ok(true)
+    }
 }
 
 #[allow(clippy::missing_trait_methods)]
@@ -83,6 +274,11 @@ impl<T: FilesystemQueueManagerExt + Send + Sync + core::fmt::Debug> GenericQueue
         T::get_transport_deserializer(self)
     }
 
+    #[inline]
+    fn get_ages(&self) -> Option<&alloc::sync::Arc<QueueAges>> {
+        T::get_ages(self)
+    }
+
     #[inline]
     #[tracing::instrument(skip(self))]
     async fn write_ctx(&self, queue: &QueueID, ctx: &ContextFinished) -> anyhow::Result<()> {
@@ -111,6 +307,9 @@ impl<T: FilesystemQueueManagerExt + Send + Sync + core::fmt::Debug> GenericQueue
         #[cfg(not(debug_assertions))]
         serde_json::to_writer(&mut buf_writer, ctx).context("failed to write context")?;
 
+        if let Some(ages) = T::get_ages(self) {
+            ages.enter(queue, msg_uuid);
+        }
         tracing::debug!(to = ?queue_path, "Email context written.");
 
         Ok(())
@@ -132,7 +331,7 @@ impl<T: FilesystemQueueManagerExt + Send + Sync + core::fmt::Debug> GenericQueue
                 .truncate(true)
                 .open(mails_eml)?;
 
-            std::io::Write::write_all(&mut file, msg.inner().to_string().as_bytes())?;
+            std::io::Write::write_all(&mut file, &msg.inner().to_bytes())?;
         }
         if let Some(parsed) = msg.get_parsed() {
             let mails_json = mails.join(format!("{msg_uuid}.json"));
@@ -160,6 +359,9 @@ impl<T: FilesystemQueueManagerExt + Send + Sync + core::fmt::Debug> GenericQueue
         std::fs::remove_file(&ctx_filepath)
             .with_context(|| format!("failed to remove `{}`", ctx_filepath.display()))?;
 
+        if let Some(ages) = T::get_ages(self) {
+            ages.leave(queue, msg_uuid);
+        }
         tracing::debug!(from = %queue, "Email context removed.");
 
         Ok(())
@@ -185,6 +387,134 @@ impl<T: FilesystemQueueManagerExt + Send + Sync + core::fmt::Debug> GenericQueue
         Ok(())
     }
 
+    #[inline]
+    #[tracing::instrument(skip(self))]
+    async fn claim(&self, msg_uuid: &uuid::Uuid) -> anyhow::Result<bool> {
+        let claims = self.get_claims_path();
+        tokio::fs::create_dir_all(&claims)
+            .await
+            .with_context(|| format!("Cannot create claims folder: `{}`", claims.display()))?;
+
+        let claim_path = claims.join(format!("{msg_uuid}.claim"));
+        let instance = self.get_instance_id();
+
+        // a second attempt is made if the claim has been released or taken over
+        // between the failed link and the read.
+        for _ in 0..2u8 {
+            let ours = Claim::new(instance);
+            ours.beat(&claims, msg_uuid).await?;
+            let temporary = ours.write_temporary(&claims, msg_uuid).await?;
+            let linked = tokio::fs::hard_link(&temporary, &claim_path).await;
+            tokio::fs::remove_file(&temporary)
+                .await
+                .with_context(|| format!("failed to remove `{}`", temporary.display()))?;
+
+            match linked {
+                Ok(()) => {
+                    tracing::debug!("Message claimed.");
+                    return Ok(true);
+                }
+                Err(error) if error.kind() == std::io::ErrorKind::AlreadyExists => (),
+                Err(error) => {
+                    remove_if_exists(&ours.heartbeat_path(&claims, msg_uuid)).await?;
+                    return Err(error)
+                        .with_context(|| format!("Cannot create `{}`", claim_path.display()));
+                }
+            }
+
+            let current = Claim::read(&claim_path).await?;
+            if current.as_ref() == Some(&ours) {
+                tracing::debug!("Message claimed.");
+                return Ok(true);
+            }
+            remove_if_exists(&ours.heartbeat_path(&claims, msg_uuid)).await?;
+
+            let Some(current) = current else {
+                continue;
+            };
+
+            if !current
+                .is_stale(
+                    &claims,
+                    msg_uuid,
+                    self.get_config().server.queues.claim_timeout,
+                )
+                .await?
+            {
+                return Ok(false);
+            }
+
+            tracing::warn!(owner = current.instance, "Taking over a stale claim.");
+
+            let tombstone = claims.join(format!("{msg_uuid}.{}.stale", ours.nonce));
+            if !current.move_aside(&claim_path, &tombstone).await? {
+                // released in the meantime, or taken over by another instance.
+                continue;
+            }
+
+            tokio::fs::remove_file(&tombstone)
+                .await
+                .with_context(|| format!("failed to remove `{}`", tombstone.display()))?;
+            remove_if_exists(&current.heartbeat_path(&claims, msg_uuid)).await?;
+        }
+
+        Ok(false)
+    }
+
+    #[inline]
+    #[tracing::instrument(skip(self))]
+    async fn heartbeat(&self, msg_uuid: &uuid::Uuid) -> anyhow::Result<bool> {
+        let claims = self.get_claims_path();
+        let claim_path = claims.join(format!("{msg_uuid}.claim"));
+
+        let current = match Claim::read(&claim_path).await? {
+            Some(current) if current.instance == self.get_instance_id() => current,
+            Some(_) | None => return Ok(false),
+        };
+
+        // NOTE: only the heartbeat file of this claim is written, the claim is read back
+        //       after it, so a claim taken over in the meantime is lost but never overwritten.
+        current.beat(&claims, msg_uuid).await?;
+
+        if Claim::read(&claim_path).await?.as_ref() != Some(&current) {
+            remove_if_exists(&current.heartbeat_path(&claims, msg_uuid)).await?;
+            tracing::warn!("Claim taken over by another instance.");
+            return Ok(false);
+        }
+        Ok(true)
+    }
+
+    #[inline]
+    #[tracing::instrument(skip(self))]
+    async fn release(&self, msg_uuid: &uuid::Uuid) -> anyhow::Result<()> {
+        let claims = self.get_claims_path();
+        let claim_path = claims.join(format!("{msg_uuid}.claim"));
+
+        match Claim::read(&claim_path).await? {
+            Some(current) if current.instance == self.get_instance_id() => {
+                let aside = claims.join(format!("{msg_uuid}.{}.released", current.nonce));
+                if current.move_aside(&claim_path, &aside).await? {
+                    tokio::fs::remove_file(&aside)
+                        .await
+                        .with_context(|| format!("failed to remove `{}`", aside.display()))?;
+                    remove_if_exists(&current.heartbeat_path(&claims, msg_uuid)).await?;
+                    tracing::debug!("Claim released.");
+                } else {
+                    tracing::warn!("Claim taken over by another instance.");
+                }
+            }
+            Some(current) => {
+                tracing::warn!(
+                    owner = current.instance,
+                    "Claim taken over by another instance."
+                );
+            }
+            None => (),
+        }
+
+        Ok(())
+    }
+
     #[inline]
     async fn list(&self, queue: &QueueID) -> anyhow::Result<Vec<anyhow::Result<String>>> {
         let queue_path = self.get_queue_path(queue);
@@ -277,11 +607,24 @@ impl<T: FilesystemQueueManagerExt + Send + Sync + core::fmt::Debug> GenericQueue
             format!("{msg_uuid}.eml").into(),
         ]);
 
-        let content = std::fs::read_to_string(&msg_filepath)
+        let content = std::fs::read(&msg_filepath)
             .with_context(|| format!("Cannot read file '{}'", msg_filepath.display()))?;
 
         // TODO: get parsed if exist
 
-        MessageBody::try_from(content.as_str())
+        MessageBody::try_from(content.as_slice())
+    }
+
+    #[inline]
+    #[tracing::instrument(skip(self))]
+    async fn get_msg_raw(&self, msg_uuid: &uuid::Uuid) -> anyhow::Result<Vec<u8>> {
+        let msg_filepath = std::path::PathBuf::from_iter([
+            self.get_config().server.queues.dirpath.clone(),
+            "mails".into(),
+            format!("{msg_uuid}.eml").into(),
+        ]);
+
+        std::fs::read(&msg_filepath)
+            .with_context(|| format!("Cannot read file '{}'", msg_filepath.display()))
     }
 }
@@ -14,7 +14,7 @@
  * this program. If not, see https://www.gnu.org/licenses/.
  *
  */
-use crate::{FilesystemQueueManagerExt, QueueID};
+use crate::{FilesystemQueueManagerExt, QueueAges, QueueID};
 use anyhow::Context;
 use vsmtp_common::transport::DeserializerFn;
 use vsmtp_config::Config;
@@ -26,6 +26,8 @@ extern crate alloc;
 pub struct QueueManager {
     config: alloc::sync::Arc<Config>,
     transport_deserializer: Vec<DeserializerFn>,
+    instance_id: String,
+    ages: alloc::sync::Arc<QueueAges>,
 }
 
 impl core::fmt::Debug for QueueManager {
@@ -35,6 +37,34 @@ impl core::fmt::Debug for QueueManager {
     }
 }
 
+impl QueueManager {
+    /// Read the messages already stored in the queues and the quarantines into the ages
+    /// (see [`FilesystemQueueManagerExt::get_ages`]), entered at the last modification of their context.
+    ///
+    /// The queues are not read by [`FilesystemQueueManagerExt::init`], a long running
+    /// instance calls it once at startup.
+    #[inline]
+    pub fn load_ages(&self) {
+        for queue in <QueueID as strum::IntoEnumIterator>::iter() {
+            if matches!(queue, QueueID::Quarantine { .. }) {
+                continue;
+            }
+            self.ages.load(&queue, &self.get_queue_path(&queue));
+        }
+        let quarantines = self.config.app.dirpath.join("quarantine");
+        for entry in quarantines.read_dir().into_iter().flatten().flatten() {
+            if let Some(name) = entry.file_name().to_str() {
+                self.ages.load(
+                    &QueueID::Quarantine {
+                        name: name.to_owned(),
+                    },
+                    &entry.path(),
+                );
+            }
+        }
+    }
+}
+
 #[allow(clippy::missing_trait_methods)]
 #[async_trait::async_trait]
 impl FilesystemQueueManagerExt for QueueManager {
@@ -52,9 +82,19 @@ impl FilesystemQueueManagerExt for QueueManager {
             })
             .collect::<anyhow::Result<Vec<_>>>()?;
 
+        let claims = config.server.queues.dirpath.join("claims");
+        std::fs::create_dir_all(&claims).with_context(|| {
+            format!(
+                "could not create claims directory at `{}`",
+                claims.display()
+            )
+        })?;
+
         Ok(alloc::sync::Arc::new(Self {
+            instance_id: format!("{}-{}", config.server.name, uuid::Uuid::new_v4().simple()),
             config,
             transport_deserializer,
+            ages: alloc::sync::Arc::default(),
         }))
     }
 
@@ -67,13 +107,238 @@ impl FilesystemQueueManagerExt for QueueManager {
     fn get_transport_deserializer(&self) -> &[DeserializerFn] {
         &self.transport_deserializer
     }
+
+    #[inline]
+    fn get_instance_id(&self) -> &str {
+        &self.instance_id
+    }
+
+    #[inline]
+    fn get_ages(&self) -> Option<&alloc::sync::Arc<QueueAges>> {
+        Some(&self.ages)
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use vsmtp_test::config::local_test;
+    use crate::{GenericQueueManager, QueueID};
+    use vsmtp_test::config::{local_ctx, local_msg, local_test};
     extern crate alloc;
 
+    fn shared_spool(spool: &std::path::Path) -> [alloc::sync::Arc<super::QueueManager>; 2] {
+        let mut config = local_test();
+        config.server.queues.dirpath = spool.to_path_buf();
+        let config = alloc::sync::Arc::new(config);
+
+        [
+            <super::QueueManager as GenericQueueManager>::init(
+                alloc::sync::Arc::clone(&config),
+                vec![],
+            )
+            .unwrap(),
+            <super::QueueManager as GenericQueueManager>::init(config, vec![]).unwrap(),
+        ]
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn exactly_once() {
+        let spool = tempfile::tempdir().unwrap();
+        let instances = shared_spool(spool.path());
+
+        let mut uuids = vec![];
+        for _ in 0..50u8 {
+            let mut ctx = local_ctx();
+            ctx.mail_from.message_uuid = uuid::Uuid::new_v4();
+            instances[0]
+                .write_both(&QueueID::Deliver, &ctx, &local_msg())
+                .await
+                .unwrap();
+            uuids.push(ctx.mail_from.message_uuid);
+        }
+
+        let processed = alloc::sync::Arc::new(std::sync::Mutex::new(vec![]));
+        let workers = instances
+            .iter()
+            .flat_map(|instance| {
+                [
+                    alloc::sync::Arc::clone(instance),
+                    alloc::sync::Arc::clone(instance),
+                ]
+            })
+            .map(|instance| {
+                let processed = alloc::sync::Arc::clone(&processed);
+                tokio::spawn(async move {
+                    for id in instance.list(&QueueID::Deliver).await.unwrap() {
+                        let msg_uuid = uuid::Uuid::parse_str(&id.unwrap()).unwrap();
+                        if !instance.claim(&msg_uuid).await.unwrap() {
+                            continue;
+                        }
+                        // processed and released by another worker since the listing.
+                        if instance.get_ctx(&QueueID::Deliver, &msg_uuid).await.is_ok() {
+                            processed.lock().unwrap().push(msg_uuid);
+                            instance
+                                .move_to_from_id(&QueueID::Deliver, &QueueID::Dead, &msg_uuid)
+                                .await
+                                .unwrap();
+                        }
+                        instance.release(&msg_uuid).await.unwrap();
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for worker in workers {
+            worker.await.unwrap();
+        }
+
+        let mut processed = processed.lock().unwrap().clone();
+        processed.sort();
+        uuids.sort();
+        assert_eq!(processed, uuids);
+        assert_eq!(
+            std::fs::read_dir(spool.path().join("claims"))
+                .unwrap()
+                .count(),
+            0
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn heartbeat_while_claiming() {
+        let spool = tempfile::tempdir().unwrap();
+        let [first, second] = shared_spool(spool.path());
+        let msg_uuid = uuid::Uuid::new_v4();
+
+        assert!(first.claim(&msg_uuid).await.unwrap());
+
+        // the claim exists at all times while refreshed, another instance never takes it.
+        let refresh = {
+            let first = alloc::sync::Arc::clone(&first);
+            tokio::spawn(async move {
+                for _ in 0..100 {
+                    assert!(first.heartbeat(&msg_uuid).await.unwrap());
+                }
+            })
+        };
+        for _ in 0..100 {
+            assert!(!second.claim(&msg_uuid).await.unwrap());
+        }
+        refresh.await.unwrap();
+
+        first.release(&msg_uuid).await.unwrap();
+        assert_eq!(
+            std::fs::read_dir(spool.path().join("claims"))
+                .unwrap()
+                .count(),
+            0
+        );
+    }
+
+    #[tokio::test]
+    async fn stale_claim() {
+        let clock = vsmtp_test::clock::TestClock::start();
+        let spool = tempfile::tempdir().unwrap();
+        let [first, second] = shared_spool(spool.path());
+        let msg_uuid = uuid::Uuid::new_v4();
+
+        assert!(first.claim(&msg_uuid).await.unwrap());
+        assert!(!second.claim(&msg_uuid).await.unwrap());
+        assert!(!first.claim(&msg_uuid).await.unwrap());
+        assert!(first.heartbeat(&msg_uuid).await.unwrap());
+        assert!(!second.heartbeat(&msg_uuid).await.unwrap());
+
+        // the owner stalled, and its heartbeat aged out.
+        clock.advance(
+            time::Duration::try_from(first.config.server.queues.claim_timeout).unwrap()
+                + time::Duration::seconds(1),
+        );
+        let claim_path = spool
+            .path()
+            .join("claims")
+            .join(format!("{msg_uuid}.claim"));
+
+        assert!(second.claim(&msg_uuid).await.unwrap());
+        assert!(!first.claim(&msg_uuid).await.unwrap());
+        // the late heartbeat of the previous owner does not overwrite the new claim.
+        assert!(!first.heartbeat(&msg_uuid).await.unwrap());
+        assert!(std::fs::read_to_string(&claim_path)
+            .unwrap()
+            .starts_with(&second.instance_id));
+        assert!(second.heartbeat(&msg_uuid).await.unwrap());
+        assert_eq!(
+            std::fs::read_dir(spool.path().join("claims"))
+                .unwrap()
+                .count(),
+            2
+        );
+
+        // the previous owner cannot release the claim of the new one.
+        first.release(&msg_uuid).await.unwrap();
+        assert!(claim_path.exists());
+        second.release(&msg_uuid).await.unwrap();
+        assert!(!claim_path.exists());
+        assert!(first.claim(&msg_uuid).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn load_ages() {
+        let spool = tempfile::tempdir().unwrap();
+        let [first, second] = shared_spool(spool.path());
+
+        let mut ctx = local_ctx();
+        ctx.mail_from.message_uuid = uuid::Uuid::new_v4();
+        first
+            .write_both(&QueueID::Deliver, &ctx, &local_msg())
+            .await
+            .unwrap();
+        assert_eq!(first.get_ages().unwrap().count("deliver"), 1);
+
+        // the queues are not read by `init`, only on demand.
+        let ages = second.get_ages().unwrap();
+        assert_eq!(ages.count("deliver"), 0);
+        second.load_ages();
+        assert_eq!(ages.count("deliver"), 1);
+        assert!(ages.oldest("deliver").is_some());
+    }
+
+    #[tokio::test]
+    async fn raw_message() {
+        let spool = tempfile::tempdir().unwrap();
+        let [queue_manager, _] = shared_spool(spool.path());
+
+        let mut ctx = local_ctx();
+        ctx.mail_from.message_uuid = uuid::Uuid::new_v4();
+        let msg_uuid = ctx.mail_from.message_uuid;
+        queue_manager
+            .write_both(&QueueID::Deliver, &ctx, &local_msg())
+            .await
+            .unwrap();
+        assert_eq!(
+            queue_manager.get_msg_raw(&msg_uuid).await.unwrap(),
+            local_msg().inner().to_string().into_bytes()
+        );
+
+        // the folding, the spacing and the line endings are kept as received.
+        let received =
+            b"Subject:  folded\r\n\tsubject \r\nX-Empty:\r\n\r\nbody  \nlast line".to_vec();
+        std::fs::write(
+            spool.path().join(format!("mails/{msg_uuid}.eml")),
+            &received,
+        )
+        .unwrap();
+
+        let (raw_ctx, raw) = queue_manager
+            .get_both_raw(&QueueID::Deliver, &msg_uuid)
+            .await
+            .unwrap();
+        assert_eq!(raw_ctx, ctx);
+        assert_eq!(raw, received);
+        assert!(queue_manager
+            .get_msg_raw(&uuid::Uuid::new_v4())
+            .await
+            .is_err());
+    }
+
     #[test]
     fn debug() {
         assert_eq!(
@@ -15,7 +15,7 @@
  *
  */
 
-use crate::{FilesystemQueueManagerExt, QueueID};
+use crate::{FilesystemQueueManagerExt, QueueAges, QueueID};
 use anyhow::Context;
 use vsmtp_common::transport::DeserializerFn;
 use vsmtp_config::Config;
@@ -27,6 +27,8 @@ pub struct QueueManager {
     config: alloc::sync::Arc<Config>,
     pub(crate) tempdir: tempfile::TempDir,
     transport_deserializer: Vec<DeserializerFn>,
+    instance_id: String,
+    ages: alloc::sync::Arc<QueueAges>,
 }
 
 impl core::fmt::Debug for QueueManager {
@@ -45,9 +47,11 @@ impl FilesystemQueueManagerExt for QueueManager {
         transport_deserializer: Vec<DeserializerFn>,
     ) -> anyhow::Result<alloc::sync::Arc<Self>> {
         let this = alloc::sync::Arc::new(Self {
-            config,
             tempdir: tempfile::Builder::new().rand_bytes(20).tempdir()?,
+            instance_id: format!("{}-{}", config.server.name, uuid::Uuid::new_v4().simple()),
+            config,
             transport_deserializer,
+            ages: alloc::sync::Arc::default(),
         });
 
         for i in <QueueID as strum::IntoEnumIterator>::iter() {
@@ -71,10 +75,27 @@ impl FilesystemQueueManagerExt for QueueManager {
         &self.transport_deserializer
     }
 
+    #[inline]
+    fn get_instance_id(&self) -> &str {
+        &self.instance_id
+    }
+
+    #[inline]
+    fn get_ages(&self) -> Option<&alloc::sync::Arc<QueueAges>> {
+        Some(&self.ages)
+    }
+
     #[inline]
     fn get_queue_path(&self, queue: &QueueID) -> std::path::PathBuf {
         self.tempdir
             .path()
             .join(Self::get_root_folder(&self.config, queue).join(queue.to_string()))
     }
+
+    #[inline]
+    fn get_claims_path(&self) -> std::path::PathBuf {
+        self.tempdir
+            .path()
+            .join(self.config.server.queues.dirpath.join("claims"))
+    }
 }
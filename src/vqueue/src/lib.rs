@@ -60,15 +60,19 @@ pub mod cli {
         ///
         pub mod message_remove;
         ///
+        pub mod message_resolve;
+        ///
         pub mod message_show;
         ///
         pub mod show;
     }
 }
 
+mod ages;
 mod api;
 mod extension;
-pub use api::{GenericQueueManager, QueueID};
+pub use ages::{QueueAges, StuckMessages};
+pub use api::{DetailedMailContext, GenericQueueManager, MoveAllReport, QueueID};
 pub use extension::FilesystemQueueManagerExt;
 
 mod implementation {
@@ -81,10 +85,14 @@ mod implementation {
     /// ```shell
     /// $> tree -L 2 /var/spool/vsmtp
     /// /var/spool/vsmtp
+    /// ├── claims                 # claims of the instances sharing the spool on the messages
+    /// │   ├── <msg-id>.claim     # * "<instance-id> <nonce>", see [`GenericQueueManager::claim`]
+    /// │   └── <msg-id>.<nonce>.heartbeat # * "<heartbeat>", refreshed by the owner of the claim
     /// ├── dead                   # fatal error happened
     /// ├── delegated              # [`delegation flow`] (smtp ping/pong with another service)
     /// ├── deliver                # to deliver (first attempt)
     /// ├── deferred               # to deliver (1..N) times (at least one error occurred before)
+    /// ├── hold                   # put on hold by an administrator, until released
     /// ├── mails                  # the message body (received between DATA and "<CRLF>.<CRLF>"
     /// │   ├── <msg-id>.eml       # * stored as received (not modified)
     /// │   └── <msg-id-2>.json    # * parsed and stored in .json (possibly modified)
@@ -31,13 +31,14 @@ mod tests {
         mod signature_header;
     }
     mod canonicalization;
+    mod key_management;
 }
 
 const RSA_MINIMUM_ACCEPTABLE_KEY_SIZE: usize = 1024;
 
 pub use algorithm::{HashAlgorithm, SigningAlgorithm};
 pub use canonicalization::Canonicalization;
-pub use private_key::PrivateKey;
+pub use private_key::{KeyAlgorithm, PrivateKey};
 pub use public_key::PublicKey;
 pub use sign::{sign, SigningError};
 pub use signature::Signature;
@@ -15,7 +15,21 @@
  *
 */
 
-use super::{sign::InnerError, BackendError, SigningAlgorithm, RSA_MINIMUM_ACCEPTABLE_KEY_SIZE};
+use super::{
+    public_key::InnerPublicKey, sign::InnerError, BackendError, PublicKey, SigningAlgorithm,
+    RSA_MINIMUM_ACCEPTABLE_KEY_SIZE,
+};
+use crate::ParseError;
+
+/// Algorithm of a key pair produced by [`PrivateKey::generate`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq, strum::EnumString, strum::Display)]
+#[strum(serialize_all = "lowercase")]
+pub enum KeyAlgorithm {
+    ///
+    Ed25519,
+    /// RSA with a modulus of 2048 bits.
+    Rsa2048,
+}
 
 ///
 pub enum PrivateKey {
@@ -26,6 +40,71 @@ pub enum PrivateKey {
 }
 
 impl PrivateKey {
+    /// Generate a new key pair, returning the key and its PKCS#8 PEM encoding.
+    ///
+    /// # Errors
+    ///
+    /// * the random number generator failed
+    /// * the key could not be encoded
+    pub fn generate(algorithm: KeyAlgorithm) -> anyhow::Result<(Self, String)> {
+        match algorithm {
+            KeyAlgorithm::Ed25519 => {
+                let document = ring_compat::ring::signature::Ed25519KeyPair::generate_pkcs8(
+                    &ring_compat::ring::rand::SystemRandom::new(),
+                )
+                .map_err(|_| anyhow::anyhow!("failed to generate an ed25519 key"))?;
+
+                let key =
+                    ring_compat::ring::signature::Ed25519KeyPair::from_pkcs8(document.as_ref())
+                        .map_err(|e| {
+                            anyhow::anyhow!("failed to parse the generated ed25519 key: {e}")
+                        })?;
+
+                let pem = rsa::pkcs8::der::pem::encode_string(
+                    "PRIVATE KEY",
+                    rsa::pkcs8::LineEnding::LF,
+                    document.as_ref(),
+                )
+                .map_err(|e| anyhow::anyhow!("failed to encode the ed25519 key: {e}"))?;
+
+                Ok((Self::Ed25519(Box::new(key)), pem))
+            }
+            KeyAlgorithm::Rsa2048 => {
+                let key = rsa::RsaPrivateKey::new(&mut rsa::rand_core::OsRng, 2048)?;
+                let pem =
+                    rsa::pkcs8::EncodePrivateKey::to_pkcs8_pem(&key, rsa::pkcs8::LineEnding::LF)
+                        .map_err(|e| anyhow::anyhow!("failed to encode the rsa key: {e}"))?
+                        .to_string();
+
+                Ok((Self::Rsa(Box::new(key)), pem))
+            }
+        }
+    }
+
+    /// The public key associated, to publish in the `<selector>._domainkey.<sdid>` DNS record.
+    ///
+    /// # Errors
+    ///
+    /// * the RSA key is too small, or could not be encoded
+    pub fn public_key(&self) -> Result<PublicKey, ParseError> {
+        let inner = match self {
+            Self::Rsa(rsa) => InnerPublicKey::Rsa(rsa::RsaPublicKey::from(rsa.as_ref())),
+            Self::Ed25519(ed25519) => InnerPublicKey::Ed25519(
+                ring_compat::signature::ed25519::VerifyingKey::from_slice(
+                    <ring_compat::ring::signature::Ed25519KeyPair as ring_compat::ring::signature::KeyPair>::public_key(
+                        ed25519,
+                    )
+                    .as_ref(),
+                )
+                .map_err(|e| ParseError::InvalidArgument {
+                    reason: format!("invalid Ed25519 public key: {e}"),
+                })?,
+            ),
+        };
+
+        PublicKey::from_inner(inner)
+    }
+
     pub(super) const fn get_preferred_signing_algo(&self) -> SigningAlgorithm {
         match self {
             Self::Rsa(_) => SigningAlgorithm::RsaSha256,
@@ -113,6 +113,26 @@ impl PublicKey {
     pub fn has_debug_flag(&self) -> bool {
         self.record.flags.iter().any(|f| *f == Flags::Testing)
     }
+
+    pub(super) fn from_inner(inner: InnerPublicKey) -> Result<Self, ParseError> {
+        Ok(Self {
+            record: Record::try_from(&inner)?,
+            inner,
+        })
+    }
+
+    /// Is it the same key, regardless of the other tags of the record?
+    #[must_use]
+    pub fn same_key(&self, other: &Self) -> bool {
+        self.inner == other.inner
+    }
+}
+
+/// The value of the `TXT` record to publish at `<selector>._domainkey.<sdid>`.
+impl std::fmt::Display for PublicKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.record.fmt(f)
+    }
 }
 
 impl std::str::FromStr for PublicKey {
@@ -135,7 +155,7 @@ impl TryFrom<rsa::RsaPublicKey> for PublicKey {
     fn try_from(key: rsa::RsaPublicKey) -> Result<Self, Self::Error> {
         let inner = InnerPublicKey::Rsa(key);
         Ok(Self {
-            record: Record::try_from(&inner)?,
+            record: Record::try_from(&inner).map_err(|_| ())?,
             inner,
         })
     }
@@ -152,7 +172,7 @@ impl TryFrom<&ring_compat::ring::signature::Ed25519KeyPair> for PublicKey {
 
         let inner = InnerPublicKey::Ed25519(key);
         Ok(Self {
-            record: Record::try_from(&inner)?,
+            record: Record::try_from(&inner).map_err(|_| ())?,
             inner,
         })
     }
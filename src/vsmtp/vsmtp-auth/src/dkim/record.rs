@@ -174,19 +174,71 @@ impl std::str::FromStr for Record {
     }
 }
 
-#[cfg(test)]
+impl std::fmt::Display for Record {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "v={}", self.version)?;
+        if !self.acceptable_hash_algorithms.is_empty() {
+            write!(
+                f,
+                "; h={}",
+                self.acceptable_hash_algorithms
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join(":")
+            )?;
+        }
+        write!(f, "; k={}", self.r#type)?;
+        if let Some(notes) = &self.notes {
+            write!(f, "; n={notes}")?;
+        }
+        write!(f, "; p={}", STANDARD.encode(&self.public_key))?;
+        if self.service_type != [ServiceType::Wildcard] {
+            write!(
+                f,
+                "; s={}",
+                self.service_type
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join(":")
+            )?;
+        }
+        if !self.flags.is_empty() {
+            write!(
+                f,
+                "; t={}",
+                self.flags
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join(":")
+            )?;
+        }
+        Ok(())
+    }
+}
+
 mod try_from {
     use super::{HashAlgorithm, Record, ServiceType, Type, Version};
-    use crate::dkim::{public_key::InnerPublicKey, RSA_MINIMUM_ACCEPTABLE_KEY_SIZE};
+    use crate::{
+        dkim::{public_key::InnerPublicKey, RSA_MINIMUM_ACCEPTABLE_KEY_SIZE},
+        ParseError,
+    };
 
     impl TryFrom<&InnerPublicKey> for Record {
-        type Error = ();
+        type Error = ParseError;
 
         fn try_from(inner: &InnerPublicKey) -> Result<Self, Self::Error> {
             match inner {
                 InnerPublicKey::Rsa(rsa) => {
-                    if rsa::PublicKeyParts::size(rsa) * 8 < RSA_MINIMUM_ACCEPTABLE_KEY_SIZE {
-                        return Err(());
+                    let size = rsa::PublicKeyParts::size(rsa) * 8;
+                    if size < RSA_MINIMUM_ACCEPTABLE_KEY_SIZE {
+                        return Err(ParseError::InvalidArgument {
+                            reason: format!(
+                                "invalid key size: {size} bits, was expecting at least {RSA_MINIMUM_ACCEPTABLE_KEY_SIZE} bits"
+                            ),
+                        });
                     }
                     Ok(Self {
                         version: Version::Dkim1,
@@ -198,7 +250,9 @@ mod try_from {
                         r#type: Type::Rsa,
                         notes: None,
                         public_key: rsa::pkcs8::EncodePublicKey::to_public_key_der(rsa)
-                            .unwrap()
+                            .map_err(|e| ParseError::InvalidArgument {
+                                reason: format!("invalid RSA public key: {e}"),
+                            })?
                             .as_ref()
                             .to_vec(),
                         service_type: vec![ServiceType::Wildcard],
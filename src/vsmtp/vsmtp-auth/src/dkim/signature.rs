@@ -83,6 +83,21 @@ impl Signature {
         })
     }
 
+    /// The selector of the public key, tag "s="
+    #[must_use]
+    pub fn selector(&self) -> &str {
+        &self.selector
+    }
+
+    /// The names of the headers covered by the signature, in the order of the tag "h="
+    ///
+    /// A name can be listed several times, to cover several instances of the header,
+    /// or more than there is to prevent the addition of new instances.
+    #[must_use]
+    pub fn headers_field(&self) -> &[String] {
+        &self.headers_field
+    }
+
     ///
     #[must_use]
     pub fn get_dns_query(&self) -> String {
@@ -93,14 +108,15 @@ impl Signature {
         )
     }
 
-    ///
+    /// The value of the header, to be written after `DKIM-Signature: `, as it has been hashed.
     #[must_use]
     pub fn get_signature_value(&self) -> String {
-        self.raw[HEADER_KEY_LOWER.len()..].to_string()
+        self.raw[HEADER_KEY_LOWER.len()..].trim_start().to_string()
     }
 
     fn signature_without_headers(&self) -> String {
-        let mut out = self.raw.to_string();
+        // NOTE: the header is hashed without its trailing CRLF (RFC 6376 3.7).
+        let mut out = self.raw.trim_end_matches("\r\n").to_string();
         if self.signature.is_empty() {
             return out;
         }
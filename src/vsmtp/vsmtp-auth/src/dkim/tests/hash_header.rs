@@ -66,7 +66,7 @@ fn mail_5() {
             "Subject: after dns update\r\nDKIM-Signature: v=1; a=rsa-sha256; c=simple/simple; d=example.com; s=mail;\r\n",
             "\tt=1659541683; bh=Touenr7dUe0Mxv9r3OfnQ+GHpFRIdDa3Wa3TWnDOQKs=;\r\n",
             "\th=Date:To:From:Subject:From;\r\n",
-            "\tb=\r\n"
+            "\tb="
         )
     );
 
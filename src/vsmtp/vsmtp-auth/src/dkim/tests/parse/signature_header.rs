@@ -75,7 +75,7 @@ fn from_str_wikipedia() {
 
     pretty_assertions::assert_eq!(
         sign.get_signature_value(),
-        signature["DKIM-Signature:".len()..]
+        signature["DKIM-Signature: ".len()..]
     );
 
     assert!(sign.has_expired(100));
@@ -33,6 +33,21 @@ pub enum Credentials {
         /// [ email / 1*255TCHAR ]
         token: String,
     },
+    /// the client presented a TLS certificate issued by a trusted authority
+    Certificate {
+        /// subject of the certificate, identifying the client
+        subject: String,
+    },
+    /// the client authenticated by its certificate with the `EXTERNAL` mechanism,
+    /// requesting to act as an identity to be validated against the certificate
+    External {
+        /// identity requested by the client, empty to act as the subject of the certificate
+        authzid: String,
+        /// subject of the certificate
+        subject: String,
+        /// domains and email addresses of the subject alternative names of the certificate
+        alt_names: Vec<String>,
+    },
 }
 
 #[cfg(not(debug_assertions))]
@@ -48,6 +63,20 @@ impl std::fmt::Debug for Credentials {
                 .debug_struct("Credentials::AnonymousToken")
                 .field("token", &"***")
                 .finish(),
+            Credentials::Certificate { subject } => f
+                .debug_struct("Credentials::Certificate")
+                .field("subject", subject)
+                .finish(),
+            Credentials::External {
+                authzid,
+                subject,
+                alt_names,
+            } => f
+                .debug_struct("Credentials::External")
+                .field("authzid", authzid)
+                .field("subject", subject)
+                .field("alt_names", alt_names)
+                .finish(),
         }
     }
 }
@@ -73,6 +102,23 @@ impl serde::Serialize for Credentials {
                 s.serialize_field("token", "***")?;
                 s.end()
             }
+            Credentials::Certificate { subject } => {
+                let mut s =
+                    serializer.serialize_struct_variant("Credentials", 2, "Certificate", 1)?;
+                s.serialize_field("subject", subject)?;
+                s.end()
+            }
+            Credentials::External {
+                authzid,
+                subject,
+                alt_names,
+            } => {
+                let mut s = serializer.serialize_struct_variant("Credentials", 3, "External", 3)?;
+                s.serialize_field("authzid", authzid)?;
+                s.serialize_field("subject", subject)?;
+                s.serialize_field("alt_names", alt_names)?;
+                s.end()
+            }
         }
     }
 }
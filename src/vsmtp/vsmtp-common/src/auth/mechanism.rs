@@ -43,8 +43,11 @@ pub enum Mechanism {
     /// Common
     /// See <https://datatracker.ietf.org/doc/html/rfc4505>
     Anonymous,
+    /// The client is authenticated by the certificate presented in the TLS handshake,
+    /// and only sends the identity it requests to act as.
+    /// See <https://datatracker.ietf.org/doc/html/rfc4422#appendix-A>
+    External,
     /*
-    - EXTERNAL
     - SECURID
     - DIGEST-MD5
     - SCRAM-SHA-1
@@ -65,7 +68,7 @@ impl Mechanism {
     #[must_use]
     pub const fn client_first(self) -> bool {
         match self {
-            Self::Plain | Self::Anonymous => true,
+            Self::Plain | Self::Anonymous | Self::External => true,
             Self::Login | Self::CramMd5 => false,
         }
     }
@@ -75,7 +78,7 @@ impl Mechanism {
     #[must_use]
     pub const fn must_be_under_tls(self) -> bool {
         match self {
-            Self::Plain | Self::Login | Self::CramMd5 | Self::Anonymous => true,
+            Self::Plain | Self::Login | Self::CramMd5 | Self::Anonymous | Self::External => true,
         }
     }
 }
@@ -91,6 +94,7 @@ mod tests {
         assert_eq!(Mechanism::Login.to_string(), "LOGIN");
         assert_eq!(Mechanism::CramMd5.to_string(), "CRAM-MD5");
         assert_eq!(Mechanism::Anonymous.to_string(), "ANONYMOUS");
+        assert_eq!(Mechanism::External.to_string(), "EXTERNAL");
     }
 
     #[test]
@@ -0,0 +1,136 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2022 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+
+/// Get the current time (UTC).
+///
+/// The time-based logic (timestamps of the transaction, retry schedule of the
+/// deferred queue, ...) must use this function instead of [`time::OffsetDateTime::now_utc`],
+/// so that the tests can drive the time with [`mock`].
+#[must_use]
+#[inline]
+pub fn now() -> time::OffsetDateTime {
+    #[cfg(feature = "testing")]
+    if let Some(now) = mock::get() {
+        return now;
+    }
+
+    time::OffsetDateTime::now_utc()
+}
+
+/// Control of the clock and of the identifiers ([`crate::id`]) for the tests.
+///
+/// They are mocked for the current thread only: the tests running in parallel
+/// do not interfere, but the code executed by another thread (for instance a task
+/// spawned on a multi-threaded runtime) still reads the real sources.
+#[cfg(feature = "testing")]
+pub mod mock {
+    #[derive(Clone, Copy)]
+    struct State {
+        now: Option<time::OffsetDateTime>,
+        ids: Option<u64>,
+    }
+
+    thread_local! {
+        static STATE: core::cell::Cell<State> = const {
+            core::cell::Cell::new(State { now: None, ids: None })
+        };
+    }
+
+    fn update(f: impl FnOnce(&mut State)) {
+        STATE.with(|cell| {
+            let mut state = cell.get();
+            f(&mut state);
+            cell.set(state);
+        });
+    }
+
+    pub(super) fn get() -> Option<time::OffsetDateTime> {
+        STATE.with(|cell| cell.get().now)
+    }
+
+    /// Freeze the clock of the current thread at `now`.
+    #[inline]
+    pub fn set(now: time::OffsetDateTime) {
+        update(|state| state.now = Some(now));
+    }
+
+    /// Move the clock of the current thread forward, freezing it at the current time if it was not.
+    #[inline]
+    pub fn advance(duration: time::Duration) {
+        set(super::now() + duration);
+    }
+
+    /// Generate the identifiers of the current thread from `seed`: the same seed
+    /// produces the same sequence of (valid v4) identifiers.
+    #[inline]
+    pub fn seed_ids(seed: u64) {
+        update(|state| state.ids = Some(seed));
+    }
+
+    pub(crate) fn next_id() -> Option<uuid::Uuid> {
+        let mut out = None;
+        update(|state| out = state.ids.as_mut().map(crate::id::from_seed));
+        out
+    }
+
+    /// Use the system clock and random identifiers again.
+    #[inline]
+    pub fn reset() {
+        update(|state| {
+            *state = State {
+                now: None,
+                ids: None,
+            }
+        });
+    }
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use super::*;
+    use time::ext::NumericalDuration;
+
+    #[test]
+    fn mocked() {
+        let start = time::OffsetDateTime::UNIX_EPOCH;
+
+        mock::set(start);
+        assert_eq!(now(), start);
+
+        mock::advance(5.minutes());
+        assert_eq!(now(), start + 5.minutes());
+
+        let other_thread = std::thread::spawn(now).join().unwrap();
+        assert_ne!(other_thread, start + 5.minutes());
+
+        mock::reset();
+        assert!(now() > start + 5.minutes());
+    }
+
+    #[test]
+    fn reset_ids() {
+        mock::seed_ids(42);
+        let seeded = crate::id::new_uuid();
+
+        mock::reset();
+        mock::seed_ids(42);
+        assert_eq!(crate::id::new_uuid(), seeded);
+
+        mock::reset();
+        assert_ne!(crate::id::new_uuid(), seeded);
+    }
+}
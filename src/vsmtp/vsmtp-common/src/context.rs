@@ -18,7 +18,8 @@ use crate::{
     auth::Credentials,
     status, transfer,
     transport::{AbstractTransport, DeliverTo, WrapperSerde},
-    Address, CipherSuite, ClientName, Domain, ProtocolVersion,
+    Address, AddressExtension, CipherSuite, ClientName, DeliveryStrategy, Domain, MimeBodyType,
+    OriginalRecipient, ProtocolVersion, SessionCategory, SubmissionQuota,
 };
 use vsmtp_auth::{dkim, spf};
 
@@ -60,9 +61,9 @@ pub enum Stage {
     Finished,
 }
 
-// FIXME: remove clone ? used to create a copy of the context for internal state
-// and serde::Serialize (= only used for vsl::dump function)
-// (and serde::Serialize for other sub-context)
+// NOTE: clone is only used to create a copy of the context for internal state,
+// the stage transitions move the properties instead of cloning them.
+// serde::Serialize is only used for vsl::dump function (and for other sub-context)
 /// A step-by-step SMTP envelop produced by the transaction
 #[derive(Debug, Clone, serde::Serialize)]
 pub enum Context {
@@ -165,24 +166,51 @@ impl Context {
         }
     }
 
+    /// Move the context out of `self`, leaving an empty one in place.
+    ///
+    /// Used by the stage transitions to move the properties instead of cloning them,
+    /// the placeholder does not allocate.
+    fn take(&mut self) -> Self {
+        core::mem::replace(
+            self,
+            Self::Connect(ContextConnect {
+                connect: ConnectProperties {
+                    connect_timestamp: time::OffsetDateTime::UNIX_EPOCH,
+                    connect_uuid: uuid::Uuid::nil(),
+                    client_addr: (std::net::Ipv4Addr::UNSPECIFIED, 0).into(),
+                    server_addr: (std::net::Ipv4Addr::UNSPECIFIED, 0).into(),
+                    server_name: Domain::root(),
+                    skipped: None,
+                    tls: None,
+                    auth: None,
+                    error_count: 0,
+                    sender_churn: 0,
+                    banner: None,
+                    session_category: None,
+                    submission_quota: None,
+                },
+            }),
+        )
+    }
+
     /// Called when a "RSET" is issued
     #[inline]
     pub fn reset(&mut self) {
-        match self {
-            Self::Connect(_) => (),
+        *self = match self.take() {
+            connect @ Self::Connect(_) => connect,
             Self::Helo(ContextHelo { connect, helo })
             | Self::MailFrom(ContextMailFrom { connect, helo, .. })
             | Self::RcptTo(ContextRcptTo { connect, helo, .. })
             | Self::Finished(ContextFinished { connect, helo, .. }) => {
-                *self = Self::Helo(ContextHelo {
-                    connect: connect.clone(),
-                    helo: helo.clone(),
-                });
+                Self::Helo(ContextHelo { connect, helo })
             }
-        }
+        };
     }
 
     /// Convert the context to a [`ContextConnect`]
+    ///
+    /// The address of the client is stored in its canonical form,
+    /// see [`crate::utils::canonical_socket_addr`].
     #[inline]
     #[must_use]
     pub fn new(
@@ -196,12 +224,17 @@ impl Context {
             connect: ConnectProperties {
                 connect_timestamp: timestamp,
                 connect_uuid: uuid,
-                client_addr,
+                client_addr: crate::utils::canonical_socket_addr(client_addr),
                 server_addr,
                 server_name,
                 skipped: None,
                 tls: None,
                 auth: None,
+                error_count: 0,
+                sender_churn: 0,
+                banner: None,
+                session_category: None,
+                submission_quota: None,
             },
         })
     }
@@ -218,14 +251,20 @@ impl Context {
         using_deprecated: bool,
     ) -> Result<&mut Self, Error> {
         match self {
-            Self::Connect(ContextConnect { connect }) => {
-                *self = Self::Helo(ContextHelo {
-                    connect: connect.clone(),
-                    helo: HeloProperties {
-                        client_name,
-                        using_deprecated,
-                    },
-                });
+            Self::Connect(_) => {
+                *self = match self.take() {
+                    Self::Connect(ContextConnect { connect }) => Self::Helo(ContextHelo {
+                        connect,
+                        helo: HeloProperties {
+                            client_name,
+                            using_deprecated,
+                        },
+                    }),
+                    other @ (Self::Helo(_)
+                    | Self::MailFrom(_)
+                    | Self::RcptTo(_)
+                    | Self::Finished(_)) => other,
+                };
                 Ok(self)
             }
             Self::Helo(ContextHelo { helo, .. }) => {
@@ -246,9 +285,10 @@ impl Context {
     pub fn with_credentials(&mut self, credentials: Credentials) -> Result<(), Error> {
         match self {
             Self::Connect(ContextConnect { connect }) | Self::Helo(ContextHelo { connect, .. }) => {
+                let cancel_count = connect.auth.as_ref().map_or(0, |auth| auth.cancel_count);
                 connect.auth = Some(AuthProperties {
                     credentials: Some(credentials),
-                    cancel_count: 0,
+                    cancel_count,
                     authenticated: false,
                 });
                 Ok(())
@@ -263,21 +303,37 @@ impl Context {
     ///
     /// * state if not [`Stage::Helo`] or [`Stage::MailFrom`]
     #[inline]
-    pub fn to_mail_from(&mut self, reverse_path: Option<Address>, utf8: bool) -> Result<(), Error> {
-        match self {
-            Self::Helo(ContextHelo { connect, helo }) => {
-                let now = time::OffsetDateTime::now_utc();
-                *self = Self::MailFrom(ContextMailFrom {
-                    connect: connect.clone(),
-                    helo: helo.clone(),
-                    mail_from: MailFromProperties {
-                        reverse_path,
-                        mail_timestamp: now,
-                        message_uuid: uuid::Uuid::new_v4(),
-                        spf: None,
-                        utf8,
-                    },
-                });
+    pub fn to_mail_from(
+        &mut self,
+        reverse_path: Option<Address>,
+        utf8: bool,
+        body_type: Option<MimeBodyType>,
+        declared_size: Option<usize>,
+    ) -> Result<(), Error> {
+        match self {
+            Self::Helo(_) => {
+                *self = match self.take() {
+                    Self::Helo(ContextHelo { connect, helo }) => Self::MailFrom(ContextMailFrom {
+                        connect,
+                        helo,
+                        mail_from: MailFromProperties {
+                            reverse_path,
+                            mail_timestamp: crate::clock::now(),
+                            message_uuid: crate::id::new_uuid(),
+                            spf: None,
+                            utf8,
+                            body_type,
+                            declared_size,
+                            envelop_id: None,
+                            raw_mail_from: None,
+                            raw_rcpt: vec![],
+                        },
+                    }),
+                    other @ (Self::Connect(_)
+                    | Self::MailFrom(_)
+                    | Self::RcptTo(_)
+                    | Self::Finished(_)) => other,
+                };
                 Ok(())
             }
             Self::MailFrom(ContextMailFrom { mail_from, .. }) => {
@@ -296,19 +352,31 @@ impl Context {
     #[inline]
     pub fn to_finished(&mut self) -> Result<(), Error> {
         match self {
-            Self::RcptTo(ContextRcptTo {
-                connect,
-                helo,
-                mail_from,
-                rcpt_to,
-            }) => {
-                *self = Self::Finished(ContextFinished {
-                    connect: connect.clone(),
-                    helo: helo.clone(),
-                    mail_from: mail_from.clone(),
-                    rcpt_to: rcpt_to.clone(),
-                    finished: FinishedProperties { dkim: None },
-                });
+            Self::RcptTo(_) => {
+                *self = match self.take() {
+                    Self::RcptTo(ContextRcptTo {
+                        connect,
+                        helo,
+                        mail_from,
+                        rcpt_to,
+                    }) => Self::Finished(ContextFinished {
+                        connect,
+                        helo,
+                        mail_from,
+                        rcpt_to,
+                        finished: FinishedProperties {
+                            dkim: None,
+                            rcpt_headers: std::collections::HashMap::new(),
+                            added_message_id: None,
+                            deferred_until: None,
+                            timings: Timings::received(),
+                        },
+                    }),
+                    other @ (Self::Connect(_)
+                    | Self::Helo(_)
+                    | Self::MailFrom(_)
+                    | Self::Finished(_)) => other,
+                };
                 Ok(())
             }
             Self::Connect(_) | Self::Helo(_) | Self::MailFrom(_) | Self::Finished(_) => {
@@ -329,6 +397,161 @@ impl Context {
         }
     }
 
+    /// Get the number of error replies sent to the client during the connection.
+    #[must_use]
+    #[inline]
+    pub const fn error_count(&self) -> i64 {
+        match self {
+            Self::Connect(ContextConnect { connect })
+            | Self::Helo(ContextHelo { connect, .. })
+            | Self::MailFrom(ContextMailFrom { connect, .. })
+            | Self::RcptTo(ContextRcptTo { connect, .. })
+            | Self::Finished(ContextFinished { connect, .. }) => connect.error_count,
+        }
+    }
+
+    /// Update the number of error replies sent to the client, see [`Context::error_count`].
+    #[inline]
+    pub fn set_error_count(&mut self, error_count: i64) {
+        match self {
+            Self::Connect(ContextConnect { connect })
+            | Self::Helo(ContextHelo { connect, .. })
+            | Self::MailFrom(ContextMailFrom { connect, .. })
+            | Self::RcptTo(ContextRcptTo { connect, .. })
+            | Self::Finished(ContextFinished { connect, .. }) => connect.error_count = error_count,
+        }
+    }
+
+    /// Get the number of transactions abandoned by a `RSET`, `HELO` or `EHLO` during the connection.
+    #[must_use]
+    #[inline]
+    pub const fn sender_churn(&self) -> i64 {
+        match self {
+            Self::Connect(ContextConnect { connect })
+            | Self::Helo(ContextHelo { connect, .. })
+            | Self::MailFrom(ContextMailFrom { connect, .. })
+            | Self::RcptTo(ContextRcptTo { connect, .. })
+            | Self::Finished(ContextFinished { connect, .. }) => connect.sender_churn,
+        }
+    }
+
+    /// Reset the transaction like [`Context::reset`], counting it in [`Context::sender_churn`]
+    /// if a `MAIL FROM` was received.
+    ///
+    /// Returns the number of transactions abandoned.
+    #[inline]
+    pub fn reset_transaction(&mut self) -> i64 {
+        if let Self::MailFrom(ContextMailFrom { connect, .. })
+        | Self::RcptTo(ContextRcptTo { connect, .. }) = self
+        {
+            connect.sender_churn += 1;
+        }
+        self.reset();
+        self.sender_churn()
+    }
+
+    /// Get the outcome of the session, available once the connection is closed.
+    #[must_use]
+    #[inline]
+    pub const fn session_category(&self) -> Option<SessionCategory> {
+        match self {
+            Self::Connect(ContextConnect { connect })
+            | Self::Helo(ContextHelo { connect, .. })
+            | Self::MailFrom(ContextMailFrom { connect, .. })
+            | Self::RcptTo(ContextRcptTo { connect, .. })
+            | Self::Finished(ContextFinished { connect, .. }) => connect.session_category,
+        }
+    }
+
+    /// Set the outcome of the session, see [`Context::session_category`].
+    #[inline]
+    pub fn set_session_category(&mut self, session_category: SessionCategory) {
+        match self {
+            Self::Connect(ContextConnect { connect })
+            | Self::Helo(ContextHelo { connect, .. })
+            | Self::MailFrom(ContextMailFrom { connect, .. })
+            | Self::RcptTo(ContextRcptTo { connect, .. })
+            | Self::Finished(ContextFinished { connect, .. }) => {
+                connect.session_category = Some(session_category);
+            }
+        }
+    }
+
+    /// Get the text of the greeting set by the rules, see [`Context::set_banner`].
+    #[must_use]
+    #[inline]
+    pub fn banner(&self) -> Option<&str> {
+        match self {
+            Self::Connect(ContextConnect { connect })
+            | Self::Helo(ContextHelo { connect, .. })
+            | Self::MailFrom(ContextMailFrom { connect, .. })
+            | Self::RcptTo(ContextRcptTo { connect, .. })
+            | Self::Finished(ContextFinished { connect, .. }) => connect.banner.as_deref(),
+        }
+    }
+
+    /// Set the text of the `220` greeting sent to the client, after the name of the server.
+    ///
+    /// # Errors
+    ///
+    /// * state if not [`Stage::Connect`], the greeting has already been sent
+    #[inline]
+    #[function_name::named]
+    pub fn set_banner(&mut self, banner: String) -> Result<(), Error> {
+        match self {
+            Self::Connect(ContextConnect { connect }) => {
+                connect.banner = Some(banner);
+                Ok(())
+            }
+            Self::Helo { .. }
+            | Self::MailFrom { .. }
+            | Self::RcptTo { .. }
+            | Self::Finished { .. } => Err(FieldAccessError {
+                field: function_name!().to_owned(),
+                stage: vec![Stage::Connect],
+            }
+            .into()),
+        }
+    }
+
+    /// Get the quota of the authenticated user set by the rules,
+    /// see [`Context::set_submission_quota`].
+    #[must_use]
+    #[inline]
+    pub const fn submission_quota(&self) -> Option<SubmissionQuota> {
+        match self {
+            Self::Connect(ContextConnect { connect })
+            | Self::Helo(ContextHelo { connect, .. })
+            | Self::MailFrom(ContextMailFrom { connect, .. })
+            | Self::RcptTo(ContextRcptTo { connect, .. })
+            | Self::Finished(ContextFinished { connect, .. }) => connect.submission_quota,
+        }
+    }
+
+    /// Set the quota of the authenticated user for the transactions of the connection,
+    /// in place of the one of the configuration.
+    ///
+    /// # Errors
+    ///
+    /// * state if not [`Stage::Connect`] or [`Stage::Helo`], the quota is checked on `MAIL FROM`
+    #[inline]
+    #[function_name::named]
+    pub fn set_submission_quota(&mut self, quota: SubmissionQuota) -> Result<(), Error> {
+        match self {
+            Self::Connect(ContextConnect { connect }) | Self::Helo(ContextHelo { connect, .. }) => {
+                connect.submission_quota = Some(quota);
+                Ok(())
+            }
+            Self::MailFrom { .. } | Self::RcptTo { .. } | Self::Finished { .. } => {
+                Err(FieldAccessError {
+                    field: function_name!().to_owned(),
+                    stage: vec![Stage::Connect, Stage::Helo],
+                }
+                .into())
+            }
+        }
+    }
+
     /// Get the timestamp of the TCP/IP connection
     #[must_use]
     #[inline]
@@ -516,22 +739,21 @@ impl Context {
         }
     }
 
-    /// Set the [`AuthProperties`] of the connection.
+    /// Get the [`AuthProperties`] of the connection, setting them if the client
+    /// has not tried to authenticate yet. The previous attempts are kept.
     ///
     /// # Errors
     ///
     /// * state if not [`Stage::Connect`] or [`Stage::Helo`]
     #[inline]
-    #[allow(clippy::expect_used, clippy::unwrap_in_result)]
     pub fn to_auth(&mut self) -> Result<&mut AuthProperties, Error> {
         match self {
             Self::Connect(ContextConnect { connect }) | Self::Helo(ContextHelo { connect, .. }) => {
-                connect.auth = Some(AuthProperties {
+                Ok(connect.auth.get_or_insert_with(|| AuthProperties {
                     authenticated: false,
                     cancel_count: 0,
                     credentials: None,
-                });
-                Ok(connect.auth.as_mut().expect("has been set just above"))
+                }))
             }
             Self::MailFrom(ContextMailFrom { .. })
             | Self::RcptTo(ContextRcptTo { .. })
@@ -559,101 +781,474 @@ impl Context {
         }
     }
 
-    /// Set the reverse path.
+    /// Set the reverse path.
+    ///
+    /// # Errors
+    ///
+    /// * state if not [`Stage::MailFrom`] or after
+    #[inline]
+    #[function_name::named]
+    pub fn set_reverse_path(&mut self, reverse_path: Option<Address>) -> Result<(), Error> {
+        match self {
+            Self::Connect { .. } | Self::Helo { .. } => Err(FieldAccessError {
+                field: function_name!().to_owned(),
+                stage: after!(MailFrom),
+            }
+            .into()),
+            Self::MailFrom(ContextMailFrom { mail_from, .. })
+            | Self::RcptTo(ContextRcptTo { mail_from, .. })
+            | Self::Finished(ContextFinished { mail_from, .. }) => {
+                mail_from.reverse_path = reverse_path;
+                Ok(())
+            }
+        }
+    }
+
+    /// Get the [`time::OffsetDateTime`] when the `MAIL FROM` has been received.
+    ///
+    /// # Errors
+    ///
+    /// * state if not [`Stage::MailFrom`] or after
+    #[inline]
+    #[function_name::named]
+    pub fn mail_timestamp(&self) -> Result<&time::OffsetDateTime, Error> {
+        match self {
+            Self::Connect { .. } | Self::Helo { .. } => Err(FieldAccessError {
+                field: function_name!().to_owned(),
+                stage: after!(MailFrom),
+            }
+            .into()),
+            Self::MailFrom(ContextMailFrom { mail_from, .. })
+            | Self::RcptTo(ContextRcptTo { mail_from, .. })
+            | Self::Finished(ContextFinished { mail_from, .. }) => Ok(&mail_from.mail_timestamp),
+        }
+    }
+
+    /// Get the message id
+    ///
+    /// # Errors
+    ///
+    /// * state if not [`Stage::MailFrom`] or after
+    #[inline]
+    #[function_name::named]
+    pub fn message_uuid(&self) -> Result<&uuid::Uuid, Error> {
+        match self {
+            Self::Connect { .. } | Self::Helo { .. } => Err(FieldAccessError {
+                field: function_name!().to_owned(),
+                stage: after!(MailFrom),
+            }
+            .into()),
+            Self::MailFrom(ContextMailFrom { mail_from, .. })
+            | Self::RcptTo(ContextRcptTo { mail_from, .. })
+            | Self::Finished(ContextFinished { mail_from, .. }) => Ok(&mail_from.message_uuid),
+        }
+    }
+
+    /// Get the short queue id of the message, see [`MailFromProperties::queue_id`]
+    ///
+    /// # Errors
+    ///
+    /// * state if not [`Stage::MailFrom`] or after
+    #[inline]
+    #[function_name::named]
+    pub fn queue_id(&self) -> Result<String, Error> {
+        match self {
+            Self::Connect { .. } | Self::Helo { .. } => Err(FieldAccessError {
+                field: function_name!().to_owned(),
+                stage: after!(MailFrom),
+            }
+            .into()),
+            Self::MailFrom(ContextMailFrom { mail_from, .. })
+            | Self::RcptTo(ContextRcptTo { mail_from, .. })
+            | Self::Finished(ContextFinished { mail_from, .. }) => Ok(mail_from.queue_id()),
+        }
+    }
+
+    /// Generate a new message id in the context
+    ///
+    /// # Errors
+    ///
+    /// * state if not [`Stage::MailFrom`] or after
+    #[inline]
+    #[function_name::named]
+    pub fn generate_message_id(&mut self) -> Result<(), Error> {
+        match self {
+            Self::Connect(_) | Self::Helo(_) => Err(FieldAccessError {
+                field: function_name!().to_owned(),
+                stage: after!(MailFrom),
+            }
+            .into()),
+            Self::MailFrom(ContextMailFrom { mail_from, .. })
+            | Self::RcptTo(ContextRcptTo { mail_from, .. })
+            | Self::Finished(ContextFinished { mail_from, .. }) => {
+                mail_from.message_uuid = crate::id::new_uuid();
+                Ok(())
+            }
+        }
+    }
+
+    /// Check if the client advertised utf8 option in the transaction
+    #[inline]
+    #[must_use]
+    pub fn is_utf8_advertised(&self) -> bool {
+        match self {
+            Self::Connect(_) | Self::Helo(_) => false,
+            Self::MailFrom(ContextMailFrom { mail_from, .. })
+            | Self::RcptTo(ContextRcptTo { mail_from, .. })
+            | Self::Finished(ContextFinished { mail_from, .. }) => mail_from.utf8,
+        }
+    }
+
+    /// Get the body type declared by the client with the `BODY` parameter of
+    /// the `MAIL FROM` command, `None` if it was not declared.
+    ///
+    /// # Errors
+    ///
+    /// * state if not [`Stage::MailFrom`] or after
+    #[inline]
+    #[function_name::named]
+    pub fn body_type(&self) -> Result<Option<MimeBodyType>, Error> {
+        match self {
+            Self::Connect { .. } | Self::Helo { .. } => Err(FieldAccessError {
+                field: function_name!().to_owned(),
+                stage: after!(MailFrom),
+            }
+            .into()),
+            Self::MailFrom(ContextMailFrom { mail_from, .. })
+            | Self::RcptTo(ContextRcptTo { mail_from, .. })
+            | Self::Finished(ContextFinished { mail_from, .. }) => Ok(mail_from.body_type),
+        }
+    }
+
+    /// Get the size of the message declared by the client with the `SIZE` parameter
+    /// of the `MAIL FROM` command, `None` if it was not declared.
+    ///
+    /// # Errors
+    ///
+    /// * state if not [`Stage::MailFrom`] or after
+    #[inline]
+    #[function_name::named]
+    pub fn declared_size(&self) -> Result<Option<usize>, Error> {
+        match self {
+            Self::Connect { .. } | Self::Helo { .. } => Err(FieldAccessError {
+                field: function_name!().to_owned(),
+                stage: after!(MailFrom),
+            }
+            .into()),
+            Self::MailFrom(ContextMailFrom { mail_from, .. })
+            | Self::RcptTo(ContextRcptTo { mail_from, .. })
+            | Self::Finished(ContextFinished { mail_from, .. }) => Ok(mail_from.declared_size),
+        }
+    }
+
+    /// Get the identifier of the transaction given by the client with the `ENVID`
+    /// parameter of the `MAIL FROM` command.
+    ///
+    /// # Errors
+    ///
+    /// * state if not [`Stage::MailFrom`] or after
+    #[inline]
+    #[function_name::named]
+    pub fn envelop_id(&self) -> Result<Option<&str>, Error> {
+        match self {
+            Self::Connect { .. } | Self::Helo { .. } => Err(FieldAccessError {
+                field: function_name!().to_owned(),
+                stage: after!(MailFrom),
+            }
+            .into()),
+            Self::MailFrom(ContextMailFrom { mail_from, .. })
+            | Self::RcptTo(ContextRcptTo { mail_from, .. })
+            | Self::Finished(ContextFinished { mail_from, .. }) => {
+                Ok(mail_from.envelop_id.as_deref())
+            }
+        }
+    }
+
+    /// Set the identifier of the transaction given with the `ENVID` parameter.
+    ///
+    /// # Errors
+    ///
+    /// * state if not [`Stage::MailFrom`] or after
+    #[inline]
+    #[function_name::named]
+    pub fn set_envelop_id(&mut self, envelop_id: Option<String>) -> Result<(), Error> {
+        match self {
+            Self::Connect { .. } | Self::Helo { .. } => Err(FieldAccessError {
+                field: function_name!().to_owned(),
+                stage: after!(MailFrom),
+            }
+            .into()),
+            Self::MailFrom(ContextMailFrom { mail_from, .. })
+            | Self::RcptTo(ContextRcptTo { mail_from, .. })
+            | Self::Finished(ContextFinished { mail_from, .. }) => {
+                mail_from.envelop_id = envelop_id;
+                Ok(())
+            }
+        }
+    }
+
+    /// Get the arguments of the `MAIL FROM` command as sent by the client.
+    ///
+    /// # Errors
+    ///
+    /// * state if not [`Stage::MailFrom`] or after
+    #[inline]
+    #[function_name::named]
+    pub fn raw_mail_from(&self) -> Result<Option<&str>, Error> {
+        match self {
+            Self::Connect { .. } | Self::Helo { .. } => Err(FieldAccessError {
+                field: function_name!().to_owned(),
+                stage: after!(MailFrom),
+            }
+            .into()),
+            Self::MailFrom(ContextMailFrom { mail_from, .. })
+            | Self::RcptTo(ContextRcptTo { mail_from, .. })
+            | Self::Finished(ContextFinished { mail_from, .. }) => {
+                Ok(mail_from.raw_mail_from.as_deref())
+            }
+        }
+    }
+
+    /// Set the arguments of the `MAIL FROM` command as sent by the client.
+    ///
+    /// # Errors
+    ///
+    /// * state if not [`Stage::MailFrom`] or after
+    #[inline]
+    #[function_name::named]
+    pub fn set_raw_mail_from(&mut self, raw: String) -> Result<(), Error> {
+        match self {
+            Self::Connect { .. } | Self::Helo { .. } => Err(FieldAccessError {
+                field: function_name!().to_owned(),
+                stage: after!(MailFrom),
+            }
+            .into()),
+            Self::MailFrom(ContextMailFrom { mail_from, .. })
+            | Self::RcptTo(ContextRcptTo { mail_from, .. })
+            | Self::Finished(ContextFinished { mail_from, .. }) => {
+                mail_from.raw_mail_from = Some(raw);
+                Ok(())
+            }
+        }
+    }
+
+    /// Get the arguments of the `RCPT TO` commands as sent by the client, in the order received.
+    ///
+    /// # Errors
+    ///
+    /// * state if not [`Stage::MailFrom`] or after
+    #[inline]
+    #[function_name::named]
+    pub fn raw_rcpt(&self) -> Result<&[String], Error> {
+        match self {
+            Self::Connect { .. } | Self::Helo { .. } => Err(FieldAccessError {
+                field: function_name!().to_owned(),
+                stage: after!(MailFrom),
+            }
+            .into()),
+            Self::MailFrom(ContextMailFrom { mail_from, .. })
+            | Self::RcptTo(ContextRcptTo { mail_from, .. })
+            | Self::Finished(ContextFinished { mail_from, .. }) => Ok(&mail_from.raw_rcpt),
+        }
+    }
+
+    /// Record the arguments of a `RCPT TO` command as sent by the client, at most
+    /// `count_max` commands being kept.
+    ///
+    /// # Errors
+    ///
+    /// * state if not [`Stage::MailFrom`] or after
+    #[inline]
+    #[function_name::named]
+    pub fn add_raw_rcpt(&mut self, raw: String, count_max: usize) -> Result<(), Error> {
+        match self {
+            Self::Connect { .. } | Self::Helo { .. } => Err(FieldAccessError {
+                field: function_name!().to_owned(),
+                stage: after!(MailFrom),
+            }
+            .into()),
+            Self::MailFrom(ContextMailFrom { mail_from, .. })
+            | Self::RcptTo(ContextRcptTo { mail_from, .. })
+            | Self::Finished(ContextFinished { mail_from, .. }) => {
+                if mail_from.raw_rcpt.len() < count_max {
+                    mail_from.raw_rcpt.push(raw);
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Record the original recipient given with the `ORCPT` parameter for `forward_path`.
+    ///
+    /// # Errors
+    ///
+    /// * state if not [`Stage::RcptTo`] or after
+    #[inline]
+    #[function_name::named]
+    pub fn set_original_recipient(
+        &mut self,
+        forward_path: Address,
+        original_recipient: OriginalRecipient,
+    ) -> Result<(), Error> {
+        match self {
+            Self::Connect(_) | Self::Helo(_) | Self::MailFrom(_) => Err(FieldAccessError {
+                field: function_name!().to_owned(),
+                stage: after!(RcptTo),
+            }
+            .into()),
+            Self::RcptTo(ContextRcptTo { rcpt_to, .. })
+            | Self::Finished(ContextFinished { rcpt_to, .. }) => {
+                rcpt_to
+                    .original_recipients
+                    .insert(forward_path, original_recipient);
+                Ok(())
+            }
+        }
+    }
+
+    /// Set the Maildir folder (such as `Junk` or `INBOX.lists`) the message is stored in
+    /// for `forward_path`, if delivered by the `maildir` transport.
+    ///
+    /// # Errors
+    ///
+    /// * state if not [`Stage::RcptTo`] or after
+    #[inline]
+    #[function_name::named]
+    pub fn set_rcpt_folder(&mut self, forward_path: Address, folder: String) -> Result<(), Error> {
+        match self {
+            Self::Connect(_) | Self::Helo(_) | Self::MailFrom(_) => Err(FieldAccessError {
+                field: function_name!().to_owned(),
+                stage: after!(RcptTo),
+            }
+            .into()),
+            Self::RcptTo(ContextRcptTo { rcpt_to, .. })
+            | Self::Finished(ContextFinished { rcpt_to, .. }) => {
+                rcpt_to.folders.insert(forward_path, folder);
+                Ok(())
+            }
+        }
+    }
+
+    /// Record the extension of the local part of `forward_path`, see [`AddressExtension`].
     ///
     /// # Errors
     ///
-    /// * state if not [`Stage::MailFrom`] or after
+    /// * state if not [`Stage::RcptTo`] or after
     #[inline]
     #[function_name::named]
-    pub fn set_reverse_path(&mut self, reverse_path: Option<Address>) -> Result<(), Error> {
+    pub fn set_rcpt_extension(
+        &mut self,
+        forward_path: Address,
+        extension: AddressExtension,
+    ) -> Result<(), Error> {
         match self {
-            Self::Connect { .. } | Self::Helo { .. } => Err(FieldAccessError {
+            Self::Connect(_) | Self::Helo(_) | Self::MailFrom(_) => Err(FieldAccessError {
                 field: function_name!().to_owned(),
-                stage: after!(MailFrom),
+                stage: after!(RcptTo),
             }
             .into()),
-            Self::MailFrom(ContextMailFrom { mail_from, .. })
-            | Self::RcptTo(ContextRcptTo { mail_from, .. })
-            | Self::Finished(ContextFinished { mail_from, .. }) => {
-                mail_from.reverse_path = reverse_path;
+            Self::RcptTo(ContextRcptTo { rcpt_to, .. })
+            | Self::Finished(ContextFinished { rcpt_to, .. }) => {
+                rcpt_to.extensions.insert(forward_path, extension);
                 Ok(())
             }
         }
     }
 
-    /// Get the [`time::OffsetDateTime`] when the `MAIL FROM` has been received.
+    /// Set the reverse path used to deliver the message to `forward_path`, replacing
+    /// the one of the transaction (to encode the recipient with VERP for instance).
     ///
     /// # Errors
     ///
-    /// * state if not [`Stage::MailFrom`] or after
+    /// * state if not [`Stage::RcptTo`] or after
     #[inline]
     #[function_name::named]
-    pub fn mail_timestamp(&self) -> Result<&time::OffsetDateTime, Error> {
+    pub fn set_rcpt_reverse_path(
+        &mut self,
+        forward_path: Address,
+        reverse_path: Address,
+    ) -> Result<(), Error> {
         match self {
-            Self::Connect { .. } | Self::Helo { .. } => Err(FieldAccessError {
+            Self::Connect(_) | Self::Helo(_) | Self::MailFrom(_) => Err(FieldAccessError {
                 field: function_name!().to_owned(),
-                stage: after!(MailFrom),
+                stage: after!(RcptTo),
             }
             .into()),
-            Self::MailFrom(ContextMailFrom { mail_from, .. })
-            | Self::RcptTo(ContextRcptTo { mail_from, .. })
-            | Self::Finished(ContextFinished { mail_from, .. }) => Ok(&mail_from.mail_timestamp),
+            Self::RcptTo(ContextRcptTo { rcpt_to, .. })
+            | Self::Finished(ContextFinished { rcpt_to, .. }) => {
+                rcpt_to.reverse_paths.insert(forward_path, reverse_path);
+                Ok(())
+            }
         }
     }
 
-    /// Get the message id
+    /// Get how the `deliver` transport reaches the exchangers of the recipients.
     ///
     /// # Errors
     ///
-    /// * state if not [`Stage::MailFrom`] or after
+    /// * state if not [`Stage::RcptTo`] or after
     #[inline]
     #[function_name::named]
-    pub fn message_uuid(&self) -> Result<&uuid::Uuid, Error> {
+    pub fn delivery_strategy(&self) -> Result<DeliveryStrategy, Error> {
         match self {
-            Self::Connect { .. } | Self::Helo { .. } => Err(FieldAccessError {
-                field: function_name!().to_owned(),
-                stage: after!(MailFrom),
+            Self::Connect { .. } | Self::Helo { .. } | Self::MailFrom { .. } => {
+                Err(FieldAccessError {
+                    field: function_name!().to_owned(),
+                    stage: after!(RcptTo),
+                }
+                .into())
             }
-            .into()),
-            Self::MailFrom(ContextMailFrom { mail_from, .. })
-            | Self::RcptTo(ContextRcptTo { mail_from, .. })
-            | Self::Finished(ContextFinished { mail_from, .. }) => Ok(&mail_from.message_uuid),
+            Self::RcptTo(ContextRcptTo { rcpt_to, .. })
+            | Self::Finished(ContextFinished { rcpt_to, .. }) => Ok(rcpt_to.delivery_strategy),
         }
     }
 
-    /// Generate a new message id in the context
+    /// Set how the `deliver` transport reaches the exchangers of the recipients.
     ///
     /// # Errors
     ///
-    /// * state if not [`Stage::MailFrom`] or after
+    /// * state if not [`Stage::RcptTo`] or after
     #[inline]
     #[function_name::named]
-    pub fn generate_message_id(&mut self) -> Result<(), Error> {
+    pub fn set_delivery_strategy(&mut self, strategy: DeliveryStrategy) -> Result<(), Error> {
         match self {
-            Self::Connect(_) | Self::Helo(_) => Err(FieldAccessError {
+            Self::Connect(_) | Self::Helo(_) | Self::MailFrom(_) => Err(FieldAccessError {
                 field: function_name!().to_owned(),
-                stage: after!(MailFrom),
+                stage: after!(RcptTo),
             }
             .into()),
-            Self::MailFrom(ContextMailFrom { mail_from, .. })
-            | Self::RcptTo(ContextRcptTo { mail_from, .. })
-            | Self::Finished(ContextFinished { mail_from, .. }) => {
-                mail_from.message_uuid = uuid::Uuid::new_v4();
+            Self::RcptTo(ContextRcptTo { rcpt_to, .. })
+            | Self::Finished(ContextFinished { rcpt_to, .. }) => {
+                rcpt_to.delivery_strategy = strategy;
                 Ok(())
             }
         }
     }
 
-    /// Check if the client advertised utf8 option in the transaction
+    /// Record that the unknown recipient `original` has been rewritten to the catch-all
+    /// mailbox `catch_all` of its domain.
+    ///
+    /// # Errors
+    ///
+    /// * state if not [`Stage::RcptTo`] or after
     #[inline]
-    #[must_use]
-    pub fn is_utf8_advertised(&self) -> bool {
+    #[function_name::named]
+    pub fn add_catch_all(&mut self, catch_all: Address, original: Address) -> Result<(), Error> {
         match self {
-            Self::Connect(_) | Self::Helo(_) => false,
-            Self::MailFrom(ContextMailFrom { mail_from, .. })
-            | Self::RcptTo(ContextRcptTo { mail_from, .. })
-            | Self::Finished(ContextFinished { mail_from, .. }) => mail_from.utf8,
+            Self::Connect(_) | Self::Helo(_) | Self::MailFrom(_) => Err(FieldAccessError {
+                field: function_name!().to_owned(),
+                stage: after!(RcptTo),
+            }
+            .into()),
+            Self::RcptTo(ContextRcptTo { rcpt_to, .. })
+            | Self::Finished(ContextFinished { rcpt_to, .. }) => {
+                rcpt_to
+                    .catch_all
+                    .entry(catch_all)
+                    .or_default()
+                    .push(original);
+                Ok(())
+            }
         }
     }
 
@@ -676,27 +1271,10 @@ impl Context {
                 stage: after!(MailFrom),
             }
             .into()),
-            Self::MailFrom(ContextMailFrom {
-                connect,
-                helo,
-                mail_from,
-            }) => {
-                *self = Self::RcptTo(ContextRcptTo {
-                    connect: connect.clone(),
-                    helo: helo.clone(),
-                    mail_from: mail_from.clone(),
-                    rcpt_to: RcptToProperties {
-                        // FIXME: should not have default value
-                        transaction_type: TransactionType::Internal,
-                        delivery: std::iter::once((
-                            WrapperSerde::Ready(transport),
-                            vec![(forward_path.clone(), transfer::Status::default())],
-                        ))
-                        .collect::<_>(),
-                        forward_paths: vec![forward_path],
-                    },
-                });
-                Ok(())
+            Self::MailFrom(_) => {
+                // FIXME: should not have default value
+                self.to_rcpt_to(TransactionType::Internal);
+                self.add_forward_path(forward_path, transport)
             }
             Self::RcptTo(ContextRcptTo { rcpt_to, .. })
             | Self::Finished(ContextFinished { rcpt_to, .. }) => {
@@ -733,6 +1311,10 @@ impl Context {
             Self::RcptTo(ContextRcptTo { rcpt_to, .. })
             | Self::Finished(ContextFinished { rcpt_to, .. }) => {
                 rcpt_to.forward_paths.retain(|rcpt| rcpt != forward_path);
+                rcpt_to.folders.remove(forward_path);
+                rcpt_to.extensions.remove(forward_path);
+                rcpt_to.reverse_paths.remove(forward_path);
+                rcpt_to.catch_all.remove(forward_path);
 
                 for rcpts in &mut rcpt_to.delivery.values_mut() {
                     if let Some(index) = rcpts.iter().position(|(rcpt, _)| *rcpt == *forward_path) {
@@ -766,6 +1348,96 @@ impl Context {
         }
     }
 
+    /// Get the Maildir folders set by the rules for the recipients.
+    ///
+    /// # Errors
+    ///
+    /// * state if not [`Stage::RcptTo`] or after
+    #[inline]
+    #[function_name::named]
+    pub fn rcpt_folders(&self) -> Result<&std::collections::HashMap<Address, String>, Error> {
+        match self {
+            Self::Connect { .. } | Self::Helo { .. } | Self::MailFrom { .. } => {
+                Err(FieldAccessError {
+                    field: function_name!().to_owned(),
+                    stage: after!(RcptTo),
+                }
+                .into())
+            }
+            Self::RcptTo(ContextRcptTo { rcpt_to, .. })
+            | Self::Finished(ContextFinished { rcpt_to, .. }) => Ok(&rcpt_to.folders),
+        }
+    }
+
+    /// Get the extensions of the local parts of the recipients, see [`Context::set_rcpt_extension`].
+    ///
+    /// # Errors
+    ///
+    /// * state if not [`Stage::RcptTo`] or after
+    #[inline]
+    #[function_name::named]
+    pub fn rcpt_extensions(
+        &self,
+    ) -> Result<&std::collections::HashMap<Address, AddressExtension>, Error> {
+        match self {
+            Self::Connect { .. } | Self::Helo { .. } | Self::MailFrom { .. } => {
+                Err(FieldAccessError {
+                    field: function_name!().to_owned(),
+                    stage: after!(RcptTo),
+                }
+                .into())
+            }
+            Self::RcptTo(ContextRcptTo { rcpt_to, .. })
+            | Self::Finished(ContextFinished { rcpt_to, .. }) => Ok(&rcpt_to.extensions),
+        }
+    }
+
+    /// Get the reverse paths set by the rules for the recipients.
+    ///
+    /// # Errors
+    ///
+    /// * state if not [`Stage::RcptTo`] or after
+    #[inline]
+    #[function_name::named]
+    pub fn rcpt_reverse_paths(
+        &self,
+    ) -> Result<&std::collections::HashMap<Address, Address>, Error> {
+        match self {
+            Self::Connect { .. } | Self::Helo { .. } | Self::MailFrom { .. } => {
+                Err(FieldAccessError {
+                    field: function_name!().to_owned(),
+                    stage: after!(RcptTo),
+                }
+                .into())
+            }
+            Self::RcptTo(ContextRcptTo { rcpt_to, .. })
+            | Self::Finished(ContextFinished { rcpt_to, .. }) => Ok(&rcpt_to.reverse_paths),
+        }
+    }
+
+    /// Get the unknown recipients rewritten to the catch-all mailboxes, by catch-all mailbox.
+    ///
+    /// # Errors
+    ///
+    /// * state if not [`Stage::RcptTo`] or after
+    #[inline]
+    #[function_name::named]
+    pub fn rcpt_catch_all(
+        &self,
+    ) -> Result<&std::collections::HashMap<Address, Vec<Address>>, Error> {
+        match self {
+            Self::Connect { .. } | Self::Helo { .. } | Self::MailFrom { .. } => {
+                Err(FieldAccessError {
+                    field: function_name!().to_owned(),
+                    stage: after!(RcptTo),
+                }
+                .into())
+            }
+            Self::RcptTo(ContextRcptTo { rcpt_to, .. })
+            | Self::Finished(ContextFinished { rcpt_to, .. }) => Ok(&rcpt_to.catch_all),
+        }
+    }
+
     /// Get a mutable reference of the forwards path.
     ///
     /// # Errors
@@ -800,6 +1472,8 @@ impl Context {
     ) -> Result<(), Error> {
         let deliver = self.delivery_mut()?;
 
+        // keep the address as received, `search` can be another spelling of it.
+        let mut found = None;
         for (_, v) in deliver.iter_mut() {
             if let Some((idx, _)) = v
                 .iter()
@@ -807,14 +1481,15 @@ impl Context {
                 .enumerate()
                 .find(|(_, rcpt)| *rcpt == search)
             {
-                v.swap_remove(idx);
+                found = Some(v.swap_remove(idx).0);
             }
         }
+        let rcpt = found.unwrap_or_else(|| search.clone());
 
         deliver
             .entry(WrapperSerde::Ready(transport))
-            .and_modify(|rcpt| rcpt.push((search.clone(), transfer::Status::default())))
-            .or_insert_with(|| vec![(search.clone(), transfer::Status::default())]);
+            .or_default()
+            .push((rcpt, transfer::Status::default()));
 
         Ok(())
     }
@@ -905,6 +1580,35 @@ impl Context {
         }
     }
 
+    /// Convert a [`ContextMailFrom`] to a [`ContextRcptTo`] without recipient.
+    fn to_rcpt_to(&mut self, transaction_type: TransactionType) {
+        *self = match self.take() {
+            Self::MailFrom(ContextMailFrom {
+                connect,
+                helo,
+                mail_from,
+            }) => Self::RcptTo(ContextRcptTo {
+                connect,
+                helo,
+                mail_from,
+                rcpt_to: RcptToProperties {
+                    transaction_type,
+                    delivery: std::collections::HashMap::new(),
+                    forward_paths: vec![],
+                    original_recipients: std::collections::HashMap::new(),
+                    folders: std::collections::HashMap::new(),
+                    extensions: std::collections::HashMap::new(),
+                    reverse_paths: std::collections::HashMap::new(),
+                    catch_all: std::collections::HashMap::new(),
+                    delivery_strategy: DeliveryStrategy::default(),
+                },
+            }),
+            other @ (Self::Connect(_) | Self::Helo(_) | Self::RcptTo(_) | Self::Finished(_)) => {
+                other
+            }
+        };
+    }
+
     /// Set the [`TransactionType`].
     /// If the state was [`Stage::MailFrom`], the state is changed to [`Stage::RcptTo`].
     ///
@@ -920,21 +1624,8 @@ impl Context {
                 stage: after!(MailFrom),
             }
             .into()),
-            Self::MailFrom(ContextMailFrom {
-                connect,
-                helo,
-                mail_from,
-            }) => {
-                *self = Self::RcptTo(ContextRcptTo {
-                    connect: connect.clone(),
-                    helo: helo.clone(),
-                    mail_from: mail_from.clone(),
-                    rcpt_to: RcptToProperties {
-                        transaction_type,
-                        delivery: std::collections::HashMap::new(),
-                        forward_paths: vec![],
-                    },
-                });
+            Self::MailFrom(_) => {
+                self.to_rcpt_to(transaction_type);
                 Ok(())
             }
             Self::RcptTo(ContextRcptTo { rcpt_to, .. })
@@ -1031,6 +1722,38 @@ impl Context {
         }
     }
 
+    /// Add a header only on the copy of the message delivered to `forward_path`.
+    ///
+    /// # Errors
+    ///
+    /// * state if not [`Stage::Finished`]
+    #[inline]
+    #[function_name::named]
+    pub fn add_rcpt_header(
+        &mut self,
+        forward_path: Address,
+        name: String,
+        value: String,
+    ) -> Result<(), Error> {
+        match self {
+            Self::Connect(_) | Self::Helo(_) | Self::MailFrom(_) | Self::RcptTo(_) => {
+                Err(FieldAccessError {
+                    field: function_name!().to_owned(),
+                    stage: after!(Finished),
+                }
+                .into())
+            }
+            Self::Finished(ContextFinished { finished, .. }) => {
+                finished
+                    .rcpt_headers
+                    .entry(forward_path)
+                    .or_default()
+                    .push((name, value));
+                Ok(())
+            }
+        }
+    }
+
     /// Convert the instance into a [`ContextFinished`].
     ///
     /// # Errors
@@ -1127,6 +1850,23 @@ pub struct ConnectProperties {
     pub tls: Option<TlsProperties>,
     ///
     pub auth: Option<AuthProperties>,
+    /// Number of error replies sent to the client before the evaluation of the rules.
+    #[serde(default)]
+    pub error_count: i64,
+    /// Number of transactions started by a `MAIL FROM` and abandoned by a `RSET`.
+    #[serde(default)]
+    pub sender_churn: i64,
+    /// Text of the `220` greeting set by the rules of the `connect` stage, sent
+    /// after the name of the server in place of `Service ready`.
+    #[serde(skip)]
+    pub banner: Option<String>,
+    /// Outcome of the session, set when the connection is closed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session_category: Option<SessionCategory>,
+    /// Quota of the authenticated user set by the rules, in place of the one of the
+    /// configuration.
+    #[serde(skip)]
+    pub submission_quota: Option<SubmissionQuota>,
 }
 
 /// Properties accessible after the HELO/EHLO command
@@ -1152,8 +1892,65 @@ pub struct MailFromProperties {
     pub message_uuid: uuid::Uuid,
     ///
     pub spf: Option<spf::Result>,
-    /// the transaction should support utf8 content
+    /// `SMTPUTF8` has been negotiated for this transaction (RFC 6531): the envelope
+    /// and the headers may contain UTF-8, and must be handled as such downstream.
     pub utf8: bool,
+    /// Body type declared with the `BODY` parameter, if any (RFC 6152).
+    #[serde(default)]
+    pub body_type: Option<MimeBodyType>,
+    /// Size of the message declared with the `SIZE` parameter, if any (RFC 1870).
+    #[serde(default)]
+    pub declared_size: Option<usize>,
+    /// Identifier of the transaction given by the client with the `ENVID`
+    /// parameter (RFC 3461), decoded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub envelop_id: Option<String>,
+    /// Arguments of the `MAIL FROM` command as sent by the client, truncated, kept for
+    /// the forensic logs. Dropped once the message is queued, unless quarantined.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub raw_mail_from: Option<String>,
+    /// Arguments of the `RCPT TO` commands as sent by the client, in the order received,
+    /// see [`MailFromProperties::raw_mail_from`].
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub raw_rcpt: Vec<String>,
+}
+
+impl MailFromProperties {
+    /// Length of the short queue id, see [`MailFromProperties::queue_id`].
+    pub const QUEUE_ID_LEN: usize = 12;
+
+    /// Drop the text of the commands sent by the client, see [`MailFromProperties::raw_mail_from`].
+    #[inline]
+    pub fn clear_raw_commands(&mut self) {
+        self.raw_mail_from = None;
+        self.raw_rcpt = vec![];
+    }
+
+    /// Short identifier of the message, easier to communicate than the uuid and
+    /// compatible with the tooling expecting short queue ids.
+    ///
+    /// The id is 12 characters of base32 (Crockford alphabet), derived from the
+    /// uuid and the timestamp of the transaction: it is stable across the
+    /// lifetime of the message and does not need to be stored on disk.
+    #[inline]
+    #[must_use]
+    pub fn queue_id(&self) -> String {
+        const ALPHABET: &str = "0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+        let (high, low) = self.message_uuid.as_u64_pair();
+        let timestamp = u64::try_from(self.mail_timestamp.unix_timestamp()).unwrap_or_default()
+            << 30u32
+            | u64::from(self.mail_timestamp.nanosecond());
+        let value = high ^ low ^ timestamp.rotate_left(32);
+
+        (0..Self::QUEUE_ID_LEN)
+            .rev()
+            .map(|i| {
+                let index = usize::try_from((value >> (5 * i)) & 0x1f).unwrap_or_default();
+                ALPHABET.chars().nth(index).unwrap_or('0')
+            })
+            .collect()
+    }
 }
 
 /// Properties accessible after the RCPT TO command
@@ -1166,6 +1963,27 @@ pub struct RcptToProperties {
     pub delivery: std::collections::HashMap<WrapperSerde, DeliverTo>,
     ///
     pub transaction_type: TransactionType,
+    /// Original recipients given by the client with the `ORCPT` parameter (RFC 3461).
+    #[serde(default, skip_serializing_if = "std::collections::HashMap::is_empty")]
+    pub original_recipients: std::collections::HashMap<Address, OriginalRecipient>,
+    /// Maildir folders chosen by the rules for the recipients, used by the `maildir` transport.
+    #[serde(default, skip_serializing_if = "std::collections::HashMap::is_empty")]
+    pub folders: std::collections::HashMap<Address, String>,
+    /// Extensions of the local parts of the recipients of the domains of the server,
+    /// split on `RCPT TO` with the delimiters of `server.recipient_delimiter`.
+    #[serde(default, skip_serializing_if = "std::collections::HashMap::is_empty")]
+    pub extensions: std::collections::HashMap<Address, AddressExtension>,
+    /// Reverse paths chosen by the rules for the recipients, replacing the one of the
+    /// transaction when delivered by the `deliver` and `forward` transports.
+    #[serde(default, skip_serializing_if = "std::collections::HashMap::is_empty")]
+    pub reverse_paths: std::collections::HashMap<Address, Address>,
+    /// Unknown recipients rewritten to the catch-all mailbox of their domain, by catch-all
+    /// mailbox, recorded in the `X-Original-To` headers of its copy of the message.
+    #[serde(default, skip_serializing_if = "std::collections::HashMap::is_empty")]
+    pub catch_all: std::collections::HashMap<Address, Vec<Address>>,
+    /// How the `deliver` transport reaches the exchangers, chosen by the rules.
+    #[serde(default, skip_serializing_if = "DeliveryStrategy::is_serial")]
+    pub delivery_strategy: DeliveryStrategy,
 }
 
 /// Properties accessible once the message has been fully received
@@ -1174,7 +1992,138 @@ pub struct RcptToProperties {
 pub struct FinishedProperties {
     ///
     pub dkim: Option<dkim::VerificationResult>,
+    /// Headers added only on the copy of the message delivered to a recipient,
+    /// by the rules with `msg::append_rcpt_header`.
+    #[serde(default, skip_serializing_if = "std::collections::HashMap::is_empty")]
+    pub rcpt_headers: std::collections::HashMap<Address, Vec<(String, String)>>,
+    /// The `Message-ID` header added by the server to a message received without it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub added_message_id: Option<String>,
+    /// Time before which the delivery of the message is not attempted, set when a rule
+    /// defers it with `state::defer`.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "time::serde::iso8601::option"
+    )]
+    pub deferred_until: Option<time::OffsetDateTime>,
+    /// Time spent by the message in each stage since its reception.
+    #[serde(default, skip_serializing_if = "Timings::is_empty")]
+    pub timings: Timings,
+}
+
+impl FinishedProperties {
+    /// Defer the delivery of the message, the next attempt being `delay` from now.
+    #[inline]
+    pub fn defer(&mut self, delay: std::time::Duration) {
+        self.deferred_until = Some(
+            crate::clock::now()
+                .saturating_add(time::Duration::try_from(delay).unwrap_or(time::Duration::MAX)),
+        );
+    }
+}
+
+/// A stage of the processing of a message, see [`Timings`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, strum::AsRefStr, strum::EnumIter)]
+#[strum(serialize_all = "snake_case")]
+pub enum TimingStage {
+    /// In the working queue, waiting for and running the `postq` rules.
+    Working,
+    /// Waiting in the deliver and deferred queues.
+    Queued,
+    /// Sending the message to the remote servers.
+    InFlight,
+}
+
+/// Time spent by a message in each stage, from the end of its reception to its final
+/// disposition, recorded at each transition between the stages.
+#[derive(Debug, Default, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Timings {
+    /// End of the reception of the message.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "time::serde::iso8601::option"
+    )]
+    pub received_at: Option<time::OffsetDateTime>,
+    /// End of the last stage recorded.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "time::serde::iso8601::option"
+    )]
+    pub since: Option<time::OffsetDateTime>,
+    /// Milliseconds spent in [`TimingStage::Working`].
+    #[serde(default)]
+    pub working_ms: u64,
+    /// Milliseconds spent in [`TimingStage::Queued`].
+    #[serde(default)]
+    pub queued_ms: u64,
+    /// Milliseconds spent in [`TimingStage::InFlight`].
+    #[serde(default)]
+    pub in_flight_ms: u64,
+}
+
+fn elapsed_ms(since: time::OffsetDateTime, now: time::OffsetDateTime) -> u64 {
+    u64::try_from((now - since).whole_milliseconds()).unwrap_or(0)
+}
+
+impl Timings {
+    /// Start the timings of a message received now.
+    #[must_use]
+    #[inline]
+    pub fn received() -> Self {
+        let now = crate::clock::now();
+        Self {
+            received_at: Some(now),
+            since: Some(now),
+            ..Self::default()
+        }
+    }
+
+    /// Are the timings not started ? (message received by a previous version)
+    #[must_use]
+    #[inline]
+    pub const fn is_empty(&self) -> bool {
+        self.received_at.is_none()
+    }
+
+    /// Add the time elapsed since the end of the last stage recorded to `stage`.
+    #[inline]
+    pub fn record(&mut self, stage: TimingStage) {
+        let now = crate::clock::now();
+        if let Some(since) = self.since {
+            let elapsed = elapsed_ms(since, now);
+            let total = match stage {
+                TimingStage::Working => &mut self.working_ms,
+                TimingStage::Queued => &mut self.queued_ms,
+                TimingStage::InFlight => &mut self.in_flight_ms,
+            };
+            *total = total.saturating_add(elapsed);
+        }
+        self.since = Some(now);
+    }
+
+    /// Milliseconds spent in `stage`.
+    #[must_use]
+    #[inline]
+    pub const fn stage_ms(&self, stage: TimingStage) -> u64 {
+        match stage {
+            TimingStage::Working => self.working_ms,
+            TimingStage::Queued => self.queued_ms,
+            TimingStage::InFlight => self.in_flight_ms,
+        }
+    }
+
+    /// Milliseconds elapsed since the reception of the message.
+    #[must_use]
+    #[inline]
+    pub fn total_ms(&self) -> Option<u64> {
+        self.received_at
+            .map(|received_at| elapsed_ms(received_at, crate::clock::now()))
+    }
 }
+
 #[doc(hidden)]
 #[allow(clippy::module_name_repetitions)]
 #[derive(Debug, Clone, serde::Serialize)]
@@ -0,0 +1,41 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2023 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+
+//! The DNS blocklists known by a keyword, shared by the `dnsxl` plugin and the check
+//! of the outgoing addresses.
+
+/// The keywords of the blocklists, with their zone.
+pub const BLOCKLISTS: &[(&str, &str)] = &[
+    ("spamhaus", "zen.spamhaus.org"),
+    ("spamrats", "all.spamrats.com"),
+    ("spamcops", "bl.spamcop.net"),
+    ("lashback", "ubl.unsubscore.com"),
+    ("s5h", "all.s5h.net"),
+    ("sorbs", "dnsxl.sorbs.net"),
+    ("backscatterer", "ips.backscatterer.org"),
+    ("singular", "singular.ttk.pte.hu"),
+];
+
+/// The zone of the blocklist `keyword` (case insensitive), `None` if it is not a keyword.
+#[must_use]
+#[inline]
+pub fn zone(keyword: &str) -> Option<&'static str> {
+    BLOCKLISTS
+        .iter()
+        .find(|(known, _)| known.eq_ignore_ascii_case(keyword))
+        .map(|(_, zone)| *zone)
+}
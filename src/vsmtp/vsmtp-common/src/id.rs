@@ -0,0 +1,79 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2022 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+
+/// Generate the identifier of a new connection or message.
+///
+/// The identifiers of the connections and of the messages (used as the queue id)
+/// must be generated with this function instead of [`uuid::Uuid::new_v4`],
+/// so that the tests can make them predictable with [`crate::clock::mock::seed_ids`].
+#[must_use]
+#[inline]
+pub fn new_uuid() -> uuid::Uuid {
+    #[cfg(feature = "testing")]
+    if let Some(uuid) = crate::clock::mock::next_id() {
+        return uuid;
+    }
+
+    uuid::Uuid::new_v4()
+}
+
+/// `splitmix64`, enough to produce distinct identifiers from a seed.
+#[cfg(feature = "testing")]
+fn split_mix(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    let mut value = *state;
+    value = (value ^ (value >> 30_u8)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    value = (value ^ (value >> 27_u8)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    value ^ (value >> 31_u8)
+}
+
+/// The next identifier of the sequence of `state`.
+#[cfg(feature = "testing")]
+pub(crate) fn from_seed(state: &mut u64) -> uuid::Uuid {
+    let high = split_mix(state);
+    let low = split_mix(state);
+
+    let bytes = ((u128::from(high) << 64_u8) | u128::from(low)).to_be_bytes();
+    uuid::Builder::from_random_bytes(bytes).into_uuid()
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use super::*;
+    use crate::clock::mock;
+
+    #[test]
+    fn seeded() {
+        mock::seed_ids(42);
+        let first = [new_uuid(), new_uuid(), new_uuid()];
+
+        mock::seed_ids(42);
+        let second = [new_uuid(), new_uuid(), new_uuid()];
+        assert_eq!(first, second);
+        assert_ne!(first[0], first[1]);
+        assert_eq!(first[0].get_version(), Some(uuid::Version::Random));
+
+        let other_thread = std::thread::spawn(new_uuid).join().unwrap();
+        assert!(!first.contains(&other_thread));
+
+        mock::seed_ids(43);
+        assert_ne!(new_uuid(), first[0]);
+
+        mock::reset();
+        assert!(!first.contains(&new_uuid()));
+    }
+}
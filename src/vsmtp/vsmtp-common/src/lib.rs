@@ -71,10 +71,17 @@ pub const SUBMISSIONS_PORT: u16 = 465;
 mod types {
     #[macro_use]
     pub mod address;
+    pub mod address_extension;
     pub mod client_name;
+    pub mod delivery_strategy;
     pub mod domain;
+    pub mod mime_body_type;
+    pub mod network;
+    pub mod original_recipient;
     pub mod reply;
     pub mod reply_code;
+    pub mod session_category;
+    pub mod submission_quota;
     pub mod target;
     pub mod tls_cipher_suite;
     pub mod tls_protocol_version;
@@ -82,10 +89,17 @@ mod types {
 
 pub use types::{
     address::Address,
+    address_extension::AddressExtension,
     client_name::ClientName,
+    delivery_strategy::DeliveryStrategy,
     domain::{domain_iter, Domain},
+    mime_body_type::MimeBodyType,
+    network::Network,
+    original_recipient::OriginalRecipient,
     reply::Reply,
     reply_code::*,
+    session_category::SessionCategory,
+    submission_quota::SubmissionQuota,
     target::Target,
     tls_cipher_suite::CipherSuite,
     tls_protocol_version::ProtocolVersion,
@@ -98,15 +112,28 @@ mod context;
 pub use context::{
     AuthProperties, ConnectProperties, Context, ContextConnect, ContextFinished, ContextHelo,
     ContextMailFrom, ContextRcptTo, Error, FieldAccessError, FinishedProperties, HeloProperties,
-    MailFromProperties, RcptToProperties, Stage, TlsProperties, TransactionType,
+    MailFromProperties, RcptToProperties, Stage, TimingStage, Timings, TlsProperties,
+    TransactionType,
 };
 
+/// source of the current time, mockable in the tests
+pub mod clock;
+
+/// source of the identifiers of the connections and messages, seedable in the tests
+pub mod id;
+
+pub mod xtext;
+
 /// abstraction of the libc
 pub mod libc_abstraction;
 
 /// status of the mail context
 pub mod status;
 
+pub mod policy_event;
+
+pub mod dnsxl;
+
 /// transfer related types
 pub mod transfer {
     /// underlying transfer errors
@@ -130,6 +157,7 @@ pub mod auth {
 
 #[cfg(test)]
 mod tests {
+    mod context;
     mod libc_abstraction;
 }
 
@@ -184,3 +184,31 @@ pub fn getpwuid(uid: libc::uid_t) -> anyhow::Result<std::path::PathBuf> {
     // SAFETY: the foreign allocated is used correctly as specified in `CStr::from_ptr`
     Ok(unsafe { std::ffi::CStr::from_ptr(buffer) }.to_str()?.into())
 }
+
+/// Get the space available to an unprivileged user on the filesystem of `path`, in bytes.
+///
+/// # Errors
+///
+/// * `@path` cannot be convert to `CString`
+/// * see statvfs(3) ERRORS
+#[inline]
+pub fn free_space(path: &std::path::Path) -> anyhow::Result<u64> {
+    let path = alloc::ffi::CString::new(path.to_string_lossy().as_bytes())?;
+    let mut stat = core::mem::MaybeUninit::<libc::statvfs>::uninit();
+
+    #[allow(unsafe_code)]
+    // SAFETY: ffi call
+    match unsafe { libc::statvfs(path.as_ptr(), stat.as_mut_ptr()) } {
+        0i32 => {
+            #[allow(unsafe_code)]
+            // SAFETY: `stat` has been initialized by the successful call
+            let stat = unsafe { stat.assume_init() };
+            #[allow(clippy::useless_conversion)] // the types depend on the platform
+            Ok(u64::from(stat.f_bavail).saturating_mul(u64::from(stat.f_frsize)))
+        }
+        _ => Err(anyhow::anyhow!(
+            "statvfs: '{}'",
+            std::io::Error::last_os_error()
+        )),
+    }
+}
@@ -0,0 +1,286 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2023 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+
+//! Structured events of the policy rejections, for the anti-abuse feeds.
+//!
+//! One event is emitted with the target [`TARGET`] for each client, sender or recipient
+//! refused by the rules or by a built-in check, and for each permanent failure of a
+//! delivery. The fields are always named the same, a field without value is omitted.
+
+use crate::{Address, Context, ContextFinished, Reply};
+
+/// Target of the [`tracing`] events of the policy rejections.
+pub const TARGET: &str = "vsmtp::policy_event";
+
+/// What produced the rejection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, strum::Display)]
+#[strum(serialize_all = "snake_case")]
+#[non_exhaustive]
+pub enum Kind {
+    /// A rule returned `state::deny()`.
+    Deny,
+    /// A rule returned `state::reject()`.
+    Reject,
+    /// The client or the sender is blocked by the access lists.
+    AccessList,
+    /// The authenticated client is not allowed to send as the sender.
+    SenderNotOwned,
+    /// The authenticated client has exhausted its sustained submission quota.
+    QuotaExceeded,
+    /// The recipient is not a known mailbox.
+    UnknownRecipient,
+    /// The client has sent too many unknown recipients.
+    RecipientProbing,
+    /// The message exceeds the maximum size.
+    SizeExceeded,
+    /// The delivery to the recipient failed permanently.
+    DeliveryFailed,
+    /// The maximum number of connections of the server, of the address of the client
+    /// or of the authenticated user is reached.
+    ConnectionLimit,
+    /// The transaction has the maximum number of recipients.
+    RecipientLimit,
+    /// The recipient has already been given in the transaction.
+    DuplicateRecipient,
+    /// The client has reset too many transactions.
+    TransactionChurn,
+}
+
+/// A policy rejection, see the [module documentation](self).
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct PolicyEvent {
+    /// What produced the rejection.
+    pub kind: Kind,
+    /// Stage of the transaction, or of the rules.
+    pub stage: Option<String>,
+    /// Name of the rule which returned the status.
+    pub rule: Option<String>,
+    /// Address of the client.
+    pub client_ip: Option<std::net::IpAddr>,
+    /// Identifier of the connection.
+    pub connection_uuid: Option<uuid::Uuid>,
+    /// Identifier of the message.
+    pub message_uuid: Option<uuid::Uuid>,
+    /// Reverse path of the transaction, `<>` for the null sender.
+    pub sender: Option<String>,
+    /// The recipient refused.
+    pub recipient: Option<String>,
+    /// Arguments of the `MAIL FROM` command as sent by the client.
+    pub raw_mail_from: Option<String>,
+    /// Arguments of the `RCPT TO` command refused, as sent by the client.
+    pub raw_rcpt: Option<String>,
+    /// Code of the reply sent to the client, or received from the remote server.
+    pub reply_code: Option<u16>,
+    /// Text of the reply, or reason of the failure.
+    pub reason: Option<String>,
+}
+
+impl PolicyEvent {
+    /// An event with the client, the identifiers and the sender of the transaction of `ctx`.
+    #[must_use]
+    #[inline]
+    pub fn new(kind: Kind, ctx: &Context) -> Self {
+        Self {
+            kind,
+            stage: Some(ctx.stage().to_string()),
+            rule: None,
+            client_ip: Some(ctx.client_addr().ip()),
+            connection_uuid: Some(*ctx.connection_uuid()),
+            message_uuid: ctx.message_uuid().ok().copied(),
+            sender: ctx.reverse_path().ok().map(|reverse_path| {
+                reverse_path
+                    .as_ref()
+                    .map_or_else(|| "<>".to_owned(), ToString::to_string)
+            }),
+            recipient: None,
+            raw_mail_from: ctx.raw_mail_from().ok().flatten().map(str::to_owned),
+            raw_rcpt: None,
+            reply_code: None,
+            reason: None,
+        }
+    }
+
+    /// An event with only the address of the client, for a connection refused before
+    /// its session is started.
+    #[must_use]
+    #[inline]
+    pub const fn of_client(kind: Kind, client_ip: std::net::IpAddr) -> Self {
+        Self {
+            kind,
+            stage: None,
+            rule: None,
+            client_ip: Some(client_ip),
+            connection_uuid: None,
+            message_uuid: None,
+            sender: None,
+            recipient: None,
+            raw_mail_from: None,
+            raw_rcpt: None,
+            reply_code: None,
+            reason: None,
+        }
+    }
+
+    /// An event with the client, the identifiers and the sender of a received message.
+    #[must_use]
+    #[inline]
+    pub fn of_finished(kind: Kind, ctx: &ContextFinished) -> Self {
+        Self {
+            kind,
+            stage: None,
+            rule: None,
+            client_ip: Some(ctx.connect.client_addr.ip()),
+            connection_uuid: Some(ctx.connect.connect_uuid),
+            message_uuid: Some(ctx.mail_from.message_uuid),
+            sender: Some(
+                ctx.mail_from
+                    .reverse_path
+                    .as_ref()
+                    .map_or_else(|| "<>".to_owned(), ToString::to_string),
+            ),
+            recipient: None,
+            raw_mail_from: ctx.mail_from.raw_mail_from.clone(),
+            raw_rcpt: None,
+            reply_code: None,
+            reason: None,
+        }
+    }
+
+    /// Set the name of the stage.
+    #[must_use]
+    #[inline]
+    pub fn with_stage(self, stage: impl Into<String>) -> Self {
+        Self {
+            stage: Some(stage.into()),
+            ..self
+        }
+    }
+
+    /// Set the name of the rule.
+    #[must_use]
+    #[inline]
+    pub fn with_rule(self, rule: impl Into<String>) -> Self {
+        Self {
+            rule: Some(rule.into()),
+            ..self
+        }
+    }
+
+    /// Set the recipient refused.
+    #[must_use]
+    #[inline]
+    pub fn with_recipient(self, recipient: &Address) -> Self {
+        Self {
+            recipient: Some(recipient.to_string()),
+            ..self
+        }
+    }
+
+    /// Set the arguments of the `MAIL FROM` command, before the transaction has them.
+    #[must_use]
+    #[inline]
+    pub fn with_raw_mail_from(self, raw: &str) -> Self {
+        Self {
+            raw_mail_from: Some(raw.to_owned()),
+            ..self
+        }
+    }
+
+    /// Set the arguments of the `RCPT TO` command refused.
+    #[must_use]
+    #[inline]
+    pub fn with_raw_rcpt(self, raw: &str) -> Self {
+        Self {
+            raw_rcpt: Some(raw.to_owned()),
+            ..self
+        }
+    }
+
+    /// Set the code and the text of the reply.
+    #[must_use]
+    #[inline]
+    pub fn with_reply(self, reply: &Reply) -> Self {
+        Self {
+            reply_code: Some(reply.code().value()),
+            reason: Some(
+                reply
+                    .lines()
+                    .map(String::as_str)
+                    .collect::<Vec<_>>()
+                    .join(" "),
+            ),
+            ..self
+        }
+    }
+
+    /// Set the sender, before the transaction has one.
+    #[must_use]
+    #[inline]
+    pub fn with_sender(self, sender: Option<&Address>) -> Self {
+        Self {
+            sender: Some(sender.map_or_else(|| "<>".to_owned(), ToString::to_string)),
+            ..self
+        }
+    }
+
+    /// Set the code and the text of the last reply of the remote server, or the error
+    /// itself if it does not come from a reply.
+    #[must_use]
+    #[inline]
+    pub fn with_error(self, error: &crate::transfer::Error) -> Self {
+        let reply = error.reply();
+        Self {
+            reply_code: reply
+                .and_then(|reply| reply.get(..3))
+                .and_then(|code| code.parse().ok()),
+            reason: Some(reply.map_or_else(|| error.variant().to_string(), str::to_owned)),
+            ..self
+        }
+    }
+
+    /// Set the reason of the failure.
+    #[must_use]
+    #[inline]
+    pub fn with_reason(self, reason: impl Into<String>) -> Self {
+        Self {
+            reason: Some(reason.into()),
+            ..self
+        }
+    }
+
+    /// Emit the event, with the target [`TARGET`].
+    #[inline]
+    pub fn emit(&self) {
+        tracing::warn!(
+            target: TARGET,
+            kind = %self.kind,
+            stage = self.stage.as_deref(),
+            rule = self.rule.as_deref(),
+            client_ip = self.client_ip.map(tracing::field::display),
+            connection_uuid = self.connection_uuid.map(tracing::field::display),
+            message_uuid = self.message_uuid.map(tracing::field::display),
+            sender = self.sender.as_deref(),
+            recipient = self.recipient.as_deref(),
+            raw_mail_from = self.raw_mail_from.as_deref(),
+            raw_rcpt = self.raw_rcpt.as_deref(),
+            reply_code = self.reply_code,
+            reason = self.reason.as_deref(),
+            "Policy rejection."
+        );
+    }
+}
@@ -42,6 +42,11 @@ pub enum Status {
     /// this status disable delivery to all recipients.
     Quarantine(String),
 
+    /// ignore all future rules for the transaction.
+    /// the message is kept in the deferred queue, and its delivery is
+    /// attempted once the delay given has elapsed.
+    Defer(std::time::Duration),
+
     /// the email as been delegated to another service.
     // #[cfg(feature = "delegation")]
     #[serde(skip)]
@@ -60,7 +65,11 @@ impl Status {
     pub const fn is_finished(&self) -> bool {
         matches!(
             self,
-            Self::Faccept(_) | Self::Deny(_) | Self::Quarantine(_) | Self::Delegated(_)
+            Self::Faccept(_)
+                | Self::Deny(_)
+                | Self::Quarantine(_)
+                | Self::Defer(_)
+                | Self::Delegated(_)
         )
     }
 }
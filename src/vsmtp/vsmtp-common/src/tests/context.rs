@@ -0,0 +1,233 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2022 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use crate::{
+    auth::Credentials,
+    transport::{AbstractTransport, DeliverTo, GetID, TransportStates},
+    ClientName, Context, ContextFinished, MailFromProperties, MimeBodyType, Stage, TransactionType,
+};
+
+/// Delegate to the system allocator, counting the allocations of the current thread.
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: core::cell::Cell<usize> = const { core::cell::Cell::new(0) };
+}
+
+#[allow(unsafe_code)]
+// SAFETY: the system allocator is used for every operation
+unsafe impl std::alloc::GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: std::alloc::Layout) -> *mut u8 {
+        ALLOCATIONS.with(|count| count.set(count.get() + 1));
+        // SAFETY: same contract as `GlobalAlloc::alloc`
+        unsafe { std::alloc::System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: std::alloc::Layout) {
+        // SAFETY: same contract as `GlobalAlloc::dealloc`
+        unsafe { std::alloc::System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+fn count_allocations(f: impl FnOnce()) -> usize {
+    let before = ALLOCATIONS.with(core::cell::Cell::get);
+    f();
+    ALLOCATIONS.with(core::cell::Cell::get) - before
+}
+
+#[test]
+fn stage_transitions_do_not_allocate() {
+    let mut ctx = Context::new(
+        "127.0.0.1:25".parse().unwrap(),
+        "127.0.0.1:5977".parse().unwrap(),
+        "testserver.com".parse().unwrap(),
+        time::OffsetDateTime::now_utc(),
+        uuid::Uuid::new_v4(),
+    );
+    ctx.with_credentials(Credentials::Verify {
+        authid: "john".to_owned(),
+        authpass: "doe".to_owned(),
+    })
+    .unwrap();
+
+    let client_name = ClientName::Domain("client.testserver.com".parse().unwrap());
+    let reverse_path = addr!("john.doe@example.com");
+
+    let allocations = count_allocations(|| {
+        ctx.to_helo(client_name, false).unwrap();
+        ctx.to_mail_from(Some(reverse_path), false, None, None)
+            .unwrap();
+        ctx.set_transaction_type(TransactionType::Internal).unwrap();
+        ctx.to_finished().unwrap();
+        ctx.reset();
+    });
+
+    assert_eq!(allocations, 0);
+    assert_eq!(ctx.stage(), Stage::Helo);
+    assert_eq!(
+        ctx.client_name().unwrap().to_string(),
+        "client.testserver.com"
+    );
+    assert!(ctx.auth().is_some());
+}
+
+#[test]
+fn body_type() {
+    let mut ctx = Context::new(
+        "127.0.0.1:25".parse().unwrap(),
+        "127.0.0.1:5977".parse().unwrap(),
+        "testserver.com".parse().unwrap(),
+        time::OffsetDateTime::now_utc(),
+        uuid::Uuid::new_v4(),
+    );
+    ctx.body_type().unwrap_err();
+
+    ctx.to_helo(
+        ClientName::Domain("client.testserver.com".parse().unwrap()),
+        false,
+    )
+    .unwrap();
+    ctx.to_mail_from(
+        Some(addr!("john.doe@example.com")),
+        false,
+        Some(MimeBodyType::EightBitMime),
+        None,
+    )
+    .unwrap();
+    assert_eq!(ctx.body_type().unwrap(), Some(MimeBodyType::EightBitMime));
+
+    ctx.set_transaction_type(TransactionType::Internal).unwrap();
+    ctx.to_finished().unwrap();
+    let ContextFinished { mail_from, .. } = ctx.unwrap_finished().unwrap();
+    let serialized = serde_json::to_value(&mail_from).unwrap();
+    assert_eq!(serialized["body_type"], "8BITMIME");
+
+    // not declared, or queued before the body type was recorded
+    let mut serialized = serialized;
+    serialized.as_object_mut().unwrap().remove("body_type");
+    assert_eq!(
+        serde_json::from_value::<MailFromProperties>(serialized)
+            .unwrap()
+            .body_type,
+        None
+    );
+}
+
+#[test]
+fn declared_size() {
+    let mut ctx = Context::new(
+        "127.0.0.1:25".parse().unwrap(),
+        "127.0.0.1:5977".parse().unwrap(),
+        "testserver.com".parse().unwrap(),
+        time::OffsetDateTime::now_utc(),
+        uuid::Uuid::new_v4(),
+    );
+    ctx.declared_size().unwrap_err();
+
+    ctx.to_helo(
+        ClientName::Domain("client.testserver.com".parse().unwrap()),
+        false,
+    )
+    .unwrap();
+    ctx.to_mail_from(Some(addr!("john.doe@example.com")), false, None, Some(1024))
+        .unwrap();
+    assert_eq!(ctx.declared_size().unwrap(), Some(1024));
+
+    ctx.set_transaction_type(TransactionType::Internal).unwrap();
+    ctx.to_finished().unwrap();
+    let ContextFinished { mail_from, .. } = ctx.unwrap_finished().unwrap();
+    let mut serialized = serde_json::to_value(&mail_from).unwrap();
+    assert_eq!(serialized["declared_size"], 1024);
+
+    // queued before the declared size was recorded
+    serialized.as_object_mut().unwrap().remove("declared_size");
+    assert_eq!(
+        serde_json::from_value::<MailFromProperties>(serialized)
+            .unwrap()
+            .declared_size,
+        None
+    );
+}
+
+#[derive(serde::Serialize)]
+struct Transport(&'static str);
+
+impl GetID for Transport {}
+
+#[async_trait::async_trait]
+impl AbstractTransport for Transport {
+    async fn deliver(
+        self: std::sync::Arc<Self>,
+        _: &TransportStates,
+        _: &ContextFinished,
+        rcpt_to: DeliverTo,
+        _: &[u8],
+    ) -> DeliverTo {
+        rcpt_to
+    }
+}
+
+#[test]
+fn transport_states() {
+    let states = TransportStates::default().with(std::sync::Arc::new(Transport("state")));
+
+    assert_eq!(states.get::<Transport>().map(|state| state.0), Some("state"));
+    assert!(states.get::<String>().is_none());
+    assert!(TransportStates::default().get::<Transport>().is_none());
+}
+
+#[test]
+fn unicode_equivalent_forward_paths() {
+    let mut ctx = Context::new(
+        "127.0.0.1:25".parse().unwrap(),
+        "127.0.0.1:5977".parse().unwrap(),
+        "testserver.com".parse().unwrap(),
+        time::OffsetDateTime::now_utc(),
+        uuid::Uuid::new_v4(),
+    );
+    ctx.to_helo(
+        ClientName::Domain("client.testserver.com".parse().unwrap()),
+        false,
+    )
+    .unwrap();
+    ctx.to_mail_from(Some(addr!("john.doe@example.com")), true, None, None)
+        .unwrap();
+
+    // "é" as one code point, and as "e" followed by the combining acute accent
+    let composed = addr!("andr\u{e9}@example.com");
+    let decomposed = addr!("andre\u{301}@example.com");
+
+    ctx.add_forward_path(composed.clone(), std::sync::Arc::new(Transport("first")))
+        .unwrap();
+
+    ctx.set_transport_for_one(&decomposed, std::sync::Arc::new(Transport("second")))
+        .unwrap();
+    let delivery = ctx
+        .delivery()
+        .unwrap()
+        .values()
+        .flatten()
+        .map(|(rcpt, _)| rcpt.full())
+        .collect::<Vec<_>>();
+    assert_eq!(delivery, [composed.full()]);
+
+    assert!(ctx.remove_forward_path(&decomposed).unwrap());
+    assert!(ctx.forward_paths().unwrap().is_empty());
+    assert!(ctx.delivery().unwrap().values().all(Vec::is_empty));
+}
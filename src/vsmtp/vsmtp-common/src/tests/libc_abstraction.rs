@@ -14,7 +14,7 @@
  * this program. If not, see https://www.gnu.org/licenses/.
  *
 */
-use crate::libc_abstraction::{chown, if_indextoname, if_nametoindex, setgid, setuid};
+use crate::libc_abstraction::{chown, free_space, if_indextoname, if_nametoindex, setgid, setuid};
 
 #[test]
 fn test_setuid_current() {
@@ -72,3 +72,9 @@ fn test_chown_file() {
 
     std::fs::remove_file(file_to_create).unwrap();
 }
+
+#[test]
+fn test_free_space() {
+    free_space(&std::env::temp_dir()).unwrap();
+    free_space(std::path::Path::new("./no_such_file_exist")).unwrap_err();
+}
@@ -40,6 +40,14 @@ pub enum LocalDelivery {
         // FIXME: should be a type `Mailbox` ?
         mailbox: String,
     },
+    /// The expansion of an alias loops, is deeper than the maximum allowed, or is empty
+    #[error("alias `{alias}` cannot be expanded: {reason}")]
+    Alias {
+        /// Address of the alias
+        alias: String,
+        /// Why the expansion stopped
+        reason: String,
+    },
     ///
     // FIXME: should be std::io::Error ?
     #[error("todo")]
@@ -55,6 +63,14 @@ pub enum Lookup {
     #[error("record not found")]
     NoRecords {},
 
+    /// The domain queried does not exist (`NXDOMAIN`)
+    #[error("domain not found")]
+    DomainNotFound {},
+
+    /// The server failed to answer the query (`SERVFAIL`, `REFUSED`, ...)
+    #[error("server failure: {0}")]
+    ServerFailure(String),
+
     /// The lookup returned a record with a null MX
     #[error("null MX record found for '{domain}'")]
     ContainsNullMX {
@@ -101,6 +117,11 @@ pub enum Queuer {
     /// Failed too many time to deliver the email
     #[error("max deferred attempt reached")]
     MaxDeferredAttemptReached,
+
+    /// The processing of the message panicked, the message is isolated so that
+    /// it cannot make the next attempts panic again
+    #[error("the processing of the message panicked")]
+    ProcessingPanic,
 }
 
 /// Errors produced by a SMTP exchange
@@ -177,6 +198,10 @@ pub enum Delivery {
         /// The source of the error
         with_source: Option<String>,
     },
+
+    /// The message contains 8-bit data, and the server does not support 8BITMIME
+    #[error("the message contains 8-bit data but the server does not support 8BITMIME")]
+    EightBitMimeNotSupported,
 }
 
 impl From<std::io::Error> for Delivery {
@@ -242,9 +267,13 @@ impl From<trust_dns_resolver::error::ResolveError> for Lookup {
             }
             trust_dns_resolver::error::ResolveErrorKind::Msg(e) => Self::Message(e.to_string()),
             trust_dns_resolver::error::ResolveErrorKind::NoConnections => Self::NoConnections,
-            trust_dns_resolver::error::ResolveErrorKind::NoRecordsFound { .. } => {
-                Self::NoRecords {}
-            }
+            trust_dns_resolver::error::ResolveErrorKind::NoRecordsFound {
+                response_code, ..
+            } => match *response_code {
+                trust_dns_resolver::proto::op::ResponseCode::NoError => Self::NoRecords {},
+                trust_dns_resolver::proto::op::ResponseCode::NXDomain => Self::DomainNotFound {},
+                otherwise => Self::ServerFailure(otherwise.to_string()),
+            },
             trust_dns_resolver::error::ResolveErrorKind::Io(io) => Self::IO(io.to_string()),
             trust_dns_resolver::error::ResolveErrorKind::Proto(proto) => {
                 Self::Proto(proto.to_string())
@@ -292,10 +321,32 @@ impl From<lettre::transport::smtp::Error> for Delivery {
     }
 }
 
+/// Texts of the `4xx` replies asking the client to slow down (lowercase).
+const RATE_LIMIT_PATTERNS: &[&str] = &[
+    "rate limit",
+    "rate-limit",
+    "ratelimit",
+    "too many",
+    "throttl",
+    "slow down",
+    "try again later",
+    "4.7.28",
+];
+
+/// Texts of the `4xx` replies of a server greylisting the client (lowercase).
+const GREYLIST_PATTERNS: &[&str] = &["greylist", "graylist", "grey-list", "gray-list"];
+
+fn contains_any(text: Option<&str>, patterns: &[&str]) -> bool {
+    text.map_or(false, |text| {
+        let text = text.to_ascii_lowercase();
+        patterns.iter().any(|pattern| text.contains(pattern))
+    })
+}
+
 impl Delivery {
     fn is_permanent(&self) -> bool {
         match self {
-            Self::Permanent { .. } => true,
+            Self::Permanent { .. } | Self::EightBitMimeNotSupported => true,
 
             Self::ReplyParsing { .. }
             | Self::Transient { .. }
@@ -304,6 +355,110 @@ impl Delivery {
             | Self::Connection { .. } => false,
         }
     }
+
+    /// Is the error a response of the server asking to slow down?
+    #[must_use]
+    #[inline]
+    pub fn is_rate_limited(&self) -> bool {
+        match self {
+            Self::Transient { reply, with_source } => {
+                reply.value() == 421 || contains_any(with_source.as_deref(), RATE_LIMIT_PATTERNS)
+            }
+            Self::ReplyParsing { .. }
+            | Self::Permanent { .. }
+            | Self::Tls { .. }
+            | Self::Client { .. }
+            | Self::Connection { .. }
+            | Self::EightBitMimeNotSupported => false,
+        }
+    }
+
+    /// Is the error a response of a server greylisting the client?
+    #[must_use]
+    #[inline]
+    pub fn is_greylisted(&self) -> bool {
+        matches!(self, Self::Transient { with_source, .. }
+            if contains_any(with_source.as_deref(), GREYLIST_PATTERNS))
+    }
+
+    fn reason(&self) -> Reason {
+        match self {
+            Self::Tls { .. } => Reason::Tls,
+            Self::Connection { with_source } => {
+                if contains_any(with_source.as_deref(), &["refused"]) {
+                    Reason::ConnectionRefused
+                } else {
+                    Reason::Connection
+                }
+            }
+            // NOTE: the greylisting replies often ask to try again later.
+            Self::Transient { .. } if self.is_greylisted() => Reason::Greylisted,
+            Self::Transient { .. } if self.is_rate_limited() => Reason::RateLimited,
+            Self::Transient { .. } | Self::ReplyParsing { .. } => Reason::Transient,
+            Self::Permanent { .. } | Self::EightBitMimeNotSupported => Reason::Refused,
+            Self::Client { .. } => Reason::Other,
+        }
+    }
+
+    /// The reply of the server which produced the error, `<code> <text>`.
+    fn reply(&self) -> Option<String> {
+        match self {
+            Self::Permanent { reply, with_source } | Self::Transient { reply, with_source } => {
+                Some(with_source.as_ref().map_or_else(
+                    || reply.to_string(),
+                    |text| format!("{} {text}", reply.value()),
+                ))
+            }
+            Self::ReplyParsing { with_source } => with_source.clone(),
+            Self::Tls { .. }
+            | Self::Client { .. }
+            | Self::Connection { .. }
+            | Self::EightBitMimeNotSupported => None,
+        }
+    }
+}
+
+/// Category of a delivery error, to decide if the retries need a manual intervention.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    strum::Display,
+    serde::Serialize,
+    serde::Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum Reason {
+    /// The TLS negotiation failed, or was required and not offered
+    Tls,
+    /// The server has greylisted the client
+    Greylisted,
+    /// The server asked the client to slow down
+    RateLimited,
+    /// The server refused the connection
+    ConnectionRefused,
+    /// The connection failed, timed out or was closed
+    Connection,
+    /// The server replied with another transient error
+    Transient,
+    /// The server replied with a permanent error, for some of its addresses
+    Refused,
+    /// The lookup of the target failed
+    Lookup,
+    /// The local delivery failed
+    Local,
+    /// The rules denied the delivery
+    Rules,
+    /// Any other error
+    #[default]
+    Other,
 }
 
 impl Variant {
@@ -313,19 +468,24 @@ impl Variant {
     pub fn is_permanent(&self) -> bool {
         match self {
             Self::LocalDelivery(
-                LocalDelivery::MailboxDoNotExist { .. } | LocalDelivery::Other(_),
+                LocalDelivery::MailboxDoNotExist { .. }
+                | LocalDelivery::Alias { .. }
+                | LocalDelivery::Other(_),
             )
             | Self::Envelop(Envelop::NoRecipient)
-            | Self::Queuer(Queuer::StillWaiting | Queuer::MaxDeferredAttemptReached) => true,
+            | Self::Queuer(
+                Queuer::StillWaiting | Queuer::MaxDeferredAttemptReached | Queuer::ProcessingPanic,
+            )
+            | Self::Lookup(Lookup::DomainNotFound {} | Lookup::ContainsNullMX { .. }) => true,
 
             Self::Lookup(
                 Lookup::NoRecords {}
+                | Lookup::ServerFailure(_)
                 | Lookup::TimedOut
                 | Lookup::NoConnections
                 | Lookup::IO(_)
                 | Lookup::Proto(_)
                 | Lookup::Message(_)
-                | Lookup::ContainsNullMX { .. }
                 | Lookup::NotImplemented,
             )
             | Self::Rules(Rule::Denied(_)) => false,
@@ -333,4 +493,141 @@ impl Variant {
             Self::Delivery(attempts) => attempts.iter().all(|(_, e)| e.is_permanent()),
         }
     }
+
+    /// Has a server refused the message because it contains 8-bit data
+    #[must_use]
+    #[inline]
+    pub fn is_eightbitmime_not_supported(&self) -> bool {
+        matches!(self, Self::Delivery(attempts)
+            if attempts.iter().any(|(_, e)| matches!(e, Delivery::EightBitMimeNotSupported)))
+    }
+
+    /// The category of the error, the one of the last attempt for a delivery.
+    #[must_use]
+    #[inline]
+    pub fn reason(&self) -> Reason {
+        match self {
+            Self::LocalDelivery(_) => Reason::Local,
+            Self::Lookup(_) => Reason::Lookup,
+            Self::Rules(_) => Reason::Rules,
+            Self::Envelop(_) | Self::Queuer(_) => Reason::Other,
+            Self::Delivery(attempts) => attempts
+                .last()
+                .map_or(Reason::Other, |(_, error)| error.reason()),
+        }
+    }
+
+    /// The last reply of a remote server, `<code> <text>`, if the error comes from one.
+    #[must_use]
+    #[inline]
+    pub fn last_reply(&self) -> Option<String> {
+        match self {
+            Self::Delivery(attempts) => attempts.iter().rev().find_map(|(_, error)| error.reply()),
+            Self::LocalDelivery(_)
+            | Self::Envelop(_)
+            | Self::Lookup(_)
+            | Self::Queuer(_)
+            | Self::Rules(_) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transient(code: u16, text: &str) -> Delivery {
+        Delivery::Transient {
+            reply: ReplyCode::Code { code },
+            with_source: Some(text.to_owned()),
+        }
+    }
+
+    fn attempt(error: Delivery) -> Variant {
+        Variant::Delivery(vec![(
+            Target::Domain("mx.example.com".parse().unwrap()),
+            error,
+        )])
+    }
+
+    #[test]
+    fn rate_limit_responses() {
+        assert!(transient(421, "Service not available").is_rate_limited());
+        assert!(transient(
+            451,
+            "4.7.28 Our system has detected an unusual rate of unsolicited mail"
+        )
+        .is_rate_limited());
+        assert!(transient(450, "Too many connections, try again later").is_rate_limited());
+        assert!(!transient(450, "Mailbox busy").is_rate_limited());
+        assert!(!Delivery::Permanent {
+            reply: ReplyCode::Code { code: 550 },
+            with_source: Some("rate limit exceeded".to_owned()),
+        }
+        .is_rate_limited());
+    }
+
+    #[test]
+    fn reasons() {
+        for (error, reason) in [
+            (
+                transient(
+                    450,
+                    "4.2.0 <jenny@example.com>: Recipient address rejected: Greylisted, try again later",
+                ),
+                Reason::Greylisted,
+            ),
+            (transient(421, "4.7.0 Try again later"), Reason::RateLimited),
+            (transient(452, "4.2.2 Mailbox full"), Reason::Transient),
+            (
+                Delivery::Tls {
+                    with_source: Some("invalid peer certificate".to_owned()),
+                },
+                Reason::Tls,
+            ),
+            (
+                Delivery::Connection {
+                    with_source: Some("Connection refused (os error 111)".to_owned()),
+                },
+                Reason::ConnectionRefused,
+            ),
+            (
+                Delivery::Connection {
+                    with_source: Some("timed out".to_owned()),
+                },
+                Reason::Connection,
+            ),
+        ] {
+            assert_eq!(attempt(error).reason(), reason);
+        }
+
+        assert_eq!(Variant::Lookup(Lookup::TimedOut).reason(), Reason::Lookup);
+        assert_eq!(Variant::Delivery(vec![]).reason(), Reason::Other);
+    }
+
+    #[test]
+    fn last_reply() {
+        assert_eq!(
+            attempt(transient(452, "4.2.2 Mailbox full")).last_reply(),
+            Some("452 4.2.2 Mailbox full".to_owned())
+        );
+        assert_eq!(
+            Variant::Delivery(vec![
+                (
+                    Target::Domain("mx1.example.com".parse().unwrap()),
+                    transient(451, "4.3.0 Temporary failure"),
+                ),
+                (
+                    Target::Domain("mx2.example.com".parse().unwrap()),
+                    Delivery::Connection { with_source: None },
+                ),
+            ])
+            .last_reply(),
+            Some("451 4.3.0 Temporary failure".to_owned())
+        );
+        assert_eq!(
+            attempt(Delivery::Tls { with_source: None }).last_reply(),
+            None
+        );
+    }
 }
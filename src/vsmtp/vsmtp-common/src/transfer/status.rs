@@ -15,7 +15,7 @@
  *
 */
 
-use super::error::Variant;
+use super::error::{Reason, Variant};
 
 /// the delivery status of the email of the current rcpt.
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
@@ -77,7 +77,7 @@ impl Default for Status {
     #[inline]
     fn default() -> Self {
         Self::Waiting {
-            timestamp: time::OffsetDateTime::now_utc(),
+            timestamp: crate::clock::now(),
         }
     }
 }
@@ -110,12 +110,22 @@ impl Status {
         }
     }
 
+    /// The last error of a recipient held back, to know why it is retried.
+    #[must_use]
+    #[inline]
+    pub fn held_back_error(&self) -> Option<&Error> {
+        match self {
+            Self::HeldBack { errors } => errors.last(),
+            Self::Waiting { .. } | Self::Sent { .. } | Self::Failed { .. } => None,
+        }
+    }
+
     ///
     #[inline]
     #[must_use]
     pub fn sent() -> Self {
         Self::Sent {
-            timestamp: time::OffsetDateTime::now_utc(),
+            timestamp: crate::clock::now(),
         }
     }
 
@@ -137,6 +147,12 @@ pub struct Error {
     variant: Variant,
     #[serde(with = "time::serde::iso8601")]
     timestamp: time::OffsetDateTime,
+    /// Category of the error, see [`Variant::reason`].
+    #[serde(default)]
+    reason: Reason,
+    /// Last reply of the remote server, see [`Variant::last_reply`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    reply: Option<String>,
 }
 
 #[cfg(feature = "testing")]
@@ -148,11 +164,15 @@ impl PartialEq for Error {
         let Self {
             variant: self_variant,
             timestamp: _,
+            reason: _,
+            reply: _,
         } = self;
 
         let Self {
             variant: other_variant,
             timestamp: _,
+            reason: _,
+            reply: _,
         } = other;
 
         self_variant == other_variant
@@ -165,13 +185,14 @@ impl Error {
     #[inline]
     pub fn new(variant: Variant) -> Self {
         Self {
+            reason: variant.reason(),
+            reply: variant.last_reply(),
             variant,
-            timestamp: time::OffsetDateTime::now_utc(),
+            timestamp: crate::clock::now(),
         }
     }
 
-    /// Get the underlying error (only for testing)
-    #[cfg(feature = "testing")]
+    /// Get the underlying error
     #[must_use]
     #[inline]
     pub const fn variant(&self) -> &Variant {
@@ -184,4 +205,18 @@ impl Error {
     pub const fn timestamp(&self) -> &time::OffsetDateTime {
         &self.timestamp
     }
+
+    /// Get the category of the error
+    #[must_use]
+    #[inline]
+    pub const fn reason(&self) -> Reason {
+        self.reason
+    }
+
+    /// Get the last reply of the remote server, `<code> <text>`
+    #[must_use]
+    #[inline]
+    pub fn reply(&self) -> Option<&str> {
+        self.reply.as_deref()
+    }
 }
@@ -44,13 +44,55 @@ pub const DESERIALIZER_SYMBOL_NAME: &str = "deserialize_transport";
 ///
 pub type DeliverTo = Vec<(Address, Status)>;
 
+/// The states shared by the deliveries of a runtime, given to [`AbstractTransport::deliver`].
+///
+/// Each state is stored under its own type, and read by the transports knowing it:
+/// the transports of `vsmtp-delivery` share the state of the outgoing connections
+/// (`vsmtp_delivery::DeliveryState`), the other transports can ignore them.
+#[derive(Default, Clone)]
+pub struct TransportStates {
+    states: std::collections::HashMap<
+        core::any::TypeId,
+        alloc::sync::Arc<dyn core::any::Any + Send + Sync>,
+    >,
+}
+
+impl core::fmt::Debug for TransportStates {
+    #[inline]
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("TransportStates").finish_non_exhaustive()
+    }
+}
+
+impl TransportStates {
+    /// Store `state`, replacing the previous state of its type.
+    #[must_use]
+    #[inline]
+    pub fn with<T: Send + Sync + 'static>(mut self, state: alloc::sync::Arc<T>) -> Self {
+        self.states.insert(core::any::TypeId::of::<T>(), state);
+        self
+    }
+
+    /// The state of type `T`, if stored.
+    #[must_use]
+    #[inline]
+    pub fn get<T: Send + Sync + 'static>(&self) -> Option<&T> {
+        self.states
+            .get(&core::any::TypeId::of::<T>())
+            .and_then(|state| state.downcast_ref::<T>())
+    }
+}
+
 /// Generic implementation of a transport
 #[allow(clippy::module_name_repetitions)]
 #[async_trait::async_trait]
 pub trait AbstractTransport: erased_serde::Serialize + GetID + Send + Sync {
     /// Take the data required to deliver the email and return the updated version of the recipient.
+    ///
+    /// `states` are shared by the deliveries of a runtime, see [`TransportStates`].
     async fn deliver(
         self: alloc::sync::Arc<Self>,
+        states: &TransportStates,
         context: &ContextFinished,
         rcpt_to: DeliverTo,
         message: &[u8],
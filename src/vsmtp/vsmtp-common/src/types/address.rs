@@ -16,12 +16,25 @@
 */
 
 use crate::Domain;
+use unicode_normalization::UnicodeNormalization;
 
 /// Address Email
+///
+/// The address is kept as received, but compared and hashed on its normalized form
+/// (see [`Address::normalized`]), so that the Unicode-equivalent spellings of a `SMTPUTF8`
+/// address are the same key in the recipient lists.
+///
+/// The domain is parsed once, on the creation of the address, as is its form for `lettre`
+/// (see [`Address::to_lettre`]).
 #[derive(Clone, Debug, Eq, serde_with::SerializeDisplay, serde_with::DeserializeFromStr)]
 pub struct Address {
     at_sign: usize,
     full: String,
+    normalized: String,
+    /// `None` if the address has been created unchecked with an invalid domain.
+    domain: Option<Domain>,
+    /// `None` if the address is not valid for `lettre`.
+    lettre: Option<lettre::Address>,
 }
 
 /// Syntax sugar Address object from dyn `ToString`
@@ -45,25 +58,26 @@ impl std::str::FromStr for Address {
         if let Err(error) = addr::parse_email_address(s) {
             anyhow::bail!("'{s}' is not a valid address: {error}")
         }
+        // NOTE: a quoted local part can contain a '@', the domain follows the last one.
         #[allow(clippy::expect_used)]
-        Ok(Self {
-            at_sign: s.find('@').expect("no '@' in address"),
-            full: s.to_owned(),
-        })
+        Ok(Self::with_at_sign(
+            s.rfind('@').expect("no '@' in address"),
+            s.to_owned(),
+        ))
     }
 }
 
 impl PartialEq for Address {
     #[inline]
     fn eq(&self, other: &Self) -> bool {
-        self.full == other.full
+        self.normalized == other.normalized
     }
 }
 
 impl std::hash::Hash for Address {
     #[inline]
     fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
-        self.full.hash(state);
+        self.normalized.hash(state);
     }
 }
 
@@ -75,6 +89,51 @@ impl std::fmt::Display for Address {
 }
 
 impl Address {
+    fn with_at_sign(at_sign: usize, full: String) -> Self {
+        #[allow(
+            clippy::indexing_slicing,
+            clippy::string_slice,
+            clippy::arithmetic_side_effects,
+            clippy::integer_arithmetic
+        )]
+        let domain = Domain::from_utf8(&full[at_sign + 1..]).ok();
+        Self::with_domain(at_sign, full, domain)
+    }
+
+    fn with_domain(at_sign: usize, full: String, domain: Option<Domain>) -> Self {
+        #[allow(
+            clippy::indexing_slicing,
+            clippy::string_slice,
+            clippy::arithmetic_side_effects,
+            clippy::integer_arithmetic
+        )]
+        let (local_part, domain_part) = (&full[..at_sign], &full[at_sign + 1..]);
+
+        // the local part is case sensitive, only its Unicode form is normalized.
+        let local_part = if local_part.is_ascii() {
+            local_part.to_owned()
+        } else {
+            local_part.nfc().collect::<String>()
+        };
+        // the domain is case insensitive, an internationalized one is replaced by its punycode form.
+        let normalized_domain = match domain.as_ref() {
+            Some(domain) if !domain_part.is_ascii() => domain.to_ascii().to_ascii_lowercase(),
+            _ => domain_part.to_ascii_lowercase(),
+        };
+        let lettre = domain.as_ref().and_then(|domain| {
+            #[allow(clippy::indexing_slicing, clippy::string_slice)]
+            lettre::Address::new(&full[..at_sign], domain.to_string()).ok()
+        });
+
+        Self {
+            at_sign,
+            normalized: format!("{local_part}@{normalized_domain}"),
+            full,
+            domain,
+            lettre,
+        }
+    }
+
     /// get the full email address.
     #[must_use]
     #[inline]
@@ -82,6 +141,14 @@ impl Address {
         &self.full
     }
 
+    /// get the form of the address used to compare it: the local part in Unicode
+    /// normalization form C, and the domain in lowercase, punycode encoded.
+    #[must_use]
+    #[inline]
+    pub fn normalized(&self) -> &str {
+        &self.normalized
+    }
+
     /// get the user of the address.
     #[must_use]
     #[inline]
@@ -93,10 +160,21 @@ impl Address {
     /// get the fqdn of the address.
     #[must_use]
     #[inline]
-    #[allow(clippy::expect_used)]
     pub fn domain(&self) -> Domain {
-        #[allow(clippy::indexing_slicing, clippy::string_slice)]
-        Domain::from_utf8(&self.full[self.at_sign + 1..])
+        self.domain_ref().clone()
+    }
+
+    /// get the fqdn of the address, without copying it.
+    ///
+    /// # Panics
+    ///
+    /// * the address has been created unchecked with an invalid domain
+    #[must_use]
+    #[inline]
+    #[allow(clippy::expect_used)]
+    pub fn domain_ref(&self) -> &Domain {
+        self.domain
+            .as_ref()
             .expect("at this point, domain is valid (checked in `new`)")
     }
 
@@ -109,20 +187,33 @@ impl Address {
     #[inline]
     #[allow(clippy::unwrap_used)]
     pub fn new_unchecked(addr: String) -> Self {
-        Self {
-            at_sign: addr.find('@').unwrap(),
-            full: addr,
-        }
+        Self::with_at_sign(addr.rfind('@').unwrap(), addr)
+    }
+
+    /// create a new address from a local part and a domain already parsed, without
+    /// verifying the syntax of the local part nor parsing the domain again.
+    #[must_use]
+    #[inline]
+    pub fn from_parts_unchecked(local_part: &str, domain: Domain) -> Self {
+        Self::with_domain(
+            local_part.len(),
+            format!("{local_part}@{domain}"),
+            Some(domain),
+        )
     }
 
+    /// get the address for `lettre`, validated once on the creation of the address.
+    ///
     /// # Panics
     ///
     /// * if the address is not valid
     #[must_use]
     #[inline]
-    #[allow(clippy::unwrap_used)]
+    #[allow(clippy::expect_used)]
     pub fn to_lettre(&self) -> lettre::Address {
-        lettre::Address::new(self.local_part(), self.domain().to_string()).unwrap()
+        self.lettre
+            .clone()
+            .expect("the address is not valid for `lettre`")
     }
 }
 
@@ -136,10 +227,7 @@ mod tests {
         let parsed = serde_json::from_str::<Address>(r#""hello@domain.com""#).unwrap();
         assert_eq!(
             parsed,
-            Address {
-                full: "hello@domain.com".to_owned(),
-                at_sign: 6
-            }
+            Address::new_unchecked("hello@domain.com".to_owned())
         );
         assert_eq!(parsed.local_part(), "hello");
         assert_eq!(parsed.domain().to_string(), "domain.com");
@@ -148,12 +236,103 @@ mod tests {
     #[test]
     fn serialize() {
         assert_eq!(
-            serde_json::to_string(&Address {
-                full: "hello@domain.com".to_owned(),
-                at_sign: 6
-            })
-            .unwrap(),
+            serde_json::to_string(&Address::new_unchecked("hello@domain.com".to_owned())).unwrap(),
             r#""hello@domain.com""#
         );
     }
+
+    fn hash(address: &Address) -> u64 {
+        use std::hash::{Hash, Hasher};
+
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        address.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    #[test]
+    fn composed_and_decomposed() {
+        // "é" as one code point, and as "e" followed by the combining acute accent
+        let composed = "andr\u{e9}@example.com".parse::<Address>().unwrap();
+        let decomposed = "andre\u{301}@example.com".parse::<Address>().unwrap();
+
+        assert_ne!(composed.full(), decomposed.full());
+        assert_eq!(composed, decomposed);
+        assert_eq!(hash(&composed), hash(&decomposed));
+        assert_eq!(composed.normalized(), "andr\u{e9}@example.com");
+
+        // kept as received
+        assert_eq!(decomposed.to_string(), "andre\u{301}@example.com");
+        assert_eq!(
+            serde_json::to_string(&decomposed).unwrap(),
+            "\"andre\u{301}@example.com\""
+        );
+    }
+
+    #[test]
+    fn domain_normalized() {
+        let unicode = "user@m\u{fc}nchen.de".parse::<Address>().unwrap();
+        let punycode = "user@xn--mnchen-3ya.de".parse::<Address>().unwrap();
+
+        assert_eq!(unicode.normalized(), "user@xn--mnchen-3ya.de");
+        assert_eq!(unicode, punycode);
+        assert_eq!(hash(&unicode), hash(&punycode));
+    }
+
+    #[test]
+    fn domain_case_insensitive() {
+        let lower = "John@example.com".parse::<Address>().unwrap();
+        let upper = "John@Example.COM".parse::<Address>().unwrap();
+
+        assert_eq!(upper.normalized(), "John@example.com");
+        assert_eq!(lower, upper);
+        assert_eq!(hash(&lower), hash(&upper));
+        assert_ne!(lower, "john@example.com".parse::<Address>().unwrap());
+    }
+
+    #[test]
+    fn quoted_local_part() {
+        let address = r#""john@doe"@example.com"#.parse::<Address>().unwrap();
+
+        assert_eq!(address.local_part(), r#""john@doe""#);
+        assert_eq!(address.domain().to_string(), "example.com");
+        assert_eq!(address.to_string(), r#""john@doe"@example.com"#);
+    }
+
+    #[test]
+    fn from_parts() {
+        let domain = "M\u{fc}nchen.de".parse::<Domain>().unwrap();
+        let address = Address::from_parts_unchecked("john", domain.clone());
+
+        assert_eq!(
+            address,
+            "john@xn--mnchen-3ya.de".parse::<Address>().unwrap()
+        );
+        assert_eq!(address.local_part(), "john");
+        assert_eq!(address.domain_ref(), &domain);
+        assert_eq!(address.to_lettre().domain(), domain.to_string());
+    }
+
+    #[test]
+    fn to_lettre() {
+        let address = "john.doe@example.com".parse::<Address>().unwrap();
+        assert_eq!(
+            address.to_lettre(),
+            "john.doe@example.com".parse::<lettre::Address>().unwrap()
+        );
+        assert_eq!(address.clone().to_lettre(), address.to_lettre());
+    }
+
+    #[test]
+    #[should_panic(expected = "the address is not valid for `lettre`")]
+    fn to_lettre_invalid() {
+        let _ = Address::new_unchecked("john doe@example.com".to_owned()).to_lettre();
+    }
+
+    #[test]
+    fn local_part_case_sensitive() {
+        assert_ne!(
+            "John@example.com".parse::<Address>().unwrap(),
+            "john@example.com".parse::<Address>().unwrap()
+        );
+    }
 }
@@ -0,0 +1,95 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2023 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+
+use crate::Address;
+
+/// Extension of the local part of an address (`lists` in `john+lists@example.com`),
+/// following the first of the recipient delimiters.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct AddressExtension {
+    /// The local part without the delimiter and the detail (`john`).
+    pub base: String,
+    /// The text following the delimiter (`lists`), can be empty.
+    pub detail: String,
+}
+
+impl AddressExtension {
+    /// Split the local part of `addr` on the first of the characters of `delimiters`.
+    ///
+    /// [`None`] if the local part contains none of them, or starts with one,
+    /// the base being empty then.
+    #[must_use]
+    #[inline]
+    pub fn parse(addr: &Address, delimiters: &str) -> Option<Self> {
+        let local_part = addr.local_part();
+        let (base, detail) = local_part.split_once(|c| delimiters.contains(c))?;
+
+        (!base.is_empty()).then(|| Self {
+            base: base.to_owned(),
+            detail: detail.to_owned(),
+        })
+    }
+
+    /// The address of the base, in the domain of `addr` (`john@example.com`).
+    #[must_use]
+    #[inline]
+    pub fn base_address(&self, addr: &Address) -> Address {
+        Address::from_parts_unchecked(&self.base, addr.domain())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AddressExtension;
+
+    #[test]
+    fn parse() {
+        let extension = |addr: &str, delimiters: &str| {
+            AddressExtension::parse(&addr!(addr), delimiters)
+                .map(|extension| (extension.base, extension.detail))
+        };
+
+        assert_eq!(
+            extension("john+lists@example.com", "+"),
+            Some(("john".to_owned(), "lists".to_owned()))
+        );
+        // the first delimiter found splits the local part.
+        assert_eq!(
+            extension("john-lists+vsmtp@example.com", "+-"),
+            Some(("john".to_owned(), "lists+vsmtp".to_owned()))
+        );
+        assert_eq!(
+            extension("john+@example.com", "+"),
+            Some(("john".to_owned(), String::new()))
+        );
+        assert_eq!(extension("john+lists@example.com", "-"), None);
+        assert_eq!(extension("john@example.com", "+"), None);
+        assert_eq!(extension("+lists@example.com", "+"), None);
+        assert_eq!(extension("john+lists@example.com", ""), None);
+    }
+
+    #[test]
+    fn base_address() {
+        let addr = addr!("john+lists@example.com");
+        assert_eq!(
+            AddressExtension::parse(&addr, "+")
+                .unwrap()
+                .base_address(&addr),
+            addr!("john@example.com")
+        );
+    }
+}
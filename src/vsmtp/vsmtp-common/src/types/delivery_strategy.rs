@@ -0,0 +1,72 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2023 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+/// How the `deliver` transport reaches the exchangers of a domain, chosen per message
+/// by the rules.
+#[derive(
+    Debug,
+    Default,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Hash,
+    strum::AsRefStr,
+    strum::Display,
+    strum::EnumString,
+    strum::EnumVariantNames,
+    serde_with::DeserializeFromStr,
+    serde_with::SerializeDisplay,
+)]
+#[strum(serialize_all = "snake_case")]
+pub enum DeliveryStrategy {
+    /// The exchangers are tried one after the other, by preference.
+    #[default]
+    Serial,
+    /// A connection is opened to the two most preferred exchangers concurrently, the message
+    /// is sent to the first one ready for a transaction, the other connection being closed
+    /// before `MAIL FROM`. For the latency-critical messages.
+    Race,
+}
+
+impl DeliveryStrategy {
+    /// Is the strategy the default one.
+    #[must_use]
+    #[inline]
+    pub const fn is_serial(&self) -> bool {
+        matches!(self, Self::Serial)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DeliveryStrategy;
+
+    #[test]
+    fn serde() {
+        for (strategy, name) in [
+            (DeliveryStrategy::Serial, "\"serial\""),
+            (DeliveryStrategy::Race, "\"race\""),
+        ] {
+            assert_eq!(serde_json::to_string(&strategy).unwrap(), name);
+            assert_eq!(
+                serde_json::from_str::<DeliveryStrategy>(name).unwrap(),
+                strategy
+            );
+        }
+        assert!("parallel".parse::<DeliveryStrategy>().is_err());
+    }
+}
@@ -0,0 +1,64 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2023 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+
+/// Body type declared by the client with the `BODY` parameter of the `MAIL FROM` command.
+///
+/// See "SMTP Service Extension for 8-bit MIME Transport"
+/// <https://datatracker.ietf.org/doc/html/rfc6152>
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    strum::AsRefStr,
+    strum::Display,
+    strum::EnumString,
+    strum::EnumVariantNames,
+    serde_with::DeserializeFromStr,
+    serde_with::SerializeDisplay,
+)]
+pub enum MimeBodyType {
+    /// The body contains 7-bit data only.
+    #[strum(serialize = "7BIT")]
+    SevenBit,
+    /// The body is MIME content which may contain 8-bit data.
+    #[strum(serialize = "8BITMIME")]
+    EightBitMime,
+    // TODO: https://datatracker.ietf.org/doc/html/rfc3030
+    // Binary,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MimeBodyType;
+
+    #[test]
+    fn serde() {
+        for (body_type, name) in [
+            (MimeBodyType::SevenBit, "\"7BIT\""),
+            (MimeBodyType::EightBitMime, "\"8BITMIME\""),
+        ] {
+            assert_eq!(serde_json::to_string(&body_type).unwrap(), name);
+            assert_eq!(
+                serde_json::from_str::<MimeBodyType>(name).unwrap(),
+                body_type
+            );
+        }
+        assert!(serde_json::from_str::<MimeBodyType>("\"BINARYMIME\"").is_err());
+    }
+}
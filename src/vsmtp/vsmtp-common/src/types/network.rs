@@ -0,0 +1,133 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2022 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+
+use std::net::IpAddr;
+
+/// An address, or a network of addresses in the CIDR notation (`192.0.2.0/24`, `2001:db8::/32`).
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, serde_with::DeserializeFromStr, serde_with::SerializeDisplay,
+)]
+pub struct Network {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl core::str::FromStr for Network {
+    type Err = anyhow::Error;
+
+    #[inline]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix) = s
+            .split_once('/')
+            .map_or((s, None), |(addr, prefix)| (addr, Some(prefix)));
+
+        let addr = addr.parse::<IpAddr>()?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = prefix.map_or(Ok(max), str::parse::<u8>)?;
+        anyhow::ensure!(prefix <= max, "invalid prefix length '{prefix}'");
+
+        Ok(Self { addr, prefix })
+    }
+}
+
+impl core::fmt::Display for Network {
+    #[inline]
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+impl Network {
+    /// Length of the prefix of the network.
+    #[inline]
+    #[must_use]
+    pub const fn prefix(&self) -> u8 {
+        self.prefix
+    }
+
+    /// Is the address `ip` in the network?
+    #[inline]
+    #[must_use]
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        // a IPv4 client accepted on a IPv6 socket.
+        let ip = crate::utils::canonical_ip(ip);
+
+        match (self.addr, ip) {
+            (IpAddr::V4(network), IpAddr::V4(ip_v4)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - u32::from(self.prefix))
+                    .unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip_v4) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip_v6)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - u32::from(self.prefix))
+                    .unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip_v6) & mask
+            }
+            (IpAddr::V4(_), IpAddr::V6(_)) | (IpAddr::V6(_), IpAddr::V4(_)) => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn networks() {
+        let network = "192.0.2.0/24".parse::<Network>().unwrap();
+        assert!(network.contains("192.0.2.42".parse().unwrap()));
+        assert!(network.contains("::ffff:192.0.2.42".parse().unwrap()));
+        assert!(!network.contains("192.0.3.1".parse().unwrap()));
+        assert!(!network.contains("2001:db8::1".parse().unwrap()));
+
+        let network = "2001:db8::/32".parse::<Network>().unwrap();
+        assert!(network.contains("2001:db8:1::1".parse().unwrap()));
+        assert!(!network.contains("2001:db9::1".parse().unwrap()));
+
+        assert!("0.0.0.0/0"
+            .parse::<Network>()
+            .unwrap()
+            .contains("203.0.113.1".parse().unwrap()));
+        assert!("192.0.2.1"
+            .parse::<Network>()
+            .unwrap()
+            .contains("192.0.2.1".parse().unwrap()));
+
+        for invalid in [
+            "192.0.2.0/33",
+            "192.0.2",
+            "foo",
+            "2001:db8::/129",
+            "10.0.0.0/",
+        ] {
+            assert!(invalid.parse::<Network>().is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn serde() {
+        let network = serde_json::from_str::<Network>(r#""10.0.0.0/8""#).unwrap();
+        assert!(network.contains("10.1.2.3".parse().unwrap()));
+        assert_eq!(serde_json::to_string(&network).unwrap(), r#""10.0.0.0/8""#);
+        assert_eq!(
+            serde_json::to_string(&"192.0.2.1".parse::<Network>().unwrap()).unwrap(),
+            r#""192.0.2.1/32""#
+        );
+    }
+}
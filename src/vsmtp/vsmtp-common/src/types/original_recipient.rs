@@ -0,0 +1,74 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2023 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use crate::Address;
+
+/// Original recipient of the message, given by the client with the `ORCPT`
+/// parameter of the `RCPT TO` command (decoded).
+///
+/// See <https://www.rfc-editor.org/rfc/rfc3461#section-4.2>
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct OriginalRecipient {
+    /// The type of address used in the `ORCPT` argument. (rfc822)
+    pub addr_type: String,
+    /// The original recipient address.
+    pub mailbox: Address,
+}
+
+impl OriginalRecipient {
+    /// Format the value of the `ORCPT` parameter to relay, `utf8` being set if
+    /// the `SMTPUTF8` extension is used for the transaction.
+    #[must_use]
+    #[inline]
+    pub fn to_param(&self, utf8: bool) -> String {
+        let mailbox = if self.addr_type.eq_ignore_ascii_case("utf-8") {
+            crate::xtext::encode_utf8_addr(self.mailbox.full(), utf8)
+        } else {
+            crate::xtext::encode(self.mailbox.full())
+        };
+        format!("{};{mailbox}", self.addr_type)
+    }
+}
+
+/// `<addr-type>;<mailbox>`, decoded.
+impl std::fmt::Display for OriginalRecipient {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{};{}", self.addr_type, self.mailbox)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::OriginalRecipient;
+
+    #[test]
+    fn to_param() {
+        let rfc822 = OriginalRecipient {
+            addr_type: "rfc822".to_owned(),
+            mailbox: addr!("john+doe@example.com"),
+        };
+        assert_eq!(rfc822.to_param(false), "rfc822;john+2Bdoe@example.com");
+        assert_eq!(rfc822.to_string(), "rfc822;john+doe@example.com");
+
+        let utf8 = OriginalRecipient {
+            addr_type: "utf-8".to_owned(),
+            mailbox: addr!("用户@example.com"),
+        };
+        assert_eq!(utf8.to_param(true), "utf-8;用户@example.com");
+        assert_eq!(utf8.to_param(false), "utf-8;\\x{7528}\\x{6237}@example.com");
+    }
+}
@@ -0,0 +1,74 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2023 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+
+/// Outcome of an SMTP session, determined when the connection is closed from
+/// the commands received, without changing the dialogue with the client.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Hash,
+    strum::AsRefStr,
+    strum::Display,
+    strum::EnumString,
+    strum::EnumVariantNames,
+    strum::EnumIter,
+    serde_with::DeserializeFromStr,
+    serde_with::SerializeDisplay,
+)]
+#[strum(serialize_all = "snake_case")]
+pub enum SessionCategory {
+    /// At least one message has been accepted.
+    Completed,
+    /// A sender has been accepted, but no message was received.
+    AbortedAfterMail,
+    /// Connection checks of the load balancers and monitoring agents: the client closed the
+    /// connection, optionally after `NOOP`, `RSET`, `HELP` or `QUIT`, without any other command.
+    Probe,
+    /// Only unknown commands, or commands out of sequence, were received.
+    Scanner,
+    /// The TLS handshake was started but did not complete.
+    TlsFailed,
+    /// Any other session, the client was refused at the connection or sent `HELO`/`EHLO`
+    /// and left without a transaction for example.
+    Other,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SessionCategory;
+
+    #[test]
+    fn serde() {
+        for (category, name) in [
+            (SessionCategory::Completed, "\"completed\""),
+            (SessionCategory::AbortedAfterMail, "\"aborted_after_mail\""),
+            (SessionCategory::Probe, "\"probe\""),
+            (SessionCategory::Scanner, "\"scanner\""),
+            (SessionCategory::TlsFailed, "\"tls_failed\""),
+            (SessionCategory::Other, "\"other\""),
+        ] {
+            assert_eq!(serde_json::to_string(&category).unwrap(), name);
+            assert_eq!(
+                serde_json::from_str::<SessionCategory>(name).unwrap(),
+                category
+            );
+        }
+    }
+}
@@ -0,0 +1,48 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2023 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+/// Number of recipients an authenticated user can submit, the limits of the submission
+/// quotas of the configuration or the ones chosen by the rules for the user.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SubmissionQuota {
+    /// Recipients per minute, the capacity of a bucket refilled continuously.
+    pub burst: u32,
+    /// Recipients per sustained period (a day by default).
+    pub sustained: u32,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SubmissionQuota;
+
+    #[test]
+    fn serde() {
+        let quota = SubmissionQuota {
+            burst: 30,
+            sustained: 1000,
+        };
+        assert_eq!(
+            serde_json::to_string(&quota).unwrap(),
+            r#"{"burst":30,"sustained":1000}"#
+        );
+        assert_eq!(
+            serde_json::from_str::<SubmissionQuota>(r#"{"burst":30,"sustained":1000}"#).unwrap(),
+            quota
+        );
+        assert!(serde_json::from_str::<SubmissionQuota>(r#"{"burst":30}"#).is_err());
+    }
+}
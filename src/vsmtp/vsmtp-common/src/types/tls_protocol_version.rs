@@ -39,6 +39,13 @@ impl std::str::FromStr for ProtocolVersion {
     }
 }
 
+impl core::hash::Hash for ProtocolVersion {
+    #[inline]
+    fn hash<H: core::hash::Hasher>(&self, state: &mut H) {
+        self.0.get_u16().hash(state);
+    }
+}
+
 impl std::fmt::Display for ProtocolVersion {
     #[inline]
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
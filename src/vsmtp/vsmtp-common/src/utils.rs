@@ -44,6 +44,45 @@ pub fn ipv6_with_scope_id(input: &str) -> anyhow::Result<std::net::SocketAddr> {
     }
 }
 
+/// The canonical form of a client address: an IPv4 client accepted on a IPv6 socket
+/// (`::ffff:192.0.2.1`) is the IPv4 address.
+#[inline]
+#[must_use]
+pub fn canonical_ip(ip: std::net::IpAddr) -> std::net::IpAddr {
+    match ip {
+        std::net::IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, std::net::IpAddr::V4),
+        std::net::IpAddr::V4(_) => ip,
+    }
+}
+
+/// The canonical form of the socket address of a client, see [`canonical_ip`].
+///
+/// The scope id (`fe80::1%eth0`) is only kept for the link-local addresses, for which
+/// it identifies the interface, and the flow label is dropped.
+#[inline]
+#[must_use]
+pub fn canonical_socket_addr(addr: std::net::SocketAddr) -> std::net::SocketAddr {
+    match addr {
+        std::net::SocketAddr::V6(v6) => {
+            if let Some(v4) = v6.ip().to_ipv4_mapped() {
+                return std::net::SocketAddr::new(std::net::IpAddr::V4(v4), v6.port());
+            }
+            let is_link_local = v6
+                .ip()
+                .segments()
+                .first()
+                .map_or(false, |segment| segment & 0xffc0 == 0xfe80);
+            std::net::SocketAddr::V6(std::net::SocketAddrV6::new(
+                *v6.ip(),
+                v6.port(),
+                0,
+                if is_link_local { v6.scope_id() } else { 0 },
+            ))
+        }
+        std::net::SocketAddr::V4(_) => addr,
+    }
+}
+
 fn parse_ip6_port(input: &str) -> anyhow::Result<(&str, u16)> {
     let (addr, port) = input
         .rsplit_once(':')
@@ -84,4 +123,48 @@ mod test {
         );
         // NOTE: I did not add an scope id test here because it changes between machines.
     }
+
+    #[test]
+    fn canonical() {
+        let addr = |s: &str| s.parse::<std::net::SocketAddr>().unwrap();
+
+        assert_eq!(
+            canonical_ip("::ffff:1.2.3.4".parse().unwrap()),
+            "1.2.3.4".parse::<std::net::IpAddr>().unwrap()
+        );
+        assert_eq!(
+            canonical_ip("2001:db8::1".parse().unwrap()),
+            "2001:db8::1".parse::<std::net::IpAddr>().unwrap()
+        );
+
+        assert_eq!(
+            canonical_socket_addr(addr("[::ffff:1.2.3.4]:25")),
+            addr("1.2.3.4:25")
+        );
+        assert_eq!(
+            canonical_socket_addr(addr("1.2.3.4:25")),
+            addr("1.2.3.4:25")
+        );
+
+        let scoped = |ip: &str| {
+            std::net::SocketAddr::V6(std::net::SocketAddrV6::new(ip.parse().unwrap(), 25, 7, 2))
+        };
+        assert_eq!(
+            canonical_socket_addr(scoped("2001:db8::1")),
+            addr("[2001:db8::1]:25")
+        );
+        assert_eq!(
+            canonical_socket_addr(scoped("2001:db8::1")).to_string(),
+            "[2001:db8::1]:25"
+        );
+        assert_eq!(
+            canonical_socket_addr(scoped("fe80::1")),
+            std::net::SocketAddr::V6(std::net::SocketAddrV6::new(
+                "fe80::1".parse().unwrap(),
+                25,
+                0,
+                2
+            ))
+        );
+    }
 }
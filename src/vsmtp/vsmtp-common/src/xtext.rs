@@ -0,0 +1,184 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2022 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+
+//! The `xtext` encoding of the `ENVID` and `ORCPT` parameters.
+//!
+//! See <https://datatracker.ietf.org/doc/html/rfc3461#section-4>, and
+//! <https://datatracker.ietf.org/doc/html/rfc6533#section-3> for the `utf-8` address type.
+
+/// Errors while decoding a `xtext` value.
+#[derive(Debug, PartialEq, Eq, thiserror::Error)]
+pub enum Error {
+    /// `+` must be followed by two hexadecimal digits
+    #[error("invalid hexchar in xtext")]
+    InvalidHexChar,
+    /// The decoded value is not valid UTF-8
+    #[error("decoded xtext is not valid utf-8")]
+    Utf8,
+}
+
+const fn is_xchar(byte: u8) -> bool {
+    matches!(byte, b'!'..=b'~') && byte != b'+' && byte != b'='
+}
+
+fn hex_digit(byte: u8) -> Result<u8, Error> {
+    char::from(byte)
+        .to_digit(16)
+        .and_then(|digit| u8::try_from(digit).ok())
+        .ok_or(Error::InvalidHexChar)
+}
+
+/// Encode `value` as `xtext`: the characters outside of `!`..=`~`, `+` and `=`
+/// are replaced by `+` followed by their hexadecimal value.
+#[must_use]
+#[inline]
+pub fn encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| {
+            if is_xchar(byte) {
+                char::from(byte).to_string()
+            } else {
+                format!("+{byte:02X}")
+            }
+        })
+        .collect()
+}
+
+/// Decode a `xtext` value.
+///
+/// The other characters are kept as is, as the clients are not always strict
+/// (with `SMTPUTF8` for instance).
+///
+/// # Errors
+///
+/// * a `+` is not followed by two hexadecimal digits
+/// * the decoded value is not valid UTF-8
+#[inline]
+pub fn decode(value: &str) -> Result<String, Error> {
+    let mut out = Vec::with_capacity(value.len());
+    let mut bytes = value.bytes();
+
+    while let Some(byte) = bytes.next() {
+        if byte == b'+' {
+            let high = hex_digit(bytes.next().ok_or(Error::InvalidHexChar)?)?;
+            let low = hex_digit(bytes.next().ok_or(Error::InvalidHexChar)?)?;
+            out.push((high << 4_u8) | low);
+        } else {
+            out.push(byte);
+        }
+    }
+
+    String::from_utf8(out).map_err(|_e| Error::Utf8)
+}
+
+/// Encode `value` as `utf-8-addr-xtext` (RFC 6533): the non-ASCII characters
+/// are kept if `utf8` is set, the other ones are replaced by `\x{HEX}`.
+#[must_use]
+#[inline]
+pub fn encode_utf8_addr(value: &str, utf8: bool) -> String {
+    value
+        .chars()
+        .map(|c| {
+            let literal = if c.is_ascii() {
+                u8::try_from(c).map_or(false, is_xchar) && c != '\\'
+            } else {
+                utf8
+            };
+            if literal {
+                c.to_string()
+            } else {
+                format!("\\x{{{:X}}}", u32::from(c))
+            }
+        })
+        .collect()
+}
+
+/// Decode a `utf-8-addr-xtext` or `utf-8-addr-unitext` value (RFC 6533).
+///
+/// # Errors
+///
+/// * a `\x{...}` sequence is not a valid unicode character
+#[inline]
+pub fn decode_utf8_addr(value: &str) -> Result<String, Error> {
+    let mut out = String::with_capacity(value.len());
+    let mut rest = value;
+
+    while let Some(position) = rest.find("\\x{") {
+        out.push_str(rest.get(..position).ok_or(Error::InvalidHexChar)?);
+        let escaped = rest
+            .get(position.saturating_add(3)..)
+            .ok_or(Error::InvalidHexChar)?;
+        let (hex, after) = escaped.split_once('}').ok_or(Error::InvalidHexChar)?;
+        out.push(
+            u32::from_str_radix(hex, 16)
+                .ok()
+                .and_then(char::from_u32)
+                .ok_or(Error::InvalidHexChar)?,
+        );
+        rest = after;
+    }
+    out.push_str(rest);
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        for (decoded, encoded) in [
+            ("QQ314159", "QQ314159"),
+            ("a+b=c", "a+2Bb+3Dc"),
+            ("with space", "with+20space"),
+            ("", ""),
+            ("é", "+C3+A9"),
+        ] {
+            assert_eq!(encode(decoded), encoded);
+            assert_eq!(decode(encoded).unwrap(), decoded);
+        }
+        assert_eq!(decode("a+2bb").unwrap(), "a+b");
+    }
+
+    #[test]
+    fn invalid() {
+        assert_eq!(decode("a+"), Err(Error::InvalidHexChar));
+        assert_eq!(decode("a+2"), Err(Error::InvalidHexChar));
+        assert_eq!(decode("a+GG"), Err(Error::InvalidHexChar));
+        assert_eq!(decode("+FF"), Err(Error::Utf8));
+    }
+
+    #[test]
+    fn utf8_addr() {
+        assert_eq!(
+            encode_utf8_addr("用户@例子.广告", false),
+            "\\x{7528}\\x{6237}@\\x{4F8B}\\x{5B50}.\\x{5E7F}\\x{544A}"
+        );
+        assert_eq!(
+            encode_utf8_addr("用户+1@例子.广告", true),
+            "用户\\x{2B}1@例子.广告"
+        );
+        assert_eq!(
+            decode_utf8_addr("\\x{7528}\\x{6237}\\x{2B}1@例子.广告").unwrap(),
+            "用户+1@例子.广告"
+        );
+        assert_eq!(decode_utf8_addr("a\\x{110000}"), Err(Error::InvalidHexChar));
+        assert_eq!(decode_utf8_addr("a\\x{41"), Err(Error::InvalidHexChar));
+    }
+}
@@ -17,9 +17,11 @@
 use super::{wants::WantsValidate, with::Builder};
 use crate::{
     config::field::{
-        FieldApp, FieldAppLogs, FieldAppVSL, FieldServer, FieldServerInterfaces, FieldServerLogs,
-        FieldServerQueues, FieldServerSMTP, FieldServerSMTPError, FieldServerSMTPTimeoutClient,
-        FieldServerSystem, FieldServerSystemThreadPool,
+        DuplicateRcptPolicy, FieldApp, FieldAppLogs, FieldAppVSL, FieldAppVSLGreylist,
+        FieldAppVSLLookupCache, FieldServer, FieldServerInterfaces, FieldServerLogs,
+        FieldServerMime, FieldServerQueues, FieldServerSMTP, FieldServerSMTPError,
+        FieldServerSMTPParameters, FieldServerSMTPTimeoutClient, FieldServerSystem,
+        FieldServerSystemThreadPool,
     },
     Config,
 };
@@ -74,6 +76,7 @@ impl Builder<WantsValidate> {
                 logs: FieldServerLogs {
                     filename: srv_logs.filename,
                     level: srv_logs.level,
+                    policy_events: None,
                     #[cfg(any(feature = "journald", feature = "syslog"))]
                     sys_level: FieldServerLogs::default_sys_level(),
                     #[cfg(feature = "syslog")]
@@ -83,14 +86,30 @@ impl Builder<WantsValidate> {
                     dirpath: srv_delivery.dirpath,
                     working: srv_delivery.working,
                     delivery: srv_delivery.delivery,
+                    claim_timeout: FieldServerQueues::default_claim_timeout(),
+                    monitor: None,
                 },
                 tls: srv_tls.tls,
                 smtp: FieldServerSMTP {
                     rcpt_count_max: smtp_opt.rcpt_count_max,
+                    rcpt_count_max_reply: FieldServerSMTP::default_rcpt_count_max_reply(),
+                    line_length_max: FieldServerSMTP::default_line_length_max(),
+                    first_line_max: FieldServerSMTP::default_first_line_max(),
+                    duplicate_rcpt: DuplicateRcptPolicy::default(),
+                    parameters: FieldServerSMTPParameters::default(),
+                    data_reply: FieldServerSMTP::default_data_reply(),
+                    raw_commands_in_quarantine: false,
+                    storage_error_reply: FieldServerSMTP::default_storage_error_reply(),
+                    invalid_utf8_quarantine: None,
+                    invalid_message_reply: FieldServerSMTP::default_invalid_message_reply(),
+                    maintenance_reply: FieldServerSMTP::default_maintenance_reply(),
                     error: FieldServerSMTPError {
                         soft_count: smtp_error.error.soft_count,
                         hard_count: smtp_error.error.hard_count,
                         delay: smtp_error.error.delay,
+                        consecutive_count: smtp_error.error.consecutive_count,
+                        churn_count: smtp_error.error.churn_count,
+                        hard_count_reply: smtp_error.error.hard_count_reply,
                     },
                     timeout_client: FieldServerSMTPTimeoutClient {
                         connect: smtp_error.timeout_client.connect,
@@ -99,16 +118,40 @@ impl Builder<WantsValidate> {
                         rcpt_to: smtp_error.timeout_client.rcpt_to,
                         data: smtp_error.timeout_client.data,
                     },
+                    message_size_limit_reply: FieldServerSMTP::default_message_size_limit_reply(),
                 },
                 esmtp: esmtp.esmtp,
                 dns: dns.config,
                 r#virtual: virtual_entries.r#virtual,
+                health: None,
+                admin: None,
+                tls_statistics: None,
+                access_lists: None,
+                aliases: None,
+                missing_headers: None,
+                dkim_preservation: None,
+                normalization: None,
+                recipient_delimiter: FieldServer::default_recipient_delimiter(),
+                maildir_tag_folders: None,
+                strip_received: None,
+                trusted_upstreams: None,
+                recipients: None,
+                disclosure: None,
+                srs: None,
+                connection_limits: None,
+                lookup_tables: None,
+                mime: FieldServerMime::default(),
             },
             app: FieldApp {
                 dirpath: app.dirpath,
                 vsl: FieldAppVSL {
                     domain_dir: app_vsl.domain_dir,
                     filter_path: app_vsl.filter_path,
+                    datasets: app_vsl.datasets,
+                    decision_cache: None,
+                    greylist: FieldAppVSLGreylist::default(),
+                    lookup_cache: FieldAppVSLLookupCache::default(),
+                    tempfail_reply: FieldAppVSL::default_tempfail_reply(),
                 },
                 logs: FieldAppLogs {
                     filename: app_logs.filename,
@@ -120,7 +163,10 @@ impl Builder<WantsValidate> {
 
 #[cfg(test)]
 mod tests {
-    use crate::Config;
+    use crate::{
+        field::{FieldDataset, FieldServerESMTP},
+        Config,
+    };
 
     #[test]
     fn default_build() {
@@ -143,4 +189,83 @@ mod tests {
             .without_virtual_entries()
             .validate();
     }
+
+    #[test]
+    fn incoherent_extensions() {
+        let builder = || {
+            Config::builder()
+                .with_current_version()
+                .without_path()
+                .with_server_name("testserver.com".parse::<vsmtp_common::Domain>().unwrap())
+                .with_default_system()
+                .with_ipv4_localhost()
+                .with_default_logs_settings()
+                .with_default_delivery()
+                .without_tls_support()
+                .with_default_smtp_options()
+                .with_default_smtp_error_handler()
+        };
+
+        let error = builder()
+            .try_with_extensions(FieldServerESMTP {
+                eightbitmime: false,
+                smtputf8: true,
+                ..Default::default()
+            })
+            .err()
+            .unwrap();
+        assert_eq!(
+            error.to_string(),
+            "the extension `smtputf8` requires `eightbitmime` to be enabled"
+        );
+
+        let config = builder()
+            .try_with_extensions(FieldServerESMTP {
+                eightbitmime: false,
+                smtputf8: false,
+                chunking: true,
+                ..Default::default()
+            })
+            .unwrap()
+            .with_default_app()
+            .with_default_vsl_settings()
+            .with_default_app_logs()
+            .with_system_dns()
+            .without_virtual_entries()
+            .validate();
+        assert!(config.server.esmtp.chunking);
+    }
+
+    #[test]
+    fn with_datasets() {
+        let config = Config::builder()
+            .with_current_version()
+            .without_path()
+            .with_server_name("testserver.com".parse::<vsmtp_common::Domain>().unwrap())
+            .with_default_system()
+            .with_ipv4_localhost()
+            .with_default_logs_settings()
+            .with_default_delivery()
+            .without_tls_support()
+            .with_default_smtp_options()
+            .with_default_smtp_error_handler()
+            .with_default_extensions()
+            .with_default_app()
+            .with_default_vsl_settings()
+            .with_datasets(
+                [(
+                    "plans".to_string(),
+                    FieldDataset::File {
+                        path: "plans.json".into(),
+                    },
+                )]
+                .into_iter()
+                .collect(),
+            )
+            .with_default_app_logs()
+            .with_system_dns()
+            .without_virtual_entries()
+            .validate();
+        assert!(config.app.vsl.datasets.contains_key("plans"));
+    }
 }
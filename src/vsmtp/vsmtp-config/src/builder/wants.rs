@@ -17,8 +17,8 @@
 #![allow(clippy::module_name_repetitions)]
 
 use crate::field::{
-    FieldQueueDelivery, FieldQueueWorking, FieldServerDNS, FieldServerESMTP, FieldServerSMTPError,
-    FieldServerSMTPTimeoutClient, FieldServerTls, FieldServerVirtual,
+    FieldDataset, FieldQueueDelivery, FieldQueueWorking, FieldServerDNS, FieldServerESMTP,
+    FieldServerSMTPError, FieldServerSMTPTimeoutClient, FieldServerTls, FieldServerVirtual,
 };
 use vsmtp_common::Domain;
 
@@ -116,6 +116,7 @@ pub struct WantsAppLogs {
     pub(crate) parent: WantsAppVSL,
     pub(super) domain_dir: Option<std::path::PathBuf>,
     pub(super) filter_path: Option<std::path::PathBuf>,
+    pub(super) datasets: std::collections::BTreeMap<String, FieldDataset>,
 }
 
 ///
@@ -23,11 +23,11 @@ use super::{
     WantsPath, WantsServerESMTPConfig,
 };
 use crate::field::{
-    FieldApp, FieldAppLogs, FieldQueueDelivery, FieldQueueWorking, FieldServer, FieldServerDNS,
-    FieldServerESMTP, FieldServerInterfaces, FieldServerLogs, FieldServerQueues, FieldServerSMTP,
-    FieldServerSMTPAuth, FieldServerSMTPError, FieldServerSMTPTimeoutClient, FieldServerSystem,
-    FieldServerSystemThreadPool, FieldServerTls, FieldServerVirtual, FieldServerVirtualTls,
-    ResolverOptsWrapper,
+    FieldApp, FieldAppLogs, FieldDataset, FieldQueueDelivery, FieldQueueWorking, FieldServer,
+    FieldServerDNS, FieldServerESMTP, FieldServerInterfaces, FieldServerLogs, FieldServerQueues,
+    FieldServerSMTP, FieldServerSMTPAuth, FieldServerSMTPError, FieldServerSMTPTimeoutClient,
+    FieldServerSystem, FieldServerSystemThreadPool, FieldServerTls, FieldServerVirtual,
+    FieldServerVirtualTls, ResolverOptsWrapper,
 };
 use anyhow::Context;
 use vsmtp_common::{auth::Mechanism, Domain, Stage};
@@ -350,6 +350,7 @@ impl Builder<WantsServerTLSConfig> {
                     )],
                     cipher_suite: FieldServerTls::default_cipher_suite(),
                     root: None,
+                    client_auth: None,
                 }),
             },
         })
@@ -419,6 +420,9 @@ impl Builder<WantsServerSMTPConfig2> {
                     soft_count,
                     hard_count,
                     delay,
+                    consecutive_count: FieldServerSMTPError::default_consecutive_count(),
+                    churn_count: FieldServerSMTPError::default_churn_count(),
+                    hard_count_reply: FieldServerSMTPError::default_hard_count_reply(),
                 },
                 timeout_client: FieldServerSMTPTimeoutClient {
                     connect: *timeout_client
@@ -453,6 +457,8 @@ impl Builder<WantsServerESMTPConfig> {
     }
 
     /// Build a Extended SMTP configuration with the provided parameters. See `[FieldServerESMTP]` for more details.
+    ///
+    /// The extensions are not checked, see [`Builder::try_with_extensions`].
     #[must_use]
     pub fn with_extensions(self, extensions: FieldServerESMTP) -> Builder<WantsApp> {
         Builder::<WantsApp> {
@@ -463,6 +469,21 @@ impl Builder<WantsServerESMTPConfig> {
         }
     }
 
+    /// Build a Extended SMTP configuration with the provided parameters, rejecting
+    /// the combinations of extensions which cannot be advertised together.
+    ///
+    /// # Errors
+    ///
+    /// * `smtputf8` is enabled without `eightbitmime` (<https://datatracker.ietf.org/doc/html/rfc6531#section-3.1>)
+    pub fn try_with_extensions(
+        self,
+        extensions: FieldServerESMTP,
+    ) -> anyhow::Result<Builder<WantsApp>> {
+        extensions.check_coherence()?;
+
+        Ok(self.with_extensions(extensions))
+    }
+
     /// Use default extensions and configure authentication with safe defaults.
     #[must_use]
     pub fn with_safe_auth(self, attempt_count_max: i64) -> Builder<WantsApp> {
@@ -489,6 +510,8 @@ impl Builder<WantsServerESMTPConfig> {
                         enable_dangerous_mechanism_in_clair,
                         mechanisms,
                         attempt_count_max,
+                        senders: None,
+                        quotas: None,
                     }),
                     ..Default::default()
                 },
@@ -528,6 +551,7 @@ impl Builder<WantsAppVSL> {
                 parent: self.state,
                 domain_dir: None,
                 filter_path: None,
+                datasets: std::collections::BTreeMap::new(),
             },
         }
     }
@@ -546,6 +570,7 @@ impl Builder<WantsAppVSL> {
                         .expect("rule main script is fetched in the domain directory's parent")
                         .join("filter.vsl"),
                 ),
+                datasets: std::collections::BTreeMap::new(),
             },
         }
     }
@@ -561,6 +586,7 @@ impl Builder<WantsAppVSL> {
                 parent: self.state,
                 domain_dir: None,
                 filter_path: Some(filter_path.into()),
+                datasets: std::collections::BTreeMap::new(),
             },
         }
     }
@@ -577,12 +603,23 @@ impl Builder<WantsAppVSL> {
                 parent: self.state,
                 domain_dir: Some(domain_dir.into()),
                 filter_path: Some(filter_path.into()),
+                datasets: std::collections::BTreeMap::new(),
             },
         }
     }
 }
 
 impl Builder<WantsAppLogs> {
+    /// Read-only datasets exposed to the rules as `data::<name>`.
+    #[must_use]
+    pub fn with_datasets(
+        mut self,
+        datasets: std::collections::BTreeMap<String, FieldDataset>,
+    ) -> Builder<WantsAppLogs> {
+        self.state.datasets = datasets;
+        self
+    }
+
     ///
     #[must_use]
     pub fn with_default_app_logs(self) -> Builder<WantsServerDNS> {
@@ -701,21 +738,33 @@ impl Builder<WantsServerVirtual> {
                         tls: None,
                         dns: None,
                         dkim: None,
+                        source_ips: vec![],
+                        hello_name: None,
+                        catch_all: None,
                     },
                     (None, Some(dns_config)) => FieldServerVirtual {
                         tls: None,
                         dns: Some(dns_config),
                         dkim: None,
+                        source_ips: vec![],
+                        hello_name: None,
+                        catch_all: None,
                     },
                     (Some((certificate, private_key)), None) => FieldServerVirtual {
                         tls: Some(FieldServerVirtualTls::from_path(certificate, private_key)?),
                         dns: None,
                         dkim: None,
+                        source_ips: vec![],
+                        hello_name: None,
+                        catch_all: None,
                     },
                     (Some((certificate, private_key)), Some(dns_config)) => FieldServerVirtual {
                         tls: Some(FieldServerVirtualTls::from_path(certificate, private_key)?),
                         dns: Some(dns_config),
                         dkim: None,
+                        source_ips: vec![],
+                        hello_name: None,
+                        catch_all: None,
                     },
                 },
             );
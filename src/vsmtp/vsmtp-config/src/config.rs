@@ -46,7 +46,7 @@ pub struct Config {
 #[allow(clippy::module_name_repetitions)]
 pub mod field {
     use vsmtp_auth::dkim;
-    use vsmtp_common::{auth::Mechanism, Domain};
+    use vsmtp_common::{auth::Mechanism, Domain, SubmissionQuota};
 
     /// This structure contains all the field to configure the server at the startup.
     #[derive(Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
@@ -91,6 +91,564 @@ pub mod field {
         /// see [`FieldServerVirtual`]
         #[serde(default)]
         pub r#virtual: std::collections::BTreeMap<Domain, FieldServerVirtual>,
+        /// see [`FieldServerHealth`]
+        #[serde(default)]
+        pub health: Option<FieldServerHealth>,
+        /// see [`FieldServerAdmin`]
+        #[serde(default)]
+        pub admin: Option<FieldServerAdmin>,
+        /// see [`FieldServerTlsStatistics`]
+        #[serde(default)]
+        pub tls_statistics: Option<FieldServerTlsStatistics>,
+        /// see [`FieldServerAccessLists`]
+        #[serde(default)]
+        pub access_lists: Option<FieldServerAccessLists>,
+        /// see [`FieldServerAliases`]
+        #[serde(default)]
+        pub aliases: Option<FieldServerAliases>,
+        /// see [`FieldServerMissingHeaders`]
+        #[serde(default)]
+        pub missing_headers: Option<FieldServerMissingHeaders>,
+        /// see [`FieldServerDkimPreservation`]
+        #[serde(default)]
+        pub dkim_preservation: Option<FieldServerDkimPreservation>,
+        /// see [`FieldServerNormalization`]
+        #[serde(default)]
+        pub normalization: Option<FieldServerNormalization>,
+        /// Characters separating the local part of an address from its extension
+        /// (`john+lists@example.com`), the first one found in the local part is used.
+        ///
+        /// The extension of the recipients of the domains of the server is recorded on
+        /// `RCPT TO`: the mailboxes and the aliases are looked up with the full address,
+        /// then without the extension, and the `maildir` transport files the message in the
+        /// folder of the extension (see [`FieldServerMaildirTagFolders`]).
+        /// Empty to disable the extensions, `+` by default.
+        #[serde(default = "FieldServer::default_recipient_delimiter")]
+        pub recipient_delimiter: String,
+        /// see [`FieldServerMaildirTagFolders`]
+        #[serde(default)]
+        pub maildir_tag_folders: Option<FieldServerMaildirTagFolders>,
+        /// see [`FieldServerStripReceived`]
+        #[serde(default)]
+        pub strip_received: Option<FieldServerStripReceived>,
+        /// see [`FieldServerTrustedUpstreams`]
+        #[serde(default)]
+        pub trusted_upstreams: Option<FieldServerTrustedUpstreams>,
+        /// see [`FieldServerRecipients`]
+        #[serde(default)]
+        pub recipients: Option<FieldServerRecipients>,
+        /// see [`FieldServerDisclosure`]
+        #[serde(default)]
+        pub disclosure: Option<FieldServerDisclosure>,
+        /// see [`FieldServerSrs`]
+        #[serde(default)]
+        pub srs: Option<FieldServerSrs>,
+        /// see [`FieldServerConnectionLimits`]
+        #[serde(default)]
+        pub connection_limits: Option<FieldServerConnectionLimits>,
+        /// see [`FieldServerLookupTables`]
+        #[serde(default)]
+        pub lookup_tables: Option<FieldServerLookupTables>,
+        /// see [`FieldServerMime`]
+        #[serde(default)]
+        pub mime: FieldServerMime,
+    }
+
+    /// Limits of the parsing of the MIME structure of the messages by the rules.
+    ///
+    /// Exceeding one of them stops the parsing at that point, the rest of the message
+    /// is kept unparsed and `msg::is_parse_truncated()` returns `true`.
+    #[derive(Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+    #[serde(deny_unknown_fields)]
+    pub struct FieldServerMime {
+        /// Maximum nesting of multipart and embedded messages.
+        #[serde(default = "FieldServerMime::default_max_depth")]
+        pub max_depth: usize,
+        /// Maximum number of MIME parts in the whole message.
+        #[serde(default = "FieldServerMime::default_max_parts")]
+        pub max_parts: usize,
+        /// Maximum length of a boundary (70 characters in RFC 2046).
+        #[serde(default = "FieldServerMime::default_max_boundary_length")]
+        pub max_boundary_length: usize,
+    }
+
+    /// Liveness and readiness probes, served over HTTP for orchestrators (Kubernetes, ...).
+    ///
+    /// * `GET /healthz` replies `200` as long as the process is running.
+    /// * `GET /readyz` replies `200` when the listeners are bound, the rules are compiled,
+    ///   the spool is accessible with enough free space and the working and delivery keep up
+    ///   (their channels are not full), `503` otherwise (and during the graceful shutdown).
+    /// * `GET /metrics` replies the executions of the rules, and the statistics of
+    ///   [`FieldServerTlsStatistics`] if enabled.
+    ///
+    /// The connections of the `probes` to the SMTP listeners are answered the greeting, and `221`
+    /// to `QUIT`, without running the rules, counting them in `client_count_max` or logging them.
+    /// They are only counted in `vsmtp_smtp_probes_total` on `GET /metrics`.
+    #[derive(Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+    #[serde(deny_unknown_fields)]
+    pub struct FieldServerHealth {
+        /// Address of the HTTP listener.
+        pub addr: std::net::SocketAddr,
+        /// Addresses of the load balancers probing the SMTP listeners.
+        #[serde(default)]
+        pub probes: Vec<std::net::IpAddr>,
+        /// Minimum free space of the spool, in bytes, for the server to be ready.
+        #[serde(default = "FieldServerHealth::default_spool_free_space_min")]
+        pub spool_free_space_min: u64,
+    }
+
+    /// Control channel to operate the server at runtime, served on a unix socket.
+    ///
+    /// The commands are not authenticated: the socket is created with the mode `0660`
+    /// and the group `server.system.group`, only the user starting the server, the members
+    /// of the group and root can connect.
+    ///
+    /// The commands (one per line) are `list <queue>`, `flush`, `hold <uuid>`, `release <uuid>`,
+    /// `requeue <uuid>`, `reload`, `maintenance on|off`, `tls-stats [domain|network]`, `rules stats`
+    /// and `flush-capabilities <domain>`.
+    #[derive(Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+    #[serde(deny_unknown_fields)]
+    pub struct FieldServerAdmin {
+        /// Path of the unix socket, replaced if it already exists.
+        pub socket: std::path::PathBuf,
+    }
+
+    /// Statistics of the use of TLS by the inbound clients, per sender domain
+    /// (`MAIL FROM`) and per client network (`/24` in IPv4, `/64` in IPv6).
+    ///
+    /// The counters cover the last one to two `window`, are persisted in
+    /// `<app.dirpath>/tls-statistics.json` and exposed on `GET /metrics` of the
+    /// health listener and with the `tls-stats [domain|network]` administrative command.
+    ///
+    /// A warning is logged when a sender domain whose last `alert_threshold` messages
+    /// were received with TLS sends one in clear (possible STARTTLS stripping).
+    ///
+    /// The sender domains are chosen by the clients, so at most `entries_max` domains (and
+    /// as many networks) are followed: the one with the fewest messages is forgotten to
+    /// follow a new one.
+    #[derive(Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+    #[serde(deny_unknown_fields)]
+    pub struct FieldServerTlsStatistics {
+        /// Number of consecutive messages received with TLS before a message in clear raises the alert.
+        #[serde(default = "FieldServerTlsStatistics::default_alert_threshold")]
+        pub alert_threshold: u32,
+        /// Period after which the counters are rotated.
+        #[serde(with = "humantime_serde")]
+        #[serde(default = "FieldServerTlsStatistics::default_window")]
+        pub window: std::time::Duration,
+        /// Period of the persistence of the counters on disk.
+        #[serde(with = "humantime_serde")]
+        #[serde(default = "FieldServerTlsStatistics::default_persist_period")]
+        pub persist_period: std::time::Duration,
+        /// Maximum number of sender domains, and of client networks, followed.
+        #[serde(default = "FieldServerTlsStatistics::default_entries_max")]
+        pub entries_max: usize,
+    }
+
+    /// Lists of clients and sender domains maintained in plain text files, consulted
+    /// before the rules: the client addresses when the connection is accepted, the sender
+    /// domains on `MAIL FROM`.
+    ///
+    /// The files contain one entry per line, the text following a `#` being ignored.
+    /// A client entry is an address (`192.0.2.1`) or a network (`2001:db8::/32`), a sender
+    /// entry is a domain, matching its sub-domains too. The malformed lines are skipped
+    /// with a warning.
+    ///
+    /// The files are read again when they are modified, without reloading the rules.
+    /// The lists are also exposed to the rules with `lists::is_allowed_ip(ip)`,
+    /// `lists::is_blocked_ip(ip)` and `lists::is_blocked_sender(domain)`.
+    #[derive(Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+    #[serde(deny_unknown_fields)]
+    pub struct FieldServerAccessLists {
+        /// Clients handled with the action of the list when they connect.
+        #[serde(default)]
+        pub blocked_ips: Option<FieldAccessList>,
+        /// Sender domains handled with the action of the list on `MAIL FROM`.
+        #[serde(default)]
+        pub blocked_senders: Option<FieldAccessList>,
+        /// File of the clients never checked against the blocklists, for which the
+        /// rules can skip their own checks (greylisting, dnsbl, ...).
+        #[serde(default)]
+        pub allowed_ips: Option<std::path::PathBuf>,
+        /// Period of the check of the modification of the files.
+        #[serde(with = "humantime_serde")]
+        #[serde(default = "FieldServerAccessLists::default_reload_period")]
+        pub reload_period: std::time::Duration,
+    }
+
+    /// Expansion of the recipients by an aliases file, before the delivery.
+    ///
+    /// Each line maps an alias to a comma-separated list of targets, as in `/etc/aliases`:
+    /// `info: john, jenny@example.com, :include:/etc/vsmtp/sales.list`. An alias without
+    /// domain applies to the domains of the server (`server.name` and the virtual domains),
+    /// a target without domain belongs to the domain of the alias. The file is read again
+    /// when it is modified.
+    #[derive(Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+    #[serde(deny_unknown_fields)]
+    pub struct FieldServerAliases {
+        /// Path of the aliases file.
+        pub path: std::path::PathBuf,
+        /// Maximum depth of the expansion of an alias to other aliases.
+        #[serde(default = "FieldServerAliases::default_max_depth")]
+        pub max_depth: usize,
+        /// Period of the check of the modification of the file.
+        #[serde(with = "humantime_serde")]
+        #[serde(default = "FieldServerAliases::default_reload_period")]
+        pub reload_period: std::time::Duration,
+    }
+
+    /// Handling of the messages received without the `Date` or `Message-ID` header required
+    /// by RFC 5322, at the working stage before the `postq` rules, so that the headers added
+    /// are covered by a DKIM signature produced by the rules.
+    ///
+    /// The policy depends on the source of the message: `submission` for an authenticated
+    /// transaction, `internal` between the domains of the server, and `relay` otherwise.
+    #[derive(Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+    #[serde(deny_unknown_fields)]
+    pub struct FieldServerMissingHeaders {
+        /// Policy of the messages submitted by an authenticated user.
+        #[serde(default = "FieldServerMissingHeaders::default_submission")]
+        pub submission: MissingHeadersPolicy,
+        /// Policy of the messages relayed from, or to, other servers.
+        #[serde(default = "FieldServerMissingHeaders::default_relay")]
+        pub relay: MissingHeadersPolicy,
+        /// Policy of the messages sent between the domains of the server.
+        #[serde(default = "FieldServerMissingHeaders::default_internal")]
+        pub internal: MissingHeadersPolicy,
+        /// Handle a `Date` header which cannot be parsed as a missing one.
+        /// Otherwise, it is only logged.
+        #[serde(default)]
+        pub fix_date: bool,
+    }
+
+    /// Action taken on a message without a `Date` or `Message-ID` header.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+    #[serde(rename_all = "lowercase")]
+    pub enum MissingHeadersPolicy {
+        /// The `Date` (reception of the message) and the `Message-ID` (`<uuid@server name>`)
+        /// are added.
+        Add,
+        /// The message is refused at the end of `DATA` with a `550` reply.
+        Reject,
+        /// The message is left untouched.
+        Ignore,
+    }
+
+    /// Preservation of the DKIM signatures of the messages received already signed,
+    /// for instance by the application submitting them.
+    ///
+    /// The headers covered by the signatures are recorded at the working stage, before
+    /// the `postq` rules. The `Date` and `Message-ID` headers are not added if covered
+    /// (see [`FieldServerMissingHeaders`]), and the `Received` header is added above
+    /// the signed ones. A modification of a covered header by the rules, or by the
+    /// `From` header policy, is handled with the `fallback`.
+    #[derive(Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+    #[serde(deny_unknown_fields)]
+    pub struct FieldServerDkimPreservation {
+        /// Action taken when a modification of the message would break a signature.
+        #[serde(default)]
+        pub fallback: DkimPreservationFallback,
+    }
+
+    /// Normalization of the line endings of the messages received, for the tools reading
+    /// the messages stored: the bare `\n` and `\r` are replaced by `\r\n`, and the spaces
+    /// and tabs ending the lines of the header section are removed.
+    ///
+    /// The normalization modifies the bytes of the message, and breaks the DKIM signatures
+    /// with the `simple` canonicalization of the lines it alters, so its place relative to
+    /// `dkim::verify()` is set by `stage`: with `after-rules` (the default) the rules verify
+    /// the message as received, and the message is normalized once the `postq` rules have
+    /// run. With `before-rules` the message is normalized on reception, before the `preq`
+    /// rules, which verify the normalized message.
+    #[derive(Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+    #[serde(deny_unknown_fields)]
+    pub struct FieldServerNormalization {
+        /// When the message is normalized.
+        #[serde(default)]
+        pub stage: NormalizationStage,
+        /// Remove the spaces and tabs ending the lines of the header section.
+        #[serde(default = "FieldServerNormalization::default_trailing_whitespace")]
+        pub trailing_whitespace: bool,
+    }
+
+    /// When the messages are normalized, see [`FieldServerNormalization`].
+    #[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+    #[serde(rename_all = "kebab-case")]
+    pub enum NormalizationStage {
+        /// On reception, before the rules of the `preq` stage.
+        BeforeRules,
+        /// Once the rules of the `postq` stage have run, before the message is written in the
+        /// queue of the delivery.
+        #[default]
+        AfterRules,
+    }
+
+    /// Removal of the `Received` headers of the internal hops from the messages relayed
+    /// to other servers, to avoid disclosing the topology of the internal network.
+    ///
+    /// A `Received` header is internal if its `from` clause names a host of `hostnames`
+    /// (or one of their sub-domains) or an address of `networks`. The outermost `Received`
+    /// header, added by the server, is kept. Only the copy of the message sent is modified,
+    /// and the headers are kept if a DKIM signature of the message covers them.
+    #[derive(Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+    #[serde(deny_unknown_fields)]
+    pub struct FieldServerStripReceived {
+        /// Networks of the internal hosts, in the CIDR notation.
+        #[serde(default)]
+        pub networks: Vec<vsmtp_common::Network>,
+        /// Names of the internal hosts.
+        #[serde(default)]
+        pub hostnames: Vec<Domain>,
+    }
+
+    /// Relays in front of the server, such as a border relay, whose `Received` headers are
+    /// believed to find the host which sent the message, exposed to the rules as
+    /// `ctx::originating_ip()` and `ctx::originating_helo()`.
+    ///
+    /// The `Received` headers are read from the topmost while the host they describe is in
+    /// `networks`, the headers below the first untrusted hop are ignored.
+    #[derive(Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+    #[serde(deny_unknown_fields)]
+    pub struct FieldServerTrustedUpstreams {
+        /// Networks of the trusted relays, in the CIDR notation.
+        pub networks: Vec<vsmtp_common::Network>,
+        /// Check the originating host against the blocked clients of the access lists
+        /// (see [`FieldServerAccessLists`]) on the reception of the message.
+        #[serde(default)]
+        pub access_lists: bool,
+    }
+
+    /// Information about the server disclosed to the clients and in the messages, reduced
+    /// to hinder its fingerprinting.
+    ///
+    /// The greeting, the first line of the `EHLO` reply and the `by` clause of the `Received`
+    /// header name the server with `name`, instead of `server.name` or the name requested
+    /// with SNI. The real names are still used to select the certificates and in the context
+    /// of the rules. The `X-VSMTP` trace header, naming the software with its version, is not
+    /// added unless `software` is `true`.
+    #[derive(Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+    #[serde(deny_unknown_fields)]
+    pub struct FieldServerDisclosure {
+        /// Name of the server disclosed, `server.name` if not set.
+        #[serde(default)]
+        pub name: Option<Domain>,
+        /// Disclose the name and the version of the software.
+        #[serde(default)]
+        pub software: bool,
+    }
+
+    /// Sender Rewriting Scheme (SRS) of the messages forwarded off-domain, so that they pass
+    /// the SPF check of their destination.
+    ///
+    /// The sender of a message from another domain is rewritten for the targets of the aliases
+    /// (`server.aliases`) outside of the domains of the server, `user@example.com` becoming
+    /// `SRS0=HHHH=TT=example.com=user@<domain>`, or `SRS1=...` if it was already rewritten by
+    /// another forwarder. The bounces sent to these addresses are checked and routed back to
+    /// the original sender on `RCPT TO`, the others being refused.
+    #[derive(Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+    #[serde(deny_unknown_fields)]
+    pub struct FieldServerSrs {
+        /// Domain of the senders rewritten, `server.name` if not set. It must be a domain of
+        /// the server, to receive the bounces.
+        #[serde(default)]
+        pub domain: Option<Domain>,
+        /// Secrets of the hashes of the senders rewritten. The first one is used to rewrite,
+        /// all of them are accepted on the bounces, to rotate the secret.
+        pub secrets: Vec<String>,
+        /// Maximum age of a sender rewritten receiving a bounce, rounded to the day.
+        #[serde(with = "humantime_serde")]
+        #[serde(default = "FieldServerSrs::default_max_age")]
+        pub max_age: std::time::Duration,
+    }
+
+    /// Concurrent connections allowed to a client, counted on its IP address until it
+    /// authenticates, and on its identity afterward (the `authid`, or the subject of its
+    /// certificate), so the authenticated users are not throttled by the anonymous traffic
+    /// of their network.
+    ///
+    /// A client over the cap of its address is refused with `421` on connect, an `AUTH`
+    /// over the cap of the identity is refused with `421` and the connection closed, and a
+    /// certificate over the cap of its subject is ignored (the client stays anonymous).
+    /// The `ANONYMOUS` mechanism keeps the connection counted on its address. All the
+    /// connections are counted in `client_count_max` too.
+    #[derive(Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+    #[serde(deny_unknown_fields)]
+    pub struct FieldServerConnectionLimits {
+        /// Maximum number of connections not authenticated from the same IP address,
+        /// unlimited if not set.
+        #[serde(default)]
+        pub anonymous_per_ip: Option<usize>,
+        /// Maximum number of connections authenticated with the same identity,
+        /// unlimited if not set.
+        #[serde(default)]
+        pub authenticated_per_identity: Option<usize>,
+    }
+
+    /// Check of the existence of the mailboxes of the inbound recipients, refusing the
+    /// unknown ones on `RCPT TO` instead of bouncing them after the transaction.
+    ///
+    /// The file lists one mailbox per line, the text following a `#` being ignored: an
+    /// address (`john@example.com`), or a local part (`john`) for all the domains of the
+    /// server (`server.name` and the virtual domains). The aliases of `server.aliases` are
+    /// known too, and an address is looked up without its extension too (see
+    /// `server.recipient_delimiter`). The file is read again when it is modified.
+    ///
+    /// A client sending more than `probe_count_max` unknown recipients in `probe_window`
+    /// is considered as a dictionary attack and is disconnected with `probe_reply`.
+    /// The rules can check a recipient with `recipients::is_known(rcpt)`.
+    #[derive(Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+    #[serde(deny_unknown_fields)]
+    pub struct FieldServerRecipients {
+        /// Path of the file of the mailboxes.
+        pub path: std::path::PathBuf,
+        /// Reply to an unknown recipient.
+        #[serde(default = "FieldServerRecipients::default_reply")]
+        pub reply: vsmtp_common::Reply,
+        /// Number of unknown recipients allowed to a client in `probe_window`.
+        #[serde(default = "FieldServerRecipients::default_probe_count_max")]
+        pub probe_count_max: usize,
+        /// Period over which the unknown recipients of a client are counted.
+        #[serde(with = "humantime_serde")]
+        #[serde(default = "FieldServerRecipients::default_probe_window")]
+        pub probe_window: std::time::Duration,
+        /// Reply closing the connection of a client exceeding `probe_count_max`.
+        #[serde(default = "FieldServerRecipients::default_probe_reply")]
+        pub probe_reply: vsmtp_common::Reply,
+        /// Period of the check of the modification of the file.
+        #[serde(with = "humantime_serde")]
+        #[serde(default = "FieldServerRecipients::default_reload_period")]
+        pub reload_period: std::time::Duration,
+    }
+
+    /// Postfix-style lookup tables, to reuse the maps of an existing installation
+    /// (transport maps, access tables, virtual maps, ...) unchanged.
+    ///
+    /// Each table is declared by name as `<type>:<path>`, where the file is the source
+    /// of the map (`hash:/etc/postfix/transport` reads `/etc/postfix/transport`, not
+    /// the `.db`). The files are read again when they are modified, and the rules look
+    /// up a key with `table::lookup(name, key)`.
+    #[derive(Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+    #[serde(deny_unknown_fields)]
+    pub struct FieldServerLookupTables {
+        /// The tables, by name.
+        pub tables: std::collections::BTreeMap<String, FieldLookupTable>,
+        /// Period of the check of the modification of the files.
+        #[serde(with = "humantime_serde")]
+        #[serde(default = "FieldServerLookupTables::default_reload_period")]
+        pub reload_period: std::time::Duration,
+    }
+
+    /// A lookup table, written `<type>:<path>` in the configuration.
+    #[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+    #[serde(try_from = "String", into = "String")]
+    pub struct FieldLookupTable {
+        /// Format of the file.
+        pub r#type: LookupTableType,
+        /// Path of the file.
+        pub path: std::path::PathBuf,
+    }
+
+    impl TryFrom<String> for FieldLookupTable {
+        type Error = String;
+
+        fn try_from(value: String) -> Result<Self, Self::Error> {
+            let (r#type, path) = value
+                .split_once(':')
+                .filter(|(_, path)| !path.is_empty())
+                .ok_or_else(|| format!("the lookup table `{value}` is not `<type>:<path>`"))?;
+
+            Ok(Self {
+                r#type: r#type
+                    .parse()
+                    .map_err(|_| format!("unsupported lookup table type `{type}`"))?,
+                path: path.into(),
+            })
+        }
+    }
+
+    impl From<FieldLookupTable> for String {
+        fn from(value: FieldLookupTable) -> Self {
+            format!("{}:{}", value.r#type, value.path.display())
+        }
+    }
+
+    /// Format of a lookup table, as the Postfix ones.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, strum::EnumString, strum::Display)]
+    pub enum LookupTableType {
+        /// `key value` lines, the keys being folded to lowercase. Also accepted as
+        /// `btree`, `lmdb` and `texthash`, whose source files have the same format.
+        #[strum(
+            to_string = "hash",
+            serialize = "btree",
+            serialize = "lmdb",
+            serialize = "texthash"
+        )]
+        Hash,
+        /// `network/prefix value` lines, the first network containing the address is used,
+        /// in the order of the file.
+        #[strum(to_string = "cidr")]
+        Cidr,
+        /// `/pattern/flags value` lines, the first matching pattern is used and `$1` in
+        /// the value is replaced by the first group. Also accepted as `regexp`.
+        #[strum(to_string = "pcre", serialize = "regexp")]
+        Pcre,
+    }
+
+    /// Storage of the messages sent to a tagged address (`john+lists@example.com`) in the
+    /// Maildir++ folder named after the tag (`~john/Maildir/.Lists/`), by the `maildir` transport.
+    ///
+    /// The tag is the extension of the recipient recorded with `server.recipient_delimiter`.
+    /// The characters of the tag other than letters, digits, `-` and `_` are replaced by `_`,
+    /// and its first letter is capitalized. A folder chosen by the rules takes precedence.
+    #[derive(Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+    #[serde(deny_unknown_fields)]
+    pub struct FieldServerMaildirTagFolders {
+        /// Separator of the user name and the tag in the local part of the address,
+        /// for the recipients added after `RCPT TO` (by the rules for instance).
+        #[serde(default = "FieldServerMaildirTagFolders::default_separator")]
+        pub separator: char,
+    }
+
+    /// Action taken on a modification breaking the DKIM signature of a message received signed.
+    #[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+    #[serde(rename_all = "lowercase")]
+    pub enum DkimPreservationFallback {
+        /// The covered headers are put back as received, the other modifications are kept.
+        #[default]
+        Skip,
+        /// The modifications are kept, and the message is signed again with the active
+        /// selector of the server name (see [`FieldDkim`]). Without a key, the covered
+        /// headers are put back as received.
+        Resign,
+        /// The message is denied, and moved to the `dead` queue.
+        Reject,
+    }
+
+    /// A blocklist file, and the action applied to its entries.
+    #[derive(Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+    #[serde(deny_unknown_fields)]
+    pub struct FieldAccessList {
+        /// Path of the file.
+        pub path: std::path::PathBuf,
+        /// Action applied to the entries of the list.
+        #[serde(default)]
+        pub action: AccessListAction,
+        /// Reply sent for the `deny` and `tempfail` actions, overriding the default one.
+        #[serde(default)]
+        pub reply: Option<vsmtp_common::Reply>,
+    }
+
+    /// Action applied to a client or a sender found in a blocklist.
+    #[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+    #[serde(rename_all = "lowercase")]
+    pub enum AccessListAction {
+        /// The client is disconnected, or the sender rejected, with a permanent error.
+        #[default]
+        Deny,
+        /// The client is disconnected, or the sender rejected, with a transient error.
+        Tempfail,
+        /// The message is accepted with a `X-VSMTP-Access-List` header naming the list.
+        Tag,
     }
 
     /// Readonly configuration for the dkim module.
@@ -98,7 +656,27 @@ pub mod field {
     #[serde(deny_unknown_fields)]
     pub struct FieldDkim {
         /// The private key used to sign the mail.
+        #[serde(default)]
         pub private_key: Vec<SecretFile<std::sync::Arc<dkim::PrivateKey>>>,
+        /// The selectors of the domain, from the oldest to the newest.
+        ///
+        /// The mail is signed with the `active` one, and the verification of our own
+        /// mail also accepts the selector listed just before it, during the rotation.
+        #[serde(default, deserialize_with = "FieldDkim::deserialize_selectors")]
+        pub selectors: Vec<FieldDkimSelector>,
+    }
+
+    /// A key of the domain, published at `<selector>._domainkey.<domain>`.
+    #[derive(Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+    #[serde(deny_unknown_fields)]
+    pub struct FieldDkimSelector {
+        /// Name of the selector.
+        pub selector: String,
+        /// The private key associated to the published record.
+        pub private_key: SecretFile<std::sync::Arc<dkim::PrivateKey>>,
+        /// Is the selector the one used to sign the mail? (at most one per domain)
+        #[serde(default)]
+        pub active: bool,
     }
 
     /// The field related to the privileges used by `vSMTP`.
@@ -200,6 +778,10 @@ pub mod field {
             deserialize_with = "crate::parser::tracing_directive::deserialize"
         )]
         pub level: Vec<tracing_subscriber::filter::Directive>,
+        /// Path of a file where the policy rejections (target `vsmtp::policy_event`) are
+        /// mirrored as JSON lines, one object per event.
+        #[serde(default)]
+        pub policy_events: Option<std::path::PathBuf>,
 
         /// Level of the logs sent to the system log, either `journald` or `syslog`.
         #[cfg(any(feature = "journald", feature = "syslog"))]
@@ -242,8 +824,38 @@ pub mod field {
     #[serde(deny_unknown_fields)]
     pub struct FieldQueueWorking {
         /// Size of the channel queue communicating the mails from the `receiver` pool to the `processing` pool.
+        ///
+        /// At most `channel_size` messages are processed concurrently, and `channel_size` others wait
+        /// in the channel. Once both are reached, the reply to the end of the message is delayed
+        /// until a message leaves the channel.
         #[serde(default = "FieldQueueWorking::default_channel_size")]
         pub channel_size: usize,
+        /// Deliver the messages right after the `postq` stage, without writing them
+        /// to the `deliver` queue. The message is kept in the `working` queue during
+        /// the delivery, and only written to the `deferred` or `dead` queue if it fails.
+        ///
+        /// The messages quarantined or delegated are not concerned. If the server
+        /// stops during the delivery, the message is processed again from the
+        /// `working` queue at the next start.
+        #[serde(default = "FieldQueueWorking::default_direct_delivery")]
+        pub direct_delivery: bool,
+        /// Outcome of a message whose recipients have all been removed by the rules
+        /// of the `postq` stage.
+        #[serde(default)]
+        pub no_recipient: NoRecipientPolicy,
+    }
+
+    /// Outcome of a message without recipient after the `postq` stage.
+    #[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+    #[serde(rename_all = "lowercase")]
+    pub enum NoRecipientPolicy {
+        /// The message is removed from the queues, nothing is sent.
+        Drop,
+        /// The message is moved to the `no_recipient` quarantine queue.
+        Quarantine,
+        /// The message is moved to the `dead` queue, an error is logged.
+        #[default]
+        Dead,
     }
 
     /// The configuration of the `vqueue`
@@ -251,6 +863,10 @@ pub mod field {
     #[serde(deny_unknown_fields)]
     pub struct FieldQueueDelivery {
         /// Size of the channel queue communicating the mails from the `processing` pool to the `delivery` pool.
+        ///
+        /// At most `channel_size` messages are delivered concurrently, and `channel_size` others wait
+        /// in the channel. Once both are reached, the processing awaits a free slot, slowing down the
+        /// reception in turn.
         #[serde(default = "FieldQueueDelivery::default_channel_size")]
         pub channel_size: usize,
         /// Maximum number of attempt to deliver the mail before being considered dead.
@@ -260,6 +876,199 @@ pub mod field {
         #[serde(with = "humantime_serde")]
         #[serde(default = "FieldQueueDelivery::default_deferred_retry_period")]
         pub deferred_retry_period: std::time::Duration,
+        /// Re-encode the 8-bit parts of a message to quoted-printable or base64 when
+        /// the remote server does not advertise 8BITMIME.
+        /// If disabled, the delivery to such a server fails permanently.
+        #[serde(default = "FieldQueueDelivery::default_eightbitmime_downgrade")]
+        pub eightbitmime_downgrade: bool,
+        /// see [`FieldQueueDeliveryThrottle`]
+        #[serde(default)]
+        pub throttle: FieldQueueDeliveryThrottle,
+        /// see [`FieldQueueDeliverySourceIps`]
+        #[serde(default)]
+        pub source_ips: FieldQueueDeliverySourceIps,
+        /// see [`FieldQueueDeliveryReuse`]
+        #[serde(default)]
+        pub reuse: FieldQueueDeliveryReuse,
+        /// see [`FieldQueueDeliveryCapabilities`]
+        #[serde(default)]
+        pub capabilities: FieldQueueDeliveryCapabilities,
+        /// Name sent in the `EHLO` of the outgoing connections, unless set by the `forward`
+        /// transport, or by the virtual entry of the sender. see [`HelloName`]
+        #[serde(default)]
+        pub hello_name: HelloName,
+        /// see [`FieldQueueDeliveryTls`]
+        #[serde(default)]
+        pub tls: FieldQueueDeliveryTls,
+        /// see [`FieldQueueDeliveryRouting`]
+        #[serde(default)]
+        pub routing: FieldQueueDeliveryRouting,
+    }
+
+    /// Transports the `on_delivery_attempt` function of the root filter can select
+    /// before each attempt of delivery.
+    ///
+    /// The function is called with the recipient, the name of its current transport,
+    /// the number of the attempt and the last error, and must return within `timeout`.
+    /// It returns the name of an entry of `transports`, `state::defer(..)` to defer the message,
+    /// or `()` to keep the current transport. The function is not called if `transports`
+    /// is empty.
+    #[derive(Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+    #[serde(deny_unknown_fields)]
+    pub struct FieldQueueDeliveryRouting {
+        /// Transports by name, either `"deliver"` or the url of a smarthost to forward to,
+        /// such as `"smtp://relay.example.com:25"`.
+        #[serde(default)]
+        pub transports: std::collections::BTreeMap<String, String>,
+        /// Time budget of a call to the function, checked at each access to a variable,
+        /// after which the call is stopped and the current transport is kept.
+        #[serde(with = "humantime_serde")]
+        #[serde(default = "FieldQueueDeliveryRouting::default_timeout")]
+        pub timeout: std::time::Duration,
+    }
+
+    /// Use of TLS by the `deliver` transport, by domain of the recipients.
+    ///
+    /// The policy of a domain is the one of its entry in `destinations`, or of the entry of
+    /// its closest parent, or else `default`.
+    #[derive(Debug, Default, Clone, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+    #[serde(deny_unknown_fields)]
+    pub struct FieldQueueDeliveryTls {
+        /// Policy of the domains without an entry.
+        #[serde(default)]
+        pub default: FieldQueueDeliveryTlsPolicy,
+        /// Policies of the domains and of their sub-domains.
+        #[serde(default)]
+        pub destinations: std::collections::BTreeMap<Domain, FieldQueueDeliveryTlsPolicy>,
+    }
+
+    /// Use of TLS for the messages to a destination.
+    #[derive(Debug, Default, Clone, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+    #[serde(deny_unknown_fields)]
+    pub struct FieldQueueDeliveryTlsPolicy {
+        /// see [`StartTlsPolicy`]
+        #[serde(default)]
+        pub starttls: StartTlsPolicy,
+        /// Oldest version of TLS accepted from the server, `TLSv1.2` if not set.
+        #[serde(default)]
+        pub min_version: Option<vsmtp_common::ProtocolVersion>,
+    }
+
+    /// Use of `STARTTLS` on the outgoing connections.
+    #[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+    #[serde(rename_all = "lowercase")]
+    pub enum StartTlsPolicy {
+        /// The message is not sent if the server does not support `STARTTLS`.
+        Enforce,
+        /// `STARTTLS` is issued if the server supports it.
+        #[default]
+        Prefer,
+        /// `STARTTLS` is never issued, for the servers whose TLS is broken.
+        Disable,
+    }
+
+    /// Name presented by the server in the `EHLO` of its outgoing connections, which the
+    /// remote servers may check against the PTR record of the address and the SPF policy.
+    #[derive(Debug, Default, Clone, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+    #[serde(rename_all = "kebab-case")]
+    pub enum HelloName {
+        /// The name of the server which received the message.
+        #[default]
+        ServerName,
+        /// The domain of the sender of the message, or the name of the server for a null sender.
+        SenderDomain,
+        /// This name: `#{ name: "mx.example.com" }`.
+        Name(Domain),
+    }
+
+    /// Cache of the extensions advertised by the remote servers in their reply to `EHLO`
+    /// (`SIZE`, `8BITMIME`, `SMTPUTF8` and `DSN`), read by the delivery to
+    /// choose the parameters of the transactions and whether to downgrade the messages.
+    ///
+    /// An entry is read again after `ttl`, once `uses_max` connections relied on it, when
+    /// a new connection advertises other extensions, or when the server refuses the
+    /// parameters of a `MAIL FROM` (`501`, `504`, `555`): the message is then sent again on a
+    /// new connection. The entries of a domain are removed with the `flush-capabilities <domain>`
+    /// administrative command.
+    #[derive(Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+    #[serde(deny_unknown_fields)]
+    pub struct FieldQueueDeliveryCapabilities {
+        /// Time after which an entry is read again.
+        #[serde(with = "humantime_serde")]
+        #[serde(default = "FieldQueueDeliveryCapabilities::default_ttl")]
+        pub ttl: std::time::Duration,
+        /// Maximum number of connections relying on an entry before it is read again.
+        #[serde(default = "FieldQueueDeliveryCapabilities::default_uses_max")]
+        pub uses_max: usize,
+    }
+
+    /// Reuse of the outgoing connections for the backlogs to a destination.
+    ///
+    /// After a successful message, the connection is kept open, and the next message to the
+    /// same destination is sent on it after a `RSET`, up to `messages_max` messages. A connection
+    /// idle for `idle_timeout` is closed.
+    #[derive(Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+    #[serde(deny_unknown_fields)]
+    pub struct FieldQueueDeliveryReuse {
+        /// Reuse the connections.
+        #[serde(default = "FieldQueueDeliveryReuse::default_enable")]
+        pub enable: bool,
+        /// Maximum number of messages sent on a connection.
+        #[serde(default = "FieldQueueDeliveryReuse::default_messages_max")]
+        pub messages_max: usize,
+        /// Time after which an idle connection is closed.
+        #[serde(with = "humantime_serde")]
+        #[serde(default = "FieldQueueDeliveryReuse::default_idle_timeout")]
+        pub idle_timeout: std::time::Duration,
+    }
+
+    /// The local addresses the outgoing connections are bound to, used in rotation.
+    ///
+    /// If `blocklists` is not empty, each address of the pool (and of the virtual entries)
+    /// is looked up in the DNS blocklists every `check_period`. A listed address is removed
+    /// from the rotation until it is delisted, and the messages are deferred while no
+    /// address is available.
+    #[derive(Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+    #[serde(deny_unknown_fields)]
+    pub struct FieldQueueDeliverySourceIps {
+        /// The addresses used in rotation, the system chooses the address if empty.
+        #[serde(default)]
+        pub pool: Vec<std::net::IpAddr>,
+        /// The zones of the DNS blocklists, or the keywords of the `dnsxl` plugin
+        /// (`spamhaus`, `spamrats`, ...).
+        #[serde(default)]
+        pub blocklists: Vec<String>,
+        /// Period of the lookup of the addresses in the blocklists.
+        #[serde(with = "humantime_serde")]
+        #[serde(default = "FieldQueueDeliverySourceIps::default_check_period")]
+        pub check_period: std::time::Duration,
+    }
+
+    /// Pacing of the outgoing connections to a destination replying with rate-limit
+    /// responses (`421`, or a `4xx` reply asking to slow down).
+    ///
+    /// The connections to such a destination are spaced by `delay`, doubled on each new
+    /// rate-limit response up to `delay_max`. Once no rate-limit response has been received
+    /// for `cooldown`, each successful connection halves the spacing until it is removed.
+    /// A message to the destination before its next slot is deferred.
+    #[derive(Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+    #[serde(deny_unknown_fields)]
+    pub struct FieldQueueDeliveryThrottle {
+        /// Slow down the destinations replying with rate-limit responses.
+        #[serde(default = "FieldQueueDeliveryThrottle::default_enable")]
+        pub enable: bool,
+        /// Spacing of the connections after the first rate-limit response.
+        #[serde(with = "humantime_serde")]
+        #[serde(default = "FieldQueueDeliveryThrottle::default_delay")]
+        pub delay: std::time::Duration,
+        /// Maximum spacing of the connections.
+        #[serde(with = "humantime_serde")]
+        #[serde(default = "FieldQueueDeliveryThrottle::default_delay_max")]
+        pub delay_max: std::time::Duration,
+        /// Time without rate-limit response after which the spacing starts to decrease.
+        #[serde(with = "humantime_serde")]
+        #[serde(default = "FieldQueueDeliveryThrottle::default_cooldown")]
+        pub cooldown: std::time::Duration,
     }
 
     /// The configuration of the filesystem for the mail queuer.
@@ -274,6 +1083,49 @@ pub mod field {
         /// see [`FieldQueueDelivery`]
         #[serde(default)]
         pub delivery: FieldQueueDelivery,
+        /// Age of the heartbeat after which the claim of an instance on a message
+        /// is considered stale, and can be taken over by another instance
+        /// sharing the same spool.
+        #[serde(with = "humantime_serde")]
+        #[serde(default = "FieldServerQueues::default_claim_timeout")]
+        pub claim_timeout: std::time::Duration,
+        /// see [`FieldQueueMonitor`]
+        #[serde(default)]
+        pub monitor: Option<FieldQueueMonitor>,
+    }
+
+    /// Monitor of the age of the messages waiting in the queues.
+    ///
+    /// The age of the oldest message of each queue, and the number of messages older than
+    /// the thresholds, are exposed on `GET /metrics` of the health-check listener. Every `period`,
+    /// a queue whose oldest message crossed a threshold is logged, with the uuids of its `samples`
+    /// oldest messages: as a warning for `warning`, as an error for `critical`.
+    #[derive(Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+    #[serde(deny_unknown_fields)]
+    pub struct FieldQueueMonitor {
+        /// Period of the checks of the thresholds.
+        #[serde(with = "humantime_serde")]
+        #[serde(default = "FieldQueueMonitor::default_period")]
+        pub period: std::time::Duration,
+        /// Thresholds of the age of the messages, by queue (`working`, `deliver`, `deferred`,
+        /// `quarantine/<name>` ...).
+        #[serde(default = "FieldQueueMonitor::default_thresholds")]
+        pub thresholds: std::collections::BTreeMap<String, FieldQueueMonitorThresholds>,
+        /// Number of uuids of the oldest messages logged with an alert.
+        #[serde(default = "FieldQueueMonitor::default_samples")]
+        pub samples: usize,
+    }
+
+    /// Thresholds of the age of the messages of a queue, see [`FieldQueueMonitor`].
+    #[derive(Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+    #[serde(deny_unknown_fields)]
+    pub struct FieldQueueMonitorThresholds {
+        /// Age of the oldest message raising a warning.
+        #[serde(with = "humantime_serde")]
+        pub warning: std::time::Duration,
+        /// Age of the oldest message raising a critical alert.
+        #[serde(with = "humantime_serde")]
+        pub critical: std::time::Duration,
     }
 
     /// The configuration of one virtual entry for the server.
@@ -287,6 +1139,18 @@ pub mod field {
         /// see [`FieldDkim`]
         // TODO: should not be an Option<> and should be under #[cfg(feature = "dkim")] ?
         pub dkim: Option<FieldDkim>,
+        /// The local addresses of the outgoing connections for the messages sent by the
+        /// domain, used instead of the pool of [`FieldQueueDeliverySourceIps`].
+        #[serde(default)]
+        pub source_ips: Vec<std::net::IpAddr>,
+        /// Name sent in the `EHLO` of the outgoing connections for the messages sent by
+        /// the domain, used instead of the one of [`FieldQueueDelivery`].
+        #[serde(default)]
+        pub hello_name: Option<HelloName>,
+        /// Mailbox receiving the messages sent to the unknown recipients of the domain,
+        /// instead of refusing them with the reply of [`FieldServerRecipients`].
+        #[serde(default)]
+        pub catch_all: Option<vsmtp_common::Address>,
     }
 
     /// The TLS parameter for the **OUTGOING SIDE** of the virtual entry.
@@ -297,6 +1161,22 @@ pub mod field {
         pub certificate: SecretFile<Vec<rustls::Certificate>>,
         /// Private key to use for the TLS connection.
         pub private_key: SecretFile<rustls::PrivateKey>,
+        /// Other certificates of the same identity, with a key of another algorithm (for
+        /// instance RSA next to ECDSA): the first certificate, starting with `certificate`,
+        /// whose key can sign with a signature scheme and a cipher suite offered by the client
+        /// is presented to it.
+        #[serde(default)]
+        pub alternatives: Vec<FieldServerTlsKeyPair>,
+    }
+
+    /// A certificate chain and its private key, see [`FieldServerVirtualTls`].
+    #[derive(Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+    #[serde(deny_unknown_fields)]
+    pub struct FieldServerTlsKeyPair {
+        /// Certificate chain, the first certificate certifying `private_key`.
+        pub certificate: SecretFile<Vec<rustls::Certificate>>,
+        /// Private key of the certificate.
+        pub private_key: SecretFile<rustls::PrivateKey>,
     }
 
     #[doc(hidden)]
@@ -331,6 +1211,27 @@ pub mod field {
         /// * if some,              will used these values
         #[serde(default)]
         pub root: Option<FieldServerVirtualTls>,
+        /// Authenticate the clients presenting a certificate issued by a trusted
+        /// authority, without a SASL exchange.
+        #[serde(default)]
+        pub client_auth: Option<FieldServerTlsClientAuth>,
+    }
+
+    /// Authentication of the clients by their TLS certificate.
+    ///
+    /// A client presenting a certificate chaining to `trusted_ca` is authenticated,
+    /// with the subject of its certificate as identity. A client presenting another
+    /// certificate fails the handshake, a client without certificate is served as usual.
+    #[derive(Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+    #[serde(deny_unknown_fields)]
+    pub struct FieldServerTlsClientAuth {
+        /// Certificates of the authorities issuing the certificates of the clients.
+        pub trusted_ca: SecretFile<Vec<rustls::Certificate>>,
+        /// Addresses of the listeners requesting a certificate to the clients,
+        /// all of them if empty.
+        #[serde(default)]
+        #[serde(deserialize_with = "crate::parser::socket_addr::deserialize")]
+        pub interfaces: Vec<std::net::SocketAddr>,
     }
 
     /// Configuration of the client's error handling.
@@ -349,6 +1250,23 @@ pub mod field {
         /// Unused if `soft_count` is `-1`.
         #[serde(with = "humantime_serde")]
         pub delay: std::time::Duration,
+        /// The maximum number of bad commands in a row (syntax errors, unrecognized or
+        /// out of sequence commands) before the client is disconnected, any other command
+        /// resets the count.
+        ///
+        /// `-1` to disable (default)
+        #[serde(default = "FieldServerSMTPError::default_consecutive_count")]
+        pub consecutive_count: i64,
+        /// The maximum number of transactions abandoned (a `MAIL FROM` followed by
+        /// a `RSET`, `HELO` or `EHLO`) before the client is disconnected.
+        ///
+        /// `-1` to disable (default)
+        #[serde(default = "FieldServerSMTPError::default_churn_count")]
+        pub churn_count: i64,
+        /// Reply sent to the client before disconnecting it, after `hard_count` errors.
+        /// The last error is sent first, on a line of its own.
+        #[serde(default = "FieldServerSMTPError::default_hard_count_reply")]
+        pub hard_count_reply: vsmtp_common::Reply,
     }
 
     /// Configuration of the receiver timeout between each message.
@@ -389,6 +1307,83 @@ pub mod field {
         /// increasing the number of attempt failed, until `attempt_count_max`, producing an error.
         #[serde(default = "FieldServerSMTPAuth::default_attempt_count_max")]
         pub attempt_count_max: i64,
+        /// Restrict the reverse-paths an authenticated user can use, see [`FieldServerSMTPAuthSenders`].
+        ///
+        /// If none (default), an authenticated user can send with any address.
+        #[serde(default)]
+        pub senders: Option<FieldServerSMTPAuthSenders>,
+        /// Limit the recipients an authenticated user can submit, see [`FieldServerSMTPAuthQuotas`].
+        ///
+        /// If none (default), an authenticated user can send without limit.
+        #[serde(default)]
+        pub quotas: Option<FieldServerSMTPAuthQuotas>,
+    }
+
+    /// Rate limits of the authenticated users on the submission listeners, to contain
+    /// a compromised account.
+    ///
+    /// Checked on `MAIL FROM`, each recipient of a message received counting for one.
+    /// A user over its `burst` (recipients per minute) is replied `burst_reply`, a user
+    /// over its `sustained` limit (recipients per `sustained_period`) is replied `550 5.7.1`
+    /// with a policy event. The counters are persisted in the `app` directory, and available
+    /// with the `quota <identity>` and `quota-reset <identity>` administrative commands.
+    #[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+    #[serde(deny_unknown_fields)]
+    pub struct FieldServerSMTPAuthQuotas {
+        /// Recipients per minute, the capacity of a bucket refilled continuously.
+        pub burst: u32,
+        /// Recipients per `sustained_period`.
+        pub sustained: u32,
+        /// Period of the sustained limit, starting with the first message of the user.
+        #[serde(with = "humantime_serde")]
+        #[serde(default = "FieldServerSMTPAuthQuotas::default_sustained_period")]
+        pub sustained_period: std::time::Duration,
+        /// Limits of specific users, by identity. The rules can also set the limits of
+        /// a user with `auth::set_quota`.
+        #[serde(default)]
+        pub overrides: std::collections::BTreeMap<String, SubmissionQuota>,
+        /// Period of the persistence of the counters on disk.
+        #[serde(with = "humantime_serde")]
+        #[serde(default = "FieldServerSMTPAuthQuotas::default_persist_period")]
+        pub persist_period: std::time::Duration,
+        /// Reply to a user over its `burst`.
+        #[serde(default = "FieldServerSMTPAuthQuotas::default_burst_reply")]
+        pub burst_reply: vsmtp_common::Reply,
+    }
+
+    /// Binding of the authenticated identity to the addresses it owns.
+    ///
+    /// Enforced for the transactions authenticated on the submission listeners: a `MAIL FROM`
+    /// with an address not owned by the user is replied `553 5.7.1`, and the `From` header of
+    /// the message is checked against the envelope at the working stage.
+    ///
+    /// The addresses not owned according to [`Self::owned`] are given to the function
+    /// `is_sender_owned(authid, address)` of the root filter, if defined.
+    #[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+    #[serde(deny_unknown_fields)]
+    pub struct FieldServerSMTPAuthSenders {
+        /// Patterns of the addresses owned, by `authid`. The patterns of the key `*` are owned by every user.
+        ///
+        /// A pattern is an address (`john.doe@example.com`) or a domain wildcard (`*@example.com`),
+        /// in which `{authid}` is replaced by the identity of the user (`{authid}@example.com`).
+        #[serde(default)]
+        pub owned: std::collections::BTreeMap<String, Vec<String>>,
+        /// Action taken when the `From` header does not match the reverse-path.
+        #[serde(default)]
+        pub from_header: FromHeaderPolicy,
+    }
+
+    /// Action taken when the `From` header of a message does not match its reverse-path.
+    #[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+    #[serde(rename_all = "lowercase")]
+    pub enum FromHeaderPolicy {
+        /// The message is denied, and moved to the `dead` queue.
+        Reject,
+        /// The address of the header is replaced by the reverse-path.
+        Rewrite,
+        /// The message is delivered with a `X-VSMTP-Sender-Mismatch` header.
+        #[default]
+        Flag,
     }
 
     /// Parameters for SMTP.
@@ -399,12 +1394,100 @@ pub mod field {
         /// Maximum number of recipients received in the envelop.
         #[serde(default = "FieldServerSMTP::default_rcpt_count_max")]
         pub rcpt_count_max: usize,
+        /// Reply to a `RCPT TO` command once `rcpt_count_max` recipients have been received.
+        #[serde(default = "FieldServerSMTP::default_rcpt_count_max_reply")]
+        pub rcpt_count_max_reply: vsmtp_common::Reply,
         /// SMTP's error policy.
         #[serde(default)]
         pub error: FieldServerSMTPError,
         /// SMTP's timeout policy.
         #[serde(default)]
         pub timeout_client: FieldServerSMTPTimeoutClient,
+        /// Maximum length of a command line in bytes, including the CRLF.
+        /// A longer line is discarded and replied `500 5.5.6 Line too long`.
+        /// Does not apply to the content of the message.
+        #[serde(default = "FieldServerSMTP::default_line_length_max")]
+        pub line_length_max: usize,
+        /// Maximum number of bytes received before the first CRLF of the connection
+        /// (or of the TLS session), the connection is closed with a `421` once exceeded.
+        #[serde(default = "FieldServerSMTP::default_first_line_max")]
+        pub first_line_max: usize,
+        /// Reply to a recipient already given in the transaction.
+        #[serde(default)]
+        pub duplicate_rcpt: DuplicateRcptPolicy,
+        /// Reply to a message exceeding `server.message_size_limit` (or the size declared
+        /// with `MAIL FROM:<...> SIZE=...`), sent once the end of the message is received.
+        #[serde(default = "FieldServerSMTP::default_message_size_limit_reply")]
+        pub message_size_limit_reply: vsmtp_common::Reply,
+        /// Handling of the parameters of the commands.
+        #[serde(default)]
+        pub parameters: FieldServerSMTPParameters,
+        /// Text of the `354` reply to the `DATA` command, sent once before reading the
+        /// message. `{server_name}` is replaced by the name of the server (the one of
+        /// [`FieldServerDisclosure`] if set) and `{size_max}` by `server.message_size_limit`.
+        #[serde(default = "FieldServerSMTP::default_data_reply")]
+        pub data_reply: String,
+        /// Keep the text of the `MAIL FROM` and `RCPT TO` commands sent by the client
+        /// (see `ctx::raw_mail_from()`) in the context of the messages quarantined by the
+        /// rules before the queue. The text is dropped from the other messages once queued.
+        #[serde(default)]
+        pub raw_commands_in_quarantine: bool,
+        /// Reply to a message which cannot be written in the spool (disk full, permissions ...),
+        /// a temporary failure for the client to retry later. A `421` closes the connection.
+        #[serde(default = "FieldServerSMTP::default_storage_error_reply")]
+        pub storage_error_reply: vsmtp_common::Reply,
+        /// Quarantine of the messages which are not valid UTF-8 (an 8bit content in another
+        /// charset), once the `preq` rules have read their lossy copy.
+        ///
+        /// They are delivered as the other messages if not set, the header lines untouched by
+        /// the rules and the body being sent as received.
+        #[serde(default)]
+        pub invalid_utf8_quarantine: Option<String>,
+        /// Reply to a message which cannot be read by the parser.
+        #[serde(default = "FieldServerSMTP::default_invalid_message_reply")]
+        pub invalid_message_reply: vsmtp_common::Reply,
+        /// Reply to the clients connecting while the server is in maintenance
+        /// (see `maintenance on` of [`FieldServerAdmin`]), before closing the connection.
+        #[serde(default = "FieldServerSMTP::default_maintenance_reply")]
+        pub maintenance_reply: vsmtp_common::Reply,
+    }
+
+    /// Handling of the parameters of the `MAIL FROM` and `RCPT TO` commands, and of the
+    /// commands taking none.
+    ///
+    /// An unknown parameter is replied `555 5.5.4 Unsupported parameter` if `strict`,
+    /// and ignored otherwise (some clients send the parameters of extensions which are
+    /// not advertised, `AUTH=<>` for instance). A parameter given twice is invalid.
+    /// A command with more than `count_max` parameters, or with a parameter longer than
+    /// `length_max` bytes, is replied with a `501`.
+    #[derive(Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+    #[serde(deny_unknown_fields)]
+    pub struct FieldServerSMTPParameters {
+        /// Reject the unknown parameters, instead of ignoring them.
+        #[serde(default = "FieldServerSMTPParameters::default_strict")]
+        pub strict: bool,
+        /// Maximum number of parameters of a command.
+        #[serde(default = "FieldServerSMTPParameters::default_count_max")]
+        pub count_max: usize,
+        /// Maximum length of a parameter in bytes.
+        #[serde(default = "FieldServerSMTPParameters::default_length_max")]
+        pub length_max: usize,
+        /// Reply `501` to the parameters of the commands taking none (`DATA`, `QUIT`,
+        /// `RSET` and `STARTTLS`), instead of ignoring them with a warning.
+        #[serde(default = "FieldServerSMTPParameters::default_reject_unexpected")]
+        pub reject_unexpected: bool,
+    }
+
+    /// Handling of a `RCPT TO` command with a recipient already given in the transaction,
+    /// compared in their normalized form (see [`vsmtp_common::Address::normalized`]).
+    #[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+    #[serde(rename_all = "lowercase")]
+    pub enum DuplicateRcptPolicy {
+        /// The command is replied `250 Ok`, but the recipient is not added again.
+        #[default]
+        Ignore,
+        /// The command is replied `553 5.1.0 Duplicate recipient`.
+        Reject,
     }
 
     /// Parameters for Extended SMTP.
@@ -505,13 +1588,122 @@ pub mod field {
     }
 
     /// Configuration of the application run by `vSMTP`.
-    #[derive(Default, Debug, PartialEq, Eq, Clone, serde::Deserialize, serde::Serialize)]
+    #[derive(Debug, PartialEq, Eq, Clone, serde::Deserialize, serde::Serialize)]
     #[serde(deny_unknown_fields)]
     pub struct FieldAppVSL {
-        /// Directory containing filtering rules per domain.
+        /// Directory containing filtering rules per domain, as
+        /// `<domain_dir>/<domain>/{incoming,outgoing,internal}.vsl`.
+        ///
+        /// The rules of a domain also apply to its sub-domains, and a domain whose
+        /// scripts failed to compile denies its transactions without affecting the others.
         pub domain_dir: Option<std::path::PathBuf>,
         /// Entry point for the rule engine.
         pub filter_path: Option<std::path::PathBuf>,
+        /// Read-only datasets exposed to the rules as `data::<name>`,
+        /// reloadable without recompiling the rules. see [`FieldDataset`]
+        #[serde(default)]
+        pub datasets: std::collections::BTreeMap<String, FieldDataset>,
+        /// Cache of the decisions of the `connect` and `helo` stages, disabled by default.
+        /// see [`FieldAppVSLDecisionCache`]
+        #[serde(default)]
+        pub decision_cache: Option<FieldAppVSLDecisionCache>,
+        /// Store of the greylisting of the recipients, used by `greylist::rcpt()`.
+        /// see [`FieldAppVSLGreylist`]
+        #[serde(default)]
+        pub greylist: FieldAppVSLGreylist,
+        /// Cache of the lookups of the rules shared by the transactions, used by `cache::get_or()`.
+        /// see [`FieldAppVSLLookupCache`]
+        #[serde(default)]
+        pub lookup_cache: FieldAppVSLLookupCache,
+        /// Reply of `state::reject()` called without a code, the temporary failure of the rules,
+        /// distinct from the one of the greylisting (`greylist.reply`).
+        #[serde(default = "FieldAppVSL::default_tempfail_reply")]
+        pub tempfail_reply: vsmtp_common::Reply,
+    }
+
+    /// Cache of the statuses returned by the `connect` and `helo` stages, per client
+    /// address, listener, TLS state and helo name, to avoid running the rules again
+    /// for repeated connections.
+    ///
+    /// A stage running an `action`, or a rule calling `cache::skip()`, is not cached.
+    #[derive(Debug, PartialEq, Eq, Clone, serde::Deserialize, serde::Serialize)]
+    #[serde(deny_unknown_fields)]
+    pub struct FieldAppVSLDecisionCache {
+        /// Maximum number of decisions kept, the oldest is evicted when full.
+        #[serde(default = "FieldAppVSLDecisionCache::default_capacity")]
+        pub capacity: usize,
+        /// Duration during which a decision is reused.
+        #[serde(with = "humantime_serde")]
+        #[serde(default = "FieldAppVSLDecisionCache::default_ttl")]
+        pub ttl: std::time::Duration,
+    }
+
+    /// Greylisting per (client address, sender, recipient) triplet: the first attempt of a
+    /// triplet is deferred, and its retry after `delay` is accepted and remembered for `ttl`.
+    #[derive(Debug, PartialEq, Eq, Clone, serde::Deserialize, serde::Serialize)]
+    #[serde(deny_unknown_fields)]
+    pub struct FieldAppVSLGreylist {
+        /// Minimum duration between the first attempt of a triplet and its accepted retry.
+        #[serde(with = "humantime_serde")]
+        #[serde(default = "FieldAppVSLGreylist::default_delay")]
+        pub delay: std::time::Duration,
+        /// Duration after `delay` during which the retry is expected, before the triplet is
+        /// considered new again.
+        #[serde(with = "humantime_serde")]
+        #[serde(default = "FieldAppVSLGreylist::default_retry_window")]
+        pub retry_window: std::time::Duration,
+        /// Duration during which an accepted triplet is remembered, extended by each attempt.
+        #[serde(with = "humantime_serde")]
+        #[serde(default = "FieldAppVSLGreylist::default_ttl")]
+        pub ttl: std::time::Duration,
+        /// Maximum number of triplets kept, the one expiring first is evicted when full.
+        #[serde(default = "FieldAppVSLGreylist::default_capacity")]
+        pub capacity: usize,
+        /// Reply sent to the deferred recipients.
+        #[serde(default = "FieldAppVSLGreylist::default_reply")]
+        pub reply: vsmtp_common::Reply,
+    }
+
+    /// Cache of the values computed by the rules, such as the result of a lookup per sender
+    /// domain, shared by the transactions of the process.
+    ///
+    /// The expiration of each value is set by the rule calling `cache::get_or()`.
+    #[derive(Debug, PartialEq, Eq, Clone, serde::Deserialize, serde::Serialize)]
+    #[serde(deny_unknown_fields)]
+    pub struct FieldAppVSLLookupCache {
+        /// Maximum number of values kept, the least recently used is evicted when full.
+        #[serde(default = "FieldAppVSLLookupCache::default_capacity")]
+        pub capacity: usize,
+    }
+
+    /// Source of a dataset exposed to the rules.
+    #[derive(Debug, PartialEq, Eq, Clone, serde::Deserialize, serde::Serialize)]
+    #[serde(untagged, deny_unknown_fields)]
+    pub enum FieldDataset {
+        /// The values are read from a JSON file, which is read again on reload.
+        File {
+            /// Path of the JSON file, containing an object.
+            path: std::path::PathBuf,
+        },
+        /// The values are declared in the configuration.
+        Inline {
+            /// Values of the dataset.
+            values: std::collections::BTreeMap<String, DatasetValue>,
+        },
+    }
+
+    /// A value of a dataset.
+    #[derive(Debug, PartialEq, Eq, Clone, serde::Deserialize, serde::Serialize)]
+    #[serde(untagged)]
+    pub enum DatasetValue {
+        ///
+        Bool(bool),
+        ///
+        Int(i64),
+        ///
+        String(String),
+        ///
+        Array(Vec<DatasetValue>),
     }
 
     /// Application's parameter of the logs, same properties than [`FieldServerLogs`].
@@ -19,10 +19,19 @@
 use crate::config::field::SyslogSocket;
 use crate::{
     config::field::{
-        FieldApp, FieldAppLogs, FieldAppVSL, FieldQueueDelivery, FieldQueueWorking, FieldServer,
-        FieldServerDNS, FieldServerInterfaces, FieldServerLogs, FieldServerQueues, FieldServerSMTP,
-        FieldServerSMTPAuth, FieldServerSMTPError, FieldServerSMTPTimeoutClient, FieldServerSystem,
-        FieldServerSystemThreadPool, FieldServerTls, FieldServerVirtual, ResolverOptsWrapper,
+        DuplicateRcptPolicy, FieldApp, FieldAppLogs, FieldAppVSL, FieldAppVSLDecisionCache,
+        FieldAppVSLGreylist, FieldAppVSLLookupCache, FieldQueueDelivery,
+        FieldQueueDeliveryCapabilities, FieldQueueDeliveryReuse, FieldQueueDeliveryRouting,
+        FieldQueueDeliverySourceIps, FieldQueueDeliveryThrottle, FieldQueueDeliveryTls,
+        FieldQueueMonitor, FieldQueueMonitorThresholds, FieldQueueWorking, FieldServer,
+        FieldServerAccessLists, FieldServerAliases, FieldServerDNS, FieldServerHealth,
+        FieldServerInterfaces, FieldServerLogs, FieldServerLookupTables,
+        FieldServerMaildirTagFolders, FieldServerMime, FieldServerMissingHeaders,
+        FieldServerNormalization, FieldServerQueues, FieldServerRecipients, FieldServerSMTP,
+        FieldServerSMTPAuth, FieldServerSMTPAuthQuotas, FieldServerSMTPError,
+        FieldServerSMTPParameters, FieldServerSMTPTimeoutClient, FieldServerSrs, FieldServerSystem,
+        FieldServerSystemThreadPool, FieldServerTls, FieldServerTlsStatistics, FieldServerVirtual,
+        HelloName, MissingHeadersPolicy, NoRecipientPolicy, ResolverOptsWrapper,
     },
     field::FieldServerESMTP,
     Config,
@@ -121,6 +130,24 @@ impl Config {
                 esmtp: FieldServerESMTP::default(),
                 dns: FieldServerDNS::default(),
                 r#virtual: std::collections::BTreeMap::default(),
+                health: None,
+                admin: None,
+                tls_statistics: None,
+                access_lists: None,
+                aliases: None,
+                missing_headers: None,
+                dkim_preservation: None,
+                normalization: None,
+                recipient_delimiter: FieldServer::default_recipient_delimiter(),
+                maildir_tag_folders: None,
+                strip_received: None,
+                trusted_upstreams: None,
+                recipients: None,
+                disclosure: None,
+                srs: None,
+                connection_limits: None,
+                lookup_tables: None,
+                mime: FieldServerMime::default(),
             },
             app: FieldApp::default(),
             path: None,
@@ -143,6 +170,24 @@ impl Default for FieldServer {
             esmtp: FieldServerESMTP::default(),
             dns: FieldServerDNS::default(),
             r#virtual: std::collections::BTreeMap::default(),
+            health: None,
+            admin: None,
+            tls_statistics: None,
+            access_lists: None,
+            aliases: None,
+            missing_headers: None,
+            dkim_preservation: None,
+            normalization: None,
+            recipient_delimiter: Self::default_recipient_delimiter(),
+            maildir_tag_folders: None,
+            strip_received: None,
+            trusted_upstreams: None,
+            recipients: None,
+            disclosure: None,
+            srs: None,
+            connection_limits: None,
+            lookup_tables: None,
+            mime: FieldServerMime::default(),
         }
     }
 }
@@ -164,6 +209,10 @@ impl FieldServer {
     pub(crate) const fn default_message_size_limit() -> usize {
         10_000_000
     }
+
+    pub(crate) fn default_recipient_delimiter() -> String {
+        "+".to_owned()
+    }
 }
 
 impl Default for FieldServerSystem {
@@ -240,6 +289,7 @@ impl Default for FieldServerLogs {
         Self {
             filename: Self::default_filename(),
             level: Self::default_level(),
+            policy_events: None,
             #[cfg(any(feature = "journald", feature = "syslog"))]
             sys_level: Self::default_sys_level(),
             #[cfg(feature = "syslog")]
@@ -314,6 +364,8 @@ impl Default for FieldServerQueues {
             dirpath: Self::default_dirpath(),
             working: FieldQueueWorking::default(),
             delivery: FieldQueueDelivery::default(),
+            claim_timeout: Self::default_claim_timeout(),
+            monitor: None,
         }
     }
 }
@@ -322,12 +374,54 @@ impl FieldServerQueues {
     pub(crate) fn default_dirpath() -> std::path::PathBuf {
         "/var/spool/vsmtp".into()
     }
+
+    pub(crate) const fn default_claim_timeout() -> std::time::Duration {
+        std::time::Duration::from_secs(5 * 60)
+    }
+}
+
+impl Default for FieldQueueMonitor {
+    fn default() -> Self {
+        Self {
+            period: Self::default_period(),
+            thresholds: Self::default_thresholds(),
+            samples: Self::default_samples(),
+        }
+    }
+}
+
+impl FieldQueueMonitor {
+    pub(crate) const fn default_period() -> std::time::Duration {
+        std::time::Duration::from_secs(60)
+    }
+
+    pub(crate) fn default_thresholds(
+    ) -> std::collections::BTreeMap<String, FieldQueueMonitorThresholds> {
+        [("working", 5 * 60, 30 * 60), ("deliver", 15 * 60, 60 * 60)]
+            .into_iter()
+            .map(|(queue, warning, critical)| {
+                (
+                    queue.to_owned(),
+                    FieldQueueMonitorThresholds {
+                        warning: std::time::Duration::from_secs(warning),
+                        critical: std::time::Duration::from_secs(critical),
+                    },
+                )
+            })
+            .collect()
+    }
+
+    pub(crate) const fn default_samples() -> usize {
+        5
+    }
 }
 
 impl Default for FieldQueueWorking {
     fn default() -> Self {
         Self {
             channel_size: Self::default_channel_size(),
+            direct_delivery: Self::default_direct_delivery(),
+            no_recipient: NoRecipientPolicy::default(),
         }
     }
 }
@@ -336,6 +430,10 @@ impl FieldQueueWorking {
     pub(crate) const fn default_channel_size() -> usize {
         32
     }
+
+    pub(crate) const fn default_direct_delivery() -> bool {
+        false
+    }
 }
 
 impl Default for FieldQueueDelivery {
@@ -344,6 +442,14 @@ impl Default for FieldQueueDelivery {
             channel_size: Self::default_channel_size(),
             deferred_retry_max: Self::default_deferred_retry_max(),
             deferred_retry_period: Self::default_deferred_retry_period(),
+            eightbitmime_downgrade: Self::default_eightbitmime_downgrade(),
+            throttle: FieldQueueDeliveryThrottle::default(),
+            source_ips: FieldQueueDeliverySourceIps::default(),
+            reuse: FieldQueueDeliveryReuse::default(),
+            capabilities: FieldQueueDeliveryCapabilities::default(),
+            hello_name: HelloName::default(),
+            tls: FieldQueueDeliveryTls::default(),
+            routing: FieldQueueDeliveryRouting::default(),
         }
     }
 }
@@ -360,6 +466,309 @@ impl FieldQueueDelivery {
     pub(crate) const fn default_deferred_retry_period() -> std::time::Duration {
         std::time::Duration::from_secs(300)
     }
+
+    pub(crate) const fn default_eightbitmime_downgrade() -> bool {
+        true
+    }
+}
+
+impl Default for FieldQueueDeliveryThrottle {
+    fn default() -> Self {
+        Self {
+            enable: Self::default_enable(),
+            delay: Self::default_delay(),
+            delay_max: Self::default_delay_max(),
+            cooldown: Self::default_cooldown(),
+        }
+    }
+}
+
+impl FieldQueueDeliveryThrottle {
+    pub(crate) const fn default_enable() -> bool {
+        true
+    }
+
+    pub(crate) const fn default_delay() -> std::time::Duration {
+        std::time::Duration::from_secs(10)
+    }
+
+    pub(crate) const fn default_delay_max() -> std::time::Duration {
+        std::time::Duration::from_secs(5 * 60)
+    }
+
+    pub(crate) const fn default_cooldown() -> std::time::Duration {
+        std::time::Duration::from_secs(15 * 60)
+    }
+}
+
+impl Default for FieldQueueDeliveryRouting {
+    fn default() -> Self {
+        Self {
+            transports: std::collections::BTreeMap::new(),
+            timeout: Self::default_timeout(),
+        }
+    }
+}
+
+impl FieldQueueDeliveryRouting {
+    pub(crate) const fn default_timeout() -> std::time::Duration {
+        std::time::Duration::from_millis(100)
+    }
+}
+
+impl Default for FieldQueueDeliveryReuse {
+    fn default() -> Self {
+        Self {
+            enable: Self::default_enable(),
+            messages_max: Self::default_messages_max(),
+            idle_timeout: Self::default_idle_timeout(),
+        }
+    }
+}
+
+impl Default for FieldQueueDeliveryCapabilities {
+    fn default() -> Self {
+        Self {
+            ttl: Self::default_ttl(),
+            uses_max: Self::default_uses_max(),
+        }
+    }
+}
+
+impl FieldQueueDeliveryCapabilities {
+    pub(crate) const fn default_ttl() -> std::time::Duration {
+        std::time::Duration::from_secs(60 * 60)
+    }
+
+    pub(crate) const fn default_uses_max() -> usize {
+        100
+    }
+}
+
+impl FieldQueueDeliveryReuse {
+    pub(crate) const fn default_enable() -> bool {
+        false
+    }
+
+    pub(crate) const fn default_messages_max() -> usize {
+        100
+    }
+
+    pub(crate) const fn default_idle_timeout() -> std::time::Duration {
+        std::time::Duration::from_secs(10)
+    }
+}
+
+impl Default for FieldQueueDeliverySourceIps {
+    fn default() -> Self {
+        Self {
+            pool: vec![],
+            blocklists: vec![],
+            check_period: Self::default_check_period(),
+        }
+    }
+}
+
+impl FieldQueueDeliverySourceIps {
+    pub(crate) const fn default_check_period() -> std::time::Duration {
+        std::time::Duration::from_secs(10 * 60)
+    }
+}
+
+impl Default for FieldServerTlsStatistics {
+    fn default() -> Self {
+        Self {
+            alert_threshold: Self::default_alert_threshold(),
+            window: Self::default_window(),
+            persist_period: Self::default_persist_period(),
+            entries_max: Self::default_entries_max(),
+        }
+    }
+}
+
+impl FieldAppVSLDecisionCache {
+    pub(crate) const fn default_capacity() -> usize {
+        10_000
+    }
+
+    pub(crate) const fn default_ttl() -> std::time::Duration {
+        std::time::Duration::from_secs(60)
+    }
+}
+
+impl Default for FieldAppVSLLookupCache {
+    fn default() -> Self {
+        Self {
+            capacity: Self::default_capacity(),
+        }
+    }
+}
+
+impl FieldAppVSLLookupCache {
+    pub(crate) const fn default_capacity() -> usize {
+        10_000
+    }
+}
+
+impl Default for FieldAppVSL {
+    fn default() -> Self {
+        Self {
+            domain_dir: None,
+            filter_path: None,
+            datasets: std::collections::BTreeMap::new(),
+            decision_cache: None,
+            greylist: FieldAppVSLGreylist::default(),
+            lookup_cache: FieldAppVSLLookupCache::default(),
+            tempfail_reply: Self::default_tempfail_reply(),
+        }
+    }
+}
+
+impl FieldAppVSL {
+    pub(crate) fn default_tempfail_reply() -> vsmtp_common::Reply {
+        "451 4.3.0 Requested action aborted: local error in processing\r\n"
+            .parse()
+            .expect("valid reply")
+    }
+}
+
+impl Default for FieldAppVSLGreylist {
+    fn default() -> Self {
+        Self {
+            delay: Self::default_delay(),
+            retry_window: Self::default_retry_window(),
+            ttl: Self::default_ttl(),
+            capacity: Self::default_capacity(),
+            reply: Self::default_reply(),
+        }
+    }
+}
+
+impl FieldAppVSLGreylist {
+    pub(crate) const fn default_delay() -> std::time::Duration {
+        std::time::Duration::from_secs(5 * 60)
+    }
+
+    pub(crate) const fn default_retry_window() -> std::time::Duration {
+        std::time::Duration::from_secs(4 * 60 * 60)
+    }
+
+    pub(crate) const fn default_ttl() -> std::time::Duration {
+        std::time::Duration::from_secs(36 * 24 * 60 * 60)
+    }
+
+    pub(crate) const fn default_capacity() -> usize {
+        100_000
+    }
+
+    pub(crate) fn default_reply() -> vsmtp_common::Reply {
+        "451 4.7.1 Greylisted, please try again later\r\n"
+            .parse()
+            .expect("valid reply")
+    }
+}
+
+impl FieldServerHealth {
+    pub(crate) const fn default_spool_free_space_min() -> u64 {
+        // 64 MiB
+        64 * 1024 * 1024
+    }
+}
+
+impl FieldServerSrs {
+    pub(crate) const fn default_max_age() -> std::time::Duration {
+        std::time::Duration::from_secs(21 * 24 * 60 * 60)
+    }
+}
+
+impl FieldServerAliases {
+    pub(crate) const fn default_max_depth() -> usize {
+        10
+    }
+
+    pub(crate) const fn default_reload_period() -> std::time::Duration {
+        std::time::Duration::from_secs(10)
+    }
+}
+
+impl FieldServerNormalization {
+    pub(crate) const fn default_trailing_whitespace() -> bool {
+        true
+    }
+}
+
+impl FieldServerMaildirTagFolders {
+    pub(crate) const fn default_separator() -> char {
+        '+'
+    }
+}
+
+impl FieldServerMissingHeaders {
+    pub(crate) const fn default_submission() -> MissingHeadersPolicy {
+        MissingHeadersPolicy::Add
+    }
+
+    pub(crate) const fn default_relay() -> MissingHeadersPolicy {
+        MissingHeadersPolicy::Ignore
+    }
+
+    pub(crate) const fn default_internal() -> MissingHeadersPolicy {
+        MissingHeadersPolicy::Add
+    }
+}
+
+impl FieldServerRecipients {
+    pub(crate) fn default_reply() -> vsmtp_common::Reply {
+        "550 5.1.1 User unknown\r\n".parse().expect("valid reply")
+    }
+
+    pub(crate) const fn default_probe_count_max() -> usize {
+        5
+    }
+
+    pub(crate) const fn default_probe_window() -> std::time::Duration {
+        std::time::Duration::from_secs(10 * 60)
+    }
+
+    pub(crate) fn default_probe_reply() -> vsmtp_common::Reply {
+        "421 4.7.0 Too many unknown recipients, closing connection\r\n"
+            .parse()
+            .expect("valid reply")
+    }
+
+    pub(crate) const fn default_reload_period() -> std::time::Duration {
+        std::time::Duration::from_secs(10)
+    }
+}
+
+impl FieldServerLookupTables {
+    pub(crate) const fn default_reload_period() -> std::time::Duration {
+        std::time::Duration::from_secs(10)
+    }
+}
+
+impl FieldServerAccessLists {
+    pub(crate) const fn default_reload_period() -> std::time::Duration {
+        std::time::Duration::from_secs(10)
+    }
+}
+
+impl FieldServerTlsStatistics {
+    pub(crate) const fn default_alert_threshold() -> u32 {
+        5
+    }
+
+    pub(crate) const fn default_window() -> std::time::Duration {
+        std::time::Duration::from_secs(7 * 24 * 60 * 60)
+    }
+
+    pub(crate) const fn default_persist_period() -> std::time::Duration {
+        std::time::Duration::from_secs(5 * 60)
+    }
+
+    pub(crate) const fn default_entries_max() -> usize {
+        10_000
+    }
 }
 
 impl FieldServerVirtual {
@@ -375,10 +784,28 @@ impl Default for FieldServerSMTPAuth {
             ),
             mechanisms: Self::default_mechanisms(),
             attempt_count_max: Self::default_attempt_count_max(),
+            senders: None,
+            quotas: None,
         }
     }
 }
 
+impl FieldServerSMTPAuthQuotas {
+    pub(crate) const fn default_sustained_period() -> std::time::Duration {
+        std::time::Duration::from_secs(24 * 60 * 60)
+    }
+
+    pub(crate) const fn default_persist_period() -> std::time::Duration {
+        std::time::Duration::from_secs(60)
+    }
+
+    pub(crate) fn default_burst_reply() -> vsmtp_common::Reply {
+        "452 4.7.1 Submission rate exceeded, try again later\r\n"
+            .parse()
+            .expect("valid reply")
+    }
+}
+
 impl FieldServerSMTPAuth {
     pub(crate) const fn default_enable_dangerous_mechanism_in_clair() -> bool {
         false
@@ -399,8 +826,20 @@ impl Default for FieldServerSMTP {
     fn default() -> Self {
         Self {
             rcpt_count_max: Self::default_rcpt_count_max(),
+            rcpt_count_max_reply: Self::default_rcpt_count_max_reply(),
             error: FieldServerSMTPError::default(),
             timeout_client: FieldServerSMTPTimeoutClient::default(),
+            line_length_max: Self::default_line_length_max(),
+            first_line_max: Self::default_first_line_max(),
+            duplicate_rcpt: DuplicateRcptPolicy::default(),
+            parameters: FieldServerSMTPParameters::default(),
+            data_reply: Self::default_data_reply(),
+            raw_commands_in_quarantine: false,
+            storage_error_reply: Self::default_storage_error_reply(),
+            invalid_utf8_quarantine: None,
+            invalid_message_reply: Self::default_invalid_message_reply(),
+            message_size_limit_reply: Self::default_message_size_limit_reply(),
+            maintenance_reply: Self::default_maintenance_reply(),
         }
     }
 }
@@ -409,6 +848,79 @@ impl FieldServerSMTP {
     pub(crate) const fn default_rcpt_count_max() -> usize {
         1000
     }
+
+    pub(crate) fn default_rcpt_count_max_reply() -> vsmtp_common::Reply {
+        "452 4.5.3 Too many recipients\r\n"
+            .parse()
+            .expect("valid reply")
+    }
+
+    /// 512 bytes (rfc 5321), with room for the extensions (AUTH, SMTPUTF8, ...).
+    pub(crate) const fn default_line_length_max() -> usize {
+        1024
+    }
+
+    pub(crate) const fn default_first_line_max() -> usize {
+        8192
+    }
+
+    pub(crate) fn default_data_reply() -> String {
+        "Start mail input; end with <CRLF>.<CRLF>".to_owned()
+    }
+
+    pub(crate) fn default_storage_error_reply() -> vsmtp_common::Reply {
+        "452 4.3.1 Insufficient system storage\r\n"
+            .parse()
+            .expect("valid reply")
+    }
+
+    pub(crate) fn default_invalid_message_reply() -> vsmtp_common::Reply {
+        "554 5.6.0 Message content cannot be read\r\n"
+            .parse()
+            .expect("valid reply")
+    }
+
+    pub(crate) fn default_message_size_limit_reply() -> vsmtp_common::Reply {
+        "552 5.3.4 Message size exceeds fixed maximum message size\r\n"
+            .parse()
+            .expect("valid reply")
+    }
+
+    pub(crate) fn default_maintenance_reply() -> vsmtp_common::Reply {
+        "421 4.3.2 Service not available, closing transmission channel\r\n"
+            .parse()
+            .expect("valid reply")
+    }
+}
+
+impl Default for FieldServerSMTPParameters {
+    fn default() -> Self {
+        Self {
+            strict: Self::default_strict(),
+            count_max: Self::default_count_max(),
+            length_max: Self::default_length_max(),
+            reject_unexpected: Self::default_reject_unexpected(),
+        }
+    }
+}
+
+impl FieldServerSMTPParameters {
+    pub(crate) const fn default_strict() -> bool {
+        true
+    }
+
+    pub(crate) const fn default_count_max() -> usize {
+        10
+    }
+
+    /// `ORCPT=` followed by an address of at most 500 characters (rfc 3461).
+    pub(crate) const fn default_length_max() -> usize {
+        512
+    }
+
+    pub(crate) const fn default_reject_unexpected() -> bool {
+        true
+    }
 }
 
 impl Default for FieldServerESMTP {
@@ -510,10 +1022,29 @@ impl Default for FieldServerSMTPError {
             soft_count: 10,
             hard_count: 20,
             delay: std::time::Duration::from_millis(5000),
+            consecutive_count: Self::default_consecutive_count(),
+            churn_count: Self::default_churn_count(),
+            hard_count_reply: Self::default_hard_count_reply(),
         }
     }
 }
 
+impl FieldServerSMTPError {
+    pub(crate) const fn default_consecutive_count() -> i64 {
+        -1
+    }
+
+    pub(crate) const fn default_churn_count() -> i64 {
+        -1
+    }
+
+    pub(crate) fn default_hard_count_reply() -> vsmtp_common::Reply {
+        "451 4.7.0 Too many errors from the client\r\n"
+            .parse()
+            .expect("valid reply")
+    }
+}
+
 impl Default for FieldServerSMTPTimeoutClient {
     fn default() -> Self {
         Self {
@@ -555,3 +1086,27 @@ impl FieldAppLogs {
         "/var/log/vsmtp/app.log".into()
     }
 }
+
+impl Default for FieldServerMime {
+    fn default() -> Self {
+        Self {
+            max_depth: Self::default_max_depth(),
+            max_parts: Self::default_max_parts(),
+            max_boundary_length: Self::default_max_boundary_length(),
+        }
+    }
+}
+
+impl FieldServerMime {
+    pub(crate) const fn default_max_depth() -> usize {
+        32
+    }
+
+    pub(crate) const fn default_max_parts() -> usize {
+        1000
+    }
+
+    pub(crate) const fn default_max_boundary_length() -> usize {
+        70
+    }
+}
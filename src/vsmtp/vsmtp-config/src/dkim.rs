@@ -0,0 +1,172 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2023 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use crate::field::{FieldDkim, FieldDkimSelector};
+
+impl FieldDkim {
+    /// The selector used to sign the mail.
+    #[must_use]
+    pub fn signing_selector(&self) -> Option<&FieldDkimSelector> {
+        self.selectors.iter().find(|selector| selector.active)
+    }
+
+    /// The selectors accepted when verifying our own mail: the active one,
+    /// and the one listed just before it, still in use by the mail signed
+    /// before the rotation.
+    #[must_use]
+    pub fn verifying_selectors(&self) -> &[FieldDkimSelector] {
+        self.selectors
+            .iter()
+            .position(|selector| selector.active)
+            .and_then(|active| self.selectors.get(active.saturating_sub(1)..=active))
+            .unwrap_or_default()
+    }
+
+    /// Get a selector accepted when verifying our own mail by its name.
+    #[must_use]
+    pub fn get_verifying_selector(&self, name: &str) -> Option<&FieldDkimSelector> {
+        self.verifying_selectors()
+            .iter()
+            .find(|selector| selector.selector == name)
+    }
+
+    pub(crate) fn deserialize_selectors<'de, D>(
+        deserializer: D,
+    ) -> Result<Vec<FieldDkimSelector>, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let selectors = <Vec<FieldDkimSelector> as serde::Deserialize>::deserialize(deserializer)?;
+
+        if selectors.iter().filter(|selector| selector.active).count() > 1 {
+            return Err(serde::de::Error::custom(
+                "only one dkim selector can be `active`",
+            ));
+        }
+        for (i, selector) in selectors.iter().enumerate() {
+            if selectors[..i]
+                .iter()
+                .any(|s| s.selector == selector.selector)
+            {
+                return Err(serde::de::Error::custom(format!(
+                    "the dkim selector `{}` is declared twice",
+                    selector.selector
+                )));
+            }
+        }
+
+        Ok(selectors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::field::{FieldDkim, FieldDkimSelector, SecretFile};
+    use vsmtp_auth::dkim::{KeyAlgorithm, PrivateKey};
+
+    fn selector(name: &str, active: bool) -> FieldDkimSelector {
+        FieldDkimSelector {
+            selector: name.to_string(),
+            private_key: SecretFile {
+                inner: std::sync::Arc::new(PrivateKey::generate(KeyAlgorithm::Ed25519).unwrap().0),
+                path: format!("{name}.pem").into(),
+            },
+            active,
+        }
+    }
+
+    fn names(selectors: &[FieldDkimSelector]) -> Vec<&str> {
+        selectors.iter().map(|s| s.selector.as_str()).collect()
+    }
+
+    #[test]
+    fn rotation() {
+        let dkim = FieldDkim {
+            private_key: vec![],
+            selectors: vec![
+                selector("2022-01", false),
+                selector("2023-01", false),
+                selector("2023-06", true),
+                selector("2024-01", false),
+            ],
+        };
+
+        assert_eq!(dkim.signing_selector().unwrap().selector, "2023-06");
+        assert_eq!(names(dkim.verifying_selectors()), ["2023-01", "2023-06"]);
+        assert!(dkim.get_verifying_selector("2023-01").is_some());
+        // retired, or published but not used yet
+        assert!(dkim.get_verifying_selector("2022-01").is_none());
+        assert!(dkim.get_verifying_selector("2024-01").is_none());
+    }
+
+    #[test]
+    fn first_selector() {
+        let dkim = FieldDkim {
+            private_key: vec![],
+            selectors: vec![selector("2023-06", true), selector("2024-01", false)],
+        };
+
+        assert_eq!(dkim.signing_selector().unwrap().selector, "2023-06");
+        assert_eq!(names(dkim.verifying_selectors()), ["2023-06"]);
+    }
+
+    #[test]
+    fn no_active_selector() {
+        let dkim = FieldDkim {
+            private_key: vec![],
+            selectors: vec![selector("2023-06", false)],
+        };
+
+        assert!(dkim.signing_selector().is_none());
+        assert!(dkim.verifying_selectors().is_empty());
+    }
+
+    #[test]
+    fn deserialize() {
+        let key = std::path::PathBuf::from_iter([
+            env!("CARGO_MANIFEST_DIR"),
+            "../vsmtp-test/src/template/certs/private_key.rsa.key",
+        ]);
+        let key = key.display();
+
+        let dkim = serde_json::from_str::<FieldDkim>(&format!(
+            r#"{{ "selectors": [
+                {{ "selector": "old", "private_key": "{key}" }},
+                {{ "selector": "new", "private_key": "{key}", "active": true }}
+            ] }}"#
+        ))
+        .unwrap();
+        assert_eq!(dkim.signing_selector().unwrap().selector, "new");
+
+        let error = serde_json::from_str::<FieldDkim>(&format!(
+            r#"{{ "selectors": [
+                {{ "selector": "old", "private_key": "{key}", "active": true }},
+                {{ "selector": "new", "private_key": "{key}", "active": true }}
+            ] }}"#
+        ))
+        .unwrap_err();
+        assert!(error.to_string().contains("only one dkim selector"));
+
+        let error = serde_json::from_str::<FieldDkim>(&format!(
+            r#"{{ "selectors": [
+                {{ "selector": "new", "private_key": "{key}" }},
+                {{ "selector": "new", "private_key": "{key}", "active": true }}
+            ] }}"#
+        ))
+        .unwrap_err();
+        assert!(error.to_string().contains("declared twice"));
+    }
+}
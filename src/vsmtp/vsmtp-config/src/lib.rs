@@ -17,9 +17,10 @@
 //!
 //! # Configuration
 //!
-//! The type [`Config`] expose two methods :
+//! The type [`Config`] expose the following methods :
 //! * [`Config::builder`] to create a new configuration builder.
 //! * [`Config::from_vsl_file`] to read a configuration from a vSL file.
+//! * [`Config::from_toml_str`] and [`Config::to_toml_string`] to read and write a configuration as TOML.
 //!
 //! # Example
 //!
@@ -80,17 +81,18 @@ pub mod builder {
 
 mod config;
 mod default;
+mod dkim;
 mod rustls_helper;
 mod virtual_tls;
 
 mod dns_resolver;
 
 use anyhow::Context;
-use config::field::FieldServerVirtual;
+use config::field::{FieldServer, FieldServerESMTP, FieldServerVirtual};
 pub use dns_resolver::DnsResolvers;
 
 pub use config::{field, Config};
-pub use rustls_helper::get_rustls_config;
+pub use rustls_helper::{get_rustls_config, get_rustls_config_client_auth};
 
 use builder::{Builder, WantsVersion};
 use vsmtp_common::Domain;
@@ -182,9 +184,66 @@ impl Config {
         let raw_config =
             serde_json::to_string(&user_config).context("The main configuration is malformed")?;
 
-        let config = &mut serde_json::Deserializer::from_str(&raw_config);
+        let mut config = Self::from_json(&raw_config)?;
 
-        let mut config: Self = match serde_path_to_error::deserialize(config) {
+        config.get_domain_config(&engine)?;
+        config.server.check_catch_all()?;
+        config.server.check_tls_keys()?;
+
+        Ok(config)
+    }
+
+    /// Create a [`Config`] from a [TOML] document, the missing fields taking
+    /// their default value.
+    ///
+    /// The document is validated like a vsl configuration and the builder,
+    /// but the configurations of `app.vsl.domain_dir` are not read: the virtual
+    /// domains must be declared in `server.virtual`.
+    ///
+    /// # Errors
+    ///
+    /// * Data is not valid TOML.
+    /// * Found an unknown field.
+    /// * Version requirements are not fulfilled.
+    /// * A mandatory field is missing. (when no default value is provided)
+    /// * The extensions cannot be advertised together.
+    /// * A catch-all mailbox is not in a domain of the server.
+    /// * A TLS private key is not the one of its certificate.
+    ///
+    /// [TOML]: https://toml.io
+    pub fn from_toml_str(toml: &str) -> anyhow::Result<Self> {
+        let value = toml::from_str::<serde_json::Value>(toml)
+            .context("The configuration is not valid TOML")?;
+
+        let config = Self::from_json(&serde_json::to_string(&value)?)?;
+        config.server.check_catch_all()?;
+        config.server.check_tls_keys()?;
+
+        Ok(config)
+    }
+
+    /// Serialize the configuration to a [TOML] document, including the fields
+    /// left to their default value.
+    ///
+    /// The output can be parsed back with [`Config::from_toml_str`].
+    ///
+    /// # Errors
+    ///
+    /// * A field cannot be represented in TOML.
+    ///
+    /// [TOML]: https://toml.io
+    pub fn to_toml_string(&self) -> anyhow::Result<String> {
+        // going through `toml::Value` emits the values of a table before its sub-tables,
+        // as required by the format, and drops the fields set to `None`.
+        let value = toml::Value::try_from(self).context("The configuration is malformed")?;
+
+        toml::to_string_pretty(&value).context("The configuration is malformed")
+    }
+
+    fn from_json(raw_config: &str) -> anyhow::Result<Self> {
+        let config = &mut serde_json::Deserializer::from_str(raw_config);
+
+        let config: Self = match serde_path_to_error::deserialize(config) {
             Ok(config) => config,
             Err(error) => anyhow::bail!(Self::format_error(&error)?),
         };
@@ -198,7 +257,7 @@ impl Config {
             );
         }
 
-        config.get_domain_config(&engine)?;
+        config.server.esmtp.check_coherence()?;
 
         Ok(config)
     }
@@ -349,3 +408,105 @@ impl Config {
         )
     }
 }
+
+impl FieldServer {
+    /// The name disclosed instead of the real names of the server, if any,
+    /// see [`FieldServerDisclosure`].
+    #[must_use]
+    pub fn disclosed_name(&self) -> Option<&Domain> {
+        self.disclosure
+            .as_ref()
+            .and_then(|disclosure| disclosure.name.as_ref())
+    }
+
+    /// Reject the catch-all mailboxes outside of the domains of the server, the messages
+    /// sent to them would never reach a mailbox.
+    ///
+    /// The existence of the mailbox itself is checked against the mailboxes file when
+    /// the rules are loaded.
+    ///
+    /// # Errors
+    ///
+    /// * the domain of a catch-all mailbox is not the name of the server or a virtual domain
+    pub(crate) fn check_catch_all(&self) -> anyhow::Result<()> {
+        for (domain, entry) in &self.r#virtual {
+            let Some(catch_all) = &entry.catch_all else {
+                continue;
+            };
+            let catch_all_domain = catch_all.domain();
+            anyhow::ensure!(
+                catch_all_domain == self.name || self.r#virtual.contains_key(&catch_all_domain),
+                "the catch-all `{catch_all}` of the domain `{domain}` is not a mailbox of a domain of the server"
+            );
+        }
+        Ok(())
+    }
+
+    /// Reject the TLS private keys which are not the ones of their certificate, and warn
+    /// about the identities served with the keys of a single algorithm, see
+    /// [`field::FieldServerVirtualTls`].
+    ///
+    /// # Errors
+    ///
+    /// * a private key does not match the first certificate of its chain
+    pub(crate) fn check_tls_keys(&self) -> anyhow::Result<()> {
+        let identities = self
+            .tls
+            .as_ref()
+            .and_then(|tls| tls.root.as_ref())
+            .map(|tls| ("root".to_owned(), tls))
+            .into_iter()
+            .chain(self.r#virtual.iter().filter_map(|(domain, entry)| {
+                entry.tls.as_ref().map(|tls| (domain.to_string(), tls))
+            }));
+
+        for (identity, tls) in identities {
+            let mut algorithms = vec![];
+            for (certificate, private_key) in tls.key_pairs() {
+                let algorithm =
+                    rustls_helper::check_key_pair(&certificate.inner, &private_key.inner)
+                        .with_context(|| {
+                            format!(
+                                "the TLS certificate '{}' of `{identity}` with the key '{}'",
+                                certificate.path.display(),
+                                private_key.path.display()
+                            )
+                        })?;
+                if !algorithms.contains(&algorithm) {
+                    algorithms.push(algorithm);
+                }
+            }
+            if algorithms.len() == 1 {
+                tracing::warn!(
+                    identity,
+                    ?algorithms,
+                    "TLS certificates of a single key algorithm, the clients not supporting it cannot negotiate TLS."
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Are the name and the version of the software disclosed in the messages ?
+    #[must_use]
+    pub fn discloses_software(&self) -> bool {
+        self.disclosure
+            .as_ref()
+            .map_or(true, |disclosure| disclosure.software)
+    }
+}
+
+impl FieldServerESMTP {
+    /// Reject the combinations of extensions which cannot be advertised together.
+    ///
+    /// # Errors
+    ///
+    /// * `smtputf8` is enabled without `eightbitmime` (<https://datatracker.ietf.org/doc/html/rfc6531#section-3.1>)
+    pub(crate) fn check_coherence(&self) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            !self.smtputf8 || self.eightbitmime,
+            "the extension `smtputf8` requires `eightbitmime` to be enabled"
+        );
+        Ok(())
+    }
+}
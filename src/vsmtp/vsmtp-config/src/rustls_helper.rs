@@ -42,9 +42,40 @@ fn to_supported_cipher_suite(
         .collect::<Vec<_>>()
 }
 
+/// The certificates of an identity, with keys of different algorithms.
+struct CertifiedKeys(Vec<std::sync::Arc<rustls::sign::CertifiedKey>>);
+
+impl CertifiedKeys {
+    /// The first certificate whose key can sign with a scheme offered by the client,
+    /// in a cipher suite it offers, or the first one if none can.
+    fn select(
+        &self,
+        client_hello: &rustls::server::ClientHello<'_>,
+    ) -> Option<std::sync::Arc<rustls::sign::CertifiedKey>> {
+        let is_usable = |certified: &&std::sync::Arc<rustls::sign::CertifiedKey>| {
+            let algorithm = certified.key.algorithm();
+            certified
+                .key
+                .choose_scheme(client_hello.signature_schemes())
+                .is_some()
+                && ALL_CIPHER_SUITES.iter().any(|suite| {
+                    client_hello.cipher_suites().contains(&suite.suite())
+                        && suite.usable_for_signature_algorithm(algorithm)
+                })
+        };
+
+        self.0
+            .iter()
+            .find(is_usable)
+            .or_else(|| self.0.first())
+            .cloned()
+    }
+}
+
 struct CertResolver {
-    sni_resolver: rustls::server::ResolvesServerCertUsingSni,
-    default_cert: Option<std::sync::Arc<rustls::sign::CertifiedKey>>,
+    /// The certificates of the virtual entries, by lowercase name.
+    by_name: std::collections::HashMap<String, CertifiedKeys>,
+    default_cert: Option<CertifiedKeys>,
 }
 
 impl rustls::server::ResolvesServerCert for CertResolver {
@@ -52,28 +83,118 @@ impl rustls::server::ResolvesServerCert for CertResolver {
         &self,
         client_hello: rustls::server::ClientHello<'_>,
     ) -> Option<std::sync::Arc<rustls::sign::CertifiedKey>> {
-        self.sni_resolver
-            .resolve(client_hello)
-            .or_else(|| self.default_cert.clone())
+        client_hello
+            .server_name()
+            .and_then(|name| self.by_name.get(&name.to_ascii_lowercase()))
+            .or(self.default_cert.as_ref())
+            .and_then(|keys| keys.select(&client_hello))
     }
 }
 
+/// Check that `key` is the private key of the first certificate of the chain,
+/// by verifying a signature made with it.
+///
+/// Returns the algorithm of the key.
+pub(crate) fn check_key_pair(
+    certificate: &[rustls::Certificate],
+    key: &rustls::PrivateKey,
+) -> anyhow::Result<rustls::SignatureAlgorithm> {
+    const MESSAGE: &[u8] = b"vSMTP certificate and private key check";
+
+    let end_entity = certificate
+        .first()
+        .ok_or_else(|| anyhow::anyhow!("the certificate chain is empty"))?;
+    let end_entity = webpki::EndEntityCert::try_from(end_entity.0.as_slice())
+        .map_err(|e| anyhow::anyhow!("the certificate is invalid: {e}"))?;
+
+    let key = rustls::sign::any_supported_type(key)
+        .map_err(|_| anyhow::anyhow!("the private key is not supported"))?;
+    let signer = key
+        .choose_scheme(&[
+            rustls::SignatureScheme::RSA_PKCS1_SHA256,
+            rustls::SignatureScheme::ECDSA_NISTP256_SHA256,
+            rustls::SignatureScheme::ECDSA_NISTP384_SHA384,
+            rustls::SignatureScheme::ED25519,
+        ])
+        .ok_or_else(|| anyhow::anyhow!("the private key is not supported"))?;
+    let algorithm = match signer.scheme() {
+        rustls::SignatureScheme::RSA_PKCS1_SHA256 => &webpki::RSA_PKCS1_2048_8192_SHA256,
+        rustls::SignatureScheme::ECDSA_NISTP256_SHA256 => &webpki::ECDSA_P256_SHA256,
+        rustls::SignatureScheme::ECDSA_NISTP384_SHA384 => &webpki::ECDSA_P384_SHA384,
+        _ => &webpki::ED25519,
+    };
+    let signature = signer
+        .sign(MESSAGE)
+        .map_err(|e| anyhow::anyhow!("the private key cannot sign: {e}"))?;
+
+    end_entity
+        .verify_signature(algorithm, MESSAGE, &signature)
+        .map_err(|_| anyhow::anyhow!("the private key does not match the certificate"))?;
+
+    Ok(key.algorithm())
+}
+
 #[doc(hidden)]
 pub fn get_rustls_config(
     config: &FieldServerTls,
     virtual_entries: &std::collections::BTreeMap<Domain, FieldServerVirtual>,
 ) -> anyhow::Result<rustls::ServerConfig> {
-    fn to_rustls(
-        cert: Vec<rustls::Certificate>,
-        key: &rustls::PrivateKey,
-    ) -> anyhow::Result<rustls::sign::CertifiedKey> {
-        Ok(rustls::sign::CertifiedKey {
-            cert,
-            key: rustls::sign::any_supported_type(key)?,
-            // TODO: support OCSP and SCT
-            ocsp: None,
-            sct_list: None,
-        })
+    build_rustls_config(
+        config,
+        virtual_entries,
+        rustls::server::NoClientAuth::boxed(),
+    )
+}
+
+/// Build the TLS configuration of the listeners requesting a certificate to the
+/// clients, see [`crate::field::FieldServerTlsClientAuth`].
+///
+/// Returns `None` if the authentication of the clients is not configured.
+#[doc(hidden)]
+pub fn get_rustls_config_client_auth(
+    config: &FieldServerTls,
+    virtual_entries: &std::collections::BTreeMap<Domain, FieldServerVirtual>,
+) -> anyhow::Result<Option<rustls::ServerConfig>> {
+    let Some(client_auth) = &config.client_auth else {
+        return Ok(None);
+    };
+
+    let mut trusted_ca = rustls::RootCertStore::empty();
+    for certificate in &client_auth.trusted_ca.inner {
+        trusted_ca.add(certificate).map_err(|e| {
+            anyhow::anyhow!(
+                "cannot add the authority '{}': {e}",
+                client_auth.trusted_ca.path.display()
+            )
+        })?;
+    }
+
+    build_rustls_config(
+        config,
+        virtual_entries,
+        rustls::server::AllowAnyAnonymousOrAuthenticatedClient::new(trusted_ca).boxed(),
+    )
+    .map(Some)
+}
+
+fn build_rustls_config(
+    config: &FieldServerTls,
+    virtual_entries: &std::collections::BTreeMap<Domain, FieldServerVirtual>,
+    client_cert_verifier: std::sync::Arc<dyn rustls::server::ClientCertVerifier>,
+) -> anyhow::Result<rustls::ServerConfig> {
+    fn to_rustls(tls: &FieldServerVirtualTls) -> anyhow::Result<CertifiedKeys> {
+        tls.key_pairs()
+            .map(|(certificate, private_key)| {
+                Ok(std::sync::Arc::new(rustls::sign::CertifiedKey {
+                    cert: certificate.inner.clone(),
+                    key: rustls::sign::any_supported_type(&private_key.inner)?,
+                    // TODO: support OCSP and SCT
+                    ocsp: None,
+                    sct_list: None,
+                }))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()
+            .map(CertifiedKeys)
     }
 
     let protocol_version = match (
@@ -92,25 +213,19 @@ pub fn get_rustls_config(
         (false, false) => anyhow::bail!("requested version is not supported"),
     };
 
-    let mut cert_resolver = rustls::server::ResolvesServerCertUsingSni::new();
-    let virtual_server_with_tls = virtual_entries
+    let mut by_name = std::collections::HashMap::new();
+    for (virtual_name, tls) in virtual_entries
         .iter()
-        .filter_map(|(virtual_name, params)| params.tls.as_ref().map(|tls| (virtual_name, tls)));
-    for (
-        virtual_name,
-        FieldServerVirtualTls {
-            certificate,
-            private_key,
-            ..
-        },
-    ) in virtual_server_with_tls
+        .filter_map(|(virtual_name, params)| params.tls.as_ref().map(|tls| (virtual_name, tls)))
     {
-        cert_resolver
-            .add(
-                &virtual_name.to_string(),
-                to_rustls(certificate.inner.clone(), &private_key.inner.clone())?,
-            )
-            .map_err(|e| anyhow::anyhow!("cannot add sni to resolver '{virtual_name}': {e}"))?;
+        let keys = to_rustls(tls)?;
+        // NOTE: checks that the certificates are valid for the name.
+        for certified in &keys.0 {
+            rustls::server::ResolvesServerCertUsingSni::new()
+                .add(&virtual_name.to_string(), certified.as_ref().clone())
+                .map_err(|e| anyhow::anyhow!("cannot add sni to resolver '{virtual_name}': {e}"))?;
+        }
+        by_name.insert(virtual_name.to_string().to_ascii_lowercase(), keys);
     }
 
     let mut tls_config = rustls::ServerConfig::builder()
@@ -118,21 +233,10 @@ pub fn get_rustls_config(
         .with_kx_groups(&rustls::ALL_KX_GROUPS)
         .with_protocol_versions(protocol_version)
         .map_err(|e| anyhow::anyhow!("cannot initialize tls config: '{e}'"))?
-        // TODO: allow configurable ClientAuth (DANE)
-        .with_client_cert_verifier(rustls::server::NoClientAuth::boxed())
+        .with_client_cert_verifier(client_cert_verifier)
         .with_cert_resolver(std::sync::Arc::new(CertResolver {
-            sni_resolver: cert_resolver,
-            default_cert: config
-                .root
-                .as_ref()
-                .map(|default_tls| {
-                    to_rustls(
-                        default_tls.certificate.inner.clone(),
-                        &default_tls.private_key.inner.clone(),
-                    )
-                })
-                .transpose()?
-                .map(std::sync::Arc::new),
+            by_name,
+            default_cert: config.root.as_ref().map(to_rustls).transpose()?,
         }));
 
     tls_config.ignore_client_order = config.preempt_cipherlist;
@@ -142,3 +246,44 @@ pub fn get_rustls_config(
 
     Ok(tls_config)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{tls_certificate, tls_private_key};
+
+    const CERTS: &str = "../vsmtp-test/src/template/certs";
+
+    fn check(certificate: &str, private_key: &str) -> anyhow::Result<rustls::SignatureAlgorithm> {
+        check_key_pair(
+            &tls_certificate::from_path(&format!("{CERTS}/{certificate}")).unwrap(),
+            &tls_private_key::from_path(&format!("{CERTS}/{private_key}")).unwrap(),
+        )
+    }
+
+    #[test]
+    fn key_pair_match() {
+        assert_eq!(
+            check("certificate.crt", "private_key.rsa.key").unwrap(),
+            rustls::SignatureAlgorithm::RSA
+        );
+        assert_eq!(
+            check("certificate.ec256.crt", "private_key.ec256.key").unwrap(),
+            rustls::SignatureAlgorithm::ECDSA
+        );
+    }
+
+    #[test]
+    fn key_pair_mismatch() {
+        for (certificate, private_key) in [
+            ("certificate.crt", "private_key.ec256.key"),
+            ("certificate.ec256.crt", "private_key.rsa.key"),
+            ("certificate.crt", "client/client.key"),
+        ] {
+            assert_eq!(
+                check(certificate, private_key).unwrap_err().to_string(),
+                "the private key does not match the certificate"
+            );
+        }
+    }
+}
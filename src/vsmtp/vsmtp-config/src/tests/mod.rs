@@ -20,3 +20,4 @@ mod root_example {
     mod simple;
     mod tls;
 }
+mod toml;
@@ -15,7 +15,11 @@
  *
 */
 use crate::{
-    config::field::{FieldQueueDelivery, FieldQueueWorking},
+    config::field::{
+        FieldQueueDelivery, FieldQueueDeliveryCapabilities, FieldQueueDeliveryReuse,
+        FieldQueueDeliveryRouting, FieldQueueDeliverySourceIps, FieldQueueDeliveryThrottle,
+        FieldQueueDeliveryTls, FieldQueueWorking, HelloName, NoRecipientPolicy,
+    },
     Config,
 };
 use vsmtp_common::{collection, Stage};
@@ -43,11 +47,23 @@ fn parse() {
             .with_default_logs_settings()
             .with_spool_dir_and_queues(
                 "/var/spool/vsmtp",
-                FieldQueueWorking { channel_size: 16 },
+                FieldQueueWorking {
+                    channel_size: 16,
+                    direct_delivery: false,
+                    no_recipient: NoRecipientPolicy::Dead,
+                },
                 FieldQueueDelivery {
                     channel_size: 16,
                     deferred_retry_max: 10,
-                    deferred_retry_period: std::time::Duration::from_secs(600)
+                    deferred_retry_period: std::time::Duration::from_secs(600),
+                    eightbitmime_downgrade: true,
+                    throttle: FieldQueueDeliveryThrottle::default(),
+                    source_ips: FieldQueueDeliverySourceIps::default(),
+                    reuse: FieldQueueDeliveryReuse::default(),
+                    capabilities: FieldQueueDeliveryCapabilities::default(),
+                    hello_name: HelloName::default(),
+                    tls: FieldQueueDeliveryTls::default(),
+                    routing: FieldQueueDeliveryRouting::default(),
                 }
             )
             .without_tls_support()
@@ -0,0 +1,87 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2022 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use crate::Config;
+
+#[test]
+fn round_trip() {
+    let config = Config::from_vsl_file(std::path::PathBuf::from_iter([
+        env!("CARGO_MANIFEST_DIR"),
+        "../../../examples/config/simple.vsl",
+    ]))
+    .unwrap();
+
+    let toml = config.to_toml_string().unwrap();
+    // the defaults are filled
+    assert!(toml.contains("line_length_max = 1024"));
+
+    pretty_assertions::assert_eq!(Config::from_toml_str(&toml).unwrap(), config);
+}
+
+#[test]
+fn defaults() {
+    let config = Config::from_toml_str(&format!(
+        r#"
+version_requirement = ">={}"
+
+[server]
+name = "example.com"
+
+[server.smtp]
+rcpt_count_max = 10
+"#,
+        env!("CARGO_PKG_VERSION")
+    ))
+    .unwrap();
+
+    assert_eq!(config.server.name, "example.com".parse().unwrap());
+    assert_eq!(config.server.smtp.rcpt_count_max, 10);
+    assert_eq!(config.server.smtp.line_length_max, 1024);
+    assert!(config.server.esmtp.eightbitmime);
+    assert!(config.path.is_none());
+}
+
+#[test]
+fn errors() {
+    let version = env!("CARGO_PKG_VERSION");
+
+    for (toml, error) in [
+        ("version_requirement = ", "not valid TOML"),
+        (
+            &format!("version_requirement = \">={version}\"\nfoo = 1\n"),
+            "unknown field `foo`",
+        ),
+        (
+            "version_requirement = \"<1.0.0\"\n",
+            "Version requirement not fulfilled",
+        ),
+        (
+            &format!(
+                "version_requirement = \">={version}\"\n[server.esmtp]\neightbitmime = false\nsmtputf8 = true\n"
+            ),
+            "requires `eightbitmime`",
+        ),
+        (
+            &format!(
+                "version_requirement = \">={version}\"\n[server.virtual.\"example.org\"]\ncatch_all = \"all@example.net\"\n"
+            ),
+            "catch-all `all@example.net`",
+        ),
+    ] {
+        let message = Config::from_toml_str(toml).unwrap_err().to_string();
+        assert!(message.contains(error), "{message}");
+    }
+}
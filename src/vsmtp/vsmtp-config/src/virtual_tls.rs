@@ -15,7 +15,7 @@
  *
 */
 use crate::{
-    field::{FieldServerVirtualTls, SecretFile},
+    field::{FieldServerTlsClientAuth, FieldServerTlsKeyPair, FieldServerVirtualTls, SecretFile},
     parser::{tls_certificate, tls_private_key},
 };
 use vsmtp_auth::dkim;
@@ -123,6 +123,66 @@ impl FieldServerVirtualTls {
                 inner: tls_private_key::from_path(private_key)?,
                 path: private_key.into(),
             },
+            alternatives: vec![],
+        })
+    }
+
+    /// add another certificate of the identity, from the certificate & private key paths.
+    ///
+    /// # Errors
+    ///
+    /// * certificate file not found.
+    /// * private key file not found.
+    pub fn with_alternative(
+        mut self,
+        certificate: &str,
+        private_key: &str,
+    ) -> anyhow::Result<Self> {
+        self.alternatives.push(FieldServerTlsKeyPair {
+            certificate: SecretFile::<Vec<rustls::Certificate>> {
+                inner: tls_certificate::from_path(certificate)?,
+                path: certificate.into(),
+            },
+            private_key: SecretFile::<rustls::PrivateKey> {
+                inner: tls_private_key::from_path(private_key)?,
+                path: private_key.into(),
+            },
+        });
+        Ok(self)
+    }
+
+    /// The certificates of the identity with their private key, `certificate` first.
+    #[must_use]
+    pub fn key_pairs(
+        &self,
+    ) -> impl Iterator<
+        Item = (
+            &SecretFile<Vec<rustls::Certificate>>,
+            &SecretFile<rustls::PrivateKey>,
+        ),
+    > {
+        std::iter::once((&self.certificate, &self.private_key)).chain(
+            self.alternatives
+                .iter()
+                .map(|pair| (&pair.certificate, &pair.private_key)),
+        )
+    }
+}
+
+impl FieldServerTlsClientAuth {
+    /// create the authentication of the clients from the certificates of the authorities,
+    /// requesting a certificate on all the listeners.
+    ///
+    /// # Errors
+    ///
+    /// * certificate file not found.
+    pub fn from_path(trusted_ca: &str) -> anyhow::Result<Self> {
+        Ok(Self {
+            trusted_ca: SecretFile::<Vec<rustls::Certificate>> {
+                inner: tls_certificate::from_path(trusted_ca)?,
+                path: trusted_ca.into(),
+            },
+            interfaces: vec![],
         })
     }
 }
@@ -77,6 +77,35 @@ pub enum Commands {
     ConfigShow,
     /// Show the difference between the loaded config and the default one
     ConfigDiff,
+    /// Manage the DKIM keys of the virtual domains
+    Dkim {
+        /// Commands.
+        #[clap(subcommand)]
+        command: DkimCommands,
+    },
+}
+
+///
+#[derive(Debug, clap::Subcommand, PartialEq, Eq)]
+pub enum DkimCommands {
+    /// Generate a key pair under the application directory, and print the DNS record to publish
+    Generate {
+        /// Domain signing the mail.
+        #[clap(long)]
+        domain: vsmtp_common::Domain,
+        /// Name of the selector of the DNS record.
+        #[clap(long)]
+        selector: String,
+        /// Algorithm of the key. (ed25519 or rsa2048)
+        #[clap(long)]
+        algo: vsmtp_auth::dkim::KeyAlgorithm,
+    },
+    /// Check that the DNS records published match the keys of the configured selectors
+    Check {
+        /// Domain signing the mail.
+        #[clap(long)]
+        domain: vsmtp_common::Domain,
+    },
 }
 
 #[cfg(test)]
@@ -141,6 +170,75 @@ mod tests {
             <Args as clap::Parser>::try_parse_from(["", "-c", "path", "config-diff"]).unwrap()
         );
 
+        assert_eq!(
+            Args {
+                version: false,
+                command: Some(Commands::Dkim {
+                    command: DkimCommands::Generate {
+                        domain: "example.com".parse().unwrap(),
+                        selector: "2023-06".to_string(),
+                        algo: vsmtp_auth::dkim::KeyAlgorithm::Rsa2048,
+                    }
+                }),
+                config: "path".to_string(),
+                env: None,
+                no_daemon: false,
+                stdout: false,
+                timeout: None
+            },
+            <Args as clap::Parser>::try_parse_from([
+                "",
+                "-c",
+                "path",
+                "dkim",
+                "generate",
+                "--domain",
+                "example.com",
+                "--selector",
+                "2023-06",
+                "--algo",
+                "rsa2048"
+            ])
+            .unwrap()
+        );
+
+        assert!(<Args as clap::Parser>::try_parse_from([
+            "",
+            "dkim",
+            "generate",
+            "--domain",
+            "example.com",
+            "--selector",
+            "2023-06",
+            "--algo",
+            "rsa1024"
+        ])
+        .is_err());
+
+        assert_eq!(
+            Args {
+                version: false,
+                command: Some(Commands::Dkim {
+                    command: DkimCommands::Check {
+                        domain: "example.com".parse().unwrap(),
+                    }
+                }),
+                config: Args::default_config_location(),
+                env: None,
+                no_daemon: false,
+                stdout: false,
+                timeout: None
+            },
+            <Args as clap::Parser>::try_parse_from([
+                "",
+                "dkim",
+                "check",
+                "--domain",
+                "example.com"
+            ])
+            .unwrap()
+        );
+
         assert_eq!(
             Args {
                 version: true,
@@ -0,0 +1,281 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2023 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+
+//! Management of the DKIM keys of the virtual domains.
+
+use anyhow::Context;
+use vsmtp_auth::dkim::{KeyAlgorithm, PrivateKey, PublicKey};
+use vsmtp_common::Domain;
+use vsmtp_config::{Config, DnsResolvers};
+
+/// Maximum length of one character-string of a `TXT` record (rfc 1035).
+const TXT_STRING_MAX_LENGTH: usize = 255;
+
+fn domain_name(domain: &Domain) -> String {
+    domain.to_ascii().trim_end_matches('.').to_lowercase()
+}
+
+/// Path of the private key of a selector, under the application directory.
+#[must_use]
+pub fn key_path(config: &Config, domain: &Domain, selector: &str) -> std::path::PathBuf {
+    config
+        .app
+        .dirpath
+        .join("dkim")
+        .join(domain_name(domain))
+        .join(format!("{selector}.pem"))
+}
+
+/// Render the zone file entry publishing `record` for the selector,
+/// split in strings of 255 characters at most.
+#[must_use]
+pub fn render_txt_record(domain: &Domain, selector: &str, record: &str) -> String {
+    let name = format!("{selector}._domainkey.{}.", domain_name(domain));
+
+    let strings = record
+        .as_bytes()
+        .chunks(TXT_STRING_MAX_LENGTH)
+        .map(|chunk| format!("\"{}\"", String::from_utf8_lossy(chunk)))
+        .collect::<Vec<_>>();
+
+    match strings.as_slice() {
+        [string] => format!("{name} IN TXT {string}"),
+        strings => format!("{name} IN TXT (\n  {}\n)", strings.join("\n  ")),
+    }
+}
+
+/// Generate a key pair for the selector, write the private key under the application
+/// directory (readable by its owner only), and return the DNS record to publish.
+///
+/// # Errors
+///
+/// * a key already exists for this selector
+/// * the key could not be generated or written
+pub fn generate(
+    config: &Config,
+    domain: &Domain,
+    selector: &str,
+    algorithm: KeyAlgorithm,
+) -> anyhow::Result<(std::path::PathBuf, String)> {
+    use std::io::Write;
+    use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+
+    let path = key_path(config, domain, selector);
+    let directory = path.parent().expect("the key path has a parent directory");
+
+    std::fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(directory)
+        .with_context(|| format!("Cannot create the directory '{}'", directory.display()))?;
+
+    let (private_key, pem) = PrivateKey::generate(algorithm)?;
+
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&path)
+        .and_then(|mut file| file.write_all(pem.as_bytes()))
+        .with_context(|| format!("Cannot write the private key at '{}'", path.display()))?;
+
+    let record = private_key.public_key()?.to_string();
+
+    Ok((path, render_txt_record(domain, selector, &record)))
+}
+
+/// Outcome of the check of one selector.
+#[derive(Debug, PartialEq, Eq)]
+pub enum CheckOutcome {
+    /// The record published matches the local key.
+    Valid,
+    /// No record is published for the selector.
+    Missing,
+    /// The record published does not match the local key.
+    Mismatch,
+    /// The record published could not be parsed.
+    Invalid(String),
+}
+
+impl std::fmt::Display for CheckOutcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Valid => f.write_str("ok"),
+            Self::Missing => f.write_str("no record published"),
+            Self::Mismatch => f.write_str("the record published does not match the local key"),
+            Self::Invalid(reason) => write!(f, "invalid record: {reason}"),
+        }
+    }
+}
+
+/// Compare the records published for a selector with the local key.
+#[must_use]
+pub fn check_records(
+    private_key: &PrivateKey,
+    records: impl IntoIterator<Item = String>,
+) -> CheckOutcome {
+    let local = match private_key.public_key() {
+        Ok(local) => local,
+        Err(error) => return CheckOutcome::Invalid(error.to_string()),
+    };
+
+    let mut outcome = CheckOutcome::Missing;
+    for record in records {
+        match <PublicKey as std::str::FromStr>::from_str(&record) {
+            Ok(published) if published.same_key(&local) => return CheckOutcome::Valid,
+            Ok(_) => outcome = CheckOutcome::Mismatch,
+            Err(error) if outcome == CheckOutcome::Missing => {
+                outcome = CheckOutcome::Invalid(error.to_string());
+            }
+            Err(_) => {}
+        }
+    }
+    outcome
+}
+
+/// Query the records published for each selector of the domain, and compare them
+/// with the local keys. Returns the outcome of each selector.
+///
+/// # Errors
+///
+/// * no selector is configured for the domain
+/// * the DNS resolvers could not be built
+pub fn check(config: &Config, domain: &Domain) -> anyhow::Result<Vec<(String, CheckOutcome)>> {
+    let selectors = config
+        .server
+        .r#virtual
+        .get(domain)
+        .and_then(|r#virtual| r#virtual.dkim.as_ref())
+        .map(|dkim| dkim.selectors.as_slice())
+        .filter(|selectors| !selectors.is_empty())
+        .ok_or_else(|| anyhow::anyhow!("No dkim selector configured for '{domain}'"))?;
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+
+    runtime.block_on(async move {
+        let resolver = DnsResolvers::from_config(config)?.get_resolver_or_root(domain);
+
+        let mut outcomes = vec![];
+        for selector in selectors {
+            let query = format!("{}._domainkey.{}", selector.selector, domain_name(domain));
+            let records = match resolver.txt_lookup(query).await {
+                Ok(records) => records.into_iter().map(|i| i.to_string()).collect(),
+                Err(error) => {
+                    tracing::debug!(%error, "TXT lookup failed");
+                    vec![]
+                }
+            };
+
+            outcomes.push((
+                selector.selector.clone(),
+                check_records(&selector.private_key.inner, records),
+            ));
+        }
+
+        Ok::<_, anyhow::Error>(outcomes)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn domain() -> Domain {
+        "example.com".parse().unwrap()
+    }
+
+    #[test]
+    fn render_ed25519() {
+        let (private_key, _) = PrivateKey::generate(KeyAlgorithm::Ed25519).unwrap();
+        let record = private_key.public_key().unwrap().to_string();
+
+        let rendered = render_txt_record(&domain(), "2023-06", &record);
+        assert_eq!(
+            rendered,
+            format!("2023-06._domainkey.example.com. IN TXT \"{record}\"")
+        );
+        assert!(rendered.contains("\"v=DKIM1; h=sha256; k=ed25519; p="));
+    }
+
+    #[test]
+    fn render_rsa2048() {
+        let (private_key, _) = PrivateKey::generate(KeyAlgorithm::Rsa2048).unwrap();
+        let record = private_key.public_key().unwrap().to_string();
+        assert!(record.len() > TXT_STRING_MAX_LENGTH);
+
+        let rendered = render_txt_record(&domain(), "2023-06", &record);
+        let mut lines = rendered.lines();
+        assert_eq!(
+            lines.next(),
+            Some("2023-06._domainkey.example.com. IN TXT (")
+        );
+        let strings = lines
+            .take_while(|line| *line != ")")
+            .map(|line| line.trim().trim_matches('"'))
+            .collect::<Vec<_>>();
+
+        assert_eq!(strings.len(), 2);
+        assert!(strings.iter().all(|s| s.len() <= TXT_STRING_MAX_LENGTH));
+        assert_eq!(strings.concat(), record);
+    }
+
+    #[test]
+    fn generate_and_check() {
+        let directory = tempfile::tempdir().unwrap();
+        let mut config = Config::default();
+        config.app.dirpath = directory.path().to_path_buf();
+
+        let (path, _) = generate(&config, &domain(), "2023-06", KeyAlgorithm::Ed25519).unwrap();
+        assert_eq!(path, directory.path().join("dkim/example.com/2023-06.pem"));
+        assert_eq!(
+            std::os::unix::fs::PermissionsExt::mode(
+                &std::fs::metadata(&path).unwrap().permissions()
+            ) & 0o777,
+            0o600
+        );
+
+        // never overwrite a key
+        generate(&config, &domain(), "2023-06", KeyAlgorithm::Ed25519).unwrap_err();
+
+        let (private_key, _) = PrivateKey::generate(KeyAlgorithm::Ed25519).unwrap();
+        let (other, _) = PrivateKey::generate(KeyAlgorithm::Ed25519).unwrap();
+        let record = private_key.public_key().unwrap().to_string();
+
+        assert_eq!(check_records(&private_key, vec![]), CheckOutcome::Missing);
+        assert_eq!(
+            check_records(&private_key, vec![record.clone()]),
+            CheckOutcome::Valid
+        );
+        assert_eq!(
+            check_records(&other, vec![record.clone()]),
+            CheckOutcome::Mismatch
+        );
+        assert_eq!(
+            check_records(
+                &private_key,
+                vec![other.public_key().unwrap().to_string(), record]
+            ),
+            CheckOutcome::Valid
+        );
+        assert!(matches!(
+            check_records(&private_key, vec!["v=DKIM1; k=ed25519".to_string()]),
+            CheckOutcome::Invalid(_)
+        ));
+    }
+}
@@ -35,8 +35,10 @@
 )]
 
 mod args;
+pub mod dkim;
+mod policy_events;
 
-pub use args::{Args, Commands};
+pub use args::{Args, Commands, DkimCommands};
 
 // Tokio-tracing systems
 // pub mod tracing_subscriber;
@@ -100,9 +102,28 @@ pub fn init_logs(args: &Args, config: &vsmtp_config::Config) -> anyhow::Result<(
         for i in &config.server.logs.level {
             e = e.add_directive(i.clone());
         }
+        if config.server.logs.policy_events.is_some() {
+            e = e.add_directive(format!("{}=warn", vsmtp_common::policy_event::TARGET).parse()?);
+        }
         e
     });
 
+    let subscriber = subscriber.with(match &config.server.logs.policy_events {
+        Some(filename) => match (
+            filename.parent(),
+            filename.file_name().and_then(std::ffi::OsStr::to_str),
+        ) {
+            (Some(directory), Some(file_name)) => Some(policy_events::Layer::new(
+                tracing_appender::rolling::never(directory, file_name),
+            )),
+            _ => anyhow::bail!(
+                "filepath at '{}' does not have a parent or is not valid",
+                filename.display()
+            ),
+        },
+        None => None,
+    });
+
     #[cfg(feature = "tokio_console")]
     let subscriber = subscriber.with(console_subscriber::spawn());
 
@@ -16,7 +16,7 @@
  */
 use anyhow::Context;
 use clap::{crate_name, crate_version};
-use vsmtp::{Args, Commands};
+use vsmtp::{Args, Commands, DkimCommands};
 use vsmtp_common::libc_abstraction::{daemon, initgroups};
 use vsmtp_config::Config;
 use vsmtp_server::{socket_bind_anyhow, start_runtime};
@@ -78,6 +78,34 @@ fn try_main() -> anyhow::Result<()> {
                 }
                 return Ok(());
             }
+            Commands::Dkim {
+                command:
+                    DkimCommands::Generate {
+                        domain,
+                        selector,
+                        algo,
+                    },
+            } => {
+                let (path, record) = vsmtp::dkim::generate(&config, &domain, &selector, algo)?;
+                println!("Private key written at '{}'", path.display());
+                println!("Publish the following record in the zone of '{domain}':\n{record}");
+                return Ok(());
+            }
+            Commands::Dkim {
+                command: DkimCommands::Check { domain },
+            } => {
+                let outcomes = vsmtp::dkim::check(&config, &domain)?;
+                for (selector, outcome) in &outcomes {
+                    println!("{selector}._domainkey.{domain}: {outcome}");
+                }
+                if outcomes
+                    .iter()
+                    .any(|(_, outcome)| *outcome != vsmtp::dkim::CheckOutcome::Valid)
+                {
+                    anyhow::bail!("the DNS records of '{domain}' do not match the local keys");
+                }
+                return Ok(());
+            }
         }
     }
 
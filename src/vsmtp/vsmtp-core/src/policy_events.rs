@@ -0,0 +1,143 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2023 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+ */
+
+use tracing_subscriber::fmt::MakeWriter;
+use vsmtp_common::policy_event::TARGET;
+
+/// Layer mirroring the events of the policy rejections as JSON lines.
+pub struct Layer<W> {
+    writer: W,
+}
+
+impl<W> Layer<W> {
+    pub const fn new(writer: W) -> Self {
+        Self { writer }
+    }
+}
+
+struct Visitor<'a>(&'a mut serde_json::Map<String, serde_json::Value>);
+
+impl tracing::field::Visit for Visitor<'_> {
+    fn record_str(&mut self, field: &tracing::field::Field, value: &str) {
+        self.0.insert(field.name().to_owned(), value.into());
+    }
+
+    fn record_u64(&mut self, field: &tracing::field::Field, value: u64) {
+        self.0.insert(field.name().to_owned(), value.into());
+    }
+
+    fn record_i64(&mut self, field: &tracing::field::Field, value: i64) {
+        self.0.insert(field.name().to_owned(), value.into());
+    }
+
+    fn record_bool(&mut self, field: &tracing::field::Field, value: bool) {
+        self.0.insert(field.name().to_owned(), value.into());
+    }
+
+    fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+        self.0
+            .insert(field.name().to_owned(), format!("{value:?}").into());
+    }
+}
+
+impl<S, W> tracing_subscriber::Layer<S> for Layer<W>
+where
+    S: tracing::Subscriber,
+    W: for<'a> MakeWriter<'a> + 'static,
+{
+    fn on_event(&self, event: &tracing::Event<'_>, _: tracing_subscriber::layer::Context<'_, S>) {
+        use std::io::Write;
+
+        if event.metadata().target() != TARGET {
+            return;
+        }
+
+        let mut fields = serde_json::Map::new();
+        fields.insert(
+            "timestamp".to_owned(),
+            humantime::format_rfc3339_millis(std::time::SystemTime::now())
+                .to_string()
+                .into(),
+        );
+        event.record(&mut Visitor(&mut fields));
+        fields.remove("message");
+
+        let mut line = serde_json::Value::Object(fields).to_string();
+        line.push('\n');
+        if let Err(error) = self.writer.make_writer().write_all(line.as_bytes()) {
+            eprintln!("failed to write the policy event: {error}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Layer;
+    use tracing_subscriber::layer::SubscriberExt;
+    use vsmtp_common::policy_event::TARGET;
+
+    #[derive(Clone, Default)]
+    struct Captured(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl std::io::Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn json_lines() {
+        let captured = Captured::default();
+        let subscriber = tracing_subscriber::registry().with(Layer::new({
+            let captured = captured.clone();
+            move || captured.clone()
+        }));
+
+        let connection_uuid = "5cf6cb65-5f5a-4b22-a3a5-0f0a6d0ab2f5";
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::warn!("Not a policy event.");
+            tracing::warn!(
+                target: TARGET,
+                kind = "delivery_failed",
+                stage = "delivery",
+                rule = Option::<&str>::None,
+                connection_uuid,
+                reply_code = 550_u16,
+                reason = "550 5.1.1 unknown user",
+                "Policy rejection."
+            );
+        });
+
+        let output = String::from_utf8(captured.0.lock().unwrap().clone()).unwrap();
+        let lines = output.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 1);
+
+        let event = serde_json::from_str::<serde_json::Value>(lines[0]).unwrap();
+        assert_eq!(event["kind"], "delivery_failed");
+        assert_eq!(event["stage"], "delivery");
+        assert_eq!(event["reason"], "550 5.1.1 unknown user");
+        assert_eq!(event["connection_uuid"], connection_uuid);
+        assert_eq!(event["reply_code"], 550);
+        assert!(event["timestamp"].is_string());
+        assert!(event.get("message").is_none());
+        assert!(event.get("rule").is_none());
+    }
+}
@@ -0,0 +1,303 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2023 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use lettre::transport::smtp::{
+    client::AsyncSmtpConnection,
+    extension::{ClientId, Extension},
+};
+use vsmtp_common::clock;
+use vsmtp_config::field::FieldQueueDeliveryCapabilities;
+
+/// Extensions advertised by a server in its reply to `EHLO`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct Capabilities {
+    /// Maximum size of the messages, `0` if the server does not declare it.
+    pub(crate) size: Option<usize>,
+    pub(crate) eightbitmime: bool,
+    pub(crate) smtputf8: bool,
+    pub(crate) dsn: bool,
+}
+
+impl Capabilities {
+    /// Read the extensions in the lines of a reply to `EHLO`, the first one being the greeting.
+    pub(crate) fn parse<'line>(lines: impl Iterator<Item = &'line str>) -> Self {
+        let mut capabilities = Self::default();
+        for line in lines.skip(1) {
+            let mut words = line.split_whitespace();
+            let keyword = words.next().unwrap_or_default().to_ascii_uppercase();
+            match keyword.as_str() {
+                "SIZE" => {
+                    capabilities.size =
+                        Some(words.next().and_then(|max| max.parse().ok()).unwrap_or(0));
+                }
+                "8BITMIME" => capabilities.eightbitmime = true,
+                "SMTPUTF8" => capabilities.smtputf8 = true,
+                "DSN" => capabilities.dsn = true,
+                _ => {}
+            }
+        }
+        capabilities
+    }
+
+    /// The extensions known by `lettre`, read when the connection was opened.
+    fn of_connection(conn: &AsyncSmtpConnection) -> Self {
+        let server_info = conn.server_info();
+        Self {
+            eightbitmime: server_info.supports_feature(Extension::EightBitMime),
+            smtputf8: server_info.supports_feature(Extension::SmtpUtfEight),
+            ..Self::default()
+        }
+    }
+
+    /// Does the connection advertise the same extensions, among the ones known by `lettre`?
+    fn matches(&self, conn: &AsyncSmtpConnection) -> bool {
+        let advertised = Self::of_connection(conn);
+        self.eightbitmime == advertised.eightbitmime && self.smtputf8 == advertised.smtputf8
+    }
+}
+
+struct Entry {
+    capabilities: Capabilities,
+    /// Time of the `EHLO` the extensions were read from.
+    since: time::OffsetDateTime,
+    /// Number of connections which relied on the entry.
+    uses: usize,
+}
+
+/// The host and port of a server, and whether the connection is encrypted: a server
+/// may advertise other extensions after `STARTTLS`.
+type Destination = (String, u16, bool);
+
+/// The extensions of the servers, by destination.
+#[derive(Default)]
+pub(crate) struct Cache {
+    parameters: Option<FieldQueueDeliveryCapabilities>,
+    entries: std::sync::Mutex<alloc::collections::BTreeMap<Destination, Entry>>,
+}
+
+/// Read the extensions advertised by the server with a new `EHLO`.
+///
+/// NOTE: `lettre` only keeps the extensions it knows in the `ServerInfo`, so the
+///       `EHLO` is sent again to read the reply.
+async fn read(conn: &mut AsyncSmtpConnection, hello_name: &ClientId) -> Option<Capabilities> {
+    match conn
+        .command(lettre::transport::smtp::commands::Ehlo::new(
+            hello_name.clone(),
+        ))
+        .await
+    {
+        Ok(response) => Some(Capabilities::parse(response.message())),
+        Err(error) => {
+            tracing::debug!(%error, "Failed to read the extensions of the server.");
+            None
+        }
+    }
+}
+
+impl Cache {
+    /// Create the cache, from the configuration of the delivery.
+    pub(crate) fn new(parameters: &FieldQueueDeliveryCapabilities) -> Self {
+        Self {
+            parameters: Some(FieldQueueDeliveryCapabilities {
+                ttl: parameters.ttl,
+                uses_max: parameters.uses_max,
+            }),
+            entries: std::sync::Mutex::default(),
+        }
+    }
+
+    fn with_entries<R>(
+        &self,
+        f: impl FnOnce(&mut alloc::collections::BTreeMap<Destination, Entry>) -> R,
+    ) -> R {
+        let mut entries = self
+            .entries
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        f(&mut entries)
+    }
+
+    /// The extensions of the server `host:port`, to which `conn` has just been opened:
+    /// from the cache if its entry is still valid, without sending anything, read with
+    /// a new `EHLO` otherwise.
+    ///
+    /// Returns `true` with the extensions if they come from the cache.
+    #[allow(clippy::arithmetic_side_effects, clippy::integer_arithmetic)]
+    pub(crate) async fn get(
+        &self,
+        host: &str,
+        port: u16,
+        conn: &mut AsyncSmtpConnection,
+        hello_name: &ClientId,
+    ) -> (Capabilities, bool) {
+        let now = clock::now();
+        let destination = (host.to_owned(), port, conn.is_encrypted());
+        let cached = self.parameters.as_ref().and_then(|parameters| {
+            self.with_entries(|entries| {
+                let entry = entries.get_mut(&destination)?;
+                if now < entry.since + parameters.ttl
+                    && entry.uses < parameters.uses_max
+                    && entry.capabilities.matches(conn)
+                {
+                    entry.uses = entry.uses.saturating_add(1);
+                    return Some(entry.capabilities);
+                }
+                entries.remove(&destination);
+                None
+            })
+        });
+        if let Some(capabilities) = cached {
+            return (capabilities, true);
+        }
+
+        let Some(capabilities) = read(conn, hello_name).await else {
+            return (Capabilities::of_connection(conn), false);
+        };
+        if self.parameters.is_some() {
+            self.with_entries(|entries| {
+                entries.insert(
+                    destination,
+                    Entry {
+                        capabilities,
+                        since: now,
+                        uses: 1,
+                    },
+                );
+            });
+        }
+        (capabilities, false)
+    }
+
+    /// Remove the entry of the server `host:port` over a connection encrypted or not,
+    /// the server having refused an extension it advertised.
+    pub(crate) fn invalidate(&self, host: &str, port: u16, tls: bool) {
+        self.with_entries(|entries| {
+            entries.remove(&(host.to_owned(), port, tls));
+        });
+    }
+
+    /// Remove the entries of the servers of `domain` and of its sub-domains, returning their
+    /// destinations (`host:port`).
+    pub(crate) fn flush(&self, domain: &str) -> Vec<String> {
+        let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+        let is_of_domain = |(host, _, _): &Destination| {
+            host.trim_end_matches('.')
+                .to_ascii_lowercase()
+                .strip_suffix(&domain)
+                .map_or(false, |sub| sub.is_empty() || sub.ends_with('.'))
+        };
+
+        self.with_entries(|entries| {
+            let flushed = entries
+                .keys()
+                .filter(|destination| is_of_domain(destination))
+                .cloned()
+                .collect::<Vec<_>>();
+            for destination in &flushed {
+                entries.remove(destination);
+            }
+            let mut flushed = flushed
+                .into_iter()
+                .map(|(host, port, _)| format!("{host}:{port}"))
+                .collect::<Vec<_>>();
+            // NOTE: the entries in clear and over TLS of a server are next to each other.
+            flushed.dedup();
+            flushed
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            Capabilities::parse(
+                [
+                    "mx.example.com",
+                    "size 35882577",
+                    "PIPELINING",
+                    "8BITMIME",
+                    "ENHANCEDSTATUSCODES",
+                    "DSN",
+                ]
+                .into_iter()
+            ),
+            Capabilities {
+                size: Some(35_882_577),
+                eightbitmime: true,
+                dsn: true,
+                ..Capabilities::default()
+            }
+        );
+        assert_eq!(
+            Capabilities::parse(["DSN", "SIZE", "SMTPUTF8", "PIPELINING"].into_iter()),
+            Capabilities {
+                size: Some(0),
+                smtputf8: true,
+                ..Capabilities::default()
+            }
+        );
+    }
+
+    fn cache(destinations: &[(&str, u16, bool)]) -> Cache {
+        let cache = Cache::default();
+        cache.with_entries(|entries| {
+            for (host, port, tls) in destinations {
+                entries.insert(
+                    ((*host).to_owned(), *port, *tls),
+                    Entry {
+                        capabilities: Capabilities::default(),
+                        since: clock::now(),
+                        uses: 1,
+                    },
+                );
+            }
+        });
+        cache
+    }
+
+    #[test]
+    fn invalidate_tls() {
+        let cache = cache(&[("mx.example.com", 25, false), ("mx.example.com", 25, true)]);
+
+        cache.invalidate("mx.example.com", 25, true);
+        assert_eq!(
+            cache.with_entries(|entries| entries.keys().cloned().collect::<Vec<_>>()),
+            vec![("mx.example.com".to_owned(), 25, false)]
+        );
+    }
+
+    #[test]
+    fn flush_domain() {
+        let cache = cache(&[
+            ("mx1.flush.example", 25, false),
+            ("mx1.flush.example", 25, true),
+            ("flush.example", 25, true),
+            ("mx.notflush.example", 25, false),
+            ("192.0.2.1", 2525, false),
+        ]);
+
+        assert_eq!(
+            cache.flush("Flush.Example."),
+            vec!["flush.example:25", "mx1.flush.example:25"]
+        );
+        assert_eq!(cache.flush("192.0.2.1"), vec!["192.0.2.1:2525"]);
+        assert_eq!(cache.flush("flush.example"), Vec::<String>::new());
+    }
+}
@@ -14,15 +14,18 @@
  * this program. If not, see https://www.gnu.org/licenses/.
  *
 */
-use crate::{send::SenderParameters, to_lettre_envelope};
+use crate::{
+    send::{apply_rcpt_replies, DsnParameters, RcptReplies, SenderParameters},
+    to_lettre_envelope,
+};
 use trust_dns_resolver::TokioAsyncResolver;
 use vsmtp_common::{
     transfer::{
         error::{Lookup, Variant},
         Status,
     },
-    transport::{AbstractTransport, DeliverTo},
-    Address, ContextFinished, Domain, Target,
+    transport::{AbstractTransport, DeliverTo, TransportStates},
+    Address, ContextFinished, DeliveryStrategy, Domain, Target,
 };
 use vsmtp_config::Config;
 extern crate alloc;
@@ -88,26 +91,20 @@ impl Deliver {
         }
     }
 
-    /// fetch mx records for a specific domain and order them by priority.
-    async fn get_mx_records(
-        &self,
-        query: &str,
-    ) -> Result<
-        Vec<trust_dns_resolver::proto::rr::rdata::MX>,
-        trust_dns_resolver::error::ResolveError,
-    > {
-        let mut records_by_priority = self
+    /// fetch mx records for a specific domain and select the exchangers to try.
+    async fn get_exchangers(&self, domain: &Domain) -> Result<Exchangers, Lookup> {
+        let records = self
             .resolver
-            .mx_lookup(query)
-            .await?
-            .into_iter()
-            .collect::<Vec<_>>();
-        records_by_priority.sort_by_key(trust_dns_resolver::proto::rr::rdata::MX::preference);
-        Ok(records_by_priority)
+            .mx_lookup(domain.clone())
+            .await
+            .map(|lookup| lookup.into_iter().collect::<Vec<_>>());
+
+        Exchangers::from_mx_lookup(domain, records)
     }
 
     async fn deliver_one_domain(
         &self,
+        state: &crate::DeliveryState,
         ctx: &ContextFinished,
         message: &[u8],
         from: &Option<Address>,
@@ -115,13 +112,11 @@ impl Deliver {
         mut rcpt: DeliverTo,
     ) -> DeliverTo {
         match self
-            .deliver_one_domain_inner(ctx, message, from, &domain, &rcpt)
+            .deliver_one_domain_inner(state, ctx, message, from, &domain, &rcpt)
             .await
         {
-            Ok(()) => {
-                for i in &mut rcpt {
-                    i.1 = Status::sent();
-                }
+            Ok((mx, replies)) => {
+                apply_rcpt_replies(&Target::Domain(mx), &mut rcpt, replies);
                 rcpt
             }
             Err(error) => {
@@ -152,79 +147,139 @@ impl Deliver {
 
     async fn deliver_one_domain_inner(
         &self,
+        state: &crate::DeliveryState,
         ctx: &ContextFinished,
         message: &[u8],
         from: &Option<Address>,
         domain: &Domain,
         rcpt: &DeliverTo,
-    ) -> Result<(), Variant> {
+    ) -> Result<(Domain, RcptReplies), Variant> {
         let envelop = to_lettre_envelope(from, rcpt.iter().map(|(r, _)| r))?;
+        let dsn = DsnParameters::new(ctx, rcpt.iter().map(|(r, _)| r));
         tracing::trace!(?envelop);
 
-        let records = self
-            .get_mx_records(&domain.to_string())
-            .await
-            .map_err(Into::<Lookup>::into)?;
-        tracing::trace!(?records);
-
-        if records.is_empty() {
-            // using directly the AAAA record instead of an mx record.
-            // see https://www.rfc-editor.org/rfc/rfc5321#section-5.1
-            tracing::warn!("empty set of MX records found for '{domain}'");
-
-            // get_cert_for_server(&ctx.connect.server_name, &self.config)
-            // .ok_or(TransferErrorsVariant::TlsNoCertificate {})?,
+        let mut mxs = match self.get_exchangers(domain).await? {
+            Exchangers::Implicit => {
+                // using directly the A/AAAA records instead of an mx record.
+                // see https://www.rfc-editor.org/rfc/rfc5321#section-5.1
+                self.resolver
+                    .lookup_ip(domain.clone())
+                    .await
+                    .map_err(Into::<Lookup>::into)?;
+                tracing::info!(%domain, "No MX record, delivering to the address of the domain.");
+
+                vec![domain.clone()]
+            }
+            Exchangers::Mx(mxs) => {
+                tracing::info!(%domain, ?mxs, "Delivering to the MX records by preference.");
+                mxs
+            }
+        };
+
+        let policy = state.tls_policies.resolve(domain);
+        tracing::info!(
+            %domain,
+            starttls = ?policy.starttls,
+            min_tls_version = ?policy.min_version,
+            "Applying the TLS policy of the destination."
+        );
 
-            SenderParameters::from(Target::Domain(domain.clone()))
-                .smtp_send(&ctx.connect.server_name, &envelop, message, None)
+        let mut e = vec![];
+        if ctx.rcpt_to.delivery_strategy == DeliveryStrategy::Race {
+            if let [first, second, ..] = mxs.as_slice() {
+                tracing::debug!(%first, %second, "Racing the two most preferred exchangers.");
+
+                let [first_params, second_params] = [first, second].map(|mx| {
+                    SenderParameters::from(Target::Domain(mx.clone())).with_tls_policy(&policy)
+                });
+                match SenderParameters::smtp_send_race(
+                    state,
+                    [&first_params, &second_params],
+                    &ctx.connect.server_name,
+                    &envelop,
+                    message,
+                    ctx.mail_from.body_type,
+                    ctx.mail_from.utf8,
+                    &dsn,
+                    None,
+                )
                 .await
-                .map_err(|e| Variant::Delivery(vec![(Target::Domain(domain.clone()), e)]))?;
-            return Ok(());
+                {
+                    Ok((index, Ok(replies))) => {
+                        let mx = mxs.remove(index);
+                        tracing::info!(
+                            %mx,
+                            accepted = replies.iter().filter(|reply| reply.is_ok()).count(),
+                            refused = replies.iter().filter(|reply| reply.is_err()).count(),
+                            starttls = ?policy.starttls,
+                            "Email sent to the fastest exchanger"
+                        );
+                        tracing::trace!(%mx, sender = ?from, ?envelop, ?replies);
+
+                        return Ok((mx, replies));
+                    }
+                    // nothing has been sent, the other exchanger has not been tried.
+                    Ok((index, Err(err))) => {
+                        let mx = mxs.remove(index);
+                        tracing::error!(?from, ?mx, %err, "failed to send message");
+                        e.push((Target::Domain(mx), err));
+                    }
+                    Err(errors) => {
+                        for (mx, err) in mxs.drain(..2).zip(errors) {
+                            tracing::error!(?from, ?mx, %err, "failed to connect");
+                            e.push((Target::Domain(mx), err));
+                        }
+                    }
+                }
+                tracing::info!(
+                    remaining = mxs.len(),
+                    "The race did not deliver the message, trying the exchangers left one after the other."
+                );
+            } else {
+                tracing::debug!("A single exchanger, the race is not run.");
+            }
         }
 
-        let mxs = records
-            .iter()
-            .map(trust_dns_resolver::proto::rr::rdata::MX::exchange)
-            .collect::<Vec<_>>();
-
-        let mut e = vec![];
         for mx in mxs {
             tracing::debug!(%mx, "Trying to send an email.");
 
-            // checking for a null mx record.
-            // see https://datatracker.ietf.org/doc/html/rfc7505
-            if mx.is_root() {
-                tracing::error!(
-                    "Trying to deliver to '{domain}', but a null mx record was found. '{domain}' does not want to receive messages."
-                );
-
-                return Err(Lookup::ContainsNullMX {
-                    domain: domain.clone(),
-                }
-                .into());
-            }
-
             // get_cert_for_server(&ctx.connect.server_name, &self.config)
             // .ok_or(TransferErrorsVariant::TlsNoCertificate {})?,
 
-            match SenderParameters::from(Target::Domain((*mx).clone()))
-                .smtp_send(&ctx.connect.server_name, &envelop, message, None)
+            match SenderParameters::from(Target::Domain(mx.clone()))
+                .with_tls_policy(&policy)
+                .smtp_send(
+                    state,
+                    &ctx.connect.server_name,
+                    &envelop,
+                    message,
+                    ctx.mail_from.body_type,
+                    ctx.mail_from.utf8,
+                    &dsn,
+                    None,
+                )
                 .await
             {
-                Ok(response) => {
-                    tracing::info!("Email sent successfully");
-                    tracing::trace!(%mx, sender = ?from, ?envelop, ?response);
+                Ok(replies) => {
+                    tracing::info!(
+                        accepted = replies.iter().filter(|reply| reply.is_ok()).count(),
+                        refused = replies.iter().filter(|reply| reply.is_err()).count(),
+                        starttls = ?policy.starttls,
+                        "Email sent"
+                    );
+                    tracing::trace!(%mx, sender = ?from, ?envelop, ?replies);
 
-                    return Ok(());
+                    return Ok((mx, replies));
                 }
                 Err(err) => {
                     tracing::error!(
                         ?from,
                         ?mx,
+                        starttls = ?policy.starttls,
                         %err,
                         "failed to send message"
                     );
-                    e.push((Target::Domain(mx.clone()), err));
+                    e.push((Target::Domain(mx), err));
                 }
             }
         }
@@ -233,6 +288,64 @@ impl Deliver {
     }
 }
 
+/// The hosts to deliver the messages of a domain to.
+#[derive(Debug, PartialEq, Eq)]
+enum Exchangers {
+    /// The exchangers of the MX records, by preference.
+    Mx(Vec<Domain>),
+    /// The domain has no MX record, it is its own exchanger.
+    Implicit,
+}
+
+impl Exchangers {
+    /// Select the exchangers from the result of the MX lookup of `domain`.
+    ///
+    /// The exchangers of equal preference are shuffled to distribute the load.
+    /// The domain does not receive messages if it does not exist, or if a null MX
+    /// record is published (<https://datatracker.ietf.org/doc/html/rfc7505>).
+    fn from_mx_lookup(
+        domain: &Domain,
+        records: Result<
+            Vec<trust_dns_resolver::proto::rr::rdata::MX>,
+            trust_dns_resolver::error::ResolveError,
+        >,
+    ) -> Result<Self, Lookup> {
+        let mut records = match records.map_err(Lookup::from) {
+            Ok(records) => records,
+            Err(Lookup::NoRecords {}) => vec![],
+            Err(Lookup::DomainNotFound {}) => {
+                tracing::warn!("Trying to deliver to '{domain}', but the domain does not exist.");
+                return Err(Lookup::DomainNotFound {});
+            }
+            Err(error) => return Err(error),
+        };
+
+        if records.is_empty() {
+            return Ok(Self::Implicit);
+        }
+
+        if records.iter().any(|record| record.exchange().is_root()) {
+            tracing::warn!(
+                "Trying to deliver to '{domain}', but a null mx record was found. '{domain}' does not want to receive messages."
+            );
+            return Err(Lookup::ContainsNullMX {
+                domain: domain.clone(),
+            });
+        }
+
+        rand::seq::SliceRandom::shuffle(records.as_mut_slice(), &mut rand::thread_rng());
+        // the sort is stable, the order of equal preferences stays random.
+        records.sort_by_key(trust_dns_resolver::proto::rr::rdata::MX::preference);
+
+        Ok(Self::Mx(
+            records
+                .iter()
+                .map(|record| record.exchange().clone())
+                .collect(),
+        ))
+    }
+}
+
 impl vsmtp_common::transport::GetID for Deliver {}
 
 #[async_trait::async_trait]
@@ -240,6 +353,7 @@ impl AbstractTransport for Deliver {
     #[inline]
     async fn deliver(
         self: alloc::sync::Arc<Self>,
+        states: &TransportStates,
         context: &ContextFinished,
         rcpt_to: DeliverTo,
         message: &[u8],
@@ -252,15 +366,21 @@ impl AbstractTransport for Deliver {
                 .or_insert_with(|| vec![i]);
         }
 
-        let futures = rcpt_by_domain.into_iter().map(|(domain, rcpt)| {
-            self.deliver_one_domain(
-                context,
-                message,
-                &context.mail_from.reverse_path,
-                domain,
-                rcpt,
-            )
-        });
+        let mut fallback = None;
+        let state = crate::DeliveryState::from_states(states, &mut fallback);
+        let transport = &self;
+        let futures = rcpt_by_domain
+            .into_iter()
+            .flat_map(|(domain, rcpt)| {
+                crate::by_reverse_path(context, rcpt)
+                    .into_iter()
+                    .map(move |(from, rcpt)| (domain.clone(), from, rcpt))
+            })
+            .map(|(domain, from, rcpt)| async move {
+                transport
+                    .deliver_one_domain(state, context, message, &from, domain, rcpt)
+                    .await
+            });
 
         futures_util::future::join_all(futures)
             .await
@@ -276,6 +396,8 @@ mod test {
     use crate::deliver::Deliver;
     use trust_dns_resolver::{
         config::{ResolverConfig, ResolverOpts},
+        error::{ResolveError, ResolveErrorKind},
+        proto::{op::ResponseCode, rr::rdata::MX},
         TokioAsyncResolver,
     };
     use vsmtp_common::{
@@ -299,6 +421,8 @@ mod test {
         ));
         let updated_rcpt = alloc::sync::Arc::clone(&transport)
             .deliver(
+                &TransportStates::default()
+                    .with(alloc::sync::Arc::new(crate::DeliveryState::default())),
                 &ctx,
                 vec![(vsmtp_common::addr!("root@foo.bar"), Status::default())],
                 msg.inner().to_string().as_bytes(),
@@ -307,14 +431,99 @@ mod test {
 
         #[allow(clippy::wildcard_enum_match_arm)]
         match &updated_rcpt.first().unwrap().1 {
-            Status::HeldBack { errors } => assert_eq!(
-                *errors.first().unwrap().variant(),
-                Variant::Lookup(Lookup::NoRecords {})
-            ),
+            Status::Failed { error } => {
+                assert_eq!(*error.variant(), Variant::Lookup(Lookup::DomainNotFound {}))
+            }
             _ => panic!(),
         }
     }
 
+    fn domain() -> Domain {
+        "example.com".parse().unwrap()
+    }
+
+    fn mx(preference: u16, exchange: &str) -> MX {
+        MX::new(preference, exchange.parse().unwrap())
+    }
+
+    fn no_records(response_code: ResponseCode) -> ResolveError {
+        ResolveError::from(ResolveErrorKind::NoRecordsFound {
+            query: Box::default(),
+            soa: None,
+            negative_ttl: None,
+            response_code,
+            trusted: true,
+        })
+    }
+
+    #[test]
+    fn null_mx() {
+        let error = Exchangers::from_mx_lookup(&domain(), Ok(vec![mx(0, ".")])).unwrap_err();
+
+        assert_eq!(error, Lookup::ContainsNullMX { domain: domain() });
+        assert!(Variant::Lookup(error).is_permanent());
+    }
+
+    #[test]
+    fn domain_not_found() {
+        let error = Exchangers::from_mx_lookup(&domain(), Err(no_records(ResponseCode::NXDomain)))
+            .unwrap_err();
+
+        assert_eq!(error, Lookup::DomainNotFound {});
+        assert!(Variant::Lookup(error).is_permanent());
+    }
+
+    #[test]
+    fn server_failure() {
+        let error = Exchangers::from_mx_lookup(&domain(), Err(no_records(ResponseCode::ServFail)))
+            .unwrap_err();
+
+        assert!(matches!(error, Lookup::ServerFailure(_)));
+        assert!(!Variant::Lookup(error).is_permanent());
+
+        let error = Exchangers::from_mx_lookup(
+            &domain(),
+            Err(ResolveError::from(ResolveErrorKind::Timeout)),
+        )
+        .unwrap_err();
+        assert!(!Variant::Lookup(error).is_permanent());
+    }
+
+    #[test]
+    fn no_mx() {
+        assert_eq!(
+            Exchangers::from_mx_lookup(&domain(), Err(no_records(ResponseCode::NoError))),
+            Ok(Exchangers::Implicit)
+        );
+        assert_eq!(
+            Exchangers::from_mx_lookup(&domain(), Ok(vec![])),
+            Ok(Exchangers::Implicit)
+        );
+    }
+
+    #[test]
+    fn equal_preferences() {
+        let records = vec![mx(20, "backup.com"), mx(10, "a.com"), mx(10, "b.com")];
+
+        let a_first = (0_i32..1000_i32)
+            .filter(|_| {
+                let Ok(Exchangers::Mx(mxs)) =
+                    Exchangers::from_mx_lookup(&domain(), Ok(records.clone()))
+                else {
+                    panic!()
+                };
+                let mxs = mxs.iter().map(ToString::to_string).collect::<Vec<_>>();
+
+                assert_eq!(mxs.len(), 3);
+                assert_eq!(mxs.last().unwrap(), "backup.com");
+                mxs.first().unwrap() == "a.com"
+            })
+            .count();
+
+        // both orders of the equal preferences are used.
+        assert!((350..650).contains(&a_first), "{a_first}");
+    }
+
     #[rstest::rstest]
     #[case(
         &serde_json::json!({
@@ -0,0 +1,392 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2022 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use base64::Engine;
+extern crate alloc;
+
+/// Maximum length of a line of base64, excluding the CRLF.
+const BASE64_LINE_LENGTH: usize = 76;
+
+/// Re-encode the 8-bit MIME parts of a message, so that it can be relayed to a
+/// server not supporting 8BITMIME.
+/// See <https://www.rfc-editor.org/rfc/rfc6152#section-3>.
+///
+/// The structure of the message is preserved: only the body of the leaf parts
+/// containing 8-bit data, and their `Content-Transfer-Encoding` header, are modified.
+/// Textual parts are encoded in quoted-printable, the others in base64.
+///
+/// 8-bit data in the headers are left as is.
+pub(crate) fn to_seven_bit(message: &str) -> alloc::borrow::Cow<'_, str> {
+    let output = downgrade_entity(message);
+
+    let alloc::borrow::Cow::Owned(output) = output else {
+        return output;
+    };
+
+    let (headers, _) = split_entity(&output).unwrap_or((&output, ""));
+    if header_fields(headers)
+        .iter()
+        .any(|field| field_name(field).eq_ignore_ascii_case("MIME-Version"))
+    {
+        alloc::borrow::Cow::Owned(output)
+    } else {
+        alloc::borrow::Cow::Owned(format!("MIME-Version: 1.0\r\n{output}"))
+    }
+}
+
+/// Split an entity in its headers (including the line break of the last one) and its body.
+#[allow(
+    clippy::indexing_slicing,
+    clippy::string_slice,
+    clippy::arithmetic_side_effects,
+    clippy::integer_arithmetic
+)]
+fn split_entity(entity: &str) -> Option<(&str, &str)> {
+    if let Some(body) = entity.strip_prefix("\r\n") {
+        return Some(("", body));
+    }
+    entity
+        .find("\r\n\r\n")
+        .map(|index| (&entity[..index + 2], &entity[index + 4..]))
+}
+
+/// Split the headers in fields, folded lines included.
+#[allow(
+    clippy::indexing_slicing,
+    clippy::string_slice,
+    clippy::arithmetic_side_effects,
+    clippy::integer_arithmetic
+)]
+fn header_fields(headers: &str) -> Vec<&str> {
+    let mut fields = Vec::<&str>::new();
+    let mut start = 0;
+
+    for (index, _) in headers.match_indices('\n') {
+        let next = index + 1;
+        if !headers[next..].starts_with([' ', '\t']) {
+            fields.push(&headers[start..next]);
+            start = next;
+        }
+    }
+    if start < headers.len() {
+        fields.push(&headers[start..]);
+    }
+
+    fields
+}
+
+fn field_name(field: &str) -> &str {
+    field.split_once(':').map_or("", |(name, _)| name.trim())
+}
+
+fn field_value(field: &str) -> String {
+    field
+        .split_once(':')
+        .map_or("", |(_, value)| value)
+        .replace(['\r', '\n'], "")
+        .trim()
+        .to_owned()
+}
+
+/// Get the mime type (lowercase) and the boundary of an entity.
+/// An entity without `Content-Type` is `text/plain`.
+fn content_type(fields: &[&str]) -> (String, Option<String>) {
+    let Some(value) = fields
+        .iter()
+        .find(|field| field_name(field).eq_ignore_ascii_case("Content-Type"))
+        .map(|field| field_value(field))
+    else {
+        return ("text/plain".to_owned(), None);
+    };
+
+    let mut params = value.split(';');
+    let mime = params
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    let boundary = params
+        .filter_map(|param| param.split_once('='))
+        .find(|(key, _)| key.trim().eq_ignore_ascii_case("boundary"))
+        .map(|(_, value)| value.trim().trim_matches('"').to_owned());
+
+    (mime, boundary)
+}
+
+fn downgrade_entity(entity: &str) -> alloc::borrow::Cow<'_, str> {
+    if entity.is_ascii() {
+        return alloc::borrow::Cow::Borrowed(entity);
+    }
+    let Some((headers, body)) = split_entity(entity) else {
+        return alloc::borrow::Cow::Borrowed(entity);
+    };
+
+    let fields = header_fields(headers);
+    match content_type(&fields) {
+        (mime, Some(boundary)) if mime.starts_with("multipart/") => alloc::borrow::Cow::Owned(
+            format!("{headers}\r\n{}", downgrade_multipart(body, &boundary)),
+        ),
+        (mime, _) if mime == "message/rfc822" => {
+            alloc::borrow::Cow::Owned(format!("{headers}\r\n{}", downgrade_entity(body)))
+        }
+        _ if body.is_ascii() => alloc::borrow::Cow::Borrowed(entity),
+        (mime, _) => alloc::borrow::Cow::Owned(encode_leaf(&fields, &mime, body)),
+    }
+}
+
+/// Downgrade each part of a multipart body, the preamble, the delimiters
+/// and the epilogue are kept as is.
+#[allow(
+    clippy::indexing_slicing,
+    clippy::string_slice,
+    clippy::arithmetic_side_effects,
+    clippy::integer_arithmetic
+)]
+fn downgrade_multipart(body: &str, boundary: &str) -> String {
+    let delimiter = format!("--{boundary}");
+
+    let mut out = String::with_capacity(body.len());
+    let mut copied = 0;
+    let mut part_start = None;
+    let mut offset = 0;
+
+    for line in body.split_inclusive('\n') {
+        let trimmed = line.trim_end();
+        let is_close = trimmed.strip_prefix(delimiter.as_str()) == Some("--");
+
+        if trimmed == delimiter || is_close {
+            if let Some(start) = part_start.take() {
+                // the line break preceding the delimiter belongs to it
+                let preceding = &body[..offset];
+                let end = preceding
+                    .strip_suffix("\r\n")
+                    .or_else(|| preceding.strip_suffix('\n'))
+                    .map_or(offset, str::len)
+                    .max(start);
+
+                out.push_str(&body[copied..start]);
+                out.push_str(&downgrade_entity(&body[start..end]));
+                copied = end;
+            }
+            if is_close {
+                break;
+            }
+            part_start = Some(offset + line.len());
+        }
+        offset += line.len();
+    }
+
+    // the closing delimiter is missing, the last part goes up to the end of the body
+    if let Some(start) = part_start {
+        out.push_str(&body[copied..start]);
+        out.push_str(&downgrade_entity(&body[start..]));
+        copied = body.len();
+    }
+    out.push_str(&body[copied..]);
+
+    out
+}
+
+/// Encode the body of a leaf part, and set its `Content-Transfer-Encoding` accordingly.
+fn encode_leaf(fields: &[&str], mime: &str, body: &str) -> String {
+    let (encoding, body) = if mime.starts_with("text/") {
+        ("quoted-printable", quoted_printable::encode_to_str(body))
+    } else {
+        let encoded = base64::engine::general_purpose::STANDARD.encode(body);
+        let mut lines = encoded
+            .as_bytes()
+            .chunks(BASE64_LINE_LENGTH)
+            .map(|chunk| {
+                #[allow(clippy::expect_used)]
+                core::str::from_utf8(chunk).expect("base64 is ascii")
+            })
+            .collect::<Vec<_>>()
+            .join("\r\n");
+        if body.ends_with('\n') {
+            lines.push_str("\r\n");
+        }
+        ("base64", lines)
+    };
+    let header = format!("Content-Transfer-Encoding: {encoding}\r\n");
+
+    let mut headers = String::new();
+    let mut replaced = false;
+    for field in fields {
+        if field_name(field).eq_ignore_ascii_case("Content-Transfer-Encoding") {
+            if !replaced {
+                headers.push_str(&header);
+                replaced = true;
+            }
+        } else {
+            headers.push_str(field);
+        }
+    }
+    if !replaced {
+        headers.push_str(&header);
+    }
+
+    format!("{headers}\r\n{body}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode_qp(input: &str) -> String {
+        String::from_utf8(
+            quoted_printable::decode(input, quoted_printable::ParseMode::Strict).unwrap(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn ascii_untouched() {
+        let message = "From: john@doe\r\nSubject: hello\r\n\r\nhello world\r\n";
+        assert!(matches!(
+            to_seven_bit(message),
+            alloc::borrow::Cow::Borrowed(output) if output == message
+        ));
+    }
+
+    #[test]
+    fn single_part() {
+        let body = "Bonjour, ça va ? Très bien, à bientôt.\r\n";
+        let message = format!(
+            "From: john@doe\r\nMIME-Version: 1.0\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Transfer-Encoding: 8bit\r\n\r\n{body}"
+        );
+
+        let output = to_seven_bit(&message);
+        assert!(output.is_ascii());
+
+        let (headers, encoded) = split_entity(&output).unwrap();
+        assert_eq!(
+            headers,
+            "From: john@doe\r\nMIME-Version: 1.0\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Transfer-Encoding: quoted-printable\r\n"
+        );
+        assert_eq!(decode_qp(encoded), body);
+    }
+
+    #[test]
+    fn without_mime_headers() {
+        let message = "From: john@doe\r\n\r\nça va ?\r\n";
+
+        assert_eq!(
+            to_seven_bit(message),
+            "MIME-Version: 1.0\r\nFrom: john@doe\r\nContent-Transfer-Encoding: quoted-printable\r\n\r\n=C3=A7a va ?\r\n"
+        );
+    }
+
+    #[test]
+    fn multipart() {
+        let text = "Voilà le fichier.";
+        let binary = "données\u{00ff}";
+        let message = [
+            "From: john@doe\r\n",
+            "MIME-Version: 1.0\r\n",
+            "Content-Type: multipart/mixed;\r\n",
+            "  boundary=\"outer\"\r\n",
+            "\r\n",
+            "This is the preamble.\r\n",
+            "--outer\r\n",
+            "Content-Type: text/plain; charset=us-ascii\r\n",
+            "\r\n",
+            "plain ascii\r\n",
+            "--outer\r\n",
+            "Content-Type: multipart/alternative; boundary=inner\r\n",
+            "\r\n",
+            "--inner\r\n",
+            "Content-Type: text/plain; charset=utf-8\r\n",
+            "Content-Transfer-Encoding: 8bit\r\n",
+            "\r\n",
+            text,
+            "\r\n--inner--\r\n",
+            "--outer\r\n",
+            "Content-Type: application/octet-stream\r\n",
+            "\r\n",
+            binary,
+            "\r\n--outer--\r\n",
+            "This is the epilogue.\r\n",
+        ]
+        .concat();
+
+        let output = to_seven_bit(&message);
+        assert!(output.is_ascii());
+
+        let (headers, body) = split_entity(&output).unwrap();
+        assert!(message.starts_with(headers));
+
+        let parts = body.split("\r\n--outer").collect::<Vec<_>>();
+        assert_eq!(
+            parts[..2],
+            [
+                "This is the preamble.",
+                "\r\nContent-Type: text/plain; charset=us-ascii\r\n\r\nplain ascii"
+            ]
+        );
+        assert_eq!(parts[4], "--\r\nThis is the epilogue.\r\n");
+
+        let inner = parts[2].split("\r\n--inner").collect::<Vec<_>>();
+        assert_eq!(
+            inner[0],
+            "\r\nContent-Type: multipart/alternative; boundary=inner\r\n"
+        );
+        let (headers, encoded) = split_entity(inner[1].trim_start()).unwrap();
+        assert_eq!(
+            headers,
+            "Content-Type: text/plain; charset=utf-8\r\nContent-Transfer-Encoding: quoted-printable\r\n"
+        );
+        assert_eq!(decode_qp(encoded), text);
+
+        let (headers, encoded) = split_entity(parts[3].trim_start()).unwrap();
+        assert_eq!(
+            headers,
+            "Content-Type: application/octet-stream\r\nContent-Transfer-Encoding: base64\r\n"
+        );
+        assert_eq!(
+            base64::engine::general_purpose::STANDARD
+                .decode(encoded)
+                .unwrap(),
+            binary.as_bytes()
+        );
+    }
+
+    #[test]
+    fn base64_wrapped() {
+        let body = format!("{}\r\n", "\u{00ff}données binaires ".repeat(20));
+        let message = format!("Content-Type: application/octet-stream\r\n\r\n{body}");
+
+        let output = to_seven_bit(&message);
+        assert!(output.is_ascii());
+
+        let (_, encoded) = split_entity(&output).unwrap();
+        let lines = encoded
+            .strip_suffix("\r\n")
+            .unwrap()
+            .split("\r\n")
+            .collect::<Vec<_>>();
+        assert!(lines.len() > 1);
+        let (last, full) = lines.split_last().unwrap();
+        assert!(full.iter().all(|line| line.len() == BASE64_LINE_LENGTH));
+        assert!(!last.is_empty() && last.len() <= BASE64_LINE_LENGTH);
+
+        assert_eq!(
+            base64::engine::general_purpose::STANDARD
+                .decode(lines.concat())
+                .unwrap(),
+            body.as_bytes()
+        );
+    }
+}
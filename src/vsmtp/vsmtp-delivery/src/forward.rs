@@ -14,10 +14,13 @@
  * this program. If not, see https://www.gnu.org/licenses/.
  *
 */
-use crate::{send::SenderParameters, to_lettre_envelope};
+use crate::{
+    send::{apply_rcpt_replies, DsnParameters, RcptReplies, SenderParameters},
+    to_lettre_envelope,
+};
 use vsmtp_common::{
     transfer::{error::Variant, Status},
-    transport::{AbstractTransport, DeliverTo},
+    transport::{AbstractTransport, DeliverTo, TransportStates},
     Address, ContextFinished,
 };
 extern crate alloc;
@@ -66,23 +69,38 @@ impl Forward {
 
     async fn deliver_inner(
         &self,
+        state: &crate::DeliveryState,
         ctx: &ContextFinished,
         from: &Option<Address>,
         to: &DeliverTo,
         message: &[u8],
-    ) -> Result<lettre::transport::smtp::response::Response, Variant> {
+    ) -> Result<RcptReplies, Variant> {
         let envelop = to_lettre_envelope(from, to.iter().map(|(rcpt, _)| rcpt))?;
 
-        tracing::debug!(?self.payload.params, "Forwarding email.");
+        // NOTE: the server is chosen by the rules, the policies of the recipients can only
+        //       require its use of TLS.
+        let policy = state
+            .tls_policies
+            .resolve_strictest(to.iter().map(|(rcpt, _)| rcpt.domain_ref()));
+        let params = self.payload.params.clone().with_required_tls(&policy);
+        tracing::debug!(?params, starttls = ?policy.starttls, "Forwarding email.");
 
         //  get_cert_for_server(&ctx.connect.server_name, &self.config)
         //  .ok_or(TransferErrorsVariant::TlsNoCertificate {})?;
 
-        self.payload
-            .params
-            .smtp_send(&ctx.connect.server_name, &envelop, message, None)
+        params
+            .smtp_send(
+                state,
+                &ctx.connect.server_name,
+                &envelop,
+                message,
+                ctx.mail_from.body_type,
+                ctx.mail_from.utf8,
+                &DsnParameters::new(ctx, to.iter().map(|(rcpt, _)| rcpt)),
+                None,
+            )
             .await
-            .map_err(|e| Variant::Delivery(vec![(self.payload.params.host.clone(), e)]))
+            .map_err(|e| Variant::Delivery(vec![(params.host.clone(), e)]))
     }
 }
 
@@ -93,37 +111,41 @@ impl AbstractTransport for Forward {
     #[tracing::instrument(name = "forward", skip_all)]
     async fn deliver(
         self: alloc::sync::Arc<Self>,
+        states: &TransportStates,
         ctx: &ContextFinished,
-        mut to: DeliverTo,
+        to: DeliverTo,
         message: &[u8],
     ) -> DeliverTo {
-        match self
-            .deliver_inner(ctx, &ctx.mail_from.reverse_path, &to, message)
-            .await
-        {
-            Ok(code) => {
-                tracing::info!("Email delivered.");
-                tracing::debug!(?code);
+        let mut fallback = None;
+        let state = crate::DeliveryState::from_states(states, &mut fallback);
+        let mut delivered = DeliverTo::with_capacity(to.len());
 
-                for i in &mut to {
-                    i.1 = Status::sent();
+        // NOTE: one transaction by reverse path, in as many sessions.
+        for (from, mut to) in crate::by_reverse_path(ctx, to) {
+            match self.deliver_inner(state, ctx, &from, &to, message).await {
+                Ok(replies) => {
+                    tracing::info!("Email delivered.");
+                    tracing::debug!(?replies);
+
+                    apply_rcpt_replies(&self.payload.params.host, &mut to, replies);
                 }
-            }
-            Err(error) => {
-                tracing::error!(%error, "Email delivery failure.");
+                Err(error) => {
+                    tracing::error!(%error, "Email delivery failure.");
 
-                let is_permanent = error.is_permanent();
+                    let is_permanent = error.is_permanent();
 
-                for i in &mut to {
-                    if is_permanent {
-                        i.1 = Status::failed(error.clone());
-                    } else {
-                        i.1.held_back(error.clone());
+                    for i in &mut to {
+                        if is_permanent {
+                            i.1 = Status::failed(error.clone());
+                        } else {
+                            i.1.held_back(error.clone());
+                        }
                     }
                 }
             }
+            delivered.extend(to);
         }
-        to
+        delivered
     }
 }
 
@@ -137,7 +159,10 @@ mod tests {
         },
         transport::{AbstractTransport, WrapperSerde},
     };
-    use vsmtp_test::config::{local_ctx, local_msg};
+    use vsmtp_test::{
+        config::{local_ctx, local_msg},
+        sink::Sink,
+    };
 
     #[test_log::test(tokio::test)]
     async fn forward() {
@@ -149,6 +174,8 @@ mod tests {
         let transport = alloc::sync::Arc::new(Forward::new(target));
         let updated_rcpt = alloc::sync::Arc::clone(&transport)
             .deliver(
+                &TransportStates::default()
+                    .with(alloc::sync::Arc::new(crate::DeliveryState::default())),
                 &ctx,
                 vec![("root@localhost".parse().unwrap(), Status::default())],
                 msg.inner().to_string().as_bytes(),
@@ -170,6 +197,78 @@ mod tests {
         }
     }
 
+    #[test_log::test(tokio::test)]
+    async fn transaction_by_reverse_path() {
+        let sink = Sink::start();
+
+        let mut ctx = local_ctx();
+        for rcpt in ["a", "b", "c"] {
+            ctx.rcpt_to.reverse_paths.insert(
+                format!("{rcpt}@example.com").parse().unwrap(),
+                format!("bounce+{rcpt}=example.com@lists.example.com")
+                    .parse()
+                    .unwrap(),
+            );
+        }
+        // the same reverse path as the message, in the same transaction.
+        ctx.rcpt_to.reverse_paths.insert(
+            "d@example.com".parse().unwrap(),
+            ctx.mail_from.reverse_path.clone().unwrap(),
+        );
+
+        let transport = alloc::sync::Arc::new(Forward::new(SenderParameters {
+            port: sink.port(),
+            tls: crate::TlsPolicy::None,
+            ..SenderParameters::from(vsmtp_common::Target::Ip("127.0.0.1".parse().unwrap()))
+        }));
+        let to = ["a", "b", "c", "d", "e"]
+            .map(|rcpt| {
+                (
+                    format!("{rcpt}@example.com").parse().unwrap(),
+                    Status::default(),
+                )
+            })
+            .to_vec();
+        let delivered = transport
+            .deliver(
+                &TransportStates::default()
+                    .with(alloc::sync::Arc::new(crate::DeliveryState::default())),
+                &ctx,
+                to,
+                local_msg().inner().to_string().as_bytes(),
+            )
+            .await;
+
+        assert_eq!(delivered.len(), 5);
+        assert!(delivered
+            .iter()
+            .all(|(_, status)| matches!(status, Status::Sent { .. })));
+        assert_eq!(
+            sink.wait_for(4)
+                .into_iter()
+                .map(|transaction| (transaction.mail_from, transaction.rcpt_to))
+                .collect::<Vec<_>>(),
+            vec![
+                (
+                    "<bounce+a=example.com@lists.example.com>".to_owned(),
+                    vec!["<a@example.com>".to_owned()]
+                ),
+                (
+                    "<bounce+b=example.com@lists.example.com>".to_owned(),
+                    vec!["<b@example.com>".to_owned()]
+                ),
+                (
+                    "<bounce+c=example.com@lists.example.com>".to_owned(),
+                    vec!["<c@example.com>".to_owned()]
+                ),
+                (
+                    "<client@testserver.com>".to_owned(),
+                    vec!["<d@example.com>".to_owned(), "<e@example.com>".to_owned()]
+                ),
+            ]
+        );
+    }
+
     #[rstest::rstest]
     #[case(
         &serde_json::json!({
@@ -0,0 +1,118 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2023 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use vsmtp_common::Domain;
+use vsmtp_config::{field::HelloName, Config};
+
+/// The policies of the name sent in the `EHLO` of the outgoing connections.
+pub(crate) struct Policies {
+    policy: HelloName,
+    virtual_policies: alloc::collections::BTreeMap<Domain, HelloName>,
+}
+
+impl Default for Policies {
+    fn default() -> Self {
+        Self {
+            policy: HelloName::ServerName,
+            virtual_policies: alloc::collections::BTreeMap::new(),
+        }
+    }
+}
+
+impl Policies {
+    /// Read the policies of the server and of the virtual entries, from the configuration.
+    pub(crate) fn new(config: &Config) -> Self {
+        Self {
+            policy: config.server.queues.delivery.hello_name.clone(),
+            virtual_policies: config
+                .server
+                .r#virtual
+                .iter()
+                .filter_map(|(domain, entry)| {
+                    entry
+                        .hello_name
+                        .as_ref()
+                        .map(|policy| (domain.clone(), policy.clone()))
+                })
+                .collect(),
+        }
+    }
+
+    /// The name to send in the `EHLO` of a connection opened for a message sent by
+    /// `sender_domain`, following the policy of its virtual entry if any, or else the
+    /// policy of the server.
+    ///
+    /// `server_name` is the name of the server which received the message.
+    pub(crate) fn resolve(&self, sender_domain: Option<&Domain>, server_name: &Domain) -> Domain {
+        let policy = sender_domain
+            .and_then(|domain| self.virtual_policies.get(domain))
+            .unwrap_or(&self.policy);
+
+        match policy {
+            HelloName::ServerName => server_name.clone(),
+            HelloName::SenderDomain => sender_domain.unwrap_or(server_name).clone(),
+            HelloName::Name(name) => name.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn domain(name: &str) -> Domain {
+        name.parse().unwrap()
+    }
+
+    #[test]
+    fn policies() {
+        let server_name = domain("mx.example.com");
+        let mut config = Config::default();
+        config.server.queues.delivery.hello_name = HelloName::SenderDomain;
+        config.server.r#virtual.insert(
+            domain("brand.example"),
+            vsmtp_config::field::FieldServerVirtual {
+                hello_name: Some(HelloName::Name(domain("out.brand.example"))),
+                ..Default::default()
+            },
+        );
+        config.server.r#virtual.insert(
+            domain("other.example"),
+            vsmtp_config::field::FieldServerVirtual {
+                hello_name: Some(HelloName::ServerName),
+                ..Default::default()
+            },
+        );
+        let policies = Policies::new(&config);
+        let resolve = |sender_domain: Option<&Domain>, server_name: &Domain| {
+            policies.resolve(sender_domain, server_name)
+        };
+
+        assert_eq!(
+            resolve(Some(&domain("example.com")), &server_name),
+            domain("example.com")
+        );
+        assert_eq!(resolve(None, &server_name), domain("mx.example.com"));
+        assert_eq!(
+            resolve(Some(&domain("brand.example")), &server_name),
+            domain("out.brand.example")
+        );
+        assert_eq!(
+            resolve(Some(&domain("other.example")), &server_name),
+            domain("mx.example.com")
+        );
+    }
+}
@@ -53,10 +53,20 @@
     )
 )]
 
+mod capabilities;
+mod downgrade;
+mod hello_name;
+mod reuse;
 mod send;
+mod source_ips;
+mod state;
+mod throttle;
+mod tls_policy;
 
 pub use send::{split_and_sort_and_send, SenderOutcome, SenderParameters, TlsPolicy};
-use vsmtp_common::{transfer::error::Envelop, Address};
+pub use source_ips::SourceIpPool;
+pub use state::DeliveryState;
+use vsmtp_common::{transfer::error::Envelop, transport::DeliverTo, Address, ContextFinished};
 extern crate alloc;
 
 mod dns {
@@ -116,6 +126,26 @@ fn to_lettre_envelope<'item>(
     }
 }
 
+/// Group the recipients by the reverse path of their transaction, as SMTP has one
+/// per transaction: the one set by the rules for the recipient, or the one of the message.
+fn by_reverse_path(ctx: &ContextFinished, rcpt: DeliverTo) -> Vec<(Option<Address>, DeliverTo)> {
+    let mut groups = Vec::<(Option<Address>, DeliverTo)>::new();
+    for (forward_path, status) in rcpt {
+        let reverse_path = ctx
+            .rcpt_to
+            .reverse_paths
+            .get(&forward_path)
+            .cloned()
+            .or_else(|| ctx.mail_from.reverse_path.clone());
+
+        match groups.iter_mut().find(|(from, _)| *from == reverse_path) {
+            Some((_, group)) => group.push((forward_path, status)),
+            None => groups.push((reverse_path, vec![(forward_path, status)])),
+        }
+    }
+    groups
+}
+
 /*
 fn get_cert_for_server(server_name: &Domain, config: &Config) -> Option<Vec<rustls::Certificate>> {
     config
@@ -18,7 +18,7 @@ use anyhow::Context;
 use vsmtp_common::{
     libc_abstraction::{chown, getpwuid},
     transfer::{error::LocalDelivery, Status},
-    transport::{AbstractTransport, DeliverTo},
+    transport::{AbstractTransport, DeliverTo, TransportStates},
     Address, ContextFinished,
 };
 extern crate alloc;
@@ -33,6 +33,10 @@ struct Payload {
         deserialize_with = "vsmtp_config::parser::syst_group::opt_deserialize"
     )]
     group_local: Option<users::Group>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    folder: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tag_separator: Option<char>,
 }
 
 def_type_serde!("maildir");
@@ -41,12 +45,19 @@ impl PartialEq for Payload {
     fn eq(&self, other: &Self) -> bool {
         self.group_local.as_ref().map(users::Group::gid)
             == other.group_local.as_ref().map(users::Group::gid)
+            && self.folder == other.folder
+            && self.tag_separator == other.tag_separator
     }
 }
 
 impl Eq for Payload {}
 
 /// see <https://en.wikipedia.org/wiki/Maildir>
+///
+/// The email is stored in the `INBOX` of the recipient, or in one of its
+/// Maildir++ folders (`Junk` is stored in `~/Maildir/.Junk/new`): the folder
+/// set by the rules for the recipient, else the folder of the transport, else
+/// the folder named after the tag of the address (see [`Maildir::with_tag_folders`]).
 #[derive(Debug, PartialEq, Eq, serde::Deserialize)]
 pub struct Maildir {
     #[serde(flatten)]
@@ -72,15 +83,19 @@ impl AbstractTransport for Maildir {
     #[tracing::instrument(name = "maildir", skip_all)]
     async fn deliver(
         self: alloc::sync::Arc<Self>,
+        _: &TransportStates,
         ctx: &ContextFinished,
         mut to: DeliverTo,
         content: &[u8],
     ) -> DeliverTo {
         let msg_uuid = &ctx.mail_from.message_uuid;
         for rcpt in &mut to {
-            match users::get_user_by_name(rcpt.0.local_part())
-                .map(|user| self.write_to_maildir(&rcpt.0, &user, msg_uuid, content))
-            {
+            let (user_name, tag) = self.split_tag(ctx, &rcpt.0);
+            let folder = self.folder_of(ctx, &rcpt.0, tag);
+
+            match users::get_user_by_name(user_name).map(|user| {
+                self.write_to_maildir(&rcpt.0, &user, folder.as_deref(), msg_uuid, content)
+            }) {
                 Some(Ok(())) => {
                     tracing::info!("Email delivered.");
 
@@ -93,12 +108,12 @@ impl AbstractTransport for Maildir {
                 }
                 None => {
                     tracing::error!(
-                        error = format!("user not found: {}", rcpt.0.local_part()),
+                        error = format!("user not found: {user_name}"),
                         "Email delivery failure."
                     );
 
                     rcpt.1.held_back(LocalDelivery::MailboxDoNotExist {
-                        mailbox: rcpt.0.local_part().to_owned(),
+                        mailbox: user_name.to_owned(),
                     });
                 }
             }
@@ -116,8 +131,152 @@ impl Maildir {
             payload: Payload {
                 group_local,
                 r#type: "maildir".to_owned(),
+                folder: None,
+                tag_separator: None,
+            },
+        }
+    }
+
+    /// Create a transport storing the emails in the `folder` of the mailbox,
+    /// such as `Junk` or `INBOX.lists`.
+    ///
+    /// # Errors
+    ///
+    /// * the folder name is not valid, see [`Maildir::folder_dir`]
+    #[inline]
+    pub fn with_folder(group_local: Option<users::Group>, folder: &str) -> anyhow::Result<Self> {
+        let dir = Self::folder_dir(folder)?;
+
+        Ok(Self {
+            payload: Payload {
+                group_local,
+                r#type: "maildir".to_owned(),
+                folder: dir.map(|_| folder.to_owned()),
+                tag_separator: None,
             },
+        })
+    }
+
+    /// Store the emails sent to a tagged address (`john+lists@example.com` with the
+    /// `separator` `+`) in the mailbox of the user (`john`), in the folder named after
+    /// the tag (`Lists`), see [`Maildir::tag_folder`].
+    #[must_use]
+    #[inline]
+    pub fn with_tag_folders(mut self, separator: char) -> Self {
+        self.payload.tag_separator = Some(separator);
+        self
+    }
+
+    /// The user name and the tag of a recipient: the extension recorded on reception
+    /// with the `server.recipient_delimiter` if any, else the split of the local part
+    /// on the separator. The tag is ignored if the tag folders are disabled.
+    fn split_tag<'ctx>(
+        &self,
+        ctx: &'ctx ContextFinished,
+        rcpt: &'ctx Address,
+    ) -> (&'ctx str, Option<&'ctx str>) {
+        if let Some(extension) = ctx.rcpt_to.extensions.get(rcpt) {
+            return (
+                &extension.base,
+                self.payload
+                    .tag_separator
+                    .map(|_| extension.detail.as_str()),
+            );
+        }
+
+        let local_part = rcpt.local_part();
+        match self
+            .payload
+            .tag_separator
+            .and_then(|separator| local_part.split_once(separator))
+        {
+            Some((user, tag)) => (user, Some(tag)),
+            None => (local_part, None),
+        }
+    }
+
+    /// The name of the folder of a tag: the characters other than letters, digits,
+    /// `-` and `_` are replaced by `_`, and the first letter is capitalized
+    /// (`lists` is stored in `Lists`). [`None`] for an empty tag.
+    #[must_use]
+    #[inline]
+    pub fn tag_folder(tag: &str) -> Option<String> {
+        let mut chars = tag.chars().map(|c| {
+            if c.is_alphanumeric() || matches!(c, '-' | '_') {
+                c
+            } else {
+                '_'
+            }
+        });
+
+        let first = chars.next()?;
+        Some(first.to_uppercase().chain(chars).collect())
+    }
+
+    /// The Maildir++ directory the email is stored in for `rcpt`, [`None`] for the `INBOX`.
+    ///
+    /// An invalid folder name falls back to the `INBOX`, the folders of the rules and of the
+    /// payload can come from a modified queue file.
+    fn folder_of(
+        &self,
+        ctx: &ContextFinished,
+        rcpt: &Address,
+        tag: Option<&str>,
+    ) -> Option<String> {
+        let folder = ctx
+            .rcpt_to
+            .folders
+            .get(rcpt)
+            .or(self.payload.folder.as_ref())
+            .cloned()
+            .or_else(|| tag.and_then(Self::tag_folder))?;
+
+        match Self::folder_dir(&folder) {
+            Ok(dir) => dir,
+            Err(error) => {
+                tracing::warn!(%rcpt, %error, "Invalid folder, storing the email in the INBOX.");
+                None
+            }
+        }
+    }
+
+    /// Get the Maildir++ directory of `folder`, [`None`] for the `INBOX`.
+    ///
+    /// The `INBOX.` prefix is optional, and the sub folders are separated by dots
+    /// (`INBOX.lists.vsmtp` is stored in `.lists.vsmtp`).
+    ///
+    /// # Errors
+    ///
+    /// * the name is empty, too long, contains an empty component (`..`), a path separator
+    ///   or a control character
+    #[inline]
+    pub fn folder_dir(folder: &str) -> anyhow::Result<Option<String>> {
+        const MAX_LEN: usize = 255;
+
+        if folder.eq_ignore_ascii_case("INBOX") {
+            return Ok(None);
         }
+        let name = match folder.split_once('.') {
+            Some((inbox, name)) if inbox.eq_ignore_ascii_case("INBOX") => name,
+            _ => folder,
+        };
+
+        anyhow::ensure!(
+            !name.is_empty() && name.len() <= MAX_LEN,
+            "invalid folder name `{folder}`: must be between 1 and {MAX_LEN} bytes"
+        );
+        anyhow::ensure!(
+            !name
+                .chars()
+                .any(|c| matches!(c, '/' | '\\') || c.is_control()),
+            "invalid folder name `{folder}`: contains a path separator or a control character"
+        );
+        anyhow::ensure!(
+            !name.split('.').any(str::is_empty),
+            "invalid folder name `{folder}`: contains an empty component"
+        );
+
+        Ok(Some(format!(".{name}")))
     }
 
     // create and set rights for the MailDir & [new,cur,tmp] folder if they don't exists.
@@ -157,38 +316,92 @@ impl Maildir {
         &self,
         addr: &Address,
         user: &users::User,
+        folder: Option<&str>,
         msg_uuid: &uuid::Uuid,
         content: &[u8],
     ) -> anyhow::Result<()> {
-        let maildir = std::path::PathBuf::from_iter([getpwuid(user.uid())?, "Maildir".into()]);
+        let mut maildir = std::path::PathBuf::from_iter([getpwuid(user.uid())?, "Maildir".into()]);
         Self::create_and_chown(&maildir, user, &self.payload.group_local)?;
+
+        if let Some(folder) = folder {
+            maildir.push(folder);
+            Self::create_and_chown(&maildir, user, &self.payload.group_local)?;
+
+            let marker = maildir.join("maildirfolder");
+            if !marker.exists() {
+                std::fs::File::create(&marker)
+                    .with_context(|| format!("failed to create {}", marker.display()))?;
+                chown(
+                    &marker,
+                    Some(user.uid()),
+                    self.payload.group_local.as_ref().map(users::Group::gid),
+                )?;
+            }
+        }
+
         for dir in ["new", "tmp", "cur"] {
             Self::create_and_chown(&maildir.join(dir), user, &self.payload.group_local)?;
         }
 
-        let file_in_maildir_inbox = maildir.join(format!("new/{msg_uuid}.eml"));
+        let file_in_maildir_tmp = maildir.join(format!("tmp/{msg_uuid}.eml"));
 
         let mut email = std::fs::OpenOptions::new()
             .create(true)
             .write(true)
-            .open(&file_in_maildir_inbox)
+            .truncate(true)
+            .open(&file_in_maildir_tmp)
             .with_context(|| {
-                format!(
-                    "failed to open file at '{}'",
-                    file_in_maildir_inbox.display()
-                )
+                format!("failed to open file at '{}'", file_in_maildir_tmp.display())
             })?;
 
         std::io::Write::write_all(&mut email, format!("Delivered-To: {addr}\n").as_bytes())?;
         std::io::Write::write_all(&mut email, content)?;
 
         chown(
-            &file_in_maildir_inbox,
+            &file_in_maildir_tmp,
             Some(user.uid()),
             self.payload.group_local.as_ref().map(users::Group::gid),
         )?;
 
-        Ok(())
+        let moved = Self::move_to_new(&maildir, &file_in_maildir_tmp, msg_uuid);
+        std::fs::remove_file(&file_in_maildir_tmp)
+            .with_context(|| format!("failed to remove {}", file_in_maildir_tmp.display()))?;
+        moved
+    }
+
+    /// Link the email written in `tmp/` in `new/`, under a name not taken yet: `<uuid>.eml`,
+    /// or `<uuid>.<n>.eml` if the email has already been stored in the folder (for another
+    /// address of the same user).
+    fn move_to_new(
+        maildir: &std::path::Path,
+        tmp: &std::path::Path,
+        msg_uuid: &uuid::Uuid,
+    ) -> anyhow::Result<()> {
+        const MAX_COPIES: u32 = 100;
+
+        for copy in 0..MAX_COPIES {
+            let name = if copy == 0 {
+                format!("{msg_uuid}.eml")
+            } else {
+                format!("{msg_uuid}.{copy}.eml")
+            };
+            let file_in_maildir_new = maildir.join("new").join(name);
+
+            match std::fs::hard_link(tmp, &file_in_maildir_new) {
+                Ok(()) => return Ok(()),
+                Err(error) if error.kind() == std::io::ErrorKind::AlreadyExists => (),
+                Err(error) => {
+                    return Err(error).with_context(|| {
+                        format!("failed to link {}", file_in_maildir_new.display())
+                    })
+                }
+            }
+        }
+
+        anyhow::bail!(
+            "the email is already stored {MAX_COPIES} times in {}",
+            maildir.display()
+        )
     }
 }
 
@@ -198,7 +411,7 @@ mod test {
     use super::*;
     use users::os::unix::UserExt;
     use vsmtp_common::transfer::error::Variant;
-    use vsmtp_common::{addr, transport::WrapperSerde};
+    use vsmtp_common::{addr, transport::WrapperSerde, AddressExtension};
     use vsmtp_test::config::local_ctx;
 
     #[rstest::rstest]
@@ -214,6 +427,18 @@ mod test {
         }).to_string(),
         Maildir::new(Some(users::get_group_by_name("mail").unwrap()))
     )]
+    #[case::with_folder(
+        &serde_json::json!({
+            "v": r#"{"type":"maildir","group_local":null,"folder":"Junk"}"#
+        }).to_string(),
+        Maildir::with_folder(None, "Junk").unwrap()
+    )]
+    #[case::with_tag_folders(
+        &serde_json::json!({
+            "v": r#"{"type":"maildir","group_local":null,"tag_separator":"+"}"#
+        }).to_string(),
+        Maildir::new(None).with_tag_folders('+')
+    )]
     fn deserialize(#[case] input: &str, #[case] instance: Maildir) {
         #[derive(serde::Deserialize, serde::Serialize)]
         struct S {
@@ -234,6 +459,53 @@ mod test {
         assert_eq!(input, serde_json::to_string(&S { v: delivery }).unwrap());
     }
 
+    #[rstest::rstest]
+    #[case::inbox("INBOX", None)]
+    #[case::inbox_case("inbox", None)]
+    #[case::folder("Junk", Some(".Junk"))]
+    #[case::inbox_prefix("INBOX.lists", Some(".lists"))]
+    #[case::sub_folder("INBOX.lists.vsmtp", Some(".lists.vsmtp"))]
+    fn folder_dir(#[case] folder: &str, #[case] expected: Option<&str>) {
+        assert_eq!(Maildir::folder_dir(folder).unwrap().as_deref(), expected);
+        assert_eq!(
+            Maildir::with_folder(None, folder).unwrap() == Maildir::new(None),
+            expected.is_none()
+        );
+    }
+
+    #[rstest::rstest]
+    #[case::empty("")]
+    #[case::empty_sub_folder("INBOX.")]
+    #[case::parent("..")]
+    #[case::traversal("../../etc")]
+    #[case::dot_traversal("INBOX...")]
+    #[case::separator("Junk/new")]
+    #[case::absolute("/etc")]
+    #[case::backslash("Junk\\new")]
+    #[case::control("Junk\n")]
+    #[case::nul("Junk\0")]
+    fn folder_dir_invalid(#[case] folder: &str) {
+        assert!(Maildir::folder_dir(folder).is_err());
+        assert!(Maildir::with_folder(None, folder).is_err());
+    }
+
+    #[rstest::rstest]
+    #[case::lowercase("lists", Some("Lists"))]
+    #[case::capitalized("Spam", Some("Spam"))]
+    #[case::digits("2023", Some("2023"))]
+    #[case::sanitized("a.b/c", Some("A_b_c"))]
+    #[case::traversal("..", Some("__"))]
+    #[case::empty("", None)]
+    fn tag_folder(#[case] tag: &str, #[case] expected: Option<&str>) {
+        assert_eq!(Maildir::tag_folder(tag).as_deref(), expected);
+    }
+
+    #[test]
+    fn folder_dir_too_long() {
+        assert!(Maildir::folder_dir(&"a".repeat(255)).is_ok());
+        assert!(Maildir::folder_dir(&"a".repeat(256)).is_err());
+    }
+
     #[rstest::rstest]
     #[case::not_existing("foobar", Err(Variant::LocalDelivery(
         LocalDelivery::MailboxDoNotExist {
@@ -256,6 +528,7 @@ mod test {
                 let transport = alloc::sync::Arc::new(Maildir::new(None));
                 let result = alloc::sync::Arc::clone(&transport)
                     .deliver(
+                        &TransportStates::default(),
                         &context,
                         vec![(addr!(&format!("{mailbox}@domain.com")), Status::default())],
                         fake_message.as_bytes(),
@@ -295,4 +568,216 @@ mod test {
                 }
             });
     }
+
+    #[test]
+    fn maildir_folder() {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(async move {
+                let context = local_ctx();
+                let mailbox = users::get_current_username().unwrap();
+                let mailbox = mailbox.to_str().unwrap();
+
+                let transport =
+                    alloc::sync::Arc::new(Maildir::with_folder(None, "INBOX.Junk").unwrap());
+                let result = alloc::sync::Arc::clone(&transport)
+                    .deliver(
+                        &TransportStates::default(),
+                        &context,
+                        vec![(addr!(&format!("{mailbox}@domain.com")), Status::default())],
+                        b"Hello World!\r\n",
+                    )
+                    .await;
+
+                assert!(result
+                    .iter()
+                    .all(|(_, status)| matches!(status, Status::Sent { .. })));
+
+                let folder = std::path::PathBuf::from_iter([
+                    users::get_user_by_uid(users::get_current_uid())
+                        .unwrap()
+                        .home_dir(),
+                    std::path::Path::new("Maildir"),
+                    std::path::Path::new(".Junk"),
+                ]);
+                assert!(folder.join("maildirfolder").exists());
+                assert_eq!(
+                    std::fs::read_to_string(
+                        folder.join(format!("new/{}.eml", context.mail_from.message_uuid))
+                    )
+                    .unwrap(),
+                    format!("Delivered-To: {mailbox}@domain.com\nHello World!\r\n")
+                );
+            });
+    }
+
+    /// Deliver "Hello World!" to `rcpts` and return the `.eml` files of the Maildir++ `folder`
+    /// of the current user created by the delivery.
+    fn deliver_to_folder(
+        transport: Maildir,
+        context: &ContextFinished,
+        rcpts: &[Address],
+        folder: Option<&str>,
+    ) -> Vec<std::path::PathBuf> {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(async move {
+                let result = alloc::sync::Arc::new(transport)
+                    .deliver(
+                        &TransportStates::default(),
+                        context,
+                        rcpts
+                            .iter()
+                            .map(|rcpt| (rcpt.clone(), Status::default()))
+                            .collect(),
+                        b"Hello World!\r\n",
+                    )
+                    .await;
+
+                assert!(result
+                    .iter()
+                    .all(|(_, status)| matches!(status, Status::Sent { .. })));
+            });
+
+        let mut maildir = std::path::PathBuf::from_iter([
+            users::get_user_by_uid(users::get_current_uid())
+                .unwrap()
+                .home_dir(),
+            std::path::Path::new("Maildir"),
+        ]);
+        if let Some(folder) = folder {
+            maildir.push(folder);
+            assert!(maildir.join("maildirfolder").exists());
+        }
+
+        let uuid = context.mail_from.message_uuid.to_string();
+        let mut files = std::fs::read_dir(maildir.join("new"))
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| {
+                path.file_name()
+                    .and_then(std::ffi::OsStr::to_str)
+                    .map_or(false, |name| name.starts_with(&uuid))
+            })
+            .collect::<Vec<_>>();
+        files.sort();
+
+        assert!(!maildir.join("tmp").join(format!("{uuid}.eml")).exists());
+        files
+    }
+
+    #[test]
+    fn maildir_tag_folder() {
+        let context = local_ctx();
+        let mailbox = users::get_current_username().unwrap();
+        let mailbox = mailbox.to_str().unwrap();
+
+        let files = deliver_to_folder(
+            Maildir::new(None).with_tag_folders('+'),
+            &context,
+            &[
+                addr!(&format!("{mailbox}+lists@domain.com")),
+                addr!(&format!("{mailbox}+Lists@domain.com")),
+            ],
+            Some(".Lists"),
+        );
+
+        let uuid = context.mail_from.message_uuid;
+        assert_eq!(
+            files
+                .iter()
+                .map(|path| path.file_name().unwrap().to_str().unwrap().to_owned())
+                .collect::<Vec<_>>(),
+            [format!("{uuid}.1.eml"), format!("{uuid}.eml")]
+        );
+        assert_eq!(
+            std::fs::read_to_string(&files[1]).unwrap(),
+            format!("Delivered-To: {mailbox}+lists@domain.com\nHello World!\r\n")
+        );
+        assert_eq!(
+            std::fs::read_to_string(&files[0]).unwrap(),
+            format!("Delivered-To: {mailbox}+Lists@domain.com\nHello World!\r\n")
+        );
+    }
+
+    #[test]
+    fn maildir_tag_without_separator() {
+        let context = local_ctx();
+        let mailbox = users::get_current_username().unwrap();
+        let mailbox = mailbox.to_str().unwrap();
+
+        let files = deliver_to_folder(
+            Maildir::new(None).with_tag_folders('+'),
+            &context,
+            &[addr!(&format!("{mailbox}+@domain.com"))],
+            None,
+        );
+        assert_eq!(files.len(), 1);
+    }
+
+    #[test]
+    fn maildir_tag_from_extension() {
+        let mut context = local_ctx();
+        let mailbox = users::get_current_username().unwrap();
+        let mailbox = mailbox.to_str().unwrap();
+        let rcpt = addr!(&format!("{mailbox}-news@domain.com"));
+
+        // recorded on reception with the `-` delimiter.
+        context.rcpt_to.extensions.insert(
+            rcpt.clone(),
+            AddressExtension {
+                base: mailbox.to_owned(),
+                detail: "news".to_owned(),
+            },
+        );
+
+        let files = deliver_to_folder(
+            Maildir::new(None).with_tag_folders('+'),
+            &context,
+            &[rcpt],
+            Some(".News"),
+        );
+        assert_eq!(files.len(), 1);
+    }
+
+    #[test]
+    fn maildir_rules_folder() {
+        let mut context = local_ctx();
+        let mailbox = users::get_current_username().unwrap();
+        let mailbox = mailbox.to_str().unwrap();
+        let rcpt = addr!(&format!("{mailbox}+lists@domain.com"));
+
+        context
+            .rcpt_to
+            .folders
+            .insert(rcpt.clone(), "INBOX.Spam".to_owned());
+
+        let files = deliver_to_folder(
+            Maildir::new(None).with_tag_folders('+'),
+            &context,
+            &[rcpt],
+            Some(".Spam"),
+        );
+        assert_eq!(files.len(), 1);
+    }
+
+    #[test]
+    fn maildir_rules_invalid_folder() {
+        let mut context = local_ctx();
+        let mailbox = users::get_current_username().unwrap();
+        let mailbox = mailbox.to_str().unwrap();
+        let rcpt = addr!(&format!("{mailbox}@domain.com"));
+
+        context
+            .rcpt_to
+            .folders
+            .insert(rcpt.clone(), "../../etc".to_owned());
+
+        let files = deliver_to_folder(Maildir::new(None), &context, &[rcpt], None);
+        assert_eq!(files.len(), 1);
+    }
 }
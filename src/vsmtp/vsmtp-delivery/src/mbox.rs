@@ -19,7 +19,7 @@ use anyhow::Context;
 use vsmtp_common::{
     libc_abstraction::chown,
     transfer::{error::LocalDelivery, Status},
-    transport::{AbstractTransport, DeliverTo},
+    transport::{AbstractTransport, DeliverTo, TransportStates},
     Address, ContextFinished,
 };
 extern crate alloc;
@@ -93,6 +93,7 @@ impl AbstractTransport for MBox {
     #[inline]
     async fn deliver(
         self: alloc::sync::Arc<Self>,
+        _: &TransportStates,
         ctx: &ContextFinished,
         mut to: DeliverTo,
         content: &[u8],
@@ -275,6 +276,7 @@ mod test {
                 let transport = alloc::sync::Arc::new(MBox::new(None));
                 let result = alloc::sync::Arc::clone(&transport)
                     .deliver(
+                        &TransportStates::default(),
                         &context,
                         vec![(addr!(&format!("{mailbox}@domain.com")), Status::default())],
                         fake_message.as_bytes(),
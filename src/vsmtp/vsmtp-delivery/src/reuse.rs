@@ -0,0 +1,182 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2023 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use lettre::transport::smtp::client::AsyncSmtpConnection;
+use vsmtp_common::clock;
+use vsmtp_config::field::FieldQueueDeliveryReuse;
+
+/// An open connection, waiting for the next message to its destination.
+pub(crate) struct Connection {
+    pub(crate) conn: AsyncSmtpConnection,
+    /// Number of messages sent on the connection.
+    pub(crate) messages: usize,
+    /// Extensions advertised by the server when the connection was opened.
+    pub(crate) capabilities: crate::capabilities::Capabilities,
+    /// Do the extensions come from the cache?
+    pub(crate) cached: bool,
+    /// Time of the end of the last message.
+    since: time::OffsetDateTime,
+}
+
+impl Connection {
+    pub(crate) fn new(
+        conn: AsyncSmtpConnection,
+        capabilities: crate::capabilities::Capabilities,
+        cached: bool,
+    ) -> Self {
+        Self {
+            conn,
+            messages: 0,
+            capabilities,
+            cached,
+            since: clock::now(),
+        }
+    }
+}
+
+/// The connections kept open after a message, by destination.
+#[derive(Default)]
+pub(crate) struct Pool {
+    parameters: Option<FieldQueueDeliveryReuse>,
+    idle: std::sync::Mutex<alloc::collections::BTreeMap<String, Vec<Connection>>>,
+}
+
+/// Close the connections, politely.
+async fn close(connections: Vec<Connection>) {
+    for mut connection in connections {
+        if let Err(error) = connection.conn.quit().await {
+            tracing::debug!(%error, "Failed to close the connection.");
+        }
+    }
+}
+
+impl Pool {
+    /// Create the pool, from the configuration of the delivery.
+    pub(crate) fn new(parameters: &FieldQueueDeliveryReuse) -> Self {
+        Self {
+            parameters: parameters.enable.then(|| FieldQueueDeliveryReuse {
+                enable: parameters.enable,
+                messages_max: parameters.messages_max,
+                idle_timeout: parameters.idle_timeout,
+            }),
+            idle: std::sync::Mutex::default(),
+        }
+    }
+
+    fn with_idle<R>(
+        &self,
+        f: impl FnOnce(&mut alloc::collections::BTreeMap<String, Vec<Connection>>) -> R,
+    ) -> R {
+        let mut idle = self
+            .idle
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        f(&mut idle)
+    }
+
+    /// Remove the connections idle for too long, returning them to be closed.
+    #[allow(clippy::arithmetic_side_effects, clippy::integer_arithmetic)]
+    fn expire(
+        &self,
+        idle: &mut alloc::collections::BTreeMap<String, Vec<Connection>>,
+    ) -> Vec<Connection> {
+        let now = clock::now();
+        let idle_timeout = self
+            .parameters
+            .as_ref()
+            .map(|parameters| parameters.idle_timeout);
+
+        let mut expired = vec![];
+        for connections in idle.values_mut() {
+            let (kept, removed): (Vec<_>, Vec<_>) = core::mem::take(connections)
+                .into_iter()
+                .partition(|connection| {
+                    idle_timeout.map_or(false, |timeout| now < connection.since + timeout)
+                });
+            *connections = kept;
+            expired.extend(removed);
+        }
+        idle.retain(|_, connections| !connections.is_empty());
+        expired
+    }
+
+    /// Period of the sweep of the idle connections, `None` if the reuse is disabled.
+    pub(crate) fn sweep_period(&self) -> Option<std::time::Duration> {
+        self.parameters
+            .as_ref()
+            .and_then(|parameters| parameters.idle_timeout.checked_div(2))
+            .filter(|period| !period.is_zero())
+    }
+
+    /// Close the connections idle for too long, even if no message is sent anymore
+    /// to their destination.
+    pub(crate) async fn sweep(&self) {
+        let expired = self.with_idle(|idle| self.expire(idle));
+        if !expired.is_empty() {
+            tracing::debug!(count = expired.len(), "Closing the idle connections.");
+        }
+        close(expired).await;
+    }
+
+    /// Take an idle connection to the destination, reset with a `RSET` command.
+    pub(crate) async fn take(&self, destination: &str) -> Option<Connection> {
+        loop {
+            let (expired, connection) = self.with_idle(|idle| {
+                let expired = self.expire(idle);
+                let connection = idle.get_mut(destination).and_then(Vec::pop);
+                (expired, connection)
+            });
+            close(expired).await;
+
+            let mut connection = connection?;
+            match connection
+                .conn
+                .command(lettre::transport::smtp::commands::Rset)
+                .await
+            {
+                Ok(_) => {
+                    tracing::debug!(%destination, messages = connection.messages, "Reusing the connection.");
+                    return Some(connection);
+                }
+                Err(error) => {
+                    tracing::debug!(%destination, %error, "The idle connection is closed.");
+                    connection.conn.abort().await;
+                }
+            }
+        }
+    }
+
+    /// Give back the connection after a successful message: it is kept for the next message
+    /// to the destination, or closed if the reuse is disabled or the limit is reached.
+    pub(crate) async fn give_back(&self, destination: &str, mut connection: Connection) {
+        connection.messages = connection.messages.saturating_add(1);
+        connection.since = clock::now();
+
+        let closed = self.with_idle(|idle| {
+            let mut closed = self.expire(idle);
+            match self.parameters.as_ref() {
+                Some(parameters) if connection.messages < parameters.messages_max => {
+                    idle.entry(destination.to_owned())
+                        .or_default()
+                        .push(connection);
+                }
+                _ => closed.push(connection),
+            }
+            closed
+        });
+        close(closed).await;
+    }
+}
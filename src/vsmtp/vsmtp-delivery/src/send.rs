@@ -14,19 +14,134 @@
  * this program. If not, see https://www.gnu.org/licenses/.
  *
 */
-use futures_util::FutureExt;
 use vsmtp_common::{
+    policy_event::{Kind, PolicyEvent},
     transfer::{
-        error::{Delivery, Queuer},
+        error::{Delivery, Queuer, Variant},
         Status,
     },
-    transport::WrapperSerde,
-    ContextFinished, Domain, Target, SMTP_PORT, SUBMISSIONS_PORT, SUBMISSION_PORT,
+    transport::{DeliverTo, TransportStates, WrapperSerde},
+    Address, ContextFinished, Domain, MimeBodyType, OriginalRecipient, ProtocolVersion, ReplyCode,
+    Target, SMTP_PORT, SUBMISSIONS_PORT, SUBMISSION_PORT,
+};
+use vsmtp_config::{
+    field::{FieldQueueDeliveryTlsPolicy, StartTlsPolicy},
+    Config,
 };
-use vsmtp_config::Config;
 use vsmtp_mail_parser::MessageBody;
 extern crate alloc;
 
+/// Timeout of the network operations of the SMTP exchange.
+const SMTP_TIMEOUT: core::time::Duration = core::time::Duration::from_secs(60);
+
+/// Maximum number of transactions opened on a connection to send a message,
+/// when the server limits the number of recipients per transaction.
+const MAX_TRANSACTIONS: usize = 100;
+
+/// The replies of the server to the recipients of an envelope, in the same order.
+pub(crate) type RcptReplies = Vec<Result<(), Delivery>>;
+
+/// The parameters of the DSN extension (RFC 3461) given by the client, relayed
+/// to the servers advertising the extension.
+#[derive(Debug, Default)]
+pub(crate) struct DsnParameters {
+    /// The `ENVID` of the transaction, decoded.
+    envelop_id: Option<String>,
+    /// The `ORCPT` of the recipients of the envelope, in the same order.
+    original_recipients: Vec<Option<OriginalRecipient>>,
+}
+
+impl DsnParameters {
+    /// The parameters of the message `ctx` for the recipients `rcpt`, in the
+    /// order of the envelope.
+    pub(crate) fn new<'item>(
+        ctx: &ContextFinished,
+        rcpt: impl Iterator<Item = &'item Address>,
+    ) -> Self {
+        Self {
+            envelop_id: ctx.mail_from.envelop_id.clone(),
+            original_recipients: rcpt
+                .map(|rcpt| ctx.rcpt_to.original_recipients.get(rcpt).cloned())
+                .collect(),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.envelop_id.is_none() && self.original_recipients.iter().all(Option::is_none)
+    }
+
+    fn mail_parameter(&self) -> Option<lettre::transport::smtp::extension::MailParameter> {
+        self.envelop_id.as_ref().map(|envelop_id| {
+            // NOTE: the values of the parameters are encoded as `xtext` by lettre.
+            lettre::transport::smtp::extension::MailParameter::Other {
+                keyword: "ENVID".to_owned(),
+                value: Some(envelop_id.clone()),
+            }
+        })
+    }
+
+    fn rcpt_parameters(
+        &self,
+        index: usize,
+        utf8: bool,
+    ) -> Vec<lettre::transport::smtp::extension::RcptParameter> {
+        self.original_recipients
+            .get(index)
+            .and_then(Option::as_ref)
+            .map(
+                |original| lettre::transport::smtp::extension::RcptParameter::Other {
+                    keyword: "ORCPT".to_owned(),
+                    // NOTE: the `\x{HEX}` escapes of the `utf-8` type are left as is by lettre.
+                    value: Some(if original.addr_type.eq_ignore_ascii_case("utf-8") {
+                        original.to_param(utf8)
+                    } else {
+                        original.to_string()
+                    }),
+                },
+            )
+            .into_iter()
+            .collect()
+    }
+}
+
+/// Set the status of the recipients with the replies of the server `target`.
+pub(crate) fn apply_rcpt_replies(target: &Target, rcpt: &mut DeliverTo, replies: RcptReplies) {
+    for ((_, status), reply) in rcpt.iter_mut().zip(replies) {
+        match reply {
+            Ok(()) => *status = Status::sent(),
+            Err(error) => {
+                let error = Variant::Delivery(vec![(target.clone(), error)]);
+                if error.is_permanent() {
+                    *status = Status::failed(error);
+                } else {
+                    status.held_back(error);
+                }
+            }
+        }
+    }
+}
+
+/// Has the server refused a recipient because the transaction has too many of them?
+///
+/// The recipient can be sent in another transaction,
+/// see <https://www.rfc-editor.org/rfc/rfc5321#section-4.5.3.1.10>.
+fn is_too_many_recipients(error: &lettre::transport::smtp::Error) -> bool {
+    error
+        .status()
+        .map_or(false, |code| ReplyCode::from(code).value() == 452)
+}
+
+/// Is the error a negative reply of the server, the connection being still usable?
+fn is_negative_reply(error: &lettre::transport::smtp::Error) -> bool {
+    error.is_permanent() || error.is_transient()
+}
+
+/// Has the server refused the parameters of the `MAIL FROM` command, relying on
+/// an extension it does not support anymore?
+fn is_parameter_refusal(error: &Delivery) -> bool {
+    matches!(error, Delivery::Permanent { reply, .. } if matches!(reply.value(), 501 | 504 | 555))
+}
+
 ///
 #[must_use]
 #[allow(clippy::exhaustive_enums)]
@@ -45,6 +160,7 @@ pub enum SenderOutcome {
 #[tracing::instrument(name = "send", skip_all)]
 pub async fn split_and_sort_and_send(
     config: alloc::sync::Arc<Config>,
+    state: &alloc::sync::Arc<crate::DeliveryState>,
     message_ctx: &mut ContextFinished,
     message_body: &MessageBody,
 ) -> SenderOutcome {
@@ -71,19 +187,63 @@ pub async fn split_and_sort_and_send(
         return SenderOutcome::MoveToDead;
     }
 
-    let message_content = message_body.inner().to_string();
-    let message_bytes = message_content.as_bytes();
-
-    let futures = transports.into_iter().map(|(transport, to)| {
-        alloc::sync::Arc::clone(&transport)
-            .deliver(message_ctx, to, message_bytes)
-            .map(|r| (WrapperSerde::Ready(transport), r))
-    });
+    let message_content = message_body.inner().to_bytes();
+    let states = TransportStates::default().with(alloc::sync::Arc::clone(state));
 
-    message_ctx.rcpt_to.delivery = futures_util::future::join_all(futures)
-        .await
+    let futures = transports
         .into_iter()
-        .collect::<std::collections::HashMap<_, _>>();
+        .flat_map(|(transport, to)| {
+            split_by_rcpt_headers(
+                &message_ctx.finished.rcpt_headers,
+                &message_ctx.rcpt_to.catch_all,
+                to,
+            )
+            .into_iter()
+            .map(move |(headers, rcpt)| (alloc::sync::Arc::clone(&transport), headers, rcpt))
+        })
+        .map(|(transport, headers, to)| {
+            // NOTE: the variant of the message is only built for the recipients
+            //       having header additions.
+            let content = if headers.is_empty() {
+                alloc::borrow::Cow::Borrowed(message_content.as_slice())
+            } else {
+                alloc::borrow::Cow::Owned([headers.as_bytes(), &message_content].concat())
+            };
+            let (ctx, states) = (&*message_ctx, &states);
+
+            async move {
+                let key = WrapperSerde::Ready(alloc::sync::Arc::clone(&transport));
+                let delivered = transport.deliver(states, ctx, to, &content).await;
+                (key, delivered)
+            }
+        });
+
+    // NOTE: the recipients sent or failed on a previous attempt keep their status,
+    //       so that the statuses of all the transports are aggregated.
+    let mut delivery = std::collections::HashMap::<WrapperSerde, DeliverTo>::new();
+    for (transport, rcpt) in &message_ctx.rcpt_to.delivery {
+        let done = rcpt
+            .iter()
+            .filter(|(_, status)| !status.is_sendable())
+            .cloned()
+            .collect::<Vec<_>>();
+
+        if !done.is_empty() {
+            delivery
+                .entry(WrapperSerde::Ready(transport.clone().unwrap_ready()))
+                .or_default()
+                .extend(done);
+        }
+    }
+    for (transport, to) in futures_util::future::join_all(futures).await {
+        for (rcpt, status) in &to {
+            if let Status::Failed { error } = status {
+                emit_failure(message_ctx, rcpt, error);
+            }
+        }
+        delivery.entry(transport).or_default().extend(to);
+    }
+    message_ctx.rcpt_to.delivery = delivery;
 
     tracing::debug!(rcpt = ?message_ctx.rcpt_to.delivery
         .values().collect::<Vec<_>>(), "Sending.");
@@ -123,6 +283,7 @@ pub async fn split_and_sort_and_send(
     }
 
     let mut out = None;
+    let mut failed = vec![];
     for rcpt in &mut message_ctx.rcpt_to.delivery.values_mut().flatten() {
         if matches!(&rcpt.1, Status::HeldBack{ errors }
             if errors.len() >= config.server.queues.delivery.deferred_retry_max)
@@ -130,6 +291,24 @@ pub async fn split_and_sort_and_send(
             rcpt.1 = Status::failed(Queuer::MaxDeferredAttemptReached);
             tracing::warn!("Delivery error count maximum reached, moving to dead.");
             out = Some(SenderOutcome::MoveToDead);
+            failed.push(rcpt.clone());
+        }
+    }
+    for (rcpt, status) in &failed {
+        if let Status::Failed { error } = status {
+            emit_failure(message_ctx, rcpt, error);
+        }
+    }
+
+    for (rcpt, status) in message_ctx.rcpt_to.delivery.values().flatten() {
+        if let Some(error) = status.held_back_error() {
+            tracing::warn!(
+                %rcpt,
+                reason = %error.reason(),
+                reply = ?error.reply(),
+                error = %error.variant(),
+                "Recipient held back."
+            );
         }
     }
 
@@ -148,6 +327,111 @@ pub async fn split_and_sort_and_send(
     out
 }
 
+/// Emit the policy event of a recipient whose delivery failed permanently.
+fn emit_failure(ctx: &ContextFinished, rcpt: &Address, error: &vsmtp_common::transfer::Error) {
+    PolicyEvent::of_finished(Kind::DeliveryFailed, ctx)
+        .with_stage("delivery")
+        .with_recipient(rcpt)
+        .with_error(error)
+        .emit();
+}
+
+/// The error of a message containing 8-bit data, sent to a server not supporting 8BITMIME.
+///
+/// A body declared `8BITMIME` by the client is MIME content which can be re-encoded, see
+/// [`seven_bit_copy`], as well as a body without declaration (produced locally, or queued
+/// before the declaration was recorded). A body declared `7BIT` containing 8-bit data is
+/// malformed, and is not altered.
+fn eight_bit_refusal(body_type: Option<MimeBodyType>) -> Delivery {
+    match body_type {
+        Some(MimeBodyType::EightBitMime) | None => Delivery::EightBitMimeNotSupported,
+        Some(MimeBodyType::SevenBit) => Delivery::Permanent {
+            reply: ReplyCode::Enhanced {
+                code: 554,
+                enhanced: "5.6.3".to_owned(),
+            },
+            with_source: Some(
+                "the message declared 7BIT contains 8-bit data, and the server does not support 8BITMIME"
+                    .to_owned(),
+            ),
+        },
+    }
+}
+
+/// The copy of a message containing 8-bit data to send to a server not supporting
+/// 8BITMIME, with its 8-bit parts re-encoded if `downgrade` is enabled, or else the
+/// refusal of the message.
+///
+/// Only the copy sent on the wire is modified, the message in the queue is untouched.
+fn seven_bit_copy(
+    message: &[u8],
+    body_type: Option<MimeBodyType>,
+    downgrade: bool,
+) -> Result<String, Delivery> {
+    match core::str::from_utf8(message) {
+        Ok(message) if downgrade && body_type != Some(MimeBodyType::SevenBit) => {
+            tracing::info!("Server does not support 8BITMIME, re-encoding the message.");
+            Ok(crate::downgrade::to_seven_bit(message).into_owned())
+        }
+        _ => Err(eight_bit_refusal(body_type)),
+    }
+}
+
+/// The header section of `message`, including the line break of the last header.
+#[allow(
+    clippy::indexing_slicing,
+    clippy::arithmetic_side_effects,
+    clippy::integer_arithmetic
+)]
+fn header_section(message: &[u8]) -> &[u8] {
+    if message.starts_with(b"\r\n") {
+        return &[];
+    }
+    message
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .map_or(message, |index| &message[..index + 2])
+}
+
+/// Group the recipients by the headers to add on their copy of the message,
+/// formatted as they should be prepended.
+///
+/// The copy of a catch-all mailbox records the unknown recipients rewritten to it
+/// in `X-Original-To` headers.
+fn split_by_rcpt_headers(
+    rcpt_headers: &std::collections::HashMap<Address, Vec<(String, String)>>,
+    catch_all: &std::collections::HashMap<Address, Vec<Address>>,
+    to: DeliverTo,
+) -> alloc::collections::BTreeMap<String, DeliverTo> {
+    let mut out = alloc::collections::BTreeMap::<String, DeliverTo>::new();
+
+    for (rcpt, status) in to {
+        let original_to = catch_all
+            .get(&rcpt)
+            .map(|originals| {
+                originals
+                    .iter()
+                    .map(|original| format!("X-Original-To: {original}\r\n"))
+                    .collect::<String>()
+            })
+            .unwrap_or_default();
+        let headers = rcpt_headers
+            .get(&rcpt)
+            .map(|headers| {
+                headers
+                    .iter()
+                    .map(|(name, value)| format!("{name}: {value}\r\n"))
+                    .collect::<String>()
+            })
+            .unwrap_or_default();
+        let headers = format!("{original_to}{headers}");
+
+        out.entry(headers).or_default().push((rcpt, status));
+    }
+
+    out
+}
+
 ///
 #[derive(
     Debug,
@@ -205,6 +489,9 @@ pub struct SenderParameters {
     ///
     #[serde(default)]
     pub tls: TlsPolicy,
+    /// Oldest version of TLS accepted from the server, `TLSv1.2` if not set.
+    #[serde(skip)]
+    pub min_tls_version: Option<ProtocolVersion>,
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -329,6 +616,7 @@ impl TryFrom<url::Url> for SenderParameters {
                 )
                 .transpose()?,
             tls: tls_policy,
+            min_tls_version: None,
         })
     }
 }
@@ -359,6 +647,7 @@ impl From<Target> for SenderParameters {
                 port: SMTP_PORT,
                 credentials: None,
                 tls: TlsPolicy::default(),
+                min_tls_version: None,
             },
             Target::Ip(ip) => Self {
                 host: Target::Ip(ip),
@@ -366,6 +655,7 @@ impl From<Target> for SenderParameters {
                 port: SMTP_PORT,
                 credentials: None,
                 tls: TlsPolicy::default(),
+                min_tls_version: None,
             },
             Target::Socket(socket) => Self {
                 host: Target::Ip(socket.ip()),
@@ -373,34 +663,215 @@ impl From<Target> for SenderParameters {
                 port: socket.port(),
                 credentials: None,
                 tls: TlsPolicy::default(),
+                min_tls_version: None,
             },
         }
     }
 }
 
 impl SenderParameters {
-    #[allow(clippy::module_name_repetitions)]
+    /// Apply the use of TLS configured for a destination.
+    #[must_use]
+    #[inline]
+    pub fn with_tls_policy(self, policy: &FieldQueueDeliveryTlsPolicy) -> Self {
+        Self {
+            tls: match policy.starttls {
+                StartTlsPolicy::Enforce => TlsPolicy::StarttlsRequired,
+                StartTlsPolicy::Prefer => TlsPolicy::StarttlsOpportunistic,
+                StartTlsPolicy::Disable => TlsPolicy::None,
+            },
+            min_tls_version: policy.min_version.clone(),
+            ..self
+        }
+    }
+
+    /// Apply the use of TLS required for the recipients to the parameters configured for
+    /// a transport: `policy` can require TLS, but never disables it.
+    #[must_use]
+    pub(crate) fn with_required_tls(self, policy: &FieldQueueDeliveryTlsPolicy) -> Self {
+        Self {
+            tls: match (self.tls, policy.starttls) {
+                (TlsPolicy::None | TlsPolicy::StarttlsOpportunistic, StartTlsPolicy::Enforce) => {
+                    TlsPolicy::StarttlsRequired
+                }
+                (tls, _) => tls,
+            },
+            min_tls_version: crate::tls_policy::stricter_version(
+                self.min_tls_version,
+                policy.min_version.clone(),
+            ),
+            ..self
+        }
+    }
+
+    /// Send the message to the server, `utf8` being set if the message was received
+    /// with `SMTPUTF8`: the extension is then required if the message is not ascii.
+    #[allow(clippy::module_name_repetitions, clippy::too_many_arguments)]
     pub(crate) async fn smtp_send(
         &self,
+        state: &crate::DeliveryState,
         hello_name: &Domain,
         envelop: &lettre::address::Envelope,
         message: &[u8],
+        body_type: Option<MimeBodyType>,
+        utf8: bool,
+        dsn: &DsnParameters,
         certificate: Option<Vec<rustls::Certificate>>,
-    ) -> Result<lettre::transport::smtp::response::Response, Delivery> {
-        use lettre::transport::smtp::{
-            client::{Certificate, Tls, TlsParameters},
-            extension::ClientId,
-        };
+    ) -> Result<RcptReplies, Delivery> {
+        let destination = self.destination();
 
-        let mut builder = lettre::AsyncSmtpTransport::<lettre::Tokio1Executor>::builder_dangerous(
-            self.host.to_string(),
+        state.throttle.admit(&destination)?;
+        let response = self
+            .smtp_exchange(
+                state,
+                hello_name,
+                envelop,
+                message,
+                body_type,
+                utf8,
+                dsn,
+                certificate,
+                None,
+            )
+            .await;
+        state
+            .throttle
+            .record(&destination, response.as_ref().map(|_| ()));
+
+        response
+    }
+
+    /// Open a connection to both `candidates` concurrently, and send the message over the
+    /// first one ready for a transaction (greeted, with `STARTTLS` and the authentication done).
+    /// The other connection attempt is abandoned, its socket being closed before `MAIL FROM`.
+    ///
+    /// Returns the index of the candidate used and the outcome of the exchange,
+    /// or the errors of both connections, in order, if none could be opened.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) async fn smtp_send_race(
+        state: &crate::DeliveryState,
+        candidates: [&Self; 2],
+        hello_name: &Domain,
+        envelop: &lettre::address::Envelope,
+        message: &[u8],
+        body_type: Option<MimeBodyType>,
+        utf8: bool,
+        dsn: &DsnParameters,
+        certificate: Option<Vec<rustls::Certificate>>,
+    ) -> Result<(usize, Result<RcptReplies, Delivery>), [Delivery; 2]> {
+        use futures_util::future::Either;
+
+        let [first, second] = candidates;
+        let (index, params, connection) = match futures_util::future::select(
+            Box::pin(first.connect_throttled(state, hello_name, envelop, certificate.clone())),
+            Box::pin(second.connect_throttled(state, hello_name, envelop, certificate.clone())),
         )
-        .port(self.port)
-        .hello_name(ClientId::Domain(
-            self.hello_name.as_ref().unwrap_or(hello_name).to_string(),
-        ));
+        .await
+        {
+            Either::Left((Ok(connection), _)) => (0, first, connection),
+            Either::Right((Ok(connection), _)) => (1, second, connection),
+            Either::Left((Err(error), other)) => match other.await {
+                Ok(connection) => (1, second, connection),
+                Err(other_error) => return Err([error, other_error]),
+            },
+            Either::Right((Err(error), other)) => match other.await {
+                Ok(connection) => (0, first, connection),
+                Err(other_error) => return Err([other_error, error]),
+            },
+        };
+        tracing::info!(
+            host = %params.host,
+            "Connection ready first, the message is sent to this server."
+        );
+
+        let destination = params.destination();
+        let response = params
+            .smtp_exchange(
+                state,
+                hello_name,
+                envelop,
+                message,
+                body_type,
+                utf8,
+                dsn,
+                certificate,
+                Some(connection),
+            )
+            .await;
+        state
+            .throttle
+            .record(&destination, response.as_ref().map(|_| ()));
+
+        Ok((index, response))
+    }
+
+    /// Open a new connection to the server, paced by the throttle of its destination.
+    async fn connect_throttled(
+        &self,
+        state: &crate::DeliveryState,
+        hello_name: &Domain,
+        envelop: &lettre::address::Envelope,
+        certificate: Option<Vec<rustls::Certificate>>,
+    ) -> Result<crate::reuse::Connection, Delivery> {
+        let destination = self.destination();
+        let (client_id, sender_domain) = self.client_id(state, hello_name, envelop);
+
+        state.throttle.admit(&destination)?;
+        let connection = self
+            .connect(state, &client_id, sender_domain.as_ref(), certificate)
+            .await;
+        if let Err(error) = &connection {
+            tracing::debug!(%destination, %error, "Connection failed.");
+            state.throttle.record(&destination, Err(error));
+        }
+
+        connection
+    }
+
+    /// The host and port of the server, identifying it for the throttle and the reuse
+    /// of the connections.
+    fn destination(&self) -> String {
+        format!("{}:{}", self.host, self.port)
+    }
+
+    /// The name sent in the `EHLO` command, and the domain of the sender of `envelop`.
+    fn client_id(
+        &self,
+        state: &crate::DeliveryState,
+        hello_name: &Domain,
+        envelop: &lettre::address::Envelope,
+    ) -> (lettre::transport::smtp::extension::ClientId, Option<Domain>) {
+        let sender_domain = envelop
+            .from()
+            .and_then(|from| Domain::from_utf8(from.domain()).ok());
+        let client_id = lettre::transport::smtp::extension::ClientId::Domain(
+            self.hello_name
+                .clone()
+                .unwrap_or_else(|| {
+                    state
+                        .hello_names
+                        .resolve(sender_domain.as_ref(), hello_name)
+                })
+                .to_string(),
+        );
+
+        (client_id, sender_domain)
+    }
 
-        if matches!(
+    /// Open a new connection to the server.
+    async fn connect(
+        &self,
+        state: &crate::DeliveryState,
+        hello_name: &lettre::transport::smtp::extension::ClientId,
+        sender_domain: Option<&Domain>,
+        certificate: Option<Vec<rustls::Certificate>>,
+    ) -> Result<crate::reuse::Connection, Delivery> {
+        use lettre::transport::smtp::{
+            authentication::DEFAULT_MECHANISMS,
+            client::{AsyncSmtpConnection, Certificate, Tls, TlsParameters, TlsVersion},
+        };
+
+        let tls = if matches!(
             &self.tls,
             TlsPolicy::StarttlsOpportunistic | TlsPolicy::StarttlsRequired | TlsPolicy::Tunnel
         ) {
@@ -419,32 +890,912 @@ impl SenderParameters {
                 tls_builder = tls_builder.add_root_certificate(Certificate::from_pem(&certs)?);
             }
 
+            if let Some(min_version) = &self.min_tls_version {
+                tls_builder = tls_builder.set_min_tls_version(
+                    if min_version.0 == rustls::ProtocolVersion::TLSv1_3 {
+                        TlsVersion::Tlsv13
+                    } else {
+                        TlsVersion::Tlsv12
+                    },
+                );
+            }
+
             let params = tls_builder.build()?;
 
-            builder = builder.tls(match self.tls {
+            match self.tls {
                 TlsPolicy::StarttlsOpportunistic => Tls::Opportunistic(params),
                 TlsPolicy::StarttlsRequired => Tls::Required(params),
                 TlsPolicy::Tunnel => Tls::Wrapper(params),
                 #[allow(clippy::unreachable)]
                 TlsPolicy::None => unreachable!(),
-            });
+            }
+        } else {
+            Tls::None
+        };
+
+        let source_ip = state.source_ips().next(sender_domain)?;
+
+        // NOTE: the connection is handled here instead of using `lettre::AsyncSmtpTransport`
+        //       to read the extensions advertised by the server before sending the message.
+        let mut conn = AsyncSmtpConnection::connect_tokio1(
+            (self.host.to_string(), self.port),
+            Some(SMTP_TIMEOUT),
+            hello_name,
+            match &tls {
+                Tls::Wrapper(params) => Some(params.clone()),
+                _ => None,
+            },
+            source_ip,
+        )
+        .await?;
+
+        match tls {
+            Tls::Opportunistic(params) if conn.can_starttls() => {
+                conn.starttls(params, hello_name).await?;
+            }
+            Tls::Required(params) => conn.starttls(params, hello_name).await?,
+            _ => (),
         }
 
+        // NOTE: the extensions are read before the authentication.
+        let (capabilities, cached) = state
+            .capabilities
+            .get(&self.host.to_string(), self.port, &mut conn, hello_name)
+            .await;
+        let mut connection = crate::reuse::Connection::new(conn, capabilities, cached);
+
         if let Some(credentials) = &self.credentials {
-            builder = builder.credentials(credentials.clone().into());
-        };
+            connection
+                .conn
+                .auth(DEFAULT_MECHANISMS, &credentials.clone().into())
+                .await?;
+        }
+
+        Ok(connection)
+    }
+
+    /// Send the message over `established` if set, over a connection reused or
+    /// opened otherwise.
+    #[allow(clippy::too_many_arguments, clippy::too_many_lines)]
+    async fn smtp_exchange(
+        &self,
+        state: &crate::DeliveryState,
+        hello_name: &Domain,
+        envelop: &lettre::address::Envelope,
+        message: &[u8],
+        body_type: Option<MimeBodyType>,
+        utf8: bool,
+        dsn: &DsnParameters,
+        certificate: Option<Vec<rustls::Certificate>>,
+        mut established: Option<crate::reuse::Connection>,
+    ) -> Result<RcptReplies, Delivery> {
+        let (hello_name, sender_domain) = self.client_id(state, hello_name, envelop);
+        let destination = self.destination();
+        // NOTE: the connections are reused with the same parameters, and from the same source address.
+        let reuse_key = format!(
+            "{destination} {hello_name} {:?} {:?} {:?} {:?}",
+            self.tls,
+            self.min_tls_version,
+            self.credentials.as_ref().map(|(user, _)| user),
+            sender_domain
+        );
+
+        let mut retried = false;
+        let replies = loop {
+            let reused = match established.take() {
+                Some(connection) => Some(connection),
+                None if retried => None,
+                None => state.reuse.take(&reuse_key).await,
+            };
+            let mut connection = match reused {
+                Some(connection) => connection,
+                None => {
+                    self.connect(
+                        state,
+                        &hello_name,
+                        sender_domain.as_ref(),
+                        certificate.clone(),
+                    )
+                    .await?
+                }
+            };
+            let capabilities = connection.capabilities;
+
+            let dsn = if dsn.is_empty() {
+                None
+            } else if capabilities.dsn {
+                Some(dsn)
+            } else {
+                tracing::debug!(
+                    "The server does not support DSN, the ENVID and ORCPT are not relayed."
+                );
+                None
+            };
 
-        let transport = builder.build();
+            // NOTE: the message is re-encoded on the connection which told the extensions.
+            let seven_bit;
+            let message = if message.is_ascii() || capabilities.eightbitmime {
+                message
+            } else {
+                match seven_bit_copy(message, body_type, state.eightbitmime_downgrade) {
+                    Ok(copy) => {
+                        seven_bit = copy;
+                        seven_bit.as_bytes()
+                    }
+                    Err(error) => {
+                        connection.conn.abort().await;
+                        return Err(error);
+                    }
+                }
+            };
 
-        lettre::AsyncTransport::send_raw(&transport, envelop, message)
+            let mut replies = vec![None; envelop.to().len()];
+            match Self::transactions(
+                &mut connection.conn,
+                &capabilities,
+                envelop,
+                message,
+                utf8,
+                dsn,
+                &mut replies,
+            )
             .await
-            .map_err(Into::into)
+            {
+                Ok(()) => state.reuse.give_back(&reuse_key, connection).await,
+                // nothing has been sent, another exchanger can be tried.
+                Err(error) if replies.iter().all(Option::is_none) => {
+                    connection.conn.abort().await;
+                    if is_parameter_refusal(&error) {
+                        state.capabilities.invalidate(
+                            &self.host.to_string(),
+                            self.port,
+                            connection.conn.is_encrypted(),
+                        );
+                        // the extensions may have changed since they were cached.
+                        if connection.cached && !retried {
+                            tracing::info!(
+                                %destination,
+                                %error,
+                                "Parameters refused, reading the extensions of the server again."
+                            );
+                            retried = true;
+                            continue;
+                        }
+                    }
+                    return Err(error);
+                }
+                Err(error) => {
+                    connection.conn.abort().await;
+                    for reply in replies.iter_mut().filter(|reply| reply.is_none()) {
+                        *reply = Some(Err(error.clone()));
+                    }
+                }
+            }
+            break replies;
+        };
+
+        Ok(replies
+            .into_iter()
+            .map(|reply| {
+                reply.unwrap_or_else(|| {
+                    Err(Delivery::Client {
+                        with_source: Some("the recipient has not been sent".to_owned()),
+                    })
+                })
+            })
+            .collect())
+    }
+
+    /// Send the message to the recipients of the envelope, in as many transactions
+    /// as required by the server, up to [`MAX_TRANSACTIONS`].
+    ///
+    /// The recipients refused with a `452` (too many recipients) are sent in the
+    /// next transaction, the other refusals only concern their recipient, and a
+    /// refusal of the message concerns all the recipients of the transaction.
+    async fn transactions(
+        conn: &mut lettre::transport::smtp::client::AsyncSmtpConnection,
+        capabilities: &crate::capabilities::Capabilities,
+        envelop: &lettre::address::Envelope,
+        message: &[u8],
+        utf8: bool,
+        dsn: Option<&DsnParameters>,
+        replies: &mut [Option<Result<(), Delivery>>],
+    ) -> Result<(), Delivery> {
+        use lettre::transport::smtp::{
+            commands::{Data, Mail, Rcpt, Rset},
+            extension::{MailBodyParameter, MailParameter},
+        };
+
+        // NOTE: a body in 8 bits only requires 8BITMIME.
+        let is_ascii = |address: &lettre::Address| AsRef::<str>::as_ref(address).is_ascii();
+        let non_ascii_addresses = envelop.from().map_or(false, |from| !is_ascii(from))
+            || envelop.to().iter().any(|to| !is_ascii(to));
+        let utf8 = non_ascii_addresses || (utf8 && !header_section(message).is_ascii());
+        let mut parameters = vec![];
+        if utf8 {
+            if !capabilities.smtputf8 {
+                return Err(Delivery::Client {
+                    with_source: Some(
+                        "the message requires SMTPUTF8 but the server does not support it"
+                            .to_owned(),
+                    ),
+                });
+            }
+            parameters.push(MailParameter::SmtpUtfEight);
+        }
+        if !message.is_ascii() {
+            parameters.push(MailParameter::Body(MailBodyParameter::EightBitMime));
+        }
+        if capabilities.size.is_some() {
+            parameters.push(MailParameter::Size(message.len()));
+        }
+        parameters.extend(dsn.and_then(DsnParameters::mail_parameter));
+        let rcpt_parameters = |index: usize| {
+            dsn.map(|dsn| dsn.rcpt_parameters(index, utf8))
+                .unwrap_or_default()
+        };
+
+        let mut set_reply = |index: usize, reply: Result<(), Delivery>| {
+            if let Some(slot) = replies.get_mut(index) {
+                *slot = Some(reply);
+            }
+        };
+
+        let mut pending = envelop.to().iter().enumerate().collect::<Vec<_>>();
+        for transaction in 0..MAX_TRANSACTIONS {
+            conn.command(Mail::new(envelop.from().cloned(), parameters.clone()))
+                .await?;
+
+            let mut accepted = vec![];
+            let mut carried_over = vec![];
+            for (index, rcpt) in pending {
+                match conn
+                    .command(Rcpt::new(rcpt.clone(), rcpt_parameters(index)))
+                    .await
+                {
+                    Ok(_) => accepted.push(index),
+                    // NOTE: the reply is kept if the recipient cannot be sent later.
+                    Err(error) if is_too_many_recipients(&error) => {
+                        set_reply(index, Err(error.into()));
+                        carried_over.push((index, rcpt));
+                    }
+                    Err(error) if is_negative_reply(&error) => set_reply(index, Err(error.into())),
+                    Err(error) => return Err(error.into()),
+                }
+            }
+
+            if accepted.is_empty() {
+                if !carried_over.is_empty() {
+                    tracing::warn!(
+                        remaining = carried_over.len(),
+                        "The server accepts no more recipients, they are deferred."
+                    );
+                }
+                return Ok(());
+            }
+
+            let reply = match conn.command(Data).await {
+                Ok(_) => conn.message(message).await.map(|_| ()),
+                Err(error) if is_negative_reply(&error) => {
+                    conn.command(Rset).await?;
+                    Err(error)
+                }
+                Err(error) => Err(error),
+            };
+            let reply = match reply {
+                Ok(()) => Ok(()),
+                // the message is refused for all the recipients of the transaction.
+                Err(error) if is_negative_reply(&error) => Err(Delivery::from(error)),
+                Err(error) => return Err(error.into()),
+            };
+            for index in accepted {
+                set_reply(index, reply.clone());
+            }
+
+            if carried_over.is_empty() {
+                return Ok(());
+            }
+            tracing::info!(
+                transaction,
+                remaining = carried_over.len(),
+                "The server limits the number of recipients, sending to the remaining ones in a new transaction."
+            );
+            pending = carried_over;
+        }
+
+        tracing::warn!(
+            "Maximum number of transactions reached, the remaining recipients are deferred."
+        );
+        Ok(())
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use vsmtp_common::{
+        addr,
+        transport::{AbstractTransport, GetID, TransportStates},
+    };
+    use vsmtp_test::{
+        config::{local_ctx, local_msg, local_test},
+        sink::Sink,
+    };
+
+    #[derive(serde::Serialize)]
+    struct Recorder {
+        #[serde(skip)]
+        received: std::sync::Mutex<Vec<(String, String)>>,
+    }
+
+    impl GetID for Recorder {}
+
+    #[async_trait::async_trait]
+    impl AbstractTransport for Recorder {
+        async fn deliver(
+            self: alloc::sync::Arc<Self>,
+            _: &TransportStates,
+            _: &ContextFinished,
+            to: DeliverTo,
+            message: &[u8],
+        ) -> DeliverTo {
+            let message = String::from_utf8_lossy(message).into_owned();
+            self.received.lock().unwrap().extend(
+                to.iter()
+                    .map(|(rcpt, _)| (rcpt.to_string(), message.clone())),
+            );
+
+            to.into_iter()
+                .map(|(rcpt, _)| (rcpt, Status::sent()))
+                .collect()
+        }
+    }
+
+    #[tokio::test]
+    async fn rcpt_headers() {
+        let transport = alloc::sync::Arc::new(Recorder {
+            received: std::sync::Mutex::new(vec![]),
+        });
+
+        let mut ctx = local_ctx();
+        ctx.rcpt_to.delivery = [(
+            WrapperSerde::Ready(alloc::sync::Arc::<Recorder>::clone(&transport)),
+            vec![
+                (addr!("a@testserver.com"), Status::default()),
+                (addr!("b@testserver.com"), Status::default()),
+                (addr!("c@testserver.com"), Status::default()),
+            ],
+        )]
+        .into();
+        ctx.finished.rcpt_headers = [(
+            addr!("a@testserver.com"),
+            vec![("X-Mailbox".to_owned(), "a".to_owned())],
+        )]
+        .into();
+        ctx.rcpt_to.catch_all = [(
+            addr!("b@testserver.com"),
+            vec![addr!("x@testserver.com"), addr!("y@testserver.com")],
+        )]
+        .into();
+
+        let msg = local_msg();
+        assert!(matches!(
+            split_and_sort_and_send(
+                alloc::sync::Arc::new(local_test()),
+                &alloc::sync::Arc::default(),
+                &mut ctx,
+                &msg
+            )
+            .await,
+            SenderOutcome::RemoveFromDisk
+        ));
+        assert_eq!(ctx.rcpt_to.delivery.values().flatten().count(), 3);
+
+        let mut received = transport.received.lock().unwrap().clone();
+        received.sort();
+        let content = msg.inner().to_string();
+        assert_eq!(
+            received,
+            vec![
+                (
+                    "a@testserver.com".to_owned(),
+                    format!("X-Mailbox: a\r\n{content}")
+                ),
+                (
+                    "b@testserver.com".to_owned(),
+                    format!("X-Original-To: x@testserver.com\r\nX-Original-To: y@testserver.com\r\n{content}")
+                ),
+                ("c@testserver.com".to_owned(), content),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn catch_all_maildir() {
+        use users::os::unix::UserExt;
+
+        let mailbox = users::get_current_username().unwrap();
+        let mailbox = mailbox.to_str().unwrap();
+        let catch_all = addr!(&format!("{mailbox}@domain.com"));
+
+        let mut ctx = local_ctx();
+        ctx.mail_from.message_uuid = uuid::Uuid::new_v4();
+        ctx.rcpt_to.delivery = [(
+            WrapperSerde::Ready(alloc::sync::Arc::new(crate::Maildir::new(None))),
+            vec![(catch_all.clone(), Status::default())],
+        )]
+        .into();
+        ctx.rcpt_to.catch_all = [(catch_all, vec![addr!("ghost@domain.com")])].into();
+
+        let msg = local_msg();
+        assert!(matches!(
+            split_and_sort_and_send(
+                alloc::sync::Arc::new(local_test()),
+                &alloc::sync::Arc::default(),
+                &mut ctx,
+                &msg
+            )
+            .await,
+            SenderOutcome::RemoveFromDisk
+        ));
+
+        let eml = std::path::PathBuf::from_iter([
+            users::get_user_by_uid(users::get_current_uid())
+                .unwrap()
+                .home_dir(),
+            std::path::Path::new("Maildir/new"),
+            std::path::Path::new(&format!("{}.eml", ctx.mail_from.message_uuid)),
+        ]);
+        assert_eq!(
+            std::fs::read_to_string(eml).unwrap(),
+            format!(
+                "Delivered-To: {mailbox}@domain.com\nX-Original-To: ghost@domain.com\r\n{}",
+                msg.inner()
+            )
+        );
+    }
+
+    #[test]
+    fn header_section_only() {
+        assert_eq!(
+            header_section(b"Subject: \xc3\xa7a va\r\nFrom: a@b\r\n\r\n\xc3\xa7a va\r\n"),
+            b"Subject: \xc3\xa7a va\r\nFrom: a@b\r\n"
+        );
+        assert!(header_section(b"Subject: hello\r\n\r\n\xc3\xa7a va\r\n").is_ascii());
+        assert!(header_section(b"\r\n\xc3\xa7a va\r\n").is_empty());
+        assert_eq!(header_section(b"Subject: hello\r\n"), b"Subject: hello\r\n");
+    }
+
+    #[test]
+    fn eight_bit_refusal_by_body_type() {
+        // re-encoded to 7-bit by `split_and_sort_and_send`
+        for body_type in [Some(MimeBodyType::EightBitMime), None] {
+            let error = eight_bit_refusal(body_type);
+            assert!(matches!(error, Delivery::EightBitMimeNotSupported));
+        }
+
+        let error = eight_bit_refusal(Some(MimeBodyType::SevenBit));
+        assert!(matches!(
+            error,
+            Delivery::Permanent {
+                reply: ReplyCode::Enhanced { code: 554, .. },
+                ..
+            }
+        ));
+    }
+
+    /// A server accepting `max_rcpt` recipients per transaction, refusing the mailboxes
+    /// starting with `full` and the messages sent to a mailbox starting with `spam`.
+    fn scripted_sink(max_rcpt: usize) -> Sink {
+        Sink::builder()
+            .with_capabilities(&["DSN", "8BITMIME"])
+            .with_reply(move |turn| match turn.verb().as_str() {
+                "RCPT" if turn.line.to_ascii_uppercase().contains("<FULL") => {
+                    Some("552 5.2.2 Mailbox full".to_owned())
+                }
+                "RCPT" if turn.rcpt_to.len() >= max_rcpt => {
+                    Some("452 4.5.3 Too many recipients".to_owned())
+                }
+                "." if turn.rcpt_to.iter().any(|r| r.starts_with("<spam")) => {
+                    Some("554 5.7.1 Content rejected".to_owned())
+                }
+                "EHLO" | "MAIL" | "RSET" | "RCPT" | "DATA" | "." | "QUIT" => None,
+                _ => Some("500 5.5.2 Error: command not recognized".to_owned()),
+            })
+            .start()
+    }
+
+    /// The parameters of the `MAIL FROM` commands received by the `sink`, and the
+    /// recipients (with their parameters) of each delivered transaction.
+    fn received(sink: &Sink) -> (Vec<String>, Vec<Vec<String>>) {
+        let session = &sink.wait_for_sessions(1)[0];
+        (
+            session
+                .commands
+                .iter()
+                .filter(|command| command.to_ascii_uppercase().starts_with("MAIL"))
+                .filter_map(|command| command.split_once("> "))
+                .map(|(_, parameters)| parameters.to_owned())
+                .collect(),
+            session
+                .transactions
+                .iter()
+                .map(|transaction| transaction.rcpt_to.clone())
+                .collect(),
+        )
+    }
+
+    async fn send_to_sink(max_rcpt: usize, rcpt: &[&str]) -> (DeliverTo, Vec<Vec<String>>) {
+        let (to, _, delivered) = send_to_sink_with_dsn(max_rcpt, rcpt, &local_ctx()).await;
+        (to, delivered)
+    }
+
+    async fn send_to_sink_with_dsn(
+        max_rcpt: usize,
+        rcpt: &[&str],
+        ctx: &ContextFinished,
+    ) -> (DeliverTo, Vec<String>, Vec<Vec<String>>) {
+        let sink = scripted_sink(max_rcpt);
+
+        let target = Target::Ip("127.0.0.1".parse().unwrap());
+        let params = SenderParameters {
+            port: sink.port(),
+            tls: TlsPolicy::None,
+            ..SenderParameters::from(target.clone())
+        };
+        let mut to = rcpt
+            .iter()
+            .map(|r| (r.parse::<Address>().unwrap(), Status::default()))
+            .collect::<DeliverTo>();
+        let envelop = crate::to_lettre_envelope(
+            &Some(addr!("sender@testserver.com")),
+            to.iter().map(|(r, _)| r),
+        )
+        .unwrap();
+
+        let replies = params
+            .smtp_send(
+                &crate::DeliveryState::default(),
+                &"testserver.com".parse().unwrap(),
+                &envelop,
+                b"Subject: test\r\n\r\nhello\r\n",
+                None,
+                false,
+                &DsnParameters::new(ctx, to.iter().map(|(r, _)| r)),
+                None,
+            )
+            .await
+            .unwrap();
+        apply_rcpt_replies(&target, &mut to, replies);
+
+        let (mail, delivered) = received(&sink);
+        (to, mail, delivered)
+    }
+
+    /// The reply code and text of the last refusal of the recipient.
+    fn refusal(status: &Status) -> Option<(u16, String)> {
+        let error = match status {
+            Status::Failed { error } => error,
+            Status::HeldBack { errors } => errors.last()?,
+            _ => return None,
+        };
+        match error.variant() {
+            Variant::Delivery(attempts) => match attempts.as_slice() {
+                [(
+                    _,
+                    Delivery::Permanent { reply, with_source }
+                    | Delivery::Transient { reply, with_source },
+                )] => Some((reply.value(), with_source.clone().unwrap_or_default())),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    #[tokio::test]
+    async fn too_many_recipients_split() {
+        let (to, delivered) = send_to_sink(
+            2,
+            &[
+                "a@example.com",
+                "b@example.com",
+                "c@example.com",
+                "d@example.com",
+                "e@example.com",
+            ],
+        )
+        .await;
+
+        assert!(to
+            .iter()
+            .all(|(_, status)| matches!(status, Status::Sent { .. })));
+        assert_eq!(
+            delivered,
+            vec![
+                vec!["<a@example.com>", "<b@example.com>"],
+                vec!["<c@example.com>", "<d@example.com>"],
+                vec!["<e@example.com>"],
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn refused_recipient_and_message() {
+        let (to, delivered) = send_to_sink(
+            2,
+            &[
+                "a@example.com",
+                "full@example.com",
+                "b@example.com",
+                "spam@example.com",
+                "c@example.com",
+                "d@example.com",
+            ],
+        )
+        .await;
+
+        let status = |rcpt: &str| &to.iter().find(|(r, _)| r.full() == rcpt).unwrap().1;
+
+        for rcpt in ["a@example.com", "b@example.com"] {
+            assert!(matches!(status(rcpt), Status::Sent { .. }), "{rcpt}");
+        }
+        assert!(matches!(status("full@example.com"), Status::Failed { .. }));
+        assert_eq!(
+            refusal(status("full@example.com")),
+            Some((552, "5.2.2 Mailbox full".to_owned()))
+        );
+
+        // the second transaction is refused at the end of data, for all its recipients.
+        for rcpt in ["spam@example.com", "c@example.com"] {
+            assert!(matches!(status(rcpt), Status::Failed { .. }), "{rcpt}");
+            assert_eq!(
+                refusal(status(rcpt)),
+                Some((554, "5.7.1 Content rejected".to_owned()))
+            );
+        }
+        assert!(matches!(status("d@example.com"), Status::Sent { .. }));
+
+        assert_eq!(
+            delivered,
+            vec![
+                vec!["<a@example.com>", "<b@example.com>"],
+                vec!["<d@example.com>"],
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn no_recipient_accepted() {
+        let (to, delivered) = send_to_sink(0, &["a@example.com", "b@example.com"]).await;
+
+        for (_, status) in &to {
+            assert!(matches!(status, Status::HeldBack { .. }));
+            assert_eq!(
+                refusal(status),
+                Some((452, "4.5.3 Too many recipients".to_owned()))
+            );
+        }
+        assert!(delivered.is_empty());
+    }
+
+    #[tokio::test]
+    async fn dsn_parameters() {
+        let mut ctx = local_ctx();
+        ctx.mail_from.envelop_id = Some("QQ+314159=".to_owned());
+        ctx.rcpt_to.original_recipients.insert(
+            addr!("a@example.com"),
+            OriginalRecipient {
+                addr_type: "rfc822".to_owned(),
+                mailbox: addr!("john+doe@example.com"),
+            },
+        );
+
+        let (to, mail, delivered) =
+            send_to_sink_with_dsn(10, &["a@example.com", "b@example.com"], &ctx).await;
+
+        assert!(to
+            .iter()
+            .all(|(_, status)| matches!(status, Status::Sent { .. })));
+        assert_eq!(mail, vec!["ENVID=QQ+2B314159+3D"]);
+        assert_eq!(
+            delivered,
+            vec![vec![
+                "<a@example.com> ORCPT=rfc822;john+2Bdoe@example.com",
+                "<b@example.com>"
+            ]]
+        );
+    }
+
+    fn to_local_port(port: u16) -> SenderParameters {
+        SenderParameters {
+            port,
+            tls: TlsPolicy::None,
+            ..SenderParameters::from(Target::Ip("127.0.0.1".parse().unwrap()))
+        }
+    }
+
+    #[tokio::test]
+    async fn race_fastest_wins() {
+        for fast in [0, 1] {
+            let fast_server = scripted_sink(10);
+            let slow_server = Sink::builder()
+                .with_greeting_delay(core::time::Duration::from_secs(1))
+                .start();
+            let (fast_params, slow_params) = (
+                to_local_port(fast_server.port()),
+                to_local_port(slow_server.port()),
+            );
+
+            let mut to = vec![(addr!("a@example.com"), Status::default())];
+            let envelop = crate::to_lettre_envelope(
+                &Some(addr!("sender@testserver.com")),
+                to.iter().map(|(r, _)| r),
+            )
+            .unwrap();
+
+            let (index, replies) = SenderParameters::smtp_send_race(
+                &crate::DeliveryState::default(),
+                if fast == 0 {
+                    [&fast_params, &slow_params]
+                } else {
+                    [&slow_params, &fast_params]
+                },
+                &"testserver.com".parse().unwrap(),
+                &envelop,
+                b"Subject: test\r\n\r\nhello\r\n",
+                None,
+                false,
+                &DsnParameters::new(&local_ctx(), to.iter().map(|(r, _)| r)),
+                None,
+            )
+            .await
+            .unwrap();
+            assert_eq!(index, fast);
+
+            apply_rcpt_replies(&fast_params.host, &mut to, replies.unwrap());
+            assert!(matches!(to.as_slice(), [(_, Status::Sent { .. })]));
+            // a single message is delivered, the slow server has been left before any command.
+            assert_eq!(received(&fast_server).1, vec![vec!["<a@example.com>"]]);
+            assert!(slow_server.wait_for_sessions(1)[0].commands.is_empty());
+        }
+    }
+
+    #[tokio::test]
+    async fn race_without_server() {
+        // nobody is listening on these ports
+        let ports = [(); 2].map(|()| {
+            std::net::TcpListener::bind("127.0.0.1:0")
+                .unwrap()
+                .local_addr()
+                .unwrap()
+                .port()
+        });
+        let [first, second] = ports.map(to_local_port);
+
+        let to = [addr!("a@example.com")];
+        let envelop =
+            crate::to_lettre_envelope(&Some(addr!("sender@testserver.com")), to.iter()).unwrap();
+
+        let errors = SenderParameters::smtp_send_race(
+            &crate::DeliveryState::default(),
+            [&first, &second],
+            &"testserver.com".parse().unwrap(),
+            &envelop,
+            b"Subject: test\r\n\r\nhello\r\n",
+            None,
+            false,
+            &DsnParameters::new(&local_ctx(), to.iter()),
+            None,
+        )
+        .await
+        .unwrap_err();
+        assert!(errors
+            .iter()
+            .all(|error| matches!(error, Delivery::Connection { .. })));
+    }
+
+    /// A message to a local mailbox, delivered with the `maildir` transport,
+    /// and to a remote one, forwarded to a server listening on `port`.
+    fn local_and_remote(port: u16) -> (ContextFinished, WrapperSerde, WrapperSerde) {
+        let mailbox = users::get_current_username().unwrap();
+        let (local, remote) = (
+            WrapperSerde::Ready(alloc::sync::Arc::new(crate::Maildir::new(None))),
+            WrapperSerde::Ready(alloc::sync::Arc::new(crate::Forward::new(
+                SenderParameters {
+                    port,
+                    tls: TlsPolicy::None,
+                    ..SenderParameters::from(Target::Ip("127.0.0.1".parse().unwrap()))
+                },
+            ))),
+        );
+
+        let mut ctx = local_ctx();
+        ctx.rcpt_to.delivery = [
+            (
+                local.clone(),
+                vec![(
+                    addr!(&format!("{}@testserver.com", mailbox.to_str().unwrap())),
+                    Status::default(),
+                )],
+            ),
+            (
+                remote.clone(),
+                vec![(addr!("remote@example.com"), Status::default())],
+            ),
+        ]
+        .into();
+
+        (ctx, local, remote)
+    }
+
+    #[tokio::test]
+    async fn local_and_remote_delivered() {
+        let sink = scripted_sink(10);
+
+        let (mut ctx, local, remote) = local_and_remote(sink.port());
+        assert!(matches!(
+            split_and_sort_and_send(
+                alloc::sync::Arc::new(local_test()),
+                &alloc::sync::Arc::default(),
+                &mut ctx,
+                &local_msg()
+            )
+            .await,
+            SenderOutcome::RemoveFromDisk
+        ));
+
+        assert_eq!(ctx.rcpt_to.delivery.len(), 2);
+        for transport in [&local, &remote] {
+            assert!(matches!(
+                ctx.rcpt_to.delivery[transport].as_slice(),
+                [(_, Status::Sent { .. })]
+            ));
+        }
+        assert_eq!(received(&sink).1, vec![vec!["<remote@example.com>"]]);
+    }
+
+    #[tokio::test]
+    async fn remote_held_back_keeps_local_status() {
+        // nobody is listening on this port, the remote recipient is held back
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let config = alloc::sync::Arc::new(local_test());
+
+        let (mut ctx, local, remote) = local_and_remote(port);
+        assert!(matches!(
+            split_and_sort_and_send(
+                config.clone(),
+                &alloc::sync::Arc::default(),
+                &mut ctx,
+                &local_msg()
+            )
+            .await,
+            SenderOutcome::MoveToDeferred
+        ));
+
+        let sent = ctx.rcpt_to.delivery[&local].clone();
+        assert!(matches!(sent.as_slice(), [(_, Status::Sent { .. })]));
+        assert!(matches!(
+            ctx.rcpt_to.delivery[&remote].as_slice(),
+            [(_, Status::HeldBack { errors })] if errors.len() == 1
+        ));
+
+        // the next attempt is only for the remote recipient.
+        assert!(matches!(
+            split_and_sort_and_send(config, &alloc::sync::Arc::default(), &mut ctx, &local_msg())
+                .await,
+            SenderOutcome::MoveToDeferred
+        ));
+
+        assert_eq!(ctx.rcpt_to.delivery[&local], sent);
+        assert!(matches!(
+            ctx.rcpt_to.delivery[&remote].as_slice(),
+            [(_, Status::HeldBack { errors })] if errors.len() == 2
+        ));
+    }
 
     #[rstest::rstest]
     fn parse(
@@ -0,0 +1,246 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2022 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use std::net::IpAddr;
+use vsmtp_common::{transfer::error::Delivery, Domain};
+use vsmtp_config::Config;
+
+#[derive(Debug)]
+struct Registry {
+    pool: Vec<IpAddr>,
+    virtual_pools: alloc::collections::BTreeMap<Domain, Vec<IpAddr>>,
+    /// The blocklists of the addresses removed from the rotation.
+    listed: alloc::collections::BTreeMap<IpAddr, Vec<String>>,
+    /// Position of the rotation.
+    next: usize,
+}
+
+/// The local addresses the outgoing connections are bound to, and their health.
+///
+/// An address listed on a DNS blocklist is removed from the rotation until it is
+/// delisted, see `config.server.queues.delivery.source_ips`.
+#[derive(Debug)]
+pub struct SourceIpPool {
+    registry: std::sync::Mutex<Registry>,
+}
+
+impl Default for SourceIpPool {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SourceIpPool {
+    /// Create an empty pool, the system chooses the local addresses.
+    #[must_use]
+    #[inline]
+    pub const fn new() -> Self {
+        Self {
+            registry: std::sync::Mutex::new(Registry {
+                pool: vec![],
+                virtual_pools: alloc::collections::BTreeMap::new(),
+                listed: alloc::collections::BTreeMap::new(),
+                next: 0,
+            }),
+        }
+    }
+
+    fn with_registry<R>(&self, f: impl FnOnce(&mut Registry) -> R) -> R {
+        let mut registry = self
+            .registry
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        f(&mut registry)
+    }
+
+    /// Set the addresses of the pool and of the virtual entries, the health of the
+    /// addresses still configured is kept.
+    #[inline]
+    pub fn configure(&self, config: &Config) {
+        self.with_registry(|registry| {
+            registry
+                .pool
+                .clone_from(&config.server.queues.delivery.source_ips.pool);
+            registry.virtual_pools = config
+                .server
+                .r#virtual
+                .iter()
+                .filter(|(_, entry)| !entry.source_ips.is_empty())
+                .map(|(domain, entry)| (domain.clone(), entry.source_ips.clone()))
+                .collect();
+
+            let configured = registry
+                .pool
+                .iter()
+                .chain(registry.virtual_pools.values().flatten())
+                .copied()
+                .collect::<alloc::collections::BTreeSet<_>>();
+            registry.listed.retain(|ip, _| configured.contains(ip));
+        });
+    }
+
+    /// Every configured address, once.
+    #[must_use]
+    #[inline]
+    pub fn addresses(&self) -> Vec<IpAddr> {
+        self.with_registry(|registry| {
+            registry
+                .pool
+                .iter()
+                .chain(registry.virtual_pools.values().flatten())
+                .copied()
+                .collect::<alloc::collections::BTreeSet<_>>()
+                .into_iter()
+                .collect()
+        })
+    }
+
+    /// Choose the local address of the next connection of a message sent by `sender_domain`,
+    /// in the addresses of its virtual entry if any, or else in the pool.
+    ///
+    /// Returns `None` if no address is configured, the system chooses it.
+    ///
+    /// # Errors
+    ///
+    /// * all the addresses are listed on a blocklist, the message must be deferred
+    #[inline]
+    #[allow(clippy::arithmetic_side_effects, clippy::integer_arithmetic)]
+    pub fn next(&self, sender_domain: Option<&Domain>) -> Result<Option<IpAddr>, Delivery> {
+        self.with_registry(|registry| {
+            let candidates = sender_domain
+                .and_then(|domain| registry.virtual_pools.get(domain))
+                .unwrap_or(&registry.pool);
+            if candidates.is_empty() {
+                return Ok(None);
+            }
+
+            let healthy = candidates
+                .iter()
+                .filter(|ip| !registry.listed.contains_key(ip))
+                .collect::<Vec<_>>();
+            let Some(ip) = healthy.get(registry.next % healthy.len().max(1)) else {
+                return Err(Delivery::Connection {
+                    with_source: Some(
+                        "all the source addresses are listed on a blocklist".to_owned(),
+                    ),
+                });
+            };
+            registry.next = registry.next.wrapping_add(1);
+
+            Ok(Some(**ip))
+        })
+    }
+
+    /// Record the blocklists `ip` is listed on, the address is removed from the rotation
+    /// if it is listed, and restored once the list is empty.
+    ///
+    /// Returns `true` if the address has been removed or restored.
+    #[inline]
+    pub fn set_listings(&self, ip: IpAddr, listings: Vec<String>) -> bool {
+        self.with_registry(|registry| {
+            if listings.is_empty() {
+                registry.listed.remove(&ip).is_some()
+            } else {
+                registry.listed.insert(ip, listings).is_none()
+            }
+        })
+    }
+
+    /// The configured addresses with the blocklists they are listed on, empty if the
+    /// address is healthy.
+    #[must_use]
+    #[inline]
+    pub fn health(&self) -> Vec<(IpAddr, Vec<String>)> {
+        let addresses = self.addresses();
+        self.with_registry(|registry| {
+            addresses
+                .into_iter()
+                .map(|ip| (ip, registry.listed.get(&ip).cloned().unwrap_or_default()))
+                .collect()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(pool: &[&str], virtual_pool: &[&str]) -> Config {
+        let mut config = vsmtp_test::config::local_test();
+        config.server.queues.delivery.source_ips.pool =
+            pool.iter().map(|ip| ip.parse().unwrap()).collect();
+        config.server.r#virtual.insert(
+            "example.com".parse().unwrap(),
+            vsmtp_config::field::FieldServerVirtual {
+                source_ips: virtual_pool.iter().map(|ip| ip.parse().unwrap()).collect(),
+                ..Default::default()
+            },
+        );
+        config
+    }
+
+    #[test]
+    fn no_address() {
+        let pool = SourceIpPool::new();
+        pool.configure(&config(&[], &[]));
+
+        assert_eq!(pool.next(None).unwrap(), None);
+        assert!(pool.addresses().is_empty());
+    }
+
+    #[test]
+    fn rotation() {
+        let pool = SourceIpPool::new();
+        pool.configure(&config(&["192.0.2.1", "192.0.2.2"], &["198.51.100.1"]));
+
+        let picked = (0..4)
+            .map(|_| pool.next(None).unwrap().unwrap().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(picked, ["192.0.2.1", "192.0.2.2", "192.0.2.1", "192.0.2.2"]);
+
+        let example = "example.com".parse::<Domain>().unwrap();
+        assert_eq!(
+            pool.next(Some(&example)).unwrap(),
+            Some("198.51.100.1".parse().unwrap())
+        );
+        assert_eq!(pool.addresses().len(), 3);
+    }
+
+    #[test]
+    fn listed() {
+        let pool = SourceIpPool::new();
+        pool.configure(&config(&["192.0.2.1", "192.0.2.2"], &[]));
+        let ip = "192.0.2.1".parse().unwrap();
+
+        assert!(pool.set_listings(ip, vec!["zen.spamhaus.org".to_owned()]));
+        assert!(!pool.set_listings(ip, vec!["zen.spamhaus.org".to_owned()]));
+        for _ in 0..4 {
+            assert_eq!(pool.next(None).unwrap(), Some("192.0.2.2".parse().unwrap()));
+        }
+
+        pool.set_listings("192.0.2.2".parse().unwrap(), vec!["bl.example".to_owned()]);
+        assert!(pool.next(None).is_err());
+
+        // a delisted address is used again.
+        assert!(pool.set_listings(ip, vec![]));
+        assert_eq!(pool.next(None).unwrap(), Some(ip));
+
+        // the state of the addresses removed from the configuration is dropped.
+        pool.configure(&config(&["192.0.2.1"], &[]));
+        assert_eq!(pool.health(), vec![(ip, vec![])]);
+    }
+}
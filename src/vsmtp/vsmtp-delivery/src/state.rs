@@ -0,0 +1,111 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2023 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use crate::SourceIpPool;
+use vsmtp_common::transport::TransportStates;
+use vsmtp_config::Config;
+
+/// The state of the outgoing connections, shared by the deliveries of a runtime:
+/// the pacing of the throttled destinations, the connections kept open, the extensions
+/// of the servers, the policies of the `EHLO` name and of TLS, and the source addresses.
+///
+/// It is created from the configuration by the runtime, and given to
+/// [`split_and_sort_and_send`](crate::split_and_sort_and_send), which gives it to the transports.
+#[derive(Default)]
+#[allow(clippy::module_name_repetitions)]
+pub struct DeliveryState {
+    pub(crate) throttle: crate::throttle::Throttle,
+    pub(crate) reuse: crate::reuse::Pool,
+    pub(crate) capabilities: crate::capabilities::Cache,
+    pub(crate) hello_names: crate::hello_name::Policies,
+    pub(crate) tls_policies: crate::tls_policy::Policies,
+    /// Re-encode the messages containing 8-bit data for the servers not supporting 8BITMIME.
+    pub(crate) eightbitmime_downgrade: bool,
+    source_ips: alloc::sync::Arc<SourceIpPool>,
+}
+
+impl core::fmt::Debug for DeliveryState {
+    #[inline]
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("DeliveryState")
+            .field("source_ips", &self.source_ips)
+            .finish_non_exhaustive()
+    }
+}
+
+impl DeliveryState {
+    /// Create the state of the deliveries, from the configuration.
+    #[must_use]
+    #[inline]
+    pub fn new(config: &Config) -> Self {
+        let delivery = &config.server.queues.delivery;
+        let source_ips = SourceIpPool::new();
+        source_ips.configure(config);
+
+        Self {
+            throttle: crate::throttle::Throttle::new(&delivery.throttle),
+            reuse: crate::reuse::Pool::new(&delivery.reuse),
+            capabilities: crate::capabilities::Cache::new(&delivery.capabilities),
+            hello_names: crate::hello_name::Policies::new(config),
+            tls_policies: crate::tls_policy::Policies::new(&delivery.tls),
+            eightbitmime_downgrade: delivery.eightbitmime_downgrade,
+            source_ips: alloc::sync::Arc::new(source_ips),
+        }
+    }
+
+    /// The local addresses the outgoing connections are bound to.
+    #[must_use]
+    #[inline]
+    pub const fn source_ips(&self) -> &alloc::sync::Arc<SourceIpPool> {
+        &self.source_ips
+    }
+
+    /// Remove the cached extensions of the servers of `domain` and of its sub-domains,
+    /// returning their destinations (`host:port`).
+    #[must_use]
+    #[inline]
+    pub fn flush_capabilities(&self, domain: &str) -> Vec<String> {
+        self.capabilities.flush(domain)
+    }
+
+    /// Close the connections kept open for longer than `reuse.idle_timeout`, checking
+    /// every half of it. Returns immediately if the reuse is disabled.
+    #[inline]
+    pub async fn sweep_idle_connections(self: alloc::sync::Arc<Self>) {
+        let Some(period) = self.reuse.sweep_period() else {
+            return;
+        };
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            self.reuse.sweep().await;
+        }
+    }
+
+    /// The state stored in `states` by [`split_and_sort_and_send`](crate::split_and_sort_and_send).
+    ///
+    /// The transports run without it fall back on an empty state, stored in `fallback`:
+    /// their outgoing connections are then neither paced nor kept open.
+    pub(crate) fn from_states<'state>(
+        states: &'state TransportStates,
+        fallback: &'state mut Option<Self>,
+    ) -> &'state Self {
+        states.get::<Self>().unwrap_or_else(|| {
+            tracing::warn!("Transport run without the state of the deliveries.");
+            fallback.get_or_insert_with(Self::default)
+        })
+    }
+}
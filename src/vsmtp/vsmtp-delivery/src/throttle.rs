@@ -0,0 +1,237 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2023 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use vsmtp_common::{clock, transfer::error::Delivery};
+use vsmtp_config::field::FieldQueueDeliveryThrottle;
+
+/// Spacing of the connections to one destination.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Pacing {
+    interval: std::time::Duration,
+    /// Earliest time of the next connection.
+    next: time::OffsetDateTime,
+    /// Time of the last rate-limit response.
+    last_limited: time::OffsetDateTime,
+}
+
+/// The pacing of the destinations which replied with rate-limit responses.
+#[derive(Default)]
+pub(crate) struct Throttle {
+    parameters: Option<FieldQueueDeliveryThrottle>,
+    destinations: std::sync::Mutex<alloc::collections::BTreeMap<String, Pacing>>,
+}
+
+impl Throttle {
+    /// Create the throttle, from the configuration of the delivery.
+    pub(crate) fn new(parameters: &FieldQueueDeliveryThrottle) -> Self {
+        Self {
+            parameters: parameters.enable.then(|| FieldQueueDeliveryThrottle {
+                enable: parameters.enable,
+                delay: parameters.delay,
+                delay_max: parameters.delay_max,
+                cooldown: parameters.cooldown,
+            }),
+            destinations: std::sync::Mutex::default(),
+        }
+    }
+
+    fn with_destinations<R>(
+        &self,
+        f: impl FnOnce(&mut alloc::collections::BTreeMap<String, Pacing>) -> R,
+    ) -> R {
+        let mut destinations = self
+            .destinations
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        f(&mut destinations)
+    }
+
+    /// Reserve the slot of a connection to the destination, or return the time left
+    /// before the next slot if it is throttled.
+    #[allow(clippy::arithmetic_side_effects, clippy::integer_arithmetic)]
+    fn reserve(&self, destination: &str) -> Result<(), std::time::Duration> {
+        let now = clock::now();
+
+        self.with_destinations(|destinations| {
+            let Some(pacing) = destinations.get_mut(destination) else {
+                return Ok(());
+            };
+
+            if now < pacing.next {
+                return Err((pacing.next - now).try_into().unwrap_or_default());
+            }
+            pacing.next = now + pacing.interval;
+            Ok(())
+        })
+    }
+
+    /// Admit a new connection to the destination.
+    ///
+    /// # Errors
+    ///
+    /// * the destination is throttled and its next slot is not reached: the connection is
+    ///   not opened and the message is deferred, instead of holding the delivery
+    pub(crate) fn admit(&self, destination: &str) -> Result<(), Delivery> {
+        self.reserve(destination).map_err(|delay| {
+            tracing::info!(%destination, ?delay, "Destination throttled, deferring the connection.");
+            Delivery::Connection {
+                with_source: Some(format!(
+                    "destination throttled, next connection in {}s",
+                    delay.as_secs().max(1)
+                )),
+            }
+        })
+    }
+
+    /// Update the pacing of the destination with the outcome of a connection.
+    #[allow(clippy::arithmetic_side_effects, clippy::integer_arithmetic)]
+    pub(crate) fn record(&self, destination: &str, outcome: Result<(), &Delivery>) {
+        let Some(parameters) = self.parameters.as_ref() else {
+            return;
+        };
+        let now = clock::now();
+
+        self.with_destinations(|destinations| match outcome {
+            Err(error) if error.is_rate_limited() => {
+                let pacing = destinations
+                    .entry(destination.to_owned())
+                    .and_modify(|pacing| {
+                        pacing.interval = pacing
+                            .interval
+                            .saturating_mul(2)
+                            .clamp(parameters.delay, parameters.delay_max);
+                        pacing.last_limited = now;
+                    })
+                    .or_insert(Pacing {
+                        interval: parameters.delay.min(parameters.delay_max),
+                        next: now,
+                        last_limited: now,
+                    });
+                pacing.next = pacing.next.max(now + pacing.interval);
+
+                tracing::warn!(
+                    %destination,
+                    interval = ?pacing.interval,
+                    "Rate-limit response received, slowing down the deliveries."
+                );
+            }
+            Ok(()) => {
+                let Some(pacing) = destinations.get_mut(destination) else {
+                    return;
+                };
+                if now < pacing.last_limited + parameters.cooldown {
+                    return;
+                }
+
+                pacing.interval /= 2;
+                if pacing.interval < parameters.delay {
+                    tracing::info!(%destination, "Destination recovered, no longer throttled.");
+                    destinations.remove(destination);
+                }
+            }
+            Err(_) => {}
+        });
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::indexing_slicing)]
+mod tests {
+    use super::*;
+    use vsmtp_common::ReplyCode;
+
+    fn transient(code: u16, text: &str) -> Delivery {
+        Delivery::Transient {
+            reply: ReplyCode::Code { code },
+            with_source: Some(text.to_owned()),
+        }
+    }
+
+    #[test]
+    fn slow_down_and_recover() {
+        let throttle = Throttle::new(&FieldQueueDeliveryThrottle {
+            enable: true,
+            delay: std::time::Duration::from_secs(10),
+            delay_max: std::time::Duration::from_secs(30),
+            cooldown: std::time::Duration::from_secs(60),
+        });
+        let interval = |destination: &str| {
+            throttle.with_destinations(|destinations| {
+                destinations.get(destination).map(|pacing| pacing.interval)
+            })
+        };
+
+        let destination = "mx.example.com:25";
+        let start = time::OffsetDateTime::UNIX_EPOCH;
+        clock::mock::set(start);
+
+        assert_eq!(throttle.reserve(destination), Ok(()));
+
+        let limited = transient(421, "Too many connections");
+        throttle.record(destination, Err(&limited));
+        assert_eq!(
+            throttle.reserve(destination),
+            Err(std::time::Duration::from_secs(10))
+        );
+        assert!(throttle.admit(destination).is_err());
+
+        // the slot is reserved by the first connection once reached.
+        clock::mock::advance(time::Duration::seconds(10));
+        assert_eq!(throttle.reserve(destination), Ok(()));
+        assert_eq!(
+            throttle.reserve(destination),
+            Err(std::time::Duration::from_secs(10))
+        );
+
+        // doubled on each response, up to the maximum
+        throttle.record(destination, Err(&limited));
+        throttle.record(destination, Err(&limited));
+        assert_eq!(
+            interval(destination),
+            Some(std::time::Duration::from_secs(30))
+        );
+
+        // not recovering during the cooldown
+        throttle.record(destination, Ok(()));
+        assert_eq!(
+            interval(destination),
+            Some(std::time::Duration::from_secs(30))
+        );
+
+        clock::mock::advance(time::Duration::minutes(2));
+        throttle.record(destination, Ok(()));
+        assert_eq!(
+            interval(destination),
+            Some(std::time::Duration::from_secs(15))
+        );
+        throttle.record(destination, Ok(()));
+        assert_eq!(interval(destination), None);
+        assert_eq!(throttle.reserve(destination), Ok(()));
+
+        clock::mock::reset();
+    }
+
+    #[test]
+    fn disabled() {
+        let throttle = Throttle::default();
+
+        throttle.record(
+            "mx.example.com:25",
+            Err(&transient(421, "Too many connections")),
+        );
+        assert_eq!(throttle.reserve("mx.example.com:25"), Ok(()));
+    }
+}
@@ -0,0 +1,155 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2023 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use vsmtp_common::{domain_iter, Domain, ProtocolVersion};
+use vsmtp_config::field::{FieldQueueDeliveryTls, FieldQueueDeliveryTlsPolicy, StartTlsPolicy};
+
+/// The policies of TLS of the deliveries, by domain.
+#[derive(Default)]
+pub(crate) struct Policies {
+    default: FieldQueueDeliveryTlsPolicy,
+    /// The policies by domain, lowercase and without the trailing dot.
+    destinations: alloc::collections::BTreeMap<String, FieldQueueDeliveryTlsPolicy>,
+}
+
+fn key(domain: &str) -> String {
+    domain.trim_end_matches('.').to_ascii_lowercase()
+}
+
+/// The stricter of two oldest versions of TLS accepted.
+pub(crate) fn stricter_version(
+    lhs: Option<ProtocolVersion>,
+    rhs: Option<ProtocolVersion>,
+) -> Option<ProtocolVersion> {
+    match (lhs, rhs) {
+        (Some(lhs), Some(rhs)) => Some(if rhs.0 == rustls::ProtocolVersion::TLSv1_3 {
+            rhs
+        } else {
+            lhs
+        }),
+        (version, None) | (None, version) => version,
+    }
+}
+
+impl Policies {
+    /// Read the policies, from the configuration of the delivery.
+    pub(crate) fn new(tls: &FieldQueueDeliveryTls) -> Self {
+        Self {
+            default: tls.default.clone(),
+            destinations: tls
+                .destinations
+                .iter()
+                .map(|(domain, policy)| (key(&domain.to_string()), policy.clone()))
+                .collect(),
+        }
+    }
+
+    /// The policy of the messages to `domain`: the one of its entry, or of the entry of
+    /// its closest parent, or else the default one.
+    pub(crate) fn resolve(&self, domain: &Domain) -> FieldQueueDeliveryTlsPolicy {
+        let domain = key(&domain.to_string());
+        domain_iter(&domain)
+            .find_map(|parent| self.destinations.get(parent))
+            .unwrap_or(&self.default)
+            .clone()
+    }
+
+    /// The strictest of the policies of `domains`, whose messages are sent on the same
+    /// connection, or the default one if there is none.
+    pub(crate) fn resolve_strictest<'domain>(
+        &self,
+        domains: impl Iterator<Item = &'domain Domain>,
+    ) -> FieldQueueDeliveryTlsPolicy {
+        domains
+            .map(|domain| self.resolve(domain))
+            .reduce(|strictest, policy| FieldQueueDeliveryTlsPolicy {
+                starttls: match (strictest.starttls, policy.starttls) {
+                    (StartTlsPolicy::Enforce, _) | (_, StartTlsPolicy::Enforce) => {
+                        StartTlsPolicy::Enforce
+                    }
+                    (StartTlsPolicy::Prefer, _) | (_, StartTlsPolicy::Prefer) => {
+                        StartTlsPolicy::Prefer
+                    }
+                    (StartTlsPolicy::Disable, StartTlsPolicy::Disable) => StartTlsPolicy::Disable,
+                },
+                min_version: stricter_version(strictest.min_version, policy.min_version),
+            })
+            .unwrap_or_else(|| self.default.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vsmtp_config::field::StartTlsPolicy;
+
+    fn policy(starttls: StartTlsPolicy) -> FieldQueueDeliveryTlsPolicy {
+        FieldQueueDeliveryTlsPolicy {
+            starttls,
+            min_version: None,
+        }
+    }
+
+    #[test]
+    fn by_domain() {
+        let strict = FieldQueueDeliveryTlsPolicy {
+            starttls: StartTlsPolicy::Enforce,
+            min_version: Some("TLSv1.3".parse().unwrap()),
+        };
+        let policies = Policies::new(&FieldQueueDeliveryTls {
+            default: policy(StartTlsPolicy::Prefer),
+            destinations: [
+                ("bank.example".parse().unwrap(), strict.clone()),
+                (
+                    "legacy.bank.example".parse().unwrap(),
+                    policy(StartTlsPolicy::Disable),
+                ),
+            ]
+            .into_iter()
+            .collect(),
+        });
+
+        let resolve = |domain: &str| policies.resolve(&domain.parse().unwrap());
+        assert_eq!(resolve("bank.example"), strict);
+        assert_eq!(resolve("Mail.Bank.Example."), strict);
+        assert_eq!(
+            resolve("legacy.bank.example"),
+            policy(StartTlsPolicy::Disable)
+        );
+        assert_eq!(
+            resolve("mx.legacy.bank.example"),
+            policy(StartTlsPolicy::Disable)
+        );
+        assert_eq!(resolve("example.com"), policy(StartTlsPolicy::Prefer));
+        assert_eq!(resolve("notbank.example"), policy(StartTlsPolicy::Prefer));
+
+        let strictest = |domains: &[&str]| {
+            policies.resolve_strictest(
+                domains
+                    .iter()
+                    .map(|domain| domain.parse().unwrap())
+                    .collect::<Vec<_>>()
+                    .iter(),
+            )
+        };
+        assert_eq!(strictest(&["example.com", "bank.example"]), strict);
+        assert_eq!(
+            strictest(&["legacy.bank.example", "example.com"]),
+            policy(StartTlsPolicy::Prefer)
+        );
+        assert_eq!(strictest(&[]), policy(StartTlsPolicy::Prefer));
+    }
+}
@@ -0,0 +1,127 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2023 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use vsmtp_common::{transfer::Status, transport::WrapperSerde, Target};
+use vsmtp_config::Config;
+use vsmtp_delivery::{
+    split_and_sort_and_send, DeliveryState, Forward, SenderParameters, TlsPolicy,
+};
+use vsmtp_test::{
+    config::{local_ctx, local_msg, local_test},
+    sink::{Session, Sink},
+};
+
+/// A server advertising DSN for its first message, and refusing the parameters of
+/// `MAIL FROM` afterward.
+fn migrated_sink() -> Sink {
+    Sink::builder()
+        .with_reply(|turn| match turn.verb().as_str() {
+            "EHLO" if turn.received == 0 => Some("250-sink\r\n250 DSN".to_owned()),
+            "MAIL" if turn.received > 0 && turn.line.contains("> ") => {
+                Some("555 5.5.4 Unsupported option".to_owned())
+            }
+            _ => None,
+        })
+        .start()
+}
+
+/// The verbs received by the `sink`, with the parameters of `MAIL FROM`.
+fn commands(session: &Session) -> Vec<String> {
+    session
+        .commands
+        .iter()
+        .zip(session.verbs())
+        .map(|(command, verb)| match command.split_once("> ") {
+            Some((_, parameters)) if verb == "MAIL" => format!("MAIL {parameters}"),
+            _ => verb,
+        })
+        .collect()
+}
+
+async fn send(config: &std::sync::Arc<Config>, state: &std::sync::Arc<DeliveryState>, port: u16) {
+    let mut ctx = local_ctx();
+    ctx.mail_from.envelop_id = Some("QQ314159".to_owned());
+    let transport = Forward::new(SenderParameters {
+        port,
+        tls: TlsPolicy::None,
+        ..SenderParameters::from(Target::Ip("127.0.0.1".parse().unwrap()))
+    });
+    ctx.rcpt_to.delivery = std::collections::HashMap::from([(
+        WrapperSerde::Ready(std::sync::Arc::new(transport)),
+        vec![("jenny@example.com".parse().unwrap(), Status::default())],
+    )]);
+
+    let _ = split_and_sort_and_send(config.clone(), state, &mut ctx, &local_msg()).await;
+    assert!(ctx
+        .rcpt_to
+        .delivery
+        .values()
+        .flatten()
+        .all(|(_, status)| matches!(status, Status::Sent { .. })));
+}
+
+#[tokio::test]
+async fn extensions_read_again_after_a_refusal() {
+    let sink = migrated_sink();
+
+    let config = std::sync::Arc::new(local_test());
+    let state = std::sync::Arc::new(DeliveryState::new(&config));
+    send(&config, &state, sink.port()).await;
+    send(&config, &state, sink.port()).await;
+
+    assert_eq!(
+        sink.wait_for_sessions(3)
+            .iter()
+            .map(commands)
+            .collect::<Vec<_>>(),
+        vec![
+            vec![
+                "EHLO",
+                "EHLO",
+                "MAIL ENVID=QQ314159",
+                "RCPT",
+                "DATA",
+                "QUIT"
+            ],
+            // the cached extensions are relied on, and refused.
+            vec!["EHLO", "MAIL ENVID=QQ314159", "QUIT"],
+            // the extensions are read again, the message is sent without DSN.
+            vec!["EHLO", "EHLO", "MAIL", "RCPT", "DATA", "QUIT"],
+        ]
+    );
+}
+
+#[tokio::test]
+async fn extensions_cached_without_ehlo() {
+    let sink = Sink::start();
+
+    let config = std::sync::Arc::new(local_test());
+    let state = std::sync::Arc::new(DeliveryState::new(&config));
+    send(&config, &state, sink.port()).await;
+    send(&config, &state, sink.port()).await;
+
+    assert_eq!(
+        sink.wait_for_sessions(2)
+            .iter()
+            .map(Session::verbs)
+            .collect::<Vec<_>>(),
+        vec![
+            vec!["EHLO", "EHLO", "MAIL", "RCPT", "DATA", "QUIT"],
+            // only the `EHLO` opening the connection is sent.
+            vec!["EHLO", "MAIL", "RCPT", "DATA", "QUIT"],
+        ]
+    );
+}
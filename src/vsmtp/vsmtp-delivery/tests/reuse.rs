@@ -0,0 +1,103 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2023 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use vsmtp_common::{transfer::Status, transport::WrapperSerde, Target};
+use vsmtp_config::{field::FieldQueueDeliveryReuse, Config};
+use vsmtp_delivery::{
+    split_and_sort_and_send, DeliveryState, Forward, SenderParameters, TlsPolicy,
+};
+use vsmtp_test::{
+    config::{local_ctx, local_msg, local_test},
+    sink::{Session, Sink},
+};
+
+async fn send(config: &std::sync::Arc<Config>, state: &std::sync::Arc<DeliveryState>, port: u16) {
+    let mut ctx = local_ctx();
+    let transport = Forward::new(SenderParameters {
+        port,
+        tls: TlsPolicy::None,
+        ..SenderParameters::from(Target::Ip("127.0.0.1".parse().unwrap()))
+    });
+    ctx.rcpt_to.delivery = std::collections::HashMap::from([(
+        WrapperSerde::Ready(std::sync::Arc::new(transport)),
+        vec![("jenny@example.com".parse().unwrap(), Status::default())],
+    )]);
+
+    let _ = split_and_sort_and_send(config.clone(), state, &mut ctx, &local_msg()).await;
+    assert!(ctx
+        .rcpt_to
+        .delivery
+        .values()
+        .flatten()
+        .all(|(_, status)| matches!(status, Status::Sent { .. })));
+}
+
+#[tokio::test]
+async fn reused_up_to_messages_max() {
+    let sink = Sink::start();
+
+    let mut config = local_test();
+    config.server.queues.delivery.reuse = FieldQueueDeliveryReuse {
+        enable: true,
+        messages_max: 2,
+        idle_timeout: std::time::Duration::from_secs(60),
+    };
+    let config = std::sync::Arc::new(config);
+    let state = std::sync::Arc::new(DeliveryState::new(&config));
+
+    for _ in 0..3 {
+        send(&config, &state, sink.port()).await;
+    }
+
+    // the last connection is kept open by the client.
+    assert_eq!(
+        sink.wait_for_sessions(1)
+            .iter()
+            .map(Session::verbs)
+            .collect::<Vec<_>>(),
+        vec![
+            vec!["EHLO", "EHLO", "MAIL", "RCPT", "DATA", "RSET", "MAIL", "RCPT", "DATA", "QUIT"],
+            // the extensions of the server are cached.
+            vec!["EHLO", "MAIL", "RCPT", "DATA"],
+        ]
+    );
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn idle_connection_closed() {
+    let sink = Sink::start();
+
+    let mut config = local_test();
+    config.server.queues.delivery.reuse = FieldQueueDeliveryReuse {
+        enable: true,
+        messages_max: 10,
+        idle_timeout: std::time::Duration::from_millis(200),
+    };
+    let config = std::sync::Arc::new(config);
+    let state = std::sync::Arc::new(DeliveryState::new(&config));
+    tokio::spawn(state.clone().sweep_idle_connections());
+
+    send(&config, &state, sink.port()).await;
+
+    // no other message is sent to the destination, the sweep closes the connection.
+    assert_eq!(
+        sink.wait_for_sessions(1)
+            .iter()
+            .map(Session::verbs)
+            .collect::<Vec<_>>(),
+        vec![vec!["EHLO", "EHLO", "MAIL", "RCPT", "DATA", "QUIT"]]
+    );
+}
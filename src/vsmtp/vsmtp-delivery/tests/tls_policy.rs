@@ -0,0 +1,157 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2023 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use vsmtp_common::{transfer::Status, transport::WrapperSerde, Target};
+use vsmtp_config::field::{FieldQueueDeliveryTlsPolicy, StartTlsPolicy};
+use vsmtp_delivery::{
+    split_and_sort_and_send, DeliveryState, Forward, SenderParameters, TlsPolicy,
+};
+use vsmtp_test::{
+    config::{local_ctx, local_msg, local_test},
+    sink::Sink,
+};
+
+/// A server advertising `STARTTLS` if `starttls` is set but never accepting it.
+fn sink(starttls: bool) -> Sink {
+    Sink::builder()
+        .with_capabilities(if starttls { &["STARTTLS"] } else { &[] })
+        .with_reply(|turn| {
+            (turn.verb() == "STARTTLS").then(|| "454 4.7.0 TLS not available".to_owned())
+        })
+        .start()
+}
+
+/// Forward a message to `jenny@mail.example.com` with the parameters `tls` to a sink, the
+/// policy of `example.com` being `starttls`, returning the status of the recipient and
+/// the commands received by the sink.
+async fn forward(
+    tls: TlsPolicy,
+    starttls: StartTlsPolicy,
+    advertised: bool,
+) -> (Status, Vec<String>) {
+    let sink = sink(advertised);
+
+    let mut ctx = local_ctx();
+    let transport = Forward::new(SenderParameters {
+        port: sink.port(),
+        tls,
+        ..SenderParameters::from(Target::Ip("127.0.0.1".parse().unwrap()))
+    });
+    ctx.rcpt_to.delivery = std::collections::HashMap::from([(
+        WrapperSerde::Ready(std::sync::Arc::new(transport)),
+        vec![("jenny@mail.example.com".parse().unwrap(), Status::default())],
+    )]);
+
+    let mut config = local_test();
+    config.server.queues.delivery.tls.destinations = [(
+        "example.com".parse().unwrap(),
+        FieldQueueDeliveryTlsPolicy {
+            starttls,
+            min_version: None,
+        },
+    )]
+    .into_iter()
+    .collect();
+    let config = std::sync::Arc::new(config);
+    let state = std::sync::Arc::new(DeliveryState::new(&config));
+    let _ = split_and_sort_and_send(config, &state, &mut ctx, &local_msg()).await;
+    let status = ctx
+        .rcpt_to
+        .delivery
+        .into_values()
+        .flatten()
+        .map(|(_, status)| status)
+        .next()
+        .unwrap();
+
+    (status, sink.wait_for_sessions(1)[0].verbs())
+}
+
+#[tokio::test]
+async fn enforce_without_starttls() {
+    let (status, commands) = forward(TlsPolicy::None, StartTlsPolicy::Enforce, false).await;
+    // the delivery is refused, the message being tried again later.
+    assert!(matches!(status, Status::HeldBack { .. }), "{status:?}");
+    assert!(!commands.contains(&"MAIL".to_owned()), "{commands:?}");
+}
+
+#[tokio::test]
+async fn enforce_with_starttls_refused() {
+    let (status, commands) = forward(
+        TlsPolicy::StarttlsOpportunistic,
+        StartTlsPolicy::Enforce,
+        true,
+    )
+    .await;
+    assert!(matches!(status, Status::HeldBack { .. }), "{status:?}");
+    assert!(commands.contains(&"STARTTLS".to_owned()), "{commands:?}");
+    assert!(!commands.contains(&"MAIL".to_owned()), "{commands:?}");
+}
+
+#[tokio::test]
+async fn prefer_without_starttls() {
+    let (status, commands) = forward(
+        TlsPolicy::StarttlsOpportunistic,
+        StartTlsPolicy::Prefer,
+        false,
+    )
+    .await;
+    assert!(matches!(status, Status::Sent { .. }), "{status:?}");
+    assert!(commands.contains(&"DATA".to_owned()), "{commands:?}");
+}
+
+#[tokio::test]
+async fn disable_keeps_the_tls_of_the_transport() {
+    let (status, commands) =
+        forward(TlsPolicy::StarttlsRequired, StartTlsPolicy::Disable, false).await;
+    assert!(matches!(status, Status::HeldBack { .. }), "{status:?}");
+    assert!(!commands.contains(&"MAIL".to_owned()), "{commands:?}");
+}
+
+// NOTE: the `deliver` transport resolves the exchangers of the domain, it applies
+//       the policy of the domain with `SenderParameters::with_tls_policy`.
+#[tokio::test]
+async fn disable_with_starttls() {
+    let sink = sink(true);
+
+    let mut ctx = local_ctx();
+    let transport = Forward::new(SenderParameters {
+        port: sink.port(),
+        ..SenderParameters::from(Target::Ip("127.0.0.1".parse().unwrap())).with_tls_policy(
+            &FieldQueueDeliveryTlsPolicy {
+                starttls: StartTlsPolicy::Disable,
+                min_version: None,
+            },
+        )
+    });
+    ctx.rcpt_to.delivery = std::collections::HashMap::from([(
+        WrapperSerde::Ready(std::sync::Arc::new(transport)),
+        vec![("jenny@example.com".parse().unwrap(), Status::default())],
+    )]);
+
+    let config = std::sync::Arc::new(local_test());
+    let state = std::sync::Arc::new(DeliveryState::new(&config));
+    let _ = split_and_sort_and_send(config, &state, &mut ctx, &local_msg()).await;
+
+    assert!(ctx
+        .rcpt_to
+        .delivery
+        .values()
+        .flatten()
+        .all(|(_, status)| matches!(status, Status::Sent { .. })));
+    let commands = sink.wait_for_sessions(1)[0].verbs();
+    assert!(!commands.contains(&"STARTTLS".to_owned()), "{commands:?}");
+}
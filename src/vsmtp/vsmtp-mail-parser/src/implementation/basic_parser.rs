@@ -6,26 +6,62 @@ pub struct BasicParser;
 
 impl MailParser for BasicParser {
     fn parse_sync(&mut self, raw: Vec<Vec<u8>>) -> ParserResult<either::Either<RawBody, Mail>> {
-        let mut headers = Vec::<String>::new();
-        let mut body = String::new();
+        let mut headers = Vec::<Vec<u8>>::new();
+        let mut body = Vec::<u8>::new();
 
-        let mut stream = raw.iter();
+        let mut stream = raw.into_iter();
 
         for line in stream.by_ref() {
             if line == b"\r\n" {
                 break;
             }
             if !line.first().map_or(false, |c| [b' ', b'\t'].contains(c)) && !line.contains(&b':') {
-                body.push_str(std::str::from_utf8(line).unwrap());
+                body.extend_from_slice(&line);
                 break;
             }
-            headers.push(String::from_utf8(line.clone()).expect("todo: handle non utf8"));
+            headers.push(line);
         }
 
         for line in stream {
-            body.push_str(std::str::from_utf8(line).expect("todo: handle non utf8"));
+            body.extend_from_slice(&line);
         }
 
-        Ok(either::Left(RawBody::new(headers, body)))
+        // NOTE: a message which is not valid UTF-8 (an 8bit content in another charset)
+        //       is read by the rules as a lossy copy, and stored as received.
+        Ok(either::Left(RawBody::from_bytes(headers, body)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BasicParser;
+    use crate::MailParser;
+
+    #[test]
+    fn invalid_utf8() {
+        let raw = [
+            b"From: caf\xe9 <john@example.com>\r\n".to_vec(),
+            b"Subject: \xff\xfe\x00\r\n".to_vec(),
+            b"\r\n".to_vec(),
+            b"caf\xe9\r\n".to_vec(),
+        ];
+
+        let body = BasicParser::default()
+            .parse_sync(raw.to_vec())
+            .unwrap()
+            .unwrap_left();
+        assert!(body.is_lossy());
+        assert_eq!(body.to_bytes(), raw.concat());
+        assert_eq!(body.size(), raw.concat().len());
+        assert_eq!(
+            body.get_header("From", false).unwrap(),
+            "caf\u{fffd} <john@example.com>\r\n"
+        );
+
+        let body = BasicParser::default()
+            .parse_sync(vec![b"Subject: cafe\r\n".to_vec(), b"\r\n".to_vec()])
+            .unwrap()
+            .unwrap_left();
+        assert!(!body.is_lossy());
     }
 }
@@ -28,23 +28,76 @@ enum BoundaryType {
     OutOfScope,
 }
 
+/// Limits applied while parsing the MIME structure of a message.
+///
+/// Exceeding one of them stops the parsing at that point, the rest of the
+/// message is kept unparsed and [`Mail::parse_truncated`] is set.
+///
+/// The rule engine parses the messages with the limits of `server.mime` of the configuration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MimeLimits {
+    /// Maximum nesting of multipart and embedded messages.
+    pub max_depth: usize,
+    /// Maximum number of MIME parts in the whole message.
+    pub max_parts: usize,
+    /// Maximum length of a boundary (70 characters in RFC 2046).
+    pub max_boundary_length: usize,
+}
+
+impl Default for MimeLimits {
+    fn default() -> Self {
+        Self {
+            max_depth: 32,
+            max_parts: 1000,
+            max_boundary_length: 70,
+        }
+    }
+}
+
 /// Instance parsing a message body
 #[derive(Default)]
 pub struct MailMimeParser {
     boundary_stack: Vec<String>,
+    limits: MimeLimits,
+    depth: usize,
+    part_count: usize,
+    truncated: bool,
 }
 
 impl MailParser for MailMimeParser {
     fn parse_sync(&mut self, raw: Vec<Vec<u8>>) -> ParserResult<either::Either<RawBody, Mail>> {
         let ref_raw = raw
             .iter()
-            .map(|l| std::str::from_utf8(l).unwrap())
-            .collect::<Vec<&str>>();
-        self.parse_inner(&mut &ref_raw[..]).map(either::Right)
+            .map(|l| std::str::from_utf8(l))
+            .collect::<Result<Vec<&str>, _>>()
+            .map_err(|e| ParserError::InvalidMail(e.to_string()))?;
+
+        let mut mail = self.parse_inner(&mut &ref_raw[..])?;
+        mail.parse_truncated = self.truncated;
+        Ok(either::Right(mail))
     }
 }
 
 impl MailMimeParser {
+    /// Create a parser enforcing the given limits.
+    #[must_use]
+    pub fn with_limits(limits: MimeLimits) -> Self {
+        Self {
+            limits,
+            ..Self::default()
+        }
+    }
+
+    /// Stop the parsing, the remaining lines are returned as they are.
+    fn truncate(&mut self, content: &mut &[&str], reason: &str) -> Vec<String> {
+        tracing::warn!(reason, "MIME parsing truncated.");
+        self.truncated = true;
+
+        let rest = content.iter().map(ToString::to_string).collect();
+        *content = &[];
+        rest
+    }
+
     #[allow(clippy::cognitive_complexity)]
     #[tracing::instrument(name = "parsing email", skip_all)]
     fn parse_inner(&mut self, content: &mut &[&str]) -> ParserResult<Mail> {
@@ -55,12 +108,20 @@ impl MailMimeParser {
             match read_header(content) {
                 Some((name, value)) if is_mime_header(&name) => {
                     // FIXME: should header content be traced ?
-                    tracing::trace!("new mime header found: '{name}' => '{value}'",);
+                    tracing::trace!(
+                        "new mime header found: '{}' => '{}'",
+                        name.escape_debug(),
+                        value.escape_debug()
+                    );
                     mime_headers.push(get_mime_header(&name, &value));
                 }
 
                 Some((name, value)) => {
-                    tracing::trace!("new header found: '{name}' => '{value}'",);
+                    tracing::trace!(
+                        "new header found: '{}' => '{}'",
+                        name.escape_debug(),
+                        value.escape_debug()
+                    );
                     headers.0.push((name, value));
                 }
 
@@ -72,6 +133,7 @@ impl MailMimeParser {
                         return Ok(Mail {
                             headers,
                             body: BodyType::Undefined,
+                            parse_truncated: false,
                         });
                     }
 
@@ -92,6 +154,7 @@ impl MailMimeParser {
                         } else {
                             BodyType::Regular(self.as_regular_body(content)?)
                         },
+                        parse_truncated: false,
                     });
                 }
             };
@@ -102,6 +165,7 @@ impl MailMimeParser {
         Ok(Mail {
             headers,
             body: BodyType::Undefined,
+            parse_truncated: false,
         })
     }
 
@@ -193,19 +257,29 @@ impl MailMimeParser {
         parent: Option<&[MimeHeader]>,
     ) -> ParserResult<Mime> {
         match get_mime_type(&headers, parent)? {
+            ("message" | "multipart", _) if self.depth >= self.limits.max_depth => Ok(Mime {
+                headers,
+                content: MimeBodyType::Regular(self.truncate(content, "maximum depth reached")),
+            }),
             ("message", sub_type) => {
                 tracing::trace!("'message' content type found (message/{})", sub_type);
-                *content = &content[1..];
+                *content = content.get(1..).unwrap_or_default();
+                self.depth += 1;
+                let embedded = self.parse_inner(content);
+                self.depth -= 1;
                 Ok(Mime {
                     headers,
-                    content: MimeBodyType::Embedded(self.parse_inner(content)?),
+                    content: MimeBodyType::Embedded(embedded?),
                 })
             }
             ("multipart", _) => {
                 tracing::trace!("parsing multipart.");
+                self.depth += 1;
+                let multipart = self.parse_multipart(&headers, content);
+                self.depth -= 1;
                 Ok(Mime {
-                    headers: headers.clone(),
-                    content: MimeBodyType::Multipart(self.parse_multipart(&headers, content)?),
+                    headers,
+                    content: MimeBodyType::Multipart(multipart?),
                 })
             }
             (body_type, sub_type) => {
@@ -233,7 +307,11 @@ impl MailMimeParser {
 
         while content.len() > 1 {
             if let Some((name, value)) = read_header(content) {
-                tracing::trace!("mime-header found: '{}' => '{}'.", name, value);
+                tracing::trace!(
+                    "mime-header found: '{}' => '{}'.",
+                    name.escape_debug(),
+                    value.escape_debug()
+                );
                 headers.push(get_mime_header(&name, &value));
             } else {
                 tracing::trace!("finished reading mime headers, body found.");
@@ -311,11 +389,22 @@ impl MailMimeParser {
         headers: &[MimeHeader],
         content: &mut &[&str],
     ) -> ParserResult<MimeMultipart> {
-        let content_type = headers.iter().find(|h| h.name == "content-type").unwrap();
-
-        match content_type.args.get("boundary") {
+        match headers
+            .iter()
+            .find(|h| h.name == "content-type")
+            .and_then(|content_type| content_type.args.get("boundary"))
+        {
+            Some(b) if b.len() > self.limits.max_boundary_length => {
+                return Ok(MimeMultipart {
+                    preamble: self
+                        .truncate(content, "maximum boundary length reached")
+                        .join("\r\n"),
+                    parts: Vec::new(),
+                    epilogue: String::new(),
+                });
+            }
             Some(b) => {
-                tracing::trace!("boundary found in parameters: '{}'.", b);
+                tracing::trace!("boundary found in parameters: '{}'.", b.escape_debug());
                 self.boundary_stack.push(b.to_string());
             }
             None => {
@@ -342,10 +431,18 @@ impl MailMimeParser {
                 Some(BoundaryType::Delimiter) => {
                     tracing::trace!(
                         "delimiter boundary found while parsing multipart: '{}', calling parse_mime.",
-                        &content[0]
+                        content[0].escape_debug()
                     );
                     *content = &content[1..];
 
+                    if self.part_count >= self.limits.max_parts {
+                        multi_parts.epilogue = self
+                            .truncate(content, "maximum number of parts reached")
+                            .join("\r\n");
+                        return Ok(multi_parts);
+                    }
+                    self.part_count += 1;
+
                     multi_parts
                         .parts
                         .push(self.parse_mime(content, Some(headers))?);
@@ -354,7 +451,7 @@ impl MailMimeParser {
                 Some(BoundaryType::End) => {
                     tracing::trace!(
                         "end boundary found while parsing multipart: '{}', stopping multipart parsing.",
-                        &content[0]
+                        content[0].escape_debug()
                     );
                     self.boundary_stack.pop();
                     *content = &content[1..];
@@ -39,6 +39,7 @@ mod implementation {
 
 pub use implementation::{
     basic_parser::BasicParser, mail_mime_parser::get_mime_header, mail_mime_parser::MailMimeParser,
+    mail_mime_parser::MimeLimits,
 };
 
 mod message {
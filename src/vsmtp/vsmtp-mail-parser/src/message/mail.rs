@@ -76,6 +76,10 @@ pub struct Mail {
     pub headers: MailHeaders,
     /// Message body content
     pub body: BodyType,
+    /// The parsing stopped before the end of the message because a limit was
+    /// reached, see [`crate::MimeLimits`].
+    #[serde(default)]
+    pub parse_truncated: bool,
 }
 
 #[derive(Debug)]
@@ -106,9 +110,16 @@ impl<'a> std::fmt::Display for HeaderFoldable<'a> {
         // but must write a continuous string for base64 encoded values (like dkim)
         while !byte_writable.is_empty() {
             let (left, right) = if byte_writable.len() + prev > 998 {
-                byte_writable[..998 - prev]
+                // NOTE: the limit can fall in a multi-byte character, or before the value
+                // for a very long name, and a whitespace in first position is not a fold.
+                let mut limit = 998_usize.saturating_sub(prev);
+                while !byte_writable.is_char_boundary(limit) {
+                    limit -= 1;
+                }
+                byte_writable[..limit]
                     .rfind(char::is_whitespace)
-                    .map(|idx| (&byte_writable[..idx], &byte_writable[idx..]))
+                    .filter(|idx| *idx != 0)
+                    .map(|idx| byte_writable.split_at(idx))
             } else {
                 None
             }
@@ -296,6 +307,7 @@ mod test {
         let empty_mail = Mail {
             headers: MailHeaders(vec![("From".to_string(), "a@a".to_string())]),
             body: BodyType::Undefined,
+            parse_truncated: false,
         };
 
         // on newline added to separate the body, one for the empty body.
@@ -305,6 +317,7 @@ mod test {
         let regular_mail = Mail {
             headers: MailHeaders(vec![("From".to_string(), "a@a".to_string())]),
             body: BodyType::Regular(vec!["This is a regular body.".to_string()]),
+            parse_truncated: false,
         };
 
         assert_eq!(
@@ -325,6 +338,7 @@ mod test {
                 }],
                 content: MimeBodyType::Regular(vec!["this is a regular mime body.".to_string()]),
             })),
+            parse_truncated: false,
         };
 
         // mime headers should be merged with the rfc822 message header section.
@@ -441,4 +455,27 @@ mod test {
             ])
         );
     }
+
+    #[test]
+    fn fold_long_headers() {
+        // the limit falls in a multi-byte character.
+        let value = "\u{e9}".repeat(600);
+        assert_eq!(
+            HeaderFoldable("subject", &value).to_string(),
+            format!("Subject: {value}\r\n")
+        );
+
+        // the name alone is longer than the limit.
+        let name = "x".repeat(1200);
+        assert!(HeaderFoldable(&name, "value")
+            .to_string()
+            .ends_with(": value\r\n"));
+
+        // the only whitespace of the rest of the value is the one of the fold.
+        let (start, end) = ("a".repeat(500), "b".repeat(2000));
+        assert_eq!(
+            HeaderFoldable("subject", &format!("{start} {end}")).to_string(),
+            format!("Subject: {start}\r\n\t {end}\r\n")
+        );
+    }
 }
@@ -43,15 +43,22 @@ impl TryFrom<&str> for MessageBody {
     type Error = anyhow::Error;
 
     fn try_from(value: &str) -> Result<Self, Self::Error> {
-        let mut bytes = vec![];
-        let splitted = value.split("\r\n").collect::<Vec<_>>();
-        for (idx, mut line) in splitted.iter().map(|l| l.as_bytes().to_vec()).enumerate() {
-            bytes.push({
-                if idx != splitted.len() - 1 {
-                    line.extend_from_slice(b"\r\n");
-                }
-                line
-            });
+        Self::try_from(value.as_bytes())
+    }
+}
+
+impl TryFrom<&[u8]> for MessageBody {
+    type Error = anyhow::Error;
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        let mut bytes = Vec::<Vec<u8>>::new();
+        let splitted = value.split_inclusive(|c| *c == b'\n');
+        for line in splitted {
+            match bytes.last_mut() {
+                // NOTE: the lines are split on `\r\n` only, a bare `\n` is kept in its line.
+                Some(last) if !last.ends_with(b"\r\n") => last.extend_from_slice(line),
+                _ => bytes.push(line.to_vec()),
+            }
         }
 
         Ok(Self {
@@ -77,6 +84,18 @@ impl MessageBody {
         &self.raw
     }
 
+    /// Number of bytes of the message as stored, see [`RawBody::size`].
+    #[must_use]
+    pub fn size(&self) -> usize {
+        self.raw.size()
+    }
+
+    /// Number of bytes of the header section, see [`RawBody::header_size`].
+    #[must_use]
+    pub fn header_size(&self) -> usize {
+        self.raw.header_size()
+    }
+
     /// Get the parsed part
     #[must_use]
     pub const fn get_parsed(&self) -> &Option<Mail> {
@@ -145,6 +164,34 @@ impl MessageBody {
         self.raw.prepend_header([format!("{name}: {value}\r\n")]);
     }
 
+    /// Replace the header section, see [`RawBody::set_raw_headers`].
+    ///
+    /// The parsed representation is dropped, it is built again on demand.
+    pub fn set_raw_headers(&mut self, headers: Vec<String>) {
+        self.parsed = None;
+        self.raw.set_raw_headers(headers);
+    }
+
+    /// Replace the body of the message, see [`RawBody::set_body`].
+    ///
+    /// The parsed representation is dropped, it is built again on demand.
+    pub fn set_body(&mut self, body: String) {
+        self.parsed = None;
+        self.raw.set_body(body);
+    }
+
+    /// Normalize the line endings of the message, see [`RawBody::normalize`].
+    ///
+    /// The parsed representation is dropped if the message is modified, it is built
+    /// again on demand.
+    pub fn normalize(&mut self, trailing_whitespace: bool) -> bool {
+        let modified = self.raw.normalize(trailing_whitespace);
+        if modified {
+            self.parsed = None;
+        }
+        modified
+    }
+
     /// Remove a header from the list.
     pub fn remove_header(&mut self, name: &str) -> bool {
         if let Some(parsed) = &mut self.parsed {
@@ -160,8 +207,17 @@ impl MessageBody {
     /// * the value produced by the [`MailParser`] was not a parsed [`Mail`]
     /// * Fail to parse using the provided [`MailParser`]
     pub fn parse<P: MailParser>(&mut self) -> anyhow::Result<()> {
+        self.parse_with(P::default())
+    }
+
+    /// Parse the message with the given instance of [`MailParser`], configured by the caller.
+    ///
+    /// # Errors
+    ///
+    /// * see [`Self::parse`]
+    pub fn parse_with<P: MailParser>(&mut self, parser: P) -> anyhow::Result<()> {
         self.parsed = Some(
-            P::default()
+            parser
                 .convert(&self.raw)?
                 .ok_or_else(|| anyhow::anyhow!("the parser did not produced a `Mail` part."))?,
         );
@@ -172,10 +228,24 @@ impl MessageBody {
     ///
     /// * error from [`Self::parse`]
     pub fn parsed<P: MailParser>(&mut self) -> anyhow::Result<&mut Mail> {
-        if self.parsed.is_some() {
-            return Ok(self.parsed.as_mut().expect(""));
-        }
-        self.parse::<P>()?;
-        self.parsed::<P>()
+        self.parsed_with(P::default)
+    }
+
+    /// Get the parsed message, parsing it with the parser built by `parser` if it has not been yet.
+    ///
+    /// # Errors
+    ///
+    /// * error from [`Self::parse_with`]
+    pub fn parsed_with<P: MailParser>(
+        &mut self,
+        parser: impl FnOnce() -> P,
+    ) -> anyhow::Result<&mut Mail> {
+        let parsed = match self.parsed.take() {
+            Some(parsed) => parsed,
+            None => parser()
+                .convert(&self.raw)?
+                .ok_or_else(|| anyhow::anyhow!("the parser did not produced a `Mail` part."))?,
+        };
+        Ok(self.parsed.insert(parsed))
     }
 }
@@ -119,11 +119,12 @@ impl std::fmt::Display for Mime {
                 Ok(())
             }
             MimeBodyType::Multipart(multipart) => {
+                // NOTE: the headers can be modified after the parsing.
                 let boundary = self
                     .headers
                     .iter()
                     .find_map(|header| header.args.get("boundary"))
-                    .unwrap();
+                    .map_or("", String::as_str);
 
                 write!(f, "{}", MimeMultipartDisplayable(multipart, boundary))
             }
@@ -166,4 +167,21 @@ mod tests {
             "Content-Type: application/foobar\r\n".to_string()
         );
     }
+
+    #[test]
+    fn multipart_without_boundary() {
+        let input = Mime {
+            headers: vec![MimeHeader {
+                name: "Content-Type".to_string(),
+                value: "multipart/mixed".to_string(),
+                args: std::collections::HashMap::default(),
+            }],
+            content: MimeBodyType::Multipart(MimeMultipart::default()),
+        };
+
+        assert_eq!(
+            input.to_string(),
+            "Content-Type: multipart/mixed\r\n\r\n----\r\n\r\n"
+        );
+    }
 }
@@ -20,6 +20,19 @@
 pub struct RawBody {
     headers: Vec<String>,
     body: Option<String>,
+    /// The bytes received of a message which is not valid UTF-8, `headers` and `body`
+    /// being a lossy copy of it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    original: Option<Original>,
+}
+
+/// The bytes received of a message which is not valid UTF-8, see [`RawBody::from_bytes`].
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+struct Original {
+    /// The header lines which are not valid UTF-8, with their lossy copy.
+    headers: Vec<(String, Vec<u8>)>,
+    /// The body received, [`None`] once replaced by [`RawBody::set_body`].
+    body: Option<Vec<u8>>,
 }
 
 impl RawBody {
@@ -29,6 +42,53 @@ impl RawBody {
         Self {
             headers,
             body: Some(body),
+            original: None,
+        }
+    }
+
+    /// A message read from the bytes received, one header line (with its `\r\n`) per entry.
+    ///
+    /// If it is not valid UTF-8 (an 8bit content in another charset), `headers` and `body`
+    /// are a lossy copy of it, read and modified by the rules, while the header lines left
+    /// untouched and the body, unless replaced, are stored and sent as received.
+    #[must_use]
+    pub fn from_bytes(headers: Vec<Vec<u8>>, body: Vec<u8>) -> Self {
+        let body = match String::from_utf8(body) {
+            Ok(body) if headers.iter().all(|line| std::str::from_utf8(line).is_ok()) => {
+                return Self::new(
+                    headers
+                        .into_iter()
+                        .map(|line| String::from_utf8_lossy(&line).into_owned())
+                        .collect(),
+                    body,
+                );
+            }
+            Ok(body) => body.into_bytes(),
+            Err(error) => error.into_bytes(),
+        };
+
+        let lossy_body = String::from_utf8_lossy(&body).into_owned();
+        let mut original = Original {
+            headers: vec![],
+            body: Some(body),
+        };
+        let headers = headers
+            .into_iter()
+            .map(|line| match String::from_utf8(line) {
+                Ok(line) => line,
+                Err(error) => {
+                    let line = error.into_bytes();
+                    let lossy = String::from_utf8_lossy(&line).into_owned();
+                    original.headers.push((lossy.clone(), line));
+                    lossy
+                }
+            })
+            .collect();
+
+        Self {
+            headers,
+            body: Some(lossy_body),
+            original: Some(original),
         }
     }
 
@@ -38,7 +98,49 @@ impl RawBody {
         Self {
             headers,
             body: None,
+            original: None,
+        }
+    }
+
+    /// Is the message a lossy copy of a message which is not valid UTF-8,
+    /// see [`RawBody::from_bytes`]?
+    #[must_use]
+    pub const fn is_lossy(&self) -> bool {
+        self.original.is_some()
+    }
+
+    /// The bytes of a header line to store or send: the line as received if it has
+    /// not been modified and is not valid UTF-8, its text otherwise.
+    fn header_bytes<'a>(&'a self, header: &'a str) -> &'a [u8] {
+        self.original
+            .as_ref()
+            .and_then(|original| original.headers.iter().find(|(lossy, _)| lossy == header))
+            .map_or(header.as_bytes(), |(_, line)| line.as_slice())
+    }
+
+    /// The message to store or send, see [`RawBody::from_bytes`].
+    #[must_use]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let Some(original) = &self.original else {
+            return self.to_string().into_bytes();
+        };
+
+        let mut bytes = Vec::with_capacity(self.size());
+        for header in &self.headers {
+            bytes.extend_from_slice(self.header_bytes(header));
         }
+        bytes.extend_from_slice(b"\r\n");
+        bytes.extend_from_slice(self.body_bytes(original));
+        bytes
+    }
+
+    /// The bytes of the body to store or send: the body as received if it has not
+    /// been replaced, its text otherwise.
+    fn body_bytes<'a>(&'a self, original: &'a Original) -> &'a [u8] {
+        original
+            .body
+            .as_deref()
+            .unwrap_or_else(|| self.body.as_ref().map_or(&[][..], |body| body.as_bytes()))
     }
 
     /// Return an iterator over the headers field
@@ -52,6 +154,40 @@ impl RawBody {
         &self.body
     }
 
+    /// Replace the body of the message.
+    ///
+    /// The body of a message which is not valid UTF-8 is then stored and sent as
+    /// given, the body received being dropped.
+    pub fn set_body(&mut self, body: String) {
+        self.body = Some(body);
+        if let Some(original) = self.original.as_mut() {
+            original.body = None;
+        }
+    }
+
+    /// Number of bytes of the header section, without the empty line separating it from the body.
+    ///
+    /// Computed from the length of the lines, the message is not copied.
+    #[must_use]
+    pub fn header_size(&self) -> usize {
+        self.headers
+            .iter()
+            .map(|header| self.header_bytes(header).len())
+            .sum()
+    }
+
+    /// Number of bytes of the message as stored, the length of [`RawBody::to_bytes`].
+    ///
+    /// Computed from the length of the lines, the message is not copied.
+    #[must_use]
+    pub fn size(&self) -> usize {
+        let body = self.original.as_ref().map_or_else(
+            || self.body.as_ref().map_or(0, String::len),
+            |original| self.body_bytes(original).len(),
+        );
+        self.header_size() + "\r\n".len() + body
+    }
+
     ///
     // TODO: make it lazy if possible
     #[must_use]
@@ -85,6 +221,11 @@ impl RawBody {
         &self.headers
     }
 
+    /// Replace all the headers, one line (with its `\r\n`) per entry.
+    pub fn set_raw_headers(&mut self, headers: Vec<String>) {
+        self.headers = headers;
+    }
+
     /// Search for a header (using lowercase) and return its value.
     #[must_use]
     pub fn get_header(&self, name: &str, with_key: bool) -> Option<String> {
@@ -170,6 +311,55 @@ impl RawBody {
         self.headers.splice(..0, headers);
     }
 
+    /// Replace the bare `\n` and `\r` by `\r\n`, and remove the spaces and tabs ending
+    /// the lines of the header section if `trailing_whitespace`. A folded line left empty
+    /// is removed, it would end the header section otherwise.
+    ///
+    /// Returns `true` if the message has been modified.
+    pub fn normalize(&mut self, trailing_whitespace: bool) -> bool {
+        let mut modified = false;
+
+        let headers = std::mem::take(&mut self.headers);
+        for header in headers {
+            let mut line = header.trim_end_matches(|c| matches!(c, '\r' | '\n'));
+            if trailing_whitespace {
+                line = line.trim_end_matches(|c| matches!(c, ' ' | '\t'));
+            }
+            let normalized = format!(
+                "{}\r\n",
+                crlf(line.as_bytes()).map_or_else(
+                    || line.to_owned(),
+                    |line| String::from_utf8_lossy(&line).into_owned()
+                )
+            );
+
+            if normalized == "\r\n" {
+                modified = true;
+                continue;
+            }
+            modified |= normalized != header;
+            self.headers.push(normalized);
+        }
+
+        if let Some(body) = self.body.as_mut() {
+            if let Some(normalized) = crlf(body.as_bytes()) {
+                *body = String::from_utf8_lossy(&normalized).into_owned();
+                modified = true;
+            }
+        }
+        if let Some(body) = self
+            .original
+            .as_mut()
+            .and_then(|original| original.body.as_mut())
+        {
+            if let Some(normalized) = crlf(body) {
+                *body = normalized;
+            }
+        }
+
+        modified
+    }
+
     /// Remove a header from the list.
     pub fn remove_header(&mut self, name: &str) -> bool {
         if let Some(index) = self.headers.iter().position(|header| {
@@ -185,6 +375,31 @@ impl RawBody {
     }
 }
 
+/// Replace the bare `\n` and `\r` of `text` by `\r\n`, [`None`] if there is none.
+///
+/// Working on the bytes, a text valid UTF-8 stays valid.
+fn crlf(text: &[u8]) -> Option<Vec<u8>> {
+    let mut modified = false;
+    let mut output = Vec::with_capacity(text.len());
+
+    let mut bytes = text.iter().peekable();
+    while let Some(c) = bytes.next() {
+        match c {
+            b'\r' if bytes.peek() == Some(&&b'\n') => {
+                bytes.next();
+                output.extend_from_slice(b"\r\n");
+            }
+            b'\r' | b'\n' => {
+                output.extend_from_slice(b"\r\n");
+                modified = true;
+            }
+            c => output.push(*c),
+        }
+    }
+
+    modified.then_some(output)
+}
+
 impl std::fmt::Display for RawBody {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         for i in &self.headers {
@@ -197,3 +412,97 @@ impl std::fmt::Display for RawBody {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::RawBody;
+
+    #[test]
+    fn normalize() {
+        let mut raw = RawBody::new(
+            vec![
+                "From: john@example.com \t\r\n".to_owned(),
+                "Subject: bare\n".to_owned(),
+                "X-Folded: a\r\n".to_owned(),
+                " \t\r\n".to_owned(),
+                " b\r\n".to_owned(),
+            ],
+            "line 1\nline 2\rline 3  \r\n".to_owned(),
+        );
+
+        assert!(raw.normalize(true));
+        assert_eq!(
+            raw.raw_headers(),
+            &[
+                "From: john@example.com\r\n",
+                "Subject: bare\r\n",
+                "X-Folded: a\r\n",
+                " b\r\n",
+            ]
+        );
+        // the trailing whitespace of the body is kept.
+        assert_eq!(
+            raw.body().as_deref(),
+            Some("line 1\r\nline 2\r\nline 3  \r\n")
+        );
+
+        assert!(!raw.normalize(true));
+    }
+
+    #[test]
+    fn normalize_line_endings_only() {
+        let mut raw = RawBody::new(
+            vec!["Subject: spaces  \n".to_owned()],
+            "body\r\n".to_owned(),
+        );
+
+        assert!(raw.normalize(false));
+        assert_eq!(raw.raw_headers(), &["Subject: spaces  \r\n"]);
+        assert_eq!(raw.body().as_deref(), Some("body\r\n"));
+    }
+
+    #[test]
+    fn lossy_modified() {
+        let mut raw = RawBody::from_bytes(
+            vec![
+                b"From: Andr\xe9 <andre@example.com>\r\n".to_vec(),
+                b"Subject: caf\xe9\r\n".to_vec(),
+            ],
+            b"\xe9t\xe9\r\n".to_vec(),
+        );
+        assert!(raw.is_lossy());
+
+        raw.prepend_header(["Received: from client.com\r\n".to_owned()]);
+        raw.set_header("Subject", "tea\r\n");
+
+        assert_eq!(
+            raw.to_bytes(),
+            b"Received: from client.com\r\n\
+From: Andr\xe9 <andre@example.com>\r\n\
+Subject: tea\r\n\
+\r\n\
+\xe9t\xe9\r\n"
+        );
+        assert_eq!(raw.size(), raw.to_bytes().len());
+    }
+
+    #[test]
+    fn lossy_body_modified() {
+        let mut raw = RawBody::from_bytes(
+            vec![b"Subject: caf\xe9\r\n".to_vec()],
+            b"\xe9t\xe9\r\n".to_vec(),
+        );
+        assert!(raw.is_lossy());
+
+        raw.set_body("summer\r\n-- \r\ndisclaimer\r\n".to_owned());
+        raw.normalize(true);
+
+        assert_eq!(
+            raw.to_bytes(),
+            b"Subject: caf\xe9\r\n\
+\r\n\
+summer\r\n-- \r\ndisclaimer\r\n"
+        );
+        assert_eq!(raw.size(), raw.to_bytes().len());
+    }
+}
@@ -101,7 +101,8 @@ fn mime_parser() {
                     .map(str::to_string)
                     .collect::<Vec<_>>()
                 )
-            }))
+            })),
+            parse_truncated: false,
         }
     );
 }
@@ -0,0 +1,109 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2022 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use crate::{message::message_body::MessageBody, MailMimeParser, MimeLimits};
+
+const HEADERS: &str = concat!(
+    "From: john <john@example.com>\r\n",
+    "To: green@example.com\r\n",
+    "Date: tue, 30 nov 2021 20:54:27 +0100\r\n",
+    "Subject: limits\r\n",
+    "MIME-Version: 1.0\r\n",
+);
+
+fn nested(depth: usize) -> String {
+    let mut out = HEADERS.to_string();
+    for i in 0..depth {
+        out.push_str(&format!(
+            "Content-Type: multipart/mixed; boundary=\"b{i}\"\r\n\r\n--b{i}\r\n"
+        ));
+    }
+    out.push_str("Content-Type: text/plain\r\n\r\nhello\r\n");
+    for i in (0..depth).rev() {
+        out.push_str(&format!("--b{i}--\r\n"));
+    }
+    out
+}
+
+fn siblings(count: usize) -> String {
+    let mut out = HEADERS.to_string();
+    out.push_str("Content-Type: multipart/mixed; boundary=\"b\"\r\n\r\n");
+    for i in 0..count {
+        out.push_str(&format!(
+            "--b\r\nContent-Type: text/plain\r\n\r\npart {i}\r\n"
+        ));
+    }
+    out.push_str("--b--\r\n");
+    out
+}
+
+fn parse(input: &str) -> (MessageBody, std::time::Duration) {
+    let mut message = MessageBody::try_from(input).unwrap();
+    let now = std::time::Instant::now();
+    message.parse::<MailMimeParser>().unwrap();
+    (message, now.elapsed())
+}
+
+#[test]
+fn deep_nesting() {
+    let input = nested(1000);
+    let (message, elapsed) = parse(&input);
+
+    assert!(elapsed < std::time::Duration::from_secs(5), "{elapsed:?}");
+    assert!(message.get_parsed().as_ref().unwrap().parse_truncated);
+    assert_eq!(message.inner().to_string(), input);
+}
+
+#[test]
+fn many_siblings() {
+    let input = siblings(100_000);
+    let (message, elapsed) = parse(&input);
+
+    assert!(elapsed < std::time::Duration::from_secs(5), "{elapsed:?}");
+    assert!(message.get_parsed().as_ref().unwrap().parse_truncated);
+    assert_eq!(message.inner().to_string(), input);
+}
+
+#[test]
+fn long_boundary() {
+    let boundary = "b".repeat(71);
+    let input = format!(
+        "{HEADERS}Content-Type: multipart/mixed; boundary=\"{boundary}\"\r\n\r\n--{boundary}\r\n\r\nhello\r\n--{boundary}--\r\n"
+    );
+    let (message, _) = parse(&input);
+
+    assert!(message.get_parsed().as_ref().unwrap().parse_truncated);
+    assert_eq!(message.inner().to_string(), input);
+}
+
+#[test]
+fn within_limits() {
+    let (message, _) = parse(&nested(3));
+    assert!(!message.get_parsed().as_ref().unwrap().parse_truncated);
+
+    let raw = siblings(10);
+    let limits = MimeLimits {
+        max_parts: 5,
+        ..MimeLimits::default()
+    };
+    let mail = crate::MailParser::parse_sync(
+        &mut MailMimeParser::with_limits(limits),
+        raw.lines().map(|l| l.as_bytes().to_vec()).collect(),
+    )
+    .unwrap()
+    .unwrap_right();
+    assert!(mail.parse_truncated);
+}
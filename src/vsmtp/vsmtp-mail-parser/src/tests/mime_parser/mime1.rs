@@ -156,7 +156,8 @@ fn mime_parser() {
                     }],
                     epilogue: String::new()
                 })
-            }))
+            })),
+            parse_truncated: false,
         }
     );
 }
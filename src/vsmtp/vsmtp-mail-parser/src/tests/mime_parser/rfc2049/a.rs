@@ -199,13 +199,15 @@ fn simple() {
                                         .into_iter()
                                         .map(str::to_string)
                                         .collect::<Vec<_>>(),
-                                )
+                                ),
+                                parse_truncated: false,
                             })
                         }
                     ],
                     epilogue: String::new(),
                 })
-            }))
+            })),
+            parse_truncated: false,
         }
     );
 
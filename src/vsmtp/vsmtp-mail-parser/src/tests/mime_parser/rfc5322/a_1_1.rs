@@ -32,7 +32,8 @@ fn simple() {
                     .into_iter()
                     .map(str::to_string)
                     .collect::<_>()
-            )
+            ),
+            parse_truncated: false,
         }
     );
 
@@ -74,7 +75,8 @@ fn forward() {
                     .into_iter()
                     .map(str::to_string)
                     .collect::<_>()
-            )
+            ),
+            parse_truncated: false,
         }
     );
     pretty_assertions::assert_eq!(
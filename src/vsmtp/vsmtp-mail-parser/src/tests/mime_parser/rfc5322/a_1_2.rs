@@ -39,7 +39,8 @@ fn types_mailboxes() {
                     .into_iter()
                     .map(str::to_string)
                     .collect::<_>()
-            )
+            ),
+            parse_truncated: false,
         }
     );
     pretty_assertions::assert_eq!(parsed.to_string(), MAIL.replace('\n', "\r\n"));
@@ -39,7 +39,8 @@ fn group_addresses() {
                     .into_iter()
                     .map(str::to_string)
                     .collect::<_>()
-            )
+            ),
+            parse_truncated: false,
         }
     );
     pretty_assertions::assert_eq!(parsed.to_string(), MAIL.replace('\n', "\r\n"));
@@ -32,7 +32,8 @@ fn simple() {
                     .into_iter()
                     .map(str::to_string)
                     .collect::<_>()
-            )
+            ),
+            parse_truncated: false,
         }
     );
     pretty_assertions::assert_eq!(
@@ -78,7 +79,8 @@ fn reply_simple() {
                     .into_iter()
                     .map(str::to_string)
                     .collect::<_>()
-            )
+            ),
+            parse_truncated: false,
         }
     );
     pretty_assertions::assert_eq!(
@@ -126,7 +128,8 @@ fn reply_reply() {
                     .into_iter()
                     .map(str::to_string)
                     .collect::<_>()
-            )
+            ),
+            parse_truncated: false,
         }
     );
     pretty_assertions::assert_eq!(
@@ -36,7 +36,8 @@ fn resent() {
                     .into_iter()
                     .map(str::to_string)
                     .collect::<_>()
-            )
+            ),
+            parse_truncated: false,
         }
     );
     pretty_assertions::assert_eq!(
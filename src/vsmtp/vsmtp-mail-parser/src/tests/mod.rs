@@ -24,11 +24,15 @@ mod mime_parser {
     #[allow(non_snake_case)]
     mod allen_p__discussion_threads__1;
 
+    mod limits;
+
     mod methods;
 
     mod mime1;
 }
 
+mod size;
+
 fn visit_dirs(
     dir: &std::path::Path,
     cb: &dyn Fn(&std::fs::DirEntry) -> std::io::Result<()>,
@@ -0,0 +1,64 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2023 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use crate::{message::message_body::MessageBody, MailMimeParser, RawBody};
+
+const MAIL: &str = concat!(
+    "From: john <john@example.com>\r\n",
+    "To: green@example.com\r\n",
+    "Subject: size\r\n",
+    "Date: Tue, 30 Nov 2021 20:54:27 +0100\r\n",
+    "\r\n",
+    "Hello world!\r\n",
+    "Bye.\r\n",
+);
+
+#[test]
+fn size() {
+    let message = MessageBody::try_from(MAIL).unwrap();
+
+    assert_eq!(message.size(), MAIL.len());
+    assert_eq!(
+        message.header_size(),
+        MAIL.find("\r\n\r\n").unwrap() + "\r\n".len()
+    );
+
+    assert_eq!(message.size(), message.inner().to_string().len());
+}
+
+#[test]
+fn size_after_modifications() {
+    let mut message = MessageBody::try_from(MAIL).unwrap();
+    message.parse::<MailMimeParser>().unwrap();
+
+    message.prepend_header("X-Prepended", "foo");
+    message.set_header("Subject", "a longer subject");
+    message.remove_header("To");
+
+    assert_eq!(message.size(), message.inner().to_string().len());
+    assert_eq!(
+        message.header_size(),
+        message.inner().headers_lines().map(str::len).sum::<usize>()
+    );
+}
+
+#[test]
+fn size_empty_body() {
+    let raw = RawBody::new_empty(vec!["From: john <john@example.com>\r\n".to_string()]);
+
+    assert_eq!(raw.header_size(), "From: john <john@example.com>\r\n".len());
+    assert_eq!(raw.size(), raw.to_string().len());
+}
@@ -16,7 +16,7 @@
 */
 
 use crate::{ConnectionKind, Error, ParseArgsError};
-use vsmtp_common::{auth::Mechanism, Address, ClientName, Domain};
+use vsmtp_common::{auth::Mechanism, Address, ClientName, Domain, MimeBodyType, OriginalRecipient};
 
 macro_rules! strip_suffix_crlf {
     ($v:expr) => {
@@ -38,6 +38,44 @@ fn strip_quote(input: &[u8]) -> Result<&[u8], ParseArgsError> {
 #[non_exhaustive]
 pub struct UnparsedArgs(pub Vec<u8>);
 
+impl UnparsedArgs {
+    /// Maximum length in bytes of the text returned by [`UnparsedArgs::to_raw_string`].
+    pub const RAW_LEN_MAX: usize = 512;
+
+    /// The text of the arguments as sent by the client, without the CRLF, the invalid
+    /// UTF-8 sequences being replaced and the text truncated to [`UnparsedArgs::RAW_LEN_MAX`] bytes.
+    #[inline]
+    #[must_use]
+    pub fn to_raw_string(&self) -> String {
+        let value = self.0.strip_suffix(b"\r\n").unwrap_or(&self.0);
+        let mut raw = String::from_utf8_lossy(value).into_owned();
+        if raw.len() > Self::RAW_LEN_MAX {
+            let mut end = Self::RAW_LEN_MAX;
+            while !raw.is_char_boundary(end) {
+                end -= 1;
+            }
+            raw.truncate(end);
+        }
+        raw
+    }
+
+    /// Check that the arguments of a command taking none are empty, the spaces before
+    /// the CRLF being ignored.
+    ///
+    /// # Errors
+    ///
+    /// * [`ParseArgsError::UnexpectedParameters`] if the client sent any
+    #[inline]
+    pub fn expect_none(&self) -> Result<(), ParseArgsError> {
+        let value = self.0.strip_suffix(b"\r\n").unwrap_or(&self.0);
+        if value.iter().all(|c| matches!(c, b' ' | b'\t')) {
+            Ok(())
+        } else {
+            Err(ParseArgsError::UnexpectedParameters)
+        }
+    }
+}
+
 pub type Command<Verb, Args> = (Verb, Args);
 
 /// Information received from the client at the connection TCP/IP.
@@ -90,18 +128,11 @@ pub struct EhloArgs {
     pub client_name: ClientName,
 }
 
-/// See "SMTP Service Extension for 8-bit MIME Transport"
-/// <https://datatracker.ietf.org/doc/html/rfc6152>
-#[derive(strum::EnumVariantNames, strum::EnumString)]
-pub enum MimeBodyType {
-    ///
-    #[strum(serialize = "7BIT")]
-    SevenBit,
-    ///
-    #[strum(serialize = "8BITMIME")]
-    EightBitMime,
-    // TODO: https://datatracker.ietf.org/doc/html/rfc3030
-    // Binary,
+/// Information received from the client at the HELP command.
+#[non_exhaustive]
+pub struct HelpArgs {
+    /// Topic the client wants help on (e.g., any command name), if any.
+    pub topic: Option<String>,
 }
 
 /// <https://www.rfc-editor.org/rfc/rfc3461>
@@ -127,7 +158,7 @@ pub struct MailFromArgs {
     pub mime_body_type: Option<MimeBodyType>,
     // TODO:
     // Option<String>       (AUTH)
-    /// (SIZE)
+    /// (SIZE), `None` if the size is unknown (`SIZE=0`)
     pub size: Option<usize>,
     /// smtputf8 extension allowing utf8 email
     pub use_smtputf8: bool,
@@ -136,6 +167,8 @@ pub struct MailFromArgs {
     pub envelop_id: Option<String>,
     /// `RET` argument of the `MAIL FROM` command
     pub ret: Option<DsnReturn>,
+    /// The arguments as sent by the client, see [`UnparsedArgs::to_raw_string`].
+    pub raw: String,
 }
 
 /// <https://www.rfc-editor.org/rfc/rfc3461>
@@ -157,16 +190,6 @@ pub enum NotifyOn {
     },
 }
 
-/// <https://www.rfc-editor.org/rfc/rfc3461>
-#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
-#[allow(clippy::exhaustive_structs)]
-pub struct OriginalRecipient {
-    /// The type of address used in the `ORCPT` argument. (rfc822)
-    pub addr_type: String,
-    /// The original recipient address.
-    pub mailbox: Address,
-}
-
 /// Information received from the client at the RCPT TO command.
 #[non_exhaustive]
 pub struct RcptToArgs {
@@ -176,6 +199,111 @@ pub struct RcptToArgs {
     pub original_forward_path: Option<OriginalRecipient>,
     /// `NOTIFY` argument of the `RCPT TO` command
     pub notify_on: NotifyOn,
+    /// The arguments as sent by the client, see [`UnparsedArgs::to_raw_string`].
+    pub raw: String,
+}
+
+/// Handling of the parameters of the `MAIL FROM` and `RCPT TO` commands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ParametersPolicy {
+    /// Reject the unknown parameters with a [`ParseArgsError::UnknownParameter`],
+    /// instead of ignoring them.
+    pub strict: bool,
+    /// Maximum number of parameters of a command.
+    pub count_max: usize,
+    /// Maximum length of a parameter in bytes.
+    pub length_max: usize,
+    /// Reject the parameters of the commands taking none (`DATA`, `QUIT`, `RSET` and
+    /// `STARTTLS`) with a [`ParseArgsError::UnexpectedParameters`], instead of ignoring them.
+    pub reject_unexpected: bool,
+}
+
+impl Default for ParametersPolicy {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            strict: true,
+            count_max: 10,
+            // `ORCPT=` followed by an address of at most 500 characters (rfc 3461).
+            length_max: 512,
+            reject_unexpected: true,
+        }
+    }
+}
+
+impl ParametersPolicy {
+    /// Create a policy.
+    #[must_use]
+    #[inline]
+    pub const fn new(
+        strict: bool,
+        count_max: usize,
+        length_max: usize,
+        reject_unexpected: bool,
+    ) -> Self {
+        Self {
+            strict,
+            count_max,
+            length_max,
+            reject_unexpected,
+        }
+    }
+
+    /// Check the limits of the parameters, and parse each of them with `parse`,
+    /// the unknown ones being ignored if the policy is not strict.
+    ///
+    /// A parameter given twice is invalid, known or not.
+    fn for_each<'arg>(
+        &self,
+        args: impl Iterator<Item = &'arg [u8]>,
+        mut parse: impl FnMut(&'arg [u8]) -> Result<(), ParseArgsError>,
+    ) -> Result<(), ParseArgsError> {
+        let mut keys = Vec::<&[u8]>::new();
+        for (index, arg) in args.enumerate() {
+            if index >= self.count_max {
+                return Err(ParseArgsError::TooManyParameters {
+                    expected: self.count_max,
+                });
+            }
+            if arg.len() > self.length_max {
+                return Err(ParseArgsError::ParameterTooLong {
+                    expected: self.length_max,
+                    got: arg.len(),
+                });
+            }
+            let key = split_args(arg).map_or(arg, |(key, _)| key);
+            if keys.iter().any(|known| known.eq_ignore_ascii_case(key)) {
+                return Err(ParseArgsError::InvalidArgs);
+            }
+            keys.push(key);
+
+            match parse(arg) {
+                Err(ParseArgsError::UnknownParameter { name }) if !self.strict => {
+                    tracing::debug!(%name, "Unknown parameter ignored.");
+                }
+                otherwise => otherwise?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The error of an unknown parameter `key`.
+fn unknown_parameter(key: &[u8]) -> ParseArgsError {
+    ParseArgsError::UnknownParameter {
+        name: String::from_utf8_lossy(key).into_owned(),
+    }
+}
+
+/// Information received from the client at the BDAT command.
+/// <https://datatracker.ietf.org/doc/html/rfc3030>
+#[non_exhaustive]
+pub struct BdatArgs {
+    /// Size in bytes of the chunk following the command.
+    pub chunk_size: usize,
+    /// This chunk is the last one of the message.
+    pub is_last: bool,
 }
 
 /// Information received from the client at the AUTH command.
@@ -188,6 +316,31 @@ pub struct AuthArgs {
     pub initial_response: Option<Vec<u8>>,
 }
 
+/// Parse a mailbox received in the arguments of the `MAIL FROM` and `RCPT TO` commands
+/// (including the `ORCPT` parameter).
+///
+/// Internationalized addresses (UTF-8 in the local part and/or the domain, see RFC 6531)
+/// are accepted only if `SMTPUTF8` has been negotiated for the transaction.
+///
+/// # Errors
+///
+/// * [`ParseArgsError::Smtputf8Required`] if the address is not ASCII and `smtputf8` is `false`
+/// * [`ParseArgsError::InvalidMailAddress`] if the address is not a valid mailbox
+#[inline]
+pub fn parse_mailbox(mailbox: &str, smtputf8: bool) -> Result<Address, ParseArgsError> {
+    if !smtputf8 && !mailbox.is_ascii() {
+        return Err(ParseArgsError::Smtputf8Required {
+            mail: mailbox.to_owned(),
+        });
+    }
+
+    <Address as std::str::FromStr>::from_str(mailbox).map_err(|_error| {
+        ParseArgsError::InvalidMailAddress {
+            mail: mailbox.to_owned(),
+        }
+    })
+}
+
 fn split_args(slice: &[u8]) -> Option<(&[u8], &[u8])> {
     slice.iter().position(|c| *c == b'=').map(|pos| {
         let (k, v) = slice.split_at(pos);
@@ -213,6 +366,20 @@ impl TryFrom<UnparsedArgs> for HeloArgs {
     }
 }
 
+impl TryFrom<UnparsedArgs> for HelpArgs {
+    type Error = ParseArgsError;
+
+    #[inline]
+    fn try_from(value: UnparsedArgs) -> Result<Self, Self::Error> {
+        let value = String::from_utf8(strip_suffix_crlf!(value).to_vec())?;
+        let topic = value.trim();
+
+        Ok(Self {
+            topic: (!topic.is_empty()).then(|| topic.to_owned()),
+        })
+    }
+}
+
 impl TryFrom<UnparsedArgs> for EhloArgs {
     type Error = ParseArgsError;
 
@@ -289,6 +456,42 @@ impl TryFrom<UnparsedArgs> for AuthArgs {
     }
 }
 
+impl TryFrom<UnparsedArgs> for BdatArgs {
+    type Error = ParseArgsError;
+
+    #[inline]
+    fn try_from(value: UnparsedArgs) -> Result<Self, Self::Error> {
+        let value = strip_suffix_crlf!(value);
+
+        let mut args = value
+            .split(u8::is_ascii_whitespace)
+            .filter(|s| !s.is_empty());
+
+        let chunk_size = args.next().ok_or(ParseArgsError::InvalidArgs)?;
+        if chunk_size.is_empty() || !chunk_size.iter().all(u8::is_ascii_digit) {
+            return Err(ParseArgsError::InvalidArgs);
+        }
+        let chunk_size = std::str::from_utf8(chunk_size)?
+            .parse()
+            .map_err(|_e| ParseArgsError::InvalidArgs)?;
+
+        let is_last = match args.next() {
+            None => false,
+            Some(last) if last.eq_ignore_ascii_case(b"LAST") => true,
+            Some(_) => return Err(ParseArgsError::InvalidArgs),
+        };
+
+        if args.next().is_some() {
+            return Err(ParseArgsError::InvalidArgs);
+        }
+
+        Ok(Self {
+            chunk_size,
+            is_last,
+        })
+    }
+}
+
 impl MailFromArgs {
     fn parse_arguments(&mut self, raw_args: &[u8]) -> Result<(), ParseArgsError> {
         match split_args(raw_args) {
@@ -311,16 +514,13 @@ impl MailFromArgs {
                 }
             }
             Some((key, value)) if key.eq_ignore_ascii_case(b"SIZE") => {
-                if self.mime_body_type.is_some() {
-                    Err(ParseArgsError::InvalidArgs)
-                } else {
-                    self.size = Some(
-                        std::str::from_utf8(value)?
-                            .parse()
-                            .map_err(|_e| ParseArgsError::InvalidArgs)?,
-                    );
-                    Ok(())
-                }
+                let size = std::str::from_utf8(value)?
+                    .parse()
+                    .map_err(|_e| ParseArgsError::InvalidArgs)?;
+                // RFC 1870: a client which does not know the size of the message
+                // declares `SIZE=0`, the message is not expected to be empty.
+                self.size = (size != 0).then_some(size);
+                Ok(())
             }
             Some((key, value)) if key.eq_ignore_ascii_case(b"RET") => {
                 if self.ret.is_some() {
@@ -339,14 +539,14 @@ impl MailFromArgs {
                     Err(ParseArgsError::InvalidArgs)
                 } else {
                     self.envelop_id = Some(
-                        std::str::from_utf8(value)?
-                            .parse()
+                        vsmtp_common::xtext::decode(std::str::from_utf8(value)?)
                             .map_err(|_e| ParseArgsError::InvalidArgs)?,
                     );
                     Ok(())
                 }
             }
-            _ => Err(ParseArgsError::InvalidArgs),
+            Some((key, _)) => Err(unknown_parameter(key)),
+            None => Err(ParseArgsError::InvalidArgs),
         }
     }
 
@@ -356,16 +556,24 @@ impl MailFromArgs {
                 self.use_smtputf8 = true;
                 Ok(())
             }
-            _ => Err(ParseArgsError::InvalidArgs),
+            _ => Err(unknown_parameter(raw_args)),
         }
     }
-}
-
-impl TryFrom<UnparsedArgs> for MailFromArgs {
-    type Error = ParseArgsError;
 
+    /// Parse the arguments of the `MAIL FROM` command, the parameters being
+    /// handled with `parameters`.
+    ///
+    /// # Errors
+    ///
+    /// * the arguments are not valid (see [`parse_mailbox`] for the reverse path)
+    /// * a parameter is unknown and the policy is strict
+    /// * the parameters exceed the limits of the policy
     #[inline]
-    fn try_from(value: UnparsedArgs) -> Result<Self, Self::Error> {
+    pub fn parse(
+        value: &UnparsedArgs,
+        parameters: &ParametersPolicy,
+    ) -> Result<Self, ParseArgsError> {
+        let raw = value.to_raw_string();
         let value = strip_suffix_crlf!(value);
 
         let mut args = value
@@ -386,33 +594,36 @@ impl TryFrom<UnparsedArgs> for MailFromArgs {
             use_smtputf8: false,
             envelop_id: None,
             ret: None,
+            raw,
         };
 
-        for arg in args {
+        parameters.for_each(args, |arg| {
             if arg.contains(&b'=') {
-                result.parse_arguments(arg)?;
+                result.parse_arguments(arg)
             } else {
-                result.parse_options(arg)?;
+                result.parse_options(arg)
             }
-        }
+        })?;
+
+        result.reverse_path = mailbox
+            .map(|mailbox| parse_mailbox(&mailbox, result.use_smtputf8))
+            .transpose()?;
 
-        result.reverse_path = if let Some(mailbox) = mailbox {
-            if !result.use_smtputf8 && !mailbox.is_ascii() {
-                return Err(ParseArgsError::EmailUnavailable);
-            }
-            match <Address as std::str::FromStr>::from_str(&mailbox) {
-                Ok(mailbox) => Some(mailbox),
-                Err(_error) => return Err(ParseArgsError::InvalidMailAddress { mail: mailbox }),
-            }
-        } else {
-            None
-        };
         Ok(result)
     }
 }
 
+impl TryFrom<UnparsedArgs> for MailFromArgs {
+    type Error = ParseArgsError;
+
+    #[inline]
+    fn try_from(value: UnparsedArgs) -> Result<Self, Self::Error> {
+        Self::parse(&value, &ParametersPolicy::default())
+    }
+}
+
 impl RcptToArgs {
-    fn parse_arguments(&mut self, raw_args: &[u8]) -> Result<(), ParseArgsError> {
+    fn parse_arguments(&mut self, raw_args: &[u8], smtputf8: bool) -> Result<(), ParseArgsError> {
         match split_args(raw_args) {
             #[allow(clippy::expect_used)]
             Some((key, value)) if key.eq_ignore_ascii_case(b"ORCPT") => {
@@ -424,19 +635,19 @@ impl RcptToArgs {
                         None => return Err(ParseArgsError::InvalidArgs),
                     };
 
-                    let value = std::str::from_utf8(addr)?;
-                    self.original_forward_path =
-                        match <Address as std::str::FromStr>::from_str(value) {
-                            Ok(mailbox) => Some(OriginalRecipient {
-                                addr_type: std::str::from_utf8(addr_type)?.to_owned(),
-                                mailbox,
-                            }),
-                            Err(_error) => {
-                                return Err(ParseArgsError::InvalidMailAddress {
-                                    mail: value.to_owned(),
-                                })
-                            }
-                        };
+                    let addr_type = std::str::from_utf8(addr_type)?;
+                    let addr = std::str::from_utf8(addr)?;
+                    let addr = if addr_type.eq_ignore_ascii_case("utf-8") {
+                        vsmtp_common::xtext::decode_utf8_addr(addr)
+                    } else {
+                        vsmtp_common::xtext::decode(addr)
+                    }
+                    .map_err(|_e| ParseArgsError::InvalidArgs)?;
+
+                    self.original_forward_path = Some(OriginalRecipient {
+                        addr_type: addr_type.to_owned(),
+                        mailbox: parse_mailbox(&addr, smtputf8)?,
+                    });
                     Ok(())
                 }
             }
@@ -507,16 +718,27 @@ impl RcptToArgs {
 
                 Ok(())
             }
-            _ => Err(ParseArgsError::InvalidArgs),
+            Some((key, _)) => Err(unknown_parameter(key)),
+            None => Err(ParseArgsError::InvalidArgs),
         }
     }
-}
-
-impl TryFrom<UnparsedArgs> for RcptToArgs {
-    type Error = ParseArgsError;
 
+    /// Parse the arguments of the `RCPT TO` command, `smtputf8` being `true`
+    /// if the extension has been negotiated by the `MAIL FROM` command of the transaction,
+    /// the parameters being handled with `parameters`.
+    ///
+    /// # Errors
+    ///
+    /// * the arguments are not valid (see [`parse_mailbox`] for the forward path)
+    /// * a parameter is unknown and the policy is strict
+    /// * the parameters exceed the limits of the policy
     #[inline]
-    fn try_from(value: UnparsedArgs) -> Result<Self, Self::Error> {
+    pub fn parse(
+        value: &UnparsedArgs,
+        smtputf8: bool,
+        parameters: &ParametersPolicy,
+    ) -> Result<Self, ParseArgsError> {
+        let raw = value.to_raw_string();
         let value = strip_suffix_crlf!(value);
 
         let mut args = value
@@ -531,23 +753,23 @@ impl TryFrom<UnparsedArgs> for RcptToArgs {
         };
 
         let mut result = Self {
-            forward_path: <Address as std::str::FromStr>::from_str(&mailbox)
-                .map_err(|_error| ParseArgsError::InvalidMailAddress { mail: mailbox })?,
+            forward_path: parse_mailbox(&mailbox, smtputf8)?,
             original_forward_path: None,
             notify_on: NotifyOn::Some {
                 success: false,
                 failure: true,
                 delay: false,
             },
+            raw,
         };
 
-        for arg in args {
+        parameters.for_each(args, |arg| {
             if arg.contains(&b'=') {
-                result.parse_arguments(arg)?;
+                result.parse_arguments(arg, smtputf8)
             } else {
-                return Err(ParseArgsError::InvalidArgs);
+                Err(unknown_parameter(arg))
             }
-        }
+        })?;
 
         Ok(result)
     }
@@ -576,18 +798,18 @@ pub enum Verb {
     /// command.
     #[strum(serialize = "RCPT TO:")]
     RcptTo,
-    #[strum(serialize = "DATA\r\n")]
+    #[strum(serialize = "DATA")]
     /// This command causes the mail data to be appended to the mail data
     /// buffer.
     Data,
     /// This command specifies that the receiver MUST send a "221 OK" reply,
     /// and then close the transmission channel.
-    #[strum(serialize = "QUIT\r\n")]
+    #[strum(serialize = "QUIT")]
     Quit,
     /// This command specifies that the current mail transaction will be
     /// aborted. Any stored sender, recipients, and mail data MUST be
     /// discarded, and all buffers and state tables cleared.
-    #[strum(serialize = "RSET\r\n")]
+    #[strum(serialize = "RSET")]
     Rset,
     /// This command causes the server to send helpful information to the
     /// client. The command MAY take an argument (e.g., any command name)
@@ -595,17 +817,21 @@ pub enum Verb {
     #[strum(serialize = "HELP")]
     Help,
     /// This command does not affect any parameters or previously entered
-    /// commands.
-    #[strum(serialize = "NOOP\r\n")]
+    /// commands. The argument, if any, is ignored.
+    #[strum(serialize = "NOOP")]
     Noop,
     /// See "Transport Layer Security"
     /// <https://datatracker.ietf.org/doc/html/rfc3207>
-    #[strum(serialize = "STARTTLS\r\n")]
+    #[strum(serialize = "STARTTLS")]
     StartTls,
     /// Authentication with SASL protocol
     /// <https://datatracker.ietf.org/doc/html/rfc4954>
     #[strum(serialize = "AUTH ")]
     Auth,
+    /// Transfer a chunk of the message, see "SMTP Service Extensions for Transmission of
+    /// Large and Binary MIME Messages" <https://datatracker.ietf.org/doc/html/rfc3030>
+    #[strum(serialize = "BDAT ")]
+    Bdat,
     /// Any other buffer received while expecting a command is considered an
     /// unknown.
     Unknown,
@@ -622,3 +848,359 @@ impl Verb {
 }
 
 pub type Batch = Vec<Result<Command<Verb, UnparsedArgs>, Error>>;
+
+#[cfg(test)]
+#[allow(clippy::non_ascii_literal)]
+mod tests {
+    use super::{
+        parse_mailbox, BdatArgs, HelpArgs, MailFromArgs, ParametersPolicy, RcptToArgs, UnparsedArgs,
+    };
+    use crate::ParseArgsError;
+
+    const ASCII_ASCII: &str = "john.doe@example.com";
+    const UTF8_ASCII: &str = "χρήστης@example.com";
+    const ASCII_UTF8: &str = "john.doe@παράδειγμα.ελ";
+    const UTF8_UTF8: &str = "用户@例子.广告";
+
+    #[allow(clippy::unwrap_used)]
+    #[test]
+    fn matrix_negotiated() {
+        for mailbox in [ASCII_ASCII, UTF8_ASCII, ASCII_UTF8, UTF8_UTF8] {
+            assert_eq!(parse_mailbox(mailbox, true).unwrap().full(), mailbox);
+        }
+    }
+
+    #[allow(clippy::unwrap_used)]
+    #[test]
+    fn matrix_not_negotiated() {
+        assert_eq!(
+            parse_mailbox(ASCII_ASCII, false).unwrap().full(),
+            ASCII_ASCII
+        );
+        // A-labels are the ASCII form of an internationalized domain.
+        parse_mailbox("john.doe@xn--hxajbheg2az3al.xn--qxam", false).unwrap();
+
+        for mailbox in [UTF8_ASCII, ASCII_UTF8, UTF8_UTF8] {
+            assert!(matches!(
+                parse_mailbox(mailbox, false),
+                Err(ParseArgsError::Smtputf8Required { mail }) if mail == mailbox
+            ));
+        }
+    }
+
+    #[test]
+    fn invalid_mailbox() {
+        for smtputf8 in [true, false] {
+            assert!(matches!(
+                parse_mailbox("not-an-address", smtputf8),
+                Err(ParseArgsError::InvalidMailAddress { .. })
+            ));
+        }
+    }
+
+    fn args(input: &str) -> UnparsedArgs {
+        UnparsedArgs(input.as_bytes().to_vec())
+    }
+
+    #[allow(clippy::unwrap_used)]
+    #[test]
+    fn mail_from() {
+        for mailbox in [ASCII_ASCII, UTF8_ASCII, ASCII_UTF8, UTF8_UTF8] {
+            let parsed = MailFromArgs::try_from(args(&format!("<{mailbox}> SMTPUTF8\r\n")));
+            assert_eq!(parsed.unwrap().reverse_path.unwrap().full(), mailbox);
+        }
+
+        for mailbox in [UTF8_ASCII, ASCII_UTF8, UTF8_UTF8] {
+            assert!(matches!(
+                MailFromArgs::try_from(args(&format!("<{mailbox}>\r\n"))),
+                Err(ParseArgsError::Smtputf8Required { .. })
+            ));
+        }
+    }
+
+    #[allow(clippy::unwrap_used)]
+    #[test]
+    fn rcpt_to() {
+        for mailbox in [ASCII_ASCII, UTF8_ASCII, ASCII_UTF8, UTF8_UTF8] {
+            let parsed = RcptToArgs::parse(
+                &args(&format!("<{mailbox}>\r\n")),
+                true,
+                &ParametersPolicy::default(),
+            );
+            assert_eq!(parsed.unwrap().forward_path.full(), mailbox);
+        }
+
+        for mailbox in [UTF8_ASCII, ASCII_UTF8, UTF8_UTF8] {
+            assert!(matches!(
+                RcptToArgs::parse(
+                    &args(&format!("<{mailbox}>\r\n")),
+                    false,
+                    &ParametersPolicy::default(),
+                ),
+                Err(ParseArgsError::Smtputf8Required { .. })
+            ));
+        }
+    }
+
+    #[allow(clippy::unwrap_used)]
+    #[test]
+    fn rcpt_to_orcpt() {
+        let parsed = RcptToArgs::parse(
+            &args(&format!("<{ASCII_ASCII}> ORCPT=utf-8;{UTF8_UTF8}\r\n")),
+            true,
+            &ParametersPolicy::default(),
+        );
+        assert_eq!(
+            parsed
+                .unwrap()
+                .original_forward_path
+                .unwrap()
+                .mailbox
+                .full(),
+            UTF8_UTF8
+        );
+
+        assert!(matches!(
+            RcptToArgs::parse(
+                &args(&format!("<{ASCII_ASCII}> ORCPT=rfc822;{UTF8_UTF8}\r\n")),
+                false,
+                &ParametersPolicy::default(),
+            ),
+            Err(ParseArgsError::Smtputf8Required { .. })
+        ));
+
+        let parsed = RcptToArgs::parse(
+            &args(&format!(
+                "<{ASCII_ASCII}> ORCPT=rfc822;john+2Bdoe@example.com\r\n"
+            )),
+            false,
+            &ParametersPolicy::default(),
+        );
+        assert_eq!(
+            parsed
+                .unwrap()
+                .original_forward_path
+                .unwrap()
+                .mailbox
+                .full(),
+            "john+doe@example.com"
+        );
+
+        let parsed = RcptToArgs::parse(
+            &args(&format!(
+                "<{ASCII_ASCII}> ORCPT=utf-8;\\x{{7528}}\\x{{6237}}@example.com\r\n"
+            )),
+            true,
+            &ParametersPolicy::default(),
+        );
+        assert_eq!(
+            parsed
+                .unwrap()
+                .original_forward_path
+                .unwrap()
+                .mailbox
+                .full(),
+            "用户@example.com"
+        );
+
+        assert!(matches!(
+            RcptToArgs::parse(
+                &args(&format!(
+                    "<{ASCII_ASCII}> ORCPT=rfc822;john+2@example.com\r\n"
+                )),
+                false,
+                &ParametersPolicy::default(),
+            ),
+            Err(ParseArgsError::InvalidArgs)
+        ));
+    }
+
+    #[allow(clippy::unwrap_used)]
+    #[test]
+    fn mail_from_size() {
+        let parsed = MailFromArgs::try_from(args(&format!("<{ASCII_ASCII}> SIZE=1024\r\n")));
+        assert_eq!(parsed.unwrap().size, Some(1024));
+
+        let parsed = MailFromArgs::try_from(args(&format!("<{ASCII_ASCII}> SIZE=0\r\n")));
+        assert_eq!(parsed.unwrap().size, None);
+
+        assert!(matches!(
+            MailFromArgs::try_from(args(&format!("<{ASCII_ASCII}> SIZE=-1\r\n"))),
+            Err(ParseArgsError::InvalidArgs)
+        ));
+    }
+
+    #[allow(clippy::unwrap_used)]
+    #[test]
+    fn mail_from_envid() {
+        let parsed =
+            MailFromArgs::try_from(args(&format!("<{ASCII_ASCII}> ENVID=QQ+2B314159+3D\r\n")));
+        assert_eq!(parsed.unwrap().envelop_id.unwrap(), "QQ+314159=");
+
+        assert!(matches!(
+            MailFromArgs::try_from(args(&format!("<{ASCII_ASCII}> ENVID=QQ+ZZ\r\n"))),
+            Err(ParseArgsError::InvalidArgs)
+        ));
+    }
+
+    #[allow(clippy::unwrap_used)]
+    #[test]
+    fn help() {
+        assert_eq!(HelpArgs::try_from(args("\r\n")).unwrap().topic, None);
+        assert_eq!(HelpArgs::try_from(args(" \r\n")).unwrap().topic, None);
+        assert_eq!(
+            HelpArgs::try_from(args(" MAIL FROM\r\n")).unwrap().topic,
+            Some("MAIL FROM".to_owned())
+        );
+        assert!(HelpArgs::try_from(args(" MAIL")).is_err());
+    }
+
+    #[allow(clippy::unwrap_used)]
+    #[test]
+    fn bdat() {
+        let chunk = BdatArgs::try_from(args("0 LAST\r\n")).unwrap();
+        assert_eq!((chunk.chunk_size, chunk.is_last), (0, true));
+
+        let chunk = BdatArgs::try_from(args("1024\r\n")).unwrap();
+        assert_eq!((chunk.chunk_size, chunk.is_last), (1024, false));
+
+        for invalid in [
+            "\r\n",
+            "-1 LAST\r\n",
+            "+1\r\n",
+            "10 FIRST\r\n",
+            "10 LAST foo\r\n",
+        ] {
+            assert!(matches!(
+                BdatArgs::try_from(args(invalid)),
+                Err(ParseArgsError::InvalidArgs)
+            ));
+        }
+    }
+
+    #[allow(clippy::unwrap_used)]
+    #[test]
+    fn unknown_parameters() {
+        let lenient = ParametersPolicy::new(false, 10, 512, true);
+
+        for input in [
+            format!("<{ASCII_ASCII}> AUTH=<>\r\n"),
+            format!("<{ASCII_ASCII}> XCLIENT\r\n"),
+        ] {
+            assert!(matches!(
+                MailFromArgs::parse(&args(&input), &ParametersPolicy::default()),
+                Err(ParseArgsError::UnknownParameter { .. })
+            ));
+            assert!(MailFromArgs::parse(&args(&input), &lenient).is_ok());
+        }
+
+        let input = args(&format!(
+            "<{ASCII_ASCII}> FOO=bar ORCPT=rfc822;jane@example.com\r\n"
+        ));
+        assert!(matches!(
+            RcptToArgs::parse(&input, false, &ParametersPolicy::default()),
+            Err(ParseArgsError::UnknownParameter { name }) if name == "FOO"
+        ));
+        assert_eq!(
+            RcptToArgs::parse(&input, false, &lenient)
+                .unwrap()
+                .original_forward_path
+                .unwrap()
+                .mailbox
+                .full(),
+            "jane@example.com"
+        );
+
+        // the known parameters are still checked.
+        assert!(matches!(
+            MailFromArgs::parse(
+                &args(&format!("<{ASCII_ASCII}> AUTH=<> SIZE=-1\r\n")),
+                &lenient
+            ),
+            Err(ParseArgsError::InvalidArgs)
+        ));
+    }
+
+    #[test]
+    fn no_parameters() {
+        for ok in ["\r\n", " \r\n", " \t \r\n"] {
+            assert!(args(ok).expect_none().is_ok(), "{ok:?}");
+        }
+        for ko in [" foo\r\n", " foo bar \r\n"] {
+            assert!(
+                matches!(
+                    args(ko).expect_none(),
+                    Err(ParseArgsError::UnexpectedParameters)
+                ),
+                "{ko:?}"
+            );
+        }
+    }
+
+    #[allow(clippy::unwrap_used)]
+    #[test]
+    fn parameters_limits() {
+        let policy = ParametersPolicy::new(false, 2, 16, true);
+
+        assert!(MailFromArgs::parse(
+            &args(&format!("<{ASCII_ASCII}> BODY=8BITMIME SIZE=1024\r\n")),
+            &policy
+        )
+        .is_ok());
+        assert!(matches!(
+            MailFromArgs::parse(
+                &args(&format!(
+                    "<{ASCII_ASCII}> BODY=8BITMIME SIZE=1024 SMTPUTF8\r\n"
+                )),
+                &policy
+            ),
+            Err(ParseArgsError::TooManyParameters { expected: 2 })
+        ));
+        assert!(matches!(
+            MailFromArgs::parse(
+                &args(&format!("<{ASCII_ASCII}> ENVID=0123456789abcdef\r\n")),
+                &policy
+            ),
+            Err(ParseArgsError::ParameterTooLong {
+                expected: 16,
+                got: 22
+            })
+        ));
+
+        // a parameter given twice is invalid, known or not.
+        for input in ["SIZE=1 SIZE=0", "SMTPUTF8 smtputf8", "FOO=1 foo=2"] {
+            assert!(matches!(
+                MailFromArgs::parse(&args(&format!("<{ASCII_ASCII}> {input}\r\n")), &policy),
+                Err(ParseArgsError::InvalidArgs)
+            ));
+        }
+    }
+
+    #[allow(clippy::unwrap_used)]
+    #[test]
+    fn raw_args() {
+        let parsed = MailFromArgs::try_from(args(&format!(
+            "<{ASCII_ASCII}>  size=1024 Body=8bitMIME\r\n"
+        )));
+        assert_eq!(
+            parsed.unwrap().raw,
+            format!("<{ASCII_ASCII}>  size=1024 Body=8bitMIME")
+        );
+
+        let parsed = RcptToArgs::parse(
+            &args(&format!("<{ASCII_ASCII}> notify=FAILURE\r\n")),
+            false,
+            &ParametersPolicy::default(),
+        );
+        assert_eq!(
+            parsed.unwrap().raw,
+            format!("<{ASCII_ASCII}> notify=FAILURE")
+        );
+
+        let mut long = b"<".to_vec();
+        long.extend(std::iter::repeat(b'\xff').take(UnparsedArgs::RAW_LEN_MAX));
+        let raw = UnparsedArgs(long).to_raw_string();
+        assert!(raw.len() <= UnparsedArgs::RAW_LEN_MAX);
+        assert!(raw.starts_with("<\u{fffd}"));
+    }
+}
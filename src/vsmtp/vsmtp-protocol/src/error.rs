@@ -269,11 +269,36 @@ pub enum ParseArgsError {
         /// ill-formatted mail address
         mail: String,
     },
-    /// specified address it not available.
-    /// In command parsing, it can be fired if a given email is in utf8
-    /// and no smtputf8 option is provided
+    /// The mail address is internationalized (RFC 6531), but `SMTPUTF8`
+    /// has not been negotiated for the transaction.
     #[error("")]
-    EmailUnavailable,
+    Smtputf8Required {
+        /// the non-ASCII mail address
+        mail: String,
+    },
+    /// A parameter of the `MAIL FROM` or `RCPT TO` command is not known.
+    #[error("unknown parameter `{name}`")]
+    UnknownParameter {
+        /// the keyword of the parameter
+        name: String,
+    },
+    /// The command has more parameters than allowed.
+    #[error("the command is not supposed to have more than {expected} parameters")]
+    TooManyParameters {
+        /// parameter count limit
+        expected: usize,
+    },
+    /// A parameter of the command is longer than allowed.
+    #[error("parameter is not supposed to be longer than {expected} bytes but got {got}")]
+    ParameterTooLong {
+        /// parameter size limit
+        expected: usize,
+        /// actual size of the parameter we got
+        got: usize,
+    },
+    /// The command takes no parameter, but some have been sent.
+    #[error("the command is not supposed to have parameters")]
+    UnexpectedParameters,
     /// Other
     // FIXME: improve that
     #[error("")]
@@ -58,16 +58,17 @@ mod smtp_sasl;
 mod writer;
 
 pub use command::{
-    AcceptArgs, AuthArgs, DsnReturn, EhloArgs, HeloArgs, MailFromArgs, NotifyOn, OriginalRecipient,
-    RcptToArgs, UnparsedArgs, Verb,
+    parse_mailbox, AcceptArgs, AuthArgs, BdatArgs, DsnReturn, EhloArgs, HeloArgs, HelpArgs,
+    MailFromArgs, NotifyOn, ParametersPolicy, RcptToArgs, UnparsedArgs, Verb,
 };
 pub use connection_kind::ConnectionKind;
 pub use error::{Error, ErrorKind, ParseArgsError};
 pub use reader::Reader;
 pub use receiver::{Receiver, ReceiverContext};
-pub use receiver_handler::ReceiverHandler;
+pub use receiver_handler::{unknown_command_reply, ReceiverHandler};
 pub use rsasl;
 pub use smtp_sasl::{AuthError, CallbackWrap};
 pub use tokio_rustls;
 pub use tokio_rustls::rustls;
+pub use vsmtp_common::OriginalRecipient;
 pub use writer::Writer;
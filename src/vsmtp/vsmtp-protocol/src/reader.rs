@@ -15,7 +15,7 @@
  *
 */
 
-use crate::{command::Batch, command::Command, Error, UnparsedArgs, Verb};
+use crate::{command::Batch, command::Command, Error, ParseArgsError, UnparsedArgs, Verb};
 use tokio::io::AsyncReadExt;
 use tokio_stream::StreamExt;
 use vsmtp_common::Reply;
@@ -26,6 +26,12 @@ use vsmtp_common::Reply;
 /// - SMTPUTF8 (+10 characters)
 const MAX_LINE_SIZE: usize = 1024;
 
+/// max number of bytes received before the first CRLF.
+const MAX_FIRST_LINE_SIZE: usize = 8192;
+
+/// size of the reads used to skip a rejected `BDAT` chunk.
+const MAX_CHUNK_READ: usize = 8192;
+
 fn find(bytes: &[u8], search: &[u8]) -> Option<usize> {
     bytes
         .windows(search.len())
@@ -34,17 +40,23 @@ fn find(bytes: &[u8], search: &[u8]) -> Option<usize> {
 
 #[allow(clippy::expect_used)]
 fn parse_command_line(line: &Vec<u8>) -> Result<Command<Verb, UnparsedArgs>, Error> {
-    // TODO: put max len as a parameter
-
-    if line.len() >= MAX_LINE_SIZE {
-        return Err(Error::buffer_too_long(MAX_LINE_SIZE, line.len()));
-    }
     if find(line, b"\r\n").is_none() {
         return Err(Error::no_crlf());
     }
+    // a verb not ending with its delimiter (`HELP`, `NOOP`) must be followed by an
+    // argument or the end of the line, `NOOPS` is not `NOOP`.
+    let is_delimited = |verb: &str| {
+        verb.ends_with(|c: char| matches!(c, ' ' | ':' | '\n'))
+            || matches!(line.get(verb.len()), Some(b' ' | b'\r'))
+    };
+
     Ok(<Verb as strum::VariantNames>::VARIANTS
         .iter()
-        .find(|i| line.len() >= i.len() && line[..i.len()].eq_ignore_ascii_case(i.as_bytes()))
+        .find(|i| {
+            line.len() >= i.len()
+                && line[..i.len()].eq_ignore_ascii_case(i.as_bytes())
+                && is_delimited(i)
+        })
         .map_or_else(
             || (Verb::Unknown, UnparsedArgs(line.clone())),
             |verb| {
@@ -56,11 +68,25 @@ fn parse_command_line(line: &Vec<u8>) -> Result<Command<Verb, UnparsedArgs>, Err
         ))
 }
 
+/// Limits on the command lines, and the state needed to enforce them across the windows.
+#[derive(Debug, Clone, Copy)]
+struct LineLimits {
+    /// max size of a command line, including the CRLF.
+    line_length_max: usize,
+    /// max number of bytes received before the first CRLF.
+    first_line_max: usize,
+    /// a CRLF has been received.
+    seen_crlf: bool,
+    /// number of bytes dropped of the line being discarded, if it is too long.
+    discarded: Option<usize>,
+}
+
 /// Reader for TCP window
 /// it is used only for the internal reader logic and is not exposed to external.
 struct ReaderWindow<'win, R: tokio::io::AsyncRead + Unpin + Send> {
     inner: &'win mut R,
     buffer: &'win mut bytes::BytesMut,
+    limits: &'win mut LineLimits,
     additional_reserve: usize,
     n: usize,
 }
@@ -70,9 +96,13 @@ where
     R: tokio::io::AsyncRead + Unpin + Send,
 {
     /// return the full read tcp window (~= buffer)
+    ///
+    /// A line longer than the limit is dropped while it is received, and produces
+    /// a [`ParseArgsError::BufferTooLong`] once its CRLF is found. The stream fails
+    /// if too many bytes are received before the first CRLF.
     fn flush_window(
         &'win mut self,
-    ) -> impl tokio_stream::Stream<Item = std::io::Result<Vec<u8>>> + 'win {
+    ) -> impl tokio_stream::Stream<Item = std::io::Result<Result<Vec<u8>, Error>>> + 'win {
         async_stream::try_stream! {
             if !self.buffer.is_empty() {
                 self.n = self.buffer.len();
@@ -81,11 +111,36 @@ where
                 if let Some(pos) = find(&self.buffer[..self.n], b"\r\n") {
                     let out = self.buffer.split_to(pos + 2);
                     self.n -= out.len();
-                    yield Vec::<u8>::from(out);
+                    self.limits.seen_crlf = true;
+
+                    let size = self.limits.discarded.take().unwrap_or_default() + out.len();
+                    if size > self.limits.line_length_max {
+                        yield Err(Error::buffer_too_long(self.limits.line_length_max, size));
+                    } else {
+                        yield Ok(Vec::<u8>::from(out));
+                    }
                     if self.buffer.is_empty() {
                         return;
                     }
                 } else {
+                    if self.n > self.limits.line_length_max {
+                        // the last byte is kept, it could be the CR of the CRLF.
+                        let kept = usize::from(self.buffer.last() == Some(&b'\r'));
+                        let dropped = self.buffer.split_to(self.n - kept).len();
+                        self.n = kept;
+                        self.limits.discarded =
+                            Some(self.limits.discarded.unwrap_or_default() + dropped);
+                    }
+                    let received = self.limits.discarded.unwrap_or_default() + self.n;
+                    if !self.limits.seen_crlf && received > self.limits.first_line_max {
+                        Err(std::io::Error::new(
+                            std::io::ErrorKind::InvalidData,
+                            ParseArgsError::BufferTooLong {
+                                expected: self.limits.first_line_max,
+                                got: received,
+                            },
+                        ))?;
+                    }
                     self.buffer.reserve(self.additional_reserve);
                     let read_size = self.inner.read_buf(self.buffer).await?;
                     if read_size == 0 {
@@ -104,6 +159,7 @@ pub struct Reader<R: tokio::io::AsyncRead + Unpin + Send> {
     additional_reserve: usize,
     buffer: bytes::BytesMut,
     pipelining_enabled: bool,
+    limits: LineLimits,
 }
 
 impl<R: tokio::io::AsyncRead + Unpin + Send> Reader<R> {
@@ -116,9 +172,32 @@ impl<R: tokio::io::AsyncRead + Unpin + Send> Reader<R> {
             additional_reserve: 100,
             buffer: bytes::BytesMut::with_capacity(80),
             pipelining_enabled: enable_pipelining,
+            limits: LineLimits {
+                line_length_max: MAX_LINE_SIZE,
+                first_line_max: MAX_FIRST_LINE_SIZE,
+                seen_crlf: false,
+                discarded: None,
+            },
         }
     }
 
+    /// Set the max size of a command line (including the CRLF), and the max number
+    /// of bytes received before the first CRLF.
+    #[must_use]
+    #[inline]
+    pub const fn with_line_limits(mut self, line_length_max: usize, first_line_max: usize) -> Self {
+        self.limits.line_length_max = line_length_max;
+        self.limits.first_line_max = first_line_max;
+        self
+    }
+
+    /// The max size of a command line, and the max number of bytes received before the first CRLF.
+    #[must_use]
+    #[inline]
+    pub const fn line_limits(&self) -> (usize, usize) {
+        (self.limits.line_length_max, self.limits.first_line_max)
+    }
+
     /// Consume the instance and return the underlying reader.
     #[must_use]
     #[inline]
@@ -133,6 +212,7 @@ impl<R: tokio::io::AsyncRead + Unpin + Send> Reader<R> {
         ReaderWindow {
             inner: &mut self.inner,
             buffer: &mut self.buffer,
+            limits: &mut self.limits,
             additional_reserve: self.additional_reserve,
             n: 0,
         }
@@ -152,9 +232,12 @@ impl<R: tokio::io::AsyncRead + Unpin + Send> Reader<R> {
 
                 let window_content = window_reader.flush_window();
                 tokio::pin!(window_content);
-                while let Some(cmd) = window_content.next().await {
-                    batch.push(parse_command_line(&cmd?));
-                    if !pipelined {
+                while let Some(line) = window_content.next().await {
+                    let command = line?.and_then(|line| parse_command_line(&line));
+                    // the bytes following a BDAT command are the chunk, not other commands
+                    let is_bdat = matches!(command, Ok((Verb::Bdat, _)));
+                    batch.push(command);
+                    if !pipelined || is_bdat {
                         break;
                     }
                 }
@@ -163,6 +246,46 @@ impl<R: tokio::io::AsyncRead + Unpin + Send> Reader<R> {
         }
     }
 
+    /// Read exactly `size` bytes, as the chunk following a `BDAT` command.
+    ///
+    /// # Errors
+    ///
+    /// * the underlying reader failed
+    /// * the connection was closed before the end of the chunk
+    #[inline]
+    pub async fn read_chunk(&mut self, size: usize) -> std::io::Result<Vec<u8>> {
+        while self.buffer.len() < size {
+            self.buffer.reserve(size - self.buffer.len());
+            if self.inner.read_buf(&mut self.buffer).await? == 0 {
+                return Err(std::io::ErrorKind::UnexpectedEof.into());
+            }
+        }
+        Ok(Vec::<u8>::from(self.buffer.split_to(size)))
+    }
+
+    /// Read and drop `size` bytes, used to skip a rejected `BDAT` chunk
+    /// without holding it in memory.
+    ///
+    /// # Errors
+    ///
+    /// * the underlying reader failed
+    /// * the connection was closed before the end of the chunk
+    #[inline]
+    pub async fn discard_chunk(&mut self, mut size: usize) -> std::io::Result<()> {
+        loop {
+            let available = core::cmp::min(size, self.buffer.len());
+            drop(self.buffer.split_to(available));
+            size -= available;
+            if size == 0 {
+                return Ok(());
+            }
+            self.buffer.reserve(core::cmp::min(size, MAX_CHUNK_READ));
+            if self.inner.read_buf(&mut self.buffer).await? == 0 {
+                return Err(std::io::ErrorKind::UnexpectedEof.into());
+            }
+        }
+    }
+
     /// Produce a stream of "\r\n" terminated lines.
     /// Warning: it discard the rest of the line in case of pipelining
     #[inline]
@@ -195,6 +318,14 @@ impl<R: tokio::io::AsyncRead + Unpin + Send> Reader<R> {
     }
 
     /// Produce a stream of lines to generate IMF compliant messages.
+    ///
+    /// If the message exceeds `size_limit`, the rest of the message is read and
+    /// dropped until the end of data, and a single error is produced: the
+    /// connection is then ready to receive the next command.
+    ///
+    /// The reading does not stop at the limit: the client sends the whole message
+    /// before waiting for a reply (RFC 5321 section 4.1.1.4), the rest of the message
+    /// would be read as commands otherwise. The dropped lines are not buffered.
     #[inline]
     pub fn as_message_stream(
         &mut self,
@@ -205,10 +336,9 @@ impl<R: tokio::io::AsyncRead + Unpin + Send> Reader<R> {
 
             for await line in self.as_line_stream() {
                 let mut line = line?;
-                tracing::trace!("<< {:?}", std::str::from_utf8(&line));
 
                 if line == b".\r\n" {
-                    return;
+                    break;
                 }
                 if line.first() == Some(&b'.') {
                     line = line[1..].to_vec();
@@ -216,13 +346,17 @@ impl<R: tokio::io::AsyncRead + Unpin + Send> Reader<R> {
 
                 // TODO: handle line length max ?
                 size += line.len();
-                if size >= size_limit {
-                    yield Err(Error::buffer_too_long(size_limit, size));
-                    return;
+                if size > size_limit {
+                    continue;
                 }
 
+                tracing::trace!("<< {:?}", std::str::from_utf8(&line));
                 yield Ok(line);
             }
+
+            if size > size_limit {
+                yield Err(Error::buffer_too_long(size_limit, size));
+            }
         }
     }
 
@@ -290,24 +424,49 @@ mod tests {
         let output_stream = window.flush_window();
         tokio::pin!(output_stream);
 
+        for line in [
+            b"MAIL FROM:<mrose@dbc.mtview.ca.us>\r\n".as_slice(),
+            b"RCPT TO:<ned@innosoft.com>\r\n",
+            b"RCPT TO:<dan@innosoft.com>\r\n",
+            b"RCPT TO:<kvc@innosoft.com>\r\n",
+        ] {
+            assert_eq!(
+                output_stream.try_next().await.unwrap().unwrap().unwrap(),
+                line.to_vec(),
+            );
+        }
+        assert!(output_stream.try_next().await.unwrap().is_none());
+        assert!(output_stream.try_next().await.unwrap().is_none());
+    }
+
+    #[allow(clippy::unwrap_used)]
+    #[tokio::test]
+    async fn message_stream_size_limit() {
+        let input = ["aaaa\r\n", "bbbb\r\n", "cccc\r\n", ".\r\n"].concat();
+
+        let cursor = std::io::Cursor::new(input);
+        let mut reader = super::Reader::new(cursor, true);
+        let message_stream = reader.as_message_stream(8);
+        tokio::pin!(message_stream);
+
         assert_eq!(
-            output_stream.try_next().await.unwrap(),
-            Some(b"MAIL FROM:<mrose@dbc.mtview.ca.us>\r\n".to_vec()),
-        );
-        assert_eq!(
-            output_stream.try_next().await.unwrap(),
-            Some(b"RCPT TO:<ned@innosoft.com>\r\n".to_vec()),
-        );
-        assert_eq!(
-            output_stream.try_next().await.unwrap(),
-            Some(b"RCPT TO:<dan@innosoft.com>\r\n".to_vec()),
-        );
-        assert_eq!(
-            output_stream.try_next().await.unwrap(),
-            Some(b"RCPT TO:<kvc@innosoft.com>\r\n".to_vec()),
+            message_stream.next().await.unwrap().unwrap(),
+            b"aaaa\r\n".to_vec()
         );
-        assert_eq!(output_stream.try_next().await.unwrap(), None,);
-        assert_eq!(output_stream.try_next().await.unwrap(), None,);
+        assert!(matches!(
+            message_stream
+                .next()
+                .await
+                .unwrap()
+                .unwrap_err()
+                .get_ref()
+                .and_then(|error| error.downcast_ref::<crate::ParseArgsError>()),
+            Some(&crate::ParseArgsError::BufferTooLong {
+                expected: 8,
+                got: 18
+            })
+        ));
+        assert!(message_stream.next().await.is_none());
     }
 
     #[allow(clippy::unwrap_used, clippy::restriction)]
@@ -437,4 +596,114 @@ mod tests {
         let output = stream.try_next().await.unwrap().unwrap().unwrap();
         assert!(output.is_empty());
     }
+
+    #[allow(clippy::unwrap_used)]
+    #[tokio::test]
+    async fn line_too_long() {
+        let input = ["NOOP\r\n", &"X".repeat(1_000_000), "\r\n", "NOOP\r\n"].concat();
+
+        let cursor = std::io::Cursor::new(input);
+        let mut reader = super::Reader::new(cursor, true);
+        let mut batch = vec![];
+        {
+            let stream = reader.as_window_stream();
+            tokio::pin!(stream);
+            while batch.len() < 3 {
+                batch.extend(stream.try_next().await.unwrap().unwrap());
+            }
+        }
+        assert!(matches!(batch[0], Ok((command::Verb::Noop, _))));
+        assert!(matches!(
+            batch[1]
+                .as_ref()
+                .unwrap_err()
+                .get_ref()
+                .and_then(|error| error.downcast_ref::<crate::ParseArgsError>()),
+            Some(&crate::ParseArgsError::BufferTooLong {
+                expected: 1024,
+                got: 1_000_002
+            })
+        ));
+        assert!(matches!(batch[2], Ok((command::Verb::Noop, _))));
+
+        // the line has been dropped while it was received
+        assert!(reader.buffer.capacity() < 65_536);
+    }
+
+    #[allow(clippy::unwrap_used)]
+    #[tokio::test]
+    async fn no_crlf_before_limit() {
+        let cursor = std::io::Cursor::new("GET / HTTP/1.1 ".repeat(1000));
+        let mut reader = super::Reader::new(cursor, true).with_line_limits(1024, 8192);
+        let stream = reader.as_window_stream();
+        tokio::pin!(stream);
+
+        let error = stream.try_next().await.unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+        assert!(matches!(
+            error
+                .get_ref()
+                .and_then(|error| error.downcast_ref::<crate::ParseArgsError>()),
+            Some(&crate::ParseArgsError::BufferTooLong { expected: 8192, .. })
+        ));
+    }
+
+    #[allow(clippy::unwrap_used)]
+    #[test]
+    fn verbs_with_arguments() {
+        for (line, verb, args) in [
+            ("NOOP\r\n", command::Verb::Noop, "\r\n"),
+            ("noop foo bar\r\n", command::Verb::Noop, " foo bar\r\n"),
+            ("HELP\r\n", command::Verb::Help, "\r\n"),
+            ("HELP MAIL\r\n", command::Verb::Help, " MAIL\r\n"),
+            ("NOOPS\r\n", command::Verb::Unknown, "NOOPS\r\n"),
+            ("HELPER\r\n", command::Verb::Unknown, "HELPER\r\n"),
+            ("QUIT\r\n", command::Verb::Quit, "\r\n"),
+            ("RSET foo\r\n", command::Verb::Rset, " foo\r\n"),
+            ("data \r\n", command::Verb::Data, " \r\n"),
+            ("STARTTLS bar\r\n", command::Verb::StartTls, " bar\r\n"),
+            ("QUITS\r\n", command::Verb::Unknown, "QUITS\r\n"),
+            ("DATAX\r\n", command::Verb::Unknown, "DATAX\r\n"),
+        ] {
+            assert_eq!(
+                super::parse_command_line(&line.as_bytes().to_vec()).unwrap(),
+                (verb, command::UnparsedArgs(args.as_bytes().to_vec())),
+                "{line}"
+            );
+        }
+    }
+
+    #[allow(clippy::unwrap_used)]
+    #[tokio::test]
+    async fn chunks() {
+        let input = [
+            "BDAT 5\r\nHELO\n",
+            "BDAT 0 LAST\r\n",
+            "BDAT 4 LAST\r\n1234QUIT\r\n",
+        ]
+        .concat();
+
+        let cursor = std::io::Cursor::new(input);
+        let mut reader = super::Reader::new(cursor, true);
+
+        for (size, chunk) in [(5, b"HELO\n".as_slice()), (0, b""), (4, b"1234")] {
+            let batch = {
+                let stream = reader.as_window_stream();
+                tokio::pin!(stream);
+                stream.try_next().await.unwrap().unwrap()
+            };
+            // the batch stops at the BDAT command
+            assert_eq!(batch.len(), 1);
+            assert!(matches!(batch[0], Ok((command::Verb::Bdat, _))));
+
+            assert_eq!(reader.read_chunk(size).await.unwrap(), chunk);
+        }
+
+        reader.discard_chunk(4).await.unwrap();
+        assert_eq!(reader.read_chunk(2).await.unwrap(), b"\r\n");
+        assert_eq!(
+            reader.read_chunk(1).await.unwrap_err().kind(),
+            std::io::ErrorKind::UnexpectedEof
+        );
+    }
 }
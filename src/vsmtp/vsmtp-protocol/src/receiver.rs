@@ -15,15 +15,20 @@
  *
 */
 use crate::{
-    reader::Reader, writer::WindowWriter, AcceptArgs, AuthArgs, ConnectionKind, EhloArgs, Error,
-    HeloArgs, MailFromArgs, RcptToArgs, ReceiverHandler, Verb,
+    reader::Reader, writer::WindowWriter, AcceptArgs, AuthArgs, BdatArgs, ConnectionKind, EhloArgs,
+    Error, HeloArgs, HelpArgs, MailFromArgs, ParametersPolicy, ParseArgsError, RcptToArgs,
+    ReceiverHandler, Verb,
 };
 use tokio_rustls::rustls;
 use tokio_stream::StreamExt;
 use vsmtp_common::{auth::Mechanism, Reply, Stage};
 
+/// Maximum duration to receive a chunk of the message (see RFC 5321 4.5.3.2.6).
+const CHUNK_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(3 * 60);
+
 enum HandshakeOutcome {
     Message,
+    Chunk(BdatArgs),
     UpgradeTLS {
         config: alloc::sync::Arc<rustls::ServerConfig>,
         handshake_timeout: std::time::Duration,
@@ -35,10 +40,43 @@ enum HandshakeOutcome {
     Quit,
 }
 
+#[derive(Debug, Clone, Copy)]
 pub struct ErrorCounter {
     pub error_count: i64,
     pub threshold_soft_error: i64,
     pub threshold_hard_error: i64,
+    pub consecutive_bad_command: i64,
+    pub threshold_consecutive_bad_command: i64,
+}
+
+impl Default for ErrorCounter {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            error_count: 0,
+            threshold_soft_error: -1,
+            threshold_hard_error: -1,
+            consecutive_bad_command: 0,
+            threshold_consecutive_bad_command: -1,
+        }
+    }
+}
+
+impl ErrorCounter {
+    /// Count the `reply` sent as a bad command (a syntax error, a command unrecognized,
+    /// not implemented or out of sequence), any other reply resets the consecutive count.
+    ///
+    /// Returns `true` if the threshold of consecutive bad commands is reached.
+    pub(crate) fn count_bad_command(&mut self, reply: &Reply) -> bool {
+        if matches!(reply.code().value(), 500..=504 | 555) {
+            self.consecutive_bad_command += 1;
+        } else {
+            self.consecutive_bad_command = 0;
+        }
+
+        self.threshold_consecutive_bad_command != -1
+            && self.consecutive_bad_command >= self.threshold_consecutive_bad_command
+    }
 }
 
 /// An handle to send event from the [`ReceiverHandler`] to the [`Receiver`].
@@ -46,9 +84,44 @@ pub struct ErrorCounter {
 #[derive(Default)]
 pub struct ReceiverContext {
     outcome: Option<HandshakeOutcome>,
+    pub(crate) error_counter: ErrorCounter,
 }
 
 impl ReceiverContext {
+    /// Number of error replies sent to the client during the connection.
+    #[inline]
+    #[must_use]
+    pub const fn error_count(&self) -> i64 {
+        self.error_counter.error_count
+    }
+
+    /// Number of errors after which the replies are delayed, [`None`] if disabled.
+    #[inline]
+    #[must_use]
+    pub const fn threshold_soft_error(&self) -> Option<i64> {
+        match self.error_counter.threshold_soft_error {
+            -1 => None,
+            threshold => Some(threshold),
+        }
+    }
+
+    /// Number of errors after which the connection is closed, [`None`] if disabled.
+    #[inline]
+    #[must_use]
+    pub const fn threshold_hard_error(&self) -> Option<i64> {
+        match self.error_counter.threshold_hard_error {
+            -1 => None,
+            threshold => Some(threshold),
+        }
+    }
+
+    /// Number of bad commands received in a row, reset by any other command.
+    #[inline]
+    #[must_use]
+    pub const fn consecutive_bad_command_count(&self) -> i64 {
+        self.error_counter.consecutive_bad_command
+    }
+
     /// Make the [`Receiver`] quit the connection early, and close cleanly.
     #[inline]
     pub fn deny(&mut self) {
@@ -89,11 +162,18 @@ pub struct Receiver<
 {
     pub(crate) sink: WindowWriter<W>,
     pub(crate) stream: Reader<R>,
-    error_counter: ErrorCounter,
     context: ReceiverContext,
     kind: ConnectionKind,
     message_size_max: usize,
     support_pipelining: bool,
+    /// `SMTPUTF8` has been negotiated by the last accepted `MAIL FROM`.
+    smtputf8: bool,
+    /// The size declared (`SIZE=`) by the last accepted `MAIL FROM`.
+    declared_size: Option<usize>,
+    /// Handling of the parameters of `MAIL FROM` and `RCPT TO`.
+    parameters: ParametersPolicy,
+    /// The message being received with `BDAT` commands.
+    chunks: Option<Vec<u8>>,
     v: std::marker::PhantomData<V>,
     h: std::marker::PhantomData<H>,
 }
@@ -110,6 +190,7 @@ where
         handshake_timeout: std::time::Duration,
     ) -> impl tokio_stream::Stream<Item = Result<(), Error>> {
         async_stream::stream! {
+            let (line_length_max, first_line_max) = self.stream.line_limits();
             #[allow(clippy::expect_used)]
             let tcp_stream = self
                 .sink
@@ -151,16 +232,26 @@ where
             // FIXME: see https://github.com/tokio-rs/tls/issues/40
             let (read, write) = tokio::io::split(tls_tcp_stream);
 
-            let (stream, sink) = (Reader::new(read, self.support_pipelining), WindowWriter::new(write));
+            let (stream, sink) = (
+                Reader::new(read, self.support_pipelining)
+                    .with_line_limits(line_length_max, first_line_max),
+                WindowWriter::new(write),
+            );
 
             let secured_receiver = Receiver {
                 sink,
                 stream,
-                context: ReceiverContext { outcome: None },
-                error_counter: self.error_counter,
+                context: ReceiverContext {
+                    outcome: None,
+                    error_counter: self.context.error_counter,
+                },
                 kind: self.kind,
                 message_size_max: self.message_size_max,
                 support_pipelining: self.support_pipelining,
+                smtputf8: false,
+                declared_size: None,
+                parameters: self.parameters,
+                chunks: None,
                 v: self.v,
                 h: self.h,
             }.into_secured_stream(
@@ -196,19 +287,65 @@ where
         Self {
             sink,
             stream,
-            error_counter: ErrorCounter {
-                error_count: 0,
-                threshold_soft_error,
-                threshold_hard_error,
+            context: ReceiverContext {
+                outcome: None,
+                error_counter: ErrorCounter {
+                    error_count: 0,
+                    threshold_soft_error,
+                    threshold_hard_error,
+                    consecutive_bad_command: 0,
+                    threshold_consecutive_bad_command: -1,
+                },
             },
-            context: ReceiverContext { outcome: None },
             kind,
             message_size_max,
             support_pipelining,
+            smtputf8: false,
+            declared_size: None,
+            parameters: ParametersPolicy::default(),
+            chunks: None,
             v: std::marker::PhantomData,
             h: std::marker::PhantomData,
         }
     }
+
+    /// Set the max size of a command line (including the CRLF), and the max number
+    /// of bytes received before the first CRLF.
+    ///
+    /// A longer command line is discarded and replied with a `500`, the connection
+    /// is closed with a `421` if no CRLF is received in time.
+    #[must_use]
+    #[inline]
+    pub fn with_line_limits(mut self, line_length_max: usize, first_line_max: usize) -> Self {
+        self.stream = self
+            .stream
+            .with_line_limits(line_length_max, first_line_max);
+        self
+    }
+
+    /// Set the number of bad commands in a row after which the connection is closed,
+    /// `-1` to disable.
+    ///
+    /// A bad command is replied with a `500` to `504` or a `555`, any other reply resets
+    /// the count. Closes faster than the hard threshold of errors a client desynchronized
+    /// from the protocol, sending binary data for instance.
+    #[must_use]
+    #[inline]
+    pub const fn with_consecutive_bad_command_max(mut self, threshold: i64) -> Self {
+        self.context.error_counter.threshold_consecutive_bad_command = threshold;
+        self
+    }
+
+    /// Set the handling of the parameters of the `MAIL FROM` and `RCPT TO` commands.
+    ///
+    /// An unknown parameter is replied with a `555` if the policy is strict, and
+    /// ignored otherwise. A command exceeding the limits is replied with a `501`.
+    #[must_use]
+    #[inline]
+    pub const fn with_parameters(mut self, parameters: ParametersPolicy) -> Self {
+        self.parameters = parameters;
+        self
+    }
     /// Handle the inner stream to produce a [`tokio_stream::Stream`], each item
     /// being a successful SMTP transaction.
     ///
@@ -262,9 +399,9 @@ where
                 }
             ).await;
             let mut handler = match accepted {
-                (mut handler, ReceiverContext{ outcome: None }, Some(reply_accept)) => {
+                (mut handler, ReceiverContext{ outcome: None, .. }, Some(reply_accept)) => {
                     self.sink
-                        .direct_send_reply(&mut self.context, &mut self.error_counter, &mut handler, reply_accept)
+                        .direct_send_reply(&mut self.context, &mut handler, reply_accept)
                         .await?;
                     handler
                 }
@@ -273,16 +410,17 @@ where
                         config,
                         handshake_timeout
                     }),
+                    ..
                 }, None) => {
                     for await i in self.upgrade_tls(handler, config, handshake_timeout) {
                         yield i?;
                     }
                     return;
                 }
-                (mut handler, ReceiverContext{ outcome: Some(HandshakeOutcome::Quit) }, reply_accept) => {
+                (mut handler, ReceiverContext{ outcome: Some(HandshakeOutcome::Quit), .. }, reply_accept) => {
                     if let Some(reply_accept) = reply_accept {
                         self.sink
-                            .direct_send_reply(&mut self.context, &mut self.error_counter, &mut handler, reply_accept)
+                            .direct_send_reply(&mut self.context, &mut handler, reply_accept)
                             .await?;
                     }
                     return;
@@ -305,11 +443,24 @@ where
                                 }
                             }
                         }
+                        // the service is not available, the connection is closed once replied.
+                        let closing = reply.code().value() == 421;
                         self.sink
-                            .direct_send_reply(&mut self.context, &mut self.error_counter, &mut handler, reply)
+                            .direct_send_reply(&mut self.context, &mut handler, reply)
                             .await?;
 
                         yield ();
+                        if closing {
+                            return;
+                        }
+                    },
+                    HandshakeOutcome::Chunk(args) => {
+                        if let Some(closing) = self.receive_chunk(&mut handler, args).await? {
+                            yield ();
+                            if closing {
+                                return;
+                            }
+                        }
                     },
                     HandshakeOutcome::UpgradeTLS { config, handshake_timeout } => {
                         for await i in self.upgrade_tls(handler, config, handshake_timeout) {
@@ -323,10 +474,10 @@ where
 
                         let reply = handler.on_post_auth(&mut self.context, auth_result).await;
                         self.sink
-                            .direct_send_reply(&mut self.context, &mut self.error_counter, &mut handler, reply)
+                            .direct_send_reply(&mut self.context, &mut handler, reply)
                             .await?;
 
-                        if matches!(std::mem::take(&mut self.context).outcome, Some(HandshakeOutcome::Quit)) {
+                        if matches!(self.context.outcome.take(), Some(HandshakeOutcome::Quit)) {
                             return;
                         }
 
@@ -369,7 +520,6 @@ where
             if self.kind == ConnectionKind::Tunneled {
                 self.sink.direct_send_reply(
                     &mut self.context,
-                    &mut self.error_counter,
                     &mut handler,
                     reply_post_tls_handshake
                 ).await?;
@@ -390,11 +540,24 @@ where
                                 }
                             }
                         }
+                        // the service is not available, the connection is closed once replied.
+                        let closing = reply.code().value() == 421;
                         self.sink
-                            .direct_send_reply(&mut self.context, &mut self.error_counter, &mut handler, reply)
+                            .direct_send_reply(&mut self.context, &mut handler, reply)
                             .await?;
 
                         yield ();
+                        if closing {
+                            return;
+                        }
+                    },
+                    HandshakeOutcome::Chunk(args) => {
+                        if let Some(closing) = self.receive_chunk(&mut handler, args).await? {
+                            yield ();
+                            if closing {
+                                return;
+                            }
+                        }
                     },
                     HandshakeOutcome::UpgradeTLS { .. } => panic!("smtp_handshake should not return UpgradeTLS"),
                     HandshakeOutcome::Authenticate { mechanism, initial_response } => {
@@ -403,10 +566,10 @@ where
 
                         let reply = handler.on_post_auth(&mut self.context, auth_result).await;
                         self.sink
-                            .direct_send_reply(&mut self.context, &mut self.error_counter, &mut handler, reply)
+                            .direct_send_reply(&mut self.context, &mut handler, reply)
                             .await?;
 
-                        if matches!(std::mem::take(&mut self.context).outcome, Some(HandshakeOutcome::Quit)) {
+                        if matches!(self.context.outcome.take(), Some(HandshakeOutcome::Quit)) {
                             return;
                         }
 
@@ -417,6 +580,99 @@ where
         }
     }
 
+    /// Receive a chunk of the message sent with a `BDAT` command.
+    ///
+    /// # Returns
+    ///
+    /// * `Some` if it was the last chunk, and the message has been handled,
+    ///   `Some(true)` if the connection must be closed (the reply was a `421`)
+    #[allow(clippy::future_not_send)]
+    async fn receive_chunk(
+        &mut self,
+        handler: &mut T,
+        args: BdatArgs,
+    ) -> Result<Option<bool>, Error> {
+        let stage = handler.get_stage();
+        let rejected = if stage == Stage::RcptTo {
+            handler.on_bdat(&args).await
+        } else {
+            Some(handler.on_bad_sequence((Verb::Bdat, stage)).await)
+        };
+
+        // the size of the message is checked across all the chunks, a client can declare
+        // a smaller size than the maximum with `MAIL FROM:<...> SIZE=...`.
+        let size = self
+            .chunks
+            .as_ref()
+            .map_or(0, Vec::len)
+            .saturating_add(args.chunk_size);
+        let size_max = self
+            .declared_size
+            .map_or(self.message_size_max, |declared| {
+                declared.min(self.message_size_max)
+            });
+        if rejected.is_some() || size > size_max {
+            tokio::time::timeout(CHUNK_TIMEOUT, self.stream.discard_chunk(args.chunk_size))
+                .await
+                .map_err(|_elapsed| Error::timeout(CHUNK_TIMEOUT, "chunk not received"))??;
+
+            let reply = if let Some(reply) = rejected {
+                reply
+            } else {
+                self.chunks = None;
+                let too_long = Error::buffer_too_long(size_max, size);
+                let message_stream = tokio_stream::iter([Err(too_long)]);
+                handler
+                    .on_message(&mut self.context, message_stream)
+                    .await
+                    .0
+            };
+            self.sink
+                .direct_send_reply(&mut self.context, handler, reply)
+                .await?;
+            return Ok(None);
+        }
+
+        let chunk = tokio::time::timeout(CHUNK_TIMEOUT, self.stream.read_chunk(args.chunk_size))
+            .await
+            .map_err(|_elapsed| Error::timeout(CHUNK_TIMEOUT, "chunk not received"))??;
+        tracing::trace!("<< chunk of {} bytes", chunk.len());
+        self.chunks.get_or_insert_with(Vec::new).extend(chunk);
+
+        if !args.is_last {
+            let reply = handler.on_chunk(args.chunk_size).await;
+            self.sink
+                .direct_send_reply(&mut self.context, handler, reply)
+                .await?;
+            return Ok(None);
+        }
+
+        // the message is not dot-stuffed, and can be empty (`BDAT 0 LAST`)
+        let message = self.chunks.take().unwrap_or_default();
+        let message_stream = tokio_stream::iter(
+            message
+                .split_inclusive(|c| *c == b'\n')
+                .map(|line| Ok(line.to_vec()))
+                .collect::<Vec<_>>(),
+        );
+
+        let (mut reply, completed) = handler.on_message(&mut self.context, message_stream).await;
+        if let Some(completed) = completed {
+            for item in completed {
+                if let Some(error) = handler.on_message_completed(item).await {
+                    reply = error;
+                    break;
+                }
+            }
+        }
+        let closing = reply.code().value() == 421;
+        self.sink
+            .direct_send_reply(&mut self.context, handler, reply)
+            .await?;
+
+        Ok(Some(closing))
+    }
+
     /// SMTP handshake (generate the envelope and metadata).
     ///
     /// # Returns
@@ -455,7 +711,6 @@ where
                     self.sink
                         .direct_send_reply(
                             &mut self.context,
-                            &mut self.error_counter,
                             handler,
                             "451 Timeout - closing connection\r\n"
                                 .parse()
@@ -465,6 +720,31 @@ where
 
                     return Ok(HandshakeOutcome::Quit);
                 }
+                Ok(Some(Err(e)))
+                    if matches!(
+                        e.get_ref().and_then(
+                            <(dyn std::error::Error
+                                 + std::marker::Send
+                                 + std::marker::Sync
+                                 + 'static)>::downcast_ref
+                        ),
+                        Some(ParseArgsError::BufferTooLong { .. })
+                    ) =>
+                {
+                    tracing::warn!("Closing, no CRLF received: {}", e);
+                    #[allow(clippy::expect_used)]
+                    self.sink
+                        .direct_send_reply(
+                            &mut self.context,
+                            handler,
+                            "421 4.5.6 Line too long - closing connection\r\n"
+                                .parse()
+                                .expect("valid syntax"),
+                        )
+                        .await?;
+
+                    return Ok(HandshakeOutcome::Quit);
+                }
                 _ => return Ok(HandshakeOutcome::Quit),
             };
             for command in commands_batch {
@@ -479,12 +759,7 @@ where
                         ) {
                             let reply = handler.on_args_error(e).await;
                             self.sink
-                                .direct_send_reply(
-                                    &mut self.context,
-                                    &mut self.error_counter,
-                                    handler,
-                                    reply,
-                                )
+                                .direct_send_reply(&mut self.context, handler, reply)
                                 .await?;
                             continue;
                         }
@@ -494,12 +769,28 @@ where
                 };
                 tracing::trace!("<< {:?} ; {:?}", verb, std::str::from_utf8(&args.0));
 
+                if matches!(verb, Verb::Data | Verb::Quit | Verb::Rset | Verb::StartTls) {
+                    if let Err(e) = args.expect_none() {
+                        if self.parameters.reject_unexpected {
+                            let reply = handler.on_args_error(&e).await;
+                            self.sink
+                                .send_reply(&mut self.context, handler, reply, verb)
+                                .await?;
+                            continue;
+                        }
+                        tracing::warn!(?verb, "Ignoring the parameters of a command taking none.");
+                    }
+                }
+
                 let stage = handler.get_stage();
                 let reply = match (verb, stage) {
                     (Verb::Helo, _) => Some(handle_args!(HeloArgs, args, on_helo)),
                     (Verb::Ehlo, _) => Some(handle_args!(EhloArgs, args, on_ehlo)),
                     (Verb::Noop, _) => Some(handler.on_noop().await),
-                    (Verb::Rset, _) => Some(handler.on_rset().await),
+                    (Verb::Rset, _) => {
+                        self.chunks = None;
+                        Some(handler.on_rset(&mut self.context).await)
+                    }
                     (Verb::StartTls, Stage::Connect | Stage::Helo) => {
                         Some(handler.on_starttls(&mut self.context).await)
                     }
@@ -507,32 +798,51 @@ where
                         handle_args!(AuthArgs, args, Option: on_auth)
                     }
                     (Verb::MailFrom, Stage::Helo | Stage::MailFrom) => {
-                        Some(handle_args!(MailFromArgs, args, on_mail_from))
+                        match MailFromArgs::parse(&args, &self.parameters) {
+                            Ok(args) => {
+                                let (smtputf8, declared_size) = (args.use_smtputf8, args.size);
+                                let reply = handler.on_mail_from(&mut self.context, args).await;
+                                // NOTE: a refused `MAIL FROM` leaves the transaction as it was.
+                                if !reply.code().is_error() {
+                                    self.smtputf8 = smtputf8;
+                                    self.declared_size = declared_size;
+                                }
+                                Some(reply)
+                            }
+                            Err(e) => Some(handler.on_args_error(&e).await),
+                        }
                     }
                     (Verb::RcptTo, Stage::MailFrom | Stage::RcptTo) => {
-                        Some(handle_args!(RcptToArgs, args, on_rcpt_to))
+                        match RcptToArgs::parse(&args, self.smtputf8, &self.parameters) {
+                            Ok(args) => Some(handler.on_rcpt_to(&mut self.context, args).await),
+                            Err(e) => Some(handler.on_args_error(&e).await),
+                        }
                     }
-                    (Verb::Data, Stage::RcptTo) => {
+                    (Verb::Data, Stage::RcptTo) if self.chunks.is_none() => {
                         self.context.outcome = Some(HandshakeOutcome::Message);
                         Some(handler.on_data().await)
                     }
+                    (Verb::Bdat, _) => match BdatArgs::try_from(args) {
+                        Ok(args) => {
+                            self.context.outcome = Some(HandshakeOutcome::Chunk(args));
+                            None
+                        }
+                        Err(e) => Some(handler.on_args_error(&e).await),
+                    },
                     (Verb::Quit, _) => {
                         self.context.outcome = Some(HandshakeOutcome::Quit);
                         Some(handler.on_quit().await)
                     }
-                    (Verb::Help, _) => Some(handler.on_help(args).await),
+                    (Verb::Help, _) => match HelpArgs::try_from(args) {
+                        Ok(args) => Some(handler.on_help(args).await),
+                        Err(e) => Some(handler.on_args_error(&e).await),
+                    },
                     (Verb::Unknown, _) => Some(handler.on_unknown(args.0).await),
                     otherwise => Some(handler.on_bad_sequence(otherwise).await),
                 };
                 if let Some(reply) = reply {
                     self.sink
-                        .send_reply(
-                            &mut self.context,
-                            &mut self.error_counter,
-                            handler,
-                            reply,
-                            verb,
-                        )
+                        .send_reply(&mut self.context, handler, reply, verb)
                         .await?;
                 }
             }
@@ -540,7 +850,7 @@ where
             if !self.sink.is_empty() {
                 self.sink.flush().await?;
             }
-            if let Some(done) = std::mem::take(&mut self.context).outcome {
+            if let Some(done) = self.context.outcome.take() {
                 return Ok(done);
             }
         }
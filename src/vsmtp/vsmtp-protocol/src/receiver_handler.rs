@@ -16,13 +16,38 @@
 */
 
 use crate::{
-    receiver::ReceiverContext, smtp_sasl::CallbackWrap, AuthArgs, AuthError, EhloArgs, Error,
-    HeloArgs, MailFromArgs, ParseArgsError, RcptToArgs, UnparsedArgs, Verb,
+    receiver::ReceiverContext, smtp_sasl::CallbackWrap, AuthArgs, AuthError, BdatArgs, EhloArgs,
+    Error, HeloArgs, HelpArgs, MailFromArgs, ParseArgsError, RcptToArgs, Verb,
 };
 use tokio_rustls::rustls;
 // TODO: should we move these type in this crate
 use vsmtp_common::{Reply, Stage};
 
+/// The reply to an unknown command, `502` for the commands not implemented
+/// (`VRFY`, `EXPN` and `TURN`) and `500` for the others.
+#[inline]
+#[must_use]
+pub fn unknown_command_reply(buffer: &[u8]) -> Reply {
+    let unimplemented_command = [b"VRFY".as_slice(), b"EXPN".as_slice(), b"TURN".as_slice()];
+
+    #[allow(clippy::expect_used)]
+    if unimplemented_command.iter().any(|c| {
+        buffer.len() >= c.len()
+            && buffer
+                .get(..c.len())
+                .expect("range checked before")
+                .eq_ignore_ascii_case(c)
+    }) {
+        "502 Command not implemented\r\n"
+            .parse()
+            .expect("valid syntax")
+    } else {
+        "500 Syntax error command unrecognized\r\n"
+            .parse()
+            .expect("valid syntax")
+    }
+}
+
 // NOTE: could have 3 trait to make the implementation easier
 // PreTransactionHandler + TransactionHandler + PostTransactionHandler
 
@@ -95,8 +120,28 @@ pub trait ReceiverHandler {
     /// Called when the number of reply considered as error reached a threshold (soft).
     async fn on_soft_error(&mut self, ctx: &mut ReceiverContext, reply: Reply) -> Reply;
 
+    /// Called when the number of bad commands received in a row reached a threshold.
+    #[inline]
+    async fn on_consecutive_bad_commands(
+        &mut self,
+        ctx: &mut ReceiverContext,
+        reply: Reply,
+    ) -> Reply {
+        tracing::warn!(
+            count = ctx.consecutive_bad_command_count(),
+            "Closing, too many bad commands in a row."
+        );
+        ctx.deny();
+        #[allow(clippy::expect_used)]
+        reply.extended(
+            &"421 Too many bad commands in a row, closing connection\r\n"
+                .parse()
+                .expect("valid syntax"),
+        )
+    }
+
     /// Called after receiving a [`Verb::Rset`] command.
-    async fn on_rset(&mut self) -> Reply;
+    async fn on_rset(&mut self, ctx: &mut ReceiverContext) -> Reply;
 
     /// Called after receiving a [`Verb::Data`] command.
     #[inline]
@@ -107,6 +152,23 @@ pub trait ReceiverHandler {
             .expect("valid syntax")
     }
 
+    /// Called after receiving a [`Verb::Bdat`] command, before reading the chunk.
+    ///
+    /// If this callback returns `Some`, the chunk is discarded and the reply is sent instead.
+    #[inline]
+    async fn on_bdat(&mut self, _: &BdatArgs) -> Option<Reply> {
+        None
+    }
+
+    /// Called after receiving a chunk of the message which is not the last one.
+    #[inline]
+    async fn on_chunk(&mut self, chunk_size: usize) -> Reply {
+        #[allow(clippy::expect_used)]
+        format!("250 2.0.0 {chunk_size} octets received\r\n")
+            .parse()
+            .expect("valid syntax")
+    }
+
     /// Called after receiving a [`Verb::Quit`] command.
     #[inline]
     async fn on_quit(&mut self) -> Reply {
@@ -123,9 +185,9 @@ pub trait ReceiverHandler {
         "250 Ok\r\n".parse().expect("valid syntax")
     }
 
-    /// Called after receiving a [`Verb::Help`] command.
+    /// Called after receiving a [`Verb::Help`] command, with the topic requested if any.
     #[inline]
-    async fn on_help(&mut self, _: UnparsedArgs) -> Reply {
+    async fn on_help(&mut self, _: HelpArgs) -> Reply {
         #[allow(clippy::expect_used)]
         "214 joining us https://viridit.com/support"
             .parse()
@@ -135,24 +197,7 @@ pub trait ReceiverHandler {
     /// Called after receiving an unknown command (unrecognized or unimplemented).
     #[inline]
     async fn on_unknown(&mut self, buffer: Vec<u8>) -> Reply {
-        let unimplemented_command = [b"VRFY".as_slice(), b"EXPN".as_slice(), b"TURN".as_slice()];
-
-        #[allow(clippy::expect_used)]
-        if unimplemented_command.iter().any(|c| {
-            buffer.len() >= c.len()
-                && buffer
-                    .get(..c.len())
-                    .expect("range checked before")
-                    .eq_ignore_ascii_case(c)
-        }) {
-            "502 Command not implemented\r\n"
-                .parse()
-                .expect("valid syntax")
-        } else {
-            "500 Syntax error command unrecognized\r\n"
-                .parse()
-                .expect("valid syntax")
-        }
+        unknown_command_reply(&buffer)
     }
 
     /// Called when the stage of the transaction (obtained with [`get_stage`](Self::get_stage))
@@ -179,8 +224,25 @@ pub trait ReceiverHandler {
                     .parse()
                     .expect("valid syntax")
             }
-            ParseArgsError::EmailUnavailable => {
-                "550 mailbox unavailable\r\n".parse().expect("valid syntax")
+            ParseArgsError::Smtputf8Required { .. } => "553 5.6.7 SMTPUTF8 required\r\n"
+                .parse()
+                .expect("valid syntax"),
+            ParseArgsError::BufferTooLong { .. } => {
+                "500 5.5.6 Line too long\r\n".parse().expect("valid syntax")
+            }
+            ParseArgsError::UnknownParameter { .. } => "555 5.5.4 Unsupported parameter\r\n"
+                .parse()
+                .expect("valid syntax"),
+            ParseArgsError::TooManyParameters { .. } => "501 5.5.4 Too many parameters\r\n"
+                .parse()
+                .expect("valid syntax"),
+            ParseArgsError::ParameterTooLong { .. } => "501 5.5.4 Parameter too long\r\n"
+                .parse()
+                .expect("valid syntax"),
+            ParseArgsError::UnexpectedParameters => {
+                "501 5.5.4 Syntax error in parameters or arguments\r\n"
+                    .parse()
+                    .expect("valid syntax")
             }
             _other => "501 Syntax error in parameters or arguments\r\n"
                 .parse()
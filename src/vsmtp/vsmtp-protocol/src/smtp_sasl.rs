@@ -168,8 +168,13 @@ where
                             .decode(buffer)
                             .map_err(|source| AuthError::Base64 { source })?,
                     ),
-                    Some(Err(e)) => todo!("{}", e),
-                    None => todo!("what happen when the client close the connection?"),
+                    Some(Err(e)) => return Err(AuthError::IO(e)),
+                    None => {
+                        return Err(AuthError::IO(std::io::Error::new(
+                            std::io::ErrorKind::UnexpectedEof,
+                            "connection closed during the SASL handshake",
+                        )))
+                    }
                 }
             };
         }
@@ -181,6 +186,8 @@ where
                 next_challenge_line!(challenge_stream)
             }
             (Some(_), true) => return Err(AuthError::ClientMustNotStart),
+            // RFC 4954: "=" stands for an empty initial response.
+            (Some(data), false) if data == b"=" => Some(vec![]),
             (Some(data), false) => Some(STANDARD.decode(data)?),
         };
 
@@ -14,7 +14,7 @@
  * this program. If not, see https://www.gnu.org/licenses/.
  *
 */
-use crate::{receiver::ErrorCounter, ReceiverContext, ReceiverHandler, Verb};
+use crate::{ReceiverContext, ReceiverHandler, Verb};
 use tokio::io::AsyncWriteExt;
 use vsmtp_common::Reply;
 
@@ -82,22 +82,26 @@ impl<W: tokio::io::AsyncWrite + Unpin + Send> WindowWriter<W> {
     async fn handle_error<T: ReceiverHandler + Send>(
         &mut self,
         ctx: &mut ReceiverContext,
-        error_counter: &mut ErrorCounter,
         handler: &mut T,
         reply: Reply,
     ) -> Reply {
+        let desynchronized = ctx.error_counter.count_bad_command(&reply);
         if !reply.code().is_error() {
             return reply;
         }
-        error_counter.error_count += 1;
+        ctx.error_counter.error_count += 1;
 
-        let hard_error = error_counter.threshold_hard_error;
-        let soft_error = error_counter.threshold_soft_error;
+        let error_count = ctx.error_count();
+        let hard_error = ctx.error_counter.threshold_hard_error;
+        let soft_error = ctx.error_counter.threshold_soft_error;
 
-        if hard_error != -1 && error_counter.error_count >= hard_error {
+        if desynchronized {
+            return handler.on_consecutive_bad_commands(ctx, reply).await;
+        }
+        if hard_error != -1 && error_count >= hard_error {
             return handler.on_hard_error(ctx, reply).await;
         }
-        if soft_error != -1 && error_counter.error_count >= soft_error {
+        if soft_error != -1 && error_count >= soft_error {
             return handler.on_soft_error(ctx, reply).await;
         }
         reply
@@ -106,11 +110,10 @@ impl<W: tokio::io::AsyncWrite + Unpin + Send> WindowWriter<W> {
     pub async fn direct_send_reply<T: ReceiverHandler + Send>(
         &mut self,
         ctx: &mut ReceiverContext,
-        error_counter: &mut ErrorCounter,
         handler: &mut T,
         reply: Reply,
     ) -> std::io::Result<()> {
-        let final_reply = self.handle_error(ctx, error_counter, handler, reply).await;
+        let final_reply = self.handle_error(ctx, handler, reply).await;
         self.write_all(final_reply.as_ref()).await
     }
 
@@ -118,12 +121,11 @@ impl<W: tokio::io::AsyncWrite + Unpin + Send> WindowWriter<W> {
     pub async fn send_reply<T: ReceiverHandler + Send>(
         &mut self,
         ctx: &mut ReceiverContext,
-        error_counter: &mut ErrorCounter,
         handler: &mut T,
         reply: Reply,
         verb: Verb,
     ) -> std::io::Result<()> {
-        let final_reply = self.handle_error(ctx, error_counter, handler, reply).await;
+        let final_reply = self.handle_error(ctx, handler, reply).await;
         if verb.is_bufferable() {
             if !self.buffer.is_empty() {
                 self.flush().await?;
@@ -204,24 +206,30 @@ impl<W: tokio::io::AsyncWrite + Unpin + Send> Writer<W> {
     pub async fn send_reply<T: ReceiverHandler + Send>(
         &mut self,
         ctx: &mut ReceiverContext,
-        error_counter: &mut ErrorCounter,
         handler: &mut T,
         reply: Reply,
     ) -> std::io::Result<()> {
+        let desynchronized = ctx.error_counter.count_bad_command(&reply);
         if !reply.code().is_error() {
             return self.write_all(reply.as_ref()).await;
         }
-        error_counter.error_count += 1;
+        ctx.error_counter.error_count += 1;
+
+        let error_count = ctx.error_count();
+        let hard_error = ctx.error_counter.threshold_hard_error;
+        let soft_error = ctx.error_counter.threshold_soft_error;
 
-        let hard_error = error_counter.threshold_hard_error;
-        let soft_error = error_counter.threshold_soft_error;
+        if desynchronized {
+            let reply = handler.on_consecutive_bad_commands(ctx, reply).await;
+            return self.write_all(reply.as_ref()).await;
+        }
 
-        if hard_error != -1 && error_counter.error_count >= hard_error {
+        if hard_error != -1 && error_count >= hard_error {
             let reply = handler.on_hard_error(ctx, reply).await;
             return self.write_all(reply.as_ref()).await;
         }
 
-        if soft_error != -1 && error_counter.error_count >= soft_error {
+        if soft_error != -1 && error_count >= soft_error {
             let reply = handler.on_soft_error(ctx, reply).await;
             return self.write_all(reply.as_ref()).await;
         }
@@ -0,0 +1,457 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2023 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use crate::watched_file::WatchedFile;
+use std::net::IpAddr;
+use vsmtp_common::{Network, Reply};
+use vsmtp_config::field::{AccessListAction, FieldAccessList, FieldServerAccessLists};
+
+/// Header added to the messages of a client or a sender found in a list with the `tag` action.
+pub const ACCESS_LIST_HEADER: &str = "X-VSMTP-Access-List";
+
+/// A sender domain, matching its sub-domains too.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct SenderDomain(String);
+
+impl std::str::FromStr for SenderDomain {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let domain = s.trim_end_matches('.').to_ascii_lowercase();
+        anyhow::ensure!(
+            !domain.is_empty()
+                && domain.split('.').all(|label| {
+                    !label.is_empty()
+                        && label
+                            .chars()
+                            .all(|c| c.is_alphanumeric() || c == '-' || c == '_')
+                }),
+            "invalid domain '{s}'"
+        );
+
+        Ok(Self(domain))
+    }
+}
+
+impl SenderDomain {
+    fn matches(&self, domain: &str) -> bool {
+        let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+        domain
+            .strip_suffix(&self.0)
+            .map_or(false, |sub| sub.is_empty() || sub.ends_with('.'))
+    }
+}
+
+/// Parse the entries of a list, the malformed lines are skipped.
+fn parse<T: std::str::FromStr>(path: &std::path::Path, content: &str) -> Vec<T> {
+    let mut malformed = vec![];
+
+    let entries = content
+        .lines()
+        .enumerate()
+        .filter_map(|(index, line)| {
+            let entry = line.split('#').next().unwrap_or_default().trim();
+            if entry.is_empty() {
+                return None;
+            }
+            entry.parse().map_err(|_| malformed.push(index + 1)).ok()
+        })
+        .collect();
+
+    if !malformed.is_empty() {
+        tracing::warn!(
+            path = %path.display(),
+            lines = ?malformed,
+            "Malformed lines of the access list skipped."
+        );
+    }
+
+    entries
+}
+
+/// The entries of a list, read again when its file is modified.
+type ListFile<T> = WatchedFile<Vec<T>>;
+
+fn list_file<T: std::str::FromStr + 'static>(
+    path: &std::path::Path,
+) -> anyhow::Result<ListFile<T>> {
+    WatchedFile::new(path, "list", parse)
+}
+
+/// A blocklist, and the action applied to its entries.
+#[derive(Debug)]
+struct Blocklist<T> {
+    name: &'static str,
+    file: ListFile<T>,
+    action: AccessListAction,
+    reply: Reply,
+}
+
+impl<T: std::str::FromStr + 'static> Blocklist<T> {
+    fn new(
+        name: &'static str,
+        list: &FieldAccessList,
+        deny: &str,
+        tempfail: &str,
+    ) -> anyhow::Result<Self> {
+        let reply = match (&list.reply, list.action) {
+            (Some(reply), _) => reply.clone(),
+            (None, AccessListAction::Tempfail) => tempfail.parse().expect("valid smtp reply"),
+            (None, AccessListAction::Deny | AccessListAction::Tag) => {
+                deny.parse().expect("valid smtp reply")
+            }
+        };
+
+        Ok(Self {
+            name,
+            file: list_file(&list.path)?,
+            action: list.action,
+            reply,
+        })
+    }
+
+    fn verdict(&self, value: &str) -> AccessVerdict {
+        match self.action {
+            AccessListAction::Deny | AccessListAction::Tempfail => {
+                AccessVerdict::Refuse(self.reply.clone())
+            }
+            AccessListAction::Tag => AccessVerdict::Tag(format!("{}={value}", self.name)),
+        }
+    }
+}
+
+/// What to do with a client or a sender found in a blocklist.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AccessVerdict {
+    /// The client is disconnected, or the sender rejected, with this reply.
+    Refuse(Reply),
+    /// The message is accepted, with this value in the [`ACCESS_LIST_HEADER`] header.
+    Tag(String),
+}
+
+/// Lists of clients and sender domains maintained in plain text files,
+/// see [`FieldServerAccessLists`].
+///
+/// The lists are consulted before the rules, and exposed to them in the `lists` module.
+/// A list is swapped atomically when its file is read again.
+#[derive(Debug, Default)]
+pub struct AccessLists {
+    blocked_ips: Option<Blocklist<Network>>,
+    blocked_senders: Option<Blocklist<SenderDomain>>,
+    allowed_ips: Option<ListFile<Network>>,
+}
+
+impl AccessLists {
+    /// Read the lists declared in the configuration.
+    ///
+    /// # Errors
+    ///
+    /// * a file could not be read
+    pub fn new(config: Option<&FieldServerAccessLists>) -> anyhow::Result<Self> {
+        let Some(config) = config else {
+            return Ok(Self::default());
+        };
+
+        Ok(Self {
+            blocked_ips: config
+                .blocked_ips
+                .as_ref()
+                .map(|list| {
+                    Blocklist::new(
+                        "blocked_ips",
+                        list,
+                        "554 5.7.1 Client host rejected: access denied\r\n",
+                        "421 4.7.1 Client host temporarily refused\r\n",
+                    )
+                })
+                .transpose()?,
+            blocked_senders: config
+                .blocked_senders
+                .as_ref()
+                .map(|list| {
+                    Blocklist::new(
+                        "blocked_senders",
+                        list,
+                        "550 5.7.1 Sender address rejected: domain blocked\r\n",
+                        "450 4.7.1 Sender address temporarily refused\r\n",
+                    )
+                })
+                .transpose()?,
+            allowed_ips: config.allowed_ips.as_deref().map(list_file).transpose()?,
+        })
+    }
+
+    /// Read again the files modified since their last read.
+    ///
+    /// If a file cannot be read, its previous entries are kept.
+    pub fn reload_if_changed(&self) {
+        fn reload<T: std::str::FromStr>(file: &ListFile<T>) {
+            match file.reload_if_changed() {
+                Ok(true) => tracing::info!(
+                    path = %file.path().display(),
+                    entries = file.content().len(),
+                    "Access list reloaded."
+                ),
+                Ok(false) => (),
+                Err(error) => tracing::warn!(
+                    %error,
+                    "Access list reload failure, the previous entries are kept."
+                ),
+            }
+        }
+
+        if let Some(list) = &self.blocked_ips {
+            reload(&list.file);
+        }
+        if let Some(list) = &self.blocked_senders {
+            reload(&list.file);
+        }
+        if let Some(file) = &self.allowed_ips {
+            reload(file);
+        }
+    }
+
+    /// Is the client in the allowlist ?
+    #[must_use]
+    pub fn is_allowed_ip(&self, ip: IpAddr) -> bool {
+        self.allowed_ips.as_ref().map_or(false, |file| {
+            file.content().iter().any(|network| network.contains(ip))
+        })
+    }
+
+    /// Is the client in the blocklist ?
+    #[must_use]
+    pub fn is_blocked_ip(&self, ip: IpAddr) -> bool {
+        self.blocked_ips.as_ref().map_or(false, |list| {
+            list.file
+                .content()
+                .iter()
+                .any(|network| network.contains(ip))
+        })
+    }
+
+    /// Is the domain, or one of its parents, in the blocklist of the senders ?
+    #[must_use]
+    pub fn is_blocked_sender(&self, domain: &str) -> bool {
+        self.blocked_senders.as_ref().map_or(false, |list| {
+            list.file
+                .content()
+                .iter()
+                .any(|sender| sender.matches(domain))
+        })
+    }
+
+    /// Check a client when the connection is accepted.
+    #[must_use]
+    pub fn check_client(&self, ip: IpAddr) -> Option<AccessVerdict> {
+        let list = self.blocked_ips.as_ref()?;
+        let ip = vsmtp_common::utils::canonical_ip(ip);
+
+        (!self.is_allowed_ip(ip) && self.is_blocked_ip(ip)).then(|| list.verdict(&ip.to_string()))
+    }
+
+    /// Check the domain of the reverse-path on `MAIL FROM`,
+    /// the clients of the allowlist are not checked.
+    #[must_use]
+    pub fn check_sender(&self, ip: IpAddr, domain: &str) -> Option<AccessVerdict> {
+        let list = self.blocked_senders.as_ref()?;
+
+        (!self.is_allowed_ip(ip) && self.is_blocked_sender(domain))
+            .then(|| list.verdict(&domain.to_ascii_lowercase()))
+    }
+
+    /// Build the `lists` module of vsl, reading the entries of `self`.
+    #[must_use]
+    pub fn module(self: &std::sync::Arc<Self>) -> rhai::Shared<rhai::Module> {
+        fn parse_ip(ip: &str) -> Result<IpAddr, Box<rhai::EvalAltResult>> {
+            // the zone of a link-local address does not change the entry it matches.
+            ip.split('%')
+                .next()
+                .unwrap_or_default()
+                .parse::<IpAddr>()
+                .map(vsmtp_common::utils::canonical_ip)
+                .map_err(|_| format!("'{ip}' is not a valid ip address").into())
+        }
+
+        let mut module = rhai::Module::new();
+
+        let lists = self.clone();
+        module.set_native_fn(
+            "is_allowed_ip",
+            move |ip: &str| -> Result<bool, Box<rhai::EvalAltResult>> {
+                Ok(lists.is_allowed_ip(parse_ip(ip)?))
+            },
+        );
+        let lists = self.clone();
+        module.set_native_fn(
+            "is_blocked_ip",
+            move |ip: &str| -> Result<bool, Box<rhai::EvalAltResult>> {
+                Ok(lists.is_blocked_ip(parse_ip(ip)?))
+            },
+        );
+        let lists = self.clone();
+        module.set_native_fn(
+            "is_blocked_sender",
+            move |domain: &str| -> Result<bool, Box<rhai::EvalAltResult>> {
+                Ok(lists.is_blocked_sender(domain))
+            },
+        );
+
+        rhai::Shared::new(module)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn list(path: &std::path::Path, action: AccessListAction) -> FieldAccessList {
+        FieldAccessList {
+            path: path.to_path_buf(),
+            action,
+            reply: None,
+        }
+    }
+
+    #[test]
+    fn sender_domains() {
+        let domain = "Example.com.".parse::<SenderDomain>().unwrap();
+        assert!(domain.matches("example.com"));
+        assert!(domain.matches("mail.EXAMPLE.com"));
+        assert!(!domain.matches("notexample.com"));
+        assert!(!domain.matches("example.org"));
+
+        for invalid in ["", "foo..bar", "foo bar", "john@example.com"] {
+            assert!(invalid.parse::<SenderDomain>().is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn malformed_lines() {
+        let entries = parse::<Network>(
+            std::path::Path::new("blocked_ips"),
+            "# comment\n192.0.2.1\n\nnot an address\n2001:db8::/32 # trailing comment\n",
+        );
+        assert_eq!(
+            entries,
+            vec![
+                "192.0.2.1".parse().unwrap(),
+                "2001:db8::/32".parse().unwrap()
+            ]
+        );
+    }
+
+    #[test]
+    fn verdicts() {
+        let dir = tempfile::tempdir().unwrap();
+        let (blocked_ips, blocked_senders, allowed_ips) = (
+            dir.path().join("blocked_ips"),
+            dir.path().join("blocked_senders"),
+            dir.path().join("allowed_ips"),
+        );
+        std::fs::write(&blocked_ips, "192.0.2.0/24\n").unwrap();
+        std::fs::write(&blocked_senders, "spam.example\n").unwrap();
+        std::fs::write(&allowed_ips, "192.0.2.1\n").unwrap();
+
+        let lists = AccessLists::new(Some(&FieldServerAccessLists {
+            blocked_ips: Some(list(&blocked_ips, AccessListAction::Deny)),
+            blocked_senders: Some(list(&blocked_senders, AccessListAction::Tag)),
+            allowed_ips: Some(allowed_ips),
+            reload_period: std::time::Duration::from_secs(10),
+        }))
+        .unwrap();
+
+        let (blocked, allowed, other) = (
+            "192.0.2.2".parse().unwrap(),
+            "192.0.2.1".parse().unwrap(),
+            "203.0.113.1".parse().unwrap(),
+        );
+
+        assert!(matches!(
+            lists.check_client(blocked),
+            Some(AccessVerdict::Refuse(reply)) if reply.code().value() == 554
+        ));
+        assert_eq!(lists.check_client(allowed), None);
+        assert_eq!(lists.check_client(other), None);
+
+        assert_eq!(
+            lists.check_sender(other, "mx.spam.example"),
+            Some(AccessVerdict::Tag(
+                "blocked_senders=mx.spam.example".to_string()
+            ))
+        );
+        assert_eq!(lists.check_sender(allowed, "spam.example"), None);
+        assert_eq!(lists.check_sender(other, "example.com"), None);
+
+        assert_eq!(AccessLists::new(None).unwrap().check_client(blocked), None);
+
+        // a IPv4 client accepted on a IPv6 socket.
+        let (blocked, allowed) = (
+            "::ffff:192.0.2.2".parse().unwrap(),
+            "::ffff:192.0.2.1".parse().unwrap(),
+        );
+        assert_eq!(
+            lists.check_client(blocked),
+            lists.check_client("192.0.2.2".parse().unwrap())
+        );
+        assert!(lists.is_blocked_ip(blocked));
+        assert!(lists.is_allowed_ip(allowed));
+        assert_eq!(lists.check_client(allowed), None);
+        assert_eq!(lists.check_sender(allowed, "spam.example"), None);
+    }
+
+    #[test]
+    fn reload() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("blocked_ips");
+        std::fs::write(&path, "# no entries\n").unwrap();
+
+        let lists = AccessLists::new(Some(&FieldServerAccessLists {
+            blocked_ips: Some(list(&path, AccessListAction::Tempfail)),
+            blocked_senders: None,
+            allowed_ips: None,
+            reload_period: std::time::Duration::from_secs(10),
+        }))
+        .unwrap();
+
+        let client = "192.0.2.1".parse().unwrap();
+        assert_eq!(lists.check_client(client), None);
+
+        std::fs::write(&path, "# no entries\n192.0.2.1\n").unwrap();
+        lists.reload_if_changed();
+        assert_eq!(
+            lists.check_client(client),
+            Some(AccessVerdict::Refuse(
+                "421 4.7.1 Client host temporarily refused\r\n"
+                    .parse()
+                    .unwrap()
+            ))
+        );
+
+        // the previous entries are kept if the file cannot be read.
+        std::fs::remove_file(&path).unwrap();
+        lists.reload_if_changed();
+        assert!(lists.is_blocked_ip(client));
+
+        assert!(AccessLists::new(Some(&FieldServerAccessLists {
+            blocked_ips: Some(list(&path, AccessListAction::Deny)),
+            blocked_senders: None,
+            allowed_ips: None,
+            reload_period: std::time::Duration::from_secs(10),
+        }))
+        .is_err());
+    }
+}
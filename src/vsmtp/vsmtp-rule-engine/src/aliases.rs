@@ -0,0 +1,495 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2023 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use crate::watched_file::WatchedFile;
+use vsmtp_common::{
+    transfer::{self, error::LocalDelivery},
+    transport::{AbstractTransport, WrapperSerde},
+    Address, AddressExtension, ContextFinished, Domain,
+};
+use vsmtp_config::field::FieldServerAliases;
+
+/// Prefix of a target listing the addresses of a file.
+const INCLUDE: &str = ":include:";
+
+/// The targets of each alias, by lowercase address or local part.
+type Table = std::collections::HashMap<String, Vec<String>>;
+
+/// Split the lines of a list on commas, skipping the comments and the empty lines.
+fn split_targets(content: &str) -> impl Iterator<Item = &str> {
+    content
+        .lines()
+        .filter(|line| !line.trim_start().starts_with('#'))
+        .flat_map(|line| line.split(','))
+        .map(str::trim)
+        .filter(|target| !target.is_empty())
+}
+
+/// Parse an aliases file, the malformed lines are skipped.
+///
+/// A line starting with a whitespace continues the previous entry.
+fn parse(path: &std::path::Path, content: &str) -> Table {
+    let mut entries = Vec::<(usize, String)>::new();
+    for (index, line) in content.lines().enumerate() {
+        if line.trim().is_empty() || line.trim_start().starts_with('#') {
+            continue;
+        }
+        match entries.last_mut() {
+            Some((_, entry)) if line.starts_with(char::is_whitespace) => {
+                entry.push(',');
+                entry.push_str(line);
+            }
+            _ => entries.push((index + 1, line.to_string())),
+        }
+    }
+
+    let mut malformed = vec![];
+    let mut table = Table::new();
+    for (line, entry) in entries {
+        match entry.split_once(':') {
+            Some((alias, targets))
+                if !alias.trim().is_empty() && !alias.contains(char::is_whitespace) =>
+            {
+                table
+                    .entry(alias.trim().to_lowercase())
+                    .or_default()
+                    .extend(split_targets(targets).map(str::to_string));
+            }
+            _ => malformed.push(line),
+        }
+    }
+
+    if !malformed.is_empty() {
+        tracing::warn!(
+            path = %path.display(),
+            lines = ?malformed,
+            "Malformed lines of the aliases file skipped."
+        );
+    }
+
+    table
+}
+
+/// Expansion of the recipients by an aliases file, read again when it is modified.
+#[derive(Debug)]
+pub struct Aliases {
+    file: WatchedFile<Table>,
+    max_depth: usize,
+    /// the domains of the server, to which the aliases without domain apply.
+    domains: Vec<Domain>,
+    /// delimiters of the extension of the local part, see `server.recipient_delimiter`.
+    delimiters: String,
+}
+
+impl Aliases {
+    /// Read the aliases file.
+    ///
+    /// # Errors
+    ///
+    /// * the file cannot be read
+    pub fn new(
+        config: &FieldServerAliases,
+        domains: Vec<Domain>,
+        delimiters: String,
+    ) -> anyhow::Result<Self> {
+        Ok(Self {
+            file: WatchedFile::new(&config.path, "aliases", parse)?,
+            max_depth: config.max_depth,
+            domains,
+            delimiters,
+        })
+    }
+
+    /// Read the file again if it has been modified since the last read.
+    ///
+    /// If the file cannot be read, the previous aliases are kept.
+    pub fn reload_if_changed(&self) {
+        match self.file.reload_if_changed() {
+            Ok(true) => tracing::info!(
+                path = %self.file.path().display(),
+                aliases = self.file.content().len(),
+                "Aliases reloaded."
+            ),
+            Ok(false) => (),
+            Err(error) => tracing::warn!(
+                %error,
+                "Aliases reload failure, the previous ones are kept."
+            ),
+        }
+    }
+
+    /// The entry of the full address, or of the address without its extension otherwise.
+    fn lookup<'t>(&self, table: &'t Table, rcpt: &Address) -> Option<&'t Vec<String>> {
+        self.lookup_exact(table, rcpt).or_else(|| {
+            AddressExtension::parse(rcpt, &self.delimiters)
+                .and_then(|extension| self.lookup_exact(table, &extension.base_address(rcpt)))
+        })
+    }
+
+    fn lookup_exact<'t>(&self, table: &'t Table, rcpt: &Address) -> Option<&'t Vec<String>> {
+        table.get(&rcpt.full().to_lowercase()).or_else(|| {
+            self.domains
+                .contains(rcpt.domain_ref())
+                .then(|| table.get(&rcpt.local_part().to_lowercase()))
+                .flatten()
+        })
+    }
+
+    /// Read the addresses of an `:include:` file, relative to the aliases file.
+    fn include(&self, path: &str) -> Vec<String> {
+        let path = self
+            .file
+            .path()
+            .parent()
+            .map_or_else(|| path.into(), |parent| parent.join(path));
+
+        match std::fs::read_to_string(&path) {
+            Ok(content) => split_targets(&content)
+                .filter(|target| !target.starts_with(INCLUDE))
+                .map(str::to_string)
+                .collect(),
+            Err(error) => {
+                tracing::warn!(path = %path.display(), %error, "Failed to read the include file.");
+                vec![]
+            }
+        }
+    }
+
+    fn targets(&self, alias: &Address, targets: &[String]) -> Vec<Address> {
+        targets
+            .iter()
+            .flat_map(|target| match target.strip_prefix(INCLUDE) {
+                Some(path) => self.include(path.trim()),
+                None => vec![target.clone()],
+            })
+            .filter_map(|target| {
+                let target = if target.contains('@') {
+                    target
+                } else {
+                    format!("{target}@{}", alias.domain())
+                };
+                target
+                    .parse::<Address>()
+                    .map_err(|error| tracing::warn!(%alias, %error, "Invalid target skipped."))
+                    .ok()
+            })
+            .collect()
+    }
+
+    fn expand_into(
+        &self,
+        table: &Table,
+        rcpt: &Address,
+        path: &mut Vec<Address>,
+        out: &mut Vec<Address>,
+    ) -> Result<(), String> {
+        let Some(targets) = self.lookup(table, rcpt) else {
+            out.push(rcpt.clone());
+            return Ok(());
+        };
+        if path.len() >= self.max_depth {
+            return Err(format!("deeper than {} levels", self.max_depth));
+        }
+
+        path.push(rcpt.clone());
+        for target in self.targets(rcpt, targets) {
+            // an alias listing itself delivers to its own mailbox.
+            if target == *rcpt {
+                out.push(target);
+            } else if path.contains(&target) {
+                return Err(format!("loops through `{target}`"));
+            } else {
+                self.expand_into(table, &target, path, out)?;
+            }
+        }
+        path.pop();
+
+        Ok(())
+    }
+
+    /// Is `rcpt` an alias ?
+    #[must_use]
+    pub fn is_alias(&self, rcpt: &Address) -> bool {
+        let table = self.file.content();
+
+        self.lookup(&table, rcpt).is_some()
+    }
+
+    /// Expand `rcpt` to the addresses it is an alias of, recursively.
+    /// Returns [`None`] if `rcpt` is not an alias.
+    ///
+    /// # Errors
+    ///
+    /// * the expansion loops, is deeper than `max_depth`, or is empty
+    pub fn expand(&self, rcpt: &Address) -> Result<Option<Vec<Address>>, String> {
+        let table = self.file.content();
+
+        if self.lookup(&table, rcpt).is_none() {
+            return Ok(None);
+        }
+
+        let mut out = vec![];
+        self.expand_into(&table, rcpt, &mut vec![], &mut out)?;
+
+        let mut seen = std::collections::HashSet::new();
+        out.retain(|target| seen.insert(target.clone()));
+
+        if out.is_empty() {
+            return Err("no target".to_string());
+        }
+        Ok(Some(out))
+    }
+
+    /// Replace the aliases among the recipients waiting for delivery by their targets.
+    ///
+    /// The targets of the domain of the alias are delivered with the transport of
+    /// the alias, the others with `remote`, and are returned. The recipient of an alias
+    /// which cannot be expanded fails.
+    pub fn expand_recipients(
+        &self,
+        ctx: &mut ContextFinished,
+        remote: &dyn Fn() -> std::sync::Arc<dyn AbstractTransport>,
+    ) -> Vec<Address> {
+        let delivery = std::mem::take(&mut ctx.rcpt_to.delivery);
+        let mut known = delivery
+            .values()
+            .flatten()
+            .map(|(rcpt, _)| rcpt.clone())
+            .collect::<std::collections::HashSet<_>>();
+        let mut expanded = std::collections::HashMap::<Address, Vec<Address>>::new();
+        let mut forwarded = vec![];
+
+        for (transport, rcpts) in delivery {
+            for (rcpt, mut status) in rcpts {
+                if !matches!(status, transfer::Status::Waiting { .. }) {
+                    ctx.rcpt_to
+                        .delivery
+                        .entry(transport.clone())
+                        .or_default()
+                        .push((rcpt, status));
+                    continue;
+                }
+
+                match self.expand(&rcpt) {
+                    Ok(None) => {}
+                    Ok(Some(targets)) => {
+                        tracing::info!(%rcpt, ?targets, "Alias expanded.");
+                        known.remove(&rcpt);
+
+                        for target in &targets {
+                            if !known.insert(target.clone()) {
+                                continue;
+                            }
+                            let transport = if target.domain() == rcpt.domain() {
+                                transport.clone()
+                            } else {
+                                forwarded.push(target.clone());
+                                WrapperSerde::Ready(remote())
+                            };
+                            ctx.rcpt_to
+                                .delivery
+                                .entry(transport)
+                                .or_default()
+                                .push((target.clone(), transfer::Status::default()));
+                        }
+                        expanded.insert(rcpt, targets);
+                        continue;
+                    }
+                    Err(reason) => {
+                        tracing::warn!(%rcpt, %reason, "Alias cannot be expanded.");
+                        status = transfer::Status::failed(LocalDelivery::Alias {
+                            alias: rcpt.to_string(),
+                            reason,
+                        });
+                    }
+                }
+                ctx.rcpt_to
+                    .delivery
+                    .entry(transport.clone())
+                    .or_default()
+                    .push((rcpt, status));
+            }
+        }
+
+        if !expanded.is_empty() {
+            let mut seen = std::collections::HashSet::new();
+            ctx.rcpt_to.forward_paths = std::mem::take(&mut ctx.rcpt_to.forward_paths)
+                .into_iter()
+                .flat_map(|rcpt| expanded.get(&rcpt).cloned().unwrap_or_else(|| vec![rcpt]))
+                .filter(|rcpt| seen.insert(rcpt.clone()))
+                .collect();
+        }
+
+        forwarded
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vsmtp_common::addr;
+
+    fn aliases(content: &str, max_depth: usize) -> (Aliases, std::path::PathBuf) {
+        let directory =
+            std::env::temp_dir().join(format!("vsmtp-aliases-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("aliases");
+        std::fs::write(&path, content).unwrap();
+
+        (
+            Aliases::new(
+                &FieldServerAliases {
+                    path: path.clone(),
+                    max_depth,
+                    reload_period: std::time::Duration::from_secs(10),
+                },
+                vec!["example.com".parse().unwrap()],
+                "+".to_owned(),
+            )
+            .unwrap(),
+            directory,
+        )
+    }
+
+    #[test]
+    fn parse_file() {
+        let table = parse(
+            std::path::Path::new("aliases"),
+            "# comment\n\ninfo: john, jenny@other.com\nteam: john,\n  jenny\n\tbob # not a comment\nmalformed line\nPostmaster@example.com: root\n",
+        );
+
+        assert_eq!(table["info"], ["john", "jenny@other.com"]);
+        assert_eq!(table["team"], ["john", "jenny", "bob # not a comment"]);
+        assert_eq!(table["postmaster@example.com"], ["root"]);
+        assert_eq!(table.len(), 3);
+    }
+
+    #[test]
+    fn expand() {
+        let (aliases, _) = aliases(
+            "info: john, jenny@other.com\nsales@example.com: info, bob\nroot: root, admin@other.com\n",
+            10,
+        );
+
+        assert_eq!(aliases.expand(&addr!("john@example.com")), Ok(None));
+        // the aliases without domain apply only to the domains of the server.
+        assert_eq!(aliases.expand(&addr!("info@other.com")), Ok(None));
+        assert_eq!(
+            aliases.expand(&addr!("INFO@example.com")),
+            Ok(Some(vec![
+                addr!("john@example.com"),
+                addr!("jenny@other.com")
+            ]))
+        );
+        assert_eq!(
+            aliases.expand(&addr!("sales@example.com")),
+            Ok(Some(vec![
+                addr!("john@example.com"),
+                addr!("jenny@other.com"),
+                addr!("bob@example.com")
+            ]))
+        );
+        assert_eq!(
+            aliases.expand(&addr!("root@example.com")),
+            Ok(Some(vec![
+                addr!("root@example.com"),
+                addr!("admin@other.com")
+            ]))
+        );
+    }
+
+    #[test]
+    fn extension() {
+        let (aliases, _) = aliases(
+            "info: john\ninfo+sales: bob\nsupport@example.com: jenny\n",
+            10,
+        );
+
+        // the full address is looked up before the address without its extension.
+        assert_eq!(
+            aliases.expand(&addr!("info+sales@example.com")),
+            Ok(Some(vec![addr!("bob@example.com")]))
+        );
+        assert_eq!(
+            aliases.expand(&addr!("info+news@example.com")),
+            Ok(Some(vec![addr!("john@example.com")]))
+        );
+        assert_eq!(
+            aliases.expand(&addr!("support+tickets@example.com")),
+            Ok(Some(vec![addr!("jenny@example.com")]))
+        );
+        assert_eq!(aliases.expand(&addr!("info-news@example.com")), Ok(None));
+    }
+
+    #[test]
+    fn include() {
+        let (aliases, directory) = aliases("team: :include:team.list, boss\n", 10);
+        std::fs::write(
+            directory.join("team.list"),
+            "# the team\njohn\njenny@other.com, bob\n:include:other.list\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            aliases.expand(&addr!("team@example.com")),
+            Ok(Some(vec![
+                addr!("john@example.com"),
+                addr!("jenny@other.com"),
+                addr!("bob@example.com"),
+                addr!("boss@example.com")
+            ]))
+        );
+    }
+
+    #[test]
+    fn loops() {
+        let (aliases, _) = aliases("a: b\nb: c\nc: a\nempty: :include:missing.list\n", 10);
+        assert_eq!(
+            aliases.expand(&addr!("a@example.com")),
+            Err("loops through `a@example.com`".to_string())
+        );
+        assert_eq!(
+            aliases.expand(&addr!("empty@example.com")),
+            Err("no target".to_string())
+        );
+
+        let (aliases, _) = self::aliases("a: b\nb: c\nc: d\n", 2);
+        assert_eq!(
+            aliases.expand(&addr!("a@example.com")),
+            Err("deeper than 2 levels".to_string())
+        );
+    }
+
+    #[test]
+    fn reload() {
+        let (aliases, directory) = aliases("info: john\n", 10);
+        assert_eq!(
+            aliases.expand(&addr!("info@example.com")),
+            Ok(Some(vec![addr!("john@example.com")]))
+        );
+
+        // a different size ensures the modification is detected.
+        std::fs::write(directory.join("aliases"), "info: jenny, bob\n").unwrap();
+        aliases.reload_if_changed();
+        assert_eq!(
+            aliases.expand(&addr!("info@example.com")),
+            Ok(Some(vec![
+                addr!("jenny@example.com"),
+                addr!("bob@example.com")
+            ]))
+        );
+    }
+}
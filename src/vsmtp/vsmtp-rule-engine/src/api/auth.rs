@@ -19,7 +19,8 @@ use super::EngineResult;
 use crate::{error::RuntimeError, get_global};
 pub use auth::*;
 use rhai::plugin::{
-    Dynamic, FnAccess, FnNamespace, Module, NativeCallContext, PluginFunction, RhaiResult, TypeId,
+    mem, Dynamic, FnAccess, FnNamespace, Module, NativeCallContext, PluginFunction, RhaiResult,
+    TypeId,
 };
 use vsmtp_common::{auth::Credentials, status::Status, FieldAccessError, Stage};
 
@@ -70,6 +71,10 @@ mod auth {
                 tracing::trace!(token);
                 Ok(state::deny())
             }
+            Some(Credentials::Certificate { subject } | Credentials::External { subject, .. }) => {
+                tracing::warn!(subject, "Cannot authenticate unix user with a certificate");
+                Ok(state::deny())
+            }
             None => {
                 tracing::warn!("No credentials found to authenticate a unix user with");
                 Ok(state::deny())
@@ -81,7 +86,7 @@ mod auth {
     ///
     /// # Effective smtp stage
     ///
-    /// `authenticate` stage only.
+    /// All of them.
     ///
     /// # Return
     ///
@@ -110,7 +115,8 @@ mod auth {
     ///
     /// # Effective smtp stage
     ///
-    /// `authenticate` only.
+    /// `authenticate` and onwards, the credentials being kept for the rest of
+    /// the connection.
     ///
     /// # Return
     ///
@@ -166,7 +172,7 @@ mod auth {
     ///        action "log auth type" || {
     ///             let credentials = auth::credentials();
     ///
-    ///             // Logs here will output 'Verify' or 'AnonymousToken'.
+    ///             // Logs here will output 'Verify', 'AnonymousToken', 'Certificate' or 'External'.
     ///             // depending on the authentication type.
     ///             log("info", `credentials type: ${credentials.type}`);
     ///         },
@@ -182,7 +188,8 @@ mod auth {
     }
 
     /// Get the `authid` property of the connection.
-    /// Can only be use on 'Verify' authentication typed credentials.
+    /// Can only be use on 'Verify' authentication typed credentials, or on
+    /// 'Certificate' and 'External' ones where it is the subject of the certificate.
     ///
     /// # Effective smtp stage
     ///
@@ -213,6 +220,10 @@ mod auth {
     pub fn get_authid(credentials: &mut Credentials) -> EngineResult<String> {
         match credentials {
             Credentials::Verify { authid, .. } => Ok(authid.clone()),
+            // the subject identifies the client authenticated by its certificate.
+            Credentials::Certificate { subject } | Credentials::External { subject, .. } => {
+                Ok(subject.clone())
+            }
             Credentials::AnonymousToken { .. } => {
                 Err(format!("no `authid` available in credentials of type `{credentials}`").into())
             }
@@ -251,7 +262,9 @@ mod auth {
     pub fn get_authpass(credentials: &mut Credentials) -> EngineResult<String> {
         match credentials {
             Credentials::Verify { authpass, .. } => Ok(authpass.clone()),
-            Credentials::AnonymousToken { .. } => Err(format!(
+            Credentials::AnonymousToken { .. }
+            | Credentials::Certificate { .. }
+            | Credentials::External { .. } => Err(format!(
                 "no `authpass` available in credentials of type `{credentials}`"
             )
             .into()),
@@ -290,12 +303,163 @@ mod auth {
     pub fn get_anonymous_token(credentials: &mut Credentials) -> EngineResult<String> {
         match credentials {
             Credentials::AnonymousToken { token } => Ok(token.clone()),
-            Credentials::Verify { .. } => Err(format!(
+            Credentials::Verify { .. }
+            | Credentials::Certificate { .. }
+            | Credentials::External { .. } => Err(format!(
                 "no `anonymous_token` available in credentials of type `{credentials}`"
             )
             .into()),
         }
     }
+
+    /// Get the `authzid` property of the connection, the identity the client requested
+    /// to act as with the `EXTERNAL` mechanism, to be validated against its certificate.
+    /// Can only be use on 'External' authentication typed credentials.
+    ///
+    /// # Effective smtp stage
+    ///
+    /// `authenticate` only.
+    ///
+    /// # Return
+    ///
+    /// * `String` - the authorization id, empty if the client acts as the subject of its certificate.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # vsmtp_test::vsl::run(
+    /// # |builder| Ok(builder.add_root_filter_rules(r#"
+    /// #{
+    ///     authenticate: [
+    ///        rule "identity of the certificate" || {
+    ///             let credentials = auth::credentials();
+    ///             if credentials.type == "External"
+    ///                 && (credentials.authzid == "" || credentials.authzid in credentials.alt_names) {
+    ///                 state::accept()
+    ///             } else {
+    ///                 state::deny()
+    ///             }
+    ///         },
+    ///     ]
+    /// }
+    /// # "#)?.build()));
+    /// ```
+    ///
+    /// # rhai-autodocs:index:8
+    #[rhai_fn(global, get = "authzid", return_raw, pure)]
+    pub fn get_authzid(credentials: &mut Credentials) -> EngineResult<String> {
+        match credentials {
+            Credentials::External { authzid, .. } => Ok(authzid.clone()),
+            Credentials::Verify { .. }
+            | Credentials::AnonymousToken { .. }
+            | Credentials::Certificate { .. } => {
+                Err(format!("no `authzid` available in credentials of type `{credentials}`").into())
+            }
+        }
+    }
+
+    /// Get the `alt_names` property of the connection, the domains and email addresses
+    /// of the subject alternative names of the certificate presented by the client.
+    /// Can only be use on 'External' authentication typed credentials.
+    ///
+    /// # Effective smtp stage
+    ///
+    /// `authenticate` only.
+    ///
+    /// # Return
+    ///
+    /// * `Array` - the alternative names, as strings.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # vsmtp_test::vsl::run(
+    /// # |builder| Ok(builder.add_root_filter_rules(r#"
+    /// #{
+    ///     authenticate: [
+    ///        action "log alt names" || {
+    ///             let credentials = auth::credentials();
+    ///             if credentials.type == "External" {
+    ///                 log("info", `certificate names: ${credentials.alt_names}`);
+    ///             }
+    ///         },
+    ///     ]
+    /// }
+    /// # "#)?.build()));
+    /// ```
+    ///
+    /// # rhai-autodocs:index:9
+    #[rhai_fn(global, get = "alt_names", return_raw, pure)]
+    pub fn get_alt_names(credentials: &mut Credentials) -> EngineResult<rhai::Array> {
+        match credentials {
+            Credentials::External { alt_names, .. } => {
+                Ok(alt_names.iter().cloned().map(rhai::Dynamic::from).collect())
+            }
+            Credentials::Verify { .. }
+            | Credentials::AnonymousToken { .. }
+            | Credentials::Certificate { .. } => Err(format!(
+                "no `alt_names` available in credentials of type `{credentials}`"
+            )
+            .into()),
+        }
+    }
+
+    /// Set the submission quota of the authenticated user for the connection,
+    /// in place of the one of the configuration (`server.esmtp.auth.quotas`).
+    ///
+    /// The quota is checked on `MAIL FROM`, each recipient of the messages
+    /// received counting for one.
+    ///
+    /// # Args
+    ///
+    /// * `burst` - the number of recipients per minute.
+    /// * `sustained` - the number of recipients per sustained period (a day by default).
+    ///
+    /// # Effective smtp stage
+    ///
+    /// `connect`, `helo` and `authenticate`.
+    ///
+    /// # Errors
+    ///
+    /// * A limit is negative or too large.
+    /// * The function is called after the `helo` stage.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # vsmtp_test::vsl::run(
+    /// # |builder| Ok(builder.add_root_filter_rules(r#"
+    /// #{
+    ///     authenticate: [
+    ///        action "trusted mailing" || {
+    ///             let credentials = auth::credentials();
+    ///             if credentials.type == "Verify" && credentials.authid == "newsletter" {
+    ///                 auth::set_quota(300, 20000);
+    ///             }
+    ///         },
+    ///     ]
+    /// }
+    /// # "#)?.build()));
+    /// ```
+    ///
+    /// # rhai-autodocs:index:10
+    #[rhai_fn(name = "set_quota", return_raw)]
+    pub fn set_quota(
+        ncc: NativeCallContext,
+        burst: rhai::INT,
+        sustained: rhai::INT,
+    ) -> EngineResult<()> {
+        let (Ok(burst), Ok(sustained)) = (u32::try_from(burst), u32::try_from(sustained)) else {
+            return Err(RuntimeError::Generic {
+                message: format!("invalid submission quota `{burst}/{sustained}`"),
+            }
+            .into());
+        };
+
+        Ok(vsl_guard_ok!(get_global!(ncc, ctx).write())
+            .set_submission_quota(vsmtp_common::SubmissionQuota { burst, sustained })
+            .map_err(Into::<RuntimeError>::into)?)
+    }
 }
 
 fn execute_testsaslauthd(authid: &str, authpass: &str) -> EngineResult<Status> {
@@ -33,9 +33,9 @@ pub use dkim::*;
 #[derive(Debug, serde::Deserialize)]
 pub struct SignatureParams {
     sdid: Option<String>,
-    selector: String,
-    #[serde(deserialize_with = "deserialize_private_key")]
-    private_key: std::sync::Arc<backend::PrivateKey>,
+    selector: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_private_key")]
+    private_key: Option<std::sync::Arc<backend::PrivateKey>>,
     headers_field: Option<Vec<String>>,
     #[serde(deserialize_with = "deserialize_canonicalization")]
     canonicalization: Option<backend::Canonicalization>,
@@ -43,7 +43,7 @@ pub struct SignatureParams {
 
 fn deserialize_private_key<'de, D>(
     deserializer: D,
-) -> Result<std::sync::Arc<backend::PrivateKey>, D::Error>
+) -> Result<Option<std::sync::Arc<backend::PrivateKey>>, D::Error>
 where
     D: serde::Deserializer<'de>,
 {
@@ -51,6 +51,7 @@ where
 
     private_key
         .try_cast::<rhai::Shared<backend::PrivateKey>>()
+        .map(Some)
         .ok_or_else(|| serde::de::Error::custom("failed to parse private key"))
 }
 
@@ -378,6 +379,9 @@ mod dkim {
     /// * `private_key`      - the private key to sign the mail,
     ///                        associated with the public key in the `selector._domainkey.sdid`
     ///                        DNS record.
+    ///
+    /// When both `selector` and `private_key` are omitted, the `active` selector
+    /// configured for the `sdid` (`config.dkim.selectors`) is used.
     /// * `headers_field`    - list of headers to sign.
     /// * `canonicalization` - the canonicalization algorithm to use. (ex: "simple/relaxed")
     ///
@@ -396,10 +400,10 @@ mod dkim {
     ///            // default: server_name()
     ///            sdid:                "testserver.com",
     ///
-    ///            // mandatory
+    ///            // default: the active selector of the sdid
     ///            selector:            "2022-09",
     ///
-    ///            // mandatory
+    ///            // default: the key of the active selector of the sdid
     ///            private_key:         private_key,
     ///
     ///            // default: ["From", "To", "Date", "Subject", "From"]
@@ -434,6 +438,7 @@ mod dkim {
         let signature = vsl_generic_ok!(super::Impl::generate_signature(
             &vsl_guard_ok!(get_global!(ncc, msg).read()),
             &vsl_guard_ok!(get_global!(ncc, ctx).read()),
+            &get_global!(ncc, srv).config,
             rhai::serde::from_dynamic::<SignatureParams>(&params.into())?
         ));
 
@@ -581,6 +586,19 @@ impl Impl {
             });
         }
 
+        // NOTE: our own mail is verified with the local keys, so that the mail signed
+        //       with the previous selector is still accepted during a rotation.
+        if let Some(selector) = Self::get_dkim_config(&server.config, &signature.sdid)
+            .and_then(|dkim| dkim.get_verifying_selector(signature.selector()))
+        {
+            return selector
+                .private_key
+                .inner
+                .public_key()
+                .map(|key| vec![key])
+                .map_err(|inner| DkimErrors::KeyParsingFailed { inner });
+        }
+
         let resolver = server.resolvers.get_resolver_root();
 
         let txt_record =
@@ -614,17 +632,46 @@ impl Impl {
         })
     }
 
-    #[tracing::instrument(ret, err)]
+    /// The configuration of the domain, if it is one of ours.
+    fn get_dkim_config<'a>(
+        config: &'a vsmtp_config::Config,
+        sdid: &str,
+    ) -> Option<&'a vsmtp_config::field::FieldDkim> {
+        sdid.parse::<Domain>()
+            .ok()
+            .and_then(|sdid| config.server.r#virtual.get(&sdid))
+            .and_then(|r#virtual| r#virtual.dkim.as_ref())
+    }
+
+    #[tracing::instrument(skip(config), ret, err)]
     fn generate_signature(
         message: &MessageBody,
         ctx: &vsmtp_common::Context,
+        config: &vsmtp_config::Config,
         params: SignatureParams,
     ) -> Result<String, DkimErrors> {
+        let sdid = params.sdid.unwrap_or_else(|| ctx.server_name().to_string());
+
+        let (selector, private_key) = match (params.selector, params.private_key) {
+            (Some(selector), Some(private_key)) => (selector, private_key),
+            (None, None) => Self::get_dkim_config(config, &sdid)
+                .and_then(vsmtp_config::field::FieldDkim::signing_selector)
+                .map(|active| (active.selector.clone(), active.private_key.inner.clone()))
+                .ok_or_else(|| DkimErrors::InvalidArgument {
+                    inner: format!("no active dkim selector configured for `{sdid}`"),
+                })?,
+            _ => {
+                return Err(DkimErrors::InvalidArgument {
+                    inner: "`selector` and `private_key` must be provided together".to_string(),
+                })
+            }
+        };
+
         let signature = backend::sign(
             message.inner(),
-            &params.private_key,
-            params.sdid.unwrap_or_else(|| ctx.server_name().to_string()),
-            params.selector.to_string(),
+            &private_key,
+            sdid,
+            selector,
             params
                 .canonicalization
                 .unwrap_or_else(|| "simple/relaxed".parse().expect("default values are valid")),
@@ -147,10 +147,15 @@ impl Impl {
     }
 
     fn rlookup(server: &Server, ip: &str) -> EngineResult<rhai::Array> {
+        // NOTE: the zone of a link-local address is not part of its reverse name,
+        //       and a IPv4-mapped address is looked up in `in-addr.arpa`.
         let ip = vsl_conversion_ok!(
             "ip address",
-            <std::net::IpAddr as std::str::FromStr>::from_str(ip)
-                .context("fail to parse ip address in rlookup")
+            <std::net::IpAddr as std::str::FromStr>::from_str(
+                ip.split('%').next().unwrap_or_default()
+            )
+            .map(vsmtp_common::utils::canonical_ip)
+            .context("fail to parse ip address in rlookup")
         );
         let resolver = server.resolvers.get_resolver_root();
 
@@ -28,7 +28,7 @@ use rhai::plugin::{
 use vsmtp_common::Address;
 
 pub use envelop::*;
-use vsmtp_delivery::Deliver;
+use vsmtp_delivery::{Deliver, Maildir};
 
 use super::Server;
 
@@ -262,6 +262,136 @@ mod envelop {
     pub fn remove_rcpt_envelop_obj(ncc: NativeCallContext, addr: SharedObject) -> EngineResult<()> {
         super::remove_rcpt_envelop(&mut get_global!(ncc, ctx), &addr.to_string())
     }
+
+    /// Set the folder of the mailbox a recipient's email is stored in, if delivered
+    /// with the `maildir` transport. This folder takes precedence over the one given
+    /// to `transport::maildir` and over the folder of the address's tag.
+    ///
+    /// # Args
+    ///
+    /// * `rcpt` - the recipient.
+    /// * `folder` - the name of the folder, `INBOX` for the default one, sub folders are separated by dots (`INBOX.lists`).
+    ///
+    /// # Errors
+    ///
+    /// * the folder name is empty, contains an empty component (`..`), a path separator or a control character.
+    ///
+    /// # Effective smtp stage
+    ///
+    /// `rcpt` and onwards.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # let states = vsmtp_test::vsl::run(
+    /// # |builder| Ok(builder.add_root_filter_rules(r#"
+    /// #{
+    ///     rcpt: [
+    ///        action "file spam" || {
+    ///          envelop::set_folder("john.doe@example.com", "Spam");
+    ///          envelop::set_folder(address("jenny.doe@example.com"), "INBOX.lists");
+    ///        },
+    ///     ]
+    /// }
+    /// # "#)?.build()));
+    /// # let folders = states[&vsmtp_rule_engine::ExecutionStage::RcptTo].0.rcpt_folders().unwrap();
+    /// # assert_eq!(folders[&vsmtp_common::addr!("john.doe@example.com")], "Spam");
+    /// # assert_eq!(folders[&vsmtp_common::addr!("jenny.doe@example.com")], "INBOX.lists");
+    /// ```
+    ///
+    /// # rhai-autodocs:index:6
+    #[rhai_fn(name = "set_folder", return_raw)]
+    pub fn set_folder_str(ncc: NativeCallContext, rcpt: &str, folder: &str) -> EngineResult<()> {
+        super::set_folder(&mut get_global!(ncc, ctx), rcpt, folder)
+    }
+
+    #[doc(hidden)]
+    #[rhai_fn(name = "set_folder", return_raw)]
+    pub fn set_folder_obj(
+        ncc: NativeCallContext,
+        rcpt: SharedObject,
+        folder: &str,
+    ) -> EngineResult<()> {
+        super::set_folder(&mut get_global!(ncc, ctx), &rcpt.to_string(), folder)
+    }
+
+    /// Set the sender used to deliver the email to a recipient, replacing the one of
+    /// the `MAIL FROM` command for the `deliver` and `forward` transports, which send
+    /// the recipients of different senders in separate transactions.
+    ///
+    /// # Args
+    ///
+    /// * `rcpt` - the recipient.
+    /// * `sender` - the sender of its transaction, a VERP address for instance (see `verp::encode`).
+    ///
+    /// # Effective smtp stage
+    ///
+    /// `rcpt` and onwards.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # let states = vsmtp_test::vsl::run(
+    /// # |builder| Ok(builder.add_root_filter_rules(r#"
+    /// #{
+    ///     rcpt: [
+    ///        action "verp" || {
+    ///          let rcpt = address("john.doe@example.com");
+    ///          envelop::set_mail_from(rcpt, verp::encode("bounce@lists.example.com", rcpt));
+    ///        },
+    ///     ]
+    /// }
+    /// # "#)?.build()));
+    /// # let reverse_paths = states[&vsmtp_rule_engine::ExecutionStage::RcptTo].0.rcpt_reverse_paths().unwrap();
+    /// # assert_eq!(
+    /// #   reverse_paths[&vsmtp_common::addr!("john.doe@example.com")],
+    /// #   vsmtp_common::addr!("bounce+john.doe=example.com@lists.example.com")
+    /// # );
+    /// ```
+    ///
+    /// # rhai-autodocs:index:7
+    #[rhai_fn(name = "set_mail_from", return_raw)]
+    pub fn set_mail_from_str_str(
+        ncc: NativeCallContext,
+        rcpt: &str,
+        sender: &str,
+    ) -> EngineResult<()> {
+        super::set_mail_from(&mut get_global!(ncc, ctx), rcpt, sender)
+    }
+
+    #[doc(hidden)]
+    #[rhai_fn(name = "set_mail_from", return_raw)]
+    pub fn set_mail_from_obj_str(
+        ncc: NativeCallContext,
+        rcpt: SharedObject,
+        sender: &str,
+    ) -> EngineResult<()> {
+        super::set_mail_from(&mut get_global!(ncc, ctx), &rcpt.to_string(), sender)
+    }
+
+    #[doc(hidden)]
+    #[rhai_fn(name = "set_mail_from", return_raw)]
+    pub fn set_mail_from_str_obj(
+        ncc: NativeCallContext,
+        rcpt: &str,
+        sender: SharedObject,
+    ) -> EngineResult<()> {
+        super::set_mail_from(&mut get_global!(ncc, ctx), rcpt, &sender.to_string())
+    }
+
+    #[doc(hidden)]
+    #[rhai_fn(name = "set_mail_from", return_raw)]
+    pub fn set_mail_from_obj_obj(
+        ncc: NativeCallContext,
+        rcpt: SharedObject,
+        sender: SharedObject,
+    ) -> EngineResult<()> {
+        super::set_mail_from(
+            &mut get_global!(ncc, ctx),
+            &rcpt.to_string(),
+            &sender.to_string(),
+        )
+    }
 }
 
 fn rewrite_mail_from_envelop(context: &mut Context, new_addr: &str) -> EngineResult<()> {
@@ -333,3 +463,21 @@ fn remove_rcpt_envelop(context: &mut Context, addr: &str) -> EngineResult<()> {
         .map_err::<Box<rhai::EvalAltResult>, _>(|e| e.to_string().into())?;
     Ok(())
 }
+
+fn set_folder(context: &mut Context, rcpt: &str, folder: &str) -> EngineResult<()> {
+    let rcpt = vsl_conversion_ok!("address", <Address as std::str::FromStr>::from_str(rcpt));
+    Maildir::folder_dir(folder).map_err::<Box<rhai::EvalAltResult>, _>(|e| e.to_string().into())?;
+
+    vsl_guard_ok!(context.write())
+        .set_rcpt_folder(rcpt, folder.to_owned())
+        .map_err::<Box<rhai::EvalAltResult>, _>(|e| e.to_string().into())
+}
+
+fn set_mail_from(context: &mut Context, rcpt: &str, sender: &str) -> EngineResult<()> {
+    let rcpt = vsl_conversion_ok!("address", <Address as std::str::FromStr>::from_str(rcpt));
+    let sender = vsl_conversion_ok!("address", <Address as std::str::FromStr>::from_str(sender));
+
+    vsl_guard_ok!(context.write())
+        .set_rcpt_reverse_path(rcpt, sender)
+        .map_err::<Box<rhai::EvalAltResult>, _>(|e| e.to_string().into())
+}
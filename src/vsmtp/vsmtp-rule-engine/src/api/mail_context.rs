@@ -22,13 +22,30 @@ use crate::{
     get_global,
 };
 use rhai::plugin::{
-    Dynamic, FnAccess, FnNamespace, Module, NativeCallContext, PluginFunction, RhaiResult, TypeId,
+    mem, Dynamic, FnAccess, FnNamespace, ImmutableString, Module, NativeCallContext,
+    PluginFunction, RhaiResult, TypeId,
 };
 use vsmtp_plugin_vsl::objects::Object;
 
 pub use mail_context::*;
 
 /// Inspect the transaction context.
+///
+/// The fields of the transaction are set as the client sends its commands, a getter
+/// called before its field is available fails with an error listing the stages in
+/// which it is:
+///
+/// | stage | available fields |
+/// | --- | --- |
+/// | `connect` and onwards | client & server addresses, `server_name`, `is_secured`, `tls`, `is_utf8` |
+/// | `helo` and onwards | `helo` |
+/// | `mail` and onwards | `mail_from`, `mail_timestamp`, `message_id`, `envelop_id`, `body_type`, `declared_size` |
+/// | `rcpt` and onwards | `rcpt`, `original_rcpt`, `rcpt_extension`, `rcpt_list`, `rcpt_count`, `transaction_type` |
+/// | `preq` and onwards | `originating_ip`, `originating_helo` |
+///
+/// In the `rcpt` stage, `ctx::rcpt_list()` contains the recipients accepted so far
+/// including the current one, and the credentials of an authenticated client are
+/// available with `auth::credentials()`.
 #[rhai::plugin::export_module]
 mod mail_context {
 
@@ -341,6 +358,90 @@ mod mail_context {
         Ok(vsl_guard_ok!(get_global!(ncc, ctx).read()).tls().is_some())
     }
 
+    /// Get the number of error replies sent to the client since the beginning of the connection.
+    ///
+    /// # Effective smtp stage
+    ///
+    /// All of them, the count is updated before the evaluation of each stage.
+    ///
+    /// # Return
+    ///
+    /// * `number` - the number of errors.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # let states = vsmtp_test::vsl::run(
+    /// # |builder| Ok(builder.add_root_filter_rules(r#"
+    /// #{
+    ///   mail: [
+    ///     rule "sloppy client" || {
+    ///       let soft = ctx::error_thresholds().soft;
+    ///       if soft != () && ctx::error_count() >= soft && !auth::is_authenticated() {
+    ///         state::deny()
+    ///       } else {
+    ///         state::next()
+    ///       }
+    ///     }
+    ///   ],
+    /// }
+    /// # "#)?.build()));
+    /// # use vsmtp_common::status::Status;
+    /// # assert_eq!(states[&vsmtp_rule_engine::ExecutionStage::MailFrom].2, Status::Next);
+    /// ```
+    ///
+    /// # rhai-autodocs:index:11
+    #[rhai_fn(name = "error_count", return_raw)]
+    pub fn error_count(ncc: NativeCallContext) -> EngineResult<rhai::INT> {
+        Ok(vsl_guard_ok!(get_global!(ncc, ctx).read()).error_count())
+    }
+
+    /// Get the number of errors after which the replies to the client are delayed (`soft`),
+    /// and after which the connection is closed (`hard`), as configured in `server.smtp.error`.
+    ///
+    /// # Effective smtp stage
+    ///
+    /// All of them.
+    ///
+    /// # Return
+    ///
+    /// * `map` - the `soft`, `hard`, `consecutive` (bad commands in a row) and `churn`
+    ///   (transactions reset) thresholds, `()` when they are disabled.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # vsmtp_test::vsl::run(
+    /// # |builder| Ok(builder.add_root_filter_rules(r#"
+    /// #{
+    ///   connect: [
+    ///     action "log thresholds" || log("info", `error thresholds: ${ctx::error_thresholds()}`),
+    ///   ],
+    /// }
+    /// # "#)?.build()));
+    /// ```
+    ///
+    /// # rhai-autodocs:index:12
+    #[rhai_fn(name = "error_thresholds")]
+    pub fn error_thresholds(ncc: NativeCallContext) -> rhai::Map {
+        let srv = get_global!(ncc, srv);
+        let error = &srv.config.server.smtp.error;
+        let threshold = |count: i64| {
+            if count == -1 {
+                rhai::Dynamic::UNIT
+            } else {
+                rhai::Dynamic::from_int(count)
+            }
+        };
+
+        rhai::Map::from_iter([
+            ("soft".into(), threshold(error.soft_count)),
+            ("hard".into(), threshold(error.hard_count)),
+            ("consecutive".into(), threshold(error.consecutive_count)),
+            ("churn".into(), threshold(error.churn_count)),
+        ])
+    }
+
     /// Get the value of the `HELO/EHLO` command sent by the client.
     ///
     /// # Effective smtp stage
@@ -364,7 +465,7 @@ mod mail_context {
     /// # "#)?.build()));
     /// ```
     ///
-    /// # rhai-autodocs:index:11
+    /// # rhai-autodocs:index:13
     #[rhai_fn(name = "helo", return_raw)]
     pub fn helo(ncc: NativeCallContext) -> EngineResult<String> {
         Ok(vsl_guard_ok!(get_global!(ncc, ctx).read())
@@ -378,6 +479,7 @@ mod mail_context {
     /// # Effective smtp stage
     ///
     /// `mail` and onwards.
+    ///
     /// # Return
     ///
     /// * `address` - the sender address.
@@ -388,14 +490,14 @@ mod mail_context {
     /// # vsmtp_test::vsl::run(
     /// # |builder| Ok(builder.add_root_filter_rules(r#"
     /// #{
-    ///     helo: [
+    ///     mail: [
     ///        action "log info" || log("info", `received sender: ${ctx::mail_from()}`),
     ///     ]
     /// }
     /// # "#)?.build()));
     /// ```
     ///
-    /// # rhai-autodocs:index:12
+    /// # rhai-autodocs:index:14
     #[rhai_fn(return_raw)]
     pub fn mail_from(ncc: NativeCallContext) -> EngineResult<SharedObject> {
         let reverse_path = vsl_guard_ok!(get_global!(ncc, ctx).read())
@@ -433,7 +535,7 @@ mod mail_context {
     /// # "#)?.build()));
     /// ```
     ///
-    /// # rhai-autodocs:index:13
+    /// # rhai-autodocs:index:15
     #[rhai_fn(name = "rcpt_list", return_raw)]
     pub fn rcpt_list(ncc: NativeCallContext) -> EngineResult<rhai::Array> {
         Ok(vsl_guard_ok!(get_global!(ncc, ctx).read())
@@ -447,6 +549,49 @@ mod mail_context {
             .collect())
     }
 
+    /// Get the number of recipients received by the client, without copying them.
+    ///
+    /// # Effective smtp stage
+    ///
+    /// `rcpt` and onwards.
+    ///
+    /// # Return
+    ///
+    /// * `number` - the number of recipients.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # let rules = r#"
+    /// #{
+    ///     preq: [
+    ///        rule "too many recipients" || if ctx::rcpt_count() > 50 { state::deny() } else { state::accept() },
+    ///     ]
+    /// }
+    /// # "#;
+    /// # let states = vsmtp_test::vsl::run(|builder| Ok(builder
+    /// #   .add_root_filter_rules("#{}")?
+    /// #      .add_domain_rules("testserver.com".parse().unwrap())
+    /// #        .with_incoming(rules)?
+    /// #        .with_outgoing(rules)?
+    /// #        .with_internal(rules)?
+    /// #      .build()
+    /// #   .build()));
+    /// # use vsmtp_common::status::Status;
+    /// # assert_eq!(states[&vsmtp_rule_engine::ExecutionStage::PreQ].2, Status::Accept("250 Ok".parse::<vsmtp_common::Reply>().unwrap()));
+    /// ```
+    ///
+    /// # rhai-autodocs:index:16
+    #[rhai_fn(name = "rcpt_count", return_raw)]
+    pub fn rcpt_count(ncc: NativeCallContext) -> EngineResult<rhai::INT> {
+        let count = vsl_guard_ok!(get_global!(ncc, ctx).read())
+            .forward_paths()
+            .map_err(Into::<crate::error::RuntimeError>::into)?
+            .len();
+
+        Ok(rhai::INT::try_from(count).unwrap_or(rhai::INT::MAX))
+    }
+
     /// Get the value of the current `RCPT TO` command sent by the client.
     ///
     /// # Effective smtp stage
@@ -472,7 +617,7 @@ mod mail_context {
     /// # "#)?.build()));
     /// ```
     ///
-    /// # rhai-autodocs:index:14
+    /// # rhai-autodocs:index:17
     #[rhai_fn(name = "rcpt", return_raw)]
     pub fn rcpt(ncc: NativeCallContext) -> EngineResult<SharedObject> {
         let rcpt = vsl_guard_ok!(get_global!(ncc, ctx).read())
@@ -491,7 +636,7 @@ mod mail_context {
     ///
     /// # Effective smtp stage
     ///
-    /// `preq` and onwards.
+    /// `mail` and onwards.
     ///
     /// # Return
     ///
@@ -510,7 +655,7 @@ mod mail_context {
     /// # "#)?.build()));
     /// ```
     ///
-    /// # rhai-autodocs:index:15
+    /// # rhai-autodocs:index:18
     #[rhai_fn(name = "mail_timestamp", return_raw)]
     pub fn mail_timestamp(ncc: NativeCallContext) -> EngineResult<time::OffsetDateTime> {
         Ok(*vsl_guard_ok!(get_global!(ncc, ctx).read())
@@ -522,7 +667,7 @@ mod mail_context {
     ///
     /// # Effective smtp stage
     ///
-    /// `preq` and onwards.
+    /// `mail` and onwards.
     ///
     /// # Return
     ///
@@ -541,7 +686,7 @@ mod mail_context {
     /// # "#)?.build()));
     /// ```
     ///
-    /// # rhai-autodocs:index:16
+    /// # rhai-autodocs:index:19
     #[rhai_fn(name = "message_id", return_raw)]
     pub fn message_id(ncc: NativeCallContext) -> EngineResult<String> {
         Ok(vsl_guard_ok!(get_global!(ncc, ctx).read())
@@ -549,4 +694,690 @@ mod mail_context {
             .map_err(Into::<crate::error::RuntimeError>::into)?
             .to_string())
     }
+
+    /// Get the body type declared by the client with the `BODY` parameter
+    /// of the `MAIL FROM` command.
+    ///
+    /// # Effective smtp stage
+    ///
+    /// `mail` and onwards.
+    ///
+    /// # Return
+    ///
+    /// * `string` - `8BITMIME` or `7BIT`, the default when the client did not declare it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # vsmtp_test::vsl::run(
+    /// # |builder| Ok(builder.add_root_filter_rules(r#"
+    /// #{
+    ///     mail: [
+    ///        action "log body type" || log("info", `body type: ${ctx::body_type()}`),
+    ///     ]
+    /// }
+    /// # "#)?.build()));
+    /// ```
+    ///
+    /// # rhai-autodocs:index:20
+    #[rhai_fn(name = "body_type", return_raw)]
+    pub fn body_type(ncc: NativeCallContext) -> EngineResult<String> {
+        Ok(vsl_guard_ok!(get_global!(ncc, ctx).read())
+            .body_type()
+            .map_err(Into::<crate::error::RuntimeError>::into)?
+            .unwrap_or(vsmtp_common::MimeBodyType::SevenBit)
+            .to_string())
+    }
+
+    /// Get the size of the message declared by the client with the `SIZE` parameter
+    /// of the `MAIL FROM` command.
+    ///
+    /// # Effective smtp stage
+    ///
+    /// `mail` and onwards.
+    ///
+    /// # Return
+    ///
+    /// * `number` - the declared size in bytes, or `()` when the client did not declare it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # vsmtp_test::vsl::run(
+    /// # |builder| Ok(builder.add_root_filter_rules(r#"
+    /// #{
+    ///     mail: [
+    ///        action "log declared size" || log("info", `declared size: ${ctx::declared_size()}`),
+    ///     ]
+    /// }
+    /// # "#)?.build()));
+    /// ```
+    ///
+    /// # rhai-autodocs:index:21
+    #[rhai_fn(name = "declared_size", return_raw)]
+    pub fn declared_size(ncc: NativeCallContext) -> EngineResult<rhai::Dynamic> {
+        Ok(vsl_guard_ok!(get_global!(ncc, ctx).read())
+            .declared_size()
+            .map_err(Into::<crate::error::RuntimeError>::into)?
+            .map_or(rhai::Dynamic::UNIT, |size| {
+                rhai::Dynamic::from_int(rhai::INT::try_from(size).unwrap_or(rhai::INT::MAX))
+            }))
+    }
+
+    /// Set the text of the greeting sent to the client, the `220` reply is built
+    /// with the name of the server followed by `banner` (instead of `Service ready`).
+    ///
+    /// The greeting does not depend on the status returned by the rules, use
+    /// `state::deny()` to refuse the connection. The banner should be set by an
+    /// `action`, the `rule` directives being skipped by the decision cache.
+    ///
+    /// # Args
+    ///
+    /// * `banner` - a single line of printable ascii characters.
+    ///
+    /// # Effective smtp stage
+    ///
+    /// `connect` only.
+    ///
+    /// # Errors
+    ///
+    /// * The banner is empty, or contains other characters than printable ascii.
+    /// * The function is called after the `connect` stage.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # vsmtp_test::vsl::run(
+    /// # |builder| Ok(builder.add_root_filter_rules(r#"
+    /// #{
+    ///     connect: [
+    ///        action "greet the client" || ctx::set_banner(`ESMTP ready for ${ctx::client_ip()}`),
+    ///     ]
+    /// }
+    /// # "#)?.build()));
+    /// ```
+    ///
+    /// # rhai-autodocs:index:22
+    #[rhai_fn(name = "set_banner", return_raw)]
+    pub fn set_banner(ncc: NativeCallContext, banner: &str) -> EngineResult<()> {
+        if banner.is_empty() || !banner.chars().all(|c| c.is_ascii_graphic() || c == ' ') {
+            return Err(crate::error::RuntimeError::Generic {
+                message: format!("the banner `{banner}` is not a single line of printable ascii"),
+            }
+            .into());
+        }
+
+        Ok(vsl_guard_ok!(get_global!(ncc, ctx).write())
+            .set_banner(banner.to_owned())
+            .map_err(Into::<crate::error::RuntimeError>::into)?)
+    }
+
+    /// Get the type of the transaction, deduced from the domains of the sender
+    /// and of the recipients handled by the server.
+    ///
+    /// # Effective smtp stage
+    ///
+    /// `rcpt` and onwards.
+    ///
+    /// # Return
+    ///
+    /// * `string` - `incoming` when the sender's domain is not handled by the server,
+    ///   `outgoing` when it is and the recipient's is not, `internal` when both are.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # vsmtp_test::vsl::run(
+    /// # |builder| Ok(builder.add_root_filter_rules(r#"
+    /// #{
+    ///     rcpt: [
+    ///        rule "no relay for strangers" || if ctx::transaction_type() == "outgoing" && !auth::is_authenticated() {
+    ///            state::deny()
+    ///        } else {
+    ///            state::next()
+    ///        },
+    ///     ]
+    /// }
+    /// # "#)?.build()));
+    /// ```
+    ///
+    /// # rhai-autodocs:index:23
+    #[rhai_fn(name = "transaction_type", return_raw)]
+    pub fn transaction_type(ncc: NativeCallContext) -> EngineResult<String> {
+        Ok(match vsl_guard_ok!(get_global!(ncc, ctx).read())
+            .transaction_type()
+            .map_err(Into::<crate::error::RuntimeError>::into)?
+        {
+            vsmtp_common::TransactionType::Incoming(_) => "incoming",
+            vsmtp_common::TransactionType::Outgoing { .. } => "outgoing",
+            vsmtp_common::TransactionType::Internal => "internal",
+        }
+        .to_string())
+    }
+
+    /// Get the identifier of the envelop declared by the client with the `ENVID`
+    /// parameter of the `MAIL FROM` command.
+    ///
+    /// # Effective smtp stage
+    ///
+    /// `mail` and onwards.
+    ///
+    /// # Return
+    ///
+    /// * `string` - the identifier, or `()` when the client did not declare it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # vsmtp_test::vsl::run(
+    /// # |builder| Ok(builder.add_root_filter_rules(r#"
+    /// #{
+    ///     mail: [
+    ///        action "log envelop id" || log("info", `envelop id: ${ctx::envelop_id()}`),
+    ///     ]
+    /// }
+    /// # "#)?.build()));
+    /// ```
+    ///
+    /// # rhai-autodocs:index:24
+    #[rhai_fn(name = "envelop_id", return_raw)]
+    pub fn envelop_id(ncc: NativeCallContext) -> EngineResult<rhai::Dynamic> {
+        Ok(vsl_guard_ok!(get_global!(ncc, ctx).read())
+            .envelop_id()
+            .map_err(Into::<crate::error::RuntimeError>::into)?
+            .map_or(rhai::Dynamic::UNIT, |envelop_id| {
+                envelop_id.to_owned().into()
+            }))
+    }
+
+    /// Has the client requested the internationalized transaction with the `SMTPUTF8`
+    /// parameter of the `MAIL FROM` command ?
+    ///
+    /// # Effective smtp stage
+    ///
+    /// All of them, always `false` before the `mail` stage.
+    ///
+    /// # Return
+    ///
+    /// * `bool` - `true` if the parameter was sent.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # vsmtp_test::vsl::run(
+    /// # |builder| Ok(builder.add_root_filter_rules(r#"
+    /// #{
+    ///     rcpt: [
+    ///        action "log utf8" || log("info", `smtputf8: ${ctx::is_utf8()}`),
+    ///     ]
+    /// }
+    /// # "#)?.build()));
+    /// ```
+    ///
+    /// # rhai-autodocs:index:25
+    #[rhai_fn(name = "is_utf8", return_raw)]
+    pub fn is_utf8(ncc: NativeCallContext) -> EngineResult<bool> {
+        Ok(vsl_guard_ok!(get_global!(ncc, ctx).read()).is_utf8_advertised())
+    }
+
+    /// Get the properties of the TLS session of the connection.
+    ///
+    /// # Effective smtp stage
+    ///
+    /// All of them, the session being established by `STARTTLS` or on connection
+    /// on a tunneled interface.
+    ///
+    /// # Return
+    ///
+    /// * `map` - the `protocol_version` and `cipher_suite` of the session, or `()`
+    ///   when the connection is not secured.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # vsmtp_test::vsl::run(
+    /// # |builder| Ok(builder.add_root_filter_rules(r#"
+    /// #{
+    ///     rcpt: [
+    ///        rule "tls 1.3 only" || {
+    ///            let tls = ctx::tls();
+    ///            if tls != () && tls.protocol_version == "TLSv1_3" { state::next() } else { state::deny() }
+    ///        },
+    ///     ]
+    /// }
+    /// # "#)?.build()));
+    /// ```
+    ///
+    /// # rhai-autodocs:index:26
+    #[rhai_fn(name = "tls", return_raw)]
+    pub fn tls(ncc: NativeCallContext) -> EngineResult<rhai::Dynamic> {
+        Ok(vsl_guard_ok!(get_global!(ncc, ctx).read())
+            .tls()
+            .as_ref()
+            .map_or(rhai::Dynamic::UNIT, |tls| {
+                rhai::Dynamic::from_map(rhai::Map::from_iter([
+                    (
+                        "protocol_version".into(),
+                        tls.protocol_version.to_string().into(),
+                    ),
+                    ("cipher_suite".into(), tls.cipher_suite.to_string().into()),
+                ]))
+            }))
+    }
+
+    /// Get the address of the host which sent the message.
+    ///
+    /// When the client is a relay of `config.server.trusted_upstreams`, the `Received`
+    /// headers added by the trusted relays are read to find the first untrusted hop,
+    /// otherwise it is the address of the client.
+    ///
+    /// # Effective smtp stage
+    ///
+    /// `preq` and onwards.
+    ///
+    /// # Return
+    ///
+    /// * `string` - the ip address of the originating host.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # vsmtp_test::vsl::run(
+    /// # |builder| Ok(builder.add_root_filter_rules(r#"
+    /// #{
+    ///     preq: [
+    ///        action "log origin" || log("info", `sent by ${ctx::originating_ip()}`),
+    ///     ]
+    /// }
+    /// # "#)?.build()));
+    /// ```
+    ///
+    /// # rhai-autodocs:index:27
+    #[rhai_fn(name = "originating_ip", return_raw)]
+    pub fn originating_ip(ncc: NativeCallContext) -> EngineResult<String> {
+        let srv = get_global!(ncc, srv);
+        let origin = crate::trusted_upstreams::origin_of(
+            &srv.config,
+            &vsl_guard_ok!(get_global!(ncc, ctx).read()),
+            &vsl_guard_ok!(get_global!(ncc, msg).read()),
+        )
+        .map_err(Into::<crate::error::RuntimeError>::into)?;
+
+        Ok(origin.ip.to_string())
+    }
+
+    /// Get the `HELO/EHLO` name of the host which sent the message,
+    /// see `ctx::originating_ip()`.
+    ///
+    /// # Effective smtp stage
+    ///
+    /// `preq` and onwards.
+    ///
+    /// # Return
+    ///
+    /// * `string` - the name given by the originating host, or `()` if the `Received`
+    ///   header of the trusted relay does not record it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # vsmtp_test::vsl::run(
+    /// # |builder| Ok(builder.add_root_filter_rules(r#"
+    /// #{
+    ///     preq: [
+    ///        action "log origin" || log("info", `sent by ${ctx::originating_helo()}`),
+    ///     ]
+    /// }
+    /// # "#)?.build()));
+    /// ```
+    ///
+    /// # rhai-autodocs:index:28
+    #[rhai_fn(name = "originating_helo", return_raw)]
+    pub fn originating_helo(ncc: NativeCallContext) -> EngineResult<rhai::Dynamic> {
+        let srv = get_global!(ncc, srv);
+        let origin = crate::trusted_upstreams::origin_of(
+            &srv.config,
+            &vsl_guard_ok!(get_global!(ncc, ctx).read()),
+            &vsl_guard_ok!(get_global!(ncc, msg).read()),
+        )
+        .map_err(Into::<crate::error::RuntimeError>::into)?;
+
+        Ok(origin.helo.map_or(rhai::Dynamic::UNIT, Into::into))
+    }
+
+    /// Get the recipient sent by the client, when it was unknown and has been rewritten
+    /// to the catch-all mailbox of its domain (`server.virtual[domain].catch_all`).
+    ///
+    /// # Effective smtp stage
+    ///
+    /// `rcpt` and onwards.
+    ///
+    /// # Return
+    ///
+    /// * `address` - the recipient sent by the client before the rewrite of `ctx::rcpt()`,
+    ///   or `()` if the current recipient has not been rewritten.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # vsmtp_test::vsl::run(
+    /// # |builder| Ok(builder.add_root_filter_rules(r#"
+    /// #{
+    ///     rcpt: [
+    ///        action "log catch-all" || {
+    ///          let original = ctx::original_rcpt();
+    ///          if original != () {
+    ///            log("info", `${original} rewritten to ${ctx::rcpt()}`);
+    ///          }
+    ///        },
+    ///     ]
+    /// }
+    /// # "#)?.build()));
+    /// ```
+    ///
+    /// # rhai-autodocs:index:29
+    #[rhai_fn(name = "original_rcpt", return_raw)]
+    pub fn original_rcpt(ncc: NativeCallContext) -> EngineResult<rhai::Dynamic> {
+        let ctx = get_global!(ncc, ctx);
+        let ctx = vsl_guard_ok!(ctx.read());
+        let rcpt = ctx
+            .forward_paths()
+            .map_err(Into::<crate::error::RuntimeError>::into)?
+            .last()
+            .ok_or_else(|| crate::error::RuntimeError::Generic {
+                message: "recipient are empty".to_string(),
+            })?;
+
+        Ok(ctx
+            .rcpt_catch_all()
+            .map_err(Into::<crate::error::RuntimeError>::into)?
+            .get(rcpt)
+            .and_then(|originals| originals.last())
+            .map_or(rhai::Dynamic::UNIT, |original| {
+                rhai::Dynamic::from(std::sync::Arc::new(Object::Address(original.clone())))
+            }))
+    }
+
+    /// Get the arguments of the `MAIL FROM` command as sent by the client, casing,
+    /// spacing and parameters included, for the forensic logs.
+    ///
+    /// The text is truncated to 512 bytes, the invalid UTF-8 being replaced.
+    /// It is dropped once the message is queued, except for the messages quarantined
+    /// before the queue if `server.smtp.raw_commands_in_quarantine` is set.
+    ///
+    /// # Effective smtp stage
+    ///
+    /// `mail` and onwards.
+    ///
+    /// # Return
+    ///
+    /// * `string` - the arguments, or `()` if they have been dropped.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # vsmtp_test::vsl::run(
+    /// # |builder| Ok(builder.add_root_filter_rules(r#"
+    /// #{
+    ///     mail: [
+    ///        action "log raw sender" || log("info", `MAIL FROM:${ctx::raw_mail_from()}`),
+    ///     ]
+    /// }
+    /// # "#)?.build()));
+    /// ```
+    ///
+    /// # rhai-autodocs:index:30
+    #[rhai_fn(name = "raw_mail_from", return_raw)]
+    pub fn raw_mail_from(ncc: NativeCallContext) -> EngineResult<rhai::Dynamic> {
+        Ok(vsl_guard_ok!(get_global!(ncc, ctx).read())
+            .raw_mail_from()
+            .map_err(Into::<crate::error::RuntimeError>::into)?
+            .map_or(rhai::Dynamic::UNIT, |raw| raw.to_owned().into()))
+    }
+
+    /// Get the arguments of the `i`-th `RCPT TO` command of the transaction (starting
+    /// at 0) as sent by the client, see `ctx::raw_mail_from()`.
+    ///
+    /// # Args
+    ///
+    /// * `i` - the index of the command.
+    ///
+    /// # Effective smtp stage
+    ///
+    /// `rcpt` and onwards.
+    ///
+    /// # Return
+    ///
+    /// * `string` - the arguments, or `()` if there is no such command or if they
+    ///   have been dropped.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # vsmtp_test::vsl::run(
+    /// # |builder| Ok(builder.add_root_filter_rules(r#"
+    /// #{
+    ///     rcpt: [
+    ///        action "log first raw recipient" || log("info", `RCPT TO:${ctx::raw_rcpt(0)}`),
+    ///     ]
+    /// }
+    /// # "#)?.build()));
+    /// ```
+    ///
+    /// # rhai-autodocs:index:31
+    #[rhai_fn(name = "raw_rcpt", return_raw)]
+    pub fn raw_rcpt(ncc: NativeCallContext, i: rhai::INT) -> EngineResult<rhai::Dynamic> {
+        let ctx = get_global!(ncc, ctx);
+        let ctx = vsl_guard_ok!(ctx.read());
+        let raw_rcpt = ctx
+            .raw_rcpt()
+            .map_err(Into::<crate::error::RuntimeError>::into)?;
+
+        Ok(usize::try_from(i)
+            .ok()
+            .and_then(|i| raw_rcpt.get(i))
+            .map_or(rhai::Dynamic::UNIT, |raw| raw.clone().into()))
+    }
+
+    /// Get the outcome of the session with the client, determined when the connection
+    /// is closed from the commands received.
+    ///
+    /// # Effective smtp stage
+    ///
+    /// `disconnect`.
+    ///
+    /// # Return
+    ///
+    /// * `string` - one of `completed`, `aborted_after_mail`, `probe`, `scanner`,
+    ///   `tls_failed` or `other`, or `()` before the `disconnect` stage.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # vsmtp_test::vsl::run(
+    /// # |builder| Ok(builder.add_root_filter_rules(r#"
+    /// #{
+    ///     disconnect: [
+    ///        action "log scanners" || if ctx::session_category() == "scanner" {
+    ///            log("warn", `scanner at ${ctx::client_ip()}`);
+    ///        },
+    ///     ]
+    /// }
+    /// # "#)?.build()));
+    /// ```
+    ///
+    /// # rhai-autodocs:index:32
+    #[rhai_fn(name = "session_category", return_raw)]
+    pub fn session_category(ncc: NativeCallContext) -> EngineResult<rhai::Dynamic> {
+        Ok(vsl_guard_ok!(get_global!(ncc, ctx).read())
+            .session_category()
+            .map_or(rhai::Dynamic::UNIT, |category| category.to_string().into()))
+    }
+
+    /// Set how the message is delivered by the `deliver` transport to the exchangers
+    /// of the recipients' domains.
+    ///
+    /// With `race`, a connection is opened to the two most preferred exchangers
+    /// concurrently, and the message is sent to the first one ready for a transaction,
+    /// the other connection being closed before `MAIL FROM`. The exchangers left are
+    /// tried one after the other if both connections fail, or if the message could
+    /// not be sent to the fastest one. The throttle of the destinations still applies.
+    ///
+    /// # Args
+    ///
+    /// * `strategy` - `serial` (the default) or `race`, for the latency-critical messages.
+    ///
+    /// # Effective smtp stage
+    ///
+    /// `rcpt` and onwards.
+    ///
+    /// # Errors
+    ///
+    /// * The strategy is unknown.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # let rules = r#"
+    /// #{
+    ///     preq: [
+    ///        action "latency-critical" || ctx::set_delivery_strategy("race"),
+    ///     ]
+    /// }
+    /// # "#;
+    /// # let states = vsmtp_test::vsl::run(|builder| Ok(builder
+    /// #   .add_root_filter_rules("#{}")?
+    /// #      .add_domain_rules("testserver.com".parse().unwrap())
+    /// #        .with_incoming(rules)?
+    /// #        .with_outgoing(rules)?
+    /// #        .with_internal(rules)?
+    /// #      .build()
+    /// #   .build()));
+    /// # assert_eq!(
+    /// #   states[&vsmtp_rule_engine::ExecutionStage::PreQ].0.delivery_strategy().unwrap(),
+    /// #   vsmtp_common::DeliveryStrategy::Race
+    /// # );
+    /// ```
+    ///
+    /// # rhai-autodocs:index:33
+    #[rhai_fn(name = "set_delivery_strategy", return_raw)]
+    pub fn set_delivery_strategy(ncc: NativeCallContext, strategy: &str) -> EngineResult<()> {
+        let strategy = strategy
+            .parse::<vsmtp_common::DeliveryStrategy>()
+            .map_err(|_| crate::error::RuntimeError::Generic {
+                message: format!(
+                    "the delivery strategy `{strategy}` is unknown, expected `serial` or `race`"
+                ),
+            })?;
+
+        Ok(vsl_guard_ok!(get_global!(ncc, ctx).write())
+            .set_delivery_strategy(strategy)
+            .map_err(Into::<crate::error::RuntimeError>::into)?)
+    }
+
+    /// Get the number of transactions abandoned by the client since the beginning of the
+    /// connection, a `MAIL FROM` followed by a `RSET`, `HELO` or `EHLO`.
+    ///
+    /// A client trying many senders on a single connection is likely probing the server.
+    /// The connection is closed with a `421` once `server.smtp.error.churn_count` is reached.
+    ///
+    /// # Effective smtp stage
+    ///
+    /// All of them.
+    ///
+    /// # Return
+    ///
+    /// * `number` - the number of transactions reset.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # let states = vsmtp_test::vsl::run(
+    /// # |builder| Ok(builder.add_root_filter_rules(r#"
+    /// #{
+    ///   mail: [
+    ///     rule "sender churn" || {
+    ///       if ctx::sender_churn() >= 3 && !auth::is_authenticated() {
+    ///         state::deny()
+    ///       } else {
+    ///         state::next()
+    ///       }
+    ///     }
+    ///   ],
+    /// }
+    /// # "#)?.build()));
+    /// # use vsmtp_common::status::Status;
+    /// # assert_eq!(states[&vsmtp_rule_engine::ExecutionStage::MailFrom].2, Status::Next);
+    /// ```
+    ///
+    /// # rhai-autodocs:index:34
+    #[rhai_fn(name = "sender_churn", return_raw)]
+    pub fn sender_churn(ncc: NativeCallContext) -> EngineResult<rhai::INT> {
+        Ok(vsl_guard_ok!(get_global!(ncc, ctx).read()).sender_churn())
+    }
+
+    /// Get the extension of the local part of a recipient of the domains of the server
+    /// (`lists` in `john+lists@example.com`), split on the delimiters of
+    /// `server.recipient_delimiter` when the recipient was received.
+    ///
+    /// # Args
+    ///
+    /// * `rcpt` - the recipient.
+    ///
+    /// # Effective smtp stage
+    ///
+    /// `rcpt` and onwards.
+    ///
+    /// # Return
+    ///
+    /// * `string` - the text following the delimiter, can be empty, or `()` if the
+    ///   recipient has no extension.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # vsmtp_test::vsl::run(
+    /// # |builder| Ok(builder.add_root_filter_rules(r#"
+    /// #{
+    ///     rcpt: [
+    ///        action "log extension" || {
+    ///          let extension = ctx::rcpt_extension(ctx::rcpt());
+    ///          if extension != () {
+    ///            log("info", `${ctx::rcpt()} has the extension ${extension}`);
+    ///          }
+    ///        },
+    ///     ]
+    /// }
+    /// # "#)?.build()));
+    /// ```
+    ///
+    /// # rhai-autodocs:index:35
+    #[rhai_fn(name = "rcpt_extension", return_raw)]
+    pub fn rcpt_extension_str(ncc: NativeCallContext, rcpt: &str) -> EngineResult<rhai::Dynamic> {
+        super::rcpt_extension(&get_global!(ncc, ctx), rcpt)
+    }
+
+    #[doc(hidden)]
+    #[rhai_fn(name = "rcpt_extension", return_raw)]
+    pub fn rcpt_extension_obj(
+        ncc: NativeCallContext,
+        rcpt: SharedObject,
+    ) -> EngineResult<rhai::Dynamic> {
+        super::rcpt_extension(&get_global!(ncc, ctx), &rcpt.to_string())
+    }
+}
+
+fn rcpt_extension(context: &Context, rcpt: &str) -> EngineResult<rhai::Dynamic> {
+    let rcpt = vsl_conversion_ok!(
+        "address",
+        <vsmtp_common::Address as std::str::FromStr>::from_str(rcpt)
+    );
+
+    Ok(vsl_guard_ok!(context.read())
+        .rcpt_extensions()
+        .map_err(Into::<crate::error::RuntimeError>::into)?
+        .get(&rcpt)
+        .map_or(rhai::Dynamic::UNIT, |extension| {
+            extension.detail.clone().into()
+        }))
 }
@@ -17,7 +17,7 @@
 
 use crate::{
     api::{
-        EngineResult, {Message, SharedObject},
+        EngineResult, {Context, Message, SharedObject},
     },
     get_global,
 };
@@ -28,6 +28,7 @@ use rhai::plugin::{
 
 pub use message::*;
 use vsmtp_common::Address;
+use vsmtp_mail_parser::MimeLimits;
 
 /// Inspect incoming messages.
 #[rhai::plugin::export_module]
@@ -45,6 +46,86 @@ mod message {
             .to_string()
     }
 
+    /// Get the size of the message as stored, in bytes, without copying it.
+    ///
+    /// # Effective smtp stage
+    ///
+    /// `preq` and onwards.
+    ///
+    /// # Return
+    ///
+    /// * `number` - the size of the headers and the body of the message.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # let msg = vsmtp_mail_parser::MessageBody::try_from(concat!(
+    /// "Subject: Unit test are cool\r\n",
+    /// "\r\n",
+    /// "Hello world!\r\n",
+    /// # )).unwrap();
+    /// # let rules = r#"
+    /// #{
+    ///   preq: [
+    ///     rule "check size" || {
+    ///       if msg::size() == 45 && msg::header_size() == 29 {
+    ///         state::accept();
+    ///       } else {
+    ///         state::deny();
+    ///       }
+    ///     }
+    ///   ]
+    /// }
+    /// # "#;
+    /// # let states = vsmtp_test::vsl::run_with_msg(|builder| Ok(builder
+    /// #   .add_root_filter_rules("#{}")?
+    /// #      .add_domain_rules("testserver.com".parse().unwrap())
+    /// #        .with_incoming(rules)?
+    /// #        .with_outgoing(rules)?
+    /// #        .with_internal(rules)?
+    /// #      .build()
+    /// #   .build()), Some(msg));
+    /// # use vsmtp_common::{status::Status};
+    /// # assert_eq!(states[&vsmtp_rule_engine::ExecutionStage::PreQ].2, Status::Accept("250 Ok".parse::<vsmtp_common::Reply>().unwrap()));
+    /// ```
+    ///
+    /// # rhai-autodocs:index:2
+    #[rhai_fn(name = "size", return_raw)]
+    pub fn size(ncc: NativeCallContext) -> EngineResult<rhai::INT> {
+        let size = vsl_guard_ok!(get_global!(ncc, msg).read()).size();
+        Ok(rhai::INT::try_from(size).unwrap_or(rhai::INT::MAX))
+    }
+
+    /// Get the size of the header section of the message, in bytes, without copying it.
+    ///
+    /// # Effective smtp stage
+    ///
+    /// `preq` and onwards.
+    ///
+    /// # Return
+    ///
+    /// * `number` - the size of the headers, without the empty line separating them from the body.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # vsmtp_test::vsl::run(
+    /// # |builder| Ok(builder.add_root_filter_rules(r#"
+    /// #{
+    ///     preq: [
+    ///        action "log header size" || log("info", `headers: ${msg::header_size()} bytes`),
+    ///     ]
+    /// }
+    /// # "#)?.build()));
+    /// ```
+    ///
+    /// # rhai-autodocs:index:3
+    #[rhai_fn(name = "header_size", return_raw)]
+    pub fn header_size(ncc: NativeCallContext) -> EngineResult<rhai::INT> {
+        let size = vsl_guard_ok!(get_global!(ncc, msg).read()).header_size();
+        Ok(rhai::INT::try_from(size).unwrap_or(rhai::INT::MAX))
+    }
+
     /// Checks if the message contains a specific header.
     ///
     /// # Args
@@ -91,7 +172,7 @@ mod message {
     /// # assert_eq!(states[&vsmtp_rule_engine::ExecutionStage::PreQ].2, Status::Accept("250 Ok".parse::<vsmtp_common::Reply>().unwrap()));
     /// ```
     ///
-    /// # rhai-autodocs:index:2
+    /// # rhai-autodocs:index:4
     #[rhai_fn(name = "has_header", return_raw)]
     pub fn has_header(ncc: NativeCallContext, header: &str) -> EngineResult<bool> {
         Ok(vsl_guard_ok!(get_global!(ncc, msg).read())
@@ -154,7 +235,7 @@ mod message {
     /// # ));
     /// ```
     ///
-    /// # rhai-autodocs:index:3
+    /// # rhai-autodocs:index:5
     #[rhai_fn(name = "count_header", return_raw)]
     pub fn count_header(ncc: NativeCallContext, header: &str) -> EngineResult<rhai::INT> {
         super::Impl::count_header(&get_global!(ncc, msg), header)
@@ -224,7 +305,7 @@ mod message {
     /// # ));
     /// ```
     ///
-    /// # rhai-autodocs:index:4
+    /// # rhai-autodocs:index:6
     #[rhai_fn(name = "get_header", return_raw)]
     pub fn get_header(ncc: NativeCallContext, header: &str) -> EngineResult<String> {
         Ok(vsl_guard_ok!(get_global!(ncc, msg).read())
@@ -278,7 +359,7 @@ mod message {
     /// # "#)?.build()), Some(msg));
     /// ```
     ///
-    /// # rhai-autodocs:index:5
+    /// # rhai-autodocs:index:7
     #[rhai_fn(name = "get_all_headers", return_raw)]
     pub fn get_all_headers(ncc: NativeCallContext) -> EngineResult<rhai::Array> {
         Ok(vsl_guard_ok!(get_global!(ncc, msg).read())
@@ -348,7 +429,7 @@ mod message {
     /// # "#)?.build()), Some(msg));
     /// ```
     ///
-    /// # rhai-autodocs:index:6
+    /// # rhai-autodocs:index:8
     #[rhai_fn(return_raw)]
     pub fn get_header_untouched(ncc: NativeCallContext, name: &str) -> EngineResult<rhai::Array> {
         Ok(super::Impl::get_header_untouched(
@@ -405,7 +486,7 @@ mod message {
     /// # ]);
     /// ```
     ///
-    /// # rhai-autodocs:index:7
+    /// # rhai-autodocs:index:9
     #[rhai_fn(name = "append_header", return_raw)]
     pub fn append_header(ncc: NativeCallContext, header: &str, value: &str) -> EngineResult<()> {
         super::Impl::append_header(&get_global!(ncc, msg), &header, &value);
@@ -471,7 +552,7 @@ mod message {
     /// # ]);
     /// ```
     ///
-    /// # rhai-autodocs:index:8
+    /// # rhai-autodocs:index:10
     #[rhai_fn(name = "prepend_header", return_raw)]
     pub fn prepend_header(ncc: NativeCallContext, header: &str, value: &str) -> EngineResult<()> {
         super::Impl::prepend_header(&get_global!(ncc, msg), header, value);
@@ -539,7 +620,7 @@ mod message {
     /// # ));
     /// ```
     ///
-    /// # rhai-autodocs:index:9
+    /// # rhai-autodocs:index:11
     #[rhai_fn(name = "set_header", return_raw)]
     pub fn set_header(ncc: NativeCallContext, header: &str, value: &str) -> EngineResult<()> {
         super::Impl::set_header(&get_global!(ncc, msg), header, value);
@@ -613,7 +694,7 @@ mod message {
     /// # ));
     /// ```
     ///
-    /// # rhai-autodocs:index:10
+    /// # rhai-autodocs:index:12
     #[rhai_fn(name = "rename_header", return_raw)]
     pub fn rename_header(ncc: NativeCallContext, old: &str, new: &str) -> EngineResult<()> {
         super::Impl::rename_header(&get_global!(ncc, msg), old, new);
@@ -672,7 +753,7 @@ mod message {
     /// # "#)?.build()));
     /// ```
     ///
-    /// # rhai-autodocs:index:11
+    /// # rhai-autodocs:index:13
     #[rhai_fn(name = "mail", return_raw)]
     pub fn mail(ncc: NativeCallContext) -> EngineResult<String> {
         Ok(vsl_guard_ok!(get_global!(ncc, msg).read())
@@ -734,7 +815,7 @@ mod message {
     /// # ));
     /// ```
     ///
-    /// # rhai-autodocs:index:12
+    /// # rhai-autodocs:index:14
     #[rhai_fn(name = "rm_header", return_raw)]
     pub fn remove_header(ncc: NativeCallContext, header: &str) -> EngineResult<bool> {
         Ok(super::Impl::remove_header(&get_global!(ncc, msg), header))
@@ -772,13 +853,17 @@ mod message {
     /// # "#)?.build()));
     /// ```
     ///
-    /// # rhai-autodocs:index:13
+    /// # rhai-autodocs:index:15
     #[rhai_fn(name = "rw_mail_from", return_raw)]
     pub fn rewrite_mail_from_message_str(
         ncc: NativeCallContext,
         new_addr: &str,
     ) -> EngineResult<()> {
-        super::Impl::rewrite_mail_from_message(&get_global!(ncc, msg), new_addr)
+        super::Impl::rewrite_mail_from_message(
+            &get_global!(ncc, msg),
+            get_global!(ncc, srv).mime_limits(),
+            new_addr,
+        )
     }
 
     #[doc(hidden)]
@@ -787,7 +872,11 @@ mod message {
         ncc: NativeCallContext,
         new_addr: SharedObject,
     ) -> EngineResult<()> {
-        super::Impl::rewrite_mail_from_message(&get_global!(ncc, msg), &new_addr.to_string())
+        super::Impl::rewrite_mail_from_message(
+            &get_global!(ncc, msg),
+            get_global!(ncc, srv).mime_limits(),
+            &new_addr.to_string(),
+        )
     }
 
     /// Replace a recipient by an other in the `To` header of the message.
@@ -814,14 +903,19 @@ mod message {
     /// # "#)?.build()));
     /// ```
     ///
-    /// # rhai-autodocs:index:14
+    /// # rhai-autodocs:index:16
     #[rhai_fn(name = "rw_rcpt", return_raw)]
     pub fn rewrite_rcpt_message_str_str(
         ncc: NativeCallContext,
         old_addr: &str,
         new_addr: &str,
     ) -> EngineResult<()> {
-        super::Impl::rewrite_rcpt_message(&get_global!(ncc, msg), old_addr, new_addr)
+        super::Impl::rewrite_rcpt_message(
+            &get_global!(ncc, msg),
+            get_global!(ncc, srv).mime_limits(),
+            old_addr,
+            new_addr,
+        )
     }
 
     #[doc(hidden)]
@@ -831,7 +925,12 @@ mod message {
         old_addr: SharedObject,
         new_addr: &str,
     ) -> EngineResult<()> {
-        super::Impl::rewrite_rcpt_message(&get_global!(ncc, msg), &old_addr.to_string(), new_addr)
+        super::Impl::rewrite_rcpt_message(
+            &get_global!(ncc, msg),
+            get_global!(ncc, srv).mime_limits(),
+            &old_addr.to_string(),
+            new_addr,
+        )
     }
 
     #[doc(hidden)]
@@ -841,7 +940,12 @@ mod message {
         old_addr: &str,
         new_addr: SharedObject,
     ) -> EngineResult<()> {
-        super::Impl::rewrite_rcpt_message(&get_global!(ncc, msg), old_addr, &new_addr.to_string())
+        super::Impl::rewrite_rcpt_message(
+            &get_global!(ncc, msg),
+            get_global!(ncc, srv).mime_limits(),
+            old_addr,
+            &new_addr.to_string(),
+        )
     }
 
     #[doc(hidden)]
@@ -853,6 +957,7 @@ mod message {
     ) -> EngineResult<()> {
         super::Impl::rewrite_rcpt_message(
             &get_global!(ncc, msg),
+            get_global!(ncc, srv).mime_limits(),
             &old_addr.to_string(),
             &new_addr.to_string(),
         )
@@ -881,10 +986,14 @@ mod message {
     /// # "#)?.build()));
     /// ```
     ///
-    /// # rhai-autodocs:index:15
+    /// # rhai-autodocs:index:17
     #[rhai_fn(name = "add_rcpt", return_raw)]
     pub fn add_rcpt_message_str(ncc: NativeCallContext, new_addr: &str) -> EngineResult<()> {
-        super::Impl::add_rcpt_message(&get_global!(ncc, msg), new_addr)
+        super::Impl::add_rcpt_message(
+            &get_global!(ncc, msg),
+            get_global!(ncc, srv).mime_limits(),
+            new_addr,
+        )
     }
 
     #[doc(hidden)]
@@ -893,7 +1002,11 @@ mod message {
         ncc: NativeCallContext,
         new_addr: SharedObject,
     ) -> EngineResult<()> {
-        super::Impl::add_rcpt_message(&get_global!(ncc, msg), &new_addr.to_string())
+        super::Impl::add_rcpt_message(
+            &get_global!(ncc, msg),
+            get_global!(ncc, srv).mime_limits(),
+            &new_addr.to_string(),
+        )
     }
 
     /// Remove a recipient from the `To` header of the message.
@@ -919,16 +1032,163 @@ mod message {
     /// # "#)?.build()));
     /// ```
     ///
-    /// # rhai-autodocs:index:16
+    /// # rhai-autodocs:index:18
     #[rhai_fn(name = "rm_rcpt", return_raw)]
     pub fn remove_rcpt_message_str(ncc: NativeCallContext, addr: &str) -> EngineResult<()> {
-        super::Impl::remove_rcpt_message(&get_global!(ncc, msg), addr)
+        super::Impl::remove_rcpt_message(
+            &get_global!(ncc, msg),
+            get_global!(ncc, srv).mime_limits(),
+            addr,
+        )
     }
 
     #[doc(hidden)]
     #[rhai_fn(name = "rm_rcpt", return_raw)]
     pub fn remove_rcpt_message_obj(ncc: NativeCallContext, addr: SharedObject) -> EngineResult<()> {
-        super::Impl::remove_rcpt_message(&get_global!(ncc, msg), &addr.to_string())
+        super::Impl::remove_rcpt_message(
+            &get_global!(ncc, msg),
+            get_global!(ncc, srv).mime_limits(),
+            &addr.to_string(),
+        )
+    }
+
+    /// Check if the MIME structure of the message exceeded the parser limits
+    /// (nesting depth, number of parts or boundary length).
+    ///
+    /// The rest of the message is not parsed, but it is still delivered unmodified.
+    ///
+    /// # Effective smtp stage
+    ///
+    /// `preq` and onwards.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # vsmtp_test::vsl::run(
+    /// # |builder| Ok(builder.add_root_filter_rules(r#"
+    /// #{
+    ///     preq: [
+    ///        rule "quarantine malformed" || if msg::is_parse_truncated() {
+    ///            state::quarantine("malformed")
+    ///        } else {
+    ///            state::next()
+    ///        },
+    ///     ]
+    /// }
+    /// # "#)?.build()));
+    /// ```
+    ///
+    /// # rhai-autodocs:index:19
+    #[rhai_fn(return_raw)]
+    pub fn is_parse_truncated(ncc: NativeCallContext) -> EngineResult<bool> {
+        super::Impl::is_parse_truncated(&get_global!(ncc, msg), get_global!(ncc, srv).mime_limits())
+    }
+
+    /// Get the DKIM signatures of the message, the `DKIM-Signature` headers which
+    /// could not be parsed are ignored.
+    ///
+    /// # Return
+    ///
+    /// * `array` - a map by signature, with the fields `sdid` (the signing domain),
+    ///   `auid`, `selector` and `headers` (the names of the headers covered).
+    ///
+    /// # Effective smtp stage
+    ///
+    /// `preq` and onwards.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # vsmtp_test::vsl::run(
+    /// # |builder| Ok(builder.add_root_filter_rules(r#"
+    /// #{
+    ///     postq: [
+    ///        action "log signers" || {
+    ///            for signature in msg::dkim_signatures() {
+    ///                log("info", `signed by ${signature.sdid}, covering ${signature.headers}`);
+    ///            }
+    ///        },
+    ///     ]
+    /// }
+    /// # "#)?.build()));
+    /// ```
+    ///
+    /// # rhai-autodocs:index:20
+    #[rhai_fn(return_raw)]
+    pub fn dkim_signatures(ncc: NativeCallContext) -> EngineResult<rhai::Array> {
+        Ok(super::Impl::dkim_signatures(&get_global!(ncc, msg)))
+    }
+
+    /// Add a header only on the copy of the message delivered to a recipient,
+    /// on top of the headers of the message.
+    ///
+    /// # Args
+    ///
+    /// * `rcpt` - the recipient.
+    /// * `header` - the name of the header to add.
+    /// * `value` - the value of the header to add.
+    ///
+    /// # Errors
+    ///
+    /// * the name of the header is empty or contains a colon, a space or a control character.
+    /// * the value of the header contains a line break.
+    ///
+    /// # Effective smtp stage
+    ///
+    /// `preq` and onwards.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # let rules = r#"
+    /// #{
+    ///     preq: [
+    ///        action "tag copies" || {
+    ///            msg::append_rcpt_header("john.doe@example.com", "X-Tag", "john");
+    ///            msg::append_rcpt_header(address("jenny.doe@example.com"), "X-Tag", "jenny");
+    ///        },
+    ///     ]
+    /// }
+    /// # "#;
+    /// # let states = vsmtp_test::vsl::run(|builder| Ok(builder
+    /// #   .add_root_filter_rules("#{}")?
+    /// #      .add_domain_rules("testserver.com".parse().unwrap())
+    /// #        .with_incoming(rules)?
+    /// #        .with_outgoing(rules)?
+    /// #        .with_internal(rules)?
+    /// #      .build()
+    /// #   .build()));
+    /// # let ctx = states[&vsmtp_rule_engine::ExecutionStage::PreQ].0.clone().unwrap_finished().unwrap();
+    /// # assert_eq!(
+    /// #   ctx.finished.rcpt_headers[&vsmtp_common::addr!("john.doe@example.com")],
+    /// #   vec![("X-Tag".to_owned(), "john".to_owned())]
+    /// # );
+    /// # assert_eq!(
+    /// #   ctx.finished.rcpt_headers[&vsmtp_common::addr!("jenny.doe@example.com")],
+    /// #   vec![("X-Tag".to_owned(), "jenny".to_owned())]
+    /// # );
+    /// ```
+    ///
+    /// # rhai-autodocs:index:21
+    #[rhai_fn(name = "append_rcpt_header", return_raw)]
+    pub fn append_rcpt_header(
+        ncc: NativeCallContext,
+        rcpt: &str,
+        header: &str,
+        value: &str,
+    ) -> EngineResult<()> {
+        super::Impl::append_rcpt_header(&get_global!(ncc, ctx), rcpt, header, value)
+    }
+
+    #[doc(hidden)]
+    #[rhai_fn(name = "append_rcpt_header", return_raw)]
+    pub fn append_rcpt_header_obj(
+        ncc: NativeCallContext,
+        rcpt: SharedObject,
+        header: &str,
+        value: &str,
+    ) -> EngineResult<()> {
+        super::Impl::append_rcpt_header(&get_global!(ncc, ctx), &rcpt.to_string(), header, value)
     }
 }
 
@@ -1004,19 +1264,28 @@ impl Impl {
         vsl_guard_ok!(message.write()).remove_header(header.as_ref())
     }
 
-    fn rewrite_mail_from_message(message: &Message, new_addr: &str) -> EngineResult<()> {
+    fn rewrite_mail_from_message(
+        message: &Message,
+        limits: MimeLimits,
+        new_addr: &str,
+    ) -> EngineResult<()> {
         let new_addr = vsl_conversion_ok!(
             "address",
             <Address as std::str::FromStr>::from_str(new_addr)
         );
 
         let mut writer = vsl_guard_ok!(message.write());
-        vsl_parse_ok!(writer).rewrite_mail_from(new_addr.full());
+        vsl_parse_ok!(writer, limits).rewrite_mail_from(new_addr.full());
 
         Ok(())
     }
 
-    fn rewrite_rcpt_message(message: &Message, old_addr: &str, new_addr: &str) -> EngineResult<()> {
+    fn rewrite_rcpt_message(
+        message: &Message,
+        limits: MimeLimits,
+        old_addr: &str,
+        new_addr: &str,
+    ) -> EngineResult<()> {
         let new_addr = vsl_conversion_ok!(
             "address",
             <Address as std::str::FromStr>::from_str(new_addr)
@@ -1027,26 +1296,81 @@ impl Impl {
         );
 
         let mut writer = vsl_guard_ok!(message.write());
-        vsl_parse_ok!(writer).rewrite_rcpt(old_addr.full(), new_addr.full());
+        vsl_parse_ok!(writer, limits).rewrite_rcpt(old_addr.full(), new_addr.full());
         Ok(())
     }
 
-    fn add_rcpt_message(message: &Message, new_addr: &str) -> EngineResult<()> {
+    fn add_rcpt_message(message: &Message, limits: MimeLimits, new_addr: &str) -> EngineResult<()> {
         let new_addr = vsl_conversion_ok!(
             "address",
             <Address as std::str::FromStr>::from_str(new_addr)
         );
 
         let mut writer = vsl_guard_ok!(message.write());
-        vsl_parse_ok!(writer).add_rcpt(new_addr.full());
+        vsl_parse_ok!(writer, limits).add_rcpt(new_addr.full());
         Ok(())
     }
 
-    fn remove_rcpt_message(message: &Message, addr: &str) -> EngineResult<()> {
+    fn is_parse_truncated(message: &Message, limits: MimeLimits) -> EngineResult<bool> {
+        let mut writer = vsl_guard_ok!(message.write());
+        Ok(vsl_parse_ok!(writer, limits).parse_truncated)
+    }
+
+    fn dkim_signatures(message: &Message) -> rhai::Array {
+        Self::get_header_untouched(message, "DKIM-Signature")
+            .into_iter()
+            .filter_map(|header| {
+                <vsmtp_auth::dkim::Signature as std::str::FromStr>::from_str(&header.to_string())
+                    .ok()
+            })
+            .map(|signature| {
+                rhai::Dynamic::from_map(rhai::Map::from_iter([
+                    ("sdid".into(), signature.sdid.clone().into()),
+                    ("auid".into(), signature.auid.clone().into()),
+                    ("selector".into(), signature.selector().to_owned().into()),
+                    (
+                        "headers".into(),
+                        signature
+                            .headers_field()
+                            .iter()
+                            .cloned()
+                            .map(rhai::Dynamic::from)
+                            .collect::<rhai::Array>()
+                            .into(),
+                    ),
+                ]))
+            })
+            .collect()
+    }
+
+    fn remove_rcpt_message(message: &Message, limits: MimeLimits, addr: &str) -> EngineResult<()> {
         let addr = vsl_conversion_ok!("address", <Address as std::str::FromStr>::from_str(addr));
 
         let mut writer = vsl_guard_ok!(message.write());
-        vsl_parse_ok!(writer).remove_rcpt(addr.full());
+        vsl_parse_ok!(writer, limits).remove_rcpt(addr.full());
         Ok(())
     }
+
+    fn append_rcpt_header(
+        context: &Context,
+        rcpt: &str,
+        header: &str,
+        value: &str,
+    ) -> EngineResult<()> {
+        let rcpt = vsl_conversion_ok!("address", <Address as std::str::FromStr>::from_str(rcpt));
+        if header.is_empty()
+            || header
+                .chars()
+                .any(|c| c == ':' || c.is_whitespace() || c.is_control())
+        {
+            return Err(format!("invalid header name `{header}`").into());
+        }
+        if value.contains(['\r', '\n']) {
+            return Err(format!("invalid value for the header `{header}`").into());
+        }
+
+        vsl_guard_ok!(context.write())
+            .add_rcpt_header(rcpt, header.to_owned(), value.to_owned())
+            .map_err::<Box<rhai::EvalAltResult>, _>(|e| e.to_string().into())
+    }
 }
@@ -15,7 +15,10 @@
  *
 */
 
-use crate::api::{EngineResult, SharedObject};
+use crate::{
+    api::{EngineResult, SharedObject},
+    get_global,
+};
 use rhai::plugin::{
     mem, Dynamic, EvalAltResult, FnAccess, FnNamespace, ImmutableString, Module, NativeCallContext,
     PluginFunction, RhaiResult, TypeId,
@@ -285,7 +288,8 @@ mod state {
     ///
     /// # Args
     ///
-    /// * code - A customized code as a string or code object. (default: "451 Requested action aborted: local error in processing")
+    /// * code - A customized code as a string or code object. (default: `app.vsl.tempfail_reply` of the configuration,
+    ///   "451 4.3.0 Requested action aborted: local error in processing")
     ///
     /// # Errors
     ///
@@ -331,14 +335,9 @@ mod state {
     /// ```
     ///
     /// # rhai-autodocs:index:4
-    #[must_use]
-    #[rhai_fn(global)]
-    pub fn reject() -> Status {
-        Status::Reject(
-            "451 Requested action aborted: local error in processing\r\n"
-                .parse::<Reply>()
-                .expect("451 is a valid code"),
-        )
+    #[rhai_fn(global, name = "reject")]
+    pub fn reject(ncc: NativeCallContext) -> Status {
+        Status::Reject(get_global!(ncc, srv).config.app.vsl.tempfail_reply.clone())
     }
 
     #[doc(hidden)]
@@ -394,6 +393,63 @@ mod state {
         Status::Quarantine(queue.to_string())
     }
 
+    /// Skip all rules until the email is received and place the email in the
+    /// deferred queue. The delivery of the email is attempted once the delay has
+    /// elapsed, at the next flush of the deferred queue, and the rules are not
+    /// run again.
+    ///
+    /// Unlike a greylisting, the email has already been accepted: use it when a
+    /// resource needed to deliver it is temporarily unavailable.
+    ///
+    /// # Args
+    ///
+    /// * `delay` - the delay before the next attempt, as a duration string ("10m", "1h 30m")
+    ///             or a number of seconds.
+    ///
+    /// # Errors
+    ///
+    /// * The string passed as parameter is not a valid duration.
+    ///
+    /// # Effective smtp stage
+    ///
+    /// `preq` and onwards, the status is kept until the email is received when
+    /// returned before.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// #{
+    ///     postq: [
+    ///         rule "wait for the archive" || {
+    ///             if ctx::rcpt_count() > 100 {
+    ///                 state::defer("10m")
+    ///             } else {
+    ///                 state::next()
+    ///             }
+    ///         }
+    ///     ],
+    /// }
+    /// ```
+    ///
+    /// # rhai-autodocs:index:6
+    #[rhai_fn(name = "defer", return_raw)]
+    pub fn defer_str(delay: &str) -> EngineResult<Status> {
+        humantime_serde::re::humantime::parse_duration(delay)
+            .map(Status::Defer)
+            .map_err::<Box<EvalAltResult>, _>(|_| {
+                format!("parameter must be a duration, not {delay:?}").into()
+            })
+    }
+
+    #[doc(hidden)]
+    #[must_use]
+    #[rhai_fn(name = "defer")]
+    pub fn defer_seconds(delay: rhai::INT) -> Status {
+        Status::Defer(std::time::Duration::from_secs(
+            u64::try_from(delay).unwrap_or_default(),
+        ))
+    }
+
     /// Check if two statuses are equal.
     ///
     /// # Effective smtp stage
@@ -413,7 +469,7 @@ mod state {
     /// }
     /// ```
     ///
-    /// # rhai-autodocs:index:6
+    /// # rhai-autodocs:index:7
     #[rhai_fn(global, name = "==", pure)]
     pub fn eq_status_operator(status_1: &mut Status, status_2: Status) -> bool {
         *status_1 == status_2
@@ -438,7 +494,7 @@ mod state {
     /// }
     /// ```
     ///
-    /// # rhai-autodocs:index:7
+    /// # rhai-autodocs:index:8
     #[rhai_fn(global, name = "!=", pure)]
     pub fn neq_status_operator(status_1: &mut Status, status_2: Status) -> bool {
         !(*status_1 == status_2)
@@ -466,7 +522,7 @@ mod state {
     /// }
     /// ```
     ///
-    /// # rhai-autodocs:index:8
+    /// # rhai-autodocs:index:9
     #[rhai_fn(global, pure)]
     pub fn to_string(status: &mut Status) -> String {
         status.as_ref().to_string()
@@ -493,7 +549,7 @@ mod state {
     /// }
     /// ```
     ///
-    /// # rhai-autodocs:index:9
+    /// # rhai-autodocs:index:10
     #[rhai_fn(global, pure)]
     pub fn to_debug(status: &mut Status) -> String {
         status.as_ref().to_string()
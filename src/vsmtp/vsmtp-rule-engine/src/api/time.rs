@@ -54,7 +54,7 @@ mod time_mod {
     /// # rhai-autodocs:index:1
     #[must_use]
     pub fn now() -> String {
-        let now = time::OffsetDateTime::now_utc();
+        let now = vsmtp_common::clock::now();
 
         now.format(&TIME_FORMAT)
             .unwrap_or_else(|_| String::default())
@@ -85,7 +85,7 @@ mod time_mod {
     /// # rhai-autodocs:index:2
     #[must_use]
     pub fn date() -> String {
-        let now = time::OffsetDateTime::now_utc();
+        let now = vsmtp_common::clock::now();
 
         now.format(&DATE_FORMAT)
             .unwrap_or_else(|_| String::default())
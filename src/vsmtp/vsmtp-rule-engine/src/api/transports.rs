@@ -153,6 +153,7 @@ mod transport {
     /// #       port: 25,
     /// #       credentials: Some(("root@domain.tld".to_string(), "xxxxxx".to_string())),
     /// #       tls: vsmtp_delivery::TlsPolicy::StarttlsOpportunistic,
+    /// #       min_tls_version: None,
     /// #     }
     /// #   )
     /// # ))
@@ -432,7 +433,7 @@ mod transport {
             .set_transport_for_one(
                 &rcpt,
                 std::sync::Arc::new(Deliver::new(
-                    srv.resolvers.get_resolver_or_root(&rcpt.domain()),
+                    srv.resolvers.get_resolver_or_root(rcpt.domain_ref()),
                     srv.config.clone(),
                 )),
             )
@@ -453,7 +454,7 @@ mod transport {
             .set_transport_for_one(
                 &rcpt,
                 std::sync::Arc::new(Deliver::new(
-                    srv.resolvers.get_resolver_or_root(&rcpt.domain()),
+                    srv.resolvers.get_resolver_or_root(rcpt.domain_ref()),
                     srv.config.clone(),
                 )),
             )
@@ -802,16 +803,15 @@ mod transport {
             .map_err::<Box<EvalAltResult>, _>(|err| err.to_string().into())?;
 
         let ctx = get_global!(ncc, ctx);
-        let grp = get_global!(ncc, srv)
-            .config
-            .server
-            .system
-            .group_local
-            .clone();
+        let srv = get_global!(ncc, srv);
+        let grp = srv.config.server.system.group_local.clone();
 
         let mut guard = ctx.write().expect("mutex poisoned");
         guard
-            .set_transport_for_one(&rcpt, std::sync::Arc::new(Maildir::new(grp)))
+            .set_transport_for_one(
+                &rcpt,
+                std::sync::Arc::new(super::tag_folders(&srv, Maildir::new(grp))),
+            )
             .map_err::<Box<EvalAltResult>, _>(|err| err.to_string().into())
     }
 
@@ -822,19 +822,117 @@ mod transport {
             .map_err::<Box<EvalAltResult>, _>(|err| err.to_string().into())?;
 
         let ctx = get_global!(ncc, ctx);
-        let grp = get_global!(ncc, srv)
-            .config
-            .server
-            .system
-            .group_local
-            .clone();
+        let srv = get_global!(ncc, srv);
+        let grp = srv.config.server.system.group_local.clone();
+
+        let mut guard = ctx.write().expect("mutex poisoned");
+        guard
+            .set_transport_for_one(
+                &rcpt,
+                std::sync::Arc::new(super::tag_folders(&srv, Maildir::new(grp))),
+            )
+            .map_err::<Box<EvalAltResult>, _>(|err| err.to_string().into())
+    }
+
+    /// Set the delivery method to maildir for a recipient, storing the email in a folder of its mailbox.
+    /// After all rules are evaluated, the email will be stored
+    /// locally in the `~/Maildir/.<folder>/new/` folder of the recipient's user if it exists on the server.
+    ///
+    /// # Args
+    ///
+    /// * `rcpt` - the recipient to apply the method to.
+    /// * `folder` - the name of the folder, `INBOX` for the default one, sub folders are separated by dots (`INBOX.lists`).
+    ///
+    /// # Errors
+    ///
+    /// * the folder name is empty, contains an empty component (`..`), a path separator or a control character.
+    ///
+    /// # Effective smtp stage
+    ///
+    /// All of them.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// #{
+    ///     delivery: [
+    ///        action "file spam" || {
+    ///            if msg::has_header("X-Spam-Flag") {
+    ///                transport::maildir("john.doe@example.com", "Junk");
+    ///            }
+    ///        },
+    ///     ]
+    /// }
+    /// ```
+    ///
+    /// ```
+    /// # let rules = r#"
+    /// #{
+    ///   rcpt: [
+    /// #      action "rm default value" || {
+    /// #        envelop::rm_rcpt("recipient@testserver.com");
+    /// #      },
+    ///     action "setup maildir" || {
+    ///         const doe = address("doe@example.com");
+    ///         envelop::add_rcpt(doe);
+    ///         envelop::add_rcpt("a@example.com");
+    ///         transport::maildir(doe, "Junk");
+    ///         transport::maildir("a@example.com", "INBOX.lists");
+    ///     },
+    ///   ],
+    /// }
+    /// # "#;
+    ///
+    /// # let states = vsmtp_test::vsl::run(|builder| Ok(builder
+    /// #   .add_root_filter_rules("#{}")?
+    /// #      .add_domain_rules("testserver.com".parse().unwrap())
+    /// #        .with_incoming(rules)?
+    /// #        .with_outgoing(rules)?
+    /// #        .with_internal(rules)?
+    /// #      .build()
+    /// #   .build()));
+    /// # assert_eq!(states[&vsmtp_rule_engine::ExecutionStage::RcptTo].2, vsmtp_common::status::Status::Next);
+    ///
+    /// # use vsmtp_common::Address;
+    /// # let delivery = states[&vsmtp_rule_engine::ExecutionStage::RcptTo].0.delivery().unwrap();
+    /// # for (addr, folder) in [("doe@example.com", "Junk"), ("a@example.com", "INBOX.lists")] {
+    /// #   let transport = std::sync::Arc::new(vsmtp_delivery::Maildir::with_folder(None, folder).unwrap());
+    /// #   assert_eq!(
+    /// #     delivery[&vsmtp_common::transport::WrapperSerde::Ready(transport)],
+    /// #     [(Address::new_unchecked(addr.to_string()), vsmtp_common::transfer::Status::default())]
+    /// #   );
+    /// # }
+    /// ```
+    ///
+    /// # rhai-autodocs:index:8
+    #[rhai_fn(name = "maildir", return_raw)]
+    pub fn maildir_folder(ncc: NativeCallContext, rcpt: &str, folder: &str) -> EngineResult<()> {
+        let rcpt = <Address as std::str::FromStr>::from_str(rcpt)
+            .map_err::<Box<EvalAltResult>, _>(|err| err.to_string().into())?;
+
+        let ctx = get_global!(ncc, ctx);
+        let srv = get_global!(ncc, srv);
+        let grp = srv.config.server.system.group_local.clone();
+        let transport = Maildir::with_folder(grp, folder)
+            .map_err::<Box<EvalAltResult>, _>(|err| err.to_string().into())?;
+        let transport = super::tag_folders(&srv, transport);
 
         let mut guard = ctx.write().expect("mutex poisoned");
         guard
-            .set_transport_for_one(&rcpt, std::sync::Arc::new(Maildir::new(grp)))
+            .set_transport_for_one(&rcpt, std::sync::Arc::new(transport))
             .map_err::<Box<EvalAltResult>, _>(|err| err.to_string().into())
     }
 
+    #[doc(hidden)]
+    #[rhai_fn(name = "maildir", return_raw)]
+    pub fn maildir_folder_obj(
+        ncc: NativeCallContext,
+        rcpt: SharedObject,
+        folder: &str,
+    ) -> EngineResult<()> {
+        maildir_folder(ncc, &rcpt.to_string(), folder)
+    }
+
     /// Set the delivery method to maildir for all recipients.
     /// After all rules are evaluated, the email will be stored
     /// locally in each `~/Maildir/new` folder of they respective recipient
@@ -899,20 +997,27 @@ mod transport {
     /// # }
     /// ```
     ///
-    /// # rhai-autodocs:index:8
+    /// # rhai-autodocs:index:9
     #[rhai_fn(return_raw)]
     pub fn maildir_all(ncc: NativeCallContext) -> EngineResult<()> {
         let ctx = get_global!(ncc, ctx);
-        let grp = get_global!(ncc, srv)
-            .config
-            .server
-            .system
-            .group_local
-            .clone();
+        let srv = get_global!(ncc, srv);
+        let grp = srv.config.server.system.group_local.clone();
 
         let mut guard = ctx.write().expect("mutex poisoned");
         guard
-            .set_transport_foreach(std::sync::Arc::new(Maildir::new(grp)))
+            .set_transport_foreach(std::sync::Arc::new(super::tag_folders(
+                &srv,
+                Maildir::new(grp),
+            )))
             .map_err::<Box<EvalAltResult>, _>(|err| err.to_string().into())
     }
 }
+
+/// Enable the storage of the tagged addresses in their folder, if configured.
+fn tag_folders(srv: &crate::api::Server, maildir: Maildir) -> Maildir {
+    match srv.config.server.maildir_tag_folders {
+        Some(ref tag_folders) => maildir.with_tag_folders(tag_folders.separator),
+        None => maildir,
+    }
+}
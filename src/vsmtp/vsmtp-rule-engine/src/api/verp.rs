@@ -0,0 +1,214 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2022 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+
+use crate::api::{EngineResult, SharedObject};
+use rhai::plugin::{
+    mem, Dynamic, FnAccess, FnNamespace, ImmutableString, Module, NativeCallContext,
+    PluginFunction, RhaiResult, TypeId,
+};
+use vsmtp_common::Address;
+use vsmtp_plugin_vsl::objects::Object;
+
+pub use verp::*;
+
+/// Variable envelope return paths (VERP), to identify the recipient of a bounce.
+#[rhai::plugin::export_module]
+mod verp {
+    /// Encode a recipient in a sender address, `bounce+user=example.com@lists.example.com`
+    /// for the recipient `user@example.com` and the base `bounce@lists.example.com`.
+    ///
+    /// # Args
+    ///
+    /// * `base` - the address receiving the bounces, its local part must not contain a `+`.
+    /// * `rcpt` - the recipient to encode.
+    ///
+    /// # Return
+    ///
+    /// * `address` - the sender to use for the recipient, see `envelop::set_mail_from`.
+    ///
+    /// # Effective smtp stage
+    ///
+    /// All of them.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # vsmtp_test::vsl::run(
+    /// # |builder| Ok(builder.add_root_filter_rules(r#"
+    /// #{
+    ///     rcpt: [
+    ///        action "verp" || envelop::set_mail_from(ctx::rcpt(), verp::encode("bounce@lists.example.com", ctx::rcpt())),
+    ///     ]
+    /// }
+    /// # "#)?.build()));
+    /// ```
+    ///
+    /// # rhai-autodocs:index:1
+    #[rhai_fn(name = "encode", return_raw)]
+    pub fn encode_str_str(base: &str, rcpt: &str) -> EngineResult<SharedObject> {
+        super::encode(base, rcpt)
+    }
+
+    #[doc(hidden)]
+    #[rhai_fn(name = "encode", return_raw)]
+    pub fn encode_obj_str(base: SharedObject, rcpt: &str) -> EngineResult<SharedObject> {
+        super::encode(&base.to_string(), rcpt)
+    }
+
+    #[doc(hidden)]
+    #[rhai_fn(name = "encode", return_raw)]
+    pub fn encode_str_obj(base: &str, rcpt: SharedObject) -> EngineResult<SharedObject> {
+        super::encode(base, &rcpt.to_string())
+    }
+
+    #[doc(hidden)]
+    #[rhai_fn(name = "encode", return_raw)]
+    pub fn encode_obj_obj(base: SharedObject, rcpt: SharedObject) -> EngineResult<SharedObject> {
+        super::encode(&base.to_string(), &rcpt.to_string())
+    }
+
+    /// Decode the recipient of a VERP address, the recipient of a bounce for instance.
+    ///
+    /// # Args
+    ///
+    /// * `addr` - the VERP address.
+    ///
+    /// # Return
+    ///
+    /// * `address` - the recipient encoded in `addr`.
+    /// * `()` - when `addr` is not a VERP address.
+    ///
+    /// # Effective smtp stage
+    ///
+    /// All of them.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # vsmtp_test::vsl::run(
+    /// # |builder| Ok(builder.add_root_filter_rules(r#"
+    /// #{
+    ///     rcpt: [
+    ///        rule "bounce" || {
+    ///          let rcpt = verp::decode(ctx::rcpt());
+    ///          if rcpt != () {
+    ///            log("info", `bounce of the message sent to ${rcpt}`);
+    ///          }
+    ///          state::next()
+    ///        },
+    ///     ]
+    /// }
+    /// # "#)?.build()));
+    /// ```
+    ///
+    /// # rhai-autodocs:index:2
+    #[rhai_fn(name = "decode", return_raw)]
+    pub fn decode_str(addr: &str) -> EngineResult<Dynamic> {
+        super::decode(addr)
+    }
+
+    #[doc(hidden)]
+    #[rhai_fn(name = "decode", return_raw)]
+    pub fn decode_obj(addr: SharedObject) -> EngineResult<Dynamic> {
+        super::decode(&addr.to_string())
+    }
+}
+
+/// The domain of an address, as written.
+fn domain_part(addr: &Address) -> &str {
+    addr.full()
+        .rsplit_once('@')
+        .map_or_else(|| addr.full(), |(_, domain)| domain)
+}
+
+fn encode_address(base: &Address, rcpt: &Address) -> Result<Address, String> {
+    if base.local_part().contains('+') {
+        return Err(format!(
+            "the local part of the VERP address `{base}` must not contain a `+`"
+        ));
+    }
+
+    format!(
+        "{}+{}={}@{}",
+        base.local_part(),
+        rcpt.local_part(),
+        domain_part(rcpt),
+        domain_part(base)
+    )
+    .parse::<Address>()
+    .map_err(|e| format!("failed to encode `{rcpt}` in `{base}`: {e}"))
+}
+
+fn decode_address(addr: &Address) -> Option<Address> {
+    let (_, encoded) = addr.local_part().split_once('+')?;
+    // NOTE: the recipient's local part may contain a `=`, not its domain.
+    let (local_part, domain) = encoded.rsplit_once('=')?;
+
+    format!("{local_part}@{domain}").parse::<Address>().ok()
+}
+
+fn encode(base: &str, rcpt: &str) -> EngineResult<SharedObject> {
+    let base = vsl_conversion_ok!("address", <Address as std::str::FromStr>::from_str(base));
+    let rcpt = vsl_conversion_ok!("address", <Address as std::str::FromStr>::from_str(rcpt));
+
+    encode_address(&base, &rcpt)
+        .map(|addr| std::sync::Arc::new(Object::Address(addr)))
+        .map_err(Into::into)
+}
+
+fn decode(addr: &str) -> EngineResult<Dynamic> {
+    let addr = vsl_conversion_ok!("address", <Address as std::str::FromStr>::from_str(addr));
+
+    Ok(decode_address(&addr).map_or(Dynamic::UNIT, |rcpt| {
+        Dynamic::from(std::sync::Arc::new(Object::Address(rcpt)))
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vsmtp_common::addr;
+
+    #[test]
+    fn round_trip() {
+        let base = addr!("bounce@lists.example.com");
+        for rcpt in [
+            addr!("user@example.com"),
+            addr!("john.doe+news@example.com"),
+            addr!("a=b@sub.example.org"),
+        ] {
+            let verp = encode_address(&base, &rcpt).unwrap();
+            assert_eq!(decode_address(&verp), Some(rcpt));
+        }
+        assert_eq!(
+            encode_address(&base, &addr!("user@example.com")).unwrap(),
+            addr!("bounce+user=example.com@lists.example.com")
+        );
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(
+            encode_address(&addr!("bounce+tag@example.com"), &addr!("user@example.com")).is_err()
+        );
+        assert_eq!(decode_address(&addr!("bounce@lists.example.com")), None);
+        assert_eq!(
+            decode_address(&addr!("bounce+user@lists.example.com")),
+            None
+        );
+    }
+}
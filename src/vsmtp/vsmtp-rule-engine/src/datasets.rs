@@ -0,0 +1,178 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2022 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use anyhow::Context;
+use vsmtp_config::field::{DatasetValue, FieldDataset};
+
+/// Read-only datasets declared in the configuration, exposed to the rules
+/// in the `data` module (`data::<name>`).
+///
+/// The datasets can be reloaded without recompiling the rules, the new values
+/// are swapped atomically and are visible to the [`RuleState`](crate::RuleState)
+/// spawned after the reload. A transaction always sees the same snapshot.
+#[derive(Debug)]
+pub struct Datasets {
+    snapshot: std::sync::RwLock<rhai::Shared<rhai::Module>>,
+}
+
+fn to_dynamic(value: DatasetValue) -> rhai::Dynamic {
+    match value {
+        DatasetValue::Bool(value) => rhai::Dynamic::from_bool(value),
+        DatasetValue::Int(value) => rhai::Dynamic::from_int(value),
+        DatasetValue::String(value) => rhai::Dynamic::from(value),
+        DatasetValue::Array(values) => {
+            rhai::Dynamic::from_array(values.into_iter().map(to_dynamic).collect())
+        }
+    }
+}
+
+fn is_valid_name(name: &str) -> bool {
+    name.chars()
+        .next()
+        .map_or(false, |c| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+impl Datasets {
+    /// Load the datasets.
+    ///
+    /// # Errors
+    ///
+    /// * a dataset name is not a valid identifier
+    /// * a file could not be read, or does not follow the schema
+    pub fn new(
+        datasets: &std::collections::BTreeMap<String, FieldDataset>,
+    ) -> anyhow::Result<Self> {
+        Ok(Self {
+            snapshot: std::sync::RwLock::new(rhai::Shared::new(Self::load(datasets)?)),
+        })
+    }
+
+    fn load(
+        datasets: &std::collections::BTreeMap<String, FieldDataset>,
+    ) -> anyhow::Result<rhai::Module> {
+        let mut module = rhai::Module::new();
+
+        for (name, dataset) in datasets {
+            anyhow::ensure!(
+                is_valid_name(name),
+                "the dataset name '{name}' is not a valid identifier"
+            );
+
+            let values = match dataset {
+                FieldDataset::Inline { values } => values.clone(),
+                FieldDataset::File { path } => {
+                    let content = std::fs::read_to_string(path).with_context(|| {
+                        format!(
+                            "failed to read the dataset '{name}' at '{}'",
+                            path.display()
+                        )
+                    })?;
+                    serde_json::from_str(&content).with_context(|| {
+                        format!(
+                            "the dataset '{name}' at '{}' must be an object of string, integer, boolean or array",
+                            path.display()
+                        )
+                    })?
+                }
+            };
+
+            module.set_var(
+                name.as_str(),
+                values
+                    .into_iter()
+                    .map(|(key, value)| (key.into(), to_dynamic(value)))
+                    .collect::<rhai::Map>(),
+            );
+        }
+
+        Ok(module)
+    }
+
+    /// Get the current values.
+    #[must_use]
+    pub fn snapshot(&self) -> rhai::Shared<rhai::Module> {
+        self.snapshot.read().expect("datasets poisoned").clone()
+    }
+
+    /// Load the datasets again, and swap them with the current ones.
+    ///
+    /// If any dataset fails to load, the current values are kept.
+    ///
+    /// # Errors
+    ///
+    /// * see [`Datasets::new`]
+    pub fn reload(
+        &self,
+        datasets: &std::collections::BTreeMap<String, FieldDataset>,
+    ) -> anyhow::Result<()> {
+        let module = rhai::Shared::new(Self::load(datasets)?);
+        *self.snapshot.write().expect("datasets poisoned") = module;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn from_file(
+        name: &str,
+        path: &std::path::Path,
+    ) -> std::collections::BTreeMap<String, FieldDataset> {
+        [(
+            name.to_string(),
+            FieldDataset::File {
+                path: path.to_path_buf(),
+            },
+        )]
+        .into()
+    }
+
+    #[test]
+    fn schema() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("plans.json");
+
+        std::fs::write(
+            &path,
+            r#"{ "acme": "gold", "seats": 10, "vip": true, "tags": ["a", 1] }"#,
+        )
+        .unwrap();
+        let datasets = Datasets::new(&from_file("plans", &path)).unwrap();
+        let plans = datasets
+            .snapshot()
+            .get_var_value::<rhai::Map>("plans")
+            .unwrap();
+        assert_eq!(plans.get("acme").unwrap().to_string(), "gold");
+        assert_eq!(plans.get("seats").unwrap().as_int().unwrap(), 10);
+        assert!(plans.get("vip").unwrap().as_bool().unwrap());
+
+        for invalid in [r#"{ "acme": 1.5 }"#, r#"{ "acme": null }"#, r#"["acme"]"#] {
+            std::fs::write(&path, invalid).unwrap();
+            assert!(Datasets::new(&from_file("plans", &path)).is_err());
+            assert!(datasets.reload(&from_file("plans", &path)).is_err());
+        }
+        // the previous snapshot is kept.
+        assert!(datasets
+            .snapshot()
+            .get_var_value::<rhai::Map>("plans")
+            .is_some());
+
+        assert!(Datasets::new(&from_file("not valid", &path)).is_err());
+        assert!(Datasets::new(&from_file("plans", &dir.path().join("missing.json"))).is_err());
+    }
+}
@@ -0,0 +1,354 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2023 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use crate::{ExecutionStage, LookupCache};
+use std::sync::atomic::{AtomicBool, Ordering};
+use vsmtp_common::{status::Status, ClientName};
+use vsmtp_config::field::FieldAppVSLDecisionCache;
+
+/// Identify an evaluation of the rules: the client address, the listener it is connected to,
+/// whether the connection is encrypted, and the helo name (the `connect` stage has none).
+pub(crate) type DecisionKey = (
+    std::net::IpAddr,
+    std::net::SocketAddr,
+    bool,
+    Option<ClientName>,
+);
+
+/// Bounded cache of the statuses returned by the `connect` and `helo` stages,
+/// per client address, listener, TLS state and helo name.
+///
+/// Only the evaluations which ran rules without side effects are cached:
+/// an `action` or a rule calling `cache::skip()` disables the caching of the stage.
+#[derive(Debug)]
+pub struct DecisionCache {
+    capacity: usize,
+    ttl: std::time::Duration,
+    entries: std::sync::Mutex<Entries>,
+}
+
+/// The decisions, indexed by their expiration to evict the oldest one in `O(log n)`.
+#[derive(Debug, Default)]
+struct Entries {
+    /// The status of each key, with its expiration and its sequence number.
+    decisions: std::collections::HashMap<DecisionKey, (time::OffsetDateTime, u64, Status)>,
+    /// The keys ordered by expiration, the sequence number breaking the ties.
+    by_expiration: std::collections::BTreeMap<(time::OffsetDateTime, u64), DecisionKey>,
+    sequence: u64,
+}
+
+impl Entries {
+    fn remove(&mut self, key: &DecisionKey) {
+        if let Some((expiration, sequence, _)) = self.decisions.remove(key) {
+            self.by_expiration.remove(&(expiration, sequence));
+        }
+    }
+
+    /// Remove the decision expiring first, and the other expired ones.
+    fn evict(&mut self, now: time::OffsetDateTime) {
+        let mut oldest = true;
+        while let Some(entry) = self.by_expiration.first_entry() {
+            if !oldest && entry.key().0 > now {
+                break;
+            }
+            oldest = false;
+            let key = entry.remove();
+            self.decisions.remove(&key);
+        }
+    }
+
+    fn insert(&mut self, key: DecisionKey, expiration: time::OffsetDateTime, status: Status) {
+        self.sequence += 1;
+        self.by_expiration
+            .insert((expiration, self.sequence), key.clone());
+        self.decisions
+            .insert(key, (expiration, self.sequence, status));
+    }
+}
+
+impl DecisionCache {
+    /// Create an empty cache.
+    #[must_use]
+    pub fn new(config: &FieldAppVSLDecisionCache) -> Self {
+        Self {
+            capacity: config.capacity,
+            ttl: config.ttl,
+            entries: std::sync::Mutex::new(Entries::default()),
+        }
+    }
+
+    /// Build the key of the evaluation of `stage`, [`None`] if the stage is not cached.
+    pub(crate) fn key(
+        context: &vsmtp_common::Context,
+        stage: ExecutionStage,
+    ) -> Option<DecisionKey> {
+        let (ip, server_addr, secured) = (
+            context.client_addr().ip(),
+            *context.server_addr(),
+            context.is_secured(),
+        );
+        match stage {
+            ExecutionStage::Connect => Some((ip, server_addr, secured, None)),
+            ExecutionStage::Helo => context
+                .client_name()
+                .ok()
+                .map(|client_name| (ip, server_addr, secured, Some(client_name.clone()))),
+            _ => None,
+        }
+    }
+
+    /// Get the status cached for `key`, if it has not expired.
+    pub(crate) fn get(&self, key: &DecisionKey) -> Option<Status> {
+        let mut entries = self.entries.lock().expect("Mutex poisoned");
+
+        match entries.decisions.get(key) {
+            Some((expiration, _, status)) if *expiration > vsmtp_common::clock::now() => {
+                Some(status.clone())
+            }
+            Some(_) => {
+                entries.remove(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// Cache `status` for `key`. The delegations and quarantines are never cached.
+    pub(crate) fn insert(&self, key: DecisionKey, status: &Status) {
+        if !matches!(
+            status,
+            Status::Next
+                | Status::Accept(_)
+                | Status::Faccept(_)
+                | Status::Reject(_)
+                | Status::Deny(_)
+        ) || self.capacity == 0
+        {
+            return;
+        }
+
+        let now = vsmtp_common::clock::now();
+        let mut entries = self.entries.lock().expect("Mutex poisoned");
+
+        if entries.decisions.contains_key(&key) {
+            entries.remove(&key);
+        } else if entries.decisions.len() >= self.capacity {
+            entries.evict(now);
+        }
+
+        entries.insert(key, now + self.ttl, status.clone());
+    }
+
+    /// Number of decisions in the cache, including the expired ones not removed yet.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.entries.lock().expect("Mutex poisoned").decisions.len()
+    }
+
+    /// Remove all the decisions, taken with rules or datasets which have been reloaded.
+    pub fn clear(&self) {
+        let mut entries = self.entries.lock().expect("Mutex poisoned");
+        entries.decisions.clear();
+        entries.by_expiration.clear();
+    }
+
+    /// Is the cache empty ?
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Build the `cache` module of vsl, clearing `cacheable` when `cache::skip()` is called,
+    /// with the functions of the shared `lookups`.
+    #[must_use]
+    pub fn module(
+        cacheable: std::sync::Arc<AtomicBool>,
+        lookups: &std::sync::Arc<LookupCache>,
+    ) -> rhai::Shared<rhai::Module> {
+        let mut module = rhai::Module::new();
+        lookups.register(&mut module);
+
+        module.set_native_fn("skip", move || -> Result<(), Box<rhai::EvalAltResult>> {
+            cacheable.store(false, Ordering::Relaxed);
+            Ok(())
+        });
+
+        rhai::Shared::new(module)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cache(capacity: usize, ttl: std::time::Duration) -> DecisionCache {
+        DecisionCache::new(&FieldAppVSLDecisionCache { capacity, ttl })
+    }
+
+    fn key(ip: &str, helo: Option<&str>) -> DecisionKey {
+        (
+            ip.parse().unwrap(),
+            "127.0.0.1:25".parse().unwrap(),
+            false,
+            helo.map(|helo| ClientName::Domain(helo.parse().unwrap())),
+        )
+    }
+
+    fn deny() -> Status {
+        Status::Deny("554 5.7.1 go away\r\n".parse().unwrap())
+    }
+
+    #[test]
+    fn keys() {
+        let mut context = vsmtp_common::Context::new(
+            "192.168.1.1:25".parse().unwrap(),
+            "127.0.0.1:25".parse().unwrap(),
+            "testserver.com".parse().unwrap(),
+            time::OffsetDateTime::now_utc(),
+            uuid::Uuid::new_v4(),
+        );
+
+        assert_eq!(
+            DecisionCache::key(&context, ExecutionStage::Connect),
+            Some(key("192.168.1.1", None))
+        );
+        assert_eq!(DecisionCache::key(&context, ExecutionStage::Helo), None);
+
+        context
+            .to_helo(ClientName::Domain("client.com".parse().unwrap()), false)
+            .unwrap();
+        assert_eq!(
+            DecisionCache::key(&context, ExecutionStage::Helo),
+            Some(key("192.168.1.1", Some("client.com")))
+        );
+        assert_eq!(DecisionCache::key(&context, ExecutionStage::MailFrom), None);
+    }
+
+    #[test]
+    fn keys_per_listener() {
+        let context = |server_addr: &str| {
+            vsmtp_common::Context::new(
+                "192.168.1.1:1234".parse().unwrap(),
+                server_addr.parse().unwrap(),
+                "testserver.com".parse().unwrap(),
+                time::OffsetDateTime::now_utc(),
+                uuid::Uuid::new_v4(),
+            )
+        };
+        let cache = cache(10, std::time::Duration::from_secs(60));
+
+        let relay = DecisionCache::key(&context("127.0.0.1:25"), ExecutionStage::Connect).unwrap();
+        let submission =
+            DecisionCache::key(&context("127.0.0.1:587"), ExecutionStage::Connect).unwrap();
+        assert_ne!(relay, submission);
+
+        cache.insert(relay.clone(), &deny());
+        cache.insert(submission.clone(), &Status::Next);
+        assert_eq!(cache.get(&relay), Some(deny()));
+        assert_eq!(cache.get(&submission), Some(Status::Next));
+
+        // the decision taken before the STARTTLS is not replayed after it.
+        let (ip, server_addr, _, helo) = relay;
+        assert_eq!(cache.get(&(ip, server_addr, true, helo)), None);
+    }
+
+    #[test]
+    fn get_and_insert() {
+        let cache = cache(10, std::time::Duration::from_secs(60));
+
+        assert_eq!(cache.get(&key("10.0.0.1", None)), None);
+        cache.insert(key("10.0.0.1", None), &deny());
+        cache.insert(key("10.0.0.1", Some("client.com")), &Status::Next);
+
+        assert_eq!(cache.get(&key("10.0.0.1", None)), Some(deny()));
+        assert_eq!(
+            cache.get(&key("10.0.0.1", Some("client.com"))),
+            Some(Status::Next)
+        );
+        assert_eq!(cache.get(&key("10.0.0.1", Some("other.com"))), None);
+        assert_eq!(cache.get(&key("10.0.0.2", None)), None);
+    }
+
+    #[test]
+    fn not_cached() {
+        let cache = cache(10, std::time::Duration::from_secs(60));
+
+        cache.insert(
+            key("10.0.0.1", None),
+            &Status::Quarantine("quarantine".to_string()),
+        );
+        cache.insert(key("10.0.0.2", None), &Status::DelegationResult);
+        assert!(cache.is_empty());
+
+        let cache = self::cache(0, std::time::Duration::from_secs(60));
+        cache.insert(key("10.0.0.1", None), &deny());
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn expiration() {
+        let clock = vsmtp_test::clock::TestClock::start();
+        let cache = cache(10, std::time::Duration::from_secs(60));
+
+        cache.insert(key("10.0.0.1", None), &deny());
+        clock.advance(time::Duration::seconds(59));
+        assert_eq!(cache.get(&key("10.0.0.1", None)), Some(deny()));
+
+        clock.advance(time::Duration::seconds(1));
+        assert_eq!(cache.get(&key("10.0.0.1", None)), None);
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn bounded() {
+        let clock = vsmtp_test::clock::TestClock::start();
+        let cache = cache(2, std::time::Duration::from_secs(60));
+
+        cache.insert(key("10.0.0.1", None), &deny());
+        clock.advance(time::Duration::seconds(1));
+        cache.insert(key("10.0.0.2", None), &deny());
+        clock.advance(time::Duration::seconds(1));
+        cache.insert(key("10.0.0.3", None), &deny());
+
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get(&key("10.0.0.1", None)), None);
+        assert_eq!(cache.get(&key("10.0.0.3", None)), Some(deny()));
+
+        // updating an entry never evicts another one.
+        cache.insert(key("10.0.0.3", None), &Status::Next);
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get(&key("10.0.0.2", None)), Some(deny()));
+    }
+
+    #[test]
+    fn bounded_expired() {
+        let clock = vsmtp_test::clock::TestClock::start();
+        let cache = cache(3, std::time::Duration::from_secs(60));
+
+        cache.insert(key("10.0.0.1", None), &deny());
+        clock.advance(time::Duration::seconds(1));
+        cache.insert(key("10.0.0.2", None), &deny());
+        clock.advance(time::Duration::seconds(29));
+        cache.insert(key("10.0.0.3", None), &deny());
+        clock.advance(time::Duration::seconds(31));
+
+        // the expired decisions are evicted, the others are kept.
+        cache.insert(key("10.0.0.4", None), &deny());
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get(&key("10.0.0.3", None)), Some(deny()));
+        assert_eq!(cache.get(&key("10.0.0.4", None)), Some(deny()));
+    }
+}
@@ -16,7 +16,8 @@
 */
 
 use crate::{
-    api::state::deny, dsl::directives::Directives, Directive, ExecutionStage, RuleEngine, RuleState,
+    api::state::deny, dsl::directives::Directives, Directive, ExecutionStage, RuleEngine,
+    RuleState, RuleStatistics,
 };
 use vsmtp_common::status::Status;
 use vsmtp_common::{domain_iter, Domain};
@@ -40,6 +41,8 @@ pub struct SubDomainHierarchy {
     pub(super) fallback: Script,
     pub(super) default_values: DomainDirectives,
     pub(super) domains: std::collections::BTreeMap<Domain, DomainDirectives>,
+    /// Domains whose scripts failed to compile, with the error, running the fallback rules.
+    pub(super) errors: std::collections::BTreeMap<Domain, String>,
 }
 
 // NOTE: using a macro to avoid code duplication and fatal typo.
@@ -71,10 +74,26 @@ impl SubDomainHierarchy {
         self.domains.values()
     }
 
+    /// Domains whose scripts failed to compile, and the reason.
+    ///
+    /// The transactions of these domains run the fallback rules, the other domains are unaffected.
+    #[must_use]
+    pub const fn errors(&self) -> &std::collections::BTreeMap<Domain, String> {
+        &self.errors
+    }
+
     fn_get_script!(incoming);
     fn_get_script!(outgoing);
     fn_get_script!(internal);
 
+    /// Every script of the hierarchy, including the default ones.
+    pub(crate) fn scripts(&self) -> impl Iterator<Item = &Script> {
+        [&self.root_filter, &self.fallback]
+            .into_iter()
+            .chain(self.default_values.scripts())
+            .chain(self.domains.values().flat_map(DomainDirectives::scripts))
+    }
+
     /// Return the directives for the given domain **or any parent domain**.
     pub(crate) fn get_any(&self, domain: &Domain) -> Option<&DomainDirectives> {
         let domain_str = domain.to_string();
@@ -93,6 +112,12 @@ pub struct DomainDirectives {
 }
 
 impl DomainDirectives {
+    fn scripts(&self) -> impl Iterator<Item = &Script> {
+        [&self.incoming, &self.outgoing, &self.internal]
+            .into_iter()
+            .flatten()
+    }
+
     #[tracing::instrument(skip(engine, domain_dir), err)]
     fn new(engine: &rhai::Engine, domain_dir: &std::path::Path) -> anyhow::Result<Self> {
         Ok(Self {
@@ -102,6 +127,21 @@ impl DomainDirectives {
         })
     }
 
+    /// Directives of a domain which failed to load, denying its transactions.
+    fn fallback(engine: &rhai::Engine) -> Self {
+        let fallback = || {
+            Some(
+                Script::compile_source(engine, DEFAULT_FALLBACK_RULES)
+                    .expect(PANIC_MSG_INVALID_DEFAULT),
+            )
+        };
+        Self {
+            incoming: fallback(),
+            outgoing: fallback(),
+            internal: fallback(),
+        }
+    }
+
     fn default_value(engine: &rhai::Engine) -> Self {
         Self {
             incoming: Some(
@@ -139,15 +179,27 @@ impl Script {
         self.directives.values().flatten()
     }
 
+    pub(crate) fn staged_directives(&self) -> impl Iterator<Item = (ExecutionStage, &Directive)> {
+        self.directives
+            .iter()
+            .flat_map(|(stage, directives)| directives.iter().map(move |d| (*stage, d)))
+    }
+
     pub(crate) fn execute(
         rule_state: &RuleState,
         ast: &rhai::AST,
         directives: &[Directive],
         smtp_state: ExecutionStage,
+        statistics: &RuleStatistics,
     ) -> Status {
         let mut status = Status::Next;
 
         for directive in directives {
+            // only the rules can be free of side effects.
+            if !matches!(directive, Directive::Rule { .. }) {
+                rule_state.set_cacheable(false);
+            }
+
             status = directive
                 .execute(rule_state, ast, smtp_state)
                 .unwrap_or_else(|e| {
@@ -155,8 +207,10 @@ impl Script {
                     tracing::warn!(%e, "error while executing directive returning: {:?}", error_status);
                     error_status
                 });
+            statistics.record(smtp_state, directive.name(), &status);
 
             if status != Status::Next {
+                rule_state.emit_policy_event(smtp_state, Some(directive.name()), &status);
                 break;
             }
         }
@@ -194,9 +248,15 @@ impl Script {
 impl SubDomainHierarchy {
     /// Create a new hierarchy of rules using the rhai engine.
     ///
+    /// The rules of a domain are read from `<domain_dir>/<domain>/{incoming,outgoing,internal}.vsl`,
+    /// for the domains of `server.virtual` and every sub-directory of `domain_dir` named after a domain.
+    /// A missing script is replaced by the default rules, and a domain whose scripts failed
+    /// to compile runs the fallback rules, see [`SubDomainHierarchy::errors`].
+    ///
     /// # Errors
     ///
-    /// * Failed to compile scripts.
+    /// * Failed to compile the root filter.
+    /// * Failed to read `domain_dir`.
     #[tracing::instrument(skip(engine, config, domains), err)]
     pub fn new(
         engine: &rhai::Engine,
@@ -207,9 +267,15 @@ impl SubDomainHierarchy {
             FieldAppVSL {
                 filter_path: Some(filter_path),
                 domain_dir,
+                ..
             } => {
                 tracing::info!("Analyzing vSL rules at {}", filter_path.display());
 
+                let (domains, errors) = domain_dir.as_ref().map_or_else(
+                    || Ok(Default::default()),
+                    |domain_dir| Self::load_domains(engine, domain_dir, domains),
+                )?;
+
                 Ok(Self {
                     root_filter: Script::compile_file(engine, filter_path)?.unwrap_or_else(|| {
                         Script::compile_source(engine, DEFAULT_ROOT_FILTERING_RULES)
@@ -218,29 +284,8 @@ impl SubDomainHierarchy {
                     fallback: Script::compile_source(engine, DEFAULT_FALLBACK_RULES)
                         .expect(PANIC_MSG_INVALID_DEFAULT),
                     default_values: DomainDirectives::default_value(engine),
-                    domains: if let Some(domain_dir) = domain_dir {
-                        tracing::info!(
-                            "Expecting '{}/**/{{incoming,outgoing,internal}}.vsl'",
-                            domain_dir.display()
-                        );
-
-                        Some(
-                            domains
-                                .keys()
-                                .map(|domain| {
-                                    tracing::info!(?domain, "loading domain rules...");
-                                    DomainDirectives::new(
-                                        engine,
-                                        &domain_dir.join(domain.to_string()),
-                                    )
-                                    .map(|d| (domain.clone(), d))
-                                })
-                                .collect::<Result<std::collections::BTreeMap<_, _>, _>>()?,
-                        )
-                    } else {
-                        None
-                    }
-                    .unwrap_or_default(),
+                    domains,
+                    errors,
                 })
             }
             FieldAppVSL {
@@ -254,6 +299,73 @@ impl SubDomainHierarchy {
         }
     }
 
+    #[allow(clippy::type_complexity)]
+    fn load_domains(
+        engine: &rhai::Engine,
+        domain_dir: &std::path::Path,
+        domains: &std::collections::BTreeMap<Domain, FieldServerVirtual>,
+    ) -> anyhow::Result<(
+        std::collections::BTreeMap<Domain, DomainDirectives>,
+        std::collections::BTreeMap<Domain, String>,
+    )> {
+        tracing::info!(
+            "Expecting '{}/**/{{incoming,outgoing,internal}}.vsl'",
+            domain_dir.display()
+        );
+
+        let mut names = domains
+            .keys()
+            .cloned()
+            .collect::<std::collections::BTreeSet<_>>();
+        match std::fs::read_dir(domain_dir) {
+            Ok(entries) => {
+                for entry in entries {
+                    let entry = entry?;
+                    if !entry.file_type()?.is_dir() {
+                        continue;
+                    }
+                    // NOTE: the directories without a dot, like the modules imported
+                    //       by the scripts, are not domains.
+                    if let Some(Ok(domain)) = entry
+                        .file_name()
+                        .to_str()
+                        .filter(|name| name.contains('.'))
+                        .map(<Domain as std::str::FromStr>::from_str)
+                    {
+                        names.insert(domain);
+                    }
+                }
+            }
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+                tracing::warn!("domain directory not found, using default rules instead");
+            }
+            Err(error) => {
+                return Err(anyhow::Error::new(error).context(format!(
+                    "Cannot read domain directory in '{}'",
+                    domain_dir.display()
+                )))
+            }
+        }
+
+        let mut directives = std::collections::BTreeMap::new();
+        let mut errors = std::collections::BTreeMap::new();
+        for domain in names {
+            tracing::info!(%domain, "loading domain rules...");
+            match DomainDirectives::new(engine, &domain_dir.join(domain.to_string())) {
+                Ok(d) => {
+                    directives.insert(domain, d);
+                }
+                Err(error) => {
+                    tracing::error!(%domain, %error, "failed to load the domain rules, its transactions will be denied");
+                    directives.insert(domain.clone(), DomainDirectives::fallback(engine));
+                    errors.insert(domain, format!("{error:#}"));
+                }
+            }
+        }
+
+        Ok((directives, errors))
+    }
+
     pub(super) fn new_empty(engine: &rhai::Engine) -> Self {
         Self {
             root_filter: Script::compile_source(engine, DEFAULT_ROOT_FILTERING_RULES)
@@ -262,6 +374,7 @@ impl SubDomainHierarchy {
                 .expect(PANIC_MSG_INVALID_DEFAULT),
             default_values: DomainDirectives::default_value(engine),
             domains: std::collections::BTreeMap::new(),
+            errors: std::collections::BTreeMap::new(),
         }
     }
 }
@@ -117,6 +117,17 @@ pub mod cmd {
     /// // run the command with custom arguments (based one are replaced).
     /// // echo -n 'Hello World.'
     /// echo.run([ "-n", "'Hello World.'" ]);
+    ///
+    /// // run the command with its arguments, the `{name}` placeholders being replaced
+    /// // by the values of the map, `()` by an empty string.
+    /// const deliver = cmd::build(#{
+    ///     command: "/usr/bin/procmail",
+    ///     args: ["-a", "{extension}"],
+    ///     timeout: "10s",
+    /// });
+    ///
+    /// // procmail -a lists, for `john+lists@example.com`.
+    /// deliver.run(#{ extension: ctx::rcpt_extension(ctx::rcpt()) });
     /// ```
     ///
     /// # rhai-autodocs:index:2
@@ -142,4 +153,27 @@ pub mod cmd {
             .map(crate::dsl::cmd::service::Cmd::status_to_map)
             .map_err::<Box<rhai::EvalAltResult>, _>(|e| e.to_string().into())
     }
+
+    #[doc(hidden)]
+    #[rhai_fn(global, name = "run", return_raw, pure)]
+    pub fn run_with_placeholders(
+        cmd: &mut Cmd,
+        placeholders: rhai::Map,
+    ) -> EngineResult<rhai::Map> {
+        let placeholders = placeholders
+            .into_iter()
+            .map(|(name, value)| {
+                let value = if value.is_unit() {
+                    String::new()
+                } else {
+                    value.to_string()
+                };
+                (name.to_string(), value)
+            })
+            .collect();
+
+        cmd.run_with_placeholders(&placeholders)
+            .map(crate::dsl::cmd::service::Cmd::status_to_map)
+            .map_err::<Box<rhai::EvalAltResult>, _>(|e| e.to_string().into())
+    }
 }
@@ -33,6 +33,7 @@ mod tests {
     use vsmtp_config::DnsResolvers;
     use vsmtp_test::config::local_test;
 
+    use super::service;
     use crate::RuleEngine;
 
     const VSL: &str = r#"
@@ -88,4 +89,28 @@ const my_command = cmd::build(#{
         )
         .unwrap();
     }
+
+    #[test]
+    fn placeholders() {
+        let cmd = service::Cmd {
+            timeout: std::time::Duration::from_secs(15),
+            user: None,
+            group: None,
+            command: "true".to_owned(),
+            args: None,
+        };
+        let placeholders = std::collections::BTreeMap::from([
+            ("extension".to_owned(), "{rcpt}".to_owned()),
+            ("rcpt".to_owned(), "john@example.com".to_owned()),
+        ]);
+        let replace = |arg: &str| service::replace_placeholders(arg, &placeholders);
+
+        assert_eq!(replace("-a{extension}"), "-a{rcpt}");
+        assert_eq!(
+            replace("{rcpt} {unknown} {"),
+            "john@example.com {unknown} {"
+        );
+        assert_eq!(replace("{{extension}}"), "{{rcpt}}");
+        assert!(cmd.run_with_placeholders(&placeholders).unwrap().success());
+    }
 }
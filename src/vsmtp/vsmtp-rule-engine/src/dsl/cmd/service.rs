@@ -15,6 +15,38 @@
  *
 */
 
+/// Replace the `{name}` placeholders of `arg` in a single pass, the values are not
+/// scanned again and the unknown names are left as is.
+pub(super) fn replace_placeholders(
+    arg: &str,
+    placeholders: &std::collections::BTreeMap<String, String>,
+) -> String {
+    let mut out = String::with_capacity(arg.len());
+    let mut rest = arg;
+
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        match rest
+            .find('}')
+            .and_then(|end| Some((end, placeholders.get(&rest[1..end])?)))
+        {
+            Some((end, value)) => {
+                out.push_str(value);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                out.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+
+    out
+}
+
 #[derive(Debug, Clone)]
 /// A service that executes commands.
 pub struct Cmd {
@@ -61,6 +93,29 @@ impl Cmd {
         self.run_inner(args)
     }
 
+    /// run a cmd service with it's internal arguments, the `{name}` placeholders
+    /// being replaced by the values of `placeholders` (`{extension}` for instance).
+    ///
+    /// # Errors
+    ///
+    /// * if the user used to launch commands is not found.
+    /// * if the group used to launch commands is not found.
+    /// * if the cmd service failed to spawn.
+    /// * if the cmd returned an error.
+    pub fn run_with_placeholders(
+        &self,
+        placeholders: &std::collections::BTreeMap<String, String>,
+    ) -> anyhow::Result<std::process::ExitStatus> {
+        let args = self
+            .args
+            .iter()
+            .flatten()
+            .map(|arg| replace_placeholders(arg, placeholders))
+            .collect::<Vec<_>>();
+
+        self.run_inner(&args)
+    }
+
     /// # Errors
     ///
     /// * if the user used to launch commands is not found.
@@ -15,6 +15,7 @@
  *
 */
 use crate::{api::EngineResult, error::CompilationError};
+use vsmtp_mail_parser::MessageBody;
 
 pub fn create(
     context: &mut rhai::EvalContext<'_, '_, '_, '_, '_, '_>,
@@ -60,3 +61,286 @@ pub fn create(
         .collect::<rhai::Map>(),
     ))
 }
+
+/// Header marking a message whose body has been truncated before its delegation.
+pub const TRUNCATED_HEADER: &str = "X-Delegation-Truncated";
+
+/// The part of the message sent to the service of a delegation, set by the `send`
+/// property of the directive.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Payload {
+    /// `"full"`: the whole message, replaced by the message returned by the service.
+    #[default]
+    Full,
+    /// `"headers"`: the header section only.
+    Headers,
+    /// `"truncate:<bytes>"`: the header section and at most `bytes` of the body,
+    /// cut at the end of a line.
+    Truncate(usize),
+}
+
+impl std::str::FromStr for Payload {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None if s == "full" => Ok(Self::Full),
+            None if s == "headers" => Ok(Self::Headers),
+            Some(("truncate", bytes)) => Ok(Self::Truncate(bytes.parse().map_err(|_| {
+                anyhow::anyhow!("'{bytes}' is not a valid number of bytes to send")
+            })?)),
+            _ => anyhow::bail!(
+                "'{s}' is not a valid payload, expected \"full\", \"headers\" or \"truncate:<bytes>\""
+            ),
+        }
+    }
+}
+
+impl std::fmt::Display for Payload {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Full => f.write_str("full"),
+            Self::Headers => f.write_str("headers"),
+            Self::Truncate(bytes) => write!(f, "truncate:{bytes}"),
+        }
+    }
+}
+
+/// Split the lines of a header section into fields, with their lowercase name.
+fn fields(lines: &[String]) -> Vec<(String, Vec<String>)> {
+    let mut fields = Vec::<(String, Vec<String>)>::new();
+    for line in lines {
+        match fields.last_mut() {
+            Some((_, field)) if line.starts_with(' ') || line.starts_with('\t') => {
+                field.push(line.clone());
+            }
+            _ => fields.push((
+                line.split(':')
+                    .next()
+                    .unwrap_or_default()
+                    .trim()
+                    .to_lowercase(),
+                vec![line.clone()],
+            )),
+        }
+    }
+    fields
+}
+
+impl Payload {
+    /// The payload of a message sent to a service, read in its `X-VSMTP-DELEGATION` header.
+    #[must_use]
+    pub fn of_message(message: &MessageBody) -> Self {
+        message
+            .get_header("X-VSMTP-DELEGATION")
+            .and_then(|header| {
+                vsmtp_mail_parser::get_mime_header("X-VSMTP-DELEGATION", &header)
+                    .args
+                    .get("send")
+                    .and_then(|send| send.parse().ok())
+            })
+            .unwrap_or_default()
+    }
+
+    /// The message sent to the service.
+    #[must_use]
+    pub fn reduce(self, message: &MessageBody) -> MessageBody {
+        let headers = message.inner().raw_headers().clone();
+        let body = message.inner().body().as_deref().unwrap_or_default();
+
+        match self {
+            Self::Full => message.clone(),
+            Self::Headers => MessageBody::new(headers, String::new()),
+            Self::Truncate(bytes) if body.len() <= bytes => message.clone(),
+            Self::Truncate(bytes) => {
+                let end = body.as_bytes()[..bytes]
+                    .iter()
+                    .rposition(|byte| *byte == b'\n')
+                    .map_or(0, |position| position + 1);
+
+                let mut reduced = MessageBody::new(headers, body[..end].to_string());
+                reduced.prepend_header(TRUNCATED_HEADER, &body.len().to_string());
+                reduced
+            }
+        }
+    }
+
+    /// The message to store once the service returned `result` for `original`.
+    ///
+    /// Unless the whole message was sent, only the headers modified by the service are
+    /// applied to the original message: the fields whose values changed replace the
+    /// original ones, the new fields are added on top. A modification of the body is
+    /// rejected.
+    #[must_use]
+    pub fn merge(self, original: &MessageBody, result: &MessageBody) -> MessageBody {
+        if self == Self::Full {
+            return result.clone();
+        }
+
+        let sent = self.reduce(original);
+        if result.inner().body().as_deref().unwrap_or_default()
+            != sent.inner().body().as_deref().unwrap_or_default()
+        {
+            tracing::warn!(
+                payload = %self,
+                "The delegation modified the body of the message, the modification is rejected."
+            );
+        }
+
+        let original_fields = fields(original.inner().raw_headers());
+        let result_fields = fields(result.inner().raw_headers())
+            .into_iter()
+            .filter(|(name, _)| !name.eq_ignore_ascii_case(TRUNCATED_HEADER))
+            .collect::<Vec<_>>();
+
+        let added = result_fields
+            .iter()
+            .filter(|(name, _)| !original_fields.iter().any(|(other, _)| other == name))
+            .flat_map(|(_, lines)| lines.iter().cloned());
+
+        let mut merged = added.collect::<Vec<_>>();
+        let mut replaced = std::collections::HashSet::new();
+        for (name, lines) in &original_fields {
+            if !result_fields.iter().any(|(other, _)| other == name) {
+                merged.extend(lines.iter().cloned());
+            } else if replaced.insert(name.as_str()) {
+                merged.extend(
+                    result_fields
+                        .iter()
+                        .filter(|(other, _)| other == name)
+                        .flat_map(|(_, lines)| lines.iter().cloned()),
+                );
+            }
+        }
+
+        MessageBody::new(
+            merged,
+            original
+                .inner()
+                .body()
+                .as_deref()
+                .unwrap_or_default()
+                .to_string(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ORIGINAL: &str = concat!(
+        "X-VSMTP-DELEGATION: sent; stage=postq; directive=\"filter\"; id=\"0\"; send=\"truncate:40\"\r\n",
+        "From: john@doe.com\r\n",
+        "Subject: hello\r\n",
+        "X-Spam-Score: 0\r\n",
+        "\r\n",
+        "first line of the body\r\n",
+        "second line of the body\r\n",
+        "third line of the body\r\n",
+    );
+
+    /// A filter adding a header, changing the score and the subject, and rewriting the body.
+    fn filter(received: &MessageBody) -> MessageBody {
+        let mut result = received.clone();
+        result.prepend_header("X-Virus-Scanned", "clean");
+        result.set_header("X-Spam-Score", "5");
+        result.set_header("Subject", "[SPAM] hello");
+        MessageBody::new(
+            result.inner().raw_headers().clone(),
+            "rewritten by the filter\r\n".to_string(),
+        )
+    }
+
+    #[test]
+    fn parse() {
+        for (input, expected) in [
+            ("full", Payload::Full),
+            ("headers", Payload::Headers),
+            ("truncate:65536", Payload::Truncate(65536)),
+        ] {
+            assert_eq!(input.parse::<Payload>().unwrap(), expected);
+            assert_eq!(expected.to_string(), input);
+        }
+
+        for invalid in [
+            "",
+            "body",
+            "truncate",
+            "truncate:",
+            "truncate:-1",
+            "headers:1",
+        ] {
+            assert!(invalid.parse::<Payload>().is_err(), "{invalid}");
+        }
+
+        let original = MessageBody::try_from(ORIGINAL).unwrap();
+        assert_eq!(Payload::of_message(&original), Payload::Truncate(40));
+        assert_eq!(
+            Payload::of_message(&MessageBody::try_from("From: a@b.c\r\n\r\n").unwrap()),
+            Payload::Full
+        );
+    }
+
+    #[test]
+    fn reduce() {
+        let original = MessageBody::try_from(ORIGINAL).unwrap();
+        let header_size = original.header_size();
+
+        assert_eq!(Payload::Full.reduce(&original), original);
+
+        let headers = Payload::Headers.reduce(&original);
+        assert_eq!(headers.size(), header_size + "\r\n".len());
+        assert_eq!(headers.inner().body().as_deref(), Some(""));
+
+        // cut at the end of the line before the limit.
+        let truncated = Payload::Truncate(40).reduce(&original);
+        assert_eq!(
+            truncated.inner().body().as_deref(),
+            Some("first line of the body\r\n")
+        );
+        assert_eq!(
+            truncated.get_header(TRUNCATED_HEADER),
+            original
+                .inner()
+                .body()
+                .as_ref()
+                .map(|body| body.len().to_string())
+        );
+        assert!(truncated.size() < original.size());
+
+        // no line fits.
+        let truncated = Payload::Truncate(10).reduce(&original);
+        assert_eq!(truncated.inner().body().as_deref(), Some(""));
+
+        // the body fits, the message is sent as is.
+        assert_eq!(Payload::Truncate(1000).reduce(&original), original);
+    }
+
+    #[test]
+    fn merge() {
+        let original = MessageBody::try_from(ORIGINAL).unwrap();
+
+        for payload in [Payload::Headers, Payload::Truncate(40)] {
+            let result = filter(&payload.reduce(&original));
+            let merged = payload.merge(&original, &result);
+
+            assert_eq!(merged.inner().body(), original.inner().body(), "{payload}");
+            assert_eq!(merged.get_header(TRUNCATED_HEADER), None);
+            assert_eq!(
+                merged.get_header("X-Virus-Scanned").as_deref(),
+                Some("clean")
+            );
+            assert_eq!(merged.get_header("X-Spam-Score").as_deref(), Some("5"));
+            assert_eq!(
+                merged.get_header("Subject").as_deref(),
+                Some("[SPAM] hello")
+            );
+            assert_eq!(merged.get_header("From").as_deref(), Some("john@doe.com"));
+            assert_eq!(merged.count_header("X-Spam-Score"), 1);
+        }
+
+        let result = filter(&original);
+        assert_eq!(Payload::Full.merge(&original, &result), result);
+    }
+}
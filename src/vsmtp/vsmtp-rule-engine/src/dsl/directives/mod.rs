@@ -54,6 +54,8 @@ pub enum Directive {
         pointer: rhai::FnPtr,
         ///
         service: std::sync::Arc<crate::dsl::smtp::service::Smtp>,
+        /// the part of the message sent to the service.
+        payload: delegation::Payload,
     },
 }
 
@@ -154,6 +156,7 @@ impl Directive {
                 pointer,
                 service,
                 name,
+                payload,
             } => {
                 let args = vsl_guard_ok!(rule_state.message().read())
                     .get_header("X-VSMTP-DELEGATION")
@@ -179,10 +182,21 @@ impl Directive {
                         vsl_guard_ok!(rule_state.message().write()).prepend_header(
                             "X-VSMTP-DELEGATION",
                             &format!(
-                                "sent; stage={stage}; directive=\"{name}\"; id=\"{}\"",
+                                "sent; stage={stage}; directive=\"{name}\"; id=\"{}\"{}{}",
                                 vsl_guard_ok!(rule_state.context().read())
                                     .message_uuid()
-                                    .unwrap()
+                                    .unwrap(),
+                                // the span of the message continues once the service sends it back.
+                                tracing::Span::current().id().map_or_else(
+                                    String::new,
+                                    |span| format!("; span=\"{}\"", span.into_u64())
+                                ),
+                                // the payload is only recorded when the message is reduced.
+                                if *payload == delegation::Payload::Full {
+                                    String::new()
+                                } else {
+                                    format!("; send=\"{payload}\"")
+                                }
                             ),
                         );
 
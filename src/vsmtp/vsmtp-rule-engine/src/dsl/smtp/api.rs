@@ -102,6 +102,21 @@ pub mod smtp {
     /// }
     /// ```
     ///
+    /// The `send` property of a delegation declared with a map reduces the message sent
+    /// to the service: `"full"` (the default), `"headers"` or `"truncate:<bytes>"`, the
+    /// body being cut at the end of a line and marked by a `X-Delegation-Truncated`
+    /// header. Only the headers added or changed by the service are then applied to
+    /// the message, a modification of the body is rejected.
+    ///
+    /// ```text
+    /// delegate srv::classifier "classify" #{
+    ///     send: "truncate:65536",
+    ///     evaluate: || {
+    ///         // ...
+    ///     },
+    /// }
+    /// ```
+    ///
     /// # rhai-autodocs:index:1
     #[rhai_fn(return_raw)]
     pub fn connect(parameters: rhai::Map) -> EngineResult<Smtp> {
@@ -159,9 +159,9 @@ macro_rules! vsl_guard_ok {
 }
 
 macro_rules! vsl_parse_ok {
-    ($message:expr) => {{
+    ($message:expr, $limits:expr) => {{
         $message
-            .parsed::<vsmtp_mail_parser::MailMimeParser>()
+            .parsed_with(|| vsmtp_mail_parser::MailMimeParser::with_limits($limits))
             .map_err(|source| $crate::error::RuntimeError::ParseMessageBody { source })?
     }};
 }
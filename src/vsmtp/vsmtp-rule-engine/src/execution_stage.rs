@@ -46,6 +46,9 @@ pub enum ExecutionStage {
     RcptTo,
     /// Before write on disk
     PreQ,
+    /// After the connection with the client is closed, the outcome of the session
+    /// is available with `ctx::session_category()`. The status is ignored.
+    Disconnect,
     /// After write on disk & connection closed
     PostQ,
     /// Right before sending to recipient
@@ -0,0 +1,250 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2023 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use crate::api::{Context, EngineResult, SharedObject};
+use rhai::NativeCallContext;
+use vsmtp_common::{status::Status, Address, Reply};
+use vsmtp_config::field::FieldAppVSLGreylist;
+
+/// Identify the attempts of a client to send a message from a sender to a recipient.
+pub(crate) type Triplet = (std::net::IpAddr, Option<Address>, Address);
+
+#[derive(Debug, Clone, Copy)]
+enum Entry {
+    /// The triplet has been deferred for the first time at this date.
+    Pending(time::OffsetDateTime),
+    /// The triplet has been retried, and is accepted until this date.
+    Passed(time::OffsetDateTime),
+}
+
+/// Bounded store of the greylisting of the recipients, in memory.
+///
+/// The first attempt of a (client address, sender, recipient) triplet is deferred,
+/// a retry after `delay` is accepted and the triplet is then remembered for `ttl`,
+/// so that the known triplets are accepted in the same transaction as the new ones.
+#[derive(Debug)]
+pub struct Greylist {
+    delay: time::Duration,
+    retry_window: time::Duration,
+    ttl: time::Duration,
+    capacity: usize,
+    reply: Reply,
+    entries: std::sync::Mutex<std::collections::HashMap<Triplet, Entry>>,
+}
+
+impl Greylist {
+    /// Create an empty store.
+    #[must_use]
+    pub fn new(config: &FieldAppVSLGreylist) -> Self {
+        let duration =
+            |d: std::time::Duration| time::Duration::try_from(d).unwrap_or(time::Duration::MAX);
+
+        Self {
+            delay: duration(config.delay),
+            retry_window: duration(config.retry_window),
+            ttl: duration(config.ttl),
+            capacity: config.capacity,
+            reply: config.reply.clone(),
+            entries: std::sync::Mutex::new(std::collections::HashMap::new()),
+        }
+    }
+
+    fn expiration(&self, entry: Entry) -> time::OffsetDateTime {
+        match entry {
+            Entry::Pending(first_seen) => first_seen + self.delay + self.retry_window,
+            Entry::Passed(expiration) => expiration,
+        }
+    }
+
+    /// Record an attempt of `triplet`, returning `true` if it is accepted
+    /// and `false` if it must be deferred.
+    pub(crate) fn check(&self, triplet: Triplet) -> bool {
+        let now = vsmtp_common::clock::now();
+        let mut entries = self.entries.lock().expect("Mutex poisoned");
+
+        let (entry, passed) = match entries.get(&triplet) {
+            Some(entry) if self.expiration(*entry) <= now => (Entry::Pending(now), false),
+            Some(Entry::Passed(_)) => (Entry::Passed(now + self.ttl), true),
+            Some(Entry::Pending(first_seen)) if now - *first_seen >= self.delay => {
+                (Entry::Passed(now + self.ttl), true)
+            }
+            Some(entry) => (*entry, false),
+            None => (Entry::Pending(now), false),
+        };
+
+        if entries.len() >= self.capacity && !entries.contains_key(&triplet) {
+            entries.retain(|_, entry| self.expiration(*entry) > now);
+        }
+        if entries.len() >= self.capacity && !entries.contains_key(&triplet) {
+            let oldest = entries
+                .iter()
+                .min_by_key(|(_, entry)| self.expiration(**entry))
+                .map(|(triplet, _)| triplet.clone());
+            if let Some(oldest) = oldest {
+                entries.remove(&oldest);
+            }
+        }
+        if self.capacity != 0 {
+            entries.insert(triplet, entry);
+        }
+
+        passed
+    }
+
+    /// Number of triplets in the store, including the expired ones not removed yet.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.entries.lock().expect("Mutex poisoned").len()
+    }
+
+    /// Is the store empty ?
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Check the recipient `rcpt` of the transaction, removing it from the envelop if it is deferred.
+    fn rcpt(&self, context: &Context, rcpt: &str) -> EngineResult<Status> {
+        let rcpt = vsl_conversion_ok!("address", <Address as std::str::FromStr>::from_str(rcpt));
+
+        let mut context = vsl_guard_ok!(context.write());
+        let triplet = (
+            context.client_addr().ip(),
+            context
+                .reverse_path()
+                .map_err::<Box<rhai::EvalAltResult>, _>(|e| e.to_string().into())?
+                .clone(),
+            rcpt,
+        );
+
+        if self.check(triplet.clone()) {
+            return Ok(Status::Next);
+        }
+
+        tracing::debug!(client = %triplet.0, rcpt = %triplet.2, "Recipient greylisted.");
+        context
+            .remove_forward_path(&triplet.2)
+            .map_err::<Box<rhai::EvalAltResult>, _>(|e| e.to_string().into())?;
+
+        Ok(Status::Reject(self.reply.clone()))
+    }
+
+    /// Build the `greylist` module of vsl.
+    #[must_use]
+    pub fn module(self: &std::sync::Arc<Self>) -> rhai::Shared<rhai::Module> {
+        let mut module = rhai::Module::new();
+
+        let greylist = self.clone();
+        module.set_native_fn(
+            "rcpt",
+            move |ncc: NativeCallContext, rcpt: &str| -> EngineResult<Status> {
+                greylist.rcpt(&crate::get_global!(ncc, ctx), rcpt)
+            },
+        );
+        let greylist = self.clone();
+        module.set_native_fn(
+            "rcpt",
+            move |ncc: NativeCallContext, rcpt: SharedObject| -> EngineResult<Status> {
+                greylist.rcpt(&crate::get_global!(ncc, ctx), &rcpt.to_string())
+            },
+        );
+
+        rhai::Shared::new(module)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn greylist(delay: u64, capacity: usize) -> Greylist {
+        Greylist::new(&FieldAppVSLGreylist {
+            delay: std::time::Duration::from_secs(delay),
+            retry_window: std::time::Duration::from_secs(3600),
+            ttl: std::time::Duration::from_secs(86400),
+            capacity,
+            ..FieldAppVSLGreylist::default()
+        })
+    }
+
+    fn triplet(ip: &str, sender: Option<&str>, rcpt: &str) -> Triplet {
+        (
+            ip.parse().unwrap(),
+            sender.map(|sender| sender.parse().unwrap()),
+            rcpt.parse().unwrap(),
+        )
+    }
+
+    #[test]
+    fn deferred_then_passed() {
+        let clock = vsmtp_test::clock::TestClock::start();
+        let greylist = greylist(300, 10);
+        let known = triplet("10.0.0.1", Some("john@doe.com"), "jane@example.com");
+
+        assert!(!greylist.check(known.clone()));
+        clock.advance(time::Duration::seconds(299));
+        assert!(!greylist.check(known.clone()));
+        clock.advance(time::Duration::seconds(1));
+        assert!(greylist.check(known.clone()));
+
+        // the other triplets are not affected.
+        assert!(!greylist.check(triplet("10.0.0.1", None, "jane@example.com")));
+        assert!(!greylist.check(triplet(
+            "10.0.0.2",
+            Some("john@doe.com"),
+            "jane@example.com"
+        )));
+        assert!(!greylist.check(triplet(
+            "10.0.0.1",
+            Some("john@doe.com"),
+            "jenny@example.com"
+        )));
+
+        clock.advance(time::Duration::days(1) - time::Duration::seconds(1));
+        assert!(greylist.check(known.clone()));
+        clock.advance(time::Duration::days(1));
+        assert!(!greylist.check(known));
+    }
+
+    #[test]
+    fn retry_window() {
+        let clock = vsmtp_test::clock::TestClock::start();
+        let greylist = greylist(300, 10);
+        let late = triplet("10.0.0.1", Some("john@doe.com"), "jane@example.com");
+
+        assert!(!greylist.check(late.clone()));
+        clock.advance(time::Duration::seconds(300 + 3600));
+        assert!(!greylist.check(late.clone()));
+        clock.advance(time::Duration::seconds(300));
+        assert!(greylist.check(late));
+    }
+
+    #[test]
+    fn bounded() {
+        let clock = vsmtp_test::clock::TestClock::start();
+        let greylist = greylist(300, 2);
+
+        for rcpt in ["a@example.com", "b@example.com", "c@example.com"] {
+            assert!(!greylist.check(triplet("10.0.0.1", None, rcpt)));
+            clock.advance(time::Duration::seconds(1));
+        }
+
+        assert_eq!(greylist.len(), 2);
+        clock.advance(time::Duration::seconds(300));
+        assert!(!greylist.check(triplet("10.0.0.1", None, "a@example.com")));
+        assert!(greylist.check(triplet("10.0.0.1", None, "c@example.com")));
+    }
+}
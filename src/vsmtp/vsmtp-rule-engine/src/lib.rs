@@ -53,15 +53,42 @@ pub use rhai;
 
 #[macro_use]
 mod error;
+mod access_lists;
+mod aliases;
+mod datasets;
+mod decision_cache;
 mod execution_stage;
+mod greylist;
+mod lookup_cache;
+mod lookup_tables;
+mod recipients;
+mod routing;
 mod rule_engine;
 mod rule_state;
 mod server_api;
+mod srs;
+mod state_pool;
+mod statistics;
+mod trusted_upstreams;
+mod watched_file;
 
+pub use access_lists::{AccessLists, AccessVerdict, ACCESS_LIST_HEADER};
+pub use aliases::Aliases;
+pub use datasets::Datasets;
+pub use decision_cache::DecisionCache;
+#[cfg(feature = "delegation")]
+pub use dsl::directives::delegation::{Payload as DelegationPayload, TRUNCATED_HEADER};
 pub use dsl::directives::Directive;
 pub use execution_stage::ExecutionStage;
+pub use greylist::Greylist;
+pub use lookup_cache::LookupCache;
+pub use lookup_tables::LookupTables;
+pub use recipients::{RecipientVerdict, Recipients};
 pub use rule_engine::RuleEngine;
 pub use rule_state::RuleState;
+pub use srs::Srs;
+pub use statistics::{RuleHits, RuleStatistics};
+pub use trusted_upstreams::{origin, Origin};
 
 mod domain_hierarchy {
     #[cfg(feature = "builder")]
@@ -125,6 +152,8 @@ pub mod api {
     pub mod transports;
     /// Utility functions.
     pub mod utils;
+    /// Variable envelope return paths.
+    pub mod verp;
 
     /// Fetch rule engine global variables by calling the rhai system functions.
     ///
@@ -148,7 +177,7 @@ pub mod api {
 
     /// Get vsmtp static modules.
     #[must_use]
-    pub fn vsmtp_static_modules() -> [(&'static str, rhai::Module); 20] {
+    pub fn vsmtp_static_modules() -> [(&'static str, rhai::Module); 21] {
         [
             ("state", rhai::exported_module!(state)),
             ("envelop", rhai::exported_module!(envelop)),
@@ -164,6 +193,7 @@ pub mod api {
             ("dmarc", rhai::exported_module!(dmarc)),
             ("transport", rhai::exported_module!(transports)),
             ("utils", rhai::exported_module!(utils)),
+            ("verp", rhai::exported_module!(verp)),
             ("ctx", rhai::exported_module!(mail_context)),
             ("msg", rhai::exported_module!(message)),
             ("obj", vsmtp_plugin_vsl::object_module()),
@@ -0,0 +1,510 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2023 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use crate::api::EngineResult;
+use rhai::{Dynamic, NativeCallContext};
+use std::sync::atomic::{AtomicU64, Ordering};
+use vsmtp_config::field::FieldAppVSLLookupCache;
+
+/// A value being computed by a transaction, awaited by the others asking for the same key.
+#[derive(Debug)]
+struct Flight {
+    leader: std::thread::ThreadId,
+    /// [`None`] while the loader runs, then the value, or [`None`] if the loader failed.
+    result: std::sync::Mutex<Option<Option<Dynamic>>>,
+    done: std::sync::Condvar,
+}
+
+impl Flight {
+    fn finish(&self, value: Option<Dynamic>) {
+        *self.result.lock().expect("Mutex poisoned") = Some(value);
+        self.done.notify_all();
+    }
+
+    /// Block until the leader finishes, the worker of the runtime being handed over
+    /// to the other tasks, the leader's included, in the meantime.
+    fn wait(&self) -> Option<Dynamic> {
+        tokio::task::block_in_place(|| {
+            let result = self.result.lock().expect("Mutex poisoned");
+            let result = self
+                .done
+                .wait_while(result, |result| result.is_none())
+                .expect("Mutex poisoned");
+            result.clone().flatten()
+        })
+    }
+}
+
+#[derive(Debug)]
+enum Slot {
+    Ready {
+        value: Dynamic,
+        expiration: time::OffsetDateTime,
+        /// Tick of the last read, the lowest is evicted first.
+        last_used: u64,
+    },
+    Loading(std::sync::Arc<Flight>),
+}
+
+#[derive(Debug, Default)]
+struct Entries {
+    tick: u64,
+    slots: std::collections::HashMap<String, Slot>,
+}
+
+impl Entries {
+    fn next_tick(&mut self) -> u64 {
+        self.tick = self.tick.saturating_add(1);
+        self.tick
+    }
+}
+
+/// Outcome of the lookup of a key in the cache.
+enum Lookup {
+    Hit(Dynamic),
+    Wait(std::sync::Arc<Flight>),
+    Load(std::sync::Arc<Flight>),
+}
+
+/// Marks the flight as failed if the loader did not return, so that the waiters retry.
+struct Leader<'cache> {
+    cache: &'cache LookupCache,
+    key: &'cache str,
+    flight: std::sync::Arc<Flight>,
+}
+
+impl Drop for Leader<'_> {
+    fn drop(&mut self) {
+        let mut entries = self.cache.entries.lock().expect("Mutex poisoned");
+        if matches!(
+            entries.slots.get(self.key),
+            Some(Slot::Loading(flight)) if std::sync::Arc::ptr_eq(flight, &self.flight)
+        ) {
+            entries.slots.remove(self.key);
+        }
+        drop(entries);
+
+        if self.flight.result.lock().expect("Mutex poisoned").is_none() {
+            self.flight.finish(None);
+        }
+    }
+}
+
+/// Can the value be stored in the cache ?
+///
+/// Only the plain values are kept, the objects of a transaction must not outlive it.
+fn is_bounded(value: &Dynamic) -> bool {
+    if let Some(array) = value.read_lock::<rhai::Array>() {
+        return array.iter().all(is_bounded);
+    }
+    if let Some(map) = value.read_lock::<rhai::Map>() {
+        return map.values().all(is_bounded);
+    }
+    value.is_unit() || value.is_bool() || value.is_int() || value.is_float() || value.is_string()
+}
+
+/// Bounded cache of the values computed by the rules, shared by the transactions.
+///
+/// A missing or expired value is computed by a single transaction, the others
+/// asking for the same key wait for its result instead of running the lookup again.
+#[derive(Debug)]
+pub struct LookupCache {
+    capacity: usize,
+    entries: std::sync::Mutex<Entries>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+}
+
+impl LookupCache {
+    /// Create an empty cache.
+    #[must_use]
+    pub fn new(config: &FieldAppVSLLookupCache) -> Self {
+        Self {
+            capacity: config.capacity,
+            entries: std::sync::Mutex::new(Entries::default()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+        }
+    }
+
+    fn lookup(&self, key: &str) -> Lookup {
+        let now = vsmtp_common::clock::now();
+        let mut entries = self.entries.lock().expect("Mutex poisoned");
+        let tick = entries.next_tick();
+
+        match entries.slots.get_mut(key) {
+            Some(Slot::Ready {
+                value,
+                expiration,
+                last_used,
+            }) if *expiration > now => {
+                *last_used = tick;
+                return Lookup::Hit(value.clone());
+            }
+            Some(Slot::Loading(flight)) => return Lookup::Wait(flight.clone()),
+            Some(Slot::Ready { .. }) | None => {}
+        }
+
+        let flight = std::sync::Arc::new(Flight {
+            leader: std::thread::current().id(),
+            result: std::sync::Mutex::new(None),
+            done: std::sync::Condvar::new(),
+        });
+        if self.capacity == 0 {
+            return Lookup::Load(flight);
+        }
+
+        if entries.slots.len() >= self.capacity && !entries.slots.contains_key(key) {
+            entries.slots.retain(|_, slot| match slot {
+                Slot::Ready { expiration, .. } => *expiration > now,
+                Slot::Loading(_) => true,
+            });
+        }
+        if entries.slots.len() >= self.capacity && !entries.slots.contains_key(key) {
+            let least_used = entries
+                .slots
+                .iter()
+                .filter_map(|(key, slot)| match slot {
+                    Slot::Ready { last_used, .. } => Some((key, *last_used)),
+                    Slot::Loading(_) => None,
+                })
+                .min_by_key(|(_, last_used)| *last_used)
+                .map(|(key, _)| key.clone());
+            if let Some(least_used) = least_used {
+                entries.slots.remove(&least_used);
+                self.evictions.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        entries
+            .slots
+            .insert(key.to_string(), Slot::Loading(flight.clone()));
+
+        Lookup::Load(flight)
+    }
+
+    /// Get the value cached for `key`, or compute it with `loader` and keep it for `ttl`.
+    ///
+    /// # Errors
+    ///
+    /// * the error of `loader`
+    /// * the value is not a unit, a boolean, a number, a string, or an array or map of them
+    /// * `loader` asks for the key it is computing
+    pub(crate) fn get_or(
+        &self,
+        key: &str,
+        ttl: std::time::Duration,
+        loader: impl FnOnce() -> EngineResult<Dynamic>,
+    ) -> EngineResult<Dynamic> {
+        let mut loader = Some(loader);
+        loop {
+            let flight = match self.lookup(key) {
+                Lookup::Hit(value) => {
+                    self.hits.fetch_add(1, Ordering::Relaxed);
+                    return Ok(value);
+                }
+                Lookup::Wait(flight) if flight.leader == std::thread::current().id() => {
+                    return Err(format!("the value of '{key}' is used to compute itself").into());
+                }
+                Lookup::Wait(flight) => {
+                    if let Some(value) = flight.wait() {
+                        self.hits.fetch_add(1, Ordering::Relaxed);
+                        return Ok(value);
+                    }
+                    // the loader of the other transaction failed, trying again.
+                    continue;
+                }
+                Lookup::Load(flight) => flight,
+            };
+
+            self.misses.fetch_add(1, Ordering::Relaxed);
+            let leader = Leader {
+                cache: self,
+                key,
+                flight,
+            };
+
+            let loader = loader
+                .take()
+                .expect("the loader is only called by the leader of a flight");
+            let value = loader()?;
+            if !is_bounded(&value) {
+                return Err(format!(
+                    "a value of type '{}' cannot be cached, only the units, booleans, numbers, strings, arrays and maps are",
+                    value.type_name()
+                )
+                .into());
+            }
+
+            let mut entries = self.entries.lock().expect("Mutex poisoned");
+            let tick = entries.next_tick();
+            if let Some(slot) = entries.slots.get_mut(key) {
+                if matches!(slot, Slot::Loading(flight) if std::sync::Arc::ptr_eq(flight, &leader.flight))
+                {
+                    *slot = Slot::Ready {
+                        value: value.clone(),
+                        expiration: vsmtp_common::clock::now()
+                            + time::Duration::try_from(ttl).unwrap_or(time::Duration::MAX),
+                        last_used: tick,
+                    };
+                }
+            }
+            drop(entries);
+            leader.flight.finish(Some(value.clone()));
+
+            return Ok(value);
+        }
+    }
+
+    /// Remove the value of `key`, returning `true` if it was cached.
+    pub fn invalidate(&self, key: &str) -> bool {
+        let mut entries = self.entries.lock().expect("Mutex poisoned");
+        if !matches!(entries.slots.get(key), Some(Slot::Ready { .. })) {
+            return false;
+        }
+        entries.slots.remove(key);
+        true
+    }
+
+    /// Number of values in the cache, including the expired ones not removed yet.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.entries
+            .lock()
+            .expect("Mutex poisoned")
+            .slots
+            .values()
+            .filter(|slot| matches!(slot, Slot::Ready { .. }))
+            .count()
+    }
+
+    /// Is the cache empty ?
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Render the counters in the Prometheus text format.
+    #[must_use]
+    pub fn metrics(&self) -> String {
+        format!(
+            "# HELP vsmtp_lookup_cache_hits_total Values of the rules read from the lookup cache.\n# TYPE vsmtp_lookup_cache_hits_total counter\nvsmtp_lookup_cache_hits_total {}\n\
+            # HELP vsmtp_lookup_cache_misses_total Values of the rules computed on a miss of the lookup cache.\n# TYPE vsmtp_lookup_cache_misses_total counter\nvsmtp_lookup_cache_misses_total {}\n\
+            # HELP vsmtp_lookup_cache_evictions_total Values evicted from the full lookup cache.\n# TYPE vsmtp_lookup_cache_evictions_total counter\nvsmtp_lookup_cache_evictions_total {}\n\
+            # HELP vsmtp_lookup_cache_entries Values in the lookup cache.\n# TYPE vsmtp_lookup_cache_entries gauge\nvsmtp_lookup_cache_entries {}\n",
+            self.hits.load(Ordering::Relaxed),
+            self.misses.load(Ordering::Relaxed),
+            self.evictions.load(Ordering::Relaxed),
+            self.len(),
+        )
+    }
+
+    /// Add the functions of the cache to the `cache` module of vsl.
+    pub fn register(self: &std::sync::Arc<Self>, module: &mut rhai::Module) {
+        let cache = self.clone();
+        module.set_native_fn(
+            "get_or",
+            move |ncc: NativeCallContext, key: &str, ttl: &str, loader: rhai::FnPtr| {
+                let ttl =
+                    humantime_serde::re::humantime::parse_duration(ttl)
+                        .map_err::<Box<rhai::EvalAltResult>, _>(|e| e.to_string().into())?;
+                cache.get_or(key, ttl, || loader.call_within_context(&ncc, ()))
+            },
+        );
+        let cache = self.clone();
+        module.set_native_fn(
+            "get_or",
+            move |ncc: NativeCallContext, key: &str, ttl: rhai::INT, loader: rhai::FnPtr| {
+                let ttl = std::time::Duration::from_secs(u64::try_from(ttl).unwrap_or_default());
+                cache.get_or(key, ttl, || loader.call_within_context(&ncc, ()))
+            },
+        );
+        let cache = self.clone();
+        module.set_native_fn("invalidate", move |key: &str| -> EngineResult<bool> {
+            Ok(cache.invalidate(key))
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cache(capacity: usize) -> LookupCache {
+        LookupCache::new(&FieldAppVSLLookupCache { capacity })
+    }
+
+    const TTL: std::time::Duration = std::time::Duration::from_secs(60);
+
+    #[test]
+    fn single_flight() {
+        let cache = cache(10);
+        let calls = AtomicU64::new(0);
+        let barrier = std::sync::Barrier::new(16);
+
+        std::thread::scope(|scope| {
+            let handles = (0..16)
+                .map(|_| {
+                    scope.spawn(|| {
+                        barrier.wait();
+                        cache.get_or("example.com", TTL, || {
+                            calls.fetch_add(1, Ordering::SeqCst);
+                            std::thread::sleep(std::time::Duration::from_millis(100));
+                            Ok(Dynamic::from("mx.example.com"))
+                        })
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            for handle in handles {
+                assert_eq!(
+                    handle.join().unwrap().unwrap().into_string().unwrap(),
+                    "mx.example.com"
+                );
+            }
+        });
+
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert_eq!(cache.misses.load(Ordering::SeqCst), 1);
+        assert_eq!(cache.hits.load(Ordering::SeqCst), 15);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn waiter_hands_over_the_runtime() {
+        let cache = std::sync::Arc::new(cache(10));
+        let (loading, loading_rx) = std::sync::mpsc::channel();
+        let (release, release_rx) = std::sync::mpsc::channel::<()>();
+
+        let leader = {
+            let cache = cache.clone();
+            std::thread::spawn(move || {
+                cache.get_or("example.com", TTL, || {
+                    loading.send(()).unwrap();
+                    release_rx.recv().unwrap();
+                    Ok(Dynamic::from("mx.example.com"))
+                })
+            })
+        };
+        loading_rx.recv().unwrap();
+
+        // the waiter blocks the only worker of the runtime ...
+        let waiter = tokio::spawn(async move {
+            cache
+                .get_or("example.com", TTL, || unreachable!())
+                .unwrap()
+                .into_string()
+                .unwrap()
+        });
+        std::thread::sleep(std::time::Duration::from_millis(50));
+
+        // ... which is still available to the other tasks.
+        tokio::spawn(async move { release.send(()).unwrap() })
+            .await
+            .unwrap();
+
+        assert_eq!(waiter.await.unwrap(), "mx.example.com");
+        assert!(leader.join().unwrap().is_ok());
+    }
+
+    #[test]
+    fn failed_loader() {
+        let cache = cache(10);
+
+        assert!(cache
+            .get_or("example.com", TTL, || Err("lookup failed".into()))
+            .is_err());
+        assert!(cache.is_empty());
+        assert_eq!(
+            cache
+                .get_or("example.com", TTL, || Ok(Dynamic::from(true)))
+                .unwrap()
+                .as_bool(),
+            Ok(true)
+        );
+
+        assert!(cache
+            .get_or("object", TTL, || Ok(Dynamic::from(
+                std::time::Instant::now()
+            )))
+            .is_err());
+        assert!(cache
+            .get_or("nested", TTL, || Ok(Dynamic::from(vec![Dynamic::from(
+                std::time::Instant::now()
+            )])))
+            .is_err());
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn expiration() {
+        let clock = vsmtp_test::clock::TestClock::start();
+        let cache = cache(10);
+        let get = |value: rhai::INT| {
+            cache
+                .get_or("example.com", TTL, || Ok(Dynamic::from(value)))
+                .unwrap()
+                .as_int()
+                .unwrap()
+        };
+
+        assert_eq!(get(1), 1);
+        clock.advance(time::Duration::seconds(59));
+        assert_eq!(get(2), 1);
+        clock.advance(time::Duration::seconds(1));
+        assert_eq!(get(3), 3);
+
+        assert!(cache.invalidate("example.com"));
+        assert!(!cache.invalidate("example.com"));
+        assert_eq!(get(4), 4);
+    }
+
+    #[test]
+    fn least_recently_used() {
+        let cache = cache(2);
+        let get = |key: &str, value: rhai::INT| {
+            cache
+                .get_or(key, TTL, || Ok(Dynamic::from(value)))
+                .unwrap()
+                .as_int()
+                .unwrap()
+        };
+
+        assert_eq!(get("a", 1), 1);
+        assert_eq!(get("b", 2), 2);
+        assert_eq!(get("a", 0), 1);
+        assert_eq!(get("c", 3), 3);
+
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.evictions.load(Ordering::SeqCst), 1);
+        assert_eq!(get("a", 0), 1);
+        assert_eq!(get("b", 4), 4);
+    }
+
+    #[test]
+    fn disabled() {
+        let cache = cache(0);
+
+        assert_eq!(
+            cache
+                .get_or("a", TTL, || Ok(Dynamic::from(1_i64)))
+                .unwrap()
+                .as_int(),
+            Ok(1)
+        );
+        assert!(cache.is_empty());
+    }
+}
@@ -0,0 +1,591 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2023 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use crate::watched_file::WatchedFile;
+use anyhow::Context;
+use vsmtp_common::Network;
+use vsmtp_config::field::{FieldServerLookupTables, LookupTableType};
+
+/// A `/pattern/flags` of a `pcre` table, `!/pattern/` matching when the pattern does not.
+#[derive(Debug, Clone)]
+struct Pattern {
+    regex: regex::Regex,
+    negated: bool,
+}
+
+impl Pattern {
+    /// Parse the pattern at the start of `line`, returns the rest of the line.
+    fn parse(line: &str) -> anyhow::Result<(Self, &str)> {
+        let (negated, line) = line
+            .strip_prefix('!')
+            .map_or((false, line), |line| (true, line));
+
+        let mut chars = line.chars();
+        let delimiter = chars
+            .next()
+            .filter(|c| !c.is_alphanumeric() && !c.is_whitespace())
+            .context("missing pattern delimiter")?;
+        let rest = chars.as_str();
+        // NOTE: a delimiter escaped by a backslash is part of the pattern.
+        let mut escaped = false;
+        let end = rest
+            .char_indices()
+            .find(|(_, c)| {
+                let is_end = !escaped && *c == delimiter;
+                escaped = !escaped && *c == '\\';
+                is_end
+            })
+            .map(|(end, _)| end)
+            .context("missing closing pattern delimiter")?;
+        let (pattern, rest) = (&rest[..end], &rest[end + delimiter.len_utf8()..]);
+
+        let flags_end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        let (flags, rest) = rest.split_at(flags_end);
+
+        let mut builder = regex::RegexBuilder::new(pattern);
+        // NOTE: the patterns are case insensitive by default, `i` toggles it.
+        let mut case_insensitive = true;
+        for flag in flags.chars() {
+            match flag {
+                'i' => case_insensitive = !case_insensitive,
+                'm' => {
+                    builder.multi_line(true);
+                }
+                's' => {
+                    builder.dot_matches_new_line(true);
+                }
+                'x' => {
+                    builder.ignore_whitespace(true);
+                }
+                _ => anyhow::bail!("unsupported flag `{flag}`"),
+            }
+        }
+
+        Ok((
+            Self {
+                regex: builder.case_insensitive(case_insensitive).build()?,
+                negated,
+            },
+            rest.trim(),
+        ))
+    }
+
+    fn is_match(&self, key: &str) -> bool {
+        self.regex.is_match(key) != self.negated
+    }
+}
+
+/// A line of a `pcre` table, with the `if` blocks enclosing it.
+#[derive(Debug)]
+struct PatternEntry {
+    conditions: std::sync::Arc<Vec<Pattern>>,
+    pattern: Pattern,
+    value: String,
+}
+
+/// Replace `$1`, `${1}` and `$(1)` by the groups of the match, and `$$` by `$`.
+fn substitute(value: &str, captures: &regex::Captures<'_>) -> String {
+    let mut output = String::with_capacity(value.len());
+    let mut rest = value;
+
+    while let Some(index) = rest.find('$') {
+        output.push_str(&rest[..index]);
+        rest = &rest[index + 1..];
+
+        if let Some(after) = rest.strip_prefix('$') {
+            output.push('$');
+            rest = after;
+            continue;
+        }
+
+        let (digits, after) = match rest.chars().next() {
+            Some('{') => rest[1..].split_once('}').unwrap_or(("", rest)),
+            Some('(') => rest[1..].split_once(')').unwrap_or(("", rest)),
+            _ => rest.split_at(
+                rest.find(|c: char| !c.is_ascii_digit())
+                    .unwrap_or(rest.len()),
+            ),
+        };
+
+        // NOTE: a `$` not followed by a group is kept as is.
+        let group = digits.parse::<usize>().ok();
+        output.push_str(group.map_or("$", |group| captures.get(group).map_or("", |m| m.as_str())));
+        if group.is_some() {
+            rest = after;
+        }
+    }
+
+    output.push_str(rest);
+    output
+}
+
+/// The content of a table.
+#[derive(Debug)]
+enum Table {
+    Hash(std::collections::HashMap<String, String>),
+    Cidr(Vec<(Network, String)>),
+    Pcre(Vec<PatternEntry>),
+}
+
+impl Default for Table {
+    fn default() -> Self {
+        Self::Hash(std::collections::HashMap::new())
+    }
+}
+
+/// Join the lines starting with a whitespace to the previous one, and skip
+/// the empty lines and the comments. Returns the logical lines with the number
+/// of their first line.
+fn logical_lines(content: &str) -> Vec<(usize, String)> {
+    let mut lines: Vec<(usize, String)> = vec![];
+
+    for (index, line) in content.lines().enumerate() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        match lines.last_mut() {
+            Some((_, previous)) if line.starts_with(char::is_whitespace) => {
+                previous.push(' ');
+                previous.push_str(trimmed);
+            }
+            _ => lines.push((index + 1, trimmed.to_owned())),
+        }
+    }
+
+    lines
+}
+
+/// Split a `key value` line.
+fn key_value(line: &str) -> anyhow::Result<(&str, &str)> {
+    line.split_once(char::is_whitespace)
+        .map(|(key, value)| (key, value.trim()))
+        .filter(|(_, value)| !value.is_empty())
+        .context("missing value")
+}
+
+impl Table {
+    /// Parse the file, the malformed lines are skipped.
+    fn parse(r#type: LookupTableType, path: &std::path::Path, content: &str) -> Self {
+        let mut malformed = vec![];
+        let mut skip = |index: usize, error: anyhow::Error| {
+            tracing::debug!(path = %path.display(), line = index, %error, "Malformed line.");
+            malformed.push(index);
+        };
+
+        let table = match r#type {
+            LookupTableType::Hash => {
+                let mut entries = std::collections::HashMap::new();
+                for (index, line) in logical_lines(content) {
+                    match key_value(&line) {
+                        // NOTE: the first entry of a duplicated key is kept.
+                        Ok((key, value)) => {
+                            entries
+                                .entry(key.to_lowercase())
+                                .or_insert_with(|| value.to_owned());
+                        }
+                        Err(error) => skip(index, error),
+                    }
+                }
+                Self::Hash(entries)
+            }
+            LookupTableType::Cidr => Self::Cidr(
+                logical_lines(content)
+                    .into_iter()
+                    .filter_map(|(index, line)| {
+                        key_value(&line)
+                            .and_then(|(key, value)| {
+                                Ok((key.parse::<Network>()?, value.to_owned()))
+                            })
+                            .map_err(|error| skip(index, error))
+                            .ok()
+                    })
+                    .collect(),
+            ),
+            LookupTableType::Pcre => {
+                let mut entries = vec![];
+                let mut conditions = std::sync::Arc::new(vec![]);
+                let mut depth = vec![];
+
+                for (index, line) in logical_lines(content) {
+                    if let Some(condition) = line.strip_prefix("if").filter(|condition| {
+                        condition.starts_with(char::is_whitespace) || condition.starts_with('!')
+                    }) {
+                        match Pattern::parse(condition.trim_start()) {
+                            Ok((pattern, rest)) if rest.is_empty() => {
+                                depth.push(conditions.len());
+                                std::sync::Arc::make_mut(&mut conditions).push(pattern);
+                            }
+                            Ok(_) => skip(index, anyhow::anyhow!("text after the condition")),
+                            Err(error) => skip(index, error),
+                        }
+                        continue;
+                    }
+                    if line == "endif" {
+                        match depth.pop() {
+                            Some(len) => std::sync::Arc::make_mut(&mut conditions).truncate(len),
+                            None => skip(index, anyhow::anyhow!("`endif` without `if`")),
+                        }
+                        continue;
+                    }
+
+                    match Pattern::parse(&line) {
+                        Ok((_, "")) => skip(index, anyhow::anyhow!("missing value")),
+                        Ok((pattern, value)) => entries.push(PatternEntry {
+                            conditions: conditions.clone(),
+                            pattern,
+                            value: value.to_owned(),
+                        }),
+                        Err(error) => skip(index, error),
+                    }
+                }
+                Self::Pcre(entries)
+            }
+        };
+
+        if !malformed.is_empty() {
+            tracing::warn!(
+                path = %path.display(),
+                lines = ?malformed,
+                "Malformed lines of the lookup table skipped."
+            );
+        }
+
+        table
+    }
+
+    fn lookup(&self, key: &str) -> Option<String> {
+        match self {
+            Self::Hash(entries) => entries.get(&key.to_lowercase()).cloned(),
+            // NOTE: as Postfix, the first network of the file is used, not the most specific one.
+            Self::Cidr(entries) => {
+                let ip = key.parse().ok()?;
+                entries
+                    .iter()
+                    .find(|(network, _)| network.contains(ip))
+                    .map(|(_, value)| value.clone())
+            }
+            Self::Pcre(entries) => entries.iter().find_map(|entry| {
+                if !entry
+                    .conditions
+                    .iter()
+                    .all(|condition| condition.is_match(key))
+                {
+                    return None;
+                }
+                if entry.pattern.negated {
+                    return (!entry.pattern.regex.is_match(key)).then(|| entry.value.clone());
+                }
+                entry
+                    .pattern
+                    .regex
+                    .captures(key)
+                    .map(|captures| substitute(&entry.value, &captures))
+            }),
+        }
+    }
+}
+
+/// Postfix-style lookup tables declared in the configuration, exposed to the rules
+/// with `table::lookup(name, key)`.
+#[derive(Debug)]
+pub struct LookupTables {
+    /// the tables of the configuration, read again when their file is modified.
+    tables: std::collections::BTreeMap<String, WatchedFile<Table>>,
+}
+
+impl LookupTables {
+    /// Read the tables.
+    ///
+    /// # Errors
+    ///
+    /// * a file cannot be read
+    pub fn new(config: &FieldServerLookupTables) -> anyhow::Result<Self> {
+        Ok(Self {
+            tables: config
+                .tables
+                .iter()
+                .map(|(name, table)| {
+                    let r#type = table.r#type;
+                    WatchedFile::new(&table.path, "table", move |path, content| {
+                        Table::parse(r#type, path, content)
+                    })
+                    .with_context(|| format!("failed to load the lookup table '{name}'"))
+                    .map(|file| (name.clone(), file))
+                })
+                .collect::<anyhow::Result<_>>()?,
+        })
+    }
+
+    /// Read the files modified since the last read, a table failing to be read
+    /// keeps its previous content.
+    pub fn reload_if_changed(&self) {
+        for (name, file) in &self.tables {
+            match file.reload_if_changed() {
+                Ok(true) => tracing::info!(table = name, "Lookup table reloaded."),
+                Ok(false) => (),
+                Err(error) => tracing::warn!(
+                    table = name,
+                    %error,
+                    "Lookup table reload failure, the previous content is kept."
+                ),
+            }
+        }
+    }
+
+    /// Get the value of `key` in the table `name`.
+    ///
+    /// # Errors
+    ///
+    /// * the table is not declared in the configuration
+    pub fn lookup(&self, name: &str, key: &str) -> anyhow::Result<Option<String>> {
+        Ok(self
+            .tables
+            .get(name)
+            .with_context(|| format!("unknown lookup table '{name}'"))?
+            .content()
+            .lookup(key))
+    }
+
+    /// Build the `table` module of vsl.
+    #[must_use]
+    pub fn module(self: &std::sync::Arc<Self>) -> rhai::Shared<rhai::Module> {
+        let mut module = rhai::Module::new();
+
+        let tables = self.clone();
+        module.set_native_fn(
+            "lookup",
+            move |name: &str, key: &str| -> Result<rhai::Dynamic, Box<rhai::EvalAltResult>> {
+                Ok(tables
+                    .lookup(name, key)
+                    .map_err::<Box<rhai::EvalAltResult>, _>(|e| e.to_string().into())?
+                    .map_or(rhai::Dynamic::UNIT, rhai::Dynamic::from))
+            },
+        );
+        let tables = self.clone();
+        module.set_native_fn(
+            "lookup",
+            move |name: &str,
+                  key: crate::api::SharedObject|
+                  -> Result<rhai::Dynamic, Box<rhai::EvalAltResult>> {
+                Ok(tables
+                    .lookup(name, &key.to_string())
+                    .map_err::<Box<rhai::EvalAltResult>, _>(|e| e.to_string().into())?
+                    .map_or(rhai::Dynamic::UNIT, rhai::Dynamic::from))
+            },
+        );
+
+        rhai::Shared::new(module)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vsmtp_config::field::FieldLookupTable;
+
+    const FIXTURES: &str = "src/tests/lookup_tables";
+
+    fn tables() -> LookupTables {
+        let table = |r#type, file: &str| FieldLookupTable {
+            r#type,
+            path: std::path::Path::new(FIXTURES).join(file),
+        };
+
+        LookupTables::new(&FieldServerLookupTables {
+            tables: [
+                ("transport", table(LookupTableType::Hash, "transport")),
+                (
+                    "client_access",
+                    table(LookupTableType::Cidr, "client_access.cidr"),
+                ),
+                (
+                    "header_checks",
+                    table(LookupTableType::Pcre, "header_checks.pcre"),
+                ),
+            ]
+            .into_iter()
+            .map(|(name, table)| (name.to_owned(), table))
+            .collect(),
+            reload_period: std::time::Duration::from_secs(10),
+        })
+        .unwrap()
+    }
+
+    fn lookup(tables: &LookupTables, name: &str, key: &str) -> Option<String> {
+        tables.lookup(name, key).unwrap()
+    }
+
+    #[test]
+    fn hash() {
+        let tables = tables();
+        let lookup = |key| lookup(&tables, "transport", key);
+
+        assert_eq!(
+            lookup("example.com").as_deref(),
+            Some("smtp:[mail.example.com]:587")
+        );
+        // the keys are folded to lowercase
+        assert_eq!(
+            lookup("Example.COM").as_deref(),
+            Some("smtp:[mail.example.com]:587")
+        );
+        assert_eq!(
+            lookup(".example.com").as_deref(),
+            Some("smtp:[relay.example.com]")
+        );
+        assert_eq!(
+            lookup("lists.example.org").as_deref(),
+            Some("lmtp:unix:private/dovecot-lmtp")
+        );
+        // the first entry of a duplicated key is kept
+        assert_eq!(
+            lookup("example.net").as_deref(),
+            Some("relay:[first.example.net]")
+        );
+        // a continuation line is joined to the previous one
+        assert_eq!(
+            lookup("john@example.org").as_deref(),
+            Some("error:5.1.1 Mailbox moved to jenny@example.org")
+        );
+        assert_eq!(lookup("unknown.com"), None);
+        // a line without value is skipped
+        assert_eq!(lookup("nothing.example"), None);
+    }
+
+    #[test]
+    fn cidr() {
+        let tables = tables();
+        let lookup = |key| lookup(&tables, "client_access", key);
+
+        // the first network containing the address is used
+        assert_eq!(lookup("192.0.2.1").as_deref(), Some("REJECT spam source"));
+        assert_eq!(lookup("192.0.2.42").as_deref(), Some("OK"));
+        assert_eq!(lookup("192.0.2.200").as_deref(), Some("REJECT spam source"));
+        assert_eq!(lookup("198.51.100.7").as_deref(), Some("PERMIT"));
+        assert_eq!(lookup("2001:db8::1").as_deref(), Some("DUNNO"));
+        assert_eq!(lookup("2001:db8:1::1").as_deref(), Some("REJECT"));
+        // a single address
+        assert_eq!(
+            lookup("203.0.113.5").as_deref(),
+            Some("450 4.7.1 try again later")
+        );
+        assert_eq!(lookup("203.0.113.6"), None);
+        // a key which is not an address
+        assert_eq!(lookup("example.com"), None);
+    }
+
+    #[test]
+    fn pcre() {
+        let tables = tables();
+        let lookup = |key| lookup(&tables, "header_checks", key);
+
+        assert_eq!(
+            lookup("Subject: Make money fast").as_deref(),
+            Some("REJECT no spam please")
+        );
+        // case insensitive by default, `i` toggles it
+        assert_eq!(
+            lookup("SUBJECT: make MONEY fast").as_deref(),
+            Some("REJECT no spam please")
+        );
+        assert_eq!(lookup("X-Mailer: Bulk"), None);
+        assert_eq!(lookup("X-Mailer: bulk").as_deref(), Some("DISCARD"));
+        // substitution of the groups
+        assert_eq!(
+            lookup("To: John@old.example.com").as_deref(),
+            Some("REDIRECT John@new.example.com")
+        );
+        assert_eq!(
+            lookup("Received: from mx.example.org (10.0.0.1)").as_deref(),
+            Some("WARN relay mx.example.org costs $5")
+        );
+        // the patterns of an `if` block apply when the condition matches
+        assert_eq!(
+            lookup("Content-Type: application/x-msdownload").as_deref(),
+            Some("REJECT executable attachment")
+        );
+        assert_eq!(
+            lookup("Content-Type: application/octet-stream; name=\"invoice.exe\"").as_deref(),
+            Some("REJECT executable attachment exe")
+        );
+        assert_eq!(lookup("X-Content-Type: application/x-msdownload"), None);
+        // a negated pattern
+        assert_eq!(lookup("From: someone@example.com"), None);
+        assert_eq!(
+            lookup("From: somebody").as_deref(),
+            Some("WARN sender without domain")
+        );
+        // the first matching pattern is used
+        assert_eq!(lookup("Subject: money").as_deref(), Some("HOLD money"));
+    }
+
+    #[test]
+    fn unknown_table() {
+        assert!(tables().lookup("virtual", "john@example.com").is_err());
+        assert!(LookupTables::new(&FieldServerLookupTables {
+            tables: [(
+                "missing".to_owned(),
+                FieldLookupTable {
+                    r#type: LookupTableType::Hash,
+                    path: std::path::Path::new(FIXTURES).join("missing"),
+                },
+            )]
+            .into(),
+            reload_period: std::time::Duration::from_secs(10),
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn reload() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("virtual");
+        std::fs::write(&path, "john@example.com john\n").unwrap();
+
+        let tables = LookupTables::new(&FieldServerLookupTables {
+            tables: [(
+                "virtual".to_owned(),
+                FieldLookupTable {
+                    r#type: LookupTableType::Hash,
+                    path: path.clone(),
+                },
+            )]
+            .into(),
+            reload_period: std::time::Duration::from_secs(10),
+        })
+        .unwrap();
+        assert_eq!(
+            lookup(&tables, "virtual", "john@example.com").as_deref(),
+            Some("john")
+        );
+
+        std::fs::write(&path, "john@example.com john, jenny\n").unwrap();
+        tables.reload_if_changed();
+        assert_eq!(
+            lookup(&tables, "virtual", "john@example.com").as_deref(),
+            Some("john, jenny")
+        );
+
+        // a file removed keeps the previous content
+        std::fs::remove_file(&path).unwrap();
+        tables.reload_if_changed();
+        assert_eq!(
+            lookup(&tables, "virtual", "john@example.com").as_deref(),
+            Some("john, jenny")
+        );
+    }
+}
@@ -0,0 +1,366 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2023 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use crate::watched_file::WatchedFile;
+use std::net::IpAddr;
+use vsmtp_common::{Address, AddressExtension, Domain, Reply};
+use vsmtp_config::field::FieldServerRecipients;
+
+/// Outcome of the check of an unknown recipient.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecipientVerdict {
+    /// The recipient is refused with this reply.
+    Unknown(Reply),
+    /// The client sent too many unknown recipients, and is disconnected with this reply.
+    Probing(Reply),
+    /// The recipient is unknown, and is rewritten to this catch-all mailbox of its domain.
+    CatchAll(Address),
+}
+
+/// Parse the mailboxes file, the malformed lines are skipped.
+fn parse(path: &std::path::Path, content: &str) -> std::collections::HashSet<String> {
+    let mut malformed = vec![];
+
+    let mailboxes = content
+        .lines()
+        .enumerate()
+        .filter_map(|(index, line)| {
+            let entry = line.split('#').next().unwrap_or_default().trim();
+            if entry.is_empty() {
+                return None;
+            }
+            if entry.contains(char::is_whitespace) || entry.starts_with('@') {
+                malformed.push(index + 1);
+                return None;
+            }
+            Some(entry.to_lowercase())
+        })
+        .collect();
+
+    if !malformed.is_empty() {
+        tracing::warn!(
+            path = %path.display(),
+            lines = ?malformed,
+            "Malformed lines of the mailboxes file skipped."
+        );
+    }
+
+    mailboxes
+}
+
+/// Mailboxes of the domains of the server, read again when the file is modified,
+/// and the unknown recipients recently sent by each client.
+#[derive(Debug)]
+pub struct Recipients {
+    file: WatchedFile<std::collections::HashSet<String>>,
+    reply: Reply,
+    probe_count_max: usize,
+    probe_window: time::Duration,
+    probe_reply: Reply,
+    /// the domains of the server, to which the local parts apply.
+    domains: Vec<Domain>,
+    /// delimiters of the extension of the local part, see `server.recipient_delimiter`.
+    delimiters: String,
+    probes: std::sync::Mutex<std::collections::HashMap<IpAddr, Vec<time::OffsetDateTime>>>,
+}
+
+impl Recipients {
+    /// Read the mailboxes file.
+    ///
+    /// # Errors
+    ///
+    /// * the file cannot be read
+    pub fn new(
+        config: &FieldServerRecipients,
+        domains: Vec<Domain>,
+        delimiters: String,
+    ) -> anyhow::Result<Self> {
+        Ok(Self {
+            file: WatchedFile::new(&config.path, "mailboxes", parse)?,
+            reply: config.reply.clone(),
+            probe_count_max: config.probe_count_max,
+            probe_window: time::Duration::try_from(config.probe_window)
+                .unwrap_or(time::Duration::MAX),
+            probe_reply: config.probe_reply.clone(),
+            domains,
+            delimiters,
+            probes: std::sync::Mutex::new(std::collections::HashMap::new()),
+        })
+    }
+
+    /// Read the file again if it has been modified since the last read.
+    ///
+    /// If the file cannot be read, the previous mailboxes are kept.
+    pub fn reload_if_changed(&self) {
+        match self.file.reload_if_changed() {
+            Ok(true) => tracing::info!(
+                path = %self.file.path().display(),
+                mailboxes = self.file.content().len(),
+                "Mailboxes reloaded."
+            ),
+            Ok(false) => (),
+            Err(error) => tracing::warn!(
+                %error,
+                "Mailboxes reload failure, the previous ones are kept."
+            ),
+        }
+    }
+
+    /// Is the mailbox of `rcpt` listed in the file ?
+    ///
+    /// The full address is looked up, then the address without its extension.
+    #[must_use]
+    pub fn is_known(&self, rcpt: &Address) -> bool {
+        self.is_known_with_extension(
+            rcpt,
+            AddressExtension::parse(rcpt, &self.delimiters).as_ref(),
+        )
+    }
+
+    /// Same as [`Recipients::is_known`], with the `extension` of `rcpt` already split
+    /// on the delimiters of `server.recipient_delimiter`.
+    #[must_use]
+    pub fn is_known_with_extension(
+        &self,
+        rcpt: &Address,
+        extension: Option<&AddressExtension>,
+    ) -> bool {
+        let mailboxes = self.file.content();
+        let local_part = rcpt.local_part().to_lowercase();
+        let domain = rcpt
+            .full()
+            .rsplit_once('@')
+            .map_or_else(String::new, |(_, domain)| domain.to_lowercase());
+        let is_local = self.domains.contains(rcpt.domain_ref());
+
+        let base = extension.map(|extension| extension.base.to_lowercase());
+
+        std::iter::once(local_part).chain(base).any(|user| {
+            mailboxes.contains(&format!("{user}@{domain}"))
+                || (is_local && mailboxes.contains(&user))
+        })
+    }
+
+    /// Record an unknown recipient sent by `client`, returning the number of unknown
+    /// recipients it sent in the window.
+    fn record_probe(&self, client: IpAddr) -> usize {
+        let now = vsmtp_common::clock::now();
+        let mut probes = self.probes.lock().expect("recipients poisoned");
+
+        probes.retain(|_, dates| {
+            dates.retain(|date| now - *date < self.probe_window);
+            !dates.is_empty()
+        });
+        let dates = probes.entry(client).or_default();
+        dates.push(now);
+        dates.len()
+    }
+
+    /// Check the recipient `rcpt` sent by `client`, of `extension`, returning [`None`]
+    /// if its mailbox exists or if it is an alias (`is_alias`).
+    ///
+    /// An unknown recipient of a domain having a `catch_all` mailbox is rewritten to it,
+    /// and is not counted as a probe.
+    #[must_use]
+    pub fn check(
+        &self,
+        client: IpAddr,
+        rcpt: &Address,
+        extension: Option<&AddressExtension>,
+        is_alias: bool,
+        catch_all: Option<&Address>,
+    ) -> Option<RecipientVerdict> {
+        if is_alias || self.is_known_with_extension(rcpt, extension) {
+            return None;
+        }
+        if let Some(catch_all) = catch_all {
+            tracing::debug!(%client, %rcpt, %catch_all, "Unknown recipient, rewritten to the catch-all.");
+            return Some(RecipientVerdict::CatchAll(catch_all.clone()));
+        }
+
+        let count = self.record_probe(client);
+        if count > self.probe_count_max {
+            tracing::warn!(%client, %rcpt, count, "Too many unknown recipients, disconnecting.");
+            Some(RecipientVerdict::Probing(self.probe_reply.clone()))
+        } else {
+            tracing::debug!(%client, %rcpt, "Unknown recipient.");
+            Some(RecipientVerdict::Unknown(self.reply.clone()))
+        }
+    }
+
+    /// Build the `recipients` module of vsl.
+    #[must_use]
+    pub fn module(self: &std::sync::Arc<Self>) -> rhai::Shared<rhai::Module> {
+        let mut module = rhai::Module::new();
+
+        let recipients = self.clone();
+        module.set_native_fn(
+            "is_known",
+            move |rcpt: &str| -> Result<bool, Box<rhai::EvalAltResult>> {
+                let rcpt = rcpt
+                    .parse::<Address>()
+                    .map_err::<Box<rhai::EvalAltResult>, _>(|e| e.to_string().into())?;
+                Ok(recipients.is_known(&rcpt))
+            },
+        );
+        let recipients = self.clone();
+        module.set_native_fn(
+            "is_known",
+            move |rcpt: crate::api::SharedObject| -> Result<bool, Box<rhai::EvalAltResult>> {
+                let rcpt = rcpt
+                    .to_string()
+                    .parse::<Address>()
+                    .map_err::<Box<rhai::EvalAltResult>, _>(|e| e.to_string().into())?;
+                Ok(recipients.is_known(&rcpt))
+            },
+        );
+
+        rhai::Shared::new(module)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn recipients(content: &str, probe_count_max: usize) -> (tempfile::NamedTempFile, Recipients) {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        std::io::Write::write_all(&mut file, content.as_bytes()).unwrap();
+
+        let recipients = Recipients::new(
+            &FieldServerRecipients {
+                path: file.path().to_path_buf(),
+                reply: "550 5.1.1 User unknown\r\n".parse().unwrap(),
+                probe_count_max,
+                probe_window: std::time::Duration::from_secs(600),
+                probe_reply: "421 4.7.0 Too many unknown recipients\r\n".parse().unwrap(),
+                reload_period: std::time::Duration::from_secs(10),
+            },
+            vec!["example.com".parse().unwrap()],
+            "+".to_owned(),
+        )
+        .unwrap();
+
+        (file, recipients)
+    }
+
+    #[test]
+    fn known() {
+        let (_file, recipients) = recipients(
+            "john # the local part\nJenny@Example.com\nbob@other.com\nbad entry\n",
+            5,
+        );
+        let is_known = |rcpt: &str| recipients.is_known(&rcpt.parse().unwrap());
+
+        assert!(is_known("john@example.com"));
+        assert!(is_known("John+lists@example.com"));
+        assert!(is_known("jenny@example.com"));
+        assert!(is_known("bob@other.com"));
+        assert!(!is_known("john@other.com"));
+        assert!(!is_known("bob@example.com"));
+        assert!(!is_known("bad@example.com"));
+    }
+
+    #[test]
+    fn known_with_delimiters() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        std::io::Write::write_all(&mut file, b"john\njohn-admin\n").unwrap();
+        let recipients = Recipients::new(
+            &FieldServerRecipients {
+                path: file.path().to_path_buf(),
+                reply: "550 5.1.1 User unknown\r\n".parse().unwrap(),
+                probe_count_max: 5,
+                probe_window: std::time::Duration::from_secs(600),
+                probe_reply: "421 4.7.0 Too many unknown recipients\r\n".parse().unwrap(),
+                reload_period: std::time::Duration::from_secs(10),
+            },
+            vec!["example.com".parse().unwrap()],
+            "-".to_owned(),
+        )
+        .unwrap();
+        let is_known = |rcpt: &str| recipients.is_known(&rcpt.parse().unwrap());
+
+        assert!(is_known("john-lists@example.com"));
+        assert!(is_known("john-admin@example.com"));
+        assert!(!is_known("john+lists@example.com"));
+    }
+
+    #[test]
+    fn probing() {
+        let clock = vsmtp_test::clock::TestClock::start();
+        let (_file, recipients) = recipients("john\n", 2);
+        let client = "10.0.0.1".parse().unwrap();
+        let check = |client: IpAddr, rcpt: &str, is_alias: bool| {
+            let rcpt = rcpt.parse().unwrap();
+            recipients.check(client, &rcpt, None, is_alias, None)
+        };
+
+        assert_eq!(check(client, "john@example.com", false), None);
+        assert_eq!(check(client, "info@example.com", true), None);
+        assert!(matches!(
+            check(client, "a@example.com", false),
+            Some(RecipientVerdict::Unknown(_))
+        ));
+        assert!(matches!(
+            check(client, "b@example.com", false),
+            Some(RecipientVerdict::Unknown(_))
+        ));
+        assert!(matches!(
+            check(client, "c@example.com", false),
+            Some(RecipientVerdict::Probing(_))
+        ));
+        // the other clients are not affected.
+        assert!(matches!(
+            check("10.0.0.2".parse().unwrap(), "a@example.com", false),
+            Some(RecipientVerdict::Unknown(_))
+        ));
+
+        clock.advance(time::Duration::minutes(10));
+        assert!(matches!(
+            check(client, "d@example.com", false),
+            Some(RecipientVerdict::Unknown(_))
+        ));
+    }
+
+    #[test]
+    fn catch_all() {
+        let (_file, recipients) = recipients("john\nall\n", 1);
+        let client = "10.0.0.1".parse().unwrap();
+        let catch_all = "all@example.com".parse::<Address>().unwrap();
+        let check = |rcpt: &str| {
+            recipients.check(
+                client,
+                &rcpt.parse().unwrap(),
+                None,
+                false,
+                Some(&catch_all),
+            )
+        };
+
+        assert_eq!(check("john@example.com"), None);
+        // the rewritten recipients are not counted as probes.
+        for rcpt in ["a@example.com", "b@example.com", "c@example.com"] {
+            assert_eq!(
+                check(rcpt),
+                Some(RecipientVerdict::CatchAll(catch_all.clone()))
+            );
+        }
+        assert!(matches!(
+            recipients.check(client, &"d@example.com".parse().unwrap(), None, false, None),
+            Some(RecipientVerdict::Unknown(_))
+        ));
+    }
+}
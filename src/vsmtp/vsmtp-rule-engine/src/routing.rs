@@ -0,0 +1,271 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2023 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+
+use anyhow::Context;
+use vsmtp_common::{
+    status,
+    transfer::{self, Status},
+    transport::{AbstractTransport, WrapperSerde},
+    Address, ContextFinished,
+};
+use vsmtp_config::{field::FieldQueueDeliveryRouting, Config, DnsResolvers};
+use vsmtp_delivery::{Deliver, Forward, SenderParameters};
+
+/// Name of the function of the root filter called before each attempt of delivery.
+pub(crate) const ROUTING_FN: &str = "on_delivery_attempt";
+
+/// Decision of the `on_delivery_attempt` function for a recipient.
+#[derive(Debug)]
+pub(crate) enum Route {
+    /// Keep the current transport.
+    Keep,
+    /// Send the recipient with another transport of the routing.
+    Transport(std::sync::Arc<dyn AbstractTransport>),
+    /// Defer the whole message.
+    Defer(std::time::Duration),
+}
+
+/// Transports the `on_delivery_attempt` function can select, see [`FieldQueueDeliveryRouting`].
+#[derive(Debug)]
+pub(crate) struct Routing {
+    transports: std::collections::BTreeMap<String, std::sync::Arc<dyn AbstractTransport>>,
+    timeout: std::time::Duration,
+    /// The functions of the root filter, `on_delivery_attempt` among them.
+    ast: std::sync::Arc<rhai::AST>,
+}
+
+impl Routing {
+    /// Instantiate the transports of the configuration.
+    /// Returns [`None`] if there is none, or if the root filter does not define the function.
+    pub(crate) fn new(
+        routing: &FieldQueueDeliveryRouting,
+        config: &std::sync::Arc<Config>,
+        resolvers: &DnsResolvers,
+        root_filter: &rhai::AST,
+    ) -> anyhow::Result<Option<Self>> {
+        if routing.transports.is_empty() {
+            return Ok(None);
+        }
+        if !root_filter
+            .iter_functions()
+            .any(|f| f.name == ROUTING_FN && f.params.len() == 4)
+        {
+            tracing::warn!(
+                "Transports are configured for the delivery routing, but the root filter does not define `{ROUTING_FN}(rcpt, transport, attempt, last_error)`."
+            );
+            return Ok(None);
+        }
+
+        let transports = routing
+            .transports
+            .iter()
+            .map(|(name, transport)| {
+                let transport: std::sync::Arc<dyn AbstractTransport> = if transport == "deliver" {
+                    std::sync::Arc::new(Deliver::new(resolvers.get_resolver_root(), config.clone()))
+                } else {
+                    std::sync::Arc::new(Forward::new(
+                        <SenderParameters as std::str::FromStr>::from_str(transport).with_context(
+                            || format!("invalid transport `{transport}` for the route `{name}`"),
+                        )?,
+                    ))
+                };
+                Ok((name.clone(), transport))
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        Ok(Some(Self {
+            transports,
+            timeout: routing.timeout,
+            ast: std::sync::Arc::new(root_filter.clone_functions_only()),
+        }))
+    }
+
+    /// Name given to the function for a transport: the one of its entry in the routing,
+    /// or its type (`deliver`, `forward`, `maildir` ...) otherwise.
+    fn name_of(&self, transport: &WrapperSerde) -> String {
+        let id = match transport {
+            WrapperSerde::Ready(transport) => transport.get_id(),
+            WrapperSerde::Raw(raw) => raw.clone(),
+        };
+        self.transports
+            .iter()
+            .find(|(_, i)| i.get_id() == id)
+            .map_or_else(
+                || {
+                    serde_json::from_str::<serde_json::Value>(&id)
+                        .ok()
+                        // NOTE: the transports are serialized as a string holding their json form.
+                        .and_then(|id| match id {
+                            serde_json::Value::String(inner) => serde_json::from_str(&inner).ok(),
+                            id => Some(id),
+                        })
+                        .and_then(|id| id.get("type")?.as_str().map(str::to_owned))
+                        .unwrap_or_default()
+                },
+                |(name, _)| name.clone(),
+            )
+    }
+
+    /// Call the function for each recipient to send, and move the recipients to the transports
+    /// it selected. Returns the delay to defer the message of, if the function asked for it.
+    ///
+    /// The `engine` is built once for the message. A call failing or exceeding the time budget
+    /// keeps the current transport, the budget being checked at each access to a variable.
+    pub(crate) fn route(
+        &self,
+        ctx: &mut ContextFinished,
+        engine: &dyn Fn(&ContextFinished) -> rhai::Engine,
+    ) -> Option<std::time::Duration> {
+        let mut engine = engine(ctx);
+        let deadline = std::sync::Arc::new(std::sync::Mutex::new(std::time::Instant::now()));
+        {
+            let deadline = deadline.clone();
+            // NOTE: on_var is not deprecated, just subject to change in future releases.
+            //       The operations are not counted (`unchecked`), `on_progress` is not available.
+            #[allow(deprecated)]
+            engine.on_var(move |_, _, _| {
+                if std::time::Instant::now() > *deadline.lock().expect("mutex poisoned") {
+                    Err(rhai::EvalAltResult::ErrorTerminated(
+                        rhai::Dynamic::UNIT,
+                        rhai::Position::NONE,
+                    )
+                    .into())
+                } else {
+                    Ok(None)
+                }
+            });
+        }
+
+        let mut moved = vec![];
+        let mut defer = None::<std::time::Duration>;
+
+        for (transport, rcpts) in &ctx.rcpt_to.delivery {
+            for (rcpt, status) in rcpts.iter().filter(|(_, status)| status.is_sendable()) {
+                *deadline.lock().expect("mutex poisoned") =
+                    std::time::Instant::now() + self.timeout;
+
+                match self.call(&engine, rcpt, &self.name_of(transport), status) {
+                    Route::Keep => {}
+                    Route::Transport(to) => {
+                        if !matches!(transport, WrapperSerde::Ready(from) if from.as_ref() == to.as_ref())
+                        {
+                            moved.push((rcpt.clone(), to));
+                        }
+                    }
+                    Route::Defer(delay) => {
+                        defer = Some(defer.map_or(delay, |i| i.max(delay)));
+                    }
+                }
+            }
+        }
+
+        if defer.is_some() {
+            return defer;
+        }
+
+        for (rcpt, to) in moved {
+            let mut entry = None;
+            for rcpts in ctx.rcpt_to.delivery.values_mut() {
+                if let Some(idx) = rcpts.iter().position(|(i, _)| *i == rcpt) {
+                    entry = Some(rcpts.swap_remove(idx));
+                }
+            }
+            if let Some(entry) = entry {
+                tracing::info!(%rcpt, transport = %to.get_id(), "Recipient routed by the rules.");
+                ctx.rcpt_to
+                    .delivery
+                    .entry(WrapperSerde::Ready(to))
+                    .or_default()
+                    .push(entry);
+            }
+        }
+        ctx.rcpt_to.delivery.retain(|_, rcpts| !rcpts.is_empty());
+
+        None
+    }
+
+    fn call(
+        &self,
+        engine: &rhai::Engine,
+        rcpt: &Address,
+        transport: &str,
+        status: &Status,
+    ) -> Route {
+        let (attempt, last_error) = match status {
+            Status::HeldBack { errors } => (
+                rhai::INT::try_from(errors.len() + 1).unwrap_or(rhai::INT::MAX),
+                errors.last().map_or(rhai::Dynamic::UNIT, last_error),
+            ),
+            _ => (1, rhai::Dynamic::UNIT),
+        };
+        let args = (rcpt.to_string(), transport.to_owned(), attempt, last_error);
+
+        // NOTE: the rules may block, the other tasks of the runtime are moved to another worker.
+        let result = tokio::task::block_in_place(|| {
+            engine.call_fn::<rhai::Dynamic>(&mut rhai::Scope::new(), &self.ast, ROUTING_FN, args)
+        });
+
+        match result {
+            Ok(route) => self.decide(&route),
+            Err(error) if matches!(*error, rhai::EvalAltResult::ErrorTerminated(..)) => {
+                tracing::warn!(%rcpt, timeout = ?self.timeout, "`{ROUTING_FN}` timed out, the transport is kept.");
+                Route::Keep
+            }
+            Err(error) => {
+                tracing::warn!(%rcpt, %error, "`{ROUTING_FN}` failed, the transport is kept.");
+                Route::Keep
+            }
+        }
+    }
+
+    fn decide(&self, route: &rhai::Dynamic) -> Route {
+        if route.is_unit() {
+            return Route::Keep;
+        }
+        if let Some(name) = route.read_lock::<rhai::ImmutableString>() {
+            return self.transports.get(name.as_str()).map_or_else(
+                || {
+                    tracing::warn!(
+                        name = name.as_str(),
+                        "`{ROUTING_FN}` returned an unknown transport, the transport is kept."
+                    );
+                    Route::Keep
+                },
+                |transport| Route::Transport(transport.clone()),
+            );
+        }
+        if let Some(status::Status::Defer(delay)) = route.read_lock::<status::Status>().as_deref() {
+            return Route::Defer(*delay);
+        }
+
+        tracing::warn!(%route, "`{ROUTING_FN}` returned an invalid value, the transport is kept.");
+        Route::Keep
+    }
+}
+
+/// The last error of a recipient, as given to the function.
+fn last_error(error: &transfer::Error) -> rhai::Dynamic {
+    rhai::Dynamic::from_map(rhai::Map::from_iter([
+        ("reason".into(), error.reason().to_string().into()),
+        (
+            "reply".into(),
+            error
+                .reply()
+                .map_or(rhai::Dynamic::UNIT, |reply| reply.to_owned().into()),
+        ),
+    ]))
+}
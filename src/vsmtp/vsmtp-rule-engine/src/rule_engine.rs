@@ -21,9 +21,13 @@ use crate::{
         directives::{Directive, Directives},
         smtp::service,
     },
+    routing::Routing,
     rule_state::RuleState,
     server_api::ServerAPI,
-    ExecutionStage, SubDomainHierarchy,
+    state_pool::{Skeleton, Slot, StatePool},
+    AccessLists, AccessVerdict, Aliases, Datasets, DecisionCache, ExecutionStage, Greylist,
+    LookupCache, LookupTables, RecipientVerdict, Recipients, RuleStatistics, Srs,
+    SubDomainHierarchy,
 };
 use anyhow::Context;
 use rhai::{
@@ -33,10 +37,16 @@ use rhai::{
 };
 use rhai_dylib::module_resolvers::libloading::DylibModuleResolver;
 use vqueue::{GenericQueueManager, QueueID};
-use vsmtp_common::{status::Status, Domain, Reply, TransactionType};
+use vsmtp_common::{
+    status::Status, Address, AddressExtension, ContextFinished, Domain, Reply, TransactionType,
+};
 use vsmtp_config::{Config, DnsResolvers};
+use vsmtp_delivery::Deliver;
 use vsmtp_mail_parser::MessageBody;
 
+/// Function of the root filter deciding if an authenticated user owns a sender address.
+const SENDER_OWNERSHIP_FN: &str = "is_sender_owned";
+
 /// a sharable rhai engine.
 /// contains an ast representation of the user's parsed .vsl script files,
 /// and modules / packages to create a cheap rhai runtime.
@@ -45,7 +55,21 @@ pub struct RuleEngine {
     pub(super) global_modules: Vec<rhai::Shared<rhai::Module>>,
     pub(super) static_modules: Vec<(String, rhai::Shared<rhai::Module>)>,
     pub(super) server: Server,
-    pub(super) rules: SubDomainHierarchy,
+    pub(super) rules: std::sync::RwLock<std::sync::Arc<SubDomainHierarchy>>,
+    /// Engine compiling the scripts of the configuration, [`None`] if the rules come from a builder.
+    pub(super) compiler: Option<rhai::Engine>,
+    pub(super) datasets: Datasets,
+    pub(super) statistics: std::sync::Arc<RuleStatistics>,
+    pub(super) access_lists: std::sync::Arc<AccessLists>,
+    pub(super) decision_cache: Option<DecisionCache>,
+    pub(super) greylist: std::sync::Arc<Greylist>,
+    pub(super) lookups: std::sync::Arc<LookupCache>,
+    pub(super) aliases: Option<std::sync::Arc<Aliases>>,
+    pub(super) recipients: Option<std::sync::Arc<Recipients>>,
+    pub(super) lookup_tables: Option<std::sync::Arc<LookupTables>>,
+    pub(super) srs: Option<Srs>,
+    pub(super) routing: std::sync::RwLock<Option<std::sync::Arc<Routing>>>,
+    pub(super) pool: std::sync::Arc<StatePool>,
 }
 
 #[cfg(feature = "builder")]
@@ -123,14 +147,123 @@ impl RuleEngine {
 
         tracing::debug!("Building static modules ...");
 
-        let static_modules = Self::build_static_modules(&mut engine, &config)?;
+        let mut static_modules = Self::build_static_modules(&mut engine, &config)?;
 
         tracing::debug!("Building global modules ...");
 
         let global_modules = Self::build_global_modules(&mut engine)?;
 
+        tracing::debug!("Loading datasets ...");
+
+        let datasets = Datasets::new(&config.app.vsl.datasets)?;
+        engine.register_static_module("data", datasets.snapshot());
+
+        tracing::debug!("Loading access lists ...");
+
+        let access_lists =
+            std::sync::Arc::new(AccessLists::new(config.server.access_lists.as_ref())?);
+        let lists = access_lists.module();
+        engine.register_static_module("lists", lists.clone());
+        static_modules.push(("lists".to_string(), lists));
+
+        // the module is registered again for each state, with its own flag.
+        let lookups = std::sync::Arc::new(LookupCache::new(&config.app.vsl.lookup_cache));
+        engine.register_static_module(
+            "cache",
+            DecisionCache::module(
+                std::sync::Arc::new(std::sync::atomic::AtomicBool::new(true)),
+                &lookups,
+            ),
+        );
+        let decision_cache = config
+            .app
+            .vsl
+            .decision_cache
+            .as_ref()
+            .map(DecisionCache::new);
+
+        let greylist = std::sync::Arc::new(Greylist::new(&config.app.vsl.greylist));
+        let greylist_module = greylist.module();
+        engine.register_static_module("greylist", greylist_module.clone());
+        static_modules.push(("greylist".to_string(), greylist_module));
+
+        tracing::debug!("Loading aliases ...");
+
+        let domains = std::iter::once(config.server.name.clone())
+            .chain(config.server.r#virtual.keys().cloned())
+            .collect::<Vec<_>>();
+        let aliases = config
+            .server
+            .aliases
+            .as_ref()
+            .map(|aliases| {
+                Aliases::new(
+                    aliases,
+                    domains.clone(),
+                    config.server.recipient_delimiter.clone(),
+                )
+                .map(std::sync::Arc::new)
+            })
+            .transpose()?;
+
+        let srs = config
+            .server
+            .srs
+            .as_ref()
+            .map(|srs| Srs::new(srs, &config.server.name, &domains))
+            .transpose()?;
+
+        tracing::debug!("Loading recipients ...");
+
+        let recipients = config
+            .server
+            .recipients
+            .as_ref()
+            .map(|recipients| {
+                Recipients::new(
+                    recipients,
+                    domains,
+                    config.server.recipient_delimiter.clone(),
+                )
+                .map(std::sync::Arc::new)
+            })
+            .transpose()?;
+        if let Some(recipients) = &recipients {
+            // NOTE: an unknown catch-all would be refused once the recipients are rewritten to it.
+            for (domain, entry) in &config.server.r#virtual {
+                if let Some(catch_all) = &entry.catch_all {
+                    anyhow::ensure!(
+                        recipients.is_known(catch_all)
+                            || aliases
+                                .as_ref()
+                                .map_or(false, |aliases| aliases.is_alias(catch_all)),
+                        "the catch-all `{catch_all}` of the domain `{domain}` is not a known mailbox"
+                    );
+                }
+            }
+
+            let module = recipients.module();
+            engine.register_static_module("recipients", module.clone());
+            static_modules.push(("recipients".to_string(), module));
+        }
+
+        tracing::debug!("Loading lookup tables ...");
+
+        let lookup_tables = config
+            .server
+            .lookup_tables
+            .as_ref()
+            .map(|tables| LookupTables::new(tables).map(std::sync::Arc::new))
+            .transpose()?;
+        if let Some(lookup_tables) = &lookup_tables {
+            let module = lookup_tables.module();
+            engine.register_static_module("table", module.clone());
+            static_modules.push(("table".to_string(), module));
+        }
+
         // Modules can use the configuration on startup. (i.e. when embedded in modules)
         let server = std::sync::Arc::new(ServerAPI {
+            delivery: std::sync::Arc::new(vsmtp_delivery::DeliveryState::new(&config)),
             config,
             resolvers,
             queue_manager,
@@ -167,14 +300,30 @@ impl RuleEngine {
 
         #[cfg(feature = "builder")]
         #[allow(clippy::used_underscore_binding)]
-        let rules = match _input {
-            either::Left(()) => SubDomainHierarchy::new(
-                &engine,
-                &server.config.app.vsl,
-                &server.config.server.r#virtual,
-            )?,
-            either::Right(builder) => builder(crate::Builder::new(&engine))?,
+        let (rules, compiler) = match _input {
+            either::Left(()) => (
+                SubDomainHierarchy::new(
+                    &engine,
+                    &server.config.app.vsl,
+                    &server.config.server.r#virtual,
+                )?,
+                Some(engine),
+            ),
+            either::Right(builder) => (builder(crate::Builder::new(&engine))?, None),
         };
+        #[cfg(not(feature = "builder"))]
+        let compiler = Some(engine);
+
+        let routing = Routing::new(
+            &server.config.server.queues.delivery.routing,
+            &server.config,
+            &server.resolvers,
+            rules.root_filter().ast(),
+        )?;
+
+        // the counters are allocated once all the directives are known.
+        let statistics = std::sync::Arc::new(RuleStatistics::new(&rules));
+        static_modules.push(("stats".to_string(), statistics.module()));
 
         tracing::info!("Rule engine initialized.");
 
@@ -185,11 +334,32 @@ impl RuleEngine {
             tracing::debug!(?type_id);
         }
 
+        tracing::debug!("Building the rule states pool ...");
+
+        // a connection is handled by a thread of the receiver pool at a time.
+        let pool = std::sync::Arc::new(StatePool::new(
+            server.config.server.system.thread_pool.receiver.get(),
+            || Self::build_skeleton(&global_modules, &static_modules, &server, &lookups),
+        ));
+
         Ok(Self {
             global_modules,
             static_modules,
             server,
-            rules,
+            rules: std::sync::RwLock::new(std::sync::Arc::new(rules)),
+            compiler,
+            datasets,
+            statistics,
+            access_lists,
+            decision_cache,
+            greylist,
+            lookups,
+            aliases,
+            recipients,
+            lookup_tables,
+            srs,
+            routing: std::sync::RwLock::new(routing.map(std::sync::Arc::new)),
+            pool,
         })
     }
 
@@ -210,6 +380,9 @@ impl RuleEngine {
     }
 
     /// build a cheap rhai engine with vsl's api.
+    ///
+    /// The engine is taken from the pool of the rule engine if one is available,
+    /// only the objects of the connection are set.
     pub fn spawn_finished(
         &self,
         mail_context: vsmtp_common::Context,
@@ -220,13 +393,44 @@ impl RuleEngine {
             std::sync::Arc::new(std::sync::RwLock::new(message)),
         );
 
-        let (mail_context_cpy, server_cpy, message_cpy) =
-            (mail_context.clone(), self.server.clone(), message.clone());
+        let mut skeleton = self.pool.acquire().unwrap_or_else(|| {
+            Self::build_skeleton(
+                &self.global_modules,
+                &self.static_modules,
+                &self.server,
+                &self.lookups,
+            )
+        });
+
+        // the snapshot is taken again for each transaction, see `Self::snapshot_datasets`.
+        skeleton
+            .engine
+            .register_static_module("data", self.datasets.snapshot());
+        skeleton.slot.fill(mail_context.clone(), message.clone());
+
+        std::sync::Arc::new(RuleState {
+            skeleton: Some(skeleton),
+            pool: std::sync::Arc::downgrade(&self.pool),
+            server: self.server.clone(),
+            mail_context,
+            message,
+        })
+    }
+
+    /// Build the engine of a [`RuleState`], without the objects of a connection.
+    fn build_skeleton(
+        global_modules: &[rhai::Shared<rhai::Module>],
+        static_modules: &[(String, rhai::Shared<rhai::Module>)],
+        server: &Server,
+        lookups: &std::sync::Arc<LookupCache>,
+    ) -> Skeleton {
+        let (slot, server_cpy) = (std::sync::Arc::new(Slot::default()), server.clone());
+        let (ctx_slot, msg_slot) = (slot.clone(), slot.clone());
 
         let mut engine = rhai::Engine::new_raw();
 
-        engine.register_fn("ctx", move || rhai::Dynamic::from(mail_context_cpy.clone()));
-        engine.register_fn("msg", move || rhai::Dynamic::from(message_cpy.clone()));
+        engine.register_fn("ctx", move || ctx_slot.mail_context());
+        engine.register_fn("msg", move || msg_slot.message());
         engine.register_fn("srv", move || rhai::Dynamic::from(server_cpy.clone()));
 
         #[cfg(debug_assertion)]
@@ -236,14 +440,17 @@ impl RuleEngine {
                 println!("{} @ {:?} > {}", src.unwrap_or("unknown source"), pos, s);
             });
 
-        self.global_modules.iter().for_each(|module| {
+        global_modules.iter().for_each(|module| {
             engine.register_global_module(module.clone());
         });
 
-        self.static_modules.iter().for_each(|(namespace, module)| {
+        static_modules.iter().for_each(|(namespace, module)| {
             engine.register_static_module(namespace, module.clone());
         });
 
+        let cacheable = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(true));
+        engine.register_static_module("cache", DecisionCache::module(cacheable.clone(), lookups));
+
         // FIXME: the following lines should be remove for performance improvement.
         //        need to check out how to construct directives as a module.
         engine
@@ -270,14 +477,271 @@ impl RuleEngine {
 
         engine.set_fast_operators(false);
 
-        std::sync::Arc::new(RuleState {
+        Skeleton {
             engine,
-            server: self.server.clone(),
-            mail_context,
-            message,
+            slot,
+            cacheable,
+        }
+    }
+
+    /// Read the datasets declared in the configuration again, without recompiling the rules.
+    ///
+    /// The transactions started after the reload see the new values,
+    /// the current values are kept if a dataset fails to load.
+    /// The decisions cached with the previous values are dropped.
+    ///
+    /// # Errors
+    ///
+    /// * see [`Datasets::reload`]
+    pub fn reload_datasets(&self) -> anyhow::Result<()> {
+        self.datasets.reload(&self.server.config.app.vsl.datasets)?;
+        if let Some(decision_cache) = &self.decision_cache {
+            decision_cache.clear();
+        }
+        Ok(())
+    }
+
+    /// Compile the scripts of `app.vsl` again, and swap them with the current rules.
+    ///
+    /// The stages run after the reload use the new rules, the current rules are kept
+    /// if a script fails to compile. The counters of the directives still declared are kept,
+    /// the decisions cached with the previous rules are dropped.
+    ///
+    /// # Errors
+    ///
+    /// * the rules have been built by a [`crate::Builder`], not from the configuration.
+    /// * see [`SubDomainHierarchy::new`] and [`Routing::new`]
+    pub fn reload_rules(&self) -> anyhow::Result<()> {
+        let compiler = self
+            .compiler
+            .as_ref()
+            .context("the rules have not been built from the configuration")?;
+        let config = &self.server.config;
+
+        let rules = SubDomainHierarchy::new(compiler, &config.app.vsl, &config.server.r#virtual)?;
+        let routing = Routing::new(
+            &config.server.queues.delivery.routing,
+            config,
+            &self.server.resolvers,
+            rules.root_filter().ast(),
+        )?;
+
+        self.statistics.allocate(&rules);
+        *self.rules.write().expect("rules poisoned") = std::sync::Arc::new(rules);
+        *self.routing.write().expect("routing poisoned") = routing.map(std::sync::Arc::new);
+        if let Some(decision_cache) = &self.decision_cache {
+            decision_cache.clear();
+        }
+
+        tracing::info!("Rules reloaded.");
+        Ok(())
+    }
+
+    /// Get the current rules.
+    pub(crate) fn rules(&self) -> std::sync::Arc<SubDomainHierarchy> {
+        self.rules.read().expect("rules poisoned").clone()
+    }
+
+    /// Expose the current values of the datasets to the rules of the transaction starting
+    /// on `state`, the previous transactions of the connection keeping their snapshot.
+    ///
+    /// The snapshot of the connection is kept if the state is shared.
+    pub fn snapshot_datasets(&self, state: &mut std::sync::Arc<RuleState>) {
+        match std::sync::Arc::get_mut(state).and_then(|state| state.skeleton.as_mut()) {
+            Some(skeleton) => {
+                skeleton
+                    .engine
+                    .register_static_module("data", self.datasets.snapshot());
+            }
+            None => tracing::debug!("State shared, the datasets snapshot is kept."),
+        }
+    }
+
+    /// Call the `is_sender_owned(authid, address)` function of the root filter, deciding if
+    /// an authenticated user owns a sender address not declared in `server.esmtp.auth.senders`.
+    ///
+    /// Returns `false` if the function is not defined, failed or did not return a boolean.
+    #[must_use]
+    pub fn is_sender_owned(&self, state: &RuleState, authid: &str, address: &Address) -> bool {
+        let rules = self.rules();
+        let ast = rules.root_filter().ast();
+        if !ast
+            .iter_functions()
+            .any(|f| f.name == SENDER_OWNERSHIP_FN && f.params.len() == 2)
+        {
+            return false;
+        }
+
+        state
+            .engine()
+            .call_fn::<bool>(
+                &mut Scope::new(),
+                ast,
+                SENDER_OWNERSHIP_FN,
+                (authid.to_owned(), address.to_string()),
+            )
+            .unwrap_or_else(|error| {
+                tracing::warn!(%authid, %address, %error, "`{SENDER_OWNERSHIP_FN}` failed, the address is not owned.");
+                false
+            })
+    }
+
+    /// Counters of the executions of the directives, and of the statuses they returned.
+    #[must_use]
+    pub fn statistics(&self) -> std::sync::Arc<RuleStatistics> {
+        self.statistics.clone()
+    }
+
+    /// Cache of the values computed by the rules, shared by the transactions.
+    #[must_use]
+    pub fn lookups(&self) -> std::sync::Arc<LookupCache> {
+        self.lookups.clone()
+    }
+
+    /// Aliases expanded before the delivery, if an aliases file is configured.
+    #[must_use]
+    pub fn aliases(&self) -> Option<std::sync::Arc<Aliases>> {
+        self.aliases.clone()
+    }
+
+    /// Lists of clients and sender domains consulted before the rules.
+    #[must_use]
+    pub fn access_lists(&self) -> std::sync::Arc<AccessLists> {
+        self.access_lists.clone()
+    }
+
+    /// Check the host which sent the message through the trusted relays against the
+    /// blocked clients of the access lists, if `trusted_upstreams.access_lists` is set.
+    ///
+    /// The client itself is checked on connection, [`None`] is returned if it is the origin.
+    #[must_use]
+    pub fn check_origin(&self, state: &RuleState) -> Option<AccessVerdict> {
+        let trusted = self.server.config.server.trusted_upstreams.as_ref()?;
+        if !trusted.access_lists {
+            return None;
+        }
+
+        let (context, message) = (state.context(), state.message());
+        let context = context.read().expect("state poisoned");
+        let origin = crate::trusted_upstreams::origin_of(
+            &self.server.config,
+            &context,
+            &message.read().expect("message poisoned"),
+        )
+        .ok()?;
+
+        if origin.ip == context.client_addr().ip() {
+            return None;
+        }
+        self.access_lists.check_client(origin.ip)
+    }
+
+    /// Store of the greylisting of the recipients, used by `greylist::rcpt()`.
+    #[must_use]
+    pub fn greylist(&self) -> std::sync::Arc<Greylist> {
+        self.greylist.clone()
+    }
+
+    /// Replace the aliases among the recipients by their targets, if an aliases file is configured.
+    ///
+    /// The targets outside of the domain of their alias are delivered with the `deliver` transport,
+    /// and their sender is rewritten if SRS is enabled.
+    pub fn expand_aliases(&self, ctx: &mut ContextFinished) {
+        if let Some(aliases) = &self.aliases {
+            let forwarded = aliases.expand_recipients(ctx, &|| {
+                std::sync::Arc::new(Deliver::new(
+                    self.server.resolvers.get_resolver_root(),
+                    self.server.config.clone(),
+                ))
+            });
+            if let Some(srs) = &self.srs {
+                srs.rewrite_forwarded(ctx, &forwarded, &|domain| self.is_handled_domain(domain));
+            }
+        }
+    }
+
+    /// Call the `on_delivery_attempt` function of the root filter for each recipient to send,
+    /// if transports are configured for the routing of the delivery.
+    ///
+    /// The recipients are moved to the transports selected by the function, unless it asked
+    /// to defer the message, the delay being returned then.
+    #[must_use]
+    pub fn route_delivery(&self, ctx: &mut ContextFinished) -> Option<std::time::Duration> {
+        let routing = self.routing.read().expect("routing poisoned").clone()?;
+        routing.route(ctx, &|ctx: &ContextFinished| {
+            let mut skeleton = Self::build_skeleton(
+                &self.global_modules,
+                &self.static_modules,
+                &self.server,
+                &self.lookups,
+            );
+            skeleton
+                .engine
+                .register_static_module("data", self.datasets.snapshot());
+            skeleton.slot.fill(
+                std::sync::Arc::new(std::sync::RwLock::new(vsmtp_common::Context::Finished(
+                    ctx.clone(),
+                ))),
+                std::sync::Arc::new(std::sync::RwLock::new(MessageBody::default())),
+            );
+            skeleton.engine
         })
     }
 
+    /// Sender Rewriting Scheme of the messages forwarded, if enabled.
+    #[must_use]
+    pub const fn srs(&self) -> Option<&Srs> {
+        self.srs.as_ref()
+    }
+
+    /// Mailboxes of the domains of the server, if a mailboxes file is configured.
+    #[must_use]
+    pub fn recipients(&self) -> Option<std::sync::Arc<Recipients>> {
+        self.recipients.clone()
+    }
+
+    /// Postfix-style lookup tables of the configuration, if any.
+    #[must_use]
+    pub fn lookup_tables(&self) -> Option<std::sync::Arc<LookupTables>> {
+        self.lookup_tables.clone()
+    }
+
+    /// Check the existence of the mailbox of `rcpt`, sent by `client`, an alias being known.
+    /// Returns [`None`] if it exists or if no mailboxes file is configured.
+    ///
+    /// The `extension` of `rcpt` is the one recorded in the context, see
+    /// [`Context::set_rcpt_extension`](vsmtp_common::Context::set_rcpt_extension).
+    ///
+    /// An unknown recipient is rewritten to the catch-all mailbox of its virtual domain, if any.
+    #[must_use]
+    pub fn check_recipient(
+        &self,
+        client: std::net::IpAddr,
+        rcpt: &Address,
+        extension: Option<&AddressExtension>,
+    ) -> Option<RecipientVerdict> {
+        let is_alias = self
+            .aliases
+            .as_ref()
+            .map_or(false, |aliases| aliases.is_alias(rcpt));
+        let catch_all = self
+            .server
+            .config
+            .server
+            .r#virtual
+            .get(rcpt.domain_ref())
+            .and_then(|entry| entry.catch_all.as_ref());
+        self.recipients
+            .as_ref()?
+            .check(client, rcpt, extension, is_alias, catch_all)
+    }
+
+    /// Cache of the decisions of the `connect` and `helo` stages, if enabled.
+    #[must_use]
+    pub const fn decision_cache(&self) -> Option<&DecisionCache> {
+        self.decision_cache.as_ref()
+    }
+
     /// Get the subset of directive to continue the execution of rules after a delegation.
     /// at this point, any ill formed input will produce an error.
     #[allow(clippy::cognitive_complexity)]
@@ -302,7 +766,7 @@ impl RuleEngine {
             .ok_or_else(|| err!("500 Delegation header not found"))?;
         let header = vsmtp_mail_parser::get_mime_header("X-VSMTP-DELEGATION", &header);
 
-        tracing::debug!(%header, "Got header for delegation");
+        tracing::debug!(?header, "Got header for delegation");
         let (directive_name, msg_uuid) = match (
             header.args.get("stage"),
             header.args.get("directive"),
@@ -383,11 +847,13 @@ impl RuleEngine {
         skipped: &mut Option<Status>,
         smtp_state: ExecutionStage,
     ) -> Status {
+        // the rules are reloaded between two stages at most.
+        let rules = self.rules();
         let script = {
             let context = rule_state.context();
             let context = context.read().expect("Mutex poisoned");
 
-            match self.get_directives_for_smtp_state(&context, smtp_state) {
+            match Self::get_directives_for_smtp_state(&rules, &context, smtp_state) {
                 Ok(script) => script,
                 Err(_) => {
                     return Status::Deny(
@@ -439,7 +905,39 @@ impl RuleEngine {
             }
         };
 
-        let status = Script::execute(rule_state, script.ast(), directive, smtp_state);
+        let cache_key = self
+            .decision_cache
+            .as_ref()
+            .filter(|_| skipped.is_none())
+            .and_then(|cache| {
+                let context = rule_state.context();
+                let context = context.read().expect("Mutex poisoned");
+                DecisionCache::key(&context, smtp_state).map(|key| (cache, key))
+            });
+
+        if let Some(status) = cache_key.as_ref().and_then(|(cache, key)| cache.get(key)) {
+            tracing::debug!(?status, "Using the decision cached for this client.");
+            rule_state.emit_policy_event(smtp_state, None, &status);
+            if status.is_finished() {
+                *skipped = Some(status.clone());
+            }
+            return status;
+        }
+
+        rule_state.set_cacheable(true);
+        let status = Script::execute(
+            rule_state,
+            script.ast(),
+            directive,
+            smtp_state,
+            &self.statistics,
+        );
+
+        if let Some((cache, key)) = cache_key {
+            if rule_state.is_cacheable() {
+                cache.insert(key, &status);
+            }
+        }
 
         if status.is_finished() {
             tracing::info!(
@@ -475,23 +973,24 @@ impl RuleEngine {
     /// The transaction context is whether the email is incoming, outgoing or internal.
     #[tracing::instrument(skip_all, err)]
     fn get_directives_for_smtp_state<'a>(
-        &'a self,
+        hierarchy: &'a SubDomainHierarchy,
         context: &vsmtp_common::Context,
         smtp_state: ExecutionStage,
     ) -> anyhow::Result<&'a Script> {
         match smtp_state {
-            ExecutionStage::Connect | ExecutionStage::Helo | ExecutionStage::Authenticate => {
-                Ok(self.rules.root_filter())
-            }
+            ExecutionStage::Connect
+            | ExecutionStage::Helo
+            | ExecutionStage::Authenticate
+            | ExecutionStage::Disconnect => Ok(hierarchy.root_filter()),
 
             ExecutionStage::MailFrom => Ok(context
                 .reverse_path()
                 .context("bad state")?
                 .as_ref()
-                .and_then(|reverse_path| self.rules.get_any(&reverse_path.domain()))
+                .and_then(|reverse_path| hierarchy.get_any(reverse_path.domain_ref()))
                 .map_or_else(
-                    || self.rules.root_filter(),
-                    |domain| self.rules.outgoing(domain),
+                    || hierarchy.root_filter(),
+                    |domain| hierarchy.outgoing(domain),
                 )),
 
             ExecutionStage::RcptTo => {
@@ -508,27 +1007,27 @@ impl RuleEngine {
                     .context("reverse_path not found in rcpt stage")?;
 
                 Ok(reverse_path.as_ref().map_or_else(
-                    || self.rules.root_filter(),
-                    |reverse_path| self.rules.get_any(&reverse_path.domain()).map_or_else(
-                        || if let (Some(rules), TransactionType::Incoming(Some(_))) = (self.rules.get_any(&rcpt.domain()), transaction_type) {
+                    || hierarchy.root_filter(),
+                    |reverse_path| hierarchy.get_any(reverse_path.domain_ref()).map_or_else(
+                        || if let (Some(rules), TransactionType::Incoming(Some(_))) = (hierarchy.get_any(rcpt.domain_ref()), transaction_type) {
                             tracing::debug!(%rcpt, "Incoming recipient.");
-                            self.rules.incoming(rules)
+                            hierarchy.incoming(rules)
                         } else {
                             tracing::debug!(%rcpt, "Recipient unknown in unknown sender context, running fallback script.");
-                            self.rules.root_filter()
+                            hierarchy.root_filter()
                         },
                         |rules| match transaction_type {
                             TransactionType::Internal => {
                                 tracing::debug!(%rcpt, %reverse_path, "Internal email for current recipient.");
-                                self.rules.internal(rules)
+                                hierarchy.internal(rules)
                             }
                             TransactionType::Outgoing { .. } => {
                                 tracing::debug!(%rcpt, %reverse_path, "Outgoing email for current recipient.");
-                                self.rules.outgoing(rules)
+                                hierarchy.outgoing(rules)
                             }
                             TransactionType::Incoming(_) => {
                                 tracing::error!(%rcpt, %reverse_path, "email is supposed to be internal / outgoing but the sender's domain was not found in your vSL scripts.");
-                                self.rules.fallback()
+                                hierarchy.fallback()
                             }
                         })
                 ))
@@ -543,29 +1042,29 @@ impl RuleEngine {
                     .context("sender not found in rcpt stage")?;
 
                 Ok(reverse_path.as_ref().map_or_else(
-                    || self.rules.root_filter(),
-                    |reverse_path| self.rules.get_any(&reverse_path.domain()).map_or_else(
+                    || hierarchy.root_filter(),
+                    |reverse_path| hierarchy.get_any(reverse_path.domain_ref()).map_or_else(
                         || match transaction_type {
                             TransactionType::Incoming(Some(domain)) => {
-                                self.rules.get_any(domain).map_or_else(
-                                || self.rules.fallback(),
-                                |rules| self.rules.incoming(rules))
+                                hierarchy.get_any(domain).map_or_else(
+                                || hierarchy.fallback(),
+                                |rules| hierarchy.incoming(rules))
                             }
                             TransactionType::Incoming(None) => {
                                 tracing::info!("No recipient has a domain handled by your configuration, running root incoming script");
-                                self.rules.root_filter()
+                                hierarchy.root_filter()
                             }
                             TransactionType::Outgoing { .. } | TransactionType::Internal => {
                                 tracing::error!("email is supposed to incoming but was marked has outgoing, running fallback scripts.");
-                                self.rules.fallback()
+                                hierarchy.fallback()
                             }
                         },
                         |rules| match transaction_type {
-                            TransactionType::Internal => self.rules.internal(rules),
-                            TransactionType::Outgoing { .. } => self.rules.outgoing(rules),
+                            TransactionType::Internal => hierarchy.internal(rules),
+                            TransactionType::Outgoing { .. } => hierarchy.outgoing(rules),
                             TransactionType::Incoming(_) => {
                                 tracing::error!(%reverse_path, "email is supposed to be outgoing / internal but the sender's domain was not found in your vSL scripts.");
-                                self.rules.fallback()
+                                hierarchy.fallback()
                             }
                         }
                     )
@@ -738,7 +1237,14 @@ impl RuleEngine {
                                     .try_cast::<std::sync::Arc<service::Smtp>>()
                                     .ok_or_else(|| anyhow::anyhow!("the field after the 'delegate' keyword in the directive '{name}' in stage '{stage}' must be a smtp service"))?;
 
-                                Directive::Delegation { name, pointer, service }
+                                let payload = map
+                                    .get("send")
+                                    .map(|send| send.to_string().parse::<crate::dsl::directives::delegation::Payload>())
+                                    .transpose()
+                                    .with_context(|| format!("invalid 'send' property of the delegation '{name}' in stage '{stage}'"))?
+                                    .unwrap_or_default();
+
+                                Directive::Delegation { name, pointer, service, payload }
                             },
                             unknown => anyhow::bail!("unknown directive type '{unknown}' called '{name}'"),
                         };
@@ -771,33 +1277,32 @@ impl RuleEngine {
     /// Return `true` if the given domain **or any parent domain** is handled by the configuration.
     #[must_use]
     pub fn is_handled_domain(&self, domain: &Domain) -> bool {
-        self.rules.get_any(domain).is_some()
+        self.rules().get_any(domain).is_some()
+    }
+
+    /// Domains of `app.vsl.domain_dir` whose scripts failed to compile, and the reason.
+    /// Their transactions are denied by the fallback rules.
+    #[must_use]
+    pub fn domain_errors(&self) -> std::collections::BTreeMap<Domain, String> {
+        self.rules().errors().clone()
     }
 
-    /// Find the delegate directive that matches the given socket.
+    /// Is there a delegate directive that matches the given socket.
     #[must_use]
     #[cfg(feature = "delegation")]
-    pub fn get_delegation_directive_bound_to_address(
-        &self,
-        socket: std::net::SocketAddr,
-    ) -> Option<&Directive> {
-        let per_domain_scripts = self.rules.get_all().flat_map(|d| {
-            [
-                self.rules.incoming(d),
-                self.rules.internal(d),
-                self.rules.outgoing(d),
-            ]
-            .into_iter()
-        });
+    pub fn has_delegation_bound_to_address(&self, socket: std::net::SocketAddr) -> bool {
+        let rules = self.rules();
+        let per_domain_scripts = rules
+            .get_all()
+            .flat_map(|d| [rules.incoming(d), rules.internal(d), rules.outgoing(d)].into_iter());
 
-        std::iter::once(self.rules.root_filter())
+        let bound = std::iter::once(rules.root_filter())
             .chain(per_domain_scripts)
-            .filter_map(|script| {
-                script.directives().find(|d| {
+            .any(|script| {
+                script.directives().any(|d| {
                     matches!(d, Directive::Delegation { service, .. } if service.receiver == socket)
                 })
-            })
-            .take(1)
-            .next()
+            });
+        bound
     }
 }
@@ -14,19 +14,35 @@
  * this program. If not, see https://www.gnu.org/licenses/.
  *
 */
-use crate::api::{Context, Message, Server};
+use crate::{
+    api::{Context, Message, Server},
+    state_pool::{Skeleton, StatePool},
+    ExecutionStage,
+};
+use vsmtp_common::{
+    policy_event::{Kind, PolicyEvent},
+    status::Status,
+};
 use vsmtp_mail_parser::MessageBody;
 
 /// a state container that bridges rhai's & rust contexts.
 #[derive(Debug)]
 pub struct RuleState {
-    pub(super) engine: rhai::Engine,
+    /// Given back to the pool when the state is dropped.
+    pub(super) skeleton: Option<Skeleton>,
+    pub(super) pool: std::sync::Weak<StatePool>,
     pub(super) server: Server,
     pub(super) mail_context: Context,
     pub(super) message: Message,
 }
 
 impl RuleState {
+    fn skeleton(&self) -> &Skeleton {
+        self.skeleton
+            .as_ref()
+            .expect("the skeleton is only taken on drop")
+    }
+
     /// Fetch the email context (possibly) mutated by the user's rules.
     #[must_use]
     pub fn context(&self) -> Context {
@@ -47,26 +63,81 @@ impl RuleState {
 
     /// get the engine used to evaluate rules for this state.
     #[must_use]
-    pub const fn engine(&self) -> &rhai::Engine {
-        &self.engine
+    pub fn engine(&self) -> &rhai::Engine {
+        &self.skeleton().engine
+    }
+
+    /// Can the status of the stage being run be cached ?
+    #[must_use]
+    pub fn is_cacheable(&self) -> bool {
+        self.skeleton()
+            .cacheable
+            .load(std::sync::atomic::Ordering::Relaxed)
+    }
+
+    pub(crate) fn set_cacheable(&self, cacheable: bool) {
+        self.skeleton()
+            .cacheable
+            .store(cacheable, std::sync::atomic::Ordering::Relaxed);
+    }
+
+    /// Emit the policy event of a status denying or rejecting the transaction at `stage`,
+    /// returned by the `rule` if known.
+    pub(crate) fn emit_policy_event(
+        &self,
+        stage: ExecutionStage,
+        rule: Option<&str>,
+        status: &Status,
+    ) {
+        let (kind, reply) = match status {
+            Status::Deny(reply) => (Kind::Deny, reply),
+            Status::Reject(reply) => (Kind::Reject, reply),
+            _ => return,
+        };
+
+        let context = self.mail_context.read().expect("Mutex poisoned");
+        let mut event = PolicyEvent::new(kind, &context)
+            .with_stage(stage.to_string())
+            .with_reply(reply);
+        if stage == ExecutionStage::RcptTo {
+            if let Some(rcpt) = context.forward_paths().ok().and_then(|rcpt| rcpt.last()) {
+                event = event.with_recipient(rcpt);
+            }
+            if let Some(raw) = context.raw_rcpt().ok().and_then(<[String]>::last) {
+                event = event.with_raw_rcpt(raw);
+            }
+        }
+        if let Some(rule) = rule {
+            event = event.with_rule(rule);
+        }
+        event.emit();
     }
 
     /// Consume the instance and return the inner [`Context`] and [`MessageBody`]
     #[must_use]
     pub fn take(self: std::sync::Arc<Self>) -> (vsmtp_common::Context, MessageBody) {
         let this = std::sync::Arc::try_unwrap(self).expect("Arc: strong reference alive");
+        let (mail_context, message) = (this.mail_context.clone(), this.message.clone());
 
-        // early drop of engine because a strong reference is living inside
-        drop(this.engine);
+        // early drop of the state because a strong reference is living inside the skeleton
+        drop(this);
         (
-            std::sync::Arc::try_unwrap(this.mail_context)
+            std::sync::Arc::try_unwrap(mail_context)
                 .expect("Arc: strong reference alive")
                 .into_inner()
                 .expect("RwLock: is poisoned"),
-            std::sync::Arc::try_unwrap(this.message)
+            std::sync::Arc::try_unwrap(message)
                 .expect("Arc: strong reference alive")
                 .into_inner()
                 .expect("RwLock: is poisoned"),
         )
     }
 }
+
+impl Drop for RuleState {
+    fn drop(&mut self) {
+        if let (Some(skeleton), Some(pool)) = (self.skeleton.take(), self.pool.upgrade()) {
+            pool.release(skeleton);
+        }
+    }
+}
@@ -23,4 +23,19 @@ pub struct ServerAPI {
     pub config: std::sync::Arc<Config>,
     pub resolvers: std::sync::Arc<DnsResolvers>,
     pub queue_manager: std::sync::Arc<dyn GenericQueueManager>,
+    /// The state of the outgoing connections of the deliveries.
+    pub delivery: std::sync::Arc<vsmtp_delivery::DeliveryState>,
+}
+
+impl ServerAPI {
+    /// The limits of the MIME parsing of the messages, see `server.mime` of the configuration.
+    #[must_use]
+    pub fn mime_limits(&self) -> vsmtp_mail_parser::MimeLimits {
+        let mime = &self.config.server.mime;
+        vsmtp_mail_parser::MimeLimits {
+            max_depth: mime.max_depth,
+            max_parts: mime.max_parts,
+            max_boundary_length: mime.max_boundary_length,
+        }
+    }
 }
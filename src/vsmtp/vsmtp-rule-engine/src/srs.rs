@@ -0,0 +1,456 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2023 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use hmac::Mac;
+use vsmtp_common::{Address, ContextFinished, Domain};
+use vsmtp_config::field::FieldServerSrs;
+
+type HmacSha1 = hmac::Hmac<sha1::Sha1>;
+
+/// Alphabet of the timestamps, the base32 of RFC 4648.
+const BASE32: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+/// Length of the hashes, in base64 characters.
+const HASH_LEN: usize = 4;
+/// The timestamps are a number of days, modulo `2^10`.
+const STAMP_MODULO: u64 = 1024;
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// The domain of an address, as written.
+fn domain_part(addr: &Address) -> &str {
+    addr.full()
+        .rsplit_once('@')
+        .map_or_else(|| addr.full(), |(_, domain)| domain)
+}
+
+/// The local part following the tag `SRS0` or `SRS1` and its separator, if any.
+fn strip_tag<'a>(local_part: &'a str, tag: &str) -> Option<&'a str> {
+    if !local_part.get(..tag.len())?.eq_ignore_ascii_case(tag) {
+        return None;
+    }
+    local_part
+        .get(tag.len()..)?
+        .strip_prefix(|c| matches!(c, '=' | '+' | '-'))
+}
+
+/// Number of days since the epoch, modulo [`STAMP_MODULO`].
+fn today() -> u64 {
+    u64::try_from(vsmtp_common::clock::now().unix_timestamp()).unwrap_or_default() / SECONDS_PER_DAY
+        % STAMP_MODULO
+}
+
+fn encode_stamp(stamp: u64) -> String {
+    [stamp >> 5, stamp]
+        .into_iter()
+        .map(|i| char::from(BASE32[usize::try_from(i % 32).unwrap_or_default()]))
+        .collect()
+}
+
+fn decode_stamp(stamp: &str) -> Option<u64> {
+    if stamp.len() != 2 {
+        return None;
+    }
+    stamp.bytes().try_fold(0, |value, c| {
+        let index = BASE32.iter().position(|i| *i == c.to_ascii_uppercase())?;
+        Some(value * 32 + u64::try_from(index).ok()?)
+    })
+}
+
+/// Sender Rewriting Scheme (SRS) of the messages forwarded off-domain, see [`FieldServerSrs`].
+///
+/// The hashes are a HMAC-SHA1 of the fields, lowercase, truncated to 4 base64 characters
+/// and compared ignoring the case, as the local parts may not be preserved by the forwarders.
+#[derive(Debug)]
+pub struct Srs {
+    domain: Domain,
+    secrets: Vec<String>,
+    max_age_days: u64,
+}
+
+impl Srs {
+    /// Create the rewriting of the senders to `config.domain`, or `server_name`.
+    ///
+    /// # Errors
+    ///
+    /// * no secret is configured
+    /// * the domain is not one of `domains`
+    pub fn new(
+        config: &FieldServerSrs,
+        server_name: &Domain,
+        domains: &[Domain],
+    ) -> anyhow::Result<Self> {
+        anyhow::ensure!(
+            !config.secrets.is_empty(),
+            "a secret is required to rewrite the senders with SRS"
+        );
+        let domain = config.domain.as_ref().unwrap_or(server_name);
+        anyhow::ensure!(
+            domains.contains(domain),
+            "the SRS domain `{domain}` is not a domain of the server"
+        );
+
+        Ok(Self {
+            domain: domain.clone(),
+            secrets: config.secrets.clone(),
+            max_age_days: config.max_age.as_secs() / SECONDS_PER_DAY,
+        })
+    }
+
+    fn hash(secret: &str, fields: &[&str]) -> String {
+        let mut mac =
+            HmacSha1::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+        for field in fields {
+            mac.update(field.to_lowercase().as_bytes());
+        }
+        let mut hash = STANDARD.encode(mac.finalize().into_bytes());
+        hash.truncate(HASH_LEN);
+        hash
+    }
+
+    fn is_valid_hash(&self, hash: &str, fields: &[&str]) -> bool {
+        self.secrets
+            .iter()
+            .any(|secret| Self::hash(secret, fields).eq_ignore_ascii_case(hash))
+    }
+
+    fn address(&self, local_part: &str) -> Result<Address, String> {
+        let domain = self.domain.to_string();
+        format!("{local_part}@{}", domain.trim_end_matches('.'))
+            .parse::<Address>()
+            .map_err(|e| format!("failed to rewrite to `{local_part}`: {e}"))
+    }
+
+    /// Rewrite the sender of a message forwarded: `SRS0=HHHH=TT=example.com=user@<domain>`
+    /// for `user@example.com`, or `SRS1=HHHH=forwarder.com==...@<domain>` for a sender
+    /// already rewritten by `forwarder.com`.
+    ///
+    /// # Errors
+    ///
+    /// * the address rewritten is not valid
+    pub fn forward(&self, sender: &Address) -> Result<Address, String> {
+        // NOTE: there is at least one secret, checked in `new`.
+        let secret = &self.secrets[0];
+        let domain = domain_part(sender);
+
+        if let Some(opaque) = strip_tag(sender.local_part(), "SRS0") {
+            let hash = Self::hash(secret, &[domain, opaque]);
+            return self.address(&format!("SRS1={hash}={domain}=={opaque}"));
+        }
+        // NOTE: the first forwarder is kept, the bounce is sent back to it directly.
+        if let Some(rest) = strip_tag(sender.local_part(), "SRS1") {
+            let mut fields = rest.splitn(3, '=');
+            if let (Some(_), Some(first), Some(opaque)) = (
+                fields.next(),
+                fields.next(),
+                fields.next().and_then(|opaque| opaque.strip_prefix('=')),
+            ) {
+                let hash = Self::hash(secret, &[first, opaque]);
+                return self.address(&format!("SRS1={hash}={first}=={opaque}"));
+            }
+        }
+
+        let stamp = encode_stamp(today());
+        let local_part = sender.local_part();
+        let hash = Self::hash(secret, &[&stamp, domain, local_part]);
+        self.address(&format!("SRS0={hash}={stamp}={domain}={local_part}"))
+    }
+
+    /// Get the original sender of an address rewritten, the recipient of a bounce.
+    ///
+    /// Returns [`None`] if `rcpt` is not of the SRS domain or not rewritten.
+    ///
+    /// # Errors
+    ///
+    /// * the address is malformed
+    /// * the hash is not produced by one of the secrets
+    /// * the address is older than the maximum age
+    pub fn reverse(&self, rcpt: &Address) -> Result<Option<Address>, String> {
+        if rcpt.domain() != self.domain {
+            return Ok(None);
+        }
+
+        if let Some(rest) = strip_tag(rcpt.local_part(), "SRS0") {
+            let mut fields = rest.splitn(4, '=');
+            let (Some(hash), Some(stamp), Some(domain), Some(local_part)) =
+                (fields.next(), fields.next(), fields.next(), fields.next())
+            else {
+                return Err("malformed SRS0 address".to_string());
+            };
+            if !self.is_valid_hash(hash, &[stamp, domain, local_part]) {
+                return Err("invalid hash".to_string());
+            }
+            let stamp = decode_stamp(stamp).ok_or_else(|| "invalid timestamp".to_string())?;
+            if (today() + STAMP_MODULO - stamp) % STAMP_MODULO > self.max_age_days {
+                return Err("expired address".to_string());
+            }
+
+            return format!("{local_part}@{domain}")
+                .parse::<Address>()
+                .map(Some)
+                .map_err(|e| format!("invalid original sender: {e}"));
+        }
+
+        if let Some(rest) = strip_tag(rcpt.local_part(), "SRS1") {
+            let mut fields = rest.splitn(3, '=');
+            let (Some(hash), Some(first), Some(opaque)) = (
+                fields.next(),
+                fields.next(),
+                fields.next().and_then(|opaque| opaque.strip_prefix('=')),
+            ) else {
+                return Err("malformed SRS1 address".to_string());
+            };
+            if !self.is_valid_hash(hash, &[first, opaque]) {
+                return Err("invalid hash".to_string());
+            }
+
+            return format!("SRS0={opaque}@{first}")
+                .parse::<Address>()
+                .map(Some)
+                .map_err(|e| format!("invalid first forwarder: {e}"));
+        }
+
+        Ok(None)
+    }
+
+    /// Rewrite the sender of `ctx` for the recipients `forwarded` outside of the domains
+    /// of the server, unless the sender is of a domain of the server or the rules have
+    /// already chosen a reverse path for them.
+    pub fn rewrite_forwarded(
+        &self,
+        ctx: &mut ContextFinished,
+        forwarded: &[Address],
+        is_local: &dyn Fn(&Domain) -> bool,
+    ) {
+        let Some(sender) = ctx.mail_from.reverse_path.as_ref() else {
+            return;
+        };
+        if is_local(sender.domain_ref()) {
+            return;
+        }
+        let targets = forwarded
+            .iter()
+            .filter(|target| !is_local(target.domain_ref()))
+            .collect::<Vec<_>>();
+        if targets.is_empty() {
+            return;
+        }
+
+        match self.forward(sender) {
+            Ok(rewritten) => {
+                tracing::debug!(%sender, %rewritten, ?targets, "Sender rewritten with SRS.");
+                for target in targets {
+                    ctx.rcpt_to
+                        .reverse_paths
+                        .entry(target.clone())
+                        .or_insert_with(|| rewritten.clone());
+                }
+            }
+            Err(reason) => {
+                tracing::warn!(%sender, %reason, "Sender cannot be rewritten with SRS.");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vsmtp_common::addr;
+    use vsmtp_test::clock::TestClock;
+
+    fn srs(secrets: &[&str]) -> Srs {
+        Srs::new(
+            &FieldServerSrs {
+                domain: Some("forwarder.com".parse().unwrap()),
+                secrets: secrets.iter().map(ToString::to_string).collect(),
+                max_age: std::time::Duration::from_secs(21 * SECONDS_PER_DAY),
+            },
+            &"example.com".parse().unwrap(),
+            &[
+                "example.com".parse().unwrap(),
+                "forwarder.com".parse().unwrap(),
+            ],
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn config() {
+        let config = FieldServerSrs {
+            domain: None,
+            secrets: vec![],
+            max_age: std::time::Duration::from_secs(SECONDS_PER_DAY),
+        };
+        let domains = ["example.com".parse().unwrap()];
+        assert!(Srs::new(&config, &"example.com".parse().unwrap(), &domains).is_err());
+
+        let config = FieldServerSrs {
+            secrets: vec!["secret".to_string()],
+            ..config
+        };
+        assert!(Srs::new(&config, &"example.com".parse().unwrap(), &domains).is_ok());
+        assert!(Srs::new(&config, &"other.com".parse().unwrap(), &domains).is_err());
+    }
+
+    #[test]
+    fn round_trip() {
+        let _clock = TestClock::start();
+        let srs = srs(&["secret"]);
+
+        let sender = addr!("john.doe+tag@example.org");
+        let rewritten = srs.forward(&sender).unwrap();
+        assert!(rewritten.local_part().starts_with("SRS0="));
+        assert!(rewritten
+            .local_part()
+            .ends_with("=example.org=john.doe+tag"));
+        assert_eq!(rewritten.domain(), "forwarder.com".parse().unwrap());
+
+        assert_eq!(srs.reverse(&rewritten), Ok(Some(sender)));
+        // the case of the local part may not be preserved.
+        assert!(srs
+            .reverse(&rewritten.full().to_lowercase().parse().unwrap())
+            .unwrap()
+            .is_some());
+
+        assert_eq!(srs.reverse(&addr!("john@forwarder.com")), Ok(None));
+        assert_eq!(
+            srs.reverse(&addr!("SRS0=abcd=AA=a.com=b@other.com")),
+            Ok(None)
+        );
+    }
+
+    #[test]
+    fn second_forwarder() {
+        let _clock = TestClock::start();
+        let first = srs(&["first"]);
+        let second = Srs {
+            domain: "second.com".parse().unwrap(),
+            ..srs(&["second"])
+        };
+        let third = Srs {
+            domain: "third.com".parse().unwrap(),
+            ..srs(&["third"])
+        };
+
+        let sender = addr!("john@example.org");
+        let once = first.forward(&sender).unwrap();
+        let twice = second.forward(&once).unwrap();
+        assert!(twice.local_part().starts_with("SRS1="));
+        assert!(twice.local_part().contains("=forwarder.com=="));
+
+        // the bounce is sent back to the first forwarder.
+        let thrice = third.forward(&twice).unwrap();
+        assert!(thrice.local_part().contains("=forwarder.com=="));
+        assert_eq!(third.reverse(&thrice), Ok(Some(once.clone())));
+
+        assert_eq!(second.reverse(&twice), Ok(Some(once.clone())));
+        assert_eq!(first.reverse(&once), Ok(Some(sender)));
+    }
+
+    #[test]
+    fn expired() {
+        let clock = TestClock::start();
+        let srs = srs(&["secret"]);
+
+        let sender = addr!("john@example.org");
+        let rewritten = srs.forward(&sender).unwrap();
+
+        clock.advance(time::Duration::days(21));
+        assert_eq!(srs.reverse(&rewritten), Ok(Some(sender)));
+
+        clock.advance(time::Duration::days(1));
+        assert_eq!(srs.reverse(&rewritten), Err("expired address".to_string()));
+    }
+
+    #[test]
+    fn invalid_hash() {
+        let _clock = TestClock::start();
+        let srs = srs(&["secret"]);
+
+        let rewritten = srs.forward(&addr!("john@example.org")).unwrap();
+        let forged = rewritten.full().replace("john", "jenny");
+        assert_eq!(
+            srs.reverse(&forged.parse().unwrap()),
+            Err("invalid hash".to_string())
+        );
+        assert_eq!(
+            srs.reverse(&addr!("SRS0=john@forwarder.com")),
+            Err("malformed SRS0 address".to_string())
+        );
+        assert_eq!(
+            srs.reverse(&addr!("SRS1=abcd=example.org=john@forwarder.com")),
+            Err("malformed SRS1 address".to_string())
+        );
+
+        let other = self::srs(&["other"]);
+        assert_eq!(other.reverse(&rewritten), Err("invalid hash".to_string()));
+    }
+
+    #[test]
+    fn rotation() {
+        let _clock = TestClock::start();
+        let sender = addr!("john@example.org");
+        let rewritten = srs(&["old"]).forward(&sender).unwrap();
+
+        // the previous secret is still accepted.
+        let rotated = srs(&["new", "old"]);
+        assert_eq!(rotated.reverse(&rewritten), Ok(Some(sender.clone())));
+        assert_ne!(rotated.forward(&sender).unwrap(), rewritten);
+
+        assert_eq!(
+            srs(&["new"]).reverse(&rewritten),
+            Err("invalid hash".to_string())
+        );
+    }
+
+    #[test]
+    fn rewrite_forwarded() {
+        let srs = srs(&["secret"]);
+        let is_local = |domain: &Domain| *domain == "example.com".parse::<Domain>().unwrap();
+
+        let mut ctx = vsmtp_test::config::local_ctx();
+        ctx.mail_from.reverse_path = Some(addr!("john@example.org"));
+        ctx.rcpt_to
+            .reverse_paths
+            .insert(addr!("bob@other.com"), addr!("bounce@example.com"));
+        srs.rewrite_forwarded(
+            &mut ctx,
+            &[
+                addr!("jenny@other.com"),
+                addr!("bob@other.com"),
+                addr!("local@example.com"),
+            ],
+            &is_local,
+        );
+
+        assert_eq!(ctx.rcpt_to.reverse_paths.len(), 2);
+        assert_eq!(
+            srs.reverse(&ctx.rcpt_to.reverse_paths[&addr!("jenny@other.com")]),
+            Ok(Some(addr!("john@example.org")))
+        );
+        assert_eq!(
+            ctx.rcpt_to.reverse_paths[&addr!("bob@other.com")],
+            addr!("bounce@example.com")
+        );
+
+        // the senders of the server are not rewritten.
+        let mut ctx = vsmtp_test::config::local_ctx();
+        ctx.mail_from.reverse_path = Some(addr!("john@example.com"));
+        srs.rewrite_forwarded(&mut ctx, &[addr!("jenny@other.com")], &is_local);
+        assert!(ctx.rcpt_to.reverse_paths.is_empty());
+    }
+}
@@ -0,0 +1,112 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2022 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use crate::api::{Context, Message};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// The objects of the connection using a [`Skeleton`], returned by the
+/// `ctx()` and `msg()` functions of its engine.
+#[derive(Debug, Default)]
+pub(crate) struct Slot {
+    mail_context: std::sync::RwLock<Option<Context>>,
+    message: std::sync::RwLock<Option<Message>>,
+}
+
+impl Slot {
+    pub(crate) fn mail_context(&self) -> rhai::Dynamic {
+        self.mail_context
+            .read()
+            .expect("RwLock: is poisoned")
+            .clone()
+            .map_or(rhai::Dynamic::UNIT, rhai::Dynamic::from)
+    }
+
+    pub(crate) fn message(&self) -> rhai::Dynamic {
+        self.message
+            .read()
+            .expect("RwLock: is poisoned")
+            .clone()
+            .map_or(rhai::Dynamic::UNIT, rhai::Dynamic::from)
+    }
+
+    pub(crate) fn fill(&self, mail_context: Context, message: Message) {
+        *self.mail_context.write().expect("RwLock: is poisoned") = Some(mail_context);
+        *self.message.write().expect("RwLock: is poisoned") = Some(message);
+    }
+
+    fn clear(&self) {
+        *self.mail_context.write().expect("RwLock: is poisoned") = None;
+        *self.message.write().expect("RwLock: is poisoned") = None;
+    }
+}
+
+/// The part of a [`crate::RuleState`] which does not depend on the connection:
+/// an engine with the modules of vsl registered, reading the connection's
+/// objects from its [`Slot`].
+#[derive(Debug)]
+pub(crate) struct Skeleton {
+    pub(crate) engine: rhai::Engine,
+    pub(crate) slot: std::sync::Arc<Slot>,
+    /// Cleared by the directives with side effects, see [`crate::DecisionCache`].
+    pub(crate) cacheable: std::sync::Arc<AtomicBool>,
+}
+
+impl Skeleton {
+    /// Remove everything left by the previous connection.
+    fn reset(&self) {
+        self.slot.clear();
+        self.cacheable.store(true, Ordering::Relaxed);
+    }
+}
+
+/// The skeletons built in advance, so that accepting a connection only fills a [`Slot`].
+///
+/// A skeleton is taken by a [`crate::RuleState`] and given back, reset, when the
+/// state is dropped. The pool keeps at most one skeleton by thread of the `receiver`
+/// pool, a skeleton is built on demand if none is available.
+#[derive(Debug)]
+pub(crate) struct StatePool {
+    skeletons: std::sync::Mutex<Vec<Skeleton>>,
+    capacity: usize,
+}
+
+impl StatePool {
+    pub(crate) fn new(capacity: usize, build: impl Fn() -> Skeleton) -> Self {
+        Self {
+            skeletons: std::sync::Mutex::new((0..capacity).map(|_| build()).collect()),
+            capacity,
+        }
+    }
+
+    pub(crate) fn acquire(&self) -> Option<Skeleton> {
+        self.skeletons.lock().expect("Mutex: is poisoned").pop()
+    }
+
+    pub(crate) fn release(&self, skeleton: Skeleton) {
+        skeleton.reset();
+
+        let mut skeletons = self.skeletons.lock().expect("Mutex: is poisoned");
+        if skeletons.len() < self.capacity {
+            skeletons.push(skeleton);
+        }
+    }
+
+    /// Number of skeletons available.
+    #[cfg(test)]
+    pub(crate) fn len(&self) -> usize {
+        self.skeletons.lock().expect("Mutex: is poisoned").len()
+    }
+}
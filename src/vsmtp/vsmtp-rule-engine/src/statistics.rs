@@ -0,0 +1,298 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2023 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use crate::{ExecutionStage, SubDomainHierarchy};
+use std::sync::atomic::{AtomicU64, Ordering};
+use vsmtp_common::status::Status;
+
+/// Names of the statuses a directive can return, as reported by the statistics.
+const STATUSES: [&str; 9] = [
+    "next",
+    "accept",
+    "reject",
+    "deny",
+    "faccept",
+    "quarantine",
+    "delegated",
+    "delegation_result",
+    "defer",
+];
+
+const fn status_index(status: &Status) -> usize {
+    match status {
+        Status::Next => 0,
+        Status::Accept(_) => 1,
+        Status::Reject(_) => 2,
+        Status::Deny(_) => 3,
+        Status::Faccept(_) => 4,
+        Status::Quarantine(_) => 5,
+        Status::Delegated(_) => 6,
+        Status::DelegationResult => 7,
+        Status::Defer(_) => 8,
+    }
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    executions: AtomicU64,
+    statuses: [AtomicU64; STATUSES.len()],
+}
+
+impl Counters {
+    fn load(&self) -> RuleHits {
+        RuleHits {
+            executions: self.executions.load(Ordering::Relaxed),
+            statuses: std::array::from_fn(|i| self.statuses[i].load(Ordering::Relaxed)),
+        }
+    }
+
+    fn take(&self) -> RuleHits {
+        RuleHits {
+            executions: self.executions.swap(0, Ordering::Relaxed),
+            statuses: std::array::from_fn(|i| self.statuses[i].swap(0, Ordering::Relaxed)),
+        }
+    }
+}
+
+/// Number of executions of a directive, and of each status it returned.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct RuleHits {
+    /// Number of times the directive has been run.
+    pub executions: u64,
+    /// Number of times each status has been returned, indexed like the names of [`RuleHits::statuses`].
+    pub statuses: [u64; STATUSES.len()],
+}
+
+impl RuleHits {
+    /// Name of the statuses and the number of times they have been returned.
+    pub fn statuses(&self) -> impl Iterator<Item = (&'static str, u64)> {
+        STATUSES.into_iter().zip(self.statuses)
+    }
+
+    /// Number of times `status` (`"accept"`, `"deny"`, ...) has been returned.
+    #[must_use]
+    pub fn get(&self, status: &str) -> Option<u64> {
+        self.statuses()
+            .find_map(|(name, count)| (name == status).then_some(count))
+    }
+
+    fn add(&mut self, other: &Self) {
+        self.executions += other.executions;
+        for (total, count) in self.statuses.iter_mut().zip(other.statuses) {
+            *total += count;
+        }
+    }
+}
+
+/// Counters of the executions of each directive, and of the statuses they returned,
+/// per stage and name of directive.
+///
+/// The counters are allocated when the rules are compiled, recording an execution
+/// only takes a shared lock, replaced when the rules are reloaded. The directives
+/// sharing a name and a stage in different scripts share the same counters.
+#[derive(Debug, Default)]
+pub struct RuleStatistics {
+    rules: std::sync::RwLock<Directives>,
+}
+
+type Directives =
+    std::collections::BTreeMap<ExecutionStage, std::collections::BTreeMap<String, Counters>>;
+
+impl RuleStatistics {
+    /// Allocate the counters of every directive of the hierarchy.
+    #[must_use]
+    pub fn new(rules: &SubDomainHierarchy) -> Self {
+        let statistics = Self::default();
+        statistics.allocate(rules);
+        statistics
+    }
+
+    /// Allocate the counters of the directives of reloaded rules.
+    ///
+    /// The counters of the directives still declared are kept, the others are dropped.
+    pub(crate) fn allocate(&self, rules: &SubDomainHierarchy) {
+        let mut current = self.rules.write().expect("statistics poisoned");
+        let mut previous = std::mem::take(&mut *current);
+
+        for (stage, directive) in rules
+            .scripts()
+            .flat_map(crate::domain_hierarchy::tree::Script::staged_directives)
+        {
+            let name = directive.name().to_string();
+            let counters = previous
+                .get_mut(&stage)
+                .and_then(|rules| rules.remove(&name))
+                .unwrap_or_default();
+            current
+                .entry(stage)
+                .or_default()
+                .entry(name)
+                .or_insert(counters);
+        }
+    }
+
+    fn collect<T>(&self, f: impl Fn(ExecutionStage, &String, &Counters) -> T) -> Vec<T> {
+        let f = &f;
+        self.rules
+            .read()
+            .expect("statistics poisoned")
+            .iter()
+            .flat_map(|(stage, rules)| {
+                rules
+                    .iter()
+                    .map(move |(name, counters)| f(*stage, name, counters))
+            })
+            .collect()
+    }
+
+    /// Count an execution of the directive `name`, which returned `status`.
+    pub fn record(&self, stage: ExecutionStage, name: &str, status: &Status) {
+        let rules = self.rules.read().expect("statistics poisoned");
+        if let Some(counters) = rules.get(&stage).and_then(|rules| rules.get(name)) {
+            counters.executions.fetch_add(1, Ordering::Relaxed);
+            counters.statuses[status_index(status)].fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Get the counters of the directive `name` at `stage`.
+    #[must_use]
+    pub fn get(&self, stage: ExecutionStage, name: &str) -> Option<RuleHits> {
+        self.rules
+            .read()
+            .expect("statistics poisoned")
+            .get(&stage)
+            .and_then(|rules| rules.get(name))
+            .map(Counters::load)
+    }
+
+    /// Get the counters of the directive `name`, summed over all the stages.
+    #[must_use]
+    pub fn rule(&self, name: &str) -> Option<RuleHits> {
+        self.rules
+            .read()
+            .expect("statistics poisoned")
+            .values()
+            .filter_map(|rules| rules.get(name))
+            .fold(None, |total, counters| {
+                let mut total = total.unwrap_or_default();
+                total.add(&counters.load());
+                Some(total)
+            })
+    }
+
+    /// Get the counters of all the directives, per stage and name.
+    #[must_use]
+    pub fn snapshot(&self) -> Vec<(ExecutionStage, String, RuleHits)> {
+        self.collect(|stage, name, counters| (stage, name.clone(), counters.load()))
+    }
+
+    /// Set all the counters to zero, logging their values before the reset.
+    pub fn reset(&self) {
+        let snapshot = self.collect(|stage, name, counters| (stage, name.clone(), counters.take()));
+
+        tracing::info!(
+            statistics = ?Self::report_snapshot(&snapshot),
+            "Rule statistics reset."
+        );
+    }
+
+    /// Describe the counters, one line per stage and directive.
+    #[must_use]
+    pub fn report(&self) -> Vec<String> {
+        Self::report_snapshot(&self.snapshot())
+    }
+
+    fn report_snapshot(snapshot: &[(ExecutionStage, String, RuleHits)]) -> Vec<String> {
+        snapshot
+            .iter()
+            .map(|(stage, name, hits)| {
+                let mut line = format!("{stage} \"{name}\" executions={}", hits.executions);
+                for (status, count) in hits.statuses().filter(|(_, count)| *count != 0) {
+                    line.push_str(&format!(" {status}={count}"));
+                }
+                line
+            })
+            .collect()
+    }
+
+    /// Render the counters in the Prometheus text format.
+    #[must_use]
+    pub fn metrics(&self) -> String {
+        let snapshot = self.snapshot();
+        let mut output = String::new();
+
+        output.push_str(
+            "# HELP vsmtp_rule_executions_total Executions of the rules per stage.\n# TYPE vsmtp_rule_executions_total counter\n",
+        );
+        for (stage, name, hits) in &snapshot {
+            output.push_str(&format!(
+                "vsmtp_rule_executions_total{{stage=\"{stage}\",rule=\"{}\"}} {}\n",
+                escape_label(name),
+                hits.executions
+            ));
+        }
+
+        output.push_str(
+            "# HELP vsmtp_rule_statuses_total Statuses returned by the rules per stage.\n# TYPE vsmtp_rule_statuses_total counter\n",
+        );
+        for (stage, name, hits) in &snapshot {
+            for (status, count) in hits.statuses() {
+                output.push_str(&format!(
+                    "vsmtp_rule_statuses_total{{stage=\"{stage}\",rule=\"{}\",status=\"{status}\"}} {count}\n",
+                    escape_label(name),
+                ));
+            }
+        }
+
+        output
+    }
+
+    /// Build the `stats` module of vsl, reading the counters of `self`.
+    #[must_use]
+    pub fn module(self: &std::sync::Arc<Self>) -> rhai::Shared<rhai::Module> {
+        let mut module = rhai::Module::new();
+
+        let statistics = self.clone();
+        module.set_native_fn(
+            "rule",
+            move |name: &str| -> Result<rhai::Map, Box<rhai::EvalAltResult>> {
+                let hits = statistics
+                    .rule(name)
+                    .ok_or_else(|| format!("the rule '{name}' does not exist"))?;
+
+                let mut map = rhai::Map::new();
+                map.insert("executions".into(), Self::to_int(hits.executions));
+                for (status, count) in hits.statuses() {
+                    map.insert(status.into(), Self::to_int(count));
+                }
+                Ok(map)
+            },
+        );
+
+        rhai::Shared::new(module)
+    }
+
+    fn to_int(count: u64) -> rhai::Dynamic {
+        rhai::Dynamic::from_int(rhai::INT::try_from(count).unwrap_or(rhai::INT::MAX))
+    }
+}
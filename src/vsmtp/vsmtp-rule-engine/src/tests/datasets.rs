@@ -0,0 +1,98 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2022 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+
+use crate::{ExecutionStage, RuleEngine};
+use vqueue::GenericQueueManager;
+use vsmtp_common::status::Status;
+use vsmtp_config::{field::FieldDataset, DnsResolvers};
+use vsmtp_test::config::local_test;
+
+const PLAN_RULES: &str = r#"
+#{
+  connect: [
+    rule "check plan" || if data::plans.acme == "gold" { state::accept() } else { state::deny() }
+  ]
+}
+"#;
+
+fn spawn_and_run(rule_engine: &RuleEngine) -> Status {
+    let state = rule_engine.spawn_at_connect(
+        "127.0.0.1:25".parse().unwrap(),
+        "127.0.0.1:25".parse().unwrap(),
+        "testserver.com".parse().unwrap(),
+        time::OffsetDateTime::now_utc(),
+        uuid::Uuid::new_v4(),
+    );
+    rule_engine.run_when(&state, &mut None, ExecutionStage::Connect)
+}
+
+#[test]
+fn reload_datasets() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("plans.json");
+    std::fs::write(&path, r#"{ "acme": "gold" }"#).unwrap();
+
+    let mut config = local_test();
+    config
+        .app
+        .vsl
+        .datasets
+        .insert("plans".to_string(), FieldDataset::File { path: path.clone() });
+    let config = std::sync::Arc::new(config);
+    let queue_manger = vqueue::temp::QueueManager::init(config.clone(), vec![]).unwrap();
+    let dns_resolvers = std::sync::Arc::new(DnsResolvers::from_config(&config).unwrap());
+
+    let rule_engine = RuleEngine::with_hierarchy(
+        |builder| Ok(builder.add_root_filter_rules(PLAN_RULES)?.build()),
+        config,
+        dns_resolvers,
+        queue_manger,
+    )
+    .unwrap();
+
+    assert!(matches!(spawn_and_run(&rule_engine), Status::Accept(_)));
+
+    // a transaction started before the reload keeps its snapshot.
+    let mut started = rule_engine.spawn_at_connect(
+        "127.0.0.1:25".parse().unwrap(),
+        "127.0.0.1:25".parse().unwrap(),
+        "testserver.com".parse().unwrap(),
+        time::OffsetDateTime::now_utc(),
+        uuid::Uuid::new_v4(),
+    );
+
+    std::fs::write(&path, r#"{ "acme": "bronze" }"#).unwrap();
+    rule_engine.reload_datasets().unwrap();
+
+    assert!(matches!(spawn_and_run(&rule_engine), Status::Deny(_)));
+    assert!(matches!(
+        rule_engine.run_when(&started, &mut None, ExecutionStage::Connect),
+        Status::Accept(_)
+    ));
+
+    // the next transaction of the connection sees the new values.
+    rule_engine.snapshot_datasets(&mut started);
+    assert!(matches!(
+        rule_engine.run_when(&started, &mut None, ExecutionStage::Connect),
+        Status::Deny(_)
+    ));
+
+    // an invalid file does not replace the current values.
+    std::fs::write(&path, r#"{ "acme": 1.5 }"#).unwrap();
+    rule_engine.reload_datasets().unwrap_err();
+    assert!(matches!(spawn_and_run(&rule_engine), Status::Deny(_)));
+}
@@ -0,0 +1,248 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2023 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use crate::{ExecutionStage, RuleEngine};
+use vqueue::GenericQueueManager;
+use vsmtp_common::{status::Status, ClientName};
+use vsmtp_config::{
+    field::{FieldAppVSLDecisionCache, FieldDataset},
+    Config, DnsResolvers,
+};
+use vsmtp_test::config::local_test;
+
+const RULES: &str = r#"
+#{
+  connect: [
+    rule "deny flood" || if ctx::client_ip() == "10.0.0.1" { state::deny() } else { state::next() },
+  ],
+  helo: [
+    rule "helo with side effects" || {
+      cache::skip();
+      state::next()
+    },
+  ],
+  authenticate: [
+    rule "not cached" || state::next(),
+  ]
+}
+"#;
+
+fn rule_engine(config: Config) -> RuleEngine {
+    let config = std::sync::Arc::new(config);
+    let queue_manger = vqueue::temp::QueueManager::init(config.clone(), vec![]).unwrap();
+    let dns_resolvers = std::sync::Arc::new(DnsResolvers::from_config(&config).unwrap());
+
+    RuleEngine::with_hierarchy(
+        |builder| Ok(builder.add_root_filter_rules(RULES)?.build()),
+        config,
+        dns_resolvers,
+        queue_manger,
+    )
+    .unwrap()
+}
+
+fn run(
+    rule_engine: &RuleEngine,
+    client: &str,
+    helo: Option<&str>,
+    stage: ExecutionStage,
+) -> Status {
+    let state = rule_engine.spawn_at_connect(
+        client.parse().unwrap(),
+        "127.0.0.1:25".parse().unwrap(),
+        "testserver.com".parse().unwrap(),
+        time::OffsetDateTime::now_utc(),
+        uuid::Uuid::new_v4(),
+    );
+    if let Some(helo) = helo {
+        state
+            .context()
+            .write()
+            .unwrap()
+            .to_helo(ClientName::Domain(helo.parse().unwrap()), false)
+            .unwrap();
+    }
+    rule_engine.run_when(&state, &mut None, stage)
+}
+
+fn executions(rule_engine: &RuleEngine, stage: ExecutionStage, name: &str) -> u64 {
+    rule_engine
+        .statistics()
+        .get(stage, name)
+        .unwrap()
+        .executions
+}
+
+#[test]
+fn cached_decisions() {
+    let mut config = local_test();
+    config.app.vsl.decision_cache = Some(FieldAppVSLDecisionCache {
+        capacity: 100,
+        ttl: std::time::Duration::from_secs(60),
+    });
+    let rule_engine = rule_engine(config);
+
+    for port in 10000..10003 {
+        assert!(matches!(
+            run(
+                &rule_engine,
+                &format!("10.0.0.1:{port}"),
+                None,
+                ExecutionStage::Connect
+            ),
+            Status::Deny(_)
+        ));
+    }
+    assert_eq!(
+        executions(&rule_engine, ExecutionStage::Connect, "deny flood"),
+        1
+    );
+
+    assert_eq!(
+        run(
+            &rule_engine,
+            "10.0.0.2:10000",
+            None,
+            ExecutionStage::Connect
+        ),
+        Status::Next
+    );
+    assert_eq!(
+        executions(&rule_engine, ExecutionStage::Connect, "deny flood"),
+        2
+    );
+
+    // the rule opted out of the cache.
+    for _ in 0..2 {
+        assert_eq!(
+            run(
+                &rule_engine,
+                "10.0.0.2:10000",
+                Some("client.com"),
+                ExecutionStage::Helo
+            ),
+            Status::Next
+        );
+    }
+    assert_eq!(
+        executions(&rule_engine, ExecutionStage::Helo, "helo with side effects"),
+        2
+    );
+
+    // only the connect and helo stages are cached.
+    for _ in 0..2 {
+        run(
+            &rule_engine,
+            "10.0.0.2:10000",
+            Some("client.com"),
+            ExecutionStage::Authenticate,
+        );
+    }
+    assert_eq!(
+        executions(&rule_engine, ExecutionStage::Authenticate, "not cached"),
+        2
+    );
+    assert_eq!(rule_engine.decision_cache().unwrap().len(), 2);
+}
+
+#[test]
+fn disabled_by_default() {
+    let rule_engine = rule_engine(local_test());
+    assert!(rule_engine.decision_cache().is_none());
+
+    for _ in 0..2 {
+        run(
+            &rule_engine,
+            "10.0.0.1:10000",
+            None,
+            ExecutionStage::Connect,
+        );
+    }
+    assert_eq!(
+        executions(&rule_engine, ExecutionStage::Connect, "deny flood"),
+        2
+    );
+}
+
+#[test]
+fn cleared_on_reload() {
+    let dir = tempfile::tempdir().unwrap();
+    let (filter, plans) = (dir.path().join("filter.vsl"), dir.path().join("plans.json"));
+    std::fs::write(
+        &filter,
+        r#"#{ connect: [ rule "check plan" || if data::plans.acme == "gold" { state::accept() } else { state::deny() } ] }"#,
+    )
+    .unwrap();
+    std::fs::write(&plans, r#"{ "acme": "gold" }"#).unwrap();
+
+    let mut config = local_test();
+    config.app.vsl.filter_path = Some(filter.clone());
+    config.app.vsl.datasets.insert(
+        "plans".to_string(),
+        FieldDataset::File {
+            path: plans.clone(),
+        },
+    );
+    config.app.vsl.decision_cache = Some(FieldAppVSLDecisionCache {
+        capacity: 100,
+        ttl: std::time::Duration::from_secs(60),
+    });
+    let config = std::sync::Arc::new(config);
+    let queue_manger = vqueue::temp::QueueManager::init(config.clone(), vec![]).unwrap();
+    let dns_resolvers = std::sync::Arc::new(DnsResolvers::from_config(&config).unwrap());
+    let rule_engine = RuleEngine::new(config, dns_resolvers, queue_manger).unwrap();
+
+    assert!(matches!(
+        run(
+            &rule_engine,
+            "10.0.0.1:10000",
+            None,
+            ExecutionStage::Connect
+        ),
+        Status::Accept(_)
+    ));
+    assert_eq!(rule_engine.decision_cache().unwrap().len(), 1);
+
+    std::fs::write(&plans, r#"{ "acme": "bronze" }"#).unwrap();
+    rule_engine.reload_datasets().unwrap();
+    assert!(rule_engine.decision_cache().unwrap().is_empty());
+    assert!(matches!(
+        run(
+            &rule_engine,
+            "10.0.0.1:10000",
+            None,
+            ExecutionStage::Connect
+        ),
+        Status::Deny(_)
+    ));
+
+    std::fs::write(
+        &filter,
+        r#"#{ connect: [ rule "check plan" || state::accept() ] }"#,
+    )
+    .unwrap();
+    rule_engine.reload_rules().unwrap();
+    assert!(rule_engine.decision_cache().unwrap().is_empty());
+    assert!(matches!(
+        run(
+            &rule_engine,
+            "10.0.0.1:10000",
+            None,
+            ExecutionStage::Connect
+        ),
+        Status::Accept(_)
+    ));
+}
@@ -0,0 +1,54 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2023 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use crate::{domain_hierarchy::tree::Script, RuleEngine};
+use vqueue::GenericQueueManager;
+use vsmtp_config::DnsResolvers;
+use vsmtp_test::config::local_test;
+
+fn names(script: &Script) -> Vec<String> {
+    script.directives().map(|d| d.name().to_owned()).collect()
+}
+
+#[test]
+fn per_domain_rules() {
+    let mut config = local_test();
+    config.app.vsl.domain_dir = Some("src/tests/domain_hierarchy".into());
+    config.app.vsl.filter_path = Some("src/tests/domain_hierarchy/filter.vsl".into());
+    let config = std::sync::Arc::new(config);
+    let queue_manger = vqueue::temp::QueueManager::init(config.clone(), vec![]).unwrap();
+    let dns_resolvers = std::sync::Arc::new(DnsResolvers::from_config(&config).unwrap());
+
+    // a domain failing to compile does not prevent the others to load.
+    let rule_engine = RuleEngine::new(config, dns_resolvers, queue_manger).unwrap();
+
+    assert_eq!(
+        rule_engine.domain_errors().keys().collect::<Vec<_>>(),
+        vec![&"broken.com".parse().unwrap()]
+    );
+
+    // the sub-directories are loaded, even if not declared in `server.virtual`.
+    let rules = rule_engine.rules();
+    let example = rules.get_any(&"mx.example.com".parse().unwrap()).unwrap();
+    assert_eq!(names(rules.incoming(example)), vec!["example.com incoming"]);
+    assert_eq!(names(rules.outgoing(example)), vec!["example.com outgoing"]);
+    assert_eq!(names(rules.internal(example)), Vec::<String>::new());
+
+    let broken = rules.get_any(&"broken.com".parse().unwrap()).unwrap();
+    assert!(names(rules.incoming(broken))
+        .iter()
+        .all(|name| name.starts_with("fallback")));
+}
@@ -0,0 +1,86 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2023 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use crate::{ExecutionStage, RuleEngine};
+use vqueue::GenericQueueManager;
+use vsmtp_common::status::Status;
+use vsmtp_config::{field::FieldServerLookupTables, DnsResolvers};
+use vsmtp_test::config::local_test;
+
+const RULES: &str = r#"
+#{
+  connect: [
+    rule "client access" || {
+      let access = table::lookup("client_access", ctx::client_ip());
+      if access == () {
+        state::next()
+      } else if access.starts_with("REJECT") {
+        state::deny()
+      } else {
+        state::accept()
+      }
+    },
+  ]
+}
+"#;
+
+fn run(rule_engine: &RuleEngine, client_addr: &str) -> Status {
+    let state = rule_engine.spawn_at_connect(
+        client_addr.parse().unwrap(),
+        "127.0.0.1:25".parse().unwrap(),
+        "testserver.com".parse().unwrap(),
+        time::OffsetDateTime::now_utc(),
+        uuid::Uuid::new_v4(),
+    );
+    rule_engine.run_when(&state, &mut None, ExecutionStage::Connect)
+}
+
+#[test]
+fn lookup_from_rules() {
+    let mut config = local_test();
+    config.server.lookup_tables = Some(FieldServerLookupTables {
+        tables: [(
+            "client_access".to_owned(),
+            "cidr:src/tests/lookup_tables/client_access.cidr"
+                .to_owned()
+                .try_into()
+                .unwrap(),
+        )]
+        .into(),
+        reload_period: std::time::Duration::from_secs(10),
+    });
+    let config = std::sync::Arc::new(config);
+    let queue_manger = vqueue::temp::QueueManager::init(config.clone(), vec![]).unwrap();
+    let dns_resolvers = std::sync::Arc::new(DnsResolvers::from_config(&config).unwrap());
+
+    let rule_engine = RuleEngine::with_hierarchy(
+        |builder| Ok(builder.add_root_filter_rules(RULES)?.build()),
+        config,
+        dns_resolvers,
+        queue_manger,
+    )
+    .unwrap();
+
+    assert!(matches!(
+        run(&rule_engine, "192.0.2.1:1025"),
+        Status::Deny(_)
+    ));
+    assert!(matches!(
+        run(&rule_engine, "192.0.2.42:1025"),
+        Status::Accept(_)
+    ));
+    assert!(matches!(run(&rule_engine, "10.0.0.1:1025"), Status::Next));
+}
@@ -14,7 +14,14 @@
  * this program. If not, see https://www.gnu.org/licenses/.
  *
 */
+mod datasets;
+mod decision_cache;
+mod domain_hierarchy;
 mod errors;
+mod lookup_tables;
+mod reload;
+mod state_pool;
+mod statistics;
 
 use crate::RuleEngine;
 use vqueue::GenericQueueManager;
@@ -0,0 +1,91 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2022 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+
+use crate::{ExecutionStage, RuleEngine};
+use vqueue::GenericQueueManager;
+use vsmtp_common::status::Status;
+use vsmtp_config::DnsResolvers;
+use vsmtp_test::config::local_test;
+
+fn spawn_and_run(rule_engine: &RuleEngine) -> Status {
+    let state = rule_engine.spawn_at_connect(
+        "127.0.0.1:25".parse().unwrap(),
+        "127.0.0.1:25".parse().unwrap(),
+        "testserver.com".parse().unwrap(),
+        time::OffsetDateTime::now_utc(),
+        uuid::Uuid::new_v4(),
+    );
+    rule_engine.run_when(&state, &mut None, ExecutionStage::Connect)
+}
+
+#[test]
+fn reload_rules() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("filter.vsl");
+    std::fs::write(
+        &path,
+        r#"#{ connect: [ rule "check" || state::accept() ] }"#,
+    )
+    .unwrap();
+
+    let mut config = local_test();
+    config.app.vsl.filter_path = Some(path.clone());
+    let config = std::sync::Arc::new(config);
+    let queue_manger = vqueue::temp::QueueManager::init(config.clone(), vec![]).unwrap();
+    let dns_resolvers = std::sync::Arc::new(DnsResolvers::from_config(&config).unwrap());
+
+    let rule_engine = RuleEngine::new(config, dns_resolvers, queue_manger).unwrap();
+    assert!(matches!(spawn_and_run(&rule_engine), Status::Accept(_)));
+
+    std::fs::write(
+        &path,
+        r#"#{ connect: [ rule "check" || state::deny(), rule "added" || state::next() ] }"#,
+    )
+    .unwrap();
+    rule_engine.reload_rules().unwrap();
+    assert!(matches!(spawn_and_run(&rule_engine), Status::Deny(_)));
+
+    // the counters of the directives still declared are kept.
+    let statistics = rule_engine.statistics();
+    let check = statistics.rule("check").unwrap();
+    assert_eq!(check.executions, 2);
+    assert_eq!(check.get("accept"), Some(1));
+    assert_eq!(check.get("deny"), Some(1));
+    assert_eq!(statistics.rule("added").unwrap().executions, 0);
+
+    // a script failing to compile does not replace the current rules.
+    std::fs::write(&path, r#"#{ connect: [ rule "check" || "#).unwrap();
+    rule_engine.reload_rules().unwrap_err();
+    assert!(matches!(spawn_and_run(&rule_engine), Status::Deny(_)));
+}
+
+#[test]
+fn reload_rules_of_builder() {
+    let config = std::sync::Arc::new(local_test());
+    let queue_manger = vqueue::temp::QueueManager::init(config.clone(), vec![]).unwrap();
+    let dns_resolvers = std::sync::Arc::new(DnsResolvers::from_config(&config).unwrap());
+
+    let rule_engine = RuleEngine::with_hierarchy(
+        |builder| Ok(builder.build()),
+        config,
+        dns_resolvers,
+        queue_manger,
+    )
+    .unwrap();
+
+    rule_engine.reload_rules().unwrap_err();
+}
@@ -0,0 +1,95 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2022 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use crate::RuleEngine;
+use vqueue::GenericQueueManager;
+use vsmtp_config::DnsResolvers;
+use vsmtp_test::config::local_test;
+
+fn rule_engine(receiver_threads: usize) -> RuleEngine {
+    let mut config = local_test();
+    config.server.system.thread_pool.receiver = receiver_threads.try_into().unwrap();
+    let config = std::sync::Arc::new(config);
+    let queue_manger = vqueue::temp::QueueManager::init(config.clone(), vec![]).unwrap();
+    let dns_resolvers = std::sync::Arc::new(DnsResolvers::from_config(&config).unwrap());
+
+    RuleEngine::with_hierarchy(
+        |builder| Ok(builder.add_root_filter_rules("#{}")?.build()),
+        config,
+        dns_resolvers,
+        queue_manger,
+    )
+    .unwrap()
+}
+
+fn spawn(rule_engine: &RuleEngine, client: &str) -> std::sync::Arc<crate::RuleState> {
+    rule_engine.spawn_at_connect(
+        client.parse().unwrap(),
+        "127.0.0.1:25".parse().unwrap(),
+        "testserver.com".parse().unwrap(),
+        time::OffsetDateTime::now_utc(),
+        uuid::Uuid::new_v4(),
+    )
+}
+
+#[test]
+fn pool_size() {
+    let rule_engine = rule_engine(2);
+    assert_eq!(rule_engine.pool.len(), 2);
+
+    let states = ["10.0.0.1:25", "10.0.0.2:25", "10.0.0.3:25"]
+        .into_iter()
+        .map(|client| spawn(&rule_engine, client))
+        .collect::<Vec<_>>();
+    assert_eq!(rule_engine.pool.len(), 0);
+
+    // the skeleton built on demand is not kept.
+    drop(states);
+    assert_eq!(rule_engine.pool.len(), 2);
+}
+
+#[test]
+fn no_leak_between_connections() {
+    let rule_engine = rule_engine(1);
+
+    let first = spawn(&rule_engine, "10.0.0.1:25");
+    let mut scope = rhai::Scope::new();
+    first
+        .engine()
+        .run_with_scope(&mut scope, "let leaked = ctx::client_ip(); cache::skip();")
+        .unwrap();
+    assert_eq!(scope.get_value::<String>("leaked").unwrap(), "10.0.0.1");
+    assert!(!first.is_cacheable());
+
+    let context = first.context();
+    drop(first);
+    // the skeleton given back to the pool does not hold the context anymore.
+    assert_eq!(std::sync::Arc::strong_count(&context), 1);
+    assert_eq!(rule_engine.pool.len(), 1);
+
+    let second = spawn(&rule_engine, "10.0.0.2:25");
+    assert_eq!(rule_engine.pool.len(), 0);
+    assert!(second.is_cacheable());
+    assert!(second.engine().eval::<rhai::Dynamic>("leaked").is_err());
+    assert_eq!(
+        second.engine().eval::<String>("ctx::client_ip()").unwrap(),
+        "10.0.0.2"
+    );
+
+    let (ctx, _) = second.take();
+    assert_eq!(ctx.client_addr().ip().to_string(), "10.0.0.2");
+    assert_eq!(rule_engine.pool.len(), 1);
+}
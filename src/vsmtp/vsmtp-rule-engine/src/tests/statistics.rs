@@ -0,0 +1,152 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2023 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use crate::{ExecutionStage, RuleEngine, RuleHits};
+use vqueue::GenericQueueManager;
+use vsmtp_common::status::Status;
+use vsmtp_config::DnsResolvers;
+use vsmtp_test::config::local_test;
+
+const RULES: &str = r#"
+#{
+  connect: [
+    action "count" || {},
+    rule "trusted client" || if ctx::client_ip() == "127.0.0.1" { state::accept() } else { state::next() },
+    rule "deny others" || state::deny(),
+  ],
+  helo: [
+    rule "check statistics" || {
+      let hits = stats::rule("trusted client");
+      if hits.executions == 3 && hits.accept == 2 && hits.next == 1 { state::accept() } else { state::deny() }
+    },
+  ],
+  authenticate: [
+    rule "unknown rule" || stats::rule("this rule does not exist"),
+  ]
+}
+"#;
+
+fn run(rule_engine: &RuleEngine, client: &str, stage: ExecutionStage) -> Status {
+    let state = rule_engine.spawn_at_connect(
+        client.parse().unwrap(),
+        "127.0.0.1:25".parse().unwrap(),
+        "testserver.com".parse().unwrap(),
+        time::OffsetDateTime::now_utc(),
+        uuid::Uuid::new_v4(),
+    );
+    rule_engine.run_when(&state, &mut None, stage)
+}
+
+fn hits(executions: u64, statuses: &[(&str, u64)]) -> RuleHits {
+    let mut hits = RuleHits {
+        executions,
+        ..RuleHits::default()
+    };
+    for (status, count) in statuses {
+        let index = hits
+            .statuses()
+            .position(|(name, _)| name == *status)
+            .unwrap();
+        hits.statuses[index] = *count;
+    }
+    hits
+}
+
+#[test]
+fn hits_per_rule() {
+    let config = std::sync::Arc::new(local_test());
+    let queue_manger = vqueue::temp::QueueManager::init(config.clone(), vec![]).unwrap();
+    let dns_resolvers = std::sync::Arc::new(DnsResolvers::from_config(&config).unwrap());
+
+    let rule_engine = RuleEngine::with_hierarchy(
+        |builder| Ok(builder.add_root_filter_rules(RULES)?.build()),
+        config,
+        dns_resolvers,
+        queue_manger,
+    )
+    .unwrap();
+    let statistics = rule_engine.statistics();
+
+    // allocated at compile time, before any execution.
+    assert_eq!(
+        statistics.get(ExecutionStage::Connect, "deny others"),
+        Some(RuleHits::default())
+    );
+    assert_eq!(statistics.get(ExecutionStage::Helo, "deny others"), None);
+
+    for client in ["127.0.0.1:10000", "127.0.0.1:10001", "10.0.0.1:10000"] {
+        run(&rule_engine, client, ExecutionStage::Connect);
+    }
+
+    assert_eq!(
+        statistics.get(ExecutionStage::Connect, "count"),
+        Some(hits(3, &[("next", 3)]))
+    );
+    assert_eq!(
+        statistics.get(ExecutionStage::Connect, "trusted client"),
+        Some(hits(3, &[("accept", 2), ("next", 1)]))
+    );
+    assert_eq!(
+        statistics.get(ExecutionStage::Connect, "deny others"),
+        Some(hits(1, &[("deny", 1)]))
+    );
+
+    // the counters are readable from the rules.
+    assert!(matches!(
+        run(&rule_engine, "127.0.0.1:10000", ExecutionStage::Helo),
+        Status::Accept(_)
+    ));
+    assert!(matches!(
+        run(&rule_engine, "127.0.0.1:10000", ExecutionStage::Authenticate),
+        Status::Deny(_)
+    ));
+    assert_eq!(
+        statistics.rule("unknown rule"),
+        Some(hits(1, &[("deny", 1)]))
+    );
+
+    assert_eq!(
+        statistics.report(),
+        // the default rules of the hierarchy are reported too.
+        [
+            "connect \"count\" executions=3 next=3",
+            "connect \"deny others\" executions=1 deny=1",
+            "connect \"fallback connect deny by default\" executions=0",
+            "connect \"trusted client\" executions=3 next=1 accept=2",
+            "helo \"check statistics\" executions=1 accept=1",
+            "helo \"fallback helo deny by default\" executions=0",
+            "authenticate \"fallback authenticate deny by default\" executions=0",
+            "authenticate \"unknown rule\" executions=1 deny=1",
+            "mail \"fallback mail deny by default\" executions=0",
+            "mail \"outgoing deny by default\" executions=0",
+            "rcpt \"fallback rcpt deny by default\" executions=0",
+            "rcpt \"incoming deny by default\" executions=0",
+            "preq \"fallback preq deny by default\" executions=0",
+            "postq \"fallback postq deny by default\" executions=0",
+            "delivery \"fallback delivery deny by default\" executions=0",
+        ]
+    );
+    assert!(statistics.metrics().contains(
+        "vsmtp_rule_statuses_total{stage=\"connect\",rule=\"trusted client\",status=\"accept\"} 2\n"
+    ));
+
+    statistics.reset();
+    assert_eq!(statistics.rule("trusted client"), Some(RuleHits::default()));
+    assert!(matches!(
+        run(&rule_engine, "127.0.0.1:10000", ExecutionStage::Helo),
+        Status::Deny(_)
+    ));
+}
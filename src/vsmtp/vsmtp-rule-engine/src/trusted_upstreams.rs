@@ -0,0 +1,339 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2023 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use std::net::IpAddr;
+use vsmtp_common::{FieldAccessError, Network, Stage};
+use vsmtp_config::Config;
+use vsmtp_mail_parser::MessageBody;
+
+/// The host which sent the message to the first untrusted hop.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Origin {
+    /// Address of the host.
+    pub ip: IpAddr,
+    /// Name given by the host in its `HELO/EHLO`, if the hop recorded it.
+    pub helo: Option<String>,
+}
+
+/// Parse an address literal, `192.0.2.1`, `[192.0.2.1]` or `[IPv6:2001:db8::1]`.
+fn address_literal(token: &str) -> Option<IpAddr> {
+    let token = token.trim_start_matches('[').trim_end_matches(']');
+    let token = token
+        .get(..5)
+        .filter(|prefix| prefix.eq_ignore_ascii_case("ipv6:"))
+        .map_or(token, |_| &token[5..]);
+    token.parse().ok()
+}
+
+/// Split the `from` clause of a `Received` header (after `from`, up to `by`) in the
+/// domain given by the client and the content of the comments following it.
+fn from_clause(clause: &str) -> Option<(&str, Vec<&str>)> {
+    let (mut words, mut comments) = (vec![], vec![]);
+    let (mut depth, mut start, mut previous) = (0_usize, 0, ' ');
+
+    for (index, c) in clause.char_indices() {
+        match c {
+            '(' => {
+                if depth == 0 {
+                    words.extend(clause[start..index].split_whitespace());
+                    start = index + 1;
+                }
+                depth += 1;
+            }
+            ')' if depth > 0 => {
+                depth -= 1;
+                if depth == 0 {
+                    comments.push(&clause[start..index]);
+                    start = index + 1;
+                }
+            }
+            'b' | 'B' if depth == 0 && previous.is_whitespace() => {
+                let by = clause[index..].split_whitespace().next();
+                if by.map_or(false, |by| by.eq_ignore_ascii_case("by")) {
+                    words.extend(clause[start..index].split_whitespace());
+                    start = clause.len();
+                    break;
+                }
+            }
+            _ => {}
+        }
+        previous = c;
+    }
+    if depth == 0 {
+        words.extend(clause[start..].split_whitespace());
+    }
+
+    words.first().copied().map(|first| (first, comments))
+}
+
+/// Read the host described by the `from` clause of a `Received` header.
+///
+/// The address is read from the TCP-info, the comment recorded by the hop after the
+/// name given by the client, never from this name. The formats of the common servers
+/// are supported:
+///
+/// * `from helo ([192.0.2.1]) by ...` (vSMTP)
+/// * `from helo (rdns [192.0.2.1]) by ...` (Postfix, Sendmail)
+/// * `from [192.0.2.1] (helo=helo) by ...` and `from rdns ([192.0.2.1] helo=helo) by ...` (Exim)
+/// * `from unknown (HELO helo) (192.0.2.1) by ...` (qmail)
+/// * `from helo (192.0.2.1) by ...` (Exchange)
+fn received_from(value: &str) -> Option<Origin> {
+    let value = value.trim_start();
+    if !value
+        .get(..4)
+        .map_or(false, |from| from.eq_ignore_ascii_case("from"))
+    {
+        return None;
+    }
+    let (first, comments) = from_clause(&value[4..])?;
+
+    // the name given by the client is recorded in the comments by Exim and qmail,
+    // the first word being then written by the hop.
+    let (mut helo, mut addresses) = (None, vec![]);
+    for comment in comments {
+        let mut tokens = comment
+            .split(|c: char| c.is_whitespace() || c == ';')
+            .filter(|token| !token.is_empty());
+        while let Some(token) = tokens.next() {
+            if let Some(key) = token
+                .get(..5)
+                .filter(|key| key.eq_ignore_ascii_case("helo="))
+            {
+                helo = Some(token[key.len()..].to_string());
+            } else if token.eq_ignore_ascii_case("helo") {
+                helo = tokens.next().map(ToString::to_string);
+            } else {
+                addresses.push(token);
+            }
+        }
+    }
+
+    // a literal between brackets is the address seen by the hop, the others may be names.
+    let ip = addresses
+        .iter()
+        .filter(|token| token.starts_with('['))
+        .chain(addresses.iter())
+        .find_map(|token| address_literal(token))
+        .or_else(|| helo.as_ref().and_then(|_| address_literal(first)))?;
+
+    if helo.is_none() && !first.starts_with('[') && !first.eq_ignore_ascii_case("unknown") {
+        helo = Some(first.to_string());
+    }
+
+    Some(Origin { ip, helo })
+}
+
+/// Find the host which sent the message, reading the `Received` headers added by the
+/// trusted relays.
+///
+/// The client is the origin if it is not in `trusted`. Otherwise the topmost `Received`
+/// header, added by the client, describes the previous hop, and so on until a host
+/// outside of `trusted`: the headers below it may be forged, and are ignored.
+/// The last trusted hop is the origin if a header cannot be read.
+#[must_use]
+pub fn origin(trusted: &[Network], client: Origin, message: &MessageBody) -> Origin {
+    let is_trusted = |ip: IpAddr| trusted.iter().any(|network| network.contains(ip));
+
+    let mut origin = client;
+    let headers = message.inner().headers();
+    let mut received = headers
+        .iter()
+        .filter(|(name, _)| name.eq_ignore_ascii_case("Received"));
+
+    while is_trusted(origin.ip) {
+        match received.next().and_then(|(_, value)| received_from(value)) {
+            Some(hop) => origin = hop,
+            None => break,
+        }
+    }
+
+    origin
+}
+
+/// The origin of the message of a transaction, available once the message is received.
+///
+/// # Errors
+///
+/// * the message has not been received yet
+pub(crate) fn origin_of(
+    config: &Config,
+    context: &vsmtp_common::Context,
+    message: &MessageBody,
+) -> Result<Origin, vsmtp_common::Error> {
+    if context.stage() != Stage::Finished {
+        return Err(FieldAccessError::new("origin", vec![Stage::Finished]).into());
+    }
+    let client = Origin {
+        ip: context.client_addr().ip(),
+        helo: context.client_name().ok().map(ToString::to_string),
+    };
+
+    Ok(config
+        .server
+        .trusted_upstreams
+        .as_ref()
+        .map_or(client.clone(), |trusted| {
+            origin(&trusted.networks, client, message)
+        }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vsmtp_test::config::local_msg_with_headers;
+
+    fn trusted() -> Vec<Network> {
+        vec!["10.0.0.0/8".parse().unwrap(), "fd00::/8".parse().unwrap()]
+    }
+
+    fn hop(ip: &str, helo: Option<&str>) -> Origin {
+        Origin {
+            ip: ip.parse().unwrap(),
+            helo: helo.map(str::to_string),
+        }
+    }
+
+    const HEADERS: [&str; 3] = [
+        "Received: from mail.partner.org (mail.partner.org [192.0.2.1])\r\n\tby border.example.com with ESMTPS id 1; Mon, 2 Jan 2023 09:59:59 +0000",
+        "Received: from laptop ([198.51.100.7]) by mail.partner.org with SMTP id 2; Mon, 2 Jan 2023 09:59:58 +0000",
+        "From: john@partner.org",
+    ];
+
+    #[test]
+    fn formats() {
+        for (header, expected) in [
+            (
+                " from client.example.com ([192.0.2.1]) by mx.example.com with SMTP id 1;",
+                hop("192.0.2.1", Some("client.example.com")),
+            ),
+            (
+                " from mail.example.com (unknown [192.0.2.1])\r\n\tby mx.example.com (Postfix) with ESMTPS id 1;",
+                hop("192.0.2.1", Some("mail.example.com")),
+            ),
+            (
+                " from [192.0.2.1] (helo=foo.example) by mx.example.com with esmtp (Exim 4.96)",
+                hop("192.0.2.1", Some("foo.example")),
+            ),
+            (
+                " from rdns.example ([IPv6:2001:db8::1] helo=foo.example) by mx.example.com",
+                hop("2001:db8::1", Some("foo.example")),
+            ),
+            (
+                " from unknown (HELO foo.example) (192.0.2.1) by mx.example.com with SMTP;",
+                hop("192.0.2.1", Some("foo.example")),
+            ),
+            (
+                " from EX01.corp.example (10.1.2.3) by EX02.corp.example (10.1.2.4) with Microsoft SMTP Server id 15.1;",
+                hop("10.1.2.3", Some("EX01.corp.example")),
+            ),
+        ] {
+            assert_eq!(received_from(header), Some(expected), "{header}");
+        }
+
+        assert_eq!(received_from(" from foo.example by mx.example.com;"), None);
+        // the name given by the client is not the address of the hop.
+        assert_eq!(received_from(" from 192.0.2.9 by mx.example.com;"), None);
+        assert_eq!(
+            received_from(" from [10.0.0.9] ([203.0.113.5]) by mx.example.com;"),
+            Some(hop("203.0.113.5", None))
+        );
+        assert_eq!(
+            received_from(" from unknown (HELO 10.0.0.9) (203.0.113.5) by mx.example.com;"),
+            Some(hop("203.0.113.5", Some("10.0.0.9")))
+        );
+        assert_eq!(
+            received_from(
+                " from mail.example.com (unknown [192.0.2.1])\r\n\t(using TLSv1.3 with cipher TLS_AES_256_GCM_SHA384 (256/256 bits))\r\n\tby mx.example.com (Postfix) with ESMTPS id 1;"
+            ),
+            Some(hop("192.0.2.1", Some("mail.example.com")))
+        );
+        assert_eq!(received_from(" by mx.example.com with local id 1;"), None);
+    }
+
+    #[test]
+    fn one_trusted_hop() {
+        assert_eq!(
+            origin(
+                &trusted(),
+                hop("10.0.0.1", Some("border.example.com")),
+                &local_msg_with_headers(&HEADERS)
+            ),
+            hop("192.0.2.1", Some("mail.partner.org"))
+        );
+    }
+
+    #[test]
+    fn two_trusted_hops() {
+        let message = local_msg_with_headers(&[
+            "Received: from border.example.com (border.example.com [10.0.0.1]) by filter.example.com; Mon, 2 Jan 2023 10:00:00 +0000",
+            HEADERS[0],
+            HEADERS[1],
+            HEADERS[2],
+        ]);
+        assert_eq!(
+            origin(
+                &trusted(),
+                hop("fd00::2", Some("filter.example.com")),
+                &message
+            ),
+            hop("192.0.2.1", Some("mail.partner.org"))
+        );
+    }
+
+    #[test]
+    fn forged_received() {
+        // the partner's server is not trusted, the header below it may be forged.
+        let message = local_msg_with_headers(&[
+            HEADERS[0],
+            "Received: from trusted.example.com ([10.0.0.2]) by mail.partner.org; Mon, 2 Jan 2023 09:59:58 +0000",
+            "Received: from spoofed ([203.0.113.66]) by trusted.example.com; Mon, 2 Jan 2023 09:59:57 +0000",
+        ]);
+        assert_eq!(
+            origin(&trusted(), hop("10.0.0.1", None), &message),
+            hop("192.0.2.1", Some("mail.partner.org"))
+        );
+
+        // a trusted hop without a readable header is the origin.
+        let message = local_msg_with_headers(&[
+            "Received: by border.example.com with local id 1; Mon, 2 Jan 2023 09:59:59 +0000",
+            HEADERS[0],
+        ]);
+        assert_eq!(
+            origin(&trusted(), hop("10.0.0.1", None), &message),
+            hop("10.0.0.1", None)
+        );
+    }
+
+    #[test]
+    fn direct_connection() {
+        assert_eq!(
+            origin(
+                &trusted(),
+                hop("198.51.100.7", Some("laptop")),
+                &local_msg_with_headers(&HEADERS)
+            ),
+            hop("198.51.100.7", Some("laptop"))
+        );
+        assert_eq!(
+            origin(
+                &[],
+                hop("10.0.0.1", Some("border")),
+                &local_msg_with_headers(&HEADERS)
+            ),
+            hop("10.0.0.1", Some("border"))
+        );
+    }
+}
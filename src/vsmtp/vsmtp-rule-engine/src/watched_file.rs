@@ -0,0 +1,108 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2023 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use anyhow::Context;
+
+/// Parser of the content of a file, receiving its path for the warnings.
+type Parser<T> = Box<dyn Fn(&std::path::Path, &str) -> T + Send + Sync>;
+
+/// The parsed content of a file, read again when it is modified.
+///
+/// The content is swapped atomically, the readers keep the previous one until
+/// they are done with it.
+pub(crate) struct WatchedFile<T> {
+    path: std::path::PathBuf,
+    /// name of the file in the errors, `list`, `aliases`, ...
+    kind: &'static str,
+    parse: Parser<T>,
+    /// modification time and size of the file when it was last read.
+    version: std::sync::Mutex<Option<(std::time::SystemTime, u64)>>,
+    content: std::sync::RwLock<std::sync::Arc<T>>,
+}
+
+impl<T> std::fmt::Debug for WatchedFile<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WatchedFile")
+            .field("path", &self.path)
+            .field("kind", &self.kind)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T: Default> WatchedFile<T> {
+    /// Read the file at `path`.
+    ///
+    /// # Errors
+    ///
+    /// * the file cannot be read
+    pub(crate) fn new(
+        path: &std::path::Path,
+        kind: &'static str,
+        parse: impl Fn(&std::path::Path, &str) -> T + Send + Sync + 'static,
+    ) -> anyhow::Result<Self> {
+        let file = Self {
+            path: path.to_path_buf(),
+            kind,
+            parse: Box::new(parse),
+            version: std::sync::Mutex::new(None),
+            content: std::sync::RwLock::new(std::sync::Arc::default()),
+        };
+        file.reload_if_changed()?;
+        Ok(file)
+    }
+}
+
+impl<T> WatchedFile<T> {
+    pub(crate) fn path(&self) -> &std::path::Path {
+        &self.path
+    }
+
+    /// Read the file again if it has been modified since the last read.
+    ///
+    /// # Errors
+    ///
+    /// * the file cannot be read, the previous content is kept
+    pub(crate) fn reload_if_changed(&self) -> anyhow::Result<bool> {
+        let mut version = self.version.lock().expect("watched file poisoned");
+        let context = || {
+            format!(
+                "failed to read the {} at '{}'",
+                self.kind,
+                self.path.display()
+            )
+        };
+
+        let current = std::fs::metadata(&self.path)
+            .and_then(|metadata| Ok((metadata.modified()?, metadata.len())))
+            .with_context(context)?;
+        if *version == Some(current) {
+            return Ok(false);
+        }
+
+        let content = std::fs::read_to_string(&self.path).with_context(context)?;
+
+        *self.content.write().expect("watched file poisoned") =
+            std::sync::Arc::new((self.parse)(&self.path, &content));
+        *version = Some(current);
+
+        Ok(true)
+    }
+
+    /// The content of the last read.
+    pub(crate) fn content(&self) -> std::sync::Arc<T> {
+        self.content.read().expect("watched file poisoned").clone()
+    }
+}
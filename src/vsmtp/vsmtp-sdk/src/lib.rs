@@ -0,0 +1,142 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2023 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+ */
+
+//! vSMTP SDK
+//!
+//! The interface to build out-of-tree transports and rhai plugins for `vSMTP`.
+//!
+//! The other crates of `vSMTP` are internals, their API can change in any release.
+//! This crate re-exports the part of it plugins rely on, and follows semver:
+//! a plugin depending only on `vsmtp-sdk` builds against any compatible version.
+//!
+//! See `examples/sdk` in the repository for a transport and a rhai plugin using it.
+
+#![doc(html_no_source)]
+#![deny(missing_docs)]
+#![deny(unsafe_code)]
+//
+#![warn(rust_2018_idioms)]
+#![warn(clippy::all)]
+#![warn(clippy::pedantic)]
+#![warn(clippy::nursery)]
+#![warn(clippy::cargo)]
+
+pub use async_trait::async_trait;
+pub use rhai;
+pub use serde;
+
+pub use vsmtp_common::{Address, ClientName, Domain, Reply, ReplyCode, Target};
+
+/// The transaction context, and the properties of each stage.
+pub mod context {
+    pub use vsmtp_common::{
+        AuthProperties, ConnectProperties, Context, ContextFinished, FieldAccessError,
+        FinishedProperties, HeloProperties, MailFromProperties, RcptToProperties, Stage,
+        TlsProperties, TransactionType,
+    };
+}
+
+/// Status of the delivery of the recipients.
+pub mod transfer {
+    pub use vsmtp_common::transfer::{error, Error, Status};
+}
+
+/// Implementation of a transport, see [`crate::export_transport`].
+pub mod transport {
+    pub use vsmtp_common::transport::{
+        AbstractTransport, DeliverTo, DeserializerError, DeserializerFn, GetID, TransportStates,
+        DESERIALIZER_SYMBOL_NAME,
+    };
+}
+
+/// The configuration of the server.
+pub mod config {
+    pub use vsmtp_config::field::{FieldApp, FieldServer};
+    pub use vsmtp_config::Config;
+}
+
+/// Helpers for the entry point of the plugins, see [`crate::export_plugin`].
+pub mod plugin {
+    /// Seed of the hashes of `rhai`, the plugins and `vSMTP` must use the same one
+    /// to share their functions.
+    pub const AHASH_SEED: [u64; 4] = [1, 2, 3, 4];
+
+    /// Set the seed of the hashes of `rhai` to [`AHASH_SEED`].
+    ///
+    /// # Errors
+    ///
+    /// * the seed has already been set, the previous value is returned.
+    #[inline]
+    pub fn set_ahash_seed() -> Result<(), Option<[u64; 4]>> {
+        rhai::config::hashing::set_ahash_seed(Some(AHASH_SEED))
+    }
+}
+
+/// Export the entry point fetched by `vSMTP` to load a rhai module,
+/// generated with `#[rhai::plugin::export_module]`.
+///
+/// ```ignore
+/// vsmtp_sdk::export_plugin!(api::my_plugin);
+/// ```
+#[macro_export]
+macro_rules! export_plugin {
+    ($module:path) => {
+        /// `rhai-dylib` will fetch this symbol to load the module into `vSMTP`.
+        ///
+        /// # Panics
+        ///
+        /// * the `rhai` hashing seed cannot be set.
+        #[allow(improper_ctypes_definitions)]
+        #[allow(unsafe_code)]
+        #[no_mangle]
+        #[inline]
+        pub extern "C" fn module_entrypoint() -> $crate::rhai::Shared<$crate::rhai::Module> {
+            $crate::plugin::set_ahash_seed().expect("the rhai hashing seed must be set once");
+
+            $crate::rhai::exported_module!($module).into()
+        }
+    };
+}
+
+/// Export the function deserializing a transport, fetched by `vSMTP`
+/// under [`transport::DESERIALIZER_SYMBOL_NAME`].
+///
+/// ```ignore
+/// vsmtp_sdk::export_transport!(MyTransport);
+/// ```
+#[macro_export]
+macro_rules! export_transport {
+    ($transport:ty) => {
+        /// Produce an instance of the transport from its serialized form.
+        ///
+        /// # Safety
+        ///
+        /// * see [`vsmtp_sdk::transport::AbstractTransport::deserialize`].
+        #[allow(improper_ctypes_definitions)]
+        #[allow(unsafe_code)]
+        #[no_mangle]
+        #[inline]
+        pub unsafe extern "C" fn deserialize_transport(
+            input: *const ::std::os::raw::c_char,
+        ) -> ::core::result::Result<
+            ::std::sync::Arc<dyn $crate::transport::AbstractTransport>,
+            $crate::transport::DeserializerError,
+        > {
+            <$transport as $crate::transport::AbstractTransport>::deserialize(input)
+        }
+    };
+}
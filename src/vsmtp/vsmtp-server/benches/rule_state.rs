@@ -0,0 +1,71 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use vsmtp_config::DnsResolvers;
+use vsmtp_rule_engine::{ExecutionStage, RuleEngine};
+use vsmtp_test::config;
+
+const CONNECTIONS_BY_THREAD: usize = 32;
+
+fn rule_engine(receiver_threads: usize) -> RuleEngine {
+    let mut config = config::local_test();
+    config.server.system.thread_pool.receiver = receiver_threads.try_into().unwrap();
+
+    let config = std::sync::Arc::new(config);
+    let resolvers = std::sync::Arc::new(DnsResolvers::from_system_conf().unwrap());
+    let queue_manager =
+        <vqueue::temp::QueueManager as vqueue::GenericQueueManager>::init(config.clone(), vec![])
+            .unwrap();
+
+    RuleEngine::new(config, resolvers, queue_manager).unwrap()
+}
+
+/// The work done between the accept of a connection and the banner,
+/// for `concurrency` clients connecting at the same time.
+fn accept(rule_engine: &RuleEngine, concurrency: usize) {
+    std::thread::scope(|scope| {
+        for _ in 0..concurrency {
+            scope.spawn(|| {
+                for _ in 0..CONNECTIONS_BY_THREAD {
+                    let state = rule_engine.spawn_at_connect(
+                        "127.0.0.1:40000".parse().unwrap(),
+                        "127.0.0.1:25".parse().unwrap(),
+                        "testserver.com".parse().unwrap(),
+                        time::OffsetDateTime::now_utc(),
+                        uuid::Uuid::new_v4(),
+                    );
+                    criterion::black_box(rule_engine.run_when(
+                        &state,
+                        &mut None,
+                        ExecutionStage::Connect,
+                    ));
+                }
+            });
+        }
+    });
+}
+
+fn criterion_accept(c: &mut Criterion) {
+    let mut group = c.benchmark_group("accept_to_banner");
+
+    for concurrency in [4, 16] {
+        // the pool keeps a state by receiver thread.
+        let pooled = rule_engine(concurrency);
+        group.bench_with_input(
+            BenchmarkId::new("pooled", concurrency),
+            &concurrency,
+            |b, concurrency| b.iter(|| accept(&pooled, *concurrency)),
+        );
+
+        // the states are built on demand by all the clients but one.
+        let on_demand = rule_engine(1);
+        group.bench_with_input(
+            BenchmarkId::new("on_demand", concurrency),
+            &concurrency,
+            |b, concurrency| b.iter(|| accept(&on_demand, *concurrency)),
+        );
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, criterion_accept);
+criterion_main!(benches);
@@ -0,0 +1,650 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2022 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use crate::{
+    claim, scheduler::Emitter, Health, ProcessMessage, SourceIpReputation, SubmissionQuotas,
+    TlsStatistics,
+};
+use anyhow::Context;
+use vqueue::{GenericQueueManager, QueueID};
+use vsmtp_common::{transfer::Status, ContextFinished, MailFromProperties};
+use vsmtp_rule_engine::RuleEngine;
+
+/// Operations available on the administrative socket, one per line.
+#[derive(Debug, PartialEq, Eq)]
+pub enum AdminCommand {
+    /// `list <queue>`: print the id of the messages in the queue.
+    List(QueueID),
+    /// `flush`: move all the deferred messages to the delivery, without waiting the retry period.
+    Flush,
+    /// `hold <id>`: move a message waiting for delivery to the `hold` queue.
+    ///
+    /// The message is designated by the beginning of its uuid, or by its queue id.
+    Hold(String),
+    /// `release <id>`: move a message from the `hold` queue back to the delivery.
+    Release(String),
+    /// `requeue <id>`: move a message from the `dead` queue back to the delivery,
+    /// its failed recipients are tried again.
+    Requeue(String),
+    /// `release all`: move all the messages of the `hold` queue back to the delivery,
+    /// and print the ones which failed to move.
+    ReleaseAll,
+    /// `requeue all`: move all the messages of the `dead` queue back to the delivery,
+    /// and print the ones which failed to move.
+    RequeueAll,
+    /// `reload`: compile the rules and read the datasets again, and reset the statistics of the rules.
+    Reload,
+    /// `maintenance on|off`: stop/resume accepting new SMTP clients.
+    Maintenance(bool),
+    /// `tls-stats [domain|network]`: print the use of TLS by the sender domains and the client networks.
+    TlsStats(Option<String>),
+    /// `rules stats`: print the number of executions of each rule, and of the statuses they returned.
+    RuleStats,
+    /// `source-ips`: print the outgoing addresses, and the blocklists listing them.
+    SourceIps,
+    /// `flush-capabilities <domain>`: forget the cached extensions of the servers of the domain,
+    /// and print their destinations.
+    FlushCapabilities(String),
+    /// `quota <identity>`: print the recipients submitted by the authenticated user.
+    Quota(String),
+    /// `quota-reset <identity>`: forget the recipients submitted by the authenticated user.
+    QuotaReset(String),
+}
+
+impl std::str::FromStr for AdminCommand {
+    type Err = anyhow::Error;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let mut split = line.split_ascii_whitespace();
+        let (verb, arg) = (split.next(), split.next());
+        anyhow::ensure!(split.next().is_none(), "too many arguments");
+
+        let msg_id = || -> anyhow::Result<String> {
+            let arg = arg.context("missing message id")?;
+            anyhow::ensure!(is_message_id(arg), "invalid message id `{arg}`");
+            Ok(arg.to_owned())
+        };
+
+        match (verb.map(str::to_ascii_lowercase).as_deref(), arg) {
+            (Some("list"), Some(queue)) => Ok(Self::List(match queue.split_once('/') {
+                Some(("quarantine", name)) if !name.is_empty() => QueueID::Quarantine {
+                    name: name.to_owned(),
+                },
+                _ => queue
+                    .parse::<QueueID>()
+                    .ok()
+                    .filter(|queue| !matches!(queue, QueueID::Quarantine { .. }))
+                    .with_context(|| format!("unknown queue `{queue}`"))?,
+            })),
+            (Some("flush"), None) => Ok(Self::Flush),
+            (Some("hold"), _) => Ok(Self::Hold(msg_id()?)),
+            (Some("release"), Some(all)) if all.eq_ignore_ascii_case("all") => Ok(Self::ReleaseAll),
+            (Some("release"), _) => Ok(Self::Release(msg_id()?)),
+            (Some("requeue"), Some(all)) if all.eq_ignore_ascii_case("all") => Ok(Self::RequeueAll),
+            (Some("requeue"), _) => Ok(Self::Requeue(msg_id()?)),
+            (Some("reload"), None) => Ok(Self::Reload),
+            (Some("maintenance"), Some("on")) => Ok(Self::Maintenance(true)),
+            (Some("maintenance"), Some("off")) => Ok(Self::Maintenance(false)),
+            (Some("rules"), Some("stats")) => Ok(Self::RuleStats),
+            (Some("source-ips"), None) => Ok(Self::SourceIps),
+            (Some("tls-stats"), filter) => Ok(Self::TlsStats(filter.map(str::to_ascii_lowercase))),
+            (Some("flush-capabilities"), Some(domain)) => {
+                Ok(Self::FlushCapabilities(domain.to_ascii_lowercase()))
+            }
+            (Some("quota"), Some(identity)) => Ok(Self::Quota(identity.to_owned())),
+            (Some("quota-reset"), Some(identity)) => Ok(Self::QuotaReset(identity.to_owned())),
+            _ => anyhow::bail!("unknown command `{line}`"),
+        }
+    }
+}
+
+/// Is `id` the beginning of a message uuid, or a queue id.
+fn is_message_id(id: &str) -> bool {
+    let uuid_prefix = !id.is_empty() && id.chars().all(|c| c.is_ascii_hexdigit() || c == '-');
+    let queue_id = id.len() == MailFromProperties::QUEUE_ID_LEN
+        && id.chars().all(|c| c.is_ascii_alphanumeric());
+    uuid_prefix || queue_id
+}
+
+/// Try the failed recipients of a message again.
+fn reset_failed_recipients(ctx: &mut ContextFinished) {
+    for rcpt in ctx.rcpt_to.delivery.values_mut().flatten() {
+        if matches!(rcpt.1, Status::Failed { .. }) {
+            rcpt.1 = Status::default();
+        }
+    }
+}
+
+/// Control channel of a running server, served on a unix socket.
+///
+/// Each line received is an [`AdminCommand`], the reply is the output of the command
+/// (one item per line) followed by `ok`, or a single `error: <reason>` line.
+pub struct Admin<Q: GenericQueueManager + Sized + 'static> {
+    queue_manager: std::sync::Arc<Q>,
+    rule_engine: std::sync::Arc<RuleEngine>,
+    emitter: std::sync::Arc<Emitter>,
+    health: std::sync::Arc<Health>,
+    tls_statistics: Option<std::sync::Arc<TlsStatistics>>,
+    submission_quotas: Option<std::sync::Arc<SubmissionQuotas>>,
+    source_ip_reputation: Option<std::sync::Arc<SourceIpReputation>>,
+}
+
+impl<Q: GenericQueueManager + Sized + 'static> Admin<Q> {
+    /// Create the control channel, operating on the components of the runtime.
+    #[must_use]
+    pub const fn new(
+        queue_manager: std::sync::Arc<Q>,
+        rule_engine: std::sync::Arc<RuleEngine>,
+        emitter: std::sync::Arc<Emitter>,
+        health: std::sync::Arc<Health>,
+    ) -> Self {
+        Self {
+            queue_manager,
+            rule_engine,
+            emitter,
+            health,
+            tls_statistics: None,
+            submission_quotas: None,
+            source_ip_reputation: None,
+        }
+    }
+
+    /// Answer the `tls-stats` command with the statistics of the use of TLS.
+    #[must_use]
+    pub fn with_tls_statistics(mut self, tls_statistics: std::sync::Arc<TlsStatistics>) -> Self {
+        self.tls_statistics = Some(tls_statistics);
+        self
+    }
+
+    /// Answer the `quota` and `quota-reset` commands with the counters of the authenticated users.
+    #[must_use]
+    pub fn with_submission_quotas(
+        mut self,
+        submission_quotas: std::sync::Arc<SubmissionQuotas>,
+    ) -> Self {
+        self.submission_quotas = Some(submission_quotas);
+        self
+    }
+
+    /// Answer the `source-ips` command with the health of the outgoing addresses.
+    #[must_use]
+    pub fn with_source_ip_reputation(
+        mut self,
+        source_ip_reputation: std::sync::Arc<SourceIpReputation>,
+    ) -> Self {
+        self.source_ip_reputation = Some(source_ip_reputation);
+        self
+    }
+
+    async fn claim(&self, msg_uuid: uuid::Uuid) -> anyhow::Result<claim::Guard<Q>> {
+        claim::Guard::acquire(self.queue_manager.clone(), msg_uuid)
+            .await?
+            .context("message is being processed")
+    }
+
+    /// Move a message to the delivery queue, and notify the delivery process.
+    async fn to_delivery(
+        &self,
+        from: &QueueID,
+        msg_uuid: uuid::Uuid,
+        reset_failed: bool,
+    ) -> anyhow::Result<()> {
+        let claim = self.claim(msg_uuid).await?;
+
+        let moved = async {
+            let mut ctx = self.queue_manager.get_ctx(from, &msg_uuid).await?;
+            if reset_failed {
+                reset_failed_recipients(&mut ctx);
+            }
+            self.queue_manager
+                .move_to(from, &QueueID::Deliver, &ctx)
+                .await
+        }
+        .await;
+
+        // NOTE: the claim must be released before the delivery picks up the message.
+        claim.release().await;
+        moved?;
+        self.emitter
+            .send_to_delivery(ProcessMessage::new(msg_uuid))
+            .await
+            .context("delivery process is not running")
+    }
+
+    /// Move all the messages of `from` to the delivery queue, and notify the delivery process.
+    /// Prints the messages which failed to move, and the count of the moved ones.
+    async fn all_to_delivery(
+        &self,
+        from: &QueueID,
+        reset_failed: bool,
+    ) -> anyhow::Result<Vec<String>> {
+        let report = self
+            .queue_manager
+            .move_all(from, &QueueID::Deliver, &|ctx| {
+                if reset_failed {
+                    reset_failed_recipients(ctx);
+                }
+                true
+            })
+            .await?;
+
+        for msg_uuid in &report.moved {
+            self.emitter
+                .send_to_delivery(ProcessMessage::new(*msg_uuid))
+                .await
+                .context("delivery process is not running")?;
+        }
+
+        let summary = format!(
+            "moved {}, failed {}",
+            report.moved.len(),
+            report.failed.len()
+        );
+        Ok(report
+            .failed
+            .into_iter()
+            .map(|(msg_id, error)| format!("{msg_id}: {error}"))
+            .chain(std::iter::once(summary))
+            .collect())
+    }
+
+    async fn list(&self, queue: &QueueID) -> anyhow::Result<Vec<String>> {
+        self.queue_manager
+            .list(queue)
+            .await?
+            .into_iter()
+            .collect::<anyhow::Result<Vec<_>>>()
+    }
+
+    async fn flush(&self) -> anyhow::Result<Vec<String>> {
+        let mut flushed = vec![];
+        for msg_uuid in self.list(&QueueID::Deferred).await? {
+            let msg_uuid = uuid::Uuid::parse_str(&msg_uuid)?;
+            match self.to_delivery(&QueueID::Deferred, msg_uuid, false).await {
+                Ok(()) => flushed.push(msg_uuid.to_string()),
+                Err(error) => tracing::warn!(uuid = %msg_uuid, %error, "Flushing message failure."),
+            }
+        }
+        Ok(flushed)
+    }
+
+    /// Find the message of `queues` whose uuid starts with `id`, or whose queue id is `id`.
+    async fn resolve(&self, queues: &[QueueID], id: &str) -> anyhow::Result<(QueueID, uuid::Uuid)> {
+        let prefix = id.to_ascii_lowercase();
+        let mut found = vec![];
+
+        for queue in queues {
+            for msg_uuid in self.list(queue).await? {
+                let msg_uuid = uuid::Uuid::parse_str(&msg_uuid)?;
+                let matches = msg_uuid.to_string().starts_with(&prefix)
+                    || id.len() == MailFromProperties::QUEUE_ID_LEN
+                        && self
+                            .queue_manager
+                            .get_ctx(queue, &msg_uuid)
+                            .await
+                            .map_or(false, |ctx| {
+                                ctx.mail_from.queue_id().eq_ignore_ascii_case(id)
+                            });
+                if matches {
+                    found.push((queue.clone(), msg_uuid));
+                }
+            }
+        }
+
+        match found.len() {
+            0 => anyhow::bail!(
+                "message `{id}` not found in {}",
+                queues
+                    .iter()
+                    .map(|queue| format!("`{queue}`"))
+                    .collect::<Vec<_>>()
+                    .join(" or ")
+            ),
+            1 => Ok(found.remove(0)),
+            _ => anyhow::bail!(
+                "message id `{id}` is ambiguous: {}",
+                found
+                    .iter()
+                    .map(|(_, msg_uuid)| msg_uuid.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        }
+    }
+
+    async fn hold(&self, id: &str) -> anyhow::Result<()> {
+        let (queue, msg_uuid) = self
+            .resolve(&[QueueID::Deliver, QueueID::Deferred], id)
+            .await?;
+        let claim = self.claim(msg_uuid).await?;
+
+        let moved = async {
+            let ctx = self.queue_manager.get_ctx(&queue, &msg_uuid).await?;
+            self.queue_manager
+                .move_to(&queue, &QueueID::Hold, &ctx)
+                .await
+        }
+        .await;
+
+        // NOTE: the claim is released before returning, so that the message can be
+        //       released right away.
+        claim.release().await;
+        moved
+    }
+
+    async fn id_to_delivery(
+        &self,
+        from: QueueID,
+        id: &str,
+        reset_failed: bool,
+    ) -> anyhow::Result<()> {
+        let (from, msg_uuid) = self.resolve(&[from], id).await?;
+        self.to_delivery(&from, msg_uuid, reset_failed).await
+    }
+
+    /// Compile the rules and read the datasets again, the statistics of the rules are reset.
+    fn reload(&self) -> anyhow::Result<()> {
+        self.rule_engine.reload_rules()?;
+        self.rule_engine.reload_datasets()?;
+        self.rule_engine.statistics().reset();
+        Ok(())
+    }
+
+    /// Run one command.
+    ///
+    /// # Errors
+    ///
+    /// * the message is not in the expected queue, its id is ambiguous, or it is being processed
+    /// * failed to read/write the queues, or to reload the rules or the datasets
+    pub async fn execute(&self, command: AdminCommand) -> anyhow::Result<Vec<String>> {
+        tracing::info!(?command, "Executing administrative command.");
+
+        match command {
+            AdminCommand::List(queue) => self.list(&queue).await,
+            AdminCommand::Flush => self.flush().await,
+            AdminCommand::Hold(id) => self.hold(&id).await.map(|()| vec![]),
+            AdminCommand::Release(id) => self
+                .id_to_delivery(QueueID::Hold, &id, false)
+                .await
+                .map(|()| vec![]),
+            AdminCommand::Requeue(id) => self
+                .id_to_delivery(QueueID::Dead, &id, true)
+                .await
+                .map(|()| vec![]),
+            AdminCommand::ReleaseAll => self.all_to_delivery(&QueueID::Hold, false).await,
+            AdminCommand::RequeueAll => self.all_to_delivery(&QueueID::Dead, true).await,
+            AdminCommand::Reload => self.reload().map(|()| vec![]),
+            AdminCommand::Maintenance(enabled) => {
+                self.health.set_maintenance(enabled);
+                Ok(vec![])
+            }
+            AdminCommand::TlsStats(filter) => self
+                .tls_statistics
+                .as_ref()
+                .map(|tls_statistics| tls_statistics.report(filter.as_deref()))
+                .context("tls statistics are not enabled"),
+            AdminCommand::RuleStats => Ok(self.rule_engine.statistics().report()),
+            AdminCommand::SourceIps => self
+                .source_ip_reputation
+                .as_ref()
+                .map(|source_ip_reputation| source_ip_reputation.report())
+                .context("the outgoing addresses are not checked against blocklists"),
+            AdminCommand::FlushCapabilities(domain) => {
+                Ok(self.rule_engine.srv().delivery.flush_capabilities(&domain))
+            }
+            AdminCommand::Quota(identity) => self
+                .submission_quotas
+                .as_ref()
+                .map(|submission_quotas| submission_quotas.report(&identity))
+                .context("submission quotas are not enabled"),
+            AdminCommand::QuotaReset(identity) => {
+                let submission_quotas = self
+                    .submission_quotas
+                    .as_ref()
+                    .context("submission quotas are not enabled")?;
+                anyhow::ensure!(
+                    submission_quotas.reset(&identity),
+                    "no counters for `{identity}`"
+                );
+                Ok(vec![])
+            }
+        }
+    }
+
+    async fn handle_client(&self, stream: tokio::net::UnixStream) -> std::io::Result<()> {
+        let (read, mut write) = stream.into_split();
+        let mut lines = tokio::io::AsyncBufReadExt::lines(tokio::io::BufReader::new(read));
+
+        while let Some(line) = lines.next_line().await? {
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let result = match line.parse() {
+                Ok(command) => self.execute(command).await,
+                Err(error) => Err(error),
+            };
+            let reply = match result {
+                Ok(output) => output
+                    .into_iter()
+                    .chain(std::iter::once("ok".to_owned()))
+                    .map(|line| line + "\n")
+                    .collect::<String>(),
+                Err(error) => format!("error: {error:#}\n"),
+            };
+            tokio::io::AsyncWriteExt::write_all(&mut write, reply.as_bytes()).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Serve the commands on the listener, until the runtime is stopped.
+    ///
+    /// # Errors
+    ///
+    /// * failed to convert the socket to `[tokio::net::UnixListener]`
+    pub async fn serve(
+        self: std::sync::Arc<Self>,
+        listener: std::os::unix::net::UnixListener,
+    ) -> std::io::Result<()> {
+        let listener = tokio::net::UnixListener::from_std(listener)?;
+
+        tracing::info!(addr = ?listener.local_addr(), "Listening for administrative commands.");
+
+        loop {
+            let (stream, _) = match listener.accept().await {
+                Ok(client) => client,
+                Err(error) => {
+                    tracing::warn!(%error, "Administrative client accept failure.");
+                    continue;
+                }
+            };
+
+            let admin = self.clone();
+            tokio::spawn(async move {
+                if let Err(error) = admin.handle_client(stream).await {
+                    tracing::debug!(%error, "Administrative client failure.");
+                }
+            });
+        }
+    }
+}
+
+/// Create a `UnixListener` ready to be listened to, replacing a socket left by a previous instance.
+///
+/// The commands are not authenticated, the access is granted by the permissions of the socket:
+/// its mode is set to `0660` and its group to `group`, only the owner of the socket (the user
+/// starting the server), the members of `group` and root can connect.
+///
+/// # Errors
+///
+/// * failed to remove the previous socket
+/// * failed to bind to the path
+/// * failed to set the permissions or the group of the socket
+/// * failed to set the listener to non blocking
+pub fn socket_bind_unix(
+    path: &std::path::Path,
+    group: u32,
+) -> anyhow::Result<std::os::unix::net::UnixListener> {
+    match std::fs::remove_file(path) {
+        Ok(()) => (),
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => (),
+        Err(error) => {
+            return Err(error)
+                .with_context(|| format!("Failed to remove socket at: '{}'", path.display()))
+        }
+    }
+
+    let socket = std::os::unix::net::UnixListener::bind(path)
+        .with_context(|| format!("Failed to bind socket on path: '{}'", path.display()))?;
+
+    // NOTE: the mode of the socket created by `bind` depends on the umask of the process.
+    std::fs::set_permissions(
+        path,
+        <std::fs::Permissions as std::os::unix::fs::PermissionsExt>::from_mode(0o660),
+    )
+    .with_context(|| format!("Failed to set the mode of the socket: '{}'", path.display()))?;
+    vsmtp_common::libc_abstraction::chown(path, None, Some(group)).with_context(|| {
+        format!(
+            "Failed to set the group of the socket: '{}'",
+            path.display()
+        )
+    })?;
+
+    socket.set_nonblocking(true).with_context(|| {
+        format!(
+            "Failed to set non-blocking socket on path: '{}'",
+            path.display()
+        )
+    })?;
+
+    Ok(socket)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn socket_permissions() {
+        use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+        let dir = std::env::temp_dir().join(format!("vsmtp-admin-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir(&dir).unwrap();
+        let path = dir.join("admin.sock");
+        let group = std::fs::metadata(&dir).unwrap().gid();
+
+        // a socket left by a previous instance is replaced.
+        drop(socket_bind_unix(&path, group).unwrap());
+        let _listener = socket_bind_unix(&path, group).unwrap();
+
+        let metadata = std::fs::metadata(&path).unwrap();
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o660);
+        assert_eq!(metadata.gid(), group);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn parse() {
+        let msg_uuid = uuid::Uuid::new_v4();
+
+        for (line, command) in [
+            ("list deferred", AdminCommand::List(QueueID::Deferred)),
+            ("LIST hold", AdminCommand::List(QueueID::Hold)),
+            (
+                "list quarantine/spam",
+                AdminCommand::List(QueueID::Quarantine {
+                    name: "spam".to_owned(),
+                }),
+            ),
+            ("flush", AdminCommand::Flush),
+            (
+                &format!("hold {msg_uuid}"),
+                AdminCommand::Hold(msg_uuid.to_string()),
+            ),
+            (
+                &format!("release {msg_uuid}"),
+                AdminCommand::Release(msg_uuid.to_string()),
+            ),
+            (
+                &format!("requeue {msg_uuid}"),
+                AdminCommand::Requeue(msg_uuid.to_string()),
+            ),
+            ("hold 3f2a", AdminCommand::Hold("3f2a".to_owned())),
+            (
+                "release 4K7XN2QPR8TZ",
+                AdminCommand::Release("4K7XN2QPR8TZ".to_owned()),
+            ),
+            ("release all", AdminCommand::ReleaseAll),
+            ("requeue ALL", AdminCommand::RequeueAll),
+            ("reload", AdminCommand::Reload),
+            ("maintenance on", AdminCommand::Maintenance(true)),
+            ("maintenance off", AdminCommand::Maintenance(false)),
+            ("tls-stats", AdminCommand::TlsStats(None)),
+            (
+                "tls-stats Example.com",
+                AdminCommand::TlsStats(Some("example.com".to_owned())),
+            ),
+            (
+                "tls-stats 192.0.2.0/24",
+                AdminCommand::TlsStats(Some("192.0.2.0/24".to_owned())),
+            ),
+            ("rules stats", AdminCommand::RuleStats),
+            ("source-ips", AdminCommand::SourceIps),
+            (
+                "flush-capabilities Example.com",
+                AdminCommand::FlushCapabilities("example.com".to_owned()),
+            ),
+            ("quota John", AdminCommand::Quota("John".to_owned())),
+            (
+                "quota-reset john@example.com",
+                AdminCommand::QuotaReset("john@example.com".to_owned()),
+            ),
+        ] {
+            assert_eq!(line.parse::<AdminCommand>().unwrap(), command);
+        }
+    }
+
+    #[test]
+    fn parse_invalid() {
+        for line in [
+            "",
+            "foobar",
+            "list",
+            "list foobar",
+            "list quarantine",
+            "flush now",
+            "hold",
+            "hold not-a-uuid",
+            "release 4K7XN2QPR8T",
+            "requeue 3f2a 4b1c",
+            "maintenance",
+            "maintenance maybe",
+            "reload reload",
+            "tls-stats example.com example.org",
+            "rules",
+            "rules foobar",
+            "source-ips 192.0.2.1",
+            "flush-capabilities",
+            "quota",
+            "quota-reset",
+            "quota-reset john jenny",
+        ] {
+            assert!(line.parse::<AdminCommand>().is_err(), "{line}");
+        }
+    }
+}
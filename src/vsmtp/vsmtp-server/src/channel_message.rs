@@ -22,27 +22,51 @@ pub struct ProcessMessage {
     message_uuid: uuid::Uuid,
     /// is the email stored in the delegated queue.
     delegated: bool,
+    /// root span of the message, parent of the span of the next process, following
+    /// from the span in which the message has been sent.
+    ///
+    /// NOTE: the span in which the message has been sent (the SMTP session for instance)
+    /// is not its parent, so that it is closed without waiting for the next process.
+    span: tracing::Span,
 }
 
 impl ProcessMessage {
-    /// Construct a new `ProcessMessage`.
-    pub const fn new(message_uuid: uuid::Uuid) -> Self {
+    /// Construct a new `ProcessMessage`, sent from the current span.
+    pub fn new(message_uuid: uuid::Uuid) -> Self {
+        let span = tracing::info_span!(parent: None, "message", uuid = %message_uuid);
+        span.follows_from(&tracing::Span::current());
+
         Self {
             message_uuid,
             delegated: false,
+            span,
         }
     }
 
-    pub(crate) const fn delegated(message_uuid: uuid::Uuid) -> Self {
+    pub(crate) fn delegated(message_uuid: uuid::Uuid) -> Self {
         Self {
-            message_uuid,
             delegated: true,
+            ..Self::new(message_uuid)
         }
     }
 
-    pub(crate) const fn is_from_delegation(&self) -> bool {
+    /// The message also follows from the span `id`, the one in which it has been
+    /// delegated to a service for instance.
+    pub(crate) fn following(self, id: Option<tracing::Id>) -> Self {
+        self.span.follows_from(id);
+        self
+    }
+
+    /// Is the message the result of a delegation.
+    #[must_use]
+    pub const fn is_from_delegation(&self) -> bool {
         self.delegated
     }
+
+    /// The root span of the message.
+    pub(crate) const fn span(&self) -> &tracing::Span {
+        &self.span
+    }
 }
 
 impl AsRef<uuid::Uuid> for ProcessMessage {
@@ -62,6 +86,7 @@ mod test {
             ProcessMessage {
                 message_uuid: uuid::Uuid::nil(),
                 delegated: false,
+                span: tracing::Span::none(),
             }
         );
     }
@@ -0,0 +1,134 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2022 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use anyhow::Context;
+use vqueue::GenericQueueManager;
+
+/// Claim of this instance on a message while it is processed, see [`GenericQueueManager::claim`].
+///
+/// The heartbeat is refreshed in the background, and the claim is released
+/// when dropped if [`Guard::release`] has not been called.
+///
+/// The claim can be lost, taken over by another instance which then processes the message:
+/// the processing is run with [`Guard::run`], and checked with [`Guard::ensure_held`]
+/// before moving or sending the message.
+pub struct Guard<Q: GenericQueueManager + Sized + 'static> {
+    queue_manager: std::sync::Arc<Q>,
+    msg_uuid: uuid::Uuid,
+    heartbeat: Option<tokio::task::JoinHandle<()>>,
+}
+
+impl<Q: GenericQueueManager + Sized + 'static> Guard<Q> {
+    /// Claim the message, returns `None` if it is processed by another instance
+    /// (or concurrently by this one).
+    pub async fn acquire(
+        queue_manager: std::sync::Arc<Q>,
+        msg_uuid: uuid::Uuid,
+    ) -> anyhow::Result<Option<Self>> {
+        if !queue_manager.claim(&msg_uuid).await? {
+            tracing::debug!("Message already claimed.");
+            return Ok(None);
+        }
+
+        let period = (queue_manager.get_config().server.queues.claim_timeout / 3)
+            .max(std::time::Duration::from_secs(1));
+
+        let heartbeat = tokio::spawn({
+            let queue_manager = queue_manager.clone();
+            async move {
+                let mut interval =
+                    tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+                loop {
+                    interval.tick().await;
+                    match queue_manager.heartbeat(&msg_uuid).await {
+                        Ok(true) => (),
+                        Ok(false) => {
+                            tracing::warn!(uuid = %msg_uuid, "Claim lost while processing.");
+                            return;
+                        }
+                        Err(error) => {
+                            tracing::warn!(uuid = %msg_uuid, %error, "Claim heartbeat failure.");
+                        }
+                    }
+                }
+            }
+        });
+
+        Ok(Some(Self {
+            queue_manager,
+            msg_uuid,
+            heartbeat: Some(heartbeat),
+        }))
+    }
+
+    /// Fails if the claim has been lost, the message being processed by another instance.
+    pub fn ensure_held(&self) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            !self
+                .heartbeat
+                .as_ref()
+                .map_or(true, tokio::task::JoinHandle::is_finished),
+            "the claim of the message has been lost"
+        );
+        Ok(())
+    }
+
+    /// Run the processing of the message, cancelled if the claim is lost.
+    pub async fn run<T: Send>(
+        &mut self,
+        processing: impl std::future::Future<Output = anyhow::Result<T>> + Send,
+    ) -> anyhow::Result<T> {
+        self.ensure_held()?;
+        let heartbeat = self
+            .heartbeat
+            .as_mut()
+            .context("the claim has been released")?;
+
+        tokio::select! {
+            outcome = processing => return outcome,
+            _ = heartbeat => (),
+        }
+
+        // NOTE: the claim belongs to another instance, it is not released.
+        self.heartbeat = None;
+        anyhow::bail!("the claim of the message has been lost, the processing is cancelled")
+    }
+
+    /// Release the claim, before handing the message over to another process.
+    pub async fn release(mut self) {
+        if let Some(heartbeat) = self.heartbeat.take() {
+            heartbeat.abort();
+        }
+        if let Err(error) = self.queue_manager.release(&self.msg_uuid).await {
+            tracing::warn!(%error, "Claim release failure.");
+        }
+    }
+}
+
+impl<Q: GenericQueueManager + Sized + 'static> Drop for Guard<Q> {
+    fn drop(&mut self) {
+        if let Some(heartbeat) = self.heartbeat.take() {
+            heartbeat.abort();
+
+            let (queue_manager, msg_uuid) = (self.queue_manager.clone(), self.msg_uuid);
+            tokio::spawn(async move {
+                if let Err(error) = queue_manager.release(&msg_uuid).await {
+                    tracing::warn!(%error, "Claim release failure.");
+                }
+            });
+        }
+    }
+}
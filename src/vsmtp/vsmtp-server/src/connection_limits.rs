@@ -0,0 +1,190 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2023 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use std::collections::HashMap;
+use vsmtp_config::field::FieldServerConnectionLimits;
+
+/// What a connection is counted on.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Key {
+    /// The address of a client not authenticated.
+    Anonymous(std::net::IpAddr),
+    /// The identity of a client authenticated.
+    Authenticated(String),
+}
+
+/// Connections open, per IP address of the anonymous clients and per identity of the
+/// authenticated ones (see [`FieldServerConnectionLimits`]).
+#[derive(Debug)]
+pub struct ConnectionLimits {
+    anonymous_per_ip: Option<usize>,
+    authenticated_per_identity: Option<usize>,
+    counts: std::sync::Mutex<HashMap<Key, usize>>,
+}
+
+/// A connection counted in [`ConnectionLimits`], released on drop.
+#[derive(Debug)]
+pub struct ConnectionSlot {
+    limits: std::sync::Arc<ConnectionLimits>,
+    key: Key,
+}
+
+impl ConnectionLimits {
+    /// Create the bookkeeping of the limits of the configuration.
+    #[must_use]
+    pub fn new(config: &FieldServerConnectionLimits) -> Self {
+        Self {
+            anonymous_per_ip: config.anonymous_per_ip,
+            authenticated_per_identity: config.authenticated_per_identity,
+            counts: std::sync::Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn counts(&self) -> std::sync::MutexGuard<'_, HashMap<Key, usize>> {
+        self.counts
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+
+    const fn max(&self, key: &Key) -> Option<usize> {
+        match key {
+            Key::Anonymous(_) => self.anonymous_per_ip,
+            Key::Authenticated(_) => self.authenticated_per_identity,
+        }
+    }
+
+    /// Count `key` if under its cap.
+    fn try_add(&self, counts: &mut HashMap<Key, usize>, key: &Key) -> bool {
+        let count = counts.get(key).copied().unwrap_or_default();
+        if self.max(key).map_or(false, |max| count >= max) {
+            return false;
+        }
+        counts.insert(key.clone(), count + 1);
+        true
+    }
+
+    fn remove(counts: &mut HashMap<Key, usize>, key: &Key) {
+        if let Some(count) = counts.get_mut(key) {
+            *count -= 1;
+            if *count == 0 {
+                counts.remove(key);
+            }
+        }
+    }
+
+    /// Count a new connection from `ip`, or `None` if the anonymous cap of the address
+    /// is reached.
+    #[must_use]
+    pub fn acquire(self: &std::sync::Arc<Self>, ip: std::net::IpAddr) -> Option<ConnectionSlot> {
+        let key = Key::Anonymous(ip);
+        self.try_add(&mut self.counts(), &key)
+            .then(|| ConnectionSlot {
+                limits: self.clone(),
+                key,
+            })
+    }
+}
+
+impl ConnectionSlot {
+    /// Count the connection on the `identity` it authenticated with instead of its address,
+    /// returns `false` (and keeps the connection counted as it was) if the cap of the
+    /// identity is reached.
+    pub fn authenticate(&mut self, identity: &str) -> bool {
+        let key = Key::Authenticated(identity.to_owned());
+        if key == self.key {
+            return true;
+        }
+
+        let mut counts = self.limits.counts();
+        if !self.limits.try_add(&mut counts, &key) {
+            return false;
+        }
+        ConnectionLimits::remove(&mut counts, &self.key);
+        drop(counts);
+
+        self.key = key;
+        true
+    }
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        ConnectionLimits::remove(&mut self.limits.counts(), &self.key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits(
+        anonymous_per_ip: Option<usize>,
+        authenticated_per_identity: Option<usize>,
+    ) -> std::sync::Arc<ConnectionLimits> {
+        std::sync::Arc::new(ConnectionLimits::new(&FieldServerConnectionLimits {
+            anonymous_per_ip,
+            authenticated_per_identity,
+        }))
+    }
+
+    const IP: std::net::IpAddr = std::net::IpAddr::V4(std::net::Ipv4Addr::new(192, 0, 2, 1));
+
+    #[test]
+    fn anonymous_capped_per_ip() {
+        let limits = limits(Some(2), None);
+
+        let first = limits.acquire(IP).unwrap();
+        let _second = limits.acquire(IP).unwrap();
+        assert!(limits.acquire(IP).is_none());
+        assert!(limits.acquire("192.0.2.2".parse().unwrap()).is_some());
+
+        drop(first);
+        assert!(limits.acquire(IP).is_some());
+    }
+
+    #[test]
+    fn authenticated_not_counted_on_ip() {
+        let limits = limits(Some(1), Some(3));
+
+        let mut slots = vec![];
+        for _ in 0..3 {
+            let mut slot = limits.acquire(IP).unwrap();
+            assert!(slot.authenticate("john@example.com"));
+            slots.push(slot);
+        }
+
+        let mut slot = limits.acquire(IP).unwrap();
+        assert!(limits.acquire(IP).is_none());
+        assert!(!slot.authenticate("john@example.com"));
+        assert!(slot.authenticate("jenny@example.com"));
+        assert!(limits.acquire(IP).is_some());
+
+        slots.pop();
+        let mut slot = limits.acquire(IP).unwrap();
+        assert!(slot.authenticate("john@example.com"));
+    }
+
+    #[test]
+    fn released_on_drop() {
+        let limits = limits(Some(1), Some(1));
+
+        let mut slot = limits.acquire(IP).unwrap();
+        assert!(slot.authenticate("john@example.com"));
+        drop(slot);
+
+        assert!(limits.counts().is_empty());
+    }
+}
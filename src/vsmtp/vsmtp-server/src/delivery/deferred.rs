@@ -14,18 +14,25 @@
  * this program. If not, see https://www.gnu.org/licenses/.
  *
 */
-use crate::ProcessMessage;
+use crate::{claim, delivery_timings::Disposition, DeliveryTimings, ProcessMessage};
 use anyhow::Context;
 use time::ext::NumericalDuration;
 use vqueue::{GenericQueueManager, QueueID};
-use vsmtp_common::transfer::{Error, Status};
+use vsmtp_common::{
+    transfer::{Error, Status},
+    TimingStage,
+};
 use vsmtp_config::Config;
 use vsmtp_delivery::{split_and_sort_and_send, SenderOutcome};
+use vsmtp_rule_engine::RuleEngine;
 
 pub(crate) async fn flush_deferred_queue<Q: GenericQueueManager + Sized + 'static>(
     config: std::sync::Arc<Config>,
     queue_manager: std::sync::Arc<Q>,
+    rule_engine: std::sync::Arc<RuleEngine>,
     flushing_at: time::OffsetDateTime,
+    panics: &std::sync::atomic::AtomicU64,
+    delivery_timings: &std::sync::Arc<DeliveryTimings>,
 ) {
     let queued = match queue_manager.list(&QueueID::Deferred).await {
         Ok(queued) => queued,
@@ -48,11 +55,19 @@ pub(crate) async fn flush_deferred_queue<Q: GenericQueueManager + Sized + 'stati
             }
         };
 
-        if let Err(error) = handle_one(
-            config.clone(),
-            queue_manager.clone(),
-            ProcessMessage::new(message_uuid),
-            flushing_at,
+        if let Some(Err(error)) = crate::watchdog::catch_panic(
+            queue_manager.as_ref(),
+            &QueueID::Deferred,
+            &message_uuid,
+            panics,
+            handle_one(
+                config.clone(),
+                queue_manager.clone(),
+                ProcessMessage::new(message_uuid),
+                Some(rule_engine.clone()),
+                flushing_at,
+                delivery_timings.clone(),
+            ),
         )
         .await
         {
@@ -62,18 +77,60 @@ pub(crate) async fn flush_deferred_queue<Q: GenericQueueManager + Sized + 'stati
 }
 
 /// Handle one message in the deferred queue.
-#[tracing::instrument(name = "deferred", skip_all, err, fields(uuid = %process_message.as_ref()))]
+///
+/// The recipients are routed by `rule_engine` before the attempt, see [`RuleEngine::route_delivery`],
+/// and the final disposition of the message is reported to `delivery_timings`.
+#[tracing::instrument(name = "deferred", parent = process_message.span(), skip_all, err, fields(uuid = %process_message.as_ref(), queue_id = tracing::field::Empty))]
 pub async fn handle_one<Q: GenericQueueManager + Sized + 'static>(
     config: std::sync::Arc<Config>,
     queue_manager: std::sync::Arc<Q>,
     process_message: ProcessMessage,
+    rule_engine: Option<std::sync::Arc<RuleEngine>>,
     flushing_at: time::OffsetDateTime,
+    delivery_timings: std::sync::Arc<DeliveryTimings>,
 ) -> anyhow::Result<()> {
     tracing::debug!("Processing email.");
 
+    let Some(mut claim) =
+        claim::Guard::acquire(queue_manager.clone(), *process_message.as_ref()).await?
+    else {
+        return Ok(());
+    };
+
+    let outcome = claim
+        .run(handle_claimed(
+            config,
+            queue_manager,
+            &process_message,
+            rule_engine,
+            flushing_at,
+            &delivery_timings,
+        ))
+        .await;
+
+    // NOTE: the claim is released before returning, so that the message can be
+    //       processed again right away.
+    claim.release().await;
+    outcome
+}
+
+async fn handle_claimed<Q: GenericQueueManager + Sized + 'static>(
+    config: std::sync::Arc<Config>,
+    queue_manager: std::sync::Arc<Q>,
+    process_message: &ProcessMessage,
+    rule_engine: Option<std::sync::Arc<RuleEngine>>,
+    flushing_at: time::OffsetDateTime,
+    delivery_timings: &DeliveryTimings,
+) -> anyhow::Result<()> {
     let mut ctx = queue_manager
         .get_ctx(&QueueID::Deferred, process_message.as_ref())
         .await?;
+    tracing::Span::current().record("queue_id", ctx.mail_from.queue_id());
+
+    if matches!(ctx.finished.deferred_until, Some(deferred_until) if deferred_until > flushing_at) {
+        tracing::debug!("Email is deferred by the rules.");
+        return Ok(());
+    }
 
     let last_error = ctx
         .rcpt_to
@@ -108,19 +165,47 @@ pub async fn handle_one<Q: GenericQueueManager + Sized + 'static>(
         _ => {}
     }
 
-    let msg = queue_manager.get_msg(process_message.as_ref()).await?;
+    // NOTE: without rule engine, the message is sent with a state of its own.
+    let state = rule_engine
+        .as_ref()
+        .map_or_else(Default::default, |rule_engine| {
+            rule_engine.srv().delivery.clone()
+        });
+    if let Some(delay) = rule_engine.and_then(|rule_engine| rule_engine.route_delivery(&mut ctx)) {
+        ctx.finished.defer(delay);
+        tracing::debug!(?delay, "Email is deferred by the routing.");
 
-    match split_and_sort_and_send(config, &mut ctx, &msg).await {
-        SenderOutcome::MoveToDead => queue_manager
-            .move_to(&QueueID::Deferred, &QueueID::Dead, &ctx)
+        return queue_manager
+            .write_ctx(&QueueID::Deferred, &ctx)
             .await
-            .with_context(|| {
-                format!(
-                    "cannot move file from `{}` to `{}`",
-                    QueueID::Deferred,
-                    QueueID::Dead
-                )
-            }),
+            .with_context(|| format!("failed to update context in `{}`", QueueID::Deferred));
+    }
+
+    let msg = queue_manager.get_msg(process_message.as_ref()).await?;
+    let stripped = crate::strip_received::strip_received(&config, &ctx, &msg);
+
+    ctx.finished.timings.record(TimingStage::Queued);
+    let outcome =
+        split_and_sort_and_send(config, &state, &mut ctx, stripped.as_ref().unwrap_or(&msg)).await;
+    ctx.finished.timings.record(TimingStage::InFlight);
+
+    match outcome {
+        SenderOutcome::MoveToDead => {
+            queue_manager
+                .move_to(&QueueID::Deferred, &QueueID::Dead, &ctx)
+                .await
+                .with_context(|| {
+                    format!(
+                        "cannot move file from `{}` to `{}`",
+                        QueueID::Deferred,
+                        QueueID::Dead
+                    )
+                })?;
+
+            delivery_timings.report(&ctx, Disposition::Bounced);
+
+            crate::dsn::queue_failure_report(queue_manager.as_ref(), &ctx, &msg).await
+        }
 
         SenderOutcome::MoveToDeferred => queue_manager
             .write_ctx(&QueueID::Deferred, &ctx)
@@ -130,7 +215,11 @@ pub async fn handle_one<Q: GenericQueueManager + Sized + 'static>(
         SenderOutcome::RemoveFromDisk => {
             queue_manager
                 .remove_both(&QueueID::Deferred, process_message.as_ref())
-                .await
+                .await?;
+
+            delivery_timings.report(&ctx, Disposition::Delivered);
+
+            Ok(())
         }
     }
 }
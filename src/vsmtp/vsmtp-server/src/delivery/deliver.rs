@@ -14,21 +14,28 @@
  * this program. If not, see https://www.gnu.org/licenses/.
  *
 */
-use crate::{delegate, delivery::add_trace_information, ProcessMessage};
+use crate::{
+    claim, delegate, delivery::add_trace_information, delivery_timings::Disposition,
+    DeliveryTimings, ProcessMessage,
+};
 use anyhow::Context;
 use vqueue::{GenericQueueManager, QueueID};
 use vsmtp_common::{
     status,
     transfer::{self, error::Rule},
+    ContextFinished, TimingStage,
 };
 use vsmtp_config::Config;
 use vsmtp_delivery::{split_and_sort_and_send, SenderOutcome};
+use vsmtp_mail_parser::MessageBody;
 use vsmtp_rule_engine::{ExecutionStage, RuleEngine};
 
 pub(crate) async fn flush_deliver_queue<Q: GenericQueueManager + Sized + 'static>(
     config: std::sync::Arc<Config>,
     queue_manager: std::sync::Arc<Q>,
     rule_engine: std::sync::Arc<RuleEngine>,
+    panics: &std::sync::atomic::AtomicU64,
+    delivery_timings: &std::sync::Arc<DeliveryTimings>,
 ) {
     // FIXME: add span on the function.
     tracing::info!("Flushing deliver queue.");
@@ -54,24 +61,33 @@ pub(crate) async fn flush_deliver_queue<Q: GenericQueueManager + Sized + 'static
             }
         };
 
-        let _err = handle_one(
-            config.clone(),
-            queue_manager.clone(),
-            ProcessMessage::new(message_uuid),
-            rule_engine.clone(),
+        let _err = crate::watchdog::catch_panic(
+            queue_manager.as_ref(),
+            &QueueID::Deliver,
+            &message_uuid,
+            panics,
+            handle_one(
+                config.clone(),
+                queue_manager.clone(),
+                ProcessMessage::new(message_uuid),
+                rule_engine.clone(),
+                delivery_timings.clone(),
+            ),
         )
         .await;
     }
 }
 
 /// Handle one message in the delivery queue.
-#[allow(clippy::too_many_lines)]
-#[tracing::instrument(name = "delivery", skip_all, err(Debug), fields(uuid = %process_message.as_ref()))]
+///
+/// The final disposition of the message is reported to `delivery_timings`.
+#[tracing::instrument(name = "delivery", parent = process_message.span(), skip_all, err(Debug), fields(uuid = %process_message.as_ref(), queue_id = tracing::field::Empty))]
 pub async fn handle_one<Q: GenericQueueManager + Sized + 'static>(
     config: std::sync::Arc<Config>,
     queue_manager: std::sync::Arc<Q>,
     process_message: ProcessMessage,
     rule_engine: std::sync::Arc<RuleEngine>,
+    delivery_timings: std::sync::Arc<DeliveryTimings>,
 ) -> anyhow::Result<()> {
     let queue = if process_message.is_from_delegation() {
         QueueID::Delegated
@@ -79,9 +95,69 @@ pub async fn handle_one<Q: GenericQueueManager + Sized + 'static>(
         QueueID::Deliver
     };
 
+    let Some(mut claim) =
+        claim::Guard::acquire(queue_manager.clone(), *process_message.as_ref()).await?
+    else {
+        return Ok(());
+    };
+
+    let outcome = claim
+        .run(handle_claimed(
+            config,
+            queue_manager,
+            queue,
+            &process_message,
+            rule_engine,
+            &delivery_timings,
+        ))
+        .await;
+
+    // NOTE: the claim is released before returning, so that the message can be
+    //       processed again right away.
+    claim.release().await;
+    outcome
+}
+
+#[allow(clippy::too_many_lines)]
+async fn handle_claimed<Q: GenericQueueManager + Sized + 'static>(
+    config: std::sync::Arc<Config>,
+    queue_manager: std::sync::Arc<Q>,
+    queue: QueueID,
+    process_message: &ProcessMessage,
+    rule_engine: std::sync::Arc<RuleEngine>,
+    delivery_timings: &DeliveryTimings,
+) -> anyhow::Result<()> {
     let (ctx, msg) = queue_manager
         .get_both(&queue, process_message.as_ref())
         .await?;
+    tracing::Span::current().record("queue_id", ctx.mail_from.queue_id());
+
+    deliver_one(
+        config,
+        queue_manager,
+        rule_engine,
+        &queue,
+        ctx,
+        msg,
+        delivery_timings,
+    )
+    .await
+}
+
+/// Run the rule engine at the stage `Delivery` and send the message, stored in
+/// `queue`, then move it following the outcome.
+///
+/// The caller must hold the claim of the message.
+pub(crate) async fn deliver_one<Q: GenericQueueManager + Sized + 'static>(
+    config: std::sync::Arc<Config>,
+    queue_manager: std::sync::Arc<Q>,
+    rule_engine: std::sync::Arc<RuleEngine>,
+    queue: &QueueID,
+    ctx: ContextFinished,
+    msg: MessageBody,
+    delivery_timings: &DeliveryTimings,
+) -> anyhow::Result<()> {
+    let message_uuid = ctx.mail_from.message_uuid;
 
     let mut skipped = ctx.connect.skipped.clone();
     let (ctx, mut msg, result) = rule_engine.just_run_when(
@@ -96,12 +172,10 @@ pub async fn handle_one<Q: GenericQueueManager + Sized + 'static>(
     match &skipped {
         Some(status @ status::Status::Quarantine(path)) => {
             queue_manager
-                .move_to(&queue, &QueueID::Quarantine { name: path.into() }, &ctx)
+                .move_to(queue, &QueueID::Quarantine { name: path.into() }, &ctx)
                 .await?;
 
-            queue_manager
-                .write_msg(process_message.as_ref(), &msg)
-                .await?;
+            queue_manager.write_msg(&message_uuid, &msg).await?;
 
             tracing::warn!(status = status.as_ref(), "Rules skipped.");
 
@@ -111,12 +185,10 @@ pub async fn handle_one<Q: GenericQueueManager + Sized + 'static>(
             ctx.connect.skipped = Some(status::Status::DelegationResult);
 
             queue_manager
-                .move_to(&queue, &QueueID::Delegated, &ctx)
+                .move_to(queue, &QueueID::Delegated, &ctx)
                 .await?;
 
-            queue_manager
-                .write_msg(process_message.as_ref(), &msg)
-                .await?;
+            queue_manager.write_msg(&message_uuid, &msg).await?;
 
             // NOTE: needs to be executed after writing, because the other
             //       thread could pickup the email faster than this function.
@@ -126,6 +198,19 @@ pub async fn handle_one<Q: GenericQueueManager + Sized + 'static>(
 
             return Ok(());
         }
+        Some(status @ status::Status::Defer(delay)) => {
+            ctx.finished.defer(*delay);
+
+            queue_manager
+                .move_to(queue, &QueueID::Deferred, &ctx)
+                .await?;
+
+            queue_manager.write_msg(&message_uuid, &msg).await?;
+
+            tracing::warn!(status = status.as_ref(), ?delay, "Rules skipped.");
+
+            return Ok(());
+        }
         Some(status::Status::DelegationResult) => {
             anyhow::bail!(
                 "delivery is the last stage, delegation results cannot travel down any further."
@@ -136,11 +221,11 @@ pub async fn handle_one<Q: GenericQueueManager + Sized + 'static>(
                 rcpt.1 = transfer::Status::failed(Rule::Denied(code.clone()));
             }
 
-            queue_manager.move_to(&queue, &QueueID::Dead, &ctx).await?;
+            queue_manager.move_to(queue, &QueueID::Dead, &ctx).await?;
 
-            queue_manager
-                .write_msg(process_message.as_ref(), &msg)
-                .await?;
+            queue_manager.write_msg(&message_uuid, &msg).await?;
+
+            delivery_timings.report(&ctx, Disposition::Bounced);
 
             return Ok(());
         }
@@ -150,29 +235,58 @@ pub async fn handle_one<Q: GenericQueueManager + Sized + 'static>(
         None => {}
     };
 
-    add_trace_information(&ctx, &mut msg, &result)?;
+    if let Some(delay) = rule_engine.route_delivery(&mut ctx) {
+        ctx.finished.defer(delay);
+
+        queue_manager
+            .move_to(queue, &QueueID::Deferred, &ctx)
+            .await?;
+
+        queue_manager.write_msg(&message_uuid, &msg).await?;
+
+        tracing::debug!(?delay, "Email is deferred by the routing.");
 
-    match split_and_sort_and_send(config, &mut ctx, &msg).await {
+        return Ok(());
+    }
+
+    add_trace_information(&config, &ctx, &mut msg, &result)?;
+
+    // NOTE: only the copy sent is stripped, the message in the queue is kept intact.
+    let stripped = crate::strip_received::strip_received(&config, &ctx, &msg);
+
+    ctx.finished.timings.record(TimingStage::Queued);
+    let outcome = split_and_sort_and_send(
+        config,
+        &rule_engine.srv().delivery,
+        &mut ctx,
+        stripped.as_ref().unwrap_or(&msg),
+    )
+    .await;
+    ctx.finished.timings.record(TimingStage::InFlight);
+
+    match outcome {
         SenderOutcome::MoveToDead => {
-            queue_manager.move_to(&queue, &QueueID::Dead, &ctx).await?;
+            queue_manager.move_to(queue, &QueueID::Dead, &ctx).await?;
 
-            queue_manager
-                .write_msg(process_message.as_ref(), &msg)
-                .await
+            queue_manager.write_msg(&message_uuid, &msg).await?;
+
+            delivery_timings.report(&ctx, Disposition::Bounced);
+
+            crate::dsn::queue_failure_report(queue_manager.as_ref(), &ctx, &msg).await
         }
         SenderOutcome::MoveToDeferred => {
             queue_manager
-                .move_to(&queue, &QueueID::Deferred, &ctx)
+                .move_to(queue, &QueueID::Deferred, &ctx)
                 .await?;
 
-            queue_manager
-                .write_msg(process_message.as_ref(), &msg)
-                .await
+            queue_manager.write_msg(&message_uuid, &msg).await
         }
         SenderOutcome::RemoveFromDisk => {
-            queue_manager
-                .remove_both(&queue, process_message.as_ref())
-                .await
+            queue_manager.remove_both(queue, &message_uuid).await?;
+
+            delivery_timings.report(&ctx, Disposition::Delivered);
+
+            Ok(())
         }
     }
 }
@@ -19,12 +19,12 @@ use crate::{
         deferred::flush_deferred_queue,
         deliver::{flush_deliver_queue, handle_one},
     },
-    scheduler,
+    scheduler, DeliveryTimings,
 };
 use anyhow::Context;
 use time::format_description::well_known::Rfc2822;
 use tokio_stream::StreamExt;
-use vqueue::GenericQueueManager;
+use vqueue::{GenericQueueManager, QueueID};
 use vsmtp_common::status::Status;
 use vsmtp_common::ContextFinished;
 use vsmtp_config::Config;
@@ -41,19 +41,50 @@ pub(crate) async fn start<Q: GenericQueueManager + Sized + 'static>(
     rule_engine: std::sync::Arc<RuleEngine>,
     queue_manager: std::sync::Arc<Q>,
     mut receiver: scheduler::Receiver,
+    delivery_timings: std::sync::Arc<DeliveryTimings>,
 ) {
-    flush_deliver_queue(config.clone(), queue_manager.clone(), rule_engine.clone()).await;
+    let panics = receiver.panics();
+    // NOTE: the connections kept open belong to this runtime.
+    tokio::spawn(rule_engine.srv().delivery.clone().sweep_idle_connections());
+    flush_deliver_queue(
+        config.clone(),
+        queue_manager.clone(),
+        rule_engine.clone(),
+        &panics,
+        &delivery_timings,
+    )
+    .await;
 
     let mut flush_deferred_interval =
         tokio::time::interval(config.server.queues.delivery.deferred_retry_period);
 
-    let delivery_receiver = receiver.as_stream().map(|pm| {
-        tokio::spawn(handle_one(
+    let delivery_receiver = receiver.as_bounded_stream().map(|(pm, slot)| {
+        let queue = if pm.is_from_delegation() {
+            QueueID::Delegated
+        } else {
+            QueueID::Deliver
+        };
+        let msg_uuid = *pm.as_ref();
+        let handle = handle_one(
             config.clone(),
             queue_manager.clone(),
             pm,
             rule_engine.clone(),
-        ))
+            delivery_timings.clone(),
+        );
+        let (queue_manager, panics) = (queue_manager.clone(), panics.clone());
+        tokio::spawn(async move {
+            // NOTE: the errors are logged by `handle_one`.
+            let _err = crate::watchdog::catch_panic(
+                queue_manager.as_ref(),
+                &queue,
+                &msg_uuid,
+                &panics,
+                handle,
+            )
+            .await;
+            drop(slot);
+        })
     });
     tokio::pin!(delivery_receiver);
 
@@ -64,41 +95,65 @@ pub(crate) async fn start<Q: GenericQueueManager + Sized + 'static>(
                 tracing::info!("cronjob delay elapsed `{}s`, flushing queue.",
                     config.server.queues.delivery.deferred_retry_period.as_secs());
 
-                tokio::spawn(
+                let (config, queue_manager, rule_engine, panics, delivery_timings) = (
+                    config.clone(),
+                    queue_manager.clone(),
+                    rule_engine.clone(),
+                    panics.clone(),
+                    delivery_timings.clone(),
+                );
+                tokio::spawn(async move {
                     flush_deferred_queue(
-                        config.clone(),
-                        queue_manager.clone(),
-                        time::OffsetDateTime::now_utc(),
+                        config,
+                        queue_manager,
+                        rule_engine,
+                        vsmtp_common::clock::now(),
+                        &panics,
+                        &delivery_timings,
                     )
-                );
+                    .await;
+                });
             }
         };
     }
 }
 
 // <https://datatracker.ietf.org/doc/html/rfc5321#section-4.4>
+//
+// NOTE: the context keeps the real name of the server, only the header discloses
+//       the name configured in `server.disclosure`.
 fn add_trace_information(
+    config: &Config,
     ctx: &ContextFinished,
     message: &mut MessageBody,
     status: &Status,
 ) -> anyhow::Result<()> {
-    message.prepend_header(
-        "X-VSMTP",
-        &format!(
-            "id=\"{message_uuid}\"; version=\"{version}\"; status=\"{status}\"",
-            message_uuid = ctx.mail_from.message_uuid,
-            version = env!("CARGO_PKG_VERSION"),
-            status = status.as_ref()
-        ),
-    );
+    if config.server.discloses_software() {
+        message.prepend_header(
+            "X-VSMTP",
+            &format!(
+                "id=\"{message_uuid}\"; version=\"{version}\"; status=\"{status}\"",
+                message_uuid = ctx.mail_from.message_uuid,
+                version = env!("CARGO_PKG_VERSION"),
+                status = status.as_ref()
+            ),
+        );
+    }
 
     message.prepend_header(
         "Received",
         &format!(
-            "from {client_helo} by {server_domain} with SMTP id {message_uuid}; {date}",
+            "from {client_helo} ({client_ip}) by {server_domain} with SMTP id {queue_id}; {date}",
             client_helo = ctx.helo.client_name,
-            server_domain = ctx.connect.server_name,
-            message_uuid = ctx.mail_from.message_uuid,
+            client_ip = match ctx.connect.client_addr.ip() {
+                std::net::IpAddr::V4(ip) => format!("[{ip}]"),
+                std::net::IpAddr::V6(ip) => format!("[IPv6:{ip}]"),
+            },
+            server_domain = config
+                .server
+                .disclosed_name()
+                .unwrap_or(&ctx.connect.server_name),
+            queue_id = ctx.mail_from.queue_id(),
             date = ctx
                 .mail_from
                 .mail_timestamp
@@ -115,8 +170,9 @@ mod test {
     use super::add_trace_information;
     use time::format_description::well_known::Rfc2822;
     use vsmtp_common::status::Status;
+    use vsmtp_config::field::FieldServerDisclosure;
     use vsmtp_mail_parser::{MessageBody, RawBody};
-    use vsmtp_test::config::local_ctx;
+    use vsmtp_test::config::{local_ctx, local_test};
 
     #[test]
     fn test_add_trace_information() {
@@ -125,16 +181,23 @@ mod test {
         let mut message = MessageBody::default();
         let msg_uuid = uuid::Uuid::nil();
         ctx.mail_from.message_uuid = msg_uuid;
-        add_trace_information(&ctx, &mut message, &Status::Next).unwrap();
+        add_trace_information(&local_test(), &ctx, &mut message, &Status::Next).unwrap();
+
+        let queue_id = ctx.mail_from.queue_id();
+        assert_eq!(queue_id.len(), 12);
+        assert!(queue_id
+            .chars()
+            .all(|c| c.is_ascii_digit() || c.is_ascii_uppercase()));
 
         pretty_assertions::assert_eq!(
             *message.inner(),
             RawBody::new_empty(vec![
                 [
                     "Received: from client.testserver.com".to_string(),
+                    format!(" ([{}])", ctx.connect.client_addr.ip()),
                     " by testserver.com".to_string(),
                     " with SMTP".to_string(),
-                    " id 00000000-0000-0000-0000-000000000000; ".to_string(),
+                    format!(" id {queue_id}; "),
                     ctx.mail_from.mail_timestamp.format(&Rfc2822).unwrap(),
                     "\r\n".to_string()
                 ]
@@ -146,4 +209,47 @@ mod test {
             ])
         );
     }
+
+    #[test]
+    fn trace_information_disclosure() {
+        let ctx = local_ctx();
+        let mut config = local_test();
+        config.server.disclosure = Some(FieldServerDisclosure {
+            name: Some("mx.example.com".parse().unwrap()),
+            software: false,
+        });
+
+        let mut message = MessageBody::default();
+        add_trace_information(&config, &ctx, &mut message, &Status::Next).unwrap();
+
+        pretty_assertions::assert_eq!(
+            *message.inner(),
+            RawBody::new_empty(vec![[
+                "Received: from client.testserver.com".to_string(),
+                format!(" ([{}])", ctx.connect.client_addr.ip()),
+                " by mx.example.com".to_string(),
+                " with SMTP".to_string(),
+                format!(" id {}; ", ctx.mail_from.queue_id()),
+                ctx.mail_from.mail_timestamp.format(&Rfc2822).unwrap(),
+                "\r\n".to_string()
+            ]
+            .concat()])
+        );
+    }
+
+    #[test]
+    fn trace_information_latin1() {
+        let raw = b"From: Andr\xe9 <andre@example.com>\r\n\
+Subject: caf\xe9\r\n\
+\r\n\
+Latin-1 body: \xe9t\xe9\r\n";
+
+        let mut message = MessageBody::try_from(raw.as_slice()).unwrap();
+        add_trace_information(&local_test(), &local_ctx(), &mut message, &Status::Next).unwrap();
+
+        // the headers are added, the rest of the message is sent as received.
+        let bytes = message.inner().to_bytes();
+        assert!(bytes.starts_with(b"Received: from client.testserver.com"));
+        assert!(bytes.ends_with(raw));
+    }
 }
@@ -0,0 +1,211 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2023 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+
+//! Time from the reception of the messages to their final disposition, by stage,
+//! for the delivery SLAs.
+//!
+//! The stages are recorded in the context of the message ([`vsmtp_common::Timings`])
+//! at each transition between the queues, and reported once the message is delivered
+//! or bounced to the [`DeliveryTimings`] of the runtime.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use vsmtp_common::{ContextFinished, TimingStage};
+
+/// Upper bounds of the buckets of the histograms, in seconds.
+const BUCKETS: [u64; 9] = [1, 10, 60, 300, 900, 1800, 3600, 4 * 3600, 24 * 3600];
+
+/// Final disposition of a message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, strum::AsRefStr)]
+#[strum(serialize_all = "lowercase")]
+pub(crate) enum Disposition {
+    /// All the recipients have been sent the message.
+    Delivered,
+    /// The message has been moved to the dead queue.
+    Bounced,
+}
+
+#[derive(Debug, Default)]
+struct Histogram {
+    /// Number of observations by bucket, the last one for the values above [`BUCKETS`].
+    buckets: [AtomicU64; BUCKETS.len() + 1],
+    sum_ms: AtomicU64,
+}
+
+impl Histogram {
+    fn observe(&self, ms: u64) {
+        let bucket = BUCKETS
+            .iter()
+            .position(|le| ms <= le * 1000)
+            .unwrap_or(BUCKETS.len());
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.sum_ms.fetch_add(ms, Ordering::Relaxed);
+    }
+
+    fn render(&self, name: &str, stage: &str, output: &mut String) {
+        let mut count = 0;
+        for (le, bucket) in BUCKETS.iter().zip(&self.buckets) {
+            count += bucket.load(Ordering::Relaxed);
+            output.push_str(&format!(
+                "{name}_bucket{{stage=\"{stage}\",le=\"{le}\"}} {count}\n"
+            ));
+        }
+        count += self.buckets[BUCKETS.len()].load(Ordering::Relaxed);
+        output.push_str(&format!(
+            "{name}_bucket{{stage=\"{stage}\",le=\"+Inf\"}} {count}\n"
+        ));
+        output.push_str(&format!(
+            "{name}_sum{{stage=\"{stage}\"}} {}.{:03}\n",
+            self.sum_ms.load(Ordering::Relaxed) / 1000,
+            self.sum_ms.load(Ordering::Relaxed) % 1000
+        ));
+        output.push_str(&format!("{name}_count{{stage=\"{stage}\"}} {count}\n"));
+    }
+}
+
+/// Histograms of the time spent by the messages in each stage, and in total.
+///
+/// Shared by the working and delivery runtimes, and exposed on `GET /metrics`
+/// (see [`crate::Health::with_delivery_timings`]).
+#[derive(Debug, Default)]
+pub struct DeliveryTimings {
+    working: Histogram,
+    queued: Histogram,
+    in_flight: Histogram,
+    total: Histogram,
+    delivered: AtomicU64,
+    bounced: AtomicU64,
+}
+
+impl DeliveryTimings {
+    const fn histogram(&self, stage: TimingStage) -> &Histogram {
+        match stage {
+            TimingStage::Working => &self.working,
+            TimingStage::Queued => &self.queued,
+            TimingStage::InFlight => &self.in_flight,
+        }
+    }
+
+    /// Record the final disposition of a message, once it has left the queues.
+    pub(crate) fn report(&self, ctx: &ContextFinished, disposition: Disposition) {
+        match disposition {
+            Disposition::Delivered => self.delivered.fetch_add(1, Ordering::Relaxed),
+            Disposition::Bounced => self.bounced.fetch_add(1, Ordering::Relaxed),
+        };
+
+        let timings = &ctx.finished.timings;
+        let Some(total_ms) = timings.total_ms() else {
+            // received by a previous version, before the timings were recorded.
+            return;
+        };
+
+        for stage in <TimingStage as strum::IntoEnumIterator>::iter() {
+            self.histogram(stage).observe(timings.stage_ms(stage));
+        }
+        self.total.observe(total_ms);
+
+        tracing::info!(
+            disposition = disposition.as_ref(),
+            total_ms,
+            working_ms = timings.working_ms,
+            queued_ms = timings.queued_ms,
+            in_flight_ms = timings.in_flight_ms,
+            "Message final disposition."
+        );
+    }
+
+    /// Render the histograms in the Prometheus text format.
+    #[must_use]
+    pub fn metrics(&self) -> String {
+        let name = "vsmtp_delivery_duration_seconds";
+        let mut output = format!(
+            "# HELP {name} Time spent by the messages from their reception to their final disposition, by stage.\n# TYPE {name} histogram\n"
+        );
+        for stage in <TimingStage as strum::IntoEnumIterator>::iter() {
+            self.histogram(stage)
+                .render(name, stage.as_ref(), &mut output);
+        }
+        self.total.render(name, "total", &mut output);
+
+        let name = "vsmtp_delivery_final_total";
+        output.push_str(&format!(
+            "# HELP {name} Messages which reached their final disposition.\n# TYPE {name} counter\n"
+        ));
+        for (disposition, count) in [
+            (Disposition::Delivered, &self.delivered),
+            (Disposition::Bounced, &self.bounced),
+        ] {
+            output.push_str(&format!(
+                "{name}{{disposition=\"{}\"}} {}\n",
+                disposition.as_ref(),
+                count.load(Ordering::Relaxed)
+            ));
+        }
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vsmtp_common::Timings;
+    use vsmtp_test::{clock::TestClock, config::local_ctx};
+
+    #[test]
+    fn by_stage() {
+        let timings = DeliveryTimings::default();
+        let clock = TestClock::start();
+
+        let mut ctx = local_ctx();
+        ctx.finished.timings = Timings::received();
+        clock.advance(time::Duration::seconds(2));
+        ctx.finished.timings.record(TimingStage::Working);
+        // deferred once, then delivered.
+        clock.advance(time::Duration::minutes(20));
+        ctx.finished.timings.record(TimingStage::Queued);
+        clock.advance(time::Duration::milliseconds(1500));
+        ctx.finished.timings.record(TimingStage::InFlight);
+        clock.advance(time::Duration::minutes(30));
+        ctx.finished.timings.record(TimingStage::Queued);
+        clock.advance(time::Duration::milliseconds(500));
+        ctx.finished.timings.record(TimingStage::InFlight);
+
+        assert_eq!(ctx.finished.timings.working_ms, 2000);
+        assert_eq!(ctx.finished.timings.queued_ms, 50 * 60 * 1000);
+        assert_eq!(ctx.finished.timings.in_flight_ms, 2000);
+
+        timings.report(&ctx, Disposition::Delivered);
+        // received by a previous version, only counted.
+        timings.report(&local_ctx(), Disposition::Bounced);
+
+        let metrics = timings.metrics();
+        for line in [
+            "vsmtp_delivery_duration_seconds_bucket{stage=\"working\",le=\"1\"} 0\n",
+            "vsmtp_delivery_duration_seconds_bucket{stage=\"working\",le=\"10\"} 1\n",
+            "vsmtp_delivery_duration_seconds_bucket{stage=\"queued\",le=\"1800\"} 0\n",
+            "vsmtp_delivery_duration_seconds_bucket{stage=\"queued\",le=\"3600\"} 1\n",
+            "vsmtp_delivery_duration_seconds_sum{stage=\"queued\"} 3000.000\n",
+            "vsmtp_delivery_duration_seconds_sum{stage=\"in_flight\"} 2.000\n",
+            "vsmtp_delivery_duration_seconds_sum{stage=\"total\"} 3004.000\n",
+            "vsmtp_delivery_duration_seconds_bucket{stage=\"total\",le=\"+Inf\"} 1\n",
+            "vsmtp_delivery_duration_seconds_count{stage=\"total\"} 1\n",
+            "vsmtp_delivery_final_total{disposition=\"delivered\"} 1\n",
+            "vsmtp_delivery_final_total{disposition=\"bounced\"} 1\n",
+        ] {
+            assert!(metrics.contains(line), "{line} not in {metrics}");
+        }
+    }
+}
@@ -0,0 +1,384 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2022 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use vsmtp_auth::dkim;
+use vsmtp_common::{ContextFinished, Reply};
+use vsmtp_config::{field::DkimPreservationFallback, Config};
+use vsmtp_mail_parser::MessageBody;
+
+/// The header fields of a message, a field being a line and its folded lines.
+pub(crate) fn fields(message: &MessageBody) -> Vec<Vec<String>> {
+    let mut fields: Vec<Vec<String>> = vec![];
+    for line in message.inner().raw_headers() {
+        match fields.last_mut() {
+            Some(field) if line.starts_with(' ') || line.starts_with('\t') => {
+                field.push(line.clone());
+            }
+            _ => fields.push(vec![line.clone()]),
+        }
+    }
+    fields
+}
+
+pub(crate) fn has_name(field: &[String], name: &str) -> bool {
+    field
+        .first()
+        .and_then(|line| line.split_once(':'))
+        .map_or(false, |(key, _)| key.trim().eq_ignore_ascii_case(name))
+}
+
+/// The position of the `count` last fields named `name`, the ones covered by a signature.
+fn signed_positions(fields: &[Vec<String>], name: &str, count: usize) -> Vec<usize> {
+    let mut positions = fields
+        .iter()
+        .enumerate()
+        .rev()
+        .filter(|(_, field)| has_name(field, name))
+        .map(|(position, _)| position)
+        .take(count)
+        .collect::<Vec<_>>();
+    positions.reverse();
+    positions
+}
+
+fn signed_fields(fields: &[Vec<String>], name: &str, count: usize) -> Vec<Vec<String>> {
+    signed_positions(fields, name, count)
+        .into_iter()
+        .filter_map(|position| fields.get(position).cloned())
+        .collect()
+}
+
+/// The DKIM signatures of a message received already signed, and the header fields they cover.
+#[derive(Debug)]
+pub(crate) struct SignedFields {
+    /// The lowercase name of the covered headers, the number of instances covered,
+    /// and the fields as received.
+    covered: Vec<(String, usize, Vec<Vec<String>>)>,
+}
+
+impl SignedFields {
+    /// Read the `DKIM-Signature` headers of the message.
+    ///
+    /// Returns `None` if the message has no signature which can be parsed.
+    pub(crate) fn new(message: &MessageBody) -> Option<Self> {
+        let signatures = message
+            .inner()
+            .headers()
+            .into_iter()
+            .filter(|(key, _)| key.eq_ignore_ascii_case("DKIM-Signature"))
+            .filter_map(|(key, value)| {
+                <dkim::Signature as std::str::FromStr>::from_str(&format!("{key}:{value}"))
+                    .map_err(|error| tracing::debug!(%error, "Failed to parse DKIM signature."))
+                    .ok()
+            })
+            .collect::<Vec<_>>();
+        if signatures.is_empty() {
+            return None;
+        }
+
+        // a header listed `n` times covers its `n` last instances, the most covered wins.
+        let mut counts = std::collections::BTreeMap::<String, usize>::new();
+        for signature in &signatures {
+            let mut listed = std::collections::BTreeMap::<String, usize>::new();
+            for name in signature.headers_field() {
+                *listed.entry(name.to_lowercase()).or_default() += 1;
+            }
+            for (name, count) in listed {
+                let covered = counts.entry(name).or_default();
+                *covered = (*covered).max(count);
+            }
+        }
+        // the signatures themselves must be kept.
+        let received = message.count_header("DKIM-Signature");
+        let covered = counts.entry("dkim-signature".to_owned()).or_default();
+        *covered = (*covered).max(received);
+
+        let fields = fields(message);
+        Some(Self {
+            covered: counts
+                .into_iter()
+                .map(|(name, count)| {
+                    let signed = signed_fields(&fields, &name, count);
+                    (name, count, signed)
+                })
+                .collect(),
+        })
+    }
+
+    /// Is the header covered by a signature? (adding or modifying it breaks the signature)
+    pub(crate) fn covers(&self, name: &str) -> bool {
+        self.covered
+            .iter()
+            .any(|(covered, _, _)| covered.eq_ignore_ascii_case(name))
+    }
+
+    /// The names of the covered headers which have been added, removed or modified.
+    pub(crate) fn modified(&self, message: &MessageBody) -> Vec<&str> {
+        let fields = fields(message);
+        self.covered
+            .iter()
+            .filter(|(name, count, signed)| signed_fields(&fields, name, *count) != *signed)
+            .map(|(name, _, _)| name.as_str())
+            .collect()
+    }
+
+    /// Put the covered headers back as received, the other modifications are kept.
+    pub(crate) fn restore(&self, message: &mut MessageBody) {
+        let mut fields = fields(message);
+
+        for (name, count, signed) in &self.covered {
+            let positions = signed_positions(&fields, name, *count);
+            if positions
+                .iter()
+                .filter_map(|position| fields.get(*position))
+                .eq(signed.iter())
+            {
+                continue;
+            }
+
+            // the fields as received take the place of the first one, or are appended if removed.
+            let at = positions.first().copied().unwrap_or(fields.len());
+            fields = fields
+                .into_iter()
+                .enumerate()
+                .filter(|(position, _)| !positions.contains(position))
+                .map(|(_, field)| field)
+                .collect();
+            fields.splice(at..at, signed.iter().cloned());
+        }
+
+        message.set_raw_headers(fields.into_iter().flatten().collect());
+    }
+}
+
+/// Sign the message with the active selector of the server name, covering the
+/// headers modified in addition to the usual ones.
+fn resign(
+    config: &Config,
+    ctx: &ContextFinished,
+    modified: &[&str],
+    message: &mut MessageBody,
+) -> anyhow::Result<()> {
+    let sdid = ctx.connect.server_name.to_string();
+    let selector = config
+        .server
+        .r#virtual
+        .get(&ctx.connect.server_name)
+        .and_then(|r#virtual| r#virtual.dkim.as_ref())
+        .and_then(vsmtp_config::field::FieldDkim::signing_selector)
+        .ok_or_else(|| anyhow::anyhow!("no active dkim selector configured for `{sdid}`"))?;
+
+    let headers_field = ["From", "To", "Date", "Subject", "From"];
+    let headers_field = headers_field
+        .into_iter()
+        .chain(modified.iter().copied().filter(|name| {
+            !name.eq_ignore_ascii_case("dkim-signature")
+                && !headers_field
+                    .iter()
+                    .any(|field| field.eq_ignore_ascii_case(name))
+        }))
+        .map(str::to_owned)
+        .collect::<Vec<_>>();
+
+    let signature = dkim::sign(
+        message.inner(),
+        &selector.private_key.inner,
+        sdid,
+        selector.selector.clone(),
+        "simple/relaxed".parse().expect("default values are valid"),
+        headers_field,
+    )
+    .map_err(|error| anyhow::anyhow!("the signature failed: `{error}`"))?;
+
+    message.prepend_header("DKIM-Signature", &signature.get_signature_value());
+    Ok(())
+}
+
+/// Apply the fallback of the configuration if the signatures of the message received
+/// have been broken by the modifications of the working stage.
+///
+/// Returns the reply denying the message if the fallback rejects it.
+pub(crate) fn check_signatures(
+    config: &Config,
+    ctx: &mut ContextFinished,
+    signed: &SignedFields,
+    message: &mut MessageBody,
+) -> Option<Reply> {
+    let fallback = config.server.dkim_preservation.as_ref()?.fallback;
+
+    let modified = signed.modified(message);
+    if modified.is_empty() {
+        return None;
+    }
+
+    tracing::warn!(
+        headers = ?modified,
+        ?fallback,
+        "The modifications of the message break its DKIM signature."
+    );
+
+    match fallback {
+        DkimPreservationFallback::Reject => {
+            return Some(
+                "554 5.7.0 Message rejected: the modifications would break its DKIM signature\r\n"
+                    .parse::<Reply>()
+                    .expect("valid smtp reply"),
+            );
+        }
+        DkimPreservationFallback::Resign => match resign(config, ctx, &modified, message) {
+            Ok(()) => return None,
+            Err(error) => tracing::warn!(%error, "Failed to sign the message again."),
+        },
+        DkimPreservationFallback::Skip => (),
+    }
+
+    signed.restore(message);
+    if message.get_header("Message-ID").is_none() {
+        ctx.finished.added_message_id = None;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vsmtp_config::field::FieldServerDkimPreservation;
+    use vsmtp_test::config::{local_ctx, local_msg_with_headers, local_test};
+
+    const SIGNATURE: &str = "DKIM-Signature: v=1; a=rsa-sha256; c=relaxed/relaxed; d=example.com;\r\n s=app; h=from:subject:message-id:message-id; bh=YWJj; b=YWJj";
+
+    fn signed() -> MessageBody {
+        local_msg_with_headers(&[
+            SIGNATURE,
+            "From: john@example.com",
+            "Subject: hello",
+            "To: jenny@example.com",
+        ])
+    }
+
+    fn config(fallback: DkimPreservationFallback) -> Config {
+        let mut config = local_test();
+        config.server.dkim_preservation = Some(FieldServerDkimPreservation { fallback });
+        config
+    }
+
+    #[test]
+    fn covered() {
+        assert!(SignedFields::new(&local_msg_with_headers(&["From: john@example.com"])).is_none());
+
+        let signed = SignedFields::new(&signed()).unwrap();
+        assert!(signed.covers("From"));
+        assert!(signed.covers("message-id"));
+        assert!(signed.covers("DKIM-Signature"));
+        assert!(!signed.covers("To"));
+        assert!(!signed.covers("Received"));
+    }
+
+    #[test]
+    fn modified() {
+        let signed = SignedFields::new(&signed()).unwrap();
+
+        let mut message = self::signed();
+        message.prepend_header("Received", "from foo by bar");
+        message.set_header("To", "other@example.com");
+        assert!(signed.modified(&message).is_empty());
+
+        message.set_header("Subject", "[SPAM] hello");
+        assert_eq!(signed.modified(&message), vec!["subject"]);
+
+        // oversigned: no Message-ID can be added.
+        let mut message = self::signed();
+        message.append_header("Message-ID", "<id@example.com>");
+        assert_eq!(signed.modified(&message), vec!["message-id"]);
+    }
+
+    #[test]
+    fn skip() {
+        let config = config(DkimPreservationFallback::Skip);
+        let mut ctx = local_ctx();
+        let signed = SignedFields::new(&signed()).unwrap();
+
+        let mut message = self::signed();
+        message.prepend_header("Received", "from foo by bar");
+        message.set_header("Subject", "[SPAM] hello");
+        message.set_header("To", "other@example.com");
+        message.append_header("Message-ID", "<id@example.com>");
+        ctx.finished.added_message_id = Some("<id@example.com>".to_owned());
+
+        assert_eq!(
+            check_signatures(&config, &mut ctx, &signed, &mut message),
+            None
+        );
+        assert_eq!(
+            *message.inner().raw_headers(),
+            vec![
+                "Received: from foo by bar\r\n".to_owned(),
+                "DKIM-Signature: v=1; a=rsa-sha256; c=relaxed/relaxed; d=example.com;\r\n"
+                    .to_owned(),
+                " s=app; h=from:subject:message-id:message-id; bh=YWJj; b=YWJj\r\n".to_owned(),
+                "From: john@example.com\r\n".to_owned(),
+                "Subject: hello\r\n".to_owned(),
+                "To: other@example.com\r\n".to_owned(),
+            ]
+        );
+        assert_eq!(ctx.finished.added_message_id, None);
+    }
+
+    #[test]
+    fn restore_removed() {
+        let signed = SignedFields::new(&signed()).unwrap();
+
+        let mut message = self::signed();
+        message.remove_header("Subject");
+        signed.restore(&mut message);
+        assert!(signed.modified(&message).is_empty());
+        assert_eq!(message.get_header("Subject").unwrap().trim(), "hello");
+    }
+
+    #[test]
+    fn reject() {
+        let config = config(DkimPreservationFallback::Reject);
+        let signed = SignedFields::new(&signed()).unwrap();
+
+        let mut message = self::signed();
+        assert_eq!(
+            check_signatures(&config, &mut local_ctx(), &signed, &mut message),
+            None
+        );
+
+        message.set_header("From", "jenny@example.com");
+        assert!(check_signatures(&config, &mut local_ctx(), &signed, &mut message).is_some());
+        assert_eq!(
+            message.get_header("From").unwrap().trim(),
+            "jenny@example.com"
+        );
+    }
+
+    #[test]
+    fn resign_without_key() {
+        let config = config(DkimPreservationFallback::Resign);
+        let signed = SignedFields::new(&signed()).unwrap();
+
+        let mut message = self::signed();
+        message.set_header("Subject", "[SPAM] hello");
+        assert_eq!(
+            check_signatures(&config, &mut local_ctx(), &signed, &mut message),
+            None
+        );
+        assert_eq!(message.get_header("Subject").unwrap().trim(), "hello");
+        assert_eq!(message.count_header("DKIM-Signature"), 1);
+    }
+}
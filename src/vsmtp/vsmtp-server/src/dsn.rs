@@ -0,0 +1,720 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2022 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use time::format_description::well_known::Rfc2822;
+use vqueue::{GenericQueueManager, QueueID};
+use vsmtp_common::{
+    transfer::{
+        error::{Delivery, Queuer, Variant},
+        Status,
+    },
+    transport::WrapperSerde,
+    xtext, Address, ContextFinished,
+};
+use vsmtp_config::Config;
+use vsmtp_mail_parser::MessageBody;
+
+/// A recipient reported as failed in the delivery status notification.
+struct FailedRecipient<'a> {
+    rcpt: &'a Address,
+    /// Enhanced status code (RFC 3463).
+    status: String,
+    /// The reply of the remote server, if the failure comes from it.
+    diagnostic: Option<String>,
+    error: String,
+}
+
+/// The status code and the remote reply of a failure.
+fn status_of(variant: &Variant) -> (String, Option<String>) {
+    let remote = match variant {
+        Variant::Delivery(attempts) => attempts.iter().rev().find_map(|(_, error)| match error {
+            Delivery::Permanent { reply, with_source }
+            | Delivery::Transient { reply, with_source } => {
+                Some((reply, with_source.as_deref().unwrap_or_default()))
+            }
+            _ => None,
+        }),
+        _ => None,
+    };
+
+    match remote {
+        Some((reply, text)) => {
+            // NOTE: the enhanced code is kept by `lettre` at the start of the text.
+            let status = reply
+                .details()
+                .map(str::to_owned)
+                .or_else(|| {
+                    text.split_whitespace()
+                        .next()
+                        .filter(|word| {
+                            word.split('.').count() == 3
+                                && word.split('.').all(|digits| digits.parse::<u16>().is_ok())
+                        })
+                        .map(str::to_owned)
+                })
+                .unwrap_or_else(|| format!("{}.0.0", reply.value() / 100));
+            (
+                status,
+                Some(
+                    format!("smtp; {} {text}", reply.value())
+                        .trim_end()
+                        .to_owned(),
+                ),
+            )
+        }
+        None if matches!(variant, Variant::Queuer(Queuer::MaxDeferredAttemptReached)) => {
+            ("4.4.7".to_owned(), None)
+        }
+        None => ("5.0.0".to_owned(), None),
+    }
+}
+
+fn failed_recipients(ctx: &ContextFinished) -> Vec<FailedRecipient<'_>> {
+    ctx.rcpt_to
+        .delivery
+        .values()
+        .flatten()
+        .filter_map(|(rcpt, status)| match status {
+            Status::Failed { error } => {
+                let (status, diagnostic) = status_of(error.variant());
+                Some(FailedRecipient {
+                    rcpt,
+                    status,
+                    diagnostic,
+                    error: error.variant().to_string(),
+                })
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// The address of a recipient, its non-ascii characters encoded as `\x{HEX}`
+/// if the report is not internationalized.
+fn display_address(rcpt: &Address, utf8: bool) -> String {
+    if utf8 || rcpt.full().is_ascii() {
+        rcpt.full().to_owned()
+    } else {
+        xtext::encode_utf8_addr(rcpt.full(), false)
+    }
+}
+
+/// The `address-type; address` of a recipient, for the `Final-Recipient` field.
+fn typed_address(rcpt: &Address, utf8: bool) -> String {
+    if rcpt.full().is_ascii() {
+        format!("rfc822; {}", rcpt.full())
+    } else {
+        format!("utf-8; {}", xtext::encode_utf8_addr(rcpt.full(), utf8))
+    }
+}
+
+/// Encode `text` as `encoded-word`s (RFC 2047) of at most 75 characters.
+fn encoded_words(text: &str) -> String {
+    const MAX_ENCODED_TEXT: usize = 75 - "=?utf-8?Q??=".len();
+
+    let mut words = vec![];
+    let mut word = String::new();
+    for c in text.chars() {
+        let encoded = match c {
+            ' ' => "_".to_owned(),
+            c if c.is_ascii_alphanumeric() || "!*+-/".contains(c) => c.to_string(),
+            c => c
+                .encode_utf8(&mut [0; 4])
+                .bytes()
+                .map(|byte| format!("={byte:02X}"))
+                .collect(),
+        };
+        if word.len() + encoded.len() > MAX_ENCODED_TEXT {
+            words.push(std::mem::take(&mut word));
+        }
+        word.push_str(&encoded);
+    }
+    words.push(word);
+
+    words
+        .into_iter()
+        .map(|word| format!("=?utf-8?Q?{word}?="))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Fields whose value is a list of addresses.
+const ADDRESS_FIELDS: [&str; 11] = [
+    "From",
+    "Sender",
+    "Reply-To",
+    "To",
+    "Cc",
+    "Bcc",
+    "Resent-From",
+    "Resent-Sender",
+    "Resent-To",
+    "Resent-Cc",
+    "Resent-Bcc",
+];
+
+/// Maximum length of the lines of the fields downgraded, without the line break.
+const MAX_LINE_LENGTH: usize = 78;
+
+/// Group the lines of the header section in fields, folded lines included.
+fn header_fields<'a>(lines: impl Iterator<Item = &'a str>) -> Vec<String> {
+    let mut fields = Vec::<String>::new();
+    for line in lines {
+        match fields.last_mut() {
+            Some(field) if line.starts_with([' ', '\t']) => field.push_str(line),
+            _ => fields.push(line.to_owned()),
+        }
+    }
+    fields
+}
+
+/// Split `value` at the commas which are not in a quoted string nor an angle address.
+fn split_addresses(value: &str) -> Vec<&str> {
+    let (mut addresses, mut start) = (vec![], 0);
+    let (mut quoted, mut angle, mut escaped) = (false, false, false);
+    for (index, c) in value.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' if !angle => quoted = !quoted,
+            '<' if !quoted => angle = true,
+            '>' if !quoted => angle = false,
+            ',' if !quoted && !angle => {
+                addresses.push(&value[start..index]);
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    addresses.push(&value[start..]);
+    addresses
+}
+
+/// Downgrade an address: the display name is encoded, and an address whose addr-spec
+/// is not ascii is replaced by an empty group named after it (RFC 6857).
+fn downgrade_address(address: &str) -> String {
+    let address = address.trim();
+    let (display_name, addr_spec) = match address.rsplit_once('<') {
+        Some((display_name, addr_spec)) => (display_name.trim(), format!("<{addr_spec}")),
+        None => ("", address.to_owned()),
+    };
+
+    if !addr_spec.is_ascii() {
+        format!("{} :;", encoded_words(address))
+    } else if display_name.is_ascii() {
+        address.to_owned()
+    } else {
+        let display_name = display_name
+            .strip_prefix('"')
+            .and_then(|name| name.strip_suffix('"'))
+            .map_or_else(|| display_name.to_owned(), |name| name.replace('\\', ""));
+        format!("{} {addr_spec}", encoded_words(&display_name))
+    }
+}
+
+/// Replace the non-ascii words of an unstructured value by `encoded-word`s.
+fn downgrade_text(value: &str) -> String {
+    // NOTE: the spaces between two encoded words are ignored, so consecutive
+    //       non-ascii words are encoded together.
+    let mut words = vec![];
+    let mut non_ascii = vec![];
+    for word in value.split_whitespace() {
+        if word.is_ascii() {
+            if !non_ascii.is_empty() {
+                words.push(encoded_words(&non_ascii.join(" ")));
+                non_ascii.clear();
+            }
+            words.push(word.to_owned());
+        } else {
+            non_ascii.push(word);
+        }
+    }
+    if !non_ascii.is_empty() {
+        words.push(encoded_words(&non_ascii.join(" ")));
+    }
+    words.join(" ")
+}
+
+/// Replace the non-ascii text of a header field by `encoded-word`s, for the report of
+/// a message sent without `SMTPUTF8`, folding it at [`MAX_LINE_LENGTH`] characters.
+///
+/// Only the words of the unstructured fields and the display names of the addresses
+/// are encoded, the addr-specs cannot be.
+fn downgrade_header(field: &str) -> String {
+    if field.is_ascii() {
+        return field.to_owned();
+    }
+    let unfolded = field.trim_end_matches("\r\n").replace("\r\n", "");
+    let Some((name, value)) = unfolded.split_once(':') else {
+        return field.to_owned();
+    };
+
+    let value = if ADDRESS_FIELDS
+        .iter()
+        .any(|address_field| address_field.eq_ignore_ascii_case(name.trim()))
+    {
+        split_addresses(value)
+            .into_iter()
+            .map(downgrade_address)
+            .collect::<Vec<_>>()
+            .join(", ")
+    } else {
+        downgrade_text(value)
+    };
+
+    let mut output = format!("{name}:");
+    let mut line_length = output.len();
+    for word in value.split(' ') {
+        if line_length > 0 && line_length + 1 + word.len() > MAX_LINE_LENGTH {
+            output.push_str("\r\n");
+            line_length = 0;
+        }
+        output.push(' ');
+        output.push_str(word);
+        line_length += 1 + word.len();
+    }
+    output.push_str("\r\n");
+    output
+}
+
+/// Does the failure involve internationalized content (RFC 6530): a non-ascii
+/// address or header?
+fn is_internationalized(
+    sender: &Address,
+    failed: &[FailedRecipient<'_>],
+    message: &MessageBody,
+) -> bool {
+    !sender.full().is_ascii()
+        || failed.iter().any(|failed| !failed.rcpt.full().is_ascii())
+        || message.inner().headers_lines().any(|line| !line.is_ascii())
+}
+
+/// Build the delivery status notification (RFC 3464) of the recipients of `ctx`
+/// which failed, addressed to the sender of the message.
+///
+/// The `ENVID` and `ORCPT` given by the client are reported in the
+/// `Original-Envelope-Id` and `Original-Recipient` fields, `xtext` encoded.
+///
+/// If internationalized content is involved, the report is internationalized
+/// (RFC 6533) and requires `SMTPUTF8` when the message was received with it or
+/// the sender address is not ascii, otherwise the addresses are `\x{HEX}` encoded
+/// and the headers `encoded-word`s so that it can be sent on a path without `SMTPUTF8`.
+///
+/// The server is named as disclosed by the configuration, see [`vsmtp_config::field::FieldServer::disclosed_name`].
+///
+/// Returns `None` if no recipient failed, or if the message has a null reverse
+/// path: a notification is never sent about a notification.
+#[allow(clippy::too_many_lines)]
+pub(crate) fn failure_report(
+    config: &Config,
+    ctx: &ContextFinished,
+    message: &MessageBody,
+) -> Option<(ContextFinished, MessageBody)> {
+    let sender = ctx.mail_from.reverse_path.as_ref()?;
+    let failed = failed_recipients(ctx);
+    if failed.is_empty() {
+        return None;
+    }
+
+    let now = vsmtp_common::clock::now();
+    let date = now.format(&Rfc2822).ok()?;
+    let server_name = config
+        .server
+        .disclosed_name()
+        .unwrap_or(&ctx.connect.server_name);
+    let uuid = vsmtp_common::id::new_uuid();
+    let boundary = format!("{uuid}/{server_name}");
+
+    let utf8 = is_internationalized(sender, &failed, message)
+        && (ctx.mail_from.utf8 || !sender.full().is_ascii());
+
+    let explanation = failed
+        .iter()
+        .map(|failed| {
+            format!(
+                "<{}>: {}\r\n",
+                display_address(failed.rcpt, utf8),
+                failed.error
+            )
+        })
+        .collect::<String>();
+
+    let per_recipient = failed
+        .iter()
+        .map(|failed| {
+            [
+                Some(format!(
+                    "Final-Recipient: {}",
+                    typed_address(failed.rcpt, utf8)
+                )),
+                ctx.rcpt_to
+                    .original_recipients
+                    .get(failed.rcpt)
+                    .map(|original| format!("Original-Recipient: {}", original.to_param(utf8))),
+                Some("Action: failed".to_owned()),
+                Some(format!("Status: {}", failed.status)),
+                failed
+                    .diagnostic
+                    .as_ref()
+                    .map(|diagnostic| format!("Diagnostic-Code: {diagnostic}")),
+            ]
+            .into_iter()
+            .flatten()
+            .map(|field| format!("{field}\r\n"))
+            .collect::<String>()
+        })
+        .collect::<Vec<_>>()
+        .join("\r\n");
+
+    let per_message = [
+        Some(format!("Reporting-MTA: dns; {server_name}")),
+        ctx.mail_from
+            .envelop_id
+            .as_ref()
+            .map(|envelop_id| format!("Original-Envelope-Id: {}", xtext::encode(envelop_id))),
+        ctx.mail_from
+            .mail_timestamp
+            .format(&Rfc2822)
+            .ok()
+            .map(|arrival| format!("Arrival-Date: {arrival}")),
+    ]
+    .into_iter()
+    .flatten()
+    .map(|field| format!("{field}\r\n"))
+    .collect::<String>();
+
+    let headers = [
+        format!("From: Mail Delivery System <MAILER-DAEMON@{server_name}>"),
+        format!("To: <{sender}>"),
+        "Subject: Undelivered Mail Returned to Sender".to_owned(),
+        format!("Date: {date}"),
+        format!("Message-ID: <{uuid}@{server_name}>"),
+        "Auto-Submitted: auto-replied".to_owned(),
+        "MIME-Version: 1.0".to_owned(),
+        format!(
+            "Content-Type: multipart/report; report-type=delivery-status; boundary=\"{boundary}\""
+        ),
+    ]
+    .into_iter()
+    .map(|header| format!("{header}\r\n"))
+    .collect::<Vec<_>>();
+
+    let (status_type, headers_type, returned_headers) = if utf8 {
+        (
+            "message/global-delivery-status",
+            "message/global-headers",
+            message.inner().headers_lines().collect::<String>(),
+        )
+    } else {
+        (
+            "message/delivery-status",
+            "text/rfc822-headers",
+            header_fields(message.inner().headers_lines())
+                .iter()
+                .map(|field| downgrade_header(field))
+                .collect::<String>(),
+        )
+    };
+
+    let body = format!(
+        "--{boundary}\r\n\
+        Content-Type: text/plain; charset=utf-8\r\n\
+        \r\n\
+        This is the mail system at host {server_name}.\r\n\
+        \r\n\
+        Your message could not be delivered to one or more recipients.\r\n\
+        \r\n\
+        {explanation}\
+        \r\n\
+        --{boundary}\r\n\
+        Content-Type: {status_type}\r\n\
+        \r\n\
+        {per_message}\
+        \r\n\
+        {per_recipient}\
+        \r\n\
+        --{boundary}\r\n\
+        Content-Type: {headers_type}\r\n\
+        \r\n\
+        {returned_headers}\
+        \r\n\
+        --{boundary}--\r\n"
+    );
+
+    let mut report = ctx.clone();
+    report.mail_from.reverse_path = None;
+    report.mail_from.message_uuid = uuid;
+    report.mail_from.mail_timestamp = now;
+    report.mail_from.envelop_id = None;
+    report.mail_from.body_type = None;
+    report.mail_from.declared_size = None;
+    report.mail_from.utf8 = utf8;
+    report.rcpt_to.forward_paths = vec![sender.clone()];
+    report.rcpt_to.original_recipients.clear();
+    report.rcpt_to.folders.clear();
+    report.rcpt_to.reverse_paths.clear();
+    report.rcpt_to.catch_all.clear();
+    // NOTE: the transport is stored as on disk, and instantiated by the queue
+    //       manager when the report is read.
+    report.rcpt_to.delivery = std::collections::HashMap::from([(
+        WrapperSerde::Raw(r#"{"type":"deliver"}"#.to_owned()),
+        vec![(sender.clone(), Status::default())],
+    )]);
+    report.finished.rcpt_headers.clear();
+    report.finished.added_message_id = None;
+    report.finished.dkim = None;
+    report.finished.deferred_until = None;
+
+    Some((report, MessageBody::new(headers, body)))
+}
+
+/// Queue the delivery status notification of the failed recipients of `ctx`,
+/// see [`failure_report`]. It is sent at the next flush of the deferred queue.
+pub(crate) async fn queue_failure_report<Q: GenericQueueManager + Sized + 'static>(
+    queue_manager: &Q,
+    ctx: &ContextFinished,
+    message: &MessageBody,
+) -> anyhow::Result<()> {
+    let Some((report, body)) = failure_report(queue_manager.get_config(), ctx, message) else {
+        return Ok(());
+    };
+
+    tracing::info!(
+        queue_id = report.mail_from.queue_id(),
+        "Queuing the delivery status notification of the failed recipients."
+    );
+    queue_manager
+        .write_both(&QueueID::Deferred, &report, &body)
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vsmtp_common::{addr, OriginalRecipient, ReplyCode, Target};
+    use vsmtp_test::config::{local_ctx, local_msg, local_test};
+
+    fn failed_ctx() -> ContextFinished {
+        let mut ctx = local_ctx();
+        ctx.mail_from.envelop_id = Some("QQ+314159=".to_owned());
+        ctx.rcpt_to.original_recipients.insert(
+            addr!("recipient@testserver.com"),
+            OriginalRecipient {
+                addr_type: "rfc822".to_owned(),
+                mailbox: addr!("john+doe@example.com"),
+            },
+        );
+        ctx.rcpt_to.delivery.insert(
+            WrapperSerde::Raw(r#"{"type":"deliver"}"#.to_owned()),
+            vec![
+                (
+                    addr!("recipient@testserver.com"),
+                    Status::failed(Variant::Delivery(vec![(
+                        Target::Ip("127.0.0.1".parse().unwrap()),
+                        Delivery::Permanent {
+                            reply: ReplyCode::Code { code: 550 },
+                            with_source: Some("5.1.1 No such user".to_owned()),
+                        },
+                    )])),
+                ),
+                (addr!("other@testserver.com"), Status::sent()),
+            ],
+        );
+        ctx
+    }
+
+    #[test]
+    fn report() {
+        let ctx = failed_ctx();
+        let (report, body) = failure_report(&local_test(), &ctx, &local_msg()).unwrap();
+
+        assert_eq!(report.mail_from.reverse_path, None);
+        assert_ne!(report.mail_from.message_uuid, ctx.mail_from.message_uuid);
+        assert_eq!(
+            report.rcpt_to.forward_paths,
+            vec![addr!("client@testserver.com")]
+        );
+        assert!(report.rcpt_to.original_recipients.is_empty());
+
+        let content = body.inner().to_string();
+        for field in [
+            "Reporting-MTA: dns; testserver.com\r\n",
+            "Original-Envelope-Id: QQ+2B314159+3D\r\n",
+            "Final-Recipient: rfc822; recipient@testserver.com\r\n",
+            "Original-Recipient: rfc822;john+2Bdoe@example.com\r\n",
+            "Action: failed\r\n",
+            "Status: 5.1.1\r\n",
+            "Diagnostic-Code: smtp; 550 5.1.1 No such user\r\n",
+            "Subject: Happy new year\r\n",
+        ] {
+            assert!(content.contains(field), "{field} not in {content}");
+        }
+        assert!(!content.contains("other@testserver.com"));
+        assert!(content.contains("Content-Type: message/delivery-status\r\n"));
+        assert!(content.contains("Content-Type: text/rfc822-headers\r\n"));
+        assert!(!report.mail_from.utf8);
+        assert_eq!(
+            body.get_header("To").unwrap().trim(),
+            "<client@testserver.com>"
+        );
+    }
+
+    #[test]
+    fn report_disclosed_name() {
+        let mut config = local_test();
+        config.server.disclosure = Some(vsmtp_config::field::FieldServerDisclosure {
+            name: Some("mx.example.com".parse().unwrap()),
+            software: false,
+        });
+        let (_, body) = failure_report(&config, &failed_ctx(), &local_msg()).unwrap();
+
+        let content = body.inner().to_string();
+        for field in [
+            "Reporting-MTA: dns; mx.example.com\r\n",
+            "This is the mail system at host mx.example.com.",
+        ] {
+            assert!(content.contains(field), "{field} not in {content}");
+        }
+        assert!(body
+            .get_header("Message-ID")
+            .unwrap()
+            .trim()
+            .ends_with("@mx.example.com>"));
+        assert!(body
+            .get_header("Content-Type")
+            .unwrap()
+            .contains("/mx.example.com\""));
+    }
+
+    #[test]
+    fn no_report() {
+        // nothing failed
+        assert!(failure_report(&local_test(), &local_ctx(), &local_msg()).is_none());
+
+        // never bounce a bounce
+        let mut ctx = failed_ctx();
+        ctx.mail_from.reverse_path = None;
+        assert!(failure_report(&local_test(), &ctx, &local_msg()).is_none());
+    }
+
+    fn internationalized(utf8: bool) -> (ContextFinished, MessageBody) {
+        let mut ctx = failed_ctx();
+        ctx.mail_from.utf8 = utf8;
+        ctx.rcpt_to.delivery.values_mut().next().unwrap().push((
+            addr!("用户@example.com"),
+            Status::failed(Variant::Delivery(vec![(
+                Target::Ip("127.0.0.1".parse().unwrap()),
+                Delivery::Permanent {
+                    reply: ReplyCode::Code { code: 550 },
+                    with_source: Some("5.1.1 No such user".to_owned()),
+                },
+            )])),
+        ));
+        let message = MessageBody::new(
+            vec![
+                "From: NoBody <nobody@domain.tld>\r\n".to_owned(),
+                "To: 用户@example.com\r\n".to_owned(),
+                "Subject: Bonne année à tous\r\n".to_owned(),
+            ],
+            "Soyez heureux !\r\n".to_owned(),
+        );
+        (ctx, message)
+    }
+
+    #[test]
+    fn internationalized_report() {
+        let (ctx, message) = internationalized(true);
+        let (report, body) = failure_report(&local_test(), &ctx, &message).unwrap();
+        assert!(report.mail_from.utf8);
+
+        let content = body.inner().to_string();
+        for field in [
+            "Content-Type: message/global-delivery-status\r\n",
+            "Content-Type: message/global-headers\r\n",
+            "<用户@example.com>: ",
+            "Final-Recipient: utf-8; 用户@example.com\r\n",
+            "Final-Recipient: rfc822; recipient@testserver.com\r\n",
+            "Subject: Bonne année à tous\r\n",
+        ] {
+            assert!(content.contains(field), "{field} not in {content}");
+        }
+    }
+
+    #[test]
+    fn downgraded_report() {
+        let (ctx, message) = internationalized(false);
+        let (report, body) = failure_report(&local_test(), &ctx, &message).unwrap();
+        assert!(!report.mail_from.utf8);
+
+        let content = body.inner().to_string();
+        assert!(content.is_ascii(), "{content}");
+        for field in [
+            "Content-Type: message/delivery-status\r\n",
+            "Content-Type: text/rfc822-headers\r\n",
+            "<\\x{7528}\\x{6237}@example.com>: ",
+            "Final-Recipient: utf-8; \\x{7528}\\x{6237}@example.com\r\n",
+            "To: =?utf-8?Q?=E7=94=A8=E6=88=B7=40example=2Ecom?= :;\r\n",
+            "Subject: Bonne =?utf-8?Q?ann=C3=A9e_=C3=A0?= tous\r\n",
+        ] {
+            assert!(content.contains(field), "{field} not in {content}");
+        }
+
+        // the report is internationalized if the sender address is.
+        let mut ctx = ctx;
+        ctx.mail_from.reverse_path = Some(addr!("expéditeur@example.com"));
+        assert!(
+            failure_report(&local_test(), &ctx, &message)
+                .unwrap()
+                .0
+                .mail_from
+                .utf8
+        );
+    }
+
+    #[test]
+    fn downgraded_header() {
+        assert_eq!(downgrade_header("Subject: hello\r\n"), "Subject: hello\r\n");
+        assert_eq!(
+            downgrade_header("Subject: très\r\n long\r\n"),
+            "Subject: =?utf-8?Q?tr=C3=A8s?= long\r\n"
+        );
+
+        let long = downgrade_header(&format!("Subject: {}\r\n", "é".repeat(20)));
+        let lines = long.split_terminator("\r\n").collect::<Vec<_>>();
+        assert_eq!(lines.len(), 3, "{long}");
+        assert!(
+            lines[1..].iter().all(|line| line.starts_with(' ')),
+            "{long}"
+        );
+        assert!(lines.iter().all(|line| line.len() <= 78), "{long}");
+    }
+
+    #[test]
+    fn downgraded_addresses() {
+        assert_eq!(
+            downgrade_header("From: \"Jöhn, Doe\" <john@example.com>\r\n"),
+            "From: =?utf-8?Q?J=C3=B6hn=2C_Doe?= <john@example.com>\r\n"
+        );
+        assert_eq!(
+            downgrade_header("To: Jöhn <jöhn@example.com>, jane@example.com\r\n"),
+            "To: =?utf-8?Q?J=C3=B6hn_=3Cj=C3=B6hn=40example=2Ecom=3E?= :;, jane@example.com\r\n"
+        );
+        assert_eq!(
+            downgrade_header("Cc: Jöhn <john@example.com>, Jane Doe <jane.doe@example.community>\r\n"),
+            "Cc: =?utf-8?Q?J=C3=B6hn?= <john@example.com>, Jane Doe\r\n <jane.doe@example.community>\r\n"
+        );
+    }
+}
@@ -0,0 +1,537 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2022 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use crate::{
+    scheduler::Emitter, DeliveryTimings, QueueMonitor, SessionStatistics, SourceIpReputation,
+    TlsStatistics,
+};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use vsmtp_rule_engine::{LookupCache, RuleStatistics};
+
+/// Time given to a probe to send its request line, the connection is closed afterward.
+const PROBE_READ_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// State of the server reported by the health-check listener.
+///
+/// Shared between the runtimes, each of them flagging its readiness.
+#[derive(Debug)]
+pub struct Health {
+    spool_dir: std::path::PathBuf,
+    spool_free_space_min: u64,
+    probes: AtomicU64,
+    listening: AtomicBool,
+    rule_engine_ready: AtomicBool,
+    shutting_down: AtomicBool,
+    maintenance: AtomicBool,
+    tls_statistics: Option<std::sync::Arc<TlsStatistics>>,
+    session_statistics: Option<std::sync::Arc<SessionStatistics>>,
+    delivery_timings: Option<std::sync::Arc<DeliveryTimings>>,
+    rule_statistics: Option<std::sync::Arc<RuleStatistics>>,
+    lookup_cache: Option<std::sync::Arc<LookupCache>>,
+    source_ip_reputation: Option<std::sync::Arc<SourceIpReputation>>,
+    scheduler: Option<std::sync::Arc<Emitter>>,
+    queue_monitor: Option<std::sync::Arc<QueueMonitor>>,
+}
+
+impl Health {
+    /// Create a new state, not ready until all the components are flagged.
+    #[must_use]
+    pub fn new(spool_dir: impl Into<std::path::PathBuf>) -> Self {
+        Self {
+            spool_dir: spool_dir.into(),
+            spool_free_space_min: 0,
+            probes: AtomicU64::new(0),
+            listening: AtomicBool::new(false),
+            rule_engine_ready: AtomicBool::new(false),
+            shutting_down: AtomicBool::new(false),
+            maintenance: AtomicBool::new(false),
+            tls_statistics: None,
+            session_statistics: None,
+            delivery_timings: None,
+            rule_statistics: None,
+            lookup_cache: None,
+            source_ip_reputation: None,
+            scheduler: None,
+            queue_monitor: None,
+        }
+    }
+
+    /// Expose the statistics of the use of TLS on `GET /metrics`.
+    #[must_use]
+    pub fn with_tls_statistics(mut self, tls_statistics: std::sync::Arc<TlsStatistics>) -> Self {
+        self.tls_statistics = Some(tls_statistics);
+        self
+    }
+
+    /// Expose the categories of the SMTP sessions closed on `GET /metrics`.
+    #[must_use]
+    pub fn with_session_statistics(
+        mut self,
+        session_statistics: std::sync::Arc<SessionStatistics>,
+    ) -> Self {
+        self.session_statistics = Some(session_statistics);
+        self
+    }
+
+    /// Expose the time spent by the messages from their reception to their final
+    /// disposition on `GET /metrics`.
+    #[must_use]
+    pub fn with_delivery_timings(
+        mut self,
+        delivery_timings: std::sync::Arc<DeliveryTimings>,
+    ) -> Self {
+        self.delivery_timings = Some(delivery_timings);
+        self
+    }
+
+    /// Expose the executions of the rules on `GET /metrics`.
+    #[must_use]
+    pub fn with_rule_statistics(mut self, rule_statistics: std::sync::Arc<RuleStatistics>) -> Self {
+        self.rule_statistics = Some(rule_statistics);
+        self
+    }
+
+    /// Expose the hits and misses of the cache of the lookups of the rules on `GET /metrics`.
+    #[must_use]
+    pub fn with_lookup_cache(mut self, lookup_cache: std::sync::Arc<LookupCache>) -> Self {
+        self.lookup_cache = Some(lookup_cache);
+        self
+    }
+
+    /// Expose the health of the outgoing addresses on `GET /metrics`.
+    #[must_use]
+    pub fn with_source_ip_reputation(
+        mut self,
+        source_ip_reputation: std::sync::Arc<SourceIpReputation>,
+    ) -> Self {
+        self.source_ip_reputation = Some(source_ip_reputation);
+        self
+    }
+
+    /// Require `bytes` of free space in the spool for the server to be ready.
+    #[must_use]
+    pub const fn with_spool_free_space_min(mut self, bytes: u64) -> Self {
+        self.spool_free_space_min = bytes;
+        self
+    }
+
+    /// Expose the occupancy of the channels between the receiver, working and delivery on `GET /metrics`.
+    #[must_use]
+    pub fn with_scheduler(mut self, scheduler: std::sync::Arc<Emitter>) -> Self {
+        self.scheduler = Some(scheduler);
+        self
+    }
+
+    /// Expose the age of the messages waiting in the queues on `GET /metrics`.
+    #[must_use]
+    pub fn with_queue_monitor(mut self, queue_monitor: std::sync::Arc<QueueMonitor>) -> Self {
+        self.queue_monitor = Some(queue_monitor);
+        self
+    }
+
+    fn metrics(&self) -> Option<String> {
+        if self.rule_statistics.is_none()
+            && self.lookup_cache.is_none()
+            && self.tls_statistics.is_none()
+            && self.session_statistics.is_none()
+            && self.delivery_timings.is_none()
+            && self.source_ip_reputation.is_none()
+            && self.scheduler.is_none()
+            && self.queue_monitor.is_none()
+        {
+            return None;
+        }
+
+        let mut metrics = String::new();
+        if let Some(rule_statistics) = &self.rule_statistics {
+            metrics.push_str(&rule_statistics.metrics());
+        }
+        if let Some(lookup_cache) = &self.lookup_cache {
+            metrics.push_str(&lookup_cache.metrics());
+        }
+        if let Some(tls_statistics) = &self.tls_statistics {
+            metrics.push_str(&tls_statistics.metrics());
+        }
+        if let Some(session_statistics) = &self.session_statistics {
+            metrics.push_str(&session_statistics.metrics());
+        }
+        if let Some(source_ip_reputation) = &self.source_ip_reputation {
+            metrics.push_str(&source_ip_reputation.metrics());
+        }
+        if let Some(scheduler) = &self.scheduler {
+            metrics.push_str(&scheduler.metrics());
+        }
+        if let Some(queue_monitor) = &self.queue_monitor {
+            metrics.push_str(&queue_monitor.metrics());
+        }
+        if let Some(delivery_timings) = &self.delivery_timings {
+            metrics.push_str(&delivery_timings.metrics());
+        }
+        metrics.push_str(&format!(
+            "# HELP vsmtp_smtp_probes_total Connections of the load balancers to the SMTP listeners.\n# TYPE vsmtp_smtp_probes_total counter\nvsmtp_smtp_probes_total {}\n",
+            self.probe_count()
+        ));
+        Some(metrics)
+    }
+
+    /// The SMTP listeners are bound and the receiver is accepting clients.
+    pub fn set_listening(&self) {
+        self.listening.store(true, Ordering::SeqCst);
+    }
+
+    /// The rules have been compiled successfully.
+    pub fn set_rule_engine_ready(&self) {
+        self.rule_engine_ready.store(true, Ordering::SeqCst);
+    }
+
+    /// The server is stopping, the orchestrator should not route new traffic.
+    pub fn set_shutting_down(&self) {
+        self.shutting_down.store(true, Ordering::SeqCst);
+    }
+
+    /// Enter or leave the maintenance mode, new SMTP clients are refused during it.
+    pub fn set_maintenance(&self, enabled: bool) {
+        self.maintenance.store(enabled, Ordering::SeqCst);
+    }
+
+    /// Is the server in maintenance mode ?
+    #[must_use]
+    pub fn is_in_maintenance(&self) -> bool {
+        self.maintenance.load(Ordering::SeqCst)
+    }
+
+    /// Count a connection of a load balancer to the SMTP listeners.
+    pub fn count_probe(&self) {
+        self.probes.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Number of connections of the load balancers to the SMTP listeners.
+    #[must_use]
+    pub fn probe_count(&self) -> u64 {
+        self.probes.load(Ordering::Relaxed)
+    }
+
+    fn is_spool_accessible(&self) -> bool {
+        std::fs::metadata(&self.spool_dir)
+            .map(|metadata| metadata.is_dir() && !metadata.permissions().readonly())
+            .unwrap_or(false)
+    }
+
+    fn has_spool_free_space(&self) -> bool {
+        self.spool_free_space_min == 0
+            || vsmtp_common::libc_abstraction::free_space(&self.spool_dir)
+                .map_or(false, |free| free >= self.spool_free_space_min)
+    }
+
+    fn is_scheduler_saturated(&self) -> bool {
+        self.scheduler
+            .as_ref()
+            .map_or(false, |scheduler| scheduler.is_saturated())
+    }
+
+    /// Is the server ready to receive traffic ?
+    #[must_use]
+    pub fn is_ready(&self) -> bool {
+        self.listening.load(Ordering::SeqCst)
+            && self.rule_engine_ready.load(Ordering::SeqCst)
+            && !self.shutting_down.load(Ordering::SeqCst)
+            && !self.is_in_maintenance()
+            && self.is_spool_accessible()
+            && self.has_spool_free_space()
+            && !self.is_scheduler_saturated()
+    }
+
+    /// Produce the HTTP response for the first line of a request.
+    ///
+    /// The response to a `HEAD` request has the headers of the `GET`, without the body.
+    #[must_use]
+    pub fn response(&self, request_line: &str) -> std::borrow::Cow<'static, str> {
+        let mut split = request_line.split_ascii_whitespace();
+
+        let (method, path) = (split.next(), split.next());
+        // the metrics are rendered only when requested.
+        let metrics = (path == Some("/metrics")).then(|| self.metrics()).flatten();
+
+        let response: std::borrow::Cow<'static, str> = match (method, path, metrics) {
+            (Some("GET" | "HEAD"), Some("/metrics"), Some(metrics)) => {
+                format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{metrics}",
+                    metrics.len()
+                )
+                .into()
+            }
+            (Some("GET" | "HEAD"), Some("/healthz"), _) => {
+                "HTTP/1.1 200 OK\r\nContent-Length: 6\r\nConnection: close\r\n\r\nalive\n".into()
+            }
+            (Some("GET" | "HEAD"), Some("/readyz"), _) if self.is_ready() => {
+                "HTTP/1.1 200 OK\r\nContent-Length: 6\r\nConnection: close\r\n\r\nready\n".into()
+            }
+            (Some("GET" | "HEAD"), Some("/readyz"), _) => {
+                "HTTP/1.1 503 Service Unavailable\r\nContent-Length: 10\r\nConnection: close\r\n\r\nnot ready\n".into()
+            }
+            (Some("GET" | "HEAD"), Some(_), _) => {
+                "HTTP/1.1 404 Not Found\r\nContent-Length: 10\r\nConnection: close\r\n\r\nnot found\n".into()
+            }
+            _ => "HTTP/1.1 400 Bad Request\r\nContent-Length: 12\r\nConnection: close\r\n\r\nbad request\n".into(),
+        };
+
+        match (method, response.find("\r\n\r\n")) {
+            (Some("HEAD"), Some(end)) => response[..end + 4].to_owned().into(),
+            _ => response,
+        }
+    }
+
+    async fn handle_probe(&self, mut stream: tokio::net::TcpStream) -> std::io::Result<()> {
+        let mut buffer = vec![0; 1024];
+        let mut len = 0;
+
+        // only the request line is relevant, the headers are ignored.
+        let read_request_line = async {
+            while !buffer[..len].windows(2).any(|w| w == b"\r\n") && len < buffer.len() {
+                match tokio::io::AsyncReadExt::read(&mut stream, &mut buffer[len..]).await? {
+                    0 => break,
+                    read => len += read,
+                }
+            }
+            std::io::Result::Ok(())
+        };
+        tokio::time::timeout(PROBE_READ_TIMEOUT, read_request_line)
+            .await
+            .map_err(|_| std::io::Error::from(std::io::ErrorKind::TimedOut))??;
+
+        let request = String::from_utf8_lossy(&buffer[..len]);
+        let request_line = request.lines().next().unwrap_or_default();
+
+        tokio::io::AsyncWriteExt::write_all(&mut stream, self.response(request_line).as_bytes())
+            .await?;
+        tokio::io::AsyncWriteExt::shutdown(&mut stream).await
+    }
+
+    /// Serve the probes on the listener, until the runtime is stopped.
+    ///
+    /// # Errors
+    ///
+    /// * failed to convert the socket to `[tokio::net::TcpListener]`
+    pub async fn serve(
+        self: std::sync::Arc<Self>,
+        listener: std::net::TcpListener,
+    ) -> std::io::Result<()> {
+        let listener = tokio::net::TcpListener::from_std(listener)?;
+
+        tracing::info!(addr = ?listener.local_addr(), "Listening for health probes.");
+
+        loop {
+            let (stream, _) = match listener.accept().await {
+                Ok(client) => client,
+                Err(error) => {
+                    tracing::warn!(%error, "Health probe accept failure.");
+                    continue;
+                }
+            };
+
+            let health = self.clone();
+            tokio::spawn(async move {
+                if let Err(error) = health.handle_probe(stream).await {
+                    tracing::debug!(%error, "Health probe failure.");
+                }
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn readiness() {
+        let health = Health::new(std::env::temp_dir());
+
+        assert!(!health.is_ready());
+        assert!(health.response("GET /readyz HTTP/1.1").starts_with("HTTP/1.1 503"));
+        assert!(health.response("GET /healthz HTTP/1.1").starts_with("HTTP/1.1 200"));
+
+        health.set_rule_engine_ready();
+        health.set_listening();
+        assert!(health.is_ready());
+        assert!(health.response("GET /readyz HTTP/1.1").starts_with("HTTP/1.1 200"));
+
+        health.set_maintenance(true);
+        assert!(!health.is_ready());
+        health.set_maintenance(false);
+        assert!(health.is_ready());
+
+        health.set_shutting_down();
+        assert!(!health.is_ready());
+        assert!(health.response("GET /readyz HTTP/1.1").starts_with("HTTP/1.1 503"));
+        assert!(health.response("GET /healthz HTTP/1.1").starts_with("HTTP/1.1 200"));
+    }
+
+    #[test]
+    fn spool_not_accessible() {
+        let health = Health::new("/this/path/does/not/exist");
+        health.set_rule_engine_ready();
+        health.set_listening();
+
+        assert!(!health.is_ready());
+    }
+
+    #[test]
+    fn spool_free_space() {
+        for (free_space_min, ready) in [(0, true), (1, true), (u64::MAX, false)] {
+            let health =
+                Health::new(std::env::temp_dir()).with_spool_free_space_min(free_space_min);
+            health.set_rule_engine_ready();
+            health.set_listening();
+            assert_eq!(health.is_ready(), ready);
+        }
+    }
+
+    #[tokio::test]
+    async fn scheduler_saturated() {
+        let (emitter, _working, _delivery) = crate::scheduler::init(1, 2);
+        let health = Health::new(std::env::temp_dir()).with_scheduler(emitter.clone());
+        health.set_rule_engine_ready();
+        health.set_listening();
+        assert!(health.is_ready());
+
+        emitter
+            .send_to_delivery(crate::ProcessMessage::new(uuid::Uuid::new_v4()))
+            .await
+            .unwrap();
+        assert!(health.is_ready());
+
+        emitter
+            .send_to_working(crate::ProcessMessage::new(uuid::Uuid::new_v4()))
+            .await
+            .unwrap();
+        assert!(!health.is_ready());
+        assert!(health
+            .response("GET /metrics HTTP/1.1")
+            .contains("vsmtp_channel_queued{stage=\"working\"} 1\n"));
+    }
+
+    #[test]
+    fn probes() {
+        let health = Health::new(std::env::temp_dir())
+            .with_rule_statistics(std::sync::Arc::new(RuleStatistics::default()));
+        health.count_probe();
+        health.count_probe();
+
+        assert_eq!(health.probe_count(), 2);
+        assert!(health
+            .response("GET /metrics HTTP/1.1")
+            .contains("\nvsmtp_smtp_probes_total 2\n"));
+    }
+
+    #[test]
+    fn bad_requests() {
+        let health = Health::new(std::env::temp_dir());
+
+        assert!(health.response("GET /foo HTTP/1.1").starts_with("HTTP/1.1 404"));
+        assert!(health.response("EHLO foo").starts_with("HTTP/1.1 400"));
+        assert!(health.response("").starts_with("HTTP/1.1 400"));
+    }
+
+    #[test]
+    fn head_without_body() {
+        let health = Health::new(std::env::temp_dir());
+
+        assert_eq!(
+            health.response("HEAD /healthz HTTP/1.1"),
+            "HTTP/1.1 200 OK\r\nContent-Length: 6\r\nConnection: close\r\n\r\n"
+        );
+        assert_eq!(
+            health.response("HEAD /readyz HTTP/1.1"),
+            "HTTP/1.1 503 Service Unavailable\r\nContent-Length: 10\r\nConnection: close\r\n\r\n"
+        );
+        assert!(health.response("GET /healthz HTTP/1.1").ends_with("\r\n\r\nalive\n"));
+    }
+
+    #[tokio::test]
+    async fn silent_probe_timed_out() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server =
+            tokio::spawn(std::sync::Arc::new(Health::new(std::env::temp_dir())).serve(listener));
+
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let mut response = vec![];
+        let read = tokio::time::timeout(
+            PROBE_READ_TIMEOUT * 2,
+            tokio::io::AsyncReadExt::read_to_end(&mut stream, &mut response),
+        )
+        .await;
+
+        // the connection is closed without a response.
+        assert!(matches!(read, Ok(Ok(0))));
+        server.abort();
+    }
+
+    #[test]
+    fn metrics() {
+        let health = Health::new(std::env::temp_dir());
+        assert!(health.response("GET /metrics HTTP/1.1").starts_with("HTTP/1.1 404"));
+
+        let health = health.with_tls_statistics(std::sync::Arc::new(TlsStatistics::new(
+            &vsmtp_config::field::FieldServerTlsStatistics::default(),
+            std::env::temp_dir().join("health-metrics-not-persisted.json"),
+        )));
+        let response = health.response("GET /metrics HTTP/1.1");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("# TYPE vsmtp_inbound_messages_by_sender_domain gauge\n"));
+        assert!(!response.contains("vsmtp_rule_executions_total"));
+
+        let health = health.with_rule_statistics(std::sync::Arc::new(RuleStatistics::default()));
+        let response = health.response("GET /metrics HTTP/1.1");
+        assert!(response.contains("# TYPE vsmtp_rule_executions_total counter\n"));
+        assert!(response.contains("# TYPE vsmtp_inbound_messages_by_sender_domain gauge\n"));
+
+        let health = health.with_lookup_cache(std::sync::Arc::new(LookupCache::new(
+            &vsmtp_config::field::FieldAppVSLLookupCache::default(),
+        )));
+        let response = health.response("GET /metrics HTTP/1.1");
+        assert!(response.contains("\nvsmtp_lookup_cache_hits_total 0\n"));
+        assert!(response.contains("\nvsmtp_lookup_cache_entries 0\n"));
+
+        let health =
+            health.with_session_statistics(std::sync::Arc::new(SessionStatistics::default()));
+        let response = health.response("GET /metrics HTTP/1.1");
+        assert!(response.contains("\nvsmtp_smtp_sessions_total{category=\"scanner\"} 0\n"));
+    }
+
+    #[tokio::test]
+    async fn serve() {
+        let listener = crate::socket_bind_anyhow("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let health = std::sync::Arc::new(Health::new(std::env::temp_dir()));
+        tokio::spawn(health.serve(listener));
+
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        tokio::io::AsyncWriteExt::write_all(&mut stream, b"GET /healthz HTTP/1.1\r\n\r\n")
+            .await
+            .unwrap();
+
+        let mut response = String::new();
+        tokio::io::AsyncReadExt::read_to_string(&mut stream, &mut response)
+            .await
+            .unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("alive\n"));
+    }
+}
@@ -29,9 +29,25 @@
 //
 #![allow(clippy::significant_drop_tightening)]
 
+mod admin;
 mod channel_message;
+mod claim;
+mod connection_limits;
+mod delivery_timings;
+mod dkim_preservation;
+mod dsn;
+mod health;
+mod missing_headers;
+mod queue_monitor;
 mod runtime;
+mod sender_policy;
 mod server;
+mod session_stats;
+mod source_ip_reputation;
+mod strip_received;
+mod submission_quotas;
+mod tls_stats;
+mod watchdog;
 mod receiver {
     pub mod handler;
     mod post_transaction;
@@ -45,18 +61,28 @@ pub mod scheduler;
 /// This module execute logics on message after taking their responsibility, and before sending them.
 pub mod working;
 
+pub use admin::{socket_bind_unix, Admin, AdminCommand};
 pub use channel_message::ProcessMessage;
+pub use connection_limits::{ConnectionLimits, ConnectionSlot};
+pub use delivery_timings::DeliveryTimings;
+pub use health::Health;
+pub use queue_monitor::{QueueAgeEvent, QueueAgeLevel, QueueMonitor};
 pub use receiver::handler::Handler;
 pub use receiver::pre_transaction::ValidationVSL;
 pub use runtime::start_runtime;
 pub use server::{socket_bind_anyhow, Server};
+pub use session_stats::SessionStatistics;
+pub use source_ip_reputation::{BlocklistResolver, SourceIpEvent, SourceIpReputation};
+pub use submission_quotas::{SubmissionQuotas, Verdict};
+pub use tls_stats::{Downgrade, TlsStatistics};
 
 use anyhow::Context;
 use vsmtp_common::status::SmtpConnection;
 use vsmtp_common::{Address, ContextFinished};
 use vsmtp_mail_parser::MessageBody;
 
-/// delegate a message to another service.
+/// delegate a message to another service, reduced to the payload of the directive.
+#[tracing::instrument(name = "delegation", skip_all, err, fields(uuid = %context.mail_from.message_uuid))]
 pub(crate) fn delegate(
     delegator: &SmtpConnection,
     context: &ContextFinished,
@@ -83,6 +109,13 @@ pub(crate) fn delegate(
         .0
         .lock()
         .unwrap()
-        .send_raw(&envelope, message.inner().to_string().as_bytes())
+        .send_raw(
+            &envelope,
+            vsmtp_rule_engine::DelegationPayload::of_message(message)
+                .reduce(message)
+                .inner()
+                .to_string()
+                .as_bytes(),
+        )
         .context("failed to delegate email")
 }
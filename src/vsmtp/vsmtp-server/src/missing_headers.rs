@@ -0,0 +1,384 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2022 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use time::format_description::well_known::Rfc2822;
+use vsmtp_common::{ContextFinished, Reply, TransactionType};
+use vsmtp_config::{
+    field::{FieldServerMissingHeaders, MissingHeadersPolicy},
+    Config,
+};
+use vsmtp_mail_parser::MessageBody;
+
+/// Get the policy to apply, following the source of the message.
+fn get_policy(policies: &FieldServerMissingHeaders, ctx: &ContextFinished) -> MissingHeadersPolicy {
+    if ctx
+        .connect
+        .auth
+        .as_ref()
+        .map_or(false, |auth| auth.authenticated)
+    {
+        policies.submission
+    } else if matches!(ctx.rcpt_to.transaction_type, TransactionType::Internal) {
+        policies.internal
+    } else {
+        policies.relay
+    }
+}
+
+/// Can the value of a `Date` header be parsed? (a trailing comment, as in `-0700 (PDT)`, is ignored)
+fn is_valid_date(value: &str) -> bool {
+    let value = value.trim();
+    let value = match (value.ends_with(')'), value.rfind('(')) {
+        (true, Some(start)) => value[..start].trim_end(),
+        _ => value,
+    };
+
+    if value.contains(',') {
+        return time::OffsetDateTime::parse(value, &Rfc2822).is_ok();
+    }
+
+    // NOTE: the day of the week is optional in RFC 5322, but required by the parser.
+    ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"]
+        .into_iter()
+        .any(|day| time::OffsetDateTime::parse(&format!("{day}, {value}"), &Rfc2822).is_ok())
+}
+
+/// Is the `Date` header missing (or not parsed, if it is to be fixed), is the `Message-ID` missing?
+fn missing_headers(policies: &FieldServerMissingHeaders, message: &MessageBody) -> (bool, bool) {
+    let date = message.get_header("Date");
+    let broken_date = date.as_deref().map_or(false, |date| !is_valid_date(date));
+    if broken_date {
+        tracing::warn!(date = ?date, fix = policies.fix_date, "Date header cannot be parsed.");
+    }
+
+    (
+        date.is_none() || (broken_date && policies.fix_date),
+        message.get_header("Message-ID").is_none(),
+    )
+}
+
+/// Require the `Date` and `Message-ID` headers of a message at the end of `DATA`, if the
+/// policy of its source rejects the messages without them.
+///
+/// Returns the reply refusing the message, which is not queued.
+pub(crate) fn check_required_headers(
+    config: &Config,
+    ctx: &ContextFinished,
+    message: &MessageBody,
+) -> Option<Reply> {
+    let policies = config.server.missing_headers.as_ref()?;
+    if get_policy(policies, ctx) != MissingHeadersPolicy::Reject {
+        return None;
+    }
+
+    let (missing_date, missing_message_id) = missing_headers(policies, message);
+    if !missing_date && !missing_message_id {
+        return None;
+    }
+
+    tracing::warn!(
+        missing_date,
+        missing_message_id,
+        "Message rejected without Date or Message-ID header."
+    );
+    Some(
+        "550 5.6.0 Message rejected: the Date and Message-ID headers are required\r\n"
+            .parse::<Reply>()
+            .expect("valid smtp reply"),
+    )
+}
+
+/// Add the `Date` and `Message-ID` headers missing from a message, if the policy of
+/// its source says so. The `Message-ID` added is recorded in the context.
+///
+/// The messages rejected by the policy have been refused by [`check_required_headers`].
+pub(crate) fn add_missing_headers(
+    config: &Config,
+    ctx: &mut ContextFinished,
+    message: &mut MessageBody,
+) {
+    let Some(policies) = config.server.missing_headers.as_ref() else {
+        return;
+    };
+    if get_policy(policies, ctx) != MissingHeadersPolicy::Add {
+        return;
+    }
+
+    let (missing_date, missing_message_id) = missing_headers(policies, message);
+    if !missing_date && !missing_message_id {
+        return;
+    }
+
+    tracing::debug!(
+        missing_date,
+        missing_message_id,
+        "Message without Date or Message-ID header."
+    );
+
+    // NOTE: a header covered by the DKIM signature of the message cannot be added.
+    let signed = config
+        .server
+        .dkim_preservation
+        .as_ref()
+        .and_then(|_| crate::dkim_preservation::SignedFields::new(message));
+    let covered = |name: &str| signed.as_ref().map_or(false, |signed| signed.covers(name));
+    let (missing_date, missing_message_id) = (
+        missing_date && !covered("Date"),
+        missing_message_id && !covered("Message-ID"),
+    );
+
+    if missing_date {
+        match ctx.mail_from.mail_timestamp.format(&Rfc2822) {
+            Ok(value) => message.set_header("Date", &value),
+            Err(error) => tracing::warn!(%error, "Failed to format the Date header."),
+        }
+    }
+    if missing_message_id {
+        let message_id = format!(
+            "<{}@{}>",
+            ctx.mail_from.message_uuid,
+            config
+                .server
+                .disclosed_name()
+                .unwrap_or(&ctx.connect.server_name)
+        );
+        tracing::info!(
+            queue_id = ctx.mail_from.queue_id(),
+            %message_id,
+            "Message-ID header added."
+        );
+        message.set_header("Message-ID", &message_id);
+        ctx.finished.added_message_id = Some(message_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vsmtp_common::Domain;
+    use vsmtp_test::config::{
+        local_ctx, local_ctx_authenticated, local_msg_with_headers, local_test,
+    };
+
+    fn config(submission: MissingHeadersPolicy, fix_date: bool) -> Config {
+        let mut config = local_test();
+        config.server.missing_headers = Some(FieldServerMissingHeaders {
+            submission,
+            relay: MissingHeadersPolicy::Ignore,
+            internal: MissingHeadersPolicy::Add,
+            fix_date,
+        });
+        config
+    }
+
+    fn submission_ctx() -> ContextFinished {
+        let mut ctx = local_ctx_authenticated("john");
+        ctx.rcpt_to.transaction_type = TransactionType::Outgoing {
+            domain: "testserver.com".parse::<Domain>().unwrap(),
+        };
+        ctx
+    }
+
+    #[test]
+    fn add() {
+        let config = config(MissingHeadersPolicy::Add, false);
+        let mut ctx = submission_ctx();
+        let mut message = local_msg_with_headers(&["From: john@testserver.com"]);
+
+        add_missing_headers(&config, &mut ctx, &mut message);
+
+        let message_id = format!("<{}@testserver.com>", ctx.mail_from.message_uuid);
+        assert_eq!(message.get_header("Message-ID").unwrap().trim(), message_id);
+        assert_eq!(ctx.finished.added_message_id, Some(message_id));
+        assert_eq!(
+            message.get_header("Date").unwrap().trim(),
+            ctx.mail_from.mail_timestamp.format(&Rfc2822).unwrap()
+        );
+    }
+
+    #[test]
+    fn add_disclosed_name() {
+        let mut config = config(MissingHeadersPolicy::Add, false);
+        config.server.disclosure = Some(vsmtp_config::field::FieldServerDisclosure {
+            name: Some("mx.example.com".parse().unwrap()),
+            software: false,
+        });
+        let mut ctx = submission_ctx();
+        let mut message = local_msg_with_headers(&["From: john@testserver.com"]);
+
+        add_missing_headers(&config, &mut ctx, &mut message);
+        assert_eq!(
+            message.get_header("Message-ID").unwrap().trim(),
+            format!("<{}@mx.example.com>", ctx.mail_from.message_uuid)
+        );
+    }
+
+    #[test]
+    fn add_only_missing() {
+        let config = config(MissingHeadersPolicy::Add, false);
+        let mut ctx = submission_ctx();
+        let mut message = local_msg_with_headers(&[
+            "From: john@testserver.com",
+            "Date: Tue, 28 Mar 2023 23:51:14 -0700 (PDT)",
+        ]);
+
+        add_missing_headers(&config, &mut ctx, &mut message);
+        assert_eq!(
+            message.get_header("Date").unwrap().trim(),
+            "Tue, 28 Mar 2023 23:51:14 -0700 (PDT)"
+        );
+        assert!(ctx.finished.added_message_id.is_some());
+
+        let mut message = local_msg_with_headers(&["Message-ID: <id@example.com>"]);
+        let mut ctx = submission_ctx();
+        add_missing_headers(&config, &mut ctx, &mut message);
+        assert_eq!(
+            message.get_header("Message-ID").unwrap().trim(),
+            "<id@example.com>"
+        );
+        assert!(message.get_header("Date").is_some());
+        assert_eq!(ctx.finished.added_message_id, None);
+    }
+
+    #[test]
+    fn covered_by_signature() {
+        let mut config = config(MissingHeadersPolicy::Add, false);
+        config.server.dkim_preservation = Some(vsmtp_config::field::FieldServerDkimPreservation {
+            fallback: vsmtp_config::field::DkimPreservationFallback::Skip,
+        });
+        let mut ctx = submission_ctx();
+        let mut message = local_msg_with_headers(&[
+            "DKIM-Signature: v=1; a=rsa-sha256; d=example.com; s=app; h=from:message-id; bh=YWJj; b=YWJj",
+            "From: john@testserver.com",
+        ]);
+
+        add_missing_headers(&config, &mut ctx, &mut message);
+        assert!(message.get_header("Date").is_some());
+        assert!(message.get_header("Message-ID").is_none());
+        assert_eq!(ctx.finished.added_message_id, None);
+    }
+
+    #[test]
+    fn reject() {
+        let config = config(MissingHeadersPolicy::Reject, false);
+        let mut ctx = submission_ctx();
+        let mut message = local_msg_with_headers(&["Date: Tue, 28 Mar 2023 23:51:14 -0700"]);
+
+        assert!(check_required_headers(&config, &ctx, &message).is_some());
+        add_missing_headers(&config, &mut ctx, &mut message);
+        assert!(message.get_header("Message-ID").is_none());
+
+        let complete = local_msg_with_headers(&[
+            "Date: Tue, 28 Mar 2023 23:51:14 -0700",
+            "Message-ID: <id@example.com>",
+        ]);
+        assert_eq!(check_required_headers(&config, &ctx, &complete), None);
+    }
+
+    #[test]
+    fn ignore() {
+        let config = config(MissingHeadersPolicy::Ignore, true);
+        let mut ctx = submission_ctx();
+        let mut message = local_msg_with_headers(&["From: john@testserver.com"]);
+
+        add_missing_headers(&config, &mut ctx, &mut message);
+        assert!(message.get_header("Date").is_none());
+        assert!(message.get_header("Message-ID").is_none());
+        assert_eq!(ctx.finished.added_message_id, None);
+    }
+
+    #[test]
+    fn by_source() {
+        let config = config(MissingHeadersPolicy::Reject, false);
+
+        // internal
+        let mut ctx = local_ctx();
+        let mut internal = local_msg_with_headers(&["From: john@testserver.com"]);
+        assert_eq!(check_required_headers(&config, &ctx, &internal), None);
+        add_missing_headers(&config, &mut ctx, &mut internal);
+        assert!(internal.get_header("Message-ID").is_some());
+
+        // relay
+        let mut ctx = local_ctx();
+        ctx.rcpt_to.transaction_type = TransactionType::Incoming(None);
+        let mut relay = local_msg_with_headers(&["From: john@example.com"]);
+        assert_eq!(check_required_headers(&config, &ctx, &relay), None);
+        add_missing_headers(&config, &mut ctx, &mut relay);
+        assert!(relay.get_header("Message-ID").is_none());
+
+        // not configured
+        let ctx = submission_ctx();
+        assert_eq!(
+            check_required_headers(&local_test(), &ctx, &local_msg_with_headers(&[])),
+            None
+        );
+    }
+
+    #[test]
+    fn broken_date() {
+        for date in [
+            "Tue, 28 Mar 2023 23:51:14 -0700",
+            "28 Mar 2023 23:51:14 +0000",
+            "Tue, 28 Mar 2023 23:51:14 GMT",
+            "Tue, 28 Mar 2023 23:51:14 -0700 (PDT)",
+        ] {
+            assert!(is_valid_date(date), "{date}");
+        }
+        for date in ["", "yesterday", "2023-03-28T23:51:14Z", "Tue, 32 Mar 2023"] {
+            assert!(!is_valid_date(date), "{date}");
+        }
+
+        let headers = ["Date: 2023-03-28T23:51:14Z", "Message-ID: <id@example.com>"];
+
+        let mut ctx = submission_ctx();
+        let mut message = local_msg_with_headers(&headers);
+        add_missing_headers(
+            &config(MissingHeadersPolicy::Add, false),
+            &mut ctx,
+            &mut message,
+        );
+        assert_eq!(
+            message.get_header("Date").unwrap().trim(),
+            "2023-03-28T23:51:14Z"
+        );
+
+        let mut message = local_msg_with_headers(&headers);
+        add_missing_headers(
+            &config(MissingHeadersPolicy::Add, true),
+            &mut ctx,
+            &mut message,
+        );
+        assert_eq!(
+            message.get_header("Date").unwrap().trim(),
+            ctx.mail_from.mail_timestamp.format(&Rfc2822).unwrap()
+        );
+
+        assert_eq!(
+            check_required_headers(
+                &config(MissingHeadersPolicy::Reject, false),
+                &ctx,
+                &local_msg_with_headers(&headers)
+            ),
+            None
+        );
+        assert!(check_required_headers(
+            &config(MissingHeadersPolicy::Reject, true),
+            &ctx,
+            &local_msg_with_headers(&headers)
+        )
+        .is_some());
+    }
+}
@@ -0,0 +1,309 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2023 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+
+use vqueue::{QueueAges, StuckMessages};
+use vsmtp_config::field::FieldQueueMonitor;
+
+/// Severity of the age of the oldest message of a queue.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum QueueAgeLevel {
+    /// Under the thresholds.
+    #[default]
+    Normal,
+    /// Older than the `warning` threshold.
+    Warning,
+    /// Older than the `critical` threshold.
+    Critical,
+}
+
+/// A change of the severity of a queue.
+#[derive(Debug, PartialEq, Eq)]
+pub enum QueueAgeEvent {
+    /// The oldest message of the queue crossed a higher threshold.
+    Raised {
+        /// The queue.
+        queue: String,
+        /// The threshold crossed.
+        level: QueueAgeLevel,
+        /// Age of the oldest message.
+        oldest: std::time::Duration,
+        /// The messages older than the threshold.
+        stuck: StuckMessages,
+    },
+    /// The oldest message of the queue is back under the thresholds.
+    Recovered {
+        /// The queue.
+        queue: String,
+    },
+}
+
+/// Periodic check of the age of the messages waiting in the queues, see [`FieldQueueMonitor`].
+///
+/// The ages are tracked by the queue manager ([`QueueAges`]), the queues are not scanned.
+#[derive(Debug)]
+pub struct QueueMonitor {
+    ages: std::sync::Arc<QueueAges>,
+    thresholds: std::collections::BTreeMap<String, (std::time::Duration, std::time::Duration)>,
+    samples: usize,
+    levels: std::sync::Mutex<std::collections::BTreeMap<String, QueueAgeLevel>>,
+}
+
+impl QueueMonitor {
+    /// Check the `ages` of the messages tracked by a queue manager.
+    #[must_use]
+    pub fn new(ages: std::sync::Arc<QueueAges>, parameters: &FieldQueueMonitor) -> Self {
+        Self {
+            ages,
+            thresholds: parameters
+                .thresholds
+                .iter()
+                .map(|(queue, thresholds)| {
+                    (queue.clone(), (thresholds.warning, thresholds.critical))
+                })
+                .collect(),
+            samples: parameters.samples,
+            levels: std::sync::Mutex::default(),
+        }
+    }
+
+    /// The queues with metrics: the ones tracked, and the ones with thresholds.
+    fn queues(&self) -> std::collections::BTreeSet<String> {
+        self.ages
+            .queues()
+            .into_iter()
+            .chain(self.thresholds.keys().cloned())
+            .collect()
+    }
+
+    fn level(&self, queue: &str, oldest: std::time::Duration) -> QueueAgeLevel {
+        match self.thresholds.get(queue) {
+            Some((_, critical)) if oldest > *critical => QueueAgeLevel::Critical,
+            Some((warning, _)) if oldest > *warning => QueueAgeLevel::Warning,
+            Some(_) | None => QueueAgeLevel::Normal,
+        }
+    }
+
+    /// Compare the oldest message of each queue to its thresholds, logging the queues whose
+    /// severity changed since the last check.
+    ///
+    /// A queue is logged once when it crosses a threshold, and once when it is back under them.
+    #[must_use]
+    pub fn check(&self) -> Vec<QueueAgeEvent> {
+        let mut levels = self
+            .levels
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        let mut events = vec![];
+
+        for (queue, (warning, critical)) in &self.thresholds {
+            let oldest = self.ages.oldest(queue).unwrap_or_default();
+            let level = self.level(queue, oldest);
+            let previous = levels.insert(queue.clone(), level).unwrap_or_default();
+
+            if level > previous {
+                let threshold = if level == QueueAgeLevel::Critical {
+                    *critical
+                } else {
+                    *warning
+                };
+                let stuck = self.ages.older_than(queue, threshold, self.samples);
+                let samples = stuck
+                    .samples
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join(",");
+
+                if level == QueueAgeLevel::Critical {
+                    tracing::error!(%queue, ?oldest, ?threshold, count = stuck.count, %samples, "Messages stuck in the queue.");
+                } else {
+                    tracing::warn!(%queue, ?oldest, ?threshold, count = stuck.count, %samples, "Messages stuck in the queue.");
+                }
+                events.push(QueueAgeEvent::Raised {
+                    queue: queue.clone(),
+                    level,
+                    oldest,
+                    stuck,
+                });
+            } else if level == QueueAgeLevel::Normal && previous != QueueAgeLevel::Normal {
+                tracing::info!(%queue, "Queue back under the thresholds.");
+                events.push(QueueAgeEvent::Recovered {
+                    queue: queue.clone(),
+                });
+            }
+        }
+
+        events
+    }
+
+    /// Check the queues every `period`.
+    pub async fn check_periodically(self: std::sync::Arc<Self>, period: std::time::Duration) {
+        let mut interval = tokio::time::interval(period);
+
+        loop {
+            interval.tick().await;
+            let _events = self.check();
+        }
+    }
+
+    /// Render the ages of the queues in the Prometheus text format.
+    #[must_use]
+    pub fn metrics(&self) -> String {
+        let queues = self.queues();
+
+        let mut output = String::from(
+            "# HELP vsmtp_queue_messages Messages waiting in the queue.\n# TYPE vsmtp_queue_messages gauge\n",
+        );
+        for queue in &queues {
+            output.push_str(&format!(
+                "vsmtp_queue_messages{{queue=\"{queue}\"}} {}\n",
+                self.ages.count(queue)
+            ));
+        }
+
+        output.push_str("# HELP vsmtp_queue_oldest_message_age_seconds Age of the oldest message of the queue, 0 if it is empty.\n# TYPE vsmtp_queue_oldest_message_age_seconds gauge\n");
+        for queue in &queues {
+            output.push_str(&format!(
+                "vsmtp_queue_oldest_message_age_seconds{{queue=\"{queue}\"}} {}\n",
+                self.ages.oldest(queue).unwrap_or_default().as_secs()
+            ));
+        }
+
+        output.push_str("# HELP vsmtp_queue_messages_over_threshold Messages of the queue older than a threshold.\n# TYPE vsmtp_queue_messages_over_threshold gauge\n");
+        for (queue, (warning, critical)) in &self.thresholds {
+            for (name, threshold) in [("warning", warning), ("critical", critical)] {
+                output.push_str(&format!(
+                    "vsmtp_queue_messages_over_threshold{{queue=\"{queue}\",threshold=\"{name}\"}} {}\n",
+                    self.ages.older_than(queue, *threshold, 0).count
+                ));
+            }
+        }
+
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vqueue::{GenericQueueManager, QueueID};
+    use vsmtp_config::field::FieldQueueMonitorThresholds;
+    use vsmtp_test::{
+        clock::TestClock,
+        config::{local_ctx, local_test},
+    };
+
+    fn monitor(queue_manager: &vqueue::temp::QueueManager) -> QueueMonitor {
+        QueueMonitor::new(
+            queue_manager.get_ages().unwrap().clone(),
+            &FieldQueueMonitor {
+                period: std::time::Duration::from_secs(60),
+                thresholds: [(
+                    "deliver".to_owned(),
+                    FieldQueueMonitorThresholds {
+                        warning: std::time::Duration::from_secs(15 * 60),
+                        critical: std::time::Duration::from_secs(60 * 60),
+                    },
+                )]
+                .into_iter()
+                .collect(),
+                samples: 1,
+            },
+        )
+    }
+
+    async fn queued(queue_manager: &vqueue::temp::QueueManager, queue: &QueueID) -> uuid::Uuid {
+        let mut ctx = local_ctx();
+        ctx.mail_from.message_uuid = uuid::Uuid::new_v4();
+        queue_manager.write_ctx(queue, &ctx).await.unwrap();
+        ctx.mail_from.message_uuid
+    }
+
+    #[tokio::test]
+    async fn thresholds() {
+        let queue_manager =
+            vqueue::temp::QueueManager::init(std::sync::Arc::new(local_test()), vec![]).unwrap();
+        let monitor = monitor(&queue_manager);
+        let clock = TestClock::start();
+
+        let oldest = queued(&queue_manager, &QueueID::Deliver).await;
+        clock.advance(time::Duration::minutes(10));
+        let newest = queued(&queue_manager, &QueueID::Deliver).await;
+        queued(&queue_manager, &QueueID::Dead).await;
+        assert_eq!(monitor.check(), vec![]);
+
+        clock.advance(time::Duration::minutes(10));
+        assert!(matches!(
+            monitor.check().as_slice(),
+            [QueueAgeEvent::Raised { queue, level: QueueAgeLevel::Warning, oldest: age, stuck }]
+                if queue == "deliver"
+                    && *age == std::time::Duration::from_secs(20 * 60)
+                    && stuck.count == 1
+                    && stuck.samples == [oldest]
+        ));
+        // logged once.
+        assert_eq!(monitor.check(), vec![]);
+
+        let metrics = monitor.metrics();
+        for line in [
+            "vsmtp_queue_messages{queue=\"deliver\"} 2\n",
+            "vsmtp_queue_messages{queue=\"dead\"} 1\n",
+            "vsmtp_queue_messages{queue=\"deferred\"} 0\n",
+            "vsmtp_queue_oldest_message_age_seconds{queue=\"deliver\"} 1200\n",
+            "vsmtp_queue_oldest_message_age_seconds{queue=\"dead\"} 600\n",
+            "vsmtp_queue_messages_over_threshold{queue=\"deliver\",threshold=\"warning\"} 1\n",
+            "vsmtp_queue_messages_over_threshold{queue=\"deliver\",threshold=\"critical\"} 0\n",
+        ] {
+            assert!(metrics.contains(line), "{line} not in {metrics}");
+        }
+
+        clock.advance(time::Duration::minutes(45));
+        assert!(matches!(
+            monitor.check().as_slice(),
+            [QueueAgeEvent::Raised { queue, level: QueueAgeLevel::Critical, oldest: age, stuck }]
+                if queue == "deliver"
+                    && *age == std::time::Duration::from_secs(65 * 60)
+                    && stuck.count == 1
+                    && stuck.samples == [oldest]
+        ));
+
+        // the oldest message is delivered, the next one is 55 minutes old.
+        queue_manager
+            .remove_ctx(&QueueID::Deliver, &oldest)
+            .await
+            .unwrap();
+        assert_eq!(monitor.check(), vec![]);
+        assert!(monitor.metrics().contains(
+            "vsmtp_queue_messages_over_threshold{queue=\"deliver\",threshold=\"warning\"} 1\n"
+        ));
+
+        // deferred, the message leaves the queue.
+        queue_manager
+            .move_to_from_id(&QueueID::Deliver, &QueueID::Deferred, &newest)
+            .await
+            .unwrap();
+        assert_eq!(
+            monitor.check(),
+            vec![QueueAgeEvent::Recovered {
+                queue: "deliver".to_owned()
+            }]
+        );
+        assert!(monitor
+            .metrics()
+            .contains("vsmtp_queue_oldest_message_age_seconds{queue=\"deferred\"} 0\n"));
+    }
+}
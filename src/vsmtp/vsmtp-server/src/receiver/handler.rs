@@ -18,15 +18,20 @@ use crate::scheduler;
 
 use tokio_rustls::rustls;
 use vqueue::GenericQueueManager;
-use vsmtp_common::{status::Status, Address, ContextFinished, Reply, Stage, TransactionType};
-use vsmtp_config::Config;
+use vsmtp_common::{
+    policy_event::Kind, status::Status, Address, AddressExtension, ContextFinished, Reply, Stage,
+    TransactionType,
+};
+use vsmtp_config::{field::DuplicateRcptPolicy, Config};
 use vsmtp_delivery::Deliver;
 use vsmtp_mail_parser::{MailParser, MessageBody};
 use vsmtp_protocol::{
-    AuthArgs, AuthError, CallbackWrap, EhloArgs, Error, HeloArgs, MailFromArgs, RcptToArgs,
-    ReceiverContext,
+    AuthArgs, AuthError, BdatArgs, CallbackWrap, EhloArgs, Error, HeloArgs, MailFromArgs,
+    RcptToArgs, ReceiverContext, Verb,
+};
+use vsmtp_rule_engine::{
+    AccessVerdict, ExecutionStage, RecipientVerdict, RuleEngine, RuleState, ACCESS_LIST_HEADER,
 };
-use vsmtp_rule_engine::{ExecutionStage, RuleEngine, RuleState};
 
 ///
 pub struct Handler<Parser, ParserFactory>
@@ -52,6 +57,20 @@ where
     pub(super) message_parser_factory: ParserFactory,
 
     pub(super) emitter: std::sync::Arc<scheduler::Emitter>,
+    pub(super) tls_statistics: Option<std::sync::Arc<crate::TlsStatistics>>,
+    /// Count of the connection in the limits of the anonymous or authenticated clients.
+    pub(super) connection_slot: Option<crate::ConnectionSlot>,
+    /// Values of the access lists header of the client, added to all its messages.
+    pub(super) access_tags: Vec<String>,
+    /// Commands received, to categorize the session when the connection is closed.
+    pub(super) session: crate::session_stats::SessionCounters,
+    pub(super) session_statistics: std::sync::Arc<crate::SessionStatistics>,
+    /// Rate limits of the authenticated users, on the submission listeners.
+    pub(super) submission_quotas: Option<std::sync::Arc<crate::SubmissionQuotas>>,
+    /// Recipients of the transaction reserved in the submission quotas.
+    pub(super) submission_reserved: usize,
+    /// Receives the session when the handler is dropped, to run the `disconnect` stage.
+    pub(super) on_disconnect: Option<tokio::sync::oneshot::Sender<Disconnect>>,
 }
 
 #[async_trait::async_trait]
@@ -72,6 +91,7 @@ impl<Parser: MailParser + Send + Sync, ParserFactory: Fn() -> Parser + Send + Sy
         peer_certificates: Option<Vec<rustls::Certificate>>,
         alpn_protocol: Option<Vec<u8>>,
     ) -> Reply {
+        self.session.tls_pending = false;
         self.on_post_tls_handshake_inner(
             sni,
             protocol_version,
@@ -82,10 +102,13 @@ impl<Parser: MailParser + Send + Sync, ParserFactory: Fn() -> Parser + Send + Sy
     }
 
     async fn on_starttls(&mut self, ctx: &mut ReceiverContext) -> Reply {
+        self.session.dialogue_commands += 1;
         self.on_starttls_inner(ctx)
     }
 
     async fn on_auth(&mut self, ctx: &mut ReceiverContext, args: AuthArgs) -> Option<Reply> {
+        self.session.dialogue_commands += 1;
+        self.update_error_count(ctx);
         self.on_auth_inner(ctx, args)
     }
 
@@ -94,31 +117,138 @@ impl<Parser: MailParser + Send + Sync, ParserFactory: Fn() -> Parser + Send + Sy
         ctx: &mut ReceiverContext,
         result: Result<(), AuthError>,
     ) -> Reply {
+        self.update_error_count(ctx);
         self.on_post_auth_inner(ctx, result)
     }
 
     async fn on_helo(&mut self, ctx: &mut ReceiverContext, args: HeloArgs) -> Reply {
+        self.session.dialogue_commands += 1;
+        self.update_error_count(ctx);
+        if let Some(reply) = self.reset_transaction(ctx) {
+            return reply;
+        }
         self.on_helo_inner(ctx, args)
     }
 
     async fn on_ehlo(&mut self, ctx: &mut ReceiverContext, args: EhloArgs) -> Reply {
+        self.session.dialogue_commands += 1;
+        self.update_error_count(ctx);
+        if let Some(reply) = self.reset_transaction(ctx) {
+            return reply;
+        }
         self.on_ehlo_inner(ctx, args)
     }
 
     async fn on_mail_from(&mut self, ctx: &mut ReceiverContext, args: MailFromArgs) -> Reply {
+        self.session.dialogue_commands += 1;
+        self.update_error_count(ctx);
+
+        let esmtp = &self.config.server.esmtp;
+        if args.use_smtputf8 && !(esmtp.eightbitmime && esmtp.smtputf8) {
+            return "555 5.5.4 SMTPUTF8 not supported\r\n"
+                .parse::<Reply>()
+                .unwrap();
+        }
+
+        // NOTE: the rules of the transaction see the datasets reloaded since the previous one.
+        self.rule_engine.snapshot_datasets(&mut self.state);
+
+        // NOTE: the context is not locked while the rules are called.
+        let (server_addr, auth) = {
+            let context = self.state.context();
+            let context = context.read().expect("state poisoned");
+            (*context.server_addr(), context.auth().clone())
+        };
+        let not_owned = crate::sender_policy::check_mail_from(
+            &self.config,
+            &server_addr,
+            auth.as_ref(),
+            args.reverse_path.as_ref(),
+            |authid, address| {
+                self.rule_engine
+                    .is_sender_owned(&self.state, authid, address)
+            },
+        );
+        if let Some(reply) = not_owned {
+            self.emit_policy_event(Kind::SenderNotOwned, &reply, |event| {
+                event
+                    .with_sender(args.reverse_path.as_ref())
+                    .with_raw_mail_from(&args.raw)
+            });
+            return reply;
+        }
+
+        let sender_tag = match args.reverse_path.as_ref().and_then(|reverse_path| {
+            let client_ip = self
+                .state
+                .context()
+                .read()
+                .expect("state poisoned")
+                .client_addr()
+                .ip();
+            self.rule_engine
+                .access_lists()
+                .check_sender(client_ip, &reverse_path.domain().to_utf8())
+        }) {
+            Some(AccessVerdict::Refuse(reply)) => {
+                tracing::warn!(
+                    reverse_path = ?args.reverse_path,
+                    "Sender refused by the access lists."
+                );
+                self.emit_policy_event(Kind::AccessList, &reply, |event| {
+                    event
+                        .with_sender(args.reverse_path.as_ref())
+                        .with_raw_mail_from(&args.raw)
+                });
+                return reply;
+            }
+            Some(AccessVerdict::Tag(tag)) => Some(tag),
+            None => None,
+        };
+
+        self.state
+            .context()
+            .write()
+            .expect("state poisoned")
+            .to_mail_from(
+                args.reverse_path,
+                args.use_smtputf8,
+                args.mime_body_type,
+                args.size,
+            )
+            .expect("bad state");
         self.state
             .context()
             .write()
             .expect("state poisoned")
-            .to_mail_from(args.reverse_path, args.use_smtputf8)
+            .set_envelop_id(args.envelop_id)
+            .expect("bad state");
+        self.state
+            .context()
+            .write()
+            .expect("state poisoned")
+            .set_raw_mail_from(args.raw)
             .expect("bad state");
 
+        {
+            let message = self.state.message();
+            let mut message = message.write().expect("message poisoned");
+            while message.remove_header(ACCESS_LIST_HEADER) {}
+            for tag in self.access_tags.iter().chain(&sender_tag) {
+                message.prepend_header(ACCESS_LIST_HEADER, tag);
+            }
+        }
+
         match self
             .rule_engine
             .run_when(&self.state, &mut self.skipped, ExecutionStage::MailFrom)
         {
-            Status::Faccept(reply) | Status::Accept(reply) => reply,
-            Status::Quarantine(_) | Status::Next | Status::DelegationResult => {
+            Status::Faccept(reply) | Status::Accept(reply) => {
+                self.session.mail_accepted = true;
+                reply
+            }
+            Status::Quarantine(_) | Status::Defer(_) | Status::Next | Status::DelegationResult => {
+                self.session.mail_accepted = true;
                 "250 Ok\r\n".parse::<Reply>().unwrap()
             }
             // on the mail from stage, reject acts as a deny.
@@ -131,21 +261,165 @@ impl<Parser: MailParser + Send + Sync, ParserFactory: Fn() -> Parser + Send + Sy
     }
 
     #[allow(clippy::too_many_lines)]
-    async fn on_rcpt_to(&mut self, ctx: &mut ReceiverContext, args: RcptToArgs) -> Reply {
+    async fn on_rcpt_to(&mut self, ctx: &mut ReceiverContext, mut args: RcptToArgs) -> Reply {
+        self.session.dialogue_commands += 1;
+        self.update_error_count(ctx);
+
+        // FIXME: handle internal state too ??
+        let rcpt_count = self
+            .state
+            .context()
+            .read()
+            .expect("state poisoned")
+            .forward_paths()
+            .map_or(0, Vec::len);
+        if rcpt_count >= self.config.server.smtp.rcpt_count_max {
+            let reply = self.config.server.smtp.rcpt_count_max_reply.clone();
+            self.emit_policy_event(Kind::RecipientLimit, &reply, |event| {
+                event
+                    .with_recipient(&args.forward_path)
+                    .with_raw_rcpt(&args.raw)
+            });
+            return reply;
+        }
+
+        if let Some(srs) = self.rule_engine.srs() {
+            match srs.reverse(&args.forward_path) {
+                Ok(Some(original)) => {
+                    tracing::debug!(rcpt = %args.forward_path, %original, "SRS address reversed.");
+                    args.forward_path = original;
+                }
+                Ok(None) => {}
+                Err(reason) => {
+                    let reply = format!("550 5.1.1 {reason}\r\n").parse::<Reply>().unwrap();
+                    self.emit_policy_event(Kind::UnknownRecipient, &reply, |event| {
+                        event
+                            .with_recipient(&args.forward_path)
+                            .with_raw_rcpt(&args.raw)
+                    });
+                    return reply;
+                }
+            }
+        }
+
+        let is_duplicate = std::iter::once(&self.state)
+            .chain(self.state_internal.as_ref())
+            .any(|state| {
+                state
+                    .context()
+                    .read()
+                    .expect("state poisoned")
+                    .forward_paths()
+                    .map_or(false, |rcpt| rcpt.contains(&args.forward_path))
+            });
+        if is_duplicate {
+            tracing::debug!(rcpt = %args.forward_path, "Duplicate recipient.");
+
+            return match self.config.server.smtp.duplicate_rcpt {
+                DuplicateRcptPolicy::Ignore => "250 Ok\r\n".parse::<Reply>().unwrap(),
+                DuplicateRcptPolicy::Reject => {
+                    let reply = "553 5.1.0 Duplicate recipient\r\n"
+                        .parse::<Reply>()
+                        .unwrap();
+                    self.emit_policy_event(Kind::DuplicateRecipient, &reply, |event| {
+                        event
+                            .with_recipient(&args.forward_path)
+                            .with_raw_rcpt(&args.raw)
+                    });
+                    reply
+                }
+            };
+        }
+
+        let (client_ip, is_incoming) = {
+            let ctx = self.state.context();
+            let ctx = ctx.read().expect("state poisoned");
+            let is_outgoing =
+                ctx.reverse_path()
+                    .expect("bad state")
+                    .as_ref()
+                    .map_or(false, |reverse_path| {
+                        self.rule_engine
+                            .is_handled_domain(reverse_path.domain_ref())
+                    });
+            (ctx.client_addr().ip(), !is_outgoing)
+        };
+        // NOTE: split once, for the check of the mailbox and the local transports.
+        let mut extension =
+            AddressExtension::parse(&args.forward_path, &self.config.server.recipient_delimiter);
+        let mut rewritten_from = None;
+        if is_incoming
+            && self
+                .rule_engine
+                .is_handled_domain(args.forward_path.domain_ref())
         {
-            // FIXME: handle internal state too ??
-            let locked_context = self.state.context();
-            let context = locked_context.read().expect("state poisoned");
-            if context.forward_paths().map_or(0, Vec::len) >= self.config.server.smtp.rcpt_count_max
-            {
-                return "452 Requested action not taken: too many recipients\r\n"
-                    .parse::<Reply>()
-                    .unwrap();
-            } else if !context.is_utf8_advertised() && !args.forward_path.full().is_ascii() {
-                return "553 mailbox name not allowed\r\n".parse::<Reply>().unwrap();
+            match self.rule_engine.check_recipient(
+                client_ip,
+                &args.forward_path,
+                extension.as_ref(),
+            ) {
+                Some(RecipientVerdict::Unknown(reply)) => {
+                    self.emit_policy_event(Kind::UnknownRecipient, &reply, |event| {
+                        event
+                            .with_recipient(&args.forward_path)
+                            .with_raw_rcpt(&args.raw)
+                    });
+                    return reply;
+                }
+                Some(RecipientVerdict::Probing(reply)) => {
+                    self.emit_policy_event(Kind::RecipientProbing, &reply, |event| {
+                        event
+                            .with_recipient(&args.forward_path)
+                            .with_raw_rcpt(&args.raw)
+                    });
+                    ctx.deny();
+                    return reply;
+                }
+                Some(RecipientVerdict::CatchAll(catch_all)) => {
+                    tracing::info!(
+                        rcpt = %args.forward_path,
+                        %catch_all,
+                        "Unknown recipient rewritten to the catch-all."
+                    );
+                    extension = AddressExtension::parse(
+                        &catch_all,
+                        &self.config.server.recipient_delimiter,
+                    );
+                    rewritten_from = Some(std::mem::replace(&mut args.forward_path, catch_all));
+                }
+                None => {}
+            }
+        }
+
+        if let Some(original) = &rewritten_from {
+            // NOTE: the catch-all is delivered once, with the unknown recipients recorded.
+            let state = std::iter::once(&self.state)
+                .chain(self.state_internal.as_ref())
+                .find(|state| {
+                    state
+                        .context()
+                        .read()
+                        .expect("state poisoned")
+                        .forward_paths()
+                        .map_or(false, |rcpt| rcpt.contains(&args.forward_path))
+                });
+            if let Some(state) = state {
+                state
+                    .context()
+                    .write()
+                    .expect("state poisoned")
+                    .add_catch_all(args.forward_path, original.clone())
+                    .expect("bad state");
+                return "250 Ok\r\n".parse::<Reply>().unwrap();
             }
         }
 
+        if let Some(reply) = self.reserve_submission_quota(&args) {
+            return reply;
+        }
+
+        let forward_path = args.forward_path.clone();
+
         let is_internal = {
             let ctx = self.state.context();
             let mut ctx = ctx.write().expect("state poisoned");
@@ -154,10 +428,11 @@ impl<Parser: MailParser + Send + Sync, ParserFactory: Fn() -> Parser + Send + Sy
 
             let (is_outgoing, is_handled) = (
                 reverse_path.as_ref().map_or(false, |reverse_path| {
-                    self.rule_engine.is_handled_domain(&reverse_path.domain())
+                    self.rule_engine
+                        .is_handled_domain(reverse_path.domain_ref())
                 }),
                 self.rule_engine
-                    .is_handled_domain(&args.forward_path.domain()),
+                    .is_handled_domain(args.forward_path.domain_ref()),
             );
 
             match (is_outgoing, is_handled) {
@@ -268,17 +543,58 @@ impl<Parser: MailParser + Send + Sync, ParserFactory: Fn() -> Parser + Send + Sy
             }
         };
 
+        // the extension of the local recipients, for the local transports.
+        let is_local = *forward_path.domain_ref() == self.config.server.name
+            || self
+                .config
+                .server
+                .r#virtual
+                .contains_key(forward_path.domain_ref());
+        let extension = extension.filter(|_| is_local);
+
         let state = match self.state_internal.as_mut() {
             Some(state_internal) if is_internal => state_internal,
             _ => &mut self.state,
         };
 
+        if let Some(extension) = extension {
+            state
+                .context()
+                .write()
+                .expect("state poisoned")
+                .set_rcpt_extension(forward_path.clone(), extension)
+                .expect("bad state");
+        }
+        if let Some(original_recipient) = args.original_forward_path {
+            state
+                .context()
+                .write()
+                .expect("state poisoned")
+                .set_original_recipient(forward_path.clone(), original_recipient)
+                .expect("bad state");
+        }
+        if let Some(original) = rewritten_from {
+            state
+                .context()
+                .write()
+                .expect("state poisoned")
+                .add_catch_all(forward_path, original)
+                .expect("bad state");
+        }
+
+        state
+            .context()
+            .write()
+            .expect("state poisoned")
+            .add_raw_rcpt(args.raw, self.config.server.smtp.rcpt_count_max)
+            .expect("bad state");
+
         match self
             .rule_engine
             .run_when(state, &mut self.skipped, ExecutionStage::RcptTo)
         {
             Status::Faccept(reply) | Status::Accept(reply) | Status::Reject(reply) => reply,
-            Status::Quarantine(_) | Status::Next | Status::DelegationResult => {
+            Status::Quarantine(_) | Status::Defer(_) | Status::Next | Status::DelegationResult => {
                 "250 Ok\r\n".parse::<Reply>().unwrap()
             }
             Status::Deny(reply) => {
@@ -289,40 +605,78 @@ impl<Parser: MailParser + Send + Sync, ParserFactory: Fn() -> Parser + Send + Sy
         }
     }
 
-    async fn on_rset(&mut self) -> Reply {
-        self.state
-            .context()
-            .write()
-            .expect("state poisoned")
-            .reset();
-
-        self.state_internal = None;
+    async fn on_rset(&mut self, ctx: &mut ReceiverContext) -> Reply {
+        if let Some(reply) = self.reset_transaction(ctx) {
+            return reply;
+        }
 
         // TODO: reset message?
 
         "250 Ok\r\n".parse::<Reply>().unwrap()
     }
 
+    async fn on_data(&mut self) -> Reply {
+        let server = &self.config.server;
+        let text = server
+            .smtp
+            .data_reply
+            .replace(
+                "{server_name}",
+                &server.disclosed_name().unwrap_or(&server.name).to_string(),
+            )
+            .replace("{size_max}", &server.message_size_limit.to_string())
+            .replace(['\r', '\n'], " ");
+
+        format!("354 {text}\r\n").parse::<Reply>().unwrap()
+    }
+
+    async fn on_bdat(&mut self, _: &BdatArgs) -> Option<Reply> {
+        if self.config.server.esmtp.chunking {
+            None
+        } else {
+            Some("502 Command not implemented\r\n".parse::<Reply>().unwrap())
+        }
+    }
+
     async fn on_message(
         &mut self,
         ctx: &mut ReceiverContext,
         stream: impl tokio_stream::Stream<Item = Result<Vec<u8>, Error>> + Send + Unpin,
     ) -> (Reply, Option<Vec<Self::Item>>) {
-        self.on_message_inner(ctx, stream).await
+        self.update_error_count(ctx);
+        let (reply, items) = self.on_message_inner(ctx, stream).await;
+        if items.is_none() {
+            self.release_submission_quota(usize::MAX);
+        }
+        (reply, items)
     }
 
     async fn on_message_completed(&mut self, item: Self::Item) -> Option<Reply> {
         let (ctx, msg) = item;
-        self.on_message_completed_inner(ctx, msg).await
+        let recipients = ctx.rcpt_to.forward_paths.len();
+        let reply = self.on_message_completed_inner(ctx, msg).await;
+        if reply.is_none() {
+            self.session.messages_accepted += 1;
+            self.submission_reserved = self.submission_reserved.saturating_sub(recipients);
+        } else {
+            self.release_submission_quota(recipients);
+        }
+        reply
+    }
+
+    async fn on_unknown(&mut self, buffer: Vec<u8>) -> Reply {
+        self.session.invalid_commands += 1;
+        vsmtp_protocol::unknown_command_reply(&buffer)
+    }
+
+    async fn on_bad_sequence(&mut self, _: (Verb, Stage)) -> Reply {
+        self.session.invalid_commands += 1;
+        "503 Bad sequence of commands\r\n".parse::<Reply>().unwrap()
     }
 
     async fn on_hard_error(&mut self, ctx: &mut ReceiverContext, reply: Reply) -> Reply {
         ctx.deny();
-        reply.extended(
-            &"451 Too many errors from the client\r\n"
-                .parse::<Reply>()
-                .unwrap(),
-        )
+        reply.extended(&self.config.server.smtp.error.hard_count_reply)
     }
 
     async fn on_soft_error(&mut self, _: &mut ReceiverContext, reply: Reply) -> Reply {
@@ -338,3 +692,54 @@ impl<Parser: MailParser + Send + Sync, ParserFactory: Fn() -> Parser + Send + Sy
             .stage()
     }
 }
+
+impl<Parser: MailParser + Send + Sync, ParserFactory: Fn() -> Parser + Send + Sync> Drop
+    for Handler<Parser, ParserFactory>
+{
+    /// Hand the session over to the `disconnect` stage, run once the connection is closed.
+    fn drop(&mut self) {
+        // NOTE: the recipients of a transaction aborted by the client are not submitted.
+        self.release_submission_quota(usize::MAX);
+
+        if let Some(on_disconnect) = self.on_disconnect.take() {
+            let disconnect = Disconnect {
+                state: self.state.clone(),
+                rule_engine: self.rule_engine.clone(),
+                session: std::mem::take(&mut self.session),
+                session_statistics: self.session_statistics.clone(),
+            };
+            if on_disconnect.send(disconnect).is_err() {
+                tracing::debug!("Session cancelled, the disconnect stage is skipped.");
+            }
+        }
+    }
+}
+
+/// The end of a session, run by [`crate::Server::serve`] after the receiver is gone,
+/// out of the destructor of the [`Handler`].
+pub(crate) struct Disconnect {
+    state: std::sync::Arc<RuleState>,
+    rule_engine: std::sync::Arc<RuleEngine>,
+    session: crate::session_stats::SessionCounters,
+    session_statistics: std::sync::Arc<crate::SessionStatistics>,
+}
+
+impl Disconnect {
+    /// Categorize the session, and run the rules of the `disconnect` stage.
+    pub(crate) fn run(self) {
+        let category = self.session.classify();
+        self.state
+            .context()
+            .write()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .set_session_category(category);
+
+        // NOTE: the client is gone, the status is ignored.
+        let _status = self
+            .rule_engine
+            .run_when(&self.state, &mut None, ExecutionStage::Disconnect);
+
+        self.session_statistics.record(category);
+        tracing::info!(%category, "Session closed.");
+    }
+}
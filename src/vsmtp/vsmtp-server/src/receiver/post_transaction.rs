@@ -19,13 +19,15 @@ use crate::{Handler, ProcessMessage};
 use futures_util::TryStreamExt;
 use vqueue::QueueID;
 use vsmtp_common::{
+    policy_event::{Kind, PolicyEvent},
     status::{self, Status},
     transfer::{self, error::Rule},
     ContextFinished, Reply,
 };
+use vsmtp_config::field::NormalizationStage;
 use vsmtp_mail_parser::{Mail, MailParser, MessageBody, ParserError, RawBody};
 use vsmtp_protocol::{Error, ParseArgsError, ReceiverContext};
-use vsmtp_rule_engine::{ExecutionStage, RuleEngine, RuleState};
+use vsmtp_rule_engine::{AccessVerdict, ExecutionStage, RuleEngine, RuleState, ACCESS_LIST_HEADER};
 
 impl<Parser, ParserFactory> Handler<Parser, ParserFactory>
 where
@@ -55,6 +57,20 @@ where
                 }
             };
             *guard = MessageBody::from(mail);
+
+            // NOTE: before the rules, which verify the DKIM signatures of the normalized message.
+            if let Some(normalization) = rule_engine
+                .srv()
+                .config
+                .server
+                .normalization
+                .as_ref()
+                .filter(|normalization| normalization.stage == NormalizationStage::BeforeRules)
+            {
+                if guard.normalize(normalization.trailing_whitespace) {
+                    tracing::debug!("Message normalized on reception.");
+                }
+            }
         }
 
         state
@@ -64,6 +80,28 @@ where
             .to_finished()
             .expect("bad state");
 
+        // the client being a trusted relay, the host which sent the message is checked.
+        match rule_engine.check_origin(state) {
+            Some(AccessVerdict::Refuse(reply)) => {
+                tracing::warn!("Originating host refused by the access lists.");
+                PolicyEvent::new(
+                    Kind::AccessList,
+                    &state.context().read().expect("state poisoned"),
+                )
+                .with_reply(&reply)
+                .emit();
+                return Status::Deny(reply);
+            }
+            Some(AccessVerdict::Tag(tag)) => {
+                state
+                    .message()
+                    .write()
+                    .expect("message poisoned")
+                    .prepend_header(ACCESS_LIST_HEADER, &tag);
+            }
+            None => (),
+        }
+
         let status = rule_engine.run_when(state, &mut skipped, ExecutionStage::PreQ);
 
         if let Some(skipped) = skipped {
@@ -76,25 +114,75 @@ where
         status
     }
 
+    /// The message lacks the headers required by `server.missing_headers`: it is refused
+    /// at the end of `DATA` instead of being moved to the `dead` queue once accepted.
+    fn check_required_headers(
+        &self,
+        status: &Status,
+        ctx: &ContextFinished,
+        message: &MessageBody,
+    ) -> Option<Reply> {
+        // NOTE: the result of a delegation is the message already accepted.
+        if matches!(status, Status::DelegationResult) {
+            return None;
+        }
+        crate::missing_headers::check_required_headers(&self.config, ctx, message)
+    }
+
+    /// The message could not be written in the spool: the client is replied
+    /// `server.smtp.storage_error_reply` to retry later.
+    fn storage_error(&self, error: &anyhow::Error) -> Reply {
+        tracing::error!(
+            %error,
+            "Failed to write the message in the spool, is the disk full ?"
+        );
+        self.session_statistics.record_storage_error();
+        self.config.server.smtp.storage_error_reply.clone()
+    }
+
     // TODO: enhance error handling
     pub(super) async fn on_message_completed_inner(
         &self,
         mut ctx: ContextFinished,
-        msg: MessageBody,
+        mut msg: MessageBody,
     ) -> Option<Reply> {
+        // NOTE: the rules have read a lossy copy of a message which is not valid UTF-8.
+        if let Some(quarantine) = &self.config.server.smtp.invalid_utf8_quarantine {
+            if msg.inner().is_lossy()
+                && !matches!(
+                    ctx.connect.skipped,
+                    Some(status::Status::Deny(_) | status::Status::Quarantine(_))
+                )
+            {
+                tracing::warn!("Message not valid UTF-8 quarantined.");
+                ctx.connect.skipped = Some(status::Status::Quarantine(quarantine.clone()));
+            }
+        }
+
         let (mut message_uuid, skipped) = (ctx.mail_from.message_uuid, ctx.connect.skipped.clone());
 
+        if let Some(tls_statistics) = &self.tls_statistics {
+            tls_statistics.record(&ctx);
+        }
+
+        // NOTE: the text of the commands is only kept on request, for the quarantined messages.
+        if !(self.config.server.smtp.raw_commands_in_quarantine
+            && matches!(skipped, Some(status::Status::Quarantine(_))))
+        {
+            ctx.mail_from.clear_raw_commands();
+        }
+
         let denied = "554 permanent problems with the remote server\r\n"
             .parse::<Reply>()
             .unwrap();
 
+        let mut delegated_from = None;
         let (queue, should_skip_working, delegated) = match &skipped {
             Some(status @ status::Status::Quarantine(path)) => {
                 let quarantine = QueueID::Quarantine { name: path.into() };
-                match self.queue_manager.write_ctx(&quarantine, &ctx).await {
-                    Ok(()) => (),
-                    Err(_e) => return Some(denied),
-                };
+                if let Err(error) = self.queue_manager.write_ctx(&quarantine, &ctx).await {
+                    return Some(self.storage_error(&error));
+                }
 
                 tracing::warn!(status = status.as_ref(), "Rules skipped.");
                 (None, None, false)
@@ -120,6 +208,29 @@ where
                         }
                 }
 
+                delegated_from = msg
+                    .get_header("X-VSMTP-DELEGATION")
+                    .and_then(|header| {
+                        vsmtp_mail_parser::get_mime_header("X-VSMTP-DELEGATION", &header)
+                            .args
+                            .get("span")
+                            .and_then(|span| span.parse::<std::num::NonZeroU64>().ok())
+                    })
+                    .map(tracing::Id::from_non_zero_u64);
+
+                // the service only received a part of the message, its headers are
+                // applied to the message stored before the delegation.
+                let payload = vsmtp_rule_engine::DelegationPayload::of_message(&msg);
+                if payload != vsmtp_rule_engine::DelegationPayload::Full {
+                    match self.queue_manager.get_msg(&message_uuid).await {
+                        Ok(original) => msg = payload.merge(&original, &msg),
+                        Err(error) => {
+                            tracing::warn!(%error, "Failed to read the message delegated.");
+                            return Some(denied);
+                        }
+                    }
+                }
+
                 (None, Some(false), true)
             }
             Some(status::Status::Deny(code)) => {
@@ -129,24 +240,34 @@ where
 
                 (Some(QueueID::Dead), None, false)
             }
-            None | Some(status::Status::Next) => (Some(QueueID::Working), Some(false), false),
+            // NOTE: the deferral is applied by the working queue, after the aliases are expanded.
+            None | Some(status::Status::Next | status::Status::Defer(_)) => {
+                (Some(QueueID::Working), Some(false), false)
+            }
             Some(reason) => {
                 tracing::warn!(stage = %ExecutionStage::PreQ, status = ?reason.as_ref(), "Rules skipped.");
                 (Some(QueueID::Deliver), Some(true), false)
             }
         };
 
-        match self.queue_manager.write_msg(&message_uuid, &msg).await {
-            Ok(()) => (),
-            Err(_e) => return Some(denied),
-        };
+        if let Err(error) = self.queue_manager.write_msg(&message_uuid, &msg).await {
+            if let Some(status::Status::Quarantine(path)) = &skipped {
+                let quarantine = QueueID::Quarantine { name: path.into() };
+                let _ = self
+                    .queue_manager
+                    .remove_ctx(&quarantine, &message_uuid)
+                    .await;
+            }
+            return Some(self.storage_error(&error));
+        }
 
         if let Some(queue) = queue {
-            match self.queue_manager.write_ctx(&queue, &ctx).await {
-                Ok(()) => (),
-                Err(_e) => {
-                    return Some(denied);
+            if let Err(error) = self.queue_manager.write_ctx(&queue, &ctx).await {
+                // NOTE: the message of a delegation is the one stored before it, and is kept.
+                if !delegated {
+                    let _ = self.queue_manager.remove_msg(&message_uuid).await;
                 }
+                return Some(self.storage_error(&error));
             }
         }
 
@@ -154,7 +275,8 @@ where
             ProcessMessage::delegated
         } else {
             ProcessMessage::new
-        }(message_uuid);
+        }(message_uuid)
+        .following(delegated_from);
 
         let process = match &should_skip_working {
             Some(false) => self.emitter.send_to_working(process_msg).await,
@@ -163,7 +285,17 @@ where
         };
 
         match process {
-            Ok(()) => None,
+            Ok(()) => {
+                tracing::info!(
+                    uuid = %message_uuid,
+                    queue_id = ctx.mail_from.queue_id(),
+                    size = msg.size(),
+                    declared_size = ?ctx.mail_from.declared_size,
+                    rcpt_count = ctx.rcpt_to.forward_paths.len(),
+                    "Message queued."
+                );
+                None
+            }
             Err(_e) => Some(denied),
         }
     }
@@ -197,22 +329,15 @@ where
             .await
         {
             Ok(mail) => mail,
-            Err(ParserError::BufferTooLong { .. }) => {
-                return Err(
-                    "552 4.3.1 Message size exceeds fixed maximum message size\r\n"
-                        .parse::<Reply>()
-                        .unwrap(),
-                );
+            Err(ParserError::BufferTooLong { .. } | ParserError::MailSizeExceeded { .. }) => {
+                let reply = self.config.server.smtp.message_size_limit_reply.clone();
+                self.emit_policy_event(Kind::SizeExceeded, &reply, |event| event);
+                return Err(reply);
             }
-            Err(ParserError::MailSizeExceeded { .. }) => {
-                return Err(
-                    "552 4.3.1 Message size exceeds fixed maximum message size\r\n"
-                        .parse::<Reply>()
-                        .unwrap(),
-                )
+            Err(error) => {
+                tracing::warn!(%error, "Failed to read the message.");
+                return Err(self.config.server.smtp.invalid_message_reply.clone());
             }
-
-            Err(otherwise) => todo!("handle error cleanly {:?}", otherwise),
         };
 
         tracing::info!("Message body fully received, processing...");
@@ -227,7 +352,16 @@ where
     ) -> (Reply, Option<Vec<(ContextFinished, MessageBody)>>) {
         let mail = match self.get_message_body(stream).await {
             Ok(mail) => mail,
-            Err(reply) => return (reply, None),
+            Err(reply) => {
+                // the transaction is aborted, the client can start a new one.
+                self.state
+                    .context()
+                    .write()
+                    .expect("state poisoned")
+                    .reset();
+                self.state_internal = None;
+                return (reply, None);
+            }
         };
 
         let internal_reply = if let Some(state_internal) = &self.state_internal {
@@ -249,13 +383,16 @@ where
                     Some((reply, None))
                 }
                 Status::Delegated(_) => unreachable!(),
-                status => {
-                    mail_ctx.connect.skipped = Some(status);
-                    Some((
-                        "250 Ok\r\n".parse::<Reply>().unwrap(),
-                        Some((mail_ctx, message)),
-                    ))
-                }
+                status => match self.check_required_headers(&status, &mail_ctx, &message) {
+                    Some(reply) => Some((reply, None)),
+                    None => {
+                        mail_ctx.connect.skipped = Some(status);
+                        Some((
+                            "250 Ok\r\n".parse::<Reply>().unwrap(),
+                            Some((mail_ctx, message)),
+                        ))
+                    }
+                },
             }
         } else {
             None
@@ -312,13 +449,16 @@ where
                         Some((reply, None))
                     }
                     Status::Delegated(_) => unreachable!(),
-                    status => {
-                        mail_ctx.connect.skipped = Some(status);
-                        Some((
-                            "250 Ok\r\n".parse::<Reply>().unwrap(),
-                            Some((mail_ctx, message)),
-                        ))
-                    }
+                    status => match self.check_required_headers(&status, &mail_ctx, &message) {
+                        Some(reply) => Some((reply, None)),
+                        None => {
+                            mail_ctx.connect.skipped = Some(status);
+                            Some((
+                                "250 Ok\r\n".parse::<Reply>().unwrap(),
+                                Some((mail_ctx, message)),
+                            ))
+                        }
+                    },
                 }
             }
         };
@@ -330,8 +470,16 @@ where
             ),
             (Some((internal_reply, internal)), None) => (internal_reply, internal.map(|i| vec![i])),
             (None, Some((reply, other))) => (reply, other.map(|i| vec![i])),
-            // both mail are empty: should be unreachable
-            (None, None) => todo!(),
+            // NOTE: every recipient has been removed before the end of the message.
+            (None, None) => {
+                tracing::warn!("Message without recipient refused.");
+                (
+                    "554 5.5.1 No valid recipients\r\n"
+                        .parse::<Reply>()
+                        .unwrap(),
+                    None,
+                )
+            }
         }
     }
 }
@@ -15,29 +15,36 @@
  *
 */
 
-use crate::{scheduler::Emitter, Handler};
+use crate::{scheduler::Emitter, session_stats::SessionCounters, Handler, Verdict};
 use tokio_rustls::rustls;
 use vqueue::GenericQueueManager;
 use vsmtp_common::{
     auth::{Credentials, Mechanism},
+    policy_event::{Kind, PolicyEvent},
     status::Status,
-    ClientName, Reply,
+    ClientName, Domain, Reply, SubmissionQuota,
 };
 use vsmtp_config::Config;
 use vsmtp_mail_parser::MailParser;
 use vsmtp_protocol::{
-    AcceptArgs, AuthArgs, AuthError, CallbackWrap, ConnectionKind, EhloArgs, HeloArgs,
+    AcceptArgs, AuthArgs, AuthError, CallbackWrap, ConnectionKind, EhloArgs, HeloArgs, RcptToArgs,
     ReceiverContext,
 };
-use vsmtp_rule_engine::{ExecutionStage, RuleEngine, RuleState};
+use vsmtp_rule_engine::{AccessVerdict, ExecutionStage, RuleEngine, RuleState};
 
-fn build_ehlo_reply(config: &vsmtp_config::Config, is_transaction_secured: bool) -> Reply {
-    let auth_mechanism_list: Option<(Vec<Mechanism>, Vec<Mechanism>)> = config
-        .server
-        .esmtp
-        .auth
-        .as_ref()
-        .map(|auth| auth.mechanisms.iter().partition(|m| m.must_be_under_tls()));
+fn build_ehlo_reply(
+    config: &vsmtp_config::Config,
+    is_transaction_secured: bool,
+    has_client_certificate: bool,
+) -> Reply {
+    let auth_mechanism_list: Option<(Vec<Mechanism>, Vec<Mechanism>)> =
+        config.server.esmtp.auth.as_ref().map(|auth| {
+            auth.mechanisms
+                .iter()
+                // EXTERNAL authenticates the client with the certificate of the TLS handshake.
+                .filter(|m| **m != Mechanism::External || has_client_certificate)
+                .partition(|m| m.must_be_under_tls())
+        });
 
     let esmtp = &config.server.esmtp;
 
@@ -99,7 +106,14 @@ fn build_ehlo_reply(config: &vsmtp_config::Config, is_transaction_secured: bool)
     //       they need the transaction context)
     let mut reply = String::default();
     let mut extensions = [
-        Some(("250", config.server.name.to_string())),
+        Some((
+            "250",
+            config
+                .server
+                .disclosed_name()
+                .unwrap_or(&config.server.name)
+                .to_string(),
+        )),
         auth,
         esmtp
             .eightbitmime
@@ -132,11 +146,169 @@ fn build_ehlo_reply(config: &vsmtp_config::Config, is_transaction_secured: bool)
     reply.parse::<Reply>().expect("valid reply")
 }
 
+/// The `220` reply sent when the connection is opened, or after the TLS handshake
+/// of a tunneled connection, with the `banner` set by the rules if any.
+fn greeting(server_name: &Domain, banner: Option<&str>) -> Reply {
+    format!(
+        "220 {server_name} {}\r\n",
+        banner.unwrap_or("Service ready")
+    )
+    .parse::<Reply>()
+    .expect("valid")
+}
+
+/// The certificate presented by the client in the TLS handshake, and verified against
+/// the trusted authorities.
+fn client_certificate(ctx: &vsmtp_common::Context) -> Option<&rustls::Certificate> {
+    ctx.tls().as_ref()?.peer_certificates.as_ref()?.first()
+}
+
+/// The domains and email addresses of the subject alternative names of the certificate
+/// presented by the client.
+fn certificate_alt_names(certificate: &rustls::Certificate) -> Vec<String> {
+    use x509_parser::extensions::GeneralName;
+
+    x509_parser::parse_x509_certificate(&certificate.0)
+        .ok()
+        .and_then(|(_, certificate)| {
+            certificate
+                .subject_alternative_name()
+                .ok()
+                .flatten()
+                .map(|alt_names| {
+                    alt_names
+                        .value
+                        .general_names
+                        .iter()
+                        .filter_map(|name| match name {
+                            GeneralName::DNSName(name) | GeneralName::RFC822Name(name) => {
+                                Some((*name).to_owned())
+                            }
+                            _ => None,
+                        })
+                        .collect()
+                })
+        })
+        .unwrap_or_default()
+}
+
+/// The subject of the certificate presented by the client, in the RFC 4514 form.
+fn certificate_subject(certificate: &rustls::Certificate) -> Option<String> {
+    x509_parser::parse_x509_certificate(&certificate.0)
+        .map(|(_, certificate)| certificate.subject().to_string())
+        .map_err(|error| tracing::warn!(%error, "Failed to parse the certificate of the client."))
+        .ok()
+}
+
 impl<Parser, ParserFactory> Handler<Parser, ParserFactory>
 where
     Parser: MailParser + Send + Sync,
     ParserFactory: Fn() -> Parser + Send + Sync,
 {
+    /// Emit the policy event of a built-in rejection, `complete` adding the details
+    /// missing from the context of the transaction.
+    pub(super) fn emit_policy_event(
+        &self,
+        kind: Kind,
+        reply: &Reply,
+        complete: impl FnOnce(PolicyEvent) -> PolicyEvent,
+    ) {
+        let event = PolicyEvent::new(kind, &self.state.context().read().expect("state poisoned"))
+            .with_reply(reply);
+        complete(event).emit();
+    }
+
+    /// The identity and the limits of the authenticated user, on the submission listeners.
+    fn submission_quota(&self) -> Option<(&crate::SubmissionQuotas, String, SubmissionQuota)> {
+        let submission_quotas = self.submission_quotas.as_ref()?;
+        let ctx = self.state.context();
+        let ctx = ctx.read().expect("state poisoned");
+        let identity = ctx
+            .auth()
+            .as_ref()
+            .filter(|auth| auth.authenticated)
+            .and_then(crate::sender_policy::identity)?
+            .to_owned();
+        let quota = submission_quotas.limits(&identity, ctx.submission_quota());
+        Some((submission_quotas, identity, quota))
+    }
+
+    /// Reserve the recipient in the submission quota of the authenticated user,
+    /// returns the reply if the user has exhausted it.
+    pub(super) fn reserve_submission_quota(&mut self, args: &RcptToArgs) -> Option<Reply> {
+        let (submission_quotas, identity, quota) = self.submission_quota()?;
+
+        match submission_quotas.reserve(&identity, quota) {
+            Verdict::Accept => {
+                self.submission_reserved += 1;
+                None
+            }
+            Verdict::Burst => {
+                tracing::warn!(%identity, burst = quota.burst, "Submission rate exceeded.");
+                Some(submission_quotas.burst_reply().clone())
+            }
+            Verdict::Sustained => {
+                tracing::warn!(
+                    %identity,
+                    sustained = quota.sustained,
+                    "Submission quota exceeded, the account may be compromised."
+                );
+                let reply = "550 5.7.1 Submission quota exceeded\r\n"
+                    .parse::<Reply>()
+                    .unwrap();
+                self.emit_policy_event(Kind::QuotaExceeded, &reply, |event| {
+                    event
+                        .with_recipient(&args.forward_path)
+                        .with_raw_rcpt(&args.raw)
+                });
+                Some(reply)
+            }
+        }
+    }
+
+    /// Give back up to `recipients` of the recipients reserved in the submission quota,
+    /// when the transaction is reset or aborted.
+    pub(super) fn release_submission_quota(&mut self, recipients: usize) {
+        let recipients = recipients.min(self.submission_reserved);
+        if recipients == 0 {
+            return;
+        }
+        self.submission_reserved -= recipients;
+
+        if let Some((submission_quotas, identity, quota)) = self.submission_quota() {
+            submission_quotas.release(&identity, quota, recipients);
+        }
+    }
+
+    /// Reset the transaction, on `RSET`, `HELO` and `EHLO`.
+    ///
+    /// Returns the `421` closing the connection once `server.smtp.error.churn_count`
+    /// transactions have been abandoned.
+    pub(super) fn reset_transaction(&mut self, ctx: &mut ReceiverContext) -> Option<Reply> {
+        let sender_churn = self
+            .state
+            .context()
+            .write()
+            .expect("state poisoned")
+            .reset_transaction();
+
+        self.state_internal = None;
+        self.release_submission_quota(usize::MAX);
+
+        let churn_max = self.config.server.smtp.error.churn_count;
+        if churn_max != -1 && sender_churn >= churn_max {
+            tracing::warn!(sender_churn, "Closing, too many transactions reset.");
+            let reply = "421 4.7.0 Too many transactions reset, closing connection\r\n"
+                .parse::<Reply>()
+                .unwrap();
+            self.emit_policy_event(Kind::TransactionChurn, &reply, |event| event);
+            ctx.deny();
+            return Some(reply);
+        }
+
+        None
+    }
+
     /// Callback to provided to [`vsmtp_protocol::Receiver`] to handle the connection
     pub fn on_accept(
         AcceptArgs {
@@ -164,10 +336,7 @@ where
             uuid,
         );
 
-        if rule_engine
-            .get_delegation_directive_bound_to_address(server_addr)
-            .is_some()
-        {
+        if rule_engine.has_delegation_bound_to_address(server_addr) {
             state
                 .context()
                 .write()
@@ -176,13 +345,72 @@ where
             skipped = Some(Status::DelegationResult);
         }
 
+        let mut access_tags = vec![];
+        match rule_engine.access_lists().check_client(client_addr.ip()) {
+            Some(AccessVerdict::Refuse(reply)) => {
+                tracing::warn!(client = %client_addr.ip(), "Client refused by the access lists.");
+                PolicyEvent::new(
+                    Kind::AccessList,
+                    &state.context().read().expect("state poisoned"),
+                )
+                .with_reply(&reply)
+                .emit();
+                ctx.deny();
+                return (
+                    Self {
+                        config,
+                        rustls_config,
+                        rule_engine,
+                        queue_manager,
+                        message_parser_factory,
+                        emitter,
+                        state,
+                        state_internal: None,
+                        tls_statistics: None,
+                        connection_slot: None,
+                        access_tags,
+                        skipped,
+                        session: SessionCounters {
+                            refused: true,
+                            ..Default::default()
+                        },
+                        session_statistics: std::sync::Arc::default(),
+                        submission_quotas: None,
+                        submission_reserved: 0,
+                        on_disconnect: None,
+                    },
+                    ctx,
+                    Some(reply),
+                );
+            }
+            Some(AccessVerdict::Tag(tag)) => access_tags.push(tag),
+            None => (),
+        }
+
         let reply = match rule_engine.run_when(&state, &mut skipped, ExecutionStage::Connect) {
-            // FIXME: do we really want to let the end-user override the EHLO/HELO reply?
-            Status::Faccept(reply) | Status::Accept(reply) => reply,
-            Status::Quarantine(_) | Status::Next | Status::DelegationResult => {
-                format!("220 {} Service ready\r\n", config.server.name)
-                    .parse::<Reply>()
-                    .expect("valid")
+            status @ (Status::Faccept(_)
+            | Status::Accept(_)
+            | Status::Quarantine(_)
+            | Status::Defer(_)
+            | Status::Next
+            | Status::DelegationResult) => {
+                // NOTE: the reply given to `accept` is not the greeting,
+                //       `ctx::set_banner` customizes it.
+                if let Status::Faccept(reply) | Status::Accept(reply) = &status {
+                    if reply.code().value() != 250 {
+                        tracing::warn!(
+                            %reply,
+                            "The reply given to `accept` at the connect stage is ignored."
+                        );
+                    }
+                }
+                greeting(
+                    config
+                        .server
+                        .disclosed_name()
+                        .unwrap_or(&config.server.name),
+                    state.context().read().expect("state poisoned").banner(),
+                )
             }
             Status::Deny(reply) | Status::Reject(reply) => {
                 ctx.deny();
@@ -196,7 +424,18 @@ where
                         emitter,
                         state,
                         state_internal: None,
+                        tls_statistics: None,
+                        connection_slot: None,
+                        access_tags,
                         skipped,
+                        session: SessionCounters {
+                            refused: true,
+                            ..Default::default()
+                        },
+                        session_statistics: std::sync::Arc::default(),
+                        submission_quotas: None,
+                        submission_reserved: 0,
+                        on_disconnect: None,
                     },
                     ctx,
                     Some(reply),
@@ -225,7 +464,18 @@ where
                     emitter,
                     state,
                     state_internal: None,
+                    tls_statistics: None,
+                    connection_slot: None,
+                    access_tags,
                     skipped,
+                    session: SessionCounters {
+                        tls_pending: true,
+                        ..Default::default()
+                    },
+                    session_statistics: std::sync::Arc::default(),
+                    submission_quotas: None,
+                    submission_reserved: 0,
+                    on_disconnect: None,
                 },
                 ctx,
                 None,
@@ -242,13 +492,81 @@ where
                 emitter,
                 state,
                 state_internal: None,
+                tls_statistics: None,
+                connection_slot: None,
+                access_tags,
                 skipped,
+                session: SessionCounters::default(),
+                session_statistics: std::sync::Arc::default(),
+                submission_quotas: None,
+                submission_reserved: 0,
+                on_disconnect: None,
             },
             ctx,
             Some(reply),
         )
     }
 
+    /// Account the messages received in the statistics of the use of TLS.
+    #[must_use]
+    pub fn with_tls_statistics(
+        mut self,
+        tls_statistics: Option<std::sync::Arc<crate::TlsStatistics>>,
+    ) -> Self {
+        self.tls_statistics = tls_statistics;
+        self
+    }
+
+    /// Account the category of the session in the statistics when the connection is closed,
+    /// and the messages refused because the spool could not be written.
+    #[must_use]
+    pub fn with_session_statistics(
+        mut self,
+        session_statistics: std::sync::Arc<crate::SessionStatistics>,
+    ) -> Self {
+        self.session_statistics = session_statistics;
+        self
+    }
+
+    /// Limit the recipients submitted by the authenticated users.
+    #[must_use]
+    pub fn with_submission_quotas(
+        mut self,
+        submission_quotas: Option<std::sync::Arc<crate::SubmissionQuotas>>,
+    ) -> Self {
+        self.submission_quotas = submission_quotas;
+        self
+    }
+
+    /// Hand the session over to `on_disconnect` when the handler is dropped, for the
+    /// rules of the `disconnect` stage to run once the connection is closed.
+    #[must_use]
+    pub(crate) fn with_disconnect(
+        mut self,
+        on_disconnect: tokio::sync::oneshot::Sender<super::handler::Disconnect>,
+    ) -> Self {
+        self.on_disconnect = Some(on_disconnect);
+        self
+    }
+
+    /// Count the connection on the identity of the client once authenticated.
+    #[must_use]
+    pub fn with_connection_slot(mut self, connection_slot: Option<crate::ConnectionSlot>) -> Self {
+        self.connection_slot = connection_slot;
+        self
+    }
+
+    /// Copy the number of errors of the connection to the contexts read by the rules.
+    pub(super) fn update_error_count(&self, ctx: &ReceiverContext) {
+        for state in std::iter::once(&self.state).chain(&self.state_internal) {
+            state
+                .context()
+                .write()
+                .expect("state poisoned")
+                .set_error_count(ctx.error_count());
+        }
+    }
+
     pub(super) fn generate_sasl_callback_inner(&self) -> CallbackWrap {
         CallbackWrap(Box::new(RsaslSessionCallback {
             rule_engine: self.rule_engine.clone(),
@@ -266,10 +584,34 @@ where
     ) -> Reply {
         let server_name = sni.map(|sni| sni.parse().unwrap());
 
-        self.state
-            .context()
-            .write()
-            .expect("state poisoned")
+        // NOTE: the certificates are only requested, and verified against the
+        //       trusted authorities, on the listeners configured for it.
+        let subject = self
+            .config
+            .server
+            .tls
+            .as_ref()
+            .and_then(|tls| tls.client_auth.as_ref())
+            .and(peer_certificates.as_ref())
+            .and_then(|certificates| certificates.first())
+            .and_then(certificate_subject);
+
+        // NOTE: the handshake cannot be refused anymore, a certificate over the limit of its
+        //       subject leaves the client anonymous instead.
+        let subject = subject.filter(|subject| {
+            let accepted = self
+                .connection_slot
+                .as_mut()
+                .map_or(true, |slot| slot.authenticate(subject));
+            if !accepted {
+                tracing::warn!(subject, "Connection count max reached for the subject.");
+            }
+            accepted
+        });
+
+        let state = self.state.context();
+        let mut state = state.write().expect("state poisoned");
+        state
             .to_secured(
                 server_name.clone(),
                 protocol_version,
@@ -279,12 +621,24 @@ where
             )
             .expect("bad state");
 
-        format!(
-            "220 {} Service ready\r\n",
-            server_name.unwrap_or_else(|| self.config.server.name.clone())
+        if let Some(subject) = subject {
+            tracing::info!(subject, "Client authenticated by its certificate.");
+            state
+                .with_credentials(Credentials::Certificate { subject })
+                .expect("bad state");
+            state.auth_mut().expect("bad state").authenticated = true;
+        }
+
+        // NOTE: the certificate has been selected with the name requested by the client,
+        //       the disclosed name only replaces it in the greeting.
+        greeting(
+            self.config
+                .server
+                .disclosed_name()
+                .or(server_name.as_ref())
+                .unwrap_or(&self.config.server.name),
+            state.banner(),
         )
-        .parse::<Reply>()
-        .unwrap()
     }
 
     pub(super) fn on_starttls_inner(&mut self, ctx: &mut ReceiverContext) -> Reply {
@@ -304,6 +658,7 @@ where
                     .parse::<Reply>()
                     .unwrap(),
                 |config| {
+                    self.session.tls_pending = true;
                     ctx.upgrade_tls(config.clone(), std::time::Duration::from_secs(2));
                     "220 TLS go ahead\r\n".parse::<Reply>().unwrap()
                 },
@@ -333,6 +688,18 @@ where
                 );
             }
 
+            if args.mechanism == Mechanism::External
+                && (!auth.mechanisms.contains(&Mechanism::External)
+                    || client_certificate(&self.state.context().read().expect("state poisoned"))
+                        .is_none())
+            {
+                return Some(
+                    "504 5.5.4 Mechanism not available without a client certificate\r\n"
+                        .parse::<Reply>()
+                        .unwrap(),
+                );
+            }
+
             ctx.authenticate(args.mechanism, args.initial_response);
 
             None
@@ -348,13 +715,27 @@ where
     ) -> Reply {
         match result {
             Ok(()) => {
-                self.state
-                    .context()
-                    .write()
-                    .expect("state poisoned")
-                    .auth_mut()
-                    .expect("bad state")
-                    .authenticated = true;
+                let state = self.state.context();
+                let mut state = state.write().expect("state poisoned");
+                let auth = state.auth_mut().expect("bad state");
+
+                // NOTE: the anonymous mechanism keeps the connection counted on its address.
+                if let (Some(slot), Some(Credentials::Verify { authid, .. })) =
+                    (&mut self.connection_slot, &auth.credentials)
+                {
+                    if !slot.authenticate(authid) {
+                        tracing::warn!(authid, "Connection count max reached for the user.");
+                        let reply = "421 4.7.0 Too many connections for this user, closing\r\n"
+                            .parse::<Reply>()
+                            .unwrap();
+                        PolicyEvent::new(Kind::ConnectionLimit, &state)
+                            .with_reply(&reply)
+                            .emit();
+                        ctx.deny();
+                        return reply;
+                    }
+                }
+                auth.authenticated = true;
 
                 "235 2.7.0 Authentication succeeded\r\n"
                     .parse::<Reply>()
@@ -405,7 +786,13 @@ where
                     .parse::<Reply>()
                     .unwrap()
             }
-            Err(AuthError::IO(e)) => todo!("{e}"),
+            Err(AuthError::IO(e)) => {
+                tracing::warn!(%e, "auth error");
+                ctx.deny();
+                "454 4.7.0 Temporary authentication failure\r\n"
+                    .parse::<Reply>()
+                    .unwrap()
+            }
             Err(AuthError::ConfigError(rsasl::prelude::SASLError::NoSharedMechanism)) => {
                 ctx.deny();
                 "504 5.5.4 Mechanism is not supported\r\n"
@@ -429,7 +816,7 @@ where
             .run_when(&self.state, &mut self.skipped, ExecutionStage::Helo)
         {
             Status::Faccept(reply) | Status::Accept(reply) => reply,
-            Status::Quarantine(_) | Status::Next | Status::DelegationResult => {
+            Status::Quarantine(_) | Status::Defer(_) | Status::Next | Status::DelegationResult => {
                 "250 Ok\r\n".parse::<Reply>().unwrap()
             }
             Status::Deny(reply) | Status::Reject(reply) => {
@@ -458,10 +845,14 @@ where
             .run_when(&self.state, &mut self.skipped, ExecutionStage::Helo)
         {
             Status::Faccept(reply) | Status::Accept(reply) => reply,
-            Status::Quarantine(_) | Status::Next | Status::DelegationResult => {
+            Status::Quarantine(_) | Status::Defer(_) | Status::Next | Status::DelegationResult => {
                 let ctx = vsl_ctx.read().expect("state poisoned");
 
-                build_ehlo_reply(&self.state.server().config, ctx.is_secured())
+                build_ehlo_reply(
+                    &self.state.server().config,
+                    ctx.is_secured(),
+                    client_certificate(&ctx).is_some(),
+                )
             }
             Status::Deny(reply) | Status::Reject(reply) => {
                 ctx.deny();
@@ -519,6 +910,28 @@ impl RsaslSessionCallback {
 
         Ok(())
     }
+
+    /// The credentials of the `EXTERNAL` mechanism: the identity requested by the client,
+    /// and the certificate it presented in the TLS handshake.
+    fn external_credentials(
+        &self,
+        context: &rsasl::callback::Context<'_>,
+    ) -> Result<Credentials, vsmtp_common::auth::Error> {
+        let state = self.state.context();
+        let state = state.read().expect("state poisoned");
+        let certificate =
+            client_certificate(&state).ok_or(vsmtp_common::auth::Error::MissingField)?;
+
+        Ok(Credentials::External {
+            authzid: context
+                .get_ref::<rsasl::property::AuthzId>()
+                .unwrap_or_default()
+                .to_owned(),
+            subject: certificate_subject(certificate)
+                .ok_or(vsmtp_common::auth::Error::MissingField)?,
+            alt_names: certificate_alt_names(certificate),
+        })
+    }
 }
 
 impl rsasl::callback::SessionCallback for RsaslSessionCallback {
@@ -537,7 +950,12 @@ impl rsasl::callback::SessionCallback for RsaslSessionCallback {
         context: &rsasl::callback::Context<'_>,
         validate: &mut rsasl::validate::Validate<'_>,
     ) -> Result<(), rsasl::validate::ValidationError> {
-        let credentials = Credentials::try_from((session_data, context)).map_err(|e| match e {
+        let credentials = if session_data.mechanism().mechanism == Mechanism::External.as_ref() {
+            self.external_credentials(context)
+        } else {
+            Credentials::try_from((session_data, context))
+        }
+        .map_err(|e| match e {
             vsmtp_common::auth::Error::MissingField => {
                 rsasl::validate::ValidationError::MissingRequiredProperty
             }
@@ -584,7 +1002,7 @@ mod tests {
             .with_system_dns()
             .without_virtual_entries()
             .validate();
-        let reply = build_ehlo_reply(&config, true);
+        let reply = build_ehlo_reply(&config, true, false);
         assert_eq!(reply.code().value(), 250);
         assert_eq!(
             reply.to_string(),
@@ -598,7 +1016,6 @@ mod tests {
             ]
             .join("\r\n")
         );
-        // build_ehlo_reply(config: &vsmtp_config::Config, is_transaction_secured: bool)
     }
 
     #[test]
@@ -634,7 +1051,7 @@ mod tests {
             .with_system_dns()
             .without_virtual_entries()
             .validate();
-        let reply = build_ehlo_reply(&config, true);
+        let reply = build_ehlo_reply(&config, true, false);
         assert_eq!(reply.code().value(), 250);
         assert_eq!(
             reply.to_string(),
@@ -646,6 +1063,5 @@ mod tests {
             ]
             .join("\r\n")
         );
-        // build_ehlo_reply(config: &vsmtp_config::Config, is_transaction_secured: bool)
     }
 }
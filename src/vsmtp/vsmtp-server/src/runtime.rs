@@ -14,7 +14,10 @@
  * this program. If not, see https://www.gnu.org/licenses/.
  *
 */
-use crate::{delivery, scheduler, working, Server};
+use crate::{
+    delivery, scheduler, working, Admin, DeliveryTimings, Health, QueueMonitor, Server,
+    SessionStatistics, SourceIpReputation, SubmissionQuotas, TlsStatistics,
+};
 use anyhow::Context;
 use vsmtp_common::transport::{AbstractTransport, DeserializerFn, DESERIALIZER_SYMBOL_NAME};
 use vsmtp_config::{Config, DnsResolvers};
@@ -123,7 +126,7 @@ fn get_transport_deserializer(libs: &[libloading::Library]) -> Vec<DeserializerF
 ///
 /// # Errors
 ///
-#[allow(clippy::module_name_repetitions)]
+#[allow(clippy::module_name_repetitions, clippy::too_many_lines)]
 pub fn start_runtime(
     config: Config,
     sockets: (
@@ -146,6 +149,51 @@ pub fn start_runtime(
     );
     let transport_deserializer = get_transport_deserializer(&libs);
 
+    let tls_statistics = config.server.tls_statistics.as_ref().map(|parameters| {
+        std::sync::Arc::new(TlsStatistics::new(
+            parameters,
+            config.app.dirpath.join("tls-statistics.json"),
+        ))
+    });
+
+    let submission_quotas = config
+        .server
+        .esmtp
+        .auth
+        .as_ref()
+        .and_then(|auth| auth.quotas.as_ref())
+        .map(|parameters| {
+            std::sync::Arc::new(SubmissionQuotas::new(
+                parameters,
+                config.app.dirpath.join("submission-quotas.json"),
+            ))
+        });
+
+    let session_statistics = std::sync::Arc::new(SessionStatistics::default());
+    let delivery_timings = std::sync::Arc::new(DeliveryTimings::default());
+
+    let mut health = Health::new(&config.server.queues.dirpath)
+        .with_session_statistics(session_statistics.clone())
+        .with_delivery_timings(delivery_timings.clone());
+    if let Some(parameters) = &config.server.health {
+        health = health.with_spool_free_space_min(parameters.spool_free_space_min);
+    }
+    if let Some(tls_statistics) = &tls_statistics {
+        health = health.with_tls_statistics(tls_statistics.clone());
+    }
+    let health_listener = config
+        .server
+        .health
+        .as_ref()
+        .map(|health| crate::socket_bind_anyhow(health.addr))
+        .transpose()?;
+    let admin_listener = config
+        .server
+        .admin
+        .as_ref()
+        .map(|admin| crate::socket_bind_unix(&admin.socket, config.server.system.group.gid()))
+        .transpose()?;
+
     let mut error_handler = tokio::sync::mpsc::channel::<()>(3);
 
     let (emitter, working_rx, delivery_rx) = scheduler::init(
@@ -162,12 +210,72 @@ pub fn start_runtime(
         DnsResolvers::from_config(&config).context("could not initialize dns")?,
     );
 
+    // NOTE: the queues are only read when monitored, the ages being tracked by the writes after that.
+    let queue_monitor = config
+        .server
+        .queues
+        .monitor
+        .as_ref()
+        .and_then(|parameters| {
+            queue_manager.load_ages();
+            vqueue::GenericQueueManager::get_ages(queue_manager.as_ref())
+                .map(|ages| std::sync::Arc::new(QueueMonitor::new(ages.clone(), parameters)))
+        });
+    if let Some(queue_monitor) = &queue_monitor {
+        health = health.with_queue_monitor(queue_monitor.clone());
+    }
+
     let rule_engine = std::sync::Arc::new(RuleEngine::new(
         config.clone(),
         resolvers,
         queue_manager.clone(),
     )?);
 
+    // NOTE: the pool checked is the one of the deliveries, see `ServerAPI::delivery`.
+    let source_ip_reputation = (!config
+        .server
+        .queues
+        .delivery
+        .source_ips
+        .blocklists
+        .is_empty())
+    .then(|| {
+        let server = rule_engine.srv();
+        std::sync::Arc::new(SourceIpReputation::new(
+            &config.server.queues.delivery.source_ips.blocklists,
+            server.resolvers.get_resolver_root(),
+            server.delivery.source_ips().clone(),
+        ))
+    });
+    if let Some(source_ip_reputation) = &source_ip_reputation {
+        health = health.with_source_ip_reputation(source_ip_reputation.clone());
+    }
+
+    let health = std::sync::Arc::new(
+        health
+            .with_rule_statistics(rule_engine.statistics())
+            .with_lookup_cache(rule_engine.lookups())
+            .with_scheduler(emitter.clone()),
+    );
+    health.set_rule_engine_ready();
+
+    let mut admin = Admin::new(
+        queue_manager.clone(),
+        rule_engine.clone(),
+        emitter.clone(),
+        health.clone(),
+    );
+    if let Some(tls_statistics) = &tls_statistics {
+        admin = admin.with_tls_statistics(tls_statistics.clone());
+    }
+    if let Some(submission_quotas) = &submission_quotas {
+        admin = admin.with_submission_quotas(submission_quotas.clone());
+    }
+    if let Some(source_ip_reputation) = &source_ip_reputation {
+        admin = admin.with_source_ip_reputation(source_ip_reputation.clone());
+    }
+    let admin = std::sync::Arc::new(admin);
+
     let _tasks_delivery = init_runtime(
         error_handler.0.clone(),
         "delivery",
@@ -177,6 +285,7 @@ pub fn start_runtime(
             rule_engine.clone(),
             queue_manager.clone(),
             delivery_rx,
+            delivery_timings.clone(),
         ),
         timeout,
     )?;
@@ -190,27 +299,125 @@ pub fn start_runtime(
             queue_manager.clone(),
             emitter.clone(),
             working_rx,
+            delivery_timings,
         ),
         timeout,
     )?;
 
+    let rule_engine_sig = rule_engine.clone();
+    let health_receiver = health.clone();
+    let tls_statistics_sig = tls_statistics.clone();
+    let submission_quotas_sig = submission_quotas.clone();
     let _tasks_receiver = init_runtime(
         error_handler.0.clone(),
         "receiver",
         config.server.system.thread_pool.receiver.get(),
         async move {
+            if let Some(listener) = health_listener {
+                tokio::spawn(health_receiver.clone().serve(listener));
+            }
+            if let Some(listener) = admin_listener {
+                tokio::spawn(admin.serve(listener));
+            }
+            if let (Some(tls_statistics), Some(parameters)) =
+                (&tls_statistics, &config.server.tls_statistics)
+            {
+                tokio::spawn(
+                    tls_statistics
+                        .clone()
+                        .persist_periodically(parameters.persist_period),
+                );
+            }
+            if let Some(submission_quotas) = &submission_quotas {
+                tokio::spawn(submission_quotas.clone().persist_periodically());
+            }
+            if let Some(source_ip_reputation) = source_ip_reputation {
+                tokio::spawn(
+                    source_ip_reputation
+                        .check_periodically(config.server.queues.delivery.source_ips.check_period),
+                );
+            }
+            if let (Some(queue_monitor), Some(parameters)) =
+                (queue_monitor, &config.server.queues.monitor)
+            {
+                tokio::spawn(queue_monitor.check_periodically(parameters.period));
+            }
+            if let Some(parameters) = &config.server.access_lists {
+                let (access_lists, period) = (rule_engine.access_lists(), parameters.reload_period);
+                tokio::spawn(async move {
+                    let mut interval = tokio::time::interval(period);
+                    loop {
+                        interval.tick().await;
+                        access_lists.reload_if_changed();
+                    }
+                });
+            }
+
+            if let Some((aliases, parameters)) =
+                rule_engine.aliases().zip(config.server.aliases.as_ref())
+            {
+                let period = parameters.reload_period;
+                tokio::spawn(async move {
+                    let mut interval = tokio::time::interval(period);
+                    loop {
+                        interval.tick().await;
+                        aliases.reload_if_changed();
+                    }
+                });
+            }
+
+            if let Some((recipients, parameters)) = rule_engine
+                .recipients()
+                .zip(config.server.recipients.as_ref())
+            {
+                let period = parameters.reload_period;
+                tokio::spawn(async move {
+                    let mut interval = tokio::time::interval(period);
+                    loop {
+                        interval.tick().await;
+                        recipients.reload_if_changed();
+                    }
+                });
+            }
+
+            if let Some((lookup_tables, parameters)) = rule_engine
+                .lookup_tables()
+                .zip(config.server.lookup_tables.as_ref())
+            {
+                let period = parameters.reload_period;
+                tokio::spawn(async move {
+                    let mut interval = tokio::time::interval(period);
+                    loop {
+                        interval.tick().await;
+                        lookup_tables.reload_if_changed();
+                    }
+                });
+            }
+
             let server = match Server::new(
                 config.clone(),
                 rule_engine.clone(),
                 queue_manager.clone(),
                 emitter,
             ) {
-                Ok(server) => server,
+                Ok(server) => {
+                    let mut server = server
+                        .with_health(health_receiver.clone())
+                        .with_session_statistics(session_statistics);
+                    if let Some(submission_quotas) = submission_quotas {
+                        server = server.with_submission_quotas(submission_quotas);
+                    }
+                    match tls_statistics {
+                        Some(tls_statistics) => server.with_tls_statistics(tls_statistics),
+                        None => server,
+                    }
+                }
                 Err(error) => {
                     tracing::error!(%error, "Receiver build failure.");
                     return;
                 }
             };
+            health_receiver.set_listening();
             if let Err(error) = server.listen(sockets).await {
                 tracing::error!(%error, "Receiver failure.");
             }
@@ -224,10 +431,34 @@ pub fn start_runtime(
         signal_hook::consts::SIGTERM,
         // Ctrl+C on a terminal
         signal_hook::consts::SIGINT,
+        // Send by `systemctl reload`, reload the datasets of the rule engine
+        // and reset the statistics of the rules
+        signal_hook::consts::SIGHUP,
     ])?;
     let _signal_handler = std::thread::spawn(move || {
         for sig in signals.forever() {
+            if sig == signal_hook::consts::SIGHUP {
+                match rule_engine_sig.reload_datasets() {
+                    Ok(()) => {
+                        tracing::info!("Datasets reloaded.");
+                        rule_engine_sig.statistics().reset();
+                    }
+                    Err(error) => tracing::error!(%error, "Datasets reload failure."),
+                }
+                continue;
+            }
             tracing::warn!(signal = sig, "Stopping vSMTP server.");
+            health.set_shutting_down();
+            if let Some(tls_statistics) = &tls_statistics_sig {
+                if let Err(error) = tls_statistics.persist() {
+                    tracing::warn!(%error, "TLS statistics persistence failure.");
+                }
+            }
+            if let Some(submission_quotas) = &submission_quotas_sig {
+                if let Err(error) = submission_quotas.persist() {
+                    tracing::warn!(%error, "Submission quotas persistence failure.");
+                }
+            }
             error_handler_sig
                 .blocking_send(())
                 .expect("failed to send terminating instruction");
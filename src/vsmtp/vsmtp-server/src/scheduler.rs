@@ -17,36 +17,167 @@
 
 use crate::ProcessMessage;
 
+/// A channel between two parts of the software, and the messages being handled by the receiving part.
+///
+/// The capacity bounds both the messages waiting in the channel and the messages handled
+/// concurrently by the receiving part: once `capacity` messages are in progress, the next
+/// ones stay in the channel, and once the channel is full, the sender awaits a free slot.
+/// A slow delivery stage thus slows the working stage, which in turn delays the reply to
+/// the end of the `DATA` command.
+#[derive(Debug)]
+struct Channel {
+    sender: tokio::sync::mpsc::Sender<ProcessMessage>,
+    in_progress: std::sync::Arc<tokio::sync::Semaphore>,
+    capacity: usize,
+    panics: std::sync::Arc<std::sync::atomic::AtomicU64>,
+}
+
+impl Channel {
+    fn new(capacity: usize) -> (Self, Receiver) {
+        let (sender, inner) = tokio::sync::mpsc::channel(capacity);
+        let in_progress = std::sync::Arc::new(tokio::sync::Semaphore::new(capacity));
+        let panics = std::sync::Arc::new(std::sync::atomic::AtomicU64::new(0));
+
+        (
+            Self {
+                sender,
+                in_progress: in_progress.clone(),
+                capacity,
+                panics: panics.clone(),
+            },
+            Receiver {
+                inner,
+                in_progress,
+                panics,
+            },
+        )
+    }
+
+    async fn send(&self, message: ProcessMessage) -> std::io::Result<()> {
+        let permit = match self.sender.try_reserve() {
+            Ok(permit) => permit,
+            Err(tokio::sync::mpsc::error::TrySendError::Full(())) => {
+                tracing::debug!(
+                    capacity = self.capacity,
+                    "Channel full, waiting for a slot."
+                );
+                self.sender
+                    .reserve()
+                    .await
+                    .map_err(|_err| std::io::Error::from(std::io::ErrorKind::ConnectionAborted))?
+            }
+            Err(tokio::sync::mpsc::error::TrySendError::Closed(())) => {
+                return Err(std::io::Error::from(std::io::ErrorKind::ConnectionAborted))
+            }
+        };
+
+        permit.send(message);
+        Ok(())
+    }
+
+    const fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Number of messages waiting in the channel.
+    fn queued(&self) -> usize {
+        self.sender.max_capacity() - self.sender.capacity()
+    }
+
+    /// Number of messages handled by the receiving part.
+    fn in_progress(&self) -> usize {
+        self.capacity - self.in_progress.available_permits()
+    }
+}
+
 /// This instance can emit message to the different part of the software.
+#[derive(Debug)]
 pub struct Emitter {
-    working: tokio::sync::mpsc::Sender<ProcessMessage>,
-    delivery: tokio::sync::mpsc::Sender<ProcessMessage>,
+    working: Channel,
+    delivery: Channel,
 }
 
 impl Emitter {
+    /// Send a message to the delivery, waiting for a free slot if the channel is full.
     #[tracing::instrument(skip(self))]
     pub(crate) async fn send_to_delivery(&self, message: ProcessMessage) -> std::io::Result<()> {
-        match self.delivery.send(message).await {
-            Ok(()) => Ok(()),
-            Err(_err) => Err(std::io::Error::from(std::io::ErrorKind::ConnectionAborted)),
-        }
+        self.delivery.send(message).await
     }
 
+    /// Send a message to the working, waiting for a free slot if the channel is full.
     #[tracing::instrument(skip(self))]
     pub(crate) async fn send_to_working(&self, message: ProcessMessage) -> std::io::Result<()> {
-        match self.working.send(message).await {
-            Ok(()) => Ok(()),
-            Err(_err) => Err(std::io::Error::from(std::io::ErrorKind::ConnectionAborted)),
+        self.working.send(message).await
+    }
+
+    /// Is a channel full, the sender awaiting a free slot ?
+    #[must_use]
+    pub fn is_saturated(&self) -> bool {
+        [&self.working, &self.delivery]
+            .into_iter()
+            .any(|channel| channel.queued() == channel.capacity)
+    }
+
+    /// Produce the occupancy of the channels in the Prometheus text format.
+    #[must_use]
+    pub fn metrics(&self) -> String {
+        let mut output = String::new();
+
+        let gauges: [(&str, &str, fn(&Channel) -> usize); 3] = [
+            (
+                "vsmtp_channel_capacity",
+                "Maximum number of messages waiting in the channel, and handled concurrently.",
+                Channel::capacity,
+            ),
+            (
+                "vsmtp_channel_queued",
+                "Messages waiting in the channel.",
+                Channel::queued,
+            ),
+            (
+                "vsmtp_channel_in_progress",
+                "Messages received from the channel and being handled.",
+                Channel::in_progress,
+            ),
+        ];
+
+        for (name, help, value) in gauges {
+            output.push_str(&format!("# HELP {name} {help}\n# TYPE {name} gauge\n"));
+            for (stage, channel) in [("working", &self.working), ("delivery", &self.delivery)] {
+                output.push_str(&format!("{name}{{stage=\"{stage}\"}} {}\n", value(channel)));
+            }
+        }
+
+        let name = "vsmtp_processing_panics_total";
+        output.push_str(&format!(
+            "# HELP {name} Messages whose processing panicked, moved to the dead queue.\n# TYPE {name} counter\n"
+        ));
+        for (stage, channel) in [("working", &self.working), ("delivery", &self.delivery)] {
+            output.push_str(&format!(
+                "{name}{{stage=\"{stage}\"}} {}\n",
+                channel.panics.load(std::sync::atomic::Ordering::Relaxed)
+            ));
         }
+        output
     }
 }
 
 /// This instance can receive message from the different part of the software.
 pub struct Receiver {
     inner: tokio::sync::mpsc::Receiver<ProcessMessage>,
+    in_progress: std::sync::Arc<tokio::sync::Semaphore>,
+    panics: std::sync::Arc<std::sync::atomic::AtomicU64>,
 }
 
+/// A slot of the receiving part, released when the message has been handled.
+pub type Slot = tokio::sync::OwnedSemaphorePermit;
+
 impl Receiver {
+    /// Counter of the messages whose processing panicked, see [`crate::watchdog`].
+    pub(crate) fn panics(&self) -> std::sync::Arc<std::sync::atomic::AtomicU64> {
+        self.panics.clone()
+    }
+
     /// Produce a stream of message.
     pub fn as_stream(&mut self) -> impl tokio_stream::Stream<Item = ProcessMessage> + '_ {
         async_stream::stream! {
@@ -55,25 +186,95 @@ impl Receiver {
             }
         }
     }
+
+    /// Produce a stream of message, each with the slot to hold while it is handled.
+    /// No message is received while all the slots are taken, leaving them in the channel.
+    pub fn as_bounded_stream(
+        &mut self,
+    ) -> impl tokio_stream::Stream<Item = (ProcessMessage, Slot)> + '_ {
+        async_stream::stream! {
+            loop {
+                // the semaphore is never closed.
+                let slot = match self.in_progress.clone().acquire_owned().await {
+                    Ok(slot) => slot,
+                    Err(_closed) => break,
+                };
+                match self.inner.recv().await {
+                    Some(message) => yield (message, slot),
+                    None => break,
+                }
+            }
+        }
+    }
 }
 
 /// This instance is responsible of the communication between the different part of the software.
 ///
 /// **receiver**  <->  **working**  <->  **delivery**
+///
+/// A size bounds both the messages waiting in the channel and the messages handled
+/// concurrently by the receiving part, the sender awaiting once both are reached.
 #[must_use]
 pub fn init(
     working_channel_size: usize,
     delivery_channel_size: usize,
 ) -> (std::sync::Arc<Emitter>, Receiver, Receiver) {
-    let (working_tx, working_rx) = tokio::sync::mpsc::channel(working_channel_size);
-    let (delivery_tx, delivery_rx) = tokio::sync::mpsc::channel(delivery_channel_size);
+    let (working, working_rx) = Channel::new(working_channel_size);
+    let (delivery, delivery_rx) = Channel::new(delivery_channel_size);
 
     (
-        std::sync::Arc::new(Emitter {
-            working: working_tx,
-            delivery: delivery_tx,
-        }),
-        Receiver { inner: working_rx },
-        Receiver { inner: delivery_rx },
+        std::sync::Arc::new(Emitter { working, delivery }),
+        working_rx,
+        delivery_rx,
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio_stream::StreamExt;
+
+    #[tokio::test]
+    async fn backpressure() {
+        let (emitter, _working, mut delivery) = init(1, 1);
+
+        emitter
+            .send_to_delivery(ProcessMessage::new(uuid::Uuid::new_v4()))
+            .await
+            .unwrap();
+        assert!(emitter
+            .metrics()
+            .contains("vsmtp_channel_queued{stage=\"delivery\"} 1\n"));
+
+        // the channel is full, the sender awaits.
+        let second = uuid::Uuid::new_v4();
+        let send = emitter.send_to_delivery(ProcessMessage::new(second));
+        tokio::pin!(send);
+        assert!(
+            tokio::time::timeout(std::time::Duration::from_millis(50), &mut send)
+                .await
+                .is_err()
+        );
+
+        let mut stream = Box::pin(delivery.as_bounded_stream());
+        let (_first, slot) = stream.next().await.unwrap();
+        send.await.unwrap();
+
+        let metrics = emitter.metrics();
+        assert!(metrics.contains("vsmtp_channel_queued{stage=\"delivery\"} 1\n"));
+        assert!(metrics.contains("vsmtp_channel_in_progress{stage=\"delivery\"} 1\n"));
+        assert!(metrics.contains("vsmtp_channel_capacity{stage=\"working\"} 1\n"));
+        assert!(metrics.contains("vsmtp_processing_panics_total{stage=\"delivery\"} 0\n"));
+
+        // all the slots are taken, the second message stays in the channel.
+        assert!(
+            tokio::time::timeout(std::time::Duration::from_millis(50), stream.next())
+                .await
+                .is_err()
+        );
+
+        drop(slot);
+        let (message, _slot) = stream.next().await.unwrap();
+        assert_eq!(*message.as_ref(), second);
+    }
+}
@@ -0,0 +1,524 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2022 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use vsmtp_common::{auth::Credentials, Address, AuthProperties, ContextFinished, Reply};
+use vsmtp_config::{
+    field::{FieldServerSMTPAuthSenders, FromHeaderPolicy},
+    Config,
+};
+use vsmtp_mail_parser::MessageBody;
+
+/// Does the pattern of the policy match the address, for the given user?
+fn pattern_match(pattern: &str, authid: &str, address: &Address) -> bool {
+    let pattern = pattern.replace("{authid}", authid);
+
+    match pattern.strip_prefix("*@") {
+        Some(domain) => address
+            .full()
+            .rsplit_once('@')
+            .map_or(false, |(_, address_domain)| {
+                address_domain.eq_ignore_ascii_case(domain)
+            }),
+        None => address.full().eq_ignore_ascii_case(&pattern),
+    }
+}
+
+/// Is the address owned by the user? A user without identity owns nothing.
+pub(crate) fn is_owned(
+    policy: &FieldServerSMTPAuthSenders,
+    authid: Option<&str>,
+    address: &Address,
+) -> bool {
+    let Some(authid) = authid else {
+        return false;
+    };
+
+    [authid, "*"]
+        .into_iter()
+        .filter_map(|user| policy.owned.get(user))
+        .flatten()
+        .any(|pattern| pattern_match(pattern, authid, address))
+}
+
+/// The identity of the user authenticated, if any.
+pub(crate) fn identity(auth: &AuthProperties) -> Option<&str> {
+    match &auth.credentials {
+        Some(Credentials::Verify { authid, .. }) => Some(authid.as_str()),
+        Some(Credentials::Certificate { subject }) => Some(subject.as_str()),
+        // the identity requested by the client, once validated by the rules.
+        Some(Credentials::External {
+            authzid, subject, ..
+        }) => Some(if authzid.is_empty() { subject } else { authzid }.as_str()),
+        Some(Credentials::AnonymousToken { .. }) | None => None,
+    }
+}
+
+/// Has the connection been accepted on a submission listener (`addr_submission` or `addr_submissions`)?
+fn is_submission(config: &Config, server_addr: &std::net::SocketAddr) -> bool {
+    let interfaces = &config.server.interfaces;
+    interfaces
+        .addr_submission
+        .iter()
+        .chain(&interfaces.addr_submissions)
+        .any(|addr| addr == server_addr)
+}
+
+/// Get the policy to enforce, and the identity of the user, if the transaction is authenticated
+/// on a submission listener.
+fn get_policy<'a>(
+    config: &'a Config,
+    server_addr: &std::net::SocketAddr,
+    auth: Option<&'a AuthProperties>,
+) -> Option<(&'a FieldServerSMTPAuthSenders, Option<&'a str>)> {
+    let policy = config.server.esmtp.auth.as_ref()?.senders.as_ref()?;
+    if !is_submission(config, server_addr) {
+        return None;
+    }
+    let auth = auth.filter(|auth| auth.authenticated)?;
+
+    Some((policy, identity(auth)))
+}
+
+/// The reply refusing the null reverse-path of an authenticated transaction.
+fn null_reverse_path(authid: Option<&str>) -> Reply {
+    tracing::warn!(
+        ?authid,
+        "Sender address rejected: null reverse-path of an authenticated user."
+    );
+    "553 5.7.1 Sender address rejected: null reverse-path not allowed for authenticated users\r\n"
+        .parse::<Reply>()
+        .expect("valid smtp reply")
+}
+
+/// Check the reverse-path of an authenticated transaction, returns the reply
+/// to send if the address is not owned by the user.
+///
+/// An address not owned according to the policy is given to `owned_by_rules`
+/// with the identity of the user, the `is_sender_owned` function of the rules.
+///
+/// The null reverse-path is refused: it is owned by no one, and the `From` header
+/// of the message could not be compared to it.
+pub(crate) fn check_mail_from(
+    config: &Config,
+    server_addr: &std::net::SocketAddr,
+    auth: Option<&AuthProperties>,
+    reverse_path: Option<&Address>,
+    owned_by_rules: impl Fn(&str, &Address) -> bool,
+) -> Option<Reply> {
+    let (policy, authid) = get_policy(config, server_addr, auth)?;
+    let Some(reverse_path) = reverse_path else {
+        return Some(null_reverse_path(authid));
+    };
+
+    if is_owned(policy, authid, reverse_path)
+        || authid.map_or(false, |authid| owned_by_rules(authid, reverse_path))
+    {
+        return None;
+    }
+
+    tracing::warn!(
+        ?authid,
+        %reverse_path,
+        "Sender address rejected: not owned by user."
+    );
+    Some(
+        "553 5.7.1 Sender address rejected: not owned by user\r\n"
+            .parse::<Reply>()
+            .expect("valid smtp reply"),
+    )
+}
+
+/// Extract the address of a `From` header, with or without display name.
+fn from_header_address(value: &str) -> Option<&str> {
+    match (value.find('<'), value.rfind('>')) {
+        (Some(start), Some(end)) if start < end => Some(value[start + 1..end].trim()),
+        (None, None) => Some(value.trim()),
+        _ => None,
+    }
+}
+
+/// Check the `From` header of an authenticated transaction against its reverse-path,
+/// and apply the action of the policy on mismatch.
+///
+/// Returns the reply denying the message if the policy rejects it.
+pub(crate) fn check_from_header(
+    config: &Config,
+    ctx: &ContextFinished,
+    message: &mut MessageBody,
+) -> Option<Reply> {
+    let (policy, authid) = get_policy(config, &ctx.connect.server_addr, ctx.connect.auth.as_ref())?;
+    let Some(reverse_path) = ctx.mail_from.reverse_path.as_ref() else {
+        return Some(null_reverse_path(authid));
+    };
+
+    let from = message.get_header("From").unwrap_or_default();
+    if from_header_address(&from).map_or(false, |address| {
+        address.eq_ignore_ascii_case(reverse_path.full())
+    }) {
+        return None;
+    }
+
+    tracing::warn!(
+        from = %from.escape_debug(),
+        %reverse_path,
+        action = ?policy.from_header,
+        "From header does not match the reverse-path."
+    );
+
+    match policy.from_header {
+        FromHeaderPolicy::Reject => Some(
+            "553 5.7.1 Sender address rejected: From header does not match the envelope\r\n"
+                .parse::<Reply>()
+                .expect("valid smtp reply"),
+        ),
+        FromHeaderPolicy::Rewrite => {
+            let value = match (from.find('<'), from.rfind('>')) {
+                (Some(start), Some(end)) if start < end => {
+                    format!("{}<{reverse_path}>{}", &from[..start], &from[end + 1..])
+                }
+                _ => reverse_path.to_string(),
+            };
+            message.set_header("From", value.trim());
+            None
+        }
+        FromHeaderPolicy::Flag => {
+            message.prepend_header(
+                "X-VSMTP-Sender-Mismatch",
+                &format!("from=\"{}\"; envelope=\"{reverse_path}\"", from.trim()),
+            );
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vsmtp_common::addr;
+    use vsmtp_config::field::FieldServerSMTPAuth;
+    use vsmtp_test::config::{local_ctx, local_ctx_authenticated, local_test};
+
+    fn policy(from_header: FromHeaderPolicy) -> FieldServerSMTPAuthSenders {
+        FieldServerSMTPAuthSenders {
+            owned: [
+                ("john".to_owned(), vec!["john.doe@example.com".to_owned()]),
+                ("admin".to_owned(), vec!["*@example.com".to_owned()]),
+                (
+                    "*".to_owned(),
+                    vec!["{authid}@users.example.com".to_owned()],
+                ),
+            ]
+            .into_iter()
+            .collect(),
+            from_header,
+        }
+    }
+
+    fn authenticated_ctx(authid: &str, reverse_path: &str) -> ContextFinished {
+        let mut ctx = local_ctx_authenticated(authid);
+        ctx.mail_from.reverse_path = Some(addr!(reverse_path));
+        ctx
+    }
+
+    fn config(from_header: FromHeaderPolicy) -> Config {
+        let mut config = local_test();
+        config
+            .server
+            .interfaces
+            .addr_submission
+            .push(local_ctx().connect.server_addr);
+        config.server.esmtp.auth = Some(FieldServerSMTPAuth {
+            senders: Some(policy(from_header)),
+            ..Default::default()
+        });
+        config
+    }
+
+    fn message(from: &str) -> MessageBody {
+        MessageBody::new(
+            vec![format!("From: {from}\r\n"), "Subject: hello\r\n".to_owned()],
+            "\r\n".to_owned(),
+        )
+    }
+
+    #[test]
+    fn exact_address() {
+        let policy = policy(FromHeaderPolicy::Flag);
+
+        assert!(is_owned(
+            &policy,
+            Some("john"),
+            &addr!("john.doe@example.com")
+        ));
+        assert!(is_owned(
+            &policy,
+            Some("john"),
+            &addr!("JOHN.DOE@example.com")
+        ));
+        assert!(!is_owned(
+            &policy,
+            Some("john"),
+            &addr!("jane.doe@example.com")
+        ));
+        assert!(!is_owned(
+            &policy,
+            Some("jane"),
+            &addr!("john.doe@example.com")
+        ));
+        assert!(!is_owned(&policy, None, &addr!("john.doe@example.com")));
+    }
+
+    #[test]
+    fn domain_wildcard() {
+        let policy = policy(FromHeaderPolicy::Flag);
+
+        assert!(is_owned(
+            &policy,
+            Some("admin"),
+            &addr!("anyone@example.com")
+        ));
+        assert!(is_owned(
+            &policy,
+            Some("admin"),
+            &addr!("anyone@EXAMPLE.com")
+        ));
+        assert!(!is_owned(
+            &policy,
+            Some("admin"),
+            &addr!("anyone@example.org")
+        ));
+        assert!(!is_owned(
+            &policy,
+            Some("admin"),
+            &addr!("anyone@sub.example.com")
+        ));
+
+        assert!(is_owned(
+            &policy,
+            Some("jane"),
+            &addr!("jane@users.example.com")
+        ));
+        assert!(!is_owned(
+            &policy,
+            Some("jane"),
+            &addr!("john@users.example.com")
+        ));
+    }
+
+    #[test]
+    fn mail_from() {
+        let config = config(FromHeaderPolicy::Flag);
+        let ctx = authenticated_ctx("john", "john.doe@example.com");
+        let check = |config: &Config, auth: Option<&AuthProperties>, reverse_path: &Address| {
+            check_mail_from(
+                config,
+                &ctx.connect.server_addr,
+                auth,
+                Some(reverse_path),
+                |_, _| false,
+            )
+        };
+
+        assert_eq!(
+            check(
+                &config,
+                ctx.connect.auth.as_ref(),
+                &addr!("john.doe@example.com")
+            ),
+            None
+        );
+        assert_eq!(
+            check(
+                &config,
+                ctx.connect.auth.as_ref(),
+                &addr!("ceo@example.com")
+            ),
+            Some(
+                "553 5.7.1 Sender address rejected: not owned by user\r\n"
+                    .parse::<Reply>()
+                    .unwrap()
+            )
+        );
+        assert_eq!(check(&config, None, &addr!("ceo@example.com")), None);
+        assert_eq!(
+            check(
+                &local_test(),
+                ctx.connect.auth.as_ref(),
+                &addr!("ceo@example.com")
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn mail_from_owned_by_rules() {
+        let config = config(FromHeaderPolicy::Flag);
+        let ctx = authenticated_ctx("john", "john.doe@example.com");
+
+        assert_eq!(
+            check_mail_from(
+                &config,
+                &ctx.connect.server_addr,
+                ctx.connect.auth.as_ref(),
+                Some(&addr!("support@example.com")),
+                |authid, address| authid == "john" && address.full() == "support@example.com",
+            ),
+            None
+        );
+        assert!(check_mail_from(
+            &config,
+            &ctx.connect.server_addr,
+            ctx.connect.auth.as_ref(),
+            Some(&addr!("ceo@example.com")),
+            |authid, address| authid == "john" && address.full() == "support@example.com",
+        )
+        .is_some());
+    }
+
+    #[test]
+    fn null_reverse_path_refused() {
+        let config = config(FromHeaderPolicy::Flag);
+        let mut ctx = authenticated_ctx("admin", "john.doe@example.com");
+        let refused = Some(
+            "553 5.7.1 Sender address rejected: null reverse-path not allowed for authenticated users\r\n"
+                .parse::<Reply>()
+                .unwrap(),
+        );
+
+        assert_eq!(
+            check_mail_from(
+                &config,
+                &ctx.connect.server_addr,
+                ctx.connect.auth.as_ref(),
+                None,
+                |_, _| true,
+            ),
+            refused
+        );
+        assert_eq!(
+            check_mail_from(&config, &ctx.connect.server_addr, None, None, |_, _| false),
+            None
+        );
+
+        ctx.mail_from.reverse_path = None;
+        assert_eq!(
+            check_from_header(&config, &ctx, &mut message("john.doe@example.com")),
+            refused
+        );
+    }
+
+    #[test]
+    fn relay_listener() {
+        let config = config(FromHeaderPolicy::Reject);
+        let mut ctx = authenticated_ctx("john", "john.doe@example.com");
+        ctx.connect.server_addr = "127.0.0.1:25".parse().unwrap();
+
+        assert_eq!(
+            check_mail_from(
+                &config,
+                &ctx.connect.server_addr,
+                ctx.connect.auth.as_ref(),
+                Some(&addr!("ceo@example.com")),
+                |_, _| false,
+            ),
+            None
+        );
+        assert_eq!(
+            check_from_header(&config, &ctx, &mut message("ceo@example.com")),
+            None
+        );
+    }
+
+    #[test]
+    fn from_header_match() {
+        let config = config(FromHeaderPolicy::Reject);
+        let ctx = authenticated_ctx("john", "john.doe@example.com");
+
+        for from in [
+            "john.doe@example.com",
+            "<john.doe@example.com>",
+            "\"John Doe\" <John.Doe@example.com>",
+        ] {
+            let mut message = message(from);
+            assert_eq!(check_from_header(&config, &ctx, &mut message), None);
+            assert_eq!(message.get_header("From").unwrap().trim(), from);
+        }
+    }
+
+    #[test]
+    fn from_header_reject() {
+        let config = config(FromHeaderPolicy::Reject);
+        let ctx = authenticated_ctx("john", "john.doe@example.com");
+
+        assert!(
+            check_from_header(&config, &ctx, &mut message("\"The CEO\" <ceo@example.com>"))
+                .is_some()
+        );
+        assert!(check_from_header(&config, &ctx, &mut MessageBody::default()).is_some());
+    }
+
+    #[test]
+    fn from_header_rewrite() {
+        let config = config(FromHeaderPolicy::Rewrite);
+        let ctx = authenticated_ctx("john", "john.doe@example.com");
+
+        let mut message = message("\"The CEO\" <ceo@example.com>");
+        assert_eq!(check_from_header(&config, &ctx, &mut message), None);
+        assert_eq!(
+            message.get_header("From").unwrap().trim(),
+            "\"The CEO\" <john.doe@example.com>"
+        );
+
+        let mut message = self::message("ceo@example.com");
+        assert_eq!(check_from_header(&config, &ctx, &mut message), None);
+        assert_eq!(
+            message.get_header("From").unwrap().trim(),
+            "john.doe@example.com"
+        );
+    }
+
+    #[test]
+    fn from_header_flag() {
+        let config = config(FromHeaderPolicy::Flag);
+        let ctx = authenticated_ctx("john", "john.doe@example.com");
+
+        let mut message = message("ceo@example.com");
+        assert_eq!(check_from_header(&config, &ctx, &mut message), None);
+        assert_eq!(
+            message
+                .get_header("X-VSMTP-Sender-Mismatch")
+                .unwrap()
+                .trim(),
+            "from=\"ceo@example.com\"; envelope=\"john.doe@example.com\""
+        );
+        assert_eq!(
+            message.get_header("From").unwrap().trim(),
+            "ceo@example.com"
+        );
+    }
+
+    #[test]
+    fn not_authenticated() {
+        let config = config(FromHeaderPolicy::Reject);
+        let mut ctx = authenticated_ctx("john", "john.doe@example.com");
+        ctx.connect.auth = None;
+
+        assert_eq!(
+            check_from_header(&config, &ctx, &mut message("ceo@example.com")),
+            None
+        );
+    }
+}
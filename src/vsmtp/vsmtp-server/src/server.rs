@@ -14,13 +14,19 @@
  * this program. If not, see https://www.gnu.org/licenses/.
  *
 */
-use crate::{receiver::handler::Handler, scheduler::Emitter, ValidationVSL};
+use crate::{
+    receiver::handler::Handler, scheduler::Emitter, ConnectionLimits, ConnectionSlot, Health,
+    SessionStatistics, SubmissionQuotas, TlsStatistics, ValidationVSL,
+};
 use anyhow::Context;
 use tokio_rustls::rustls;
 use tokio_stream::StreamExt;
 use vqueue::GenericQueueManager;
-use vsmtp_common::Reply;
-use vsmtp_config::{get_rustls_config, Config};
+use vsmtp_common::{
+    policy_event::{Kind, PolicyEvent},
+    Reply,
+};
+use vsmtp_config::{get_rustls_config, get_rustls_config_client_auth, Config};
 use vsmtp_mail_parser::BasicParser;
 use vsmtp_protocol::{AcceptArgs, ConnectionKind};
 use vsmtp_rule_engine::RuleEngine;
@@ -28,12 +34,22 @@ use vsmtp_rule_engine::RuleEngine;
 /// TCP/IP server
 pub struct Server {
     conn_max_reach_reply: Reply,
+    conn_max_per_ip_reply: Reply,
+    probe_greeting: Reply,
 
     config: std::sync::Arc<Config>,
     tls_config: Option<std::sync::Arc<rustls::ServerConfig>>,
+    /// Used instead of `tls_config` on the listeners authenticating the clients
+    /// by their certificate.
+    tls_config_client_auth: Option<std::sync::Arc<rustls::ServerConfig>>,
     rule_engine: std::sync::Arc<RuleEngine>,
     queue_manager: std::sync::Arc<dyn GenericQueueManager>,
     emitter: std::sync::Arc<Emitter>,
+    health: Option<std::sync::Arc<Health>>,
+    tls_statistics: Option<std::sync::Arc<TlsStatistics>>,
+    session_statistics: std::sync::Arc<SessionStatistics>,
+    submission_quotas: Option<std::sync::Arc<SubmissionQuotas>>,
+    connection_limits: Option<std::sync::Arc<ConnectionLimits>>,
 }
 
 /// Create a `TCPListener` ready to be listened to
@@ -91,6 +107,18 @@ impl Server {
             conn_max_reach_reply: "554 Cannot process connection, closing\r\n"
                 .parse::<Reply>()
                 .expect("valid smtp reply"),
+            conn_max_per_ip_reply: "421 4.7.0 Too many connections from your address, closing\r\n"
+                .parse::<Reply>()
+                .expect("valid smtp reply"),
+            probe_greeting: format!(
+                "220 {} Service ready\r\n",
+                config
+                    .server
+                    .disclosed_name()
+                    .unwrap_or(&config.server.name)
+            )
+            .parse::<Reply>()
+            .expect("valid smtp reply"),
             tls_config: if let Some(smtps) = &config.server.tls {
                 Some(std::sync::Arc::new(get_rustls_config(
                     smtps,
@@ -99,24 +127,181 @@ impl Server {
             } else {
                 None
             },
+            tls_config_client_auth: if let Some(smtps) = &config.server.tls {
+                get_rustls_config_client_auth(smtps, &config.server.r#virtual)?
+                    .map(std::sync::Arc::new)
+            } else {
+                None
+            },
+            connection_limits: config
+                .server
+                .connection_limits
+                .as_ref()
+                .map(|limits| std::sync::Arc::new(ConnectionLimits::new(limits))),
             rule_engine,
             queue_manager,
             config,
             emitter,
+            health: None,
+            tls_statistics: None,
+            session_statistics: std::sync::Arc::default(),
+            submission_quotas: None,
         })
     }
 
+    /// Share the state of the server, new clients are refused while it is in maintenance.
+    #[must_use]
+    pub fn with_health(mut self, health: std::sync::Arc<Health>) -> Self {
+        self.health = Some(health);
+        self
+    }
+
+    /// Account the messages received in the statistics of the use of TLS.
+    #[must_use]
+    pub fn with_tls_statistics(mut self, tls_statistics: std::sync::Arc<TlsStatistics>) -> Self {
+        self.tls_statistics = Some(tls_statistics);
+        self
+    }
+
+    /// Account the sessions closed in the statistics of their categories.
+    #[must_use]
+    pub fn with_session_statistics(
+        mut self,
+        session_statistics: std::sync::Arc<SessionStatistics>,
+    ) -> Self {
+        self.session_statistics = session_statistics;
+        self
+    }
+
+    /// Limit the recipients submitted by the authenticated users on the submission listeners.
+    #[must_use]
+    pub fn with_submission_quotas(
+        mut self,
+        submission_quotas: std::sync::Arc<SubmissionQuotas>,
+    ) -> Self {
+        self.submission_quotas = Some(submission_quotas);
+        self
+    }
+
+    fn is_probe(&self, client_ip: std::net::IpAddr) -> bool {
+        self.config
+            .server
+            .health
+            .as_ref()
+            .map_or(false, |health| health.probes.contains(&client_ip))
+    }
+
+    /// Answer the greeting to a load balancer, and `221` to `QUIT` if `open`, without running
+    /// the rules. Nothing is logged, the probes are only counted.
+    async fn answer_probe(
+        mut stream: tokio::net::TcpStream,
+        greeting: Reply,
+        open: bool,
+        timeout: std::time::Duration,
+    ) {
+        let mut buffer = vec![0; 512];
+        let mut len = 0;
+
+        if tokio::io::AsyncWriteExt::write_all(&mut stream, greeting.as_ref().as_bytes())
+            .await
+            .is_err()
+        {
+            return;
+        }
+
+        while open {
+            let Some(end) = buffer[..len].windows(2).position(|w| w == b"\r\n") else {
+                if len == buffer.len() {
+                    break;
+                }
+                match tokio::time::timeout(
+                    timeout,
+                    tokio::io::AsyncReadExt::read(&mut stream, &mut buffer[len..]),
+                )
+                .await
+                {
+                    Ok(Ok(read)) if read != 0 => len += read,
+                    _ => break,
+                }
+                continue;
+            };
+
+            let is_quit = buffer[..end].eq_ignore_ascii_case(b"QUIT");
+            let reply = match buffer[..end].to_ascii_uppercase().as_slice() {
+                b"QUIT" => "221 Service closing transmission channel\r\n",
+                b"NOOP" | b"RSET" => "250 Ok\r\n",
+                _ => "502 Command not implemented\r\n",
+            };
+            buffer.copy_within(end + 2..len, 0);
+            len -= end + 2;
+
+            if tokio::io::AsyncWriteExt::write_all(&mut stream, reply.as_bytes())
+                .await
+                .is_err()
+                || is_quit
+            {
+                break;
+            }
+        }
+
+        let _err = tokio::io::AsyncWriteExt::shutdown(&mut stream).await;
+    }
+
+    async fn refuse_client(mut stream: tokio::net::TcpStream, reply: &Reply) {
+        if let Err(error) =
+            tokio::io::AsyncWriteExt::write_all(&mut stream, reply.as_ref().as_bytes()).await
+        {
+            tracing::error!(%error, "Code delivery failure.");
+        }
+
+        if let Err(error) = tokio::io::AsyncWriteExt::shutdown(&mut stream).await {
+            tracing::error!(%error, "Closing connection failure.");
+        }
+    }
+
     #[tracing::instrument(name = "handle-client", skip_all, fields(client = %client_addr, server = %server_addr))]
     async fn handle_client(
         &self,
         client_counter: std::sync::Arc<std::sync::atomic::AtomicI64>,
         kind: ConnectionKind,
-        mut stream: tokio::net::TcpStream,
+        stream: tokio::net::TcpStream,
         client_addr: std::net::SocketAddr,
         server_addr: std::net::SocketAddr,
     ) {
+        if self.is_probe(client_addr.ip()) {
+            if let Some(health) = &self.health {
+                health.count_probe();
+            }
+            let in_maintenance = self
+                .health
+                .as_ref()
+                .map_or(false, |health| health.is_in_maintenance());
+
+            tokio::spawn(Self::answer_probe(
+                stream,
+                if in_maintenance {
+                    self.config.server.smtp.maintenance_reply.clone()
+                } else {
+                    self.probe_greeting.clone()
+                },
+                !in_maintenance,
+                self.config.server.smtp.timeout_client.helo,
+            ));
+            return;
+        }
+
         tracing::info!(%kind, "Connection accepted.");
 
+        if self
+            .health
+            .as_ref()
+            .map_or(false, |health| health.is_in_maintenance())
+        {
+            tracing::warn!("Server in maintenance, rejecting connection.");
+            Self::refuse_client(stream, &self.config.server.smtp.maintenance_reply).await;
+            return;
+        }
+
         if self.config.server.client_count_max != -1
             && client_counter.load(std::sync::atomic::Ordering::SeqCst)
                 >= self.config.server.client_count_max
@@ -126,37 +311,67 @@ impl Server {
                 "Connection count max reached, rejecting connection.",
             );
 
-            if let Err(error) = tokio::io::AsyncWriteExt::write_all(
-                &mut stream,
-                self.conn_max_reach_reply.as_ref().as_bytes(),
-            )
-            .await
-            {
-                tracing::error!(%error, "Code delivery failure.");
-            }
-
-            if let Err(error) = tokio::io::AsyncWriteExt::shutdown(&mut stream).await {
-                tracing::error!(%error, "Closing connection failure.");
-            }
+            PolicyEvent::of_client(Kind::ConnectionLimit, client_addr.ip())
+                .with_stage("connect")
+                .with_reply(&self.conn_max_reach_reply)
+                .emit();
+            Self::refuse_client(stream, &self.conn_max_reach_reply).await;
             return;
         }
 
+        let connection_slot = match &self.connection_limits {
+            Some(limits) => match limits.acquire(client_addr.ip()) {
+                Some(slot) => Some(slot),
+                None => {
+                    tracing::warn!(
+                        "Connection count max reached for the address, rejecting connection."
+                    );
+                    PolicyEvent::of_client(Kind::ConnectionLimit, client_addr.ip())
+                        .with_stage("connect")
+                        .with_reply(&self.conn_max_per_ip_reply)
+                        .emit();
+                    Self::refuse_client(stream, &self.conn_max_per_ip_reply).await;
+                    return;
+                }
+            },
+            None => None,
+        };
+
         client_counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
 
+        let tls_config = match (&self.tls_config_client_auth, &self.config.server.tls) {
+            (Some(tls_config), Some(tls))
+                if tls.client_auth.as_ref().map_or(false, |client_auth| {
+                    client_auth.interfaces.is_empty()
+                        || client_auth.interfaces.contains(&server_addr)
+                }) =>
+            {
+                Some(tls_config.clone())
+            }
+            _ => self.tls_config.clone(),
+        };
+
         let session = Self::serve(
             AcceptArgs::new(
                 client_addr,
                 stream.local_addr().expect("retrieve local address"),
-                time::OffsetDateTime::now_utc(),
-                uuid::Uuid::new_v4(),
+                vsmtp_common::clock::now(),
+                vsmtp_common::id::new_uuid(),
                 kind,
             ),
             stream,
-            self.tls_config.clone(),
+            tls_config,
             self.config.clone(),
             self.rule_engine.clone(),
             self.queue_manager.clone(),
             self.emitter.clone(),
+            self.tls_statistics.clone(),
+            self.session_statistics.clone(),
+            // NOTE: the relays receive the messages of the other servers.
+            self.submission_quotas
+                .clone()
+                .filter(|_| kind != ConnectionKind::Relay),
+            connection_slot,
         );
         let client_counter_copy = client_counter.clone();
         tokio::spawn(async move {
@@ -228,6 +443,8 @@ impl Server {
             tokio_stream::StreamExt::next(&mut map).await
         {
             let (stream, client_addr) = client?;
+            // NOTE: the logs, the probes and the rules see the same form of the address.
+            let client_addr = vsmtp_common::utils::canonical_socket_addr(client_addr);
 
             self.handle_client(
                 client_counter.clone(),
@@ -244,6 +461,7 @@ impl Server {
     ///
     /// # Errors
     #[tracing::instrument(skip_all, err, fields(uuid = %args.uuid))]
+    #[allow(clippy::too_many_arguments)]
     pub async fn serve(
         args: AcceptArgs,
         tcp_stream: tokio::net::TcpStream,
@@ -252,6 +470,10 @@ impl Server {
         rule_engine: std::sync::Arc<RuleEngine>,
         queue_manager: std::sync::Arc<dyn GenericQueueManager>,
         emitter: std::sync::Arc<Emitter>,
+        tls_statistics: Option<std::sync::Arc<TlsStatistics>>,
+        session_statistics: std::sync::Arc<SessionStatistics>,
+        submission_quotas: Option<std::sync::Arc<SubmissionQuotas>>,
+        connection_slot: Option<ConnectionSlot>,
     ) -> anyhow::Result<()> {
         let receiver = vsmtp_protocol::Receiver::<_, ValidationVSL, _, _>::new(
             tcp_stream,
@@ -260,10 +482,22 @@ impl Server {
             config.server.smtp.error.hard_count,
             config.server.message_size_limit,
             config.server.esmtp.pipelining,
-        );
+        )
+        .with_line_limits(
+            config.server.smtp.line_length_max,
+            config.server.smtp.first_line_max,
+        )
+        .with_consecutive_bad_command_max(config.server.smtp.error.consecutive_count)
+        .with_parameters(vsmtp_protocol::ParametersPolicy::new(
+            config.server.smtp.parameters.strict,
+            config.server.smtp.parameters.count_max,
+            config.server.smtp.parameters.length_max,
+            config.server.smtp.parameters.reject_unexpected,
+        ));
+        let (on_disconnect, disconnect) = tokio::sync::oneshot::channel();
         let smtp_stream = receiver.into_stream(
             |args| async move {
-                Handler::on_accept(
+                let (handler, ctx, reply) = Handler::on_accept(
                     args,
                     rule_engine,
                     config,
@@ -271,6 +505,16 @@ impl Server {
                     queue_manager,
                     emitter,
                     BasicParser::default,
+                );
+                (
+                    handler
+                        .with_tls_statistics(tls_statistics)
+                        .with_session_statistics(session_statistics)
+                        .with_submission_quotas(submission_quotas)
+                        .with_connection_slot(connection_slot)
+                        .with_disconnect(on_disconnect),
+                    ctx,
+                    reply,
                 )
             },
             args.client_addr,
@@ -278,9 +522,15 @@ impl Server {
             args.timestamp,
             args.uuid,
         );
-        tokio::pin!(smtp_stream);
+        {
+            tokio::pin!(smtp_stream);
+            while matches!(smtp_stream.next().await, Some(Ok(()))) {}
+        }
 
-        while matches!(smtp_stream.next().await, Some(Ok(()))) {}
+        // NOTE: the handler has been dropped with the stream.
+        if let Ok(disconnect) = disconnect.await {
+            disconnect.run();
+        }
 
         tracing::info!("Connection closed cleanly.");
         Ok(())
@@ -0,0 +1,208 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2023 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use std::sync::atomic::{AtomicU64, Ordering};
+use strum::IntoEnumIterator;
+use vsmtp_common::SessionCategory;
+
+/// Commands received during a session, to determine its [`SessionCategory`] when the
+/// connection is closed.
+#[derive(Debug, Default)]
+pub(crate) struct SessionCounters {
+    /// The client has been refused at the connection, by the access lists or the rules.
+    pub(crate) refused: bool,
+    /// The TLS handshake has been started (`STARTTLS` or a tunneled connection)
+    /// and not completed yet.
+    pub(crate) tls_pending: bool,
+    /// `HELO`, `EHLO`, `STARTTLS`, `AUTH`, `MAIL FROM` and `RCPT TO` received.
+    pub(crate) dialogue_commands: u32,
+    /// Unknown commands and commands out of sequence received.
+    pub(crate) invalid_commands: u32,
+    /// A `MAIL FROM` has been accepted.
+    pub(crate) mail_accepted: bool,
+    /// Messages accepted at the end of the `DATA` or `BDAT` commands.
+    pub(crate) messages_accepted: u32,
+}
+
+impl SessionCounters {
+    /// Category of the session, the first matching in the order of [`SessionCategory`].
+    pub(crate) const fn classify(&self) -> SessionCategory {
+        if self.messages_accepted != 0 {
+            SessionCategory::Completed
+        } else if self.tls_pending {
+            SessionCategory::TlsFailed
+        } else if self.mail_accepted {
+            SessionCategory::AbortedAfterMail
+        } else if self.refused || self.dialogue_commands != 0 {
+            SessionCategory::Other
+        } else if self.invalid_commands != 0 {
+            SessionCategory::Scanner
+        } else {
+            SessionCategory::Probe
+        }
+    }
+}
+
+/// Number of SMTP sessions closed, per [`SessionCategory`], and of the messages
+/// refused because the spool could not be written.
+#[derive(Debug, Default)]
+pub struct SessionStatistics {
+    completed: AtomicU64,
+    aborted_after_mail: AtomicU64,
+    probe: AtomicU64,
+    scanner: AtomicU64,
+    tls_failed: AtomicU64,
+    other: AtomicU64,
+    storage_errors: AtomicU64,
+}
+
+impl SessionStatistics {
+    const fn sessions(&self, category: SessionCategory) -> &AtomicU64 {
+        match category {
+            SessionCategory::Completed => &self.completed,
+            SessionCategory::AbortedAfterMail => &self.aborted_after_mail,
+            SessionCategory::Probe => &self.probe,
+            SessionCategory::Scanner => &self.scanner,
+            SessionCategory::TlsFailed => &self.tls_failed,
+            SessionCategory::Other => &self.other,
+        }
+    }
+
+    /// Account a session closed.
+    pub fn record(&self, category: SessionCategory) {
+        self.sessions(category).fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Number of sessions closed in the `category`.
+    #[must_use]
+    pub fn count(&self, category: SessionCategory) -> u64 {
+        self.sessions(category).load(Ordering::Relaxed)
+    }
+
+    /// Account a message refused because the spool could not be written.
+    pub fn record_storage_error(&self) {
+        self.storage_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Number of messages refused because the spool could not be written.
+    #[must_use]
+    pub fn storage_error_count(&self) -> u64 {
+        self.storage_errors.load(Ordering::Relaxed)
+    }
+
+    /// Render the counters in the Prometheus text format.
+    #[must_use]
+    pub fn metrics(&self) -> String {
+        let name = "vsmtp_smtp_sessions_total";
+        let mut output =
+            format!("# HELP {name} SMTP sessions closed, per category.\n# TYPE {name} counter\n");
+        for category in SessionCategory::iter() {
+            output.push_str(&format!(
+                "{name}{{category=\"{category}\"}} {}\n",
+                self.count(category)
+            ));
+        }
+        let name = "vsmtp_spool_write_errors_total";
+        output.push_str(&format!(
+            "# HELP {name} Messages refused because the spool could not be written.\n# TYPE {name} counter\n{name} {}\n",
+            self.storage_error_count()
+        ));
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classify() {
+        for (counters, category) in [
+            (SessionCounters::default(), SessionCategory::Probe),
+            (
+                SessionCounters {
+                    invalid_commands: 3,
+                    ..Default::default()
+                },
+                SessionCategory::Scanner,
+            ),
+            (
+                SessionCounters {
+                    invalid_commands: 1,
+                    dialogue_commands: 1,
+                    ..Default::default()
+                },
+                SessionCategory::Other,
+            ),
+            (
+                SessionCounters {
+                    refused: true,
+                    ..Default::default()
+                },
+                SessionCategory::Other,
+            ),
+            (
+                SessionCounters {
+                    dialogue_commands: 2,
+                    tls_pending: true,
+                    ..Default::default()
+                },
+                SessionCategory::TlsFailed,
+            ),
+            (
+                SessionCounters {
+                    dialogue_commands: 3,
+                    mail_accepted: true,
+                    ..Default::default()
+                },
+                SessionCategory::AbortedAfterMail,
+            ),
+            (
+                SessionCounters {
+                    dialogue_commands: 5,
+                    invalid_commands: 1,
+                    mail_accepted: true,
+                    messages_accepted: 1,
+                    ..Default::default()
+                },
+                SessionCategory::Completed,
+            ),
+        ] {
+            assert_eq!(counters.classify(), category, "{counters:?}");
+        }
+    }
+
+    #[test]
+    fn metrics() {
+        let statistics = SessionStatistics::default();
+        statistics.record(SessionCategory::Probe);
+        statistics.record(SessionCategory::Probe);
+        statistics.record(SessionCategory::TlsFailed);
+
+        assert_eq!(statistics.count(SessionCategory::Probe), 2);
+        let metrics = statistics.metrics();
+        assert!(metrics.contains("vsmtp_smtp_sessions_total{category=\"probe\"} 2\n"));
+        assert!(metrics.contains("vsmtp_smtp_sessions_total{category=\"tls_failed\"} 1\n"));
+        assert!(metrics.contains("vsmtp_smtp_sessions_total{category=\"completed\"} 0\n"));
+        assert!(metrics.contains("vsmtp_spool_write_errors_total 0\n"));
+
+        statistics.record_storage_error();
+        assert_eq!(statistics.storage_error_count(), 1);
+        assert!(statistics
+            .metrics()
+            .contains("vsmtp_spool_write_errors_total 1\n"));
+    }
+}
@@ -0,0 +1,445 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2022 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use vsmtp_delivery::SourceIpPool;
+
+/// Target of the [`tracing`] events of the changes of health of the outgoing addresses.
+pub const TARGET: &str = "vsmtp::source_ip_event";
+
+/// The zone of `blocklist`, a zone or a keyword of the `dnsxl` plugin.
+fn zone(blocklist: &str) -> String {
+    vsmtp_common::dnsxl::zone(blocklist).map_or_else(
+        || blocklist.trim_end_matches('.').to_ascii_lowercase(),
+        str::to_owned,
+    )
+}
+
+/// The name looked up to know if `ip` is listed in `zone`: the address reversed
+/// (by nibbles for IPv6) prepended to the zone.
+fn query(ip: std::net::IpAddr, zone: &str) -> String {
+    let reversed = match ip {
+        std::net::IpAddr::V4(ip) => ip
+            .octets()
+            .iter()
+            .rev()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>(),
+        std::net::IpAddr::V6(ip) => ip
+            .octets()
+            .iter()
+            .rev()
+            .flat_map(|byte| [byte & 0x0f, byte >> 4])
+            .map(|nibble| format!("{nibble:x}"))
+            .collect::<Vec<_>>(),
+    };
+    format!("{}.{zone}", reversed.join("."))
+}
+
+/// The lookup of the DNS blocklists.
+#[async_trait::async_trait]
+pub trait BlocklistResolver: Send + Sync {
+    /// Does the `query` (see [`SourceIpReputation`]) resolve to an address,
+    /// meaning the address is listed ?
+    ///
+    /// # Errors
+    ///
+    /// * the lookup failed, the listing is unknown
+    async fn is_listed(&self, query: &str) -> anyhow::Result<bool>;
+}
+
+#[async_trait::async_trait]
+impl BlocklistResolver for trust_dns_resolver::TokioAsyncResolver {
+    async fn is_listed(&self, query: &str) -> anyhow::Result<bool> {
+        match self.ipv4_lookup(format!("{query}.")).await {
+            Ok(_) => Ok(true),
+            Err(error)
+                if matches!(
+                    error.kind(),
+                    trust_dns_resolver::error::ResolveErrorKind::NoRecordsFound { .. }
+                ) =>
+            {
+                Ok(false)
+            }
+            Err(error) => Err(error.into()),
+        }
+    }
+}
+
+/// A change of the health of an outgoing address.
+#[derive(Debug, PartialEq, Eq)]
+pub enum SourceIpEvent {
+    /// The address has been listed, and removed from the rotation.
+    Listed {
+        /// The outgoing address.
+        ip: std::net::IpAddr,
+        /// The zones of the blocklists listing it.
+        blocklists: Vec<String>,
+    },
+    /// The address has been delisted, and restored in the rotation.
+    Delisted {
+        /// The outgoing address.
+        ip: std::net::IpAddr,
+    },
+}
+
+impl SourceIpEvent {
+    /// Emit the event, with the target [`TARGET`].
+    pub fn emit(&self) {
+        match self {
+            Self::Listed { ip, blocklists } => tracing::error!(
+                target: TARGET,
+                %ip,
+                ?blocklists,
+                "Outgoing address listed on a blocklist, removed from the rotation."
+            ),
+            Self::Delisted { ip } => tracing::info!(
+                target: TARGET,
+                ip = %ip,
+                "Outgoing address delisted, restored in the rotation."
+            ),
+        }
+    }
+}
+
+/// Periodic lookup of the outgoing addresses in the DNS blocklists, removing
+/// the listed addresses from the rotation of the [`SourceIpPool`].
+///
+/// An address `192.0.2.1` is listed in the zone `zen.spamhaus.org` if
+/// `1.2.0.192.zen.spamhaus.org` has an `A` record.
+pub struct SourceIpReputation {
+    blocklists: Vec<String>,
+    resolver: std::sync::Arc<dyn BlocklistResolver>,
+    pool: std::sync::Arc<SourceIpPool>,
+}
+
+impl std::fmt::Debug for SourceIpReputation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SourceIpReputation")
+            .field("blocklists", &self.blocklists)
+            .field("pool", &self.pool)
+            .finish_non_exhaustive()
+    }
+}
+
+impl SourceIpReputation {
+    /// Check the addresses of the `pool` in the `blocklists`, zones or keywords
+    /// of the `dnsxl` plugin.
+    #[must_use]
+    pub fn new(
+        blocklists: &[String],
+        resolver: std::sync::Arc<dyn BlocklistResolver>,
+        pool: std::sync::Arc<SourceIpPool>,
+    ) -> Self {
+        Self {
+            blocklists: blocklists.iter().map(|blocklist| zone(blocklist)).collect(),
+            resolver,
+            pool,
+        }
+    }
+
+    /// The zones listing `ip`, `None` if a lookup failed and the address is not listed
+    /// by the others.
+    async fn listings(&self, ip: std::net::IpAddr) -> Option<Vec<String>> {
+        let (mut listings, mut unknown) = (vec![], false);
+
+        for zone in &self.blocklists {
+            match self.resolver.is_listed(&query(ip, zone)).await {
+                Ok(true) => listings.push(zone.clone()),
+                Ok(false) => (),
+                Err(error) => {
+                    tracing::warn!(%ip, %zone, %error, "Blocklist lookup failure.");
+                    unknown = true;
+                }
+            }
+        }
+
+        (!unknown || !listings.is_empty()).then_some(listings)
+    }
+
+    /// Look up every address of the pool, and update their health, returning the changes
+    /// (see [`SourceIpEvent::emit`]).
+    ///
+    /// The health of an address is kept if its listing is unknown.
+    pub async fn check(&self) -> Vec<SourceIpEvent> {
+        let mut events = vec![];
+
+        for ip in self.pool.addresses() {
+            let Some(listings) = self.listings(ip).await else {
+                continue;
+            };
+
+            let listed = !listings.is_empty();
+            if !self.pool.set_listings(ip, listings.clone()) {
+                continue;
+            }
+
+            if listed {
+                events.push(SourceIpEvent::Listed {
+                    ip,
+                    blocklists: listings,
+                });
+            } else {
+                events.push(SourceIpEvent::Delisted { ip });
+            }
+        }
+
+        events
+    }
+
+    /// Check the addresses with the period, emitting the changes of their health,
+    /// until the runtime is stopped.
+    pub async fn check_periodically(self: std::sync::Arc<Self>, period: std::time::Duration) {
+        let mut interval = tokio::time::interval(period);
+
+        loop {
+            interval.tick().await;
+            for event in self.check().await {
+                event.emit();
+            }
+        }
+    }
+
+    /// Render the health of the addresses in the Prometheus text format.
+    #[must_use]
+    pub fn metrics(&self) -> String {
+        let name = "vsmtp_outbound_source_ip_healthy";
+        let mut output = format!(
+            "# HELP {name} Is the outgoing address in the rotation (not listed on a blocklist).\n# TYPE {name} gauge\n"
+        );
+
+        for (ip, listings) in self.pool.health() {
+            output.push_str(&format!(
+                "{name}{{ip=\"{ip}\"}} {}\n",
+                u8::from(listings.is_empty())
+            ));
+        }
+        output
+    }
+
+    /// Describe the health of the addresses, one line per address.
+    #[must_use]
+    pub fn report(&self) -> Vec<String> {
+        self.pool
+            .health()
+            .into_iter()
+            .map(|(ip, listings)| {
+                if listings.is_empty() {
+                    format!("{ip} healthy")
+                } else {
+                    format!("{ip} listed {}", listings.join(","))
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vsmtp_test::config::local_test;
+
+    /// A resolver answering from a set of listed queries.
+    #[derive(Default)]
+    struct StubResolver {
+        listed: std::sync::Mutex<std::collections::HashSet<String>>,
+    }
+
+    impl StubResolver {
+        fn set(&self, query: &str, listed: bool) {
+            let mut set = self.listed.lock().unwrap();
+            if listed {
+                set.insert(query.to_owned());
+            } else {
+                set.remove(query);
+            }
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl BlocklistResolver for StubResolver {
+        async fn is_listed(&self, query: &str) -> anyhow::Result<bool> {
+            if query.ends_with(".broken.example") {
+                anyhow::bail!("SERVFAIL");
+            }
+            Ok(self.listed.lock().unwrap().contains(query))
+        }
+    }
+
+    fn reputation(
+        pool: &std::sync::Arc<SourceIpPool>,
+        blocklists: &[&str],
+    ) -> (SourceIpReputation, std::sync::Arc<StubResolver>) {
+        let mut config = local_test();
+        config.server.queues.delivery.source_ips.pool =
+            vec!["192.0.2.1".parse().unwrap(), "192.0.2.2".parse().unwrap()];
+        pool.configure(&config);
+
+        let resolver = std::sync::Arc::new(StubResolver::default());
+        let blocklists = blocklists
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        (
+            SourceIpReputation::new(&blocklists, resolver.clone(), pool.clone()),
+            resolver,
+        )
+    }
+
+    fn rotation(pool: &SourceIpPool) -> std::collections::BTreeSet<String> {
+        (0..4)
+            .filter_map(|_| pool.next(None).ok().flatten())
+            .map(|ip| ip.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn queries() {
+        assert_eq!(
+            query("192.0.2.1".parse().unwrap(), "zen.spamhaus.org"),
+            "1.2.0.192.zen.spamhaus.org"
+        );
+        assert_eq!(
+            query("2001:db8::1".parse().unwrap(), "bl.example"),
+            "1.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.bl.example"
+        );
+        assert_eq!(zone("Spamhaus"), "zen.spamhaus.org");
+        assert_eq!(zone("SORBS"), "dnsxl.sorbs.net");
+        assert_eq!(zone("bl.example."), "bl.example");
+    }
+
+    #[tokio::test]
+    async fn listed_and_delisted() {
+        let pool = std::sync::Arc::new(SourceIpPool::new());
+        let (reputation, resolver) = reputation(&pool, &["spamhaus", "bl.example"]);
+
+        assert!(reputation.check().await.is_empty());
+        assert_eq!(
+            rotation(&pool),
+            ["192.0.2.1", "192.0.2.2"].map(String::from).into()
+        );
+
+        resolver.set("1.2.0.192.zen.spamhaus.org", true);
+        assert_eq!(
+            reputation.check().await,
+            vec![SourceIpEvent::Listed {
+                ip: "192.0.2.1".parse().unwrap(),
+                blocklists: vec!["zen.spamhaus.org".to_owned()],
+            }]
+        );
+        assert_eq!(rotation(&pool), ["192.0.2.2"].map(String::from).into());
+        assert_eq!(
+            reputation.report(),
+            ["192.0.2.1 listed zen.spamhaus.org", "192.0.2.2 healthy"]
+        );
+        assert!(reputation
+            .metrics()
+            .contains("vsmtp_outbound_source_ip_healthy{ip=\"192.0.2.1\"} 0\n"));
+
+        // still listed, nothing to report.
+        assert!(reputation.check().await.is_empty());
+
+        resolver.set("1.2.0.192.zen.spamhaus.org", false);
+        assert_eq!(
+            reputation.check().await,
+            vec![SourceIpEvent::Delisted {
+                ip: "192.0.2.1".parse().unwrap()
+            }]
+        );
+        assert_eq!(
+            rotation(&pool),
+            ["192.0.2.1", "192.0.2.2"].map(String::from).into()
+        );
+    }
+
+    #[derive(Clone, Default)]
+    struct Captured(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl std::io::Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // the subscriber is set for the current thread only, so the check must run on it.
+    #[tokio::test(flavor = "current_thread")]
+    async fn periodic_check_emits() {
+        let captured = Captured::default();
+        let _guard = tracing::subscriber::set_default(
+            tracing_subscriber::fmt()
+                .with_writer({
+                    let captured = captured.clone();
+                    move || captured.clone()
+                })
+                .with_ansi(false)
+                .with_target(true)
+                .finish(),
+        );
+
+        let pool = std::sync::Arc::new(SourceIpPool::new());
+        let (reputation, resolver) = reputation(&pool, &["spamhaus"]);
+        resolver.set("1.2.0.192.zen.spamhaus.org", true);
+
+        // the first check is immediate, the next one is never reached.
+        let _timeout = tokio::time::timeout(
+            std::time::Duration::from_millis(100),
+            std::sync::Arc::new(reputation).check_periodically(std::time::Duration::from_secs(60)),
+        )
+        .await;
+
+        let output = String::from_utf8(captured.0.lock().unwrap().clone()).unwrap();
+        let events = output
+            .lines()
+            .filter(|line| line.contains(TARGET))
+            .collect::<Vec<_>>();
+        assert_eq!(events.len(), 1, "{output}");
+        assert!(
+            events[0].contains("ip=192.0.2.1")
+                && events[0].contains("blocklists=[\"zen.spamhaus.org\"]"),
+            "{output}"
+        );
+    }
+
+    #[tokio::test]
+    async fn all_listed() {
+        let pool = std::sync::Arc::new(SourceIpPool::new());
+        let (reputation, resolver) = reputation(&pool, &["bl.example"]);
+
+        resolver.set("1.2.0.192.bl.example", true);
+        resolver.set("2.2.0.192.bl.example", true);
+        assert_eq!(reputation.check().await.len(), 2);
+
+        // the messages are deferred.
+        assert!(pool.next(None).is_err());
+    }
+
+    #[tokio::test]
+    async fn lookup_failure() {
+        let pool = std::sync::Arc::new(SourceIpPool::new());
+        let (reputation, resolver) = reputation(&pool, &["bl.example", "broken.example"]);
+
+        resolver.set("1.2.0.192.bl.example", true);
+        assert_eq!(reputation.check().await.len(), 1);
+
+        // the listing is unknown, the address stays out of the rotation.
+        resolver.set("1.2.0.192.bl.example", false);
+        assert!(reputation.check().await.is_empty());
+        assert_eq!(rotation(&pool), ["192.0.2.2"].map(String::from).into());
+    }
+}
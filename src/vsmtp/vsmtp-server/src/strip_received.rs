@@ -0,0 +1,205 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2022 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use crate::dkim_preservation::{fields, has_name, SignedFields};
+use vsmtp_common::{ContextFinished, Domain, TransactionType};
+use vsmtp_config::{field::FieldServerStripReceived, Config};
+use vsmtp_mail_parser::MessageBody;
+
+/// The hosts named in the `from` clause of a `Received` header, with their address literals.
+fn from_clause(value: &str) -> Vec<&str> {
+    let mut tokens = value
+        .split(|c: char| c.is_whitespace() || "()[];=".contains(c))
+        .filter(|token| !token.is_empty());
+
+    if !tokens
+        .next()
+        .map_or(false, |token| token.eq_ignore_ascii_case("from"))
+    {
+        return vec![];
+    }
+    tokens
+        .take_while(|token| !token.eq_ignore_ascii_case("by"))
+        .collect()
+}
+
+/// Does the `Received` header describe a hop from an internal host?
+fn is_internal(config: &FieldServerStripReceived, value: &str) -> bool {
+    from_clause(value).into_iter().any(|host| {
+        let literal = host
+            .get(..5)
+            .filter(|prefix| prefix.eq_ignore_ascii_case("ipv6:"))
+            .map_or(host, |_| &host[5..]);
+
+        literal.parse::<std::net::IpAddr>().map_or_else(
+            |_| {
+                host.parse::<Domain>().map_or(false, |host| {
+                    config
+                        .hostnames
+                        .iter()
+                        .any(|internal| internal.zone_of(&host))
+                })
+            },
+            |ip| config.networks.iter().any(|network| network.contains(ip)),
+        )
+    })
+}
+
+/// Remove the `Received` headers of the internal hops from a message relayed to
+/// other servers, keeping the outermost one.
+///
+/// Returns the copy of the message to send, or [`None`] if the message is unchanged.
+pub(crate) fn strip_received(
+    config: &Config,
+    ctx: &ContextFinished,
+    message: &MessageBody,
+) -> Option<MessageBody> {
+    let strip = config.server.strip_received.as_ref()?;
+    if !matches!(
+        ctx.rcpt_to.transaction_type,
+        TransactionType::Outgoing { .. } | TransactionType::Incoming(None)
+    ) {
+        return None;
+    }
+
+    if SignedFields::new(message).map_or(false, |signed| signed.covers("Received")) {
+        tracing::debug!("The Received headers are covered by a DKIM signature, they are kept.");
+        return None;
+    }
+
+    let fields = fields(message);
+    let count = fields.len();
+
+    let mut outermost = true;
+    let fields = fields
+        .into_iter()
+        .filter(|field| {
+            if !has_name(field, "Received") || std::mem::take(&mut outermost) {
+                return true;
+            }
+            let field = field.concat();
+            !field
+                .split_once(':')
+                .map_or(false, |(_, value)| is_internal(strip, value))
+        })
+        .collect::<Vec<_>>();
+
+    if fields.len() == count {
+        return None;
+    }
+    tracing::debug!(
+        removed = count - fields.len(),
+        "Removed the Received headers of the internal hops."
+    );
+
+    let mut message = message.clone();
+    message.set_raw_headers(fields.into_iter().flatten().collect());
+    Some(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vsmtp_test::config::{local_ctx, local_msg_with_headers, local_test};
+
+    fn config() -> Config {
+        let mut config = local_test();
+        config.server.strip_received = Some(FieldServerStripReceived {
+            networks: vec!["10.0.0.0/8".parse().unwrap(), "fd00::/8".parse().unwrap()],
+            hostnames: vec!["corp.example.com".parse().unwrap()],
+        });
+        config
+    }
+
+    fn outgoing() -> ContextFinished {
+        let mut ctx = local_ctx();
+        ctx.rcpt_to.transaction_type = TransactionType::Outgoing {
+            domain: "example.com".parse().unwrap(),
+        };
+        ctx
+    }
+
+    const OUTERMOST: &str =
+        "Received: from laptop.corp.example.com by mx.example.com with SMTP id 42; Mon, 2 Jan 2023 10:00:00 +0000";
+
+    #[test]
+    fn from_clauses() {
+        assert_eq!(
+            from_clause(" from mail.example.com (mail.example.com [192.0.2.1])\r\n\tby mx.example.com with ESMTPS id 1;"),
+            vec!["mail.example.com", "mail.example.com", "192.0.2.1"]
+        );
+        assert_eq!(
+            from_clause(" from [IPv6:fd00::1] (helo=foo) by bar"),
+            vec!["IPv6:fd00::1", "helo", "foo"]
+        );
+        assert!(from_clause(" by mx.example.com with local id 1;").is_empty());
+    }
+
+    #[test]
+    fn internal_hops() {
+        let message = local_msg_with_headers(&[
+            OUTERMOST,
+            "Received: from relay.corp.example.com (relay [10.1.2.3])\r\n\tby gateway.corp.example.com; Mon, 2 Jan 2023 09:59:59 +0000",
+            "Received: from mail.partner.org (mail.partner.org [192.0.2.1]) by relay.example.com; Mon, 2 Jan 2023 09:59:58 +0000",
+            "Received: from workstation ([IPv6:fd00::42]) by relay; Mon, 2 Jan 2023 09:59:57 +0000",
+            "Received: from corp.example.com.evil.org by relay; Mon, 2 Jan 2023 09:59:56 +0000",
+            "From: john@example.com",
+        ]);
+
+        let stripped = strip_received(&config(), &outgoing(), &message).unwrap();
+        assert_eq!(
+            *stripped.inner().raw_headers(),
+            vec![
+                format!("{OUTERMOST}\r\n"),
+                "Received: from mail.partner.org (mail.partner.org [192.0.2.1]) by relay.example.com; Mon, 2 Jan 2023 09:59:58 +0000\r\n".to_owned(),
+                "Received: from corp.example.com.evil.org by relay; Mon, 2 Jan 2023 09:59:56 +0000\r\n".to_owned(),
+                "From: john@example.com\r\n".to_owned(),
+            ]
+        );
+        // the queued message is untouched.
+        assert_eq!(message.count_header("Received"), 5);
+    }
+
+    #[test]
+    fn unchanged() {
+        let message = local_msg_with_headers(&[
+            OUTERMOST,
+            "Received: from mail.partner.org ([192.0.2.1]) by relay.example.com; Mon, 2 Jan 2023 09:59:58 +0000",
+            "From: john@example.com",
+        ]);
+        assert!(strip_received(&config(), &outgoing(), &message).is_none());
+
+        let internal = local_msg_with_headers(&[
+            OUTERMOST,
+            "Received: from relay.corp.example.com ([10.1.2.3]) by relay; Mon, 2 Jan 2023 09:59:58 +0000",
+        ]);
+        assert!(strip_received(&local_test(), &outgoing(), &internal).is_none());
+        // only the messages relayed to other servers.
+        assert!(strip_received(&config(), &local_ctx(), &internal).is_none());
+        assert!(strip_received(&config(), &outgoing(), &internal).is_some());
+    }
+
+    #[test]
+    fn covered_by_dkim() {
+        let message = local_msg_with_headers(&[
+            OUTERMOST,
+            "Received: from relay.corp.example.com ([10.1.2.3]) by relay; Mon, 2 Jan 2023 09:59:58 +0000",
+            "DKIM-Signature: v=1; a=rsa-sha256; c=relaxed/relaxed; d=example.com;\r\n s=app; h=from:received; bh=YWJj; b=YWJj",
+            "From: john@example.com",
+        ]);
+        assert!(strip_received(&config(), &outgoing(), &message).is_none());
+    }
+}
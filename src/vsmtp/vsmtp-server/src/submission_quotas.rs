@@ -0,0 +1,479 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2023 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use anyhow::Context;
+use std::collections::BTreeMap;
+use vsmtp_common::SubmissionQuota;
+use vsmtp_config::field::FieldServerSMTPAuthQuotas;
+
+/// Period in which the `burst` of recipients is refilled.
+const BURST_PERIOD: time::Duration = time::Duration::MINUTE;
+
+/// Recipients submitted by one authenticated user.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+struct Counters {
+    /// Recipients available in the bucket.
+    burst_available: f64,
+    #[serde(with = "time::serde::iso8601")]
+    burst_updated: time::OffsetDateTime,
+    /// Recipients submitted since `sustained_start`.
+    sustained_count: u64,
+    #[serde(with = "time::serde::iso8601")]
+    sustained_start: time::OffsetDateTime,
+}
+
+impl Counters {
+    fn new(quota: SubmissionQuota, now: time::OffsetDateTime) -> Self {
+        Self {
+            burst_available: f64::from(quota.burst),
+            burst_updated: now,
+            sustained_count: 0,
+            sustained_start: now,
+        }
+    }
+
+    /// Refill the bucket and start a new sustained period if the previous one is over.
+    fn update(
+        &mut self,
+        quota: SubmissionQuota,
+        sustained_period: std::time::Duration,
+        now: time::OffsetDateTime,
+    ) {
+        if now > self.burst_updated {
+            let elapsed = (now - self.burst_updated) / BURST_PERIOD;
+            self.burst_available =
+                f64::from(quota.burst).min(self.burst_available + elapsed * f64::from(quota.burst));
+            self.burst_updated = now;
+        }
+
+        if now >= self.sustained_start + sustained_period {
+            self.sustained_count = 0;
+            self.sustained_start = now;
+        }
+    }
+}
+
+#[derive(Debug, Default, serde::Serialize, serde::Deserialize)]
+struct State {
+    users: BTreeMap<String, Counters>,
+}
+
+/// Outcome of the check of a user's quota.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    /// The recipient is reserved for the user.
+    Accept,
+    /// The user has exhausted its burst, and must retry later.
+    Burst,
+    /// The user has exhausted its sustained limit.
+    Sustained,
+}
+
+/// Rate limits of the recipients submitted by the authenticated users.
+#[derive(Debug)]
+pub struct SubmissionQuotas {
+    parameters: FieldServerSMTPAuthQuotas,
+    filepath: std::path::PathBuf,
+    state: std::sync::Mutex<State>,
+}
+
+impl SubmissionQuotas {
+    /// Create the quotas, restoring the counters persisted at `filepath` if any.
+    #[must_use]
+    pub fn new(parameters: &FieldServerSMTPAuthQuotas, filepath: std::path::PathBuf) -> Self {
+        let state = match std::fs::read(&filepath) {
+            Ok(content) => serde_json::from_slice(&content)
+                .map_err(|error| {
+                    tracing::warn!(%error, path = %filepath.display(), "Submission quotas are corrupted, starting over.");
+                })
+                .ok(),
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => None,
+            Err(error) => {
+                tracing::warn!(%error, path = %filepath.display(), "Submission quotas cannot be read, starting over.");
+                None
+            }
+        };
+
+        Self {
+            parameters: parameters.clone(),
+            filepath,
+            state: std::sync::Mutex::new(state.unwrap_or_default()),
+        }
+    }
+
+    /// The limits of the user: the ones set by the rules, else the override
+    /// of the configuration, else the default ones.
+    #[must_use]
+    pub fn limits(&self, identity: &str, rules: Option<SubmissionQuota>) -> SubmissionQuota {
+        rules
+            .or_else(|| self.parameters.overrides.get(identity).copied())
+            .unwrap_or(SubmissionQuota {
+                burst: self.parameters.burst,
+                sustained: self.parameters.sustained,
+            })
+    }
+
+    /// The reply to a user over its burst limit.
+    #[must_use]
+    pub const fn burst_reply(&self) -> &vsmtp_common::Reply {
+        &self.parameters.burst_reply
+    }
+
+    fn with_counters<R>(
+        &self,
+        identity: &str,
+        quota: SubmissionQuota,
+        now: time::OffsetDateTime,
+        f: impl FnOnce(&mut Counters) -> R,
+    ) -> R {
+        let mut guard = self
+            .state
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+
+        let counters = guard
+            .users
+            .entry(identity.to_owned())
+            .or_insert_with(|| Counters::new(quota, now));
+        counters.update(quota, self.parameters.sustained_period, now);
+        f(counters)
+    }
+
+    /// Reserve a recipient for the user, refused if the user has exhausted its quota.
+    ///
+    /// The recipient is counted at once, so the parallel sessions of the user share
+    /// its quota, and must be given back with [`Self::release`] if it is not submitted.
+    #[must_use]
+    pub fn reserve(&self, identity: &str, quota: SubmissionQuota) -> Verdict {
+        self.reserve_at(identity, quota, vsmtp_common::clock::now())
+    }
+
+    fn reserve_at(
+        &self,
+        identity: &str,
+        quota: SubmissionQuota,
+        now: time::OffsetDateTime,
+    ) -> Verdict {
+        self.with_counters(identity, quota, now, |counters| {
+            if counters.sustained_count >= u64::from(quota.sustained) {
+                Verdict::Sustained
+            } else if counters.burst_available < 1.0 {
+                Verdict::Burst
+            } else {
+                counters.burst_available -= 1.0;
+                counters.sustained_count += 1;
+                Verdict::Accept
+            }
+        })
+    }
+
+    /// Give back the recipients reserved for a transaction which has been reset or aborted.
+    pub fn release(&self, identity: &str, quota: SubmissionQuota, recipients: usize) {
+        self.release_at(identity, quota, recipients, vsmtp_common::clock::now());
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    fn release_at(
+        &self,
+        identity: &str,
+        quota: SubmissionQuota,
+        recipients: usize,
+        now: time::OffsetDateTime,
+    ) {
+        self.with_counters(identity, quota, now, |counters| {
+            counters.burst_available =
+                f64::from(quota.burst).min(counters.burst_available + recipients as f64);
+            counters.sustained_count = counters.sustained_count.saturating_sub(recipients as u64);
+        });
+    }
+
+    /// Forget the counters of the user, returns false if the user had none.
+    pub fn reset(&self, identity: &str) -> bool {
+        self.state
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .users
+            .remove(identity)
+            .is_some()
+    }
+
+    /// Write the counters to the disk.
+    ///
+    /// # Errors
+    ///
+    /// * failed to serialize or write the counters
+    pub fn persist(&self) -> anyhow::Result<()> {
+        let content = serde_json::to_vec(
+            &*self
+                .state
+                .lock()
+                .unwrap_or_else(std::sync::PoisonError::into_inner),
+        )?;
+
+        let tmp = self.filepath.with_extension("json.tmp");
+        std::fs::write(&tmp, content)
+            .and_then(|()| std::fs::rename(&tmp, &self.filepath))
+            .with_context(|| format!("Cannot write '{}'", self.filepath.display()))
+    }
+
+    /// Persist the counters with the period, until the runtime is stopped.
+    pub async fn persist_periodically(self: std::sync::Arc<Self>) {
+        let mut interval = tokio::time::interval(self.parameters.persist_period);
+        interval.tick().await;
+
+        loop {
+            interval.tick().await;
+            if let Err(error) = self.persist() {
+                tracing::warn!(%error, "Submission quotas persistence failure.");
+            }
+        }
+    }
+
+    /// Describe the counters of the user, against the limits of the configuration.
+    #[must_use]
+    pub fn report(&self, identity: &str) -> Vec<String> {
+        self.report_at(identity, vsmtp_common::clock::now())
+    }
+
+    fn report_at(&self, identity: &str, now: time::OffsetDateTime) -> Vec<String> {
+        let quota = self.limits(identity, None);
+        let mut guard = self
+            .state
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+
+        let Some(counters) = guard.users.get_mut(identity) else {
+            return vec![];
+        };
+        counters.update(quota, self.parameters.sustained_period, now);
+
+        vec![format!(
+            "user {identity} burst={:.0}/{} sustained={}/{} since={}",
+            counters.burst_available.floor(),
+            quota.burst,
+            counters.sustained_count,
+            quota.sustained,
+            counters
+                .sustained_start
+                .format(&time::format_description::well_known::Rfc3339)
+                .unwrap_or_default(),
+        )]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quotas() -> SubmissionQuotas {
+        SubmissionQuotas::new(
+            &FieldServerSMTPAuthQuotas {
+                burst: 10,
+                sustained: 25,
+                sustained_period: std::time::Duration::from_secs(60 * 60),
+                overrides: [(
+                    "newsletter".to_owned(),
+                    SubmissionQuota {
+                        burst: 100,
+                        sustained: 1000,
+                    },
+                )]
+                .into_iter()
+                .collect(),
+                persist_period: std::time::Duration::from_secs(60),
+                burst_reply: "452 4.7.1 Submission rate exceeded, try again later\r\n"
+                    .parse()
+                    .unwrap(),
+            },
+            std::env::temp_dir().join(format!("submission-quotas-{}.json", uuid::Uuid::new_v4())),
+        )
+    }
+
+    fn now() -> time::OffsetDateTime {
+        time::OffsetDateTime::from_unix_timestamp(1_700_000_000).unwrap()
+    }
+
+    fn reserve_n(
+        quotas: &SubmissionQuotas,
+        identity: &str,
+        quota: SubmissionQuota,
+        recipients: usize,
+        now: time::OffsetDateTime,
+    ) {
+        for _ in 0..recipients {
+            assert_eq!(quotas.reserve_at(identity, quota, now), Verdict::Accept);
+        }
+    }
+
+    #[test]
+    fn burst_refill() {
+        let quotas = quotas();
+        let quota = quotas.limits("john", None);
+        let now = now();
+
+        reserve_n(&quotas, "john", quota, 10, now);
+        assert_eq!(quotas.reserve_at("john", quota, now), Verdict::Burst);
+
+        // one recipient per 6 seconds
+        assert_eq!(
+            quotas.reserve_at("john", quota, now + time::Duration::seconds(3)),
+            Verdict::Burst
+        );
+        assert_eq!(
+            quotas.reserve_at("john", quota, now + time::Duration::seconds(7)),
+            Verdict::Accept
+        );
+        assert_eq!(
+            quotas.reserve_at("john", quota, now + time::Duration::seconds(7)),
+            Verdict::Burst
+        );
+
+        // the bucket is never filled beyond the burst
+        assert_eq!(
+            quotas.report_at("john", now + time::Duration::minutes(30)),
+            ["user john burst=10/10 sustained=11/25 since=2023-11-14T22:13:20Z"]
+        );
+
+        // other users have their own counters
+        assert_eq!(quotas.reserve_at("jenny", quota, now), Verdict::Accept);
+    }
+
+    #[test]
+    fn release() {
+        let quotas = quotas();
+        let quota = quotas.limits("john", None);
+        let now = now();
+
+        // a transaction reset gives back its recipients
+        reserve_n(&quotas, "john", quota, 10, now);
+        assert_eq!(quotas.reserve_at("john", quota, now), Verdict::Burst);
+        quotas.release_at("john", quota, 4, now);
+        assert_eq!(
+            quotas.report_at("john", now),
+            ["user john burst=4/10 sustained=6/25 since=2023-11-14T22:13:20Z"]
+        );
+        reserve_n(&quotas, "john", quota, 4, now);
+        assert_eq!(quotas.reserve_at("john", quota, now), Verdict::Burst);
+
+        // never beyond the limits
+        quotas.release_at("john", quota, 100, now + time::Duration::seconds(30));
+        assert_eq!(
+            quotas.report_at("john", now + time::Duration::seconds(30)),
+            ["user john burst=10/10 sustained=0/25 since=2023-11-14T22:13:20Z"]
+        );
+    }
+
+    #[test]
+    fn sustained_exhaustion() {
+        let quotas = quotas();
+        let quota = quotas.limits("john", None);
+        let mut now = now();
+
+        for _ in 0..5 {
+            reserve_n(&quotas, "john", quota, 5, now);
+            now += time::Duration::minutes(1);
+        }
+        assert_eq!(quotas.reserve_at("john", quota, now), Verdict::Sustained);
+        assert_eq!(
+            quotas.reserve_at("john", quota, now + time::Duration::minutes(30)),
+            Verdict::Sustained
+        );
+
+        // a new period starts an hour after the first message
+        assert_eq!(
+            quotas.reserve_at("john", quota, now + time::Duration::minutes(55)),
+            Verdict::Accept
+        );
+
+        assert!(quotas.reset("john"));
+        assert!(!quotas.reset("john"));
+        assert_eq!(
+            quotas.reserve_at("john", quota, now + time::Duration::minutes(55)),
+            Verdict::Accept
+        );
+    }
+
+    #[test]
+    fn overrides() {
+        let quotas = quotas();
+        let now = now();
+
+        assert_eq!(
+            quotas.limits("john", None),
+            SubmissionQuota {
+                burst: 10,
+                sustained: 25
+            }
+        );
+        let newsletter = quotas.limits("newsletter", None);
+        assert_eq!(
+            newsletter,
+            SubmissionQuota {
+                burst: 100,
+                sustained: 1000
+            }
+        );
+
+        reserve_n(&quotas, "newsletter", newsletter, 50, now);
+        assert_eq!(
+            quotas.reserve_at("newsletter", newsletter, now),
+            Verdict::Accept
+        );
+
+        // the rules take precedence over the configuration
+        let rules = SubmissionQuota {
+            burst: 1,
+            sustained: 1,
+        };
+        assert_eq!(quotas.limits("newsletter", Some(rules)), rules);
+        assert_eq!(
+            quotas.reserve_at("newsletter", rules, now),
+            Verdict::Sustained
+        );
+    }
+
+    #[test]
+    fn persistence() {
+        let quotas = quotas();
+        let quota = quotas.limits("john", None);
+        let now = now();
+
+        reserve_n(&quotas, "john", quota, 10, now);
+        quotas.persist().unwrap();
+
+        // a restart does not give the user a new quota
+        let restored = SubmissionQuotas::new(&quotas.parameters, quotas.filepath.clone());
+        std::fs::remove_file(&quotas.filepath).unwrap();
+        assert_eq!(restored.reserve_at("john", quota, now), Verdict::Burst);
+        reserve_n(
+            &restored,
+            "john",
+            quota,
+            10,
+            now + time::Duration::minutes(5),
+        );
+        assert_eq!(
+            restored.report_at("john", now + time::Duration::minutes(5)),
+            ["user john burst=0/10 sustained=20/25 since=2023-11-14T22:13:20Z"]
+        );
+
+        // corrupted counters are discarded
+        std::fs::write(&quotas.filepath, b"{").unwrap();
+        let corrupted = SubmissionQuotas::new(&quotas.parameters, quotas.filepath.clone());
+        std::fs::remove_file(&quotas.filepath).unwrap();
+        assert!(corrupted.report_at("john", now).is_empty());
+    }
+}
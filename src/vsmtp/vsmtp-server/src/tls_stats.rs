@@ -0,0 +1,548 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2023 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use anyhow::Context;
+use std::collections::BTreeMap;
+use vsmtp_common::{ContextFinished, TlsProperties};
+use vsmtp_config::field::FieldServerTlsStatistics;
+
+/// Protocol of the messages received without TLS.
+const CLEAR: &str = "clear";
+
+/// Messages received by one sender domain or client network.
+#[derive(Debug, Default, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+struct Counters {
+    /// Messages received in the current window, per protocol (`clear` or `<version>/<cipher>`).
+    current: BTreeMap<String, u64>,
+    /// Messages received in the previous window.
+    previous: BTreeMap<String, u64>,
+    /// Number of consecutive messages received with TLS.
+    streak: u32,
+}
+
+impl Counters {
+    /// Messages received over the current and the previous window, per protocol.
+    fn total(&self) -> BTreeMap<&str, u64> {
+        let mut total = BTreeMap::new();
+        for (protocol, count) in self.current.iter().chain(&self.previous) {
+            *total.entry(protocol.as_str()).or_default() += count;
+        }
+        total
+    }
+
+    fn rotate(&mut self, keep_current: bool) {
+        self.previous = std::mem::take(&mut self.current);
+        if !keep_current {
+            self.previous.clear();
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.current.is_empty() && self.previous.is_empty()
+    }
+
+    /// Messages received over the current and the previous window.
+    fn count(&self) -> u64 {
+        self.current.values().chain(self.previous.values()).sum()
+    }
+}
+
+/// The counters of `key`, forgetting the ones with the fewest messages to make room
+/// if `entries` already has `entries_max` keys.
+fn entry<'a>(
+    entries: &'a mut BTreeMap<String, Counters>,
+    key: &str,
+    entries_max: usize,
+) -> &'a mut Counters {
+    if !entries.contains_key(key) && entries.len() >= entries_max {
+        let forgotten = entries
+            .iter()
+            .min_by_key(|(_, counters)| counters.count())
+            .map(|(key, _)| key.clone());
+        if let Some(forgotten) = forgotten {
+            tracing::debug!(
+                key = %forgotten,
+                "TLS statistics full, forgetting the entry."
+            );
+            entries.remove(&forgotten);
+        }
+    }
+    entries.entry(key.to_owned()).or_default()
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct State {
+    #[serde(with = "time::serde::iso8601")]
+    window_start: time::OffsetDateTime,
+    domains: BTreeMap<String, Counters>,
+    networks: BTreeMap<String, Counters>,
+}
+
+/// A sender domain sending in clear after a series of messages received with TLS.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Downgrade {
+    /// Domain of the `MAIL FROM`.
+    pub domain: String,
+    /// Number of consecutive messages previously received with TLS.
+    pub streak: u32,
+}
+
+/// Statistics of the use of TLS by the inbound clients, per sender domain
+/// and per client network.
+#[derive(Debug)]
+pub struct TlsStatistics {
+    alert_threshold: u32,
+    window: std::time::Duration,
+    entries_max: usize,
+    filepath: std::path::PathBuf,
+    state: std::sync::Mutex<State>,
+}
+
+/// Network of the client, `/24` in IPv4 and `/64` in IPv6.
+fn network(ip: std::net::IpAddr) -> String {
+    match ip {
+        std::net::IpAddr::V4(ip) => {
+            let [a, b, c, _] = ip.octets();
+            format!("{}/24", std::net::Ipv4Addr::new(a, b, c, 0))
+        }
+        std::net::IpAddr::V6(ip) => {
+            let [a, b, c, d, ..] = ip.segments();
+            format!("{}/64", std::net::Ipv6Addr::new(a, b, c, d, 0, 0, 0, 0))
+        }
+    }
+}
+
+fn protocol(tls: Option<&TlsProperties>) -> String {
+    tls.map_or_else(
+        || CLEAR.to_owned(),
+        |tls| format!("{:?}/{:?}", tls.protocol_version.0, tls.cipher_suite.0),
+    )
+}
+
+fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+impl TlsStatistics {
+    /// Create the statistics, restoring the counters persisted at `filepath` if any.
+    #[must_use]
+    pub fn new(parameters: &FieldServerTlsStatistics, filepath: std::path::PathBuf) -> Self {
+        let state = match std::fs::read(&filepath) {
+            Ok(content) => serde_json::from_slice(&content)
+                .map_err(|error| {
+                    tracing::warn!(%error, path = %filepath.display(), "TLS statistics are corrupted, starting over.");
+                })
+                .ok(),
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => None,
+            Err(error) => {
+                tracing::warn!(%error, path = %filepath.display(), "TLS statistics cannot be read, starting over.");
+                None
+            }
+        };
+
+        Self {
+            alert_threshold: parameters.alert_threshold,
+            window: parameters.window,
+            entries_max: parameters.entries_max,
+            filepath,
+            state: std::sync::Mutex::new(state.unwrap_or_else(|| State {
+                window_start: vsmtp_common::clock::now(),
+                domains: BTreeMap::new(),
+                networks: BTreeMap::new(),
+            })),
+        }
+    }
+
+    fn with_state<R>(&self, now: time::OffsetDateTime, f: impl FnOnce(&mut State) -> R) -> R {
+        let mut guard = self
+            .state
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        let state = &mut *guard;
+
+        if now >= state.window_start + self.window {
+            let keep_current = now < state.window_start + self.window * 2;
+            for counters in state
+                .domains
+                .values_mut()
+                .chain(state.networks.values_mut())
+            {
+                counters.rotate(keep_current);
+            }
+            state.domains.retain(|_, counters| !counters.is_empty());
+            state.networks.retain(|_, counters| !counters.is_empty());
+            state.window_start = now;
+        }
+
+        f(state)
+    }
+
+    /// Account a message received, returns the alert if the sender domain
+    /// has stopped using TLS.
+    pub fn record(&self, ctx: &ContextFinished) -> Option<Downgrade> {
+        let sender_domain = ctx.mail_from.reverse_path.as_ref().map(|reverse_path| {
+            reverse_path
+                .domain_ref()
+                .to_ascii()
+                .trim_end_matches('.')
+                .to_lowercase()
+        });
+
+        let downgrade = self.record_at(
+            sender_domain.as_deref(),
+            ctx.connect.client_addr.ip(),
+            ctx.connect.tls.as_ref(),
+            vsmtp_common::clock::now(),
+        );
+
+        if let Some(Downgrade { domain, streak }) = &downgrade {
+            tracing::warn!(
+                sender_domain = %domain,
+                client = %ctx.connect.client_addr,
+                streak,
+                "Message received in clear from a sender domain previously using TLS, possible STARTTLS downgrade."
+            );
+        }
+        downgrade
+    }
+
+    fn record_at(
+        &self,
+        sender_domain: Option<&str>,
+        client_ip: std::net::IpAddr,
+        tls: Option<&TlsProperties>,
+        now: time::OffsetDateTime,
+    ) -> Option<Downgrade> {
+        let protocol = protocol(tls);
+
+        self.with_state(now, |state| {
+            let counters = entry(&mut state.networks, &network(client_ip), self.entries_max);
+            *counters.current.entry(protocol.clone()).or_default() += 1;
+
+            // NOTE: the null sender (bounces) has no domain to follow.
+            let domain = sender_domain?;
+            let counters = entry(&mut state.domains, domain, self.entries_max);
+            *counters.current.entry(protocol).or_default() += 1;
+
+            if tls.is_some() {
+                counters.streak = counters.streak.saturating_add(1);
+                return None;
+            }
+
+            let streak = std::mem::take(&mut counters.streak);
+            (self.alert_threshold != 0 && streak >= self.alert_threshold).then(|| Downgrade {
+                domain: domain.to_owned(),
+                streak,
+            })
+        })
+    }
+
+    /// Write the counters to the disk.
+    ///
+    /// # Errors
+    ///
+    /// * failed to serialize or write the counters
+    pub fn persist(&self) -> anyhow::Result<()> {
+        let content = self.with_state(vsmtp_common::clock::now(), |state| {
+            serde_json::to_vec(state)
+        })?;
+
+        let tmp = self.filepath.with_extension("json.tmp");
+        std::fs::write(&tmp, content)
+            .and_then(|()| std::fs::rename(&tmp, &self.filepath))
+            .with_context(|| format!("Cannot write '{}'", self.filepath.display()))
+    }
+
+    /// Persist the counters with the period, until the runtime is stopped.
+    pub async fn persist_periodically(self: std::sync::Arc<Self>, period: std::time::Duration) {
+        let mut interval = tokio::time::interval(period);
+        interval.tick().await;
+
+        loop {
+            interval.tick().await;
+            if let Err(error) = self.persist() {
+                tracing::warn!(%error, "TLS statistics persistence failure.");
+            }
+        }
+    }
+
+    /// Render the counters in the Prometheus text format.
+    #[must_use]
+    pub fn metrics(&self) -> String {
+        self.with_state(vsmtp_common::clock::now(), |state| {
+            let mut output = String::new();
+
+            for (name, label, help, entries) in [
+                (
+                    "vsmtp_inbound_messages_by_sender_domain",
+                    "sender_domain",
+                    "Messages received per sender domain and protocol.",
+                    &state.domains,
+                ),
+                (
+                    "vsmtp_inbound_messages_by_client_network",
+                    "client_network",
+                    "Messages received per client network and protocol.",
+                    &state.networks,
+                ),
+            ] {
+                output.push_str(&format!("# HELP {name} {help}\n# TYPE {name} gauge\n"));
+
+                for (key, counters) in entries {
+                    for (protocol, count) in counters.total() {
+                        let (version, cipher) = protocol.split_once('/').unwrap_or_default();
+                        output.push_str(&format!(
+                            "{name}{{{label}=\"{}\",tls=\"{}\",version=\"{version}\",cipher=\"{cipher}\"}} {count}\n",
+                            escape_label(key),
+                            protocol != CLEAR,
+                        ));
+                    }
+                }
+            }
+
+            output
+        })
+    }
+
+    /// Describe the counters, one line per sender domain and client network,
+    /// restricted to `filter` if provided.
+    #[must_use]
+    pub fn report(&self, filter: Option<&str>) -> Vec<String> {
+        self.with_state(vsmtp_common::clock::now(), |state| {
+            [("domain", &state.domains), ("network", &state.networks)]
+                .into_iter()
+                .flat_map(|(kind, entries)| {
+                    entries
+                        .iter()
+                        .filter(|(key, _)| filter.map_or(true, |filter| filter == key.as_str()))
+                        .map(move |(key, counters)| {
+                            let mut line = format!("{kind} {key}");
+                            if kind == "domain" {
+                                line.push_str(&format!(" streak={}", counters.streak));
+                            }
+                            for (protocol, count) in counters.total() {
+                                line.push_str(&format!(" {protocol}={count}"));
+                            }
+                            line
+                        })
+                })
+                .collect()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio_rustls::rustls;
+    use vsmtp_common::{CipherSuite, ProtocolVersion};
+
+    fn statistics(alert_threshold: u32) -> TlsStatistics {
+        TlsStatistics::new(
+            &FieldServerTlsStatistics {
+                alert_threshold,
+                window: std::time::Duration::from_secs(60 * 60),
+                ..Default::default()
+            },
+            std::env::temp_dir().join(format!("tls-statistics-{}.json", uuid::Uuid::new_v4())),
+        )
+    }
+
+    fn tls() -> TlsProperties {
+        TlsProperties {
+            protocol_version: ProtocolVersion(rustls::ProtocolVersion::TLSv1_3),
+            cipher_suite: CipherSuite(rustls::CipherSuite::TLS13_AES_256_GCM_SHA384),
+            peer_certificates: None,
+            alpn_protocol: None,
+        }
+    }
+
+    const CLIENT: std::net::IpAddr = std::net::IpAddr::V4(std::net::Ipv4Addr::new(192, 0, 2, 42));
+
+    #[test]
+    fn networks() {
+        assert_eq!(network(CLIENT), "192.0.2.0/24");
+        assert_eq!(
+            network("2001:db8:1:2:3:4:5:6".parse().unwrap()),
+            "2001:db8:1:2::/64"
+        );
+    }
+
+    #[test]
+    fn downgrade_alert() {
+        let statistics = statistics(3);
+        let now = time::OffsetDateTime::now_utc();
+        let tls = tls();
+
+        // alternating sessions never build a streak
+        for _ in 0..5 {
+            assert_eq!(
+                statistics.record_at(Some("example.com"), CLIENT, Some(&tls), now),
+                None
+            );
+            assert_eq!(
+                statistics.record_at(Some("example.com"), CLIENT, None, now),
+                None
+            );
+        }
+
+        for _ in 0..2 {
+            statistics.record_at(Some("example.com"), CLIENT, Some(&tls), now);
+        }
+        assert_eq!(
+            statistics.record_at(Some("example.com"), CLIENT, None, now),
+            None
+        );
+
+        for _ in 0..3 {
+            statistics.record_at(Some("example.com"), CLIENT, Some(&tls), now);
+        }
+        // another domain is followed separately
+        assert_eq!(
+            statistics.record_at(Some("example.org"), CLIENT, None, now),
+            None
+        );
+        assert_eq!(
+            statistics.record_at(Some("example.com"), CLIENT, None, now),
+            Some(Downgrade {
+                domain: "example.com".to_owned(),
+                streak: 3
+            })
+        );
+        // the streak is reset
+        assert_eq!(
+            statistics.record_at(Some("example.com"), CLIENT, None, now),
+            None
+        );
+        // the null sender is only accounted in the network
+        assert_eq!(statistics.record_at(None, CLIENT, None, now), None);
+
+        assert_eq!(
+            statistics.report(Some("example.com")),
+            ["domain example.com streak=0 TLSv1_3/TLS13_AES_256_GCM_SHA384=10 clear=8"]
+        );
+        assert_eq!(
+            statistics.report(Some("192.0.2.0/24")),
+            ["network 192.0.2.0/24 TLSv1_3/TLS13_AES_256_GCM_SHA384=10 clear=10"]
+        );
+    }
+
+    #[test]
+    fn entries_max() {
+        let statistics = TlsStatistics::new(
+            &FieldServerTlsStatistics {
+                entries_max: 2,
+                ..Default::default()
+            },
+            std::env::temp_dir().join(format!("tls-statistics-{}.json", uuid::Uuid::new_v4())),
+        );
+        let now = time::OffsetDateTime::now_utc();
+
+        for _ in 0..3 {
+            statistics.record_at(Some("example.com"), CLIENT, Some(&tls()), now);
+        }
+        statistics.record_at(Some("example.org"), CLIENT, None, now);
+        // the domain with the fewest messages makes room for the new one.
+        statistics.record_at(
+            Some("example.net"),
+            std::net::IpAddr::V4(std::net::Ipv4Addr::new(198, 51, 100, 1)),
+            None,
+            now,
+        );
+        statistics.record_at(
+            Some("example.net"),
+            std::net::IpAddr::V4(std::net::Ipv4Addr::new(203, 0, 113, 1)),
+            None,
+            now,
+        );
+
+        assert_eq!(
+            statistics.report(None),
+            [
+                "domain example.com streak=3 TLSv1_3/TLS13_AES_256_GCM_SHA384=3",
+                "domain example.net streak=0 clear=2",
+                "network 192.0.2.0/24 TLSv1_3/TLS13_AES_256_GCM_SHA384=3 clear=1",
+                "network 203.0.113.0/24 clear=1",
+            ]
+        );
+    }
+
+    #[test]
+    fn window() {
+        let statistics = statistics(5);
+        let now = time::OffsetDateTime::now_utc();
+
+        statistics.record_at(Some("example.com"), CLIENT, None, now);
+        statistics.record_at(
+            Some("example.com"),
+            CLIENT,
+            None,
+            now + time::Duration::minutes(61),
+        );
+        assert_eq!(
+            statistics.report(Some("example.com")),
+            ["domain example.com streak=0 clear=2"]
+        );
+
+        // the first message is out of the previous window
+        statistics.record_at(
+            Some("example.com"),
+            CLIENT,
+            None,
+            now + time::Duration::minutes(122),
+        );
+        assert_eq!(
+            statistics.report(Some("example.com")),
+            ["domain example.com streak=0 clear=2"]
+        );
+
+        // nothing received for two windows
+        statistics.record_at(
+            Some("example.org"),
+            CLIENT,
+            None,
+            now + time::Duration::minutes(300),
+        );
+        assert!(statistics.report(Some("example.com")).is_empty());
+    }
+
+    #[test]
+    fn persistence_and_metrics() {
+        let statistics = statistics(5);
+        statistics.record_at(
+            Some("example.com"),
+            CLIENT,
+            Some(&tls()),
+            time::OffsetDateTime::now_utc(),
+        );
+        statistics.persist().unwrap();
+
+        let restored = TlsStatistics::new(
+            &FieldServerTlsStatistics::default(),
+            statistics.filepath.clone(),
+        );
+        assert_eq!(restored.report(None), statistics.report(None));
+        std::fs::remove_file(&statistics.filepath).unwrap();
+
+        let metrics = restored.metrics();
+        assert!(metrics.contains(
+            "vsmtp_inbound_messages_by_sender_domain{sender_domain=\"example.com\",tls=\"true\",version=\"TLSv1_3\",cipher=\"TLS13_AES_256_GCM_SHA384\"} 1\n"
+        ));
+        assert!(metrics.contains(
+            "vsmtp_inbound_messages_by_client_network{client_network=\"192.0.2.0/24\",tls=\"true\",version=\"TLSv1_3\",cipher=\"TLS13_AES_256_GCM_SHA384\"} 1\n"
+        ));
+    }
+}
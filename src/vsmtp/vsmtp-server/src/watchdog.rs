@@ -0,0 +1,310 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2023 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+
+//! Isolation of the messages whose processing panicked.
+//!
+//! A panic in the processing of a message (a bug in a plugin called by the rules
+//! for instance) would leave the message in its queue, and make it panic again at
+//! each retry. The message is instead moved to the dead queue after the first panic,
+//! its recipients failed with [`Queuer::ProcessingPanic`].
+
+use vqueue::{GenericQueueManager, QueueID};
+use vsmtp_common::transfer::{self, error::Queuer};
+
+/// Run the processing of the message `msg_uuid`, stored in `queue`, in a task of its own.
+///
+/// Return the result of the processing, or [`None`] if it did not complete.
+///
+/// If the task panics, the panic is logged, counted in `panics`, and the message
+/// is moved to the dead queue. The caller is never unwound.
+pub(crate) async fn catch_panic<Q, F>(
+    queue_manager: &Q,
+    queue: &QueueID,
+    msg_uuid: &uuid::Uuid,
+    panics: &std::sync::atomic::AtomicU64,
+    processing: F,
+) -> Option<anyhow::Result<()>>
+where
+    Q: GenericQueueManager + Sized + 'static,
+    F: std::future::Future<Output = anyhow::Result<()>> + Send + 'static,
+{
+    let payload = match tokio::spawn(processing).await {
+        Ok(result) => return Some(result),
+        Err(error) if error.is_panic() => error.into_panic(),
+        Err(error) => {
+            tracing::warn!(uuid = %msg_uuid, %error, "Processing cancelled.");
+            return None;
+        }
+    };
+
+    let message = payload
+        .downcast_ref::<&str>()
+        .map(|message| (*message).to_owned())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_default();
+    tracing::error!(uuid = %msg_uuid, %queue, panic = %message, "Processing panicked, moving the message to the dead queue.");
+    panics.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+
+    if let Err(error) = isolate(queue_manager, queue, msg_uuid).await {
+        tracing::error!(uuid = %msg_uuid, %queue, %error, "Failed to move the message to the dead queue.");
+    }
+    None
+}
+
+/// Move the message to the dead queue, its recipients not sent yet failed.
+async fn isolate<Q: GenericQueueManager + Sized + 'static>(
+    queue_manager: &Q,
+    queue: &QueueID,
+    msg_uuid: &uuid::Uuid,
+) -> anyhow::Result<()> {
+    // NOTE: the message may have been moved before the panic, and is then left as is.
+    let mut ctx = queue_manager.get_ctx(queue, msg_uuid).await?;
+    for rcpt in ctx.rcpt_to.delivery.values_mut().flatten() {
+        if !matches!(rcpt.1, transfer::Status::Sent { .. }) {
+            rcpt.1 = transfer::Status::failed(Queuer::ProcessingPanic);
+        }
+    }
+
+    queue_manager.move_to(queue, &QueueID::Dead, &ctx).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{scheduler, working, ProcessMessage};
+    use vsmtp_common::transport::{AbstractTransport, WrapperSerde};
+    use vsmtp_config::DnsResolvers;
+    use vsmtp_delivery::Forward;
+    use vsmtp_rule_engine::RuleEngine;
+    use vsmtp_test::config::{local_ctx, local_msg, local_test};
+
+    fn queue_manager() -> std::sync::Arc<vqueue::temp::QueueManager> {
+        vqueue::temp::QueueManager::init(
+            std::sync::Arc::new(local_test()),
+            vec![Forward::get_symbol()],
+        )
+        .unwrap()
+    }
+
+    async fn queued(queue_manager: &vqueue::temp::QueueManager) -> uuid::Uuid {
+        let mut ctx = local_ctx();
+        ctx.rcpt_to.delivery.insert(
+            WrapperSerde::Ready(std::sync::Arc::new(Forward::new(
+                "127.0.0.1:25".parse().unwrap(),
+            ))),
+            vec![
+                (
+                    "sent@testserver.com".parse().unwrap(),
+                    transfer::Status::sent(),
+                ),
+                (
+                    "waiting@testserver.com".parse().unwrap(),
+                    transfer::Status::default(),
+                ),
+            ],
+        );
+        queue_manager
+            .write_ctx(&QueueID::Working, &ctx)
+            .await
+            .unwrap();
+        ctx.mail_from.message_uuid
+    }
+
+    #[tokio::test]
+    async fn panic_isolated() {
+        let queue_manager = queue_manager();
+        let panics = std::sync::atomic::AtomicU64::new(0);
+
+        let poisoned = queued(&queue_manager).await;
+        assert!(catch_panic(
+            queue_manager.as_ref(),
+            &QueueID::Working,
+            &poisoned,
+            &panics,
+            async { panic!("bug in a plugin") },
+        )
+        .await
+        .is_none());
+
+        assert_eq!(panics.load(std::sync::atomic::Ordering::Relaxed), 1);
+        queue_manager
+            .get_ctx(&QueueID::Working, &poisoned)
+            .await
+            .unwrap_err();
+        let ctx = queue_manager
+            .get_ctx(&QueueID::Dead, &poisoned)
+            .await
+            .unwrap();
+        let statuses = ctx
+            .rcpt_to
+            .delivery
+            .values()
+            .flatten()
+            .map(|(rcpt, status)| (rcpt.to_string(), status.clone()))
+            .collect::<std::collections::BTreeMap<_, _>>();
+        assert!(matches!(
+            statuses["sent@testserver.com"],
+            transfer::Status::Sent { .. }
+        ));
+        assert!(matches!(
+            &statuses["waiting@testserver.com"],
+            transfer::Status::Failed { error }
+                if matches!(error.variant(), transfer::error::Variant::Queuer(Queuer::ProcessingPanic))
+        ));
+
+        // the next messages are processed as usual.
+        let healthy = queued(&queue_manager).await;
+        catch_panic(
+            queue_manager.as_ref(),
+            &QueueID::Working,
+            &healthy,
+            &panics,
+            async { Ok(()) },
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        let error = catch_panic(
+            queue_manager.as_ref(),
+            &QueueID::Working,
+            &healthy,
+            &panics,
+            async { anyhow::bail!("delivery failed") },
+        )
+        .await
+        .unwrap()
+        .unwrap_err();
+        assert_eq!(error.to_string(), "delivery failed");
+
+        assert_eq!(panics.load(std::sync::atomic::Ordering::Relaxed), 1);
+        queue_manager
+            .get_ctx(&QueueID::Working, &healthy)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn moved_before_panic() {
+        let queue_manager = queue_manager();
+        let panics = std::sync::atomic::AtomicU64::new(0);
+
+        let msg_uuid = queued(&queue_manager).await;
+        catch_panic(
+            queue_manager.as_ref(),
+            &QueueID::Deliver,
+            &msg_uuid,
+            &panics,
+            async { panic!("bug after the move") },
+        )
+        .await;
+
+        assert_eq!(panics.load(std::sync::atomic::Ordering::Relaxed), 1);
+        queue_manager
+            .get_ctx(&QueueID::Working, &msg_uuid)
+            .await
+            .unwrap();
+    }
+
+    /// Rules panicking on the messages with a `X-Poison` header.
+    const POISONED: &str = r#"#{
+        postq: [
+            rule "bug in a plugin" || {
+                if msg::has_header("X-Poison") {
+                    // NOTE: the arithmetic of the rules is unchecked, a division by zero panics.
+                    let zero = 0;
+                    print(1 / zero);
+                }
+                state::next()
+            },
+        ],
+    }"#;
+
+    #[tokio::test]
+    async fn panicking_rule() {
+        let queue_manager = queue_manager();
+        let config = std::sync::Arc::new(local_test());
+        let resolvers = std::sync::Arc::new(DnsResolvers::from_config(&config).unwrap());
+        let rule_engine = std::sync::Arc::new(
+            RuleEngine::with_hierarchy(
+                |builder| {
+                    Ok(builder
+                        .add_root_filter_rules("#{}")?
+                        .add_domain_rules("testserver.com".parse().unwrap())
+                        .with_incoming(POISONED)?
+                        .with_outgoing(POISONED)?
+                        .with_internal(POISONED)?
+                        .build()
+                        .build())
+                },
+                config,
+                resolvers,
+                queue_manager.clone(),
+            )
+            .unwrap(),
+        );
+        let (emitter, _working, _delivery) = scheduler::init(1, 1);
+        let panics = std::sync::atomic::AtomicU64::new(0);
+
+        let mut uuids = vec![];
+        for poisoned in [true, false] {
+            let mut ctx = local_ctx();
+            ctx.mail_from.message_uuid = uuid::Uuid::new_v4();
+            let mut msg = local_msg();
+            if poisoned {
+                msg.prepend_header("X-Poison", "yes");
+            }
+            queue_manager
+                .write_both(&QueueID::Working, &ctx, &msg)
+                .await
+                .unwrap();
+            uuids.push(ctx.mail_from.message_uuid);
+        }
+
+        for msg_uuid in &uuids {
+            catch_panic(
+                queue_manager.as_ref(),
+                &QueueID::Working,
+                msg_uuid,
+                &panics,
+                working::handle_one(
+                    rule_engine.clone(),
+                    queue_manager.clone(),
+                    ProcessMessage::new(*msg_uuid),
+                    emitter.clone(),
+                    std::sync::Arc::default(),
+                ),
+            )
+            .await;
+        }
+
+        assert_eq!(panics.load(std::sync::atomic::Ordering::Relaxed), 1);
+        queue_manager
+            .get_ctx(&QueueID::Dead, &uuids[0])
+            .await
+            .unwrap();
+
+        // the next message is processed as usual.
+        queue_manager
+            .get_ctx(&QueueID::Working, &uuids[1])
+            .await
+            .unwrap_err();
+        queue_manager
+            .get_ctx(&QueueID::Deliver, &uuids[1])
+            .await
+            .unwrap();
+    }
+}
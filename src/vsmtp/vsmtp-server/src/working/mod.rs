@@ -15,9 +15,10 @@
  *
 */
 use crate::{
-    delegate,
+    claim, delegate,
+    delivery_timings::Disposition,
     scheduler::{self, Emitter},
-    ProcessMessage,
+    DeliveryTimings, ProcessMessage,
 };
 use anyhow::Context;
 use tokio_stream::StreamExt;
@@ -25,22 +26,49 @@ use vqueue::{GenericQueueManager, QueueID};
 use vsmtp_common::{
     status,
     transfer::{self, error::Rule},
+    TimingStage,
 };
+use vsmtp_config::field::{NoRecipientPolicy, NormalizationStage};
 use vsmtp_rule_engine::{ExecutionStage, RuleEngine};
 
+/// Quarantine queue of the messages without recipient, see [`NoRecipientPolicy`].
+pub const NO_RECIPIENT_QUARANTINE: &str = "no_recipient";
+
 pub(super) async fn start<Q: GenericQueueManager + Sized + 'static>(
     rule_engine: std::sync::Arc<RuleEngine>,
     queue_manager: std::sync::Arc<Q>,
     emitter: std::sync::Arc<Emitter>,
     mut receiver: scheduler::Receiver,
+    delivery_timings: std::sync::Arc<DeliveryTimings>,
 ) {
-    let working_receiver = receiver.as_stream().map(|pm| {
-        tokio::spawn(handle_one(
+    let panics = receiver.panics();
+    let working_receiver = receiver.as_bounded_stream().map(|(pm, slot)| {
+        let queue = if pm.is_from_delegation() {
+            QueueID::Delegated
+        } else {
+            QueueID::Working
+        };
+        let msg_uuid = *pm.as_ref();
+        let handle = handle_one(
             rule_engine.clone(),
             queue_manager.clone(),
             pm,
             emitter.clone(),
-        ))
+            delivery_timings.clone(),
+        );
+        let (queue_manager, panics) = (queue_manager.clone(), panics.clone());
+        tokio::spawn(async move {
+            // NOTE: the errors are logged by `handle_one`.
+            let _err = crate::watchdog::catch_panic(
+                queue_manager.as_ref(),
+                &queue,
+                &msg_uuid,
+                &panics,
+                handle,
+            )
+            .await;
+            drop(slot);
+        })
     });
     tokio::pin!(working_receiver);
 
@@ -51,13 +79,16 @@ pub(super) async fn start<Q: GenericQueueManager + Sized + 'static>(
 ///
 /// Running the rule engine at the stage `PostQ` and then
 /// handle the quarantine, delegation or delivery outcome of the message.
+///
+/// The final disposition of the message is reported to `delivery_timings`.
 #[allow(clippy::too_many_lines)]
-#[tracing::instrument(name = "working", skip_all, err)]
+#[tracing::instrument(name = "working", parent = process_message.span(), skip_all, err, fields(uuid = %process_message.as_ref(), queue_id = tracing::field::Empty))]
 pub async fn handle_one<Q: GenericQueueManager + Sized + 'static>(
     rule_engine: std::sync::Arc<RuleEngine>,
     queue_manager: std::sync::Arc<Q>,
     process_message: ProcessMessage,
     emitter: std::sync::Arc<Emitter>,
+    delivery_timings: std::sync::Arc<DeliveryTimings>,
 ) -> anyhow::Result<()> {
     struct Opt {
         move_to_queue: Option<QueueID>,
@@ -72,12 +103,38 @@ pub async fn handle_one<Q: GenericQueueManager + Sized + 'static>(
         QueueID::Working
     };
 
-    let (ctx, mail_message) = queue_manager
+    let Some(mut claim) =
+        claim::Guard::acquire(queue_manager.clone(), *process_message.as_ref()).await?
+    else {
+        return Ok(());
+    };
+
+    let (mut ctx, mut mail_message) = queue_manager
         .get_both(&queue, process_message.as_ref())
         .await?;
+    tracing::Span::current().record("queue_id", ctx.mail_from.queue_id());
 
     let mut skipped = ctx.connect.skipped.clone();
-    let (ctx, mail_message, _) = rule_engine.just_run_when(
+
+    // the headers covered by the signatures of a message received already signed.
+    let signed = queue_manager
+        .get_config()
+        .server
+        .dkim_preservation
+        .as_ref()
+        .filter(|_| !process_message.is_from_delegation())
+        .and_then(|_| crate::dkim_preservation::SignedFields::new(&mail_message));
+
+    // NOTE: before the rules, so that the headers added are covered by a DKIM signature.
+    if !process_message.is_from_delegation() {
+        crate::missing_headers::add_missing_headers(
+            queue_manager.get_config(),
+            &mut ctx,
+            &mut mail_message,
+        );
+    }
+
+    let (ctx, mut mail_message, _) = rule_engine.just_run_when(
         &mut skipped,
         ExecutionStage::PostQ,
         vsmtp_common::Context::Finished(ctx),
@@ -86,6 +143,55 @@ pub async fn handle_one<Q: GenericQueueManager + Sized + 'static>(
 
     let mut ctx = ctx.unwrap_finished().context("context is not finished")?;
 
+    if matches!(skipped, None | Some(status::Status::Next)) {
+        if let Some(reply) = crate::sender_policy::check_from_header(
+            queue_manager.get_config(),
+            &ctx,
+            &mut mail_message,
+        ) {
+            skipped = Some(status::Status::Deny(reply));
+        }
+    }
+
+    // the message kept in quarantine, or delegated, is not checked.
+    if let Some(signed) = signed.as_ref().filter(|_| {
+        !matches!(
+            skipped,
+            Some(
+                status::Status::Deny(_)
+                    | status::Status::Quarantine(_)
+                    | status::Status::Delegated(_)
+            )
+        )
+    }) {
+        if let Some(reply) = crate::dkim_preservation::check_signatures(
+            queue_manager.get_config(),
+            &mut ctx,
+            signed,
+            &mut mail_message,
+        ) {
+            skipped = Some(status::Status::Deny(reply));
+        }
+    }
+
+    // NOTE: after the rules, which verify the DKIM signatures of the message as received.
+    if let Some(normalization) = queue_manager
+        .get_config()
+        .server
+        .normalization
+        .as_ref()
+        .filter(|normalization| normalization.stage == NormalizationStage::AfterRules)
+    {
+        if mail_message.normalize(normalization.trailing_whitespace) {
+            tracing::debug!("Message normalized.");
+        }
+    }
+
+    ctx.finished.timings.record(TimingStage::Working);
+
+    // NOTE: the rules can take a while, the message may have been taken over since.
+    claim.ensure_held()?;
+
     let Opt {
         move_to_queue,
         send_to_delivery,
@@ -132,6 +238,20 @@ pub async fn handle_one<Q: GenericQueueManager + Sized + 'static>(
                 delegated: true,
             }
         }
+        Some(status::Status::Defer(delay)) => {
+            ctx.finished.defer(*delay);
+            // NOTE: the status is not kept, otherwise the message would be deferred
+            //       again if moved back to the delivery queue.
+            ctx.connect.skipped = None;
+
+            tracing::warn!(stage = %ExecutionStage::PostQ, status = "defer", ?delay, "Rules skipped.");
+            Opt {
+                move_to_queue: Some(QueueID::Deferred),
+                send_to_delivery: false,
+                write_email: true,
+                delegated: false,
+            }
+        }
         Some(status::Status::DelegationResult) => Opt {
             move_to_queue: None,
             send_to_delivery: true,
@@ -167,6 +287,70 @@ pub async fn handle_one<Q: GenericQueueManager + Sized + 'static>(
         }
     };
 
+    if matches!(move_to_queue, Some(QueueID::Deliver | QueueID::Deferred)) {
+        rule_engine.expand_aliases(&mut ctx);
+    }
+
+    // NOTE: the rules may have removed all the recipients of the message.
+    let no_recipient = queue_manager
+        .get_config()
+        .server
+        .queues
+        .working
+        .no_recipient;
+    let (move_to_queue, send_to_delivery) =
+        if matches!(move_to_queue, Some(QueueID::Deliver | QueueID::Deferred))
+            && ctx.rcpt_to.forward_paths.is_empty()
+        {
+            match no_recipient {
+                NoRecipientPolicy::Drop => {
+                    tracing::info!("No recipient left, the message is dropped.");
+                    queue_manager
+                        .remove_both(&queue, process_message.as_ref())
+                        .await?;
+                    claim.release().await;
+                    return Ok(());
+                }
+                NoRecipientPolicy::Quarantine => {
+                    tracing::warn!("No recipient left, the message is quarantined.");
+                    (
+                        Some(QueueID::Quarantine {
+                            name: NO_RECIPIENT_QUARANTINE.to_owned(),
+                        }),
+                        false,
+                    )
+                }
+                NoRecipientPolicy::Dead => {
+                    tracing::error!("No recipient left, the message is moved to the dead queue.");
+                    (Some(QueueID::Dead), false)
+                }
+            }
+        } else {
+            (move_to_queue, send_to_delivery)
+        };
+
+    if matches!(move_to_queue, Some(QueueID::Deliver)) {
+        // NOTE: the message received is already in the working queue, it is only
+        //       written again if the delivery does not succeed.
+        let config = rule_engine.srv().config.clone();
+        if config.server.queues.working.direct_delivery && !delegated {
+            tracing::debug!("Delivering the message directly.");
+            let result = claim
+                .run(crate::delivery::deliver::deliver_one(
+                    config,
+                    queue_manager,
+                    rule_engine,
+                    &queue,
+                    ctx,
+                    mail_message,
+                    &delivery_timings,
+                ))
+                .await;
+            claim.release().await;
+            return result;
+        }
+    }
+
     if write_email {
         queue_manager
             .write_msg(process_message.as_ref(), &mail_message)
@@ -175,8 +359,14 @@ pub async fn handle_one<Q: GenericQueueManager + Sized + 'static>(
 
     if let Some(next_queue) = move_to_queue {
         queue_manager.move_to(&queue, &next_queue, &ctx).await?;
+        if next_queue == QueueID::Dead {
+            delivery_timings.report(&ctx, Disposition::Bounced);
+        }
     }
 
+    // the delivery claims the message in turn.
+    claim.release().await;
+
     if send_to_delivery {
         emitter
             .send_to_delivery(if delegated {
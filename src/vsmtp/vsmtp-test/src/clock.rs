@@ -0,0 +1,76 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2023 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+
+/// Clock of the current thread driven by the test, instead of waiting for the
+/// retry schedules, the expirations, etc.
+///
+/// The clock ([`vsmtp_common::clock`]) and the identifiers ([`vsmtp_common::id`])
+/// use their real sources again when the guard is dropped, even if the test panics.
+///
+/// ```
+/// let clock = vsmtp_test::clock::TestClock::start();
+/// let start = clock.now();
+///
+/// clock.advance(time::Duration::minutes(5));
+/// assert_eq!(vsmtp_common::clock::now(), start + time::Duration::minutes(5));
+/// ```
+#[must_use = "the clock is reset when the guard is dropped"]
+pub struct TestClock {
+    // the mock is specific to the thread
+    _not_send: core::marker::PhantomData<*const ()>,
+}
+
+// NOTE: the methods take the guard to make sure the clock is still mocked.
+#[allow(clippy::unused_self)]
+impl TestClock {
+    /// Freeze the clock at an arbitrary, fixed, date.
+    pub fn start() -> Self {
+        Self::start_at(
+            time::OffsetDateTime::from_unix_timestamp(1_672_531_200).expect("valid timestamp"),
+        )
+    }
+
+    /// Freeze the clock at `now`.
+    pub fn start_at(now: time::OffsetDateTime) -> Self {
+        vsmtp_common::clock::mock::set(now);
+        Self {
+            _not_send: core::marker::PhantomData,
+        }
+    }
+
+    /// Generate the identifiers of the connections and messages from `seed`.
+    pub fn seed_ids(&self, seed: u64) {
+        vsmtp_common::clock::mock::seed_ids(seed);
+    }
+
+    /// Move the clock forward.
+    pub fn advance(&self, duration: time::Duration) {
+        vsmtp_common::clock::mock::advance(duration);
+    }
+
+    /// The current time of the clock.
+    #[must_use]
+    pub fn now(&self) -> time::OffsetDateTime {
+        vsmtp_common::clock::now()
+    }
+}
+
+impl Drop for TestClock {
+    fn drop(&mut self) {
+        vsmtp_common::clock::mock::reset();
+    }
+}
@@ -15,8 +15,9 @@
  *
 */
 use vsmtp_common::{
-    ClientName, ConnectProperties, ContextFinished, FinishedProperties, HeloProperties,
-    MailFromProperties, RcptToProperties, TransactionType,
+    auth::Credentials, AuthProperties, ClientName, ConnectProperties, ContextFinished,
+    DeliveryStrategy, FinishedProperties, HeloProperties, MailFromProperties, RcptToProperties,
+    Timings, TransactionType,
 };
 use vsmtp_config::Config;
 use vsmtp_mail_parser::MessageBody;
@@ -103,7 +104,7 @@ pub fn with_tls() -> Config {
 pub fn local_ctx() -> ContextFinished {
     ContextFinished {
         connect: ConnectProperties {
-            connect_timestamp: time::OffsetDateTime::now_utc(),
+            connect_timestamp: vsmtp_common::clock::now(),
             client_addr: "127.0.0.1:25".parse().expect(""),
             server_addr: "127.0.0.1:5977".parse().expect(""),
             server_name: "testserver.com".parse().expect(""),
@@ -111,27 +112,64 @@ pub fn local_ctx() -> ContextFinished {
             auth: None,
             tls: None,
             skipped: None,
+            error_count: 0,
+            sender_churn: 0,
+            banner: None,
+            session_category: None,
+            submission_quota: None,
         },
         helo: HeloProperties {
             client_name: ClientName::Domain("client.testserver.com".parse().expect("")),
             using_deprecated: false,
         },
         mail_from: MailFromProperties {
-            mail_timestamp: time::OffsetDateTime::now_utc(),
+            mail_timestamp: vsmtp_common::clock::now(),
             message_uuid: uuid::Uuid::new_v4(),
             reverse_path: Some("client@testserver.com".to_string().parse().expect("")),
             spf: None,
             utf8: false,
+            body_type: None,
+            declared_size: None,
+            envelop_id: None,
+            raw_mail_from: None,
+            raw_rcpt: vec![],
         },
         rcpt_to: RcptToProperties {
             forward_paths: vec!["recipient@testserver.com".to_string().parse().expect("")],
             delivery: std::collections::HashMap::new(),
             transaction_type: TransactionType::Internal,
+            original_recipients: std::collections::HashMap::new(),
+            folders: std::collections::HashMap::new(),
+            extensions: std::collections::HashMap::new(),
+            reverse_paths: std::collections::HashMap::new(),
+            catch_all: std::collections::HashMap::new(),
+            delivery_strategy: DeliveryStrategy::default(),
+        },
+        finished: FinishedProperties {
+            dkim: None,
+            rcpt_headers: std::collections::HashMap::new(),
+            added_message_id: None,
+            deferred_until: None,
+            timings: Timings::default(),
         },
-        finished: FinishedProperties { dkim: None },
     }
 }
 
+/// [`local_ctx`] of a client authenticated as `authid`.
+#[must_use]
+pub fn local_ctx_authenticated(authid: &str) -> ContextFinished {
+    let mut ctx = local_ctx();
+    ctx.connect.auth = Some(AuthProperties {
+        authenticated: true,
+        cancel_count: 0,
+        credentials: Some(Credentials::Verify {
+            authid: authid.to_owned(),
+            authpass: "pass".to_owned(),
+        }),
+    });
+    ctx
+}
+
 ///
 #[must_use]
 pub fn local_msg() -> MessageBody {
@@ -148,3 +186,23 @@ pub fn local_msg() -> MessageBody {
         "Be happy!\r\n".to_string(),
     )
 }
+
+/// A message with the header fields `headers` (given without their CRLF) and a short body.
+///
+/// # Panics
+///
+/// * the message cannot be parsed
+#[must_use]
+pub fn local_msg_with_headers(headers: &[&str]) -> MessageBody {
+    MessageBody::try_from(
+        format!(
+            "{}\r\nHello world!\r\n",
+            headers
+                .iter()
+                .flat_map(|header| [*header, "\r\n"])
+                .collect::<String>()
+        )
+        .as_str(),
+    )
+    .expect("message is valid")
+}
@@ -33,6 +33,9 @@ macro_rules! arc {
     };
 }
 
+/// Clock and identifiers driven by the tests
+pub mod clock;
+
 /// Config shortcut
 pub mod config;
 
@@ -41,6 +44,12 @@ pub mod receiver;
 mod recv_handler_wrapper;
 pub use recv_handler_wrapper::Wrapper;
 
+/// Multi-connection scenarios against one server instance
+pub mod scenario;
+
+/// A SMTP server standing for the next hop of the deliveries
+pub mod sink;
+
 ///
 pub mod get_tls_file;
 
@@ -23,6 +23,7 @@ macro_rules! run_test {
         expected = $expected:expr
         $(, starttls $( = $server_name_starttls:expr )? => $secured_input:expr)?
         $(, tunnel = $server_name_tunnel:expr)?
+        $(, submission = $submission:expr)?
         $(, config = $config:expr)?
         $(, config_arc = $config_arc:expr)?
         $(, mail_handler = $mail_handler:expr)?
@@ -136,8 +137,23 @@ macro_rules! run_test {
             _f()
         };
 
+        // the listener of the test is declared as a submission one.
+        $( let config = if $submission {
+            let mut config = std::sync::Arc::try_unwrap(config).expect("configuration not shared");
+            config.server.interfaces.addr_submission.push(server_addr);
+            std::sync::Arc::new(config)
+        } else {
+            config
+        }; )?
+
+        // NOTE: the transports are registered to read back the contexts queued by the test.
         let queue_manager =
-            <vqueue::temp::QueueManager as vqueue::GenericQueueManager>::init(config.clone(), vec![]).unwrap();
+            <vqueue::temp::QueueManager as vqueue::GenericQueueManager>::init(config.clone(), vec![
+                <vsmtp_delivery::Deliver as vsmtp_common::transport::AbstractTransport>::get_symbol(),
+                <vsmtp_delivery::Forward as vsmtp_common::transport::AbstractTransport>::get_symbol(),
+                <vsmtp_delivery::Maildir as vsmtp_common::transport::AbstractTransport>::get_symbol(),
+                <vsmtp_delivery::MBox as vsmtp_common::transport::AbstractTransport>::get_symbol(),
+            ]).unwrap();
 
         let queue_manager_cloned = std::sync::Arc::clone(&queue_manager);
 
@@ -148,6 +164,11 @@ macro_rules! run_test {
                     #[allow(clippy::no_effect)]
                     $server_name_tunnel;
                     vsmtp_protocol::ConnectionKind::Tunneled
+                };)?                                                                $(
+                let _f = || if $submission {
+                    vsmtp_protocol::ConnectionKind::Submission
+                } else {
+                    vsmtp_protocol::ConnectionKind::Relay
                 };)?
                 _f()
             };
@@ -179,7 +200,17 @@ macro_rules! run_test {
                 config.server.smtp.error.hard_count,
                 config.server.message_size_limit,
                 config.server.esmtp.pipelining,
-            );
+            ).with_line_limits(
+                config.server.smtp.line_length_max,
+                config.server.smtp.first_line_max,
+            )
+            .with_consecutive_bad_command_max(config.server.smtp.error.consecutive_count)
+            .with_parameters(vsmtp_protocol::ParametersPolicy::new(
+                config.server.smtp.parameters.strict,
+                config.server.smtp.parameters.count_max,
+                config.server.smtp.parameters.length_max,
+                config.server.smtp.parameters.reject_unexpected,
+            ));
             let smtp_stream = smtp_receiver.into_stream(
                 |args| async move {
                     let smtp_handler = || vsmtp_server::Handler::on_accept(
@@ -221,7 +252,7 @@ macro_rules! run_test {
                 },
                 client_addr,
                 server_addr,
-                time::OffsetDateTime::now_utc(),
+                vsmtp_common::clock::now(),
                 uuid::Uuid::new_v4()
             );
             tokio::pin!(smtp_stream);
@@ -305,6 +336,7 @@ macro_rules! run_test {
         expected = $expected:expr
         $(, starttls $( = $server_name_starttls:expr )? => $secured_input:expr)?
         $(, tunnel = $server_name_tunnel:expr)?
+        $(, submission = $submission:expr)?
         $(, config = $config:expr)?
         $(, config_arc = $config_arc:expr)?
         $(, mail_handler = $mail_handler:expr)?
@@ -318,6 +350,7 @@ macro_rules! run_test {
                 expected = $expected
                 $(, starttls $( = $server_name_starttls )? => $secured_input)?
                 $(, tunnel = $server_name_tunnel)?
+                $(, submission = $submission)?
                 $(, config = $config)?
                 $(, config_arc = $config_arc)?
                 $(, mail_handler = $mail_handler)?
@@ -375,8 +408,14 @@ macro_rules! run_pipelined_test {
             _f()
         };
 
+        // NOTE: the transports are registered to read back the contexts queued by the test.
         let queue_manager =
-            <vqueue::temp::QueueManager as vqueue::GenericQueueManager>::init(config.clone(), vec![]).unwrap();
+            <vqueue::temp::QueueManager as vqueue::GenericQueueManager>::init(config.clone(), vec![
+                <vsmtp_delivery::Deliver as vsmtp_common::transport::AbstractTransport>::get_symbol(),
+                <vsmtp_delivery::Forward as vsmtp_common::transport::AbstractTransport>::get_symbol(),
+                <vsmtp_delivery::Maildir as vsmtp_common::transport::AbstractTransport>::get_symbol(),
+                <vsmtp_delivery::MBox as vsmtp_common::transport::AbstractTransport>::get_symbol(),
+            ]).unwrap();
 
         let queue_manager_cloned = std::sync::Arc::clone(&queue_manager);
 
@@ -418,7 +457,17 @@ macro_rules! run_pipelined_test {
                 config.server.smtp.error.hard_count,
                 config.server.message_size_limit,
                 config.server.esmtp.pipelining,
-            );
+            ).with_line_limits(
+                config.server.smtp.line_length_max,
+                config.server.smtp.first_line_max,
+            )
+            .with_consecutive_bad_command_max(config.server.smtp.error.consecutive_count)
+            .with_parameters(vsmtp_protocol::ParametersPolicy::new(
+                config.server.smtp.parameters.strict,
+                config.server.smtp.parameters.count_max,
+                config.server.smtp.parameters.length_max,
+                config.server.smtp.parameters.reject_unexpected,
+            ));
             let smtp_stream = smtp_receiver.into_stream(
                 |args| async move {
                     let smtp_handler = || vsmtp_server::Handler::on_accept(
@@ -460,7 +509,7 @@ macro_rules! run_pipelined_test {
                 },
                 client_addr,
                 server_addr,
-                time::OffsetDateTime::now_utc(),
+                vsmtp_common::clock::now(),
                 uuid::Uuid::new_v4()
             );
             tokio::pin!(smtp_stream);
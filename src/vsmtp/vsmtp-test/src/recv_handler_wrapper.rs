@@ -22,8 +22,8 @@ use vsmtp_common::ContextFinished;
 use vsmtp_common::{Reply, Stage};
 use vsmtp_mail_parser::MessageBody;
 use vsmtp_protocol::{
-    AuthArgs, AuthError, CallbackWrap, EhloArgs, Error, HeloArgs, MailFromArgs, RcptToArgs,
-    ReceiverContext, ReceiverHandler,
+    AuthArgs, AuthError, BdatArgs, CallbackWrap, EhloArgs, Error, HeloArgs, MailFromArgs,
+    RcptToArgs, ReceiverContext, ReceiverHandler, Verb,
 };
 
 // NOTE: could be enhance to allow entry point on each call
@@ -119,6 +119,10 @@ where
         self.inner.on_rcpt_to(ctx, args).await
     }
 
+    async fn on_bdat(&mut self, args: &BdatArgs) -> Option<Reply> {
+        self.inner.on_bdat(args).await
+    }
+
     async fn on_message(
         &mut self,
         ctx: &mut ReceiverContext,
@@ -141,7 +145,15 @@ where
         self.inner.on_soft_error(ctx, reply).await
     }
 
-    async fn on_rset(&mut self) -> Reply {
-        self.inner.on_rset().await
+    async fn on_rset(&mut self, ctx: &mut ReceiverContext) -> Reply {
+        self.inner.on_rset(ctx).await
+    }
+
+    async fn on_unknown(&mut self, buffer: Vec<u8>) -> Reply {
+        self.inner.on_unknown(buffer).await
+    }
+
+    async fn on_bad_sequence(&mut self, args: (Verb, Stage)) -> Reply {
+        self.inner.on_bad_sequence(args).await
     }
 }
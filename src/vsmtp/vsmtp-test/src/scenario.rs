@@ -0,0 +1,356 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2023 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+
+/// An event raised by a [`Session`] of a [`Scenario`], and awaited by another one,
+/// to order the concurrent sessions without sleeping.
+///
+/// A signal is awaited by one session only.
+#[derive(Debug, Clone, Default)]
+pub struct Signal(std::sync::Arc<tokio::sync::Notify>);
+
+impl Signal {
+    /// Create a signal not raised yet.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn raise(&self) {
+        self.0.notify_one();
+    }
+
+    async fn wait(&self) {
+        self.0.notified().await;
+    }
+}
+
+/// A client connection of a [`Scenario`], sending its `input` one line per reply
+/// of the server, and asserting the lines received against `expected`.
+///
+/// The session is over once the server has closed the connection.
+#[derive(Debug, Clone)]
+pub struct Session {
+    input: Vec<String>,
+    expected: Vec<String>,
+    after: Option<Signal>,
+    hold_until: Option<Signal>,
+    notify: Option<Signal>,
+    advance_clock: Option<time::Duration>,
+}
+
+impl Session {
+    /// Create a session connecting as soon as its step of the scenario starts.
+    #[must_use]
+    pub fn new(
+        input: impl IntoIterator<Item = impl ToString>,
+        expected: impl IntoIterator<Item = impl ToString>,
+    ) -> Self {
+        Self {
+            input: input.into_iter().map(|i| i.to_string()).collect(),
+            expected: expected.into_iter().map(|i| i.to_string()).collect(),
+            after: None,
+            hold_until: None,
+            notify: None,
+            advance_clock: None,
+        }
+    }
+
+    /// Wait for `signal` before connecting to the server.
+    #[must_use]
+    pub fn after(mut self, signal: Signal) -> Self {
+        self.after = Some(signal);
+        self
+    }
+
+    /// Wait for `signal` before sending the last line of `input`,
+    /// keeping the connection open.
+    #[must_use]
+    pub fn hold_until(mut self, signal: Signal) -> Self {
+        self.hold_until = Some(signal);
+        self
+    }
+
+    /// Raise `signal` once the session waits for its [`Session::hold_until`] signal,
+    /// or else once it is over.
+    #[must_use]
+    pub fn notify(mut self, signal: Signal) -> Self {
+        self.notify = Some(signal);
+        self
+    }
+
+    /// Move the clock of [`vsmtp_common::clock`] forward before connecting.
+    #[must_use]
+    pub const fn advance_clock(mut self, duration: time::Duration) -> Self {
+        self.advance_clock = Some(duration);
+        self
+    }
+
+    async fn play(mut self, server_addr: std::net::SocketAddr) -> Vec<String> {
+        use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
+
+        if let Some(signal) = &self.after {
+            signal.wait().await;
+        }
+        if let Some(duration) = self.advance_clock {
+            vsmtp_common::clock::mock::advance(duration);
+        }
+
+        let stream = tokio::net::TcpStream::connect(server_addr)
+            .await
+            .expect("server is listening");
+        let mut stream = tokio::io::BufReader::new(stream);
+
+        let mut output = vec![];
+        let mut line_to_send = self.input.into_iter();
+
+        loop {
+            let mut line_received = String::new();
+            // read until '\n' or '\r\n'
+            if stream
+                .read_line(&mut line_received)
+                .await
+                .map_or(true, |l| l == 0)
+            {
+                break;
+            }
+
+            let is_last = line_received.chars().nth(3) != Some('-');
+            output.push(line_received);
+            if !is_last {
+                continue;
+            }
+            if line_to_send.len() == 1 {
+                if let Some(signal) = self.hold_until.take() {
+                    if let Some(notify) = self.notify.take() {
+                        notify.raise();
+                    }
+                    signal.wait().await;
+                }
+            }
+            if let Some(line) = line_to_send.next() {
+                stream
+                    .write_all(line.as_bytes())
+                    .await
+                    .expect("server is connected");
+            }
+        }
+
+        if let Some(notify) = self.notify {
+            notify.raise();
+        }
+        output
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Step {
+    Sequential(Session),
+    Concurrent(Vec<Session>),
+}
+
+/// Client sessions played against one server instance, see [`crate::run_scenario`].
+///
+/// The steps are played in order, each waiting for the previous one to be over.
+///
+/// The clock of [`vsmtp_common::clock`] is mocked for the current thread only:
+/// a scenario advancing it must run on a current-thread runtime, so that the
+/// server reads the same time.
+#[derive(Debug, Clone, Default)]
+pub struct Scenario {
+    steps: Vec<Step>,
+}
+
+impl Scenario {
+    /// Create an empty scenario.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Play a session once the previous steps are over.
+    #[must_use]
+    pub fn then(mut self, session: Session) -> Self {
+        self.steps.push(Step::Sequential(session));
+        self
+    }
+
+    /// Play sessions concurrently once the previous steps are over.
+    #[must_use]
+    pub fn concurrently(mut self, sessions: impl IntoIterator<Item = Session>) -> Self {
+        self.steps
+            .push(Step::Concurrent(sessions.into_iter().collect()));
+        self
+    }
+
+    /// Play the scenario against the server listening on `server_addr`,
+    /// returning the lines received by each session, in the order of declaration.
+    ///
+    /// # Panics
+    ///
+    /// * a session cannot connect to the server, or panicked
+    /// * the lines received by a session are not the ones expected
+    pub async fn play(self, server_addr: std::net::SocketAddr) -> Vec<Vec<String>> {
+        let mut outputs = vec![];
+
+        for step in self.steps {
+            let sessions = match step {
+                Step::Sequential(session) => vec![session],
+                Step::Concurrent(sessions) => sessions,
+            };
+            let expected = sessions
+                .iter()
+                .map(|session| session.expected.clone())
+                .collect::<Vec<_>>();
+
+            let handles = sessions
+                .into_iter()
+                .map(|session| tokio::spawn(session.play(server_addr)))
+                .collect::<Vec<_>>();
+
+            for (expected, handle) in expected.into_iter().zip(handles) {
+                let received = handle.await.expect("session panicked");
+                assert_eq!(expected, received);
+                outputs.push(received);
+            }
+        }
+
+        outputs
+    }
+}
+
+/// The state of the server after a [`Scenario`], see [`crate::run_scenario`].
+pub struct Outcome<M> {
+    /// The queues written by the server.
+    pub queue_manager: std::sync::Arc<vqueue::temp::QueueManager>,
+    /// The lines received by each session, in the order of declaration.
+    pub outputs: Vec<Vec<String>>,
+    /// The messages emitted to the working process.
+    pub working: Vec<M>,
+    /// The messages emitted to the delivery process.
+    pub delivery: Vec<M>,
+}
+
+/// Collect the messages already emitted on a channel of the server.
+pub async fn drain<M>(stream: impl tokio_stream::Stream<Item = M>) -> Vec<M> {
+    tokio::pin!(stream);
+
+    let mut messages = vec![];
+    while let Ok(Some(message)) = tokio::time::timeout(
+        std::time::Duration::ZERO,
+        tokio_stream::StreamExt::next(&mut stream),
+    )
+    .await
+    {
+        messages.push(message);
+    }
+    messages
+}
+
+/// Run a [`Scenario`] against a `vSMTP` server listening on the loopback,
+/// and return its [`Outcome`].
+///
+/// Unlike [`crate::run_test`], the connections are handled by `vsmtp_server::Server`,
+/// its connection limits included.
+#[macro_export]
+macro_rules! run_scenario {
+    (
+        scenario = $scenario:expr
+        $(, config = $config:expr)?
+        $(, config_arc = $config_arc:expr)?
+        $(, hierarchy_builder = $hierarchy_builder:expr)?
+        $(,)?
+    ) => {{
+        let config: std::sync::Arc<vsmtp_config::Config> =  {
+            let _f = || std::sync::Arc::new($crate::config::local_test());      $(
+            let _f = || std::sync::Arc::new($config);                       )?  $(
+            let _f = || $config_arc;                                        )?
+            _f()
+        };
+
+        let socket = vsmtp_server::socket_bind_anyhow("127.0.0.1:0").unwrap();
+        let server_addr = socket.local_addr().unwrap();
+
+        // the transports assigned to the recipients are read back from the queues.
+        let queue_manager = <vqueue::temp::QueueManager as vqueue::GenericQueueManager>::init(
+            config.clone(),
+            vec![
+                <vsmtp_delivery::Deliver as vsmtp_common::transport::AbstractTransport>::get_symbol(),
+                <vsmtp_delivery::Forward as vsmtp_common::transport::AbstractTransport>::get_symbol(),
+                <vsmtp_delivery::Maildir as vsmtp_common::transport::AbstractTransport>::get_symbol(),
+                <vsmtp_delivery::MBox as vsmtp_common::transport::AbstractTransport>::get_symbol(),
+            ],
+        ).unwrap();
+        let resolvers = std::sync::Arc::new(vsmtp_config::DnsResolvers::from_config(&config).unwrap());
+
+        let (emitter, mut working_rx, mut delivery_rx) = vsmtp_server::scheduler::init(
+            config.server.queues.working.channel_size,
+            config.server.queues.delivery.channel_size,
+        );
+
+        let rule_engine: std::sync::Arc<vsmtp_rule_engine::RuleEngine> = {
+            let _f = || vsmtp_rule_engine::RuleEngine::new(
+                config.clone(),
+                resolvers.clone(),
+                queue_manager.clone()
+            ).unwrap();                                         $(
+            let _f = || vsmtp_rule_engine::RuleEngine::with_hierarchy(
+                $hierarchy_builder,
+                config.clone(),
+                resolvers.clone(),
+                queue_manager.clone()
+            ).unwrap();                                         )?
+            std::sync::Arc::new(_f())
+        };
+
+        let server = vsmtp_server::Server::new(
+            config.clone(),
+            rule_engine,
+            queue_manager.clone(),
+            emitter,
+        )
+        .unwrap();
+        let server = tokio::spawn(server.listen((vec![socket], vec![], vec![])));
+
+        let outputs = $crate::scenario::Scenario::play($scenario, server_addr).await;
+        server.abort();
+
+        $crate::scenario::Outcome {
+            queue_manager,
+            outputs,
+            working: $crate::scenario::drain(working_rx.as_stream()).await,
+            delivery: $crate::scenario::drain(delivery_rx.as_stream()).await,
+        }
+    }};
+    (
+        fn $name:ident,
+        scenario = $scenario:expr
+        $(, config = $config:expr)?
+        $(, config_arc = $config_arc:expr)?
+        $(, hierarchy_builder = $hierarchy_builder:expr)?
+        $(,)?
+    ) => {
+        #[test_log::test(tokio::test)]
+        async fn $name() {
+            run_scenario! {
+                scenario = $scenario
+                $(, config = $config)?
+                $(, config_arc = $config_arc)?
+                $(, hierarchy_builder = $hierarchy_builder)?
+            };
+        }
+    };
+}
@@ -0,0 +1,371 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2023 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+
+const TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+const POLL_PERIOD: std::time::Duration = std::time::Duration::from_millis(10);
+
+/// A message accepted by the [`Sink`].
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Transaction {
+    /// Argument of the `MAIL FROM` command, with its parameters (`<john@doe> BODY=8BITMIME`).
+    pub mail_from: String,
+    /// Arguments of the `RCPT TO` commands accepted, with their parameters.
+    pub rcpt_to: Vec<String>,
+    /// The message, dot-unstuffed, each line ending with `\r\n`.
+    pub message: String,
+}
+
+/// A connection to the [`Sink`].
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Session {
+    /// The commands received, without the content of the messages nor the line endings.
+    pub commands: Vec<String>,
+    /// The messages accepted.
+    pub transactions: Vec<Transaction>,
+    /// Has the connection been closed?
+    pub closed: bool,
+}
+
+impl Session {
+    /// The verbs of the commands received, in upper case.
+    #[must_use]
+    pub fn verbs(&self) -> Vec<String> {
+        self.commands
+            .iter()
+            .map(|command| {
+                command
+                    .split_whitespace()
+                    .next()
+                    .unwrap_or_default()
+                    .to_ascii_uppercase()
+            })
+            .collect()
+    }
+}
+
+/// A command received by the [`Sink`], see [`SinkBuilder::with_reply`].
+#[derive(Debug)]
+pub struct Turn<'a> {
+    /// Index of the connection, from 0.
+    pub session: usize,
+    /// Number of messages accepted before, by all the connections.
+    pub received: usize,
+    /// Recipients accepted in the current transaction.
+    pub rcpt_to: &'a [String],
+    /// The command, `.` for the end of the content of a message.
+    pub line: &'a str,
+}
+
+impl Turn<'_> {
+    /// The verb of the command, in upper case.
+    #[must_use]
+    pub fn verb(&self) -> String {
+        self.line
+            .split_whitespace()
+            .next()
+            .unwrap_or_default()
+            .to_ascii_uppercase()
+    }
+}
+
+type Reply = dyn Fn(&Turn<'_>) -> Option<String> + Send + Sync;
+
+/// Build a [`Sink`] replying something else than accepting everything.
+#[must_use]
+pub struct SinkBuilder {
+    capabilities: Vec<String>,
+    greeting_delay: Option<std::time::Duration>,
+    reply: Option<Box<Reply>>,
+}
+
+impl SinkBuilder {
+    /// Extensions advertised in the reply to `EHLO` (`DSN`, `8BITMIME`, ...).
+    pub fn with_capabilities(mut self, capabilities: &[&str]) -> Self {
+        self.capabilities = capabilities.iter().map(ToString::to_string).collect();
+        self
+    }
+
+    /// Wait `delay` before greeting the client.
+    pub const fn with_greeting_delay(mut self, delay: std::time::Duration) -> Self {
+        self.greeting_delay = Some(delay);
+        self
+    }
+
+    /// Reply something else than the default to some commands: `reply` returns the
+    /// reply (without the last `\r\n`), or `None` for the default one.
+    ///
+    /// The recipients and the messages are accepted if their reply is positive.
+    pub fn with_reply(
+        mut self,
+        reply: impl Fn(&Turn<'_>) -> Option<String> + Send + Sync + 'static,
+    ) -> Self {
+        self.reply = Some(Box::new(reply));
+        self
+    }
+
+    /// Listen on the loopback, serving each connection on its own thread.
+    ///
+    /// # Panics
+    ///
+    /// * no port is available on the loopback
+    #[must_use]
+    pub fn start(self) -> Sink {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0")
+            .expect("a port is available on the loopback");
+        let sink = Sink {
+            addr: listener.local_addr().expect("listener is bound"),
+            sessions: std::sync::Arc::default(),
+        };
+
+        let (sessions, builder) = (sink.sessions.clone(), std::sync::Arc::new(self));
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let index = {
+                    let mut sessions = sessions.lock().expect("mutex poisoned");
+                    sessions.push(Session::default());
+                    sessions.len() - 1
+                };
+                let (sessions, builder) = (sessions.clone(), builder.clone());
+                std::thread::spawn(move || {
+                    // the client may leave at any time.
+                    let _result = builder.serve(stream, index, &sessions);
+                    sessions.lock().expect("mutex poisoned")[index].closed = true;
+                });
+            }
+        });
+        sink
+    }
+
+    fn default_reply(&self, turn: &Turn<'_>) -> String {
+        match turn.verb().as_str() {
+            "EHLO" => {
+                let lines = std::iter::once("sink")
+                    .chain(self.capabilities.iter().map(String::as_str))
+                    .collect::<Vec<_>>();
+                lines
+                    .iter()
+                    .enumerate()
+                    .map(|(i, line)| {
+                        format!("250{}{line}", if i + 1 == lines.len() { ' ' } else { '-' })
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\r\n")
+            }
+            "HELO" => "250 sink".to_owned(),
+            "DATA" => "354 End data with <CR><LF>.<CR><LF>".to_owned(),
+            "." => "250 2.0.0 Ok: queued".to_owned(),
+            "RCPT" => "250 2.1.5 Ok".to_owned(),
+            "QUIT" => "221 2.0.0 Bye".to_owned(),
+            _ => "250 2.0.0 Ok".to_owned(),
+        }
+    }
+
+    fn serve(
+        &self,
+        stream: std::net::TcpStream,
+        index: usize,
+        sessions: &std::sync::Mutex<Vec<Session>>,
+    ) -> std::io::Result<()> {
+        use std::io::{BufRead, Write};
+
+        let mut write = stream.try_clone()?;
+        let mut lines = std::io::BufReader::new(stream).lines();
+        let record =
+            |f: &dyn Fn(&mut Session)| f(&mut sessions.lock().expect("mutex poisoned")[index]);
+        let received = || {
+            sessions
+                .lock()
+                .expect("mutex poisoned")
+                .iter()
+                .map(|session| session.transactions.len())
+                .sum::<usize>()
+        };
+
+        if let Some(delay) = self.greeting_delay {
+            std::thread::sleep(delay);
+        }
+        write.write_all(b"220 sink ESMTP\r\n")?;
+
+        let mut transaction = Transaction::default();
+        while let Some(line) = lines.next() {
+            let line = line?;
+            record(&|session| session.commands.push(line.clone()));
+
+            let turn = Turn {
+                session: index,
+                received: received(),
+                rcpt_to: &transaction.rcpt_to,
+                line: &line,
+            };
+            let verb = turn.verb();
+            let reply = self
+                .reply
+                .as_ref()
+                .and_then(|reply| reply(&turn))
+                .unwrap_or_else(|| self.default_reply(&turn));
+            let positive = reply.starts_with('2') || reply.starts_with('3');
+
+            match verb.as_str() {
+                "MAIL" if positive => {
+                    transaction = Transaction {
+                        mail_from: line
+                            .split_once(':')
+                            .map_or_else(String::new, |(_, arg)| arg.trim().to_owned()),
+                        ..Transaction::default()
+                    };
+                }
+                "RSET" => transaction = Transaction::default(),
+                "RCPT" if positive => {
+                    transaction.rcpt_to.push(
+                        line.split_once(':')
+                            .map_or_else(String::new, |(_, arg)| arg.trim().to_owned()),
+                    );
+                }
+                _ => {}
+            }
+            write.write_all(format!("{reply}\r\n").as_bytes())?;
+
+            if verb == "DATA" && positive {
+                let mut message = String::new();
+                for line in lines.by_ref() {
+                    let line = line?;
+                    if line == "." {
+                        break;
+                    }
+                    message.push_str(line.strip_prefix('.').unwrap_or(&line));
+                    message.push_str("\r\n");
+                }
+
+                let turn = Turn {
+                    session: index,
+                    received: received(),
+                    rcpt_to: &transaction.rcpt_to,
+                    line: ".",
+                };
+                let reply = self
+                    .reply
+                    .as_ref()
+                    .and_then(|reply| reply(&turn))
+                    .unwrap_or_else(|| self.default_reply(&turn));
+
+                let accepted = Transaction {
+                    message,
+                    ..std::mem::take(&mut transaction)
+                };
+                if reply.starts_with('2') {
+                    record(&|session| session.transactions.push(accepted.clone()));
+                }
+                write.write_all(format!("{reply}\r\n").as_bytes())?;
+            }
+
+            if verb == "QUIT" {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A SMTP server recording the commands and the messages it receives, standing for
+/// the next hop of the deliveries in the tests.
+///
+/// It accepts everything by default, see [`Sink::builder`] otherwise.
+#[derive(Debug, Clone)]
+pub struct Sink {
+    /// Address to send the messages to.
+    pub addr: std::net::SocketAddr,
+    sessions: std::sync::Arc<std::sync::Mutex<Vec<Session>>>,
+}
+
+impl Sink {
+    /// A sink accepting everything, advertising no extension.
+    #[must_use]
+    pub fn start() -> Self {
+        Self::builder().start()
+    }
+
+    /// Customize the replies of the sink.
+    pub fn builder() -> SinkBuilder {
+        SinkBuilder {
+            capabilities: vec![],
+            greeting_delay: None,
+            reply: None,
+        }
+    }
+
+    /// Port of the sink, on the loopback.
+    #[must_use]
+    pub const fn port(&self) -> u16 {
+        self.addr.port()
+    }
+
+    /// The connections received until now.
+    ///
+    /// # Panics
+    ///
+    /// * a thread of the sink panicked
+    #[must_use]
+    pub fn sessions(&self) -> Vec<Session> {
+        self.sessions.lock().expect("mutex poisoned").clone()
+    }
+
+    /// The messages accepted until now, in the order of the connections.
+    #[must_use]
+    pub fn transactions(&self) -> Vec<Transaction> {
+        self.sessions()
+            .into_iter()
+            .flat_map(|session| session.transactions)
+            .collect()
+    }
+
+    fn wait<T>(what: &str, mut f: impl FnMut() -> Option<T>) -> T {
+        let start = std::time::Instant::now();
+        loop {
+            if let Some(out) = f() {
+                return out;
+            }
+            assert!(start.elapsed() < TIMEOUT, "timed out waiting for {what}");
+            std::thread::sleep(POLL_PERIOD);
+        }
+    }
+
+    /// Wait for the sink to have accepted `count` messages, and return them.
+    ///
+    /// # Panics
+    ///
+    /// * the messages are not received in time
+    #[must_use]
+    pub fn wait_for(&self, count: usize) -> Vec<Transaction> {
+        Self::wait(&format!("{count} message(s) in the sink"), || {
+            let transactions = self.transactions();
+            (transactions.len() >= count).then_some(transactions)
+        })
+    }
+
+    /// Wait for `count` connections to be closed, and return all of them.
+    ///
+    /// # Panics
+    ///
+    /// * the connections are not closed in time
+    #[must_use]
+    pub fn wait_for_sessions(&self, count: usize) -> Vec<Session> {
+        Self::wait(&format!("{count} session(s) closed in the sink"), || {
+            let sessions = self.sessions();
+            (sessions.iter().filter(|session| session.closed).count() >= count).then_some(sessions)
+        })
+    }
+}
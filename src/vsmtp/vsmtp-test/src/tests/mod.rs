@@ -22,18 +22,40 @@ mod examples {
     mod message;
 }
 mod protocol {
+    mod access_lists;
+    mod banner;
+    mod catch_all;
+    mod chunking;
     mod clair;
+    mod data_reply;
+    mod disclosure;
     mod dsn;
+    mod duplicate_rcpt;
+    mod error_count;
+    mod greylist;
+    mod lookup_cache;
     mod mail_from;
     mod message_max_size;
+    mod parameters;
     mod pipelining;
+    mod policy_events;
+    mod raw_commands;
+    mod recipients;
     mod rset;
+    mod sender_churn;
+    mod session_category;
+    mod srs;
+    mod storage_error;
+    mod trusted_upstreams;
     mod vrfy;
 
     pub mod auth;
     mod helo;
+    mod line_length;
     mod tls {
+        mod certificate_selection;
         //mod cipher_suite;
+        mod client_certificate;
         mod starttls;
         mod tunneled;
         mod tunneled_with_auth;
@@ -41,8 +63,13 @@ mod protocol {
     mod utf8;
 }
 mod process {
+    mod admin;
     mod deferred;
+    mod delegation;
     mod delivery;
+    mod dsn;
+    mod eightbitmime;
+    mod resilience;
     mod working;
 }
 mod rule_engine {
@@ -56,6 +83,7 @@ mod rule_engine {
     mod quarantine;
     mod rule_default;
     mod rule_triage;
+    mod size;
 }
 mod server;
 mod vqueue;
@@ -0,0 +1,291 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2022 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use crate::config::{local_ctx, local_msg, local_test};
+use tokio_stream::StreamExt;
+use vqueue::{GenericQueueManager, QueueID};
+use vsmtp_common::{
+    transfer::{error::Queuer, Status},
+    transport::{AbstractTransport, WrapperSerde},
+};
+use vsmtp_config::DnsResolvers;
+use vsmtp_delivery::Deliver;
+use vsmtp_rule_engine::RuleEngine;
+use vsmtp_server::{scheduler, Admin, AdminCommand, Health};
+
+#[test_log::test(tokio::test)]
+async fn hold_release_requeue() {
+    let config = std::sync::Arc::new(local_test());
+    let queue_manager = <vqueue::temp::QueueManager as vqueue::GenericQueueManager>::init(
+        config.clone(),
+        vec![Deliver::get_symbol()],
+    )
+    .unwrap();
+
+    let (emitter, _working, mut delivery) = scheduler::init(
+        config.server.queues.working.channel_size,
+        config.server.queues.delivery.channel_size,
+    );
+    let resolvers = std::sync::Arc::new(DnsResolvers::from_config(&config).unwrap());
+    let rule_engine = std::sync::Arc::new(
+        RuleEngine::with_hierarchy(
+            |builder| Ok(builder.add_root_filter_rules("#{}")?.build()),
+            config.clone(),
+            resolvers.clone(),
+            queue_manager.clone(),
+        )
+        .unwrap(),
+    );
+    let health = std::sync::Arc::new(Health::new(std::env::temp_dir()));
+
+    let admin = Admin::new(queue_manager.clone(), rule_engine, emitter, health.clone());
+
+    let mut ctx = local_ctx();
+    let message_uuid = uuid::Uuid::new_v4();
+    ctx.mail_from.message_uuid = message_uuid;
+    ctx.rcpt_to.delivery.insert(
+        WrapperSerde::Ready(std::sync::Arc::new(Deliver::new(
+            resolvers.get_resolver_root(),
+            config.clone(),
+        ))),
+        vec![("test@localhost".parse().unwrap(), Status::default())],
+    );
+    queue_manager
+        .write_both(&QueueID::Deferred, &ctx, &local_msg())
+        .await
+        .unwrap();
+
+    admin
+        .execute(AdminCommand::Hold(message_uuid.to_string()))
+        .await
+        .unwrap();
+    pretty_assertions::assert_eq!(
+        admin
+            .execute(AdminCommand::List(QueueID::Hold))
+            .await
+            .unwrap(),
+        vec![message_uuid.to_string()]
+    );
+    queue_manager
+        .get_ctx(&QueueID::Deferred, &message_uuid)
+        .await
+        .unwrap_err();
+
+    admin
+        .execute(AdminCommand::Requeue(message_uuid.to_string()))
+        .await
+        .unwrap_err();
+
+    // a message is designated by the beginning of its uuid, or by its queue id.
+    admin
+        .execute(AdminCommand::Release(
+            message_uuid.to_string()[..8].to_owned(),
+        ))
+        .await
+        .unwrap();
+    queue_manager
+        .get_ctx(&QueueID::Deliver, &message_uuid)
+        .await
+        .unwrap();
+    let delivery_recv = delivery.as_stream();
+    tokio::pin!(delivery_recv);
+    pretty_assertions::assert_eq!(delivery_recv.next().await.unwrap().as_ref(), &message_uuid);
+
+    let mut ctx = queue_manager
+        .get_ctx(&QueueID::Deliver, &message_uuid)
+        .await
+        .unwrap();
+    for rcpt in ctx.rcpt_to.delivery.values_mut().flatten() {
+        rcpt.1 = Status::failed(Queuer::MaxDeferredAttemptReached);
+    }
+    queue_manager
+        .move_to(&QueueID::Deliver, &QueueID::Dead, &ctx)
+        .await
+        .unwrap();
+
+    admin
+        .execute(AdminCommand::Requeue(ctx.mail_from.queue_id()))
+        .await
+        .unwrap();
+    let ctx = queue_manager
+        .get_ctx(&QueueID::Deliver, &message_uuid)
+        .await
+        .unwrap();
+    assert!(ctx
+        .rcpt_to
+        .delivery
+        .values()
+        .flatten()
+        .all(|rcpt| matches!(rcpt.1, Status::Waiting { .. })));
+
+    admin
+        .execute(AdminCommand::Maintenance(true))
+        .await
+        .unwrap();
+    assert!(health.is_in_maintenance());
+}
+
+#[test_log::test(tokio::test)]
+async fn requeue_all() {
+    let config = std::sync::Arc::new(local_test());
+    let queue_manager = <vqueue::temp::QueueManager as vqueue::GenericQueueManager>::init(
+        config.clone(),
+        vec![Deliver::get_symbol()],
+    )
+    .unwrap();
+
+    let (emitter, _working, mut delivery) = scheduler::init(
+        config.server.queues.working.channel_size,
+        config.server.queues.delivery.channel_size,
+    );
+    let resolvers = std::sync::Arc::new(DnsResolvers::from_config(&config).unwrap());
+    let rule_engine = std::sync::Arc::new(
+        RuleEngine::with_hierarchy(
+            |builder| Ok(builder.add_root_filter_rules("#{}")?.build()),
+            config.clone(),
+            resolvers.clone(),
+            queue_manager.clone(),
+        )
+        .unwrap(),
+    );
+    let health = std::sync::Arc::new(Health::new(std::env::temp_dir()));
+
+    let admin = Admin::new(queue_manager.clone(), rule_engine, emitter, health);
+
+    let mut uuids = vec![];
+    for _ in 0..3 {
+        let mut ctx = local_ctx();
+        ctx.mail_from.message_uuid = uuid::Uuid::new_v4();
+        ctx.rcpt_to.delivery.insert(
+            WrapperSerde::Ready(std::sync::Arc::new(Deliver::new(
+                resolvers.get_resolver_root(),
+                config.clone(),
+            ))),
+            vec![(
+                "test@localhost".parse().unwrap(),
+                Status::failed(Queuer::MaxDeferredAttemptReached),
+            )],
+        );
+        queue_manager
+            .write_both(&QueueID::Dead, &ctx, &local_msg())
+            .await
+            .unwrap();
+        uuids.push(ctx.mail_from.message_uuid);
+    }
+
+    let processed = uuids[0];
+    assert!(queue_manager.claim(&processed).await.unwrap());
+
+    pretty_assertions::assert_eq!(
+        admin.execute(AdminCommand::RequeueAll).await.unwrap(),
+        vec![
+            format!("{processed}: message is being processed"),
+            "moved 2, failed 1".to_owned()
+        ]
+    );
+    queue_manager
+        .get_ctx(&QueueID::Dead, &processed)
+        .await
+        .unwrap();
+
+    let delivery_recv = delivery.as_stream();
+    tokio::pin!(delivery_recv);
+    let mut notified = vec![
+        *delivery_recv.next().await.unwrap().as_ref(),
+        *delivery_recv.next().await.unwrap().as_ref(),
+    ];
+    notified.sort();
+    let mut moved = uuids[1..].to_vec();
+    moved.sort();
+    pretty_assertions::assert_eq!(notified, moved);
+
+    for msg_uuid in &moved {
+        let ctx = queue_manager
+            .get_ctx(&QueueID::Deliver, msg_uuid)
+            .await
+            .unwrap();
+        assert!(ctx
+            .rcpt_to
+            .delivery
+            .values()
+            .flatten()
+            .all(|rcpt| matches!(rcpt.1, Status::Waiting { .. })));
+    }
+}
+
+#[test_log::test(tokio::test)]
+async fn ambiguous_message_id() {
+    let config = std::sync::Arc::new(local_test());
+    let queue_manager = <vqueue::temp::QueueManager as vqueue::GenericQueueManager>::init(
+        config.clone(),
+        vec![Deliver::get_symbol()],
+    )
+    .unwrap();
+
+    let (emitter, _working, _delivery) = scheduler::init(
+        config.server.queues.working.channel_size,
+        config.server.queues.delivery.channel_size,
+    );
+    let resolvers = std::sync::Arc::new(DnsResolvers::from_config(&config).unwrap());
+    let rule_engine = std::sync::Arc::new(
+        RuleEngine::with_hierarchy(
+            |builder| Ok(builder.add_root_filter_rules("#{}")?.build()),
+            config.clone(),
+            resolvers.clone(),
+            queue_manager.clone(),
+        )
+        .unwrap(),
+    );
+    let health = std::sync::Arc::new(Health::new(std::env::temp_dir()));
+
+    let admin = Admin::new(queue_manager.clone(), rule_engine, emitter, health);
+
+    let uuids = [
+        uuid::Uuid::from_u128(0xabcd_0001_0000_4000_8000_0000_0000_0001),
+        uuid::Uuid::from_u128(0xabcd_0002_0000_4000_8000_0000_0000_0002),
+    ];
+    for message_uuid in uuids {
+        let mut ctx = local_ctx();
+        ctx.mail_from.message_uuid = message_uuid;
+        queue_manager
+            .write_both(&QueueID::Deferred, &ctx, &local_msg())
+            .await
+            .unwrap();
+    }
+
+    let error = admin
+        .execute(AdminCommand::Hold("abcd".to_owned()))
+        .await
+        .unwrap_err();
+    assert!(error.to_string().contains("ambiguous"), "{error}");
+
+    admin
+        .execute(AdminCommand::Hold("ABCD0002".to_owned()))
+        .await
+        .unwrap();
+    pretty_assertions::assert_eq!(
+        admin
+            .execute(AdminCommand::List(QueueID::Hold))
+            .await
+            .unwrap(),
+        vec![uuids[1].to_string()]
+    );
+
+    admin
+        .execute(AdminCommand::Release("ffff".to_owned()))
+        .await
+        .unwrap_err();
+}
@@ -15,7 +15,9 @@
  *
 */
 
+use crate::clock::TestClock;
 use crate::config::{local_ctx, local_msg, local_test};
+use crate::sink::Sink;
 use vqueue::{GenericQueueManager, QueueID};
 use vsmtp_common::{
     transfer::Status,
@@ -23,6 +25,7 @@ use vsmtp_common::{
 };
 use vsmtp_config::DnsResolvers;
 use vsmtp_delivery::{Deliver, Forward, MBox, Maildir};
+use vsmtp_rule_engine::RuleEngine;
 use vsmtp_server::{delivery::deferred::handle_one, ProcessMessage};
 
 #[tokio::test]
@@ -63,7 +66,9 @@ async fn move_to_deferred() {
         config.clone(),
         queue_manager.clone(),
         ProcessMessage::new(message_uuid),
+        None,
         time::OffsetDateTime::UNIX_EPOCH,
+        std::sync::Arc::default(),
     )
     .await
     .unwrap();
@@ -103,7 +108,9 @@ async fn move_to_dead() {
         config.clone(),
         queue_manager.clone(),
         ProcessMessage::new(message_uuid),
+        None,
         time::OffsetDateTime::UNIX_EPOCH,
+        std::sync::Arc::default(),
     )
     .await
     .unwrap();
@@ -118,3 +125,329 @@ async fn move_to_dead() {
         .await
         .unwrap();
 }
+
+#[tokio::test]
+async fn retry_backoff() {
+    use time::ext::NumericalDuration;
+
+    let config = std::sync::Arc::new(local_test());
+    let queue_manager = <vqueue::temp::QueueManager as vqueue::GenericQueueManager>::init(
+        config.clone(),
+        vec![Forward::get_symbol()],
+    )
+    .unwrap();
+
+    // nobody is listening on this port, every attempt is held back
+    let port = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+
+    let clock = TestClock::start();
+    let start = clock.now();
+
+    let mut status = Status::default();
+    status.held_back(vsmtp_common::transfer::error::Queuer::StillWaiting);
+
+    let mut ctx = local_ctx();
+    let message_uuid = uuid::Uuid::new_v4();
+    ctx.mail_from.message_uuid = message_uuid;
+    ctx.rcpt_to.delivery.insert(
+        WrapperSerde::Ready(std::sync::Arc::new(Forward::new(
+            format!("127.0.0.1:{port}").parse().unwrap(),
+        ))),
+        vec![("test@localhost".parse().unwrap(), status)],
+    );
+
+    queue_manager
+        .write_both(&QueueID::Deferred, &ctx, &local_msg())
+        .await
+        .unwrap();
+
+    let errors = |ctx: vsmtp_common::ContextFinished| {
+        let (_, status) = ctx.rcpt_to.delivery.values().flatten().next().unwrap();
+        match status {
+            Status::HeldBack { errors } => errors
+                .iter()
+                .map(|error| *error.timestamp())
+                .collect::<Vec<_>>(),
+            otherwise => panic!("unexpected status: {otherwise:?}"),
+        }
+    };
+
+    // one error, the next attempt is 5 minutes after it
+    clock.advance(1.minutes());
+    handle_one(
+        config.clone(),
+        queue_manager.clone(),
+        ProcessMessage::new(message_uuid),
+        None,
+        clock.now(),
+        std::sync::Arc::default(),
+    )
+    .await
+    .unwrap();
+
+    let ctx = queue_manager
+        .get_ctx(&QueueID::Deferred, &message_uuid)
+        .await
+        .unwrap();
+    assert_eq!(errors(ctx), vec![start]);
+
+    clock.advance(5.minutes());
+    handle_one(
+        config.clone(),
+        queue_manager.clone(),
+        ProcessMessage::new(message_uuid),
+        None,
+        clock.now(),
+        std::sync::Arc::default(),
+    )
+    .await
+    .unwrap();
+
+    let ctx = queue_manager
+        .get_ctx(&QueueID::Deferred, &message_uuid)
+        .await
+        .unwrap();
+    assert_eq!(errors(ctx), vec![start, start + 6.minutes()]);
+}
+
+#[tokio::test]
+async fn deferred_by_rules() {
+    use time::ext::NumericalDuration;
+
+    let config = std::sync::Arc::new(local_test());
+    let queue_manager =
+        <vqueue::temp::QueueManager as vqueue::GenericQueueManager>::init(config.clone(), vec![])
+            .unwrap();
+
+    let clock = TestClock::start();
+
+    let mut ctx = local_ctx();
+    let message_uuid = uuid::Uuid::new_v4();
+    ctx.mail_from.message_uuid = message_uuid;
+    ctx.finished.defer(std::time::Duration::from_secs(600));
+
+    queue_manager
+        .write_both(&QueueID::Deferred, &ctx, &local_msg())
+        .await
+        .unwrap();
+
+    // not attempted before the delay given by the rules
+    clock.advance(9.minutes());
+    handle_one(
+        config.clone(),
+        queue_manager.clone(),
+        ProcessMessage::new(message_uuid),
+        None,
+        clock.now(),
+        std::sync::Arc::default(),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(
+        queue_manager
+            .get_ctx(&QueueID::Deferred, &message_uuid)
+            .await
+            .unwrap(),
+        ctx
+    );
+
+    // without recipients, the attempt moves the message to the dead queue
+    clock.advance(1.minutes());
+    handle_one(
+        config.clone(),
+        queue_manager.clone(),
+        ProcessMessage::new(message_uuid),
+        None,
+        clock.now(),
+        std::sync::Arc::default(),
+    )
+    .await
+    .unwrap();
+
+    queue_manager
+        .get_ctx(&QueueID::Deferred, &message_uuid)
+        .await
+        .unwrap_err();
+    queue_manager
+        .get_ctx(&QueueID::Dead, &message_uuid)
+        .await
+        .unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn routed_after_second_tempfail() {
+    use time::ext::NumericalDuration;
+
+    let smarthost = Sink::start();
+
+    let mut config = local_test();
+    config
+        .server
+        .queues
+        .delivery
+        .routing
+        .transports
+        .insert("smarthost".to_string(), smarthost.addr.to_string());
+    let config = std::sync::Arc::new(config);
+    let queue_manager = <vqueue::temp::QueueManager as vqueue::GenericQueueManager>::init(
+        config.clone(),
+        vec![Forward::get_symbol()],
+    )
+    .unwrap();
+    let resolvers = std::sync::Arc::new(DnsResolvers::from_config(&config).unwrap());
+
+    let rule_engine = std::sync::Arc::new(
+        RuleEngine::with_hierarchy(
+            |builder| {
+                Ok(builder
+                    .add_root_filter_rules(
+                        r#"
+fn on_delivery_attempt(rcpt, transport, attempt, last_error) {
+    if transport == "forward" && attempt > 2 && last_error.reason.starts_with("connection") {
+        "smarthost"
+    }
+}
+
+#{}
+"#,
+                    )?
+                    .build())
+            },
+            config.clone(),
+            resolvers,
+            queue_manager.clone(),
+        )
+        .unwrap(),
+    );
+
+    // nobody is listening on this port, every attempt is held back
+    let port = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+
+    let clock = TestClock::start();
+
+    let mut ctx = local_ctx();
+    let message_uuid = uuid::Uuid::new_v4();
+    ctx.mail_from.message_uuid = message_uuid;
+    ctx.rcpt_to.delivery.insert(
+        WrapperSerde::Ready(std::sync::Arc::new(Forward::new(
+            format!("127.0.0.1:{port}").parse().unwrap(),
+        ))),
+        vec![("test@localhost".parse().unwrap(), Status::default())],
+    );
+
+    queue_manager
+        .write_both(&QueueID::Deferred, &ctx, &local_msg())
+        .await
+        .unwrap();
+
+    let attempt = || {
+        handle_one(
+            config.clone(),
+            queue_manager.clone(),
+            ProcessMessage::new(message_uuid),
+            Some(rule_engine.clone()),
+            clock.now(),
+            std::sync::Arc::default(),
+        )
+    };
+
+    // the two first attempts keep the transport
+    for errors in 1..=2 {
+        attempt().await.unwrap();
+
+        let ctx = queue_manager
+            .get_ctx(&QueueID::Deferred, &message_uuid)
+            .await
+            .unwrap();
+        let (transport, rcpts) = ctx.rcpt_to.delivery.iter().next().unwrap();
+        assert!(format!("{transport:?}").contains(&port.to_string()));
+        assert!(matches!(&rcpts[0].1, Status::HeldBack { errors: e } if e.len() == errors));
+
+        clock.advance(6.minutes());
+    }
+
+    // the third is sent to the smarthost
+    attempt().await.unwrap();
+
+    queue_manager
+        .get_ctx(&QueueID::Deferred, &message_uuid)
+        .await
+        .unwrap_err();
+    queue_manager
+        .get_ctx(&QueueID::Dead, &message_uuid)
+        .await
+        .unwrap_err();
+
+    let received = smarthost.wait_for(1);
+    assert!(received[0].message.contains("Subject: Happy new year\r\n"));
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn routing_stopped_after_timeout() {
+    let mut config = local_test();
+    config
+        .server
+        .queues
+        .delivery
+        .routing
+        .transports
+        .insert("smarthost".to_string(), "127.0.0.1:25".to_string());
+    config.server.queues.delivery.routing.timeout = std::time::Duration::from_millis(50);
+    let config = std::sync::Arc::new(config);
+    let queue_manager = <vqueue::temp::QueueManager as vqueue::GenericQueueManager>::init(
+        config.clone(),
+        vec![Forward::get_symbol()],
+    )
+    .unwrap();
+    let resolvers = std::sync::Arc::new(DnsResolvers::from_config(&config).unwrap());
+
+    let rule_engine = RuleEngine::with_hierarchy(
+        |builder| {
+            Ok(builder
+                .add_root_filter_rules(
+                    r#"
+fn on_delivery_attempt(rcpt, transport, attempt, last_error) {
+    let i = 0;
+    while i >= 0 { i += 1; }
+    "smarthost"
+}
+
+#{}
+"#,
+                )?
+                .build())
+        },
+        config.clone(),
+        resolvers,
+        queue_manager,
+    )
+    .unwrap();
+
+    let mut ctx = local_ctx();
+    let transport = WrapperSerde::Ready(std::sync::Arc::new(Forward::new(
+        "127.0.0.1:2525".parse().unwrap(),
+    )));
+    ctx.rcpt_to.delivery.insert(
+        transport.clone(),
+        vec![("test@localhost".parse().unwrap(), Status::default())],
+    );
+
+    let start = std::time::Instant::now();
+    assert_eq!(rule_engine.route_delivery(&mut ctx), None);
+    assert!(start.elapsed() < std::time::Duration::from_secs(5));
+
+    // the transport is kept.
+    assert_eq!(
+        ctx.rcpt_to.delivery.keys().collect::<Vec<_>>(),
+        vec![&transport]
+    );
+}
@@ -0,0 +1,272 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2022 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+
+use crate::{
+    config::{local_ctx, local_test},
+    sink::Sink,
+};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
+use tokio_stream::StreamExt;
+use vqueue::{GenericQueueManager, QueueID};
+use vsmtp_common::{
+    transfer::Status,
+    transport::{AbstractTransport, WrapperSerde},
+};
+use vsmtp_config::DnsResolvers;
+use vsmtp_delivery::Maildir;
+use vsmtp_mail_parser::MessageBody;
+use vsmtp_protocol::{AcceptArgs, ConnectionKind};
+use vsmtp_rule_engine::{RuleEngine, TRUNCATED_HEADER};
+use vsmtp_server::{scheduler, working::handle_one, ProcessMessage, Server};
+
+/// Rules delegating the first 256 bytes of the body to the filter listening on `port`,
+/// and quarantining the message if the filter scored it as spam.
+fn rules(port: u16, receiver: std::net::SocketAddr) -> String {
+    format!(
+        r#"
+let filter = smtp::connect(#{{
+    delegator: #{{ address: "127.0.0.1:{port}", timeout: "2s" }},
+    receiver: "{receiver}",
+}});
+
+#{{
+    postq: [
+        delegate filter "classify" #{{
+            send: "truncate:256",
+            evaluate: || if msg::get_header("X-Spam-Score") == "5" {{
+                state::quarantine("spam")
+            }} else {{
+                state::next()
+            }},
+        }},
+    ],
+}}
+"#
+    )
+}
+
+/// Send `message` back to the server listening on `server_addr`, as the filter does.
+async fn send_back(server_addr: std::net::SocketAddr, message: &str) -> Vec<String> {
+    let input = [
+        "HELO filter.testserver.com\r\n".to_owned(),
+        "MAIL FROM:<client@testserver.com>\r\n".to_owned(),
+        "RCPT TO:<recipient@testserver.com>\r\n".to_owned(),
+        "DATA\r\n".to_owned(),
+        format!("{message}.\r\n"),
+        "QUIT\r\n".to_owned(),
+    ];
+
+    let stream = tokio::net::TcpStream::connect(server_addr).await.unwrap();
+    let mut stream = tokio::io::BufReader::new(stream);
+    let mut replies = vec![];
+    let mut input = input.iter();
+    loop {
+        let mut line = String::new();
+        if stream.read_line(&mut line).await.unwrap() == 0 {
+            break;
+        }
+        let continued = line.chars().nth(3) == Some('-');
+        replies.push(line);
+        if continued {
+            continue;
+        }
+        match input.next() {
+            Some(command) => stream
+                .get_mut()
+                .write_all(command.as_bytes())
+                .await
+                .unwrap(),
+            None => break,
+        }
+    }
+    replies
+}
+
+#[test_log::test(tokio::test(flavor = "multi_thread"))]
+async fn truncated_and_merged() {
+    let config = std::sync::Arc::new(local_test());
+    let queue_manager = <vqueue::temp::QueueManager as vqueue::GenericQueueManager>::init(
+        config.clone(),
+        vec![Maildir::get_symbol()],
+    )
+    .unwrap();
+    let resolvers = std::sync::Arc::new(DnsResolvers::from_config(&config).unwrap());
+    let (emitter, mut working, _delivery) = scheduler::init(
+        config.server.queues.working.channel_size,
+        config.server.queues.delivery.channel_size,
+    );
+
+    let filter = Sink::start();
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let receiver = listener.local_addr().unwrap();
+
+    let rules = rules(filter.port(), receiver);
+    let rule_engine = std::sync::Arc::new(
+        RuleEngine::with_hierarchy(
+            move |builder| {
+                Ok(builder
+                    .add_root_filter_rules("#{}")?
+                    .add_domain_rules("testserver.com".parse().unwrap())
+                    .with_incoming(&rules)?
+                    .with_outgoing(&rules)?
+                    .with_internal(&rules)?
+                    .build()
+                    .build())
+            },
+            config.clone(),
+            resolvers,
+            queue_manager.clone(),
+        )
+        .unwrap(),
+    );
+
+    let body = (0..100)
+        .map(|i| format!("line {i} of the body\r\n"))
+        .collect::<String>();
+    let original = MessageBody::new(
+        [
+            "From: john@testserver.com\r\n",
+            "Subject: hello\r\n",
+            "X-Spam-Score: 0\r\n",
+        ]
+        .into_iter()
+        .map(str::to_string)
+        .collect(),
+        body.clone(),
+    );
+
+    let mut ctx = local_ctx();
+    let message_uuid = uuid::Uuid::new_v4();
+    ctx.mail_from.message_uuid = message_uuid;
+    // the recipients of the delegation are the ones to deliver.
+    ctx.rcpt_to.delivery.insert(
+        WrapperSerde::Ready(std::sync::Arc::new(Maildir::new(None))),
+        ctx.rcpt_to
+            .forward_paths
+            .iter()
+            .map(|rcpt| (rcpt.clone(), Status::default()))
+            .collect(),
+    );
+    queue_manager
+        .write_both(&QueueID::Working, &ctx, &original)
+        .await
+        .unwrap();
+
+    // the message is delegated, reduced to its headers and the first lines of its body.
+    handle_one(
+        rule_engine.clone(),
+        queue_manager.clone(),
+        ProcessMessage::new(message_uuid),
+        emitter.clone(),
+        std::sync::Arc::default(),
+    )
+    .await
+    .unwrap();
+    queue_manager
+        .get_ctx(&QueueID::Delegated, &message_uuid)
+        .await
+        .unwrap();
+
+    let sent = filter.wait_for(1).remove(0).message;
+    let (sent_headers, sent_body) = sent.split_once("\r\n\r\n").unwrap();
+    // NOTE: lettre terminates the data with `\r\n.\r\n`, adding an empty line.
+    let sent_body = sent_body.strip_suffix("\r\n").unwrap();
+    assert!(sent_headers.contains(&format!("{TRUNCATED_HEADER}: {}\r\n", body.len())));
+    assert!(
+        sent_body.len() <= 256 && sent_body.ends_with("\r\n"),
+        "{sent_body}"
+    );
+    assert!(body.starts_with(sent_body));
+
+    // the filter scores the message, and rewrites the body it received.
+    let result = format!(
+        "X-Virus-Scanned: clean\r\n{}",
+        sent.replace("X-Spam-Score: 0", "X-Spam-Score: 5")
+            .replace("line 0 of the body", "rewritten by the filter")
+    );
+
+    let server = tokio::spawn({
+        let (config, rule_engine, queue_manager, emitter) = (
+            config.clone(),
+            rule_engine.clone(),
+            queue_manager.clone(),
+            emitter.clone(),
+        );
+        async move {
+            let (stream, client_addr) = listener.accept().await.unwrap();
+            Server::serve(
+                AcceptArgs::new(
+                    client_addr,
+                    receiver,
+                    vsmtp_common::clock::now(),
+                    uuid::Uuid::new_v4(),
+                    ConnectionKind::Relay,
+                ),
+                stream,
+                None,
+                config,
+                rule_engine,
+                queue_manager,
+                emitter,
+                None,
+                std::sync::Arc::default(),
+                None,
+                None,
+            )
+            .await
+        }
+    });
+    let replies = send_back(receiver, &result).await;
+    server.await.unwrap().unwrap();
+    assert!(replies[5].starts_with("250 "), "{replies:?}");
+
+    // only the headers of the filter are applied onto the message stored.
+    let merged = queue_manager.get_msg(&message_uuid).await.unwrap();
+    assert_eq!(merged.inner().body().as_deref(), Some(body.as_str()));
+    assert_eq!(merged.get_header("X-Spam-Score").as_deref(), Some("5"));
+    assert_eq!(
+        merged.get_header("X-Virus-Scanned").as_deref(),
+        Some("clean")
+    );
+    assert_eq!(merged.get_header(TRUNCATED_HEADER), None);
+
+    // the result of the delegation is evaluated by the rules.
+    let working_recv = working.as_stream();
+    tokio::pin!(working_recv);
+    let process_message = working_recv.next().await.unwrap();
+    assert!(process_message.is_from_delegation());
+    assert_eq!(*process_message.as_ref(), message_uuid);
+
+    handle_one(
+        rule_engine,
+        queue_manager.clone(),
+        process_message,
+        emitter,
+        std::sync::Arc::default(),
+    )
+    .await
+    .unwrap();
+    queue_manager
+        .get_ctx(
+            &QueueID::Quarantine {
+                name: "spam".to_string(),
+            },
+            &message_uuid,
+        )
+        .await
+        .unwrap();
+}
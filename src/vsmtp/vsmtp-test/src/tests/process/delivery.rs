@@ -17,10 +17,15 @@
 
 use crate::config::{local_ctx, local_msg, local_test};
 use vqueue::{GenericQueueManager, QueueID};
-use vsmtp_common::transfer;
+use vsmtp_common::transfer::{
+    self,
+    error::{Lookup, Variant},
+};
 use vsmtp_common::transport::{AbstractTransport, WrapperSerde};
 use vsmtp_config::DnsResolvers;
-use vsmtp_delivery::{Deliver, Forward, MBox, Maildir};
+use vsmtp_delivery::{
+    split_and_sort_and_send, Deliver, DeliveryState, Forward, MBox, Maildir, SenderOutcome,
+};
 use vsmtp_rule_engine::{ExecutionStage, RuleEngine};
 use vsmtp_server::{delivery::deliver::handle_one, ProcessMessage};
 
@@ -39,14 +44,19 @@ async fn move_to_deferred() {
     .unwrap();
     let resolvers = std::sync::Arc::new(DnsResolvers::from_config(&config).unwrap());
 
+    // the connection to the server is refused, a transient failure.
+    let closed = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+
     let mut ctx = local_ctx();
     let message_uuid = uuid::Uuid::new_v4();
     ctx.mail_from.message_uuid = message_uuid;
     ctx.rcpt_to
         .delivery
-        .entry(WrapperSerde::Ready(std::sync::Arc::new(Deliver::new(
-            resolvers.get_resolver_root(),
-            config.clone(),
+        .entry(WrapperSerde::Ready(std::sync::Arc::new(Forward::new(
+            closed.to_string().parse().unwrap(),
         ))))
         .and_modify(|rcpt| {
             rcpt.push((
@@ -88,6 +98,7 @@ async fn move_to_deferred() {
             )
             .unwrap(),
         ),
+        std::sync::Arc::default(),
     )
     .await
     .unwrap();
@@ -103,6 +114,76 @@ async fn move_to_deferred() {
         .unwrap();
 }
 
+/// Answer every query on a local UDP socket with the response code `rcode`.
+async fn fake_dns(rcode: u8) -> std::net::SocketAddr {
+    let socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let addr = socket.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        let mut buffer = [0; 512];
+        while let Ok((len, peer)) = socket.recv_from(&mut buffer).await {
+            // the header and the question of the query, without its additional records.
+            let mut end = 12;
+            while end < len && buffer[end] != 0 {
+                end += usize::from(buffer[end]) + 1;
+            }
+            end = std::cmp::min(end + 5, len);
+
+            let mut response = buffer[..end].to_vec();
+            response[2] = 0x80 | (buffer[2] & 0x01);
+            response[3] = 0x80 | rcode;
+            response[6..12].fill(0);
+            let _ = socket.send_to(&response, peer).await;
+        }
+    });
+
+    addr
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn domain_not_found_to_dead() {
+    let config = std::sync::Arc::new(local_test());
+    let dns = fake_dns(3).await;
+    let resolver = trust_dns_resolver::TokioAsyncResolver::tokio(
+        trust_dns_resolver::config::ResolverConfig::from_parts(
+            None,
+            vec![],
+            trust_dns_resolver::config::NameServerConfigGroup::from_ips_clear(
+                &[dns.ip()],
+                dns.port(),
+                true,
+            ),
+        ),
+        trust_dns_resolver::config::ResolverOpts::default(),
+    )
+    .unwrap();
+
+    let mut ctx = local_ctx();
+    ctx.rcpt_to.delivery.insert(
+        WrapperSerde::Ready(std::sync::Arc::new(Deliver::new(
+            std::sync::Arc::new(resolver),
+            config.clone(),
+        ))),
+        vec![(
+            "test@foobar.com".parse().unwrap(),
+            transfer::Status::default(),
+        )],
+    );
+
+    let state = std::sync::Arc::new(DeliveryState::new(&config));
+    assert!(matches!(
+        split_and_sort_and_send(config, &state, &mut ctx, &local_msg()).await,
+        SenderOutcome::MoveToDead
+    ));
+
+    let (_, status) = &ctx.rcpt_to.delivery.values().next().unwrap()[0];
+    assert!(matches!(
+        status,
+        transfer::Status::Failed { error }
+            if *error.variant() == Variant::Lookup(Lookup::DomainNotFound {})
+    ));
+}
+
 #[tokio::test]
 async fn denied() {
     let config = std::sync::Arc::new(local_test());
@@ -140,6 +221,7 @@ async fn denied() {
             )
             .unwrap(),
         ),
+        std::sync::Arc::default(),
     )
     .await
     .unwrap();
@@ -0,0 +1,222 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2022 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use crate::{
+    clock::TestClock,
+    config::{local_ctx, local_msg, local_test},
+    sink::Sink,
+};
+use vqueue::{GenericQueueManager, QueueID};
+use vsmtp_common::{
+    addr, transfer,
+    transport::{AbstractTransport, WrapperSerde},
+    ContextFinished, OriginalRecipient,
+};
+use vsmtp_delivery::{split_and_sort_and_send, Deliver, DeliveryState, Forward, SenderOutcome};
+use vsmtp_server::{delivery::deferred::handle_one, ProcessMessage};
+
+/// A SMTP server advertising DSN, replying `rcpt_reply` to the recipients.
+fn dsn_sink(rcpt_reply: &'static str) -> Sink {
+    Sink::builder()
+        .with_capabilities(&["DSN", "8BITMIME"])
+        .with_reply(move |turn| (turn.verb() == "RCPT").then(|| rcpt_reply.to_owned()))
+        .start()
+}
+
+/// A message received with `ENVID` and `ORCPT` (with characters to encode),
+/// forwarded to the sink listening on `port`.
+fn dsn_ctx(port: u16) -> ContextFinished {
+    let mut ctx = local_ctx();
+    ctx.mail_from.message_uuid = uuid::Uuid::new_v4();
+    ctx.mail_from.envelop_id = Some("QQ+314159=".to_owned());
+    ctx.rcpt_to.original_recipients.insert(
+        addr!("recipient@testserver.com"),
+        OriginalRecipient {
+            addr_type: "rfc822".to_owned(),
+            mailbox: addr!("john+doe@example.com"),
+        },
+    );
+    ctx.rcpt_to.delivery.insert(
+        WrapperSerde::Ready(std::sync::Arc::new(Forward::new(
+            format!("127.0.0.1:{port}").parse().unwrap(),
+        ))),
+        vec![(
+            addr!("recipient@testserver.com"),
+            transfer::Status::default(),
+        )],
+    );
+    ctx
+}
+
+#[tokio::test]
+async fn parameters_relayed() {
+    let sink = dsn_sink("250 Ok");
+
+    let mut ctx = dsn_ctx(sink.port());
+    let config = std::sync::Arc::new(local_test());
+    let state = std::sync::Arc::new(DeliveryState::new(&config));
+    let outcome = split_and_sort_and_send(config, &state, &mut ctx, &local_msg()).await;
+    assert!(matches!(outcome, SenderOutcome::RemoveFromDisk));
+
+    let commands = &sink.wait_for_sessions(1)[0].commands;
+    assert!(commands.contains(&"MAIL FROM:<client@testserver.com> ENVID=QQ+2B314159+3D".to_owned()));
+    assert!(commands.contains(
+        &"RCPT TO:<recipient@testserver.com> ORCPT=rfc822;john+2Bdoe@example.com".to_owned()
+    ));
+}
+
+#[tokio::test]
+async fn bounce() {
+    let sink = dsn_sink("550 5.1.1 No such user");
+
+    let config = std::sync::Arc::new(local_test());
+    let queue_manager = <vqueue::temp::QueueManager as vqueue::GenericQueueManager>::init(
+        config.clone(),
+        vec![Deliver::get_symbol(), Forward::get_symbol()],
+    )
+    .unwrap();
+
+    let ctx = dsn_ctx(sink.port());
+    let message_uuid = ctx.mail_from.message_uuid;
+    queue_manager
+        .write_both(&QueueID::Deferred, &ctx, &local_msg())
+        .await
+        .unwrap();
+
+    handle_one(
+        config.clone(),
+        queue_manager.clone(),
+        ProcessMessage::new(message_uuid),
+        None,
+        time::OffsetDateTime::UNIX_EPOCH,
+        std::sync::Arc::default(),
+    )
+    .await
+    .unwrap();
+
+    queue_manager
+        .get_ctx(&QueueID::Dead, &message_uuid)
+        .await
+        .unwrap();
+
+    let queued = queue_manager
+        .list(&QueueID::Deferred)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|id| uuid::Uuid::parse_str(&id.unwrap()).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(queued.len(), 1);
+    assert_ne!(queued[0], message_uuid);
+
+    let (report, body) = queue_manager
+        .get_both(&QueueID::Deferred, &queued[0])
+        .await
+        .unwrap();
+    assert_eq!(report.mail_from.reverse_path, None);
+    assert_eq!(
+        report.rcpt_to.delivery.values().flatten().next().unwrap().0,
+        addr!("client@testserver.com")
+    );
+
+    let content = body.inner().to_string();
+    for field in [
+        "Content-Type: multipart/report; report-type=delivery-status;",
+        "Original-Envelope-Id: QQ+2B314159+3D\r\n",
+        "Final-Recipient: rfc822; recipient@testserver.com\r\n",
+        "Original-Recipient: rfc822;john+2Bdoe@example.com\r\n",
+        "Status: 5.1.1\r\n",
+        "Diagnostic-Code: smtp; 550 5.1.1 No such user\r\n",
+    ] {
+        assert!(content.contains(field), "{field} not in {content}");
+    }
+}
+
+#[tokio::test]
+async fn delayed_bounce() {
+    use time::{ext::NumericalDuration, format_description::well_known::Rfc2822};
+
+    let mut config = local_test();
+    config.server.queues.delivery.deferred_retry_max = 2;
+    let config = std::sync::Arc::new(config);
+    let queue_manager = <vqueue::temp::QueueManager as vqueue::GenericQueueManager>::init(
+        config.clone(),
+        vec![Deliver::get_symbol(), Forward::get_symbol()],
+    )
+    .unwrap();
+
+    // nobody is listening on this port, every attempt is held back
+    let port = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+
+    let clock = TestClock::start();
+    let arrival = clock.now();
+
+    let mut ctx = dsn_ctx(port);
+    ctx.mail_from.mail_timestamp = arrival;
+    let message_uuid = ctx.mail_from.message_uuid;
+    queue_manager
+        .write_both(&QueueID::Deferred, &ctx, &local_msg())
+        .await
+        .unwrap();
+
+    for delay in [1.minutes(), 2.hours()] {
+        clock.advance(delay);
+        handle_one(
+            config.clone(),
+            queue_manager.clone(),
+            ProcessMessage::new(message_uuid),
+            None,
+            clock.now(),
+            std::sync::Arc::default(),
+        )
+        .await
+        .unwrap();
+    }
+
+    queue_manager
+        .get_ctx(&QueueID::Dead, &message_uuid)
+        .await
+        .unwrap();
+
+    let queued = queue_manager
+        .list(&QueueID::Deferred)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|id| uuid::Uuid::parse_str(&id.unwrap()).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(queued.len(), 1);
+
+    let (report, body) = queue_manager
+        .get_both(&QueueID::Deferred, &queued[0])
+        .await
+        .unwrap();
+    assert_eq!(report.mail_from.mail_timestamp, clock.now());
+
+    let content = body.inner().to_string();
+    for field in [
+        format!("Date: {}\r\n", clock.now().format(&Rfc2822).unwrap()),
+        format!("Arrival-Date: {}\r\n", arrival.format(&Rfc2822).unwrap()),
+        "Action: failed\r\n".to_owned(),
+        "Status: 4.4.7\r\n".to_owned(),
+    ] {
+        assert!(content.contains(&field), "{field} not in {content}");
+    }
+}
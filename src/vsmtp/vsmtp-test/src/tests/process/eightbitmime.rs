@@ -0,0 +1,125 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2022 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use crate::config::{local_ctx, local_test};
+use crate::sink::Sink;
+use vsmtp_common::{
+    transfer::{self, error::Delivery},
+    transport::WrapperSerde,
+};
+use vsmtp_delivery::{split_and_sort_and_send, DeliveryState, Forward, SenderOutcome};
+use vsmtp_mail_parser::MessageBody;
+
+const TEXT: &str = "Bonjour à tous, voilà le compte rendu de la réunion.\r\n";
+
+fn eight_bit_message() -> MessageBody {
+    MessageBody::new(
+        [
+            "From: NoBody <nobody@domain.tld>\r\n",
+            "To: Hei <hei@domain.tld>\r\n",
+            "Subject: Compte rendu\r\n",
+            "MIME-Version: 1.0\r\n",
+            "Content-Type: text/plain; charset=utf-8\r\n",
+            "Content-Transfer-Encoding: 8bit\r\n",
+        ]
+        .into_iter()
+        .map(str::to_string)
+        .collect(),
+        TEXT.to_string(),
+    )
+}
+
+async fn send_to_sink(
+    eightbitmime_downgrade: bool,
+) -> (SenderOutcome, vsmtp_common::ContextFinished, Option<String>) {
+    // not advertising 8BITMIME.
+    let sink = Sink::start();
+
+    let mut config = local_test();
+    config.server.queues.delivery.eightbitmime_downgrade = eightbitmime_downgrade;
+
+    let mut ctx = local_ctx();
+    ctx.rcpt_to.delivery.insert(
+        WrapperSerde::Ready(std::sync::Arc::new(Forward::new(
+            sink.addr.to_string().parse().unwrap(),
+        ))),
+        vec![(
+            "recipient@testserver.com".parse().unwrap(),
+            transfer::Status::default(),
+        )],
+    );
+
+    let message = eight_bit_message();
+    let state = std::sync::Arc::new(DeliveryState::new(&config));
+    let outcome =
+        split_and_sort_and_send(std::sync::Arc::new(config), &state, &mut ctx, &message).await;
+
+    // the message is re-encoded, or refused, on the first connection.
+    assert_eq!(sink.sessions().len(), 1);
+
+    let received = sink
+        .transactions()
+        .into_iter()
+        .next()
+        .map(|transaction| transaction.message);
+
+    // the queued copy is untouched
+    assert_eq!(
+        message.inner().to_string(),
+        eight_bit_message().inner().to_string()
+    );
+
+    (outcome, ctx, received)
+}
+
+#[tokio::test]
+async fn downgraded() {
+    let (outcome, _, received) = send_to_sink(true).await;
+    assert!(matches!(outcome, SenderOutcome::RemoveFromDisk));
+
+    let received = received.unwrap();
+    assert!(received.is_ascii());
+
+    let (headers, body) = received.split_once("\r\n\r\n").unwrap();
+    assert!(headers.contains("Content-Transfer-Encoding: quoted-printable"));
+    assert!(!headers.contains("Content-Transfer-Encoding: 8bit"));
+
+    // NOTE: lettre terminates the data with `\r\n.\r\n`, adding an empty line.
+    let body = body.strip_suffix("\r\n").unwrap();
+    assert_eq!(
+        String::from_utf8(
+            quoted_printable::decode(body, quoted_printable::ParseMode::Strict).unwrap()
+        )
+        .unwrap(),
+        TEXT
+    );
+}
+
+#[tokio::test]
+async fn downgrade_disabled() {
+    let (outcome, ctx, received) = send_to_sink(false).await;
+    assert!(matches!(outcome, SenderOutcome::MoveToDead));
+    assert_eq!(received, None);
+
+    let (_, status) = ctx.rcpt_to.delivery.values().flatten().next().unwrap();
+    assert_eq!(
+        *status,
+        transfer::Status::failed(transfer::error::Variant::Delivery(vec![(
+            "127.0.0.1".parse().unwrap(),
+            Delivery::EightBitMimeNotSupported
+        )]))
+    );
+}
@@ -0,0 +1,303 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2023 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+
+use crate::config::local_test;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
+use vqueue::{GenericQueueManager, QueueID};
+use vsmtp_common::transport::AbstractTransport;
+use vsmtp_config::DnsResolvers;
+use vsmtp_delivery::Deliver;
+use vsmtp_protocol::{AcceptArgs, ConnectionKind};
+use vsmtp_rule_engine::RuleEngine;
+use vsmtp_server::{scheduler, working::handle_one, ProcessMessage, Server};
+
+/// Hostile messages, one per file, with `\n` line endings.
+const CORPUS: &str = "src/tests/process/corpus";
+
+/// Rules reading the headers and parsing the message.
+const RULES: &str = r#"#{
+    postq: [
+        action "log the headers" || {
+            log("info", `From: ${msg::get_header("From")}, Subject: ${msg::get_header("Subject")}`);
+        },
+        rule "quarantine the truncated" || if msg::is_parse_truncated() {
+            state::quarantine("corpus")
+        } else {
+            state::next()
+        },
+    ],
+}"#;
+
+/// The content of the `DATA` command for `raw`, with its terminating dot.
+fn data(raw: &[u8]) -> Vec<u8> {
+    let mut out = vec![];
+    for line in raw
+        .strip_suffix(b"\n")
+        .unwrap_or(raw)
+        .split(|c| *c == b'\n')
+    {
+        if line.first() == Some(&b'.') {
+            out.push(b'.');
+        }
+        out.extend_from_slice(line);
+        out.extend_from_slice(b"\r\n");
+    }
+    out.extend_from_slice(b".\r\n");
+    out
+}
+
+/// Send `raw` to the server, and run the `postq` stage on the message accepted.
+///
+/// Returns the replies received, and the queues containing the message afterward.
+async fn run(raw: &[u8]) -> (Vec<String>, Vec<QueueID>) {
+    let (replies, queues, _) = run_with(raw, |_| ()).await;
+    (replies, queues)
+}
+
+/// Same as [`run`], with the configuration modified by `configure`.
+///
+/// Also returns the messages as stored.
+async fn run_with(
+    raw: &[u8],
+    configure: impl FnOnce(&mut vsmtp_config::Config),
+) -> (Vec<String>, Vec<QueueID>, Vec<Vec<u8>>) {
+    let mut config = local_test();
+    config.server.smtp.line_length_max = 1 << 16;
+    configure(&mut config);
+    let config = std::sync::Arc::new(config);
+    let queue_manager = <vqueue::temp::QueueManager as vqueue::GenericQueueManager>::init(
+        config.clone(),
+        vec![Deliver::get_symbol()],
+    )
+    .unwrap();
+    let resolvers = std::sync::Arc::new(DnsResolvers::from_config(&config).unwrap());
+    let rule_engine = std::sync::Arc::new(
+        RuleEngine::with_hierarchy(
+            |builder| {
+                Ok(builder
+                    .add_root_filter_rules("#{}")?
+                    .add_domain_rules("testserver.com".parse().unwrap())
+                    .with_incoming(RULES)?
+                    .with_outgoing("#{}")?
+                    .with_internal("#{}")?
+                    .build()
+                    .build())
+            },
+            config.clone(),
+            resolvers,
+            queue_manager.clone(),
+        )
+        .unwrap(),
+    );
+    let (emitter, _working, _delivery) = scheduler::init(1, 1);
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let server_addr = listener.local_addr().unwrap();
+    let server = tokio::spawn({
+        let (config, rule_engine, queue_manager, emitter) = (
+            config.clone(),
+            rule_engine.clone(),
+            queue_manager.clone(),
+            emitter.clone(),
+        );
+        async move {
+            let (stream, client_addr) = listener.accept().await.unwrap();
+            Server::serve(
+                AcceptArgs::new(
+                    client_addr,
+                    server_addr,
+                    vsmtp_common::clock::now(),
+                    uuid::Uuid::new_v4(),
+                    ConnectionKind::Relay,
+                ),
+                stream,
+                None,
+                config,
+                rule_engine,
+                queue_manager,
+                emitter,
+                None,
+                std::sync::Arc::default(),
+                None,
+                None,
+            )
+            .await
+        }
+    });
+
+    let input = [
+        b"HELO client.com\r\n".to_vec(),
+        b"MAIL FROM:<john@example.com>\r\n".to_vec(),
+        b"RCPT TO:<jenny@testserver.com>\r\n".to_vec(),
+        b"DATA\r\n".to_vec(),
+        data(raw),
+        b"QUIT\r\n".to_vec(),
+    ];
+    let stream = tokio::net::TcpStream::connect(server_addr).await.unwrap();
+    let mut stream = tokio::io::BufReader::new(stream);
+    let mut replies = vec![];
+    let mut input = input.iter();
+    loop {
+        let mut line = String::new();
+        if stream.read_line(&mut line).await.unwrap() == 0 {
+            break;
+        }
+        let continued = line.chars().nth(3) == Some('-');
+        replies.push(line);
+        if continued {
+            continue;
+        }
+        match input.next() {
+            Some(command) => stream.get_mut().write_all(command).await.unwrap(),
+            None => break,
+        }
+    }
+    server.await.unwrap().unwrap();
+
+    for message_uuid in queue_manager.list(&QueueID::Working).await.unwrap() {
+        handle_one(
+            rule_engine.clone(),
+            queue_manager.clone(),
+            ProcessMessage::new(message_uuid.unwrap().parse().unwrap()),
+            emitter.clone(),
+            std::sync::Arc::default(),
+        )
+        .await
+        .unwrap();
+    }
+
+    let (mut queues, mut messages) = (vec![], vec![]);
+    for queue in [
+        QueueID::Working,
+        QueueID::Deliver,
+        QueueID::Dead,
+        QueueID::Quarantine {
+            name: "corpus".to_string(),
+        },
+        QueueID::Quarantine {
+            name: "invalid_utf8".to_string(),
+        },
+    ] {
+        // NOTE: a queue never written to may not exist.
+        for message_uuid in queue_manager.list(&queue).await.unwrap_or_default() {
+            queues.push(queue.clone());
+            messages.push(
+                queue_manager
+                    .get_msg_raw(&message_uuid.unwrap().parse().unwrap())
+                    .await
+                    .unwrap(),
+            );
+        }
+    }
+
+    (replies, queues, messages)
+}
+
+#[test_log::test(tokio::test)]
+async fn hostile_corpus() {
+    let mut paths = std::fs::read_dir(CORPUS)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect::<Vec<_>>();
+    paths.sort();
+    assert!(!paths.is_empty());
+
+    for path in paths {
+        let (replies, queues) = run(&std::fs::read(&path).unwrap()).await;
+
+        assert!(
+            replies
+                .get(5)
+                .map_or(false, |reply| reply.starts_with("250 ")),
+            "{}: {replies:?}",
+            path.display()
+        );
+        assert!(
+            matches!(
+                queues.as_slice(),
+                [QueueID::Deliver | QueueID::Dead | QueueID::Quarantine { .. }]
+            ),
+            "{}: {queues:?}",
+            path.display()
+        );
+    }
+}
+
+#[test_log::test(tokio::test)]
+async fn mime_limits_of_config() {
+    let raw = b"From: john@example.com\n\
+To: jenny@testserver.com\n\
+Subject: nested\n\
+Date: Tue, 30 Nov 2021 20:54:27 +0100\n\
+MIME-Version: 1.0\n\
+Content-Type: multipart/mixed; boundary=\"outer\"\n\
+\n\
+--outer\n\
+Content-Type: multipart/mixed; boundary=\"inner\"\n\
+\n\
+--inner\n\
+Content-Type: text/plain\n\
+\n\
+hello\n\
+--inner--\n\
+--outer--\n";
+
+    let (_, queues) = run(raw).await;
+    assert_eq!(queues, [QueueID::Deliver]);
+
+    let (_, queues, _) = run_with(raw, |config| config.server.mime.max_depth = 1).await;
+    assert_eq!(
+        queues,
+        [QueueID::Quarantine {
+            name: "corpus".to_string()
+        }]
+    );
+}
+
+#[test_log::test(tokio::test)]
+async fn invalid_utf8_delivered() {
+    let raw = std::fs::read("src/tests/process/invalid_utf8.eml").unwrap();
+    let (replies, queues, messages) = run_with(&raw, |_| ()).await;
+
+    assert_eq!(replies.get(5).map(String::as_str), Some("250 Ok\r\n"));
+    assert_eq!(queues, [QueueID::Deliver]);
+
+    // stored as received, the trace headers are added at the delivery.
+    let data = data(&raw);
+    assert_eq!(messages, [data.strip_suffix(b".\r\n").unwrap()]);
+}
+
+#[test_log::test(tokio::test)]
+async fn invalid_utf8_quarantined() {
+    let (replies, queues, _) = run_with(
+        &std::fs::read("src/tests/process/invalid_utf8.eml").unwrap(),
+        |config| config.server.smtp.invalid_utf8_quarantine = Some("invalid_utf8".to_string()),
+    )
+    .await;
+
+    assert_eq!(replies.get(5).map(String::as_str), Some("250 Ok\r\n"));
+    assert_eq!(
+        replies.last().map(String::as_str),
+        Some("221 Service closing transmission channel\r\n")
+    );
+    assert_eq!(
+        queues,
+        [QueueID::Quarantine {
+            name: "invalid_utf8".to_string()
+        }]
+    );
+}
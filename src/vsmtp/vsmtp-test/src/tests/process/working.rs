@@ -16,11 +16,31 @@
 */
 
 use crate::config::{local_ctx, local_msg, local_test};
+use crate::sink::Sink;
 use tokio_stream::StreamExt;
 use vqueue::{GenericQueueManager, QueueID};
-use vsmtp_config::DnsResolvers;
+use vsmtp_auth::dkim;
+use vsmtp_common::{
+    addr,
+    transfer::Status,
+    transport::{AbstractTransport, TransportStates, WrapperSerde},
+    TransactionType,
+};
+use vsmtp_config::{
+    field::{
+        DkimPreservationFallback, FieldDkim, FieldDkimSelector, FieldServerDkimPreservation,
+        FieldServerMissingHeaders, FieldServerNormalization, FieldServerSrs, FieldServerVirtual,
+        MissingHeadersPolicy, NoRecipientPolicy, NormalizationStage, SecretFile,
+    },
+    DnsResolvers,
+};
+use vsmtp_delivery::{Deliver, DeliveryState, Forward, Maildir};
 use vsmtp_rule_engine::{ExecutionStage, RuleEngine};
-use vsmtp_server::{scheduler, working::handle_one, ProcessMessage};
+use vsmtp_server::{
+    scheduler,
+    working::{handle_one, NO_RECIPIENT_QUARANTINE},
+    ProcessMessage,
+};
 
 #[test_log::test(tokio::test)]
 async fn cannot_deserialize() {
@@ -50,6 +70,7 @@ async fn cannot_deserialize() {
         queue_manager,
         ProcessMessage::new(uuid::Uuid::nil()),
         emitter,
+        std::sync::Arc::default(),
     )
     .await
     .unwrap_err();
@@ -98,6 +119,7 @@ async fn basic() {
         queue_manager.clone(),
         ProcessMessage::new(message_uuid),
         emitter.clone(),
+        std::sync::Arc::default(),
     )
     .await
     .unwrap();
@@ -115,6 +137,158 @@ async fn basic() {
         .unwrap();
 }
 
+/// Records the spans created with their parent, and the spans they follow from.
+#[derive(Clone, Default)]
+struct Spans {
+    created: std::sync::Arc<std::sync::Mutex<Vec<(&'static str, u64, Option<u64>)>>>,
+    follows_from: std::sync::Arc<std::sync::Mutex<Vec<(u64, u64)>>>,
+}
+
+impl Spans {
+    /// The id and the parent of the last span named `name`.
+    fn last(&self, name: &str) -> (u64, Option<u64>) {
+        self.created
+            .lock()
+            .unwrap()
+            .iter()
+            .rev()
+            .find(|(created, ..)| *created == name)
+            .map(|(_, id, parent)| (*id, *parent))
+            .unwrap()
+    }
+
+    fn follows(&self, span: u64, follows: u64) -> bool {
+        self.follows_from.lock().unwrap().contains(&(span, follows))
+    }
+}
+
+impl<S: tracing::Subscriber> tracing_subscriber::Layer<S> for Spans {
+    fn on_new_span(
+        &self,
+        attrs: &tracing::span::Attributes<'_>,
+        id: &tracing::Id,
+        ctx: tracing_subscriber::layer::Context<'_, S>,
+    ) {
+        let parent = if attrs.is_root() {
+            None
+        } else {
+            attrs
+                .parent()
+                .cloned()
+                .or_else(|| ctx.current_span().id().cloned())
+        };
+        self.created.lock().unwrap().push((
+            attrs.metadata().name(),
+            id.into_u64(),
+            parent.as_ref().map(tracing::Id::into_u64),
+        ));
+    }
+
+    fn on_follows_from(
+        &self,
+        span: &tracing::Id,
+        follows: &tracing::Id,
+        _: tracing_subscriber::layer::Context<'_, S>,
+    ) {
+        self.follows_from
+            .lock()
+            .unwrap()
+            .push((span.into_u64(), follows.into_u64()));
+    }
+}
+
+// the subscriber is set for the current thread only, so the processes must run on it.
+#[tokio::test(flavor = "current_thread")]
+async fn spans_of_the_message() {
+    let spans = Spans::default();
+    let _guard = tracing::subscriber::set_default(tracing_subscriber::layer::SubscriberExt::with(
+        tracing_subscriber::registry(),
+        spans.clone(),
+    ));
+
+    let config = std::sync::Arc::new(local_test());
+    let queue_manager =
+        <vqueue::temp::QueueManager as vqueue::GenericQueueManager>::init(config.clone(), vec![])
+            .unwrap();
+
+    let mut ctx = local_ctx();
+    let message_uuid = uuid::Uuid::new_v4();
+    ctx.mail_from.message_uuid = message_uuid;
+    queue_manager
+        .write_both(&QueueID::Working, &ctx, &local_msg())
+        .await
+        .unwrap();
+
+    let (emitter, _working, mut delivery) = scheduler::init(
+        config.server.queues.working.channel_size,
+        config.server.queues.delivery.channel_size,
+    );
+    let resolvers = std::sync::Arc::new(DnsResolvers::from_config(&config).unwrap());
+
+    // the session is closed before the message is processed.
+    let process_message =
+        tracing::info_span!("receipt").in_scope(|| ProcessMessage::new(message_uuid));
+    let (receipt, _) = spans.last("receipt");
+
+    handle_one(
+        std::sync::Arc::new(
+            RuleEngine::with_hierarchy(
+                |builder| {
+                    Ok(builder
+                        .add_root_filter_rules("#{}")?
+                        .add_domain_rules("testserver.com".parse().unwrap())
+                        .with_incoming("#{}")?
+                        .with_outgoing("#{}")?
+                        .with_internal("#{}")?
+                        .build()
+                        .build())
+                },
+                config.clone(),
+                resolvers.clone(),
+                queue_manager.clone(),
+            )
+            .unwrap(),
+        ),
+        queue_manager.clone(),
+        process_message,
+        emitter.clone(),
+        std::sync::Arc::default(),
+    )
+    .await
+    .unwrap();
+    let (working, message) = spans.last("working");
+    assert!(spans.follows(message.unwrap(), receipt));
+
+    let delivery_recv = delivery.as_stream();
+    tokio::pin!(delivery_recv);
+    vsmtp_server::delivery::deliver::handle_one(
+        config.clone(),
+        queue_manager.clone(),
+        delivery_recv.next().await.unwrap(),
+        std::sync::Arc::new(
+            RuleEngine::with_hierarchy(
+                |builder| {
+                    Ok(builder
+                        .add_root_filter_rules(&format!(
+                            "#{{ {}: [ rule \"\" || sys::deny() ] }}",
+                            ExecutionStage::Delivery
+                        ))?
+                        .build())
+                },
+                config.clone(),
+                resolvers,
+                queue_manager.clone(),
+            )
+            .unwrap(),
+        ),
+        std::sync::Arc::default(),
+    )
+    .await
+    .unwrap();
+    let (_, message) = spans.last("delivery");
+    assert!(spans.follows(message.unwrap(), working));
+}
+
 #[test_log::test(tokio::test)]
 async fn denied() {
     let config = std::sync::Arc::new(local_test());
@@ -157,6 +331,7 @@ async fn denied() {
         queue_manager.clone(),
         ProcessMessage::new(message_uuid),
         emitter,
+        std::sync::Arc::default(),
     )
     .await
     .unwrap();
@@ -221,6 +396,7 @@ async fn quarantine() {
         queue_manager.clone(),
         ProcessMessage::new(message_uuid),
         emitter,
+        std::sync::Arc::default(),
     )
     .await
     .unwrap();
@@ -244,3 +420,1231 @@ async fn quarantine() {
         .await
         .unwrap_err();
 }
+
+#[test_log::test(tokio::test)]
+async fn deferred() {
+    let config = std::sync::Arc::new(local_test());
+    let queue_manager =
+        <vqueue::temp::QueueManager as vqueue::GenericQueueManager>::init(config.clone(), vec![])
+            .unwrap();
+
+    let clock = crate::clock::TestClock::start();
+
+    let mut ctx = local_ctx();
+    let message_uuid = uuid::Uuid::new_v4();
+
+    ctx.mail_from.message_uuid = message_uuid;
+    queue_manager
+        .write_both(&QueueID::Working, &ctx, &local_msg())
+        .await
+        .unwrap();
+
+    let (emitter, _working, _delivery) = scheduler::init(
+        config.server.queues.working.channel_size,
+        config.server.queues.delivery.channel_size,
+    );
+    let resolvers = std::sync::Arc::new(DnsResolvers::from_config(&config).unwrap());
+
+    let rules = format!(
+        "#{{ {}: [ rule \"defer\" || state::defer(\"10m\") ] }}",
+        ExecutionStage::PostQ
+    );
+
+    handle_one(
+        std::sync::Arc::new(
+            RuleEngine::with_hierarchy(
+                move |builder| {
+                    Ok(builder
+                        .add_root_filter_rules(&rules)?
+                        .add_domain_rules("testserver.com".parse().unwrap())
+                        .with_incoming(&rules)?
+                        .with_outgoing(&rules)?
+                        .with_internal(&rules)?
+                        .build()
+                        .build())
+                },
+                config.clone(),
+                resolvers.clone(),
+                queue_manager.clone(),
+            )
+            .unwrap(),
+        ),
+        queue_manager.clone(),
+        ProcessMessage::new(message_uuid),
+        emitter,
+        std::sync::Arc::default(),
+    )
+    .await
+    .unwrap();
+
+    let ctx = queue_manager
+        .get_ctx(&QueueID::Deferred, &message_uuid)
+        .await
+        .unwrap();
+    assert_eq!(
+        ctx.finished.deferred_until,
+        Some(clock.now() + time::Duration::minutes(10))
+    );
+    assert_eq!(ctx.connect.skipped, None);
+
+    queue_manager
+        .get_ctx(&QueueID::Working, &message_uuid)
+        .await
+        .unwrap_err();
+    queue_manager
+        .get_ctx(&QueueID::Deliver, &message_uuid)
+        .await
+        .unwrap_err();
+}
+
+#[test_log::test(tokio::test)]
+async fn aliases() {
+    let directory = std::env::temp_dir().join(format!("vsmtp-aliases-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&directory).unwrap();
+    std::fs::write(
+        directory.join("aliases"),
+        "# aliases of the test\ninfo: john, jenny@other.com\nloop: loop2\nloop2: loop\n",
+    )
+    .unwrap();
+
+    let mut config = local_test();
+    config.server.aliases = Some(vsmtp_config::field::FieldServerAliases {
+        path: directory.join("aliases"),
+        max_depth: 10,
+        reload_period: std::time::Duration::from_secs(10),
+    });
+    let config = std::sync::Arc::new(config);
+    let queue_manager = <vqueue::temp::QueueManager as vqueue::GenericQueueManager>::init(
+        config.clone(),
+        vec![Maildir::get_symbol(), Deliver::get_symbol()],
+    )
+    .unwrap();
+
+    let mut ctx = local_ctx();
+    let message_uuid = uuid::Uuid::new_v4();
+    ctx.mail_from.message_uuid = message_uuid;
+    ctx.rcpt_to.forward_paths = vec![
+        addr!("info@testserver.com"),
+        addr!("loop@testserver.com"),
+        addr!("john@testserver.com"),
+    ];
+    ctx.rcpt_to.delivery.insert(
+        WrapperSerde::Ready(std::sync::Arc::new(Maildir::new(None))),
+        ctx.rcpt_to
+            .forward_paths
+            .iter()
+            .map(|rcpt| (rcpt.clone(), Status::default()))
+            .collect(),
+    );
+    queue_manager
+        .write_both(&QueueID::Working, &ctx, &local_msg())
+        .await
+        .unwrap();
+
+    let (emitter, _working, _delivery) = scheduler::init(
+        config.server.queues.working.channel_size,
+        config.server.queues.delivery.channel_size,
+    );
+    let resolvers = std::sync::Arc::new(DnsResolvers::from_config(&config).unwrap());
+
+    handle_one(
+        std::sync::Arc::new(
+            RuleEngine::with_hierarchy(
+                |builder| {
+                    Ok(builder
+                        .add_root_filter_rules("#{}")?
+                        .add_domain_rules("testserver.com".parse().unwrap())
+                        .with_incoming("#{}")?
+                        .with_outgoing("#{}")?
+                        .with_internal("#{}")?
+                        .build()
+                        .build())
+                },
+                config.clone(),
+                resolvers.clone(),
+                queue_manager.clone(),
+            )
+            .unwrap(),
+        ),
+        queue_manager.clone(),
+        ProcessMessage::new(message_uuid),
+        emitter,
+        std::sync::Arc::default(),
+    )
+    .await
+    .unwrap();
+
+    let ctx = queue_manager
+        .get_ctx(&QueueID::Deliver, &message_uuid)
+        .await
+        .unwrap();
+
+    assert_eq!(
+        ctx.rcpt_to.forward_paths,
+        [
+            addr!("john@testserver.com"),
+            addr!("jenny@other.com"),
+            addr!("loop@testserver.com"),
+        ]
+    );
+
+    let delivery = ctx
+        .rcpt_to
+        .delivery
+        .into_iter()
+        .map(|(transport, rcpt)| (serde_json::to_string(&transport).unwrap(), rcpt))
+        .collect::<Vec<_>>();
+    assert_eq!(delivery.len(), 2);
+
+    let (_, remote) = delivery
+        .iter()
+        .find(|(transport, _)| transport.contains("deliver"))
+        .unwrap();
+    assert_eq!(
+        remote.as_slice(),
+        [(addr!("jenny@other.com"), Status::default())]
+    );
+
+    let (_, local) = delivery
+        .iter()
+        .find(|(transport, _)| transport.contains("maildir"))
+        .unwrap();
+    assert_eq!(local.len(), 2);
+    assert!(local.contains(&(addr!("john@testserver.com"), Status::default())));
+    assert!(local
+        .iter()
+        .any(|(rcpt, status)| *rcpt == addr!("loop@testserver.com")
+            && matches!(status, Status::Failed { .. })));
+}
+
+#[test_log::test(tokio::test)]
+async fn aliases_srs() {
+    let directory = std::env::temp_dir().join(format!("vsmtp-srs-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&directory).unwrap();
+    std::fs::write(directory.join("aliases"), "info: john, jenny@other.com\n").unwrap();
+
+    let mut config = local_test();
+    config.server.aliases = Some(vsmtp_config::field::FieldServerAliases {
+        path: directory.join("aliases"),
+        max_depth: 10,
+        reload_period: std::time::Duration::from_secs(10),
+    });
+    config.server.srs = Some(FieldServerSrs {
+        domain: None,
+        secrets: vec!["secret".to_string()],
+        max_age: std::time::Duration::from_secs(21 * 24 * 60 * 60),
+    });
+    let config = std::sync::Arc::new(config);
+    let queue_manager = <vqueue::temp::QueueManager as vqueue::GenericQueueManager>::init(
+        config.clone(),
+        vec![Maildir::get_symbol(), Deliver::get_symbol()],
+    )
+    .unwrap();
+
+    let mut ctx = local_ctx();
+    let message_uuid = uuid::Uuid::new_v4();
+    ctx.mail_from.message_uuid = message_uuid;
+    ctx.mail_from.reverse_path = Some(addr!("someone@external.org"));
+    ctx.rcpt_to.forward_paths = vec![addr!("info@testserver.com")];
+    ctx.rcpt_to.transaction_type =
+        TransactionType::Incoming(Some("testserver.com".parse().unwrap()));
+    ctx.rcpt_to.delivery.insert(
+        WrapperSerde::Ready(std::sync::Arc::new(Maildir::new(None))),
+        vec![(addr!("info@testserver.com"), Status::default())],
+    );
+    queue_manager
+        .write_both(&QueueID::Working, &ctx, &local_msg())
+        .await
+        .unwrap();
+
+    let (emitter, _working, _delivery) = scheduler::init(
+        config.server.queues.working.channel_size,
+        config.server.queues.delivery.channel_size,
+    );
+    let resolvers = std::sync::Arc::new(DnsResolvers::from_config(&config).unwrap());
+    let rule_engine = std::sync::Arc::new(
+        RuleEngine::with_hierarchy(
+            |builder| {
+                Ok(builder
+                    .add_root_filter_rules("#{}")?
+                    .add_domain_rules("testserver.com".parse().unwrap())
+                    .with_incoming("#{}")?
+                    .with_outgoing("#{}")?
+                    .with_internal("#{}")?
+                    .build()
+                    .build())
+            },
+            config.clone(),
+            resolvers.clone(),
+            queue_manager.clone(),
+        )
+        .unwrap(),
+    );
+
+    handle_one(
+        rule_engine.clone(),
+        queue_manager.clone(),
+        ProcessMessage::new(message_uuid),
+        emitter,
+        std::sync::Arc::default(),
+    )
+    .await
+    .unwrap();
+
+    let (ctx, msg) = queue_manager
+        .get_both(&QueueID::Deliver, &message_uuid)
+        .await
+        .unwrap();
+
+    // only the target outside of the domain of the server is rewritten.
+    assert_eq!(ctx.rcpt_to.reverse_paths.len(), 1);
+    let rewritten = ctx.rcpt_to.reverse_paths[&addr!("jenny@other.com")].clone();
+    assert!(rewritten.local_part().starts_with("SRS0="));
+    assert_eq!(rewritten.domain(), config.server.name);
+    assert_eq!(
+        rule_engine.srs().unwrap().reverse(&rewritten).unwrap(),
+        Some(addr!("someone@external.org"))
+    );
+
+    // the remote target is forwarded with the sender rewritten.
+    let sink = Sink::start();
+
+    let forward = std::sync::Arc::new(Forward::new(sink.addr.to_string().parse().unwrap()));
+    let delivered = forward
+        .deliver(
+            &TransportStates::default().with(std::sync::Arc::new(DeliveryState::default())),
+            &ctx,
+            vec![(addr!("jenny@other.com"), Status::default())],
+            msg.inner().to_string().as_bytes(),
+        )
+        .await;
+    assert!(matches!(delivered.as_slice(), [(_, Status::Sent { .. })]));
+
+    let commands = &sink.wait_for_sessions(1)[0].commands;
+    let mail_from = format!("MAIL FROM:<{rewritten}>");
+    assert!(commands
+        .iter()
+        .any(|command| command.starts_with(&mail_from)));
+}
+
+fn missing_headers_config(internal: MissingHeadersPolicy) -> vsmtp_config::Config {
+    let mut config = local_test();
+    config.server.missing_headers = Some(FieldServerMissingHeaders {
+        submission: MissingHeadersPolicy::Add,
+        relay: MissingHeadersPolicy::Ignore,
+        internal,
+        fix_date: false,
+    });
+    config
+}
+
+#[test_log::test(tokio::test)]
+async fn missing_headers_added() {
+    let (private_key, _) = dkim::PrivateKey::generate(dkim::KeyAlgorithm::Ed25519).unwrap();
+    let public_key = private_key.public_key().unwrap();
+
+    let mut config = missing_headers_config(MissingHeadersPolicy::Add);
+    config.server.r#virtual.insert(
+        "testserver.com".parse().unwrap(),
+        FieldServerVirtual {
+            dkim: Some(FieldDkim {
+                private_key: vec![],
+                selectors: vec![FieldDkimSelector {
+                    selector: "s1".to_owned(),
+                    private_key: SecretFile {
+                        inner: std::sync::Arc::new(private_key),
+                        path: "s1.key".into(),
+                    },
+                    active: true,
+                }],
+            }),
+            ..Default::default()
+        },
+    );
+    let config = std::sync::Arc::new(config);
+    let queue_manager =
+        <vqueue::temp::QueueManager as vqueue::GenericQueueManager>::init(config.clone(), vec![])
+            .unwrap();
+
+    let mut ctx = local_ctx();
+    let message_uuid = uuid::Uuid::new_v4();
+    ctx.mail_from.message_uuid = message_uuid;
+    queue_manager
+        .write_both(&QueueID::Working, &ctx, &local_msg())
+        .await
+        .unwrap();
+
+    let (emitter, _working, _delivery) = scheduler::init(
+        config.server.queues.working.channel_size,
+        config.server.queues.delivery.channel_size,
+    );
+    let resolvers = std::sync::Arc::new(DnsResolvers::from_config(&config).unwrap());
+
+    // the headers must be visible to the rules, so that they can be signed.
+    let rules = format!(
+        r#"#{{ {}: [
+            rule "headers added" || if msg::has_header("Date") && msg::has_header("Message-ID") {{
+                state::next()
+            }} else {{
+                state::deny()
+            }},
+            action "sign" || dkim::sign(#{{
+                sdid: "testserver.com",
+                headers_field: ["From", "To", "Date", "Message-ID"],
+                canonicalization: "simple/relaxed",
+            }}),
+        ] }}"#,
+        ExecutionStage::PostQ
+    );
+
+    handle_one(
+        std::sync::Arc::new(
+            RuleEngine::with_hierarchy(
+                move |builder| {
+                    Ok(builder
+                        .add_root_filter_rules(&rules)?
+                        .add_domain_rules("testserver.com".parse().unwrap())
+                        .with_incoming(&rules)?
+                        .with_outgoing(&rules)?
+                        .with_internal(&rules)?
+                        .build()
+                        .build())
+                },
+                config.clone(),
+                resolvers.clone(),
+                queue_manager.clone(),
+            )
+            .unwrap(),
+        ),
+        queue_manager.clone(),
+        ProcessMessage::new(message_uuid),
+        emitter,
+        std::sync::Arc::default(),
+    )
+    .await
+    .unwrap();
+
+    let ctx = queue_manager
+        .get_ctx(&QueueID::Deliver, &message_uuid)
+        .await
+        .unwrap();
+    let message_id = format!("<{message_uuid}@testserver.com>");
+    assert_eq!(ctx.finished.added_message_id, Some(message_id.clone()));
+
+    let message = queue_manager.get_msg(&message_uuid).await.unwrap();
+    assert_eq!(message.get_header("Message-ID").unwrap().trim(), message_id);
+    assert!(message.get_header("Date").is_some());
+
+    let signature = message
+        .inner()
+        .headers()
+        .into_iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case("DKIM-Signature"))
+        .map(|(name, value)| {
+            <dkim::Signature as std::str::FromStr>::from_str(&format!("{name}:{value}")).unwrap()
+        })
+        .unwrap();
+    assert_eq!(signature.sdid, "testserver.com");
+    assert!(signature
+        .headers_field()
+        .iter()
+        .any(|name| name.eq_ignore_ascii_case("Message-ID")));
+    dkim::verify(&signature, message.inner(), &public_key).unwrap();
+}
+
+#[test_log::test(tokio::test(flavor = "multi_thread", worker_threads = 2))]
+async fn missing_headers_rejected() {
+    let mut config = missing_headers_config(MissingHeadersPolicy::Add);
+    config.server.missing_headers.as_mut().unwrap().relay = MissingHeadersPolicy::Reject;
+
+    let input = [
+        "HELO foo\r\n",
+        "MAIL FROM:<nobody@domain.tld>\r\n",
+        "RCPT TO:<hei@testserver.com>\r\n",
+        "DATA\r\n",
+        concat!(
+            "From: NoBody <nobody@domain.tld>\r\n",
+            "To: Hei <hei@testserver.com>\r\n",
+            "\r\n",
+            "Be happy!\r\n",
+            ".\r\n",
+        ),
+        "MAIL FROM:<nobody@domain.tld>\r\n",
+        "RCPT TO:<hei@testserver.com>\r\n",
+        "DATA\r\n",
+        concat!(
+            "From: NoBody <nobody@domain.tld>\r\n",
+            "To: Hei <hei@testserver.com>\r\n",
+            "Date: Tue, 30 Nov 2021 20:54:27 +0100\r\n",
+            "Message-ID: <happy@domain.tld>\r\n",
+            "\r\n",
+            "Be happy!\r\n",
+            ".\r\n",
+        ),
+        "QUIT\r\n",
+    ];
+    // refused at the end of `DATA`, the connection goes on.
+    let expected = [
+        "220 testserver.com Service ready\r\n",
+        "250 Ok\r\n",
+        "250 Ok\r\n",
+        "250 Ok\r\n",
+        "354 Start mail input; end with <CRLF>.<CRLF>\r\n",
+        "550 5.6.0 Message rejected: the Date and Message-ID headers are required\r\n",
+        "250 Ok\r\n",
+        "250 Ok\r\n",
+        "354 Start mail input; end with <CRLF>.<CRLF>\r\n",
+        "250 Ok\r\n",
+        "221 Service closing transmission channel\r\n",
+    ];
+
+    let queue_manager = crate::run_test! {
+        input = input,
+        expected = expected,
+        config = config,
+        hierarchy_builder = |builder| {
+            Ok(builder
+                .add_root_filter_rules("#{}")?
+                .add_domain_rules("testserver.com".parse().unwrap())
+                .with_incoming("#{}")?
+                .with_outgoing("#{}")?
+                .with_internal("#{}")?
+                .build()
+                .build())
+        },
+    };
+
+    // only the second message is queued.
+    let queued = queue_manager
+        .list(&QueueID::Working)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(Result::unwrap)
+        .collect::<Vec<_>>();
+    assert_eq!(queued.len(), 1);
+    let message = queue_manager
+        .get_msg(&queued[0].parse().unwrap())
+        .await
+        .unwrap();
+    assert_eq!(
+        message.get_header("Message-ID").unwrap().trim(),
+        "<happy@domain.tld>"
+    );
+}
+
+/// Run the working stage on [`local_msg`] signed by `example.com`, with the
+/// preservation of the signatures and `rules` at postq.
+///
+/// Returns the queue manager, the uuid of the message and the public key of the signer.
+async fn run_signed(
+    fallback: DkimPreservationFallback,
+    rules: &'static str,
+) -> (
+    std::sync::Arc<vqueue::temp::QueueManager>,
+    uuid::Uuid,
+    dkim::PublicKey,
+) {
+    let mut config = missing_headers_config(MissingHeadersPolicy::Add);
+    config.server.dkim_preservation = Some(FieldServerDkimPreservation { fallback });
+    let config = std::sync::Arc::new(config);
+    let queue_manager =
+        <vqueue::temp::QueueManager as vqueue::GenericQueueManager>::init(config.clone(), vec![])
+            .unwrap();
+
+    let (private_key, _) = dkim::PrivateKey::generate(dkim::KeyAlgorithm::Ed25519).unwrap();
+    let mut message = local_msg();
+    let signature = dkim::sign(
+        message.inner(),
+        &private_key,
+        "example.com".to_owned(),
+        "app".to_owned(),
+        "simple/relaxed".parse().unwrap(),
+        ["From", "To", "Subject", "Date", "Message-ID"]
+            .map(str::to_owned)
+            .to_vec(),
+    )
+    .unwrap();
+    message.prepend_header("DKIM-Signature", &signature.get_signature_value());
+
+    let mut ctx = local_ctx();
+    let message_uuid = uuid::Uuid::new_v4();
+    ctx.mail_from.message_uuid = message_uuid;
+    queue_manager
+        .write_both(&QueueID::Working, &ctx, &message)
+        .await
+        .unwrap();
+
+    let (emitter, _working, _delivery) = scheduler::init(
+        config.server.queues.working.channel_size,
+        config.server.queues.delivery.channel_size,
+    );
+    let resolvers = std::sync::Arc::new(DnsResolvers::from_config(&config).unwrap());
+
+    let rules = format!("#{{ {}: [ {rules} ] }}", ExecutionStage::PostQ);
+    handle_one(
+        std::sync::Arc::new(
+            RuleEngine::with_hierarchy(
+                move |builder| {
+                    Ok(builder
+                        .add_root_filter_rules(&rules)?
+                        .add_domain_rules("testserver.com".parse().unwrap())
+                        .with_incoming(&rules)?
+                        .with_outgoing(&rules)?
+                        .with_internal(&rules)?
+                        .build()
+                        .build())
+                },
+                config.clone(),
+                resolvers.clone(),
+                queue_manager.clone(),
+            )
+            .unwrap(),
+        ),
+        queue_manager.clone(),
+        ProcessMessage::new(message_uuid),
+        emitter,
+        std::sync::Arc::default(),
+    )
+    .await
+    .unwrap();
+
+    (
+        queue_manager,
+        message_uuid,
+        private_key.public_key().unwrap(),
+    )
+}
+
+/// Verify the signature of `example.com` on the message.
+fn verify_signed(message: &vsmtp_mail_parser::MessageBody, public_key: &dkim::PublicKey) {
+    let signature = message
+        .inner()
+        .headers()
+        .into_iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case("DKIM-Signature"))
+        .map(|(name, value)| {
+            <dkim::Signature as std::str::FromStr>::from_str(&format!("{name}:{value}")).unwrap()
+        })
+        .unwrap();
+    assert_eq!(signature.sdid, "example.com");
+    dkim::verify(&signature, message.inner(), public_key).unwrap();
+}
+
+#[test_log::test(tokio::test)]
+async fn dkim_preserved() {
+    let (queue_manager, message_uuid, public_key) = run_signed(
+        DkimPreservationFallback::Skip,
+        r#"action "detect" || {
+            let signatures = msg::dkim_signatures();
+            if signatures.len() != 1 || signatures[0].sdid != "example.com" {
+                throw "signature not detected";
+            }
+            msg::prepend_header("X-Scanned", "yes");
+        }"#,
+    )
+    .await;
+
+    let ctx = queue_manager
+        .get_ctx(&QueueID::Deliver, &message_uuid)
+        .await
+        .unwrap();
+    // Message-ID and Date are covered, they are not added.
+    assert_eq!(ctx.finished.added_message_id, None);
+
+    let message = queue_manager.get_msg(&message_uuid).await.unwrap();
+    assert!(message.get_header("Message-ID").is_none());
+    assert!(message.get_header("Date").is_none());
+    assert!(message.get_header("X-Scanned").is_some());
+    verify_signed(&message, &public_key);
+}
+
+#[test_log::test(tokio::test)]
+async fn dkim_preserved_skip() {
+    let (queue_manager, message_uuid, public_key) = run_signed(
+        DkimPreservationFallback::Skip,
+        r#"action "tag subject" || msg::set_header("Subject", "[EXTERNAL] Happy new year")"#,
+    )
+    .await;
+
+    queue_manager
+        .get_ctx(&QueueID::Deliver, &message_uuid)
+        .await
+        .unwrap();
+
+    let message = queue_manager.get_msg(&message_uuid).await.unwrap();
+    assert_eq!(
+        message.get_header("Subject").unwrap().trim(),
+        "Happy new year"
+    );
+    verify_signed(&message, &public_key);
+}
+
+#[test_log::test(tokio::test)]
+async fn dkim_preserved_reject() {
+    let (queue_manager, message_uuid, _) = run_signed(
+        DkimPreservationFallback::Reject,
+        r#"action "tag subject" || msg::set_header("Subject", "[EXTERNAL] Happy new year")"#,
+    )
+    .await;
+
+    queue_manager
+        .get_ctx(&QueueID::Deliver, &message_uuid)
+        .await
+        .unwrap_err();
+    queue_manager
+        .get_ctx(&QueueID::Dead, &message_uuid)
+        .await
+        .unwrap();
+}
+
+/// Process a message signed by `example.com` with the `simple` canonicalization, with
+/// bare `\n` in its body and spaces ending its `Subject`.
+async fn run_normalized(
+    normalization: Option<FieldServerNormalization>,
+) -> (
+    vsmtp_mail_parser::MessageBody,
+    vsmtp_mail_parser::MessageBody,
+    dkim::PublicKey,
+) {
+    let mut config = local_test();
+    config.server.normalization = normalization;
+    let config = std::sync::Arc::new(config);
+    let queue_manager =
+        <vqueue::temp::QueueManager as vqueue::GenericQueueManager>::init(config.clone(), vec![])
+            .unwrap();
+
+    let (private_key, _) = dkim::PrivateKey::generate(dkim::KeyAlgorithm::Ed25519).unwrap();
+    let mut message = vsmtp_mail_parser::MessageBody::new(
+        [
+            "From: NoBody <nobody@domain.tld>\r\n",
+            "To: Hei <hei@domain.tld>\r\n",
+            "Subject: Happy new year  \r\n",
+        ]
+        .into_iter()
+        .map(str::to_string)
+        .collect(),
+        "Be happy!\nAnd see you soon.\r\n".to_string(),
+    );
+    let signature = dkim::sign(
+        message.inner(),
+        &private_key,
+        "example.com".to_owned(),
+        "app".to_owned(),
+        "simple/simple".parse().unwrap(),
+        ["From", "To", "Subject"].map(str::to_owned).to_vec(),
+    )
+    .unwrap();
+    message.prepend_header("DKIM-Signature", &signature.get_signature_value());
+
+    let mut ctx = local_ctx();
+    let message_uuid = uuid::Uuid::new_v4();
+    ctx.mail_from.message_uuid = message_uuid;
+    queue_manager
+        .write_both(&QueueID::Working, &ctx, &message)
+        .await
+        .unwrap();
+
+    let (emitter, _working, _delivery) = scheduler::init(
+        config.server.queues.working.channel_size,
+        config.server.queues.delivery.channel_size,
+    );
+    let resolvers = std::sync::Arc::new(DnsResolvers::from_config(&config).unwrap());
+
+    handle_one(
+        std::sync::Arc::new(
+            RuleEngine::with_hierarchy(
+                |builder| {
+                    Ok(builder
+                        .add_root_filter_rules("#{}")?
+                        .add_domain_rules("testserver.com".parse().unwrap())
+                        .with_incoming("#{}")?
+                        .with_outgoing("#{}")?
+                        .with_internal("#{}")?
+                        .build()
+                        .build())
+                },
+                config.clone(),
+                resolvers,
+                queue_manager.clone(),
+            )
+            .unwrap(),
+        ),
+        queue_manager.clone(),
+        ProcessMessage::new(message_uuid),
+        emitter,
+        std::sync::Arc::default(),
+    )
+    .await
+    .unwrap();
+
+    let stored = queue_manager.get_msg(&message_uuid).await.unwrap();
+    (message, stored, private_key.public_key().unwrap())
+}
+
+#[test_log::test(tokio::test)]
+async fn normalization_disabled() {
+    let (message, stored, public_key) = run_normalized(None).await;
+
+    assert_eq!(stored.inner().to_string(), message.inner().to_string());
+    verify_signed(&stored, &public_key);
+}
+
+#[test_log::test(tokio::test)]
+async fn normalization_before_rules() {
+    // the message is normalized on reception, it is left untouched by the working stage.
+    let (message, stored, public_key) = run_normalized(Some(FieldServerNormalization {
+        stage: NormalizationStage::BeforeRules,
+        trailing_whitespace: true,
+    }))
+    .await;
+
+    assert_eq!(stored.inner().to_string(), message.inner().to_string());
+    verify_signed(&stored, &public_key);
+}
+
+#[test_log::test(tokio::test)]
+async fn normalization_after_rules() {
+    let (_, stored, public_key) = run_normalized(Some(FieldServerNormalization {
+        stage: NormalizationStage::AfterRules,
+        trailing_whitespace: true,
+    }))
+    .await;
+
+    assert_eq!(
+        stored.get_header("Subject").as_deref(),
+        Some("Happy new year")
+    );
+    assert_eq!(
+        stored.inner().body().as_deref(),
+        Some("Be happy!\r\nAnd see you soon.\r\n")
+    );
+    // the signature with the `simple` canonicalization no longer matches the message.
+    let signature = stored
+        .inner()
+        .headers()
+        .into_iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case("DKIM-Signature"))
+        .map(|(name, value)| {
+            <dkim::Signature as std::str::FromStr>::from_str(&format!("{name}:{value}")).unwrap()
+        })
+        .unwrap();
+    dkim::verify(&signature, stored.inner(), &public_key).unwrap_err();
+}
+
+/// Sign the message as sent by the client once normalized, with the `simple` canonicalization,
+/// and verify it with the `preq` rules: it passes only if it is normalized before the rules.
+async fn run_normalized_on_reception(stage: NormalizationStage) -> Vec<String> {
+    let (private_key, _) = dkim::PrivateKey::generate(dkim::KeyAlgorithm::Ed25519).unwrap();
+    let mut message = vsmtp_mail_parser::MessageBody::new(
+        [
+            "From: NoBody <nobody@domain.tld>\r\n",
+            "To: Hei <hei@domain.tld>\r\n",
+            "Subject: Happy new year\r\n",
+        ]
+        .into_iter()
+        .map(str::to_string)
+        .collect(),
+        "Be happy!\r\n".to_string(),
+    );
+    let signature = dkim::sign(
+        message.inner(),
+        &private_key,
+        "testserver.com".to_owned(),
+        "s1".to_owned(),
+        "simple/simple".parse().unwrap(),
+        ["From", "To", "Subject"].map(str::to_owned).to_vec(),
+    )
+    .unwrap();
+    message.prepend_header("DKIM-Signature", &signature.get_signature_value());
+    // spaces added to the `Subject` on the way.
+    let sent = message.inner().to_string().replace(
+        "Subject: Happy new year\r\n",
+        "Subject: Happy new year  \r\n",
+    );
+
+    let mut config = local_test();
+    config.server.normalization = Some(FieldServerNormalization {
+        stage,
+        trailing_whitespace: true,
+    });
+    config.server.r#virtual.insert(
+        "testserver.com".parse().unwrap(),
+        FieldServerVirtual {
+            dkim: Some(FieldDkim {
+                private_key: vec![],
+                selectors: vec![FieldDkimSelector {
+                    selector: "s1".to_owned(),
+                    private_key: SecretFile {
+                        inner: std::sync::Arc::new(private_key),
+                        path: "s1.key".into(),
+                    },
+                    active: true,
+                }],
+            }),
+            ..Default::default()
+        },
+    );
+
+    let input = [
+        "HELO foo\r\n".to_owned(),
+        "MAIL FROM:<nobody@domain.tld>\r\n".to_owned(),
+        "RCPT TO:<hei@testserver.com>\r\n".to_owned(),
+        "DATA\r\n".to_owned(),
+        format!("{sent}.\r\n"),
+        "QUIT\r\n".to_owned(),
+    ];
+    let mut expected = vec![
+        "220 testserver.com Service ready\r\n",
+        "250 Ok\r\n",
+        "250 Ok\r\n",
+        "250 Ok\r\n",
+        "354 Start mail input; end with <CRLF>.<CRLF>\r\n",
+    ];
+    if stage == NormalizationStage::BeforeRules {
+        expected.extend(["250 Ok\r\n", "221 Service closing transmission channel\r\n"]);
+    } else {
+        // the message is refused, the connection goes on.
+        expected.extend([
+            "554 permanent problems with the remote server\r\n",
+            "221 Service closing transmission channel\r\n",
+        ]);
+    }
+
+    let queue_manager = crate::run_test! {
+        input = input,
+        expected = expected,
+        config = config,
+        hierarchy_builder = |builder| {
+            Ok(builder
+                .add_root_filter_rules("#{}")?
+                .add_domain_rules("testserver.com".parse().unwrap())
+                .with_incoming(r#"#{
+                    preq: [
+                        rule "dkim" || if dkim::verify().status == "pass" { state::next() } else { state::deny() },
+                    ],
+                }"#)?
+                .with_outgoing("#{}")?
+                .with_internal("#{}")?
+                .build()
+                .build())
+        },
+    };
+
+    queue_manager
+        .list(&QueueID::Working)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(Result::unwrap)
+        .collect()
+}
+
+#[test_log::test(tokio::test(flavor = "multi_thread", worker_threads = 2))]
+async fn normalization_before_rules_on_reception() {
+    // the rules verify the signature of the normalized message.
+    assert_eq!(
+        run_normalized_on_reception(NormalizationStage::BeforeRules)
+            .await
+            .len(),
+        1
+    );
+}
+
+#[test_log::test(tokio::test(flavor = "multi_thread", worker_threads = 2))]
+async fn normalization_after_rules_on_reception() {
+    // the rules verify the signature of the message as received.
+    assert!(run_normalized_on_reception(NormalizationStage::AfterRules)
+        .await
+        .is_empty());
+}
+
+/// Process a message forwarded to `127.0.0.1:port` with the direct delivery enabled.
+async fn direct_delivery(port: u16) -> (std::sync::Arc<vqueue::temp::QueueManager>, uuid::Uuid) {
+    let mut config = local_test();
+    config.server.queues.working.direct_delivery = true;
+    let config = std::sync::Arc::new(config);
+    let queue_manager = <vqueue::temp::QueueManager as vqueue::GenericQueueManager>::init(
+        config.clone(),
+        vec![Forward::get_symbol()],
+    )
+    .unwrap();
+
+    let mut ctx = local_ctx();
+    let message_uuid = uuid::Uuid::new_v4();
+    ctx.mail_from.message_uuid = message_uuid;
+    ctx.rcpt_to.delivery.insert(
+        WrapperSerde::Ready(std::sync::Arc::new(Forward::new(
+            format!("127.0.0.1:{port}").parse().unwrap(),
+        ))),
+        vec![(addr!("recipient@testserver.com"), Status::default())],
+    );
+    queue_manager
+        .write_both(&QueueID::Working, &ctx, &local_msg())
+        .await
+        .unwrap();
+
+    let (emitter, _working, mut delivery) = scheduler::init(
+        config.server.queues.working.channel_size,
+        config.server.queues.delivery.channel_size,
+    );
+    let resolvers = std::sync::Arc::new(DnsResolvers::from_config(&config).unwrap());
+
+    handle_one(
+        std::sync::Arc::new(
+            RuleEngine::with_hierarchy(
+                |builder| {
+                    Ok(builder
+                        .add_root_filter_rules("#{}")?
+                        .add_domain_rules("testserver.com".parse().unwrap())
+                        .with_incoming("#{}")?
+                        .with_outgoing("#{}")?
+                        .with_internal("#{}")?
+                        .build()
+                        .build())
+                },
+                config.clone(),
+                resolvers,
+                queue_manager.clone(),
+            )
+            .unwrap(),
+        ),
+        queue_manager.clone(),
+        ProcessMessage::new(message_uuid),
+        emitter,
+        std::sync::Arc::default(),
+    )
+    .await
+    .unwrap();
+
+    // the message is not handed over to the delivery process.
+    let delivery_recv = delivery.as_stream();
+    tokio::pin!(delivery_recv);
+    assert!(delivery_recv.next().await.is_none());
+
+    for queue in [QueueID::Working, QueueID::Deliver] {
+        queue_manager
+            .get_ctx(&queue, &message_uuid)
+            .await
+            .unwrap_err();
+    }
+
+    (queue_manager, message_uuid)
+}
+
+#[test_log::test(tokio::test)]
+async fn direct_delivery_sent() {
+    let sink = Sink::start();
+
+    let (queue_manager, message_uuid) = direct_delivery(sink.port()).await;
+
+    queue_manager.get_msg(&message_uuid).await.unwrap_err();
+}
+
+#[test_log::test(tokio::test)]
+async fn direct_delivery_deferred() {
+    // nobody is listening on this port
+    let port = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+
+    let (queue_manager, message_uuid) = direct_delivery(port).await;
+
+    let (ctx, _) = queue_manager
+        .get_both(&QueueID::Deferred, &message_uuid)
+        .await
+        .unwrap();
+    assert!(ctx
+        .rcpt_to
+        .delivery
+        .values()
+        .flatten()
+        .all(|(_, status)| matches!(status, Status::HeldBack { .. })));
+}
+
+// the clock is mocked for the current thread only, so the processes must run on it.
+#[tokio::test(flavor = "current_thread")]
+async fn delivery_timings() {
+    let clock = crate::clock::TestClock::start();
+    let sink = Sink::start();
+
+    let config = std::sync::Arc::new(local_test());
+    let queue_manager = <vqueue::temp::QueueManager as vqueue::GenericQueueManager>::init(
+        config.clone(),
+        vec![Forward::get_symbol()],
+    )
+    .unwrap();
+    let resolvers = std::sync::Arc::new(DnsResolvers::from_config(&config).unwrap());
+    let rule_engine = std::sync::Arc::new(
+        RuleEngine::with_hierarchy(
+            |builder| {
+                Ok(builder
+                    .add_root_filter_rules("#{}")?
+                    .add_domain_rules("testserver.com".parse().unwrap())
+                    .with_incoming("#{}")?
+                    .with_outgoing("#{}")?
+                    .with_internal("#{}")?
+                    .build()
+                    .build())
+            },
+            config.clone(),
+            resolvers,
+            queue_manager.clone(),
+        )
+        .unwrap(),
+    );
+    let delivery_timings = std::sync::Arc::new(vsmtp_server::DeliveryTimings::default());
+
+    let mut ctx = local_ctx();
+    let message_uuid = uuid::Uuid::new_v4();
+    ctx.mail_from.message_uuid = message_uuid;
+    ctx.finished.timings = vsmtp_common::Timings::received();
+    ctx.rcpt_to.delivery.insert(
+        WrapperSerde::Ready(std::sync::Arc::new(Forward::new(
+            sink.addr.to_string().parse().unwrap(),
+        ))),
+        vec![(addr!("recipient@testserver.com"), Status::default())],
+    );
+    queue_manager
+        .write_both(&QueueID::Working, &ctx, &local_msg())
+        .await
+        .unwrap();
+
+    let (emitter, _working, mut delivery) = scheduler::init(
+        config.server.queues.working.channel_size,
+        config.server.queues.delivery.channel_size,
+    );
+
+    clock.advance(time::Duration::seconds(2));
+    handle_one(
+        rule_engine.clone(),
+        queue_manager.clone(),
+        ProcessMessage::new(message_uuid),
+        emitter,
+        delivery_timings.clone(),
+    )
+    .await
+    .unwrap();
+
+    clock.advance(time::Duration::minutes(10));
+    let delivery_recv = delivery.as_stream();
+    tokio::pin!(delivery_recv);
+    vsmtp_server::delivery::deliver::handle_one(
+        config.clone(),
+        queue_manager.clone(),
+        delivery_recv.next().await.unwrap(),
+        rule_engine,
+        delivery_timings.clone(),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(sink.wait_for(1).len(), 1);
+
+    let metrics = delivery_timings.metrics();
+    for line in [
+        "vsmtp_delivery_duration_seconds_sum{stage=\"working\"} 2.000\n",
+        "vsmtp_delivery_duration_seconds_sum{stage=\"queued\"} 600.000\n",
+        "vsmtp_delivery_duration_seconds_sum{stage=\"in_flight\"} 0.000\n",
+        "vsmtp_delivery_duration_seconds_sum{stage=\"total\"} 602.000\n",
+        "vsmtp_delivery_duration_seconds_count{stage=\"total\"} 1\n",
+        "vsmtp_delivery_final_total{disposition=\"delivered\"} 1\n",
+        "vsmtp_delivery_final_total{disposition=\"bounced\"} 0\n",
+    ] {
+        assert!(metrics.contains(line), "{line} not in {metrics}");
+    }
+}
+
+#[test_log::test(tokio::test)]
+async fn no_recipient() {
+    for (policy, queue) in [
+        (NoRecipientPolicy::Drop, None),
+        (
+            NoRecipientPolicy::Quarantine,
+            Some(QueueID::Quarantine {
+                name: NO_RECIPIENT_QUARANTINE.to_owned(),
+            }),
+        ),
+        (NoRecipientPolicy::Dead, Some(QueueID::Dead)),
+    ] {
+        let mut config = local_test();
+        config.server.queues.working.no_recipient = policy;
+        let config = std::sync::Arc::new(config);
+        let queue_manager = <vqueue::temp::QueueManager as vqueue::GenericQueueManager>::init(
+            config.clone(),
+            vec![],
+        )
+        .unwrap();
+
+        let mut ctx = local_ctx();
+        let message_uuid = uuid::Uuid::new_v4();
+        ctx.mail_from.message_uuid = message_uuid;
+        queue_manager
+            .write_both(&QueueID::Working, &ctx, &local_msg())
+            .await
+            .unwrap();
+
+        let (emitter, _working, mut delivery) = scheduler::init(
+            config.server.queues.working.channel_size,
+            config.server.queues.delivery.channel_size,
+        );
+        let resolvers = std::sync::Arc::new(DnsResolvers::from_config(&config).unwrap());
+
+        let rules = format!(
+            "#{{ {}: [ action \"remove all\" || envelop::rm_rcpt(\"recipient@testserver.com\") ] }}",
+            ExecutionStage::PostQ
+        );
+
+        handle_one(
+            std::sync::Arc::new(
+                RuleEngine::with_hierarchy(
+                    move |builder| {
+                        Ok(builder
+                            .add_root_filter_rules(&rules)?
+                            .add_domain_rules("testserver.com".parse().unwrap())
+                            .with_incoming(&rules)?
+                            .with_outgoing(&rules)?
+                            .with_internal(&rules)?
+                            .build()
+                            .build())
+                    },
+                    config.clone(),
+                    resolvers,
+                    queue_manager.clone(),
+                )
+                .unwrap(),
+            ),
+            queue_manager.clone(),
+            ProcessMessage::new(message_uuid),
+            emitter,
+            std::sync::Arc::default(),
+        )
+        .await
+        .unwrap();
+
+        // the message is never handed over to the delivery process.
+        let delivery_recv = delivery.as_stream();
+        tokio::pin!(delivery_recv);
+        assert!(delivery_recv.next().await.is_none());
+
+        for other in [QueueID::Working, QueueID::Deliver, QueueID::Deferred] {
+            queue_manager
+                .get_ctx(&other, &message_uuid)
+                .await
+                .unwrap_err();
+        }
+
+        match queue {
+            Some(queue) => {
+                let ctx = queue_manager.get_ctx(&queue, &message_uuid).await.unwrap();
+                assert!(ctx.rcpt_to.forward_paths.is_empty());
+                queue_manager.get_msg(&message_uuid).await.unwrap();
+            }
+            None => {
+                queue_manager.get_msg(&message_uuid).await.unwrap_err();
+            }
+        }
+    }
+}
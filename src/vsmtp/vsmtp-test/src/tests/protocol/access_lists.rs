@@ -0,0 +1,129 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2022 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use crate::config;
+use crate::run_test;
+use vsmtp_config::field::{AccessListAction, FieldAccessList, FieldServerAccessLists};
+
+fn write_list(content: &str) -> std::path::PathBuf {
+    let path = std::env::temp_dir().join(format!("vsmtp-access-list-{}", uuid::Uuid::new_v4()));
+    std::fs::write(&path, content).unwrap();
+    path
+}
+
+fn blocklist(content: &str, action: AccessListAction) -> Option<FieldAccessList> {
+    Some(FieldAccessList {
+        path: write_list(content),
+        action,
+        reply: None,
+    })
+}
+
+fn with_access_lists(access_lists: FieldServerAccessLists) -> vsmtp_config::Config {
+    let mut config = config::local_test();
+    config.server.access_lists = Some(access_lists);
+    config
+}
+
+run_test! {
+    fn blocked_client,
+    input = ["QUIT\r\n"],
+    expected = ["554 5.7.1 Client host rejected: access denied\r\n"],
+    config = with_access_lists(FieldServerAccessLists {
+        blocked_ips: blocklist("# on-call\n127.0.0.0/8\n", AccessListAction::Deny),
+        blocked_senders: None,
+        allowed_ips: None,
+        reload_period: std::time::Duration::from_secs(10),
+    }),
+}
+
+run_test! {
+    fn blocked_sender,
+    input = [
+        "HELO foo\r\n",
+        "MAIL FROM:<john@mx.spam.example>\r\n",
+        "MAIL FROM:<john@doe>\r\n",
+        "QUIT\r\n",
+    ],
+    expected = [
+        "220 testserver.com Service ready\r\n",
+        "250 Ok\r\n",
+        "550 5.7.1 Sender address rejected: domain blocked\r\n",
+        "250 Ok\r\n",
+        "221 Service closing transmission channel\r\n",
+    ],
+    config = with_access_lists(FieldServerAccessLists {
+        blocked_ips: None,
+        blocked_senders: blocklist("spam.example\nnot a domain\n", AccessListAction::Deny),
+        allowed_ips: None,
+        reload_period: std::time::Duration::from_secs(10),
+    }),
+}
+
+run_test! {
+    fn allowed_client,
+    input = [
+        "HELO foo\r\n",
+        "MAIL FROM:<john@spam.example>\r\n",
+        "QUIT\r\n",
+    ],
+    expected = [
+        "220 testserver.com Service ready\r\n",
+        "250 Ok\r\n",
+        "250 Ok\r\n",
+        "221 Service closing transmission channel\r\n",
+    ],
+    config = with_access_lists(FieldServerAccessLists {
+        blocked_ips: blocklist("127.0.0.1\n", AccessListAction::Deny),
+        blocked_senders: blocklist("spam.example\n", AccessListAction::Tempfail),
+        allowed_ips: Some(write_list("127.0.0.1\n")),
+        reload_period: std::time::Duration::from_secs(10),
+    }),
+}
+
+run_test! {
+    fn tagged_client_in_rules,
+    input = [
+        "HELO foo\r\n",
+        "MAIL FROM:<john@doe>\r\n",
+        "QUIT\r\n",
+    ],
+    expected = [
+        "220 testserver.com Service ready\r\n",
+        "250 Ok\r\n",
+        "554 permanent problems with the remote server\r\n",
+    ],
+    config = with_access_lists(FieldServerAccessLists {
+        blocked_ips: blocklist("127.0.0.1\n", AccessListAction::Tag),
+        blocked_senders: None,
+        allowed_ips: None,
+        reload_period: std::time::Duration::from_secs(10),
+    }),
+    hierarchy_builder = |builder| {
+        Ok(builder.add_root_filter_rules(r#"#{
+            mail: [
+              rule "blocklist" || {
+                if lists::is_blocked_ip(ctx::client_ip()) && !lists::is_allowed_ip(ctx::client_ip()) {
+                    state::deny()
+                } else {
+                    state::next()
+                }
+              }
+            ],
+          }
+          "#)?.build())
+    },
+}
@@ -274,7 +274,45 @@ run_test! {
         "501 Authentication canceled by client\r\n",
         "334 \r\n",
         "501 Authentication canceled by client\r\n",
-        "334 \r\n",
+    ],
+    config = {
+        let mut config = unsafe_auth_config();
+        config.server.esmtp.auth.as_mut().unwrap().attempt_count_max = 3;
+        config
+    }
+}
+
+run_test! {
+    fn login_in_clair_unsecured_cancel,
+    input = [
+        "EHLO client.com\r\n",
+        "AUTH LOGIN\r\n",
+        &format!("{}\r\n", STANDARD.encode("hello")),
+        "*\r\n",
+        "AUTH LOGIN\r\n",
+        "*\r\n",
+        "AUTH LOGIN\r\n",
+        &format!("{}\r\n", STANDARD.encode("hello")),
+        "*\r\n",
+        "MAIL FROM:<foo@bar>\r\n",
+    ],
+    expected = [
+        "220 testserver.com Service ready\r\n",
+        "250-testserver.com\r\n",
+        "250-AUTH PLAIN LOGIN CRAM-MD5 ANONYMOUS\r\n",
+        "250-8BITMIME\r\n",
+        "250-SMTPUTF8\r\n",
+        "250-STARTTLS\r\n",
+        "250-PIPELINING\r\n",
+        "250-DSN\r\n",
+        "250 SIZE 20000000\r\n",
+        &format!("334 {}\r\n", STANDARD.encode("User Name\0")),
+        &format!("334 {}\r\n", STANDARD.encode("Password\0")),
+        "501 Authentication canceled by client\r\n",
+        &format!("334 {}\r\n", STANDARD.encode("User Name\0")),
+        "501 Authentication canceled by client\r\n",
+        &format!("334 {}\r\n", STANDARD.encode("User Name\0")),
+        &format!("334 {}\r\n", STANDARD.encode("Password\0")),
         "501 Authentication canceled by client\r\n",
     ],
     config = {
@@ -284,6 +322,54 @@ run_test! {
     }
 }
 
+run_test! {
+    fn login_in_clair_unsecured_cancel_then_success,
+    input = [
+        "EHLO client.com\r\n",
+        "AUTH LOGIN\r\n",
+        "*\r\n",
+        "AUTH LOGIN\r\n",
+        &format!("{}\r\n", STANDARD.encode("hello")),
+        &format!("{}\r\n", STANDARD.encode("world")),
+        "MAIL FROM:<foo@bar>\r\n",
+        "RCPT TO:<joe@doe>\r\n",
+        "DATA\r\n",
+        ".\r\n",
+        "QUIT\r\n"
+    ],
+    expected = [
+        "220 testserver.com Service ready\r\n",
+        "250-testserver.com\r\n",
+        "250-AUTH PLAIN LOGIN CRAM-MD5 ANONYMOUS\r\n",
+        "250-8BITMIME\r\n",
+        "250-SMTPUTF8\r\n",
+        "250-STARTTLS\r\n",
+        "250-PIPELINING\r\n",
+        "250-DSN\r\n",
+        "250 SIZE 20000000\r\n",
+        &format!("334 {}\r\n", STANDARD.encode("User Name\0")),
+        "501 Authentication canceled by client\r\n",
+        &format!("334 {}\r\n", STANDARD.encode("User Name\0")),
+        &format!("334 {}\r\n", STANDARD.encode("Password\0")),
+        "235 2.7.0 Authentication succeeded\r\n",
+        "250 Ok\r\n",
+        "250 Ok\r\n",
+        "354 Start mail input; end with <CRLF>.<CRLF>\r\n",
+        "250 Ok\r\n",
+        "221 Service closing transmission channel\r\n"
+    ],
+    config = {
+        let mut config = unsafe_auth_config();
+        config.server.esmtp.auth.as_mut().unwrap().attempt_count_max = 3;
+        config
+    },
+    mail_handler = |ctx: ContextFinished, _: MessageBody| {
+        let auth = ctx.connect.auth.unwrap();
+        assert!(auth.authenticated);
+        assert_eq!(auth.cancel_count, 1);
+    }
+}
+
 run_test! {
     fn plain_in_clair_unsecured_bad_base64,
     input = [
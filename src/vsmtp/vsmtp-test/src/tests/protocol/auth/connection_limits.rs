@@ -0,0 +1,139 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2023 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use super::unsafe_auth_config;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
+use vsmtp_config::{field::FieldServerConnectionLimits, DnsResolvers};
+use vsmtp_protocol::{AcceptArgs, ConnectionKind};
+use vsmtp_rule_engine::RuleEngine;
+use vsmtp_server::{ConnectionLimits, ConnectionSlot, Server};
+
+const LOCALHOST: std::net::IpAddr = std::net::IpAddr::V4(std::net::Ipv4Addr::LOCALHOST);
+
+/// Serve one session counted in `slot`, authenticating with `authid`.
+///
+/// Returns the replies received.
+async fn run(slot: ConnectionSlot, authid: &str) -> Vec<String> {
+    let config = std::sync::Arc::new(unsafe_auth_config());
+    let queue_manager =
+        <vqueue::temp::QueueManager as vqueue::GenericQueueManager>::init(config.clone(), vec![])
+            .unwrap();
+    let resolvers = std::sync::Arc::new(DnsResolvers::from_config(&config).unwrap());
+    let rule_engine = std::sync::Arc::new(
+        RuleEngine::new(config.clone(), resolvers, queue_manager.clone()).unwrap(),
+    );
+    let (emitter, _working, _delivery) = vsmtp_server::scheduler::init(1, 1);
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let server_addr = listener.local_addr().unwrap();
+    let server = tokio::spawn(async move {
+        let (stream, client_addr) = listener.accept().await.unwrap();
+        Server::serve(
+            AcceptArgs::new(
+                client_addr,
+                server_addr,
+                vsmtp_common::clock::now(),
+                uuid::Uuid::new_v4(),
+                ConnectionKind::Relay,
+            ),
+            stream,
+            None,
+            config,
+            rule_engine,
+            queue_manager,
+            emitter,
+            None,
+            std::sync::Arc::default(),
+            None,
+            Some(slot),
+        )
+        .await
+    });
+
+    let input = [
+        "EHLO client.com\r\n".to_owned(),
+        format!(
+            "AUTH PLAIN {}\r\n",
+            STANDARD.encode(format!("\0{authid}\0world"))
+        ),
+        "QUIT\r\n".to_owned(),
+    ];
+    let stream = tokio::net::TcpStream::connect(server_addr).await.unwrap();
+    let mut stream = tokio::io::BufReader::new(stream);
+    let mut replies = vec![];
+    let mut input = input.iter();
+    loop {
+        let mut line = String::new();
+        if stream.read_line(&mut line).await.unwrap() == 0 {
+            break;
+        }
+        let continued = line.chars().nth(3) == Some('-');
+        replies.push(line);
+        if continued {
+            continue;
+        }
+        match input.next() {
+            Some(command) => stream
+                .get_mut()
+                .write_all(command.as_bytes())
+                .await
+                .unwrap(),
+            None => break,
+        }
+    }
+    server.await.unwrap().unwrap();
+
+    replies
+}
+
+fn limits(authenticated_per_identity: usize) -> std::sync::Arc<ConnectionLimits> {
+    std::sync::Arc::new(ConnectionLimits::new(&FieldServerConnectionLimits {
+        anonymous_per_ip: Some(1),
+        authenticated_per_identity: Some(authenticated_per_identity),
+    }))
+}
+
+#[tokio::test]
+async fn identity_over_the_limit() {
+    let limits = limits(1);
+    let mut other = limits.acquire(LOCALHOST).unwrap();
+    assert!(other.authenticate("hello"));
+
+    let replies = run(limits.acquire(LOCALHOST).unwrap(), "hello").await;
+
+    assert_eq!(
+        replies.last().unwrap(),
+        "421 4.7.0 Too many connections for this user, closing\r\n"
+    );
+}
+
+#[tokio::test]
+async fn authenticated_not_counted_on_the_address() {
+    let limits = limits(2);
+    let mut other = limits.acquire(LOCALHOST).unwrap();
+    assert!(other.authenticate("hello"));
+
+    // NOTE: the other connection left the anonymous slot of the address.
+    let replies = run(limits.acquire(LOCALHOST).unwrap(), "hello").await;
+
+    assert!(replies.contains(&"235 2.7.0 Authentication succeeded\r\n".to_owned()));
+    assert_eq!(
+        replies.last().unwrap(),
+        "221 Service closing transmission channel\r\n"
+    );
+}
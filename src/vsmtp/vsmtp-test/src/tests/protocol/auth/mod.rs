@@ -73,3 +73,5 @@ pub fn unsafe_auth_config() -> Config {
 }
 
 mod basic;
+mod connection_limits;
+mod sender;
@@ -0,0 +1,222 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2022 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use super::unsafe_auth_config;
+use crate::run_test;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use vsmtp_common::addr;
+use vsmtp_common::ContextFinished;
+use vsmtp_config::field::{FieldServerSMTPAuthSenders, FromHeaderPolicy};
+use vsmtp_mail_parser::MessageBody;
+
+fn sender_policy_config() -> vsmtp_config::Config {
+    let mut config = unsafe_auth_config();
+    config.server.esmtp.auth.as_mut().unwrap().senders = Some(FieldServerSMTPAuthSenders {
+        owned: [
+            ("hello".to_owned(), vec!["hello@bar".to_owned()]),
+            ("*".to_owned(), vec!["{authid}@users.bar".to_owned()]),
+        ]
+        .into_iter()
+        .collect(),
+        from_header: FromHeaderPolicy::Flag,
+    });
+    config
+}
+
+run_test! {
+    fn sender_not_owned,
+    input = [
+        "EHLO client.com\r\n",
+        &format!("AUTH PLAIN {}\r\n", STANDARD.encode(format!("\0{}\0{}", "hello", "world"))),
+        "MAIL FROM:<ceo@bar>\r\n",
+        "MAIL FROM:<hello@bar>\r\n",
+        "RCPT TO:<joe@doe>\r\n",
+        "DATA\r\n",
+        ".\r\n",
+        "QUIT\r\n"
+    ],
+    expected = [
+        "220 testserver.com Service ready\r\n",
+        "250-testserver.com\r\n",
+        "250-AUTH PLAIN LOGIN CRAM-MD5 ANONYMOUS\r\n",
+        "250-8BITMIME\r\n",
+        "250-SMTPUTF8\r\n",
+        "250-STARTTLS\r\n",
+        "250-PIPELINING\r\n",
+        "250-DSN\r\n",
+        "250 SIZE 20000000\r\n",
+        "235 2.7.0 Authentication succeeded\r\n",
+        "553 5.7.1 Sender address rejected: not owned by user\r\n",
+        "250 Ok\r\n",
+        "250 Ok\r\n",
+        "354 Start mail input; end with <CRLF>.<CRLF>\r\n",
+        "250 Ok\r\n",
+        "221 Service closing transmission channel\r\n"
+    ],
+    submission = true,
+    config = sender_policy_config(),
+    mail_handler = |ctx: ContextFinished, _: MessageBody| {
+        assert_eq!(ctx.mail_from.reverse_path, Some(addr!("hello@bar")));
+    },
+}
+
+run_test! {
+    fn sender_null_reverse_path,
+    input = [
+        "EHLO client.com\r\n",
+        &format!("AUTH PLAIN {}\r\n", STANDARD.encode(format!("\0{}\0{}", "hello", "world"))),
+        "MAIL FROM:<>\r\n",
+        "MAIL FROM:<hello@bar>\r\n",
+        "RCPT TO:<joe@doe>\r\n",
+        "DATA\r\n",
+        ".\r\n",
+        "QUIT\r\n"
+    ],
+    expected = [
+        "220 testserver.com Service ready\r\n",
+        "250-testserver.com\r\n",
+        "250-AUTH PLAIN LOGIN CRAM-MD5 ANONYMOUS\r\n",
+        "250-8BITMIME\r\n",
+        "250-SMTPUTF8\r\n",
+        "250-STARTTLS\r\n",
+        "250-PIPELINING\r\n",
+        "250-DSN\r\n",
+        "250 SIZE 20000000\r\n",
+        "235 2.7.0 Authentication succeeded\r\n",
+        "553 5.7.1 Sender address rejected: null reverse-path not allowed for authenticated users\r\n",
+        "250 Ok\r\n",
+        "250 Ok\r\n",
+        "354 Start mail input; end with <CRLF>.<CRLF>\r\n",
+        "250 Ok\r\n",
+        "221 Service closing transmission channel\r\n"
+    ],
+    submission = true,
+    config = sender_policy_config(),
+    mail_handler = |ctx: ContextFinished, _: MessageBody| {
+        assert_eq!(ctx.mail_from.reverse_path, Some(addr!("hello@bar")));
+    },
+}
+
+run_test! {
+    fn sender_owned_by_pattern,
+    input = [
+        "EHLO client.com\r\n",
+        &format!("AUTH PLAIN {}\r\n", STANDARD.encode(format!("\0{}\0{}", "hello", "world"))),
+        "MAIL FROM:<hello@users.bar>\r\n",
+        "RCPT TO:<joe@doe>\r\n",
+        "DATA\r\n",
+        ".\r\n",
+        "QUIT\r\n"
+    ],
+    expected = [
+        "220 testserver.com Service ready\r\n",
+        "250-testserver.com\r\n",
+        "250-AUTH PLAIN LOGIN CRAM-MD5 ANONYMOUS\r\n",
+        "250-8BITMIME\r\n",
+        "250-SMTPUTF8\r\n",
+        "250-STARTTLS\r\n",
+        "250-PIPELINING\r\n",
+        "250-DSN\r\n",
+        "250 SIZE 20000000\r\n",
+        "235 2.7.0 Authentication succeeded\r\n",
+        "250 Ok\r\n",
+        "250 Ok\r\n",
+        "354 Start mail input; end with <CRLF>.<CRLF>\r\n",
+        "250 Ok\r\n",
+        "221 Service closing transmission channel\r\n"
+    ],
+    submission = true,
+    config = sender_policy_config(),
+    mail_handler = |ctx: ContextFinished, _: MessageBody| {
+        assert_eq!(ctx.mail_from.reverse_path, Some(addr!("hello@users.bar")));
+    },
+}
+
+run_test! {
+    fn sender_not_owned_on_relay,
+    input = [
+        "EHLO client.com\r\n",
+        &format!("AUTH PLAIN {}\r\n", STANDARD.encode(format!("\0{}\0{}", "hello", "world"))),
+        "MAIL FROM:<ceo@bar>\r\n",
+        "QUIT\r\n"
+    ],
+    expected = [
+        "220 testserver.com Service ready\r\n",
+        "250-testserver.com\r\n",
+        "250-AUTH PLAIN LOGIN CRAM-MD5 ANONYMOUS\r\n",
+        "250-8BITMIME\r\n",
+        "250-SMTPUTF8\r\n",
+        "250-STARTTLS\r\n",
+        "250-PIPELINING\r\n",
+        "250-DSN\r\n",
+        "250 SIZE 20000000\r\n",
+        "235 2.7.0 Authentication succeeded\r\n",
+        "250 Ok\r\n",
+        "221 Service closing transmission channel\r\n"
+    ],
+    config = sender_policy_config(),
+}
+
+run_test! {
+    fn sender_owned_by_rules,
+    input = [
+        "EHLO client.com\r\n",
+        &format!("AUTH PLAIN {}\r\n", STANDARD.encode(format!("\0{}\0{}", "hello", "world"))),
+        "MAIL FROM:<ceo@bar>\r\n",
+        "MAIL FROM:<support@bar>\r\n",
+        "QUIT\r\n"
+    ],
+    expected = [
+        "220 testserver.com Service ready\r\n",
+        "250-testserver.com\r\n",
+        "250-AUTH PLAIN LOGIN CRAM-MD5 ANONYMOUS\r\n",
+        "250-8BITMIME\r\n",
+        "250-SMTPUTF8\r\n",
+        "250-STARTTLS\r\n",
+        "250-PIPELINING\r\n",
+        "250-DSN\r\n",
+        "250 SIZE 20000000\r\n",
+        "235 2.7.0 Authentication succeeded\r\n",
+        "553 5.7.1 Sender address rejected: not owned by user\r\n",
+        "250 Ok\r\n",
+        "221 Service closing transmission channel\r\n"
+    ],
+    submission = true,
+    config = sender_policy_config(),
+    hierarchy_builder = |builder| {
+        Ok(builder
+            .add_root_filter_rules(r#"
+fn is_sender_owned(authid, address) {
+    authid == "hello" && address == "support@bar"
+}
+
+#{
+    authenticate: [
+        rule "auth hardcoded" || {
+            const credentials = auth::credentials();
+            if credentials.authid == "hello" && credentials.authpass == "world" {
+                state::accept()
+            } else {
+                state::deny()
+            }
+        }
+    ],
+}
+"#)?
+            .build())
+    },
+}
@@ -0,0 +1,74 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2022 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use crate::config;
+use crate::run_test;
+
+run_test! {
+    fn banner_set,
+    input = ["QUIT\r\n"],
+    expected = [
+        "220 testserver.com ESMTP ready for 127.0.0.1\r\n",
+        "221 Service closing transmission channel\r\n",
+    ],
+    config = config::local_test(),
+    hierarchy_builder = |builder| Ok(builder.add_root_filter_rules(r#"#{
+        connect: [
+            action "banner" || ctx::set_banner(`ESMTP ready for ${ctx::client_ip()}`),
+        ],
+    }"#)?.build()),
+}
+
+run_test! {
+    fn banner_kept_by_accept,
+    input = ["QUIT\r\n"],
+    expected = [
+        "220 testserver.com ESMTP\r\n",
+        "221 Service closing transmission channel\r\n",
+    ],
+    config = config::local_test(),
+    hierarchy_builder = |builder| Ok(builder.add_root_filter_rules(r#"#{
+        connect: [
+            action "banner" || ctx::set_banner("ESMTP"),
+            rule "trusted" || state::accept(),
+        ],
+    }"#)?.build()),
+}
+
+run_test! {
+    fn banner_with_deny,
+    input = ["QUIT\r\n"],
+    expected = ["554 permanent problems with the remote server\r\n"],
+    config = config::local_test(),
+    hierarchy_builder = |builder| Ok(builder.add_root_filter_rules(r#"#{
+        connect: [
+            action "banner" || ctx::set_banner("ESMTP"),
+            rule "refused" || state::deny(),
+        ],
+    }"#)?.build()),
+}
+
+run_test! {
+    fn banner_invalid,
+    input = ["QUIT\r\n"],
+    expected = ["554 permanent problems with the remote server\r\n"],
+    config = config::local_test(),
+    hierarchy_builder = |builder| Ok(builder.add_root_filter_rules(r#"#{
+        connect: [
+            action "banner" || ctx::set_banner("ESMTP\r\n250 injected"),
+        ],
+    }"#)?.build()),
+}
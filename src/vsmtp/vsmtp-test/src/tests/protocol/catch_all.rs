@@ -0,0 +1,132 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2023 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use crate::config;
+use crate::run_test;
+use vsmtp_common::{addr, ContextFinished};
+use vsmtp_config::field::{FieldServerRecipients, FieldServerVirtual};
+use vsmtp_mail_parser::MessageBody;
+
+fn with_catch_all() -> vsmtp_config::Config {
+    let path = std::env::temp_dir().join(format!("vsmtp-mailboxes-{}", uuid::Uuid::new_v4()));
+    std::fs::write(&path, "jenny\nall\n").unwrap();
+
+    let mut config = config::local_test();
+    config.server.recipients = Some(FieldServerRecipients {
+        path,
+        reply: "550 5.1.1 User unknown\r\n".parse().unwrap(),
+        probe_count_max: 1,
+        probe_window: std::time::Duration::from_secs(600),
+        probe_reply: "421 4.7.0 Too many unknown recipients, closing connection\r\n"
+            .parse()
+            .unwrap(),
+        reload_period: std::time::Duration::from_secs(10),
+    });
+    config.server.r#virtual.insert(
+        "testserver.com".parse().unwrap(),
+        FieldServerVirtual {
+            catch_all: Some(addr!("all@testserver.com")),
+            ..Default::default()
+        },
+    );
+    config
+}
+
+const RULES: &str = r#"
+#{
+    rcpt: [
+        rule "no catch-all for spam" || {
+            if `${ctx::original_rcpt()}` == "spam@testserver.com" { state::deny() } else { state::next() }
+        },
+    ],
+}
+"#;
+
+run_test! {
+    fn unknown_recipients_rewritten,
+    input = [
+        "HELO foo\r\n",
+        "MAIL FROM:<john@doe>\r\n",
+        "RCPT TO:<jenny@testserver.com>\r\n",
+        "RCPT TO:<ghost@testserver.com>\r\n",
+        "RCPT TO:<phantom@testserver.com>\r\n",
+        "DATA\r\n",
+        ".\r\n",
+        "QUIT\r\n",
+    ],
+    expected = [
+        "220 testserver.com Service ready\r\n",
+        "250 Ok\r\n",
+        "250 Ok\r\n",
+        "250 Ok\r\n",
+        "250 Ok\r\n",
+        "250 Ok\r\n",
+        "354 Start mail input; end with <CRLF>.<CRLF>\r\n",
+        "250 Ok\r\n",
+        "221 Service closing transmission channel\r\n",
+    ],
+    config = with_catch_all(),
+    mail_handler = |ctx: ContextFinished, _: MessageBody| {
+        pretty_assertions::assert_eq!(
+            ctx.rcpt_to.forward_paths,
+            vec![addr!("jenny@testserver.com"), addr!("all@testserver.com")]
+        );
+        pretty_assertions::assert_eq!(
+            ctx.rcpt_to.catch_all,
+            [(
+                addr!("all@testserver.com"),
+                vec![addr!("ghost@testserver.com"), addr!("phantom@testserver.com")]
+            )]
+            .into()
+        );
+    },
+    hierarchy_builder = |builder| {
+        Ok(builder
+            .add_root_filter_rules("#{}")?
+            .add_domain_rules("testserver.com".parse().unwrap())
+            .with_incoming(RULES)?
+            .with_outgoing("#{}")?
+            .with_internal("#{}")?
+            .build()
+            .build())
+    },
+}
+
+run_test! {
+    fn rewrite_seen_by_the_rules,
+    input = [
+        "HELO foo\r\n",
+        "MAIL FROM:<john@doe>\r\n",
+        "RCPT TO:<spam@testserver.com>\r\n",
+    ],
+    expected = [
+        "220 testserver.com Service ready\r\n",
+        "250 Ok\r\n",
+        "250 Ok\r\n",
+        "554 permanent problems with the remote server\r\n",
+    ],
+    config = with_catch_all(),
+    hierarchy_builder = |builder| {
+        Ok(builder
+            .add_root_filter_rules("#{}")?
+            .add_domain_rules("testserver.com".parse().unwrap())
+            .with_incoming(RULES)?
+            .with_outgoing("#{}")?
+            .with_internal("#{}")?
+            .build()
+            .build())
+    },
+}
@@ -0,0 +1,244 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2022 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+
+use crate::config;
+use crate::run_test;
+use vsmtp_common::ContextFinished;
+use vsmtp_mail_parser::MessageBody;
+
+fn with_chunking() -> vsmtp_config::Config {
+    let mut config = config::local_test();
+    config.server.esmtp.chunking = true;
+    config
+}
+
+run_test! {
+    fn bdat_empty_last,
+    input = [
+        "HELO foobar\r\n",
+        "MAIL FROM:<john@doe>\r\n",
+        "RCPT TO:<aa@bb>\r\n",
+        "BDAT 0 LAST\r\n",
+        "QUIT\r\n",
+    ],
+    expected = [
+        "220 testserver.com Service ready\r\n",
+        "250 Ok\r\n",
+        "250 Ok\r\n",
+        "250 Ok\r\n",
+        "250 Ok\r\n",
+        "221 Service closing transmission channel\r\n",
+    ],
+    config = with_chunking(),
+    mail_handler = |_: ContextFinished, body: MessageBody| {
+        pretty_assertions::assert_eq!(*body.inner().body(), Some(String::new()));
+    },
+}
+
+run_test! {
+    fn bdat_several_chunks,
+    input = [
+        "HELO foobar\r\n",
+        "MAIL FROM:<john@doe>\r\n",
+        "RCPT TO:<aa@bb>\r\n",
+        "BDAT 21\r\nsubject: chunking\r\n\r\n",
+        "BDAT 10 LAST\r\n.not a dot",
+        "QUIT\r\n",
+    ],
+    expected = [
+        "220 testserver.com Service ready\r\n",
+        "250 Ok\r\n",
+        "250 Ok\r\n",
+        "250 Ok\r\n",
+        "250 2.0.0 21 octets received\r\n",
+        "250 Ok\r\n",
+        "221 Service closing transmission channel\r\n",
+    ],
+    config = with_chunking(),
+    mail_handler = |_: ContextFinished, body: MessageBody| {
+        pretty_assertions::assert_eq!(body.get_header("subject").as_deref(), Some("chunking"));
+        pretty_assertions::assert_eq!(*body.inner().body(), Some(".not a dot".to_string()));
+    },
+}
+
+run_test! {
+    fn bdat_chunk_too_big,
+    input = [
+        "HELO foobar\r\n",
+        "MAIL FROM:<john@doe>\r\n",
+        "RCPT TO:<aa@bb>\r\n",
+        &format!("BDAT 2000 LAST\r\n{}", "X".repeat(2000)),
+        "QUIT\r\n",
+    ],
+    expected = [
+        "220 testserver.com Service ready\r\n",
+        "250 Ok\r\n",
+        "250 Ok\r\n",
+        "250 Ok\r\n",
+        "552 5.3.4 Message size exceeds fixed maximum message size\r\n",
+        "221 Service closing transmission channel\r\n",
+    ],
+    config = {
+        let mut config = with_chunking();
+        config.server.message_size_limit = 1000;
+        config
+    },
+}
+
+run_test! {
+    fn bdat_chunks_too_big,
+    input = [
+        "HELO foobar\r\n",
+        "MAIL FROM:<john@doe>\r\n",
+        "RCPT TO:<aa@bb>\r\n",
+        &format!("BDAT 600\r\n{}", "X".repeat(600)),
+        &format!("BDAT 600\r\n{}", "X".repeat(600)),
+        &format!("BDAT 10 LAST\r\n{}", "X".repeat(10)),
+        "QUIT\r\n",
+    ],
+    expected = [
+        "220 testserver.com Service ready\r\n",
+        "250 Ok\r\n",
+        "250 Ok\r\n",
+        "250 Ok\r\n",
+        "250 2.0.0 600 octets received\r\n",
+        "552 5.3.4 Message size exceeds fixed maximum message size\r\n",
+        // the transaction is aborted, the following chunks are discarded.
+        "503 Bad sequence of commands\r\n",
+        "221 Service closing transmission channel\r\n",
+    ],
+    config = {
+        let mut config = with_chunking();
+        config.server.message_size_limit = 1000;
+        config
+    },
+}
+
+run_test! {
+    fn bdat_chunks_exceed_declared_size,
+    input = [
+        "EHLO foobar\r\n",
+        "MAIL FROM:<john@doe> SIZE=30\r\n",
+        "RCPT TO:<aa@bb>\r\n",
+        "BDAT 21\r\nsubject: chunking\r\n\r\n",
+        "BDAT 20 LAST\r\n01234567890123456789",
+        "QUIT\r\n",
+    ],
+    expected = [
+        "220 testserver.com Service ready\r\n",
+        "250-testserver.com\r\n",
+        "250-8BITMIME\r\n",
+        "250-SMTPUTF8\r\n",
+        "250-STARTTLS\r\n",
+        "250-PIPELINING\r\n",
+        "250-CHUNKING\r\n",
+        "250-DSN\r\n",
+        "250 SIZE 20000000\r\n",
+        "250 Ok\r\n",
+        "250 Ok\r\n",
+        "250 2.0.0 21 octets received\r\n",
+        "552 5.3.4 Message size exceeds fixed maximum message size\r\n",
+        "221 Service closing transmission channel\r\n",
+    ],
+    config = with_chunking(),
+}
+
+run_test! {
+    fn bdat_unknown_declared_size,
+    input = [
+        "HELO foobar\r\n",
+        "MAIL FROM:<john@doe> SIZE=0\r\n",
+        "RCPT TO:<aa@bb>\r\n",
+        "BDAT 21\r\nsubject: chunking\r\n\r\n",
+        "BDAT 20 LAST\r\n01234567890123456789",
+        "QUIT\r\n",
+    ],
+    expected = [
+        "220 testserver.com Service ready\r\n",
+        "250 Ok\r\n",
+        "250 Ok\r\n",
+        "250 Ok\r\n",
+        "250 2.0.0 21 octets received\r\n",
+        "250 Ok\r\n",
+        "221 Service closing transmission channel\r\n",
+    ],
+    config = with_chunking(),
+    mail_handler = |ctx: ContextFinished, _: MessageBody| {
+        pretty_assertions::assert_eq!(ctx.mail_from.declared_size, None);
+    },
+}
+
+run_test! {
+    fn bdat_unknown_declared_size_exceed_max,
+    input = [
+        "HELO foobar\r\n",
+        "MAIL FROM:<john@doe> SIZE=0\r\n",
+        "RCPT TO:<aa@bb>\r\n",
+        &format!("BDAT 600\r\n{}", "X".repeat(600)),
+        &format!("BDAT 600 LAST\r\n{}", "X".repeat(600)),
+        "QUIT\r\n",
+    ],
+    expected = [
+        "220 testserver.com Service ready\r\n",
+        "250 Ok\r\n",
+        "250 Ok\r\n",
+        "250 Ok\r\n",
+        "250 2.0.0 600 octets received\r\n",
+        "552 5.3.4 Message size exceeds fixed maximum message size\r\n",
+        "221 Service closing transmission channel\r\n",
+    ],
+    config = {
+        let mut config = with_chunking();
+        config.server.message_size_limit = 1000;
+        config
+    },
+}
+
+run_test! {
+    fn bdat_not_enabled,
+    input = [
+        "HELO foobar\r\n",
+        "MAIL FROM:<john@doe>\r\n",
+        "RCPT TO:<aa@bb>\r\n",
+        "BDAT 6 LAST\r\nQUIT\r\n",
+        "QUIT\r\n",
+    ],
+    expected = [
+        "220 testserver.com Service ready\r\n",
+        "250 Ok\r\n",
+        "250 Ok\r\n",
+        "250 Ok\r\n",
+        "502 Command not implemented\r\n",
+        "221 Service closing transmission channel\r\n",
+    ],
+}
+
+run_test! {
+    fn bdat_bad_sequence,
+    input = [
+        "HELO foobar\r\n",
+        "BDAT 0 LAST\r\n",
+        "QUIT\r\n",
+    ],
+    expected = [
+        "220 testserver.com Service ready\r\n",
+        "250 Ok\r\n",
+        "503 Bad sequence of commands\r\n",
+        "221 Service closing transmission channel\r\n",
+    ],
+    config = with_chunking(),
+}
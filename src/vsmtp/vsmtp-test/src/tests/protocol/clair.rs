@@ -117,6 +117,18 @@ run_test! {
     ]
 }
 
+run_test! {
+    fn noop_and_help_with_arguments,
+    input = ["NOOP foo\r\n", "HELP MAIL\r\n", "NOOPS\r\n", "QUIT\r\n"],
+    expected = [
+        "220 testserver.com Service ready\r\n",
+        "250 Ok\r\n",
+        "214 joining us https://viridit.com/support\r\n",
+        "500 Syntax error command unrecognized\r\n",
+        "221 Service closing transmission channel\r\n"
+    ]
+}
+
 run_test! {
     fn test_receiver_11,
     input = [
@@ -195,7 +207,7 @@ run_test! {
         "250 Ok\r\n",
         "250 Ok\r\n",
         "250 Ok\r\n",
-        "452 Requested action not taken: too many recipients\r\n",
+        "452 4.5.3 Too many recipients\r\n",
         "221 Service closing transmission channel\r\n"
     ],
     config = {
@@ -205,6 +217,32 @@ run_test! {
     }
 }
 
+run_test! {
+    fn max_rcpt_reached_configured_reply,
+    input = [
+        "HELO client.com\r\n",
+        "MAIL FROM:<foo@bar.com>\r\n",
+        "RCPT TO:<foo+1@bar.com>\r\n",
+        "RCPT TO:<foo+2@bar.com>\r\n",
+        "QUIT\r\n",
+    ],
+    expected = [
+        "220 testserver.com Service ready\r\n",
+        "250 Ok\r\n",
+        "250 Ok\r\n",
+        "250 Ok\r\n",
+        "452 4.5.3 Only one recipient per message\r\n",
+        "221 Service closing transmission channel\r\n"
+    ],
+    config = {
+        let mut config = config::local_test();
+        config.server.smtp.rcpt_count_max = 1;
+        config.server.smtp.rcpt_count_max_reply =
+            "452 4.5.3 Only one recipient per message\r\n".parse().unwrap();
+        config
+    }
+}
+
 run_test! {
     fn test_receiver_13,
     input = [
@@ -278,7 +316,8 @@ run_test! {
                             .map(|(k, v)| (k.to_string(), v))
                             .collect::<Vec<_>>()
                         ),
-                         body: BodyType::Regular(vec![format!("mail {count}")])
+                         body: BodyType::Regular(vec![format!("mail {count}")]),
+                        parse_truncated: false,
                     }
                 );
 
@@ -369,7 +408,8 @@ run_test! {
                             .map(|(k, v)| (k.to_string(), v))
                             .collect::<Vec<_>>()
                         ),
-                        body: BodyType::Regular(vec![format!("mail {count}")])
+                        body: BodyType::Regular(vec![format!("mail {count}")]),
+                        parse_truncated: false,
                     }
                 );
 
@@ -418,8 +458,8 @@ async fn test_receiver_9() {
             "500 Syntax error command unrecognized\r\n",
             "214 joining us https://viridit.com/support\r\n",
             "500 Syntax error command unrecognized\r\n",
-            "451-Syntax error command unrecognized\r\n",
-            "451 Too many errors from the client\r\n"
+            "451-4.7.0 Syntax error command unrecognized\r\n",
+            "451 4.7.0 Too many errors from the client\r\n"
         ],
         config_arc = config.clone(),
     };
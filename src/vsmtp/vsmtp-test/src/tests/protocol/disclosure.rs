@@ -0,0 +1,109 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2023 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use crate::config::{local_test, with_tls};
+use crate::run_test;
+use vsmtp_config::field::{FieldServerDisclosure, FieldServerVirtual, FieldServerVirtualTls};
+
+fn paranoid(mut config: vsmtp_config::Config) -> vsmtp_config::Config {
+    config.server.disclosure = Some(FieldServerDisclosure {
+        name: Some("mx.example.com".parse().unwrap()),
+        software: false,
+    });
+    config
+}
+
+run_test! {
+    fn disclosure_default,
+    input = ["EHLO client.com\r\n", "QUIT\r\n"],
+    expected = [
+        "220 testserver.com Service ready\r\n",
+        "250-testserver.com\r\n",
+        "250-8BITMIME\r\n",
+        "250-SMTPUTF8\r\n",
+        "250-STARTTLS\r\n",
+        "250-PIPELINING\r\n",
+        "250-DSN\r\n",
+        "250 SIZE 20000000\r\n",
+        "221 Service closing transmission channel\r\n",
+    ],
+    config = local_test(),
+}
+
+run_test! {
+    fn disclosure_paranoid,
+    input = ["EHLO client.com\r\n", "QUIT\r\n"],
+    expected = [
+        "220 mx.example.com Service ready\r\n",
+        "250-mx.example.com\r\n",
+        "250-8BITMIME\r\n",
+        "250-SMTPUTF8\r\n",
+        "250-STARTTLS\r\n",
+        "250-PIPELINING\r\n",
+        "250-DSN\r\n",
+        "250 SIZE 20000000\r\n",
+        "221 Service closing transmission channel\r\n",
+    ],
+    config = paranoid(local_test()),
+}
+
+run_test! {
+    fn disclosure_paranoid_with_banner,
+    input = ["QUIT\r\n"],
+    expected = [
+        "220 mx.example.com ESMTP\r\n",
+        "221 Service closing transmission channel\r\n",
+    ],
+    config = paranoid(local_test()),
+    hierarchy_builder = |builder| Ok(builder.add_root_filter_rules(r#"#{
+        connect: [
+            action "banner" || ctx::set_banner("ESMTP"),
+        ],
+    }"#)?.build()),
+}
+
+// the certificate is selected with the name requested by the client,
+// and the disclosed name is sent in the greeting.
+run_test! {
+    fn disclosure_paranoid_under_tunnel,
+    input = ["QUIT\r\n"],
+    expected = [
+        "220 mx.example.com Service ready\r\n",
+        "221 Service closing transmission channel\r\n",
+    ],
+    tunnel = "testserver.com",
+    config = {
+      let mut config = paranoid(with_tls());
+      config.server.r#virtual.insert(
+          "testserver.com".parse().unwrap(),
+          FieldServerVirtual {
+            tls: Some(
+                  FieldServerVirtualTls::from_path(
+                      "src/template/certs/certificate.crt",
+                      "src/template/certs/private_key.rsa.key",
+                  )
+                  .unwrap(),
+              ),
+              dns: None,
+              dkim: None,
+              source_ips: vec![],
+              hello_name: None,
+              catch_all: None,
+          },
+      );
+      config
+    },
+}
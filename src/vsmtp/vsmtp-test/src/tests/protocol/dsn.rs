@@ -16,6 +16,8 @@
 */
 
 use crate::run_test;
+use vsmtp_common::{addr, ContextFinished, OriginalRecipient};
+use vsmtp_mail_parser::MessageBody;
 
 run_test! {
     fn submission,
@@ -110,6 +112,48 @@ run_test! {
     ],
 }
 
+run_test! {
+    fn parameters_persisted,
+    input = [
+        "EHLO Example.ORG\r\n",
+        "MAIL FROM:<Alice@Example.ORG> RET=HDRS ENVID=QQ+2B314159\r\n",
+        "RCPT TO:<Bob@Example.COM> NOTIFY=SUCCESS ORCPT=rfc822;Bob+2Bdsn@Example.COM\r\n",
+        "RCPT TO:<Carol@Ivory.EDU>\r\n",
+        "DATA\r\n",
+        ".\r\n",
+        "QUIT\r\n",
+    ],
+    expected = [
+        "220 testserver.com Service ready\r\n",
+        "250-testserver.com\r\n",
+        "250-8BITMIME\r\n",
+        "250-SMTPUTF8\r\n",
+        "250-STARTTLS\r\n",
+        "250-PIPELINING\r\n",
+        "250-DSN\r\n",
+        "250 SIZE 20000000\r\n",
+        "250 Ok\r\n",
+        "250 Ok\r\n",
+        "250 Ok\r\n",
+        "354 Start mail input; end with <CRLF>.<CRLF>\r\n",
+        "250 Ok\r\n",
+        "221 Service closing transmission channel\r\n",
+    ],
+    mail_handler = |ctx: ContextFinished, _: MessageBody| {
+        assert_eq!(ctx.mail_from.envelop_id.as_deref(), Some("QQ+314159"));
+        assert_eq!(
+            ctx.rcpt_to.original_recipients,
+            std::collections::HashMap::from([(
+                addr!("Bob@Example.COM"),
+                OriginalRecipient {
+                    addr_type: "rfc822".to_owned(),
+                    mailbox: addr!("Bob+dsn@Example.COM"),
+                },
+            )])
+        );
+    }
+}
+
 /*
 run_test! {
     fn relay_three,
@@ -0,0 +1,135 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2022 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use crate::config;
+use crate::run_test;
+
+const RULES: &str = r#"
+#{
+    helo: [
+        rule "no error yet" || if ctx::error_count() == 0 { state::next() } else { state::deny() },
+    ],
+    mail: [
+        rule "sloppy client" || {
+            let thresholds = ctx::error_thresholds();
+            if thresholds.soft == 5 && thresholds.hard == () && ctx::error_count() >= 2 {
+                state::deny()
+            } else {
+                state::next()
+            }
+        },
+    ],
+}
+"#;
+
+fn config() -> vsmtp_config::Config {
+    let mut config = config::local_test();
+    config.server.smtp.error.soft_count = 5;
+    config.server.smtp.error.hard_count = -1;
+    config
+}
+
+run_test! {
+    fn error_count_below_threshold,
+    input = [
+        "HELO foo\r\n",
+        "foo\r\n",
+        "MAIL FROM:<john@doe>\r\n",
+        "QUIT\r\n",
+    ],
+    expected = [
+        "220 testserver.com Service ready\r\n",
+        "250 Ok\r\n",
+        "500 Syntax error command unrecognized\r\n",
+        "250 Ok\r\n",
+        "221 Service closing transmission channel\r\n",
+    ],
+    config = config(),
+    hierarchy_builder = |builder| Ok(builder.add_root_filter_rules(RULES)?.build()),
+}
+
+run_test! {
+    fn error_count_above_threshold,
+    input = [
+        "HELO foo\r\n",
+        "foo\r\n",
+        "RCPT TO:<green@doe>\r\n",
+        "MAIL FROM:<john@doe>\r\n",
+    ],
+    expected = [
+        "220 testserver.com Service ready\r\n",
+        "250 Ok\r\n",
+        "500 Syntax error command unrecognized\r\n",
+        "503 Bad sequence of commands\r\n",
+        "554 permanent problems with the remote server\r\n",
+    ],
+    config = config(),
+    hierarchy_builder = |builder| Ok(builder.add_root_filter_rules(RULES)?.build()),
+}
+
+fn consecutive_config() -> vsmtp_config::Config {
+    let mut config = config::local_test();
+    config.server.smtp.error.soft_count = -1;
+    config.server.smtp.error.hard_count = -1;
+    config.server.smtp.error.consecutive_count = 3;
+    config
+}
+
+run_test! {
+    fn consecutive_bad_commands_reset,
+    input = [
+        "HELO foo\r\n",
+        "foo\r\n",
+        "DATA\r\n",
+        "NOOP\r\n",
+        "bar\r\n",
+        "MAIL FROM:<john@doe>\r\n",
+        "\x01\x02\r\n",
+        "QUIT\r\n",
+    ],
+    expected = [
+        "220 testserver.com Service ready\r\n",
+        "250 Ok\r\n",
+        "500 Syntax error command unrecognized\r\n",
+        "503 Bad sequence of commands\r\n",
+        "250 Ok\r\n",
+        "500 Syntax error command unrecognized\r\n",
+        "250 Ok\r\n",
+        "500 Syntax error command unrecognized\r\n",
+        "221 Service closing transmission channel\r\n",
+    ],
+    config = consecutive_config(),
+}
+
+run_test! {
+    fn consecutive_bad_commands_above_threshold,
+    input = [
+        "HELO foo\r\n",
+        "foo\r\n",
+        "DATA\r\n",
+        "\x01\x02\r\n",
+        "MAIL FROM:<john@doe>\r\n",
+    ],
+    expected = [
+        "220 testserver.com Service ready\r\n",
+        "250 Ok\r\n",
+        "500 Syntax error command unrecognized\r\n",
+        "503 Bad sequence of commands\r\n",
+        "421-Syntax error command unrecognized\r\n",
+        "421 Too many bad commands in a row, closing connection\r\n",
+    ],
+    config = consecutive_config(),
+}
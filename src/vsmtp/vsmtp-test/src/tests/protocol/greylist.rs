@@ -0,0 +1,112 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2022 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use crate::config;
+use crate::{run_pipelined_test, run_test};
+use vsmtp_common::{addr, ContextFinished};
+use vsmtp_mail_parser::MessageBody;
+
+run_pipelined_test! {
+    fn greylisted_rcpt_in_pipelined_batch,
+    input = [
+        "EHLO foobar\r\n",
+        "MAIL FROM:<john@doe>\r\n\
+        RCPT TO:<known@example.com>\r\n\
+        RSET\r\n",
+        "MAIL FROM:<john@doe>\r\n\
+        RCPT TO:<known@example.com>\r\n\
+        RCPT TO:<new@example.com>\r\n\
+        DATA\r\n",
+        ".\r\n",
+        "MAIL FROM:<john@doe>\r\n\
+        RCPT TO:<new@example.com>\r\n\
+        RSET\r\n",
+        "QUIT\r\n",
+    ],
+    expected = [
+        "220 testserver.com Service ready\r\n",
+        "250-testserver.com\r\n\
+        250-8BITMIME\r\n\
+        250-SMTPUTF8\r\n\
+        250-STARTTLS\r\n\
+        250-PIPELINING\r\n\
+        250-DSN\r\n\
+        250 SIZE 20000000\r\n",
+        "250 Ok\r\n\
+        451 4.7.1 Greylisted, please try again later\r\n\
+        250 Ok\r\n",
+        "250 Ok\r\n\
+        250 Ok\r\n\
+        451 4.7.1 Greylisted, please try again later\r\n\
+        354 Start mail input; end with <CRLF>.<CRLF>\r\n",
+        "250 Ok\r\n",
+        "250 Ok\r\n\
+        250 Ok\r\n\
+        250 Ok\r\n",
+        "221 Service closing transmission channel\r\n",
+    ],
+    config = {
+        let mut config = config::local_test();
+        // the retry is accepted immediately.
+        config.app.vsl.greylist.delay = std::time::Duration::ZERO;
+        config
+    },
+    mail_handler = |ctx: ContextFinished, _: MessageBody| {
+        assert_eq!(ctx.rcpt_to.forward_paths, [addr!("known@example.com")]);
+    },
+    hierarchy_builder = |builder| {
+        Ok(builder.add_root_filter_rules(r#"#{
+            rcpt: [
+              rule "greylist" || greylist::rcpt(ctx::rcpt()),
+            ],
+          }
+        "#)?.build())
+    },
+}
+
+run_test! {
+    fn greylisting_distinct_from_policy_tempfail,
+    input = [
+        "HELO foobar\r\n",
+        "MAIL FROM:<john@doe>\r\n",
+        "RCPT TO:<new@example.com>\r\n",
+        "RCPT TO:<policy@example.com>\r\n",
+        "QUIT\r\n",
+    ],
+    expected = [
+        "220 testserver.com Service ready\r\n",
+        "250 Ok\r\n",
+        "250 Ok\r\n",
+        "451 4.7.1 Greylisted, retry later\r\n",
+        "451 4.3.2 Try later\r\n",
+        "221 Service closing transmission channel\r\n",
+    ],
+    config = {
+        let mut config = config::local_test();
+        config.app.vsl.greylist.reply = "451 4.7.1 Greylisted, retry later\r\n".parse().unwrap();
+        config.app.vsl.tempfail_reply = "451 4.3.2 Try later\r\n".parse().unwrap();
+        config
+    },
+    hierarchy_builder = |builder| {
+        Ok(builder.add_root_filter_rules(r#"#{
+            rcpt: [
+              rule "policy" || if ctx::rcpt().local_part == "policy" { state::reject() } else { state::next() },
+              rule "greylist" || greylist::rcpt(ctx::rcpt()),
+            ],
+          }
+        "#)?.build())
+    },
+}
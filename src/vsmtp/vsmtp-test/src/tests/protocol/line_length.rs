@@ -0,0 +1,64 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2022 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use crate::config;
+use crate::run_test;
+
+run_test! {
+    fn line_too_long,
+    input = [
+        "HELO foobar\r\n",
+        &("X".repeat(1_000_000) + "\r\n"),
+        "NOOP\r\n",
+        "QUIT\r\n",
+    ],
+    expected = [
+        "220 testserver.com Service ready\r\n",
+        "250 Ok\r\n",
+        "500 5.5.6 Line too long\r\n",
+        "250 Ok\r\n",
+        "221 Service closing transmission channel\r\n",
+    ],
+}
+
+run_test! {
+    fn no_crlf_before_first_line_max,
+    input = [
+        &"X".repeat(8193),
+    ],
+    expected = [
+        "220 testserver.com Service ready\r\n",
+        "421 4.5.6 Line too long - closing connection\r\n",
+    ],
+}
+
+run_test! {
+    fn custom_line_length_max,
+    input = [
+        &["HELO ", &"a".repeat(100), "\r\n"].concat(),
+        "QUIT\r\n",
+    ],
+    expected = [
+        "220 testserver.com Service ready\r\n",
+        "500 5.5.6 Line too long\r\n",
+        "221 Service closing transmission channel\r\n",
+    ],
+    config = {
+        let mut config = config::local_test();
+        config.server.smtp.line_length_max = 64;
+        config
+    },
+}
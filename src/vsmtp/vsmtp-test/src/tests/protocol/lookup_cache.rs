@@ -0,0 +1,62 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2022 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use crate::config;
+use crate::run_test;
+
+const RULES: &str = r#"
+#{
+    mail: [
+        rule "first sender" || {
+            let first = cache::get_or("first sender", "1h", || `${ctx::mail_from()}`);
+            if first == "john@doe" { state::next() } else { state::deny() }
+        },
+    ],
+    rcpt: [
+        rule "forget" || {
+            if ctx::rcpt() == "forget@doe" && !cache::invalidate("first sender") {
+                return state::deny();
+            }
+            state::next()
+        },
+    ],
+}
+"#;
+
+run_test! {
+    fn lookup_shared_by_transactions,
+    input = [
+        "HELO foo\r\n",
+        "MAIL FROM:<john@doe>\r\n",
+        "RSET\r\n",
+        "MAIL FROM:<jane@doe>\r\n",
+        "RCPT TO:<forget@doe>\r\n",
+        "RSET\r\n",
+        "MAIL FROM:<jane@doe>\r\n",
+    ],
+    expected = [
+        "220 testserver.com Service ready\r\n",
+        "250 Ok\r\n",
+        "250 Ok\r\n",
+        "250 Ok\r\n",
+        "250 Ok\r\n",
+        "250 Ok\r\n",
+        "250 Ok\r\n",
+        "554 permanent problems with the remote server\r\n",
+    ],
+    config = config::local_test(),
+    hierarchy_builder = |builder| Ok(builder.add_root_filter_rules(RULES)?.build()),
+}
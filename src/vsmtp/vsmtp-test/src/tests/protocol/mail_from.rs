@@ -20,23 +20,40 @@ use vsmtp_common::addr;
 use vsmtp_common::Address;
 use vsmtp_common::ClientName;
 use vsmtp_common::ContextFinished;
+use vsmtp_common::MimeBodyType;
 use vsmtp_mail_parser::MessageBody;
 
 // TODO: add SMTPUTF8
 // TODO: add errors tests
 
 #[rstest::rstest]
-#[case("<foo@bar>", Some("foo@bar"))]
-#[case::null("<>", None)]
-#[case::null_with_body("<> BODY=8BITMIME", None)]
-#[case::whitespace_before("       <foo@bar>", Some("foo@bar"))]
-#[case::whitespace_after("<foo@bar>           ", Some("foo@bar"))]
-#[case::bit7("<foo@bar> BODY=7BIT", Some("foo@bar"))]
-#[case::bitmime8("<foo@bar> BODY=8BITMIME", Some("foo@bar"))]
-#[case::bit7_whitespace("<foo@bar>      BODY=7BIT", Some("foo@bar"))]
-#[case::bitmime8_whitespace("      <foo@bar>      BODY=8BITMIME   ", Some("foo@bar"))]
+#[case("<foo@bar>", Some("foo@bar"), None)]
+#[case::null("<>", None, None)]
+#[case::null_with_body("<> BODY=8BITMIME", None, Some(MimeBodyType::EightBitMime))]
+#[case::whitespace_before("       <foo@bar>", Some("foo@bar"), None)]
+#[case::whitespace_after("<foo@bar>           ", Some("foo@bar"), None)]
+#[case::bit7("<foo@bar> BODY=7BIT", Some("foo@bar"), Some(MimeBodyType::SevenBit))]
+#[case::bitmime8(
+    "<foo@bar> BODY=8BITMIME",
+    Some("foo@bar"),
+    Some(MimeBodyType::EightBitMime)
+)]
+#[case::bit7_whitespace(
+    "<foo@bar>      BODY=7BIT",
+    Some("foo@bar"),
+    Some(MimeBodyType::SevenBit)
+)]
+#[case::bitmime8_whitespace(
+    "      <foo@bar>      BODY=8BITMIME   ",
+    Some("foo@bar"),
+    Some(MimeBodyType::EightBitMime)
+)]
 #[trace]
-fn test(#[case] mail_from: &str, #[case] reverse_path: Option<&str>) {
+fn test(
+    #[case] mail_from: &str,
+    #[case] reverse_path: Option<&str>,
+    #[case] body_type: Option<MimeBodyType>,
+) {
     let runtime = tokio::runtime::Builder::new_multi_thread()
         .enable_all()
         .build()
@@ -71,18 +88,21 @@ fn test(#[case] mail_from: &str, #[case] reverse_path: Option<&str>) {
                 #[derive(Clone)]
                 struct T {
                     reverse_path: Option<Address>,
+                    body_type: Option<MimeBodyType>,
                 }
 
                 impl crate::recv_handler_wrapper::OnMessageCompletedHook for T {
                     fn on_message_completed(self, ctx: ContextFinished, _: MessageBody) {
                         assert_eq!(ctx.helo.client_name, ClientName::Domain("foobar".parse().unwrap()));
                         assert_eq!(ctx.mail_from.reverse_path, self.reverse_path);
+                        assert_eq!(ctx.mail_from.body_type, self.body_type);
 
                     }
                 }
 
                 T {
                     reverse_path,
+                    body_type,
                 }
             }
         }
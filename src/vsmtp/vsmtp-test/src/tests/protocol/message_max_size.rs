@@ -25,7 +25,11 @@ run_test! {
         "MAIL FROM:<john@doe>\r\n",
         "RCPT TO:<aa@bb>\r\n",
         "DATA\r\n",
-        &("X".repeat(1_000_000) + ".\r\n"),
+        &(("X".repeat(98) + "\r\n").repeat(10_001) + ".\r\n"),
+        "MAIL FROM:<john@doe>\r\n",
+        "RCPT TO:<aa@bb>\r\n",
+        "DATA\r\n",
+        "X\r\n.\r\n",
         "QUIT\r\n",
     ],
     expected = [
@@ -34,7 +38,11 @@ run_test! {
         "250 Ok\r\n",
         "250 Ok\r\n",
         "354 Start mail input; end with <CRLF>.<CRLF>\r\n",
-        "552 4.3.1 Message size exceeds fixed maximum message size\r\n",
+        "552 5.3.4 Message size exceeds fixed maximum message size\r\n",
+        "250 Ok\r\n",
+        "250 Ok\r\n",
+        "354 Start mail input; end with <CRLF>.<CRLF>\r\n",
+        "250 Ok\r\n",
         "221 Service closing transmission channel\r\n",
     ],
     config = {
@@ -43,3 +51,65 @@ run_test! {
         config
     },
 }
+
+run_test! {
+    fn test_message_size_unknown,
+    input = [
+        "HELO foobar\r\n",
+        "MAIL FROM:<john@doe> SIZE=0\r\n",
+        "RCPT TO:<aa@bb>\r\n",
+        "DATA\r\n",
+        &(("X".repeat(98) + "\r\n").repeat(100) + ".\r\n"),
+        "MAIL FROM:<john@doe> SIZE=0\r\n",
+        "RCPT TO:<aa@bb>\r\n",
+        "DATA\r\n",
+        &(("X".repeat(98) + "\r\n").repeat(10_001) + ".\r\n"),
+        "QUIT\r\n",
+    ],
+    expected = [
+        "220 testserver.com Service ready\r\n",
+        "250 Ok\r\n",
+        "250 Ok\r\n",
+        "250 Ok\r\n",
+        "354 Start mail input; end with <CRLF>.<CRLF>\r\n",
+        "250 Ok\r\n",
+        "250 Ok\r\n",
+        "250 Ok\r\n",
+        "354 Start mail input; end with <CRLF>.<CRLF>\r\n",
+        "552 5.3.4 Message size exceeds fixed maximum message size\r\n",
+        "221 Service closing transmission channel\r\n",
+    ],
+    config = {
+        let mut config = config::local_test();
+        config.server.message_size_limit = 1_000_000;
+        config
+    },
+}
+
+run_test! {
+    fn test_message_size_custom_reply,
+    input = [
+        "HELO foobar\r\n",
+        "MAIL FROM:<john@doe>\r\n",
+        "RCPT TO:<aa@bb>\r\n",
+        "DATA\r\n",
+        &(("X".repeat(98) + "\r\n").repeat(101) + ".\r\n"),
+        "QUIT\r\n",
+    ],
+    expected = [
+        "220 testserver.com Service ready\r\n",
+        "250 Ok\r\n",
+        "250 Ok\r\n",
+        "250 Ok\r\n",
+        "354 Start mail input; end with <CRLF>.<CRLF>\r\n",
+        "552 5.3.4 Messages are limited to 10kB\r\n",
+        "221 Service closing transmission channel\r\n",
+    ],
+    config = {
+        let mut config = config::local_test();
+        config.server.message_size_limit = 10_000;
+        config.server.smtp.message_size_limit_reply =
+            "552 5.3.4 Messages are limited to 10kB\r\n".parse().unwrap();
+        config
+    },
+}
@@ -0,0 +1,177 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2023 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use crate::config::local_test;
+use crate::run_test;
+
+run_test! {
+    fn unknown_parameters_strict,
+    input = [
+        "EHLO client.com\r\n",
+        "MAIL FROM:<john@doe> AUTH=<>\r\n",
+        "MAIL FROM:<john@doe> XVERP\r\n",
+        "MAIL FROM:<john@doe>\r\n",
+        "RCPT TO:<aa@bb> XRCPTFORWARD=foo\r\n",
+        "RCPT TO:<aa@bb>\r\n",
+        "QUIT\r\n",
+    ],
+    expected = [
+        "220 testserver.com Service ready\r\n",
+        "250-testserver.com\r\n",
+        "250-8BITMIME\r\n",
+        "250-SMTPUTF8\r\n",
+        "250-STARTTLS\r\n",
+        "250-PIPELINING\r\n",
+        "250-DSN\r\n",
+        "250 SIZE 20000000\r\n",
+        "555 5.5.4 Unsupported parameter\r\n",
+        "555 5.5.4 Unsupported parameter\r\n",
+        "250 Ok\r\n",
+        "555 5.5.4 Unsupported parameter\r\n",
+        "250 Ok\r\n",
+        "221 Service closing transmission channel\r\n",
+    ],
+    config = local_test(),
+}
+
+run_test! {
+    fn unknown_parameters_lenient,
+    input = [
+        "EHLO client.com\r\n",
+        "MAIL FROM:<john@doe> AUTH=<> XVERP\r\n",
+        "RCPT TO:<aa@bb> XRCPTFORWARD=foo\r\n",
+        "RSET\r\n",
+        "MAIL FROM:<john@doe> AUTH=<> AUTH=<>\r\n",
+        "MAIL FROM:<john@doe> SIZE=-1 AUTH=<>\r\n",
+        "QUIT\r\n",
+    ],
+    expected = [
+        "220 testserver.com Service ready\r\n",
+        "250-testserver.com\r\n",
+        "250-8BITMIME\r\n",
+        "250-SMTPUTF8\r\n",
+        "250-STARTTLS\r\n",
+        "250-PIPELINING\r\n",
+        "250-DSN\r\n",
+        "250 SIZE 20000000\r\n",
+        "250 Ok\r\n",
+        "250 Ok\r\n",
+        "250 Ok\r\n",
+        "501 Syntax error in parameters or arguments\r\n",
+        "501 Syntax error in parameters or arguments\r\n",
+        "221 Service closing transmission channel\r\n",
+    ],
+    config = {
+        let mut config = local_test();
+        config.server.smtp.parameters.strict = false;
+        config
+    },
+}
+
+run_test! {
+    fn parameters_limits,
+    input = [
+        "EHLO client.com\r\n",
+        "MAIL FROM:<john@doe> BODY=8BITMIME SIZE=100 SMTPUTF8\r\n",
+        "MAIL FROM:<john@doe> ENVID=0123456789abcdef\r\n",
+        "MAIL FROM:<john@doe> BODY=8BITMIME SIZE=100\r\n",
+        "QUIT\r\n",
+    ],
+    expected = [
+        "220 testserver.com Service ready\r\n",
+        "250-testserver.com\r\n",
+        "250-8BITMIME\r\n",
+        "250-SMTPUTF8\r\n",
+        "250-STARTTLS\r\n",
+        "250-PIPELINING\r\n",
+        "250-DSN\r\n",
+        "250 SIZE 20000000\r\n",
+        "501 5.5.4 Too many parameters\r\n",
+        "501 5.5.4 Parameter too long\r\n",
+        "250 Ok\r\n",
+        "221 Service closing transmission channel\r\n",
+    ],
+    config = {
+        let mut config = local_test();
+        config.server.smtp.parameters.count_max = 2;
+        config.server.smtp.parameters.length_max = 16;
+        config
+    },
+}
+
+run_test! {
+    fn unexpected_parameters_rejected,
+    input = [
+        "EHLO client.com\r\n",
+        "NOOP something\r\n",
+        "STARTTLS z\r\n",
+        "MAIL FROM:<john@doe>\r\n",
+        "RCPT TO:<aa@bb>\r\n",
+        "RSET x\r\n",
+        "DATA param\r\n",
+        "RCPT TO:<bb@cc>\r\n",
+        "QUIT y\r\n",
+        "QUIT \r\n",
+    ],
+    expected = [
+        "220 testserver.com Service ready\r\n",
+        "250-testserver.com\r\n",
+        "250-8BITMIME\r\n",
+        "250-SMTPUTF8\r\n",
+        "250-STARTTLS\r\n",
+        "250-PIPELINING\r\n",
+        "250-DSN\r\n",
+        "250 SIZE 20000000\r\n",
+        "250 Ok\r\n",
+        "501 5.5.4 Syntax error in parameters or arguments\r\n",
+        "250 Ok\r\n",
+        "250 Ok\r\n",
+        "501 5.5.4 Syntax error in parameters or arguments\r\n",
+        "501 5.5.4 Syntax error in parameters or arguments\r\n",
+        "250 Ok\r\n",
+        "501 5.5.4 Syntax error in parameters or arguments\r\n",
+        "221 Service closing transmission channel\r\n",
+    ],
+    config = local_test(),
+}
+
+run_test! {
+    fn unexpected_parameters_ignored,
+    input = [
+        "EHLO client.com\r\n",
+        "MAIL FROM:<john@doe>\r\n",
+        "RSET x\r\n",
+        "QUIT y\r\n",
+    ],
+    expected = [
+        "220 testserver.com Service ready\r\n",
+        "250-testserver.com\r\n",
+        "250-8BITMIME\r\n",
+        "250-SMTPUTF8\r\n",
+        "250-STARTTLS\r\n",
+        "250-PIPELINING\r\n",
+        "250-DSN\r\n",
+        "250 SIZE 20000000\r\n",
+        "250 Ok\r\n",
+        "250 Ok\r\n",
+        "221 Service closing transmission channel\r\n",
+    ],
+    config = {
+        let mut config = local_test();
+        config.server.smtp.parameters.reject_unexpected = false;
+        config
+    },
+}
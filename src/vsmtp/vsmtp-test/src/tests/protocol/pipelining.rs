@@ -184,7 +184,7 @@ run_pipelined_test! {
         "QUIT\r\n",
     ],
     expected = [
-        "250 Ok\r\n",
+        "220 testserver.com Service ready\r\n",
         "250-testserver.com\r\n\
         250-8BITMIME\r\n\
         250-SMTPUTF8\r\n\
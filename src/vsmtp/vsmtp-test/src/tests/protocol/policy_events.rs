@@ -0,0 +1,178 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2022 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use crate::config;
+use crate::run_test;
+
+const RULES: &str = r#"
+#{
+    rcpt: [
+        rule "deny spam trap" || {
+            if ctx::rcpt() == "trap@doe" { state::deny() } else { state::next() }
+        },
+    ],
+}
+"#;
+
+#[derive(Clone, Default)]
+struct Captured(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+impl std::io::Write for Captured {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Captured {
+    /// Capture the events of the current thread until the guard is dropped.
+    fn set_default() -> (Self, tracing::subscriber::DefaultGuard) {
+        let captured = Self::default();
+        let guard = tracing::subscriber::set_default(
+            tracing_subscriber::fmt()
+                .with_writer({
+                    let captured = captured.clone();
+                    move || captured.clone()
+                })
+                .with_ansi(false)
+                .with_target(true)
+                .finish(),
+        );
+        (captured, guard)
+    }
+
+    /// The output, and its lines of the policy events.
+    fn events(&self) -> (String, Vec<String>) {
+        let output = String::from_utf8(self.0.lock().unwrap().clone()).unwrap();
+        let events = output
+            .lines()
+            .filter(|line| line.contains(vsmtp_common::policy_event::TARGET))
+            .map(str::to_owned)
+            .collect::<Vec<_>>();
+        (output, events)
+    }
+}
+
+// the subscriber is set for the current thread only, so the server must run on it.
+#[tokio::test(flavor = "current_thread")]
+async fn denied_rcpt() {
+    let (captured, _guard) = Captured::set_default();
+
+    run_test! {
+        input = [
+            "HELO foo\r\n",
+            "MAIL FROM:<john@doe>\r\n",
+            "RCPT TO:<aa@bb>\r\n",
+            "RCPT TO:<trap@doe>\r\n",
+            "QUIT\r\n",
+        ],
+        expected = [
+            "220 testserver.com Service ready\r\n",
+            "250 Ok\r\n",
+            "250 Ok\r\n",
+            "250 Ok\r\n",
+            "554 permanent problems with the remote server\r\n",
+        ],
+        config = config::local_test(),
+        hierarchy_builder = |builder| Ok(builder.add_root_filter_rules(RULES)?.build()),
+    };
+
+    let (output, events) = captured.events();
+    assert_eq!(events.len(), 1, "{output}");
+
+    let event = &events[0];
+    for field in [
+        "kind=deny",
+        "stage=\"rcpt\"",
+        "rule=\"deny spam trap\"",
+        "client_ip=127.0.0.1",
+        "connection_uuid=",
+        "message_uuid=",
+        "sender=\"john@doe\"",
+        "recipient=\"trap@doe\"",
+        "raw_mail_from=\"<john@doe>\"",
+        "raw_rcpt=\"<trap@doe>\"",
+        "reply_code=554",
+        "reason=\"permanent problems with the remote server\"",
+    ] {
+        assert!(event.contains(field), "`{field}` missing in {event}");
+    }
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn built_in_limits() {
+    let (captured, _guard) = Captured::set_default();
+
+    run_test! {
+        input = [
+            "HELO foo\r\n",
+            "MAIL FROM:<john@doe>\r\n",
+            "RCPT TO:<aa@bb>\r\n",
+            "RCPT TO:<aa@bb>\r\n",
+            "RCPT TO:<cc@bb>\r\n",
+            "RCPT TO:<dd@bb>\r\n",
+            "RSET\r\n",
+            "QUIT\r\n",
+        ],
+        expected = [
+            "220 testserver.com Service ready\r\n",
+            "250 Ok\r\n",
+            "250 Ok\r\n",
+            "250 Ok\r\n",
+            "553 5.1.0 Duplicate recipient\r\n",
+            "250 Ok\r\n",
+            "452 4.5.3 Too many recipients\r\n",
+            "421 4.7.0 Too many transactions reset, closing connection\r\n",
+        ],
+        config = {
+            let mut config = config::local_test();
+            config.server.smtp.rcpt_count_max = 2;
+            config.server.smtp.duplicate_rcpt = vsmtp_config::field::DuplicateRcptPolicy::Reject;
+            config.server.smtp.error.churn_count = 1;
+            config
+        },
+    };
+
+    let (output, events) = captured.events();
+    assert_eq!(events.len(), 3, "{output}");
+
+    for (event, fields) in events.iter().zip([
+        &[
+            "kind=duplicate_recipient",
+            "recipient=\"aa@bb\"",
+            "raw_rcpt=\"<aa@bb>\"",
+            "reply_code=553",
+        ][..],
+        &[
+            "kind=recipient_limit",
+            "recipient=\"dd@bb\"",
+            "raw_rcpt=\"<dd@bb>\"",
+            "reply_code=452",
+        ][..],
+        &[
+            "kind=transaction_churn",
+            "client_ip=127.0.0.1",
+            "reply_code=421",
+        ][..],
+    ]) {
+        for field in fields {
+            assert!(event.contains(field), "`{field}` missing in {event}");
+        }
+    }
+}
@@ -0,0 +1,136 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2023 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use crate::{config, run_test};
+use vqueue::{GenericQueueManager, QueueID};
+use vsmtp_common::ContextFinished;
+use vsmtp_mail_parser::MessageBody;
+
+const INPUT: [&str; 7] = [
+    "HELO foo\r\n",
+    "MAIL FROM:<John.DOE@Example.com>  size=42 Body=8bitMIME\r\n",
+    "RCPT TO:<Aa@bb.com> orcpt=rfc822;AA@BB.COM\r\n",
+    "RCPT TO:<cc@bb.com>\r\n",
+    "DATA\r\n",
+    ".\r\n",
+    "QUIT\r\n",
+];
+
+const EXPECTED: [&str; 8] = [
+    "220 testserver.com Service ready\r\n",
+    "250 Ok\r\n",
+    "250 Ok\r\n",
+    "250 Ok\r\n",
+    "250 Ok\r\n",
+    "354 Start mail input; end with <CRLF>.<CRLF>\r\n",
+    "250 Ok\r\n",
+    "221 Service closing transmission channel\r\n",
+];
+
+const RULES: &str = r#"
+#{
+    mail: [
+        rule "raw mail from" || {
+            if ctx::raw_mail_from() == "<John.DOE@Example.com>  size=42 Body=8bitMIME" {
+                state::next()
+            } else {
+                state::deny()
+            }
+        },
+    ],
+    rcpt: [
+        rule "raw rcpt" || {
+            if ctx::raw_rcpt(0) == "<Aa@bb.com> orcpt=rfc822;AA@BB.COM"
+                && (ctx::rcpt_count() == 1 || ctx::raw_rcpt(1) == "<cc@bb.com>")
+                && ctx::raw_rcpt(2) == () {
+                state::next()
+            } else {
+                state::deny()
+            }
+        },
+    ],
+}
+"#;
+
+run_test! {
+    fn raw_text,
+    input = INPUT,
+    expected = EXPECTED,
+    mail_handler = |ctx: ContextFinished, _: MessageBody| {
+        assert_eq!(
+            ctx.mail_from.raw_mail_from.as_deref(),
+            Some("<John.DOE@Example.com>  size=42 Body=8bitMIME")
+        );
+        assert_eq!(
+            ctx.mail_from.raw_rcpt,
+            ["<Aa@bb.com> orcpt=rfc822;AA@BB.COM", "<cc@bb.com>"]
+        );
+    },
+    hierarchy_builder = |builder| Ok(builder.add_root_filter_rules(RULES)?.build()),
+}
+
+async fn queued_ctx(queue_manager: &dyn GenericQueueManager, queue: &QueueID) -> ContextFinished {
+    let messages = queue_manager.list(queue).await.unwrap();
+    assert_eq!(messages.len(), 1);
+    let message_uuid = messages[0].as_ref().unwrap().parse().unwrap();
+    queue_manager.get_ctx(queue, &message_uuid).await.unwrap()
+}
+
+#[test_log::test(tokio::test)]
+async fn dropped_once_queued() {
+    let queue_manager = run_test! {
+        input = INPUT,
+        expected = EXPECTED,
+        hierarchy_builder = |builder| Ok(builder.add_root_filter_rules(RULES)?.build()),
+    };
+
+    let ctx = queued_ctx(&*queue_manager, &QueueID::Working).await;
+    assert_eq!(ctx.mail_from.raw_mail_from, None);
+    assert!(ctx.mail_from.raw_rcpt.is_empty());
+}
+
+#[test_log::test(tokio::test)]
+async fn kept_in_quarantine() {
+    let quarantine = QueueID::Quarantine {
+        name: "forensic".to_owned(),
+    };
+
+    for keep in [false, true] {
+        let mut config = config::local_test();
+        config.server.smtp.raw_commands_in_quarantine = keep;
+
+        let queue_manager = run_test! {
+            input = INPUT,
+            expected = EXPECTED,
+            config = config,
+            hierarchy_builder = |builder| Ok(builder.add_root_filter_rules(r#"#{
+                preq: [ rule "quarantine" || state::quarantine("forensic") ],
+            }"#)?.build()),
+        };
+
+        let ctx = queued_ctx(&*queue_manager, &quarantine).await;
+        if keep {
+            assert_eq!(
+                ctx.mail_from.raw_mail_from.as_deref(),
+                Some("<John.DOE@Example.com>  size=42 Body=8bitMIME")
+            );
+            assert_eq!(ctx.mail_from.raw_rcpt.len(), 2);
+        } else {
+            assert_eq!(ctx.mail_from.raw_mail_from, None);
+            assert!(ctx.mail_from.raw_rcpt.is_empty());
+        }
+    }
+}
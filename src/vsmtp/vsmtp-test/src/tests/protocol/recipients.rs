@@ -0,0 +1,113 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2023 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use crate::config;
+use crate::run_test;
+use vsmtp_config::field::FieldServerRecipients;
+
+fn with_mailboxes(content: &str) -> vsmtp_config::Config {
+    let path = std::env::temp_dir().join(format!("vsmtp-mailboxes-{}", uuid::Uuid::new_v4()));
+    std::fs::write(&path, content).unwrap();
+
+    let mut config = config::local_test();
+    config.server.recipients = Some(FieldServerRecipients {
+        path,
+        reply: "550 5.1.1 User unknown\r\n".parse().unwrap(),
+        probe_count_max: 2,
+        probe_window: std::time::Duration::from_secs(600),
+        probe_reply: "421 4.7.0 Too many unknown recipients, closing connection\r\n"
+            .parse()
+            .unwrap(),
+        reload_period: std::time::Duration::from_secs(10),
+    });
+    config
+}
+
+run_test! {
+    fn unknown_recipients_then_disconnected,
+    input = [
+        "HELO foo\r\n",
+        "MAIL FROM:<john@doe>\r\n",
+        "RCPT TO:<jenny@testserver.com>\r\n",
+        "RCPT TO:<bob@other.com>\r\n",
+        "RCPT TO:<admin@testserver.com>\r\n",
+        "RCPT TO:<root@testserver.com>\r\n",
+        "RCPT TO:<oracle@testserver.com>\r\n",
+    ],
+    expected = [
+        "220 testserver.com Service ready\r\n",
+        "250 Ok\r\n",
+        "250 Ok\r\n",
+        "250 Ok\r\n",
+        "250 Ok\r\n",
+        "550 5.1.1 User unknown\r\n",
+        "550 5.1.1 User unknown\r\n",
+        "421 4.7.0 Too many unknown recipients, closing connection\r\n",
+    ],
+    config = with_mailboxes("jenny\n"),
+    hierarchy_builder = |builder| {
+        Ok(builder
+            .add_root_filter_rules("#{}")?
+            .add_domain_rules("testserver.com".parse().unwrap())
+            .with_incoming("#{}")?
+            .with_outgoing("#{}")?
+            .with_internal("#{}")?
+            .build()
+            .build())
+    },
+}
+
+run_test! {
+    fn known_recipients_with_extension,
+    input = [
+        "HELO foo\r\n",
+        "MAIL FROM:<john@doe>\r\n",
+        "RCPT TO:<jenny-lists@testserver.com>\r\n",
+        "RCPT TO:<jenny+lists@testserver.com>\r\n",
+        "RCPT TO:<jenny-admin@testserver.com>\r\n",
+    ],
+    expected = [
+        "220 testserver.com Service ready\r\n",
+        "250 Ok\r\n",
+        "250 Ok\r\n",
+        "250 Ok\r\n",
+        "550 5.1.1 User unknown\r\n",
+        "550 5.7.1 Unexpected extension\r\n",
+    ],
+    config = {
+        let mut config = with_mailboxes("jenny\n");
+        config.server.recipient_delimiter = "-".to_owned();
+        config
+    },
+    hierarchy_builder = |builder| {
+        Ok(builder
+            .add_root_filter_rules("#{}")?
+            .add_domain_rules("testserver.com".parse().unwrap())
+            .with_incoming(r#"#{
+                rcpt: [
+                    rule "extension" || if ctx::rcpt_extension(ctx::rcpt()) == "lists" {
+                        state::next()
+                    } else {
+                        state::deny("550 5.7.1 Unexpected extension")
+                    }
+                ]
+            }"#)?
+            .with_outgoing("#{}")?
+            .with_internal("#{}")?
+            .build()
+            .build())
+    },
+}
@@ -76,7 +76,8 @@ run_test! {
                     .map(|(k, v)| (k.to_string(), v.to_string()))
                     .collect::<Vec<_>>()
                 ),
-                body: BodyType::Regular(vec!["mail content wow".to_string()])
+                body: BodyType::Regular(vec!["mail content wow".to_string()]),
+                parse_truncated: false,
             }
         );
     },
@@ -165,7 +166,8 @@ run_test! {
             *body.parsed::<MailMimeParser>().unwrap(),
             Mail {
                 headers: MailHeaders(vec![]),
-                body: BodyType::Undefined
+                body: BodyType::Undefined,
+                parse_truncated: false,
             }
         );
     },
@@ -249,7 +251,8 @@ run_test! {
                     .map(|(k, v)| (k.to_string(), v.to_string()))
                     .collect::<Vec<_>>()
                 ),
-                body: BodyType::Undefined
+                body: BodyType::Undefined,
+                parse_truncated: false,
             }
         );
     },
@@ -0,0 +1,94 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2022 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use crate::config;
+use crate::run_test;
+
+const RULES: &str = r#"
+#{
+    mail: [
+        rule "sender churn" || if ctx::sender_churn() >= 1 { state::deny() } else { state::next() },
+    ],
+}
+"#;
+
+run_test! {
+    fn sender_churn_in_rules,
+    input = [
+        "HELO foo\r\n",
+        "MAIL FROM:<john@doe>\r\n",
+        "RSET\r\n",
+        "MAIL FROM:<jenny@doe>\r\n",
+    ],
+    // denied, the connection is closed.
+    expected = [
+        "220 testserver.com Service ready\r\n",
+        "250 Ok\r\n",
+        "250 Ok\r\n",
+        "250 Ok\r\n",
+        "554 permanent problems with the remote server\r\n",
+    ],
+    hierarchy_builder = |builder| Ok(builder.add_root_filter_rules(RULES)?.build()),
+}
+
+fn churn_config() -> vsmtp_config::Config {
+    let mut config = config::local_test();
+    config.server.smtp.error.churn_count = 2;
+    config
+}
+
+run_test! {
+    fn sender_churn_above_threshold,
+    input = [
+        "HELO foo\r\n",
+        "MAIL FROM:<john@doe>\r\n",
+        "RSET\r\n",
+        "RSET\r\n",
+        "MAIL FROM:<jenny@doe>\r\n",
+        "RSET\r\n",
+        "MAIL FROM:<jack@doe>\r\n",
+    ],
+    expected = [
+        "220 testserver.com Service ready\r\n",
+        "250 Ok\r\n",
+        "250 Ok\r\n",
+        "250 Ok\r\n",
+        "250 Ok\r\n",
+        "250 Ok\r\n",
+        "421 4.7.0 Too many transactions reset, closing connection\r\n",
+    ],
+    config = churn_config(),
+}
+
+run_test! {
+    fn sender_churn_reset_by_helo,
+    input = [
+        "HELO foo\r\n",
+        "MAIL FROM:<john@doe>\r\n",
+        "HELO foo\r\n",
+        "MAIL FROM:<jenny@doe>\r\n",
+        "HELO bar\r\n",
+    ],
+    expected = [
+        "220 testserver.com Service ready\r\n",
+        "250 Ok\r\n",
+        "250 Ok\r\n",
+        "250 Ok\r\n",
+        "250 Ok\r\n",
+        "421 4.7.0 Too many transactions reset, closing connection\r\n",
+    ],
+    config = churn_config(),
+}
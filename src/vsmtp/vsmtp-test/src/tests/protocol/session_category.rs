@@ -0,0 +1,217 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2023 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use crate::config::with_tls;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
+use vsmtp_common::SessionCategory;
+use vsmtp_config::{get_rustls_config, DnsResolvers};
+use vsmtp_protocol::{AcceptArgs, ConnectionKind};
+use vsmtp_rule_engine::RuleEngine;
+use vsmtp_server::{Server, SessionStatistics};
+
+const RULES: &str = r#"#{
+    disconnect: [
+        action "log the category" || log("warn", `session category: ${ctx::session_category()}`),
+    ],
+}"#;
+
+#[derive(Clone, Default)]
+struct Captured(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+impl std::io::Write for Captured {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Serve one session, sending the `input` commands one after the other.
+///
+/// Returns the statistics of the server, and the logs of the session.
+async fn session(input: &[&str]) -> (SessionStatistics, String) {
+    let captured = Captured::default();
+    let _guard = tracing::subscriber::set_default(
+        tracing_subscriber::fmt()
+            .with_writer({
+                let captured = captured.clone();
+                move || captured.clone()
+            })
+            .with_ansi(false)
+            .finish(),
+    );
+
+    let config = std::sync::Arc::new(with_tls());
+    let queue_manager =
+        <vqueue::temp::QueueManager as vqueue::GenericQueueManager>::init(config.clone(), vec![])
+            .unwrap();
+    let resolvers = std::sync::Arc::new(DnsResolvers::from_config(&config).unwrap());
+    let rule_engine = std::sync::Arc::new(
+        RuleEngine::with_hierarchy(
+            |builder| Ok(builder.add_root_filter_rules(RULES)?.build()),
+            config.clone(),
+            resolvers,
+            queue_manager.clone(),
+        )
+        .unwrap(),
+    );
+    let (emitter, _working, _delivery) = vsmtp_server::scheduler::init(1, 1);
+    let tls_config = std::sync::Arc::new(
+        get_rustls_config(
+            config.server.tls.as_ref().unwrap(),
+            &config.server.r#virtual,
+        )
+        .unwrap(),
+    );
+    let statistics = std::sync::Arc::new(SessionStatistics::default());
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let server_addr = listener.local_addr().unwrap();
+    let server = tokio::spawn({
+        let statistics = statistics.clone();
+        async move {
+            let (stream, client_addr) = listener.accept().await.unwrap();
+            Server::serve(
+                AcceptArgs::new(
+                    client_addr,
+                    server_addr,
+                    vsmtp_common::clock::now(),
+                    uuid::Uuid::new_v4(),
+                    ConnectionKind::Relay,
+                ),
+                stream,
+                Some(tls_config),
+                config,
+                rule_engine,
+                queue_manager,
+                emitter,
+                None,
+                statistics,
+                None,
+                None,
+            )
+            .await
+        }
+    });
+
+    let stream = tokio::net::TcpStream::connect(server_addr).await.unwrap();
+    let mut stream = tokio::io::BufReader::new(stream);
+    let mut input = input.iter();
+    loop {
+        let mut line = String::new();
+        // NOTE: the connection is reset by the server after a failed TLS handshake.
+        if stream.read_line(&mut line).await.unwrap_or(0) == 0 {
+            break;
+        }
+        if line.chars().nth(3) == Some('-') {
+            continue;
+        }
+        match input.next() {
+            Some(command) => stream
+                .get_mut()
+                .write_all(command.as_bytes())
+                .await
+                .unwrap(),
+            None => break,
+        }
+    }
+    drop(stream);
+    server.await.unwrap().unwrap();
+
+    let logs = String::from_utf8(captured.0.lock().unwrap().clone()).unwrap();
+    (std::sync::Arc::try_unwrap(statistics).unwrap(), logs)
+}
+
+async fn assert_category(input: &[&str], category: SessionCategory) {
+    let (statistics, logs) = session(input).await;
+
+    assert_eq!(statistics.count(category), 1, "{logs}");
+    assert!(
+        statistics.metrics().contains(&format!(
+            "vsmtp_smtp_sessions_total{{category=\"{category}\"}} 1\n"
+        )),
+        "{logs}"
+    );
+    assert!(
+        logs.contains(&format!("session category: {category}")),
+        "{logs}"
+    );
+    assert!(logs.contains(&format!("category={category}")), "{logs}");
+}
+
+#[tokio::test]
+async fn completed() {
+    assert_category(
+        &[
+            "HELO foo\r\n",
+            "MAIL FROM:<john@doe>\r\n",
+            "RCPT TO:<aa@bb>\r\n",
+            "DATA\r\n",
+            "Subject: hello\r\n\r\nworld\r\n.\r\n",
+            "QUIT\r\n",
+        ],
+        SessionCategory::Completed,
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn aborted_after_mail() {
+    assert_category(
+        &[
+            "HELO foo\r\n",
+            "MAIL FROM:<john@doe>\r\n",
+            "RCPT TO:<aa@bb>\r\n",
+            "RSET\r\n",
+            "QUIT\r\n",
+        ],
+        SessionCategory::AbortedAfterMail,
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn probe() {
+    assert_category(&["QUIT\r\n"], SessionCategory::Probe).await;
+    assert_category(&["NOOP\r\n", "QUIT\r\n"], SessionCategory::Probe).await;
+    assert_category(&["RSET\r\n", "NOOP\r\n"], SessionCategory::Probe).await;
+}
+
+#[tokio::test]
+async fn scanner() {
+    assert_category(
+        &["GET / HTTP/1.1\r\n", "RCPT TO:<aa@bb>\r\n", "QUIT\r\n"],
+        SessionCategory::Scanner,
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn tls_failed() {
+    assert_category(
+        &["EHLO foo\r\n", "STARTTLS\r\n", "QUIT\r\n"],
+        SessionCategory::TlsFailed,
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn other() {
+    assert_category(&["EHLO foo\r\n", "QUIT\r\n"], SessionCategory::Other).await;
+}
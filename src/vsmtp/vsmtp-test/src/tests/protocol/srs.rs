@@ -0,0 +1,91 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2023 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use crate::config;
+use crate::run_test;
+use vsmtp_common::{addr, Address, ContextFinished};
+use vsmtp_config::field::FieldServerSrs;
+use vsmtp_mail_parser::MessageBody;
+use vsmtp_rule_engine::Srs;
+
+fn with_srs() -> vsmtp_config::Config {
+    let mut config = config::local_test();
+    config.server.srs = Some(FieldServerSrs {
+        domain: None,
+        secrets: vec!["secret".to_string()],
+        max_age: std::time::Duration::from_secs(21 * 24 * 60 * 60),
+    });
+    config
+}
+
+/// The sender `someone@external.org` rewritten by the server.
+fn rewritten() -> Address {
+    let config = with_srs();
+    Srs::new(
+        config.server.srs.as_ref().unwrap(),
+        &config.server.name,
+        &[config.server.name.clone()],
+    )
+    .unwrap()
+    .forward(&addr!("someone@external.org"))
+    .unwrap()
+}
+
+run_test! {
+    fn bounce_routed_to_the_original_sender,
+    input = [
+        "HELO foo\r\n".to_string(),
+        "MAIL FROM:<>\r\n".to_string(),
+        format!("RCPT TO:<{}>\r\n", rewritten()),
+        "DATA\r\n".to_string(),
+        ".\r\n".to_string(),
+        "QUIT\r\n".to_string(),
+    ],
+    expected = [
+        "220 testserver.com Service ready\r\n",
+        "250 Ok\r\n",
+        "250 Ok\r\n",
+        "250 Ok\r\n",
+        "354 Start mail input; end with <CRLF>.<CRLF>\r\n",
+        "250 Ok\r\n",
+        "221 Service closing transmission channel\r\n",
+    ],
+    config = with_srs(),
+    mail_handler = |ctx: ContextFinished, _: MessageBody| {
+        pretty_assertions::assert_eq!(
+            ctx.rcpt_to.forward_paths,
+            vec![addr!("someone@external.org")]
+        );
+    },
+}
+
+run_test! {
+    fn forged_address_refused,
+    input = [
+        "HELO foo\r\n",
+        "MAIL FROM:<>\r\n",
+        "RCPT TO:<SRS0=AAAA=AA=external.org=someone@testserver.com>\r\n",
+        "QUIT\r\n",
+    ],
+    expected = [
+        "220 testserver.com Service ready\r\n",
+        "250 Ok\r\n",
+        "250 Ok\r\n",
+        "550 5.1.1 invalid hash\r\n",
+        "221 Service closing transmission channel\r\n",
+    ],
+    config = with_srs(),
+}
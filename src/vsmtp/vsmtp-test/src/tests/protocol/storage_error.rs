@@ -0,0 +1,231 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2022 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use crate::config::local_test;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
+use vqueue::{DetailedMailContext, GenericQueueManager, QueueAges, QueueID};
+use vsmtp_common::{transport::DeserializerFn, ContextFinished};
+use vsmtp_config::{Config, DnsResolvers};
+use vsmtp_mail_parser::MessageBody;
+use vsmtp_protocol::{AcceptArgs, ConnectionKind};
+use vsmtp_rule_engine::RuleEngine;
+use vsmtp_server::{Server, SessionStatistics};
+
+/// The write of the spool failing, as on a full disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Failure {
+    Message,
+    Context,
+}
+
+/// A spool whose writes of the messages or of the contexts fail.
+#[derive(Debug)]
+struct FailingSpool {
+    inner: std::sync::Arc<vqueue::temp::QueueManager>,
+    failure: Failure,
+    /// the message whose writes have been attempted.
+    written: std::sync::Mutex<Option<uuid::Uuid>>,
+}
+
+#[async_trait::async_trait]
+impl GenericQueueManager for FailingSpool {
+    fn init(
+        _: std::sync::Arc<Config>,
+        _: Vec<DeserializerFn>,
+    ) -> anyhow::Result<std::sync::Arc<Self>> {
+        unimplemented!("built from a temporary spool")
+    }
+
+    fn get_config(&self) -> &Config {
+        self.inner.get_config()
+    }
+
+    fn get_transport_deserializer(&self) -> &[DeserializerFn] {
+        self.inner.get_transport_deserializer()
+    }
+
+    fn get_ages(&self) -> Option<&std::sync::Arc<QueueAges>> {
+        self.inner.get_ages()
+    }
+
+    async fn write_ctx(&self, queue: &QueueID, ctx: &ContextFinished) -> anyhow::Result<()> {
+        *self.written.lock().unwrap() = Some(ctx.mail_from.message_uuid);
+        anyhow::ensure!(self.failure != Failure::Context, "No space left on device");
+        self.inner.write_ctx(queue, ctx).await
+    }
+
+    async fn write_msg(&self, msg_uuid: &uuid::Uuid, msg: &MessageBody) -> anyhow::Result<()> {
+        *self.written.lock().unwrap() = Some(*msg_uuid);
+        anyhow::ensure!(self.failure != Failure::Message, "No space left on device");
+        self.inner.write_msg(msg_uuid, msg).await
+    }
+
+    async fn remove_ctx(&self, queue: &QueueID, msg_uuid: &uuid::Uuid) -> anyhow::Result<()> {
+        self.inner.remove_ctx(queue, msg_uuid).await
+    }
+
+    async fn remove_msg(&self, msg_uuid: &uuid::Uuid) -> anyhow::Result<()> {
+        self.inner.remove_msg(msg_uuid).await
+    }
+
+    async fn list(&self, queue: &QueueID) -> anyhow::Result<Vec<anyhow::Result<String>>> {
+        self.inner.list(queue).await
+    }
+
+    async fn get_ctx(
+        &self,
+        queue: &QueueID,
+        msg_uuid: &uuid::Uuid,
+    ) -> anyhow::Result<ContextFinished> {
+        self.inner.get_ctx(queue, msg_uuid).await
+    }
+
+    async fn get_detailed_ctx(
+        &self,
+        queue: &QueueID,
+        msg_uuid: &uuid::Uuid,
+    ) -> anyhow::Result<DetailedMailContext> {
+        self.inner.get_detailed_ctx(queue, msg_uuid).await
+    }
+
+    async fn get_msg(&self, msg_uuid: &uuid::Uuid) -> anyhow::Result<MessageBody> {
+        self.inner.get_msg(msg_uuid).await
+    }
+
+    async fn get_msg_raw(&self, msg_uuid: &uuid::Uuid) -> anyhow::Result<Vec<u8>> {
+        self.inner.get_msg_raw(msg_uuid).await
+    }
+}
+
+/// Send a message to a server whose spool fails with `failure`.
+///
+/// Returns the replies, the statistics of the server and the spool.
+async fn session(
+    failure: Failure,
+) -> (Vec<String>, SessionStatistics, std::sync::Arc<FailingSpool>) {
+    let config = std::sync::Arc::new(local_test());
+    let spool = std::sync::Arc::new(FailingSpool {
+        inner: <vqueue::temp::QueueManager as GenericQueueManager>::init(config.clone(), vec![])
+            .unwrap(),
+        failure,
+        written: std::sync::Mutex::new(None),
+    });
+    let resolvers = std::sync::Arc::new(DnsResolvers::from_config(&config).unwrap());
+    let rule_engine = std::sync::Arc::new(
+        RuleEngine::with_hierarchy(
+            |builder| Ok(builder.add_root_filter_rules("#{}")?.build()),
+            config.clone(),
+            resolvers,
+            spool.clone(),
+        )
+        .unwrap(),
+    );
+    let (emitter, _working, _delivery) = vsmtp_server::scheduler::init(1, 1);
+    let statistics = std::sync::Arc::new(SessionStatistics::default());
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let server_addr = listener.local_addr().unwrap();
+    let server = tokio::spawn({
+        let (statistics, queue_manager) = (statistics.clone(), spool.clone());
+        async move {
+            let (stream, client_addr) = listener.accept().await.unwrap();
+            Server::serve(
+                AcceptArgs::new(
+                    client_addr,
+                    server_addr,
+                    vsmtp_common::clock::now(),
+                    uuid::Uuid::new_v4(),
+                    ConnectionKind::Relay,
+                ),
+                stream,
+                None,
+                config,
+                rule_engine,
+                queue_manager,
+                emitter,
+                None,
+                statistics,
+                None,
+                None,
+            )
+            .await
+        }
+    });
+
+    let stream = tokio::net::TcpStream::connect(server_addr).await.unwrap();
+    let mut stream = tokio::io::BufReader::new(stream);
+    let mut input = [
+        "HELO foo\r\n",
+        "MAIL FROM:<john@doe>\r\n",
+        "RCPT TO:<aa@bb>\r\n",
+        "DATA\r\n",
+        "Subject: hello\r\n\r\nworld\r\n.\r\n",
+        "QUIT\r\n",
+    ]
+    .into_iter();
+    let mut replies = vec![];
+    loop {
+        let mut line = String::new();
+        if stream.read_line(&mut line).await.unwrap_or(0) == 0 {
+            break;
+        }
+        replies.push(line);
+        match input.next() {
+            Some(command) => stream
+                .get_mut()
+                .write_all(command.as_bytes())
+                .await
+                .unwrap(),
+            None => break,
+        }
+    }
+    drop(stream);
+    server.await.unwrap().unwrap();
+
+    (
+        replies,
+        std::sync::Arc::try_unwrap(statistics).unwrap(),
+        spool,
+    )
+}
+
+async fn assert_refused(failure: Failure) {
+    let (replies, statistics, spool) = session(failure).await;
+
+    assert_eq!(replies[5], "452 4.3.1 Insufficient system storage\r\n");
+    assert_eq!(statistics.storage_error_count(), 1);
+    assert!(statistics
+        .metrics()
+        .contains("vsmtp_spool_write_errors_total 1\n"));
+
+    // nothing is left half written in the spool.
+    let message_uuid = spool.written.lock().unwrap().unwrap();
+    spool
+        .get_ctx(&QueueID::Working, &message_uuid)
+        .await
+        .unwrap_err();
+    spool.get_msg(&message_uuid).await.unwrap_err();
+}
+
+#[tokio::test]
+async fn message_not_written() {
+    assert_refused(Failure::Message).await;
+}
+
+#[tokio::test]
+async fn context_not_written() {
+    assert_refused(Failure::Context).await;
+}
@@ -0,0 +1,171 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2023 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use crate::config::with_tls;
+use tokio_rustls::rustls;
+use vsmtp_config::{
+    field::{FieldServerVirtual, FieldServerVirtualTls},
+    get_rustls_config,
+};
+
+const RSA: (&str, &str) = (
+    "src/template/certs/certificate.crt",
+    "src/template/certs/private_key.rsa.key",
+);
+const ECDSA: (&str, &str) = (
+    "src/template/certs/certificate.ec256.crt",
+    "src/template/certs/private_key.ec256.key",
+);
+const SECOND_RSA: (&str, &str) = (
+    "src/template/certs/sni/second.certificate.crt",
+    "src/template/certs/sni/second.private_key.rsa.key",
+);
+
+/// Accept any certificate of the server.
+struct AnyServer;
+
+impl rustls::client::ServerCertVerifier for AnyServer {
+    fn verify_server_cert(
+        &self,
+        _: &rustls::Certificate,
+        _: &[rustls::Certificate],
+        _: &rustls::ServerName,
+        _: &mut dyn Iterator<Item = &[u8]>,
+        _: &[u8],
+        _: std::time::SystemTime,
+    ) -> Result<rustls::client::ServerCertVerified, rustls::Error> {
+        Ok(rustls::client::ServerCertVerified::assertion())
+    }
+}
+
+fn end_entity(path: &str) -> rustls::Certificate {
+    rustls_pemfile::certs(&mut std::io::BufReader::new(
+        std::fs::File::open(path).unwrap(),
+    ))
+    .unwrap()
+    .into_iter()
+    .map(rustls::Certificate)
+    .next()
+    .unwrap()
+}
+
+/// An ECDSA certificate with an RSA alternative, for the root and `second.testserver.com`.
+fn server_config() -> rustls::ServerConfig {
+    let mut config = with_tls();
+    let tls = config.server.tls.as_mut().unwrap();
+    tls.protocol_version = vec![
+        vsmtp_common::ProtocolVersion(rustls::ProtocolVersion::TLSv1_2),
+        vsmtp_common::ProtocolVersion(rustls::ProtocolVersion::TLSv1_3),
+    ];
+    tls.root = Some(
+        FieldServerVirtualTls::from_path(ECDSA.0, ECDSA.1)
+            .unwrap()
+            .with_alternative(RSA.0, RSA.1)
+            .unwrap(),
+    );
+    config.server.r#virtual.insert(
+        "second.testserver.com".parse().unwrap(),
+        FieldServerVirtual {
+            tls: Some(
+                FieldServerVirtualTls::from_path(ECDSA.0, ECDSA.1)
+                    .unwrap()
+                    .with_alternative(SECOND_RSA.0, SECOND_RSA.1)
+                    .unwrap(),
+            ),
+            ..Default::default()
+        },
+    );
+
+    get_rustls_config(
+        config.server.tls.as_ref().unwrap(),
+        &config.server.r#virtual,
+    )
+    .unwrap()
+}
+
+/// Handshake with a client offering `cipher_suites`, returns the certificate presented.
+async fn presented(
+    cipher_suites: &[rustls::SupportedCipherSuite],
+    server_name: &str,
+) -> rustls::Certificate {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let server_addr = listener.local_addr().unwrap();
+    let acceptor = tokio_rustls::TlsAcceptor::from(std::sync::Arc::new(server_config()));
+    let server = tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        acceptor.accept(stream).await.unwrap();
+    });
+
+    let client_config = rustls::ClientConfig::builder()
+        .with_cipher_suites(cipher_suites)
+        .with_safe_default_kx_groups()
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_custom_certificate_verifier(std::sync::Arc::new(AnyServer))
+        .with_no_client_auth();
+    let stream = tokio::net::TcpStream::connect(server_addr).await.unwrap();
+    let stream = tokio_rustls::TlsConnector::from(std::sync::Arc::new(client_config))
+        .connect(rustls::ServerName::try_from(server_name).unwrap(), stream)
+        .await
+        .unwrap();
+    server.await.unwrap();
+
+    stream.get_ref().1.peer_certificates().unwrap()[0].clone()
+}
+
+#[tokio::test]
+async fn ecdsa_capable_client() {
+    assert_eq!(
+        presented(rustls::DEFAULT_CIPHER_SUITES, "testserver.com").await,
+        end_entity(ECDSA.0)
+    );
+    assert_eq!(
+        presented(
+            &[rustls::cipher_suite::TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256],
+            "testserver.com"
+        )
+        .await,
+        end_entity(ECDSA.0)
+    );
+}
+
+#[tokio::test]
+async fn rsa_only_client() {
+    assert_eq!(
+        presented(
+            &[rustls::cipher_suite::TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256],
+            "testserver.com"
+        )
+        .await,
+        end_entity(RSA.0)
+    );
+}
+
+#[tokio::test]
+async fn rsa_only_client_with_sni() {
+    assert_eq!(
+        presented(
+            &[rustls::cipher_suite::TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256],
+            "second.testserver.com"
+        )
+        .await,
+        end_entity(SECOND_RSA.0)
+    );
+    assert_eq!(
+        presented(rustls::DEFAULT_CIPHER_SUITES, "second.testserver.com").await,
+        end_entity(ECDSA.0)
+    );
+}
@@ -0,0 +1,363 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2023 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use crate::config::with_tls;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
+use tokio_rustls::rustls;
+use vsmtp_common::auth::Mechanism;
+use vsmtp_config::{
+    field::{FieldServerSMTPAuth, FieldServerTlsClientAuth, FieldServerVirtualTls},
+    get_rustls_config_client_auth, DnsResolvers,
+};
+use vsmtp_protocol::{AcceptArgs, ConnectionKind};
+use vsmtp_rule_engine::RuleEngine;
+use vsmtp_server::Server;
+
+const RULES: &str = r#"#{
+    rcpt: [
+        rule "relay for the partners" || {
+            if auth::is_authenticated()
+                && auth::credentials().type == "Certificate"
+                && auth::credentials().authid == "O=Partner, CN=mailer.partner.example" {
+                state::next()
+            } else {
+                state::deny()
+            }
+        },
+    ],
+}"#;
+
+const INPUT: &[&str] = &[
+    "HELO client.com\r\n",
+    "MAIL FROM:<john@partner.example>\r\n",
+    "RCPT TO:<jane@elsewhere.example>\r\n",
+    "QUIT\r\n",
+];
+
+const EXTERNAL_RULES: &str = r#"#{
+    authenticate: [
+        rule "identity of the certificate" || {
+            let credentials = auth::credentials();
+            if credentials.type == "External"
+                && credentials.subject == "O=Partner, CN=mailer.partner.example"
+                && (credentials.authzid == "" || credentials.authzid in credentials.alt_names) {
+                state::accept()
+            } else {
+                state::deny()
+            }
+        },
+    ],
+}"#;
+
+/// Accept any certificate of the server.
+struct AnyServer;
+
+impl rustls::client::ServerCertVerifier for AnyServer {
+    fn verify_server_cert(
+        &self,
+        _: &rustls::Certificate,
+        _: &[rustls::Certificate],
+        _: &rustls::ServerName,
+        _: &mut dyn Iterator<Item = &[u8]>,
+        _: &[u8],
+        _: std::time::SystemTime,
+    ) -> Result<rustls::client::ServerCertVerified, rustls::Error> {
+        Ok(rustls::client::ServerCertVerified::assertion())
+    }
+}
+
+fn read_certificates(path: &str) -> Vec<rustls::Certificate> {
+    rustls_pemfile::certs(&mut std::io::BufReader::new(
+        std::fs::File::open(path).unwrap(),
+    ))
+    .unwrap()
+    .into_iter()
+    .map(rustls::Certificate)
+    .collect()
+}
+
+fn read_private_key(path: &str) -> rustls::PrivateKey {
+    rustls_pemfile::pkcs8_private_keys(&mut std::io::BufReader::new(
+        std::fs::File::open(path).unwrap(),
+    ))
+    .unwrap()
+    .into_iter()
+    .map(rustls::PrivateKey)
+    .next()
+    .unwrap()
+}
+
+fn config() -> vsmtp_config::Config {
+    let mut config = with_tls();
+    let tls = config.server.tls.as_mut().unwrap();
+    tls.root = Some(
+        FieldServerVirtualTls::from_path(
+            "src/template/certs/certificate.crt",
+            "src/template/certs/private_key.rsa.key",
+        )
+        .unwrap(),
+    );
+    tls.client_auth =
+        Some(FieldServerTlsClientAuth::from_path("src/template/certs/client/ca.crt").unwrap());
+    config
+}
+
+/// The configuration offering the `EXTERNAL` mechanism.
+fn external_config() -> vsmtp_config::Config {
+    let mut config = config();
+    config.server.esmtp.auth = Some(FieldServerSMTPAuth {
+        mechanisms: vec![Mechanism::Plain, Mechanism::External],
+        ..Default::default()
+    });
+    config
+}
+
+/// Serve a tunneled connection on a listener authenticating the clients by their
+/// certificate, the client presenting `certificate` (with its private key).
+///
+/// Returns the replies received, or the error of the TLS handshake.
+async fn run(certificate: Option<(&str, &str)>) -> std::io::Result<Vec<String>> {
+    run_with(certificate, config(), RULES, INPUT).await
+}
+
+/// Same as [`run`], with the configuration, the rules and the commands of the client.
+async fn run_with(
+    certificate: Option<(&str, &str)>,
+    config: vsmtp_config::Config,
+    rules: &'static str,
+    input: &[&str],
+) -> std::io::Result<Vec<String>> {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let server_addr = listener.local_addr().unwrap();
+
+    let config = std::sync::Arc::new(config);
+    let server = tokio::spawn(async move {
+        let (stream, client_addr) = listener.accept().await.unwrap();
+
+        let queue_manager = <vqueue::temp::QueueManager as vqueue::GenericQueueManager>::init(
+            config.clone(),
+            vec![],
+        )
+        .unwrap();
+        let resolvers = std::sync::Arc::new(DnsResolvers::from_config(&config).unwrap());
+        let rule_engine = std::sync::Arc::new(
+            RuleEngine::with_hierarchy(
+                |builder| Ok(builder.add_root_filter_rules(rules)?.build()),
+                config.clone(),
+                resolvers,
+                queue_manager.clone(),
+            )
+            .unwrap(),
+        );
+        let (emitter, _working, _delivery) = vsmtp_server::scheduler::init(1, 1);
+        let tls_config = get_rustls_config_client_auth(
+            config.server.tls.as_ref().unwrap(),
+            &config.server.r#virtual,
+        )
+        .unwrap()
+        .map(std::sync::Arc::new);
+
+        Server::serve(
+            AcceptArgs::new(
+                client_addr,
+                server_addr,
+                vsmtp_common::clock::now(),
+                uuid::Uuid::new_v4(),
+                ConnectionKind::Tunneled,
+            ),
+            stream,
+            tls_config,
+            config,
+            rule_engine,
+            queue_manager,
+            emitter,
+            None,
+            std::sync::Arc::default(),
+            None,
+            None,
+        )
+        .await
+    });
+
+    let client_config = rustls::ClientConfig::builder()
+        .with_safe_defaults()
+        .with_custom_certificate_verifier(std::sync::Arc::new(AnyServer));
+    let client_config = match certificate {
+        Some((certificate, private_key)) => client_config
+            .with_single_cert(
+                read_certificates(certificate),
+                read_private_key(private_key),
+            )
+            .unwrap(),
+        None => client_config.with_no_client_auth(),
+    };
+
+    let stream = tokio::net::TcpStream::connect(server_addr).await?;
+    let stream = tokio_rustls::TlsConnector::from(std::sync::Arc::new(client_config))
+        .connect(
+            rustls::ServerName::IpAddress("127.0.0.1".parse().unwrap()),
+            stream,
+        )
+        .await?;
+    let mut stream = tokio::io::BufReader::new(stream);
+
+    let mut output = vec![];
+    let mut input = input.iter();
+    loop {
+        let mut line = String::new();
+        if stream.read_line(&mut line).await? == 0 {
+            break;
+        }
+        let continued = line.chars().nth(3) == Some('-');
+        output.push(line);
+        if continued {
+            continue;
+        }
+        match input.next() {
+            // NOTE: the connection is closed by the server after a denial.
+            Some(command) if stream.get_mut().write_all(command.as_bytes()).await.is_ok() => {}
+            _ => break,
+        }
+    }
+
+    server.await.unwrap().unwrap();
+    Ok(output)
+}
+
+#[tokio::test]
+async fn trusted_certificate() {
+    pretty_assertions::assert_eq!(
+        run(Some((
+            "src/template/certs/client/client.crt",
+            "src/template/certs/client/client.key",
+        )))
+        .await
+        .unwrap(),
+        [
+            "220 testserver.com Service ready\r\n",
+            "250 Ok\r\n",
+            "250 Ok\r\n",
+            "250 Ok\r\n",
+            "221 Service closing transmission channel\r\n",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn no_certificate() {
+    pretty_assertions::assert_eq!(
+        run(None).await.unwrap(),
+        [
+            "220 testserver.com Service ready\r\n",
+            "250 Ok\r\n",
+            "250 Ok\r\n",
+            "554 permanent problems with the remote server\r\n",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn untrusted_certificate() {
+    // a self-signed certificate, not issued by the trusted authority.
+    let output = run(Some((
+        "src/template/certs/certificate.crt",
+        "src/template/certs/private_key.pkcs8.key",
+    )))
+    .await;
+
+    assert!(output.map_or(true, |output| output.is_empty()));
+}
+
+const CLIENT_CERTIFICATE: (&str, &str) = (
+    "src/template/certs/client/client.crt",
+    "src/template/certs/client/client.key",
+);
+
+/// Authenticate with the `EXTERNAL` mechanism, requesting `initial_response` (base64).
+async fn run_external(certificate: Option<(&str, &str)>, initial_response: &str) -> Vec<String> {
+    run_with(
+        certificate,
+        external_config(),
+        EXTERNAL_RULES,
+        &[
+            "EHLO client.com\r\n",
+            &format!("AUTH EXTERNAL {initial_response}\r\n"),
+            "QUIT\r\n",
+        ],
+    )
+    .await
+    .unwrap()
+}
+
+#[tokio::test]
+async fn external_offered_with_certificate() {
+    let with_certificate = run_external(Some(CLIENT_CERTIFICATE), "=").await;
+    assert!(with_certificate.contains(&"250-AUTH PLAIN EXTERNAL\r\n".to_owned()));
+
+    let without_certificate = run_external(None, "=").await;
+    assert!(without_certificate.contains(&"250-AUTH PLAIN\r\n".to_owned()));
+}
+
+#[tokio::test]
+async fn external_as_subject() {
+    let output = run_external(Some(CLIENT_CERTIFICATE), "=").await;
+    pretty_assertions::assert_eq!(
+        output[output.len() - 2..],
+        [
+            "235 2.7.0 Authentication succeeded\r\n",
+            "221 Service closing transmission channel\r\n",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn external_as_alt_name() {
+    // base64 of "postmaster@partner.example"
+    let output = run_external(
+        Some(CLIENT_CERTIFICATE),
+        "cG9zdG1hc3RlckBwYXJ0bmVyLmV4YW1wbGU=",
+    )
+    .await;
+    pretty_assertions::assert_eq!(
+        output[output.len() - 2..],
+        [
+            "235 2.7.0 Authentication succeeded\r\n",
+            "221 Service closing transmission channel\r\n",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn external_as_someone_else() {
+    // base64 of "john@partner.example"
+    let output = run_external(Some(CLIENT_CERTIFICATE), "am9obkBwYXJ0bmVyLmV4YW1wbGU=").await;
+    pretty_assertions::assert_eq!(
+        output.last().unwrap(),
+        "535 5.7.8 Authentication credentials invalid\r\n"
+    );
+}
+
+#[tokio::test]
+async fn external_without_certificate() {
+    let output = run_external(None, "=").await;
+    pretty_assertions::assert_eq!(
+        output[output.len() - 2..],
+        [
+            "504 5.5.4 Mechanism not available without a client certificate\r\n",
+            "221 Service closing transmission channel\r\n",
+        ]
+    );
+}
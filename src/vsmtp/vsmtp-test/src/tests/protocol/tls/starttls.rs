@@ -70,6 +70,9 @@ run_test! {
               ),
               dns: None,
               dkim: None,
+              source_ips: vec![],
+              hello_name: None,
+              catch_all: None,
           },
       );
       config
@@ -119,6 +122,9 @@ async fn domain_not_defined() {
                 ),
                 dns: None,
                 dkim: None,
+                source_ips: vec![],
+                hello_name: None,
+                catch_all: None,
             },
         );
         config
@@ -155,6 +161,9 @@ async fn domain_not_defined_with_default() {
                 ),
                 dns: None,
                 dkim: None,
+                source_ips: vec![],
+                hello_name: None,
+                catch_all: None,
             },
         );
         config
@@ -207,6 +216,9 @@ run_test! {
               ),
               dns: None,
               dkim: None,
+              source_ips: vec![],
+              hello_name: None,
+              catch_all: None,
           },
       );
       config
@@ -55,6 +55,9 @@ run_test! {
               ),
               dns: None,
               dkim: None,
+              source_ips: vec![],
+              hello_name: None,
+              catch_all: None,
           },
       );
       config
@@ -100,6 +103,9 @@ run_test! {
               ),
               dns: None,
               dkim: None,
+              source_ips: vec![],
+              hello_name: None,
+              catch_all: None,
           },
       );
       config
@@ -141,6 +147,9 @@ run_test! {
                 ),
                 dns: None,
                 dkim: None,
+                source_ips: vec![],
+                hello_name: None,
+                catch_all: None,
             },
         );
         config
@@ -91,6 +91,9 @@ run_test! {
                 ),
                 dns: None,
                 dkim: None,
+                source_ips: vec![],
+                hello_name: None,
+                catch_all: None,
             },
         );
         config
@@ -0,0 +1,157 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2022 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use crate::config;
+use crate::run_test;
+use vsmtp_config::field::{
+    AccessListAction, FieldAccessList, FieldServerAccessLists, FieldServerTrustedUpstreams,
+};
+
+fn rules(ip: &str, helo: &str) -> String {
+    format!(
+        r#"
+#{{
+    preq: [
+        rule "origin" || {{
+            if ctx::originating_ip() == "{ip}" && ctx::originating_helo() == "{helo}" {{
+                state::next()
+            }} else {{
+                state::deny()
+            }}
+        }},
+    ],
+}}
+"#
+    )
+}
+
+fn with_trusted_upstreams(access_lists: bool) -> vsmtp_config::Config {
+    let mut config = config::local_test();
+    config.server.trusted_upstreams = Some(FieldServerTrustedUpstreams {
+        networks: vec!["127.0.0.0/8".parse().unwrap()],
+        access_lists,
+    });
+    config
+}
+
+run_test! {
+    fn originating_host_of_trusted_relay,
+    input = [
+        "HELO foo\r\n",
+        "MAIL FROM:<john@partner.org>\r\n",
+        "RCPT TO:<jane@testserver.com>\r\n",
+        "DATA\r\n",
+        concat!(
+            "Received: from mail.partner.org (mail.partner.org [192.0.2.1])\r\n",
+            "\tby border.testserver.com with ESMTPS id 1; Mon, 2 Jan 2023 09:59:59 +0000\r\n",
+            "Received: from forged ([10.0.0.1]) by mail.partner.org; Mon, 2 Jan 2023 09:59:58 +0000\r\n",
+            "From: john@partner.org\r\n",
+            "\r\n",
+            "Hello world!\r\n",
+            ".\r\n",
+        ),
+        "QUIT\r\n",
+    ],
+    expected = [
+        "220 testserver.com Service ready\r\n",
+        "250 Ok\r\n",
+        "250 Ok\r\n",
+        "250 Ok\r\n",
+        "354 Start mail input; end with <CRLF>.<CRLF>\r\n",
+        "250 Ok\r\n",
+        "221 Service closing transmission channel\r\n",
+    ],
+    config = with_trusted_upstreams(false),
+    hierarchy_builder = |builder| {
+        Ok(builder.add_root_filter_rules(&rules("192.0.2.1", "mail.partner.org"))?.build())
+    },
+}
+
+run_test! {
+    fn originating_host_of_direct_connection,
+    input = [
+        "HELO foo\r\n",
+        "MAIL FROM:<john@partner.org>\r\n",
+        "RCPT TO:<jane@testserver.com>\r\n",
+        "DATA\r\n",
+        concat!(
+            "Received: from mail.partner.org (mail.partner.org [192.0.2.1]) by border.testserver.com; Mon, 2 Jan 2023 09:59:59 +0000\r\n",
+            "From: john@partner.org\r\n",
+            "\r\n",
+            "Hello world!\r\n",
+            ".\r\n",
+        ),
+        "QUIT\r\n",
+    ],
+    expected = [
+        "220 testserver.com Service ready\r\n",
+        "250 Ok\r\n",
+        "250 Ok\r\n",
+        "250 Ok\r\n",
+        "354 Start mail input; end with <CRLF>.<CRLF>\r\n",
+        "250 Ok\r\n",
+        "221 Service closing transmission channel\r\n",
+    ],
+    config = config::local_test(),
+    hierarchy_builder = |builder| {
+        Ok(builder.add_root_filter_rules(&rules("127.0.0.1", "foo"))?.build())
+    },
+}
+
+run_test! {
+    fn originating_host_blocked,
+    input = [
+        "HELO foo\r\n",
+        "MAIL FROM:<john@partner.org>\r\n",
+        "RCPT TO:<jane@testserver.com>\r\n",
+        "DATA\r\n",
+        concat!(
+            "Received: from mail.partner.org (mail.partner.org [192.0.2.1]) by border.testserver.com; Mon, 2 Jan 2023 09:59:59 +0000\r\n",
+            "From: john@partner.org\r\n",
+            "\r\n",
+            "Hello world!\r\n",
+            ".\r\n",
+        ),
+    ],
+    expected = [
+        "220 testserver.com Service ready\r\n",
+        "250 Ok\r\n",
+        "250 Ok\r\n",
+        "250 Ok\r\n",
+        "354 Start mail input; end with <CRLF>.<CRLF>\r\n",
+        "554 5.7.1 Client host rejected: access denied\r\n",
+    ],
+    config = {
+        let path = std::env::temp_dir().join(format!("vsmtp-access-list-{}", uuid::Uuid::new_v4()));
+        std::fs::write(&path, "192.0.2.0/24\n").unwrap();
+
+        let mut config = with_trusted_upstreams(true);
+        config.server.access_lists = Some(FieldServerAccessLists {
+            blocked_ips: Some(FieldAccessList {
+                path,
+                action: AccessListAction::Deny,
+                reply: None,
+            }),
+            blocked_senders: None,
+            allowed_ips: None,
+            reload_period: std::time::Duration::from_secs(10),
+        });
+        config
+    },
+    hierarchy_builder = |builder| {
+        Ok(builder.add_root_filter_rules(&rules("192.0.2.1", "mail.partner.org"))?.build())
+    },
+}
@@ -94,7 +94,8 @@ macro_rules! test_lang {
                                     s
                                 })
                                 .collect::<Vec<_>>()
-                        )
+                        ),
+                        parse_truncated: false,
                     }
                 );
             },
@@ -205,7 +206,7 @@ run_test! {
         "250-PIPELINING\r\n",
         "250-DSN\r\n",
         "250 SIZE 20000000\r\n",
-        "550 mailbox unavailable\r\n",
+        "553 5.6.7 SMTPUTF8 required\r\n",
         "221 Service closing transmission channel\r\n",
     ],
 }
@@ -228,7 +229,7 @@ run_test! {
         "250-DSN\r\n",
         "250 SIZE 20000000\r\n",
         "250 Ok\r\n",
-        "553 mailbox name not allowed\r\n",
+        "553 5.6.7 SMTPUTF8 required\r\n",
         "221 Service closing transmission channel\r\n",
     ],
 }
@@ -256,6 +257,60 @@ run_test! {
     ],
 }
 
+run_test! {
+    fn mail_smtputf8_not_supported,
+    input = [
+        "EHLO foobar\r\n",
+        "MAIL FROM:<john.doe@mail.com> SMTPUTF8\r\n",
+        "QUIT\r\n",
+    ],
+    expected = [
+        "220 testserver.com Service ready\r\n",
+        "250-testserver.com\r\n",
+        "250-8BITMIME\r\n",
+        "250-STARTTLS\r\n",
+        "250-PIPELINING\r\n",
+        "250-DSN\r\n",
+        "250 SIZE 20000000\r\n",
+        "555 5.5.4 SMTPUTF8 not supported\r\n",
+        "221 Service closing transmission channel\r\n",
+    ],
+    config = {
+        let mut config = crate::config::local_test();
+        config.server.esmtp.smtputf8 = false;
+        config
+    },
+}
+
+run_test! {
+    fn refused_mail_smtputf8_ignored,
+    input = [
+        "EHLO foobar\r\n",
+        "MAIL FROM:<john.doe@mail.com>\r\n",
+        "MAIL FROM:<john.doe@mail.com> SMTPUTF8\r\n",
+        "RCPT TO:<用户@例子.广告>\r\n",
+        "QUIT\r\n",
+    ],
+    expected = [
+        "220 testserver.com Service ready\r\n",
+        "250-testserver.com\r\n",
+        "250-8BITMIME\r\n",
+        "250-STARTTLS\r\n",
+        "250-PIPELINING\r\n",
+        "250-DSN\r\n",
+        "250 SIZE 20000000\r\n",
+        "250 Ok\r\n",
+        "555 5.5.4 SMTPUTF8 not supported\r\n",
+        "553 5.6.7 SMTPUTF8 required\r\n",
+        "221 Service closing transmission channel\r\n",
+    ],
+    config = {
+        let mut config = crate::config::local_test();
+        config.server.esmtp.smtputf8 = false;
+        config
+    },
+}
+
 run_test! {
     fn data_with_utf8_headers,
     input = [
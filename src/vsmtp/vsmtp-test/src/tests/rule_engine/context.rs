@@ -113,8 +113,13 @@ fn snipped_info(
             "message_uuid",
             &["mail", "rcpt", "preq"],
         ),
+        "ctx::declared_size()" => (
+            "mail".parse().unwrap(),
+            "declared_size",
+            &["mail", "rcpt", "preq"],
+        ),
 
-        "ctx::rcpt()" | "ctx::rcpt_list()" => {
+        "ctx::rcpt()" | "ctx::rcpt_list()" | "ctx::rcpt_count()" => {
             ("rcpt".parse().unwrap(), "forward_paths", &["rcpt", "preq"])
         }
 
@@ -219,9 +224,11 @@ fn expected(
 #[case("ctx::mail_from()")]
 #[case("ctx::mail_timestamp()")]
 #[case("ctx::message_id()")]
+#[case("ctx::declared_size()")]
 // after rcpt
 #[case("ctx::rcpt()")]
 #[case("ctx::rcpt_list()")]
+#[case("ctx::rcpt_count()")]
 fn each(
     #[values(
         ExecutionStage::Connect,
@@ -48,7 +48,7 @@ run_test! {
         "mail from: <any@example.com>\r\n",
     ],
     expected = [
-        "100 welcome to the test\r\n",
+        "220 testserver.com Service ready\r\n",
         "250 Ok\r\n",
         "554 permanent problems with the remote server\r\n",
     ],
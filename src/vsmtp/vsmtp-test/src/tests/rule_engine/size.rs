@@ -0,0 +1,133 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2023 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ *  This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use crate::run_test;
+use vsmtp_common::ContextFinished;
+use vsmtp_mail_parser::MessageBody;
+
+/// Delegate to the system allocator, counting the allocations of the current thread.
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: core::cell::Cell<usize> = const { core::cell::Cell::new(0) };
+}
+
+#[allow(unsafe_code)]
+// SAFETY: the system allocator is used for every operation
+unsafe impl std::alloc::GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: std::alloc::Layout) -> *mut u8 {
+        ALLOCATIONS.with(|count| count.set(count.get() + 1));
+        // SAFETY: same contract as `GlobalAlloc::alloc`
+        unsafe { std::alloc::System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: std::alloc::Layout) {
+        // SAFETY: same contract as `GlobalAlloc::dealloc`
+        unsafe { std::alloc::System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+fn count_allocations<T>(f: impl FnOnce() -> T) -> (T, usize) {
+    let before = ALLOCATIONS.with(core::cell::Cell::get);
+    let out = f();
+    (out, ALLOCATIONS.with(core::cell::Cell::get) - before)
+}
+
+const MAIL: &str = concat!(
+    "From: john doe <john@doe.com>\r\n",
+    "To: green@foo.net\r\n",
+    "Subject: test email\r\n",
+    "\r\n",
+    "This is a raw email.\r\n",
+);
+
+#[test]
+fn sizes_do_not_allocate() {
+    let message = MessageBody::try_from(&*MAIL.repeat(1000)).unwrap();
+
+    let (size, count) = count_allocations(|| message.size());
+    assert_eq!(size, MAIL.len() * 1000);
+    assert_eq!(count, 0);
+
+    let (header_size, count) = count_allocations(|| message.header_size());
+    assert_eq!(header_size, MAIL.find("\r\n\r\n").unwrap() + "\r\n".len());
+    assert_eq!(count, 0);
+}
+
+const RULES: &str = r#"
+#{
+    mail: [
+        rule "declared size" || if ctx::declared_size() == 1000 { state::next() } else { state::deny() },
+    ],
+    preq: [
+        rule "sizes" || {
+            if msg::header_size() > 0
+                && msg::size() > msg::header_size()
+                && ctx::declared_size() == 1000
+                && ctx::rcpt_count() == 2 {
+                state::accept()
+            } else {
+                state::deny()
+            }
+        },
+    ],
+}
+"#;
+
+run_test! {
+    fn getters_without_copy,
+    input = [
+        "EHLO foo\r\n",
+        "MAIL FROM:<john@server.com> SIZE=1000\r\n",
+        "RCPT TO:<doe@server.com>\r\n",
+        "RCPT TO:<green@server.com>\r\n",
+        "DATA\r\n",
+        &format!("{MAIL}.\r\n"),
+        "QUIT\r\n",
+    ],
+    expected = [
+        "220 testserver.com Service ready\r\n",
+        "250-testserver.com\r\n",
+        "250-8BITMIME\r\n",
+        "250-SMTPUTF8\r\n",
+        "250-STARTTLS\r\n",
+        "250-PIPELINING\r\n",
+        "250-DSN\r\n",
+        "250 SIZE 20000000\r\n",
+        "250 Ok\r\n",
+        "250 Ok\r\n",
+        "250 Ok\r\n",
+        "354 Start mail input; end with <CRLF>.<CRLF>\r\n",
+        "250 Ok\r\n",
+        "221 Service closing transmission channel\r\n",
+    ],
+    mail_handler = |ctx: ContextFinished, msg: MessageBody| {
+        assert_eq!(ctx.mail_from.declared_size, Some(1000));
+        assert_eq!(msg.size(), msg.inner().to_string().len());
+    },
+    hierarchy_builder = |builder| {
+        Ok(builder.add_root_filter_rules(RULES)?
+            .add_domain_rules("server.com".parse().unwrap())
+                .with_incoming(RULES)?
+                .with_outgoing(RULES)?
+                .with_internal(RULES)?
+                .build()
+            .build())
+    },
+}
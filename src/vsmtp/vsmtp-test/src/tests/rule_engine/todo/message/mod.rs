@@ -104,6 +104,7 @@ fn test_email_context_mail() {
                     "other.rcpt@toremove.org, other.rcpt@torewrite.net".to_string(),
                 )]),
                 body: BodyType::Undefined,
+                parse_truncated: false,
             }
         );
 
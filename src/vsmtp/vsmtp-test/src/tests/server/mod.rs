@@ -20,6 +20,9 @@ use vsmtp_config::DnsResolvers;
 use vsmtp_rule_engine::RuleEngine;
 use vsmtp_server::{socket_bind_anyhow, Server};
 
+mod probes;
+mod scenario;
+
 macro_rules! listen_with {
     ($addr:expr, $addr_submission:expr, $addr_submissions:expr, $timeout:expr, $client_count_max:expr) => {{
         let config = std::sync::Arc::new({
@@ -144,78 +147,3 @@ async fn one_client_max_ok() {
 
     assert_eq!(client.unwrap().unwrap().message().next().unwrap(), "Ok");
 }
-
-// FIXME: randomly fail the CI
-/*
-#[tokio::test(flavor = "multi_thread", worker_threads = 8)]
-async fn one_client_max_err() {
-    let server = tokio::spawn(async move {
-        listen_with![
-            vec!["127.0.0.1:10006".parse().unwrap()],
-            vec!["127.0.0.1:10568".parse().unwrap()],
-            vec!["127.0.0.1:10446".parse().unwrap()],
-            1000,
-            1
-        ];
-    });
-
-    let now = tokio::time::Instant::now();
-    let until = now
-        .checked_add(std::time::Duration::from_millis(100))
-        .unwrap();
-
-    let client = tokio::spawn(async move {
-        tokio::time::sleep_until(until).await;
-        let mail = lettre::Message::builder()
-            .from("NoBody <nobody@domain.tld>".parse().unwrap())
-            .reply_to("Yuin <yuin@domain.tld>".parse().unwrap())
-            .to("Hei <hei@domain.tld>".parse().unwrap())
-            .subject("Happy new year")
-            .body(String::from("Be happy!"))
-            .unwrap();
-
-        let sender = lettre::AsyncSmtpTransport::<lettre::Tokio1Executor>::builder_dangerous(
-            "127.0.0.1",
-        )
-        .port(10006)
-        .build();
-
-        lettre::AsyncTransport::send(&sender, mail).await
-    });
-
-    let client2 = tokio::spawn(async move {
-        tokio::time::sleep_until(until).await;
-        let mail = lettre::Message::builder()
-            .from("NoBody <nobody2@domain.tld>".parse().unwrap())
-            .reply_to("Yuin <yuin@domain.tld>".parse().unwrap())
-            .to("Hei <hei@domain.tld>".parse().unwrap())
-            .subject("Happy new year")
-            .body(String::from("Be happy!"))
-            .unwrap();
-
-        let sender = lettre::AsyncSmtpTransport::<lettre::Tokio1Executor>::builder_dangerous(
-            "127.0.0.1",
-        )
-        .port(10006)
-        .build();
-
-        lettre::AsyncTransport::send(&sender, mail).await
-    });
-
-    let (server, client, client2) = tokio::join!(server, client, client2);
-    server.unwrap();
-
-    let client1 = format!("{}", client.unwrap().unwrap_err());
-    let client2 = format!("{}", client2.unwrap().unwrap_err());
-
-    // one of the client has been denied on connection, but we cant know which one
-    let ok1_failed2 = client1
-        == "permanent error (554): permanent problems with the remote server"
-        && client2 == "permanent error (554): Cannot process connection, closing";
-    let ok2_failed1 = client2
-        == "permanent error (554): permanent problems with the remote server"
-        && client1 == "permanent error (554): Cannot process connection, closing";
-
-    assert!(ok1_failed2 || ok2_failed1);
-}
-*/
@@ -0,0 +1,111 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2022 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use crate::config;
+use vsmtp_config::{field::FieldServerHealth, DnsResolvers};
+use vsmtp_rule_engine::{ExecutionStage, RuleEngine};
+use vsmtp_server::{socket_bind_anyhow, Health, Server};
+
+/// Connect from `client_ip`, send `QUIT` and read the replies until the server closes the connection.
+async fn quit_from(client_ip: &str, server_addr: std::net::SocketAddr) -> String {
+    let socket = tokio::net::TcpSocket::new_v4().unwrap();
+    socket
+        .bind(format!("{client_ip}:0").parse().unwrap())
+        .unwrap();
+    let mut stream = socket.connect(server_addr).await.unwrap();
+
+    tokio::io::AsyncWriteExt::write_all(&mut stream, b"QUIT\r\n")
+        .await
+        .unwrap();
+
+    let mut replies = String::new();
+    tokio::io::AsyncReadExt::read_to_string(&mut stream, &mut replies)
+        .await
+        .unwrap();
+    replies
+}
+
+#[tokio::test]
+async fn probe_skips_the_rules() {
+    let config = std::sync::Arc::new({
+        let mut config = config::local_test();
+        config.server.health = Some(FieldServerHealth {
+            addr: "127.0.0.1:0".parse().unwrap(),
+            probes: vec!["127.0.0.2".parse().unwrap()],
+            spool_free_space_min: 0,
+        });
+        config
+    });
+
+    let queue_manager =
+        <vqueue::temp::QueueManager as vqueue::GenericQueueManager>::init(config.clone(), vec![])
+            .unwrap();
+    let (emitter, _working, _delivery) = vsmtp_server::scheduler::init(
+        config.server.queues.working.channel_size,
+        config.server.queues.delivery.channel_size,
+    );
+    let resolvers = std::sync::Arc::new(DnsResolvers::from_config(&config).unwrap());
+
+    let rule_engine = std::sync::Arc::new(
+        RuleEngine::with_hierarchy(
+            |builder| {
+                Ok(builder
+                    .add_root_filter_rules(r#"#{ connect: [ action "count" || {} ] }"#)?
+                    .build())
+            },
+            config.clone(),
+            resolvers,
+            queue_manager.clone(),
+        )
+        .unwrap(),
+    );
+    let statistics = rule_engine.statistics();
+    let health = std::sync::Arc::new(Health::new(std::env::temp_dir()));
+
+    let listener = socket_bind_anyhow("127.0.0.1:0").unwrap();
+    let server_addr = listener.local_addr().unwrap();
+    let server = Server::new(config.clone(), rule_engine, queue_manager, emitter)
+        .unwrap()
+        .with_health(health.clone());
+    let server = tokio::spawn(server.listen((vec![listener], vec![], vec![])));
+
+    assert_eq!(
+        quit_from("127.0.0.2", server_addr).await,
+        "220 testserver.com Service ready\r\n221 Service closing transmission channel\r\n"
+    );
+    assert_eq!(health.probe_count(), 1);
+    assert_eq!(
+        statistics
+            .get(ExecutionStage::Connect, "count")
+            .unwrap()
+            .executions,
+        0
+    );
+
+    assert!(quit_from("127.0.0.1", server_addr)
+        .await
+        .ends_with("221 Service closing transmission channel\r\n"));
+    assert_eq!(health.probe_count(), 1);
+    assert_eq!(
+        statistics
+            .get(ExecutionStage::Connect, "count")
+            .unwrap()
+            .executions,
+        1
+    );
+
+    server.abort();
+}
@@ -0,0 +1,180 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2023 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+
+use crate::{
+    clock::TestClock,
+    config::local_test,
+    run_scenario,
+    scenario::{Scenario, Session, Signal},
+};
+use time::ext::NumericalDuration;
+use vqueue::{GenericQueueManager, QueueID};
+
+fn send_mail(from: &str) -> Session {
+    Session::new(
+        [
+            "HELO foo\r\n".to_string(),
+            format!("MAIL FROM:<{from}>\r\n"),
+            "RCPT TO:<green@foo.net>\r\n".to_string(),
+            "DATA\r\n".to_string(),
+            format!("From: <{from}>\r\nSubject: test email\r\n\r\nThis is a raw email.\r\n.\r\n"),
+            "QUIT\r\n".to_string(),
+        ],
+        [
+            "220 testserver.com Service ready\r\n",
+            "250 Ok\r\n",
+            "250 Ok\r\n",
+            "250 Ok\r\n",
+            "354 Start mail input; end with <CRLF>.<CRLF>\r\n",
+            "250 Ok\r\n",
+            "221 Service closing transmission channel\r\n",
+        ],
+    )
+}
+
+#[test_log::test(tokio::test)]
+async fn one_client_max_err() {
+    let (connected, refused) = (Signal::new(), Signal::new());
+
+    let outcome = run_scenario! {
+        scenario = Scenario::new()
+            .concurrently([
+                Session::new(
+                    ["HELO foo\r\n", "QUIT\r\n"],
+                    [
+                        "220 testserver.com Service ready\r\n",
+                        "250 Ok\r\n",
+                        "221 Service closing transmission channel\r\n",
+                    ],
+                )
+                .notify(connected.clone())
+                .hold_until(refused.clone()),
+                Session::new(
+                    Vec::<String>::new(),
+                    ["554 Cannot process connection, closing\r\n"],
+                )
+                .after(connected)
+                .notify(refused),
+            ])
+            // the slot is released once the first client is gone
+            .then(send_mail("john@doe.com")),
+        config = {
+            let mut config = local_test();
+            config.server.client_count_max = 1;
+            config
+        },
+    };
+
+    assert_eq!(outcome.working.len(), 1);
+}
+
+#[test_log::test(tokio::test)]
+async fn sequential_sessions() {
+    let outcome = run_scenario! {
+        scenario = Scenario::new()
+            .then(send_mail("john@doe.com"))
+            .then(send_mail("jane@doe.com")),
+    };
+
+    assert!(outcome.delivery.is_empty());
+
+    let mut reverse_paths = vec![];
+    for message in &outcome.working {
+        let ctx = outcome
+            .queue_manager
+            .get_ctx(&QueueID::Working, message.as_ref())
+            .await
+            .unwrap();
+        reverse_paths.push(ctx.mail_from.reverse_path.unwrap().to_string());
+    }
+    assert_eq!(reverse_paths, ["john@doe.com", "jane@doe.com"]);
+}
+
+#[test_log::test(tokio::test)]
+async fn concurrent_sessions() {
+    let outcome = run_scenario! {
+        scenario = Scenario::new().concurrently([
+            send_mail("john@doe.com"),
+            send_mail("jane@doe.com"),
+            send_mail("jim@doe.com"),
+        ]),
+    };
+
+    assert_eq!(outcome.outputs.len(), 3);
+
+    let mut uuids = outcome
+        .working
+        .iter()
+        .map(|message| *message.as_ref())
+        .collect::<Vec<_>>();
+    uuids.sort();
+    uuids.dedup();
+    assert_eq!(uuids.len(), 3);
+}
+
+#[test_log::test(tokio::test)]
+async fn clock_advanced_between_sessions() {
+    let clock = TestClock::start();
+    let start = clock.now();
+
+    let outcome = run_scenario! {
+        scenario = Scenario::new()
+            .then(send_mail("john@doe.com"))
+            .then(send_mail("john@doe.com").advance_clock(1.days())),
+    };
+    drop(clock);
+
+    let mut timestamps = vec![];
+    for message in &outcome.working {
+        let ctx = outcome
+            .queue_manager
+            .get_ctx(&QueueID::Working, message.as_ref())
+            .await
+            .unwrap();
+        timestamps.push((ctx.connect.connect_timestamp, ctx.mail_from.mail_timestamp));
+    }
+    assert_eq!(
+        timestamps,
+        [(start, start), (start + 1.days(), start + 1.days())]
+    );
+}
+
+#[test_log::test(tokio::test)]
+async fn seeded_identifiers() {
+    let clock = TestClock::start();
+
+    let mut uuids = vec![];
+    for _ in 0..2 {
+        clock.seed_ids(7);
+        let outcome = run_scenario! {
+            scenario = Scenario::new()
+                .then(send_mail("john@doe.com"))
+                .then(send_mail("jane@doe.com")),
+        };
+        uuids.push(
+            outcome
+                .working
+                .iter()
+                .map(|message| *message.as_ref())
+                .collect::<Vec<_>>(),
+        );
+    }
+
+    assert_eq!(uuids[0].len(), 2);
+    assert_ne!(uuids[0][0], uuids[0][1]);
+    assert_eq!(uuids[0], uuids[1]);
+}
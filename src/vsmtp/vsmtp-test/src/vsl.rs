@@ -53,6 +53,7 @@ pub fn run_with_msg_and_config(
         ExecutionStage::RcptTo,
         ExecutionStage::PreQ,
         ExecutionStage::PostQ,
+        ExecutionStage::Disconnect,
     ] {
         let runtime = tokio::runtime::Builder::new_multi_thread()
             .enable_all()
@@ -0,0 +1,379 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2023 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+
+use lettre::transport::smtp::client::{Tls, TlsParameters};
+use vsmtp_common::ContextFinished;
+
+/// Time budget of the waits of the harness (readiness, spool, logs, exit).
+pub const TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+const POLL_PERIOD: std::time::Duration = std::time::Duration::from_millis(50);
+
+/// Name of the server, and of the certificate of `vsmtp-test/src/template/certs`.
+pub const SERVER_NAME: &str = "testserver.com";
+
+lazy_static::lazy_static! {
+    static ref BINARY: std::path::PathBuf = build_binary();
+}
+
+fn workspace_root() -> std::path::PathBuf {
+    std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+        .ancestors()
+        .nth(3)
+        .expect("vsmtp-test is in the workspace")
+        .to_path_buf()
+}
+
+/// Build the `vsmtp` binary of the workspace once for all the scenarios,
+/// unless `VSMTP_E2E_BIN` gives the path of the binary to test.
+fn build_binary() -> std::path::PathBuf {
+    if let Some(binary) = std::env::var_os("VSMTP_E2E_BIN") {
+        return binary.into();
+    }
+
+    let workspace = workspace_root();
+    let status =
+        std::process::Command::new(std::env::var_os("CARGO").unwrap_or_else(|| "cargo".into()))
+            .current_dir(&workspace)
+            .args(["build", "--package", "vsmtp", "--bin", "vsmtp"])
+            .status()
+            .expect("failed to run cargo");
+    assert!(status.success(), "failed to build the `vsmtp` binary");
+
+    std::env::var_os("CARGO_TARGET_DIR")
+        .map_or_else(|| workspace.join("target"), Into::into)
+        .join("debug")
+        .join("vsmtp")
+}
+
+/// Reserve a port on the loopback, released for the server to bind it.
+fn free_addr() -> std::net::SocketAddr {
+    std::net::TcpListener::bind("127.0.0.1:0")
+        .and_then(|listener| listener.local_addr())
+        .expect("a port is available on the loopback")
+}
+
+/// Poll `f` until it returns [`Some`], or panic with `what` once [`TIMEOUT`] is elapsed.
+pub fn wait_for<T>(what: &str, mut f: impl FnMut() -> Option<T>) -> T {
+    let start = std::time::Instant::now();
+    loop {
+        if let Some(out) = f() {
+            return out;
+        }
+        assert!(start.elapsed() < TIMEOUT, "timed out waiting for {what}");
+        std::thread::sleep(POLL_PERIOD);
+    }
+}
+
+/// The configuration, rules and spool of a `vsmtp` process, written in a temporary
+/// directory by [`Harness::start`].
+///
+/// The server listens on the loopback, on ports chosen at start, and logs to a file
+/// read by [`Instance::logs`].
+#[derive(Debug)]
+pub struct Harness {
+    root: std::path::PathBuf,
+    config: Vec<String>,
+    filter: String,
+    tls: bool,
+}
+
+impl Default for Harness {
+    fn default() -> Self {
+        Self {
+            root: std::env::temp_dir()
+                .join("vsmtp-e2e")
+                .join(uuid::Uuid::new_v4().to_string()),
+            config: vec![],
+            filter: "#{}".to_owned(),
+            tls: false,
+        }
+    }
+}
+
+impl Harness {
+    /// A server without rules nor TLS.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a statement to the `on_config` function, after the ones of the harness.
+    /// (for instance `config.server.smtp.rcpt_count_max = 2;`)
+    #[must_use]
+    pub fn config(mut self, statement: impl Into<String>) -> Self {
+        self.config.push(statement.into());
+        self
+    }
+
+    /// Set the rules of the root filter.
+    #[must_use]
+    pub fn filter(mut self, rules: impl Into<String>) -> Self {
+        self.filter = rules.into();
+        self
+    }
+
+    /// Serve TLS with the certificate of `vsmtp-test/src/template/certs`, named [`SERVER_NAME`].
+    #[must_use]
+    pub const fn with_tls(mut self) -> Self {
+        self.tls = true;
+        self
+    }
+
+    fn write(&self, path: &str, content: &str) -> std::path::PathBuf {
+        let path = self.root.join(path);
+        std::fs::create_dir_all(path.parent().expect("path has a parent"))
+            .and_then(|()| std::fs::write(&path, content))
+            .unwrap_or_else(|error| panic!("failed to write `{}`: {error}", path.display()));
+        path
+    }
+
+    /// Write the configuration, launch the binary, and wait for the server to be ready.
+    ///
+    /// # Panics
+    ///
+    /// * the process exited, or is not ready within [`TIMEOUT`]
+    #[must_use]
+    pub fn start(self) -> Instance {
+        let (addr, submission, health) = (free_addr(), free_addr(), free_addr());
+        let root = self.root.display().to_string();
+
+        std::fs::create_dir_all(self.root.join("domain-available"))
+            .expect("failed to create the domain directory");
+        let filter = self.write("filter.vsl", &self.filter);
+
+        let mut config = vec![
+            format!(r#"config.server.name = "{SERVER_NAME}";"#),
+            r#"config.server.system.user = "root";"#.to_owned(),
+            r#"config.server.system.group = "root";"#.to_owned(),
+            format!(
+                r#"config.server.interfaces = #{{ addr: ["{addr}"], addr_submission: ["{submission}"], addr_submissions: [] }};"#
+            ),
+            format!(r#"config.server.health = #{{ addr: "{health}" }};"#),
+            format!(r#"config.server.queues.dirpath = "{root}/spool";"#),
+            format!(
+                r#"config.server.logs = #{{ filename: "{root}/vsmtp.log", level: ["info"] }};"#
+            ),
+            format!(r#"config.app.dirpath = "{root}/app";"#),
+            format!(r#"config.app.logs.filename = "{root}/app.log";"#),
+            format!(r#"config.app.vsl.domain_dir = "{root}/domain-available";"#),
+            format!(r#"config.app.vsl.filter_path = "{}";"#, filter.display()),
+        ];
+        if self.tls {
+            let certificate = self.write(
+                "certs/certificate.crt",
+                vsmtp_test::get_tls_file::get_certificate(),
+            );
+            let private_key = self.write(
+                "certs/private_key.rsa.key",
+                vsmtp_test::get_tls_file::get_rsa_key(),
+            );
+            config.push(format!(
+                r#"config.server.tls = #{{ protocol_version: ["TLSv1.2", "TLSv1.3"], root: #{{ certificate: "{}", private_key: "{}" }} }};"#,
+                certificate.display(),
+                private_key.display()
+            ));
+        }
+        config.extend(self.config.iter().cloned());
+
+        let config = self.write(
+            "vsmtp.vsl",
+            &format!(
+                "fn on_config(config) {{\n    {}\n    config\n}}\n",
+                config.join("\n    ")
+            ),
+        );
+
+        let output = std::fs::File::create(self.root.join("output.log"))
+            .expect("failed to create the output file");
+        let child = std::process::Command::new(&*BINARY)
+            .arg("--no-daemon")
+            .arg("--config")
+            .arg(&config)
+            .stdout(output.try_clone().expect("failed to clone the output file"))
+            .stderr(output)
+            .spawn()
+            .unwrap_or_else(|error| panic!("failed to launch `{}`: {error}", BINARY.display()));
+
+        let mut instance = Instance {
+            child,
+            root: self.root,
+            addr,
+            submission,
+            health,
+        };
+        instance.wait_ready();
+        instance
+    }
+}
+
+/// A running `vsmtp` process, killed on drop.
+#[derive(Debug)]
+pub struct Instance {
+    child: std::process::Child,
+    root: std::path::PathBuf,
+    /// Address of the SMTP listener.
+    pub addr: std::net::SocketAddr,
+    /// Address of the submission listener.
+    pub submission: std::net::SocketAddr,
+    health: std::net::SocketAddr,
+}
+
+impl Drop for Instance {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+impl Instance {
+    fn wait_ready(&mut self) {
+        use std::io::{Read, Write};
+
+        wait_for("the server to be ready", || {
+            if let Some(status) = self.child.try_wait().expect("failed to poll the process") {
+                panic!(
+                    "the server exited with {status} before being ready:\n{}{}",
+                    std::fs::read_to_string(self.root.join("output.log")).unwrap_or_default(),
+                    self.logs()
+                );
+            }
+
+            let mut stream = std::net::TcpStream::connect(self.health).ok()?;
+            stream.write_all(b"GET /readyz HTTP/1.1\r\n\r\n").ok()?;
+            let mut response = String::new();
+            stream.read_to_string(&mut response).ok()?;
+            response.starts_with("HTTP/1.1 200").then_some(())
+        });
+    }
+
+    /// A client of the SMTP listener, without TLS.
+    #[must_use]
+    pub fn client(&self) -> lettre::transport::smtp::SmtpTransportBuilder {
+        lettre::SmtpTransport::builder_dangerous(self.addr.ip().to_string())
+            .port(self.addr.port())
+            .tls(Tls::None)
+            .timeout(Some(TIMEOUT))
+    }
+
+    /// A client of the submission listener, upgrading the connection with STARTTLS.
+    #[must_use]
+    pub fn submission_client(&self) -> lettre::transport::smtp::SmtpTransportBuilder {
+        lettre::SmtpTransport::builder_dangerous(self.submission.ip().to_string())
+            .port(self.submission.port())
+            .tls(Tls::Required(
+                TlsParameters::builder(SERVER_NAME.to_owned())
+                    .dangerous_accept_invalid_certs(true)
+                    .build()
+                    .expect("valid TLS parameters"),
+            ))
+            .timeout(Some(TIMEOUT))
+    }
+
+    /// Directory of a queue: `working`, `deliver`, `deferred`, `dead` ...
+    /// or `quarantine/<name>`.
+    fn queue_dir(&self, queue: &str) -> std::path::PathBuf {
+        if queue.starts_with("quarantine/") {
+            self.root.join("app").join(queue)
+        } else {
+            self.root.join("spool").join(queue)
+        }
+    }
+
+    /// The contexts of the messages of a queue.
+    ///
+    /// # Panics
+    ///
+    /// * a context cannot be read
+    #[must_use]
+    pub fn spool(&self, queue: &str) -> Vec<ContextFinished> {
+        let Ok(entries) = std::fs::read_dir(self.queue_dir(queue)) else {
+            return vec![];
+        };
+        entries
+            .map(|entry| entry.expect("failed to read the queue").path())
+            .filter(|path| {
+                path.extension()
+                    .map_or(false, |extension| extension == "json")
+            })
+            .map(|path| {
+                serde_json::from_str(
+                    &std::fs::read_to_string(&path).expect("failed to read the context"),
+                )
+                .unwrap_or_else(|error| panic!("invalid context `{}`: {error}", path.display()))
+            })
+            .collect()
+    }
+
+    /// Wait for a queue to hold `count` messages, and return their contexts.
+    #[must_use]
+    pub fn wait_for_spool(&self, queue: &str, count: usize) -> Vec<ContextFinished> {
+        wait_for(&format!("{count} message(s) in `{queue}`"), || {
+            let contexts = self.spool(queue);
+            (contexts.len() == count).then_some(contexts)
+        })
+    }
+
+    /// The content of a message of the spool.
+    #[must_use]
+    pub fn message(&self, ctx: &ContextFinished) -> String {
+        std::fs::read_to_string(
+            self.root
+                .join("spool/mails")
+                .join(format!("{}.eml", ctx.mail_from.message_uuid)),
+        )
+        .unwrap_or_default()
+    }
+
+    /// The logs of the server.
+    #[must_use]
+    pub fn logs(&self) -> String {
+        std::fs::read_to_string(self.root.join("vsmtp.log")).unwrap_or_default()
+    }
+
+    /// Wait for a line of the logs to contain `pattern`.
+    pub fn wait_for_log(&self, pattern: &str) {
+        wait_for(&format!("`{pattern}` in the logs"), || {
+            self.logs().contains(pattern).then_some(())
+        });
+    }
+
+    /// Send `SIGTERM` to the process, and wait for it to exit.
+    #[must_use]
+    pub fn terminate(&mut self) -> std::process::ExitStatus {
+        let status = std::process::Command::new("kill")
+            .args(["-s", "TERM", &self.child.id().to_string()])
+            .status()
+            .expect("failed to run kill");
+        assert!(status.success(), "failed to send SIGTERM");
+
+        wait_for("the server to exit", || {
+            self.child.try_wait().expect("failed to poll the process")
+        })
+    }
+}
+
+/// A message from `from` to `to`, with a subject and a body.
+#[must_use]
+pub fn message(from: &str, to: &str, subject: &str) -> lettre::Message {
+    lettre::Message::builder()
+        .from(from.parse().expect("valid mailbox"))
+        .to(to.parse().expect("valid mailbox"))
+        .subject(subject)
+        .body(String::from("Be happy!"))
+        .expect("valid message")
+}
@@ -0,0 +1,25 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2023 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+
+//! End-to-end tests of the `vsmtp` binary of the workspace, run with
+//! `cargo test --package vsmtp-test --features e2e`.
+//!
+//! Each scenario starts its own process with [`harness::Harness`], on ports and a spool
+//! of its own, and drives it with a real SMTP client.
+
+mod harness;
+mod scenarios;
@@ -0,0 +1,235 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2023 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+
+use crate::harness::{message, Harness};
+use lettre::{
+    transport::smtp::authentication::{Credentials, Mechanism},
+    Transport,
+};
+use vsmtp_test::sink::Sink;
+
+#[test]
+fn submit_and_deliver_to_sink() {
+    let sink = Sink::start();
+    let instance = Harness::new()
+        .filter(format!(
+            r#"#{{
+    rcpt: [
+        action "forward to the sink" || transport::forward_all("{}"),
+    ],
+}}"#,
+            sink.addr
+        ))
+        .start();
+
+    instance
+        .client()
+        .build()
+        .send(&message(
+            "john@example.org",
+            "jane@example.com",
+            "submit_and_deliver_to_sink",
+        ))
+        .unwrap();
+
+    let received = sink.wait_for(1);
+    assert!(received[0]
+        .message
+        .contains("Subject: submit_and_deliver_to_sink"));
+
+    instance.wait_for_spool("deliver", 0);
+    assert!(instance.spool("deferred").is_empty());
+    assert!(instance.spool("dead").is_empty());
+    instance.wait_for_log("Message body fully received");
+}
+
+#[test]
+fn headers_by_recipient() {
+    let sink = Sink::start();
+    let instance = Harness::new()
+        .filter(format!(
+            r#"#{{
+    rcpt: [
+        action "forward to the sink" || transport::forward_all("{}"),
+    ],
+    preq: [
+        action "tag the copies" || {{
+            msg::append_rcpt_header("jane@example.com", "X-Tag", "jane");
+            msg::append_rcpt_header("jenny@example.com", "X-Tag", "jenny");
+        }},
+    ],
+}}"#,
+            sink.addr
+        ))
+        .start();
+
+    let email = message(
+        "john@example.org",
+        "jane@example.com",
+        "headers_by_recipient",
+    );
+    instance
+        .client()
+        .build()
+        .send_raw(
+            &lettre::address::Envelope::new(
+                Some("john@example.org".parse().unwrap()),
+                vec![
+                    "jane@example.com".parse().unwrap(),
+                    "jenny@example.com".parse().unwrap(),
+                ],
+            )
+            .unwrap(),
+            &email.formatted(),
+        )
+        .unwrap();
+
+    // a copy of the message by set of headers.
+    let received = sink.wait_for(2);
+    for (rcpt, tag, other) in [
+        ("<jane@example.com>", "X-Tag: jane\r\n", "X-Tag: jenny\r\n"),
+        ("<jenny@example.com>", "X-Tag: jenny\r\n", "X-Tag: jane\r\n"),
+    ] {
+        let copy = received
+            .iter()
+            .find(|transaction| transaction.rcpt_to == [rcpt])
+            .unwrap_or_else(|| panic!("no copy for {rcpt} in {received:?}"));
+        assert!(copy.message.contains(tag), "{}", copy.message);
+        assert!(!copy.message.contains(other), "{}", copy.message);
+    }
+
+    instance.wait_for_spool("deliver", 0);
+}
+
+#[test]
+fn quarantine_on_preq() {
+    let instance = Harness::new()
+        .filter(
+            r#"#{
+    preq: [
+        rule "quarantine" || state::quarantine("e2e"),
+    ],
+}"#,
+        )
+        .start();
+
+    instance
+        .client()
+        .build()
+        .send(&message(
+            "john@example.org",
+            "jane@example.com",
+            "quarantine_on_preq",
+        ))
+        .unwrap();
+
+    let quarantined = instance.wait_for_spool("quarantine/e2e", 1);
+    pretty_assertions::assert_eq!(
+        quarantined[0]
+            .mail_from
+            .reverse_path
+            .as_ref()
+            .map(ToString::to_string),
+        Some("john@example.org".to_owned())
+    );
+    pretty_assertions::assert_eq!(
+        quarantined[0]
+            .rcpt_to
+            .forward_paths
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>(),
+        vec!["jane@example.com".to_owned()]
+    );
+    assert!(instance
+        .message(&quarantined[0])
+        .contains("Subject: quarantine_on_preq"));
+    assert!(instance.spool("deliver").is_empty());
+}
+
+#[test]
+fn starttls_and_auth_on_submission() {
+    let instance = Harness::new()
+        .with_tls()
+        .config("config.server.esmtp.auth = #{};")
+        .filter(
+            r#"#{
+    authenticate: [
+        rule "hardcoded credentials" || {
+            const credentials = auth::credentials();
+
+            if credentials.type == "Verify"
+                && credentials.authid == "john"
+                && credentials.authpass == "secret" {
+                state::accept()
+            } else {
+                state::deny()
+            }
+        },
+    ],
+    preq: [
+        rule "hold the submissions" || {
+            if auth::is_authenticated() { state::quarantine("submitted") } else { state::deny() }
+        },
+    ],
+}"#,
+        )
+        .start();
+
+    let email = message(
+        "john@example.org",
+        "jane@example.com",
+        "starttls_and_auth_on_submission",
+    );
+
+    let error = instance
+        .submission_client()
+        .authentication(vec![Mechanism::Plain])
+        .credentials(Credentials::new("john".to_owned(), "wrong".to_owned()))
+        .build()
+        .send(&email)
+        .unwrap_err();
+    assert!(error.is_permanent(), "{error}");
+
+    instance
+        .submission_client()
+        .authentication(vec![Mechanism::Plain])
+        .credentials(Credentials::new("john".to_owned(), "secret".to_owned()))
+        .build()
+        .send(&email)
+        .unwrap();
+
+    let submitted = instance.wait_for_spool("quarantine/submitted", 1);
+    assert!(submitted[0].connect.tls.is_some());
+    assert!(submitted[0]
+        .connect
+        .auth
+        .as_ref()
+        .map_or(false, |auth| auth.authenticated));
+}
+
+#[test]
+fn graceful_exit_on_sigterm() {
+    let mut instance = Harness::new().start();
+
+    assert!(instance.client().build().test_connection().unwrap());
+
+    let status = instance.terminate();
+    assert!(status.success(), "the server exited with {status}");
+    assert!(instance.logs().contains("Stopping vSMTP server."));
+    assert!(std::net::TcpStream::connect(instance.addr).is_err());
+}